@@ -0,0 +1,153 @@
+use crate::TeeEvidenceParsedClaim;
+use serde_json::{Map, Value};
+
+/// Builder of the claims of a verifier, so that verifiers, in this crate or
+/// downstream, encode them the same way: binary fields as hex strings,
+/// little-endian integers as numbers, and groups of claims as nested
+/// objects.
+///
+/// ```
+/// use as_types::ClaimsBuilder;
+///
+/// let header = ClaimsBuilder::new()
+///     .le_uint("version", &[4, 0])
+///     .hex("vendor_id", [0x93, 0x9a]);
+/// let claims = ClaimsBuilder::new()
+///     .nest("header", header)
+///     .namespace("quote")
+///     .claim("eventlog_present", false)
+///     .build();
+///
+/// assert_eq!(claims.get_u64("quote.header.version"), Some(4));
+/// assert_eq!(claims.get_str("quote.header.vendor_id"), Some("939a"));
+/// assert_eq!(claims.get_bool("eventlog_present"), Some(false));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClaimsBuilder {
+    claims: Map<String, Value>,
+}
+
+impl ClaimsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the claim `name` to `value`.
+    pub fn claim(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.claims.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// Set the claim `name` to the hex encoding of `bytes`.
+    pub fn hex(self, name: &str, bytes: impl AsRef<[u8]>) -> Self {
+        self.claim(name, hex::encode(bytes))
+    }
+
+    /// Set the claim `name` to the number of the little-endian `bytes`,
+    /// e.g. a version or an SVN. Fields longer than 8 bytes are not numbers,
+    /// and are hex encoded.
+    pub fn le_uint(self, name: &str, bytes: &[u8]) -> Self {
+        if bytes.len() > 8 {
+            return self.hex(name, bytes);
+        }
+        let number = bytes
+            .iter()
+            .rev()
+            .fold(0u64, |number, byte| (number << 8) | u64::from(*byte));
+        self.claim(name, number)
+    }
+
+    /// Set the claim `name` to the claims of `claims`, as a nested object.
+    pub fn nest(self, name: &str, claims: ClaimsBuilder) -> Self {
+        self.claim(name, claims)
+    }
+
+    /// Merge the object `claims` into the claims, e.g. the claims of a
+    /// nested piece of evidence. Objects found on both sides are merged,
+    /// other claims of `claims` replace those of the same name. Anything
+    /// but an object is ignored.
+    pub fn merge(mut self, claims: impl Into<Value>) -> Self {
+        if let Value::Object(claims) = claims.into() {
+            merge(&mut self.claims, claims);
+        }
+        self
+    }
+
+    /// Move all the claims under `prefix`, a dot separated path of nested
+    /// objects, e.g. `quote.body`.
+    pub fn namespace(self, prefix: &str) -> Self {
+        let claims = prefix
+            .rsplit('.')
+            .fold(Value::Object(self.claims), |claims, name| {
+                let mut parent = Map::new();
+                parent.insert(name.to_string(), claims);
+                Value::Object(parent)
+            });
+        match claims {
+            Value::Object(claims) => Self { claims },
+            _ => unreachable!("namespaced claims are an object"),
+        }
+    }
+
+    pub fn build(self) -> TeeEvidenceParsedClaim {
+        TeeEvidenceParsedClaim(self.into())
+    }
+}
+
+impl From<ClaimsBuilder> for Value {
+    fn from(builder: ClaimsBuilder) -> Self {
+        Value::Object(builder.claims)
+    }
+}
+
+fn merge(claims: &mut Map<String, Value>, other: Map<String, Value>) {
+    for (name, value) in other {
+        match (claims.get_mut(&name), value) {
+            (Some(Value::Object(claims)), Value::Object(value)) => merge(claims, value),
+            (_, value) => {
+                claims.insert(name, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_claims_builder() {
+        let body = ClaimsBuilder::new()
+            .hex("mr_td", [0x70, 0x5e])
+            .le_uint("tee_type", &[0x81, 0, 0, 0])
+            .le_uint("mr_seam", &[0xff; 9])
+            .claim("tcb_svn", vec![3, 0, 5]);
+        let claims = ClaimsBuilder::new()
+            .nest("body", body)
+            .namespace("tdx.quote")
+            .merge(json!({
+                "tdx": { "quote": { "header": { "version": 4 } }, "eventlog_present": true },
+            }))
+            .merge(json!(["not", "an", "object"]))
+            .build();
+
+        assert_eq!(
+            claims.into_inner(),
+            json!({
+                "tdx": {
+                    "quote": {
+                        "header": { "version": 4 },
+                        "body": {
+                            "mr_td": "705e",
+                            "tee_type": 0x81,
+                            "mr_seam": "ffffffffffffffffff",
+                            "tcb_svn": [3, 0, 5],
+                        },
+                    },
+                    "eventlog_present": true,
+                },
+            })
+        );
+    }
+}
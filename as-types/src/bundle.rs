@@ -0,0 +1,397 @@
+//! Bundles of composite evidence: the evidence of a TEE and of the devices
+//! attached to it, with the runtime data and the init-data they bind, in
+//! one request.
+//!
+//! ```json
+//! {
+//!     "version": 1,
+//!     "evidence": [
+//!         { "media_type": "application/vnd.coco.tdx-evidence+json", "data": "<base64>" },
+//!         { "media_type": "application/vnd.coco.spdm-evidence+json", "name": "nic", "data": "<base64>" },
+//!         { "media_type": "application/vnd.coco.spdm-evidence+json", "name": "nic", "data": "<base64>" }
+//!     ],
+//!     "runtime_data": { "media_type": "application/jwk+json", "data": "<base64>" },
+//!     "init_data": { "media_type": "application/toml", "data": "<base64>" }
+//! }
+//! ```
+//!
+//! Ordering:
+//! - the evidence of the TEE comes first, with the media type of the TEE,
+//!   [`tee_media_type`], and without name;
+//! - the evidence of each device follows, with
+//!   [`DEVICE_EVIDENCE_MEDIA_TYPE`] and the name of the kind of device,
+//!   e.g. `nvidia_gpus`. The devices of a kind are next to each other, and
+//!   the `n`th of them has the claims `<kind>.<n>`.
+//!
+//! Binding:
+//! - the runtime data is the JWK of the TEE public key,
+//!   [`RUNTIME_DATA_MEDIA_TYPE`]. The report data of the TEE evidence
+//!   binds `SHA384(nonce || pubkey)`, and the SPDM nonce of the devices
+//!   `SHA256(nonce || pubkey)`;
+//! - the init-data, TOML or JSON, [`INIT_DATA_MEDIA_TYPES`], is measured
+//!   at launch, its digest, with the `algorithm` of the document, in the
+//!   MRCONFIGID of TDX or the HOSTDATA of SNP.
+//!
+//! The `data` of the items are the bytes of the evidence or document,
+//! base64 encoded in JSON. [`EvidenceBundle::unbundle`] checks a bundle and
+//! turns it into the composite evidence that the verifiers take.
+
+use anyhow::{anyhow, bail, Context, Result};
+use kbs_types::{Attestation, Tee, TeePubKey};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Version of the bundles described here.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Media type of the evidence of SPDM devices.
+pub const DEVICE_EVIDENCE_MEDIA_TYPE: &str = "application/vnd.coco.spdm-evidence+json";
+
+/// Media type of the runtime data, the JWK of the TEE public key.
+pub const RUNTIME_DATA_MEDIA_TYPE: &str = "application/jwk+json";
+
+/// Media types of the init-data.
+pub const INIT_DATA_MEDIA_TYPES: [&str; 2] = ["application/toml", "application/json"];
+
+const TEE_MEDIA_TYPE_PREFIX: &str = "application/vnd.coco.";
+const TEE_MEDIA_TYPE_SUFFIX: &str = "-evidence+json";
+
+/// Media type of the evidence of `tee`, e.g.
+/// `application/vnd.coco.tdx-evidence+json`.
+pub fn tee_media_type(tee: &Tee) -> String {
+    let tee = match serde_json::to_value(tee) {
+        Ok(Value::String(tee)) => tee,
+        _ => format!("{tee:?}").to_lowercase(),
+    };
+    format!("{TEE_MEDIA_TYPE_PREFIX}{tee}{TEE_MEDIA_TYPE_SUFFIX}")
+}
+
+/// The TEE of the media type of its evidence, see [`tee_media_type`].
+fn media_type_tee(media_type: &str) -> Option<Tee> {
+    let tee = media_type
+        .strip_prefix(TEE_MEDIA_TYPE_PREFIX)?
+        .strip_suffix(TEE_MEDIA_TYPE_SUFFIX)?;
+    serde_json::from_value(Value::String(tee.to_string())).ok()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EvidenceBundle {
+    pub version: u32,
+    /// The evidence of the TEE, then of its devices.
+    pub evidence: Vec<BundledItem>,
+    pub runtime_data: BundledItem,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub init_data: Option<BundledItem>,
+}
+
+/// A piece of evidence or a document of a bundle.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BundledItem {
+    pub media_type: String,
+    /// Kind of the device of device evidence, empty otherwise.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    #[serde(with = "base64_data")]
+    pub data: Vec<u8>,
+}
+
+impl BundledItem {
+    pub fn new(media_type: &str, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            media_type: media_type.to_string(),
+            name: String::new(),
+            data: data.into(),
+        }
+    }
+}
+
+/// A checked bundle, see [`EvidenceBundle::unbundle`].
+#[derive(Debug, Clone)]
+pub struct Unbundled {
+    pub tee: Tee,
+    /// The TEE public key and the composite evidence: the JSON evidence of
+    /// the TEE, with the list of the evidence of each kind of device in the
+    /// field of its name.
+    pub attestation: Attestation,
+    /// The kinds of the devices, in order.
+    pub devices: Vec<String>,
+    pub init_data: Option<BundledItem>,
+}
+
+impl EvidenceBundle {
+    /// Check that the bundle follows the rules of its version, and turn it
+    /// into composite evidence.
+    pub fn unbundle(self) -> Result<Unbundled> {
+        if self.version != BUNDLE_VERSION {
+            bail!(
+                "Unsupported evidence bundle version {}, expected {BUNDLE_VERSION}",
+                self.version
+            );
+        }
+        let mut evidence = self.evidence.into_iter();
+        let tee_evidence = evidence
+            .next()
+            .ok_or_else(|| anyhow!("The bundle has no evidence"))?;
+        let tee = media_type_tee(&tee_evidence.media_type).ok_or_else(|| {
+            anyhow!(
+                "The bundle starts with {} evidence, not with that of a TEE",
+                tee_evidence.media_type
+            )
+        })?;
+        if !tee_evidence.name.is_empty() {
+            bail!("The TEE evidence is named {}", tee_evidence.name);
+        }
+        let mut composite: Map<String, Value> = serde_json::from_slice(&tee_evidence.data)
+            .context("The TEE evidence is not a JSON object")?;
+
+        let mut devices: Vec<String> = Vec::new();
+        for (n, item) in evidence.enumerate() {
+            if item.media_type != DEVICE_EVIDENCE_MEDIA_TYPE {
+                bail!(
+                    "Evidence {} is {}, the TEE evidence comes first and only \
+                     device evidence follows",
+                    n + 1,
+                    item.media_type
+                );
+            }
+            if item.name.is_empty()
+                || !item
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                bail!(
+                    "Evidence {} has no valid name, letters, digits and _",
+                    n + 1
+                );
+            }
+            let device: Value = serde_json::from_slice(&item.data)
+                .with_context(|| format!("The {} evidence is not JSON", item.name))?;
+            if devices.last() != Some(&item.name) {
+                if devices.contains(&item.name) {
+                    bail!(
+                        "The {} evidence is not next to the evidence of the same devices",
+                        item.name
+                    );
+                }
+                if composite.contains_key(&item.name) {
+                    bail!(
+                        "The {} evidence has the name of a TEE evidence field",
+                        item.name
+                    );
+                }
+                composite.insert(item.name.clone(), Value::Array(Vec::new()));
+                devices.push(item.name.clone());
+            }
+            if let Some(Value::Array(evidence)) = composite.get_mut(&item.name) {
+                evidence.push(device);
+            }
+        }
+
+        if self.runtime_data.media_type != RUNTIME_DATA_MEDIA_TYPE {
+            bail!(
+                "The runtime data is {}, expected {RUNTIME_DATA_MEDIA_TYPE}",
+                self.runtime_data.media_type
+            );
+        }
+        let tee_pubkey: TeePubKey = serde_json::from_slice(&self.runtime_data.data)
+            .context("The runtime data is not the JWK of the TEE public key")?;
+        if let Some(init_data) = &self.init_data {
+            if !INIT_DATA_MEDIA_TYPES.contains(&init_data.media_type.as_str()) {
+                bail!(
+                    "The init-data is {}, expected one of {INIT_DATA_MEDIA_TYPES:?}",
+                    init_data.media_type
+                );
+            }
+            std::str::from_utf8(&init_data.data).context("The init-data is not UTF-8")?;
+        }
+
+        Ok(Unbundled {
+            tee,
+            attestation: Attestation {
+                tee_pubkey,
+                tee_evidence: Value::Object(composite).to_string(),
+            },
+            devices,
+            init_data: self.init_data,
+        })
+    }
+}
+
+/// Builder of bundles, which orders their evidence.
+///
+/// ```
+/// use as_types::bundle::BundleBuilder;
+/// use kbs_types::{Tee, TeePubKey};
+///
+/// let tee_pubkey = TeePubKey {
+///     kty: "RSA".to_string(),
+///     alg: "RSA1_5".to_string(),
+///     k_mod: "n".to_string(),
+///     k_exp: "AQAB".to_string(),
+/// };
+/// let bundle = BundleBuilder::new(Tee::Sample, r#"{"svn":"1","report_data":"AAAA"}"#)
+///     .device("nic", r#"{"attestation_report":"","certificate_chain":""}"#)
+///     .init_data("application/toml", "algorithm = \"sha384\"")
+///     .build(&tee_pubkey)
+///     .unwrap();
+///
+/// let unbundled = bundle.unbundle().unwrap();
+/// assert_eq!(unbundled.devices, ["nic"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct BundleBuilder {
+    tee_evidence: BundledItem,
+    devices: Vec<BundledItem>,
+    init_data: Option<BundledItem>,
+}
+
+impl BundleBuilder {
+    /// A bundle of the evidence of `tee`.
+    pub fn new(tee: Tee, evidence: impl Into<Vec<u8>>) -> Self {
+        Self {
+            tee_evidence: BundledItem::new(&tee_media_type(&tee), evidence),
+            devices: Vec::new(),
+            init_data: None,
+        }
+    }
+
+    /// Add the evidence of a device of the `kind`, after those of the same
+    /// kind already added.
+    pub fn device(mut self, kind: &str, evidence: impl Into<Vec<u8>>) -> Self {
+        let mut item = BundledItem::new(DEVICE_EVIDENCE_MEDIA_TYPE, evidence);
+        item.name = kind.to_string();
+        self.devices.push(item);
+        self
+    }
+
+    /// Set the init-data document, of one of the [`INIT_DATA_MEDIA_TYPES`].
+    pub fn init_data(mut self, media_type: &str, document: impl Into<Vec<u8>>) -> Self {
+        self.init_data = Some(BundledItem::new(media_type, document));
+        self
+    }
+
+    /// The bundle binding `tee_pubkey`.
+    pub fn build(mut self, tee_pubkey: &TeePubKey) -> Result<EvidenceBundle> {
+        // The sort is stable: the devices of a kind stay in order.
+        self.devices.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut evidence = vec![self.tee_evidence];
+        evidence.extend(self.devices);
+        Ok(EvidenceBundle {
+            version: BUNDLE_VERSION,
+            evidence,
+            runtime_data: BundledItem::new(
+                RUNTIME_DATA_MEDIA_TYPE,
+                serde_json::to_vec(tee_pubkey)?,
+            ),
+            init_data: self.init_data,
+        })
+    }
+}
+
+mod base64_data {
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let data = String::deserialize(deserializer)?;
+        base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn tee_pubkey() -> TeePubKey {
+        TeePubKey {
+            kty: "RSA".to_string(),
+            alg: "RSA1_5".to_string(),
+            k_mod: "n".to_string(),
+            k_exp: "AQAB".to_string(),
+        }
+    }
+
+    fn bundle() -> EvidenceBundle {
+        BundleBuilder::new(Tee::Tdx, json!({"quote": "AAAA"}).to_string())
+            .device("nvidia_gpus", r#"{"gpu":0}"#)
+            .device("nic", r#"{"nic":0}"#)
+            .device("nvidia_gpus", r#"{"gpu":1}"#)
+            .init_data("application/json", r#"{"policy":"allow"}"#)
+            .build(&tee_pubkey())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_unbundle() {
+        let bundle = bundle();
+        assert_eq!(
+            bundle.evidence[0].media_type,
+            "application/vnd.coco.tdx-evidence+json"
+        );
+        let text = serde_json::to_string(&bundle).unwrap();
+        let bundle: EvidenceBundle = serde_json::from_str(&text).unwrap();
+
+        let unbundled = bundle.unbundle().unwrap();
+        assert!(matches!(unbundled.tee, Tee::Tdx));
+        assert_eq!(unbundled.devices, ["nic", "nvidia_gpus"]);
+        assert_eq!(unbundled.attestation.tee_pubkey.k_exp, "AQAB");
+        assert_eq!(
+            serde_json::from_str::<Value>(&unbundled.attestation.tee_evidence).unwrap(),
+            json!({
+                "quote": "AAAA",
+                "nic": [{"nic": 0}],
+                "nvidia_gpus": [{"gpu": 0}, {"gpu": 1}],
+            })
+        );
+        assert_eq!(unbundled.init_data.unwrap().data, br#"{"policy":"allow"}"#);
+    }
+
+    #[test]
+    fn test_unbundle_invalid() {
+        let unbundle = |change: fn(&mut EvidenceBundle)| {
+            let mut bundle = bundle();
+            change(&mut bundle);
+            bundle.unbundle().unwrap_err().to_string()
+        };
+
+        assert_eq!(
+            unbundle(|bundle| bundle.version = 2),
+            "Unsupported evidence bundle version 2, expected 1"
+        );
+        assert_eq!(
+            unbundle(|bundle| bundle.evidence.swap(0, 1)),
+            "The bundle starts with application/vnd.coco.spdm-evidence+json evidence, \
+             not with that of a TEE"
+        );
+        assert_eq!(
+            unbundle(|bundle| bundle.evidence.swap(1, 2)),
+            "The nvidia_gpus evidence is not next to the evidence of the same devices"
+        );
+        assert_eq!(
+            unbundle(|bundle| bundle.evidence[1].name = "nic.0".to_string()),
+            "Evidence 1 has no valid name, letters, digits and _"
+        );
+        assert_eq!(
+            unbundle(|bundle| bundle.evidence[1].name = "quote".to_string()),
+            "The quote evidence has the name of a TEE evidence field"
+        );
+        assert_eq!(
+            unbundle(|bundle| bundle.runtime_data.media_type = "text/plain".to_string()),
+            "The runtime data is text/plain, expected application/jwk+json"
+        );
+        assert_eq!(
+            unbundle(|bundle| bundle.init_data.as_mut().unwrap().media_type =
+                "application/yaml".to_string()),
+            "The init-data is application/yaml, expected one of \
+             [\"application/toml\", \"application/json\"]"
+        );
+    }
+}
@@ -1,10 +1,342 @@
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
 
-pub type TeeEvidenceParsedClaim = serde_json::Value;
+mod builder;
+pub mod bundle;
+
+pub use builder::ClaimsBuilder;
+
+/// Claims parsed from TEE evidence by a verifier, a JSON object.
+///
+/// Claims are either nested, as verifiers generate them, or flattened, as
+/// the policy engine sees them (`{"tdx.quote.body.mr_td": "..."}`). Both
+/// forms can be looked up by path with [`TeeEvidenceParsedClaim::get`].
+/// Measurements and other binary fields are hex or base64 strings, and
+/// versions and SVNs are numbers, as [`ClaimsBuilder`] encodes them. It
+/// dereferences to the underlying [`serde_json::Value`], and serializes
+/// like it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct TeeEvidenceParsedClaim(pub Value);
+
+impl TeeEvidenceParsedClaim {
+    pub fn into_inner(self) -> Value {
+        self.0
+    }
+
+    /// The claim at a dot separated `path`, e.g. `quote.body.mr_td` or
+    /// `ccel.container_images.0.digest`. Flattened claim names match as
+    /// a whole, nested objects and arrays are walked one segment at a time.
+    pub fn get(&self, path: &str) -> Option<&Value> {
+        lookup(&self.0, path)
+    }
+
+    /// The string claim at `path`.
+    pub fn get_str(&self, path: &str) -> Option<&str> {
+        self.get(path)?.as_str()
+    }
+
+    /// The number claim at `path`.
+    pub fn get_u64(&self, path: &str) -> Option<u64> {
+        self.get(path)?.as_u64()
+    }
+
+    /// The boolean claim at `path`. Some verifiers give flags as `"0"` or
+    /// `"1"` strings, or as `"true"` or `"false"`, which are converted.
+    pub fn get_bool(&self, path: &str) -> Option<bool> {
+        match self.get(path)? {
+            Value::Bool(flag) => Some(*flag),
+            Value::String(flag) => match flag.as_str() {
+                "1" | "true" => Some(true),
+                "0" | "false" => Some(false),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The bytes of the hex encoded claim at `path`. An algorithm prefix
+    /// like `sha256:` is left out.
+    pub fn get_hex(&self, path: &str) -> Option<Vec<u8>> {
+        let value = self.get_str(path)?;
+        let value = value.split_once(':').map_or(value, |(_, digest)| digest);
+        hex::decode(value).ok()
+    }
+}
+
+/// Look `path` up in `value`, trying the longest claim names first, so
+/// that flattened names with dots match.
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    if let Some(found) = child(value, path) {
+        return Some(found);
+    }
+    path.match_indices('.').rev().find_map(|(i, _)| {
+        let (head, rest) = (&path[..i], &path[i + 1..]);
+        lookup(child(value, head)?, rest)
+    })
+}
+
+fn child<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+    match value {
+        Value::Object(map) => map.get(name),
+        Value::Array(items) => items.get(name.parse::<usize>().ok()?),
+        _ => None,
+    }
+}
+
+impl Deref for TeeEvidenceParsedClaim {
+    type Target = Value;
+
+    fn deref(&self) -> &Value {
+        &self.0
+    }
+}
+
+impl DerefMut for TeeEvidenceParsedClaim {
+    fn deref_mut(&mut self) -> &mut Value {
+        &mut self.0
+    }
+}
+
+impl From<Value> for TeeEvidenceParsedClaim {
+    fn from(value: Value) -> Self {
+        Self(value)
+    }
+}
+
+impl From<TeeEvidenceParsedClaim> for Value {
+    fn from(claims: TeeEvidenceParsedClaim) -> Self {
+        claims.0
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SetPolicyInput {
     pub r#type: String,
     pub policy_id: String,
     pub policy: String,
+    /// The evidence the policy applies to. Evaluating the policy against
+    /// other evidence fails, rather than the policy deciding on claims it
+    /// was not written for. Any evidence if `None`.
+    #[serde(default)]
+    pub selector: Option<PolicySelector>,
+    /// The parameters of a policy template, supplied on evaluation. The
+    /// policy reads them at `data.parameters.<name>`.
+    #[serde(default)]
+    pub parameters: Vec<PolicyParameter>,
+    /// Base64 detached signature of the policy text by the admin key,
+    /// required if the AS has one.
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+/// The evidence a policy applies to.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PolicySelector {
+    /// TEE types, e.g. `tdx`, any if empty.
+    #[serde(default)]
+    pub tees: Vec<String>,
+    /// Flattened claims the evidence must have, e.g.
+    /// `tdx.quote.body.mr_td`. A name ending with `*` matches any claim
+    /// with that prefix.
+    #[serde(default)]
+    pub claims: Vec<String>,
+}
+
+/// A parameter declared by a policy, supplied per tenant, e.g. the list of
+/// allowed `mr_td` of a team. A parameter without a default is required.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PolicyParameter {
+    pub name: String,
+    pub r#type: PolicyParameterType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+    /// Whether the attestation request, that is the attester, can supply
+    /// the value when neither the tenant nor the default does. Only for
+    /// parameters that do not decide what evidence is trusted.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub from_request: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyParameterType {
+    String,
+    Number,
+    Bool,
+    StringList,
+    NumberList,
+}
+
+impl PolicyParameterType {
+    /// Whether `value` is of this type.
+    pub fn accepts(&self, value: &Value) -> bool {
+        let list_of = |item: fn(&Value) -> bool| {
+            value
+                .as_array()
+                .is_some_and(|values| values.iter().all(item))
+        };
+        match self {
+            PolicyParameterType::String => value.is_string(),
+            PolicyParameterType::Number => value.is_number(),
+            PolicyParameterType::Bool => value.is_boolean(),
+            PolicyParameterType::StringList => list_of(Value::is_string),
+            PolicyParameterType::NumberList => list_of(Value::is_number),
+        }
+    }
+}
+
+/// Input to store a JSON data document that policies can consult, such as
+/// an organization specific lookup table. Policies read it at
+/// `data.custom.<name>`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SetPolicyDataInput {
+    pub name: String,
+    pub data: serde_json::Value,
+}
+
+/// A stored version of a policy data document. Versions start at 1 and
+/// every update of the document adds one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PolicyData {
+    pub name: String,
+    pub version: u64,
+    pub data: serde_json::Value,
+}
+
+/// The decision a policy makes over a set of claims.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyDecision {
+    Allow,
+    Deny,
+}
+
+/// A condition of the policy that the claims did not satisfy, as explained
+/// by the policy itself. All but `rule` are optional, since how much a
+/// policy can tell depends on the policy engine and the policy.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PolicyViolation {
+    /// Name of the failed rule or condition.
+    pub rule: String,
+    /// Flattened name of the claim that caused the failure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claim: Option<String>,
+    /// Value of that claim in the evidence.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<serde_json::Value>,
+    /// What the policy expected instead, e.g. the reference values.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// A single stored claims fixture for policy tests.
+///
+/// `claims` is the policy input, in the same flattened form the AS
+/// passes to the policy engine (e.g. `{"tdx.quote.body.mr_td": "..."}`).
+/// `reference` is exposed to the policy as `data.reference`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PolicyTestFixture {
+    pub name: String,
+    pub claims: serde_json::Value,
+    #[serde(default)]
+    pub reference: HashMap<String, Vec<String>>,
+    /// Values of the parameters of the policy.
+    #[serde(default)]
+    pub parameters: serde_json::Map<String, Value>,
+    pub expected: PolicyDecision,
+}
+
+/// Input to run a policy against a suite of fixtures without installing it.
+/// `policy` is encoded the same way as in [`SetPolicyInput`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TestPolicyInput {
+    pub r#type: String,
+    pub policy: String,
+    pub fixtures: Vec<PolicyTestFixture>,
+    /// The parameters the policy declares, as in [`SetPolicyInput`].
+    #[serde(default)]
+    pub parameters: Vec<PolicyParameter>,
+}
+
+/// Outcome of a single policy test fixture.
+/// `actual` is `None` when the policy engine failed to evaluate the fixture,
+/// in which case `error` carries the reason.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PolicyTestResult {
+    pub name: String,
+    pub expected: PolicyDecision,
+    pub actual: Option<PolicyDecision>,
+    pub passed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PolicyTestReport {
+    pub passed: usize,
+    pub failed: usize,
+    pub results: Vec<PolicyTestResult>,
+}
+
+impl PolicyTestReport {
+    /// Record the result of one fixture.
+    pub fn push(&mut self, result: PolicyTestResult) {
+        if result.passed {
+            self.passed += 1;
+        } else {
+            self.failed += 1;
+        }
+        self.results.push(result);
+    }
+
+    /// Whether every fixture in the suite got its expected decision.
+    pub fn success(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_claims_lookup() {
+        let claims = TeeEvidenceParsedClaim::from(json!({
+            "quote": {"body": {"mr_td": "0a0b", "tcb_svn": [3, 1]}},
+            "ccel.kernel": "sha256:ff00",
+            "policy_debug_allowed": "0",
+            "measurement": "not hex",
+        }));
+
+        assert_eq!(claims.get_str("quote.body.mr_td"), Some("0a0b"));
+        assert_eq!(claims.get_hex("quote.body.mr_td"), Some(vec![0x0a, 0x0b]));
+        assert_eq!(claims.get_u64("quote.body.tcb_svn.0"), Some(3));
+        assert_eq!(claims.get_hex("ccel.kernel"), Some(vec![0xff, 0x00]));
+        assert_eq!(claims.get_bool("policy_debug_allowed"), Some(false));
+        assert_eq!(claims.get_hex("measurement"), None);
+        assert_eq!(claims.get("quote.body.tcb_svn.2"), None);
+        assert_eq!(claims.get("quote.header"), None);
+        assert_eq!(claims["quote"]["body"]["tcb_svn"][1], 1);
+    }
+
+    #[test]
+    fn test_claims_serde() {
+        let value = json!({"tdx.quote.body.mr_td": "0a0b", "tdx.quote.body.tcb_svn.0": 3});
+        let claims: TeeEvidenceParsedClaim = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(claims.get_u64("tdx.quote.body.tcb_svn.0"), Some(3));
+        assert_eq!(serde_json::to_value(&claims).unwrap(), value);
+
+        let text = serde_json::to_string(&claims).unwrap();
+        assert_eq!(
+            serde_json::from_str::<TeeEvidenceParsedClaim>(&text).unwrap(),
+            claims
+        );
+        assert_eq!(Value::from(claims), value);
+    }
 }
@@ -1,5 +1,15 @@
-use std::process::{exit, Command};
+use std::process::exit;
+#[cfg(feature = "service")]
+use std::process::Command;
 
+// The policy engine and the gRPC RVPS client are only part of the service,
+// the verifiers need neither.
+#[cfg(not(feature = "service"))]
+fn real_main() -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(feature = "service")]
 fn real_main() -> Result<(), String> {
     let out_dir = std::env::var("OUT_DIR").unwrap();
     println!("cargo:rerun-if-changed={out_dir}");
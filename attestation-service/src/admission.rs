@@ -0,0 +1,230 @@
+//! Admission control of attestation requests.
+//!
+//! When a whole node pool restarts, thousands of pods attest at once. Each
+//! request waiting for a worker holds its evidence and verifier in memory,
+//! so an unbounded backlog ends with the AS running out of memory. With
+//! limits in `admission` of the AS config, requests beyond them are shed
+//! right away with an [`Overloaded`] error, which attesters retry later.
+//! [`Load`] tells how close the AS is to the limits. Batches of attestations
+//! are evaluated as many at a time as there are worker threads, and can be
+//! limited in size with `max_batch_size`.
+
+use crate::worker::WorkerPool;
+use serde::Deserialize;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct AdmissionConfig {
+    /// Attestation requests evaluated at the same time, from parsing the
+    /// evidence to issuing the token. Unlimited if not set.
+    #[serde(default)]
+    pub max_in_flight: Option<usize>,
+
+    /// Verifications waiting for a free worker thread. Unlimited if not
+    /// set.
+    #[serde(default)]
+    pub max_queue_depth: Option<usize>,
+
+    /// Attestations of a batch, see
+    /// [`crate::AttestationService::verify_batch`]. Unlimited if not set.
+    #[serde(default)]
+    pub max_batch_size: Option<usize>,
+}
+
+/// The AS is over a limit of its admission config, the request was shed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Overloaded {
+    pub limit: &'static str,
+    pub value: usize,
+}
+
+impl fmt::Display for Overloaded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The attestation service is overloaded ({} of {} reached), retry later",
+            self.limit, self.value
+        )
+    }
+}
+
+impl std::error::Error for Overloaded {}
+
+/// Saturation of the AS.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Load {
+    /// Attestation requests being evaluated.
+    pub in_flight: usize,
+    /// Verifications running on worker threads.
+    pub running: usize,
+    /// Verifications waiting for a free worker thread.
+    pub queued: usize,
+    /// Worker threads.
+    pub workers: usize,
+    /// Requests shed since the AS started.
+    pub shed: u64,
+    pub max_in_flight: Option<usize>,
+    pub max_queue_depth: Option<usize>,
+}
+
+pub(crate) struct Admission {
+    config: AdmissionConfig,
+    in_flight: AtomicUsize,
+    shed: AtomicU64,
+}
+
+/// An admitted request, counted in flight until dropped.
+pub(crate) struct Admitted<'a> {
+    in_flight: &'a AtomicUsize,
+}
+
+impl Drop for Admitted<'_> {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Admission {
+    pub fn new(config: AdmissionConfig) -> Self {
+        Self {
+            config,
+            in_flight: AtomicUsize::new(0),
+            shed: AtomicU64::new(0),
+        }
+    }
+
+    /// Admit a request, unless the AS is over a limit given the verifications
+    /// queued in `workers`.
+    pub fn admit(&self, workers: &WorkerPool) -> Result<Admitted<'_>, Overloaded> {
+        // Counted first, so that concurrent requests cannot all pass the
+        // limit. A shed request is uncounted when `admitted` is dropped.
+        let admitted = Admitted {
+            in_flight: &self.in_flight,
+        };
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst);
+
+        let overloaded = match (self.config.max_in_flight, self.config.max_queue_depth) {
+            (Some(max), _) if in_flight >= max => Some(("max_in_flight", max)),
+            (_, Some(max)) if workers.queued() >= max => Some(("max_queue_depth", max)),
+            _ => None,
+        };
+        match overloaded {
+            Some((limit, value)) => {
+                self.shed.fetch_add(1, Ordering::SeqCst);
+                debug!("Shedding attestation request, {limit} of {value} reached");
+                Err(Overloaded { limit, value })
+            }
+            None => Ok(admitted),
+        }
+    }
+
+    /// Check that a batch of `len` attestations is not over the limit.
+    pub fn check_batch(&self, len: usize) -> anyhow::Result<()> {
+        match self.config.max_batch_size {
+            Some(max) if len > max => {
+                anyhow::bail!("The batch has {len} attestations, more than the {max} allowed")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn load(&self, workers: &WorkerPool) -> Load {
+        Load {
+            in_flight: self.in_flight.load(Ordering::SeqCst),
+            running: workers.running(),
+            queued: workers.queued(),
+            workers: workers.threads(),
+            shed: self.shed.load(Ordering::SeqCst),
+            max_in_flight: self.config.max_in_flight,
+            max_queue_depth: self.config.max_queue_depth,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::sync::Semaphore;
+
+    #[test]
+    fn test_max_in_flight() {
+        let workers = WorkerPool::new(Some(1));
+        let admission = Admission::new(AdmissionConfig {
+            max_in_flight: Some(2),
+            max_queue_depth: None,
+            max_batch_size: None,
+        });
+
+        let first = admission.admit(&workers).unwrap();
+        let _second = admission.admit(&workers).unwrap();
+        assert_eq!(
+            admission.admit(&workers).err(),
+            Some(Overloaded {
+                limit: "max_in_flight",
+                value: 2
+            })
+        );
+        drop(first);
+        let _third = admission.admit(&workers).unwrap();
+
+        let load = admission.load(&workers);
+        assert_eq!(load.in_flight, 2);
+        assert_eq!(load.shed, 1);
+        assert_eq!(load.workers, 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_max_queue_depth() {
+        let workers = Arc::new(WorkerPool::new(Some(1)));
+        let admission = Admission::new(AdmissionConfig {
+            max_in_flight: None,
+            max_queue_depth: Some(1),
+            max_batch_size: None,
+        });
+
+        // One verification runs, and blocks the next one in the queue.
+        let release = Arc::new(Semaphore::new(0));
+        let jobs: Vec<_> = (0..2)
+            .map(|_| {
+                let workers = workers.clone();
+                let release = release.clone();
+                tokio::spawn(async move {
+                    workers
+                        .run(async move {
+                            let _ = release.acquire().await;
+                        })
+                        .await
+                })
+            })
+            .collect();
+        while workers.queued() < 1 || workers.running() < 1 {
+            tokio::task::yield_now().await;
+        }
+
+        assert!(admission.admit(&workers).is_err());
+        let load = admission.load(&workers);
+        assert_eq!((load.running, load.queued, load.shed), (1, 1, 1));
+
+        release.add_permits(1);
+        for job in jobs {
+            job.await.unwrap().unwrap();
+        }
+        assert_eq!((workers.running(), workers.queued()), (0, 0));
+        assert!(admission.admit(&workers).is_ok());
+    }
+
+    #[test]
+    fn test_check_batch() {
+        let admission = Admission::new(AdmissionConfig {
+            max_batch_size: Some(2),
+            ..Default::default()
+        });
+        assert!(admission.check_batch(2).is_ok());
+        assert!(admission.check_batch(3).is_err());
+        assert!(Admission::new(AdmissionConfig::default())
+            .check_batch(10_000)
+            .is_ok());
+    }
+}
@@ -0,0 +1,394 @@
+//! Security advisory feed, tightening the TCB baseline of the policies.
+//!
+//! When Intel or AMD disclose a TCB issue, the platforms are fixed by new
+//! firmware or microcode, with higher SVNs, and policies that keep allowing
+//! the former SVNs keep admitting vulnerable platforms until they are
+//! updated. With `advisory_feed` in the AS config, the AS polls a feed of
+//! advisories every `poll_secs`, 3600 by default, from an HTTP URL or a
+//! file, and maps each advisory to the minimum SVNs of the claims that fix
+//! it:
+//!
+//! ```json
+//! "advisory_feed": {
+//!     "source": "https://security.example.com/tcb-advisories.json",
+//!     "format": "amd",
+//!     "poll_secs": 3600
+//! }
+//! ```
+//!
+//! The feed is a JSON document with a list of `advisories`, each with an
+//! `id`, an optional `published` date, and the SVNs of its `format`:
+//!
+//! * `curated`, the default: `minimums`, the minimum SVN of each flattened
+//!   claim, e.g. `{"snp.reported_tcb_microcode": 213}`, for a feed curated
+//!   by a security team;
+//! * `amd`: `tcb`, the minimum `bootloader`, `tee`, `snp` and `microcode`
+//!   SVNs of an AMD security bulletin, claimed as `snp.reported_tcb_<name>`;
+//! * `intel`: `tdx_tcb_components`, the 16 minimum SVNs of the TDX TCB
+//!   components of an Intel security advisory, claimed as
+//!   `tdx.quote.body.tcb_svn.<index>`.
+//!
+//! The advisories are kept in the `advisory_baseline` policy data document,
+//! with the highest minimum SVN of each claim, which policies read at
+//! `data.custom.advisory_baseline`:
+//!
+//! ```json
+//! {
+//!     "minimums": { "snp.reported_tcb_microcode": 213 },
+//!     "advisories": [
+//!         {
+//!             "id": "AMD-SB-3019",
+//!             "published": "2026-08-12T00:00:00Z",
+//!             "minimums": { "snp.reported_tcb_microcode": 213 },
+//!             "applied": "2026-08-12T09:41:07Z"
+//!         }
+//!     ]
+//! }
+//! ```
+//!
+//! A new advisory in the feed adds a version of the document, and is
+//! logged under the `attestation_service::advisories` target. The versions
+//! of the document are kept by the policy engine, so that the baseline in
+//! force at any time, and when each advisory was applied, is known after
+//! the fact. An advisory removed from the feed stays in the baseline, which
+//! only tightens: an operator relaxes it by setting the document.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// Name of the policy data document of the baseline.
+pub const ADVISORY_BASELINE: &str = "advisory_baseline";
+
+/// Target of the logs of the applied advisories, to filter them in or out
+/// with `RUST_LOG`.
+pub const ADVISORY_LOG_TARGET: &str = "attestation_service::advisories";
+
+const FEED_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The SVNs of an AMD security bulletin, in the order of the TCB version.
+const AMD_COMPONENTS: &[&str] = &["bootloader", "tee", "snp", "microcode"];
+
+/// Number of TDX TCB components of an Intel security advisory.
+const INTEL_COMPONENTS: usize = 16;
+
+fn default_poll_secs() -> u64 {
+    3600
+}
+
+/// Format of the advisories of a feed, see the module documentation.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    #[default]
+    Curated,
+    Amd,
+    Intel,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AdvisoryFeedConfig {
+    /// HTTP URL or path of the feed.
+    pub source: String,
+    #[serde(default)]
+    pub format: FeedFormat,
+    /// Period of the polls of the feed, in seconds.
+    #[serde(default = "default_poll_secs")]
+    pub poll_secs: u64,
+}
+
+impl AdvisoryFeedConfig {
+    pub fn check(&self) -> Result<()> {
+        if !is_url(&self.source) && !Path::new(&self.source).is_file() {
+            bail!("{} is neither an HTTP URL nor a file", self.source);
+        }
+        if self.poll_secs == 0 {
+            bail!("poll_secs must be positive");
+        }
+        Ok(())
+    }
+}
+
+fn is_url(source: &str) -> bool {
+    source.starts_with("https://") || source.starts_with("http://")
+}
+
+/// An advisory of the baseline.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Advisory {
+    /// ID of the advisory, e.g. `INTEL-SA-01036` or `AMD-SB-3019`.
+    pub id: String,
+    #[serde(default)]
+    pub published: Option<DateTime<Utc>>,
+    /// Minimum SVN of each flattened claim.
+    pub minimums: BTreeMap<String, u64>,
+    /// When the AS applied the advisory. Set by the AS.
+    #[serde(default = "Utc::now")]
+    pub applied: DateTime<Utc>,
+}
+
+/// The `advisory_baseline` policy data document.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Baseline {
+    /// Highest minimum SVN of each claim, over the advisories.
+    #[serde(default)]
+    pub minimums: BTreeMap<String, u64>,
+    /// The applied advisories, oldest first.
+    #[serde(default)]
+    pub advisories: Vec<Advisory>,
+}
+
+impl Baseline {
+    /// Add the `advisories` that the baseline does not have yet, by ID, and
+    /// return them.
+    pub fn apply(&mut self, advisories: Vec<Advisory>) -> Vec<Advisory> {
+        let mut applied = Vec::new();
+        for advisory in advisories {
+            if self.advisories.iter().any(|known| known.id == advisory.id) {
+                continue;
+            }
+            for (claim, svn) in &advisory.minimums {
+                let minimum = self.minimums.entry(claim.clone()).or_default();
+                *minimum = (*minimum).max(*svn);
+            }
+            self.advisories.push(advisory.clone());
+            applied.push(advisory);
+        }
+        applied
+    }
+}
+
+#[derive(Deserialize)]
+struct Feed {
+    advisories: Vec<FeedAdvisory>,
+}
+
+#[derive(Deserialize)]
+struct FeedAdvisory {
+    id: String,
+    #[serde(default)]
+    published: Option<DateTime<Utc>>,
+    #[serde(default)]
+    minimums: Option<BTreeMap<String, u64>>,
+    #[serde(default)]
+    tcb: Option<BTreeMap<String, u64>>,
+    #[serde(default)]
+    tdx_tcb_components: Option<Vec<u64>>,
+}
+
+impl FeedAdvisory {
+    fn minimums(self, format: FeedFormat) -> Result<BTreeMap<String, u64>> {
+        let missing = |field: &str| format!("Advisory {} has no {field}", self.id);
+        match format {
+            FeedFormat::Curated => self.minimums.with_context(|| missing("minimums")),
+            FeedFormat::Amd => {
+                let tcb = self.tcb.with_context(|| missing("tcb"))?;
+                tcb.into_iter()
+                    .map(|(component, svn)| {
+                        if !AMD_COMPONENTS.contains(&component.as_str()) {
+                            bail!("Unknown AMD TCB component {component}");
+                        }
+                        Ok((format!("snp.reported_tcb_{component}"), svn))
+                    })
+                    .collect()
+            }
+            FeedFormat::Intel => {
+                let components = self
+                    .tdx_tcb_components
+                    .with_context(|| missing("tdx_tcb_components"))?;
+                if components.len() > INTEL_COMPONENTS {
+                    bail!("An advisory has at most {INTEL_COMPONENTS} TDX TCB components");
+                }
+                Ok(components
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, svn)| *svn > 0)
+                    .map(|(i, svn)| (format!("tdx.quote.body.tcb_svn.{i}"), svn))
+                    .collect())
+            }
+        }
+    }
+}
+
+/// Parse the advisories of a feed of `format`.
+pub fn parse(format: FeedFormat, feed: &[u8]) -> Result<Vec<Advisory>> {
+    let feed: Feed = serde_json::from_slice(feed).context("Malformed advisory feed")?;
+    feed.advisories
+        .into_iter()
+        .map(|advisory| {
+            let (id, published) = (advisory.id.clone(), advisory.published);
+            let minimums = advisory
+                .minimums(format)
+                .with_context(|| format!("Invalid advisory {id}"))?;
+            Ok(Advisory {
+                id,
+                published,
+                minimums,
+                applied: Utc::now(),
+            })
+        })
+        .collect()
+}
+
+pub(crate) struct AdvisoryFeed {
+    config: AdvisoryFeedConfig,
+    client: reqwest::Client,
+}
+
+impl AdvisoryFeed {
+    pub fn new(config: AdvisoryFeedConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(FEED_TIMEOUT)
+            .build()
+            .context("create HTTP client")?;
+        Ok(Self { config, client })
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.poll_secs)
+    }
+
+    /// Fetch the advisories of the feed.
+    pub async fn fetch(&self) -> Result<Vec<Advisory>> {
+        let source = &self.config.source;
+        let feed = match is_url(source) {
+            true => self
+                .client
+                .get(source)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .with_context(|| format!("Cannot fetch the advisory feed {source}"))?
+                .bytes()
+                .await?
+                .to_vec(),
+            false => std::fs::read(source)
+                .with_context(|| format!("Cannot read the advisory feed {source}"))?,
+        };
+        parse(self.config.format, &feed)
+    }
+
+    /// Apply the advisories of the feed to the `current` baseline document,
+    /// if any, and return the new document if one was applied.
+    pub async fn refresh(&self, current: Option<Value>) -> Result<Option<Value>> {
+        let mut baseline: Baseline = match current {
+            Some(current) => serde_json::from_value(current)
+                .with_context(|| format!("Malformed {ADVISORY_BASELINE} document"))?,
+            None => Baseline::default(),
+        };
+        let applied = baseline.apply(self.fetch().await?);
+        if applied.is_empty() {
+            return Ok(None);
+        }
+        for advisory in &applied {
+            info!(
+                target: ADVISORY_LOG_TARGET,
+                "Advisory {} applied, minimum SVNs {}",
+                advisory.id,
+                serde_json::to_string(&advisory.minimums)?
+            );
+        }
+        Ok(Some(serde_json::to_value(baseline)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse() {
+        let feed = json!({"advisories": [
+            {
+                "id": "AMD-SB-3019",
+                "published": "2026-08-12T00:00:00Z",
+                "tcb": {"bootloader": 9, "microcode": 213}
+            }
+        ]});
+        let advisories = parse(FeedFormat::Amd, feed.to_string().as_bytes()).unwrap();
+        assert_eq!(advisories[0].id, "AMD-SB-3019");
+        assert_eq!(
+            advisories[0].minimums,
+            BTreeMap::from([
+                ("snp.reported_tcb_bootloader".to_string(), 9),
+                ("snp.reported_tcb_microcode".to_string(), 213),
+            ])
+        );
+        assert!(parse(FeedFormat::Curated, feed.to_string().as_bytes()).is_err());
+        let feed = json!({"advisories": [{"id": "AMD-SB-1", "tcb": {"psp": 1}}]});
+        assert!(parse(FeedFormat::Amd, feed.to_string().as_bytes()).is_err());
+
+        let feed = json!({"advisories": [
+            {"id": "INTEL-SA-01036", "tdx_tcb_components": [5, 0, 2]}
+        ]});
+        let advisories = parse(FeedFormat::Intel, feed.to_string().as_bytes()).unwrap();
+        assert_eq!(
+            advisories[0].minimums,
+            BTreeMap::from([
+                ("tdx.quote.body.tcb_svn.0".to_string(), 5),
+                ("tdx.quote.body.tcb_svn.2".to_string(), 2),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let advisory = |id: &str, claim: &str, svn: u64| Advisory {
+            id: id.to_string(),
+            published: None,
+            minimums: BTreeMap::from([(claim.to_string(), svn)]),
+            applied: Utc::now(),
+        };
+        let mut baseline = Baseline::default();
+        let applied = baseline.apply(vec![
+            advisory("SA-1", "snp.reported_tcb_microcode", 213),
+            advisory("SA-2", "snp.reported_tcb_microcode", 209),
+            advisory("SA-3", "snp.reported_tcb_snp", 22),
+        ]);
+        assert_eq!(applied.len(), 3);
+        assert_eq!(baseline.minimums["snp.reported_tcb_microcode"], 213);
+        assert_eq!(baseline.minimums["snp.reported_tcb_snp"], 22);
+
+        // Known advisories are not applied again, even with other SVNs.
+        let applied = baseline.apply(vec![
+            advisory("SA-1", "snp.reported_tcb_microcode", 1),
+            advisory("SA-4", "snp.reported_tcb_microcode", 215),
+        ]);
+        assert_eq!(applied, [baseline.advisories[3].clone()]);
+        assert_eq!(baseline.minimums["snp.reported_tcb_microcode"], 215);
+
+        let document = serde_json::to_value(&baseline).unwrap();
+        assert_eq!(
+            serde_json::from_value::<Baseline>(document).unwrap(),
+            baseline
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refresh() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("feed.json");
+        let feed = json!({"advisories": [
+            {"id": "SA-1", "minimums": {"snp.reported_tcb_microcode": 213}}
+        ]});
+        std::fs::write(&source, feed.to_string()).unwrap();
+        let config = AdvisoryFeedConfig {
+            source: source.to_string_lossy().to_string(),
+            format: FeedFormat::Curated,
+            poll_secs: 60,
+        };
+        config.check().unwrap();
+        let feed = AdvisoryFeed::new(config).unwrap();
+
+        let document = feed.refresh(None).await.unwrap().unwrap();
+        assert_eq!(document["minimums"]["snp.reported_tcb_microcode"], 213);
+        assert_eq!(document["advisories"][0]["id"], "SA-1");
+        assert!(feed.refresh(Some(document)).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,631 @@
+//! Audit trail of the attestations.
+//!
+//! For compliance, it must be known after the fact why each attestation
+//! passed or failed. With `audit` in the AS config, the AS records an
+//! [`AuditRecord`] of every attestation request: the digest of its evidence,
+//! its TEE, the steps of the evaluation it ran, the certificate chains its
+//! evidence was verified with, with their serials and expiry, the decision
+//! of each policy with the rules that failed, and the step that rejected it.
+//! The records are emitted as JSON to a sink:
+//!
+//! * `file`: a JSON line appended to `path`;
+//! * `syslog`: an RFC 5424 message of the `log audit` facility, sent to the
+//!   syslog `socket`, `/dev/log` by default;
+//! * `otlp`: an OTLP log record, sent as JSON to `<endpoint>/v1/logs` of an
+//!   OpenTelemetry collector.
+//!
+//! ```json
+//! "audit": {
+//!     "sink": { "type": "file", "path": "/var/log/attestation-service/audit.jsonl" },
+//!     "max_records": 10000
+//! }
+//! ```
+//!
+//! Every attestation gets an ID, returned with its token or its error, by
+//! which its record is fetched: from the last `max_records` records, kept in
+//! memory, or else from the file of the `file` sink. A record that the sink
+//! does not take is logged, and the attestation goes on.
+
+use anyhow::{anyhow, bail, Context, Result};
+use as_types::PolicyDecision;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::policy_engine::{PolicyDenied, PolicyEvaluation};
+use crate::rejections::RejectionStage;
+use crate::verifier::anchors;
+use crate::verifier::trace::VerificationTrace;
+
+/// Target of the records that the sink did not take, to filter them in or
+/// out with `RUST_LOG`.
+pub const AUDIT_LOG_TARGET: &str = "attestation_service::audit";
+
+const DEFAULT_MAX_RECORDS: usize = 10_000;
+const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+
+/// The `log audit` syslog facility.
+const SYSLOG_FACILITY: u8 = 13;
+
+const OTLP_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn default_max_records() -> usize {
+    DEFAULT_MAX_RECORDS
+}
+
+fn default_syslog_socket() -> PathBuf {
+    PathBuf::from(DEFAULT_SYSLOG_SOCKET)
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AuditSink {
+    File {
+        path: PathBuf,
+    },
+    Syslog {
+        #[serde(default = "default_syslog_socket")]
+        socket: PathBuf,
+    },
+    Otlp {
+        /// Base URL of the OTLP/HTTP receiver, e.g. `http://localhost:4318`.
+        endpoint: String,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditConfig {
+    pub sink: AuditSink,
+
+    /// Records kept in memory to be fetched by attestation ID.
+    #[serde(default = "default_max_records")]
+    pub max_records: usize,
+}
+
+impl AuditConfig {
+    pub fn check(&self) -> Result<()> {
+        if self.max_records == 0 {
+            bail!("max_records must be at least 1");
+        }
+        match &self.sink {
+            AuditSink::File { path } => {
+                let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+                if dir.is_some_and(|dir| !dir.is_dir()) {
+                    bail!("{} is not in a directory", path.display());
+                }
+            }
+            AuditSink::Syslog { .. } => {}
+            AuditSink::Otlp { endpoint } => {
+                if !endpoint.starts_with("https://") && !endpoint.starts_with("http://") {
+                    bail!("OTLP endpoint {endpoint} is not an HTTP URL");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A certificate of a chain that evidence was verified with.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditedCertificate {
+    /// SHA-256 of the DER certificate, like `sha256:<hex>`.
+    pub fingerprint: String,
+    #[serde(default)]
+    pub subject: String,
+    #[serde(default)]
+    pub issuer: String,
+    /// Hex encoded serial number.
+    #[serde(default)]
+    pub serial: String,
+    #[serde(default)]
+    pub not_after: Option<DateTime<Utc>>,
+}
+
+impl AuditedCertificate {
+    fn new(der: &[u8]) -> Self {
+        let fingerprint = anchors::fingerprint(der);
+        match X509Certificate::from_der(der) {
+            Ok((_, cert)) => Self {
+                fingerprint,
+                subject: cert.subject().to_string(),
+                issuer: cert.issuer().to_string(),
+                serial: hex::encode(cert.raw_serial()),
+                not_after: Utc
+                    .timestamp_opt(cert.validity().not_after.timestamp(), 0)
+                    .single(),
+            },
+            Err(_) => Self {
+                fingerprint,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// A certificate chain that evidence was verified with.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditedChain {
+    /// The key the chain certifies, e.g. `PCK` or `VCEK`.
+    pub name: String,
+    /// The certificates of the chain, leaf first.
+    pub certificates: Vec<AuditedCertificate>,
+}
+
+/// The decision of a policy on an attestation.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditedPolicy {
+    pub policy_id: String,
+    /// The decision, unset if the policy failed to evaluate.
+    pub decision: Option<PolicyDecision>,
+    /// The rules that failed, if the policy denied the attestation.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_rules: Vec<String>,
+}
+
+/// The audit record of an attestation.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub attestation_id: String,
+    pub timestamp: DateTime<Utc>,
+    pub tee: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// SHA-256 of the attestation as received, like `sha256:<hex>`.
+    pub evidence_digest: String,
+    /// The steps of the evaluation that ran, in order: the stages of the
+    /// verifier pipeline, see [`crate::verifier::pipeline`], or `verify`
+    /// for verifiers without stages, then those of the AS, named like the
+    /// [`RejectionStage`]s.
+    pub steps: Vec<String>,
+    /// Whether the evidence was verified by an earlier attestation, see
+    /// [`crate::result_cache`], in which case no verifier step nor
+    /// certificate chain is recorded.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cached_verification: bool,
+    pub certificate_chains: Vec<AuditedChain>,
+    pub policies: Vec<AuditedPolicy>,
+    /// Whether the attestation passed, and a token was issued.
+    pub passed: bool,
+    /// The step that rejected the attestation, if it failed at one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A new ID of an attestation.
+pub fn new_attestation_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// The audit record of an attestation being evaluated.
+pub(crate) struct AuditTrace(Mutex<AuditRecord>);
+
+impl AuditTrace {
+    fn update(&self, update: impl FnOnce(&mut AuditRecord)) {
+        update(&mut self.0.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+
+    /// Record that the evaluation reached `step`.
+    pub fn step(&self, step: RejectionStage) {
+        self.update(|record| record.steps.push(step.to_string()));
+    }
+
+    /// Record the trace of the verification of the evidence.
+    pub fn verified(&self, trace: VerificationTrace) {
+        self.update(|record| {
+            match trace.stages.is_empty() {
+                true => record.steps.push(RejectionStage::Verify.to_string()),
+                false => record
+                    .steps
+                    .extend(trace.stages.iter().map(ToString::to_string)),
+            }
+            record
+                .certificate_chains
+                .extend(trace.chains.into_iter().map(|chain| {
+                    AuditedChain {
+                        name: chain.name,
+                        certificates: chain
+                            .certificates
+                            .iter()
+                            .map(|der| AuditedCertificate::new(der))
+                            .collect(),
+                    }
+                }));
+        });
+    }
+
+    /// Record that the evidence was verified by an earlier attestation.
+    pub fn cached(&self) {
+        self.update(|record| record.cached_verification = true);
+    }
+
+    /// Record the decisions of the policies.
+    pub fn policies(&self, evaluations: &[(String, Result<PolicyEvaluation>)]) {
+        self.update(|record| {
+            for (policy_id, evaluation) in evaluations {
+                let (decision, failed_rules) = match evaluation {
+                    Ok(_) => (Some(PolicyDecision::Allow), Vec::new()),
+                    Err(e) => match e.downcast_ref::<PolicyDenied>() {
+                        Some(denied) => (
+                            Some(PolicyDecision::Deny),
+                            denied
+                                .violations
+                                .iter()
+                                .map(|violation| violation.rule.clone())
+                                .collect(),
+                        ),
+                        None => (None, Vec::new()),
+                    },
+                };
+                record.policies.push(AuditedPolicy {
+                    policy_id: policy_id.clone(),
+                    decision,
+                    failed_rules,
+                });
+            }
+        });
+    }
+
+    /// Record that the attestation was rejected at `stage`.
+    pub fn rejected(&self, stage: RejectionStage) {
+        self.update(|record| record.rejected_at = Some(stage.to_string()));
+    }
+}
+
+enum Sink {
+    File {
+        path: PathBuf,
+        /// Held while a record is appended.
+        file: Mutex<()>,
+    },
+    Syslog {
+        socket: PathBuf,
+    },
+    Otlp {
+        client: reqwest::Client,
+        url: String,
+    },
+}
+
+impl Sink {
+    fn new(config: &AuditSink) -> Result<Self> {
+        Ok(match config {
+            AuditSink::File { path } => Self::File {
+                path: path.clone(),
+                file: Mutex::new(()),
+            },
+            AuditSink::Syslog { socket } => Self::Syslog {
+                socket: socket.clone(),
+            },
+            AuditSink::Otlp { endpoint } => Self::Otlp {
+                client: reqwest::Client::builder()
+                    .timeout(OTLP_TIMEOUT)
+                    .build()
+                    .context("create HTTP client")?,
+                url: format!("{}/v1/logs", endpoint.trim_end_matches('/')),
+            },
+        })
+    }
+
+    async fn emit(&self, record: &AuditRecord) -> Result<()> {
+        let line = serde_json::to_string(record)?;
+        match self {
+            Self::File { path, file } => {
+                let _file = file.lock().unwrap_or_else(PoisonError::into_inner);
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Cannot open {}", path.display()))?;
+                writeln!(file, "{line}")?;
+            }
+            Self::Syslog { socket } => {
+                UnixDatagram::unbound()?
+                    .send_to(syslog_message(record, &line).as_bytes(), socket)
+                    .with_context(|| format!("Cannot send to {}", socket.display()))?;
+            }
+            Self::Otlp { client, url } => {
+                client
+                    .post(url)
+                    .json(&otlp_logs(record, &line))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The RFC 5424 syslog message of `record`, whose JSON is `line`.
+fn syslog_message(record: &AuditRecord, line: &str) -> String {
+    // Informational or notice.
+    let severity = match record.passed {
+        true => 6,
+        false => 5,
+    };
+    format!(
+        "<{}>1 {} - attestation-service {} audit - {line}",
+        SYSLOG_FACILITY * 8 + severity,
+        record.timestamp.to_rfc3339(),
+        std::process::id(),
+    )
+}
+
+/// The OTLP logs request of `record`, whose JSON is `line`.
+fn otlp_logs(record: &AuditRecord, line: &str) -> Value {
+    let (severity_number, severity_text) = match record.passed {
+        true => (9, "INFO"),
+        false => (13, "WARN"),
+    };
+    json!({
+        "resourceLogs": [{
+            "resource": {
+                "attributes": [
+                    { "key": "service.name", "value": { "stringValue": "attestation-service" } },
+                ],
+            },
+            "scopeLogs": [{
+                "scope": { "name": AUDIT_LOG_TARGET },
+                "logRecords": [{
+                    "timeUnixNano": record
+                        .timestamp
+                        .timestamp_nanos_opt()
+                        .unwrap_or_default()
+                        .to_string(),
+                    "severityNumber": severity_number,
+                    "severityText": severity_text,
+                    "body": { "stringValue": line },
+                    "attributes": [
+                        { "key": "attestation.id", "value": { "stringValue": record.attestation_id } },
+                        { "key": "attestation.passed", "value": { "boolValue": record.passed } },
+                    ],
+                }],
+            }],
+        }],
+    })
+}
+
+/// The audit trail of the attestations.
+pub(crate) struct Audit {
+    config: AuditConfig,
+    sink: Sink,
+    /// The last records, oldest first.
+    records: Mutex<VecDeque<AuditRecord>>,
+}
+
+impl Audit {
+    pub fn new(config: AuditConfig) -> Result<Self> {
+        config.check()?;
+        Ok(Self {
+            sink: Sink::new(&config.sink)?,
+            config,
+            records: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    /// Start the record of the `attestation` of `tee` by `tenant`, with
+    /// the ID `attestation_id`, or a new one.
+    pub fn start(
+        &self,
+        attestation_id: Option<String>,
+        tee: &str,
+        tenant: Option<&str>,
+        attestation: &str,
+    ) -> AuditTrace {
+        AuditTrace(Mutex::new(AuditRecord {
+            attestation_id: attestation_id.unwrap_or_else(new_attestation_id),
+            timestamp: Utc::now(),
+            tee: tee.to_string(),
+            tenant: tenant.map(str::to_string),
+            evidence_digest: format!("sha256:{}", hex::encode(Sha256::digest(attestation))),
+            steps: Vec::new(),
+            cached_verification: false,
+            certificate_chains: Vec::new(),
+            policies: Vec::new(),
+            passed: false,
+            rejected_at: None,
+            error: None,
+        }))
+    }
+
+    /// Complete the record of `trace` with the `result` of the attestation,
+    /// emit it to the sink, and return its attestation ID.
+    pub async fn finish<T>(&self, trace: AuditTrace, result: &Result<T>) -> String {
+        let mut record = trace.0.into_inner().unwrap_or_else(PoisonError::into_inner);
+        match result {
+            Ok(_) => record.passed = true,
+            Err(e) => record.error = Some(format!("{e:#}")),
+        }
+        if let Err(e) = self.sink.emit(&record).await {
+            warn!(
+                target: AUDIT_LOG_TARGET,
+                "Cannot emit the audit record: {e:#}: {}",
+                serde_json::to_string(&record).unwrap_or_default()
+            );
+        }
+        let attestation_id = record.attestation_id.clone();
+        let mut records = self.records.lock().unwrap_or_else(PoisonError::into_inner);
+        if records.len() >= self.config.max_records {
+            records.pop_front();
+        }
+        records.push_back(record);
+        attestation_id
+    }
+
+    /// The record of the attestation `attestation_id`, if any.
+    pub fn record(&self, attestation_id: &str) -> Result<Option<AuditRecord>> {
+        let recent = self
+            .records
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .rev()
+            .find(|record| record.attestation_id == attestation_id)
+            .cloned();
+        if recent.is_some() {
+            return Ok(recent);
+        }
+        let Sink::File { path, file } = &self.sink else {
+            return Ok(None);
+        };
+        let _file = file.lock().unwrap_or_else(PoisonError::into_inner);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file =
+            fs::File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            // Skip the lines of other attestations without parsing them.
+            if !line.contains(attestation_id) {
+                continue;
+            }
+            let record = serde_json::from_str::<AuditRecord>(&line)
+                .map_err(|e| anyhow!("Malformed audit record: {e}"))?;
+            if record.attestation_id == attestation_id {
+                return Ok(Some(record));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verifier::pipeline::Stage;
+    use crate::verifier::trace::CertificateChain;
+    use as_types::PolicyViolation;
+
+    #[tokio::test]
+    async fn test_audit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let config = AuditConfig {
+            sink: AuditSink::File { path: path.clone() },
+            max_records: 1,
+        };
+        let audit = Audit::new(config).unwrap();
+
+        let trace = audit.start(Some("a".to_string()), "tdx", Some("acme"), "{}");
+        trace.verified(VerificationTrace {
+            stages: vec![Stage::Parse, Stage::CollateralVerify],
+            chains: vec![CertificateChain {
+                name: "PCK".to_string(),
+                certificates: vec![b"not a certificate".to_vec()],
+            }],
+            ..Default::default()
+        });
+        trace.step(RejectionStage::Policy);
+        let denied = PolicyDenied {
+            violations: vec![PolicyViolation {
+                rule: "mr_td".to_string(),
+                claim: None,
+                value: None,
+                expected: None,
+                message: None,
+            }],
+        };
+        trace.policies(&[
+            (
+                "default".to_string(),
+                Ok(PolicyEvaluation {
+                    report: String::new(),
+                    policy_digest: String::new(),
+                }),
+            ),
+            ("strict".to_string(), Err(denied.into())),
+        ]);
+        trace.rejected(RejectionStage::Policy);
+        let result: Result<()> = Err(anyhow!("Untrusted TEE evidence"));
+        assert_eq!(audit.finish(trace, &result).await, "a");
+
+        let trace = audit.start(None, "snp", None, "{}");
+        trace.cached();
+        let b = audit.finish(trace, &Ok(())).await;
+
+        // `a` is no longer in memory, but in the file.
+        let a = audit.record("a").unwrap().unwrap();
+        assert_eq!(
+            a.evidence_digest,
+            format!("sha256:{}", hex::encode(Sha256::digest("{}")))
+        );
+        assert_eq!(a.steps, ["parse", "collateral_verify", "policy"]);
+        assert_eq!(
+            a.certificate_chains[0].certificates[0].fingerprint,
+            anchors::fingerprint(b"not a certificate")
+        );
+        assert_eq!(a.policies[0].decision, Some(PolicyDecision::Allow));
+        assert_eq!(a.policies[1].decision, Some(PolicyDecision::Deny));
+        assert_eq!(a.policies[1].failed_rules, ["mr_td"]);
+        assert_eq!(a.rejected_at.as_deref(), Some("policy"));
+        assert_eq!(a.error.as_deref(), Some("Untrusted TEE evidence"));
+        assert!(!a.passed);
+
+        let b = audit.record(&b).unwrap().unwrap();
+        assert!(b.passed && b.cached_verification);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        assert_eq!(audit.record("c").unwrap(), None);
+    }
+
+    #[test]
+    fn test_sink_messages() {
+        let record = AuditRecord {
+            attestation_id: "a".to_string(),
+            timestamp: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+            tee: "tdx".to_string(),
+            tenant: None,
+            evidence_digest: String::new(),
+            steps: Vec::new(),
+            cached_verification: false,
+            certificate_chains: Vec::new(),
+            policies: Vec::new(),
+            passed: false,
+            rejected_at: None,
+            error: None,
+        };
+        assert_eq!(
+            syslog_message(&record, "{}"),
+            format!(
+                "<109>1 2023-11-14T22:13:20+00:00 - attestation-service {} audit - {{}}",
+                std::process::id()
+            )
+        );
+        let logs = otlp_logs(&record, "{}");
+        let log = &logs["resourceLogs"][0]["scopeLogs"][0]["logRecords"][0];
+        assert_eq!(log["timeUnixNano"], "1700000000000000000");
+        assert_eq!(log["severityText"], "WARN");
+        assert_eq!(log["body"]["stringValue"], "{}");
+
+        let check = |sink| {
+            AuditConfig {
+                sink,
+                max_records: 10,
+            }
+            .check()
+        };
+        assert!(check(AuditSink::Otlp {
+            endpoint: "localhost:4318".to_string()
+        })
+        .is_err());
+        assert!(check(AuditSink::File {
+            path: PathBuf::from("/nonexistent/audit.jsonl")
+        })
+        .is_err());
+        assert!(check(AuditSink::Syslog {
+            socket: default_syslog_socket()
+        })
+        .is_ok());
+    }
+}
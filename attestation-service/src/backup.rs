@@ -0,0 +1,120 @@
+//! Backups of the mutable state of the AS.
+//!
+//! A backup holds the policies, every version of the policy data documents,
+//! the reference values of the integrated RVPS, the deny-list and the public
+//! token signing keys. It is exported as a token signed by the AS, so that it cannot be
+//! tampered with, and restored into the same AS, e.g. for disaster
+//! recovery, or into another one that trusts the public keys of the
+//! exporting AS in its `state_signers`, e.g. to clone production into
+//! staging. The archive has a `typ` claim of [`BACKUP_TYP`], so that no
+//! other token of the AS, such as an attestation token, is taken for one.
+//!
+//! The signing keys are only recorded for reference: private keys never
+//! leave the AS, and a restored AS keeps its own keys. The reference values
+//! of a remote RVPS are backed up with that RVPS.
+
+use crate::deny_list::DenyListEntry;
+use crate::policy_engine::PolicyEngineState;
+use crate::rvps::ReferenceValue;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// Version of the backup format.
+pub const BACKUP_VERSION: u32 = 1;
+
+/// Claim of the backup in the archive token.
+pub(crate) const BACKUP_CLAIM: &str = "backup";
+
+/// `typ` claim of the archive token.
+pub const BACKUP_TYP: &str = "as-state+jwt";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Backup {
+    pub version: u32,
+
+    #[serde(flatten)]
+    pub policy_engine: PolicyEngineState,
+
+    /// `None` if the reference values are held by a remote RVPS.
+    pub reference_values: Option<Vec<ReferenceValue>>,
+
+    /// `None` in backups of an AS without a deny-list, which leave the
+    /// deny-list as it is when restored.
+    #[serde(default)]
+    pub deny_list: Option<Vec<DenyListEntry>>,
+
+    /// Public token signing keys of the exporting AS, in JWKS format.
+    pub signing_keys: Value,
+}
+
+impl Backup {
+    /// Get the backup from the claims of a verified archive.
+    pub(crate) fn from_claims(claims: &Value) -> Result<Self> {
+        if claims["typ"] != BACKUP_TYP {
+            bail!("The token is not a backup archive");
+        }
+        let backup = claims
+            .get(BACKUP_CLAIM)
+            .context("The archive holds no backup")?;
+        let version = backup["version"].as_u64().unwrap_or_default();
+        if version != BACKUP_VERSION as u64 {
+            bail!("Unsupported backup version {version}, expected {BACKUP_VERSION}");
+        }
+        serde_json::from_value(backup.clone()).context("Malformed backup")
+    }
+}
+
+/// The public keys of each of the `state_signers` of the AS config, in
+/// JWKS format.
+pub(crate) fn signer_jwks(state_signers: &[PathBuf]) -> Result<Vec<String>> {
+    state_signers
+        .iter()
+        .map(|path| {
+            let jwks = std::fs::read_to_string(path)
+                .with_context(|| format!("Cannot read the keys {}", path.display()))?;
+            serde_json::from_str::<Value>(&jwks)
+                .ok()
+                .filter(|jwks| jwks["keys"].is_array())
+                .with_context(|| format!("{} is not a JWKS", path.display()))?;
+            Ok(jwks)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use as_types::PolicyData;
+    use serde_json::json;
+
+    #[test]
+    fn test_from_claims() {
+        let backup = Backup {
+            version: BACKUP_VERSION,
+            policy_engine: PolicyEngineState {
+                policies: [("default".to_string(), "package policy".to_string())].into(),
+                policy_data: vec![PolicyData {
+                    name: "platforms".to_string(),
+                    version: 3,
+                    data: json!({"allowed_fmspcs": []}),
+                }],
+                ..Default::default()
+            },
+            reference_values: Some(vec![ReferenceValue::new().unwrap().set_name("kernel")]),
+            deny_list: Some(Vec::new()),
+            signing_keys: json!({"keys": []}),
+        };
+
+        let claims = json!({ "typ": BACKUP_TYP, BACKUP_CLAIM: backup });
+        assert_eq!(Backup::from_claims(&claims).unwrap(), backup);
+
+        assert!(Backup::from_claims(&json!({ "typ": BACKUP_TYP })).is_err());
+        // An attestation token with a backup claim is not an archive.
+        assert!(Backup::from_claims(&json!({ BACKUP_CLAIM: backup })).is_err());
+        let mut claims = claims;
+        claims[BACKUP_CLAIM]["version"] = json!(BACKUP_VERSION + 1);
+        assert!(Backup::from_claims(&claims).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+//! What an AS supports, for orchestration tooling to pick request
+//! parameters that the AS it talks to accepts, instead of hard-coding them
+//! per environment: the verifiers it is built with or that are registered,
+//! see [`crate::verifier::registry`], and whether its config
+//! lets them verify evidence, the evidence format versions and freshness
+//! methods they accept, its policy engines and its token formats.
+
+use crate::config::Config;
+use crate::fips;
+use crate::policy_engine::PolicyEngineType;
+use crate::posture;
+use crate::token::AttestationTokenBrokerType;
+use crate::verifier::freshness::FreshnessMethod;
+use crate::verifier::registry::VerifierRegistry;
+use crate::verifier::VersionRange;
+use kbs_types::Tee;
+use strum::VariantNames;
+
+/// TEEs whose verifier the AS is built with.
+const COMPILED_VERIFIERS: &[(Tee, bool)] = &[
+    (Tee::Sample, true),
+    (Tee::Tdx, cfg!(feature = "tdx-verifier")),
+    (Tee::Sgx, cfg!(feature = "sgx-verifier")),
+    (Tee::Snp, cfg!(feature = "snp-verifier")),
+    (Tee::AzSnpVtpm, cfg!(feature = "az-snp-vtpm-verifier")),
+    (Tee::Csv, cfg!(feature = "csv-verifier")),
+    (Tee::Cca, cfg!(feature = "cca-verifier")),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    pub verifiers: Vec<VerifierCapabilities>,
+    /// Policy engines the AS is built with.
+    pub policy_engines: Vec<String>,
+    /// Policy engine of the config.
+    pub policy_engine: String,
+    /// Token brokers the AS is built with.
+    pub token_brokers: Vec<String>,
+    /// Token broker of the config.
+    pub token_broker: String,
+    /// Format of the tokens of the token broker, e.g. `JWT`.
+    pub token_format: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifierCapabilities {
+    /// TEE name, as in the `tee` of attestation requests, e.g. `tdx`.
+    pub tee: String,
+    /// Whether the verifier is registered at runtime rather than compiled
+    /// in.
+    pub registered: bool,
+    /// Whether evidence of the TEE is verified, it is not in FIPS mode when
+    /// it relies on algorithms that are not approved, nor for the sample TEE
+    /// when `sample_verifier` is off.
+    pub enabled: bool,
+    /// Accepted versions of each evidence format of the TEE.
+    pub evidence_versions: Vec<EvidenceFormat>,
+    /// Freshness methods of the evidence the verifier checks, strongest
+    /// first.
+    pub freshness_methods: Vec<FreshnessMethod>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EvidenceFormat {
+    /// Evidence format, e.g. `TD quote`.
+    pub format: &'static str,
+    pub accepted: VersionRange,
+}
+
+impl EvidenceFormat {
+    fn new(format: &'static str, accepted: VersionRange) -> Self {
+        Self { format, accepted }
+    }
+}
+
+/// Whether evidence of `tee` is verified under `config`.
+fn enabled(tee: &Tee, config: &Config) -> bool {
+    (!config.fips_mode() || fips::check_tee(tee).is_ok()) && posture::check_tee(config, tee).is_ok()
+}
+
+/// Evidence formats of `tee` with their versions accepted under `config`.
+fn evidence_versions(tee: &Tee, config: &Config) -> Vec<EvidenceFormat> {
+    let versions = &config.evidence_versions;
+    match tee {
+        Tee::Tdx => vec![
+            EvidenceFormat::new("TD quote", versions.tdx),
+            EvidenceFormat::new("SGX quote", versions.sgx),
+        ],
+        Tee::Sgx => vec![EvidenceFormat::new("SGX quote", versions.sgx)],
+        #[cfg(feature = "snp-verifier")]
+        Tee::Snp => vec![EvidenceFormat::new(
+            "SNP attestation report",
+            versions.snp.or(crate::verifier::snp::REPORT_VERSIONS),
+        )],
+        Tee::AzSnpVtpm => vec![
+            EvidenceFormat::new("SNP attestation report", versions.az_snp_vtpm),
+            EvidenceFormat::new("TD quote", versions.tdx),
+        ],
+        _ => vec![],
+    }
+}
+
+pub(crate) fn capabilities(config: &Config, registry: &VerifierRegistry) -> Capabilities {
+    let verifier_config = config.verifier_config();
+    let compiled = COMPILED_VERIFIERS
+        .iter()
+        .filter(|(tee, compiled)| *compiled && !registry.is_registered(tee))
+        .map(|(tee, _)| (tee.clone(), false));
+    let registered = registry.registered().into_iter().map(|tee| (tee, true));
+    let verifiers = compiled
+        .chain(registered)
+        .map(|(tee, registered)| VerifierCapabilities {
+            tee: serde_variant::to_variant_name(&tee)
+                .unwrap_or_default()
+                .to_string(),
+            registered,
+            enabled: enabled(&tee, config),
+            evidence_versions: match registered {
+                true => vec![],
+                false => evidence_versions(&tee, config),
+            },
+            freshness_methods: registry
+                .to_verifier(&tee, &verifier_config, None)
+                .map(|verifier| verifier.freshness_methods())
+                .unwrap_or_default(),
+        })
+        .collect();
+
+    Capabilities {
+        verifiers,
+        policy_engines: names(PolicyEngineType::VARIANTS),
+        policy_engine: config.policy_engine.clone(),
+        token_brokers: names(AttestationTokenBrokerType::VARIANTS),
+        token_broker: format!("{:?}", config.attestation_token_broker),
+        token_format: match config.attestation_token_config.co_signers.is_empty() {
+            true => config.attestation_token_broker.token_format().to_string(),
+            false => "JWS JSON".to_string(),
+        },
+    }
+}
+
+fn names(variants: &[&str]) -> Vec<String> {
+    variants.iter().map(|name| name.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verifier::report_data::ReportDataMode;
+    use crate::verifier::sample::Sample;
+    use crate::verifier::{Verifier, VerifierConfig};
+
+    #[test]
+    fn test_capabilities() {
+        let mut config = Config::default();
+        config.evidence_versions.sgx = VersionRange {
+            min: Some(3),
+            max: None,
+            deprecated_below: None,
+        };
+        let capabilities = capabilities(&config, &VerifierRegistry::default());
+
+        let sample = &capabilities.verifiers[0];
+        assert_eq!(sample.tee, "sample");
+        assert!(!sample.registered);
+        assert!(sample.enabled);
+        assert!(sample.evidence_versions.is_empty());
+        assert_eq!(
+            sample.freshness_methods,
+            vec![FreshnessMethod::Nonce, FreshnessMethod::Timestamp]
+        );
+        assert_eq!(capabilities.policy_engines, vec!["OPA".to_string()]);
+        assert_eq!(capabilities.token_brokers, vec!["Simple".to_string()]);
+        assert_eq!(capabilities.token_format, "JWT");
+
+        assert_eq!(
+            evidence_versions(&Tee::Sgx, &config),
+            vec![EvidenceFormat::new(
+                "SGX quote",
+                VersionRange {
+                    min: Some(3),
+                    max: None,
+                    deprecated_below: None,
+                }
+            )]
+        );
+
+        if !fips::FIPS_BUILD {
+            assert!(enabled(&Tee::Csv, &config));
+        }
+        config.fips_mode = true;
+        assert!(!enabled(&Tee::Csv, &config));
+        assert!(enabled(&Tee::Tdx, &config));
+    }
+
+    fn sample(
+        _config: &VerifierConfig,
+        _report_data: ReportDataMode,
+    ) -> anyhow::Result<Box<dyn Verifier + Send + Sync>> {
+        Ok(Box::<Sample>::default())
+    }
+
+    #[test]
+    fn test_registered_verifiers() {
+        let mut registry = VerifierRegistry::default();
+        registry.register(Tee::Sev, sample).unwrap();
+        registry.register(Tee::Sample, sample).unwrap();
+        let capabilities = capabilities(&Config::default(), &registry);
+
+        let registered: Vec<_> = capabilities
+            .verifiers
+            .iter()
+            .filter(|verifier| verifier.registered)
+            .map(|verifier| verifier.tee.as_str())
+            .collect();
+        assert_eq!(registered, ["sample", "sev"]);
+        let sev = capabilities
+            .verifiers
+            .iter()
+            .find(|verifier| verifier.tee == "sev");
+        assert_eq!(
+            sev.unwrap().freshness_methods,
+            vec![FreshnessMethod::Nonce, FreshnessMethod::Timestamp]
+        );
+        // The registered verifier takes over the compiled in one.
+        assert_eq!(
+            capabilities
+                .verifiers
+                .iter()
+                .filter(|verifier| verifier.tee == "sample")
+                .count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,532 @@
+//! X.509 certificates issued on attestation, for attested TLS.
+//!
+//! An attester can send a CSR with its evidence. If the evidence verifies
+//! and the policy allows it, the AS also issues a short-lived certificate
+//! for the key of the CSR, which must be the TEE public key that the
+//! report data of the evidence binds. Relying parties can then trust the
+//! TLS connections of the workload to the CA, without a CA integration of
+//! their own. The selected claims of the evidence are embedded in the
+//! certificate, as a JSON object in a UTF8String extension of OID
+//! [`CLAIMS_OID`].
+//!
+//! The attester proves nothing about the names of its CSR, so they are
+//! ignored: the subject of the certificates is the configured common name,
+//! or the value of a verified claim, and their subject alternative names
+//! are the configured DNS names and the `ni:` URI (RFC 6920) of the TEE
+//! public key.
+//!
+//! The certificates are signed by a built-in CA, a CA certificate and
+//! private key in PEM files, or by an external CA through a command that
+//! is given the issuance request as JSON on its standard input,
+//!
+//! ```json
+//! {
+//!     "csr": "<PEM>",
+//!     "subject": "<common name>",
+//!     "san": ["DNS:workload.example.com", "URI:ni:///sha-256;..."],
+//!     "claims": { ... },
+//!     "validity_secs": 3600
+//! }
+//! ```
+//!
+//! and writes the PEM certificate chain on its standard output. The command
+//! must use the given subject and names rather than those of the CSR.
+//!
+//! Issuance needs the `cert-issuer` feature.
+
+use anyhow::*;
+use kbs_types::TeePubKey;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::PathBuf;
+
+#[cfg(feature = "cert-issuer")]
+use {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD,
+    base64::Engine,
+    openssl::asn1::{Asn1Object, Asn1OctetString, Asn1Time},
+    openssl::bn::{BigNum, MsbOption},
+    openssl::hash::MessageDigest,
+    openssl::nid::Nid,
+    openssl::pkey::{PKey, Private, Public},
+    openssl::sha::sha256,
+    openssl::x509::extension::{
+        AuthorityKeyIdentifier, BasicConstraints, ExtendedKeyUsage, KeyUsage,
+        SubjectAlternativeName, SubjectKeyIdentifier,
+    },
+    openssl::x509::{X509Builder, X509Extension, X509NameBuilder, X509Req, X509},
+    serde_json::{json, Map},
+    std::process::Stdio,
+    std::time::Duration,
+    tokio::io::AsyncWriteExt,
+    tokio::process::Command,
+};
+
+/// OID of the extension of the embedded claims.
+pub const CLAIMS_OID: &str = "2.25.263723788370929272651179899926517300742";
+
+/// How long a CA command may take.
+#[cfg(feature = "cert-issuer")]
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn default_validity_secs() -> u64 {
+    3600
+}
+
+/// Common name of the certificates without a configured one.
+const DEFAULT_COMMON_NAME: &str = "Attested workload";
+
+fn default_common_name() -> String {
+    DEFAULT_COMMON_NAME.to_string()
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CaConfig {
+    /// A CA certificate and its private key, PEM files.
+    Builtin { cert: PathBuf, key: PathBuf },
+
+    /// A command that issues the certificates.
+    Command { command: Vec<String> },
+}
+
+/// Configuration of the certificate issuance, e.g.
+///
+/// ```json
+/// {
+///     "type": "builtin",
+///     "cert": "/etc/as/ca.pem",
+///     "key": "/etc/as/ca-key.pem",
+///     "subject_claim": "tdx.quote.body.mr_td",
+///     "dns_names": ["workload.example.com"],
+///     "claims": ["tdx.quote.body.mr_td", "tdx.quote.body.rtmr_*"]
+/// }
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CertificateIssuerConfig {
+    #[serde(flatten)]
+    pub ca: CaConfig,
+
+    /// Validity of the certificates, in seconds.
+    #[serde(default = "default_validity_secs")]
+    pub validity_secs: u64,
+
+    /// Flattened claims embedded in the certificates. A name ending with
+    /// `*` matches any claim with that prefix.
+    #[serde(default)]
+    pub claims: Vec<String>,
+
+    /// Common name of the certificates, unless `subject_claim` is set.
+    #[serde(default = "default_common_name")]
+    pub common_name: String,
+
+    /// Flattened claim whose value is the common name of the certificates,
+    /// e.g. `tdx.quote.body.mr_td`. Issuance fails if the evidence has no
+    /// such claim.
+    #[serde(default)]
+    pub subject_claim: Option<String>,
+
+    /// DNS names of the subject alternative names of the certificates.
+    #[serde(default)]
+    pub dns_names: Vec<String>,
+}
+
+#[cfg(feature = "cert-issuer")]
+enum Ca {
+    Builtin { cert: X509, key: PKey<Private> },
+    Command(Vec<String>),
+}
+
+#[cfg_attr(not(feature = "cert-issuer"), allow(dead_code))]
+pub(crate) struct CertificateIssuer {
+    #[cfg(feature = "cert-issuer")]
+    ca: Ca,
+    config: CertificateIssuerConfig,
+}
+
+impl CertificateIssuer {
+    #[cfg(feature = "cert-issuer")]
+    pub fn new(config: &CertificateIssuerConfig) -> Result<Self> {
+        let ca = match &config.ca {
+            CaConfig::Builtin { cert, key } => {
+                let read = |path: &PathBuf| {
+                    std::fs::read(path).with_context(|| format!("read {}", path.display()))
+                };
+                Ca::Builtin {
+                    cert: X509::from_pem(&read(cert)?).context("Malformed CA certificate")?,
+                    key: PKey::private_key_from_pem(&read(key)?)
+                        .context("Malformed CA private key")?,
+                }
+            }
+            CaConfig::Command { command } => {
+                if command.is_empty() {
+                    bail!("empty CA command");
+                }
+                Ca::Command(command.clone())
+            }
+        };
+        Ok(Self {
+            ca,
+            config: config.clone(),
+        })
+    }
+
+    #[cfg(not(feature = "cert-issuer"))]
+    pub fn new(_config: &CertificateIssuerConfig) -> Result<Self> {
+        bail!("The AS is built without the `cert-issuer` feature")
+    }
+
+    /// The claims of `claims`, flattened, to embed in the certificates.
+    #[cfg(feature = "cert-issuer")]
+    fn selected_claims(&self, claims: &Value) -> Map<String, Value> {
+        claims
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(name, _)| {
+                self.config
+                    .claims
+                    .iter()
+                    .any(|pattern| crate::token::chain::matches(pattern, name))
+            })
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Issue a certificate for the key of `csr`, a PEM CSR, which must be
+    /// `tee_pubkey`, embedding the selected `claims`. Return the PEM
+    /// certificate chain.
+    #[cfg(feature = "cert-issuer")]
+    pub async fn issue(&self, csr: &str, tee_pubkey: &TeePubKey, claims: &Value) -> Result<String> {
+        let request = X509Req::from_pem(csr.as_bytes()).context("Malformed CSR")?;
+        let public_key = request.public_key()?;
+        if !request.verify(&public_key)? {
+            bail!("CSR signature verification failed");
+        }
+        check_binding(&public_key, tee_pubkey)?;
+        let subject = self.common_name(claims)?;
+        let san = self.san(&public_key)?;
+        let claims = self.selected_claims(claims);
+
+        match &self.ca {
+            Ca::Builtin { cert, key } => {
+                let issued = self.sign(&subject, &san, &public_key, &claims, cert, key)?;
+                let mut chain = issued.to_pem()?;
+                chain.extend(cert.to_pem()?);
+                Ok(String::from_utf8(chain)?)
+            }
+            Ca::Command(command) => {
+                let input = json!({
+                    "csr": csr,
+                    "subject": subject,
+                    "san": san,
+                    "claims": claims,
+                    "validity_secs": self.config.validity_secs,
+                });
+                let chain = run_command(command, input.to_string().as_bytes()).await?;
+                if X509::stack_from_pem(chain.as_bytes()).map_or(true, |certs| certs.is_empty()) {
+                    bail!("The CA command returned no PEM certificate");
+                }
+                Ok(chain)
+            }
+        }
+    }
+
+    #[cfg(not(feature = "cert-issuer"))]
+    pub async fn issue(
+        &self,
+        _csr: &str,
+        _tee_pubkey: &TeePubKey,
+        _claims: &Value,
+    ) -> Result<String> {
+        bail!("The AS is built without the `cert-issuer` feature")
+    }
+
+    /// The common name of the certificate of the evidence with `claims`.
+    #[cfg(feature = "cert-issuer")]
+    fn common_name(&self, claims: &Value) -> Result<String> {
+        let Some(claim) = &self.config.subject_claim else {
+            return Ok(self.config.common_name.clone());
+        };
+        match claims.get(claim) {
+            Some(Value::String(value)) => Ok(value.clone()),
+            Some(value) => Ok(value.to_string()),
+            None => bail!("The evidence has no {claim} claim to name the certificate"),
+        }
+    }
+
+    /// The subject alternative names of the certificate of `public_key`, as
+    /// `DNS:` and `URI:` entries.
+    #[cfg(feature = "cert-issuer")]
+    fn san(&self, public_key: &PKey<Public>) -> Result<Vec<String>> {
+        let mut san: Vec<String> = self
+            .config
+            .dns_names
+            .iter()
+            .map(|name| format!("DNS:{name}"))
+            .collect();
+        san.push(format!("URI:{}", key_uri(public_key)?));
+        Ok(san)
+    }
+
+    #[cfg(feature = "cert-issuer")]
+    fn sign(
+        &self,
+        subject: &str,
+        san: &[String],
+        public_key: &PKey<Public>,
+        claims: &Map<String, Value>,
+        ca_cert: &X509,
+        ca_key: &PKey<Private>,
+    ) -> Result<X509> {
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let mut serial = BigNum::new()?;
+        serial.rand(127, MsbOption::MAYBE_ZERO, false)?;
+        let serial = serial.to_asn1_integer()?;
+        let not_before = Asn1Time::from_unix(now)?;
+        let not_after = Asn1Time::from_unix(now + self.config.validity_secs as i64)?;
+        let claims_oid = Asn1Object::from_str(CLAIMS_OID)?;
+        let claims =
+            Asn1OctetString::new_from_bytes(&der_utf8_string(&serde_json::to_string(claims)?))?;
+
+        let mut name = X509NameBuilder::new()?;
+        name.append_entry_by_nid(Nid::COMMONNAME, subject)?;
+        let name = name.build();
+
+        let mut builder = X509Builder::new()?;
+        builder.set_version(2)?;
+        builder.set_serial_number(&serial)?;
+        builder.set_subject_name(&name)?;
+        builder.set_issuer_name(ca_cert.subject_name())?;
+        builder.set_pubkey(public_key)?;
+        builder.set_not_before(&not_before)?;
+        builder.set_not_after(&not_after)?;
+        builder.append_extension(BasicConstraints::new().critical().build()?)?;
+        builder.append_extension(
+            KeyUsage::new()
+                .critical()
+                .digital_signature()
+                .key_encipherment()
+                .build()?,
+        )?;
+        builder.append_extension(
+            ExtendedKeyUsage::new()
+                .server_auth()
+                .client_auth()
+                .build()?,
+        )?;
+        let mut alternative_names = SubjectAlternativeName::new();
+        for name in san {
+            match name.split_once(':') {
+                Some(("DNS", dns)) => alternative_names.dns(dns),
+                Some(("URI", uri)) => alternative_names.uri(uri),
+                _ => bail!("Unknown subject alternative name {name}"),
+            };
+        }
+        let extension = alternative_names.build(&builder.x509v3_context(Some(ca_cert), None))?;
+        builder.append_extension(extension)?;
+        let extension =
+            SubjectKeyIdentifier::new().build(&builder.x509v3_context(Some(ca_cert), None))?;
+        builder.append_extension(extension)?;
+        let extension = AuthorityKeyIdentifier::new()
+            .keyid(false)
+            .issuer(false)
+            .build(&builder.x509v3_context(Some(ca_cert), None))?;
+        builder.append_extension(extension)?;
+        builder.append_extension(X509Extension::new_from_der(&claims_oid, false, &claims)?)?;
+        builder.sign(ca_key, MessageDigest::sha384())?;
+        Ok(builder.build())
+    }
+}
+
+/// Check that `public_key` is the TEE public key, with its base64url
+/// modulus and exponent.
+#[cfg(feature = "cert-issuer")]
+fn check_binding(public_key: &PKey<Public>, tee_pubkey: &TeePubKey) -> Result<()> {
+    let rsa = public_key
+        .rsa()
+        .map_err(|_| anyhow!("The CSR key is not an RSA key"))?;
+    let component = |value: &str| -> Result<Vec<u8>> {
+        Ok(BigNum::from_slice(&URL_SAFE_NO_PAD.decode(value)?)?.to_vec())
+    };
+    if rsa.n().to_vec() != component(&tee_pubkey.k_mod)?
+        || rsa.e().to_vec() != component(&tee_pubkey.k_exp)?
+    {
+        bail!("The CSR key is not the TEE public key");
+    }
+    Ok(())
+}
+
+/// The `ni:` URI of the SHA-256 digest of the DER `public_key`, RFC 6920.
+#[cfg(feature = "cert-issuer")]
+fn key_uri(public_key: &PKey<Public>) -> Result<String> {
+    let digest = sha256(&public_key.public_key_to_der()?);
+    Ok(format!("ni:///sha-256;{}", URL_SAFE_NO_PAD.encode(digest)))
+}
+
+/// DER encoding of `value` as an UTF8String.
+#[cfg(feature = "cert-issuer")]
+fn der_utf8_string(value: &str) -> Vec<u8> {
+    let mut der = vec![0x0c];
+    let length = value.len();
+    if length < 0x80 {
+        der.push(length as u8);
+    } else {
+        let bytes: Vec<u8> = length
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|byte| *byte == 0)
+            .collect();
+        der.push(0x80 | bytes.len() as u8);
+        der.extend(bytes);
+    }
+    der.extend(value.as_bytes());
+    der
+}
+
+#[cfg(feature = "cert-issuer")]
+async fn run_command(command: &[String], input: &[u8]) -> Result<String> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("run CA command {}", command[0]))?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("No stdin of the CA command"))?;
+    stdin.write_all(input).await?;
+    drop(stdin);
+
+    let output = tokio::time::timeout(COMMAND_TIMEOUT, child.wait_with_output())
+        .await
+        .map_err(|_| anyhow!("The CA command timed out"))??;
+    if !output.status.success() {
+        bail!("The CA command failed with {}", output.status);
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+#[cfg(all(test, feature = "cert-issuer"))]
+mod tests {
+    use super::*;
+    use openssl::nid::Nid;
+    use openssl::rsa::Rsa;
+    use openssl::x509::{X509NameBuilder, X509ReqBuilder};
+
+    fn name(common_name: &str) -> openssl::x509::X509Name {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, common_name)
+            .unwrap();
+        name.build()
+    }
+
+    fn csr(key: &PKey<Private>) -> String {
+        let mut builder = X509ReqBuilder::new().unwrap();
+        builder.set_subject_name(&name("workload")).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder.sign(key, MessageDigest::sha256()).unwrap();
+        String::from_utf8(builder.build().to_pem().unwrap()).unwrap()
+    }
+
+    fn tee_pubkey(key: &PKey<Private>) -> TeePubKey {
+        let rsa = key.rsa().unwrap();
+        TeePubKey {
+            kty: "RSA".to_string(),
+            alg: "RSA1_5".to_string(),
+            k_mod: URL_SAFE_NO_PAD.encode(rsa.n().to_vec()),
+            k_exp: URL_SAFE_NO_PAD.encode(rsa.e().to_vec()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_issue() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut ca_cert = X509Builder::new().unwrap();
+        ca_cert.set_subject_name(&name("AS CA")).unwrap();
+        ca_cert.set_issuer_name(&name("AS CA")).unwrap();
+        ca_cert.set_pubkey(&ca_key).unwrap();
+        let key_id = SubjectKeyIdentifier::new()
+            .build(&ca_cert.x509v3_context(None, None))
+            .unwrap();
+        ca_cert.append_extension(key_id).unwrap();
+        ca_cert
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        ca_cert
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        ca_cert.sign(&ca_key, MessageDigest::sha256()).unwrap();
+        let ca_cert = ca_cert.build();
+        std::fs::write(dir.path().join("ca.pem"), ca_cert.to_pem().unwrap()).unwrap();
+        std::fs::write(
+            dir.path().join("ca-key.pem"),
+            ca_key.private_key_to_pem_pkcs8().unwrap(),
+        )
+        .unwrap();
+
+        let issuer = CertificateIssuer::new(&CertificateIssuerConfig {
+            ca: CaConfig::Builtin {
+                cert: dir.path().join("ca.pem"),
+                key: dir.path().join("ca-key.pem"),
+            },
+            validity_secs: 600,
+            claims: vec!["tdx.quote.body.mr_td".to_string()],
+            common_name: DEFAULT_COMMON_NAME.to_string(),
+            subject_claim: Some("tdx.quote.body.mr_td".to_string()),
+            dns_names: vec!["workload.example.com".to_string()],
+        })
+        .unwrap();
+
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let claims = serde_json::json!({
+            "tdx.quote.body.mr_td": "aa",
+            "tdx.quote.body.report_data": "bb",
+        });
+        let chain = issuer
+            .issue(&csr(&key), &tee_pubkey(&key), &claims)
+            .await
+            .unwrap();
+        let certs = X509::stack_from_pem(chain.as_bytes()).unwrap();
+        assert_eq!(certs.len(), 2);
+        assert!(certs[0].verify(&ca_key).unwrap());
+        assert!(certs[0].public_key().unwrap().public_eq(&key));
+        let der = certs[0].to_der().unwrap();
+        let embedded = der_utf8_string(r#"{"tdx.quote.body.mr_td":"aa"}"#);
+        assert!(der.windows(embedded.len()).any(|window| window == embedded));
+
+        // Named from the claims and config, not from the CSR.
+        let common_name = certs[0]
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .unwrap()
+            .data()
+            .as_utf8()
+            .unwrap()
+            .to_string();
+        assert_eq!(common_name, "aa");
+        let san = certs[0].subject_alt_names().unwrap();
+        assert_eq!(san.len(), 2);
+        assert_eq!(san.get(0).unwrap().dnsname(), Some("workload.example.com"));
+        let key_uri = key_uri(&certs[0].public_key().unwrap()).unwrap();
+        assert_eq!(san.get(1).unwrap().uri(), Some(key_uri.as_str()));
+        assert!(certs[0].authority_key_id().is_some());
+
+        // The claim that names the certificate is required.
+        assert!(issuer
+            .issue(&csr(&key), &tee_pubkey(&key), &serde_json::json!({}))
+            .await
+            .is_err());
+
+        // The CSR key must be the TEE key.
+        let other = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        assert!(issuer
+            .issue(&csr(&key), &tee_pubkey(&other), &claims)
+            .await
+            .is_err());
+    }
+}
@@ -4,14 +4,55 @@ import "C"
 
 import (
 	"context"
+	"crypto/sha256"
 	"encoding/json"
+	"sync"
 
+	"github.com/open-policy-agent/opa/ast"
 	"github.com/open-policy-agent/opa/rego"
 	"github.com/open-policy-agent/opa/storage/inmem"
 )
 
+// Most compiled policies kept. The cache is emptied when full, policies
+// being replaced far less often than they are evaluated.
+const maxCompiled = 64
+
+// Compiled policy modules by digest of their text, so that a policy is
+// parsed and compiled once, not on every evaluation. A compiler is only
+// read once compiled, and is shared by concurrent evaluations.
+var (
+	compiledMu sync.Mutex
+	compiled   = make(map[[sha256.Size]byte]*ast.Compiler)
+)
+
+func compile(policy string, library string) (*ast.Compiler, error) {
+	key := sha256.Sum256([]byte(policy + "\x00" + library))
+	compiledMu.Lock()
+	compiler, ok := compiled[key]
+	compiledMu.Unlock()
+	if ok {
+		return compiler, nil
+	}
+
+	compiler, err := ast.CompileModules(map[string]string{
+		"policy.rego":             policy,
+		"attestation/timing.rego": library,
+	})
+	if err != nil {
+		return nil, err
+	}
+
+	compiledMu.Lock()
+	defer compiledMu.Unlock()
+	if len(compiled) >= maxCompiled {
+		compiled = make(map[[sha256.Size]byte]*ast.Compiler)
+	}
+	compiled[key] = compiler
+	return compiler, nil
+}
+
 //export evaluateGo
-func evaluateGo(policy string, data string, input string) *C.char {
+func evaluateGo(policy string, library string, data string, input string) *C.char {
 	// Deserialize the message in json format
 	input_map := make(map[string]interface{})
 	err := json.Unmarshal([]byte(input), &input_map)
@@ -28,10 +69,15 @@ func evaluateGo(policy string, data string, input string) *C.char {
 	// in-memory store containing the supplied data.
 	store := inmem.NewFromObject(data_map)
 
+	compiler, err := compile(policy, library)
+	if err != nil {
+		return C.CString("Error:: " + err.Error())
+	}
+
 	// Construct a Rego object that can be prepared or evaluated.
 	r := rego.New(
 		rego.Query("input;data.policy"),
-		rego.Module("policy.rego", policy),
+		rego.Compiler(compiler),
 		rego.Store(store),
 	)
 
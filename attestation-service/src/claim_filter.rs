@@ -0,0 +1,200 @@
+//! Filtering of the claims of tokens, per tenant and policy.
+//!
+//! The token of an attestation has every claim of the evidence in its
+//! `tcb-status`, such as the report data and the full kernel command line,
+//! which a multi-tenant AS may not want to disclose to the relying parties
+//! of a tenant. With `claim_filters` in the AS config, the claims of the
+//! tokens of some tenants or policies are redacted, their value replaced
+//! with `<redacted>`, or omitted:
+//!
+//! ```json
+//! "claim_filters": [
+//!     {
+//!         "tenants": ["acme"],
+//!         "redact": ["tdx.quote.body.report_data"],
+//!         "omit": ["tdx.ccel.kernel_parameters*", "tdx.ccel.kernel_cmdline"]
+//!     },
+//!     { "policy_ids": ["confidential"], "omit": ["init_data.data.*"] }
+//! ]
+//! ```
+//!
+//! Every filter whose `tenants` has the tenant of the request, and whose
+//! `policy_ids` has one of the policies evaluated, applies, an empty list
+//! matching any. A claim name ending with `*` matches any claim with that
+//! prefix, and a claim both redacted and omitted is omitted. The policies
+//! are evaluated with all the claims: the filters only apply to the token,
+//! and to the claims logged at `Info` level, see [`crate::claims_log`].
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::token::chain::matches;
+
+/// Value of a redacted claim.
+pub const REDACTED: &str = "<redacted>";
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClaimFilter {
+    /// Tenants the filter applies to, every tenant if empty.
+    #[serde(default)]
+    pub tenants: Vec<String>,
+
+    /// Policies the filter applies to, when one of them is evaluated,
+    /// every policy if empty.
+    #[serde(default)]
+    pub policy_ids: Vec<String>,
+
+    /// Claims whose value is replaced with `<redacted>`.
+    #[serde(default)]
+    pub redact: Vec<String>,
+
+    /// Claims left out.
+    #[serde(default)]
+    pub omit: Vec<String>,
+}
+
+impl ClaimFilter {
+    pub fn check(&self) -> Result<()> {
+        if self.redact.is_empty() && self.omit.is_empty() {
+            bail!("The filter neither redacts nor omits claims");
+        }
+        for pattern in self.redact.iter().chain(&self.omit) {
+            if pattern.is_empty() || pattern.trim_end_matches('*').contains('*') {
+                bail!("Invalid claim pattern {pattern:?}, only a trailing * is supported");
+            }
+        }
+        Ok(())
+    }
+
+    fn applies_to(&self, tenant: Option<&str>, policy_ids: &[String]) -> bool {
+        let tenant_applies = self.tenants.is_empty()
+            || tenant.is_some_and(|tenant| self.tenants.iter().any(|t| t == tenant));
+        let policy_applies =
+            self.policy_ids.is_empty() || self.policy_ids.iter().any(|p| policy_ids.contains(p));
+        tenant_applies && policy_applies
+    }
+}
+
+/// The filters of a request, see [`select`].
+#[derive(Debug, Clone, Default)]
+pub struct ClaimFilters<'a> {
+    filters: Vec<&'a ClaimFilter>,
+}
+
+/// The `filters` that apply to a request of `tenant`, whose claims are
+/// evaluated by the policies `policy_ids`.
+pub fn select<'a>(
+    filters: &'a [ClaimFilter],
+    tenant: Option<&str>,
+    policy_ids: &[String],
+) -> ClaimFilters<'a> {
+    ClaimFilters {
+        filters: filters
+            .iter()
+            .filter(|filter| filter.applies_to(tenant, policy_ids))
+            .collect(),
+    }
+}
+
+impl ClaimFilters<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// The flattened `claims`, filtered.
+    pub fn apply(&self, claims: &Value) -> Value {
+        let Value::Object(claims) = claims else {
+            return claims.clone();
+        };
+        let matches_any =
+            |patterns: &[String], name: &str| patterns.iter().any(|pattern| matches(pattern, name));
+        Value::Object(
+            claims
+                .iter()
+                .filter(|(name, _)| {
+                    !self
+                        .filters
+                        .iter()
+                        .any(|filter| matches_any(&filter.omit, name))
+                })
+                .map(|(name, value)| {
+                    match self
+                        .filters
+                        .iter()
+                        .any(|filter| matches_any(&filter.redact, name))
+                    {
+                        true => (name.clone(), Value::String(REDACTED.to_string())),
+                        false => (name.clone(), value.clone()),
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_filters() {
+        let filters: Vec<ClaimFilter> = serde_json::from_value(json!([
+            {
+                "tenants": ["acme"],
+                "redact": ["tdx.quote.body.report_data", "tdx.ccel.kernel_cmdline"],
+                "omit": ["tdx.ccel.kernel_parameters*", "tdx.ccel.kernel_cmdline"]
+            },
+            { "policy_ids": ["confidential"], "omit": ["init_data.data.*"] }
+        ]))
+        .unwrap();
+        for filter in &filters {
+            filter.check().unwrap();
+        }
+        let claims = json!({
+            "tdx.quote.body.report_data": "00ff",
+            "tdx.quote.body.mr_td": "705e",
+            "tdx.ccel.kernel_parameters.console": "hvc0",
+            "tdx.ccel.kernel_parameters.rw": null,
+            "tdx.ccel.kernel_cmdline": "console=hvc0 rw",
+            "init_data.data.policy.rego": "package agent_policy",
+        });
+
+        let acme = select(&filters, Some("acme"), &["default".to_string()]);
+        assert_eq!(
+            acme.apply(&claims),
+            json!({
+                "tdx.quote.body.report_data": REDACTED,
+                "tdx.quote.body.mr_td": "705e",
+                "init_data.data.policy.rego": "package agent_policy",
+            })
+        );
+
+        let confidential = select(&filters, None, &["confidential".to_string()]);
+        assert_eq!(
+            confidential.apply(&claims)["init_data.data.policy.rego"],
+            Value::Null
+        );
+        assert_eq!(confidential.apply(&claims).as_object().unwrap().len(), 5);
+
+        let other = select(&filters, Some("globex"), &["default".to_string()]);
+        assert!(other.is_empty());
+        assert_eq!(other.apply(&claims), claims);
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(ClaimFilter::default().check().is_err());
+        let filter = ClaimFilter {
+            omit: vec!["tdx.*.report_data".to_string()],
+            ..Default::default()
+        };
+        assert!(filter.check().is_err());
+        let filter = ClaimFilter {
+            redact: vec!["tdx.quote.*".to_string()],
+            ..Default::default()
+        };
+        filter.check().unwrap();
+    }
+}
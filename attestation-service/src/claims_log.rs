@@ -0,0 +1,168 @@
+//! Logging of the claims of verified evidence.
+//!
+//! The flattened claims of every evidence are logged as one line of JSON
+//! under the `attestation_service::claims` target, at `Debug` level by
+//! default so that production logs do not grow by the claims of every
+//! request. With `claims_log` in the AS config, the level can be changed,
+//! a fraction of the requests sampled, and the values of some claims
+//! redacted, e.g.
+//!
+//! ```json
+//! "claims_log": {
+//!     "level": "Info",
+//!     "sample_rate": 0.01,
+//!     "redact": ["tdx.quote.body.report_data", "tdx.ccel.kernel_parameters*"]
+//! }
+//! ```
+//!
+//! At `Info` level, the claims are also filtered as those of the token of
+//! the request, see [`crate::claim_filter`].
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::claim_filter::ClaimFilters;
+use crate::token::chain;
+
+/// Target of the claims logs, to filter them in or out with `RUST_LOG`.
+pub const CLAIMS_LOG_TARGET: &str = "attestation_service::claims";
+
+/// Value logged in place of a redacted claim.
+const REDACTED: &str = "<redacted>";
+
+fn default_sample_rate() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClaimsLogLevel {
+    /// The claims are not logged.
+    Off,
+    Info,
+    #[default]
+    Debug,
+    Trace,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ClaimsLogConfig {
+    #[serde(default)]
+    pub level: ClaimsLogLevel,
+
+    /// Fraction of the requests whose claims are logged, from 0 to 1.
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: f64,
+
+    /// Claims logged without their value. A name ending with `*` matches
+    /// any claim with that prefix.
+    #[serde(default)]
+    pub redact: Vec<String>,
+}
+
+impl Default for ClaimsLogConfig {
+    fn default() -> Self {
+        Self {
+            level: ClaimsLogLevel::default(),
+            sample_rate: default_sample_rate(),
+            redact: Vec::new(),
+        }
+    }
+}
+
+impl ClaimsLogConfig {
+    pub fn check(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.sample_rate) {
+            bail!("sample_rate {} is not within 0 and 1", self.sample_rate);
+        }
+        Ok(())
+    }
+
+    fn level(&self) -> Option<log::Level> {
+        match self.level {
+            ClaimsLogLevel::Off => None,
+            ClaimsLogLevel::Info => Some(log::Level::Info),
+            ClaimsLogLevel::Debug => Some(log::Level::Debug),
+            ClaimsLogLevel::Trace => Some(log::Level::Trace),
+        }
+    }
+
+    /// The claims to log, with the redacted ones masked.
+    fn redacted(&self, claims: &Value) -> Value {
+        let mut claims = claims.clone();
+        if let Some(claims) = claims.as_object_mut() {
+            for (name, value) in claims.iter_mut() {
+                if self
+                    .redact
+                    .iter()
+                    .any(|pattern| chain::matches(pattern, name))
+                {
+                    *value = Value::String(REDACTED.to_string());
+                }
+            }
+        }
+        claims
+    }
+
+    /// Log the flattened `claims` of evidence of `tee`, if the request is
+    /// sampled and the level enabled, with the `filters` of the request at
+    /// `Info` level.
+    pub fn log(&self, tee: &str, claims: &Value, filters: &ClaimFilters) {
+        let Some(level) = self.level() else {
+            return;
+        };
+        if !log_enabled!(target: CLAIMS_LOG_TARGET, level) {
+            return;
+        }
+        if self.sample_rate < 1.0 && rand::random::<f64>() >= self.sample_rate {
+            return;
+        }
+        let claims = match level {
+            log::Level::Info => filters.apply(claims),
+            _ => claims.clone(),
+        };
+        log!(
+            target: CLAIMS_LOG_TARGET,
+            level,
+            "Claims of {tee} evidence: {}",
+            self.redacted(&claims)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_redacted() {
+        let config: ClaimsLogConfig = serde_json::from_value(json!({
+            "redact": ["tdx.quote.body.report_data", "tdx.ccel.*"]
+        }))
+        .unwrap();
+        assert_eq!(config.level, ClaimsLogLevel::Debug);
+        assert_eq!(config.sample_rate, 1.0);
+        config.check().unwrap();
+
+        let claims = json!({
+            "tdx.quote.body.report_data": "00ff",
+            "tdx.quote.body.mr_td": "705e",
+            "tdx.ccel.kernel": "5b7a",
+        });
+        assert_eq!(
+            config.redacted(&claims),
+            json!({
+                "tdx.quote.body.report_data": REDACTED,
+                "tdx.quote.body.mr_td": "705e",
+                "tdx.ccel.kernel": REDACTED,
+            })
+        );
+
+        let config = ClaimsLogConfig {
+            sample_rate: 1.5,
+            ..config
+        };
+        assert!(config.check().is_err());
+    }
+}
@@ -1,18 +1,67 @@
+use crate::admission::AdmissionConfig;
+use crate::advisories::AdvisoryFeedConfig;
+use crate::audit::AuditConfig;
+use crate::certificate::{CertificateIssuer, CertificateIssuerConfig};
+use crate::claim_filter::ClaimFilter;
+use crate::claims_log::ClaimsLogConfig;
+use crate::decryption::{DecryptionKeyConfig, EvidenceDecryptor};
+use crate::failure_cache::FailureCacheConfig;
+use crate::history::HistoryConfig;
+use crate::hooks::{HookConfig, Hooks};
+use crate::host::{HostAgentConfig, HostAgents};
+use crate::nonces::{NonceConfig, NonceStoreType};
+use crate::policy_engine::signing::{PolicySignatureVerifier, PolicySigningConfig};
+use crate::policy_engine::{check_policy_id, DefaultPolicy, PolicyEngineType};
+use crate::replay::ReplayConfig;
+use crate::result_cache::ResultCacheConfig;
+use crate::self_test::PlatformProbeConfig;
+use crate::tofu::TofuConfig;
+use crate::token::ear::TokenFormat;
 use crate::token::{AttestationTokenBrokerType, AttestationTokenConfig};
+use crate::tracing::TracingConfig;
+use crate::transparency::TransparencyLogConfig;
+use crate::verifier::anchors::TrustAnchorsConfig;
+use crate::verifier::charset::EventlogStrings;
+use crate::verifier::crypto::CryptoBackendType;
+use crate::verifier::detail::DetailLevel;
+use crate::verifier::encoding::MeasurementEncoding;
+use crate::verifier::pipeline::VerifierPipelines;
+use crate::verifier::report_data::ReportDataModes;
+use crate::verifier::schema::ClaimsSchema;
+use crate::verifier::spdm::{self, SpdmDeviceConfig};
+use crate::verifier::timing::EvidenceAgeConfig;
+use crate::verifier::transform::ClaimTransform;
+use crate::verifier::{
+    CcaConfig, CollateralConfig, EvidenceVersions, NvidiaGpuConfig, SeConfig, SnpGuestPolicy,
+    VerifierConfig,
+};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::fs::File;
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+use crate::rvps::cache::RvpsCacheConfig;
 use crate::rvps::store::StoreType;
+use crate::rvps::watch::WatchConfig;
 
 /// Environment macro for Attestation Service work dir.
 const AS_WORK_DIR: &str = "AS_WORK_DIR";
 const DEFAULT_WORK_DIR: &str = "/opt/confidential-containers/attestation-service";
 
+/// Prefix of the env vars that override config values, see [`ConfigLayers`].
+pub const CONFIG_ENV_PREFIX: &str = "AS_CONFIG_";
+
+/// Fields missing from the config file take their value in
+/// [`Config::default`].
 #[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
 pub struct Config {
     /// The location for Attestation Service to store data.
     pub work_dir: PathBuf,
@@ -20,8 +69,53 @@ pub struct Config {
     /// Policy Engine type.
     pub policy_engine: String,
 
+    /// Policies evaluated when the attestation request names none, those of
+    /// the first that applies to the TEE and tenant of the request. The
+    /// `default` policy if none does.
+    #[serde(default)]
+    pub default_policies: Vec<DefaultPolicy>,
+
+    /// Claims given as input to a policy, by policy ID, to keep claims the
+    /// policy does not need, like eventlog entries, out of its evaluation.
+    /// A name ending with `*` matches any claim with that prefix. Policies
+    /// not listed get every claim.
+    #[serde(default)]
+    pub policy_input_claims: HashMap<String, Vec<String>>,
+
+    /// Only set policies signed by the admin key, see
+    /// [`crate::policy_engine::signing`].
+    #[serde(default)]
+    pub policy_signing: Option<PolicySigningConfig>,
+
+    /// Shadow policies, by the ID of the policy they shadow: evaluated on
+    /// every attestation alongside it, with discrepancies logged and
+    /// counted, but never affecting the result, see
+    /// [`crate::policy_engine::shadow`].
+    #[serde(default)]
+    pub shadow_policies: HashMap<String, String>,
+
+    /// Host agents that can attach signed metadata of the host to
+    /// attestation requests, see [`crate::host`].
+    #[serde(default)]
+    pub host_agents: Vec<HostAgentConfig>,
+
     pub rvps_store_type: StoreType,
 
+    /// Cache the reference values of a remote RVPS, see
+    /// [`crate::rvps::cache`].
+    #[serde(default)]
+    pub rvps_cache: Option<RvpsCacheConfig>,
+
+    /// Ingest the bundles of reference values dropped in a directory into
+    /// the integrated RVPS, see [`crate::rvps::watch`].
+    #[serde(default)]
+    pub reference_values_watch: Option<WatchConfig>,
+
+    /// Poll a feed of security advisories into the `advisory_baseline`
+    /// policy data document, see [`crate::advisories`].
+    #[serde(default)]
+    pub advisory_feed: Option<AdvisoryFeedConfig>,
+
     /// The Attestation Result Token Broker type.
     ///
     /// Possible values:
@@ -30,6 +124,625 @@ pub struct Config {
 
     /// The Attestation Result Token Broker Config
     pub attestation_token_config: AttestationTokenConfig,
+
+    /// Public keys, in JWKS format, of the other ASes whose state archives
+    /// this AS imports, besides its own, e.g. of the primary of a warm
+    /// standby, see [`crate::backup`].
+    #[serde(default)]
+    pub state_signers: Vec<PathBuf>,
+
+    /// Private keys of the AS that attesters can encrypt evidence to.
+    /// Encrypted evidence is rejected if there is none.
+    #[serde(default)]
+    pub evidence_decryption_keys: Vec<DecryptionKeyConfig>,
+
+    /// Accepted evidence format versions of each verifier.
+    #[serde(default)]
+    pub evidence_versions: EvidenceVersions,
+
+    /// How each verifier compares the report data of evidence with the
+    /// nonce and TEE public key, see [`crate::verifier::report_data`].
+    #[serde(default)]
+    pub report_data: ReportDataModes,
+
+    /// How many evidence verifications can run in parallel, on threads
+    /// apart from the async runtime. Defaults to the number of CPUs.
+    #[serde(default)]
+    pub worker_threads: Option<usize>,
+
+    /// Limits beyond which attestation requests are shed, see
+    /// [`crate::admission`].
+    #[serde(default)]
+    pub admission: AdmissionConfig,
+
+    /// Claims that may change between a token and the previous one it
+    /// chains to on re-attestation, with `continuity_ok` still set. A name
+    /// ending with `*` matches any claim with that prefix.
+    #[serde(default)]
+    pub token_chain_mutable_claims: Vec<String>,
+
+    /// Crypto backend that verifies the certificate chains and signatures
+    /// of evidence.
+    ///
+    /// Possible values:
+    /// * `OpenSSL`
+    /// * `Ring`
+    #[serde(default)]
+    pub crypto_backend: CryptoBackendType,
+
+    /// Only use FIPS approved algorithms, see [`crate::fips`]. Always on if
+    /// the AS is built with the `fips` feature.
+    #[serde(default)]
+    pub fips_mode: bool,
+
+    /// Verify evidence of the sample TEE, which anyone can forge. On by
+    /// default, which is deprecated, see [`crate::posture`]. A missing
+    /// value takes its [`Config::default`], `true`.
+    pub sample_verifier: bool,
+
+    /// Reject evidence of debuggable TEEs, by the canonical `debug` claim.
+    #[serde(default)]
+    pub deny_debug_tees: bool,
+
+    /// Refuse to start with any insecure setting, see [`crate::posture`].
+    #[serde(default)]
+    pub strict_security: bool,
+
+    /// Remember the evidence verified within a window, and flag or reject
+    /// it when it is submitted again, see [`crate::replay`].
+    #[serde(default)]
+    pub replay_protection: Option<ReplayConfig>,
+
+    /// Reject evidence that failed verification again, without verifying
+    /// it, until its failure expires, see [`crate::failure_cache`].
+    #[serde(default)]
+    pub failure_cache: Option<FailureCacheConfig>,
+
+    /// Do not verify the same evidence again within a TTL, and refresh the
+    /// tokens of clients without new evidence while the policies and
+    /// reference values are unchanged, see [`crate::result_cache`].
+    #[serde(default)]
+    pub result_cache: Option<ResultCacheConfig>,
+
+    /// Keep the nonces of challenges, and reject evidence that does not
+    /// bind one that was issued, is unexpired and unused, see
+    /// [`crate::nonces`].
+    #[serde(default)]
+    pub nonces: Option<NonceConfig>,
+
+    /// Reject evidence bound to the time it was produced, rather than to a
+    /// nonce, that is older than a maximum age, see
+    /// [`crate::verifier::timing`].
+    #[serde(default)]
+    pub evidence_age: Option<EvidenceAgeConfig>,
+
+    /// Stages of the verifiers, see [`crate::verifier::pipeline`].
+    #[serde(default)]
+    pub verifier_pipelines: VerifierPipelines,
+
+    /// Record the measurements that match no reference value as provisional
+    /// reference values, see [`crate::tofu`]. Lab use only.
+    #[serde(default)]
+    pub tofu: Option<TofuConfig>,
+
+    /// Also put the verification warnings in the `warnings` claim of the
+    /// tokens, see [`crate::verifier::warnings`].
+    #[serde(default)]
+    pub warnings_in_token: bool,
+
+    /// Reject evidence that comes without the eventlog of the boot
+    /// measurements, the CCEL of TDX. Otherwise such evidence verifies
+    /// with a warning, and an `eventlog_present` claim set to false that
+    /// policies can check.
+    #[serde(default)]
+    pub require_eventlog: bool,
+
+    /// Reject TDX and SGX quotes with structural anomalies, such as nonzero
+    /// reserved fields or big-endian integers. Otherwise such quotes verify
+    /// with a warning, see [`crate::verifier::anomalies`].
+    #[serde(default)]
+    pub strict_quote_parsing: bool,
+
+    /// How strings of eventlogs that are not UTF-8, such as a kernel
+    /// command line with stray bytes, are decoded: `strict`, the default,
+    /// rejects the evidence, `lossy` replaces the invalid sequences, see
+    /// [`crate::verifier::charset`].
+    #[serde(default)]
+    pub eventlog_strings: EventlogStrings,
+
+    /// Also claim the kernel command line of TDX eventlogs in a canonical
+    /// form, with its parameters sorted, as `tdx.ccel.kernel_cmdline`.
+    #[serde(default)]
+    pub canonical_kernel_cmdline: bool,
+
+    /// Claim the flags of the kernel command line of TDX eventlogs, such as
+    /// `rw` or `quiet`, as `true` rather than `null`, and in a
+    /// `tdx.ccel.kernel_flags` object where each of these flags that is not
+    /// on the command line is `false`.
+    #[serde(default)]
+    pub kernel_flags: Option<Vec<String>>,
+
+    /// Accept TDX evidence with an unsigned TD report in place of the
+    /// quote, for CI and nested virtualization, where there is no quoting
+    /// enclave. Its claims have `reduced_assurance` set to true, which the
+    /// default policy denies. Insecure, see [`crate::posture`].
+    #[serde(default)]
+    pub tdx_td_reports: bool,
+
+    /// Root certificates of each vendor that certificate chains of evidence
+    /// are verified up to, besides the built-in ones, see
+    /// [`crate::verifier::anchors`].
+    #[serde(default)]
+    pub trust_anchors: TrustAnchorsConfig,
+
+    /// Transforms of hex claims into decoded integer claims, by flattened
+    /// claim name, see [`crate::verifier::transform`].
+    #[serde(default)]
+    pub claim_transforms: HashMap<String, ClaimTransform>,
+
+    /// Version of the claims schema policies see, unless the attestation
+    /// request asks for another one, see [`crate::verifier::schema`].
+    #[serde(default)]
+    pub claims_schema: ClaimsSchema,
+
+    /// Encoding of the measurement register claims, unless the attestation
+    /// request asks for another one, see [`crate::verifier::encoding`].
+    #[serde(default)]
+    pub measurement_encoding: MeasurementEncoding,
+
+    /// How much of the verification tokens tell, unless the attestation
+    /// request asks for another level, see [`crate::verifier::detail`].
+    #[serde(default)]
+    pub detail_level: DetailLevel,
+
+    /// Guest policy required of SEV-SNP attestation reports, e.g. no
+    /// debugging.
+    #[serde(default)]
+    pub snp_guest_policy: SnpGuestPolicy,
+
+    /// Verify CCA tokens locally with the keys of the trusted platforms,
+    /// see [`crate::verifier::cca`].
+    #[serde(default)]
+    pub cca: CcaConfig,
+
+    /// Verify IBM Secure Execution evidence against the host key documents
+    /// and the headers of the trusted images, see
+    /// [`crate::verifier::se`].
+    #[serde(default)]
+    pub se: SeConfig,
+
+    /// Fetch the DCAP collateral of TDX and SGX quotes from a PCCS and
+    /// cache it, or load it from an offline bundle, see
+    /// [`crate::verifier::collateral`].
+    #[serde(default)]
+    pub collateral: CollateralConfig,
+
+    /// Config sections of the verifiers registered at runtime, by TEE
+    /// name, see [`crate::verifier::registry`].
+    #[serde(default)]
+    pub verifiers: HashMap<String, Value>,
+
+    /// Policy that authorizes TD migrations, given the claims of the TD
+    /// quotes of both ends, see [`crate::migration`]. A missing value takes
+    /// its [`Config::default`], `migration`, which no policy is set for
+    /// until one is, so that migrations are denied.
+    pub migration_policy: String,
+
+    /// Probes of the quote infrastructure of the platform, run by the
+    /// self-test, see [`crate::self_test`].
+    #[serde(default)]
+    pub platform_probes: Option<PlatformProbeConfig>,
+
+    /// Issue X.509 certificates to attesters that send a CSR of their TEE
+    /// public key, see [`crate::certificate`].
+    #[serde(default)]
+    pub certificate_issuer: Option<CertificateIssuerConfig>,
+
+    /// Level, sampling and redaction of the logs of the claims of verified
+    /// evidence, see [`crate::claims_log`].
+    #[serde(default)]
+    pub claims_log: ClaimsLogConfig,
+
+    /// Claims redacted or omitted from the tokens of some tenants or
+    /// policies, and from the claims logged at `Info` level, see
+    /// [`crate::claim_filter`].
+    #[serde(default)]
+    pub claim_filters: Vec<ClaimFilter>,
+
+    /// Actions run for every allowed attestation, which can release
+    /// secrets to the attester, see [`crate::hooks`].
+    #[serde(default)]
+    pub post_verification_hooks: Vec<HookConfig>,
+
+    /// Append every issued token to a transparency log, see
+    /// [`crate::transparency`].
+    #[serde(default)]
+    pub transparency_log: Option<TransparencyLogConfig>,
+
+    /// Record the claims and decision of every attestation, to export them
+    /// for analytics, see [`crate::history`].
+    #[serde(default)]
+    pub history: Option<HistoryConfig>,
+
+    /// Record why every attestation passed or failed, to a sink, see
+    /// [`crate::audit`].
+    #[serde(default)]
+    pub audit: Option<AuditConfig>,
+
+    /// Send a trace of every attestation to an OpenTelemetry collector, see
+    /// [`crate::tracing`].
+    #[serde(default)]
+    pub tracing: Option<TracingConfig>,
+
+    /// Verify the evidence of NVIDIA confidential GPUs attached to TEE
+    /// evidence locally, see [`crate::verifier::nvidia_gpu`]. Evidence with
+    /// GPU evidence is rejected if not set.
+    #[serde(default)]
+    pub nvidia_gpu: Option<NvidiaGpuConfig>,
+
+    /// Verify the SPDM evidence of the devices of these profiles attached
+    /// to TEE evidence, see [`crate::verifier::spdm`].
+    #[serde(default)]
+    pub spdm_devices: Vec<SpdmDeviceConfig>,
+}
+
+impl Config {
+    /// Whether the AS runs in FIPS mode.
+    pub fn fips_mode(&self) -> bool {
+        self.fips_mode || crate::fips::FIPS_BUILD
+    }
+
+    /// The part of the config that the verifiers take.
+    pub fn verifier_config(&self) -> VerifierConfig {
+        VerifierConfig {
+            evidence_versions: self.evidence_versions.clone(),
+            report_data: self.report_data.clone(),
+            verifier_pipelines: self.verifier_pipelines.clone(),
+            require_eventlog: self.require_eventlog,
+            strict_quote_parsing: self.strict_quote_parsing,
+            eventlog_strings: self.eventlog_strings,
+            canonical_kernel_cmdline: self.canonical_kernel_cmdline,
+            kernel_flags: self.kernel_flags.clone(),
+            tdx_td_reports: self.tdx_td_reports,
+            trust_anchors: self.trust_anchors.clone(),
+            crypto_backend: self.crypto_backend,
+            claim_transforms: self.claim_transforms.clone(),
+            claims_schema: self.claims_schema,
+            snp_guest_policy: self.snp_guest_policy.clone(),
+            cca: self.cca.clone(),
+            se: self.se.clone(),
+            collateral: self.collateral.clone(),
+            verifiers: self.verifiers.clone(),
+        }
+    }
+
+    /// The SPDM devices the AS verifies, those of `spdm_devices` then the
+    /// GPUs of `nvidia_gpu`.
+    pub fn all_spdm_devices(&self) -> Vec<SpdmDeviceConfig> {
+        let mut devices = self.spdm_devices.clone();
+        devices.extend(self.nvidia_gpu.as_ref().map(SpdmDeviceConfig::from));
+        devices
+    }
+
+    /// Validate the config without starting the AS: the work dir must be
+    /// writable, the backends supported by this build, and the keys
+    /// loadable. The error lists every problem found.
+    pub fn check(&self) -> Result<()> {
+        self.check_with(true)
+    }
+
+    /// The checks of [`Config::check`] that neither read the files the
+    /// config names, nor load its keys and backends, for configs of
+    /// untrusted origin.
+    pub fn check_static(&self) -> Result<()> {
+        self.check_with(false)
+    }
+
+    /// The checks of the config, and those that read files or load keys
+    /// and backends if `load`.
+    fn check_with(&self, load: bool) -> Result<()> {
+        let mut problems = Vec::new();
+        let mut check = |what: &str, result: Result<()>| {
+            if let Err(e) = result {
+                problems.push(format!("{what}: {e:#}"));
+            }
+        };
+
+        if load {
+            check("work_dir", check_work_dir(&self.work_dir));
+        }
+        check(
+            "policy_engine",
+            PolicyEngineType::from_str(&self.policy_engine)
+                .map(|_| ())
+                .map_err(|_| anyhow!("Policy Engine {} is not supported", self.policy_engine)),
+        );
+        for (i, policy) in self.default_policies.iter().enumerate() {
+            if policy.policy_id.is_empty() {
+                check(
+                    &format!("default_policies[{i}].policy_id"),
+                    Err(anyhow!("must not be empty")),
+                );
+            }
+            if policy.policy_ids.iter().any(String::is_empty) {
+                check(
+                    &format!("default_policies[{i}].policy_ids"),
+                    Err(anyhow!("must not have an empty policy ID")),
+                );
+            }
+        }
+        for (policy_id, shadow_policy_id) in &self.shadow_policies {
+            check(
+                &format!("shadow_policies.{policy_id}"),
+                check_policy_id(policy_id).and_then(|_| check_policy_id(shadow_policy_id)),
+            );
+            if policy_id == shadow_policy_id {
+                check(
+                    &format!("shadow_policies.{policy_id}"),
+                    Err(anyhow!("a policy cannot shadow itself")),
+                );
+            }
+        }
+        if let (true, Some(policy_signing)) = (load, &self.policy_signing) {
+            check(
+                "policy_signing.admin_public_key",
+                PolicySignatureVerifier::new(policy_signing).map(|_| ()),
+            );
+        }
+        if load {
+            check(
+                "host_agents",
+                HostAgents::new(&self.host_agents).map(|_| ()),
+            );
+        }
+        if self.migration_policy.is_empty() {
+            check("migration_policy", Err(anyhow!("must not be empty")));
+        }
+        if load {
+            check(
+                "attestation_token_config",
+                self.attestation_token_broker
+                    .to_token_broker(self.attestation_token_config.clone())
+                    .map(|_| ()),
+            );
+        }
+        let token_config = &self.attestation_token_config;
+        if token_config.format == TokenFormat::EarCose && !token_config.co_signers.is_empty() {
+            check(
+                "attestation_token_config.format",
+                Err(anyhow!("COSE tokens cannot be co-signed")),
+            );
+        }
+        if token_config.format.is_ear() && token_config.claim_mapper.is_some() {
+            check(
+                "attestation_token_config.format",
+                Err(anyhow!("EAR tokens cannot have a claim mapper")),
+            );
+        }
+        for (i, filter) in self.claim_filters.iter().enumerate() {
+            check(&format!("claim_filters[{i}]"), filter.check());
+        }
+        if load {
+            if let Some(claim_mapper) = &self.attestation_token_config.claim_mapper {
+                check(
+                    "attestation_token_config.claim_mapper",
+                    claim_mapper.to_claim_mapper().map(|_| ()),
+                );
+            }
+            check(
+                "state_signers",
+                crate::backup::signer_jwks(&self.state_signers).map(|_| ()),
+            );
+            check(
+                "evidence_decryption_keys",
+                EvidenceDecryptor::new(&self.evidence_decryption_keys).map(|_| ()),
+            );
+            if let Some(certificate_issuer) = &self.certificate_issuer {
+                check(
+                    "certificate_issuer",
+                    CertificateIssuer::new(certificate_issuer).map(|_| ()),
+                );
+            }
+            check("claims_log", self.claims_log.check());
+            check("trust_anchors", self.trust_anchors.check());
+            if let Some(watch) = &self.reference_values_watch {
+                check("reference_values_watch", watch.check());
+            }
+            if let Some(feed) = &self.advisory_feed {
+                check("advisory_feed", feed.check());
+            }
+            check(
+                "spdm_devices",
+                spdm::devices(&self.all_spdm_devices(), self.crypto_backend).map(|_| ()),
+            );
+            check(
+                "post_verification_hooks",
+                Hooks::new(&self.post_verification_hooks).map(|_| ()),
+            );
+        }
+        for (format, range) in [
+            ("tdx", self.evidence_versions.tdx),
+            ("sgx", self.evidence_versions.sgx),
+            ("snp", self.evidence_versions.snp),
+            ("azsnpvtpm", self.evidence_versions.az_snp_vtpm),
+        ] {
+            if let (Some(min), Some(max)) = (range.min, range.max) {
+                if min > max {
+                    check(
+                        &format!("evidence_versions.{format}"),
+                        Err(anyhow!("min {min} is greater than max {max}")),
+                    );
+                }
+            }
+        }
+        if self.worker_threads == Some(0) {
+            check("worker_threads", Err(anyhow!("must be at least 1")));
+        }
+        for (limit, value) in [
+            ("admission.max_in_flight", self.admission.max_in_flight),
+            ("admission.max_queue_depth", self.admission.max_queue_depth),
+            ("admission.max_batch_size", self.admission.max_batch_size),
+        ] {
+            if value == Some(0) {
+                check(limit, Err(anyhow!("must be at least 1")));
+            }
+        }
+        check(
+            "verifier_pipelines.tdx",
+            self.verifier_pipelines.tdx().map(|_| ()),
+        );
+        check(
+            "verifier_pipelines.snp",
+            self.verifier_pipelines.snp().map(|_| ()),
+        );
+        if load {
+            check(
+                "crypto_backend",
+                self.crypto_backend.to_backend().map(|_| ()),
+            );
+            #[cfg(feature = "cca-verifier")]
+            check(
+                "cca.platform_keys",
+                crate::verifier::cca::load_platform_keys(&self.cca.platform_keys).map(|_| ()),
+            );
+            #[cfg(feature = "se-verifier")]
+            if self.se.measurement_key.is_some() {
+                check(
+                    "se",
+                    crate::verifier::se::factory(&self.verifier_config(), Default::default())
+                        .map(|_| ()),
+                );
+            }
+            #[cfg(any(feature = "tdx-verifier", feature = "sgx-verifier"))]
+            check(
+                "collateral",
+                crate::verifier::collateral::Provider::new(self.collateral.clone()).map(|_| ()),
+            );
+        }
+        for name in self.verifiers.keys() {
+            check(
+                &format!("verifiers.{name}"),
+                serde_json::from_value::<kbs_types::Tee>(name.as_str().into())
+                    .map(|_| ())
+                    .map_err(|_| anyhow!("not a TEE name")),
+            );
+        }
+        if self.fips_mode() {
+            check("fips_mode", crate::fips::check_config(self));
+        }
+        if let Some(replay) = &self.replay_protection {
+            if replay.window_secs == 0 {
+                check(
+                    "replay_protection.window_secs",
+                    Err(anyhow!("must be at least 1")),
+                );
+            }
+        }
+
+        if let Some(failure_cache) = &self.failure_cache {
+            if failure_cache.ttl_secs == 0 {
+                check("failure_cache.ttl_secs", Err(anyhow!("must be at least 1")));
+            }
+            if failure_cache.max_entries == 0 {
+                check(
+                    "failure_cache.max_entries",
+                    Err(anyhow!("must be at least 1")),
+                );
+            }
+        }
+
+        if let Some(result_cache) = &self.result_cache {
+            if result_cache.ttl_secs == 0 {
+                check("result_cache.ttl_secs", Err(anyhow!("must be at least 1")));
+            }
+            if result_cache.max_entries == 0 {
+                check(
+                    "result_cache.max_entries",
+                    Err(anyhow!("must be at least 1")),
+                );
+            }
+        }
+
+        if let Some(nonces) = &self.nonces {
+            if nonces.ttl_secs == 0 {
+                check("nonces.ttl_secs", Err(anyhow!("must be at least 1")));
+            }
+            if nonces.max_outstanding == 0 {
+                check("nonces.max_outstanding", Err(anyhow!("must be at least 1")));
+            }
+            if nonces.store == NonceStoreType::Redis && nonces.redis.is_none() {
+                check(
+                    "nonces.redis",
+                    Err(anyhow!("must be set for the Redis store")),
+                );
+            }
+        }
+
+        if let Some(evidence_age) = &self.evidence_age {
+            if evidence_age.max_age_secs == 0 {
+                check(
+                    "evidence_age.max_age_secs",
+                    Err(anyhow!("must be at least 1")),
+                );
+            }
+        }
+
+        if let Some(rvps_cache) = &self.rvps_cache {
+            if rvps_cache.ttl_secs == 0 {
+                check("rvps_cache.ttl_secs", Err(anyhow!("must be at least 1")));
+            }
+            if rvps_cache.max_entries == 0 {
+                check("rvps_cache.max_entries", Err(anyhow!("must be at least 1")));
+            }
+        }
+
+        if let Some(tofu) = &self.tofu {
+            if tofu.claims.is_empty() {
+                check("tofu.claims", Err(anyhow!("must not be empty")));
+            }
+            if tofu.expiration_days < 1 {
+                check("tofu.expiration_days", Err(anyhow!("must be at least 1")));
+            }
+        }
+        if let Some(history) = &self.history {
+            if history.max_records == 0 {
+                check("history.max_records", Err(anyhow!("must be at least 1")));
+            }
+            if let (true, Some(kek)) = (load, &history.evidence_kek) {
+                check(
+                    "history.evidence_kek",
+                    crate::history::envelope::Kek::from_file(kek).map(|_| ()),
+                );
+            }
+        }
+        if let (true, Some(audit)) = (load, &self.audit) {
+            check("audit", audit.check());
+        }
+        if let Some(tracing) = &self.tracing {
+            check("tracing", tracing.check());
+        }
+
+        if !problems.is_empty() {
+            bail!("Invalid AS config:\n  {}", problems.join("\n  "));
+        }
+        Ok(())
+    }
+}
+
+/// Check that the work dir, or the dir it would be created in, is a
+/// writable directory.
+fn check_work_dir(work_dir: &Path) -> Result<()> {
+    let mut dir = work_dir;
+    while !dir.exists() {
+        dir = dir
+            .parent()
+            .ok_or_else(|| anyhow!("{} cannot be created", work_dir.display()))?;
+    }
+    if !dir.is_dir() {
+        bail!("{} is not a directory", dir.display());
+    }
+    tempfile::tempfile_in(dir).with_context(|| format!("{} is not writable", dir.display()))?;
+    Ok(())
 }
 
 impl Default for Config {
@@ -42,9 +755,65 @@ impl Default for Config {
         Config {
             work_dir,
             policy_engine: "opa".to_string(),
+            default_policies: Vec::new(),
+            policy_input_claims: HashMap::new(),
+            policy_signing: None,
+            shadow_policies: HashMap::new(),
+            host_agents: Vec::new(),
             rvps_store_type: StoreType::LocalFs,
+            rvps_cache: None,
+            reference_values_watch: None,
+            advisory_feed: None,
             attestation_token_broker: AttestationTokenBrokerType::Simple,
             attestation_token_config: AttestationTokenConfig::default(),
+            state_signers: Vec::new(),
+            evidence_decryption_keys: Vec::new(),
+            evidence_versions: EvidenceVersions::default(),
+            report_data: ReportDataModes::default(),
+            worker_threads: None,
+            admission: AdmissionConfig::default(),
+            token_chain_mutable_claims: Vec::new(),
+            crypto_backend: CryptoBackendType::default(),
+            fips_mode: false,
+            sample_verifier: true,
+            deny_debug_tees: false,
+            strict_security: false,
+            replay_protection: None,
+            failure_cache: None,
+            result_cache: None,
+            nonces: None,
+            evidence_age: None,
+            verifier_pipelines: VerifierPipelines::default(),
+            tofu: None,
+            warnings_in_token: false,
+            require_eventlog: false,
+            strict_quote_parsing: false,
+            eventlog_strings: EventlogStrings::default(),
+            canonical_kernel_cmdline: false,
+            kernel_flags: None,
+            tdx_td_reports: false,
+            trust_anchors: TrustAnchorsConfig::default(),
+            claim_transforms: HashMap::new(),
+            claims_schema: ClaimsSchema::default(),
+            measurement_encoding: MeasurementEncoding::default(),
+            detail_level: DetailLevel::default(),
+            snp_guest_policy: SnpGuestPolicy::default(),
+            cca: CcaConfig::default(),
+            se: SeConfig::default(),
+            collateral: CollateralConfig::default(),
+            verifiers: HashMap::new(),
+            migration_policy: "migration".to_string(),
+            platform_probes: None,
+            certificate_issuer: None,
+            claims_log: ClaimsLogConfig::default(),
+            claim_filters: Vec::new(),
+            post_verification_hooks: Vec::new(),
+            transparency_log: None,
+            history: None,
+            audit: None,
+            tracing: None,
+            nvidia_gpu: None,
+            spdm_devices: Vec::new(),
         }
     }
 }
@@ -54,18 +823,668 @@ impl TryFrom<&Path> for Config {
     ///    {
     ///        "work_dir": "/var/lib/attestation-service/",
     ///        "policy_engine": "opa",
+    ///        "default_policies": [
+    ///            { "policy_id": "tdx", "policy_ids": ["acme"], "tees": ["tdx"], "tenants": ["acme"] },
+    ///            { "policy_id": "tdx", "tees": ["tdx"] },
+    ///            { "policy_id": "amd", "tees": ["snp", "azsnpvtpm"] }
+    ///        ],
+    ///        "policy_input_claims": {
+    ///            "tdx": ["tdx.quote.body.*", "tdx.ccel.kernel", "tcb_status"]
+    ///        },
+    ///        "policy_signing": {
+    ///            "admin_public_key": "/etc/attestation-service/policy_admin.pem"
+    ///        },
+    ///        "shadow_policies": {
+    ///            "default": "default-next"
+    ///        },
+    ///        "host_agents": [
+    ///            { "id": "node-agent", "public_key": "/etc/attestation-service/node-agent.pem" }
+    ///        ],
     ///        "rvps_store_type": "LocalFs",
+    ///        "rvps_cache": {
+    ///            "ttl_secs": 60,
+    ///            "max_entries": 10000
+    ///        },
+    ///        "reference_values_watch": {
+    ///            "dir": "/etc/attestation-service/corim",
+    ///            "poll_secs": 30
+    ///        },
+    ///        "advisory_feed": {
+    ///            "source": "https://security.example.com/tcb-advisories.json",
+    ///            "format": "amd",
+    ///            "poll_secs": 3600
+    ///        },
     ///        "attestation_token_broker": "Simple",
     ///        "attestation_token_config": {
-    ///            "duration_min": 5
-    ///        }
+    ///            "duration_min": 5,
+    ///            "signing_keys": [
+    ///                { "type": "file", "path": "/etc/attestation-service/token-key.pem" }
+    ///            ]
+    ///        },
+    ///        "state_signers": ["/etc/attestation-service/primary-keys.json"],
+    ///        "evidence_decryption_keys": [
+    ///            {
+    ///                "id": "as-key-1",
+    ///                "alg": "RSA-OAEP-256",
+    ///                "type": "file",
+    ///                "path": "/etc/attestation-service/evidence-key.pem"
+    ///            }
+    ///        ],
+    ///        "evidence_versions": {
+    ///            "tdx": { "min": 4, "max": 4 },
+    ///            "snp": { "min": 2, "max": 3 }
+    ///        },
+    ///        "report_data": {
+    ///            "tdx": "prefix"
+    ///        },
+    ///        "worker_threads": 4,
+    ///        "admission": {
+    ///            "max_in_flight": 512,
+    ///            "max_queue_depth": 256,
+    ///            "max_batch_size": 1000
+    ///        },
+    ///        "token_chain_mutable_claims": [
+    ///            "tdx.quote.body.tcb_svn.*"
+    ///        ],
+    ///        "crypto_backend": "OpenSSL",
+    ///        "fips_mode": true,
+    ///        "sample_verifier": false,
+    ///        "deny_debug_tees": true,
+    ///        "strict_security": true,
+    ///        "replay_protection": {
+    ///            "window_secs": 3600,
+    ///            "retry_secs": 30,
+    ///            "action": "Reject"
+    ///        },
+    ///        "failure_cache": {
+    ///            "ttl_secs": 30,
+    ///            "max_entries": 10000
+    ///        },
+    ///        "result_cache": {
+    ///            "ttl_secs": 60,
+    ///            "max_entries": 10000,
+    ///            "reattest_secs": 3600
+    ///        },
+    ///        "nonces": {
+    ///            "ttl_secs": 300,
+    ///            "store": "LocalFs",
+    ///            "max_outstanding": 100000
+    ///        },
+    ///        "evidence_age": {
+    ///            "max_age_secs": 300,
+    ///            "clock_skew_secs": 30
+    ///        },
+    ///        "verifier_pipelines": {
+    ///            "tdx": ["parse", "collateral_verify", "eventlog_replay", "freshness", "claims_normalize"],
+    ///            "allow_insecure": false
+    ///        },
+    ///        "tofu": {
+    ///            "claims": ["tdx.quote.body.mr_td", "tdx.ccel.*"],
+    ///            "expiration_days": 90,
+    ///            "platform_claims": ["snp.chip_id"]
+    ///        },
+    ///        "warnings_in_token": true,
+    ///        "require_eventlog": true,
+    ///        "strict_quote_parsing": true,
+    ///        "eventlog_strings": "lossy",
+    ///        "canonical_kernel_cmdline": true,
+    ///        "kernel_flags": ["rw", "quiet", "nomodeset"],
+    ///        "tdx_td_reports": false,
+    ///        "trust_anchors": {
+    ///            "snp": ["/etc/attestation-service/genoa_ask_ark.pem"],
+    ///            "intel": ["/etc/attestation-service/intel_sgx_root_ca.pem"],
+    ///            "corim": ["/etc/attestation-service/acme_corim_root.pem"],
+    ///            "ibm": ["/etc/attestation-service/digicert_root.pem"]
+    ///        },
+    ///        "claim_transforms": {
+    ///            "tdx.quote.body.xfam": "le_uint"
+    ///        },
+    ///        "measurement_encoding": "base64",
+    ///        "detail_level": "verdict",
+    ///        "snp_guest_policy": {
+    ///            "deny_debug": true,
+    ///            "min_abi": [1, 51]
+    ///        },
+    ///        "cca": {
+    ///            "platform_keys": ["/etc/attestation-service/cpak.pem"]
+    ///        },
+    ///        "se": {
+    ///            "signing_keys": ["/etc/attestation-service/ibm_z_host_key_signing_chain.pem"],
+    ///            "measurement_key": "/etc/attestation-service/se_measurement_key.pem",
+    ///            "images": [{ "pld": "<hex>", "ald": "<hex>", "tld": "<hex>", "tag": "<hex>" }]
+    ///        },
+    ///        "collateral": {
+    ///            "pccs_url": "https://pccs.example:8081",
+    ///            "cache_dir": "/var/lib/attestation-service/collateral",
+    ///            "fleet": [{ "tee": "tdx", "fmspc": "00806f050000", "ca": "platform" }],
+    ///            "max_cached": 32
+    ///        },
+    ///        "verifiers": {
+    ///            "sev": { "kds_url": "https://kdsintf.amd.com" }
+    ///        },
+    ///        "migration_policy": "td-migration",
+    ///        "claims_log": {
+    ///            "level": "Debug",
+    ///            "sample_rate": 0.1,
+    ///            "redact": ["tdx.quote.body.report_data"]
+    ///        },
+    ///        "claim_filters": [
+    ///            {
+    ///                "tenants": ["acme"],
+    ///                "redact": ["tdx.quote.body.report_data"],
+    ///                "omit": ["tdx.ccel.kernel_parameters*"]
+    ///            }
+    ///        ],
+    ///        "post_verification_hooks": [
+    ///            {
+    ///                "name": "disk-key",
+    ///                "type": "command",
+    ///                "command": ["/usr/bin/kms-unwrap"],
+    ///                "release": true
+    ///            }
+    ///        ],
+    ///        "transparency_log": {
+    ///            "type": "local"
+    ///        },
+    ///        "history": {
+    ///            "max_records": 100000,
+    ///            "hash": ["tdx.quote.body.report_data"]
+    ///        },
+    ///        "audit": {
+    ///            "sink": { "type": "syslog" }
+    ///        },
+    ///        "tracing": {
+    ///            "endpoint": "http://collector:4318"
+    ///        },
+    ///        "nvidia_gpu": {
+    ///            "root_certificate": "/etc/attestation-service/nvidia-device-identity-ca.pem"
+    ///        },
+    ///        "spdm_devices": [
+    ///            {
+    ///                "profile": "generic",
+    ///                "name": "nic",
+    ///                "root_certificate": "/etc/attestation-service/nic-vendor-ca.pem"
+    ///            }
+    ///        ]
     ///    }
+    ///
+    /// or its TOML equivalent, if the file name ends with `.toml`.
     type Error = anyhow::Error;
     fn try_from(config_path: &Path) -> Result<Self, Self::Error> {
-        let file = File::open(config_path)
-            .map_err(|e| anyhow!("failed to open AS config file {}", e.to_string()))?;
+        ConfigLayers::new(Some(config_path))?.load()
+    }
+}
+
+/// The top-level sections of the config that a running AS applies without
+/// a restart, see [`crate::AttestationService::reload_config`]. A dotted
+/// section is a field of a top-level section, whose other fields need a
+/// restart.
+pub const RELOADABLE_SECTIONS: &[&str] = &[
+    "default_policies",
+    "shadow_policies",
+    "migration_policy",
+    "policy_input_claims",
+    "reference_values_watch",
+    "attestation_token_config.duration_min",
+    "claim_filters",
+    "detail_level",
+    "evidence_age",
+    "warnings_in_token",
+];
+
+/// The sections of the config that differ between two [`ConfigLayers`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConfigChanges {
+    /// Sections applied without a restart.
+    pub reloaded: Vec<String>,
+    /// Top-level sections that only apply on restart.
+    pub restart_needed: Vec<String>,
+}
+
+impl ConfigChanges {
+    pub fn is_empty(&self) -> bool {
+        self.reloaded.is_empty() && self.restart_needed.is_empty()
+    }
+}
+
+/// The config, layered from the lowest to the highest precedence:
+///
+/// 1. the defaults of the fields,
+/// 2. the config file, JSON, or TOML if its name ends with `.toml`,
+/// 3. env vars named after the key of a value, prefixed with
+///    [`CONFIG_ENV_PREFIX`] and with `__` between nested keys, like
+///    `AS_CONFIG_ATTESTATION_TOKEN_CONFIG__DURATION_MIN=10`,
+/// 4. overrides of the command line, like
+///    `attestation_token_config.duration_min=10`.
+///
+/// Numeric keys index arrays, e.g. `listeners.0.address`. Values are JSON,
+/// or strings if they do not parse as JSON, so `0.0.0.0:3000` is a string
+/// but a numeric string has to be quoted, like `"3000"`.
+#[derive(Clone, Debug)]
+pub struct ConfigLayers {
+    value: Value,
+}
+
+impl ConfigLayers {
+    /// The defaults, overridden by the config file at `config_path`, if any.
+    pub fn new(config_path: Option<&Path>) -> Result<Self> {
+        let mut layers = Self {
+            value: Value::Object(Map::new()),
+        };
+        if let Some(config_path) = config_path {
+            let document = fs::read_to_string(config_path)
+                .map_err(|e| anyhow!("failed to open AS config file {}", e.to_string()))?;
+            let toml = config_path.extension().is_some_and(|ext| ext == "toml");
+            let file = parse_document(&document, toml)
+                .map_err(|e| anyhow!("failed to parse AS config file {e:#}"))?;
+            if !file.is_object() {
+                bail!(
+                    "AS config file {} is not a JSON object",
+                    config_path.display()
+                );
+            }
+            merge(&mut layers.value, file);
+        }
+        Ok(layers)
+    }
+
+    /// The defaults, overridden by the config file `document`, e.g. a
+    /// candidate config: JSON if it is an object, else TOML.
+    pub fn from_document(document: &str) -> Result<Self> {
+        let toml = !document.trim_start().starts_with('{');
+        let mut value = Value::Object(Map::new());
+        merge(
+            &mut value,
+            parse_document(document, toml).context("Cannot parse the config")?,
+        );
+        if !value.is_object() {
+            bail!("The config is not an object");
+        }
+        Ok(Self { value })
+    }
+
+    /// Override the values of the env vars with [`CONFIG_ENV_PREFIX`] among
+    /// `vars`, like those of `std::env::vars()`.
+    pub fn with_env(mut self, vars: impl IntoIterator<Item = (String, String)>) -> Result<Self> {
+        let mut vars: Vec<_> = vars
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(CONFIG_ENV_PREFIX))
+            .collect();
+        // Set the array items in order.
+        vars.sort();
+        for (name, value) in vars {
+            let key = name[CONFIG_ENV_PREFIX.len()..]
+                .to_lowercase()
+                .replace("__", ".");
+            self.set(&key, parse_value(&value))
+                .with_context(|| format!("Invalid config env var {name}"))?;
+        }
+        Ok(self)
+    }
+
+    /// Override a value with a `<key>=<value>` of the command line.
+    pub fn with_override(mut self, assignment: &str) -> Result<Self> {
+        let (key, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Config override {assignment} is not <key>=<value>"))?;
+        self.set(key, parse_value(value))
+            .with_context(|| format!("Invalid config override {assignment}"))?;
+        Ok(self)
+    }
+
+    /// Set the value of the dotted `key`, adding the objects on the way. A
+    /// numeric key indexes an array, or appends to it if it is its length.
+    pub fn set(&mut self, key: &str, value: Value) -> Result<()> {
+        let mut node = &mut self.value;
+        for segment in key.split('.') {
+            if segment.is_empty() {
+                bail!("Empty segment in config key {key}");
+            }
+            node = match node {
+                Value::Array(items) => {
+                    let index: usize = segment
+                        .parse()
+                        .map_err(|_| anyhow!("{segment} of config key {key} is not an index"))?;
+                    if index == items.len() {
+                        items.push(Value::Null);
+                    }
+                    items.get_mut(index).ok_or_else(|| {
+                        anyhow!("Index {index} of config key {key} is out of range")
+                    })?
+                }
+                node => {
+                    if !node.is_object() {
+                        *node = Value::Object(Map::new());
+                    }
+                    node.as_object_mut()
+                        .expect("object node")
+                        .entry(segment)
+                        .or_insert(Value::Null)
+                }
+            };
+        }
+        *node = value;
+        Ok(())
+    }
+
+    /// Deserialize the layered config, e.g. into a [`Config`].
+    pub fn load<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_value(self.value.clone())
+            .map_err(|e| anyhow!("failed to parse AS config {}", e.to_string()))
+    }
+
+    /// SHA-256 of the layered config, e.g. `sha256:3f1c...`, by which
+    /// dependent systems tell whether the config of the AS has changed.
+    /// Defaults are left out, so it only changes with the values set.
+    pub fn digest(&self) -> String {
+        let config = serde_json::to_vec(&self.value).unwrap_or_default();
+        format!("sha256:{}", hex::encode(Sha256::digest(config)))
+    }
+
+    /// The sections of the `candidate` config that differ from this one,
+    /// whether they are among the `reloadable` ones, like
+    /// [`RELOADABLE_SECTIONS`], or need a restart.
+    pub fn changes(&self, candidate: &Self, reloadable: &[&str]) -> ConfigChanges {
+        let mut changes = ConfigChanges::default();
+        let (Some(current), Some(candidate)) =
+            (self.value.as_object(), candidate.value.as_object())
+        else {
+            return changes;
+        };
+        let mut sections: Vec<&String> = current.keys().chain(candidate.keys()).collect();
+        sections.sort();
+        sections.dedup();
+        for section in sections {
+            let (before, after) = (current.get(section), candidate.get(section));
+            if before == after {
+                continue;
+            }
+            if reloadable.contains(&section.as_str()) {
+                changes.reloaded.push(section.clone());
+                continue;
+            }
+            // The reloadable fields of the section, if the others are equal.
+            let fields: Vec<&str> = reloadable
+                .iter()
+                .filter_map(|name| name.strip_prefix(section.as_str())?.strip_prefix('.'))
+                .collect();
+            let field =
+                |value: Option<&Value>, name: &str| value.and_then(|v| v.get(name)).cloned();
+            let others = |value: Option<&Value>| {
+                let mut value = value.cloned().unwrap_or(Value::Object(Map::new()));
+                if let Some(object) = value.as_object_mut() {
+                    object.retain(|name, _| !fields.contains(&name.as_str()));
+                }
+                value
+            };
+            if fields.is_empty() || others(before) != others(after) {
+                changes.restart_needed.push(section.clone());
+                continue;
+            }
+            for name in fields {
+                if field(before, name) != field(after, name) {
+                    changes.reloaded.push(format!("{section}.{name}"));
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// Parse a config `document`, TOML if `toml`, else JSON.
+fn parse_document(document: &str, toml: bool) -> Result<Value> {
+    match toml {
+        true => Ok(toml::from_str(document)?),
+        false => Ok(serde_json::from_str(document)?),
+    }
+}
+
+/// Merge the objects of `layer` into those of `base`, other values of
+/// `layer` replace those of `base`.
+fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+fn parse_value(value: &str) -> Value {
+    serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))
+}
 
-        serde_json::from_reader::<File, Config>(file)
-            .map_err(|e| anyhow!("failed to parse AS config file {}", e.to_string()))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::advisories::FeedFormat;
+    use crate::audit::AuditSink;
+    use crate::verifier::VersionRange;
+
+    #[test]
+    fn test_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config {
+            work_dir: dir.path().join("as"),
+            ..Default::default()
+        };
+        config.check().unwrap();
+
+        config.policy_engine = "cedar".to_string();
+        config.worker_threads = Some(0);
+        config.default_policies = serde_json::from_str(
+            r#"[{"policy_id": ""}, {"policy_id": "tdx", "policy_ids": [""]}]"#,
+        )
+        .unwrap();
+        config.admission.max_queue_depth = Some(0);
+        config
+            .shadow_policies
+            .insert("default".to_string(), "default".to_string());
+        config.evidence_versions.tdx = VersionRange {
+            min: Some(5),
+            max: Some(4),
+            ..Default::default()
+        };
+        config.verifiers.insert("sev".to_string(), Value::Null);
+        config.verifiers.insert("tpm".to_string(), Value::Null);
+        config.result_cache = Some(ResultCacheConfig {
+            ttl_secs: 0,
+            ..Default::default()
+        });
+        config.nonces = Some(NonceConfig {
+            store: NonceStoreType::Redis,
+            max_outstanding: 0,
+            ..Default::default()
+        });
+        config.evidence_age = Some(EvidenceAgeConfig {
+            max_age_secs: 0,
+            clock_skew_secs: 60,
+        });
+        config.reference_values_watch = Some(WatchConfig {
+            dir: dir.path().join("corim"),
+            poll_secs: 30,
+        });
+        config.advisory_feed = Some(AdvisoryFeedConfig {
+            source: "feed.json".to_string(),
+            format: FeedFormat::Intel,
+            poll_secs: 0,
+        });
+        config.state_signers = vec![dir.path().join("primary-keys.json")];
+        config.audit = Some(AuditConfig {
+            sink: AuditSink::Otlp {
+                endpoint: "collector:4318".to_string(),
+            },
+            max_records: 10,
+        });
+        config.tracing = Some(TracingConfig {
+            endpoint: "collector:4317".to_string(),
+            service_name: "as".to_string(),
+        });
+        let e = config.check().unwrap_err().to_string();
+        assert!(e.contains("policy_engine: Policy Engine cedar is not supported"));
+        assert!(e.contains("worker_threads: must be at least 1"));
+        assert!(e.contains("default_policies[0].policy_id: must not be empty"));
+        assert!(e.contains("default_policies[1].policy_ids: must not have an empty policy ID"));
+        assert!(e.contains("admission.max_queue_depth: must be at least 1"));
+        assert!(e.contains("shadow_policies.default: a policy cannot shadow itself"));
+        assert!(e.contains("evidence_versions.tdx: min 5 is greater than max 4"));
+        assert!(e.contains("verifiers.tpm: not a TEE name"));
+        assert!(e.contains("result_cache.ttl_secs: must be at least 1"));
+        assert!(e.contains("nonces.redis: must be set for the Redis store"));
+        assert!(e.contains("nonces.max_outstanding: must be at least 1"));
+        assert!(e.contains("evidence_age.max_age_secs: must be at least 1"));
+        assert!(e.contains("reference_values_watch: ") && e.contains("corim is not a directory"));
+        assert!(e.contains("advisory_feed: feed.json is neither an HTTP URL nor a file"));
+        assert!(e.contains("state_signers: Cannot read the keys"));
+        assert!(e.contains("audit: OTLP endpoint collector:4318 is not an HTTP URL"));
+        assert!(e.contains("tracing: OTLP endpoint collector:4317 is not an HTTP URL"));
+        assert!(!e.contains("verifiers.sev"));
+        assert!(!e.contains("work_dir"));
+
+        // Without reading the files the config names.
+        let e = config.check_static().unwrap_err().to_string();
+        assert!(e.contains("worker_threads: must be at least 1"));
+        assert!(e.contains("tracing: OTLP endpoint collector:4317 is not an HTTP URL"));
+        assert!(!e.contains("reference_values_watch"));
+        assert!(!e.contains("advisory_feed"));
+        assert!(!e.contains("state_signers"));
+    }
+
+    #[test]
+    fn test_config_layers() {
+        let config: Config = ConfigLayers::new(None).unwrap().load().unwrap();
+        assert_eq!(config.policy_engine, "opa");
+        assert_eq!(config.work_dir, Config::default().work_dir);
+        assert!(config.sample_verifier);
+        assert_eq!(config.migration_policy, "migration");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "work_dir": "/var/lib/as",
+                "policy_engine": "opa",
+                "attestation_token_config": { "duration_min": 5, "issuer_name": "file" },
+                "listeners": [{ "address": "0.0.0.0:3000" }]
+            }"#,
+        )
+        .unwrap();
+        let env = [
+            ("AS_CONFIG_WORK_DIR", "/var/lib/env"),
+            ("AS_CONFIG_ATTESTATION_TOKEN_CONFIG__DURATION_MIN", "10"),
+            ("AS_CONFIG_LISTENERS__1__ADDRESS", "[::]:3000"),
+            ("AS_WORK_DIR", "/ignored"),
+        ];
+        let layers = ConfigLayers::new(Some(&path))
+            .unwrap()
+            .with_env(env.map(|(name, value)| (name.to_string(), value.to_string())))
+            .unwrap()
+            .with_override("attestation_token_config.duration_min=15")
+            .unwrap()
+            .with_override("admission.max_in_flight=8")
+            .unwrap();
+
+        let config: Config = layers.load().unwrap();
+        assert_eq!(config.work_dir, PathBuf::from("/var/lib/env"));
+        assert_eq!(config.attestation_token_config.duration_min, 15);
+        assert_eq!(
+            config.attestation_token_config.issuer_name.as_deref(),
+            Some("file")
+        );
+        assert_eq!(config.admission.max_in_flight, Some(8));
+        assert_eq!(
+            layers.value["listeners"],
+            serde_json::json!([{ "address": "0.0.0.0:3000" }, { "address": "[::]:3000" }])
+        );
+        assert!(layers.digest().starts_with("sha256:"));
+        assert_eq!(layers.digest(), layers.clone().digest());
+        assert_ne!(
+            layers.digest(),
+            layers
+                .clone()
+                .with_override("log_level=debug")
+                .unwrap()
+                .digest()
+        );
+
+        let layers = ConfigLayers::new(None).unwrap();
+        assert!(layers.clone().with_override("work_dir").is_err());
+        assert!(layers.clone().with_override("a..b=1").is_err());
+        assert!(layers
+            .with_override("listeners=[]")
+            .unwrap()
+            .with_override("listeners.1.address=[::]:3000")
+            .is_err());
+    }
+
+    #[test]
+    fn test_toml() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let document = r#"
+work_dir = "/var/lib/as"
+migration_policy = "td-migration"
+
+[attestation_token_config]
+duration_min = 10
+"#;
+        std::fs::write(&path, document).unwrap();
+        let config = Config::try_from(path.as_path()).unwrap();
+        assert_eq!(config.work_dir, PathBuf::from("/var/lib/as"));
+        assert_eq!(config.migration_policy, "td-migration");
+        assert_eq!(config.attestation_token_config.duration_min, 10);
+
+        let layers = ConfigLayers::from_document(document).unwrap();
+        assert_eq!(
+            layers.digest(),
+            ConfigLayers::new(Some(&path)).unwrap().digest()
+        );
+        let layers = ConfigLayers::from_document(r#"{"work_dir": "/var/lib/as"}"#).unwrap();
+        assert_eq!(layers.load::<Config>().unwrap().work_dir, config.work_dir);
+        assert!(ConfigLayers::from_document("[1, 2]").is_err());
+        assert!(ConfigLayers::from_document("work_dir =").is_err());
+    }
+
+    #[test]
+    fn test_changes() {
+        let current = ConfigLayers::from_document(
+            r#"{
+                "work_dir": "/var/lib/as",
+                "attestation_token_config": { "duration_min": 5, "issuer_name": "as" },
+                "claim_filters": []
+            }"#,
+        )
+        .unwrap();
+        assert!(current.changes(&current, RELOADABLE_SECTIONS).is_empty());
+
+        let candidate = current
+            .clone()
+            .with_override("attestation_token_config.duration_min=10")
+            .unwrap()
+            .with_override(r#"default_policies=[{"policy_id": "tdx"}]"#)
+            .unwrap();
+        let changes = current.changes(&candidate, RELOADABLE_SECTIONS);
+        assert_eq!(
+            changes.reloaded,
+            ["attestation_token_config.duration_min", "default_policies"]
+        );
+        assert!(changes.restart_needed.is_empty());
+
+        let candidate = candidate
+            .with_override("attestation_token_config.issuer_name=other")
+            .unwrap()
+            .with_override("work_dir=/var/lib/other")
+            .unwrap()
+            .with_override(r#"claim_filters=[{"omit": ["tdx.quote.body.report_data"]}]"#)
+            .unwrap();
+        let changes = current.changes(&candidate, RELOADABLE_SECTIONS);
+        assert_eq!(changes.reloaded, ["claim_filters", "default_policies"]);
+        assert_eq!(
+            changes.restart_needed,
+            ["attestation_token_config", "work_dir"]
+        );
     }
 }
@@ -0,0 +1,149 @@
+//! Single-shot HPKE ([RFC 9180](https://www.rfc-editor.org/rfc/rfc9180))
+//! receiver in base mode, with DHKEM(X25519, HKDF-SHA256), HKDF-SHA256 and
+//! AES-128-GCM.
+
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes128Gcm, KeyInit, Nonce};
+use anyhow::*;
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+const KEM_ID: u16 = 0x0020;
+const KDF_ID: u16 = 0x0001;
+const AEAD_ID: u16 = 0x0001;
+
+const MODE_BASE: u8 = 0x00;
+const N_SECRET: usize = 32;
+const N_K: usize = 16;
+const N_N: usize = 12;
+
+/// Length of the encapsulated key (an X25519 public key).
+pub const N_ENC: usize = 32;
+
+fn kem_suite_id() -> Vec<u8> {
+    [b"KEM".as_slice(), &KEM_ID.to_be_bytes()].concat()
+}
+
+fn hpke_suite_id() -> Vec<u8> {
+    [
+        b"HPKE".as_slice(),
+        &KEM_ID.to_be_bytes(),
+        &KDF_ID.to_be_bytes(),
+        &AEAD_ID.to_be_bytes(),
+    ]
+    .concat()
+}
+
+fn labeled_extract(suite_id: &[u8], salt: &[u8], label: &[u8], ikm: &[u8]) -> Vec<u8> {
+    let labeled_ikm = [b"HPKE-v1".as_slice(), suite_id, label, ikm].concat();
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(salt), &labeled_ikm);
+    prk.to_vec()
+}
+
+fn labeled_expand(
+    suite_id: &[u8],
+    prk: &[u8],
+    label: &[u8],
+    info: &[u8],
+    len: usize,
+) -> Result<Vec<u8>> {
+    let labeled_info = [
+        &(len as u16).to_be_bytes(),
+        b"HPKE-v1".as_slice(),
+        suite_id,
+        label,
+        info,
+    ]
+    .concat();
+    let mut okm = vec![0; len];
+    Hkdf::<Sha256>::from_prk(prk)
+        .map_err(|e| anyhow!("HPKE: invalid PRK: {e}"))?
+        .expand(&labeled_info, &mut okm)
+        .map_err(|e| anyhow!("HPKE: expand: {e}"))?;
+    Ok(okm)
+}
+
+/// Derive the KEM shared secret from the Diffie-Hellman output of the
+/// recipient key with the encapsulated key `enc`.
+pub fn extract_and_expand(dh: &[u8], enc: &[u8], pk_r: &[u8]) -> Result<Vec<u8>> {
+    let suite_id = kem_suite_id();
+    let kem_context = [enc, pk_r].concat();
+    let eae_prk = labeled_extract(&suite_id, b"", b"eae_prk", dh);
+    labeled_expand(
+        &suite_id,
+        &eae_prk,
+        b"shared_secret",
+        &kem_context,
+        N_SECRET,
+    )
+}
+
+/// Open `ciphertext`, the first message sealed in the context set up with
+/// `shared_secret` and `info`.
+pub fn open(shared_secret: &[u8], info: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let suite_id = hpke_suite_id();
+    let psk_id_hash = labeled_extract(&suite_id, b"", b"psk_id_hash", b"");
+    let info_hash = labeled_extract(&suite_id, b"", b"info_hash", info);
+    let key_schedule_context = [&[MODE_BASE], psk_id_hash.as_slice(), &info_hash].concat();
+
+    let secret = labeled_extract(&suite_id, shared_secret, b"secret", b"");
+    let key = labeled_expand(&suite_id, &secret, b"key", &key_schedule_context, N_K)?;
+    let base_nonce = labeled_expand(
+        &suite_id,
+        &secret,
+        b"base_nonce",
+        &key_schedule_context,
+        N_N,
+    )?;
+
+    // The nonce of the first message is the base nonce itself.
+    Aes128Gcm::new_from_slice(&key)
+        .map_err(|e| anyhow!("HPKE: invalid key: {e}"))?
+        .decrypt(
+            Nonce::from_slice(&base_nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| anyhow!("HPKE: failed to open ciphertext"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    fn unhex(s: &str) -> Vec<u8> {
+        hex::decode(s).unwrap()
+    }
+
+    // RFC 9180, A.1.1. DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, AES-128-GCM,
+    // base setup, sequence number 0.
+    #[test]
+    fn rfc9180_base_x25519_sha256_aes128gcm() {
+        let sk_r: [u8; 32] =
+            unhex("4612c550263fc8ad58375df3f557aac531d26850903e55a9f23f21d8534e8ac8")
+                .try_into()
+                .unwrap();
+        let enc = unhex("37fda3567bdbd628e88668c3c8d7e97d1d1253b6d4ea6d44c150f741f1bf4431");
+        let info = unhex("4f6465206f6e2061204772656369616e2055726e");
+        let aad = unhex("436f756e742d30");
+        let ct = unhex("f938558b5d72f1a23810b4be2ab4f84331acc02fc97babc53a52ae8218a355a96d8770ac83d07bea87e13c512a");
+        let pt = unhex("4265617574792069732074727574682c20747275746820626561757479");
+
+        let sk_r = StaticSecret::from(sk_r);
+        let pk_r = PublicKey::from(&sk_r);
+        let pk_e = PublicKey::from(<[u8; 32]>::try_from(enc.as_slice()).unwrap());
+        let dh = sk_r.diffie_hellman(&pk_e);
+
+        let shared_secret = extract_and_expand(dh.as_bytes(), &enc, pk_r.as_bytes()).unwrap();
+        assert_eq!(
+            shared_secret,
+            unhex("fe0e18c9f024ce43799ae393c7e8fe8fce9d218875e8227b0187c04e7d2ea1fc")
+        );
+
+        assert_eq!(open(&shared_secret, &info, &aad, &ct).unwrap(), pt);
+        assert!(open(&shared_secret, &info, b"Count-1", &ct).is_err());
+    }
+}
@@ -0,0 +1,378 @@
+//! Decryption of evidence that attesters encrypt to the AS, to hide their
+//! measurements from intermediaries.
+//!
+//! Encrypted evidence replaces the `tee-evidence` of the attestation with
+//! an envelope like
+//!
+//! ```json
+//! {
+//!     "encrypted": {
+//!         "alg": "HPKE-X25519-SHA256-AES128GCM",
+//!         "kid": "as-key-1",
+//!         "encapsulated_key": "<base64>",
+//!         "ciphertext": "<base64>"
+//!     }
+//! }
+//! ```
+//!
+//! which is decrypted with the AS key `kid` before the evidence is
+//! dispatched to a verifier. Supported algorithms are
+//! - `RSA-OAEP-256`: `encapsulated_key` is an AES-256-GCM key encrypted
+//!   with RSA-OAEP (SHA-256), and `ciphertext` (tag appended) is encrypted
+//!   under it with the nonce `iv`;
+//! - `HPKE-X25519-SHA256-AES128GCM`: `ciphertext` is sealed with HPKE in
+//!   base mode ([RFC 9180](https://www.rfc-editor.org/rfc/rfc9180)), with
+//!   [`HPKE_INFO`] as info and no AAD.
+
+use anyhow::*;
+use base64::Engine;
+use kbs_types::Attestation;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+mod file;
+mod hpke;
+#[cfg(feature = "pkcs11")]
+pub(crate) mod pkcs11;
+
+/// HPKE info binding the ciphertext to its use.
+pub const HPKE_INFO: &[u8] = b"attestation-service/evidence";
+
+const ENVELOPE_KEY: &str = "encrypted";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    #[serde(rename = "RSA-OAEP-256")]
+    RsaOaep256,
+    #[serde(rename = "HPKE-X25519-SHA256-AES128GCM")]
+    HpkeX25519Sha256Aes128Gcm,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EncryptedEvidence {
+    pub alg: Algorithm,
+    pub kid: String,
+    /// Base64 encoded.
+    pub encapsulated_key: String,
+    /// Base64 encoded AES-GCM nonce, for `RSA-OAEP-256` only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iv: Option<String>,
+    /// Base64 encoded.
+    pub ciphertext: String,
+}
+
+/// A private key of the AS that evidence can be encrypted to.
+pub trait DecryptionKey {
+    /// Decrypt the key the attester encapsulated for `alg`. It is the
+    /// AES-256-GCM content key for `RSA-OAEP-256`, or the KEM shared secret
+    /// for HPKE.
+    fn decapsulate(&self, alg: Algorithm, encapsulated_key: &[u8]) -> Result<Vec<u8>>;
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum KeySource {
+    /// A PEM encoded PKCS#8 (or PKCS#1 for RSA) private key file.
+    File { path: PathBuf },
+
+    /// An RSA private key held by an HSM, used through its PKCS#11 module.
+    #[cfg(feature = "pkcs11")]
+    Pkcs11 {
+        module: PathBuf,
+        token: String,
+        pin: String,
+        label: String,
+    },
+}
+
+/// Configuration of an evidence decryption key, e.g.
+///
+/// ```json
+/// { "id": "as-key-1", "alg": "RSA-OAEP-256", "type": "file", "path": "/etc/as/key.pem" }
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DecryptionKeyConfig {
+    /// The `kid` attesters refer to the key with.
+    pub id: String,
+    pub alg: Algorithm,
+    #[serde(flatten)]
+    pub source: KeySource,
+}
+
+impl DecryptionKeyConfig {
+    fn to_key(&self) -> Result<Box<dyn DecryptionKey + Send + Sync>> {
+        match &self.source {
+            KeySource::File { path } => Ok(Box::new(file::FileKey::new(path, self.alg)?)
+                as Box<dyn DecryptionKey + Send + Sync>),
+            #[cfg(feature = "pkcs11")]
+            KeySource::Pkcs11 {
+                module,
+                token,
+                pin,
+                label,
+            } => {
+                if self.alg != Algorithm::RsaOaep256 {
+                    bail!("PKCS#11 key {} only supports RSA-OAEP-256", self.id);
+                }
+                Ok(Box::new(pkcs11::Pkcs11Key::new(module, token, pin, label)?)
+                    as Box<dyn DecryptionKey + Send + Sync>)
+            }
+        }
+    }
+}
+
+/// Decrypts encrypted evidence with the configured keys.
+#[derive(Default)]
+pub struct EvidenceDecryptor {
+    keys: HashMap<String, (Algorithm, Box<dyn DecryptionKey + Send + Sync>)>,
+}
+
+impl EvidenceDecryptor {
+    pub fn new(configs: &[DecryptionKeyConfig]) -> Result<Self> {
+        let mut keys = HashMap::new();
+        for config in configs {
+            let key = config
+                .to_key()
+                .with_context(|| format!("load evidence decryption key {}", config.id))?;
+            if keys.insert(config.id.clone(), (config.alg, key)).is_some() {
+                bail!("duplicated evidence decryption key {}", config.id);
+            }
+        }
+
+        Ok(Self { keys })
+    }
+
+    /// Replace encrypted `tee-evidence` with its plaintext. Evidence that is
+    /// not encrypted is returned as is.
+    pub fn decrypt(&self, mut attestation: Attestation) -> Result<Attestation> {
+        let envelope = match serde_json::from_str::<Value>(&attestation.tee_evidence) {
+            Result::Ok(Value::Object(mut map)) => match map.remove(ENVELOPE_KEY) {
+                Some(envelope) => envelope,
+                None => return Ok(attestation),
+            },
+            _ => return Ok(attestation),
+        };
+        let encrypted: EncryptedEvidence =
+            serde_json::from_value(envelope).context("Malformed encrypted evidence")?;
+
+        let plaintext = self.decrypt_evidence(&encrypted)?;
+        attestation.tee_evidence =
+            String::from_utf8(plaintext).context("Decrypted evidence is not UTF-8")?;
+
+        Ok(attestation)
+    }
+
+    fn decrypt_evidence(&self, encrypted: &EncryptedEvidence) -> Result<Vec<u8>> {
+        let (alg, key) = self
+            .keys
+            .get(&encrypted.kid)
+            .ok_or_else(|| anyhow!("Unknown evidence decryption key {}", encrypted.kid))?;
+        if *alg != encrypted.alg {
+            bail!(
+                "Evidence decryption key {} does not support {:?}",
+                encrypted.kid,
+                encrypted.alg
+            );
+        }
+
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let encapsulated_key = b64
+            .decode(&encrypted.encapsulated_key)
+            .context("Base64 decode encapsulated key")?;
+        let ciphertext = b64
+            .decode(&encrypted.ciphertext)
+            .context("Base64 decode ciphertext")?;
+        let secret = key.decapsulate(*alg, &encapsulated_key)?;
+
+        match alg {
+            Algorithm::RsaOaep256 => {
+                use aes_gcm::aead::Aead;
+                use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+
+                let iv = b64
+                    .decode(encrypted.iv.as_ref().context("Missing iv")?)
+                    .context("Base64 decode iv")?;
+                if iv.len() != 12 {
+                    bail!("Invalid AES-GCM nonce length {}", iv.len());
+                }
+                Aes256Gcm::new_from_slice(&secret)
+                    .map_err(|e| anyhow!("Invalid content key: {e}"))?
+                    .decrypt(Nonce::from_slice(&iv), ciphertext.as_slice())
+                    .map_err(|_| anyhow!("Failed to decrypt evidence"))
+            }
+            Algorithm::HpkeX25519Sha256Aes128Gcm => {
+                hpke::open(&secret, HPKE_INFO, b"", &ciphertext)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes_gcm::aead::Aead;
+    use aes_gcm::{Aes128Gcm, Aes256Gcm, KeyInit, Nonce};
+    use kbs_types::TeePubKey;
+    use rsa::pkcs8::EncodePrivateKey;
+    use rsa::sha2::Sha256;
+    use rsa::{Oaep, RsaPrivateKey, RsaPublicKey};
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    const EVIDENCE: &str = r#"{"svn":"1","report_data":"AAAA"}"#;
+
+    fn attestation(tee_evidence: String) -> Attestation {
+        Attestation {
+            tee_pubkey: TeePubKey {
+                kty: "RSA".to_string(),
+                alg: "RSA1_5".to_string(),
+                k_mod: "n".to_string(),
+                k_exp: "AQAB".to_string(),
+            },
+            tee_evidence,
+        }
+    }
+
+    fn envelope(encrypted: EncryptedEvidence) -> String {
+        serde_json::json!({ ENVELOPE_KEY: encrypted }).to_string()
+    }
+
+    #[test]
+    fn decrypt_rsa_oaep() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rsa.pem");
+        let mut rng = rand::thread_rng();
+        let private_key = RsaPrivateKey::new(&mut rng, 2048).unwrap();
+        private_key
+            .write_pkcs8_pem_file(&path, rsa::pkcs8::LineEnding::LF)
+            .unwrap();
+
+        let decryptor = EvidenceDecryptor::new(&[DecryptionKeyConfig {
+            id: "rsa".to_string(),
+            alg: Algorithm::RsaOaep256,
+            source: KeySource::File { path },
+        }])
+        .unwrap();
+
+        let cek = [7u8; 32];
+        let iv = [9u8; 12];
+        let ciphertext = Aes256Gcm::new_from_slice(&cek)
+            .unwrap()
+            .encrypt(Nonce::from_slice(&iv), EVIDENCE.as_bytes())
+            .unwrap();
+        let encapsulated_key = RsaPublicKey::from(&private_key)
+            .encrypt(&mut rng, Oaep::new::<Sha256>(), &cek)
+            .unwrap();
+
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let mut encrypted = EncryptedEvidence {
+            alg: Algorithm::RsaOaep256,
+            kid: "rsa".to_string(),
+            encapsulated_key: b64.encode(encapsulated_key),
+            iv: Some(b64.encode(iv)),
+            ciphertext: b64.encode(ciphertext),
+        };
+        let decrypted = decryptor
+            .decrypt(attestation(envelope(encrypted.clone())))
+            .unwrap();
+        assert_eq!(decrypted.tee_evidence, EVIDENCE);
+
+        encrypted.kid = "unknown".to_string();
+        assert!(decryptor
+            .decrypt(attestation(envelope(encrypted.clone())))
+            .is_err());
+
+        encrypted.kid = "rsa".to_string();
+        encrypted.alg = Algorithm::HpkeX25519Sha256Aes128Gcm;
+        assert!(decryptor.decrypt(attestation(envelope(encrypted))).is_err());
+    }
+
+    #[test]
+    fn decrypt_hpke() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("x25519.pem");
+        let sk_r = StaticSecret::from([3u8; 32]);
+        std::fs::write(&path, file::tests::x25519_pkcs8_pem(&sk_r.to_bytes())).unwrap();
+
+        let decryptor = EvidenceDecryptor::new(&[DecryptionKeyConfig {
+            id: "hpke".to_string(),
+            alg: Algorithm::HpkeX25519Sha256Aes128Gcm,
+            source: KeySource::File { path },
+        }])
+        .unwrap();
+
+        // Seal to the public key of the AS, as an attester would.
+        let sk_e = StaticSecret::from([5u8; 32]);
+        let enc = PublicKey::from(&sk_e);
+        let pk_r = PublicKey::from(&sk_r);
+        let dh = sk_e.diffie_hellman(&pk_r);
+        let shared_secret =
+            hpke::extract_and_expand(dh.as_bytes(), enc.as_bytes(), pk_r.as_bytes()).unwrap();
+        let ciphertext = seal(&shared_secret, EVIDENCE.as_bytes());
+
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let encrypted = EncryptedEvidence {
+            alg: Algorithm::HpkeX25519Sha256Aes128Gcm,
+            kid: "hpke".to_string(),
+            encapsulated_key: b64.encode(enc.as_bytes()),
+            iv: None,
+            ciphertext: b64.encode(ciphertext),
+        };
+        let decrypted = decryptor.decrypt(attestation(envelope(encrypted))).unwrap();
+        assert_eq!(decrypted.tee_evidence, EVIDENCE);
+    }
+
+    #[test]
+    fn plain_evidence_is_untouched() {
+        let decryptor = EvidenceDecryptor::default();
+        let decrypted = decryptor
+            .decrypt(attestation(EVIDENCE.to_string()))
+            .unwrap();
+        assert_eq!(decrypted.tee_evidence, EVIDENCE);
+
+        let encrypted = serde_json::json!({ ENVELOPE_KEY: { "alg": "none" } }).to_string();
+        assert!(decryptor.decrypt(attestation(encrypted)).is_err());
+    }
+
+    /// HPKE seal of the first message, mirroring `hpke::open`.
+    fn seal(shared_secret: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        use hkdf::Hkdf;
+
+        let suite_id = [b"HPKE".as_slice(), &[0, 0x20, 0, 1, 0, 1]].concat();
+        let extract = |salt: &[u8], label: &[u8], ikm: &[u8]| {
+            let ikm = [b"HPKE-v1".as_slice(), &suite_id, label, ikm].concat();
+            Hkdf::<Sha256>::extract(Some(salt), &ikm).0.to_vec()
+        };
+        let expand = |prk: &[u8], label: &[u8], info: &[u8], len: usize| {
+            let info = [
+                &(len as u16).to_be_bytes(),
+                b"HPKE-v1".as_slice(),
+                &suite_id,
+                label,
+                info,
+            ]
+            .concat();
+            let mut okm = vec![0; len];
+            Hkdf::<Sha256>::from_prk(prk)
+                .unwrap()
+                .expand(&info, &mut okm)
+                .unwrap();
+            okm
+        };
+
+        let context = [
+            &[0u8],
+            extract(b"", b"psk_id_hash", b"").as_slice(),
+            &extract(b"", b"info_hash", HPKE_INFO),
+        ]
+        .concat();
+        let secret = extract(shared_secret, b"secret", b"");
+        let key = expand(&secret, b"key", &context, 16);
+        let nonce = expand(&secret, b"base_nonce", &context, 12);
+        Aes128Gcm::new_from_slice(&key)
+            .unwrap()
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .unwrap()
+    }
+}
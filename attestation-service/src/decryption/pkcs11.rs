@@ -0,0 +1,84 @@
+use anyhow::*;
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::error::RvError;
+use cryptoki::mechanism::rsa::{PkcsMgfType, PkcsOaepParams, PkcsOaepSource};
+use cryptoki::mechanism::{Mechanism, MechanismType};
+use cryptoki::object::{Attribute, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::types::AuthPin;
+use std::path::Path;
+use std::sync::Mutex;
+
+use super::{Algorithm, DecryptionKey};
+
+/// An RSA private key that never leaves the HSM holding it.
+pub struct Pkcs11Key {
+    // Sessions are not `Sync`.
+    session: Mutex<Session>,
+    key: ObjectHandle,
+}
+
+/// Open a session of the PKCS#11 `token`, logged in with `pin`.
+pub(crate) fn login(module: &Path, token: &str, pin: &str) -> Result<Session> {
+    let pkcs11 = Pkcs11::new(module)
+        .with_context(|| format!("load PKCS#11 module {}", module.display()))?;
+    // Other keys may be held by the same module.
+    match pkcs11.initialize(CInitializeArgs::OsThreads) {
+        Err(cryptoki::error::Error::Pkcs11(RvError::CryptokiAlreadyInitialized)) | Ok(()) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let slot = pkcs11
+        .get_slots_with_token()?
+        .into_iter()
+        .find(|slot| {
+            pkcs11
+                .get_token_info(*slot)
+                .map(|info| info.label() == token)
+                .unwrap_or(false)
+        })
+        .ok_or_else(|| anyhow!("PKCS#11 token {token} not found"))?;
+
+    let session = pkcs11.open_ro_session(slot)?;
+    session.login(UserType::User, Some(&AuthPin::new(pin.to_string())))?;
+    Ok(session)
+}
+
+impl Pkcs11Key {
+    pub fn new(module: &Path, token: &str, pin: &str, label: &str) -> Result<Self> {
+        let session = login(module, token, pin)?;
+
+        let key = session
+            .find_objects(&[
+                Attribute::Class(ObjectClass::PRIVATE_KEY),
+                Attribute::Label(label.as_bytes().to_vec()),
+            ])?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("private key {label} not found in PKCS#11 token {token}"))?;
+
+        Ok(Self {
+            session: Mutex::new(session),
+            key,
+        })
+    }
+}
+
+impl DecryptionKey for Pkcs11Key {
+    fn decapsulate(&self, alg: Algorithm, encapsulated_key: &[u8]) -> Result<Vec<u8>> {
+        if alg != Algorithm::RsaOaep256 {
+            bail!("PKCS#11 key does not support {alg:?}");
+        }
+
+        let params = PkcsOaepParams::new(
+            MechanismType::SHA256,
+            PkcsMgfType::MGF1_SHA256,
+            PkcsOaepSource::empty(),
+        );
+        self.session
+            .lock()
+            .map_err(|_| anyhow!("PKCS#11 session lock poisoned"))?
+            .decrypt(&Mechanism::RsaPkcsOaep(params), self.key, encapsulated_key)
+            .map_err(|e| anyhow!("RSA-OAEP decryption failed: {e}"))
+    }
+}
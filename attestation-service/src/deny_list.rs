@@ -0,0 +1,261 @@
+//! Deny-list of known-bad measurements.
+//!
+//! When a guest image turns out to be compromised, rolling out policies
+//! that deny its measurements takes time, and a stale policy keeps allowing
+//! it meanwhile. Security teams instead add the measurement to the
+//! deny-list of the AS, which takes effect on the next attestation: evidence
+//! with a claim of a denied value is rejected before any policy is
+//! evaluated, with a [`Denied`] error, at the `deny_list` rejection stage.
+//!
+//! An entry matches a claim of the verifier by its flattened name, e.g.
+//! `tdx.quote.body.mr_td`, before the claims schema and the measurement
+//! encoding of the request apply, so that no request option evades it.
+//! Hex values match ignoring case. The entries are kept in `deny_list.json`
+//! in the work dir, and are part of the backups of the AS, so that standbys
+//! replicate them, see [`crate::backup`].
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+const DENY_LIST_FILE: &str = "deny_list.json";
+
+/// A denied value of a claim.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DenyListEntry {
+    /// Flattened name of the claim, as the verifier emits it.
+    pub claim: String,
+    pub value: String,
+    /// Why the value is denied, e.g. the compromise found.
+    pub reason: String,
+    /// Reference of the security advisory, e.g. a CVE ID or a URL.
+    #[serde(default)]
+    pub advisory: Option<String>,
+    /// When the entry was added. Set by the AS.
+    #[serde(default = "Utc::now")]
+    pub added: DateTime<Utc>,
+}
+
+impl DenyListEntry {
+    /// Whether `value` of the claim of the entry is the denied one.
+    fn matches(&self, value: &Value) -> bool {
+        let text = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        match self.value.chars().all(|c| c.is_ascii_hexdigit()) {
+            true => text.eq_ignore_ascii_case(&self.value),
+            false => text == self.value,
+        }
+    }
+}
+
+/// The evidence has a deny-listed claim value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Denied {
+    pub entry: DenyListEntry,
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} is deny-listed: {}",
+            self.entry.claim, self.entry.value, self.entry.reason
+        )?;
+        match &self.entry.advisory {
+            Some(advisory) => write!(f, " ({advisory})"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for Denied {}
+
+/// The deny-list, persisted in the work dir.
+pub(crate) struct DenyList {
+    path: PathBuf,
+    entries: Mutex<Vec<DenyListEntry>>,
+    /// Changes since the AS started, so that results appraised before a
+    /// change are not reused, see [`crate::result_cache`].
+    generation: AtomicU64,
+}
+
+impl DenyList {
+    pub fn new(work_dir: &Path) -> Result<Self> {
+        let path = work_dir.join(DENY_LIST_FILE);
+        let entries = match path.exists() {
+            true => serde_json::from_slice(&fs::read(&path)?)
+                .with_context(|| format!("Malformed {}", path.display()))?,
+            false => Vec::new(),
+        };
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+            generation: AtomicU64::new(0),
+        })
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Vec<DenyListEntry>>> {
+        self.entries
+            .lock()
+            .map_err(|_| anyhow!("The deny-list is poisoned"))
+    }
+
+    fn save(&self, entries: &[DenyListEntry]) -> Result<()> {
+        fs::write(&self.path, serde_json::to_vec_pretty(entries)?)
+            .with_context(|| format!("Cannot write {}", self.path.display()))?;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn list(&self) -> Result<Vec<DenyListEntry>> {
+        Ok(self.lock()?.clone())
+    }
+
+    /// Deny the value of `entry`, replacing the entry of the same claim
+    /// and value if any.
+    pub fn add(&self, mut entry: DenyListEntry) -> Result<()> {
+        if entry.claim.is_empty() || entry.value.is_empty() {
+            bail!("A deny-list entry needs a claim and a value");
+        }
+        if entry.reason.is_empty() {
+            bail!("A deny-list entry needs a reason");
+        }
+        entry.added = Utc::now();
+        let mut entries = self.lock()?;
+        entries.retain(|e| !(e.claim == entry.claim && e.value == entry.value));
+        warn!(
+            "Deny-listing {} {}: {}",
+            entry.claim, entry.value, entry.reason
+        );
+        entries.push(entry);
+        self.save(&entries)
+    }
+
+    /// Replace the deny-list with `entries`, e.g. of a backup.
+    pub fn replace(&self, entries: Vec<DenyListEntry>) -> Result<()> {
+        let mut current = self.lock()?;
+        if *current == entries {
+            return Ok(());
+        }
+        *current = entries;
+        self.save(&current)
+    }
+
+    /// Remove the entry of `claim` and `value`.
+    pub fn remove(&self, claim: &str, value: &str) -> Result<()> {
+        let mut entries = self.lock()?;
+        let len = entries.len();
+        entries.retain(|e| !(e.claim == claim && e.value == value));
+        if entries.len() == len {
+            bail!("{claim} {value} is not deny-listed");
+        }
+        info!("{claim} {value} removed from the deny-list");
+        self.save(&entries)
+    }
+
+    /// Check the flattened `claims` of evidence against the deny-list.
+    pub fn check(&self, claims: &Value) -> Result<()> {
+        let Some(claims) = claims.as_object() else {
+            return Ok(());
+        };
+        let entries = self.lock()?;
+        for entry in entries.iter() {
+            if claims
+                .get(&entry.claim)
+                .is_some_and(|value| entry.matches(value))
+            {
+                return Err(Denied {
+                    entry: entry.clone(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entry(claim: &str, value: &str) -> DenyListEntry {
+        DenyListEntry {
+            claim: claim.to_string(),
+            value: value.to_string(),
+            reason: "leaked signing key".to_string(),
+            advisory: Some("CVE-2024-0001".to_string()),
+            added: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let deny_list = DenyList::new(dir.path()).unwrap();
+        let claims = json!({
+            "tdx.quote.body.mr_td": "AABB",
+            "tdx.quote.header.version": 4,
+        });
+        deny_list.check(&claims).unwrap();
+
+        deny_list
+            .add(entry("tdx.quote.body.mr_td", "aabb"))
+            .unwrap();
+        let e = deny_list.check(&claims).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "tdx.quote.body.mr_td aabb is deny-listed: leaked signing key (CVE-2024-0001)"
+        );
+        assert!(e.is::<Denied>());
+        assert_eq!(deny_list.generation(), 1);
+
+        deny_list
+            .add(entry("tdx.quote.header.version", "4"))
+            .unwrap();
+        deny_list.remove("tdx.quote.body.mr_td", "aabb").unwrap();
+        assert!(deny_list.check(&claims).is_err());
+        assert!(deny_list.remove("tdx.quote.body.mr_td", "aabb").is_err());
+        assert!(deny_list.add(entry("", "aabb")).is_err());
+    }
+
+    #[test]
+    fn test_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let deny_list = DenyList::new(dir.path()).unwrap();
+        deny_list.add(entry("snp.measurement", "abc=")).unwrap();
+        deny_list.add(entry("snp.measurement", "abc=")).unwrap();
+
+        let entries = DenyList::new(dir.path()).unwrap().list().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].value, "abc=");
+        // Values that are not hex match exactly.
+        let deny_list = DenyList::new(dir.path()).unwrap();
+        assert!(deny_list
+            .check(&json!({ "snp.measurement": "ABC=" }))
+            .is_ok());
+
+        // Restoring the same entries is no change.
+        deny_list.replace(entries).unwrap();
+        assert_eq!(deny_list.generation(), 0);
+        deny_list.replace(Vec::new()).unwrap();
+        assert_eq!(deny_list.generation(), 1);
+        assert!(DenyList::new(dir.path())
+            .unwrap()
+            .list()
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,452 @@
+//! Human readable reports of evidence verification.
+//!
+//! [`crate::AttestationService::explain`] verifies evidence like
+//! `evaluate`, but instead of issuing a token it records every check it
+//! performs, the claims compared with reference values and the policy
+//! decision, to be rendered as Markdown or plain text. Such reports can be
+//! attached to change tickets when onboarding new reference values.
+//!
+//! The checks are recorded by the evaluation itself as it runs, through an
+//! [`Explanation`], so that the report cannot miss any of them.
+
+use anyhow::Result;
+use as_types::{PolicyDecision, PolicyViolation};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Mutex, PoisonError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum ReportFormat {
+    Markdown,
+    Text,
+}
+
+/// A verification step and its outcome.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub passed: bool,
+    /// What was checked, or why it failed.
+    pub details: String,
+}
+
+/// A claim compared with its reference values.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Comparison {
+    pub claim: String,
+    pub value: Value,
+    pub reference: Vec<String>,
+    /// Whether the value is one of the reference values. Claims with an
+    /// empty list of reference values always match.
+    pub matched: bool,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct VerificationReport {
+    pub tee: String,
+    pub checks: Vec<Check>,
+    pub comparisons: Vec<Comparison>,
+    pub violations: Vec<PolicyViolation>,
+    /// Non-fatal verification warnings.
+    pub warnings: Vec<String>,
+    pub decision: PolicyDecision,
+}
+
+impl VerificationReport {
+    pub fn new(tee: String) -> Self {
+        Self {
+            tee,
+            checks: Vec::new(),
+            comparisons: Vec::new(),
+            violations: Vec::new(),
+            warnings: Vec::new(),
+            decision: PolicyDecision::Deny,
+        }
+    }
+
+    /// Record the outcome of a step, described by `details` if it passed,
+    /// and pass it on.
+    pub(crate) fn check<T>(
+        &mut self,
+        name: &str,
+        result: Result<T>,
+        details: impl FnOnce(&T) -> String,
+    ) -> Result<T> {
+        let (passed, details) = match &result {
+            Ok(value) => (true, details(value)),
+            Err(e) => (false, format!("{e:#}")),
+        };
+        self.checks.push(Check {
+            name: name.to_string(),
+            passed,
+            details,
+        });
+        result
+    }
+
+    /// Compare the flattened `claims` with their reference values, as the
+    /// default policy does: numbers are compared by their decimal
+    /// representation, and SHA-384 digests match `sha384:` references. The
+    /// comparisons are added to those of the inputs of the other policies.
+    pub(crate) fn compare(&mut self, claims: &Value, reference: &HashMap<String, Vec<String>>) {
+        let Some(claims) = claims.as_object() else {
+            return;
+        };
+        let comparisons = claims
+            .iter()
+            .filter_map(|(claim, value)| {
+                let reference = reference.get(claim)?;
+                let text = match value {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                let equal = |r: &String| {
+                    *r == text || (value.is_string() && r.strip_prefix("sha384:") == Some(&text))
+                };
+                Some(Comparison {
+                    claim: claim.clone(),
+                    value: value.clone(),
+                    reference: reference.clone(),
+                    matched: reference.is_empty() || reference.iter().any(equal),
+                })
+            })
+            .filter(|comparison| !self.comparisons.contains(comparison))
+            .collect::<Vec<_>>();
+        self.comparisons.extend(comparisons);
+        self.comparisons.sort_by(|a, b| a.claim.cmp(&b.claim));
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Markdown => self.render_markdown(),
+            ReportFormat::Text => self.render_text(),
+        }
+    }
+
+    fn render_markdown(&self) -> String {
+        let cell = |s: &str| s.replace('|', "\\|").replace('\n', " ");
+        let mut out = String::new();
+        let _ = writeln!(out, "# Attestation verification report\n");
+        let _ = writeln!(out, "- TEE: `{}`", self.tee);
+        let _ = writeln!(out, "- Decision: **{}**", decision_name(self.decision));
+
+        let _ = writeln!(out, "\n## Checks\n");
+        let _ = writeln!(out, "| Check | Result | Details |");
+        let _ = writeln!(out, "|---|---|---|");
+        for check in &self.checks {
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} |",
+                cell(&check.name),
+                pass_name(check.passed),
+                cell(&check.details)
+            );
+        }
+
+        if !self.comparisons.is_empty() {
+            let _ = writeln!(out, "\n## Reference values\n");
+            let _ = writeln!(out, "| Claim | Value | Reference values | Result |");
+            let _ = writeln!(out, "|---|---|---|---|");
+            for comparison in &self.comparisons {
+                let _ = writeln!(
+                    out,
+                    "| `{}` | `{}` | {} | {} |",
+                    comparison.claim,
+                    cell(&comparison.value.to_string()),
+                    cell(&reference_list(&comparison.reference)),
+                    match_name(comparison.matched)
+                );
+            }
+        }
+
+        if !self.violations.is_empty() {
+            let _ = writeln!(out, "\n## Policy violations\n");
+            for violation in &self.violations {
+                let _ = writeln!(out, "- {}", violation_line(violation));
+            }
+        }
+
+        if !self.warnings.is_empty() {
+            let _ = writeln!(out, "\n## Warnings\n");
+            for warning in &self.warnings {
+                let _ = writeln!(out, "- {warning}");
+            }
+        }
+        out
+    }
+
+    fn render_text(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "Attestation verification report");
+        let _ = writeln!(out, "TEE: {}", self.tee);
+        let _ = writeln!(out, "Decision: {}", decision_name(self.decision));
+
+        let _ = writeln!(out, "\nChecks:");
+        for check in &self.checks {
+            let _ = writeln!(
+                out,
+                "  [{}] {}: {}",
+                pass_name(check.passed),
+                check.name,
+                check.details
+            );
+        }
+
+        if !self.comparisons.is_empty() {
+            let _ = writeln!(out, "\nReference values:");
+            for comparison in &self.comparisons {
+                let _ = writeln!(
+                    out,
+                    "  [{}] {} = {}, expected {}",
+                    match_name(comparison.matched),
+                    comparison.claim,
+                    comparison.value,
+                    reference_list(&comparison.reference)
+                );
+            }
+        }
+
+        if !self.violations.is_empty() {
+            let _ = writeln!(out, "\nPolicy violations:");
+            for violation in &self.violations {
+                let _ = writeln!(out, "  - {}", violation_line(violation));
+            }
+        }
+
+        if !self.warnings.is_empty() {
+            let _ = writeln!(out, "\nWarnings:");
+            for warning in &self.warnings {
+                let _ = writeln!(out, "  - {warning}");
+            }
+        }
+        out
+    }
+}
+
+/// Where an evaluation records the checks it performs, if explaining.
+#[derive(Clone, Copy)]
+pub(crate) struct Explanation<'a> {
+    report: Option<&'a Mutex<VerificationReport>>,
+    freshness: bool,
+}
+
+impl<'a> Explanation<'a> {
+    /// An evaluation that records nothing.
+    pub fn none() -> Self {
+        Self {
+            report: None,
+            freshness: true,
+        }
+    }
+
+    /// An evaluation that records its checks in `report`, and checks the
+    /// freshness of the evidence only if `freshness`, e.g. not when
+    /// appraising the evidence of the history again.
+    pub fn new(report: &'a Mutex<VerificationReport>, freshness: bool) -> Self {
+        Self {
+            report: Some(report),
+            freshness,
+        }
+    }
+
+    pub fn is_explaining(&self) -> bool {
+        self.report.is_some()
+    }
+
+    pub fn checks_freshness(&self) -> bool {
+        self.freshness
+    }
+
+    /// Record the outcome of a step if explaining, see
+    /// [`VerificationReport::check`], and pass it on.
+    pub fn check<T>(
+        &self,
+        name: &str,
+        result: Result<T>,
+        details: impl FnOnce(&T) -> String,
+    ) -> Result<T> {
+        match self.report {
+            Some(report) => report
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .check(name, result, details),
+            None => result,
+        }
+    }
+
+    /// Compare the JSON policy `input` with its reference values if
+    /// explaining, see [`VerificationReport::compare`].
+    pub fn compare(&self, input: &str, reference: &HashMap<String, Vec<String>>) {
+        self.record(|report| {
+            if let Ok(claims) = serde_json::from_str(input) {
+                report.compare(&claims, reference);
+            }
+        });
+    }
+
+    pub fn record(&self, record: impl FnOnce(&mut VerificationReport)) {
+        if let Some(report) = self.report {
+            record(&mut report.lock().unwrap_or_else(PoisonError::into_inner));
+        }
+    }
+}
+
+fn decision_name(decision: PolicyDecision) -> &'static str {
+    match decision {
+        PolicyDecision::Allow => "allow",
+        PolicyDecision::Deny => "deny",
+    }
+}
+
+fn pass_name(passed: bool) -> &'static str {
+    if passed {
+        "pass"
+    } else {
+        "FAIL"
+    }
+}
+
+fn match_name(matched: bool) -> &'static str {
+    if matched {
+        "match"
+    } else {
+        "MISMATCH"
+    }
+}
+
+fn reference_list(reference: &[String]) -> String {
+    match reference {
+        [] => "any value".to_string(),
+        [value] => value.clone(),
+        values => format!("one of {}", values.join(", ")),
+    }
+}
+
+fn violation_line(violation: &PolicyViolation) -> String {
+    let mut line = violation.rule.clone();
+    if let Some(claim) = &violation.claim {
+        let _ = write!(line, ": {claim}");
+        if let Some(value) = &violation.value {
+            let _ = write!(line, " = {value}");
+        }
+        if let Some(expected) = &violation.expected {
+            let _ = write!(line, ", expected {expected}");
+        }
+    }
+    if let Some(message) = &violation.message {
+        let _ = write!(line, " ({message})");
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use serde_json::json;
+
+    fn report() -> VerificationReport {
+        let mut report = VerificationReport::new("tdx".to_string());
+        report
+            .check("Evidence format", Ok(()), |_| {
+                "Attestation parsed".to_string()
+            })
+            .unwrap();
+        assert!(report
+            .check::<()>("TEE evidence", Err(anyhow!("bad | quote")), |_| {
+                unreachable!()
+            })
+            .is_err());
+
+        report.compare(
+            &json!({
+                "tdx.quote.body.mr_td": "aa",
+                "tdx.quote.body.tcb_svn.0": 3,
+                "tdx.ccel.kernel": "bb",
+                "tdx.quote.header.version": 4,
+            }),
+            &HashMap::from([
+                ("tdx.quote.body.mr_td".to_string(), vec!["cc".to_string()]),
+                (
+                    "tdx.quote.body.tcb_svn.0".to_string(),
+                    vec!["3".to_string()],
+                ),
+                ("tdx.ccel.kernel".to_string(), vec![]),
+                (
+                    "tdx.quote.header.version".to_string(),
+                    vec!["sha384:4".to_string()],
+                ),
+            ]),
+        );
+        report.violations.push(PolicyViolation {
+            rule: "judge_field".to_string(),
+            claim: Some("tdx.quote.body.mr_td".to_string()),
+            value: Some(json!("aa")),
+            expected: Some(json!(["cc"])),
+            message: None,
+        });
+        report
+    }
+
+    #[test]
+    fn test_compare() {
+        let report = report();
+        let results: Vec<_> = report
+            .comparisons
+            .iter()
+            .map(|c| (c.claim.as_str(), c.matched))
+            .collect();
+        assert_eq!(
+            results,
+            [
+                ("tdx.ccel.kernel", true),
+                ("tdx.quote.body.mr_td", false),
+                ("tdx.quote.body.tcb_svn.0", true),
+                ("tdx.quote.header.version", false),
+            ]
+        );
+        assert_eq!(report.checks.len(), 2);
+        assert!(!report.checks[1].passed);
+
+        // The inputs of several policies.
+        let mut report = report;
+        report.compare(
+            &json!({"tdx.quote.body.mr_td": "aa", "tdx.quote.body.mr_seam": "dd"}),
+            &HashMap::from([
+                ("tdx.quote.body.mr_td".to_string(), vec!["cc".to_string()]),
+                ("tdx.quote.body.mr_seam".to_string(), vec!["dd".to_string()]),
+            ]),
+        );
+        let claims: Vec<_> = report.comparisons.iter().map(|c| &c.claim).collect();
+        assert_eq!(
+            claims,
+            [
+                "tdx.ccel.kernel",
+                "tdx.quote.body.mr_seam",
+                "tdx.quote.body.mr_td",
+                "tdx.quote.body.tcb_svn.0",
+                "tdx.quote.header.version",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let report = report();
+
+        let markdown = report.render(ReportFormat::Markdown);
+        assert!(markdown.contains("- Decision: **deny**"));
+        assert!(markdown.contains("| TEE evidence | FAIL | bad \\| quote |"));
+        assert!(markdown.contains("| `tdx.quote.body.mr_td` | `\"aa\"` | cc | MISMATCH |"));
+        assert!(
+            markdown.contains("- judge_field: tdx.quote.body.mr_td = \"aa\", expected [\"cc\"]")
+        );
+
+        let text = report.render(ReportFormat::Text);
+        assert!(text.contains("  [pass] Evidence format: Attestation parsed"));
+        assert!(text.contains("  [match] tdx.ccel.kernel = \"bb\", expected any value"));
+    }
+}
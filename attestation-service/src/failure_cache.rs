@@ -0,0 +1,320 @@
+//! Caching of verification failures.
+//!
+//! Verifying evidence can fetch collateral and check several signature
+//! chains. A misbehaving attester that submits the same invalid evidence
+//! over and over would have the AS do it all again every time. With
+//! `failure_cache` in the AS config, the AS remembers the evidence that
+//! failed verification for a short TTL, by the SHA-256 of the TEE, the
+//! `tee-pubkey` and the evidence, and rejects it again with a
+//! [`CachedFailure`] error without verifying it.
+//!
+//! The nonce is not part of the digest, so that an attester cannot get
+//! the same evidence verified again by asking for new nonces. So only the
+//! failures that follow from the evidence alone are cached, those of the
+//! [`CACHED_STAGES`]: a freshness failure depends on the nonce, and would
+//! have anyone who replays the evidence with a stale nonce get it rejected
+//! for every nonce, and collateral verification fetches collateral, that
+//! may fail for a while. Evidence denied by the policy is appraised again,
+//! as the policy or the reference values may change. Failures are kept in
+//! memory, so each AS instance has its own cache.
+
+use crate::rejections::RejectionStage;
+use crate::ttl_cache::TtlCache;
+use anyhow::{anyhow, Result};
+use kbs_types::Attestation;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The stages whose failures follow from the evidence alone.
+pub const CACHED_STAGES: &[RejectionStage] = &[
+    RejectionStage::Parse,
+    RejectionStage::EventlogReplay,
+    RejectionStage::ClaimsNormalize,
+];
+
+const DEFAULT_TTL_SECS: u64 = 30;
+const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+fn default_ttl_secs() -> u64 {
+    DEFAULT_TTL_SECS
+}
+
+fn default_max_entries() -> usize {
+    DEFAULT_MAX_ENTRIES
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FailureCacheConfig {
+    /// How long a verification failure is remembered, in seconds.
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+
+    /// How many failures are remembered at most. The oldest one is
+    /// forgotten to make room for a new one.
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+}
+
+impl Default for FailureCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: DEFAULT_TTL_SECS,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+}
+
+/// The evidence failed verification within the TTL of the failure cache,
+/// and was not verified again.
+#[derive(Debug)]
+pub struct CachedFailure {
+    /// The error of the failed verification.
+    pub error: String,
+    /// Time since the evidence failed verification.
+    pub failed: Duration,
+}
+
+impl fmt::Display for CachedFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Evidence failed verification {}s ago, retry later: {}",
+            self.failed.as_secs(),
+            self.error
+        )
+    }
+}
+
+impl std::error::Error for CachedFailure {}
+
+struct Failure {
+    stage: RejectionStage,
+    error: String,
+    /// Submissions rejected from the cache.
+    hits: u64,
+}
+
+/// Verification failures within their TTL, by evidence digest.
+pub(crate) struct FailureCache {
+    failures: Mutex<TtlCache<Vec<u8>, Failure>>,
+}
+
+impl FailureCache {
+    pub fn new(config: FailureCacheConfig) -> Self {
+        Self {
+            failures: Mutex::new(TtlCache::new(
+                Duration::from_secs(config.ttl_secs),
+                config.max_entries,
+            )),
+        }
+    }
+
+    /// Fail with a [`CachedFailure`] error, and the stage that rejected
+    /// the evidence, if it failed verification within the TTL.
+    pub fn check(
+        &self,
+        tee: &str,
+        attestation: &Attestation,
+    ) -> Result<(), (RejectionStage, anyhow::Error)> {
+        self.check_at(tee, attestation, Instant::now())
+    }
+
+    /// Remember that the evidence failed verification at `stage`, if it is
+    /// one of the [`CACHED_STAGES`].
+    pub fn record(
+        &self,
+        tee: &str,
+        attestation: &Attestation,
+        stage: RejectionStage,
+        error: &anyhow::Error,
+    ) -> Result<()> {
+        self.record_at(tee, attestation, stage, error, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        tee: &str,
+        attestation: &Attestation,
+        now: Instant,
+    ) -> Result<(), (RejectionStage, anyhow::Error)> {
+        let digest = digest(tee, attestation).map_err(|e| (RejectionStage::Request, e))?;
+        let mut failures = self.failures.lock().map_err(|_| {
+            (
+                RejectionStage::Request,
+                anyhow!("Failure cache is poisoned"),
+            )
+        })?;
+        let Some((at, failure)) = failures.get_mut(&digest, now) else {
+            return Ok(());
+        };
+        let failed = now.duration_since(at);
+
+        // Log the first rejection only, not every one of a client that
+        // keeps retrying.
+        if failure.hits == 0 {
+            warn!(
+                "Evidence of {tee} submitted again {}s after it failed verification, rejected until its failure expires",
+                failed.as_secs()
+            );
+        }
+        failure.hits += 1;
+        Err((
+            failure.stage,
+            CachedFailure {
+                error: failure.error.clone(),
+                failed,
+            }
+            .into(),
+        ))
+    }
+
+    fn record_at(
+        &self,
+        tee: &str,
+        attestation: &Attestation,
+        stage: RejectionStage,
+        error: &anyhow::Error,
+        now: Instant,
+    ) -> Result<()> {
+        if !CACHED_STAGES.contains(&stage) {
+            return Ok(());
+        }
+        let digest = digest(tee, attestation)?;
+        let mut failures = self
+            .failures
+            .lock()
+            .map_err(|_| anyhow!("Failure cache is poisoned"))?;
+        failures.insert(
+            digest,
+            Failure {
+                stage,
+                error: format!("{error:#}"),
+                hits: 0,
+            },
+            now,
+        );
+        Ok(())
+    }
+}
+
+fn digest(tee: &str, attestation: &Attestation) -> Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    for part in [
+        tee,
+        &serde_json::to_string(&attestation.tee_pubkey)?,
+        &attestation.tee_evidence,
+    ] {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part.as_bytes());
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kbs_types::TeePubKey;
+
+    fn attestation(tee_evidence: &str) -> Attestation {
+        Attestation {
+            tee_pubkey: TeePubKey {
+                kty: "RSA".to_string(),
+                alg: "RSA1_5".to_string(),
+                k_mod: "n".to_string(),
+                k_exp: "AQAB".to_string(),
+            },
+            tee_evidence: tee_evidence.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_failure_cache() {
+        let cache = FailureCache::new(FailureCacheConfig {
+            ttl_secs: 30,
+            max_entries: 2,
+        });
+        let start = Instant::now();
+        let quote = attestation("quote");
+
+        assert!(cache.check_at("tdx", &quote, start).is_ok());
+        cache
+            .record_at(
+                "tdx",
+                &quote,
+                RejectionStage::Parse,
+                &anyhow!("Malformed quote"),
+                start,
+            )
+            .unwrap();
+
+        let (stage, e) = cache
+            .check_at("tdx", &quote, start + Duration::from_secs(10))
+            .unwrap_err();
+        assert_eq!(stage, RejectionStage::Parse);
+        let failure = e.downcast_ref::<CachedFailure>().unwrap();
+        assert_eq!(failure.error, "Malformed quote");
+        assert_eq!(failure.failed, Duration::from_secs(10));
+        // Other evidence, or the same evidence of another TEE.
+        assert!(cache
+            .check_at("tdx", &attestation("other quote"), start)
+            .is_ok());
+        assert!(cache.check_at("sgx", &quote, start).is_ok());
+        // After the TTL.
+        assert!(cache
+            .check_at("tdx", &quote, start + Duration::from_secs(30))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_uncached_stages() {
+        let cache = FailureCache::new(FailureCacheConfig::default());
+        let start = Instant::now();
+        // Failures that depend on the nonce, on fetched collateral or that
+        // the verifier does not tell the stage of.
+        for stage in [
+            RejectionStage::Freshness,
+            RejectionStage::CollateralVerify,
+            RejectionStage::Verify,
+        ] {
+            let quote = attestation(&stage.to_string());
+            cache
+                .record_at("tdx", &quote, stage, &anyhow!("Failed"), start)
+                .unwrap();
+            assert!(cache.check_at("tdx", &quote, start).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_max_entries() {
+        let cache = FailureCache::new(FailureCacheConfig {
+            ttl_secs: 30,
+            max_entries: 2,
+        });
+        let start = Instant::now();
+        for (i, quote) in ["first", "second", "third"].into_iter().enumerate() {
+            cache
+                .record_at(
+                    "tdx",
+                    &attestation(quote),
+                    RejectionStage::Parse,
+                    &anyhow!("Malformed quote"),
+                    start + Duration::from_secs(i as u64),
+                )
+                .unwrap();
+        }
+
+        let now = start + Duration::from_secs(5);
+        assert!(cache.check_at("tdx", &attestation("first"), now).is_ok());
+        assert!(cache.check_at("tdx", &attestation("second"), now).is_err());
+        assert!(cache.check_at("tdx", &attestation("third"), now).is_err());
+    }
+
+    #[test]
+    fn test_config() {
+        let config: FailureCacheConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, FailureCacheConfig::default());
+    }
+}
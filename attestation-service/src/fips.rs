@@ -0,0 +1,98 @@
+//! FIPS mode.
+//!
+//! In FIPS mode, the AS only uses FIPS 140-3 approved algorithms:
+//! - evidence certificate chains and signatures are verified by the OpenSSL
+//!   crypto backend, which must be configured with a FIPS provider;
+//! - evidence can only be encrypted to the AS with `RSA-OAEP-256`, as
+//!   X25519 is not approved;
+//! - evidence of TEEs whose attestation relies on other algorithms, like
+//!   the SM2 and SM3 of Hygon CSV, is rejected;
+//! - the TLS listeners of the gRPC server only negotiate AES-GCM cipher
+//!   suites over NIST curves.
+//!
+//! Tokens are signed with RS384 and keys of at least 2048 bits, and the
+//! digests the verifiers compute are SHA-256 or SHA-384, which are approved
+//! in any mode.
+//!
+//! FIPS mode only restricts the algorithms: tokens are signed by the `rsa`
+//! crate and TLS is terminated by rustls, neither of which is a validated
+//! module. So the tokens make no claim of FIPS compliance, and the mode is
+//! only reported to admins, by `GetServiceInfo` and the service status.
+//!
+//! FIPS mode is turned on with `fips_mode` in the AS config, or always on
+//! when the AS is built with the `fips` feature.
+
+use crate::config::Config;
+use crate::decryption::Algorithm;
+use crate::verifier::crypto::CryptoBackendType;
+use anyhow::*;
+use kbs_types::Tee;
+
+/// Whether the AS is built to always run in FIPS mode.
+pub const FIPS_BUILD: bool = cfg!(feature = "fips");
+
+/// Check that `config` only uses approved algorithms.
+pub(crate) fn check_config(config: &Config) -> Result<()> {
+    if config.crypto_backend != CryptoBackendType::OpenSSL {
+        bail!(
+            "FIPS mode requires the OpenSSL crypto backend, not {:?}",
+            config.crypto_backend
+        );
+    }
+
+    for key in &config.evidence_decryption_keys {
+        if key.alg != Algorithm::RsaOaep256 {
+            bail!(
+                "Evidence decryption key {} uses {:?}, which is not allowed in FIPS mode",
+                key.id,
+                key.alg
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that the evidence of `tee` is signed with approved algorithms.
+pub(crate) fn check_tee(tee: &Tee) -> Result<()> {
+    if matches!(tee, Tee::Csv) {
+        bail!("CSV evidence relies on SM2 and SM3, which are not allowed in FIPS mode");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decryption::{DecryptionKeyConfig, KeySource};
+
+    #[test]
+    fn test_check_config() {
+        let mut config = Config {
+            crypto_backend: CryptoBackendType::OpenSSL,
+            ..Default::default()
+        };
+        check_config(&config).unwrap();
+
+        config.evidence_decryption_keys.push(DecryptionKeyConfig {
+            id: "as-key-1".to_string(),
+            alg: Algorithm::HpkeX25519Sha256Aes128Gcm,
+            source: KeySource::File {
+                path: "key.pem".into(),
+            },
+        });
+        assert!(check_config(&config).is_err());
+
+        config.evidence_decryption_keys[0].alg = Algorithm::RsaOaep256;
+        check_config(&config).unwrap();
+
+        config.crypto_backend = CryptoBackendType::Ring;
+        assert!(check_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_check_tee() {
+        check_tee(&Tee::Tdx).unwrap();
+        assert!(check_tee(&Tee::Csv).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+//! Storage envelope of the evidence of the history.
+//!
+//! The evidence is stored as a versioned envelope rather than as is: its
+//! JSON is gzip compressed, then, with `evidence_kek` in the `history`
+//! config, encrypted with AES-256-GCM under a key of its own, which is
+//! wrapped by the key encryption key (KEK), also with AES-256-GCM. The
+//! evidence, e.g. its report data, is so protected at rest, and the KEK can
+//! be kept apart from the work dir.
+//!
+//! ```json
+//! {
+//!     "version": 1,
+//!     "compression": "gzip",
+//!     "encryption": {
+//!         "alg": "A256GCM",
+//!         "kek_id": "8f43...",
+//!         "wrapped_key": "...",
+//!         "iv": "..."
+//!     },
+//!     "data": "..."
+//! }
+//! ```
+//!
+//! Evidence recorded before the envelope, as plain JSON, is still read.
+
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use super::StoredEvidence;
+
+/// Version of the envelopes written by this AS.
+const ENVELOPE_VERSION: u32 = 1;
+
+/// The only encryption algorithm, of both the evidence and its key.
+const ENCRYPTION_ALG: &str = "A256GCM";
+
+const KEY_LEN: usize = 32;
+const IV_LEN: usize = 12;
+
+/// Compression of the evidence in an envelope.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Gzip,
+}
+
+/// Encryption of the evidence in an envelope.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Encryption {
+    pub alg: String,
+    /// Identifier of the KEK, the hex encoded SHA-256 of the KEK itself.
+    pub kek_id: String,
+    /// The key of the evidence, encrypted by the KEK, after the IV of its
+    /// encryption, base64 encoded.
+    pub wrapped_key: String,
+    /// IV of the encryption of the evidence, base64 encoded.
+    pub iv: String,
+}
+
+/// The evidence of an attestation, as stored in the history.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EvidenceEnvelope {
+    pub version: u32,
+    pub compression: Compression,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<Encryption>,
+    /// The evidence, compressed and encrypted, base64 encoded.
+    pub data: String,
+}
+
+/// Key encryption key of the evidence of the history.
+pub struct Kek {
+    key: [u8; KEY_LEN],
+    id: String,
+}
+
+impl Kek {
+    /// Read the KEK from `path`, a file of 32 raw bytes.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let key = std::fs::read(path)
+            .with_context(|| format!("Cannot read the evidence KEK {}", path.display()))?;
+        Self::new(&key)
+    }
+
+    fn new(key: &[u8]) -> Result<Self> {
+        let key: [u8; KEY_LEN] = key
+            .try_into()
+            .map_err(|_| anyhow!("The evidence KEK must be {KEY_LEN} bytes long"))?;
+        Ok(Self {
+            id: hex::encode(Sha256::digest(key)),
+            key,
+        })
+    }
+}
+
+fn encrypt(key: &[u8], plaintext: &[u8]) -> Result<([u8; IV_LEN], Vec<u8>)> {
+    let mut iv = [0; IV_LEN];
+    rand::thread_rng().fill_bytes(&mut iv);
+    let ciphertext = Aes256Gcm::new_from_slice(key)
+        .map_err(|e| anyhow!("Invalid key: {e}"))?
+        .encrypt(Nonce::from_slice(&iv), plaintext)
+        .map_err(|_| anyhow!("Encryption failed"))?;
+    Ok((iv, ciphertext))
+}
+
+fn decrypt(key: &[u8], iv: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    if iv.len() != IV_LEN {
+        bail!("Invalid AES-GCM IV length {}", iv.len());
+    }
+    Aes256Gcm::new_from_slice(key)
+        .map_err(|e| anyhow!("Invalid key: {e}"))?
+        .decrypt(Nonce::from_slice(iv), ciphertext)
+        .map_err(|_| anyhow!("Decryption failed"))
+}
+
+impl EvidenceEnvelope {
+    /// Seal `evidence`, encrypted if a `kek` is given.
+    pub fn seal(evidence: &StoredEvidence, kek: Option<&Kek>) -> Result<Self> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&serde_json::to_vec(evidence)?)?;
+        let mut data = encoder.finish()?;
+
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let encryption = match kek {
+            Some(kek) => {
+                let mut key = [0; KEY_LEN];
+                rand::thread_rng().fill_bytes(&mut key);
+                let (iv, ciphertext) = encrypt(&key, &data)?;
+                data = ciphertext;
+                let (wrap_iv, wrapped_key) = encrypt(&kek.key, &key)?;
+                Some(Encryption {
+                    alg: ENCRYPTION_ALG.to_string(),
+                    kek_id: kek.id.clone(),
+                    wrapped_key: b64.encode([&wrap_iv[..], &wrapped_key].concat()),
+                    iv: b64.encode(iv),
+                })
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            version: ENVELOPE_VERSION,
+            compression: Compression::Gzip,
+            encryption,
+            data: b64.encode(data),
+        })
+    }
+
+    /// Open the envelope, with `kek` if it is encrypted.
+    pub fn open(&self, kek: Option<&Kek>) -> Result<StoredEvidence> {
+        if self.version != ENVELOPE_VERSION {
+            bail!("Unsupported evidence envelope version {}", self.version);
+        }
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let mut data = b64.decode(&self.data).context("Base64 decode data")?;
+
+        if let Some(encryption) = &self.encryption {
+            if encryption.alg != ENCRYPTION_ALG {
+                bail!("Unsupported evidence encryption {}", encryption.alg);
+            }
+            let kek = kek.ok_or_else(|| anyhow!("The evidence is encrypted, but no KEK is set"))?;
+            if encryption.kek_id != kek.id {
+                bail!("The evidence is encrypted with another KEK");
+            }
+            let wrapped_key = b64
+                .decode(&encryption.wrapped_key)
+                .context("Base64 decode wrapped key")?;
+            if wrapped_key.len() < IV_LEN {
+                bail!("Wrapped key too short");
+            }
+            let (wrap_iv, wrapped_key) = wrapped_key.split_at(IV_LEN);
+            let key = decrypt(&kek.key, wrap_iv, wrapped_key).context("Cannot unwrap the key")?;
+            let iv = b64.decode(&encryption.iv).context("Base64 decode iv")?;
+            data = decrypt(&key, &iv, &data).context("Cannot decrypt the evidence")?;
+        }
+
+        if self.compression == Compression::Gzip {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(data.as_slice())
+                .read_to_end(&mut decompressed)
+                .context("Cannot decompress the evidence")?;
+            data = decompressed;
+        }
+        serde_json::from_slice(&data).context("Malformed stored evidence")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evidence() -> StoredEvidence {
+        StoredEvidence {
+            nonce: "1234".to_string(),
+            attestation: r#"{"tee-evidence": "..."}"#.to_string(),
+            policy_parameters: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_seal_open() {
+        let envelope = EvidenceEnvelope::seal(&evidence(), None).unwrap();
+        assert_eq!(envelope.compression, Compression::Gzip);
+        assert!(envelope.encryption.is_none());
+        assert_eq!(envelope.open(None).unwrap(), evidence());
+    }
+
+    #[test]
+    fn test_seal_open_encrypted() {
+        let kek = Kek::new(&[7; KEY_LEN]).unwrap();
+        let envelope = EvidenceEnvelope::seal(&evidence(), Some(&kek)).unwrap();
+        assert_eq!(envelope.encryption.as_ref().unwrap().kek_id, kek.id);
+        assert_eq!(envelope.open(Some(&kek)).unwrap(), evidence());
+
+        assert!(envelope.open(None).is_err());
+        let other = Kek::new(&[8; KEY_LEN]).unwrap();
+        assert!(envelope.open(Some(&other)).is_err());
+
+        let mut tampered = envelope.clone();
+        tampered.data = base64::engine::general_purpose::STANDARD.encode(b"tampered");
+        assert!(tampered.open(Some(&kek)).is_err());
+    }
+
+    #[test]
+    fn test_reject_invalid_kek() {
+        assert!(Kek::new(&[0; 16]).is_err());
+    }
+}
@@ -0,0 +1,703 @@
+//! History of the attestations, for analytics.
+//!
+//! With `history` in the AS config, the flattened claims of every
+//! attestation the policy decides on are recorded with the decision, as JSON
+//! lines of `history.jsonl` in the work dir, that `as-tool simulate` also
+//! reads. Once the file holds `max_records` records, it is rotated to
+//! `history.jsonl.1`, replacing the previous one. With `evidence`, the
+//! evidence of the attestations is recorded too, to appraise it again, see
+//! [`crate::reappraisal`], in a compressed envelope, encrypted with
+//! `evidence_kek`, see [`envelope`]. Sensitive claims can be recorded
+//! without their value, redacted, or as a salted hash of it, see [`salts`].
+//!
+//! The history is exported in columnar form, one row per attestation and
+//! one column per claim, as CSV, or as Parquet with the `parquet-export`
+//! feature. Claim values are exported as text, JSON encoded unless they are
+//! strings, and a missing claim is an empty or null value.
+//!
+//! ```json
+//! "history": { "max_records": 100000, "redact": ["tdx.quote.body.report_data"], "hash": ["*.user_data"] }
+//! ```
+
+use anyhow::{anyhow, bail, Context, Result};
+use as_types::PolicyDecision;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use crate::policy_engine::ParameterValues;
+use crate::token::chain;
+
+pub mod envelope;
+pub mod salts;
+
+use envelope::{EvidenceEnvelope, Kek};
+use salts::Salts;
+
+const HISTORY_FILE: &str = "history.jsonl";
+const ROTATED_HISTORY_FILE: &str = "history.jsonl.1";
+
+const DEFAULT_MAX_RECORDS: usize = 100_000;
+
+/// Value recorded in place of a redacted claim.
+const REDACTED: &str = "<redacted>";
+
+/// Columns of every record, before the claims.
+pub const RECORD_COLUMNS: &[&str] = &["id", "timestamp", "tee", "decision"];
+
+fn default_max_records() -> usize {
+    DEFAULT_MAX_RECORDS
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HistoryConfig {
+    /// Records of the history file before it is rotated.
+    #[serde(default = "default_max_records")]
+    pub max_records: usize,
+
+    /// Claims recorded without their value. A name ending with `*` matches
+    /// any claim with that prefix.
+    #[serde(default)]
+    pub redact: Vec<String>,
+
+    /// Claims recorded as a salted hash of their value, with the salt of
+    /// the tenant, see [`salts`]. A name ending with `*` matches any claim
+    /// with that prefix.
+    #[serde(default)]
+    pub hash: Vec<String>,
+
+    /// Also record the evidence of the attestations, to appraise it again
+    /// later, see [`crate::reappraisal`].
+    #[serde(default)]
+    pub evidence: bool,
+
+    /// File of the 32 bytes key encryption key the recorded evidence is
+    /// encrypted with, see [`envelope`]. The evidence is recorded
+    /// unencrypted if not set.
+    #[serde(default)]
+    pub evidence_kek: Option<PathBuf>,
+
+    /// Days the records are kept, until pruned by the maintenance of the
+    /// AS, see [`crate::maintenance`]. Only the rotation bounds the
+    /// history if not set.
+    #[serde(default)]
+    pub retention_days: Option<i64>,
+}
+
+/// The evidence of an attestation, decrypted, as verified.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StoredEvidence {
+    pub nonce: String,
+    /// The attestation in JSON.
+    pub attestation: String,
+    #[serde(default)]
+    pub policy_parameters: ParameterValues,
+}
+
+/// The evidence of an attestation, as recorded in the history.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum RecordedEvidence {
+    Envelope(EvidenceEnvelope),
+    /// Evidence recorded before the envelope.
+    Plain(StoredEvidence),
+}
+
+/// An attestation of the history.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryRecord {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub tee: String,
+    pub decision: PolicyDecision,
+    /// Tenant of the attestation, whose salt hashes the claims, see
+    /// [`salts`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// The flattened claims of the evidence.
+    pub claims: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub evidence: Option<RecordedEvidence>,
+}
+
+impl HistoryRecord {
+    /// The text of `column` in the record.
+    fn column(&self, column: &str) -> Option<String> {
+        match column {
+            "id" => Some(self.id.clone()),
+            "timestamp" => Some(self.timestamp.to_rfc3339()),
+            "tee" => Some(self.tee.clone()),
+            "decision" => serde_json::to_value(self.decision)
+                .ok()
+                .and_then(|decision| decision.as_str().map(str::to_string)),
+            claim => match self.claims.get(claim)? {
+                Value::Null => None,
+                Value::String(value) => Some(value.clone()),
+                value => Some(value.to_string()),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "parquet" => Ok(Self::Parquet),
+            _ => bail!("Export format {format} is not supported"),
+        }
+    }
+}
+
+/// The history, persisted in the work dir.
+pub(crate) struct History {
+    config: HistoryConfig,
+    work_dir: PathBuf,
+    kek: Option<Kek>,
+    salts: Salts,
+    /// Records of the current history file.
+    records: Mutex<usize>,
+}
+
+impl History {
+    pub fn new(config: HistoryConfig, work_dir: &Path) -> Result<Self> {
+        if config.max_records == 0 {
+            bail!("history.max_records must be at least 1");
+        }
+        let path = work_dir.join(HISTORY_FILE);
+        let records = match path.exists() {
+            true => BufReader::new(fs::File::open(&path)?).lines().count(),
+            false => 0,
+        };
+        let kek = config
+            .evidence_kek
+            .as_deref()
+            .map(Kek::from_file)
+            .transpose()?;
+        let salts = Salts::new(work_dir)?;
+        Ok(Self {
+            config,
+            work_dir: work_dir.to_path_buf(),
+            kek,
+            salts,
+            records: Mutex::new(records),
+        })
+    }
+
+    /// Whether the evidence of the attestations is recorded.
+    pub fn records_evidence(&self) -> bool {
+        self.config.evidence
+    }
+
+    /// Record the decision on the flattened `claims` of the `evidence` of
+    /// `tee`, attested by `tenant`.
+    pub fn record(
+        &self,
+        tee: &str,
+        tenant: Option<&str>,
+        decision: PolicyDecision,
+        claims: &Value,
+        evidence: Option<StoredEvidence>,
+    ) -> Result<()> {
+        let matches =
+            |patterns: &[String], name: &str| patterns.iter().any(|p| chain::matches(p, name));
+        let mut claims = claims.clone();
+        if let Some(claims) = claims.as_object_mut() {
+            let mut salt = None;
+            for (name, value) in claims.iter_mut() {
+                if matches(&self.config.redact, name) {
+                    *value = Value::String(REDACTED.to_string());
+                } else if matches(&self.config.hash, name) {
+                    if salt.is_none() {
+                        salt = Some(self.salts.salt(tenant.unwrap_or_default())?);
+                    }
+                    *value = Value::String(salts::hash(salt.as_deref().unwrap_or_default(), value));
+                }
+            }
+        }
+        let record = HistoryRecord {
+            id: uuid::Uuid::new_v4().to_string(),
+            timestamp: Utc::now(),
+            tee: tee.to_string(),
+            decision,
+            tenant: tenant.map(str::to_string),
+            claims,
+            evidence: evidence
+                .filter(|_| self.config.evidence)
+                .map(|evidence| EvidenceEnvelope::seal(&evidence, self.kek.as_ref()))
+                .transpose()?
+                .map(RecordedEvidence::Envelope),
+        };
+
+        let mut records = self
+            .records
+            .lock()
+            .map_err(|_| anyhow!("Attestation history is poisoned"))?;
+        let path = self.work_dir.join(HISTORY_FILE);
+        if *records >= self.config.max_records {
+            fs::rename(&path, self.work_dir.join(ROTATED_HISTORY_FILE))
+                .context("Cannot rotate the attestation history")?;
+            *records = 0;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Cannot open {}", path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(&record)?)?;
+        *records += 1;
+        Ok(())
+    }
+
+    pub fn records(&self) -> Result<Vec<HistoryRecord>> {
+        let _records = self
+            .records
+            .lock()
+            .map_err(|_| anyhow!("Attestation history is poisoned"))?;
+        read_history(&self.work_dir)
+    }
+
+    /// Remove the records older than the retention of the history, and
+    /// return how many, and the bytes reclaimed.
+    pub fn prune(&self) -> Result<(usize, u64)> {
+        let Some(days) = self.config.retention_days else {
+            return Ok((0, 0));
+        };
+        let oldest = Utc::now() - chrono::Duration::days(days);
+        let mut records = self
+            .records
+            .lock()
+            .map_err(|_| anyhow!("Attestation history is poisoned"))?;
+        let (mut pruned, mut reclaimed) = (0, 0);
+        for file in [ROTATED_HISTORY_FILE, HISTORY_FILE] {
+            let path = self.work_dir.join(file);
+            if !path.exists() {
+                continue;
+            }
+            let size = fs::metadata(&path)?.len();
+            let mut kept = String::new();
+            let mut removed = 0;
+            for line in BufReader::new(fs::File::open(&path)?).lines() {
+                let line = line?;
+                let expired = serde_json::from_str::<HistoryRecord>(&line)
+                    .is_ok_and(|record| record.timestamp < oldest);
+                if expired {
+                    removed += 1;
+                    continue;
+                }
+                kept.push_str(&line);
+                kept.push('\n');
+            }
+            if removed == 0 {
+                continue;
+            }
+            let file = tempfile::NamedTempFile::new_in(&self.work_dir)?;
+            fs::write(file.path(), &kept)?;
+            file.persist(&path)
+                .with_context(|| format!("Cannot write {}", path.display()))?;
+            if path.ends_with(HISTORY_FILE) {
+                *records = kept.lines().count();
+            }
+            pruned += removed;
+            reclaimed += size.saturating_sub(kept.len() as u64);
+        }
+        Ok((pruned, reclaimed))
+    }
+
+    /// The evidence of `record`, if recorded.
+    pub fn evidence(&self, record: &HistoryRecord) -> Result<Option<StoredEvidence>> {
+        match &record.evidence {
+            Some(RecordedEvidence::Envelope(envelope)) => envelope
+                .open(self.kek.as_ref())
+                .with_context(|| format!("Cannot open the evidence of {}", record.id))
+                .map(Some),
+            Some(RecordedEvidence::Plain(evidence)) => Ok(Some(evidence.clone())),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The records of the history of the AS of `work_dir`, oldest first.
+pub fn read_history(work_dir: &Path) -> Result<Vec<HistoryRecord>> {
+    let mut records = Vec::new();
+    for file in [ROTATED_HISTORY_FILE, HISTORY_FILE] {
+        let path = work_dir.join(file);
+        if !path.exists() {
+            continue;
+        }
+        for (i, line) in BufReader::new(fs::File::open(&path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            records.push(
+                serde_json::from_str(&line)
+                    .with_context(|| format!("Malformed line {} of {}", i + 1, path.display()))?,
+            );
+        }
+    }
+    Ok(records)
+}
+
+/// The columns of the export of `records`: the record columns and the
+/// claims selected by `columns`, or every column if none is selected. A
+/// selected claim ending with `*` selects any claim with that prefix.
+fn select_columns(records: &[HistoryRecord], columns: &[String]) -> Vec<String> {
+    let claims: BTreeSet<&String> = records
+        .iter()
+        .filter_map(|record| record.claims.as_object())
+        .flat_map(|claims| claims.keys())
+        .collect();
+    if columns.is_empty() {
+        return RECORD_COLUMNS
+            .iter()
+            .map(|column| column.to_string())
+            .chain(claims.into_iter().cloned())
+            .collect();
+    }
+
+    let mut selected = Vec::new();
+    for column in columns {
+        let matching: Vec<String> = match column.ends_with('*') {
+            true => claims
+                .iter()
+                .filter(|claim| chain::matches(column, claim))
+                .map(|claim| claim.to_string())
+                .collect(),
+            false => vec![column.clone()],
+        };
+        for column in matching {
+            if !selected.contains(&column) {
+                selected.push(column);
+            }
+        }
+    }
+    selected
+}
+
+/// Export `records` in `format`, with the `columns` selected as in
+/// [`select_columns`].
+pub fn export(
+    records: &[HistoryRecord],
+    format: ExportFormat,
+    columns: &[String],
+) -> Result<Vec<u8>> {
+    let columns = select_columns(records, columns);
+    let rows: Vec<Vec<Option<String>>> = records
+        .iter()
+        .map(|record| columns.iter().map(|column| record.column(column)).collect())
+        .collect();
+    match format {
+        ExportFormat::Csv => to_csv(&columns, &rows),
+        ExportFormat::Parquet => to_parquet(&columns, &rows),
+    }
+}
+
+fn to_csv(columns: &[String], rows: &[Vec<Option<String>>]) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(columns)?;
+    for row in rows {
+        writer.write_record(row.iter().map(|value| value.as_deref().unwrap_or_default()))?;
+    }
+    writer
+        .into_inner()
+        .map_err(|e| anyhow!("Cannot write CSV: {e}"))
+}
+
+#[cfg(feature = "parquet-export")]
+fn to_parquet(columns: &[String], rows: &[Vec<Option<String>>]) -> Result<Vec<u8>> {
+    use parquet::basic::{LogicalType, Repetition, Type as PhysicalType};
+    use parquet::data_type::{ByteArray, ByteArrayType};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::types::Type;
+    use std::sync::Arc;
+
+    let fields = columns
+        .iter()
+        .map(|column| {
+            Ok(Arc::new(
+                Type::primitive_type_builder(column, PhysicalType::BYTE_ARRAY)
+                    .with_repetition(Repetition::OPTIONAL)
+                    .with_logical_type(Some(LogicalType::String))
+                    .build()?,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let schema = Arc::new(
+        Type::group_type_builder("history")
+            .with_fields(fields)
+            .build()?,
+    );
+    let mut writer = SerializedFileWriter::new(
+        Vec::new(),
+        schema,
+        Arc::new(WriterProperties::builder().build()),
+    )?;
+
+    let mut row_group = writer.next_row_group()?;
+    for i in 0..columns.len() {
+        let mut column = row_group
+            .next_column()?
+            .ok_or_else(|| anyhow!("Missing Parquet column {}", columns[i]))?;
+        let values: Vec<ByteArray> = rows
+            .iter()
+            .filter_map(|row| row[i].as_deref())
+            .map(ByteArray::from)
+            .collect();
+        let definition_levels: Vec<i16> =
+            rows.iter().map(|row| i16::from(row[i].is_some())).collect();
+        column
+            .typed::<ByteArrayType>()
+            .write_batch(&values, Some(&definition_levels), None)?;
+        column.close()?;
+    }
+    row_group.close()?;
+    Ok(writer.into_inner()?)
+}
+
+#[cfg(not(feature = "parquet-export"))]
+fn to_parquet(_columns: &[String], _rows: &[Vec<Option<String>>]) -> Result<Vec<u8>> {
+    bail!("This AS is built without the parquet-export feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_history() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let config = HistoryConfig {
+            max_records: 2,
+            redact: vec!["tdx.quote.body.report_data".to_string()],
+            hash: Vec::new(),
+            evidence: false,
+            evidence_kek: None,
+            retention_days: None,
+        };
+        let history = History::new(config.clone(), work_dir.path()).unwrap();
+        let claims = json!({
+            "tdx.quote.body.mr_td": "aa",
+            "tdx.quote.body.report_data": "bb",
+            "tdx.quote.body.tcb_svn.0": 3,
+        });
+        history
+            .record("tdx", None, PolicyDecision::Allow, &claims, None)
+            .unwrap();
+        history
+            .record(
+                "snp",
+                None,
+                PolicyDecision::Deny,
+                &json!({"snp.measurement": "cc"}),
+                None,
+            )
+            .unwrap();
+        history
+            .record("tdx", None, PolicyDecision::Allow, &claims, None)
+            .unwrap();
+
+        // The first two records were rotated.
+        let records = History::new(config, work_dir.path())
+            .unwrap()
+            .records()
+            .unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].tee, "snp");
+        assert_eq!(records[0].claims["tdx.quote.body.report_data"], REDACTED);
+        assert!(work_dir.path().join(ROTATED_HISTORY_FILE).exists());
+
+        let columns = vec![
+            "tee".to_string(),
+            "decision".to_string(),
+            "tdx.quote.body.*".to_string(),
+        ];
+        let exported = export(&records, ExportFormat::Csv, &columns).unwrap();
+        assert_eq!(
+            String::from_utf8(exported).unwrap(),
+            "tee,decision,tdx.quote.body.mr_td,tdx.quote.body.report_data,tdx.quote.body.tcb_svn.0\n\
+             tdx,allow,aa,<redacted>,3\n\
+             snp,deny,,,\n\
+             tdx,allow,aa,<redacted>,3\n"
+        );
+
+        let exported = export(&records[1..2], ExportFormat::Csv, &[]).unwrap();
+        let header = String::from_utf8(exported).unwrap();
+        assert!(header.starts_with("id,timestamp,tee,decision,snp.measurement\n"));
+    }
+
+    #[test]
+    fn test_history_evidence() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let kek = work_dir.path().join("kek");
+        fs::write(&kek, [1; 32]).unwrap();
+        let config = HistoryConfig {
+            max_records: 10,
+            redact: Vec::new(),
+            hash: Vec::new(),
+            evidence: true,
+            evidence_kek: Some(kek),
+            retention_days: None,
+        };
+        let history = History::new(config, work_dir.path()).unwrap();
+        let evidence = StoredEvidence {
+            nonce: "sensitive-nonce".to_string(),
+            attestation: "{}".to_string(),
+            policy_parameters: ParameterValues::default(),
+        };
+        history
+            .record(
+                "tdx",
+                None,
+                PolicyDecision::Allow,
+                &json!({}),
+                Some(evidence.clone()),
+            )
+            .unwrap();
+
+        // Evidence recorded before the envelope is still read.
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(work_dir.path().join(HISTORY_FILE))
+            .unwrap();
+        let plain = json!({
+            "id": "2",
+            "timestamp": Utc::now(),
+            "tee": "tdx",
+            "decision": "allow",
+            "claims": {},
+            "evidence": evidence,
+        });
+        writeln!(file, "{plain}").unwrap();
+
+        let records = history.records().unwrap();
+        assert!(matches!(
+            records[0].evidence,
+            Some(RecordedEvidence::Envelope(_))
+        ));
+        let stored = fs::read_to_string(work_dir.path().join(HISTORY_FILE)).unwrap();
+        assert!(!stored.lines().next().unwrap().contains("sensitive-nonce"));
+        assert_eq!(
+            history.evidence(&records[0]).unwrap(),
+            Some(evidence.clone())
+        );
+        assert_eq!(history.evidence(&records[1]).unwrap(), Some(evidence));
+    }
+
+    #[test]
+    fn test_history_hash() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let config = HistoryConfig {
+            max_records: 10,
+            redact: Vec::new(),
+            hash: vec![
+                "tdx.quote.body.report_data".to_string(),
+                "*.user_data".to_string(),
+            ],
+            evidence: false,
+            evidence_kek: None,
+            retention_days: None,
+        };
+        let history = History::new(config, work_dir.path()).unwrap();
+        let claims = json!({
+            "tdx.quote.body.mr_td": "aa",
+            "tdx.quote.body.report_data": "bb",
+            "tdx.ccel.user_data": "cc",
+        });
+        for tenant in ["acme", "acme", "other"] {
+            history
+                .record("tdx", Some(tenant), PolicyDecision::Allow, &claims, None)
+                .unwrap();
+        }
+
+        let records = history.records().unwrap();
+        assert_eq!(records[0].tenant.as_deref(), Some("acme"));
+        assert_eq!(records[0].claims["tdx.quote.body.mr_td"], "aa");
+        let salt = Salts::new(work_dir.path()).unwrap().salt("acme").unwrap();
+        assert_eq!(
+            records[0].claims["tdx.quote.body.report_data"],
+            salts::hash(&salt, &json!("bb"))
+        );
+        assert_eq!(
+            records[0].claims["tdx.ccel.user_data"],
+            salts::hash(&salt, &json!("cc"))
+        );
+        assert_eq!(records[0].claims, records[1].claims);
+        assert_ne!(
+            records[0].claims["tdx.quote.body.report_data"],
+            records[2].claims["tdx.quote.body.report_data"]
+        );
+    }
+
+    #[test]
+    fn test_prune() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let config = HistoryConfig {
+            max_records: 10,
+            redact: Vec::new(),
+            hash: Vec::new(),
+            evidence: false,
+            evidence_kek: None,
+            retention_days: Some(30),
+        };
+        let history = History::new(config, work_dir.path()).unwrap();
+        let mut file = fs::File::create(work_dir.path().join(HISTORY_FILE)).unwrap();
+        for (id, days) in [("old", 40), ("recent", 1)] {
+            let record = json!({
+                "id": id,
+                "timestamp": Utc::now() - chrono::Duration::days(days),
+                "tee": "tdx",
+                "decision": "allow",
+                "claims": {},
+            });
+            writeln!(file, "{record}").unwrap();
+        }
+
+        let (pruned, reclaimed) = history.prune().unwrap();
+        assert_eq!(pruned, 1);
+        assert!(reclaimed > 0);
+        let records = history.records().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, "recent");
+        assert_eq!(history.prune().unwrap(), (0, 0));
+    }
+
+    #[cfg(feature = "parquet-export")]
+    #[test]
+    fn test_export_parquet() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let record = HistoryRecord {
+            id: "1".to_string(),
+            timestamp: Utc::now(),
+            tee: "snp".to_string(),
+            decision: PolicyDecision::Allow,
+            tenant: None,
+            claims: json!({"snp.measurement": "cc"}),
+            evidence: None,
+        };
+        let exported = export(&[record], ExportFormat::Parquet, &[]).unwrap();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&exported).unwrap();
+        let reader = SerializedFileReader::new(file).unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(metadata.file_metadata().num_rows(), 1);
+        assert_eq!(metadata.file_metadata().schema_descr().num_columns(), 5);
+    }
+}
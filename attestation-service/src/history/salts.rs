@@ -0,0 +1,131 @@
+//! Salted hashes of the sensitive claims of the history.
+//!
+//! Claims such as the report data bind the attestation to data of the
+//! workload, which the history should not keep for years. With `hash` in
+//! the `history` config, such claims are recorded as the salted SHA-256
+//! hash of their value, `salted-sha256:<hex>`, instead of the value, while
+//! the measurement claims are recorded as is:
+//!
+//! ```json
+//! "history": { "hash": ["tdx.quote.body.report_data", "snp.report_data", "*.user_data"] }
+//! ```
+//!
+//! The hash is that of the salt of the tenant of the attestation followed
+//! by the claim value, as exported: strings as is, other values JSON
+//! encoded. An auditor given the salt of a tenant can so check that a
+//! record has a given value, but values cannot be guessed without the
+//! salt, nor linked across tenants. The salts are random, 32 bytes, made
+//! on the first record of each tenant, and kept in `history_salts.json` of
+//! the work dir, in base64. Removing the salt of a tenant makes its hashes
+//! meaningless, without rewriting the history.
+//!
+//! The claims are hashed, not the recorded evidence, which should be
+//! encrypted with `evidence_kek`, see [`super::envelope`].
+
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use rand::RngCore;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const SALTS_FILE: &str = "history_salts.json";
+
+const SALT_LEN: usize = 32;
+
+/// Prefix of the hashed claim values.
+const HASH_PREFIX: &str = "salted-sha256:";
+
+/// The salts of the tenants, persisted in the work dir.
+pub(crate) struct Salts {
+    path: PathBuf,
+    /// Base64 salt by tenant.
+    salts: Mutex<BTreeMap<String, String>>,
+}
+
+impl Salts {
+    pub fn new(work_dir: &Path) -> Result<Self> {
+        let path = work_dir.join(SALTS_FILE);
+        let salts = match path.exists() {
+            true => serde_json::from_slice(&fs::read(&path)?)
+                .with_context(|| format!("Malformed {}", path.display()))?,
+            false => BTreeMap::new(),
+        };
+        Ok(Self {
+            path,
+            salts: Mutex::new(salts),
+        })
+    }
+
+    /// The salt of `tenant`, made and persisted if it has none yet.
+    pub fn salt(&self, tenant: &str) -> Result<Vec<u8>> {
+        let mut salts = self
+            .salts
+            .lock()
+            .map_err(|_| anyhow!("History salts are poisoned"))?;
+        let engine = base64::engine::general_purpose::STANDARD;
+        if let Some(salt) = salts.get(tenant) {
+            return engine
+                .decode(salt)
+                .with_context(|| format!("Malformed history salt of tenant {tenant}"));
+        }
+
+        let mut salt = vec![0; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        salts.insert(tenant.to_string(), engine.encode(&salt));
+        let file = tempfile::NamedTempFile::new_in(self.path.parent().unwrap_or(Path::new(".")))?;
+        fs::write(file.path(), serde_json::to_vec_pretty(&*salts)?)?;
+        file.persist(&self.path)
+            .with_context(|| format!("Cannot write {}", self.path.display()))?;
+        Ok(salt)
+    }
+}
+
+/// The salted hash of the claim `value`.
+pub fn hash(salt: &[u8], value: &Value) -> String {
+    let text = match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    };
+    let digest = Sha256::new()
+        .chain_update(salt)
+        .chain_update(text.as_bytes())
+        .finalize();
+    format!("{HASH_PREFIX}{}", hex::encode(digest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_salts() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let salts = Salts::new(work_dir.path()).unwrap();
+        let acme = salts.salt("acme").unwrap();
+        assert_eq!(acme.len(), SALT_LEN);
+        assert_eq!(salts.salt("acme").unwrap(), acme);
+        assert_ne!(salts.salt("other").unwrap(), acme);
+
+        // The salts are kept across restarts.
+        let salts = Salts::new(work_dir.path()).unwrap();
+        assert_eq!(salts.salt("acme").unwrap(), acme);
+    }
+
+    #[test]
+    fn test_hash() {
+        let hashed = hash(b"salt", &json!("bb"));
+        assert!(hashed.starts_with(HASH_PREFIX));
+        assert_eq!(hashed, hash(b"salt", &json!("bb")));
+        assert_ne!(hashed, hash(b"pepper", &json!("bb")));
+        assert_ne!(hash(b"salt", &json!(3)), hash(b"salt", &json!("4")));
+        assert_eq!(
+            hash(b"", &json!("bb")),
+            format!("{HASH_PREFIX}{}", hex::encode(Sha256::digest(b"bb")))
+        );
+    }
+}
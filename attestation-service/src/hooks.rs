@@ -0,0 +1,381 @@
+//! Post-verification hooks.
+//!
+//! For simple deployments that "verify then release" without running a
+//! full KBS, hooks run after the policy allowed the evidence and the token
+//! is issued. A hook can notify another service of the token, and release
+//! a secret to the attester, e.g. a disk key unwrapped by an external KMS.
+//! Released secrets are returned with the token, each as a compact JWE
+//! encrypted to the TEE public key of the attester (`A256GCM` content
+//! encryption, with the `alg` of the TEE public key, `RSA1_5` or
+//! `RSA-OAEP-256`), by hook name.
+//!
+//! Hooks are configured in `post_verification_hooks` of the AS config:
+//!
+//! ```json
+//! "post_verification_hooks": [
+//!     { "name": "audit", "type": "http", "url": "https://audit.example/attested" },
+//!     { "name": "disk-key", "type": "command", "command": ["/usr/bin/kms-unwrap"], "release": true, "required": true }
+//! ]
+//! ```
+//!
+//! A hook that fails only logs a warning, unless it is `required`, in
+//! which case the attestation fails.
+
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use anyhow::*;
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use kbs_types::TeePubKey;
+use rand::RngCore;
+use rsa::sha2::Sha256;
+use rsa::{BigUint, Oaep, Pkcs1v15Encrypt, RsaPublicKey};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// How long a hook may take.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An action run for every allowed attestation.
+#[async_trait]
+pub trait PostVerificationHook {
+    /// Run for allowed evidence of `tee`, with the issued `token`. Return
+    /// the secret to release to the attester, if any.
+    async fn on_allow(&self, tee: &str, token: &str) -> Result<Option<Vec<u8>>>;
+}
+
+/// Configuration of a hook.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HookConfig {
+    /// Name of the hook, and of the secret it releases.
+    pub name: String,
+
+    #[serde(flatten)]
+    pub action: HookAction,
+
+    /// Fail the attestation if the hook fails.
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HookAction {
+    /// POST `{"tee": ..., "token": ...}` to `url`. With `release`, the
+    /// response body is the secret released to the attester.
+    Http {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        #[serde(default)]
+        release: bool,
+    },
+
+    /// Run a command given the token on its standard input. With `release`,
+    /// its standard output is the secret released to the attester.
+    Command {
+        command: Vec<String>,
+        #[serde(default)]
+        release: bool,
+    },
+}
+
+impl HookAction {
+    fn to_hook(&self) -> Result<Box<dyn PostVerificationHook + Send + Sync>> {
+        match self {
+            HookAction::Http {
+                url,
+                headers,
+                release,
+            } => {
+                if !url.starts_with("https://") && !url.starts_with("http://") {
+                    bail!("Hook URL {url} is not an HTTP URL");
+                }
+                let client = reqwest::Client::builder()
+                    .timeout(HOOK_TIMEOUT)
+                    .build()
+                    .context("create HTTP client")?;
+                Ok(Box::new(HttpHook {
+                    client,
+                    url: url.clone(),
+                    headers: headers.clone(),
+                    release: *release,
+                }))
+            }
+            HookAction::Command { command, release } => {
+                if command.is_empty() {
+                    bail!("empty hook command");
+                }
+                Ok(Box::new(CommandHook {
+                    command: command.clone(),
+                    release: *release,
+                }))
+            }
+        }
+    }
+}
+
+struct HttpHook {
+    client: reqwest::Client,
+    url: String,
+    headers: BTreeMap<String, String>,
+    release: bool,
+}
+
+#[async_trait]
+impl PostVerificationHook for HttpHook {
+    async fn on_allow(&self, tee: &str, token: &str) -> Result<Option<Vec<u8>>> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .json(&json!({ "tee": tee, "token": token }));
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("POST {}", self.url))?
+            .error_for_status()?;
+        if !self.release {
+            return Ok(None);
+        }
+        Ok(Some(response.bytes().await?.to_vec()))
+    }
+}
+
+struct CommandHook {
+    command: Vec<String>,
+    release: bool,
+}
+
+#[async_trait]
+impl PostVerificationHook for CommandHook {
+    async fn on_allow(&self, _tee: &str, token: &str) -> Result<Option<Vec<u8>>> {
+        let mut child = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("run hook command {}", self.command[0]))?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("No stdin of the hook command"))?;
+        stdin.write_all(token.as_bytes()).await?;
+        drop(stdin);
+
+        let output = tokio::time::timeout(HOOK_TIMEOUT, child.wait_with_output())
+            .await
+            .map_err(|_| anyhow!("The hook command timed out"))??;
+        if !output.status.success() {
+            bail!("The hook command failed with {}", output.status);
+        }
+        Ok(self.release.then_some(output.stdout))
+    }
+}
+
+struct Hook {
+    name: String,
+    required: bool,
+    hook: Box<dyn PostVerificationHook + Send + Sync>,
+}
+
+/// The post-verification hooks, run in their configured order.
+#[derive(Default)]
+pub struct Hooks(Vec<Hook>);
+
+impl Hooks {
+    pub fn new(configs: &[HookConfig]) -> Result<Self> {
+        configs
+            .iter()
+            .map(|config| {
+                Ok(Hook {
+                    name: config.name.clone(),
+                    required: config.required,
+                    hook: config
+                        .action
+                        .to_hook()
+                        .with_context(|| format!("load hook {}", config.name))?,
+                })
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+
+    pub fn push(
+        &mut self,
+        name: &str,
+        required: bool,
+        hook: Box<dyn PostVerificationHook + Send + Sync>,
+    ) {
+        self.0.push(Hook {
+            name: name.to_string(),
+            required,
+            hook,
+        });
+    }
+
+    /// Run the hooks for allowed evidence of `tee`, and return the released
+    /// secrets by hook name, encrypted to `tee_pubkey`.
+    pub async fn run(
+        &self,
+        tee: &str,
+        token: &str,
+        tee_pubkey: &TeePubKey,
+    ) -> Result<BTreeMap<String, String>> {
+        let mut secrets = BTreeMap::new();
+        for hook in &self.0 {
+            let released = match hook.hook.on_allow(tee, token).await {
+                Result::Ok(released) => released,
+                Err(e) if hook.required => return Err(e.context(format!("Hook {}", hook.name))),
+                Err(e) => {
+                    warn!("Hook {}: {e:#}", hook.name);
+                    continue;
+                }
+            };
+            if let Some(secret) = released {
+                let jwe = seal(&secret, tee_pubkey)
+                    .with_context(|| format!("Cannot release the secret of hook {}", hook.name))?;
+                secrets.insert(hook.name.clone(), jwe);
+            }
+        }
+        Ok(secrets)
+    }
+}
+
+/// Encrypt `secret` to the TEE public key as a compact JWE.
+pub(crate) fn seal(secret: &[u8], tee_pubkey: &TeePubKey) -> Result<String> {
+    if tee_pubkey.kty != "RSA" {
+        bail!(
+            "TEE public keys of type {} are not supported",
+            tee_pubkey.kty
+        );
+    }
+    let component = |value: &str| -> Result<BigUint> {
+        Ok(BigUint::from_bytes_be(&URL_SAFE_NO_PAD.decode(value)?))
+    };
+    let public_key =
+        RsaPublicKey::new(component(&tee_pubkey.k_mod)?, component(&tee_pubkey.k_exp)?)
+            .context("Invalid TEE public key")?;
+
+    let mut rng = rand::thread_rng();
+    let mut cek = [0u8; 32];
+    rng.fill_bytes(&mut cek);
+    let mut iv = [0u8; 12];
+    rng.fill_bytes(&mut iv);
+
+    let encrypted_key = match tee_pubkey.alg.as_str() {
+        "RSA1_5" => public_key.encrypt(&mut rng, Pkcs1v15Encrypt, &cek),
+        "RSA-OAEP-256" => public_key.encrypt(&mut rng, Oaep::new::<Sha256>(), &cek),
+        alg => bail!("TEE public key algorithm {alg} is not supported"),
+    }
+    .map_err(|e| anyhow!("Key encryption failed: {e}"))?;
+
+    let header = URL_SAFE_NO_PAD.encode(serde_json::to_vec(
+        &json!({ "alg": tee_pubkey.alg, "enc": "A256GCM" }),
+    )?);
+    let mut ciphertext = Aes256Gcm::new_from_slice(&cek)
+        .map_err(|e| anyhow!("{e}"))?
+        .encrypt(
+            Nonce::from_slice(&iv),
+            Payload {
+                msg: secret,
+                aad: header.as_bytes(),
+            },
+        )
+        .map_err(|e| anyhow!("Secret encryption failed: {e}"))?;
+    // AES-GCM appends its 16 bytes tag.
+    let tag = ciphertext.split_off(ciphertext.len() - 16);
+
+    Ok([
+        header,
+        URL_SAFE_NO_PAD.encode(encrypted_key),
+        URL_SAFE_NO_PAD.encode(iv),
+        URL_SAFE_NO_PAD.encode(ciphertext),
+        URL_SAFE_NO_PAD.encode(tag),
+    ]
+    .join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::traits::PublicKeyParts;
+    use rsa::RsaPrivateKey;
+
+    struct Release(&'static [u8]);
+
+    #[async_trait]
+    impl PostVerificationHook for Release {
+        async fn on_allow(&self, _tee: &str, _token: &str) -> Result<Option<Vec<u8>>> {
+            Ok(Some(self.0.to_vec()))
+        }
+    }
+
+    struct Failing;
+
+    #[async_trait]
+    impl PostVerificationHook for Failing {
+        async fn on_allow(&self, _tee: &str, _token: &str) -> Result<Option<Vec<u8>>> {
+            bail!("unreachable")
+        }
+    }
+
+    fn open(jwe: &str, key: &RsaPrivateKey) -> Vec<u8> {
+        let parts: Vec<_> = jwe.split('.').collect();
+        let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).unwrap();
+        let cek = key
+            .decrypt(Oaep::new::<Sha256>(), &decode(parts[1]))
+            .unwrap();
+        let mut ciphertext = decode(parts[3]);
+        ciphertext.extend(decode(parts[4]));
+        Aes256Gcm::new_from_slice(&cek)
+            .unwrap()
+            .decrypt(
+                Nonce::from_slice(&decode(parts[2])),
+                Payload {
+                    msg: &ciphertext,
+                    aad: parts[0].as_bytes(),
+                },
+            )
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let tee_pubkey = TeePubKey {
+            kty: "RSA".to_string(),
+            alg: "RSA-OAEP-256".to_string(),
+            k_mod: URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+            k_exp: URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+        };
+
+        let mut hooks = Hooks::default();
+        hooks.push("disk-key", true, Box::new(Release(b"secret")));
+        hooks.push("audit", false, Box::new(Failing));
+        let secrets = hooks.run("tdx", "token", &tee_pubkey).await.unwrap();
+        assert_eq!(secrets.len(), 1);
+        assert_eq!(open(&secrets["disk-key"], &key), b"secret");
+
+        hooks.push("required", true, Box::new(Failing));
+        assert!(hooks.run("tdx", "token", &tee_pubkey).await.is_err());
+
+        let config: HookConfig = serde_json::from_value(json!({
+            "name": "kms",
+            "type": "command",
+            "command": [],
+            "release": true
+        }))
+        .unwrap();
+        assert!(Hooks::new(&[config]).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+//! Metadata of the host, attached to an attestation request by a host
+//! agent.
+//!
+//! The evidence of a guest tells nothing of the host it runs on, such as
+//! its kernel version or the cluster it belongs to. A host agent registered
+//! in the AS config can sign such metadata and have it attached to the
+//! attestation request of a guest, in [`crate::EvaluateOptions::host_metadata`]:
+//!
+//! ```json
+//! "host_agents": [
+//!     { "id": "kubelet-plugin", "public_key": "/etc/attestation-service/host-agent.pem" }
+//! ]
+//! ```
+//!
+//! The key of an agent is an RSA public key, as a PEM SubjectPublicKeyInfo.
+//! The metadata is a JSON object, signed as is with RSASSA-PKCS1-v1_5
+//! SHA-256, and its `nonce` must be that of the attestation request, so
+//! that the metadata of a host cannot be replayed for a guest on another.
+//!
+//! Once verified, the metadata is added to the policy input as
+//! `input.host`, with the ID of the agent that signed it in `agent`, and
+//! to the token as the `host` claim. Metadata of an agent that is not
+//! registered, or with a bad signature, rejects the request.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::sha2::{Digest, Sha256};
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Name of the policy input field and of the token claim.
+pub const HOST_FIELD: &str = "host";
+
+/// Field of the metadata with the ID of the agent that signed it.
+const AGENT_FIELD: &str = "agent";
+
+/// Field of the metadata with the nonce of the attestation request.
+const NONCE_FIELD: &str = "nonce";
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HostAgentConfig {
+    /// ID of the agent, that its metadata names.
+    pub id: String,
+
+    /// PEM RSA public key of the agent.
+    pub public_key: PathBuf,
+}
+
+/// Signed metadata of the host, as attached to the attestation request.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HostMetadata {
+    /// ID of the agent that signed the metadata.
+    pub agent: String,
+
+    /// JSON object of the metadata, as signed.
+    pub metadata: String,
+
+    /// Base64 RSASSA-PKCS1-v1_5 SHA-256 signature of `metadata`.
+    pub signature: String,
+}
+
+/// The registered host agents, and their keys.
+#[derive(Default)]
+pub struct HostAgents {
+    keys: HashMap<String, RsaPublicKey>,
+}
+
+impl HostAgents {
+    pub fn new(agents: &[HostAgentConfig]) -> Result<Self> {
+        let mut keys = HashMap::new();
+        for agent in agents {
+            if agent.id.is_empty() {
+                bail!("The ID of a host agent must not be empty");
+            }
+            let pem = fs::read_to_string(&agent.public_key)
+                .with_context(|| format!("read {}", agent.public_key.display()))?;
+            let key = RsaPublicKey::from_public_key_pem(&pem)
+                .map_err(|e| anyhow!("Invalid public key of host agent {}: {e}", agent.id))?;
+            if keys.insert(agent.id.clone(), key).is_some() {
+                bail!("Host agent {} is registered twice", agent.id);
+            }
+        }
+        Ok(Self { keys })
+    }
+
+    /// Verify the signature of `host` by its agent, and that it is bound to
+    /// `nonce`, and return the metadata with the ID of the agent.
+    pub fn verify(&self, host: &HostMetadata, nonce: &str) -> Result<Value> {
+        let key = self
+            .keys
+            .get(&host.agent)
+            .ok_or_else(|| anyhow!("Host agent {} is not registered", host.agent))?;
+        let signature = base64::engine::general_purpose::STANDARD
+            .decode(&host.signature)
+            .context("Malformed host metadata signature")?;
+        key.verify(
+            Pkcs1v15Sign::new::<Sha256>(),
+            &Sha256::digest(host.metadata.as_bytes()),
+            &signature,
+        )
+        .map_err(|_| anyhow!("The host metadata signature is not that of {}", host.agent))?;
+
+        let mut metadata: Value =
+            serde_json::from_str(&host.metadata).context("Malformed host metadata")?;
+        let Some(fields) = metadata.as_object_mut() else {
+            bail!("The host metadata is not a JSON object");
+        };
+        if fields.get(NONCE_FIELD).and_then(Value::as_str) != Some(nonce) {
+            bail!("The host metadata is not bound to the nonce of the request");
+        }
+        if fields.contains_key(AGENT_FIELD) {
+            bail!("The host metadata must not have an `{AGENT_FIELD}` field");
+        }
+        fields.insert(AGENT_FIELD.to_string(), host.agent.clone().into());
+        Ok(metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs8::{EncodePublicKey, LineEnding};
+    use rsa::RsaPrivateKey;
+    use serde_json::json;
+
+    fn sign(agent: &RsaPrivateKey, metadata: &str) -> String {
+        let signature = agent
+            .sign(
+                Pkcs1v15Sign::new::<Sha256>(),
+                &Sha256::digest(metadata.as_bytes()),
+            )
+            .unwrap();
+        base64::engine::general_purpose::STANDARD.encode(signature)
+    }
+
+    #[test]
+    fn test_verify() {
+        let agent = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.pem");
+        fs::write(
+            &path,
+            agent
+                .to_public_key()
+                .to_public_key_pem(LineEnding::LF)
+                .unwrap(),
+        )
+        .unwrap();
+        let agents = HostAgents::new(&[HostAgentConfig {
+            id: "node-agent".to_string(),
+            public_key: path.clone(),
+        }])
+        .unwrap();
+
+        let metadata =
+            json!({"nonce": "n0", "kernel_version": "6.8.0", "cluster_id": "c1"}).to_string();
+        let host = HostMetadata {
+            agent: "node-agent".to_string(),
+            signature: sign(&agent, &metadata),
+            metadata,
+        };
+        let verified = agents.verify(&host, "n0").unwrap();
+        assert_eq!(verified["kernel_version"], "6.8.0");
+        assert_eq!(verified["agent"], "node-agent");
+
+        let e = agents.verify(&host, "n1").unwrap_err();
+        assert!(e.to_string().contains("not bound to the nonce"));
+        let forged = HostMetadata {
+            metadata: json!({"nonce": "n0", "cluster_id": "c2"}).to_string(),
+            ..host.clone()
+        };
+        assert!(agents.verify(&forged, "n0").is_err());
+        let unregistered = HostMetadata {
+            agent: "other".to_string(),
+            ..host.clone()
+        };
+        assert!(agents.verify(&unregistered, "n0").is_err());
+
+        let metadata = json!({"nonce": "n0", "agent": "forged"}).to_string();
+        let host = HostMetadata {
+            agent: "node-agent".to_string(),
+            signature: sign(&agent, &metadata),
+            metadata,
+        };
+        assert!(agents.verify(&host, "n0").is_err());
+
+        let twice = HostAgentConfig {
+            id: "node-agent".to_string(),
+            public_key: path,
+        };
+        assert!(HostAgents::new(&[twice.clone(), twice]).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! Init-data, the launch-time configuration of a confidential guest.
+//!
+//! CoCo configures the guest components of a TEE, e.g. its attestation
+//! agent or its agent policy, with an init-data document, TOML or JSON:
+//!
+//! ```toml
+//! version = "0.1.0"
+//! algorithm = "sha384"
+//!
+//! [data]
+//! "aa.toml" = '''
+//! [token_configs.kbs]
+//! url = "https://kbs.example.com"
+//! '''
+//! "policy.rego" = '''
+//! package agent_policy
+//! default AllowRequestsFailingPolicy := false
+//! '''
+//! ```
+//!
+//! The host measures the document at launch: its digest, with the
+//! `algorithm` of the document, `sha256`, `sha384` or `sha512`, is set in
+//! the MRCONFIGID of a TD and in the HOSTDATA of an SNP guest, zero padded
+//! or truncated to the size of the field. The attestation request carries
+//! the document, in the init-data of an evidence bundle or in the
+//! `init_data` of the request, see [`crate::EvaluateOptions::init_data`].
+//! The AS hashes it the same way and rejects evidence whose field does not
+//! match, or of a TEE without such a field, at the `init_data` rejection
+//! stage. Once bound, the document is added to the claims:
+//!
+//! - `init_data.media_type`, `init_data.version` and `init_data.algorithm`;
+//! - `init_data.digest`, the digest of the document, before it is padded
+//!   or truncated, and `init_data.sha256` and `init_data.sha384`;
+//! - `init_data.data.<name>`, each entry of its `data`, e.g.
+//!   `init_data.data.policy.rego`, for policies to check the configuration
+//!   of the guest rather than a digest.
+
+use anyhow::{anyhow, bail, Context, Result};
+use as_types::bundle::BundledItem;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::collections::BTreeMap;
+
+/// Version of the init-data documents described here.
+pub const INIT_DATA_VERSION: &str = "0.1.0";
+
+/// The claim of the evidence of each TEE that binds the init-data, as a
+/// JSON pointer into the claims of its verifier, the name of its field,
+/// and its size.
+const BINDINGS: &[(&str, &str, &str, usize)] = &[
+    ("tdx", "/quote/body/mr_config_id", "MRCONFIGID", 48),
+    ("snp", "/host_data", "HOSTDATA", 32),
+];
+
+/// Digest algorithm of an init-data document.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum InitDataAlgorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl InitDataAlgorithm {
+    fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha256 => Sha256::digest(data).to_vec(),
+            Self::Sha384 => Sha384::digest(data).to_vec(),
+            Self::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+}
+
+/// The content of an init-data document.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    pub version: String,
+    pub algorithm: InitDataAlgorithm,
+    /// The configuration files of the guest components, by name.
+    #[serde(default)]
+    pub data: BTreeMap<String, String>,
+}
+
+/// An init-data document of an attestation request, see the module
+/// documentation.
+#[derive(Debug, Clone)]
+pub struct InitData {
+    /// The document as measured, with its media type.
+    pub item: BundledItem,
+    pub document: Document,
+}
+
+impl InitData {
+    /// Parse the init-data document of `item`, of the media type
+    /// `application/toml` or `application/json`.
+    pub fn parse(item: &BundledItem) -> Result<Self> {
+        let document: Document = match item.media_type.as_str() {
+            "application/toml" => {
+                let text = std::str::from_utf8(&item.data).context("The init-data is not UTF-8")?;
+                toml::from_str(text).context("Invalid TOML init-data")?
+            }
+            "application/json" => {
+                serde_json::from_slice(&item.data).context("Invalid JSON init-data")?
+            }
+            media_type => bail!("Unknown init-data media type {media_type}"),
+        };
+        if document.version != INIT_DATA_VERSION {
+            bail!(
+                "Init-data version {} is not supported, expected {INIT_DATA_VERSION}",
+                document.version
+            );
+        }
+        Ok(Self {
+            item: item.clone(),
+            document,
+        })
+    }
+
+    /// The digest of the document, with its algorithm.
+    pub fn digest(&self) -> Vec<u8> {
+        self.document.algorithm.digest(&self.item.data)
+    }
+
+    /// Check that the `claims` of the evidence of `tee`, as returned by its
+    /// verifier, bind the document.
+    pub fn check(&self, tee: &str, claims: &Value) -> Result<()> {
+        let (_, pointer, field, size) = BINDINGS
+            .iter()
+            .find(|(binding_tee, ..)| *binding_tee == tee)
+            .ok_or_else(|| anyhow!("The evidence of {tee} does not bind init-data"))?;
+        let measured = claims
+            .pointer(pointer)
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("The evidence has no {field}"))?;
+        let mut expected = self.digest();
+        expected.resize(*size, 0);
+        let expected = hex::encode(expected);
+        if !measured.eq_ignore_ascii_case(&expected) {
+            bail!(
+                "The {field} of the evidence, {measured}, is not the init-data digest {expected}"
+            );
+        }
+        Ok(())
+    }
+
+    /// The `init_data` claims of the document.
+    pub fn claims(&self) -> Map<String, Value> {
+        let mut claims = Map::new();
+        claims.insert(
+            "init_data.media_type".to_string(),
+            self.item.media_type.clone().into(),
+        );
+        claims.insert(
+            "init_data.version".to_string(),
+            self.document.version.clone().into(),
+        );
+        claims.insert(
+            "init_data.algorithm".to_string(),
+            self.document.algorithm.to_string().into(),
+        );
+        claims.insert(
+            "init_data.digest".to_string(),
+            hex::encode(self.digest()).into(),
+        );
+        claims.insert(
+            "init_data.sha256".to_string(),
+            hex::encode(Sha256::digest(&self.item.data)).into(),
+        );
+        claims.insert(
+            "init_data.sha384".to_string(),
+            hex::encode(Sha384::digest(&self.item.data)).into(),
+        );
+        for (name, content) in &self.document.data {
+            claims.insert(format!("init_data.data.{name}"), content.clone().into());
+        }
+        claims
+    }
+}
+
+/// The init-data `document` of an attestation request, JSON if it is an
+/// object, else TOML.
+pub fn item(document: String) -> BundledItem {
+    let media_type = match document.trim_start().starts_with('{') {
+        true => "application/json",
+        false => "application/toml",
+    };
+    BundledItem::new(media_type, document)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const DOCUMENT: &str = r#"version = "0.1.0"
+algorithm = "sha384"
+
+[data]
+"policy.rego" = "package agent_policy"
+"#;
+
+    #[test]
+    fn test_parse() {
+        let init_data = InitData::parse(&item(DOCUMENT.to_string())).unwrap();
+        assert_eq!(init_data.item.media_type, "application/toml");
+        assert_eq!(init_data.document.algorithm, InitDataAlgorithm::Sha384);
+        assert_eq!(init_data.digest(), Sha384::digest(DOCUMENT).to_vec());
+
+        let json = r#"{"version": "0.1.0", "algorithm": "sha256", "data": {"aa.toml": ""}}"#;
+        let init_data = InitData::parse(&item(json.to_string())).unwrap();
+        assert_eq!(init_data.item.media_type, "application/json");
+        assert_eq!(init_data.document.data["aa.toml"], "");
+
+        assert!(InitData::parse(&item(r#"{"policy": "allow"}"#.to_string())).is_err());
+        let e = InitData::parse(&item(DOCUMENT.replace("0.1.0", "0.2.0"))).unwrap_err();
+        assert!(e.to_string().contains("not supported"));
+        let e = InitData::parse(&BundledItem::new("text/plain", DOCUMENT)).unwrap_err();
+        assert!(e.to_string().contains("media type"));
+    }
+
+    #[test]
+    fn test_check() {
+        let init_data = InitData::parse(&item(DOCUMENT.to_string())).unwrap();
+        let digest = hex::encode(Sha384::digest(DOCUMENT));
+
+        let tdx = json!({"quote": {"body": {"mr_config_id": digest}}});
+        init_data.check("tdx", &tdx).unwrap();
+        let tdx = json!({"quote": {"body": {"mr_config_id": digest.to_uppercase()}}});
+        init_data.check("tdx", &tdx).unwrap();
+        let tdx = json!({"quote": {"body": {"mr_config_id": "00".repeat(48)}}});
+        let e = init_data.check("tdx", &tdx).unwrap_err();
+        assert!(e.to_string().contains("MRCONFIGID"));
+
+        // HOSTDATA has the first 32 bytes of the SHA-384.
+        let snp = json!({"host_data": &digest[..64]});
+        init_data.check("snp", &snp).unwrap();
+        assert!(init_data.check("snp", &json!({})).is_err());
+
+        // A SHA-256 fills the first 32 bytes of MRCONFIGID.
+        let sha256 = DOCUMENT.replace("sha384", "sha256");
+        let init_data = InitData::parse(&item(sha256.clone())).unwrap();
+        let mr_config_id = hex::encode(Sha256::digest(&sha256)) + &"00".repeat(16);
+        let tdx = json!({"quote": {"body": {"mr_config_id": mr_config_id}}});
+        init_data.check("tdx", &tdx).unwrap();
+
+        let e = init_data.check("sample", &json!({})).unwrap_err();
+        assert!(e.to_string().contains("does not bind"));
+    }
+
+    #[test]
+    fn test_claims() {
+        let init_data = InitData::parse(&item(DOCUMENT.to_string())).unwrap();
+        let claims = init_data.claims();
+        assert_eq!(claims["init_data.algorithm"], "sha384");
+        assert_eq!(claims["init_data.version"], "0.1.0");
+        assert_eq!(claims["init_data.digest"], claims["init_data.sha384"]);
+        assert_eq!(claims["init_data.data.policy.rego"], "package agent_policy");
+    }
+}
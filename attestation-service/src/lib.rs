@@ -3,6 +3,16 @@
 //! # Features
 //! - `rvps-grpc`: The AS will connect a remote RVPS.
 //! - `rvps-native`: The AS will integrate RVPS functionalities itself.
+//! - `pkcs11`: Evidence decryption keys and token signing keys can be held
+//!   by an HSM.
+//! - `crypto-openssl`, `crypto-ring`: Crypto backends the verifiers can use.
+//! - `cert-issuer`: X.509 certificates can be issued on attestation.
+//! - `fips`: The AS always runs in FIPS mode.
+//! - `parquet-export`: The attestation history can be exported as Parquet.
+//! - `service`: The attestation service, with its policy engines, RVPS and
+//!   token brokers, see [`AttestationService`]. Implied by `rvps-native`
+//!   and `rvps-grpc`. Without it, the crate is a library of the verifiers
+//!   that only turn evidence into claims, see [`verifier::verify`].
 
 extern crate serde;
 
@@ -14,156 +24,100 @@ extern crate strum;
 #[macro_use]
 extern crate strum_macros;
 
+#[cfg(feature = "service")]
+pub mod admission;
+#[cfg(feature = "service")]
+pub mod advisories;
+#[cfg(feature = "service")]
+pub mod audit;
+#[cfg(feature = "service")]
+pub mod backup;
+#[cfg(feature = "service")]
+pub mod capabilities;
+#[cfg(feature = "service")]
+pub mod certificate;
+#[cfg(feature = "service")]
+pub mod claim_filter;
+#[cfg(feature = "service")]
+pub mod claims_log;
+#[cfg(feature = "service")]
 pub mod config;
+#[cfg(feature = "service")]
+pub mod decryption;
+#[cfg(feature = "service")]
+pub mod deny_list;
+#[cfg(feature = "service")]
+pub mod explain;
+#[cfg(feature = "service")]
+pub mod failure_cache;
+#[cfg(feature = "service")]
+pub mod fips;
+#[cfg(feature = "service")]
+pub mod history;
+#[cfg(feature = "service")]
+pub mod hooks;
+#[cfg(feature = "service")]
+pub mod host;
+#[cfg(feature = "service")]
+pub mod init_data;
+#[cfg(feature = "service")]
+pub mod maintenance;
+pub mod metrics;
+#[cfg(feature = "service")]
+pub mod migration;
+#[cfg(feature = "service")]
+pub mod nonces;
+#[cfg(feature = "service")]
 pub mod policy_engine;
+#[cfg(feature = "service")]
+pub mod posture;
+pub mod progress;
+#[cfg(feature = "service")]
+pub mod reappraisal;
+#[cfg(feature = "service")]
+pub mod rejections;
+#[cfg(feature = "service")]
+pub mod replay;
+#[cfg(feature = "service")]
+pub mod result_cache;
+#[cfg(feature = "service")]
 pub mod rvps;
+#[cfg(feature = "service")]
+pub mod self_test;
+#[cfg(feature = "service")]
+pub mod status;
+#[cfg(feature = "service")]
+pub mod submitter;
+#[cfg(feature = "service")]
+pub mod tofu;
+#[cfg(feature = "service")]
 mod token;
+#[cfg(feature = "service")]
+pub mod tracing;
+#[cfg(feature = "service")]
+pub mod transparency;
+#[cfg(feature = "service")]
+mod ttl_cache;
 mod utils;
 pub mod verifier;
+#[cfg(feature = "service")]
+mod worker;
 
-use crate::token::AttestationTokenBroker;
+#[cfg(feature = "service")]
+mod service;
 
-use anyhow::{anyhow, Context, Result};
-use as_types::SetPolicyInput;
-use config::Config;
 pub use kbs_types::{Attestation, Tee};
-use policy_engine::PolicyEngine;
-use rvps::{Message, RVPSAPI};
-use serde_json::json;
-use std::collections::HashMap;
-
-#[cfg(any(feature = "rvps-grpc", feature = "rvps-native"))]
-use std::{fs, str::FromStr};
-
-#[cfg(any(feature = "rvps-grpc", feature = "rvps-native"))]
-use policy_engine::PolicyEngineType;
-
-use crate::utils::flatten_claims;
-
-pub struct AttestationService {
-    _config: Config,
-    policy_engine: Box<dyn PolicyEngine + Send + Sync>,
-    rvps: Box<dyn RVPSAPI + Send + Sync>,
-    token_broker: Box<dyn AttestationTokenBroker + Send + Sync>,
-}
-
-impl AttestationService {
-    /// Create a new Attestation Service instance.
-    #[cfg(feature = "rvps-native")]
-    pub fn new(config: Config) -> Result<Self> {
-        if !config.work_dir.as_path().exists() {
-            fs::create_dir_all(&config.work_dir)
-                .map_err(|e| anyhow!("Create AS work dir failed: {:?}", e))?;
-        }
-
-        let policy_engine = PolicyEngineType::from_str(&config.policy_engine)
-            .map_err(|_| anyhow!("Policy Engine {} is not supported", &config.policy_engine))?
-            .to_policy_engine(config.work_dir.as_path())?;
-
-        let rvps_store = config.rvps_store_type.to_store()?;
-        let rvps = Box::new(rvps::Core::new(rvps_store));
-
-        let token_broker = config
-            .attestation_token_broker
-            .to_token_broker(config.attestation_token_config.clone())?;
-
-        Ok(Self {
-            _config: config,
-            policy_engine,
-            rvps,
-            token_broker,
-        })
-    }
-
-    /// Create a new Attestation Service, and connect to a remote rvps.
-    #[cfg(feature = "rvps-grpc")]
-    pub async fn new_with_rvps_grpc(rvps_addr: &str, config: Config) -> Result<Self> {
-        if !config.work_dir.as_path().exists() {
-            fs::create_dir_all(&config.work_dir)
-                .map_err(|e| anyhow!("Create AS work dir failed: {:?}", e))?;
-        }
-
-        let policy_engine = PolicyEngineType::from_str(&config.policy_engine)
-            .map_err(|_| anyhow!("Policy Engine {} is not supported", &config.policy_engine))?
-            .to_policy_engine(config.work_dir.as_path())?;
-
-        let rvps = Box::new(rvps::Agent::new(rvps_addr).await?);
-
-        let token_broker = config
-            .attestation_token_broker
-            .to_token_broker(config.attestation_token_config.clone())?;
-
-        Ok(Self {
-            _config: config,
-            policy_engine,
-            rvps,
-            token_broker,
-        })
-    }
-
-    /// Set Attestation Verification Policy.
-    pub async fn set_policy(&mut self, input: SetPolicyInput) -> Result<()> {
-        self.policy_engine
-            .set_policy(input)
-            .await
-            .map_err(|e| anyhow!("Cannot Set Policy: {:?}", e))
-    }
-
-    /// Evaluate Attestation Evidence.
-    /// Issue an attestation results token which contain TCB status and TEE public key.
-    pub async fn evaluate(&self, tee: Tee, nonce: &str, attestation: &str) -> Result<String> {
-        let attestation = serde_json::from_str::<Attestation>(attestation)
-            .context("Failed to deserialize Attestation")?;
-        let verifier = crate::verifier::to_verifier(&tee)?;
-
-        let claims_from_tee_evidence = verifier
-            .evaluate(nonce.to_string(), &attestation)
-            .await
-            .map_err(|e| anyhow!("Verifier evaluate failed: {e:?}"))?;
-
-        let flattened_claims = flatten_claims(tee.clone(), &claims_from_tee_evidence)?;
-        let tcb = serde_json::to_string(&flattened_claims)?;
-        let reference_data_map = self
-            .get_reference_data(&tcb)
-            .await
-            .map_err(|e| anyhow!("Generate reference data failed{:?}", e))?;
-
-        // Now only support using default policy to evaluate
-        let evaluation_report = self
-            .policy_engine
-            .evaluate(reference_data_map, tcb.clone(), None)
-            .await
-            .map_err(|e| anyhow!("Policy Engine evaluation failed: {e}"))?;
-
-        let token_claims = json!({
-            "tee-pubkey": attestation.tee_pubkey.clone(),
-            "tcb-status": flattened_claims,
-            "evaluation-report": evaluation_report,
-        });
-        let attestation_results_token = self.token_broker.issue(token_claims)?;
-
-        Ok(attestation_results_token)
-    }
-
-    async fn get_reference_data(&self, tcb_claims: &str) -> Result<HashMap<String, Vec<String>>> {
-        let mut data = HashMap::new();
-        let tcb_claims_map: HashMap<String, String> = serde_json::from_str(tcb_claims)?;
-        for key in tcb_claims_map.keys() {
-            data.insert(
-                key.to_string(),
-                self.rvps
-                    .get_digests(key)
-                    .await?
-                    .unwrap_or_default()
-                    .hash_values
-                    .clone(),
-            );
-        }
-        Ok(data)
-    }
-
-    /// Registry a new reference value
-    pub async fn registry_reference_value(&mut self, message: Message) -> Result<()> {
-        self.rvps.verify_and_extract(message).await
-    }
-}
+#[cfg(feature = "service")]
+pub use host::HostMetadata;
+#[cfg(feature = "service")]
+pub use service::{AttestationService, BatchItem, EvaluateOptions, Evaluation};
+#[cfg(feature = "service")]
+pub use submitter::Submitter;
+#[cfg(feature = "service")]
+pub use token::cbor::Cbor;
+#[cfg(feature = "service")]
+pub use token::cosign::{CoSigner, CoSignerConfig};
+#[cfg(feature = "service")]
+pub use token::ear::TokenFormat;
+#[cfg(feature = "service")]
+pub use token::mapper::{ClaimMapper, ClaimMapperConfig};
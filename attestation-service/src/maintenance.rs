@@ -0,0 +1,85 @@
+//! Maintenance of the stores of the AS.
+//!
+//! A long-running AS accumulates state: the digests of the replay window,
+//! the nonces of challenges, expired reference values in the RVPS store,
+//! and the attestation history.
+//! A maintenance run, see [`crate::AttestationService::maintain`], prunes
+//! them:
+//!
+//! - the digests of the replay window that fell out of it;
+//! - the expired nonces of challenges, see [`crate::nonces`];
+//! - the expired reference values of the RVPS store, which verification
+//!   ignores anyway;
+//! - the records of the history older than `retention_days` of the
+//!   `history` config, reclaiming their space in the history files.
+//!
+//! `grpc-as` runs it periodically, and serves the totals of the runs since
+//! the AS started.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// What a maintenance run pruned.
+#[derive(Clone, Copy, Debug, Default, Serialize, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    /// Digests of evidence out of the replay window.
+    pub replay_entries: usize,
+    /// Expired nonces of challenges.
+    pub nonces: usize,
+    /// Expired reference values.
+    pub reference_values: usize,
+    /// History records past their retention.
+    pub history_records: usize,
+    /// Bytes of storage reclaimed.
+    pub reclaimed_bytes: u64,
+}
+
+impl MaintenanceReport {
+    fn add(&mut self, other: &MaintenanceReport) {
+        self.replay_entries += other.replay_entries;
+        self.nonces += other.nonces;
+        self.reference_values += other.reference_values;
+        self.history_records += other.history_records;
+        self.reclaimed_bytes += other.reclaimed_bytes;
+    }
+}
+
+/// The maintenance runs since the AS started.
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
+pub struct MaintenanceStats {
+    pub runs: u64,
+    pub last_run: Option<DateTime<Utc>>,
+    /// What the runs pruned, in total.
+    pub total: MaintenanceReport,
+}
+
+impl MaintenanceStats {
+    pub(crate) fn record(&mut self, report: &MaintenanceReport) {
+        self.runs += 1;
+        self.last_run = Some(Utc::now());
+        self.total.add(report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut stats = MaintenanceStats::default();
+        let report = MaintenanceReport {
+            replay_entries: 2,
+            nonces: 3,
+            reference_values: 1,
+            history_records: 10,
+            reclaimed_bytes: 4096,
+        };
+        stats.record(&report);
+        stats.record(&report);
+        assert_eq!(stats.runs, 2);
+        assert!(stats.last_run.is_some());
+        assert_eq!(stats.total.history_records, 20);
+        assert_eq!(stats.total.reclaimed_bytes, 8192);
+    }
+}
@@ -0,0 +1,311 @@
+//! Prometheus metrics of the AS.
+//!
+//! The AS counts, since it started:
+//!
+//! - `attestation_verification_seconds{tee}`: histogram of the time the
+//!   verifiers took, evidence verified by an earlier attestation aside;
+//! - `attestation_policy_evaluation_seconds{policy_id}`: histogram of the
+//!   time of each policy evaluation;
+//! - `attestation_result_cache_lookups_total{result}`: lookups of verified
+//!   evidence in the result cache, `hit` or `miss`;
+//! - `attestation_collateral_fetch_failures_total{source}`: failed fetches
+//!   of verification collateral, e.g. from the `pccs`;
+//! - `attestation_tokens_issued_total{tee,format}`: tokens issued on
+//!   attestation, refreshed tokens aside;
+//! - `attestation_rejections_total{tee,stage}`: rejected attestations, as
+//!   counted by the rejection telemetry.
+//!
+//! `AttestationService::metrics` renders them in the Prometheus text
+//! format, which `grpc-as` serves at `/metrics`.
+
+#![cfg_attr(not(feature = "service"), allow(dead_code))]
+
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Upper bounds of the buckets of the histograms, in seconds.
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+const VERIFICATION_SECONDS: &str = "attestation_verification_seconds";
+const POLICY_EVALUATION_SECONDS: &str = "attestation_policy_evaluation_seconds";
+const RESULT_CACHE_LOOKUPS: &str = "attestation_result_cache_lookups_total";
+const COLLATERAL_FETCH_FAILURES: &str = "attestation_collateral_fetch_failures_total";
+const TOKENS_ISSUED: &str = "attestation_tokens_issued_total";
+const REJECTIONS: &str = "attestation_rejections_total";
+
+const HELP: &[(&str, &str)] = &[
+    (VERIFICATION_SECONDS, "Time the verifiers took on evidence."),
+    (POLICY_EVALUATION_SECONDS, "Time of the policy evaluations."),
+    (
+        RESULT_CACHE_LOOKUPS,
+        "Lookups of verified evidence in the result cache.",
+    ),
+    (
+        COLLATERAL_FETCH_FAILURES,
+        "Failed fetches of verification collateral.",
+    ),
+    (TOKENS_ISSUED, "Issued attestation tokens."),
+    (REJECTIONS, "Rejected attestations."),
+];
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::default();
+}
+
+/// A metric and the values of its labels.
+type Series = (&'static str, Vec<(&'static str, String)>);
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Histogram {
+    /// Observations in each bucket, the last one being `+Inf`.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; BUCKETS.len() + 1];
+        }
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    counters: Mutex<BTreeMap<Series, u64>>,
+    histograms: Mutex<BTreeMap<Series, Histogram>>,
+}
+
+impl Registry {
+    fn count(&self, name: &'static str, labels: Vec<(&'static str, String)>, value: u64) {
+        *self
+            .counters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry((name, labels))
+            .or_default() += value;
+    }
+
+    fn observe(&self, name: &'static str, labels: Vec<(&'static str, String)>, value: Duration) {
+        self.histograms
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry((name, labels))
+            .or_default()
+            .observe(value.as_secs_f64());
+    }
+
+    /// The metrics in the Prometheus text format, with the `counters` of
+    /// the caller.
+    fn render(&self, counters: &[(Series, u64)]) -> String {
+        let mut series: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        let own_counters = self
+            .counters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        for ((name, labels), value) in own_counters
+            .iter()
+            .chain(counters.iter().map(|c| (&c.0, &c.1)))
+        {
+            series
+                .entry(*name)
+                .or_default()
+                .push(format!("{name}{} {value}", labels_text(labels, None)));
+        }
+        let histograms = self
+            .histograms
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        for ((name, labels), histogram) in &histograms {
+            let lines = series.entry(*name).or_default();
+            let mut cumulative = 0;
+            for (i, count) in histogram.buckets.iter().enumerate() {
+                cumulative += count;
+                let bound = BUCKETS.get(i).map_or("+Inf".to_string(), f64::to_string);
+                lines.push(format!(
+                    "{name}_bucket{} {cumulative}",
+                    labels_text(labels, Some(&bound))
+                ));
+            }
+            let labels = labels_text(labels, None);
+            lines.push(format!("{name}_sum{labels} {}", histogram.sum));
+            lines.push(format!("{name}_count{labels} {}", histogram.count));
+        }
+
+        let mut text = String::new();
+        for (name, lines) in series {
+            let kind = match name.ends_with("_total") {
+                true => "counter",
+                false => "histogram",
+            };
+            if let Some((_, help)) = HELP.iter().find(|(metric, _)| *metric == name) {
+                let _ = writeln!(text, "# HELP {name} {help}");
+            }
+            let _ = writeln!(text, "# TYPE {name} {kind}");
+            for line in lines {
+                let _ = writeln!(text, "{line}");
+            }
+        }
+        text
+    }
+}
+
+/// `{name="value",...}` of the `labels`, with the `le` bound of a bucket.
+fn labels_text(labels: &[(&'static str, String)], le: Option<&str>) -> String {
+    let mut labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+        .collect();
+    if let Some(le) = le {
+        labels.push(format!("le=\"{le}\""));
+    }
+    match labels.is_empty() {
+        true => String::new(),
+        false => format!("{{{}}}", labels.join(",")),
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Record that the verifier of `tee` took `elapsed` on evidence.
+pub(crate) fn observe_verification(tee: &str, elapsed: Duration) {
+    REGISTRY.observe(
+        VERIFICATION_SECONDS,
+        vec![("tee", tee.to_string())],
+        elapsed,
+    );
+}
+
+/// Record that the policy `policy_id` took `elapsed` to evaluate.
+pub(crate) fn observe_policy_evaluation(policy_id: &str, elapsed: Duration) {
+    REGISTRY.observe(
+        POLICY_EVALUATION_SECONDS,
+        vec![("policy_id", policy_id.to_string())],
+        elapsed,
+    );
+}
+
+/// Count a lookup of the result cache, a `hit` or not.
+pub(crate) fn count_result_cache_lookup(hit: bool) {
+    let result = match hit {
+        true => "hit",
+        false => "miss",
+    };
+    REGISTRY.count(
+        RESULT_CACHE_LOOKUPS,
+        vec![("result", result.to_string())],
+        1,
+    );
+}
+
+/// Count a failed fetch of collateral from `source`, e.g. `pccs`.
+pub(crate) fn count_collateral_fetch_failure(source: &str) {
+    REGISTRY.count(
+        COLLATERAL_FETCH_FAILURES,
+        vec![("source", source.to_string())],
+        1,
+    );
+}
+
+/// Count a token of `format` issued to evidence of `tee`.
+pub(crate) fn count_token_issued(tee: &str, format: &str) {
+    REGISTRY.count(
+        TOKENS_ISSUED,
+        vec![("tee", tee.to_string()), ("format", format.to_string())],
+        1,
+    );
+}
+
+/// The metrics in the Prometheus text format, with the `rejections` of
+/// each TEE at each stage.
+pub(crate) fn render(rejections: Vec<(String, String, u64)>) -> String {
+    let rejections: Vec<(Series, u64)> = rejections
+        .into_iter()
+        .map(|(tee, stage, count)| ((REJECTIONS, vec![("tee", tee), ("stage", stage)]), count))
+        .collect();
+    REGISTRY.render(&rejections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let registry = Registry::default();
+        registry.count(
+            TOKENS_ISSUED,
+            vec![("tee", "tdx".to_string()), ("format", "jwt".to_string())],
+            1,
+        );
+        registry.count(
+            TOKENS_ISSUED,
+            vec![("tee", "tdx".to_string()), ("format", "jwt".to_string())],
+            1,
+        );
+        registry.observe(
+            VERIFICATION_SECONDS,
+            vec![("tee", "tdx".to_string())],
+            Duration::from_millis(20),
+        );
+        registry.observe(
+            VERIFICATION_SECONDS,
+            vec![("tee", "tdx".to_string())],
+            Duration::from_secs(20),
+        );
+        let rejections = [(
+            (
+                REJECTIONS,
+                vec![
+                    ("tee", "snp".to_string()),
+                    ("stage", "po\"licy".to_string()),
+                ],
+            ),
+            3,
+        )];
+
+        let text = registry.render(&rejections);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[..3],
+            [
+                "# HELP attestation_rejections_total Rejected attestations.",
+                "# TYPE attestation_rejections_total counter",
+                "attestation_rejections_total{tee=\"snp\",stage=\"po\\\"licy\"} 3",
+            ]
+        );
+        assert!(lines.contains(&"attestation_tokens_issued_total{tee=\"tdx\",format=\"jwt\"} 2"));
+        assert!(lines.contains(&"# TYPE attestation_verification_seconds histogram"));
+        assert!(
+            lines.contains(&"attestation_verification_seconds_bucket{tee=\"tdx\",le=\"0.01\"} 0")
+        );
+        assert!(
+            lines.contains(&"attestation_verification_seconds_bucket{tee=\"tdx\",le=\"0.025\"} 1")
+        );
+        assert!(lines.contains(&"attestation_verification_seconds_bucket{tee=\"tdx\",le=\"10\"} 1"));
+        assert!(
+            lines.contains(&"attestation_verification_seconds_bucket{tee=\"tdx\",le=\"+Inf\"} 2")
+        );
+        assert!(lines.contains(&"attestation_verification_seconds_count{tee=\"tdx\"} 2"));
+    }
+}
@@ -0,0 +1,244 @@
+//! Consistency of the TD quotes of a TD migration.
+//!
+//! Before the migration session key of a TD is released, the source and
+//! destination TDs of the migration each send a TD quote bound to the
+//! nonce of the migration session, and the AS checks that the destination
+//! can take over the TD:
+//! - `identity`: both TDs have the same MRTD, MRCONFIGID, MROWNER and
+//!   MROWNERCONFIG.
+//! - `attributes`: both TDs have the same TD attributes and XFAM.
+//! - `migratable`: both TDs have the MIGRATABLE attribute.
+//! - `tcb`: no component of the TEE_TCB_SVN of the destination is lower
+//!   than that of the source, so that the TD does not migrate to an older
+//!   TDX module.
+//!
+//! The claims of the quotes are the `source.*` and `destination.*` claims,
+//! e.g. `source.tdx.quote.body.mr_td`, next to the checks and the
+//! mismatches they found, like the `tdx.consistency` claims:
+//!
+//! ```json
+//! "migration.consistent": false,
+//! "migration.checks.0": "identity",
+//! "migration.mismatches.0.check": "tcb",
+//! "migration.mismatches.0.detail": "TEE_TCB_SVN[2] of the destination is 3, below 5 of the source"
+//! ```
+//!
+//! The `migration_policy` of the AS config decides whether the migration
+//! is authorized.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Prefix of the flattened claims of the TD quote body.
+const QUOTE_BODY: &str = "tdx.quote.body.";
+
+const IDENTITY_CLAIMS: &[&str] = &["mr_td", "mr_config_id", "mr_owner", "mr_owner_config"];
+const ATTRIBUTE_CLAIMS: &[&str] = &["td_attributes", "xfam"];
+
+/// MIGRATABLE bit of the TD attributes.
+const MIGRATABLE: u64 = 1 << 29;
+/// Number of components of the TEE_TCB_SVN.
+const TCB_SVN_SIZE: usize = 16;
+
+pub const SOURCE_PREFIX: &str = "source";
+pub const DESTINATION_PREFIX: &str = "destination";
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct MigrationConsistency {
+    pub checks: Vec<&'static str>,
+    pub mismatches: Vec<Mismatch>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub check: &'static str,
+    pub detail: String,
+}
+
+impl MigrationConsistency {
+    fn mismatch(&mut self, check: &'static str, detail: String) {
+        self.mismatches.push(Mismatch { check, detail });
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+fn quote_claim<'a>(claims: &'a Value, name: &str) -> Option<&'a Value> {
+    claims.get(format!("{QUOTE_BODY}{name}"))
+}
+
+/// The TD attributes, a little endian hex claim.
+fn td_attributes(claims: &Value) -> Option<u64> {
+    let attributes = hex::decode(quote_claim(claims, "td_attributes")?.as_str()?).ok()?;
+    Some(u64::from_le_bytes(attributes.try_into().ok()?))
+}
+
+/// Check the flattened claims of the `source` and `destination` TD quotes
+/// of a migration against each other.
+pub fn check(source: &Value, destination: &Value) -> MigrationConsistency {
+    let mut consistency = MigrationConsistency::default();
+
+    for (check, names) in [
+        ("identity", IDENTITY_CLAIMS),
+        ("attributes", ATTRIBUTE_CLAIMS),
+    ] {
+        consistency.checks.push(check);
+        for name in names {
+            match (quote_claim(source, name), quote_claim(destination, name)) {
+                (Some(source), Some(destination)) if source == destination => {}
+                (Some(source), Some(destination)) => consistency.mismatch(
+                    check,
+                    format!("{name} of the destination is {destination}, {source} on the source"),
+                ),
+                _ => consistency.mismatch(check, format!("{name} is missing from the quotes")),
+            }
+        }
+    }
+
+    consistency.checks.push("migratable");
+    for (side, claims) in [(SOURCE_PREFIX, source), (DESTINATION_PREFIX, destination)] {
+        if !td_attributes(claims).is_some_and(|attributes| attributes & MIGRATABLE != 0) {
+            consistency.mismatch("migratable", format!("The {side} TD is not migratable"));
+        }
+    }
+
+    consistency.checks.push("tcb");
+    for index in 0..TCB_SVN_SIZE {
+        let svn = |claims: &Value| quote_claim(claims, &format!("tcb_svn.{index}"))?.as_u64();
+        match (svn(source), svn(destination)) {
+            (Some(source), Some(destination)) if destination >= source => {}
+            (Some(source), Some(destination)) => consistency.mismatch(
+                "tcb",
+                format!(
+                    "TEE_TCB_SVN[{index}] of the destination is {destination}, \
+                     below {source} of the source"
+                ),
+            ),
+            _ => {
+                let detail = format!("TEE_TCB_SVN[{index}] is missing from the quotes");
+                consistency.mismatch("tcb", detail);
+                break;
+            }
+        }
+    }
+
+    consistency
+}
+
+/// The claims of a migration: the flattened claims of the `source` and
+/// `destination` quotes, under their prefix, and the `migration` claims of
+/// `consistency`.
+pub fn claims(source: &Value, destination: &Value, consistency: &MigrationConsistency) -> Value {
+    let mut claims = Map::new();
+    for (prefix, side) in [(SOURCE_PREFIX, source), (DESTINATION_PREFIX, destination)] {
+        if let Some(side) = side.as_object() {
+            claims.extend(
+                side.iter()
+                    .map(|(name, value)| (format!("{prefix}.{name}"), value.clone())),
+            );
+        }
+    }
+    claims.insert(
+        "migration.consistent".to_string(),
+        consistency.is_consistent().into(),
+    );
+    for (index, check) in consistency.checks.iter().enumerate() {
+        claims.insert(format!("migration.checks.{index}"), (*check).into());
+    }
+    for (index, mismatch) in consistency.mismatches.iter().enumerate() {
+        claims.insert(
+            format!("migration.mismatches.{index}.check"),
+            mismatch.check.into(),
+        );
+        claims.insert(
+            format!("migration.mismatches.{index}.detail"),
+            mismatch.detail.clone().into(),
+        );
+    }
+    claims.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn quote(mr_td: &str, td_attributes: &str, tcb_svn: [u64; TCB_SVN_SIZE]) -> Value {
+        let mut claims = json!({
+            "tdx.quote.body.mr_td": mr_td,
+            "tdx.quote.body.mr_config_id": "00",
+            "tdx.quote.body.mr_owner": "00",
+            "tdx.quote.body.mr_owner_config": "00",
+            "tdx.quote.body.td_attributes": td_attributes,
+            "tdx.quote.body.xfam": "e742060000000000",
+        });
+        for (index, svn) in tcb_svn.iter().enumerate() {
+            claims[format!("tdx.quote.body.tcb_svn.{index}")] = (*svn).into();
+        }
+        claims
+    }
+
+    const MIGRATABLE_ATTRIBUTES: &str = "0000003000000000";
+    const SVN: [u64; TCB_SVN_SIZE] = [3, 0, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    #[test]
+    fn test_consistent() {
+        let source = quote("705e", MIGRATABLE_ATTRIBUTES, SVN);
+        let mut newer = SVN;
+        newer[2] = 6;
+        let destination = quote("705e", MIGRATABLE_ATTRIBUTES, newer);
+
+        let consistency = check(&source, &destination);
+        assert_eq!(
+            consistency.checks,
+            ["identity", "attributes", "migratable", "tcb"]
+        );
+        assert!(consistency.is_consistent());
+
+        let claims = claims(&source, &destination, &consistency);
+        assert_eq!(claims["migration.consistent"], true);
+        assert_eq!(claims["source.tdx.quote.body.tcb_svn.2"], 5);
+        assert_eq!(claims["destination.tdx.quote.body.tcb_svn.2"], 6);
+        assert!(claims.get("migration.mismatches.0.check").is_none());
+    }
+
+    #[test]
+    fn test_mismatches() {
+        let source = quote("705e", MIGRATABLE_ATTRIBUTES, SVN);
+        let mut older = SVN;
+        older[2] = 4;
+        let destination = quote("1234", "0000001000000000", older);
+
+        let consistency = check(&source, &destination);
+        let checks: Vec<_> = consistency
+            .mismatches
+            .iter()
+            .map(|mismatch| mismatch.check)
+            .collect();
+        assert_eq!(checks, ["identity", "attributes", "migratable", "tcb"]);
+        assert_eq!(
+            consistency.mismatches[2].detail,
+            "The destination TD is not migratable"
+        );
+        assert_eq!(
+            consistency.mismatches[3].detail,
+            "TEE_TCB_SVN[2] of the destination is 4, below 5 of the source"
+        );
+
+        let claims = claims(&source, &destination, &consistency);
+        assert_eq!(claims["migration.consistent"], false);
+        assert_eq!(claims["migration.mismatches.0.check"], "identity");
+    }
+
+    #[test]
+    fn test_missing_claims() {
+        let consistency = check(&json!({}), &json!({}));
+        assert!(!consistency.is_consistent());
+        assert!(consistency
+            .mismatches
+            .iter()
+            .any(|mismatch| mismatch.detail == "TEE_TCB_SVN[0] is missing from the quotes"));
+    }
+}
@@ -0,0 +1,403 @@
+//! Nonces of challenges.
+//!
+//! The nonces of challenges, see [`crate::verifier::freshness`], are random,
+//! but the AS does not otherwise know them: any nonce an attester puts in
+//! its report data is accepted, so evidence produced for a nonce of the
+//! attester's choosing, or for an old challenge, verifies as well. With
+//! `nonces` in the AS config, the AS keeps the nonces it issues until they
+//! expire, and the evidence of verifiers with the `nonce` freshness method
+//! must bind one of them, that has not expired and was not used by other
+//! evidence. The nonce is used once the evidence verifies, so an attester
+//! can retry with the same nonce after a failed verification.
+//!
+//! ```json
+//! "nonces": { "ttl_secs": 300, "store": "LocalFs" }
+//! ```
+//!
+//! The nonces are kept in memory with the `InMemory` store, the default, so
+//! each AS instance only accepts its own. With `LocalFs`, they are kept in
+//! a sled database in the work dir, and survive restarts. With `Redis`,
+//! they are shared by the replicas of the AS, see [`redis`]. Other stores
+//! implement [`NonceStore`]. Expired nonces are pruned as nonces are
+//! issued, at most every [`PRUNE_INTERVAL_SECS`], and by the maintenance,
+//! see [`crate::maintenance`].
+//!
+//! Challenges need no authentication, so an AS instance keeps at most
+//! `max_outstanding` nonces that have not expired, and fails the
+//! challenges beyond.
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Mutex;
+
+pub mod redis;
+
+use self::redis::{Redis, RedisConfig};
+
+/// Directory of the `LocalFs` store in the work dir.
+const NONCES_DIR: &str = "nonces";
+
+const DEFAULT_TTL_SECS: u64 = 300;
+
+const DEFAULT_MAX_OUTSTANDING: usize = 100_000;
+
+/// Seconds between two prunings of the expired nonces by the challenges.
+pub const PRUNE_INTERVAL_SECS: i64 = 60;
+
+fn default_ttl_secs() -> u64 {
+    DEFAULT_TTL_SECS
+}
+
+fn default_max_outstanding() -> usize {
+    DEFAULT_MAX_OUTSTANDING
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NonceStoreType {
+    #[default]
+    InMemory,
+    LocalFs,
+    Redis,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NonceConfig {
+    /// How long a nonce can be used after it is issued, in seconds.
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+
+    /// Where the issued nonces are kept.
+    #[serde(default)]
+    pub store: NonceStoreType,
+
+    /// Nonces issued by this instance that have not expired, beyond which
+    /// challenges fail.
+    #[serde(default = "default_max_outstanding")]
+    pub max_outstanding: usize,
+
+    /// The Redis server of the `Redis` store.
+    #[serde(default)]
+    pub redis: Option<RedisConfig>,
+}
+
+impl Default for NonceConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: DEFAULT_TTL_SECS,
+            store: NonceStoreType::default(),
+            max_outstanding: DEFAULT_MAX_OUTSTANDING,
+            redis: None,
+        }
+    }
+}
+
+/// A nonce issued by the AS.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IssuedNonce {
+    /// Unix time the nonce expires at.
+    pub expiry: i64,
+    /// Whether verified evidence bound the nonce.
+    pub used: bool,
+}
+
+/// Storage of the issued nonces.
+#[async_trait]
+pub trait NonceStore {
+    /// Keep an issued `nonce`.
+    async fn insert(&self, nonce: &str, issued: IssuedNonce) -> Result<()>;
+
+    /// The issued `nonce`, if it is kept.
+    async fn get(&self, nonce: &str) -> Result<Option<IssuedNonce>>;
+
+    /// Mark `nonce` used, and return it as it was before, if it is kept.
+    /// Concurrent calls for the same nonce see it used by all but one.
+    async fn mark_used(&self, nonce: &str) -> Result<Option<IssuedNonce>>;
+
+    /// Remove the nonces expired at `now`, a Unix time, and return how
+    /// many.
+    async fn prune(&self, now: i64) -> Result<usize>;
+}
+
+#[derive(Default)]
+pub struct InMemory {
+    nonces: Mutex<HashMap<String, IssuedNonce>>,
+}
+
+impl InMemory {
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, IssuedNonce>>> {
+        self.nonces
+            .lock()
+            .map_err(|_| anyhow!("Nonce store is poisoned"))
+    }
+}
+
+#[async_trait]
+impl NonceStore for InMemory {
+    async fn insert(&self, nonce: &str, issued: IssuedNonce) -> Result<()> {
+        self.lock()?.insert(nonce.to_string(), issued);
+        Ok(())
+    }
+
+    async fn get(&self, nonce: &str) -> Result<Option<IssuedNonce>> {
+        Ok(self.lock()?.get(nonce).copied())
+    }
+
+    async fn mark_used(&self, nonce: &str) -> Result<Option<IssuedNonce>> {
+        Ok(self.lock()?.get_mut(nonce).map(|issued| {
+            let before = *issued;
+            issued.used = true;
+            before
+        }))
+    }
+
+    async fn prune(&self, now: i64) -> Result<usize> {
+        let mut nonces = self.lock()?;
+        let before = nonces.len();
+        nonces.retain(|_, issued| issued.expiry > now);
+        Ok(before - nonces.len())
+    }
+}
+
+/// Nonces in a sled database.
+pub struct LocalFs {
+    engine: sled::Db,
+}
+
+impl LocalFs {
+    pub fn new(path: &Path) -> Result<Self> {
+        let engine = sled::open(path)
+            .with_context(|| format!("Cannot open the nonce store {}", path.display()))?;
+        Ok(Self { engine })
+    }
+}
+
+#[async_trait]
+impl NonceStore for LocalFs {
+    /// Sled flushes the issued nonces in the background: a nonce lost in a
+    /// crash only has to be challenged again, while uses are flushed right
+    /// away so that no nonce is used twice.
+    async fn insert(&self, nonce: &str, issued: IssuedNonce) -> Result<()> {
+        self.engine
+            .insert(nonce, serde_json::to_vec(&issued)?)
+            .context("insert into sled")?;
+        Ok(())
+    }
+
+    async fn get(&self, nonce: &str) -> Result<Option<IssuedNonce>> {
+        match self.engine.get(nonce).context("read from sled")? {
+            Some(issued) => Ok(Some(serde_json::from_slice(&issued)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn mark_used(&self, nonce: &str) -> Result<Option<IssuedNonce>> {
+        let before = self
+            .engine
+            .fetch_and_update(nonce, |issued| {
+                let mut issued: IssuedNonce = serde_json::from_slice(issued?).ok()?;
+                issued.used = true;
+                serde_json::to_vec(&issued).ok()
+            })
+            .context("update sled")?;
+        self.engine.flush()?;
+        match before {
+            Some(issued) => Ok(Some(serde_json::from_slice(&issued)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn prune(&self, now: i64) -> Result<usize> {
+        let mut pruned = 0;
+        for entry in self.engine.iter() {
+            let (nonce, issued) = entry.context("read from sled")?;
+            let issued: IssuedNonce = serde_json::from_slice(&issued)?;
+            if issued.expiry <= now {
+                self.engine.remove(nonce).context("remove from sled")?;
+                pruned += 1;
+            }
+        }
+        self.engine.flush()?;
+        Ok(pruned)
+    }
+}
+
+/// The nonces issued by the AS.
+pub(crate) struct Nonces {
+    config: NonceConfig,
+    store: Box<dyn NonceStore + Send + Sync>,
+    /// Expiry of the nonces issued by this instance that have not expired,
+    /// oldest first.
+    outstanding: Mutex<VecDeque<i64>>,
+    /// Unix time of the next pruning by a challenge.
+    next_prune: AtomicI64,
+}
+
+impl Nonces {
+    pub fn new(config: NonceConfig, work_dir: &Path) -> Result<Self> {
+        let store: Box<dyn NonceStore + Send + Sync> = match config.store {
+            NonceStoreType::InMemory => Box::<InMemory>::default(),
+            NonceStoreType::LocalFs => Box::new(LocalFs::new(&work_dir.join(NONCES_DIR))?),
+            NonceStoreType::Redis => Box::new(Redis::new(
+                config
+                    .redis
+                    .clone()
+                    .context("The Redis nonce store needs a redis server")?,
+            )?),
+        };
+        Ok(Self {
+            config,
+            store,
+            outstanding: Mutex::default(),
+            next_prune: AtomicI64::new(0),
+        })
+    }
+
+    /// Keep an issued `nonce`, and return the Unix time it expires at.
+    pub async fn issue(&self, nonce: &str) -> Result<i64> {
+        let now = Utc::now().timestamp();
+        let expiry = now + self.config.ttl_secs as i64;
+        {
+            let mut outstanding = self
+                .outstanding
+                .lock()
+                .map_err(|_| anyhow!("Outstanding nonces are poisoned"))?;
+            while outstanding.front().is_some_and(|expiry| *expiry <= now) {
+                outstanding.pop_front();
+            }
+            if outstanding.len() >= self.config.max_outstanding {
+                bail!(
+                    "{} nonces are outstanding, try again later",
+                    outstanding.len()
+                );
+            }
+            outstanding.push_back(expiry);
+        }
+
+        let next_prune = self.next_prune.load(Ordering::Relaxed);
+        if now >= next_prune
+            && self
+                .next_prune
+                .compare_exchange(
+                    next_prune,
+                    now + PRUNE_INTERVAL_SECS,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+        {
+            self.store
+                .prune(now)
+                .await
+                .context("Cannot prune the expired nonces")?;
+        }
+
+        self.store
+            .insert(
+                nonce,
+                IssuedNonce {
+                    expiry,
+                    used: false,
+                },
+            )
+            .await?;
+        Ok(expiry)
+    }
+
+    /// Check that `nonce` was issued, has not expired and was not used.
+    pub async fn check(&self, nonce: &str) -> Result<()> {
+        check(self.store.get(nonce).await?, Utc::now().timestamp())
+    }
+
+    /// Use `nonce`, once evidence that binds it verified.
+    pub async fn consume(&self, nonce: &str) -> Result<()> {
+        check(self.store.mark_used(nonce).await?, Utc::now().timestamp())
+    }
+
+    /// Remove the expired nonces, and return how many.
+    pub async fn prune(&self) -> Result<usize> {
+        self.store.prune(Utc::now().timestamp()).await
+    }
+}
+
+fn check(issued: Option<IssuedNonce>, now: i64) -> Result<()> {
+    match issued {
+        None => bail!("The nonce was not issued by the AS"),
+        Some(issued) if issued.expiry <= now => {
+            bail!("The nonce expired {}s ago", now - issued.expiry)
+        }
+        Some(issued) if issued.used => bail!("The nonce was used by other evidence"),
+        Some(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(NonceStoreType::InMemory)]
+    #[case(NonceStoreType::LocalFs)]
+    #[tokio::test]
+    async fn test_nonces(#[case] store: NonceStoreType) {
+        let work_dir = tempfile::tempdir().unwrap();
+        let nonces = Nonces::new(
+            NonceConfig {
+                ttl_secs: 300,
+                store,
+                max_outstanding: 2,
+                redis: None,
+            },
+            work_dir.path(),
+        )
+        .unwrap();
+
+        let expiry = nonces.issue("abc").await.unwrap();
+        assert!(expiry > Utc::now().timestamp());
+        nonces.check("abc").await.unwrap();
+        nonces.consume("abc").await.unwrap();
+        let e = nonces.consume("abc").await.unwrap_err();
+        assert_eq!(e.to_string(), "The nonce was used by other evidence");
+        assert!(nonces.check("abc").await.is_err());
+        let e = nonces.check("other").await.unwrap_err();
+        assert_eq!(e.to_string(), "The nonce was not issued by the AS");
+
+        let expired = IssuedNonce {
+            expiry: Utc::now().timestamp() - 10,
+            used: false,
+        };
+        nonces.store.insert("expired", expired).await.unwrap();
+        assert!(nonces
+            .check("expired")
+            .await
+            .unwrap_err()
+            .to_string()
+            .starts_with("The nonce expired"));
+        assert_eq!(nonces.prune().await.unwrap(), 1);
+        assert_eq!(nonces.store.get("expired").await.unwrap(), None);
+        assert!(nonces.store.get("abc").await.unwrap().unwrap().used);
+
+        // Challenges prune the expired nonces, at most every interval.
+        nonces.store.insert("expired", expired).await.unwrap();
+        nonces.next_prune.store(0, Ordering::Relaxed);
+        nonces.issue("def").await.unwrap();
+        assert_eq!(nonces.store.get("expired").await.unwrap(), None);
+        assert!(nonces.next_prune.load(Ordering::Relaxed) > Utc::now().timestamp());
+
+        let e = nonces.issue("ghi").await.unwrap_err();
+        assert_eq!(e.to_string(), "2 nonces are outstanding, try again later");
+        assert_eq!(nonces.store.get("ghi").await.unwrap(), None);
+        nonces
+            .outstanding
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .for_each(|expiry| *expiry = Utc::now().timestamp());
+        nonces.issue("ghi").await.unwrap();
+    }
+}
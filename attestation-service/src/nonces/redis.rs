@@ -0,0 +1,252 @@
+//! Nonces in a Redis server shared by the AS replicas.
+//!
+//! Behind a load balancer, an attester may get its challenge from one AS
+//! replica and present its evidence to another. With the `Redis` store,
+//! every replica keeps the nonces it issues in the same Redis server, as
+//! keys expiring with the nonces, so that any replica accepts them and a
+//! nonce is used once across all of them:
+//!
+//! ```json
+//! "nonces": {
+//!     "ttl_secs": 300,
+//!     "store": "Redis",
+//!     "redis": {
+//!         "url": "rediss://redis.attestation.svc:6379",
+//!         "password_file": "/etc/attestation-service/redis-password"
+//!     }
+//! }
+//! ```
+//!
+//! Nonces are marked used with a single `SET ... GET` command, so two
+//! replicas cannot both use the same nonce. This needs Redis 6.2 or later.
+//! With a `rediss://` URL, the connection is over TLS, the server
+//! certificate checked against the system roots; with `redis://`, Redis
+//! should be reached over a private network.
+
+use super::{IssuedNonce, NonceStore};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::{Client, IntoConnectionInfo};
+use serde::Deserialize;
+use std::fs;
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+/// Longest wait for the Redis server to connect or answer.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+const DEFAULT_KEY_PREFIX: &str = "attestation-service:nonce:";
+
+fn default_key_prefix() -> String {
+    DEFAULT_KEY_PREFIX.to_string()
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RedisConfig {
+    /// URL of the Redis server, `redis://<host>:<port>`, or
+    /// `rediss://<host>:<port>` over TLS.
+    pub url: String,
+
+    /// File of the password of the Redis server, if it requires one.
+    #[serde(default)]
+    pub password_file: Option<PathBuf>,
+
+    /// Prefix of the keys of the nonces, to share the server with other
+    /// applications or AS clusters.
+    #[serde(default = "default_key_prefix")]
+    pub key_prefix: String,
+}
+
+pub struct Redis {
+    config: RedisConfig,
+    client: Client,
+    /// Connected on first use, and connected again by the manager when
+    /// the connection fails, e.g. on a restart of the server.
+    connection: OnceCell<ConnectionManager>,
+}
+
+impl Redis {
+    pub fn new(config: RedisConfig) -> Result<Self> {
+        let mut info = config
+            .url
+            .as_str()
+            .into_connection_info()
+            .with_context(|| format!("Invalid Redis URL {}", config.url))?;
+        if let Some(path) = &config.password_file {
+            let password = fs::read_to_string(path)
+                .with_context(|| format!("Cannot read {}", path.display()))?;
+            info.redis.password = Some(password.trim_end().to_string());
+        }
+        let client = Client::open(info)?;
+        Ok(Self {
+            config,
+            client,
+            connection: OnceCell::new(),
+        })
+    }
+
+    /// Run `command` on the connection, within [`TIMEOUT`].
+    async fn run<T, F>(&self, command: impl FnOnce(ConnectionManager) -> F) -> Result<T>
+    where
+        F: Future<Output = redis::RedisResult<T>>,
+    {
+        let redis = async {
+            let connection = self
+                .connection
+                .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+                .await
+                .with_context(|| format!("Cannot connect to Redis at {}", self.config.url))?;
+            Ok::<T, anyhow::Error>(command(connection.clone()).await?)
+        };
+        tokio::time::timeout(TIMEOUT, redis)
+            .await
+            .with_context(|| format!("Redis at {} did not answer", self.config.url))?
+    }
+
+    fn key(&self, nonce: &str) -> String {
+        format!("{}{nonce}", self.config.key_prefix)
+    }
+}
+
+fn issued(value: Option<Vec<u8>>) -> Result<Option<IssuedNonce>> {
+    value
+        .map(|issued| serde_json::from_slice(&issued))
+        .transpose()
+        .context("Malformed nonce in Redis")
+}
+
+#[async_trait]
+impl NonceStore for Redis {
+    async fn insert(&self, nonce: &str, issued: IssuedNonce) -> Result<()> {
+        let command = redis::cmd("SET")
+            .arg(self.key(nonce))
+            .arg(serde_json::to_vec(&issued)?)
+            .arg("EXAT")
+            .arg(issued.expiry)
+            .clone();
+        self.run(|mut connection| async move { command.query_async(&mut connection).await })
+            .await
+    }
+
+    async fn get(&self, nonce: &str) -> Result<Option<IssuedNonce>> {
+        let command = redis::cmd("GET").arg(self.key(nonce)).clone();
+        issued(
+            self.run(|mut connection| async move { command.query_async(&mut connection).await })
+                .await?,
+        )
+    }
+
+    async fn mark_used(&self, nonce: &str) -> Result<Option<IssuedNonce>> {
+        let Some(before) = self.get(nonce).await? else {
+            return Ok(None);
+        };
+        let used = serde_json::to_vec(&IssuedNonce {
+            used: true,
+            ..before
+        })?;
+        // Of concurrent replicas, only the first gets the nonce unused
+        // back.
+        let command = redis::cmd("SET")
+            .arg(self.key(nonce))
+            .arg(used)
+            .arg("XX")
+            .arg("KEEPTTL")
+            .arg("GET")
+            .clone();
+        issued(
+            self.run(|mut connection| async move { command.query_async(&mut connection).await })
+                .await?,
+        )
+    }
+
+    /// Redis expires the nonces itself.
+    async fn prune(&self, _now: i64) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Encode a command in the Redis serialization protocol.
+    fn encode(args: &[&[u8]]) -> Vec<u8> {
+        let mut command = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            command.extend(format!("${}\r\n", arg.len()).as_bytes());
+            command.extend(*arg);
+            command.extend(b"\r\n");
+        }
+        command
+    }
+
+    #[test]
+    fn test_url() {
+        let config = |url: &str| RedisConfig {
+            url: url.to_string(),
+            password_file: None,
+            key_prefix: DEFAULT_KEY_PREFIX.to_string(),
+        };
+        Redis::new(config("redis://127.0.0.1:6379")).unwrap();
+        Redis::new(config("rediss://redis.attestation.svc:6379")).unwrap();
+        assert!(Redis::new(config("127.0.0.1:6379")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_redis() {
+        let issued = IssuedNonce {
+            expiry: 4102444800,
+            used: false,
+        };
+        let value = serde_json::to_string(&issued).unwrap();
+        let used = serde_json::to_string(&IssuedNonce {
+            used: true,
+            ..issued
+        })
+        .unwrap();
+
+        // A server answering the expected commands in turn.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let exchanges = vec![
+            (
+                encode(&[b"SET", b"n:abc", value.as_bytes(), b"EXAT", b"4102444800"]),
+                "+OK\r\n".to_string(),
+            ),
+            (
+                encode(&[b"GET", b"n:abc"]),
+                format!("${}\r\n{value}\r\n", value.len()),
+            ),
+            (
+                encode(&[b"SET", b"n:abc", used.as_bytes(), b"XX", b"KEEPTTL", b"GET"]),
+                format!("${}\r\n{value}\r\n", value.len()),
+            ),
+            (encode(&[b"GET", b"n:other"]), "$-1\r\n".to_string()),
+        ];
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            for (command, reply) in exchanges {
+                let mut received = vec![0; command.len()];
+                stream.read_exact(&mut received).await.unwrap();
+                assert_eq!(received, command);
+                stream.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+
+        let redis = Redis::new(RedisConfig {
+            url: format!("redis://{address}"),
+            password_file: None,
+            key_prefix: "n:".to_string(),
+        })
+        .unwrap();
+        redis.insert("abc", issued).await.unwrap();
+        assert_eq!(redis.mark_used("abc").await.unwrap(), Some(issued));
+        assert_eq!(redis.get("other").await.unwrap(), None);
+        server.await.unwrap();
+    }
+}
@@ -1,13 +1,22 @@
-use anyhow::Result;
-use as_types::SetPolicyInput;
+use crate::token::chain;
+use anyhow::{bail, Result};
+use as_types::{
+    PolicyData, PolicyParameter, PolicySelector, PolicyTestReport, PolicyViolation,
+    SetPolicyDataInput, SetPolicyInput, TestPolicyInput,
+};
 use async_trait::async_trait;
-use serde::Deserialize;
-use std::collections::HashMap;
+use kbs_types::Tee;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::path::Path;
 
 pub mod opa;
+pub mod shadow;
+pub mod signing;
 
-#[derive(Debug, EnumString, Deserialize)]
+#[derive(Debug, EnumString, EnumVariantNames, Deserialize)]
 #[strum(ascii_case_insensitive)]
 pub enum PolicyEngineType {
     OPA,
@@ -29,14 +38,621 @@ impl PolicyEngineType {
     }
 }
 
+/// A default policy of the AS config, evaluated for the TEEs and tenants it
+/// applies to when the attestation request names no policy.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DefaultPolicy {
+    pub policy_id: String,
+
+    /// Further policies evaluated along `policy_id`. The evidence must
+    /// comply with all of them.
+    #[serde(default)]
+    pub policy_ids: Vec<String>,
+
+    /// TEEs the policy applies to, every TEE if empty.
+    #[serde(default)]
+    pub tees: Vec<Tee>,
+
+    /// Tenants the policy applies to, every tenant if empty.
+    #[serde(default)]
+    pub tenants: Vec<String>,
+}
+
+impl DefaultPolicy {
+    fn applies_to(&self, tee: &Tee, tenant: Option<&str>) -> bool {
+        let name = serde_variant::to_variant_name(tee).ok();
+        let tee_applies = self.tees.is_empty()
+            || self
+                .tees
+                .iter()
+                .any(|t| serde_variant::to_variant_name(t).ok() == name);
+        let tenant_applies = self.tenants.is_empty()
+            || tenant.is_some_and(|tenant| self.tenants.iter().any(|t| t == tenant));
+        tee_applies && tenant_applies
+    }
+
+    /// Whether the policy applies to every request.
+    pub fn applies_to_all(&self) -> bool {
+        self.tees.is_empty() && self.tenants.is_empty()
+    }
+
+    /// The IDs of the policies evaluated, `policy_id` first.
+    pub fn evaluated_policies(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.policy_id.as_str()).chain(self.policy_ids.iter().map(String::as_str))
+    }
+}
+
+/// The claims of the flattened `claims` that match any of `patterns`, as
+/// the input of a policy that only needs them, see
+/// `policy_input_claims` of the AS config.
+pub(crate) fn select_claims(claims: &serde_json::Value, patterns: &[String]) -> serde_json::Value {
+    let empty = serde_json::Map::new();
+    claims
+        .as_object()
+        .unwrap_or(&empty)
+        .iter()
+        .filter(|(name, _)| patterns.iter().any(|pattern| chain::matches(pattern, name)))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// The IDs of the policies of the first of the `policies` that applies to
+/// `tee` and `tenant`, `[None]` for the `default` policy of the policy
+/// engine if none does.
+pub fn select_default_policies(
+    policies: &[DefaultPolicy],
+    tee: &Tee,
+    tenant: Option<&str>,
+) -> Vec<Option<String>> {
+    match policies
+        .iter()
+        .find(|policy| policy.applies_to(tee, tenant))
+    {
+        Some(policy) => policy
+            .evaluated_policies()
+            .map(|id| Some(id.to_string()))
+            .collect(),
+        None => vec![None],
+    }
+}
+
+/// Outcome of the evaluation of one of the policies of an attestation, in
+/// the `policies` claim of the token.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PolicyResult {
+    pub policy_id: String,
+    /// Digest of the text of the policy, see [`policy_digest`].
+    pub policy_digest: String,
+    pub allow: bool,
+}
+
+/// Outcome of [`PolicyEngine::evaluate`] when the policy admits the claims.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyEvaluation {
+    /// Evaluation report of the policy engine.
+    pub report: String,
+    /// Digest of the text of the policy evaluated, see [`policy_digest`].
+    pub policy_digest: String,
+}
+
+/// Combine the evaluations of the policies of an attestation, by policy
+/// ID: the evaluation of the first policy and the result of each if they
+/// all admit the claims. Otherwise the first error that is not a denial,
+/// or a [`PolicyDenied`] with the violations of every policy that denied
+/// the claims.
+pub(crate) fn combine_evaluations(
+    evaluations: Vec<(String, Result<PolicyEvaluation>)>,
+) -> Result<(PolicyEvaluation, Vec<PolicyResult>)> {
+    let evaluated = evaluations.len();
+    let mut results = Vec::new();
+    let mut admitted = None;
+    let mut denied = Vec::new();
+    let mut violations = Vec::new();
+    for (policy_id, evaluation) in evaluations {
+        match evaluation {
+            Ok(evaluation) => {
+                results.push(PolicyResult {
+                    policy_id,
+                    policy_digest: evaluation.policy_digest.clone(),
+                    allow: true,
+                });
+                admitted.get_or_insert(evaluation);
+            }
+            Err(e) => match e.downcast::<PolicyDenied>() {
+                Ok(denial) => {
+                    violations.extend(denial.violations);
+                    denied.push(policy_id);
+                }
+                Err(e) => return Err(e),
+            },
+        }
+    }
+    if !denied.is_empty() {
+        let denial = anyhow::Error::new(PolicyDenied { violations });
+        return Err(match evaluated {
+            1 => denial,
+            _ => denial.context(format!("Denied by the policies {}", denied.join(", "))),
+        });
+    }
+    let evaluation = admitted.ok_or_else(|| anyhow::anyhow!("No policy was evaluated"))?;
+    Ok((evaluation, results))
+}
+
+/// Hex encoded SHA-256 of the text of a policy, by which policies are kept
+/// for audits, see [`PolicyEngine::get_policy_by_digest`].
+pub fn policy_digest(policy: &[u8]) -> String {
+    hex::encode(Sha256::digest(policy))
+}
+
+/// Error of [`PolicyEngine::evaluate`] when the policy denies the claims.
+/// It carries the violations the policy engine could explain, so that
+/// callers can downcast to it and report them to the attester.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PolicyDenied {
+    pub violations: Vec<PolicyViolation>,
+}
+
+impl fmt::Display for PolicyDenied {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Untrusted TEE evidence")?;
+        for violation in &self.violations {
+            write!(f, "; {}", violation.rule)?;
+            if let Some(claim) = &violation.claim {
+                write!(f, " ({claim}")?;
+                if let Some(value) = &violation.value {
+                    write!(f, " = {value}")?;
+                }
+                write!(f, ")")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PolicyDenied {}
+
+/// Error of [`PolicyEngine::evaluate`] when the selector of the policy
+/// does not match the evidence, which was not evaluated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyMismatch {
+    pub policy_id: String,
+    pub reason: String,
+}
+
+impl fmt::Display for PolicyMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Policy {} does not apply to the evidence: {}",
+            self.policy_id, self.reason
+        )
+    }
+}
+
+impl std::error::Error for PolicyMismatch {}
+
+/// Check that the evidence of `tee` with the flattened `claims` matches
+/// the `selector` of the policy `policy_id`.
+pub(crate) fn check_selector(
+    policy_id: &str,
+    selector: &PolicySelector,
+    tee: &str,
+    claims: &serde_json::Map<String, serde_json::Value>,
+) -> std::result::Result<(), PolicyMismatch> {
+    let mismatch = |reason: String| PolicyMismatch {
+        policy_id: policy_id.to_string(),
+        reason,
+    };
+    if !selector.tees.is_empty() && !selector.tees.iter().any(|t| t == tee) {
+        return Err(mismatch(format!(
+            "it applies to {} evidence, not {tee}",
+            selector.tees.join(", ")
+        )));
+    }
+    let missing: Vec<&str> = selector
+        .claims
+        .iter()
+        .filter(|name| !claims.keys().any(|claim| chain::matches(name, claim)))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        return Err(mismatch(format!(
+            "the evidence has no {} claims",
+            missing.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// The values supplied for the parameters of the policies of an
+/// attestation, see [`resolve_parameters`].
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ParameterValues {
+    /// Values configured for the tenant of the request.
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub configured: serde_json::Map<String, serde_json::Value>,
+    /// Values of the attestation request, only taken for the parameters
+    /// declared `from_request`.
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub requested: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ParameterValues {
+    /// Values configured for the tenant, and none of the request.
+    pub fn configured(configured: serde_json::Map<String, serde_json::Value>) -> Self {
+        Self {
+            configured,
+            ..Default::default()
+        }
+    }
+}
+
+/// The values of the `declared` parameters of the policy `policy_id`: the
+/// configured one, else the default, else for the parameters declared
+/// `from_request` the requested one. The request cannot supply the other
+/// parameters, nor replace a configured value or a default, as it comes
+/// from the attester being appraised. Values the policy does not declare
+/// are left out.
+pub(crate) fn resolve_parameters(
+    policy_id: &str,
+    declared: &[PolicyParameter],
+    values: &ParameterValues,
+) -> Result<serde_json::Map<String, serde_json::Value>> {
+    let mut parameters = serde_json::Map::new();
+    for parameter in declared {
+        let requested = values.requested.get(&parameter.name);
+        if requested.is_some() && !parameter.from_request {
+            bail!(
+                "Parameter {} of policy {policy_id} cannot be set by the request",
+                parameter.name
+            );
+        }
+        let Some(value) = values
+            .configured
+            .get(&parameter.name)
+            .or(parameter.default.as_ref())
+            .or(requested)
+        else {
+            bail!(
+                "Policy {policy_id} requires the parameter {}",
+                parameter.name
+            );
+        };
+        if !parameter.r#type.accepts(value) {
+            bail!(
+                "Parameter {} of policy {policy_id} must be a {:?}, not {value}",
+                parameter.name,
+                parameter.r#type
+            );
+        }
+        parameters.insert(parameter.name.clone(), value.clone());
+    }
+    Ok(parameters)
+}
+
+/// A policy that was set for a policy ID, see
+/// [`PolicyEngine::policy_revisions`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PolicyRevision {
+    /// Digest of the text of the policy, see [`policy_digest`].
+    pub digest: String,
+    /// Unix time the policy was set at.
+    pub set_at: i64,
+}
+
+/// Check that `policy_id` can name a policy: policies are stored by ID, so
+/// it must not be a path.
+pub(crate) fn check_policy_id(policy_id: &str) -> Result<()> {
+    if policy_id.is_empty() || policy_id.contains(['/', '\\']) || policy_id.starts_with('.') {
+        bail!("Invalid policy ID `{policy_id}`");
+    }
+    Ok(())
+}
+
+/// The policies and policy data documents of a policy engine, for backups.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PolicyEngineState {
+    /// Policies by ID.
+    pub policies: BTreeMap<String, String>,
+    /// Every version of every policy data document.
+    pub policy_data: Vec<PolicyData>,
+    /// Selectors of the policies that have one, by policy ID.
+    #[serde(default)]
+    pub selectors: BTreeMap<String, PolicySelector>,
+    /// Every policy text ever set or evaluated, by digest.
+    #[serde(default)]
+    pub policy_history: BTreeMap<String, String>,
+    /// Parameters of the policies that declare some, by policy ID.
+    #[serde(default)]
+    pub parameters: BTreeMap<String, Vec<PolicyParameter>>,
+    /// Revisions of the policies, by policy ID, including those deleted.
+    #[serde(default)]
+    pub revisions: BTreeMap<String, Vec<PolicyRevision>>,
+    /// Signatures of the policies by the admin key, by policy digest, see
+    /// [`signing`].
+    #[serde(default)]
+    pub signatures: BTreeMap<String, String>,
+}
+
 #[async_trait]
 pub trait PolicyEngine {
+    /// Evaluate the claims in `input` of the evidence of `tee`, with the
+    /// values of the `parameters` the policy declares, see
+    /// [`resolve_parameters`]. A denial is returned
+    /// as a [`PolicyDenied`] error, and evidence that the selector of the
+    /// policy does not match as a [`PolicyMismatch`] error.
     async fn evaluate(
         &self,
+        tee: &str,
         reference_data_map: HashMap<String, Vec<String>>,
         input: String,
         policy_id: Option<String>,
-    ) -> Result<String>;
+        parameters: &ParameterValues,
+    ) -> Result<PolicyEvaluation>;
 
+    /// Set the policy `input.policy_id`, as a new revision of it.
     async fn set_policy(&mut self, input: SetPolicyInput) -> Result<()>;
+
+    /// Get the text of a policy that was set or evaluated, even if it was
+    /// replaced since, by its [`policy_digest`].
+    async fn get_policy_by_digest(&self, digest: &str) -> Result<String>;
+
+    /// Get the [`policy_digest`] of each policy, by policy ID.
+    async fn list_policies(&self) -> Result<BTreeMap<String, String>>;
+
+    /// Get the text of the policy `policy_id`.
+    async fn get_policy(&self, policy_id: &str) -> Result<String>;
+
+    /// Remove the policy `policy_id`, with its selector and parameters. Its
+    /// revisions are kept, so that it can be rolled back. The `default`
+    /// policy cannot be removed.
+    async fn delete_policy(&mut self, policy_id: &str) -> Result<()>;
+
+    /// Get the revisions of the policy `policy_id`, oldest first.
+    async fn policy_revisions(&self, policy_id: &str) -> Result<Vec<PolicyRevision>>;
+
+    /// Set the policy `policy_id` back to its revision of `digest`, as a
+    /// new revision. Its selector and parameters are left as they are.
+    async fn rollback_policy(&mut self, policy_id: &str, digest: &str) -> Result<()>;
+
+    /// Run the given policy against a suite of claims fixtures and report
+    /// which fixtures got their expected decision. The policy is not stored.
+    async fn test_policy(&self, input: TestPolicyInput) -> Result<PolicyTestReport>;
+
+    /// Store a new version of a data document, that policies read at
+    /// `data.custom.<name>`. Policies always see the latest version.
+    async fn set_policy_data(&mut self, input: SetPolicyDataInput) -> Result<u64>;
+
+    /// Get a version of a data document, or its latest version if `version`
+    /// is `None`.
+    async fn get_policy_data(&self, name: &str, version: Option<u64>) -> Result<PolicyData>;
+
+    /// Get all the policies and policy data documents.
+    async fn export_state(&self) -> Result<PolicyEngineState>;
+
+    /// Store the policies and policy data documents of `state`, replacing
+    /// those with the same IDs, names and versions.
+    async fn import_state(&mut self, state: PolicyEngineState) -> Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_default_policies() {
+        let policies: Vec<DefaultPolicy> = serde_json::from_str(
+            r#"[
+                {"policy_id": "tdx-acme", "policy_ids": ["acme"], "tees": ["tdx"], "tenants": ["acme"]},
+                {"policy_id": "tdx", "policy_ids": ["cves"], "tees": ["tdx"]},
+                {"policy_id": "amd", "tees": ["snp", "azsnpvtpm"]},
+                {"policy_id": "fallback"}
+            ]"#,
+        )
+        .unwrap();
+        let ids = |ids: &[&str]| -> Vec<Option<String>> {
+            ids.iter().map(|id| Some(id.to_string())).collect()
+        };
+
+        assert_eq!(
+            select_default_policies(&policies, &Tee::Tdx, Some("acme")),
+            ids(&["tdx-acme", "acme"])
+        );
+        assert_eq!(
+            select_default_policies(&policies, &Tee::Tdx, Some("other")),
+            ids(&["tdx", "cves"])
+        );
+        assert_eq!(
+            select_default_policies(&policies, &Tee::Tdx, None),
+            ids(&["tdx", "cves"])
+        );
+        assert_eq!(
+            select_default_policies(&policies, &Tee::AzSnpVtpm, None),
+            ids(&["amd"])
+        );
+        assert_eq!(
+            select_default_policies(&policies, &Tee::Sgx, Some("acme")),
+            ids(&["fallback"])
+        );
+        assert_eq!(
+            select_default_policies(&policies[..3], &Tee::Sgx, None),
+            [None]
+        );
+        assert_eq!(select_default_policies(&[], &Tee::Tdx, None), [None]);
+    }
+
+    #[test]
+    fn test_combine_evaluations() {
+        let allow = |digest: &str| -> Result<PolicyEvaluation> {
+            Ok(PolicyEvaluation {
+                report: "{}".to_string(),
+                policy_digest: digest.to_string(),
+            })
+        };
+        let deny = |rule: &str| -> Result<PolicyEvaluation> {
+            Err(PolicyDenied {
+                violations: vec![PolicyViolation {
+                    rule: rule.to_string(),
+                    claim: None,
+                    value: None,
+                    expected: None,
+                    message: None,
+                }],
+            }
+            .into())
+        };
+
+        let (evaluation, results) = combine_evaluations(vec![
+            ("tdx".to_string(), allow("00")),
+            ("cves".to_string(), allow("ff")),
+        ])
+        .unwrap();
+        assert_eq!(evaluation.policy_digest, "00");
+        assert_eq!(
+            results,
+            [
+                PolicyResult {
+                    policy_id: "tdx".to_string(),
+                    policy_digest: "00".to_string(),
+                    allow: true,
+                },
+                PolicyResult {
+                    policy_id: "cves".to_string(),
+                    policy_digest: "ff".to_string(),
+                    allow: true,
+                },
+            ]
+        );
+
+        let e = combine_evaluations(vec![
+            ("tdx".to_string(), deny("mr_td")),
+            ("cves".to_string(), allow("ff")),
+            ("acme".to_string(), deny("owner")),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            format!("{e:#}"),
+            "Denied by the policies tdx, acme: Untrusted TEE evidence; mr_td; owner"
+        );
+        assert_eq!(
+            e.downcast_ref::<PolicyDenied>().unwrap().violations.len(),
+            2
+        );
+
+        let e = combine_evaluations(vec![("tdx".to_string(), deny("mr_td"))]).unwrap_err();
+        assert_eq!(e.to_string(), "Untrusted TEE evidence; mr_td");
+
+        let e = combine_evaluations(vec![
+            ("tdx".to_string(), deny("mr_td")),
+            (
+                "cves".to_string(),
+                Err(anyhow::anyhow!("Read OPA policy file failed")),
+            ),
+        ])
+        .unwrap_err();
+        assert_eq!(e.to_string(), "Read OPA policy file failed");
+    }
+
+    #[test]
+    fn test_select_claims() {
+        let claims = serde_json::json!({
+            "tdx.quote.body.mr_td": "705ee938",
+            "tdx.ccel.kernel": "5b7aa6572f649714",
+            "tdx.ccel.ima.0": "9f86d081",
+            "tcb_status": "UpToDate",
+        });
+        let patterns = ["tdx.quote.*".to_string(), "tcb_status".to_string()];
+        assert_eq!(
+            select_claims(&claims, &patterns),
+            serde_json::json!({
+                "tdx.quote.body.mr_td": "705ee938",
+                "tcb_status": "UpToDate",
+            })
+        );
+        assert_eq!(select_claims(&claims, &[]), serde_json::json!({}));
+    }
+
+    #[test]
+    fn test_resolve_parameters() {
+        let declared: Vec<PolicyParameter> = serde_json::from_value(serde_json::json!([
+            {"name": "allowed_mr_td", "type": "string_list"},
+            {"name": "min_svn", "type": "number", "default": 3},
+            {"name": "workload", "type": "string", "from_request": true},
+        ]))
+        .unwrap();
+        let values = |configured: serde_json::Value, requested: serde_json::Value| {
+            serde_json::from_value::<ParameterValues>(
+                serde_json::json!({"configured": configured, "requested": requested}),
+            )
+            .unwrap()
+        };
+        let resolve = |values: &ParameterValues| {
+            resolve_parameters("tdx", &declared, values).map(serde_json::Value::Object)
+        };
+        assert_eq!(
+            resolve(&values(
+                serde_json::json!({"allowed_mr_td": ["705ee938"], "other": true}),
+                serde_json::json!({"workload": "db", "other": true}),
+            ))
+            .unwrap(),
+            serde_json::json!({"allowed_mr_td": ["705ee938"], "min_svn": 3, "workload": "db"})
+        );
+        // The configured value wins over the requested one.
+        assert_eq!(
+            resolve(&values(
+                serde_json::json!({"allowed_mr_td": [], "workload": "web"}),
+                serde_json::json!({"workload": "db"}),
+            ))
+            .unwrap()["workload"],
+            "web"
+        );
+
+        // The attester cannot supply the parameters that decide what is
+        // trusted, even where the tenant sets none.
+        for requested in [
+            serde_json::json!({"allowed_mr_td": ["705ee938"], "workload": "db"}),
+            serde_json::json!({"min_svn": 0, "workload": "db"}),
+        ] {
+            let configured = serde_json::json!({"allowed_mr_td": []});
+            assert!(resolve(&values(configured, requested)).is_err());
+        }
+        let missing = values(serde_json::json!({}), serde_json::json!({"workload": "db"}));
+        assert!(resolve(&missing).is_err());
+        let mistyped = values(
+            serde_json::json!({"allowed_mr_td": "705ee938"}),
+            serde_json::json!({"workload": "db"}),
+        );
+        assert!(resolve(&mistyped).is_err());
+    }
+
+    #[test]
+    fn test_check_selector() {
+        let selector = PolicySelector {
+            tees: vec!["tdx".to_string()],
+            claims: vec!["tdx.quote.body.mr_td".to_string(), "tdx.ccel.*".to_string()],
+        };
+        let claims = serde_json::json!({
+            "tdx.quote.body.mr_td": "705ee938",
+            "tdx.ccel.kernel": "5b7aa6572f649714",
+        });
+        let claims = claims.as_object().unwrap();
+        assert!(check_selector("tdx", &selector, "tdx", claims).is_ok());
+        assert!(check_selector("tdx", &PolicySelector::default(), "snp", claims).is_ok());
+
+        assert_eq!(
+            check_selector("tdx", &selector, "snp", claims)
+                .unwrap_err()
+                .to_string(),
+            "Policy tdx does not apply to the evidence: it applies to tdx evidence, not snp"
+        );
+        let mut claims = claims.clone();
+        claims.remove("tdx.ccel.kernel");
+        assert_eq!(
+            check_selector("tdx", &selector, "tdx", &claims)
+                .unwrap_err()
+                .reason,
+            "the evidence has no tdx.ccel.* claims"
+        );
+    }
 }
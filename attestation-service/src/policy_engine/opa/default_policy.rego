@@ -8,6 +8,7 @@
 #	{
 #		"sample1": "112233",
 #		"sample2": "332211",
+#		"sample3": 3,
 #		...
 #	}
 #
@@ -25,6 +26,10 @@
 # If the default policy is used for verification, the reference meeting the above format
 # needs to be provided in the attestation request, otherwise the Attestation Service will
 # automatically generate a reference data meeting the above format.
+#
+# TDX evidence with an unsigned TD report in place of a quote, accepted with
+# `tdx_td_reports` in the AS config, is denied: only a custom policy can allow
+# its reduced assurance.
 package policy
 
 import future.keywords.every
@@ -32,6 +37,8 @@ import future.keywords.every
 default allow = false
 
 allow {
+	not reduced_assurance
+
 	every k, v in input {
 		# `judge_field`: Traverse each key value pair in the input and make policy judgments on it.
 		#
@@ -44,6 +51,34 @@ allow {
 	}
 }
 
+# `violations` explains a deny decision: one entry for each claim whose value
+# does not match its reference values.
+violations[violation] {
+	some k
+	v := input[k]
+	not judge_field(k, v)
+	violation := {
+		"rule": "judge_field",
+		"claim": k,
+		"value": v,
+		"expected": data.reference[k],
+	}
+}
+
+violations[violation] {
+	reduced_assurance
+	violation := {
+		"rule": "reduced_assurance",
+		"claim": "tdx.reduced_assurance",
+		"value": true,
+		"message": "an unsigned TD report is not allowed by the default policy",
+	}
+}
+
+reduced_assurance {
+	input["tdx.reduced_assurance"] == true
+}
+
 judge_field(input_key, input_value) {
 	has_key(data.reference, input_key)
 	reference_value := data.reference[input_key]
@@ -63,7 +98,7 @@ judge_field(input_key, input_value) {
 
 match_value(reference_value, input_value) {
 	not is_array(reference_value)
-	input_value == reference_value
+	equal_value(reference_value, input_value)
 }
 
 match_value(reference_value, input_value) {
@@ -85,7 +120,27 @@ array_include(reference_value_array, input_value) {
 array_include(reference_value_array, input_value) {
 	reference_value_array != []
 	some i
-	reference_value_array[i] == input_value
+	equal_value(reference_value_array[i], input_value)
+}
+
+equal_value(reference_value, input_value) {
+	input_value == reference_value
+}
+
+# Reference values are strings, while some claims (versions, SVNs...) are
+# typed as numbers. Compare those by their numeric value.
+equal_value(reference_value, input_value) {
+	is_number(input_value)
+	is_string(reference_value)
+	to_number(reference_value) == input_value
+}
+
+# Digest reference values can name their algorithm, like `sha384:<hex>`,
+# while SHA-384 digest claims are bare hex. Digests of other algorithms are
+# prefixed in both, and compared as they are.
+equal_value(reference_value, input_value) {
+	is_string(input_value)
+	concat("", ["sha384:", input_value]) == reference_value
 }
 
 has_key(m, k) {
@@ -1,22 +1,36 @@
-use crate::policy_engine::{PolicyEngine, PolicyType};
+use crate::policy_engine::{
+    check_policy_id, check_selector, policy_digest, resolve_parameters, ParameterValues,
+    PolicyDenied, PolicyEngine, PolicyEngineState, PolicyEvaluation, PolicyRevision, PolicyType,
+};
 use anyhow::{anyhow, bail, Result};
-use as_types::SetPolicyInput;
+use as_types::{
+    PolicyData, PolicyDecision, PolicyParameter, PolicySelector, PolicyTestReport,
+    PolicyTestResult, PolicyViolation, SetPolicyDataInput, SetPolicyInput, TestPolicyInput,
+};
 use async_trait::async_trait;
 use base64::Engine;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::CStr;
 use std::fs;
 use std::os::raw::c_char;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 // Link import cgo function
 #[link(name = "cgo")]
 extern "C" {
-    pub fn evaluateGo(policy: GoString, data: GoString, input: GoString) -> *mut c_char;
+    pub fn evaluateGo(
+        policy: GoString,
+        library: GoString,
+        data: GoString,
+        input: GoString,
+    ) -> *mut c_char;
 }
 
+/// Helper functions that policies can import, as `data.attestation.timing`.
+const TIMING_LIBRARY: &str = include_str!("timing.rego");
+
 /// String structure passed into cgo
 #[derive(Debug)]
 #[repr(C)]
@@ -28,6 +42,56 @@ pub struct GoString {
 #[derive(Debug)]
 pub struct OPA {
     policy_dir_path: PathBuf,
+    /// Latest version of every policy data document.
+    custom_data: HashMap<String, PolicyData>,
+}
+
+/// Data documents are referred to by policies as `data.custom.<name>`, so
+/// their names must be Rego identifiers.
+fn check_data_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        bail!("Invalid policy data name `{name}`: must be letters, digits and `_`, not starting with a digit");
+    }
+    Ok(())
+}
+
+/// Versions of a data document stored in `dir` as `<version>.json`, in
+/// ascending order.
+fn data_versions(dir: &Path) -> Result<Vec<u64>> {
+    let mut versions = Vec::new();
+    if !dir.exists() {
+        return Ok(versions);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            if let Some(version) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+            {
+                versions.push(version);
+            }
+        }
+    }
+    versions.sort_unstable();
+    Ok(versions)
+}
+
+fn read_data(dir: &Path, name: &str, version: u64) -> Result<PolicyData> {
+    let path = dir.join(format!("{version}.json"));
+    let data = serde_json::from_slice(&fs::read(&path)?)
+        .map_err(|e| anyhow!("Parse policy data {}: {e}", path.display()))?;
+    Ok(PolicyData {
+        name: name.to_string(),
+        version,
+        data,
+    })
 }
 
 impl OPA {
@@ -46,40 +110,246 @@ impl OPA {
                 .ok_or_else(|| anyhow!("Policy DirPath to string failed"))?,
         );
         default_policy_path.push("default.rego");
-        if !default_policy_path.as_path().exists() {
+        let default_policy_written = !default_policy_path.as_path().exists();
+        if default_policy_written {
             let policy = std::include_str!("default_policy.rego").to_string();
             fs::write(&default_policy_path, policy)?;
         }
 
-        Ok(Self { policy_dir_path })
+        let mut custom_data = HashMap::new();
+        let data_dir_path = policy_dir_path.join("data");
+        if data_dir_path.exists() {
+            for entry in fs::read_dir(&data_dir_path)? {
+                let dir = entry?.path();
+                let Some(name) = dir.file_name().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+                if check_data_name(name).is_err() {
+                    continue;
+                }
+                if let Some(version) = data_versions(&dir)?.last() {
+                    custom_data.insert(name.to_string(), read_data(&dir, name, *version)?);
+                }
+            }
+        }
+
+        let opa = Self {
+            policy_dir_path,
+            custom_data,
+        };
+        let digest = opa.record_policy(&fs::read(&default_policy_path)?)?;
+        if default_policy_written {
+            opa.record_revision("default", &digest)?;
+        }
+        Ok(opa)
     }
-}
 
-#[async_trait]
-impl PolicyEngine for OPA {
-    async fn evaluate(
+    fn policy_path(&self, policy_id: &str) -> PathBuf {
+        self.policy_dir_path.join(format!("{policy_id}.rego"))
+    }
+
+    fn data_dir(&self, name: &str) -> PathBuf {
+        self.policy_dir_path.join("data").join(name)
+    }
+
+    /// Policy texts are kept by digest in the history dir.
+    fn history_path(&self, digest: &str) -> Result<PathBuf> {
+        if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("Invalid policy digest `{digest}`");
+        }
+        Ok(self
+            .policy_dir_path
+            .join("history")
+            .join(format!("{}.rego", digest.to_ascii_lowercase())))
+    }
+
+    /// Keep `policy` in the history, and return its digest.
+    fn record_policy(&self, policy: &[u8]) -> Result<String> {
+        let digest = policy_digest(policy);
+        let path = self.history_path(&digest)?;
+        if !path.exists() {
+            fs::create_dir_all(self.policy_dir_path.join("history"))
+                .map_err(|e| anyhow!("Create policy history dir failed: {:?}", e))?;
+            fs::write(&path, policy)
+                .map_err(|e| anyhow!("Write policy history failed: {:?}", e))?;
+        }
+        Ok(digest)
+    }
+
+    /// The signatures of the policies by the admin key, see
+    /// [`super::signing`], are kept by the digest of the policy text they
+    /// sign in the signatures dir.
+    fn signature_path(&self, digest: &str) -> Result<PathBuf> {
+        // Checks the digest.
+        self.history_path(digest)?;
+        Ok(self
+            .policy_dir_path
+            .join("signatures")
+            .join(format!("{}.sig", digest.to_ascii_lowercase())))
+    }
+
+    fn record_signature(&self, digest: &str, signature: Option<&str>) -> Result<()> {
+        let Some(signature) = signature else {
+            return Ok(());
+        };
+        let path = self.signature_path(digest)?;
+        fs::create_dir_all(self.policy_dir_path.join("signatures"))
+            .map_err(|e| anyhow!("Create policy signatures dir failed: {:?}", e))?;
+        fs::write(path, signature).map_err(|e| anyhow!("Write policy signature failed: {:?}", e))
+    }
+
+    fn read_signature(&self, digest: &str) -> Result<Option<String>> {
+        let path = self.signature_path(digest)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(fs::read_to_string(path)?))
+    }
+
+    /// The revisions of each policy are kept in the revisions dir, so that
+    /// they outlive the policy.
+    fn revisions_path(&self, policy_id: &str) -> PathBuf {
+        self.policy_dir_path
+            .join("revisions")
+            .join(format!("{policy_id}.json"))
+    }
+
+    fn read_revisions(&self, policy_id: &str) -> Result<Vec<PolicyRevision>> {
+        let path = self.revisions_path(policy_id);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        serde_json::from_slice(&fs::read(path)?)
+            .map_err(|e| anyhow!("Read revisions of policy {policy_id} failed: {:?}", e))
+    }
+
+    fn write_revisions(&self, policy_id: &str, revisions: &[PolicyRevision]) -> Result<()> {
+        fs::create_dir_all(self.policy_dir_path.join("revisions"))
+            .map_err(|e| anyhow!("Create policy revisions dir failed: {:?}", e))?;
+        fs::write(
+            self.revisions_path(policy_id),
+            serde_json::to_vec(revisions)?,
+        )
+        .map_err(|e| anyhow!("Write policy revisions failed: {:?}", e))
+    }
+
+    /// Add the policy of `digest` to the revisions of `policy_id`, unless
+    /// it is the latest one.
+    fn record_revision(&self, policy_id: &str, digest: &str) -> Result<()> {
+        let mut revisions = self.read_revisions(policy_id)?;
+        if revisions
+            .last()
+            .is_some_and(|latest| latest.digest == digest)
+        {
+            return Ok(());
+        }
+        revisions.push(PolicyRevision {
+            digest: digest.to_string(),
+            set_at: chrono::Utc::now().timestamp(),
+        });
+        self.write_revisions(policy_id, &revisions)
+    }
+
+    /// The selector of a policy is stored next to it.
+    fn selector_path(&self, policy_id: &str) -> PathBuf {
+        self.policy_dir_path
+            .join(format!("{policy_id}.selector.json"))
+    }
+
+    fn read_selector(&self, policy_id: &str) -> Result<Option<PolicySelector>> {
+        let path = self.selector_path(policy_id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let selector = serde_json::from_slice(&fs::read(path)?)
+            .map_err(|e| anyhow!("Read selector of policy {policy_id} failed: {:?}", e))?;
+        Ok(Some(selector))
+    }
+
+    async fn write_selector(
         &self,
-        reference_data_map: HashMap<String, Vec<String>>,
-        input: String,
-        policy_id: Option<String>,
-    ) -> Result<String> {
-        let policy_file_path = format!(
-            "{}/{}.rego",
-            self.policy_dir_path
-                .to_str()
-                .ok_or_else(|| anyhow!("Miss Policy DirPath"))?,
-            policy_id.unwrap_or("default".to_string())
-        );
-        let policy = tokio::fs::read_to_string(policy_file_path)
+        policy_id: &str,
+        selector: Option<&PolicySelector>,
+    ) -> Result<()> {
+        let path = self.selector_path(policy_id);
+        match selector {
+            Some(selector) => tokio::fs::write(&path, serde_json::to_vec(selector)?)
+                .await
+                .map_err(|e| anyhow!("Write policy selector to file failed: {:?}", e)),
+            None if path.exists() => tokio::fs::remove_file(&path)
+                .await
+                .map_err(|e| anyhow!("Remove policy selector failed: {:?}", e)),
+            None => Ok(()),
+        }
+    }
+
+    /// The parameters a policy declares are stored next to it.
+    fn parameters_path(&self, policy_id: &str) -> PathBuf {
+        self.policy_dir_path
+            .join(format!("{policy_id}.parameters.json"))
+    }
+
+    fn read_parameters(&self, policy_id: &str) -> Result<Vec<PolicyParameter>> {
+        let path = self.parameters_path(policy_id);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        serde_json::from_slice(&fs::read(path)?)
+            .map_err(|e| anyhow!("Read parameters of policy {policy_id} failed: {:?}", e))
+    }
+
+    async fn write_parameters(
+        &self,
+        policy_id: &str,
+        parameters: &[PolicyParameter],
+    ) -> Result<()> {
+        let path = self.parameters_path(policy_id);
+        if parameters.is_empty() {
+            if path.exists() {
+                tokio::fs::remove_file(&path)
+                    .await
+                    .map_err(|e| anyhow!("Remove policy parameters failed: {:?}", e))?;
+            }
+            return Ok(());
+        }
+        tokio::fs::write(&path, serde_json::to_vec(parameters)?)
             .await
-            .map_err(|e| anyhow!("Read OPA policy file failed: {:?}", e))?;
+            .map_err(|e| anyhow!("Write policy parameters to file failed: {:?}", e))
+    }
+}
 
+impl OPA {
+    /// Evaluate `policy` over `input` with the given reference data, policy
+    /// parameters and the policy data documents, and return the raw decision
+    /// document produced by OPA.
+    fn evaluate_policy(
+        &self,
+        policy: &str,
+        reference_data_map: &HashMap<String, Vec<String>>,
+        parameters: &serde_json::Map<String, Value>,
+        input: &str,
+    ) -> Result<String> {
         let policy_go = GoString {
             p: policy.as_ptr() as *const c_char,
             n: policy.len() as isize,
         };
 
-        let reference = serde_json::json!({ "reference": reference_data_map }).to_string();
+        let custom: HashMap<&String, &Value> = self
+            .custom_data
+            .iter()
+            .map(|(name, data)| (name, &data.data))
+            .collect();
+        let reference = serde_json::json!({
+            "reference": reference_data_map,
+            "custom": custom,
+            "parameters": parameters,
+        })
+        .to_string();
+
+        let library_go = GoString {
+            p: TIMING_LIBRARY.as_ptr() as *const c_char,
+            n: TIMING_LIBRARY.len() as isize,
+        };
 
         let reference_go = GoString {
             p: reference.as_ptr() as *const c_char,
@@ -92,7 +362,8 @@ impl PolicyEngine for OPA {
         };
 
         // Call the function exported by cgo and process the returned decision
-        let decision_buf: *mut c_char = unsafe { evaluateGo(policy_go, reference_go, input_go) };
+        let decision_buf: *mut c_char =
+            unsafe { evaluateGo(policy_go, library_go, reference_go, input_go) };
         let decision_str: &CStr = unsafe { CStr::from_ptr(decision_buf) };
         let res = decision_str.to_str()?.to_string();
         debug!("Evaluated: {}", res);
@@ -100,16 +371,95 @@ impl PolicyEngine for OPA {
             return Err(anyhow!(res));
         }
 
+        Ok(res)
+    }
+
+    /// Get the decision from an evaluation report. Only an explicit
+    /// `allow = false` is taken as a rejection.
+    fn decision(res: &str) -> Result<PolicyDecision> {
+        let res_kv: Value = serde_json::from_str(res)?;
+        match res_kv["allow"].as_bool() {
+            Some(false) => Ok(PolicyDecision::Deny),
+            _ => Ok(PolicyDecision::Allow),
+        }
+    }
+
+    /// Get the explanations of a denial from an evaluation report. A policy
+    /// explains itself with a `violations` rule, whose entries are either
+    /// [`PolicyViolation`] objects or plain messages.
+    fn violations(res: &str) -> Result<Vec<PolicyViolation>> {
+        let res_kv: Value = serde_json::from_str(res)?;
+        let Some(entries) = res_kv["violations"].as_array() else {
+            return Ok(Vec::new());
+        };
+
+        let message_only = |message: String| PolicyViolation {
+            rule: "violations".to_string(),
+            claim: None,
+            value: None,
+            expected: None,
+            message: Some(message),
+        };
+        let violations = entries
+            .iter()
+            .map(|entry| match entry {
+                Value::String(message) => message_only(message.clone()),
+                _ => serde_json::from_value(entry.clone())
+                    .unwrap_or_else(|_| message_only(entry.to_string())),
+            })
+            .collect();
+
+        Ok(violations)
+    }
+}
+
+#[async_trait]
+impl PolicyEngine for OPA {
+    async fn evaluate(
+        &self,
+        tee: &str,
+        reference_data_map: HashMap<String, Vec<String>>,
+        input: String,
+        policy_id: Option<String>,
+        parameters: &ParameterValues,
+    ) -> Result<PolicyEvaluation> {
+        let policy_id = policy_id.unwrap_or("default".to_string());
+        let policy_file_path = format!(
+            "{}/{}.rego",
+            self.policy_dir_path
+                .to_str()
+                .ok_or_else(|| anyhow!("Miss Policy DirPath"))?,
+            policy_id
+        );
+        let policy = tokio::fs::read_to_string(policy_file_path)
+            .await
+            .map_err(|e| anyhow!("Read OPA policy file failed: {:?}", e))?;
+        // Kept before it is evaluated, so that it can be audited even if it
+        // was written outside of `set_policy`.
+        let policy_digest = self.record_policy(policy.as_bytes())?;
+
+        if let Some(selector) = self.read_selector(&policy_id)? {
+            let claims: serde_json::Map<String, Value> = serde_json::from_str(&input)?;
+            check_selector(&policy_id, &selector, tee, &claims)?;
+        }
+        let parameters =
+            resolve_parameters(&policy_id, &self.read_parameters(&policy_id)?, parameters)?;
+
+        let res = self.evaluate_policy(&policy, &reference_data_map, &parameters, &input)?;
+
         // If a clear approval opinion is given in the evaluation report,
         // the rejection information will be reflected in the evaluation failure return value.
-        let res_kv: Value = serde_json::from_str(&res)?;
-        if let Some(allow) = res_kv["allow"].as_bool() {
-            if !allow {
-                bail!("Untrusted TEE evidence")
+        if Self::decision(&res)? == PolicyDecision::Deny {
+            return Err(PolicyDenied {
+                violations: Self::violations(&res)?,
             }
+            .into());
         }
 
-        Ok(res)
+        Ok(PolicyEvaluation {
+            report: res,
+            policy_digest,
+        })
     }
 
     async fn set_policy(&mut self, input: SetPolicyInput) -> Result<()> {
@@ -119,27 +469,313 @@ impl PolicyEngine for OPA {
             bail!("OPA Policy Engine only support .rego policy");
         }
 
+        check_policy_id(&input.policy_id)?;
+
         let policy_bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
             .decode(input.policy)
             .map_err(|e| anyhow!("Base64 decode OPA policy string failed: {:?}", e))?;
-        let mut policy_file_path = PathBuf::from(
-            &self
-                .policy_dir_path
-                .to_str()
-                .ok_or_else(|| anyhow!("Policy DirPath to string failed"))?,
-        );
-        policy_file_path.push(format!("{}.rego", input.policy_id));
+        let policy_file_path = self.policy_path(&input.policy_id);
 
+        let digest = self.record_policy(&policy_bytes)?;
+        self.record_signature(&digest, input.signature.as_deref())?;
         tokio::fs::write(&policy_file_path, policy_bytes)
             .await
-            .map_err(|e| anyhow!("Write OPA policy to file failed: {:?}", e))
+            .map_err(|e| anyhow!("Write OPA policy to file failed: {:?}", e))?;
+        self.record_revision(&input.policy_id, &digest)?;
+        self.write_selector(&input.policy_id, input.selector.as_ref())
+            .await?;
+        self.write_parameters(&input.policy_id, &input.parameters)
+            .await
+    }
+
+    async fn get_policy_by_digest(&self, digest: &str) -> Result<String> {
+        tokio::fs::read_to_string(self.history_path(digest)?)
+            .await
+            .map_err(|_| anyhow!("Policy with digest {digest} not found"))
+    }
+
+    async fn list_policies(&self) -> Result<BTreeMap<String, String>> {
+        let mut policies = BTreeMap::new();
+        for entry in fs::read_dir(&self.policy_dir_path)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "rego") {
+                if let Some(policy_id) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    policies.insert(policy_id.to_string(), policy_digest(&fs::read(&path)?));
+                }
+            }
+        }
+        Ok(policies)
+    }
+
+    async fn get_policy(&self, policy_id: &str) -> Result<String> {
+        check_policy_id(policy_id)?;
+        tokio::fs::read_to_string(self.policy_path(policy_id))
+            .await
+            .map_err(|_| anyhow!("Policy {policy_id} not found"))
+    }
+
+    async fn delete_policy(&mut self, policy_id: &str) -> Result<()> {
+        check_policy_id(policy_id)?;
+        if policy_id == "default" {
+            bail!("The default policy cannot be deleted");
+        }
+        let path = self.policy_path(policy_id);
+        if !path.exists() {
+            bail!("Policy {policy_id} not found");
+        }
+        tokio::fs::remove_file(&path)
+            .await
+            .map_err(|e| anyhow!("Remove OPA policy failed: {:?}", e))?;
+        self.write_selector(policy_id, None).await?;
+        self.write_parameters(policy_id, &[]).await?;
+        info!("Policy {policy_id} deleted");
+        Ok(())
+    }
+
+    async fn policy_revisions(&self, policy_id: &str) -> Result<Vec<PolicyRevision>> {
+        check_policy_id(policy_id)?;
+        self.read_revisions(policy_id)
+    }
+
+    async fn rollback_policy(&mut self, policy_id: &str, digest: &str) -> Result<()> {
+        check_policy_id(policy_id)?;
+        let digest = digest.to_ascii_lowercase();
+        if !self
+            .read_revisions(policy_id)?
+            .iter()
+            .any(|revision| revision.digest == digest)
+        {
+            bail!("Policy {policy_id} has no revision {digest}");
+        }
+        let policy = self.get_policy_by_digest(&digest).await?;
+        tokio::fs::write(self.policy_path(policy_id), policy)
+            .await
+            .map_err(|e| anyhow!("Write OPA policy to file failed: {:?}", e))?;
+        self.record_revision(policy_id, &digest)?;
+        info!("Policy {policy_id} rolled back to {digest}");
+        Ok(())
+    }
+
+    async fn test_policy(&self, input: TestPolicyInput) -> Result<PolicyTestReport> {
+        let policy_type = PolicyType::from_str(&input.r#type)
+            .map_err(|_| anyhow!("{} is not support by AS", &input.r#type))?;
+        if policy_type != PolicyType::Rego {
+            bail!("OPA Policy Engine only support .rego policy");
+        }
+
+        let policy_bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(input.policy)
+            .map_err(|e| anyhow!("Base64 decode OPA policy string failed: {:?}", e))?;
+        let policy = String::from_utf8(policy_bytes)
+            .map_err(|e| anyhow!("OPA policy is not valid UTF-8: {:?}", e))?;
+
+        let mut report = PolicyTestReport::default();
+        for fixture in input.fixtures {
+            let values = ParameterValues::configured(fixture.parameters);
+            let outcome = resolve_parameters("under test", &input.parameters, &values)
+                .and_then(|parameters| {
+                    self.evaluate_policy(
+                        &policy,
+                        &fixture.reference,
+                        &parameters,
+                        &fixture.claims.to_string(),
+                    )
+                })
+                .and_then(|res| Self::decision(&res));
+
+            let result = match outcome {
+                Ok(actual) => PolicyTestResult {
+                    name: fixture.name,
+                    expected: fixture.expected,
+                    actual: Some(actual),
+                    passed: actual == fixture.expected,
+                    error: None,
+                },
+                Err(e) => PolicyTestResult {
+                    name: fixture.name,
+                    expected: fixture.expected,
+                    actual: None,
+                    passed: false,
+                    error: Some(e.to_string()),
+                },
+            };
+            report.push(result);
+        }
+
+        Ok(report)
+    }
+
+    async fn set_policy_data(&mut self, input: SetPolicyDataInput) -> Result<u64> {
+        check_data_name(&input.name)?;
+
+        let dir = self.data_dir(&input.name);
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .map_err(|e| anyhow!("Create policy data dir failed: {:?}", e))?;
+        let version = data_versions(&dir)?.last().map_or(1, |latest| latest + 1);
+        tokio::fs::write(
+            dir.join(format!("{version}.json")),
+            serde_json::to_vec(&input.data)?,
+        )
+        .await
+        .map_err(|e| anyhow!("Write policy data to file failed: {:?}", e))?;
+
+        info!("Policy data `{}` updated to version {version}", input.name);
+        self.custom_data.insert(
+            input.name.clone(),
+            PolicyData {
+                name: input.name,
+                version,
+                data: input.data,
+            },
+        );
+        Ok(version)
+    }
+
+    async fn get_policy_data(&self, name: &str, version: Option<u64>) -> Result<PolicyData> {
+        check_data_name(name)?;
+        match version {
+            None => self
+                .custom_data
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow!("Policy data `{name}` not found")),
+            Some(version) => read_data(&self.data_dir(name), name, version)
+                .map_err(|_| anyhow!("Policy data `{name}` version {version} not found")),
+        }
+    }
+
+    async fn export_state(&self) -> Result<PolicyEngineState> {
+        let mut state = PolicyEngineState::default();
+        for entry in fs::read_dir(&self.policy_dir_path)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "rego") {
+                if let Some(policy_id) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    let policy = fs::read_to_string(&path)?;
+                    let digest = policy_digest(policy.as_bytes());
+                    if let Some(signature) = self.read_signature(&digest)? {
+                        state.signatures.insert(digest, signature);
+                    }
+                    state.policies.insert(policy_id.to_string(), policy);
+                    if let Some(selector) = self.read_selector(policy_id)? {
+                        state.selectors.insert(policy_id.to_string(), selector);
+                    }
+                    let parameters = self.read_parameters(policy_id)?;
+                    if !parameters.is_empty() {
+                        state.parameters.insert(policy_id.to_string(), parameters);
+                    }
+                }
+            }
+        }
+        let revisions_dir = self.policy_dir_path.join("revisions");
+        if revisions_dir.exists() {
+            for entry in fs::read_dir(&revisions_dir)? {
+                let path = entry?.path();
+                if let Some(policy_id) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    state
+                        .revisions
+                        .insert(policy_id.to_string(), self.read_revisions(policy_id)?);
+                }
+            }
+        }
+        let history_dir = self.policy_dir_path.join("history");
+        if history_dir.exists() {
+            for entry in fs::read_dir(&history_dir)? {
+                let policy = fs::read_to_string(entry?.path())?;
+                state
+                    .policy_history
+                    .insert(policy_digest(policy.as_bytes()), policy);
+            }
+        }
+        for name in self.custom_data.keys() {
+            let dir = self.data_dir(name);
+            for version in data_versions(&dir)? {
+                state.policy_data.push(read_data(&dir, name, version)?);
+            }
+        }
+        state
+            .policy_data
+            .sort_by(|a, b| (&a.name, a.version).cmp(&(&b.name, b.version)));
+        Ok(state)
+    }
+
+    async fn import_state(&mut self, state: PolicyEngineState) -> Result<()> {
+        // Check everything before writing anything.
+        for policy_id in state
+            .policies
+            .keys()
+            .chain(state.selectors.keys())
+            .chain(state.parameters.keys())
+            .chain(state.revisions.keys())
+        {
+            check_policy_id(policy_id)?;
+        }
+        for data in &state.policy_data {
+            check_data_name(&data.name)?;
+        }
+        for (digest, policy) in &state.policy_history {
+            if *digest != policy_digest(policy.as_bytes()) {
+                bail!("Policy history entry {digest} does not match its policy");
+            }
+        }
+        for digest in state.signatures.keys() {
+            self.signature_path(digest)?;
+        }
+
+        for policy in state.policy_history.values() {
+            self.record_policy(policy.as_bytes())?;
+        }
+        for (policy_id, revisions) in &state.revisions {
+            self.write_revisions(policy_id, revisions)?;
+        }
+        for (digest, signature) in &state.signatures {
+            self.record_signature(digest, Some(signature))?;
+        }
+
+        for (policy_id, policy) in state.policies {
+            tokio::fs::write(self.policy_path(&policy_id), &policy)
+                .await
+                .map_err(|e| anyhow!("Write OPA policy to file failed: {:?}", e))?;
+            let digest = self.record_policy(policy.as_bytes())?;
+            self.record_revision(&policy_id, &digest)?;
+            self.write_selector(&policy_id, state.selectors.get(&policy_id))
+                .await?;
+            self.write_parameters(
+                &policy_id,
+                state.parameters.get(&policy_id).map_or(&[], Vec::as_slice),
+            )
+            .await?;
+        }
+
+        for data in state.policy_data {
+            let dir = self.data_dir(&data.name);
+            tokio::fs::create_dir_all(&dir)
+                .await
+                .map_err(|e| anyhow!("Create policy data dir failed: {:?}", e))?;
+            tokio::fs::write(
+                dir.join(format!("{}.json", data.version)),
+                serde_json::to_vec(&data.data)?,
+            )
+            .await
+            .map_err(|e| anyhow!("Write policy data to file failed: {:?}", e))?;
+
+            let latest = self
+                .custom_data
+                .get(&data.name)
+                .map_or(0, |latest| latest.version);
+            if data.version >= latest {
+                self.custom_data.insert(data.name.clone(), data);
+            }
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json::json;
+    use as_types::PolicyTestFixture;
+    use serde_json::{json, Map};
 
     fn dummy_reference(ver: u64) -> String {
         json!({
@@ -159,27 +795,108 @@ mod tests {
 
     #[tokio::test]
     async fn test_evaluate() {
-        let opa = OPA {
-            policy_dir_path: PathBuf::from("./src/policy_engine/opa"),
-        };
-        let default_policy_id = "default_policy".to_string();
+        let work_dir = tempfile::tempdir().unwrap();
+        let opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
 
         let reference_data: HashMap<String, Vec<String>> =
             serde_json::from_str(&dummy_reference(5)).unwrap();
 
         let res = opa
             .evaluate(
+                "sample",
                 reference_data.clone(),
                 dummy_input(5, 5),
-                Some(default_policy_id.clone()),
+                None,
+                &ParameterValues::default(),
             )
             .await;
         assert!(res.is_ok(), "OPA execution() should be success");
+        let policy = std::include_str!("default_policy.rego");
+        assert_eq!(res.unwrap().policy_digest, policy_digest(policy.as_bytes()));
 
         let res = opa
-            .evaluate(reference_data, dummy_input(0, 0), Some(default_policy_id))
+            .evaluate(
+                "sample",
+                reference_data,
+                dummy_input(0, 0),
+                None,
+                &ParameterValues::default(),
+            )
             .await;
         assert!(res.is_err(), "OPA execution() should be failed");
+
+        let denied = res.unwrap_err().downcast::<PolicyDenied>().unwrap();
+        let mut claims: Vec<_> = denied
+            .violations
+            .iter()
+            .map(|v| v.claim.clone().unwrap())
+            .collect();
+        claims.sort();
+        assert_eq!(claims, vec!["productId", "svn"]);
+        assert_eq!(denied.violations[0].value, Some(json!("0")));
+        assert_eq!(denied.violations[0].expected, Some(json!(["5"])));
+
+        // Unsigned TD reports are only allowed by custom policies.
+        let td_report = json!({ "tdx.reduced_assurance": true }).to_string();
+        let res = opa
+            .evaluate(
+                "sample",
+                HashMap::new(),
+                td_report,
+                None,
+                &ParameterValues::default(),
+            )
+            .await;
+        let denied = res.unwrap_err().downcast::<PolicyDenied>().unwrap();
+        assert_eq!(denied.violations.len(), 1);
+        assert_eq!(denied.violations[0].rule, "reduced_assurance");
+        let quote = json!({ "tdx.reduced_assurance": false }).to_string();
+        let res = opa
+            .evaluate(
+                "sample",
+                HashMap::new(),
+                quote,
+                None,
+                &ParameterValues::default(),
+            )
+            .await;
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_violations() {
+        let res = json!({
+            "allow": false,
+            "violations": [
+                {
+                    "rule": "judge_field",
+                    "claim": "svn",
+                    "value": 2,
+                    "expected": ["3"]
+                },
+                "debug mode is enabled"
+            ]
+        })
+        .to_string();
+
+        let violations = OPA::violations(&res).unwrap();
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].rule, "judge_field");
+        assert_eq!(violations[0].claim.as_deref(), Some("svn"));
+        assert_eq!(violations[0].value, Some(json!(2)));
+        assert_eq!(
+            violations[1].message.as_deref(),
+            Some("debug mode is enabled")
+        );
+
+        let denied = PolicyDenied { violations };
+        assert_eq!(
+            denied.to_string(),
+            "Untrusted TEE evidence; judge_field (svn = 2); violations"
+        );
+
+        let res = json!({"allow": false}).to_string();
+        assert!(OPA::violations(&res).unwrap().is_empty());
     }
 
     #[tokio::test]
@@ -193,8 +910,365 @@ default allow = true"
             r#type: "rego".to_string(),
             policy_id: "test".to_string(),
             policy: base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(policy),
+            selector: None,
+            parameters: Vec::new(),
+            signature: None,
         };
 
         assert!(opa.set_policy(input).await.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_policy_history() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let mut opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+        let mut digests = Vec::new();
+        for policy in ["package policy\ndefault allow = true\n", "package policy\n"] {
+            opa.set_policy(SetPolicyInput {
+                r#type: "rego".to_string(),
+                policy_id: "test".to_string(),
+                policy: base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(policy),
+                selector: None,
+                parameters: Vec::new(),
+                signature: None,
+            })
+            .await
+            .unwrap();
+            digests.push((policy_digest(policy.as_bytes()), policy));
+        }
+
+        // The replaced policy is still there.
+        for (digest, policy) in digests {
+            assert_eq!(opa.get_policy_by_digest(&digest).await.unwrap(), policy);
+        }
+        let default = policy_digest(std::include_str!("default_policy.rego").as_bytes());
+        assert!(opa.get_policy_by_digest(&default).await.is_ok());
+        assert!(opa.get_policy_by_digest(&"0".repeat(64)).await.is_err());
+        assert!(opa.get_policy_by_digest("../default").await.is_err());
+
+        let state = opa.export_state().await.unwrap();
+        assert_eq!(state.policy_history.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_policy_management() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let mut opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+        let good = "package policy\ndefault allow = true\n";
+        let bad = "package policy\ndefault allow = false\n";
+        for policy in [good, bad] {
+            opa.set_policy(SetPolicyInput {
+                r#type: "rego".to_string(),
+                policy_id: "test".to_string(),
+                policy: base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(policy),
+                selector: None,
+                parameters: Vec::new(),
+                signature: None,
+            })
+            .await
+            .unwrap();
+        }
+
+        let policies = opa.list_policies().await.unwrap();
+        assert_eq!(policies.keys().collect::<Vec<_>>(), ["default", "test"]);
+        assert_eq!(policies["test"], policy_digest(bad.as_bytes()));
+        assert_eq!(opa.get_policy("test").await.unwrap(), bad);
+        assert_eq!(opa.policy_revisions("default").await.unwrap().len(), 1);
+        let revisions = opa.policy_revisions("test").await.unwrap();
+        assert_eq!(
+            revisions
+                .iter()
+                .map(|r| r.digest.as_str())
+                .collect::<Vec<_>>(),
+            [
+                policy_digest(good.as_bytes()),
+                policy_digest(bad.as_bytes())
+            ]
+        );
+
+        // Rolled back, as a new revision.
+        opa.rollback_policy("test", &revisions[0].digest)
+            .await
+            .unwrap();
+        assert_eq!(opa.get_policy("test").await.unwrap(), good);
+        assert_eq!(opa.policy_revisions("test").await.unwrap().len(), 3);
+        // Not a revision of the policy.
+        let default = &opa.policy_revisions("default").await.unwrap()[0].digest;
+        assert!(opa.rollback_policy("test", default).await.is_err());
+
+        opa.delete_policy("test").await.unwrap();
+        assert!(opa.get_policy("test").await.is_err());
+        assert!(opa.delete_policy("test").await.is_err());
+        assert!(opa.delete_policy("default").await.is_err());
+        assert!(opa.get_policy("../default").await.is_err());
+        // The revisions outlive the policy.
+        opa.rollback_policy("test", &revisions[1].digest)
+            .await
+            .unwrap();
+        assert_eq!(opa.get_policy("test").await.unwrap(), bad);
+    }
+
+    #[tokio::test]
+    async fn test_policy_data() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let mut opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+
+        let set = |name: &str, data: Value| SetPolicyDataInput {
+            name: name.to_string(),
+            data,
+        };
+        assert!(opa
+            .set_policy_data(set("bad-name", json!({})))
+            .await
+            .is_err());
+        assert!(opa.set_policy_data(set("1st", json!({}))).await.is_err());
+
+        let v1 = json!({"allowed_fmspcs": ["00906ED50000"]});
+        let v2 = json!({"allowed_fmspcs": ["00906ED50000", "00606A000000"]});
+        assert_eq!(
+            opa.set_policy_data(set("platforms", v1.clone()))
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            opa.set_policy_data(set("platforms", v2.clone()))
+                .await
+                .unwrap(),
+            2
+        );
+
+        let latest = opa.get_policy_data("platforms", None).await.unwrap();
+        assert_eq!((latest.version, latest.data), (2, v2.clone()));
+        let first = opa.get_policy_data("platforms", Some(1)).await.unwrap();
+        assert_eq!(first.data, v1);
+        assert!(opa.get_policy_data("platforms", Some(3)).await.is_err());
+        assert!(opa.get_policy_data("regions", None).await.is_err());
+
+        // The latest versions are loaded on restart.
+        let opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+        assert_eq!(opa.custom_data["platforms"].version, 2);
+        assert_eq!(opa.custom_data["platforms"].data, v2);
+    }
+
+    #[tokio::test]
+    async fn test_export_import_state() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let mut opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+        for data in [json!({"v": 1}), json!({"v": 2})] {
+            opa.set_policy_data(SetPolicyDataInput {
+                name: "platforms".to_string(),
+                data,
+            })
+            .await
+            .unwrap();
+        }
+        opa.set_policy(SetPolicyInput {
+            r#type: "rego".to_string(),
+            policy_id: "strict".to_string(),
+            policy: base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .encode("package policy\n\ndefault allow = false\n"),
+            selector: Some(PolicySelector {
+                tees: vec!["tdx".to_string()],
+                claims: Vec::new(),
+            }),
+            parameters: serde_json::from_value(json!([
+                {"name": "allowed_mr_td", "type": "string_list"}
+            ]))
+            .unwrap(),
+            signature: Some("c2lnbmF0dXJl".to_string()),
+        })
+        .await
+        .unwrap();
+
+        let state = opa.export_state().await.unwrap();
+        let strict_digest = policy_digest(b"package policy\n\ndefault allow = false\n");
+        assert_eq!(
+            state.signatures,
+            [(strict_digest, "c2lnbmF0dXJl".to_string())].into()
+        );
+        assert_eq!(
+            state.policies.keys().collect::<Vec<_>>(),
+            ["default", "strict"]
+        );
+        assert_eq!(state.selectors.keys().collect::<Vec<_>>(), ["strict"]);
+        assert_eq!(state.parameters.keys().collect::<Vec<_>>(), ["strict"]);
+        assert_eq!(state.policy_data.len(), 2);
+
+        let other_dir = tempfile::tempdir().unwrap();
+        let mut other = OPA::new(other_dir.path().to_path_buf()).unwrap();
+        other.import_state(state.clone()).await.unwrap();
+        assert_eq!(other.export_state().await.unwrap(), state);
+        assert_eq!(other.custom_data["platforms"].data, json!({"v": 2}));
+
+        let mut invalid = PolicyEngineState::default();
+        invalid
+            .policies
+            .insert("../escape".to_string(), String::new());
+        assert!(other.import_state(invalid).await.is_err());
+
+        // The policy is not evaluated without its parameters.
+        let error = other
+            .evaluate(
+                "tdx",
+                HashMap::new(),
+                "{}".to_string(),
+                Some("strict".to_string()),
+                &ParameterValues::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Policy strict requires the parameter allowed_mr_td"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_with_policy_data() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let mut opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+        opa.set_policy_data(SetPolicyDataInput {
+            name: "platforms".to_string(),
+            data: json!({"allowed_fmspcs": ["00906ED50000"]}),
+        })
+        .await
+        .unwrap();
+
+        let policy = "package policy
+default allow = false
+allow { input.fmspc == data.custom.platforms.allowed_fmspcs[_] }";
+        let res = opa
+            .evaluate_policy(
+                policy,
+                &HashMap::new(),
+                &Map::new(),
+                r#"{"fmspc": "00906ED50000"}"#,
+            )
+            .unwrap();
+        assert_eq!(OPA::decision(&res).unwrap(), PolicyDecision::Allow);
+
+        let res = opa
+            .evaluate_policy(
+                policy,
+                &HashMap::new(),
+                &Map::new(),
+                r#"{"fmspc": "00606A000000"}"#,
+            )
+            .unwrap();
+        assert_eq!(OPA::decision(&res).unwrap(), PolicyDecision::Deny);
+    }
+
+    #[test]
+    fn test_timing_library() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+
+        let policy = r#"package policy
+import data.attestation.timing
+default allow = false
+allow {
+    timing.booted_since("2024-03-12")
+    timing.attested_since("2024-03-12T00:00:00Z")
+    not timing.up_longer_than("720h")
+}"#;
+        let decision = |input: Value| {
+            let res = opa
+                .evaluate_policy(policy, &HashMap::new(), &Map::new(), &input.to_string())
+                .unwrap();
+            OPA::decision(&res).unwrap()
+        };
+
+        assert_eq!(
+            decision(json!({
+                "boot_time": "2024-04-01T08:00:00+00:00",
+                "attestation_time": "2024-04-02T08:00:00+00:00",
+            })),
+            PolicyDecision::Allow
+        );
+        // Booted before the fix
+        assert_eq!(
+            decision(json!({
+                "boot_time": "2024-03-11T23:59:59+00:00",
+                "attestation_time": "2024-04-02T08:00:00+00:00",
+            })),
+            PolicyDecision::Deny
+        );
+        // Up for too long
+        assert_eq!(
+            decision(json!({
+                "boot_time": "2024-03-12T00:00:00+00:00",
+                "attestation_time": "2024-05-02T08:00:00+00:00",
+            })),
+            PolicyDecision::Deny
+        );
+        // The boot time is unknown
+        assert_eq!(
+            decision(json!({ "attestation_time": "2024-04-02T08:00:00+00:00" })),
+            PolicyDecision::Deny
+        );
+    }
+
+    #[tokio::test]
+    async fn test_test_policy() {
+        let opa = OPA {
+            policy_dir_path: PathBuf::from("./src/policy_engine/opa"),
+            custom_data: HashMap::new(),
+        };
+        let policy = std::include_str!("default_policy.rego");
+        let reference: HashMap<String, Vec<String>> =
+            serde_json::from_str(&dummy_reference(5)).unwrap();
+
+        let fixtures = vec![
+            PolicyTestFixture {
+                name: "good".to_string(),
+                claims: serde_json::from_str(&dummy_input(5, 5)).unwrap(),
+                reference: reference.clone(),
+                parameters: Map::new(),
+                expected: PolicyDecision::Allow,
+            },
+            PolicyTestFixture {
+                name: "bad".to_string(),
+                claims: serde_json::from_str(&dummy_input(0, 0)).unwrap(),
+                reference: reference.clone(),
+                parameters: Map::new(),
+                expected: PolicyDecision::Deny,
+            },
+            PolicyTestFixture {
+                name: "wrong-expectation".to_string(),
+                claims: serde_json::from_str(&dummy_input(0, 0)).unwrap(),
+                reference,
+                parameters: Map::new(),
+                expected: PolicyDecision::Allow,
+            },
+            PolicyTestFixture {
+                name: "numeric-claim".to_string(),
+                claims: json!({"svn": 3}),
+                reference: HashMap::from([("svn".to_string(), vec!["3".to_string()])]),
+                parameters: Map::new(),
+                expected: PolicyDecision::Allow,
+            },
+            PolicyTestFixture {
+                name: "numeric-claim-mismatch".to_string(),
+                claims: json!({"svn": 2}),
+                reference: HashMap::from([("svn".to_string(), vec!["3".to_string()])]),
+                parameters: Map::new(),
+                expected: PolicyDecision::Deny,
+            },
+        ];
+
+        let input = TestPolicyInput {
+            r#type: "rego".to_string(),
+            policy: base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(policy),
+            fixtures,
+            parameters: Vec::new(),
+            signature: None,
+        };
+
+        let report = opa.test_policy(input).await.unwrap();
+        assert_eq!(report.passed, 4);
+        assert_eq!(report.failed, 1);
+        assert!(!report.success());
+        assert_eq!(report.results[2].actual, Some(PolicyDecision::Deny));
+    }
 }
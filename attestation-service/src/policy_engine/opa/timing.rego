@@ -0,0 +1,53 @@
+# Helper functions of the time of boot and time of attestation claims,
+# imported by policies as `data.attestation.timing`:
+#
+#	import data.attestation.timing
+#
+#	allow {
+#		timing.booted_since("2024-03-12")
+#	}
+#
+# Dates are RFC 3339 times, like "2024-03-12T00:00:00Z", or days, like
+# "2024-03-12", at midnight UTC. Rules on the boot time are undefined when
+# the evidence does not tell it, so that `timing.booted_since` fails closed
+# while `not timing.booted_before` does not.
+package attestation.timing
+
+# Nanoseconds since the Unix epoch of a date.
+ns(date) := t {
+	contains(date, "T")
+	t := time.parse_rfc3339_ns(date)
+}
+
+ns(date) := t {
+	not contains(date, "T")
+	t := time.parse_ns("2006-01-02", date)
+}
+
+# The guest booted before `date`.
+booted_before(date) {
+	ns(input.boot_time) < ns(date)
+}
+
+# The guest booted on or after `date`.
+booted_since(date) {
+	ns(input.boot_time) >= ns(date)
+}
+
+# The guest was attested on or after `date`.
+attested_since(date) {
+	ns(input.attestation_time) >= ns(date)
+}
+
+# The TCB the platform booted with was released before `date`.
+tcb_before(date) {
+	ns(input.tcb_date) < ns(date)
+}
+
+# Nanoseconds the guest had been running when it was attested.
+uptime_ns := ns(input.attestation_time) - ns(input.boot_time)
+
+# The guest had been running for longer than `duration`, like "720h".
+up_longer_than(duration) {
+	uptime_ns > time.parse_duration_ns(duration)
+}
@@ -0,0 +1,173 @@
+//! Shadow evaluation of policies.
+//!
+//! Before a policy is replaced, e.g. a Rego policy by its port to another
+//! policy language, the new policy can run in the shadow of the old one on
+//! every attestation, to find out where they disagree. With
+//! `shadow_policies` in the AS config, mapping the ID of a primary policy
+//! to that of its shadow,
+//!
+//! ```json
+//! "shadow_policies": { "default": "default-next" }
+//! ```
+//!
+//! the shadow policy is evaluated alongside the primary one, with the same
+//! input, reference values and parameters. Its result never affects the
+//! attestation: only the primary policy admits or denies the evidence. The
+//! evaluations are counted by policy, see [`ShadowStats`], and every
+//! discrepancy or failure of the shadow is logged as one line of JSON under
+//! the `attestation_service::shadow` target, e.g.
+//!
+//! ```json
+//! {"tee": "tdx", "policy_id": "default", "shadow_policy_id": "default-next", "primary": "allow", "shadow": "deny", "error": "Untrusted TEE evidence; ..."}
+//! ```
+
+use super::{PolicyDenied, PolicyEvaluation};
+use anyhow::Result;
+use log::warn;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, PoisonError};
+
+/// Target of the shadow logs, to filter them in or out with `RUST_LOG`.
+pub const SHADOW_LOG_TARGET: &str = "attestation_service::shadow";
+
+/// Outcome of the evaluation of a policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum Outcome {
+    /// The policy admitted the claims.
+    Allow,
+    /// The policy denied the claims.
+    Deny,
+    /// The policy could not be evaluated.
+    Error,
+}
+
+impl Outcome {
+    pub fn of(evaluation: &Result<PolicyEvaluation>) -> Self {
+        match evaluation {
+            Ok(_) => Self::Allow,
+            Err(e) if e.is::<PolicyDenied>() => Self::Deny,
+            Err(_) => Self::Error,
+        }
+    }
+}
+
+/// Shadow evaluations of a primary policy, since the AS started.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShadowCount {
+    pub policy_id: String,
+    pub shadow_policy_id: String,
+    /// Attestations evaluated by both policies.
+    pub evaluations: u64,
+    /// Evaluations where the shadow policy decided otherwise than the
+    /// primary one.
+    pub discrepancies: u64,
+    /// Evaluations where the shadow policy failed.
+    pub errors: u64,
+}
+
+/// Counters of the shadow evaluations.
+#[derive(Debug, Default)]
+pub(crate) struct ShadowStats {
+    counts: Mutex<BTreeMap<(String, String), ShadowCount>>,
+}
+
+impl ShadowStats {
+    /// Count the evaluation of the `shadow` policy of the `primary` one on
+    /// evidence of `tee`, and log it if they disagree or the shadow failed.
+    pub fn compare(
+        &self,
+        tee: &str,
+        (policy_id, primary): (&str, &Result<PolicyEvaluation>),
+        (shadow_policy_id, shadow): (&str, &Result<PolicyEvaluation>),
+    ) {
+        let (primary_outcome, shadow_outcome) = (Outcome::of(primary), Outcome::of(shadow));
+        {
+            let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+            let count = counts
+                .entry((policy_id.to_string(), shadow_policy_id.to_string()))
+                .or_insert_with(|| ShadowCount {
+                    policy_id: policy_id.to_string(),
+                    shadow_policy_id: shadow_policy_id.to_string(),
+                    ..Default::default()
+                });
+            count.evaluations += 1;
+            match shadow_outcome {
+                Outcome::Error => count.errors += 1,
+                outcome if outcome != primary_outcome => count.discrepancies += 1,
+                _ => return,
+            }
+        }
+        let mut line = json!({
+            "tee": tee,
+            "policy_id": policy_id,
+            "shadow_policy_id": shadow_policy_id,
+            "primary": primary_outcome.to_string(),
+            "shadow": shadow_outcome.to_string(),
+        });
+        if let Err(e) = shadow {
+            line["error"] = json!(format!("{e:#}"));
+        }
+        warn!(target: SHADOW_LOG_TARGET, "{line}");
+    }
+
+    /// The shadow evaluations by primary and shadow policy.
+    pub fn counts(&self) -> Vec<ShadowCount> {
+        self.counts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_compare() {
+        let allow = || {
+            Ok(PolicyEvaluation {
+                report: String::new(),
+                policy_digest: String::new(),
+            })
+        };
+        let deny = || -> Result<PolicyEvaluation> { Err(PolicyDenied::default().into()) };
+        let stats = ShadowStats::default();
+        stats.compare("tdx", ("default", &allow()), ("next", &allow()));
+        stats.compare("tdx", ("default", &deny()), ("next", &deny()));
+        stats.compare("tdx", ("default", &allow()), ("next", &deny()));
+        stats.compare("snp", ("default", &deny()), ("next", &allow()));
+        stats.compare(
+            "snp",
+            ("default", &allow()),
+            ("next", &Err(anyhow!("undefined"))),
+        );
+        stats.compare("snp", ("amd", &allow()), ("amd-next", &allow()));
+
+        assert_eq!(
+            stats.counts(),
+            vec![
+                ShadowCount {
+                    policy_id: "amd".to_string(),
+                    shadow_policy_id: "amd-next".to_string(),
+                    evaluations: 1,
+                    discrepancies: 0,
+                    errors: 0,
+                },
+                ShadowCount {
+                    policy_id: "default".to_string(),
+                    shadow_policy_id: "next".to_string(),
+                    evaluations: 5,
+                    discrepancies: 2,
+                    errors: 1,
+                },
+            ]
+        );
+        assert_eq!(Outcome::of(&deny()), Outcome::Deny);
+    }
+}
@@ -0,0 +1,187 @@
+//! Signatures of uploaded policies.
+//!
+//! Anyone who can reach the policy management API can change what the AS
+//! admits. With `policy_signing` in the AS config, policies are only set if
+//! they come with a detached signature of an admin key, so that a
+//! compromised operator account or CI job cannot install one on its own:
+//!
+//! ```json
+//! "policy_signing": { "admin_public_key": "/etc/attestation-service/policy_admin.pem" }
+//! ```
+//!
+//! The admin key is an RSA public key, as a PEM SubjectPublicKeyInfo. The
+//! `signature` of the policy input is the base64 RSASSA-PKCS1-v1_5
+//! SHA-256 signature of the policy text, e.g. made with
+//! `openssl dgst -sha256 -sign admin.key policy.rego | base64 -w0`.
+//!
+//! Deleting a policy and rolling it back to one of its revisions, see
+//! [`super::PolicyEngine::rollback_policy`], need the signature of the
+//! statement of the operation, [`delete_statement`] or
+//! [`rollback_statement`], and so do the uploads of policy data,
+//! [`set_policy_data_statement`], and the changes of the deny-list, see
+//! [`crate::deny_list`], [`add_deny_list_statement`] and
+//! [`remove_deny_list_statement`], e.g.
+//! `printf 'rollback-policy default <digest>' | openssl dgst -sha256 -sign admin.key | base64 -w0`.
+//! The signatures of the policies are kept with them, and exported in
+//! state archives, so that an imported policy is checked too.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::sha2::{Digest, Sha256};
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PolicySigningConfig {
+    /// PEM RSA public key of the admins that sign policies.
+    pub admin_public_key: PathBuf,
+}
+
+/// The statement an admin signs to delete the policy `policy_id`.
+pub fn delete_statement(policy_id: &str) -> String {
+    format!("delete-policy {policy_id}")
+}
+
+/// The statement an admin signs to roll the policy `policy_id` back to its
+/// revision of `digest`.
+pub fn rollback_statement(policy_id: &str, digest: &str) -> String {
+    format!("rollback-policy {policy_id} {digest}")
+}
+
+/// The statement an admin signs to upload `data` as a new version of the
+/// policy data document `name`, with the hex SHA-256 digest of `data` as
+/// compact JSON with sorted keys, e.g. that of `jq -cjS .data`.
+pub fn set_policy_data_statement(name: &str, data: &serde_json::Value) -> String {
+    let digest = hex::encode(Sha256::digest(data.to_string()));
+    format!("set-policy-data {name} {digest}")
+}
+
+/// The statement an admin signs to deny the value `value` of `claim`.
+pub fn add_deny_list_statement(claim: &str, value: &str) -> String {
+    format!("add-deny-list-entry {claim} {value}")
+}
+
+/// The statement an admin signs to allow the value `value` of `claim`
+/// again.
+pub fn remove_deny_list_statement(claim: &str, value: &str) -> String {
+    format!("remove-deny-list-entry {claim} {value}")
+}
+
+/// Verifies the signatures of uploaded policies.
+pub struct PolicySignatureVerifier {
+    admin_key: RsaPublicKey,
+}
+
+impl PolicySignatureVerifier {
+    pub fn new(config: &PolicySigningConfig) -> Result<Self> {
+        let pem = fs::read_to_string(&config.admin_public_key)
+            .with_context(|| format!("read {}", config.admin_public_key.display()))?;
+        let admin_key = RsaPublicKey::from_public_key_pem(&pem)
+            .map_err(|e| anyhow!("Invalid admin public key: {e}"))?;
+        Ok(Self { admin_key })
+    }
+
+    /// Check that `signature`, base64, is the signature of `policy` by the
+    /// admin key.
+    pub fn verify(&self, policy: &[u8], signature: Option<&str>) -> Result<()> {
+        self.verify_signature("policy", policy, signature)
+    }
+
+    /// Check that `signature`, base64, is the signature of `statement`, of
+    /// a policy management operation, by the admin key.
+    pub fn verify_operation(&self, statement: &str, signature: Option<&str>) -> Result<()> {
+        self.verify_signature("operation", statement.as_bytes(), signature)
+    }
+
+    fn verify_signature(&self, what: &str, message: &[u8], signature: Option<&str>) -> Result<()> {
+        let Some(signature) = signature else {
+            bail!("The {what} is not signed, and `policy_signing` requires it");
+        };
+        let signature = base64::engine::general_purpose::STANDARD
+            .decode(signature)
+            .with_context(|| format!("Malformed {what} signature"))?;
+        self.admin_key
+            .verify(
+                Pkcs1v15Sign::new::<Sha256>(),
+                &Sha256::digest(message),
+                &signature,
+            )
+            .map_err(|_| anyhow!("The {what} signature is not that of the admin key"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs8::{EncodePublicKey, LineEnding};
+    use rsa::RsaPrivateKey;
+
+    #[test]
+    fn test_verify() {
+        let admin = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("admin.pem");
+        fs::write(
+            &path,
+            admin
+                .to_public_key()
+                .to_public_key_pem(LineEnding::LF)
+                .unwrap(),
+        )
+        .unwrap();
+        let verifier = PolicySignatureVerifier::new(&PolicySigningConfig {
+            admin_public_key: path,
+        })
+        .unwrap();
+
+        let policy = b"package policy\ndefault allow = true\n";
+        let sign = |policy: &[u8]| {
+            let signature = admin
+                .sign(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(policy))
+                .unwrap();
+            base64::engine::general_purpose::STANDARD.encode(signature)
+        };
+        verifier.verify(policy, Some(&sign(policy))).unwrap();
+        assert!(verifier
+            .verify(policy, Some(&sign(b"package policy\n")))
+            .is_err());
+        assert!(verifier.verify(policy, Some("not base64!")).is_err());
+        let e = verifier.verify(policy, None).unwrap_err();
+        assert!(e.to_string().contains("not signed"));
+
+        let rollback = rollback_statement("default", "aa");
+        verifier
+            .verify_operation(&rollback, Some(&sign(rollback.as_bytes())))
+            .unwrap();
+        let delete = delete_statement("default");
+        assert!(verifier
+            .verify_operation(&delete, Some(&sign(rollback.as_bytes())))
+            .is_err());
+        let e = verifier.verify_operation(&delete, None).unwrap_err();
+        assert!(e.to_string().contains("operation is not signed"));
+        let data = serde_json::json!({"b": [1], "a": "00906ED50000"});
+        assert_eq!(
+            set_policy_data_statement("platforms", &data),
+            format!(
+                "set-policy-data platforms {}",
+                hex::encode(Sha256::digest(r#"{"a":"00906ED50000","b":[1]}"#))
+            )
+        );
+        let add = add_deny_list_statement("tdx.quote.body.mr_td", "aa");
+        verifier
+            .verify_operation(&add, Some(&sign(add.as_bytes())))
+            .unwrap();
+        let remove = remove_deny_list_statement("tdx.quote.body.mr_td", "aa");
+        assert!(verifier
+            .verify_operation(&remove, Some(&sign(add.as_bytes())))
+            .is_err());
+
+        assert!(PolicySignatureVerifier::new(&PolicySigningConfig {
+            admin_public_key: dir.path().join("missing.pem"),
+        })
+        .is_err());
+    }
+}
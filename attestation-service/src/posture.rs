@@ -0,0 +1,252 @@
+//! Security posture of the AS.
+//!
+//! Some defaults of the AS are insecure, and kept for compatibility. They
+//! are deprecated: the AS reports each of those it runs with when it
+//! starts, in the logs and in the service info of the gRPC server:
+//! - `sample_verifier`: evidence of the sample TEE, which anyone can forge,
+//!   is verified. Turned off with `"sample_verifier": false`.
+//! - `plaintext_listener`: the gRPC server serves a TCP or REST listener
+//!   without TLS, or CoAP or metrics, which it serves in plain text.
+//! - `permissive_default_policy`: requests that no `default_policies` entry
+//!   applies to are evaluated with the built-in `default` policy, which
+//!   accepts every claim without reference values.
+//! - `debug_tees_allowed`: evidence of debuggable TEEs, whose memory the
+//!   host can read, is accepted. Rejected with `"deny_debug_tees": true`.
+//!
+//! Others are insecure settings that are off by default:
+//! - `tdx_td_reports`: TDX evidence with a TD report, which the AS cannot
+//!   check the MAC of, is accepted in place of a quote.
+//!
+//! With `strict_security` in the AS config, the AS refuses to start with
+//! any of them.
+
+use crate::config::Config;
+use crate::policy_engine::DefaultPolicy;
+use crate::verifier::canonical;
+use anyhow::*;
+use kbs_types::Tee;
+use serde_json::Value;
+
+/// The built-in `default` policy of the OPA policy engine.
+const BUILT_IN_DEFAULT_POLICY: &str = include_str!("policy_engine/opa/default_policy.rego");
+
+/// An insecure setting the AS runs with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum Finding {
+    SampleVerifier,
+    PlaintextListener,
+    PermissiveDefaultPolicy,
+    DebugTeesAllowed,
+    TdxTdReports,
+}
+
+impl Finding {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::SampleVerifier => {
+                "the sample verifier accepts forged evidence, set `sample_verifier` to false"
+            }
+            Self::PlaintextListener => {
+                "a TCP listener without `tls`, or the CoAP listener, serves in plain text"
+            }
+            Self::PermissiveDefaultPolicy => {
+                "the built-in default policy accepts claims without reference values, \
+                 set a `default` policy or `default_policies` for every TEE"
+            }
+            Self::DebugTeesAllowed => {
+                "evidence of debuggable TEEs is accepted, set `deny_debug_tees`"
+            }
+            Self::TdxTdReports => {
+                "unsigned TD reports are accepted in place of TDX quotes, unset `tdx_td_reports`"
+            }
+        }
+    }
+}
+
+/// The insecure settings the AS runs with.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SecurityPosture {
+    /// Sorted, without duplicates.
+    pub findings: Vec<Finding>,
+}
+
+impl SecurityPosture {
+    /// The insecure settings of `config` alone, leaving out the policies
+    /// and the listeners.
+    pub fn of_config(config: &Config) -> Self {
+        let mut posture = Self::default();
+        if config.sample_verifier {
+            posture.add(Finding::SampleVerifier);
+        }
+        if !config.deny_debug_tees {
+            posture.add(Finding::DebugTeesAllowed);
+        }
+        if config.tdx_td_reports {
+            posture.add(Finding::TdxTdReports);
+        }
+        posture
+    }
+
+    pub fn add(&mut self, finding: Finding) {
+        if let Err(index) = self.findings.binary_search(&finding) {
+            self.findings.insert(index, finding);
+        }
+    }
+
+    pub fn is_secure(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Log a deprecation warning for each finding.
+    pub fn log(&self) {
+        for finding in &self.findings {
+            warn!(
+                "Insecure setting `{finding}` is deprecated: {}",
+                finding.description()
+            );
+        }
+    }
+
+    /// Fail with every finding if `strict`.
+    pub fn enforce(&self, strict: bool) -> Result<()> {
+        if strict && !self.is_secure() {
+            let findings = self
+                .findings
+                .iter()
+                .map(|finding| format!("`{finding}`: {}", finding.description()))
+                .collect::<Vec<_>>();
+            bail!(
+                "strict_security refuses insecure settings: {}",
+                findings.join("; ")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Whether the built-in default policy is evaluated for the requests of
+/// some TEE, given the text of the `default` policy, if any.
+pub(crate) fn permissive_default_policy(config: &Config, default_policy: Option<&str>) -> bool {
+    let falls_back = !config
+        .default_policies
+        .iter()
+        .any(DefaultPolicy::applies_to_all);
+    falls_back && default_policy == Some(BUILT_IN_DEFAULT_POLICY)
+}
+
+/// Check that the evidence of `tee` is verified under `config`.
+pub(crate) fn check_tee(config: &Config, tee: &Tee) -> Result<()> {
+    if matches!(tee, Tee::Sample) && !config.sample_verifier {
+        bail!("The sample verifier is turned off by `sample_verifier`");
+    }
+    Ok(())
+}
+
+/// Check the flattened `claims` of the evidence of `tee` against the
+/// settings of `config`.
+pub(crate) fn check_claims(config: &Config, tee: &str, claims: &Value) -> Result<()> {
+    if config.deny_debug_tees && canonical::get("debug", tee, claims) == Some(Value::Bool(true)) {
+        bail!("The TEE is debuggable, which `deny_debug_tees` denies");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_posture() {
+        let mut config = Config::default();
+        let mut posture = SecurityPosture::of_config(&config);
+        assert_eq!(
+            posture.findings,
+            [Finding::SampleVerifier, Finding::DebugTeesAllowed]
+        );
+        posture.add(Finding::PlaintextListener);
+        posture.add(Finding::SampleVerifier);
+        assert_eq!(
+            posture.findings,
+            [
+                Finding::SampleVerifier,
+                Finding::PlaintextListener,
+                Finding::DebugTeesAllowed
+            ]
+        );
+        assert_eq!(Finding::PlaintextListener.to_string(), "plaintext_listener");
+        posture.enforce(false).unwrap();
+        let err = posture.enforce(true).unwrap_err().to_string();
+        assert!(err.contains("`sample_verifier`"));
+        assert!(err.contains("`plaintext_listener`"));
+
+        config.sample_verifier = false;
+        config.deny_debug_tees = true;
+        let posture = SecurityPosture::of_config(&config);
+        assert!(posture.is_secure());
+        posture.enforce(true).unwrap();
+
+        config.tdx_td_reports = true;
+        let posture = SecurityPosture::of_config(&config);
+        assert_eq!(posture.findings, [Finding::TdxTdReports]);
+        assert_eq!(Finding::TdxTdReports.to_string(), "tdx_td_reports");
+    }
+
+    #[test]
+    fn test_permissive_default_policy() {
+        let mut config = Config::default();
+        assert!(permissive_default_policy(
+            &config,
+            Some(BUILT_IN_DEFAULT_POLICY)
+        ));
+        assert!(!permissive_default_policy(
+            &config,
+            Some("package policy\ndefault allow = false\n")
+        ));
+        assert!(!permissive_default_policy(&config, None));
+
+        config.default_policies = vec![DefaultPolicy {
+            policy_id: "tdx".to_string(),
+            tees: vec![Tee::Tdx],
+            ..Default::default()
+        }];
+        assert!(permissive_default_policy(
+            &config,
+            Some(BUILT_IN_DEFAULT_POLICY)
+        ));
+        config.default_policies.push(DefaultPolicy {
+            policy_id: "acme".to_string(),
+            tenants: vec!["acme".to_string()],
+            ..Default::default()
+        });
+        assert!(permissive_default_policy(
+            &config,
+            Some(BUILT_IN_DEFAULT_POLICY)
+        ));
+        config.default_policies.push(DefaultPolicy {
+            policy_id: "fallback".to_string(),
+            ..Default::default()
+        });
+        assert!(!permissive_default_policy(
+            &config,
+            Some(BUILT_IN_DEFAULT_POLICY)
+        ));
+    }
+
+    #[test]
+    fn test_checks() {
+        let mut config = Config::default();
+        check_tee(&config, &Tee::Sample).unwrap();
+        let debug = json!({"sgx.debug": true});
+        check_claims(&config, "sgx", &debug).unwrap();
+
+        config.sample_verifier = false;
+        config.deny_debug_tees = true;
+        assert!(check_tee(&config, &Tee::Sample).is_err());
+        check_tee(&config, &Tee::Tdx).unwrap();
+        assert!(check_claims(&config, "sgx", &debug).is_err());
+        check_claims(&config, "sgx", &json!({"sgx.debug": false})).unwrap();
+        check_claims(&config, "sgx", &json!({})).unwrap();
+    }
+}
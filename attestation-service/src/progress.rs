@@ -0,0 +1,72 @@
+//! Progress of the evaluation of evidence.
+//!
+//! Verifying large evidence, such as a long event log or IMA log, takes a
+//! while. Callers of [`crate::AttestationService::evaluate_with_options`]
+//! can pass a [`Progress`] in [`crate::EvaluateOptions::progress`] to be
+//! told of the steps of the evaluation as it reaches them, e.g. to stream
+//! them to the client before its token: the stages of the verifier
+//! pipeline, see [`crate::verifier::pipeline`], then the policy evaluation
+//! and the token issuance.
+
+use crate::verifier::pipeline::Stage;
+use std::fmt;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// Step of the evaluation of evidence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// The verifier entered a stage of its pipeline.
+    Verifier(Stage),
+    /// The claims are evaluated by the policies.
+    Policy,
+    /// The token is issued.
+    Issuance,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Verifier(stage) => write!(f, "{stage}"),
+            Step::Policy => write!(f, "policy"),
+            Step::Issuance => write!(f, "issuance"),
+        }
+    }
+}
+
+/// Reports the steps of an evaluation to a receiver.
+#[derive(Debug, Clone)]
+pub struct Progress(UnboundedSender<Step>);
+
+impl Progress {
+    /// A progress, and the receiver of its steps.
+    pub fn channel() -> (Self, UnboundedReceiver<Step>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (Self(sender), receiver)
+    }
+
+    /// Report `step`. Steps reported after the receiver is dropped are
+    /// ignored, the evaluation goes on.
+    pub fn report(&self, step: Step) {
+        let _ = self.0.send(step);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let (progress, mut steps) = Progress::channel();
+        progress.report(Step::Verifier(Stage::EventlogReplay));
+        progress.report(Step::Policy);
+        assert_eq!(
+            steps.try_recv().unwrap().to_string(),
+            "eventlog_replay".to_string()
+        );
+        assert_eq!(steps.try_recv().unwrap(), Step::Policy);
+
+        drop(steps);
+        progress.report(Step::Issuance);
+    }
+}
@@ -0,0 +1,156 @@
+//! Re-appraisal of the evidence of past attestations.
+//!
+//! A TCB recovery, new collateral or updated reference values can make
+//! evidence that was allowed before fail verification. With `evidence` in
+//! the `history` config, the AS keeps the evidence of every attestation, see
+//! [`crate::history`], and [`crate::AttestationService::reappraise`]
+//! verifies the evidence of the allowed attestations again, like
+//! [`crate::AttestationService::explain`], against the current collateral,
+//! reference values and policies. The report lists the attestations that
+//! would now be denied, with their `measurement`, so that the workloads
+//! can be asked to attest again.
+
+use as_types::{PolicyDecision, PolicyViolation};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::explain::{Check, VerificationReport};
+use crate::history::HistoryRecord;
+
+/// Name of the check of [`VerificationReport`] that fails when the AS is
+/// overloaded, rather than the evidence.
+const ADMISSION_CHECK: &str = "Admission";
+
+/// Which attestations of the history to appraise again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReappraisalFilter {
+    /// Only the attestations of this TEE.
+    pub tee: Option<String>,
+    /// Only the attestations since then.
+    pub since: Option<DateTime<Utc>>,
+}
+
+impl ReappraisalFilter {
+    pub(crate) fn matches(&self, record: &HistoryRecord) -> bool {
+        record.decision == PolicyDecision::Allow
+            && self.tee.iter().all(|tee| *tee == record.tee)
+            && self.since.iter().all(|since| record.timestamp >= *since)
+    }
+}
+
+/// A past attestation that would now be denied.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Reappraisal {
+    /// The `id` of the attestation in the history.
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub tee: String,
+    /// The `measurement` claim of the attestation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub measurement: Option<String>,
+    /// The check the evidence now fails.
+    pub failed_check: Check,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<PolicyViolation>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ReappraisalReport {
+    /// Allowed attestations whose evidence was appraised again.
+    pub reappraised: usize,
+    pub still_allowed: usize,
+    pub now_denied: Vec<Reappraisal>,
+    /// Allowed attestations recorded without their evidence.
+    pub without_evidence: usize,
+    /// Attestations that could not be appraised again.
+    pub errors: usize,
+}
+
+impl ReappraisalReport {
+    /// Add the outcome of the new verification of `record`.
+    pub(crate) fn add(&mut self, record: &HistoryRecord, verification: VerificationReport) {
+        self.reappraised += 1;
+        if verification.decision == PolicyDecision::Allow {
+            self.still_allowed += 1;
+            return;
+        }
+        match verification.checks.into_iter().find(|check| !check.passed) {
+            Some(check) if check.name != ADMISSION_CHECK => self.now_denied.push(Reappraisal {
+                id: record.id.clone(),
+                timestamp: record.timestamp,
+                tee: record.tee.clone(),
+                measurement: record.claims["measurement"].as_str().map(str::to_string),
+                failed_check: check,
+                violations: verification.violations,
+            }),
+            _ => self.errors += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(tee: &str, decision: PolicyDecision) -> HistoryRecord {
+        HistoryRecord {
+            id: "1".to_string(),
+            timestamp: Utc::now(),
+            tee: tee.to_string(),
+            decision,
+            tenant: None,
+            claims: json!({"measurement": "aa"}),
+            evidence: None,
+        }
+    }
+
+    fn verification(checks: &[(&str, bool)], decision: PolicyDecision) -> VerificationReport {
+        let mut report = VerificationReport::new("tdx".to_string());
+        report.checks = checks
+            .iter()
+            .map(|(name, passed)| Check {
+                name: name.to_string(),
+                passed: *passed,
+                details: String::new(),
+            })
+            .collect();
+        report.decision = decision;
+        report
+    }
+
+    #[test]
+    fn test_reappraisal_report() {
+        let filter = ReappraisalFilter {
+            tee: Some("tdx".to_string()),
+            since: None,
+        };
+        assert!(filter.matches(&record("tdx", PolicyDecision::Allow)));
+        assert!(!filter.matches(&record("tdx", PolicyDecision::Deny)));
+        assert!(!filter.matches(&record("snp", PolicyDecision::Allow)));
+
+        let mut report = ReappraisalReport::default();
+        let tdx = record("tdx", PolicyDecision::Allow);
+        report.add(
+            &tdx,
+            verification(&[("TEE evidence", true)], PolicyDecision::Allow),
+        );
+        report.add(
+            &tdx,
+            verification(
+                &[("TEE evidence", true), ("Policy", false)],
+                PolicyDecision::Deny,
+            ),
+        );
+        report.add(
+            &tdx,
+            verification(&[(ADMISSION_CHECK, false)], PolicyDecision::Deny),
+        );
+        assert_eq!(report.reappraised, 3);
+        assert_eq!(report.still_allowed, 1);
+        assert_eq!(report.errors, 1);
+        assert_eq!(report.now_denied.len(), 1);
+        assert_eq!(report.now_denied[0].failed_check.name, "Policy");
+        assert_eq!(report.now_denied[0].measurement.as_deref(), Some("aa"));
+    }
+}
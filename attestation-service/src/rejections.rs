@@ -0,0 +1,146 @@
+//! Telemetry of rejected evidence.
+//!
+//! After a guest image update, a wave of failed attestations can be
+//! malformed evidence as well as genuine policy denials. Every rejection is
+//! counted by TEE and by the stage that rejected it, see [`Rejections`],
+//! and logged as one line of JSON under the `attestation_service::rejections`
+//! target, e.g.
+//!
+//! ```json
+//! {"tee": "tdx", "stage": "eventlog_replay", "error": "RTMR values from TD quote is not equal with the values from EventLog"}
+//! ```
+
+use crate::verifier::pipeline::Stage;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, PoisonError};
+
+/// Target of the rejection logs, to filter them in or out with `RUST_LOG`.
+pub const REJECTIONS_LOG_TARGET: &str = "attestation_service::rejections";
+
+/// Where an attestation was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum RejectionStage {
+    /// The attestation could not be deserialized or decrypted, or asked
+    /// for something the AS does not do.
+    Request,
+    /// A stage of the verifier pipeline, see [`crate::verifier::pipeline`].
+    Parse,
+    CollateralVerify,
+    EventlogReplay,
+    Freshness,
+    ClaimsNormalize,
+    /// The verifier of a TEE without pipeline stages.
+    Verify,
+    /// The evidence was presented before.
+    Replay,
+    /// The evidence does not bind the init-data of the request, see
+    /// [`crate::init_data`].
+    InitData,
+    /// The evidence has a deny-listed claim value, see
+    /// [`crate::deny_list`].
+    DenyList,
+    ReferenceValues,
+    /// The policy denied the evidence, did not apply to it, or failed.
+    Policy,
+    /// The token or certificate could not be issued.
+    Issuance,
+}
+
+impl From<Stage> for RejectionStage {
+    fn from(stage: Stage) -> Self {
+        match stage {
+            Stage::Parse => Self::Parse,
+            Stage::CollateralVerify => Self::CollateralVerify,
+            Stage::EventlogReplay => Self::EventlogReplay,
+            Stage::Freshness => Self::Freshness,
+            Stage::ClaimsNormalize => Self::ClaimsNormalize,
+        }
+    }
+}
+
+/// Rejections of a TEE at a stage, since the AS started.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RejectionCount {
+    pub tee: String,
+    pub stage: RejectionStage,
+    pub count: u64,
+}
+
+/// Counters of the rejected attestations.
+#[derive(Debug, Default)]
+pub(crate) struct Rejections {
+    counts: Mutex<BTreeMap<(String, RejectionStage), u64>>,
+}
+
+impl Rejections {
+    /// Count and log the rejection of evidence of `tee` at `stage`, and
+    /// return the `error` that rejected it.
+    pub fn reject(&self, tee: &str, stage: RejectionStage, error: anyhow::Error) -> anyhow::Error {
+        *self
+            .counts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry((tee.to_string(), stage))
+            .or_default() += 1;
+        warn!(
+            target: REJECTIONS_LOG_TARGET,
+            "{}",
+            json!({
+                "tee": tee,
+                "stage": stage.to_string(),
+                "error": format!("{error:#}"),
+            })
+        );
+        error
+    }
+
+    /// The rejections by TEE and stage.
+    pub fn counts(&self) -> Vec<RejectionCount> {
+        self.counts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|((tee, stage), count)| RejectionCount {
+                tee: tee.clone(),
+                stage: *stage,
+                count: *count,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_reject() {
+        let rejections = Rejections::default();
+        let error = rejections.reject("tdx", Stage::EventlogReplay.into(), anyhow!("forged"));
+        assert_eq!(error.to_string(), "forged");
+        rejections.reject("tdx", RejectionStage::Policy, anyhow!("denied"));
+        rejections.reject("tdx", RejectionStage::Policy, anyhow!("denied"));
+        rejections.reject("snp", RejectionStage::Parse, anyhow!("malformed"));
+
+        let count = |tee: &str, stage, count| RejectionCount {
+            tee: tee.to_string(),
+            stage,
+            count,
+        };
+        assert_eq!(
+            rejections.counts(),
+            vec![
+                count("snp", RejectionStage::Parse, 1),
+                count("tdx", RejectionStage::EventlogReplay, 1),
+                count("tdx", RejectionStage::Policy, 2),
+            ]
+        );
+        assert_eq!(
+            RejectionStage::EventlogReplay.to_string(),
+            "eventlog_replay"
+        );
+    }
+}
@@ -0,0 +1,296 @@
+//! Replay protection.
+//!
+//! Deployments that do not bind evidence to a fresh nonce would issue a
+//! token for every submission of the same captured evidence. With
+//! `replay_protection` in the AS config, the AS remembers the SHA-256 of
+//! the signed part of the evidence it verified, such as the quote or the
+//! report (see [`crate::verifier::Verifier::signed_evidence`]), for a
+//! configurable window, and a repeated submission is either flagged with
+//! the `replayed` token claim or rejected. Re-encoding the evidence, e.g.
+//! reordering its JSON fields, does not make it new.
+//!
+//! A repeated submission with the same `tee-pubkey` shortly after the
+//! first one is an idempotent retry, e.g. after a lost response, and is not
+//! a replay: the token is bound to the same key.
+//!
+//! Digests are kept in memory, so each AS instance has its own window, and
+//! expire in the order they were recorded.
+
+use anyhow::Result;
+use kbs_types::TeePubKey;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const DEFAULT_RETRY_SECS: u64 = 30;
+
+fn default_retry_secs() -> u64 {
+    DEFAULT_RETRY_SECS
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReplayConfig {
+    /// How long the digests of verified evidence are remembered, in seconds.
+    pub window_secs: u64,
+
+    /// How long after the first submission a submission with the same
+    /// `tee-pubkey` is an idempotent retry, in seconds.
+    #[serde(default = "default_retry_secs")]
+    pub retry_secs: u64,
+
+    #[serde(default)]
+    pub action: ReplayAction,
+}
+
+/// What to do with replayed evidence.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplayAction {
+    /// Issue the token, with the `replayed` claim set.
+    #[default]
+    Flag,
+    /// Reject the evidence with a [`Replayed`] error.
+    Reject,
+}
+
+/// The evidence was already verified within the replay window.
+#[derive(Debug)]
+pub struct Replayed {
+    /// Time since the evidence was first verified.
+    pub first_seen: Duration,
+}
+
+impl fmt::Display for Replayed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Evidence replayed, first verified {}s ago",
+            self.first_seen.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for Replayed {}
+
+struct Seen {
+    at: Instant,
+    tee_pubkey: String,
+}
+
+/// The digests within the window, with the order they were recorded in.
+#[derive(Default)]
+struct Window {
+    seen: HashMap<Vec<u8>, Seen>,
+    order: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl Window {
+    /// Forget the digests recorded `window` or longer before `now`, oldest
+    /// first, and return how many.
+    fn expire(&mut self, now: Instant, window: Duration) -> usize {
+        let mut expired = 0;
+        while let Some((at, _)) = self.order.front() {
+            if now.duration_since(*at) < window {
+                break;
+            }
+            let Some((at, digest)) = self.order.pop_front() else {
+                break;
+            };
+            if self.seen.get(&digest).is_some_and(|s| s.at == at) {
+                self.seen.remove(&digest);
+                expired += 1;
+            }
+        }
+        expired
+    }
+}
+
+/// Digests of the evidence verified within the replay window.
+pub(crate) struct SeenEvidence {
+    config: ReplayConfig,
+    window: Mutex<Window>,
+}
+
+impl SeenEvidence {
+    pub fn new(config: ReplayConfig) -> Self {
+        Self {
+            config,
+            window: Mutex::new(Window::default()),
+        }
+    }
+
+    /// Record the signed part of verified evidence, for a TEE key, and
+    /// return whether it is replayed. Fails with [`Replayed`] if replays are
+    /// rejected.
+    pub fn check(&self, signed_evidence: &[u8], tee_pubkey: &TeePubKey) -> Result<bool> {
+        self.check_at(signed_evidence, tee_pubkey, Instant::now(), true)
+    }
+
+    /// Whether the signed part of verified evidence is replayed, like
+    /// [`SeenEvidence::check`], without recording it.
+    pub fn replayed(&self, signed_evidence: &[u8], tee_pubkey: &TeePubKey) -> Result<bool> {
+        self.check_at(signed_evidence, tee_pubkey, Instant::now(), false)
+    }
+
+    /// Forget the digests out of the replay window, and return how many.
+    pub fn prune(&self) -> Result<usize> {
+        self.prune_at(Instant::now())
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Window>> {
+        self.window
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Replay store is poisoned"))
+    }
+
+    fn prune_at(&self, now: Instant) -> Result<usize> {
+        let window = Duration::from_secs(self.config.window_secs);
+        Ok(self.lock()?.expire(now, window))
+    }
+
+    fn check_at(
+        &self,
+        signed_evidence: &[u8],
+        tee_pubkey: &TeePubKey,
+        now: Instant,
+        record: bool,
+    ) -> Result<bool> {
+        let window = Duration::from_secs(self.config.window_secs);
+        let retry = Duration::from_secs(self.config.retry_secs);
+        let digest = Sha256::digest(signed_evidence).to_vec();
+        let tee_pubkey = serde_json::to_string(tee_pubkey)?;
+
+        let mut seen = self.lock()?;
+        seen.expire(now, window);
+
+        let Some(first) = seen.seen.get(&digest) else {
+            if !record {
+                return Ok(false);
+            }
+            seen.order.push_back((now, digest.clone()));
+            seen.seen.insert(
+                digest,
+                Seen {
+                    at: now,
+                    tee_pubkey,
+                },
+            );
+            return Ok(false);
+        };
+        let first_seen = now.duration_since(first.at);
+        if first.tee_pubkey == tee_pubkey && first_seen < retry {
+            return Ok(false);
+        }
+
+        warn!(
+            "Evidence replayed, first verified {}s ago",
+            first_seen.as_secs()
+        );
+        match self.config.action {
+            ReplayAction::Flag => Ok(true),
+            ReplayAction::Reject => Err(Replayed { first_seen }.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tee_pubkey(k_mod: &str) -> TeePubKey {
+        TeePubKey {
+            kty: "RSA".to_string(),
+            alg: "RSA1_5".to_string(),
+            k_mod: k_mod.to_string(),
+            k_exp: "AQAB".to_string(),
+        }
+    }
+
+    fn seen_evidence(action: ReplayAction) -> SeenEvidence {
+        SeenEvidence::new(ReplayConfig {
+            window_secs: 600,
+            retry_secs: 30,
+            action,
+        })
+    }
+
+    #[test]
+    fn test_flag() {
+        let seen = seen_evidence(ReplayAction::Flag);
+        let start = Instant::now();
+        let (n, m) = (tee_pubkey("n"), tee_pubkey("m"));
+
+        // Not recorded when only checked.
+        assert!(!seen.check_at(b"quote", &m, start, false).unwrap());
+        assert!(!seen.check_at(b"quote", &n, start, true).unwrap());
+        // Idempotent retry.
+        assert!(!seen
+            .check_at(b"quote", &n, start + Duration::from_secs(10), true)
+            .unwrap());
+        // Same evidence for another key.
+        assert!(seen
+            .check_at(b"quote", &m, start + Duration::from_secs(10), true)
+            .unwrap());
+        // Same key, after the retry period.
+        assert!(seen
+            .check_at(b"quote", &n, start + Duration::from_secs(60), true)
+            .unwrap());
+        assert!(!seen
+            .check_at(b"other quote", &n, start + Duration::from_secs(60), true)
+            .unwrap());
+        // Out of the window.
+        assert!(!seen
+            .check_at(b"quote", &n, start + Duration::from_secs(600), true)
+            .unwrap());
+        // Recorded again when it expired.
+        assert!(seen
+            .check_at(b"quote", &m, start + Duration::from_secs(700), true)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_reject() {
+        let seen = seen_evidence(ReplayAction::Reject);
+        let start = Instant::now();
+        let n = tee_pubkey("n");
+
+        assert!(!seen.check_at(b"quote", &n, start, true).unwrap());
+        let e = seen
+            .check_at(b"quote", &n, start + Duration::from_secs(60), true)
+            .unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<Replayed>().unwrap().first_seen,
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn test_prune() {
+        let seen = seen_evidence(ReplayAction::Flag);
+        let start = Instant::now();
+        let n = tee_pubkey("n");
+        seen.check_at(b"quote", &n, start, true).unwrap();
+        seen.check_at(b"other quote", &n, start + Duration::from_secs(300), true)
+            .unwrap();
+
+        assert_eq!(seen.prune_at(start + Duration::from_secs(600)).unwrap(), 1);
+        assert_eq!(seen.prune_at(start + Duration::from_secs(600)).unwrap(), 0);
+        assert_eq!(seen.prune_at(start + Duration::from_secs(900)).unwrap(), 1);
+        assert!(seen.lock().unwrap().order.is_empty());
+    }
+
+    #[test]
+    fn test_config() {
+        let config: ReplayConfig = serde_json::from_str(r#"{"window_secs": 3600}"#).unwrap();
+        assert_eq!(
+            config,
+            ReplayConfig {
+                window_secs: 3600,
+                retry_secs: DEFAULT_RETRY_SECS,
+                action: ReplayAction::Flag,
+            }
+        );
+    }
+}
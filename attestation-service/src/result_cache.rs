@@ -0,0 +1,420 @@
+//! Caching of attestation results, and re-attestation.
+//!
+//! Verifying evidence checks signature chains and may fetch collateral,
+//! which is the most expensive part of an attestation. With `result_cache`
+//! in the AS config, the AS remembers the claims of the evidence it
+//! verified for `ttl_secs`, by the SHA-256 of the TEE, the nonce, the
+//! verification options, the `tee-pubkey` and the evidence, and does not
+//! verify the same evidence again within the TTL. The claims are still
+//! appraised by the policies with the reference values of each request, so
+//! a new policy revision or new reference values apply at once. Cached
+//! evidence is not checked for freshness again, so the TTL should be
+//! shorter than the freshness window of the evidence.
+//!
+//! The AS also remembers, by `jti`, the appraisal of every JWT token it
+//! issued: the policy input of each policy, and the revision of the
+//! policies and reference values, see [`revision`]. A client with a token
+//! that has not expired can then have it refreshed by
+//! [`crate::AttestationService::reattest`], without new evidence: the
+//! token is re-issued with the same claims if the digests of its policies
+//! and the reference values looked up for its claims are the same as when
+//! it was issued, and the deny-list has not changed since, see
+//! [`crate::deny_list`]. Otherwise, or once `reattest_secs` have passed since the
+//! evidence was verified, the refresh fails with an [`AttestAgain`] error,
+//! and the client must attest with new evidence. Refreshed tokens run no
+//! post-verification hooks and issue no certificates.
+//!
+//! Results are kept in memory, so each AS instance has its own cache, and
+//! only refreshes the tokens it issued.
+
+use crate::policy_engine::PolicyResult;
+use crate::ttl_cache::TtlCache;
+use anyhow::Result;
+use as_types::TeeEvidenceParsedClaim;
+use kbs_types::Attestation;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+const DEFAULT_TTL_SECS: u64 = 60;
+const DEFAULT_MAX_ENTRIES: usize = 10_000;
+const DEFAULT_REATTEST_SECS: u64 = 3600;
+
+fn default_ttl_secs() -> u64 {
+    DEFAULT_TTL_SECS
+}
+
+fn default_max_entries() -> usize {
+    DEFAULT_MAX_ENTRIES
+}
+
+fn default_reattest_secs() -> u64 {
+    DEFAULT_REATTEST_SECS
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ResultCacheConfig {
+    /// How long the claims of verified evidence are remembered, in
+    /// seconds.
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+
+    /// How many verified evidence and issued tokens are remembered at
+    /// most, each. The oldest one is forgotten to make room for a new one.
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+
+    /// How long after their evidence was verified tokens can be
+    /// refreshed, in seconds. Tokens are never refreshed if 0.
+    #[serde(default = "default_reattest_secs")]
+    pub reattest_secs: u64,
+}
+
+impl Default for ResultCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: DEFAULT_TTL_SECS,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            reattest_secs: DEFAULT_REATTEST_SECS,
+        }
+    }
+}
+
+/// A token cannot be refreshed, the client must attest with new evidence.
+#[derive(Debug)]
+pub struct AttestAgain {
+    pub reason: String,
+}
+
+impl AttestAgain {
+    pub(crate) fn new(reason: &str) -> Self {
+        Self {
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AttestAgain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The token cannot be refreshed, attest again: {}",
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for AttestAgain {}
+
+/// The outcome of the verifier of cached evidence.
+#[derive(Clone)]
+pub(crate) struct Verified {
+    pub claims: TeeEvidenceParsedClaim,
+    pub warnings: Vec<String>,
+}
+
+/// What a token issued from verified evidence was appraised with, to
+/// refresh it.
+#[derive(Clone)]
+pub(crate) struct Appraisal {
+    /// The policy input of each policy, by policy ID, that reference
+    /// values were looked up for.
+    pub inputs: Vec<(String, String)>,
+    /// See [`revision`].
+    pub revision: String,
+    /// Changes of the deny-list when the evidence was checked against it.
+    pub deny_list_generation: u64,
+    pub policies: Vec<PolicyResult>,
+    /// The claims of the token, but those the token broker adds.
+    pub claims: Value,
+}
+
+/// Claims of verified evidence and appraisals of issued tokens.
+pub(crate) struct ResultCache {
+    config: ResultCacheConfig,
+    verifications: Mutex<TtlCache<Vec<u8>, Verified>>,
+    /// Counted from when the evidence was verified.
+    appraisals: Mutex<TtlCache<String, Appraisal>>,
+}
+
+impl ResultCache {
+    pub fn new(config: ResultCacheConfig) -> Self {
+        Self {
+            verifications: Mutex::new(TtlCache::new(
+                Duration::from_secs(config.ttl_secs),
+                config.max_entries,
+            )),
+            appraisals: Mutex::new(TtlCache::new(
+                Duration::from_secs(config.reattest_secs),
+                config.max_entries,
+            )),
+            config,
+        }
+    }
+
+    /// The outcome of the verifier of the evidence of `digest`, see
+    /// [`evidence_digest`], and when it was verified, if within the TTL.
+    pub fn verified(&self, digest: &[u8]) -> Option<(Verified, Instant)> {
+        self.verified_at(digest, Instant::now())
+    }
+
+    /// Remember the outcome of the verifier of the evidence of `digest`,
+    /// verified now.
+    pub fn record_verified(&self, digest: Vec<u8>, verified: Verified) -> Instant {
+        let now = Instant::now();
+        self.record_verified_at(digest, verified, now);
+        now
+    }
+
+    /// The appraisal of the token of `jti`, and when its evidence was
+    /// verified, or an [`AttestAgain`] error if it cannot be refreshed.
+    pub fn appraisal(&self, jti: &str) -> Result<(Appraisal, Instant)> {
+        self.appraisal_at(jti, Instant::now())
+    }
+
+    /// Remember the appraisal of the token of `jti`, whose evidence was
+    /// verified at `verified`.
+    pub fn record_appraisal(&self, jti: String, appraisal: Appraisal, verified: Instant) {
+        self.record_appraisal_at(jti, appraisal, verified, Instant::now())
+    }
+
+    fn verified_at(&self, digest: &[u8], now: Instant) -> Option<(Verified, Instant)> {
+        let verifications = self
+            .verifications
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        verifications
+            .get(digest, now)
+            .map(|(at, verified)| (verified.clone(), at))
+    }
+
+    fn record_verified_at(&self, digest: Vec<u8>, verified: Verified, now: Instant) {
+        self.verifications
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(digest, verified, now);
+    }
+
+    fn appraisal_at(&self, jti: &str, now: Instant) -> Result<(Appraisal, Instant)> {
+        let appraisals = self
+            .appraisals
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if appraisals.peek(jti).is_none() {
+            return Err(AttestAgain::new("the token is unknown to this AS instance").into());
+        }
+        let (verified, appraisal) = appraisals.get(jti, now).ok_or_else(|| {
+            AttestAgain::new(&format!(
+                "its evidence was verified more than {}s ago",
+                self.config.reattest_secs
+            ))
+        })?;
+        Ok((appraisal.clone(), verified))
+    }
+
+    fn record_appraisal_at(
+        &self,
+        jti: String,
+        appraisal: Appraisal,
+        verified: Instant,
+        now: Instant,
+    ) {
+        if self.config.reattest_secs == 0 {
+            return;
+        }
+        self.appraisals
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert_since(jti, appraisal, verified, now);
+    }
+}
+
+/// Digest of the evidence of `attestation` for the TEE `tee`, bound to
+/// `nonce`, verified with `options`, e.g. the report data mode and the
+/// detail level of the request.
+pub(crate) fn evidence_digest(
+    tee: &str,
+    nonce: &str,
+    options: &str,
+    attestation: &Attestation,
+) -> Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    for part in [
+        tee,
+        nonce,
+        options,
+        &serde_json::to_string(&attestation.tee_pubkey)?,
+        &attestation.tee_evidence,
+    ] {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part.as_bytes());
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Revision of the policies and reference values of an appraisal: the
+/// SHA-256 of the digest of each policy, by policy ID, and of the
+/// reference values looked up for its input.
+pub(crate) fn revision<'a>(
+    policies: impl IntoIterator<Item = (&'a str, &'a str, &'a HashMap<String, Vec<String>>)>,
+) -> Result<String> {
+    let mut hasher = Sha256::new();
+    for (policy_id, policy_digest, reference_data) in policies {
+        let reference_data =
+            serde_json::to_string(&reference_data.iter().collect::<BTreeMap<_, _>>())?;
+        for part in [policy_id, policy_digest, &reference_data] {
+            hasher.update((part.len() as u64).to_be_bytes());
+            hasher.update(part.as_bytes());
+        }
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kbs_types::TeePubKey;
+    use serde_json::json;
+
+    fn attestation(tee_evidence: &str) -> Attestation {
+        Attestation {
+            tee_pubkey: TeePubKey {
+                kty: "RSA".to_string(),
+                alg: "RSA1_5".to_string(),
+                k_mod: "n".to_string(),
+                k_exp: "AQAB".to_string(),
+            },
+            tee_evidence: tee_evidence.to_string(),
+        }
+    }
+
+    fn verified(measurement: &str) -> Verified {
+        Verified {
+            claims: json!({ "measurement": measurement }).into(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn appraisal() -> Appraisal {
+        Appraisal {
+            inputs: vec![("default".to_string(), "{}".to_string())],
+            revision: "00".to_string(),
+            deny_list_generation: 0,
+            policies: Vec::new(),
+            claims: json!({ "tcb-status": {} }),
+        }
+    }
+
+    #[test]
+    fn test_verifications() {
+        let cache = ResultCache::new(ResultCacheConfig {
+            ttl_secs: 30,
+            max_entries: 2,
+            reattest_secs: 60,
+        });
+        let start = Instant::now();
+        let digests: Vec<_> = ["first", "second", "third"]
+            .into_iter()
+            .map(|quote| evidence_digest("tdx", "nonce", "Exact", &attestation(quote)).unwrap())
+            .collect();
+        assert!(cache.verified_at(&digests[0], start).is_none());
+        for (i, digest) in digests.iter().enumerate() {
+            let at = start + Duration::from_secs(i as u64);
+            cache.record_verified_at(digest.clone(), verified(&i.to_string()), at);
+        }
+
+        // The oldest evidence was forgotten.
+        let now = start + Duration::from_secs(5);
+        assert!(cache.verified_at(&digests[0], now).is_none());
+        let (second, at) = cache.verified_at(&digests[1], now).unwrap();
+        assert_eq!(second.claims.0, json!({ "measurement": "1" }));
+        assert_eq!(at, start + Duration::from_secs(1));
+        // After the TTL.
+        assert!(cache
+            .verified_at(&digests[2], start + Duration::from_secs(32))
+            .is_none());
+
+        // The same evidence bound to another nonce, or of another TEE.
+        let quote = attestation("first");
+        assert_ne!(
+            evidence_digest("tdx", "nonce", "Exact", &quote).unwrap(),
+            evidence_digest("tdx", "other nonce", "Exact", &quote).unwrap()
+        );
+        assert_ne!(
+            evidence_digest("tdx", "nonce", "Exact", &quote).unwrap(),
+            evidence_digest("sgx", "nonce", "Exact", &quote).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_appraisals() {
+        let cache = ResultCache::new(ResultCacheConfig {
+            ttl_secs: 30,
+            max_entries: 2,
+            reattest_secs: 60,
+        });
+        let start = Instant::now();
+        cache.record_appraisal_at("jti".to_string(), appraisal(), start, start);
+        let (_, verified) = cache
+            .appraisal_at("jti", start + Duration::from_secs(59))
+            .unwrap();
+        assert_eq!(verified, start);
+        // A refreshed token has the verification time of the first one.
+        cache.record_appraisal_at(
+            "refreshed".to_string(),
+            appraisal(),
+            verified,
+            start + Duration::from_secs(59),
+        );
+        let e = cache
+            .appraisal_at("refreshed", start + Duration::from_secs(60))
+            .unwrap_err();
+        assert!(e.is::<AttestAgain>());
+        assert!(cache
+            .appraisal_at("unknown", start)
+            .unwrap_err()
+            .is::<AttestAgain>());
+
+        let cache = ResultCache::new(ResultCacheConfig {
+            reattest_secs: 0,
+            ..Default::default()
+        });
+        cache.record_appraisal_at("jti".to_string(), appraisal(), start, start);
+        assert!(cache.appraisal_at("jti", start).is_err());
+    }
+
+    #[test]
+    fn test_revision() {
+        let reference_data = HashMap::from([
+            ("mr_td".to_string(), vec!["aa".to_string()]),
+            ("mr_seam".to_string(), vec!["bb".to_string()]),
+        ]);
+        let first = revision([("default", "00ff", &reference_data)]).unwrap();
+        assert_eq!(
+            first,
+            revision([("default", "00ff", &reference_data.clone())]).unwrap()
+        );
+
+        // Another policy revision, or other reference values.
+        assert_ne!(
+            first,
+            revision([("default", "ff00", &reference_data)]).unwrap()
+        );
+        let mut updated = reference_data.clone();
+        updated.insert(
+            "mr_td".to_string(),
+            vec!["aa".to_string(), "cc".to_string()],
+        );
+        assert_ne!(first, revision([("default", "00ff", &updated)]).unwrap());
+    }
+
+    #[test]
+    fn test_config() {
+        let config: ResultCacheConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, ResultCacheConfig::default());
+    }
+}
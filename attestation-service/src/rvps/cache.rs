@@ -0,0 +1,159 @@
+//! Local cache of the reference values of a remote RVPS.
+//!
+//! With a remote RVPS, every attestation queries it over gRPC for the
+//! reference values of each of its claims, which adds a round trip per
+//! claim to the latency of attestations, and fails them all while the RVPS
+//! is unreachable. With `rvps_cache` in the AS config, the AS keeps the
+//! answers of the RVPS, including that there is no reference value of a
+//! claim, for a TTL, by claim name:
+//!
+//! ```json
+//! "rvps_cache": { "ttl_secs": 60, "max_entries": 10000 }
+//! ```
+//!
+//! Reference values registered through the AS clear the cache, but those
+//! registered directly with the RVPS are only seen by the AS once the
+//! cached ones expire. The cache is in memory, so each AS instance has its
+//! own.
+
+use crate::ttl_cache::TtlCache;
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::TrustedDigest;
+
+const DEFAULT_TTL_SECS: u64 = 60;
+const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+fn default_ttl_secs() -> u64 {
+    DEFAULT_TTL_SECS
+}
+
+fn default_max_entries() -> usize {
+    DEFAULT_MAX_ENTRIES
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RvpsCacheConfig {
+    /// How long the reference values of a claim are cached, in seconds.
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+
+    /// How many claims are cached at most. The reference values cached the
+    /// longest ago are dropped to make room for new ones.
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+}
+
+impl Default for RvpsCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: DEFAULT_TTL_SECS,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+}
+
+/// Reference values within their TTL, by claim name.
+#[cfg_attr(not(feature = "rvps-grpc"), allow(dead_code))]
+pub(crate) struct ReferenceValueCache {
+    entries: Mutex<TtlCache<String, Option<TrustedDigest>>>,
+}
+
+#[cfg_attr(not(feature = "rvps-grpc"), allow(dead_code))]
+impl ReferenceValueCache {
+    pub fn new(config: RvpsCacheConfig) -> Self {
+        Self {
+            entries: Mutex::new(TtlCache::new(
+                Duration::from_secs(config.ttl_secs),
+                config.max_entries,
+            )),
+        }
+    }
+
+    /// The cached reference values of `name`, `None` if they are not
+    /// cached or expired, `Some(None)` if there are none.
+    pub fn get(&self, name: &str) -> Result<Option<Option<TrustedDigest>>> {
+        self.get_at(name, Instant::now())
+    }
+
+    /// Cache the reference values of `name`, or that there are none.
+    pub fn insert(&self, name: &str, digest: Option<TrustedDigest>) -> Result<()> {
+        self.insert_at(name, digest, Instant::now())
+    }
+
+    /// Drop all the cached reference values.
+    pub fn clear(&self) -> Result<()> {
+        self.lock()?.clear();
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, TtlCache<String, Option<TrustedDigest>>>> {
+        self.entries
+            .lock()
+            .map_err(|_| anyhow!("Reference value cache is poisoned"))
+    }
+
+    fn get_at(&self, name: &str, now: Instant) -> Result<Option<Option<TrustedDigest>>> {
+        Ok(self
+            .lock()?
+            .get(name, now)
+            .map(|(_, digest)| digest.clone()))
+    }
+
+    fn insert_at(&self, name: &str, digest: Option<TrustedDigest>, now: Instant) -> Result<()> {
+        self.lock()?.insert(name.to_string(), digest, now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(name: &str) -> TrustedDigest {
+        TrustedDigest {
+            name: name.to_string(),
+            hash_values: vec!["00ff".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cache() {
+        let cache = ReferenceValueCache::new(RvpsCacheConfig {
+            ttl_secs: 60,
+            max_entries: 2,
+        });
+        let start = Instant::now();
+
+        assert_eq!(cache.get_at("mr_td", start).unwrap(), None);
+        cache
+            .insert_at("mr_td", Some(digest("mr_td")), start)
+            .unwrap();
+        cache.insert_at("mr_seam", None, start).unwrap();
+        assert_eq!(
+            cache.get_at("mr_td", start).unwrap(),
+            Some(Some(digest("mr_td")))
+        );
+        // That there are no reference values is cached too.
+        assert_eq!(cache.get_at("mr_seam", start).unwrap(), Some(None));
+
+        // The oldest entry makes room for a new one.
+        let later = start + Duration::from_secs(1);
+        cache
+            .insert_at("rtmr_0", Some(digest("rtmr_0")), later)
+            .unwrap();
+        assert_eq!(cache.lock().unwrap().len(), 2);
+
+        // Expired.
+        let expired = later + Duration::from_secs(60);
+        assert_eq!(cache.get_at("rtmr_0", expired).unwrap(), None);
+
+        cache.insert("mr_td", None).unwrap();
+        cache.clear().unwrap();
+        assert_eq!(cache.get("mr_td").unwrap(), None);
+    }
+}
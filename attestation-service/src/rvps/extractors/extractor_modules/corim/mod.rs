@@ -0,0 +1,764 @@
+//! Reference values of TDX guests from signed CoRIM bundles, in which
+//! vendors ship the golden measurements of their images and firmware.
+//!
+//! A signed CoRIM is a COSE_Sign1 whose payload is a tagged CoRIM, the
+//! certificate chain of its signer, leaf first, in the `x5chain` header.
+//! The chain must end at one of the `corim` trust anchors of the AS config,
+//! see [`crate::verifier::anchors`], and the CoRIM is signed with ES256 or
+//! ES384. Its CoMID tags give the reference values, of the measurements of
+//! their reference triples, named by a text `mkey`:
+//!
+//! - `mrtd`, `mrseam`, `mrsignerseam`, `mrconfigid`, `mrowner` and
+//!   `mrownerconfig`: the digests of the fields of the TD quote body, e.g.
+//!   `tdx.quote.body.mr_td`;
+//! - `rtmr.<component>`: the digests of a component measured into the
+//!   RTMRs, as the CCEL claims name it, e.g. `rtmr.kernel` for
+//!   `tdx.ccel.kernel`;
+//! - `tcb_svn.<n>`: the SVN of the TCB component `n`, e.g.
+//!   `tdx.quote.body.tcb_svn.2`. A minimum SVN, tagged `min-svn`, is
+//!   compared with the `semver>=` operator.
+//!
+//! Measurements of other keys are ignored. The measurements of the same
+//! key are alternatives of one reference value, which expires with the
+//! `not-after` validity of the CoRIM, or after 12 months. The reference
+//! values are marked with `provenance: corim`, the ID of the CoRIM and the
+//! trust anchor of its signer in their metadata.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::*;
+use base64::Engine;
+use cbor_diag::{ByteString, DataItem, IntegerWidth, Tag, TextString};
+use chrono::{DateTime, Months, TimeZone, Timelike, Utc};
+use openssl::{
+    asn1::Asn1Time,
+    bn::BigNum,
+    ec::EcKey,
+    ecdsa::EcdsaSig,
+    hash::{hash, MessageDigest},
+    x509::X509,
+};
+
+use crate::rvps::{Operator, ReferenceValue};
+use crate::verifier::anchors::TrustAnchor;
+
+use super::Extractor;
+
+const COSE_SIGN1_TAG: u64 = 18;
+const CORIM_TAG: u64 = 501;
+const COMID_TAG: u64 = 506;
+
+/// COSE header labels and algorithms.
+const COSE_ALG: i64 = 1;
+const COSE_X5CHAIN: i64 = 33;
+const COSE_ES256: i64 = -7;
+const COSE_ES384: i64 = -35;
+
+/// Keys of the CoRIM map.
+const CORIM_ID: i64 = 0;
+const CORIM_TAGS: i64 = 1;
+const CORIM_VALIDITY: i64 = 4;
+const VALIDITY_NOT_AFTER: i64 = 1;
+const EPOCH_TAG: u64 = 1;
+
+/// Keys of the CoMID map, down to the measurement values.
+const COMID_TRIPLES: i64 = 4;
+const REFERENCE_TRIPLES: i64 = 0;
+const MEASUREMENT_KEY: i64 = 0;
+const MEASUREMENT_VALUES: i64 = 1;
+const MVAL_SVN: i64 = 1;
+const MVAL_DIGESTS: i64 = 2;
+const SVN_TAG: u64 = 552;
+const MIN_SVN_TAG: u64 = 553;
+
+/// Hash algorithm of the SVN reference values.
+const SVN_ALG: &str = "svn";
+
+/// The reference value will be expired in the default time (months)
+const DEFAULT_EXPIRED_TIME: u32 = 12;
+
+/// Metadata entries of the extracted reference values.
+const PROVENANCE: (&str, &str) = ("provenance", "corim");
+const CORIM_ID_METADATA: &str = "corim_id";
+const TRUST_ANCHOR_METADATA: &str = "trust_anchor";
+
+/// Reference values of the measurement keys of TD quote fields.
+const QUOTE_MEASUREMENTS: &[(&str, &str)] = &[
+    ("mrtd", "tdx.quote.body.mr_td"),
+    ("mrseam", "tdx.quote.body.mr_seam"),
+    ("mrsignerseam", "tdx.quote.body.mrsigner_seam"),
+    ("mrconfigid", "tdx.quote.body.mr_config_id"),
+    ("mrowner", "tdx.quote.body.mr_owner"),
+    ("mrownerconfig", "tdx.quote.body.mr_owner_config"),
+];
+
+pub struct CorimExtractor {
+    anchors: Arc<Vec<TrustAnchor>>,
+}
+
+impl CorimExtractor {
+    /// An extractor of the CoRIMs signed under `anchors`.
+    pub fn new(anchors: Arc<Vec<TrustAnchor>>) -> Self {
+        Self { anchors }
+    }
+}
+
+fn byte_string(item: &DataItem) -> Option<&[u8]> {
+    match item {
+        DataItem::ByteString(ByteString { data, .. }) => Some(data),
+        _ => None,
+    }
+}
+
+fn text_string(item: &DataItem) -> Option<&str> {
+    match item {
+        DataItem::TextString(TextString { data, .. }) => Some(data),
+        _ => None,
+    }
+}
+
+fn integer(item: &DataItem) -> Option<i64> {
+    match item {
+        DataItem::Integer { value, .. } => i64::try_from(*value).ok(),
+        DataItem::Negative { value, .. } => i64::try_from(*value).ok().map(|value| -1 - value),
+        _ => None,
+    }
+}
+
+/// The item of `label` in the CBOR map `item`.
+fn field(item: &DataItem, label: i64) -> Option<&DataItem> {
+    match item {
+        DataItem::Map { data, .. } => data
+            .iter()
+            .find(|(key, _)| integer(key) == Some(label))
+            .map(|(_, value)| value),
+        _ => None,
+    }
+}
+
+/// `item` without its tag `tag`, if it has it.
+fn untag(item: DataItem, tag: u64) -> DataItem {
+    match item {
+        DataItem::Tag {
+            tag: Tag(t), value, ..
+        } if t == tag => *value,
+        item => item,
+    }
+}
+
+/// The reference value name of the measurement key `mkey`.
+fn reference_name(mkey: &str) -> Option<String> {
+    let mkey = mkey.to_ascii_lowercase();
+    if let Some((_, name)) = QUOTE_MEASUREMENTS.iter().find(|(key, _)| *key == mkey) {
+        return Some(name.to_string());
+    }
+    if let Some(component) = mkey.strip_prefix("rtmr.") {
+        return (!component.is_empty()).then(|| format!("tdx.ccel.{component}"));
+    }
+    let component = mkey.strip_prefix("tcb_svn.")?.parse::<u8>().ok()?;
+    Some(format!("tdx.quote.body.tcb_svn.{component}"))
+}
+
+/// The name of a digest algorithm of the named information registry.
+fn digest_alg(item: &DataItem) -> Result<&'static str> {
+    match (integer(item), text_string(item)) {
+        (Some(1), _) | (_, Some("sha-256")) => Ok("sha256"),
+        (Some(7), _) | (_, Some("sha-384")) => Ok("sha384"),
+        (Some(8), _) | (_, Some("sha-512")) => Ok("sha512"),
+        _ => bail!("unsupported digest algorithm {}", item.to_diag()),
+    }
+}
+
+/// A signed CoRIM.
+struct SignedCorim {
+    protected: Vec<u8>,
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+    alg: i64,
+    x5chain: Vec<Vec<u8>>,
+}
+
+impl SignedCorim {
+    fn parse(cose: &[u8]) -> Result<Self> {
+        let malformed = || anyhow!("Malformed signed CoRIM");
+        let item = cbor_diag::parse_bytes(cose).map_err(|_| malformed())?;
+        let DataItem::Array { data, .. } = untag(item, COSE_SIGN1_TAG) else {
+            bail!(malformed());
+        };
+        let [protected, unprotected, payload, signature] = data.as_slice() else {
+            bail!(malformed());
+        };
+        let protected = byte_string(protected).ok_or_else(malformed)?.to_vec();
+        let payload = byte_string(payload).ok_or_else(malformed)?.to_vec();
+        let signature = byte_string(signature).ok_or_else(malformed)?.to_vec();
+
+        let headers = cbor_diag::parse_bytes(&protected).map_err(|_| malformed())?;
+        let alg = field(&headers, COSE_ALG)
+            .and_then(integer)
+            .ok_or_else(|| anyhow!("The signed CoRIM has no algorithm"))?;
+        // The chain may be unprotected, as it is verified anyway.
+        let x5chain = match field(&headers, COSE_X5CHAIN).or(field(unprotected, COSE_X5CHAIN)) {
+            Some(DataItem::Array { data, .. }) => data
+                .iter()
+                .map(|cert| byte_string(cert).map(<[u8]>::to_vec))
+                .collect::<Option<_>>()
+                .ok_or_else(malformed)?,
+            Some(cert) => vec![byte_string(cert).ok_or_else(malformed)?.to_vec()],
+            None => bail!("The signed CoRIM has no certificate chain"),
+        };
+
+        Ok(Self {
+            protected,
+            payload,
+            signature,
+            alg,
+            x5chain,
+        })
+    }
+
+    /// The Sig_structure the signature is over.
+    fn to_be_signed(&self) -> Vec<u8> {
+        let byte_string = |data: &[u8]| {
+            DataItem::ByteString(ByteString {
+                data: data.to_vec(),
+                bitwidth: IntegerWidth::Unknown,
+            })
+        };
+        DataItem::Array {
+            data: vec![
+                DataItem::TextString(TextString {
+                    data: "Signature1".to_string(),
+                    bitwidth: IntegerWidth::Unknown,
+                }),
+                byte_string(&self.protected),
+                byte_string(&[]),
+                byte_string(&self.payload),
+            ],
+            bitwidth: Some(IntegerWidth::Unknown),
+        }
+        .to_bytes()
+    }
+
+    /// Verify the certificate chain up to one of `anchors` and the
+    /// signature with the leaf certificate, and return the fingerprint of
+    /// the anchor.
+    fn verify(&self, anchors: &[TrustAnchor]) -> Result<String> {
+        if anchors.is_empty() {
+            bail!("No trust anchor of CoRIM signers is configured");
+        }
+        let certs = self
+            .x5chain
+            .iter()
+            .map(|der| X509::from_der(der).context("Malformed CoRIM signer certificate"))
+            .collect::<Result<Vec<_>>>()?;
+        let now = Asn1Time::days_from_now(0)?;
+        for cert in &certs {
+            let valid =
+                cert.not_before().compare(&now)?.is_le() && cert.not_after().compare(&now)?.is_ge();
+            if !valid {
+                bail!("A CoRIM signer certificate is not valid now");
+            }
+        }
+        for (cert, issuer) in certs.iter().zip(certs.iter().skip(1)) {
+            if !cert.verify(&issuer.public_key()?)? {
+                bail!("Invalid CoRIM signer certificate chain");
+            }
+        }
+        let last = certs
+            .last()
+            .ok_or_else(|| anyhow!("Empty CoRIM signer chain"))?;
+        let last_der = last.to_der()?;
+        let anchor = anchors
+            .iter()
+            .find(|anchor| {
+                anchor.root() == last_der
+                    || X509::from_der(anchor.root())
+                        .and_then(|root| root.public_key())
+                        .and_then(|key| last.verify(&key))
+                        .unwrap_or(false)
+            })
+            .ok_or_else(|| {
+                anyhow!("The CoRIM signer chain does not end at a configured trust anchor")
+            })?;
+
+        let key = EcKey::try_from(certs[0].public_key()?)
+            .context("The CoRIM signer key is not an ECDSA key")?;
+        let (digest, size) = match (self.alg, key.group().degree()) {
+            (COSE_ES256, 256) => (MessageDigest::sha256(), 32),
+            (COSE_ES384, 384) => (MessageDigest::sha384(), 48),
+            (alg, degree) => bail!("Unsupported COSE algorithm {alg} for a {degree} bits key"),
+        };
+        if self.signature.len() != size * 2 {
+            bail!("Malformed CoRIM signature");
+        }
+        let (r, s) = self.signature.split_at(size);
+        let signature =
+            EcdsaSig::from_private_components(BigNum::from_slice(r)?, BigNum::from_slice(s)?)?;
+        if !signature.verify(&hash(digest, &self.to_be_signed())?, &key)? {
+            bail!("Invalid CoRIM signature");
+        }
+        Ok(anchor.claim())
+    }
+}
+
+/// A measurement of a reference triple.
+#[derive(Debug, PartialEq, Eq)]
+struct Measurement {
+    name: String,
+    alg: String,
+    value: String,
+    operator: Operator,
+}
+
+/// The measurements of `mval`, the measurement values of the reference
+/// value `name`: its digests, or else its SVN.
+fn measurements(name: &str, mval: &DataItem) -> Result<Vec<Measurement>> {
+    let measurement = |alg: &str, value: String, operator| Measurement {
+        name: name.to_string(),
+        alg: alg.to_string(),
+        value,
+        operator,
+    };
+    if let Some(digests) = field(mval, MVAL_DIGESTS) {
+        let DataItem::Array { data, .. } = digests else {
+            bail!("malformed digests of {name}");
+        };
+        return data
+            .iter()
+            .map(|digest| {
+                let DataItem::Array { data, .. } = digest else {
+                    bail!("malformed digest of {name}");
+                };
+                let [alg, value] = data.as_slice() else {
+                    bail!("malformed digest of {name}");
+                };
+                let value =
+                    byte_string(value).ok_or_else(|| anyhow!("malformed digest of {name}"))?;
+                Ok(measurement(
+                    digest_alg(alg)?,
+                    hex::encode(value),
+                    Operator::Eq,
+                ))
+            })
+            .collect();
+    }
+    let svn = match field(mval, MVAL_SVN) {
+        Some(DataItem::Tag {
+            tag: Tag(MIN_SVN_TAG),
+            value,
+            ..
+        }) => integer(value).map(|svn| (svn, Operator::SemverAtLeast)),
+        Some(DataItem::Tag {
+            tag: Tag(SVN_TAG),
+            value,
+            ..
+        }) => integer(value).map(|svn| (svn, Operator::Eq)),
+        Some(svn) => integer(svn).map(|svn| (svn, Operator::Eq)),
+        None => bail!("measurement of {name} without digest or SVN"),
+    };
+    let (svn, operator) = svn.ok_or_else(|| anyhow!("malformed SVN of {name}"))?;
+    Ok(vec![measurement(SVN_ALG, svn.to_string(), operator)])
+}
+
+/// The measurements of the reference triples of the CoMID `comid`.
+fn comid_measurements(comid: &[u8]) -> Result<Vec<Measurement>> {
+    let malformed = || anyhow!("Malformed CoMID");
+    let comid = cbor_diag::parse_bytes(comid).map_err(|_| malformed())?;
+    let Some(triples) = field(&comid, COMID_TRIPLES).and_then(|t| field(t, REFERENCE_TRIPLES))
+    else {
+        return Ok(Vec::new());
+    };
+    let DataItem::Array { data: triples, .. } = triples else {
+        bail!(malformed());
+    };
+    let mut measurements = Vec::new();
+    for triple in triples {
+        // [environment, [+ measurement]], the environment being the TD.
+        let DataItem::Array { data, .. } = triple else {
+            bail!(malformed());
+        };
+        let [_, DataItem::Array { data: triple, .. }] = data.as_slice() else {
+            bail!(malformed());
+        };
+        for measurement in triple {
+            let Some(mkey) = field(measurement, MEASUREMENT_KEY).and_then(text_string) else {
+                continue;
+            };
+            let Some(name) = reference_name(mkey) else {
+                warn!("CoRIM measurement {mkey} ignored");
+                continue;
+            };
+            let mval = field(measurement, MEASUREMENT_VALUES).ok_or_else(malformed)?;
+            measurements.extend(self::measurements(&name, mval)?);
+        }
+    }
+    Ok(measurements)
+}
+
+/// The ID of the CoRIM `corim`, the expiry of its reference values and the
+/// measurements of its CoMIDs.
+fn corim_measurements(corim: &[u8]) -> Result<(String, DateTime<Utc>, Vec<Measurement>)> {
+    let malformed = || anyhow!("Malformed CoRIM");
+    let corim = untag(
+        cbor_diag::parse_bytes(corim).map_err(|_| malformed())?,
+        CORIM_TAG,
+    );
+    let id = match field(&corim, CORIM_ID) {
+        Some(DataItem::TextString(TextString { data, .. })) => data.clone(),
+        Some(DataItem::ByteString(ByteString { data, .. })) => hex::encode(data),
+        _ => bail!("The CoRIM has no ID"),
+    };
+
+    let expired = match field(&corim, CORIM_VALIDITY).and_then(|v| field(v, VALIDITY_NOT_AFTER)) {
+        Some(DataItem::Tag {
+            tag: Tag(EPOCH_TAG),
+            value,
+            ..
+        }) => integer(value)
+            .and_then(|time| Utc.timestamp_opt(time, 0).single())
+            .ok_or_else(malformed)?,
+        Some(_) => bail!(malformed()),
+        None => Utc::now()
+            .with_nanosecond(0)
+            .and_then(|t| t.checked_add_months(Months::new(DEFAULT_EXPIRED_TIME)))
+            .ok_or_else(|| anyhow!("Expired time calculated overflowed"))?,
+    };
+    if expired <= Utc::now() {
+        bail!("The CoRIM {id} expired at {expired}");
+    }
+
+    let Some(DataItem::Array { data: tags, .. }) = field(&corim, CORIM_TAGS) else {
+        bail!(malformed());
+    };
+    let mut measurements = Vec::new();
+    for tag in tags {
+        // CoSWID and CoTL tags give no reference values.
+        if let DataItem::Tag {
+            tag: Tag(COMID_TAG),
+            value,
+            ..
+        } = tag
+        {
+            let comid = byte_string(value).ok_or_else(malformed)?;
+            measurements.extend(comid_measurements(comid)?);
+        }
+    }
+    if measurements.is_empty() {
+        bail!("No reference value in the CoRIM {id}");
+    }
+    Ok((id, expired, measurements))
+}
+
+impl Extractor for CorimExtractor {
+    fn verify_and_extract(&self, provenance_base64: &str) -> Result<Vec<ReferenceValue>> {
+        let signed = base64::engine::general_purpose::STANDARD
+            .decode(provenance_base64)
+            .context("base64 decode")?;
+        let signed = SignedCorim::parse(&signed)?;
+        let anchor = signed.verify(&self.anchors)?;
+        let (id, expired, measurements) = corim_measurements(&signed.payload)?;
+
+        // Measurements of the same key make one reference value.
+        let mut rvs: BTreeMap<String, ReferenceValue> = BTreeMap::new();
+        for measurement in measurements {
+            let rv = match rvs.remove(&measurement.name) {
+                Some(rv) if rv.operator() != measurement.operator => {
+                    bail!(
+                        "Measurements of {} with different operators",
+                        measurement.name
+                    )
+                }
+                Some(rv) => rv,
+                None => ReferenceValue::new()?
+                    .set_name(&measurement.name)
+                    .set_expired(expired)
+                    .set_metadata(PROVENANCE.0, PROVENANCE.1)
+                    .set_metadata(CORIM_ID_METADATA, &id)
+                    .set_metadata(TRUST_ANCHOR_METADATA, &anchor)
+                    .set_operator(measurement.operator),
+            };
+            rvs.insert(
+                measurement.name,
+                rv.add_hash_value(measurement.alg, measurement.value),
+            );
+        }
+
+        Ok(rvs.into_values().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::{
+        ec::EcGroup,
+        nid::Nid,
+        pkey::{PKey, Private},
+        x509::X509NameBuilder,
+    };
+
+    fn int(value: i64) -> DataItem {
+        match u64::try_from(value) {
+            std::result::Result::Ok(value) => DataItem::Integer {
+                value,
+                bitwidth: IntegerWidth::Unknown,
+            },
+            Err(_) => DataItem::Negative {
+                value: (-1 - value) as u64,
+                bitwidth: IntegerWidth::Unknown,
+            },
+        }
+    }
+
+    fn bytes(data: &[u8]) -> DataItem {
+        DataItem::ByteString(ByteString {
+            data: data.to_vec(),
+            bitwidth: IntegerWidth::Unknown,
+        })
+    }
+
+    fn text(data: &str) -> DataItem {
+        DataItem::TextString(TextString {
+            data: data.to_string(),
+            bitwidth: IntegerWidth::Unknown,
+        })
+    }
+
+    fn tag(tag: u64, value: DataItem) -> DataItem {
+        DataItem::Tag {
+            tag: Tag(tag),
+            bitwidth: IntegerWidth::Unknown,
+            value: Box::new(value),
+        }
+    }
+
+    fn array(data: Vec<DataItem>) -> DataItem {
+        DataItem::Array {
+            data,
+            bitwidth: Some(IntegerWidth::Unknown),
+        }
+    }
+
+    fn map(data: Vec<(i64, DataItem)>) -> DataItem {
+        DataItem::Map {
+            data: data.into_iter().map(|(k, v)| (int(k), v)).collect(),
+            bitwidth: Some(IntegerWidth::Unknown),
+        }
+    }
+
+    fn measurement(mkey: &str, mval: Vec<(i64, DataItem)>) -> DataItem {
+        map(vec![
+            (MEASUREMENT_KEY, text(mkey)),
+            (MEASUREMENT_VALUES, map(mval)),
+        ])
+    }
+
+    fn corim(not_after: i64) -> Vec<u8> {
+        let sha384 = |digest: &[u8]| {
+            (
+                MVAL_DIGESTS,
+                array(vec![array(vec![int(7), bytes(digest)])]),
+            )
+        };
+        let comid = map(vec![(
+            COMID_TRIPLES,
+            map(vec![(
+                REFERENCE_TRIPLES,
+                array(vec![
+                    array(vec![
+                        map(vec![(0, map(vec![(1, text("ACME"))]))]),
+                        array(vec![
+                            measurement("MRTD", vec![sha384(&[0xaa; 4])]),
+                            measurement("rtmr.kernel", vec![sha384(&[0xbb; 4])]),
+                            measurement("tcb_svn.2", vec![(MVAL_SVN, tag(MIN_SVN_TAG, int(5)))]),
+                            measurement("rtmr0", vec![sha384(&[0xcc; 4])]),
+                        ]),
+                    ]),
+                    // Another accepted MRTD.
+                    array(vec![
+                        map(vec![]),
+                        array(vec![measurement(
+                            "mrtd",
+                            vec![(
+                                MVAL_DIGESTS,
+                                array(vec![array(vec![text("sha-384"), bytes(&[0xdd; 4])])]),
+                            )],
+                        )]),
+                    ]),
+                ]),
+            )]),
+        )]);
+        tag(
+            CORIM_TAG,
+            map(vec![
+                (CORIM_ID, text("acme-td-image-1.2")),
+                (
+                    CORIM_TAGS,
+                    array(vec![tag(COMID_TAG, bytes(&comid.to_bytes()))]),
+                ),
+                (
+                    CORIM_VALIDITY,
+                    map(vec![(VALIDITY_NOT_AFTER, tag(EPOCH_TAG, int(not_after)))]),
+                ),
+            ]),
+        )
+        .to_bytes()
+    }
+
+    fn key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::SECP384R1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn cert(key: &PKey<Private>, issuer_key: &PKey<Private>, name: &str, issuer: &str) -> X509 {
+        let name_of = |cn: &str| {
+            let mut name = X509NameBuilder::new().unwrap();
+            name.append_entry_by_nid(Nid::COMMONNAME, cn).unwrap();
+            name.build()
+        };
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name_of(name)).unwrap();
+        builder.set_issuer_name(&name_of(issuer)).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(issuer_key, MessageDigest::sha384()).unwrap();
+        builder.build()
+    }
+
+    /// `corim` signed by `signer`, with the chain `x5chain`.
+    fn sign(corim: &[u8], signer: &PKey<Private>, x5chain: &[&X509]) -> String {
+        let protected = map(vec![
+            (COSE_ALG, int(COSE_ES384)),
+            (
+                COSE_X5CHAIN,
+                array(
+                    x5chain
+                        .iter()
+                        .map(|cert| bytes(&cert.to_der().unwrap()))
+                        .collect(),
+                ),
+            ),
+        ])
+        .to_bytes();
+        let mut signed = SignedCorim {
+            protected,
+            payload: corim.to_vec(),
+            signature: Vec::new(),
+            alg: COSE_ES384,
+            x5chain: Vec::new(),
+        };
+        let digest = hash(MessageDigest::sha384(), &signed.to_be_signed()).unwrap();
+        let signature = EcdsaSig::sign(&digest, &signer.ec_key().unwrap()).unwrap();
+        signed.signature = [
+            signature.r().to_vec_padded(48).unwrap(),
+            signature.s().to_vec_padded(48).unwrap(),
+        ]
+        .concat();
+        let cose = tag(
+            COSE_SIGN1_TAG,
+            array(vec![
+                bytes(&signed.protected),
+                map(vec![]),
+                bytes(&signed.payload),
+                bytes(&signed.signature),
+            ]),
+        );
+        base64::engine::general_purpose::STANDARD.encode(cose.to_bytes())
+    }
+
+    #[test]
+    fn test_extract() {
+        let (root_key, signer_key) = (key(), key());
+        let root = cert(&root_key, &root_key, "ACME Root", "ACME Root");
+        let signer = cert(&signer_key, &root_key, "ACME CoRIM", "ACME Root");
+        let anchor = TrustAnchor {
+            certs: vec![root.to_der().unwrap()],
+        };
+        let extractor = CorimExtractor::new(Arc::new(vec![anchor.clone()]));
+        let not_after = Utc::now().timestamp() + 3600;
+
+        let rvs = extractor
+            .verify_and_extract(&sign(&corim(not_after), &signer_key, &[&signer]))
+            .unwrap();
+        assert_eq!(rvs.len(), 3);
+        assert_eq!(rvs[0].name(), "tdx.ccel.kernel");
+        assert_eq!(rvs[0].hash_values()[0].value(), "bbbbbbbb");
+        assert_eq!(rvs[1].name(), "tdx.quote.body.mr_td");
+        assert_eq!(rvs[1].hash_values().len(), 2);
+        assert_eq!(rvs[1].hash_values()[0].alg(), "sha384");
+        assert_eq!(rvs[1].hash_values()[1].value(), "dddddddd");
+        assert_eq!(rvs[1].expired().timestamp(), not_after);
+        assert_eq!(rvs[1].metadata()["corim_id"], "acme-td-image-1.2");
+        assert_eq!(rvs[1].metadata()["trust_anchor"], anchor.claim());
+        assert_eq!(rvs[2].name(), "tdx.quote.body.tcb_svn.2");
+        assert_eq!(rvs[2].hash_values()[0].value(), "5");
+        assert_eq!(rvs[2].operator(), Operator::SemverAtLeast);
+
+        // The chain may end at the anchor itself.
+        extractor
+            .verify_and_extract(&sign(&corim(not_after), &signer_key, &[&signer, &root]))
+            .unwrap();
+        // Expired.
+        assert!(extractor
+            .verify_and_extract(&sign(&corim(not_after - 7200), &signer_key, &[&signer]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_reject_untrusted() {
+        let (root_key, signer_key, other_key) = (key(), key(), key());
+        let root = cert(&root_key, &root_key, "ACME Root", "ACME Root");
+        let signer = cert(&signer_key, &root_key, "ACME CoRIM", "ACME Root");
+        let other = cert(&other_key, &other_key, "Other", "Other");
+        let corim = corim(Utc::now().timestamp() + 3600);
+        let anchors = |certs: &[&X509]| -> Arc<Vec<TrustAnchor>> {
+            Arc::new(
+                certs
+                    .iter()
+                    .map(|cert| TrustAnchor {
+                        certs: vec![cert.to_der().unwrap()],
+                    })
+                    .collect(),
+            )
+        };
+
+        // Not signed by the signer.
+        let forged = sign(&corim, &other_key, &[&signer]);
+        assert!(CorimExtractor::new(anchors(&[&root]))
+            .verify_and_extract(&forged)
+            .is_err());
+        // Signed under another anchor.
+        let signed = sign(&corim, &signer_key, &[&signer]);
+        assert!(CorimExtractor::new(anchors(&[&other]))
+            .verify_and_extract(&signed)
+            .is_err());
+        assert!(CorimExtractor::new(anchors(&[]))
+            .verify_and_extract(&signed)
+            .is_err());
+        assert!(CorimExtractor::new(anchors(&[&root]))
+            .verify_and_extract("AAAA")
+            .is_err());
+    }
+
+    #[test]
+    fn test_reference_name() {
+        assert_eq!(
+            reference_name("MRSEAM").as_deref(),
+            Some("tdx.quote.body.mr_seam")
+        );
+        assert_eq!(
+            reference_name("rtmr.td_hob").as_deref(),
+            Some("tdx.ccel.td_hob")
+        );
+        assert_eq!(reference_name("rtmr."), None);
+        assert_eq!(reference_name("tcb_svn.x"), None);
+        assert_eq!(reference_name("mr_enclave"), None);
+    }
+}
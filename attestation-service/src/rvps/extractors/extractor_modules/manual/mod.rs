@@ -0,0 +1,200 @@
+//! Reference values from a YAML or CSV file of golden measurements, for
+//! teams that do not produce signed provenance. Nothing is verified, the
+//! reference values are marked with `provenance: manual` in their
+//! metadata.
+
+use std::collections::BTreeMap;
+
+use anyhow::*;
+use base64::Engine;
+use chrono::{DateTime, Months, NaiveDate, Timelike, Utc};
+use serde::Deserialize;
+
+use crate::rvps::{Operator, ReferenceValue};
+
+use super::Extractor;
+
+/// Default reference value hash algorithm
+const DEFAULT_ALG: &str = "sha384";
+
+/// The reference value will be expired in the default time (months)
+const DEFAULT_EXPIRED_TIME: u32 = 12;
+
+/// Metadata entry of the extracted reference values.
+const PROVENANCE: (&str, &str) = ("provenance", "manual");
+
+/// A golden measurement, a YAML list entry or a CSV row.
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+struct Measurement {
+    name: String,
+    digest: String,
+    #[serde(default)]
+    alg: Option<String>,
+    /// RFC 3339 time or date.
+    #[serde(default)]
+    expires: Option<String>,
+    /// How claims are compared with the digests, `eq` by default.
+    #[serde(default)]
+    operator: Option<Operator>,
+}
+
+#[derive(Default)]
+pub struct ManualExtractor;
+
+/// CSV files start with a header, `name,digest` then the optional `alg`,
+/// `expires` and `operator` columns. Anything else is YAML.
+fn parse(file: &str) -> Result<Vec<Measurement>> {
+    let header = file.lines().map(str::trim).find(|line| !line.is_empty());
+    if header.is_some_and(|header| header.starts_with("name,")) {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(file.as_bytes())
+            .deserialize()
+            .collect::<std::result::Result<_, _>>()
+            .context("deserialize CSV measurements")
+    } else {
+        serde_yaml::from_str(file).context("deserialize YAML measurements")
+    }
+}
+
+fn parse_expires(expires: &str) -> Result<DateTime<Utc>> {
+    if let std::result::Result::Ok(time) = DateTime::parse_from_rfc3339(expires) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(expires, "%Y-%m-%d")
+        .with_context(|| format!("invalid expiry `{expires}`"))?;
+    date.and_hms_opt(0, 0, 0)
+        .map(|time| DateTime::from_naive_utc_and_offset(time, Utc))
+        .ok_or_else(|| anyhow!("invalid expiry `{expires}`"))
+}
+
+impl Extractor for ManualExtractor {
+    fn verify_and_extract(&self, provenance_base64: &str) -> Result<Vec<ReferenceValue>> {
+        let file = base64::engine::general_purpose::STANDARD
+            .decode(provenance_base64)
+            .context("base64 decode")?;
+        let file = String::from_utf8(file).context("measurements file is not UTF-8")?;
+
+        let default_expired = Utc::now()
+            .with_nanosecond(0)
+            .and_then(|t| t.checked_add_months(Months::new(DEFAULT_EXPIRED_TIME)))
+            .ok_or_else(|| anyhow!("Expired time calculated overflowed"))?;
+
+        // Digests of the same name make one reference value, which expires
+        // with the first of them.
+        let mut rvs: BTreeMap<String, ReferenceValue> = BTreeMap::new();
+        for measurement in parse(&file)? {
+            let expired = match &measurement.expires {
+                Some(expires) => parse_expires(expires)
+                    .with_context(|| format!("reference value of {}", measurement.name))?,
+                None => default_expired,
+            };
+            let alg = measurement.alg.unwrap_or_else(|| DEFAULT_ALG.into());
+            let operator = measurement.operator.unwrap_or_default();
+            let rv = match rvs.remove(&measurement.name) {
+                Some(rv) if rv.operator() != operator => {
+                    bail!("Digests of {} with different operators", measurement.name)
+                }
+                Some(rv) if *rv.expired() <= expired => rv,
+                Some(rv) => rv.set_expired(expired),
+                None => ReferenceValue::new()?
+                    .set_name(&measurement.name)
+                    .set_expired(expired)
+                    .set_metadata(PROVENANCE.0, PROVENANCE.1)
+                    .set_operator(operator),
+            };
+            rvs.insert(measurement.name, rv.add_hash_value(alg, measurement.digest));
+        }
+
+        Ok(rvs.into_values().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn extract(file: &str) -> Result<Vec<ReferenceValue>> {
+        ManualExtractor.verify_and_extract(&base64::engine::general_purpose::STANDARD.encode(file))
+    }
+
+    #[test]
+    fn test_extract_yaml() {
+        let rvs = extract(
+            "
+- name: kernel
+  digest: 5b7aa6572f649714ff00b6a2b9170516a068fd1a0ba72aa8de27574131d454e6
+  alg: sha256
+  expires: 2030-01-01
+- name: kernel
+  digest: 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+  alg: sha256
+  expires: 2029-06-01T12:00:00Z
+- name: mr_td
+  digest: 705ee9381b8633a9fbe532b52345e8433343d2868959f57889d84ca3
+",
+        )
+        .unwrap();
+
+        assert_eq!(rvs.len(), 2);
+        assert_eq!(rvs[0].name(), "kernel");
+        assert_eq!(rvs[0].hash_values().len(), 2);
+        assert_eq!(rvs[0].hash_values()[0].alg(), "sha256");
+        assert_eq!(
+            *rvs[0].expired(),
+            Utc.with_ymd_and_hms(2029, 6, 1, 12, 0, 0).unwrap()
+        );
+        assert_eq!(rvs[0].metadata()["provenance"], "manual");
+        assert_eq!(rvs[1].hash_values()[0].alg(), DEFAULT_ALG);
+        assert!(*rvs[1].expired() > Utc::now());
+    }
+
+    #[test]
+    fn test_extract_csv() {
+        let rvs = extract(
+            "name, digest, alg, expires
+kernel, 5b7aa6572f649714, sha256, 2030-01-01
+mr_td, 705ee9381b8633a9,,
+",
+        )
+        .unwrap();
+
+        assert_eq!(rvs.len(), 2);
+        assert_eq!(rvs[0].hash_values()[0].value(), "5b7aa6572f649714");
+        assert_eq!(
+            *rvs[0].expired(),
+            Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap()
+        );
+        assert_eq!(rvs[1].name(), "mr_td");
+        assert_eq!(rvs[1].hash_values()[0].alg(), DEFAULT_ALG);
+        assert_eq!(rvs[1].metadata()["provenance"], "manual");
+
+        assert!(extract("name,digest\nkernel\n").is_err());
+        assert!(extract("- name: kernel\n  digest: 00\n  expires: soon\n").is_err());
+    }
+
+    #[test]
+    fn test_extract_operator() {
+        let rvs = extract(
+            "name,digest,alg,expires,operator
+svn,3,,,semver>=
+kernel,aa,,,
+",
+        )
+        .unwrap();
+        assert_eq!(rvs[0].operator(), Operator::Eq);
+        assert_eq!(rvs[1].operator(), Operator::SemverAtLeast);
+
+        assert!(extract(
+            "
+- name: svn
+  digest: 3
+  operator: semver>=
+- name: svn
+  digest: 4
+"
+        )
+        .is_err());
+    }
+}
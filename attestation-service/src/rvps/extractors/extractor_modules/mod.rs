@@ -10,9 +10,13 @@ use std::collections::HashMap;
 
 use crate::rvps::ReferenceValue;
 
+#[cfg(feature = "corim")]
+pub mod corim;
 #[cfg(feature = "in-toto")]
 pub mod in_toto;
 
+pub mod manual;
+pub mod nvidia_rim;
 pub mod sample;
 
 /// Extractor is a standard interface that all provenance extractors
@@ -41,6 +45,19 @@ impl Default for ExtractorModuleList {
             mod_list.insert("sample".to_string(), instantiate_func);
         }
 
+        {
+            let instantiate_func: ExtractorInstantiateFunc =
+                Box::new(|| -> ExtractorInstance { Box::<manual::ManualExtractor>::default() });
+            mod_list.insert("manual".to_string(), instantiate_func);
+        }
+
+        {
+            let instantiate_func: ExtractorInstantiateFunc = Box::new(|| -> ExtractorInstance {
+                Box::<nvidia_rim::NvidiaRimExtractor>::default()
+            });
+            mod_list.insert("nvidia-rim".to_string(), instantiate_func);
+        }
+
         #[cfg(feature = "in-toto")]
         {
             let instantiate_func: ExtractorInstantiateFunc =
@@ -0,0 +1,179 @@
+//! Reference values of the measurements of NVIDIA confidential GPUs, from
+//! the driver and VBIOS RIMs (reference integrity manifests) that NVIDIA
+//! publishes, bundled with the deployment instead of fetched from NVIDIA's
+//! RIM service. See [`crate::verifier::nvidia_gpu`].
+//!
+//! A RIM is a SWID tag, whose payload lists the measurements:
+//!
+//! ```xml
+//! <Resource type="Measurement" index="9" active="True" alternatives="2"
+//!     SHA384:Hash0="..." SHA384:Hash1="..."/>
+//! ```
+//!
+//! Each active measurement gives the reference value
+//! `nvidia_gpu.measurements.<n>`, of the digests of its alternatives. RIM
+//! indices start at 0 and SPDM measurement indices at 1, so `n` is the RIM
+//! index plus one. The XML signature of the RIM is not verified, the
+//! reference values are marked with `provenance: nvidia-rim` in their
+//! metadata.
+
+use anyhow::*;
+use base64::Engine;
+use chrono::{Months, Timelike, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::rvps::ReferenceValue;
+use crate::verifier::nvidia_gpu::MEASUREMENT_REFERENCE_PREFIX;
+
+use super::Extractor;
+
+/// Hash algorithm of the measurements of Hopper GPUs.
+const ALG: &str = "sha384";
+
+/// The reference value will be expired in the default time (months)
+const DEFAULT_EXPIRED_TIME: u32 = 12;
+
+/// Metadata entry of the extracted reference values.
+const PROVENANCE: (&str, &str) = ("provenance", "nvidia-rim");
+
+lazy_static! {
+    static ref RESOURCE: Regex = Regex::new(r"<(?:\w+:)?Resource\s([^>]*)>").unwrap();
+    static ref ATTRIBUTE: Regex = Regex::new(r#"([\w:]+)\s*=\s*"([^"]*)""#).unwrap();
+}
+
+#[derive(Default)]
+pub struct NvidiaRimExtractor;
+
+/// A measurement of a RIM.
+#[derive(Debug, PartialEq, Eq)]
+struct Measurement {
+    index: u8,
+    digests: Vec<String>,
+}
+
+/// The active measurements of the RIM `xml`.
+fn measurements(xml: &str) -> Result<Vec<Measurement>> {
+    let mut measurements = Vec::new();
+    for resource in RESOURCE.captures_iter(xml) {
+        let mut index = None;
+        let mut measurement = false;
+        let mut active = false;
+        let mut digests = Vec::new();
+        for attribute in ATTRIBUTE.captures_iter(&resource[1]) {
+            let value = &attribute[2];
+            match &attribute[1] {
+                "type" => measurement = value == "Measurement",
+                "active" => active = value.eq_ignore_ascii_case("true"),
+                "index" => {
+                    index = Some(
+                        value
+                            .parse::<u8>()
+                            .with_context(|| format!("invalid measurement index `{value}`"))?,
+                    )
+                }
+                name if name
+                    .rsplit(':')
+                    .next()
+                    .is_some_and(|h| h.starts_with("Hash")) =>
+                {
+                    hex::decode(value).with_context(|| format!("invalid digest `{value}`"))?;
+                    digests.push(value.to_lowercase());
+                }
+                _ => {}
+            }
+        }
+        if !measurement || !active {
+            continue;
+        }
+        let index = index.ok_or_else(|| anyhow!("measurement without index"))?;
+        if digests.is_empty() {
+            bail!("measurement {index} without digest");
+        }
+        measurements.push(Measurement { index, digests });
+    }
+    if measurements.is_empty() {
+        bail!("no active measurement in the RIM");
+    }
+    Ok(measurements)
+}
+
+impl Extractor for NvidiaRimExtractor {
+    fn verify_and_extract(&self, provenance_base64: &str) -> Result<Vec<ReferenceValue>> {
+        let rim = base64::engine::general_purpose::STANDARD
+            .decode(provenance_base64)
+            .context("base64 decode")?;
+        let rim = String::from_utf8(rim).context("RIM is not UTF-8")?;
+
+        let expired = Utc::now()
+            .with_nanosecond(0)
+            .and_then(|t| t.checked_add_months(Months::new(DEFAULT_EXPIRED_TIME)))
+            .ok_or_else(|| anyhow!("Expired time calculated overflowed"))?;
+
+        measurements(&rim)?
+            .into_iter()
+            .map(|measurement| {
+                let index = measurement
+                    .index
+                    .checked_add(1)
+                    .ok_or_else(|| anyhow!("measurement index {} overflows", measurement.index))?;
+                let rv = ReferenceValue::new()?
+                    .set_name(&format!("{MEASUREMENT_REFERENCE_PREFIX}{index}"))
+                    .set_expired(expired)
+                    .set_metadata(PROVENANCE.0, PROVENANCE.1);
+                Ok(measurement
+                    .digests
+                    .into_iter()
+                    .fold(rv, |rv, digest| rv.add_hash_value(ALG.into(), digest)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RIM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<SoftwareIdentity xmlns="http://standards.iso.org/iso/19770/-2/2015/schema.xsd"
+    xmlns:SHA384="http://www.w3.org/2001/04/xmldsig-more#sha384"
+    name="GH100" version="535.86.10">
+  <Payload>
+    <Resource type="Measurement" index="0" active="False" alternatives="1"
+        SHA384:Hash0="00"/>
+    <Resource type="Measurement" index="9" active="True" alternatives="2"
+        SHA384:Hash0="AAAA" SHA384:Hash1="bbbb"/>
+    <Resource type="Measurement" index="10" active="True" alternatives="1"
+        SHA384:Hash0="cccc"/>
+  </Payload>
+</SoftwareIdentity>"#;
+
+    fn extract(rim: &str) -> Result<Vec<ReferenceValue>> {
+        NvidiaRimExtractor
+            .verify_and_extract(&base64::engine::general_purpose::STANDARD.encode(rim))
+    }
+
+    #[test]
+    fn test_extract() {
+        let rvs = extract(RIM).unwrap();
+        assert_eq!(rvs.len(), 2);
+        assert_eq!(rvs[0].name(), "nvidia_gpu.measurements.10");
+        assert_eq!(rvs[0].hash_values().len(), 2);
+        assert_eq!(rvs[0].hash_values()[0].value(), "aaaa");
+        assert_eq!(rvs[0].hash_values()[1].alg(), ALG);
+        assert_eq!(rvs[0].metadata()["provenance"], "nvidia-rim");
+        assert_eq!(rvs[1].name(), "nvidia_gpu.measurements.11");
+        assert_eq!(rvs[1].hash_values()[0].value(), "cccc");
+    }
+
+    #[test]
+    fn test_reject_invalid_rim() {
+        assert!(extract("<SoftwareIdentity/>").is_err());
+        assert!(extract(r#"<Resource type="Measurement" active="True" Hash0="aa"/>"#).is_err());
+        assert!(extract(r#"<Resource type="Measurement" index="1" active="True"/>"#).is_err());
+        assert!(extract(
+            r#"<Resource type="Measurement" index="1" active="True" SHA384:Hash0="zz"/>"#
+        )
+        .is_err());
+    }
+}
@@ -60,6 +60,8 @@ impl Extractor for SampleExtractor {
                         name: name.to_string(),
                         expired,
                         hash_value: rvs,
+                        metadata: Default::default(),
+                        operator: Default::default(),
                     }),
                     None => {
                         warn!("Expired time calculated overflowed for reference value of {name}.");
@@ -50,7 +50,11 @@ pub struct ExtractorsImpl {
 impl ExtractorsImpl {
     /// Register an `Extractor` instance to `Extractors`. The `Extractor` is responsible for
     /// handling specific kind of provenance (as `extractor_name` indicates).
-    fn register_instance(&mut self, extractor_name: String, extractor_instance: ExtractorInstance) {
+    pub fn register_instance(
+        &mut self,
+        extractor_name: String,
+        extractor_instance: ExtractorInstance,
+    ) {
         self.extractors_instance_map
             .insert(extractor_name, extractor_instance);
     }
@@ -11,7 +11,8 @@ use self::rvps_api::{
     ReferenceValueQueryRequest, ReferenceValueRegisterRequest,
 };
 
-use super::{Message, TrustedDigest, RVPSAPI};
+use super::cache::{ReferenceValueCache, RvpsCacheConfig};
+use super::{Message, ReferenceValue, TrustedDigest, RVPSAPI};
 
 pub mod rvps_api {
     tonic::include_proto!("reference");
@@ -20,6 +21,7 @@ pub mod rvps_api {
 /// An agent for rvps, uses grpc to connect
 pub struct Agent {
     client: Mutex<ReferenceValueProviderServiceClient<tonic::transport::Channel>>,
+    cache: Option<ReferenceValueCache>,
 }
 
 impl Agent {
@@ -28,8 +30,16 @@ impl Agent {
             client: Mutex::new(
                 ReferenceValueProviderServiceClient::connect(addr.to_string()).await?,
             ),
+            cache: None,
         })
     }
+
+    /// Cache the reference values queried from the RVPS, see
+    /// [`super::cache`].
+    pub fn with_cache(mut self, config: RvpsCacheConfig) -> Self {
+        self.cache = Some(ReferenceValueCache::new(config));
+        self
+    }
 }
 
 #[async_trait::async_trait]
@@ -44,10 +54,18 @@ impl RVPSAPI for Agent {
             .register_reference_value(req)
             .await
             .context("register failed")?;
+        if let Some(cache) = &self.cache {
+            cache.clear()?;
+        }
         Ok(())
     }
 
     async fn get_digests(&self, name: &str) -> Result<Option<TrustedDigest>> {
+        if let Some(cache) = &self.cache {
+            if let Some(digest) = cache.get(name)? {
+                return Ok(digest);
+            }
+        }
         let req = tonic::Request::new(ReferenceValueQueryRequest {
             name: name.to_string(),
         });
@@ -58,7 +76,23 @@ impl RVPSAPI for Agent {
             .query_reference_value(req)
             .await?
             .into_inner();
-        let trust_digest = serde_json::from_str(&res.reference_value_results)?;
+        let trust_digest: Option<TrustedDigest> =
+            serde_json::from_str(&res.reference_value_results)?;
+        if let Some(cache) = &self.cache {
+            cache.insert(name, trust_digest.clone())?;
+        }
         Ok(trust_digest)
     }
+
+    async fn export_reference_values(&self) -> Result<Option<Vec<ReferenceValue>>> {
+        Ok(None)
+    }
+
+    async fn import_reference_values(&mut self, _rvs: Vec<ReferenceValue>) -> Result<()> {
+        bail!("Reference values of a remote RVPS cannot be imported through the AS")
+    }
+
+    async fn prune_expired(&mut self) -> Result<usize> {
+        Ok(0)
+    }
 }
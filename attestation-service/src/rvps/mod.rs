@@ -5,11 +5,14 @@
 
 extern crate strum;
 
+pub mod cache;
 #[allow(clippy::new_without_default)]
 pub mod extractors;
+pub mod operator;
 pub mod pre_processor;
 pub mod reference_value;
 pub mod store;
+pub mod watch;
 
 #[cfg(feature = "rvps-grpc")]
 pub mod grpc;
@@ -24,6 +27,7 @@ pub use native::Core;
 use anyhow::*;
 use serde::{Deserialize, Serialize};
 
+pub use operator::Operator;
 pub use reference_value::{ReferenceValue, TrustedDigest};
 pub use store::Store;
 
@@ -55,8 +59,17 @@ fn default_version() -> String {
 /// * `verify_and_extract` is responsible for verify a message and
 /// store reference values from it.
 /// * `get_digests` gets trusted digests by the artifact's name.
+/// * `export_reference_values` gets all the stored reference values, or
+/// `None` if they are held by a remote RVPS.
+/// * `import_reference_values` stores reference values as they are,
+/// replacing those with the same names.
+/// * `prune_expired` removes the expired reference values from the store,
+/// and returns how many, none for a remote RVPS, which prunes its own.
 #[async_trait::async_trait]
 pub trait RVPSAPI {
     async fn verify_and_extract(&mut self, message: Message) -> Result<()>;
     async fn get_digests(&self, name: &str) -> Result<Option<TrustedDigest>>;
+    async fn export_reference_values(&self) -> Result<Option<Vec<ReferenceValue>>>;
+    async fn import_reference_values(&mut self, rvs: Vec<ReferenceValue>) -> Result<()>;
+    async fn prune_expired(&mut self) -> Result<usize>;
 }
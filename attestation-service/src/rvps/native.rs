@@ -3,15 +3,15 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use chrono::{DateTime, Utc};
 use log::{info, warn};
 use std::time::SystemTime;
 
 use super::{
-    extractors::{Extractors, ExtractorsImpl},
+    extractors::{extractor_modules::ExtractorInstance, Extractors, ExtractorsImpl},
     pre_processor::{PreProcessor, PreProcessorAPI, Ware},
-    Message, Store, TrustedDigest, MESSAGE_VERSION, RVPSAPI,
+    Message, ReferenceValue, Store, TrustedDigest, MESSAGE_VERSION, RVPSAPI,
 };
 
 /// The core of the RVPS, s.t. componants except communication componants.
@@ -40,6 +40,14 @@ impl Core {
         self.pre_processor.add_ware(ware);
         self
     }
+
+    /// Handle the provenance of type `name` with `extractor`, e.g. one
+    /// that needs the config of the AS.
+    pub fn with_extractor(&mut self, name: &str, extractor: ExtractorInstance) -> &Self {
+        self.extractors
+            .register_instance(name.to_string(), extractor);
+        self
+    }
 }
 
 #[async_trait::async_trait]
@@ -57,6 +65,16 @@ impl RVPSAPI for Core {
         self.pre_processor.process(&mut message)?;
 
         let rv = self.extractors.process(message)?;
+        for v in rv.iter() {
+            let values: Vec<_> = v
+                .hash_values()
+                .iter()
+                .map(|pair| pair.value().clone())
+                .collect();
+            v.operator()
+                .validate(&values)
+                .with_context(|| format!("Reference value of {}", v.name()))?;
+        }
         for v in rv.iter() {
             let old = self.store.set(v.name().to_string(), v.clone())?;
             if let Some(old) = old {
@@ -83,12 +101,43 @@ impl RVPSAPI for Core {
                     .iter()
                     .map(|pair| pair.value().to_owned())
                     .collect();
+                let hash_algorithms = rv
+                    .hash_values()
+                    .iter()
+                    .map(|pair| pair.alg().to_owned())
+                    .collect();
 
                 Ok(Some(TrustedDigest {
                     name: name.to_owned(),
                     hash_values,
+                    hash_algorithms,
+                    operator: rv.operator(),
                 }))
             }
         }
     }
+
+    async fn export_reference_values(&self) -> Result<Option<Vec<ReferenceValue>>> {
+        Ok(Some(self.store.list()?))
+    }
+
+    async fn import_reference_values(&mut self, rvs: Vec<ReferenceValue>) -> Result<()> {
+        for rv in rvs {
+            self.store.set(rv.name().to_string(), rv)?;
+        }
+        Ok(())
+    }
+
+    async fn prune_expired(&mut self) -> Result<usize> {
+        let now: DateTime<Utc> = DateTime::from(SystemTime::now());
+        let mut pruned = 0;
+        for rv in self.store.list()? {
+            if now > *rv.expired() {
+                info!("Expired reference value of {} is removed.", rv.name());
+                self.store.remove(rv.name())?;
+                pruned += 1;
+            }
+        }
+        Ok(pruned)
+    }
 }
@@ -0,0 +1,193 @@
+//! Comparison operators of reference values.
+//!
+//! By default a claim matches its reference value when it is equal to one
+//! of its hash values, which the policy checks. Reference values can name
+//! another operator, that the AS evaluates before the policy:
+//!
+//! * `eq`: the claim is one of the values, the default.
+//! * `one-of`: the same as `eq`, to make the intent explicit.
+//! * `regex`: the claim matches one of the values, regular expressions
+//!   anchored at both ends.
+//! * `semver>=`: the claim is a semantic version, or an integer like an
+//!   SVN, at least one of the values.
+//! * `intmask`: the claim is an integer, equal to one of the values,
+//!   `<value>/<mask>`, on the bits of the mask. Both can be hexadecimal
+//!   with a `0x` prefix.
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum Operator {
+    #[default]
+    #[serde(rename = "eq")]
+    Eq,
+    #[serde(rename = "one-of")]
+    OneOf,
+    #[serde(rename = "regex")]
+    Regex,
+    #[serde(rename = "semver>=")]
+    SemverAtLeast,
+    #[serde(rename = "intmask")]
+    IntMask,
+}
+
+impl Operator {
+    pub fn is_default(&self) -> bool {
+        *self == Operator::Eq
+    }
+
+    pub fn is_eq(&self) -> bool {
+        matches!(self, Operator::Eq | Operator::OneOf)
+    }
+
+    /// The name of the operator, as in the reference values.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operator::Eq => "eq",
+            Operator::OneOf => "one-of",
+            Operator::Regex => "regex",
+            Operator::SemverAtLeast => "semver>=",
+            Operator::IntMask => "intmask",
+        }
+    }
+
+    /// Check the values, e.g. when they are registered.
+    pub fn validate(&self, values: &[String]) -> Result<()> {
+        for value in values {
+            match self {
+                Operator::Eq | Operator::OneOf => {}
+                Operator::Regex => {
+                    anchored(value)?;
+                }
+                Operator::SemverAtLeast => {
+                    version(value)?;
+                }
+                Operator::IntMask => {
+                    masked(value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `claim` matches any of the reference `values`.
+    pub fn matches(&self, values: &[String], claim: &Value) -> Result<bool> {
+        let claim = match claim {
+            Value::String(claim) => claim.clone(),
+            claim => claim.to_string(),
+        };
+        for value in values {
+            let matched = match self {
+                Operator::Eq | Operator::OneOf => *value == claim,
+                Operator::Regex => anchored(value)?.is_match(&claim),
+                Operator::SemverAtLeast => match version(&claim) {
+                    Result::Ok(claim) => claim >= version(value)?,
+                    Err(_) => false,
+                },
+                Operator::IntMask => {
+                    let (value, mask) = masked(value)?;
+                    match integer(&claim) {
+                        Result::Ok(claim) => claim & mask == value & mask,
+                        Err(_) => false,
+                    }
+                }
+            };
+            if matched {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+fn anchored(pattern: &str) -> Result<regex::Regex> {
+    regex::Regex::new(&format!("^(?:{pattern})$"))
+        .with_context(|| format!("invalid regular expression `{pattern}`"))
+}
+
+/// A semantic version, where integers are the major version.
+fn version(version: &str) -> Result<semver::Version> {
+    let version = version.trim().trim_start_matches('v');
+    if let Result::Ok(major) = version.parse::<u64>() {
+        return Ok(semver::Version::new(major, 0, 0));
+    }
+    semver::Version::parse(version).with_context(|| format!("invalid version `{version}`"))
+}
+
+fn integer(value: &str) -> Result<u64> {
+    let value = value.trim();
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .with_context(|| format!("invalid integer `{value}`"))
+}
+
+fn masked(value: &str) -> Result<(u64, u64)> {
+    let (value, mask) = value
+        .split_once('/')
+        .ok_or_else(|| anyhow!("`{value}` is not <value>/<mask>"))?;
+    Ok((integer(value)?, integer(mask)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn values(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_matches() {
+        let kernels = values(&["aa", "bb", "cc"]);
+        assert!(Operator::OneOf.matches(&kernels, &json!("bb")).unwrap());
+        assert!(!Operator::OneOf.matches(&kernels, &json!("dd")).unwrap());
+
+        let patterns = values(&["5\\.15\\..*"]);
+        assert!(Operator::Regex
+            .matches(&patterns, &json!("5.15.0-91"))
+            .unwrap());
+        assert!(!Operator::Regex
+            .matches(&patterns, &json!("6.5.15.0"))
+            .unwrap());
+        assert!(Operator::Regex
+            .matches(&values(&["("]), &json!("("))
+            .is_err());
+
+        let minimum = values(&["1.2.0"]);
+        assert!(Operator::SemverAtLeast
+            .matches(&minimum, &json!("1.10.3"))
+            .unwrap());
+        assert!(!Operator::SemverAtLeast
+            .matches(&minimum, &json!("1.1.9"))
+            .unwrap());
+        assert!(!Operator::SemverAtLeast
+            .matches(&minimum, &json!("latest"))
+            .unwrap());
+        assert!(Operator::SemverAtLeast
+            .matches(&values(&["3"]), &json!(4))
+            .unwrap());
+        assert!(!Operator::SemverAtLeast
+            .matches(&values(&["3"]), &json!(2))
+            .unwrap());
+
+        let debug_off = values(&["0x0/0x2"]);
+        assert!(Operator::IntMask.matches(&debug_off, &json!(5)).unwrap());
+        assert!(!Operator::IntMask
+            .matches(&debug_off, &json!("0x7"))
+            .unwrap());
+        assert!(Operator::IntMask.validate(&values(&["3"])).is_err());
+    }
+
+    #[test]
+    fn test_serde() {
+        let operator: Operator = serde_json::from_value(json!("semver>=")).unwrap();
+        assert_eq!(operator, Operator::SemverAtLeast);
+        assert_eq!(serde_json::to_value(Operator::OneOf).unwrap(), "one-of");
+        assert!(serde_json::from_value::<Operator>(json!("lt")).is_err());
+    }
+}
@@ -8,6 +8,10 @@
 use anyhow::{anyhow, Result};
 use chrono::{DateTime, NaiveDateTime, Timelike, Utc};
 use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use super::Operator;
 
 /// Default version of ReferenceValue
 pub const REFERENCE_VALUE_VERSION: &str = "0.1.0";
@@ -39,10 +43,11 @@ impl HashValuePair {
 fn primitive_date_time_from_str<'de, D: Deserializer<'de>>(
     d: D,
 ) -> Result<DateTime<Utc>, D::Error> {
-    let s = <Option<&str>>::deserialize(d)?
+    // Owned, as a JSON value cannot lend its strings.
+    let s = <Option<String>>::deserialize(d)?
         .ok_or_else(|| serde::de::Error::invalid_length(0, &"<TIME>"))?;
 
-    let ndt = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%SZ")
+    let ndt = NaiveDateTime::parse_from_str(&s, "%Y-%m-%dT%H:%M:%SZ")
         .map_err(|err| serde::de::Error::custom::<String>(err.to_string()))?;
 
     Ok(DateTime::from_naive_utc_and_offset(ndt, Utc))
@@ -56,6 +61,10 @@ fn primitive_date_time_from_str<'de, D: Deserializer<'de>>(
 /// * `expired`: expired time for this reference value.
 /// * `hash_value`: A set of key-value pairs, each indicates a hash
 /// algorithm and its relative hash value for the artifact.
+/// * `metadata`: where the reference value comes from, e.g.
+/// `provenance: manual` for reference values without signed provenance.
+/// * `operator`: how claims are compared with the hash values, see
+/// [`Operator`].
 /// The actual struct deliver from RVPS to AS is
 /// [`TrustedDigest`], whose simple structure is easy
 /// for AS to handle.
@@ -68,6 +77,10 @@ pub struct ReferenceValue {
     pub expired: DateTime<Utc>,
     #[serde(rename = "hash-value")]
     pub hash_value: Vec<HashValuePair>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Operator::is_default")]
+    pub operator: Operator,
 }
 
 /// Set the default version for ReferenceValue
@@ -88,6 +101,8 @@ impl ReferenceValue {
                 .with_nanosecond(0)
                 .ok_or_else(|| anyhow!("set nanosecond failed."))?,
             hash_value: Vec::new(),
+            metadata: BTreeMap::new(),
+            operator: Operator::default(),
         })
     }
 
@@ -134,6 +149,28 @@ impl ReferenceValue {
     pub fn name(&self) -> &String {
         &self.name
     }
+
+    /// Set a metadata entry of the ReferenceValue.
+    pub fn set_metadata(mut self, key: &str, value: &str) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Get metadata of the ReferenceValue.
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// Set the comparison operator of the ReferenceValue.
+    pub fn set_operator(mut self, operator: Operator) -> Self {
+        self.operator = operator;
+        self
+    }
+
+    /// Get the comparison operator of the ReferenceValue.
+    pub fn operator(&self) -> Operator {
+        self.operator
+    }
 }
 
 /// Trusted Digest is what RVPS actually delivered to
@@ -141,12 +178,79 @@ impl ReferenceValue {
 /// * `name`: The name of the artifact, e.g., `linux-1.1.1`
 /// * `hash_values`: digests that have been verified and can
 /// be trusted, so we can refer them as `trusted digests`.
+/// * `hash_algorithms`: the algorithm of each hash value, i.e. the
+/// measurement bank it belongs to.
+/// * `operator`: how claims are compared with the hash values.
 #[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
 pub struct TrustedDigest {
     /// The resource name.
     pub name: String,
     /// The reference hash values, base64 coded.
     pub hash_values: Vec<String>,
+    /// Absent from the digests of RVPS that do not know about hash banks.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hash_algorithms: Vec<String>,
+    /// Absent from the digests of RVPS that do not know about operators.
+    #[serde(default, skip_serializing_if = "Operator::is_default")]
+    pub operator: Operator,
+}
+
+/// Hash algorithms digest claims can be prefixed with, like `sha256:<hex>`.
+const HASH_BANKS: [&str; 3] = ["sha256", "sha384", "sha512"];
+
+impl TrustedDigest {
+    /// The reference values of `claim` given to the policy, which compares
+    /// them with the claim. The hash values of `eq` digests are given as
+    /// they are, see [`Self::bank_values`]. With other operators, the claim
+    /// itself when it matches, otherwise the operator expressions, which no
+    /// claim is equal to.
+    pub fn reference_data(self, claim: &Value) -> Result<Vec<String>> {
+        if self.operator.is_eq() || self.hash_values.is_empty() {
+            return Ok(self.bank_values(claim));
+        }
+        if self.operator.matches(&self.hash_values, claim)? {
+            let claim = match claim {
+                Value::String(claim) => claim.clone(),
+                claim => claim.to_string(),
+            };
+            return Ok(vec![claim]);
+        }
+        Ok(self
+            .hash_values
+            .iter()
+            .map(|value| format!("{} {value}", self.operator.name()))
+            .collect())
+    }
+
+    /// The hash values of the measurement bank of `claim`. Digest claims
+    /// of a bank prefixed with its algorithm, like `sha256:<hex>`, are only
+    /// given the hash values of that algorithm, prefixed the same way, and
+    /// those already prefixed with it. Other claims are given all the hash
+    /// values.
+    fn bank_values(self, claim: &Value) -> Vec<String> {
+        let bank = claim
+            .as_str()
+            .and_then(|claim| claim.split_once(':'))
+            .map(|(alg, _)| alg)
+            .filter(|alg| HASH_BANKS.contains(alg));
+        let Some(bank) = bank else {
+            return self.hash_values;
+        };
+
+        let prefix = format!("{bank}:");
+        self.hash_values
+            .iter()
+            .enumerate()
+            .filter_map(|(i, value)| {
+                if value.starts_with(&prefix) {
+                    return Some(value.clone());
+                }
+                let alg = self.hash_algorithms.get(i)?;
+                (alg.eq_ignore_ascii_case(bank) && !value.contains(':'))
+                    .then(|| format!("{prefix}{value}"))
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -154,7 +258,7 @@ mod test {
     use chrono::{TimeZone, Utc};
     use serde_json::json;
 
-    use super::ReferenceValue;
+    use super::{ReferenceValue, TrustedDigest};
 
     #[test]
     fn reference_value_serialize() {
@@ -203,4 +307,75 @@ mod test {
         let deserialized_rf: ReferenceValue = serde_json::from_str(&rv_json).unwrap();
         assert_eq!(deserialized_rf, rv);
     }
+
+    #[test]
+    fn trusted_digest_reference_data() {
+        let digest = |operator: &str, hash_values: &[&str]| TrustedDigest {
+            name: "svn".into(),
+            hash_values: hash_values.iter().map(|value| value.to_string()).collect(),
+            hash_algorithms: Vec::new(),
+            operator: serde_json::from_value(json!(operator)).unwrap(),
+        };
+
+        assert_eq!(
+            digest("eq", &["aa", "bb"])
+                .reference_data(&json!("cc"))
+                .unwrap(),
+            vec!["aa", "bb"]
+        );
+        assert_eq!(
+            digest("semver>=", &["3"])
+                .reference_data(&json!(4))
+                .unwrap(),
+            vec!["4"]
+        );
+        assert_eq!(
+            digest("semver>=", &["3"])
+                .reference_data(&json!(2))
+                .unwrap(),
+            vec!["semver>= 3"]
+        );
+        assert!(digest("regex", &[])
+            .reference_data(&json!("x"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn trusted_digest_hash_banks() {
+        let digest = TrustedDigest {
+            name: "kernel-6.7-prod".into(),
+            hash_values: vec!["aa".into(), "bb".into(), "cc".into(), "sha256:dd".into()],
+            hash_algorithms: vec![
+                "sha256".into(),
+                "sha384".into(),
+                "sha512".into(),
+                "sha384".into(),
+            ],
+            operator: Default::default(),
+        };
+
+        assert_eq!(
+            digest.clone().reference_data(&json!("sha256:aa")).unwrap(),
+            vec!["sha256:aa", "sha256:dd"]
+        );
+        assert_eq!(
+            digest.clone().reference_data(&json!("sha512:cc")).unwrap(),
+            vec!["sha512:cc"]
+        );
+        assert_eq!(
+            digest.clone().reference_data(&json!("bb")).unwrap(),
+            vec!["aa", "bb", "cc", "sha256:dd"]
+        );
+
+        // Digests of an RVPS that does not know about hash banks
+        let digest = TrustedDigest {
+            hash_algorithms: Vec::new(),
+            ..digest
+        };
+        assert_eq!(
+            digest.reference_data(&json!("sha256:aa")).unwrap(),
+            vec!["sha256:dd"]
+        );
+    }
 }
@@ -0,0 +1,67 @@
+//! This Store keeps the RV information in memory only, for tests and
+//! ephemeral deployments.
+
+use std::collections::BTreeMap;
+
+use anyhow::*;
+
+use crate::rvps::ReferenceValue;
+
+use super::Store;
+
+/// `InMemory` implements [`Store`] trait. Its reference values are lost
+/// when it is dropped.
+#[derive(Default)]
+pub struct InMemory {
+    rvs: BTreeMap<String, ReferenceValue>,
+}
+
+impl Store for InMemory {
+    fn set(&mut self, name: String, rv: ReferenceValue) -> Result<Option<ReferenceValue>> {
+        Ok(self.rvs.insert(name, rv))
+    }
+
+    fn get(&self, name: &str) -> Result<Option<ReferenceValue>> {
+        Ok(self.rvs.get(name).cloned())
+    }
+
+    fn list(&self) -> Result<Vec<ReferenceValue>> {
+        Ok(self.rvs.values().cloned().collect())
+    }
+
+    fn remove(&mut self, name: &str) -> Result<Option<ReferenceValue>> {
+        Ok(self.rvs.remove(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rvps::{store::in_memory::InMemory, ReferenceValue, Store};
+
+    /// This test will test the `set`, `get` and `list` interfaces for
+    /// [`InMemory`].
+    #[test]
+    fn set_and_get() {
+        let rv = ReferenceValue::new()
+            .expect("create ReferenceValue failed.")
+            .set_name("kernel");
+        let mut store = InMemory::default();
+        assert!(store.list().expect("list rvs failed.").is_empty());
+        assert!(store
+            .set("kernel".to_owned(), rv.clone())
+            .expect("set rv failed.")
+            .is_none());
+        assert_eq!(
+            store
+                .set("kernel".to_owned(), rv.clone())
+                .expect("set rv failed."),
+            Some(rv.clone())
+        );
+        assert_eq!(
+            store.get("kernel").expect("get rv failed."),
+            Some(rv.clone())
+        );
+        assert_eq!(store.get("initrd").expect("get rv failed."), None);
+        assert_eq!(store.list().expect("list rvs failed."), vec![rv]);
+    }
+}
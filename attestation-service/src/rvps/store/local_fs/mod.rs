@@ -35,7 +35,7 @@ impl Default for LocalFs {
 impl LocalFs {
     /// Create a new [`LocalFs`] with given
     /// file storage path.
-    fn new(path: &Path) -> Result<Self> {
+    pub fn new(path: &Path) -> Result<Self> {
         let engine = sled::open(path)?;
         Ok(Self { engine })
     }
@@ -69,6 +69,24 @@ impl Store for LocalFs {
             None => Ok(None),
         }
     }
+
+    fn list(&self) -> Result<Vec<ReferenceValue>> {
+        self.engine
+            .iter()
+            .values()
+            .map(|v| Ok(serde_json::from_slice(&v.context("read from sled")?)?))
+            .collect()
+    }
+
+    fn remove(&mut self, name: &str) -> Result<Option<ReferenceValue>> {
+        let res = match self.engine.remove(name).context("remove from sled")? {
+            Some(v) => Some(serde_json::from_slice(&v)?),
+            None => None,
+        };
+
+        self.engine.flush()?;
+        Ok(res)
+    }
 }
 
 #[cfg(test)]
@@ -138,6 +156,30 @@ mod tests {
         }
     }
 
+    /// This test will test the `list` interface for [`LocalFs`].
+    #[test]
+    #[serial]
+    fn list() {
+        let temp_dir = tempfile::tempdir().expect("create tempdir failed");
+        let mut store = LocalFs::new(temp_dir.path()).expect("create local fs store failed.");
+        assert!(store.list().expect("list rvs failed.").is_empty());
+
+        let rvs: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|name| {
+                ReferenceValue::new()
+                    .expect("create ReferenceValue failed.")
+                    .set_name(name)
+            })
+            .collect();
+        for rv in &rvs {
+            store
+                .set(rv.name().to_owned(), rv.clone())
+                .expect("set rv failed.");
+        }
+        assert_eq!(store.list().expect("list rvs failed."), rvs);
+    }
+
     /// This test will simulate a restart operation
     /// for [`LocalFs`].
     #[test]
@@ -0,0 +1,113 @@
+//! This Store stores RV information inside a local JSON file
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+
+use crate::rvps::ReferenceValue;
+
+use super::Store;
+
+/// Local file path to store the reference values.
+const FILE_PATH: &str = "/opt/confidential-containers/attestation-service/reference_values.json";
+
+/// `LocalJson` implements [`Store`] trait. It keeps the reference values
+/// in a JSON object by name, readable and editable with common tools, and
+/// rewrites the whole file, atomically, on every change.
+pub struct LocalJson {
+    path: PathBuf,
+    rvs: BTreeMap<String, ReferenceValue>,
+}
+
+impl Default for LocalJson {
+    /// Create a `LocalJson` storage, which will use path [`FILE_PATH`]
+    /// to store the reference values.
+    fn default() -> Self {
+        let path = Path::new(FILE_PATH);
+        LocalJson::new(path).expect("Failed to create LocalJson Store.")
+    }
+}
+
+impl LocalJson {
+    /// Create a new [`LocalJson`] with given file path. The file is
+    /// created on the first change if it does not exist.
+    pub fn new(path: &Path) -> Result<Self> {
+        let rvs = match fs::read(path) {
+            std::result::Result::Ok(contents) => serde_json::from_slice(&contents)
+                .with_context(|| format!("parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("read {}", path.display())),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            rvs,
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)?;
+        let file = tempfile::NamedTempFile::new_in(dir)?;
+        serde_json::to_writer_pretty(&file, &self.rvs)?;
+        file.as_file().sync_all()?;
+        file.persist(&self.path)
+            .with_context(|| format!("write {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+impl Store for LocalJson {
+    fn set(&mut self, name: String, rv: ReferenceValue) -> Result<Option<ReferenceValue>> {
+        let previous = self.rvs.insert(name, rv);
+        self.save()?;
+        Ok(previous)
+    }
+
+    fn get(&self, name: &str) -> Result<Option<ReferenceValue>> {
+        Ok(self.rvs.get(name).cloned())
+    }
+
+    fn list(&self) -> Result<Vec<ReferenceValue>> {
+        Ok(self.rvs.values().cloned().collect())
+    }
+
+    fn remove(&mut self, name: &str) -> Result<Option<ReferenceValue>> {
+        let previous = self.rvs.remove(name);
+        if previous.is_some() {
+            self.save()?;
+        }
+        Ok(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rvps::{store::local_json::LocalJson, ReferenceValue, Store};
+
+    /// This test will test the `set`, `get` and `list` interfaces, and
+    /// simulate a restart, for [`LocalJson`].
+    #[test]
+    fn set_and_restart() {
+        let temp_dir = tempfile::tempdir().expect("create tempdir failed");
+        let path = temp_dir.path().join("rvs/reference_values.json");
+        let rv = ReferenceValue::new()
+            .expect("create ReferenceValue failed.")
+            .set_name("kernel");
+        {
+            let mut store = LocalJson::new(&path).expect("create local json store failed.");
+            assert!(store.list().expect("list rvs failed.").is_empty());
+            assert!(store
+                .set("kernel".to_owned(), rv.clone())
+                .expect("set rv failed.")
+                .is_none());
+        }
+        let store = LocalJson::new(&path).expect("read previous local json store failed.");
+        assert_eq!(
+            store.get("kernel").expect("get rv failed."),
+            Some(rv.clone())
+        );
+        assert_eq!(store.list().expect("list rvs failed."), vec![rv]);
+    }
+}
@@ -5,18 +5,25 @@
 
 //! Store is responsible for storing verified Reference Values
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use serde::Deserialize;
+use std::path::Path;
 
+use self::in_memory::InMemory;
 use self::local_fs::LocalFs;
+use self::local_json::LocalJson;
 
 use super::ReferenceValue;
 
+pub mod in_memory;
 pub mod local_fs;
+pub mod local_json;
 
 #[derive(Deserialize, Debug, Clone, EnumString)]
 pub enum StoreType {
     LocalFs,
+    LocalJson,
+    InMemory,
 }
 
 impl StoreType {
@@ -24,6 +31,18 @@ impl StoreType {
     pub fn to_store(&self) -> Result<Box<dyn Store + Send + Sync>> {
         match self {
             StoreType::LocalFs => Ok(Box::<LocalFs>::default() as Box<dyn Store + Send + Sync>),
+            StoreType::LocalJson => Ok(Box::<LocalJson>::default() as Box<dyn Store + Send + Sync>),
+            StoreType::InMemory => Ok(Box::<InMemory>::default() as Box<dyn Store + Send + Sync>),
+        }
+    }
+
+    /// Open the store of this type at `path` instead of its default path,
+    /// e.g. to migrate reference values between stores.
+    pub fn open(&self, path: &Path) -> Result<Box<dyn Store + Send + Sync>> {
+        match self {
+            StoreType::LocalFs => Ok(Box::new(LocalFs::new(path)?)),
+            StoreType::LocalJson => Ok(Box::new(LocalJson::new(path)?)),
+            StoreType::InMemory => bail!("An InMemory store has no path"),
         }
     }
 }
@@ -40,4 +59,10 @@ pub trait Store {
 
     // Retrieve a reference value
     fn get(&self, name: &str) -> Result<Option<ReferenceValue>>;
+
+    /// Retrieve all the reference values.
+    fn list(&self) -> Result<Vec<ReferenceValue>>;
+
+    /// Remove the reference value of `name`, and return it if it existed.
+    fn remove(&mut self, name: &str) -> Result<Option<ReferenceValue>>;
 }
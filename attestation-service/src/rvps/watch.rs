@@ -0,0 +1,203 @@
+//! Reference value bundles dropped in a watched directory.
+//!
+//! Vendors ship their golden measurements as signed bundles, which release
+//! pipelines drop in a directory of the AS, e.g. a mounted config map. With
+//! `reference_values_watch` in the AS config, the bundles added to or
+//! changed in the directory are ingested every `poll_secs`, 30 by default:
+//!
+//! ```json
+//! "reference_values_watch": {
+//!     "dir": "/etc/attestation-service/corim",
+//!     "poll_secs": 30
+//! }
+//! ```
+//!
+//! The provenance type of a bundle is told by the extension of its file,
+//! `.corim` for signed CoRIMs, see [`super::extractors`]. Other files are
+//! ignored. A bundle that the RVPS rejects is logged, and not retried until
+//! its file changes, so that a bundle read while being written is ingested
+//! once complete.
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::{Message, MESSAGE_VERSION, RVPSAPI};
+
+/// Provenance types of the extensions of the bundle files.
+const BUNDLE_TYPES: &[(&str, &str)] = &[("corim", "corim")];
+
+fn default_poll_secs() -> u64 {
+    30
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct WatchConfig {
+    /// Directory of the bundles.
+    pub dir: PathBuf,
+    /// Period of the scans of the directory, in seconds.
+    #[serde(default = "default_poll_secs")]
+    pub poll_secs: u64,
+}
+
+impl WatchConfig {
+    pub fn check(&self) -> Result<()> {
+        if !self.dir.is_dir() {
+            bail!("{} is not a directory", self.dir.display());
+        }
+        if self.poll_secs == 0 {
+            bail!("poll_secs must be positive");
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct Watch {
+    config: WatchConfig,
+    /// Digests of the bundles last ingested or rejected, by file.
+    seen: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl Watch {
+    pub fn new(config: WatchConfig) -> Self {
+        Self {
+            config,
+            seen: HashMap::new(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.poll_secs)
+    }
+
+    /// Ingest the bundles added or changed since the last reload into
+    /// `rvps`, and return how many were.
+    pub async fn reload(&mut self, rvps: &mut (dyn RVPSAPI + Send + Sync)) -> Result<usize> {
+        let mut paths = fs::read_dir(&self.config.dir)
+            .with_context(|| format!("Cannot read {}", self.config.dir.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect::<Vec<_>>();
+        paths.sort();
+        self.seen.retain(|path, _| paths.contains(path));
+
+        let mut ingested = 0;
+        for path in paths {
+            let extension = path.extension().and_then(|extension| extension.to_str());
+            let Some((_, r#type)) = BUNDLE_TYPES
+                .iter()
+                .find(|(bundle_extension, _)| Some(*bundle_extension) == extension)
+            else {
+                continue;
+            };
+            let bundle = match fs::read(&path) {
+                Ok(bundle) => bundle,
+                Err(e) => {
+                    warn!("Cannot read {}: {e}", path.display());
+                    continue;
+                }
+            };
+            let digest = Sha256::digest(&bundle).to_vec();
+            if self.seen.get(&path) == Some(&digest) {
+                continue;
+            }
+            self.seen.insert(path.clone(), digest);
+
+            let message = Message {
+                version: MESSAGE_VERSION.to_string(),
+                payload: base64::engine::general_purpose::STANDARD.encode(bundle),
+                r#type: r#type.to_string(),
+            };
+            match rvps.verify_and_extract(message).await {
+                Ok(()) => {
+                    info!("Reference values of {} ingested", path.display());
+                    ingested += 1;
+                }
+                Err(e) => warn!("Reference values of {} rejected: {e:#}", path.display()),
+            }
+        }
+        Ok(ingested)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rvps::{ReferenceValue, TrustedDigest};
+
+    /// An RVPS recording the payloads of the messages, rejecting the
+    /// bundle `bad`.
+    #[derive(Default)]
+    struct Recorder {
+        payloads: Vec<String>,
+    }
+
+    #[async_trait::async_trait]
+    impl RVPSAPI for Recorder {
+        async fn verify_and_extract(&mut self, message: Message) -> Result<()> {
+            let payload = base64::engine::general_purpose::STANDARD.decode(&message.payload)?;
+            self.payloads.push(String::from_utf8(payload)?);
+            if message.payload == base64::engine::general_purpose::STANDARD.encode("bad") {
+                bail!("Invalid signature");
+            }
+            Ok(())
+        }
+
+        async fn get_digests(&self, _name: &str) -> Result<Option<TrustedDigest>> {
+            Ok(None)
+        }
+
+        async fn export_reference_values(&self) -> Result<Option<Vec<ReferenceValue>>> {
+            Ok(None)
+        }
+
+        async fn import_reference_values(&mut self, _rvs: Vec<ReferenceValue>) -> Result<()> {
+            Ok(())
+        }
+
+        async fn prune_expired(&mut self) -> Result<usize> {
+            Ok(0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = WatchConfig {
+            dir: dir.path().to_path_buf(),
+            poll_secs: 30,
+        };
+        config.check().unwrap();
+        let mut watch = Watch::new(config);
+        let mut rvps = Recorder::default();
+
+        fs::write(dir.path().join("a.corim"), "a").unwrap();
+        fs::write(dir.path().join("b.corim"), "bad").unwrap();
+        fs::write(dir.path().join("README.md"), "ignored").unwrap();
+        assert_eq!(watch.reload(&mut rvps).await.unwrap(), 1);
+        assert_eq!(rvps.payloads, ["a", "bad"]);
+
+        // Only the changed bundles are ingested again.
+        assert_eq!(watch.reload(&mut rvps).await.unwrap(), 0);
+        fs::write(dir.path().join("b.corim"), "b").unwrap();
+        assert_eq!(watch.reload(&mut rvps).await.unwrap(), 1);
+        assert_eq!(rvps.payloads, ["a", "bad", "b"]);
+
+        // A bundle removed then dropped again is ingested again.
+        fs::remove_file(dir.path().join("a.corim")).unwrap();
+        assert_eq!(watch.reload(&mut rvps).await.unwrap(), 0);
+        fs::write(dir.path().join("a.corim"), "a").unwrap();
+        assert_eq!(watch.reload(&mut rvps).await.unwrap(), 1);
+
+        assert!(WatchConfig {
+            dir: dir.path().join("missing"),
+            poll_secs: 30,
+        }
+        .check()
+        .is_err());
+    }
+}
@@ -0,0 +1,236 @@
+//! Self-test of the AS, and probes of the quote infrastructure of the
+//! platform.
+//!
+//! [`crate::AttestationService::self_test`] runs a canned verification end
+//! to end, of evidence of the sample TEE: the verifier, the reference
+//! values of the RVPS, the policy engine and the token broker. When the AS
+//! also verifies quotes generated on its own host, e.g. in tests,
+//! `platform_probes` in the AS config adds probes of the Intel quote
+//! infrastructure: the Quote Generation Service (QGS), and the PCCS that
+//! the Quote Provider Library (QPL) fetches collateral from. Monitoring can
+//! then tell an AS that is broken from a platform that is:
+//!
+//! ```json
+//! "platform_probes": {
+//!     "qgs_socket": "/var/run/tdx-qgs/qgs.socket",
+//!     "qcnl_config": "/etc/sgx_default_qcnl.conf"
+//! }
+//! ```
+
+use crate::explain::Check;
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use kbs_types::{Attestation, TeePubKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha384};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::net::{TcpStream, UnixStream};
+
+const DEFAULT_TIMEOUT_SECS: u64 = 5;
+
+/// Default port of `https` PCCS URLs.
+const HTTPS_PORT: u16 = 443;
+
+fn default_timeout_secs() -> u64 {
+    DEFAULT_TIMEOUT_SECS
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PlatformProbeConfig {
+    /// Unix domain socket of the QGS.
+    #[serde(default)]
+    pub qgs_socket: Option<PathBuf>,
+
+    /// QPL config, whose `pccs_url` is probed.
+    #[serde(default)]
+    pub qcnl_config: Option<PathBuf>,
+
+    /// Timeout of each probe, in seconds.
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct SelfTest {
+    /// Steps of the canned verification.
+    pub service: Vec<Check>,
+    /// Probes of the platform, none if not configured.
+    pub platform: Vec<Check>,
+}
+
+impl SelfTest {
+    /// Whether the AS works.
+    pub fn service_ok(&self) -> bool {
+        self.service.iter().all(|check| check.passed)
+    }
+
+    /// Whether the quote infrastructure of the platform works.
+    pub fn platform_ok(&self) -> bool {
+        self.platform.iter().all(|check| check.passed)
+    }
+}
+
+/// Evidence of the sample TEE, bound to `nonce`.
+pub fn sample_attestation(nonce: &str) -> Attestation {
+    let tee_pubkey = TeePubKey {
+        kty: "RSA".to_string(),
+        alg: "RSA1_5".to_string(),
+        k_mod: "self-test".to_string(),
+        k_exp: "AQAB".to_string(),
+    };
+    let mut hasher = Sha384::new();
+    hasher.update(nonce);
+    hasher.update(&tee_pubkey.k_mod);
+    hasher.update(&tee_pubkey.k_exp);
+    let report_data = base64::engine::general_purpose::STANDARD.encode(hasher.finalize());
+
+    Attestation {
+        tee_pubkey,
+        tee_evidence: serde_json::json!({
+            "svn": "1",
+            "report_data": report_data,
+        })
+        .to_string(),
+    }
+}
+
+pub(crate) async fn probe_platform(config: &PlatformProbeConfig) -> Vec<Check> {
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let mut checks = Vec::new();
+    if let Some(socket) = &config.qgs_socket {
+        checks.push(to_check("QGS", probe_qgs(socket, timeout).await));
+    }
+    if let Some(qcnl_config) = &config.qcnl_config {
+        checks.push(to_check("PCCS", probe_pccs(qcnl_config, timeout).await));
+    }
+    checks
+}
+
+fn to_check(name: &str, result: Result<String>) -> Check {
+    let (passed, details) = match result {
+        Ok(details) => (true, details),
+        Err(e) => (false, format!("{e:#}")),
+    };
+    Check {
+        name: name.to_string(),
+        passed,
+        details,
+    }
+}
+
+async fn probe_qgs(socket: &Path, timeout: Duration) -> Result<String> {
+    tokio::time::timeout(timeout, UnixStream::connect(socket))
+        .await
+        .map_err(|_| anyhow!("connection timed out"))?
+        .with_context(|| format!("connect to {}", socket.display()))?;
+    Ok(format!(
+        "The QGS accepts connections on {}",
+        socket.display()
+    ))
+}
+
+async fn probe_pccs(qcnl_config: &Path, timeout: Duration) -> Result<String> {
+    let config = tokio::fs::read_to_string(qcnl_config)
+        .await
+        .with_context(|| format!("read {}", qcnl_config.display()))?;
+    let url =
+        pccs_url(&config).ok_or_else(|| anyhow!("no pccs_url in {}", qcnl_config.display()))?;
+    let address = pccs_address(url)?;
+    tokio::time::timeout(timeout, TcpStream::connect(&address))
+        .await
+        .map_err(|_| anyhow!("connection to {address} timed out"))?
+        .with_context(|| format!("connect to {address}"))?;
+    Ok(format!("The PCCS accepts connections on {address}"))
+}
+
+/// The PCCS URL of a QPL config, either JSON (`"pccs_url": "https://..."`)
+/// or the legacy `PCCS_URL=https://...` format.
+fn pccs_url(config: &str) -> Option<&str> {
+    config
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#') && !line.starts_with("//"))
+        .filter(|line| line.to_ascii_lowercase().contains("pccs_url"))
+        .find_map(|line| {
+            let url = &line[line.find("http")?..];
+            let end = url
+                .find(|c: char| c == '"' || c.is_whitespace())
+                .unwrap_or(url.len());
+            Some(&url[..end])
+        })
+}
+
+/// `host:port` of an `https` URL.
+fn pccs_address(url: &str) -> Result<String> {
+    let authority = url
+        .strip_prefix("https://")
+        .ok_or_else(|| anyhow!("PCCS URL {url} is not https"))?
+        .split('/')
+        .next()
+        .unwrap_or_default();
+    if authority.is_empty() {
+        return Err(anyhow!("PCCS URL {url} has no host"));
+    }
+    // The port follows the last colon, unless it is in an IPv6 address.
+    match authority.rsplit_once(':') {
+        Some((_, port)) if !port.ends_with(']') => Ok(authority.to_string()),
+        _ => Ok(format!("{authority}:{HTTPS_PORT}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pccs_address() {
+        let json = r#"{
+            // PCCS server address
+            "pccs_url": "https://localhost:8081/sgx/certification/v4/",
+            "use_secure_cert": true
+        }"#;
+        assert_eq!(
+            pccs_url(json),
+            Some("https://localhost:8081/sgx/certification/v4/")
+        );
+        assert_eq!(
+            pccs_url("# PCCS_URL=https://old/\nPCCS_URL=https://pccs.example.com/sgx/\n"),
+            Some("https://pccs.example.com/sgx/")
+        );
+        assert_eq!(pccs_url("{}"), None);
+
+        assert_eq!(
+            pccs_address("https://localhost:8081/sgx/certification/v4/").unwrap(),
+            "localhost:8081"
+        );
+        assert_eq!(
+            pccs_address("https://pccs.example.com/sgx/").unwrap(),
+            "pccs.example.com:443"
+        );
+        assert_eq!(pccs_address("https://[::1]/").unwrap(), "[::1]:443");
+        assert!(pccs_address("http://pccs.example.com/").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_probe_platform() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("qgs.socket");
+        let _qgs = tokio::net::UnixListener::bind(&socket).unwrap();
+        let checks = probe_platform(&PlatformProbeConfig {
+            qgs_socket: Some(socket),
+            qcnl_config: Some(dir.path().join("missing.conf")),
+            timeout_secs: 1,
+        })
+        .await;
+
+        let self_test = SelfTest {
+            service: Vec::new(),
+            platform: checks,
+        };
+        assert!(self_test.platform[0].passed);
+        assert!(!self_test.platform[1].passed);
+        assert!(!self_test.platform_ok());
+        assert!(self_test.service_ok());
+    }
+}
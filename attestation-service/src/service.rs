@@ -0,0 +1,2476 @@
+//! The attestation service: the verifiers, then the reference values of the
+//! RVPS and the policy engine appraise evidence, and the token broker issues
+//! attestation results tokens. Built with the `service` feature.
+
+use crate::backup::{self, Backup, BACKUP_CLAIM, BACKUP_TYP, BACKUP_VERSION};
+use crate::token::cosign::{self, CoSigners};
+use crate::token::ear::{self, TokenFormat};
+use crate::token::{chain, AttestationTokenBroker};
+
+use crate::admission::{Admission, Admitted, Load};
+use crate::advisories::{AdvisoryFeed, ADVISORY_BASELINE};
+use crate::audit::{Audit, AuditRecord, AuditTrace};
+use crate::capabilities::Capabilities;
+use crate::certificate::CertificateIssuer;
+use crate::claim_filter;
+use crate::config::Config;
+use crate::decryption::EvidenceDecryptor;
+use crate::deny_list::{DenyList, DenyListEntry};
+use crate::explain::{Explanation, VerificationReport};
+use crate::failure_cache::FailureCache;
+use crate::history::{ExportFormat, History, HistoryRecord, StoredEvidence};
+use crate::hooks::{Hooks, PostVerificationHook};
+use crate::host::{self, HostAgents, HostMetadata};
+use crate::init_data::InitData;
+use crate::maintenance::{MaintenanceReport, MaintenanceStats};
+use crate::nonces::Nonces;
+use crate::policy_engine::shadow::{ShadowCount, ShadowStats};
+use crate::policy_engine::signing::{self, PolicySignatureVerifier};
+use crate::policy_engine::{
+    policy_digest, select_default_policies, ParameterValues, PolicyEngine, PolicyEvaluation,
+    PolicyResult, PolicyRevision,
+};
+use crate::posture::{Finding, SecurityPosture};
+use crate::progress::{Progress, Step};
+use crate::reappraisal::{ReappraisalFilter, ReappraisalReport};
+use crate::rejections::{RejectionCount, RejectionStage, Rejections};
+use crate::replay::SeenEvidence;
+use crate::result_cache::{self, Appraisal, AttestAgain, ResultCache, Verified};
+use crate::rvps::store::StoreType;
+use crate::rvps::watch::Watch;
+use crate::rvps::{Message, RVPSAPI};
+use crate::self_test::SelfTest;
+use crate::status::{ServiceStatus, SERVICE_STATUS_CLAIM, SERVICE_STATUS_TYP};
+use crate::submitter::{self, Submitter};
+use crate::tofu::{Provisional, ProvisionalValue};
+use crate::token::cosign::CoSigner;
+use crate::token::mapper::{ClaimMapper, ClaimMapperConfig};
+use crate::tracing::{self, Tracer};
+use crate::transparency::{InclusionProof, Receipt, TransparencyLog, TREE_HEAD_TYP};
+use crate::verifier::canonical::{self, CanonicalClaim};
+use crate::verifier::detail::{self, DetailLevel};
+use crate::verifier::diagnostics::{self, Diagnostics};
+use crate::verifier::encoding::MeasurementEncoding;
+use crate::verifier::expiry::Expiry;
+use crate::verifier::freshness::{self, Challenge, FreshnessMethod};
+use crate::verifier::pipeline;
+use crate::verifier::profile::{self, Profile};
+use crate::verifier::registry::{VerifierFactory, VerifierRegistry};
+use crate::verifier::report_data::ReportDataMode;
+use crate::verifier::schema::ClaimsSchema;
+use crate::verifier::spdm::{self, SpdmDevice};
+use crate::verifier::timing;
+use crate::verifier::trace;
+use crate::verifier::transform;
+use crate::worker::WorkerPool;
+use crate::{
+    capabilities, fips, history, metrics, migration, policy_engine, posture, rvps, self_test,
+    status, verifier,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use as_types::bundle::{BundledItem, EvidenceBundle, Unbundled};
+use as_types::{
+    PolicyData, PolicyDecision, PolicyTestReport, SetPolicyDataInput, SetPolicyInput,
+    TestPolicyInput,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use futures::StreamExt;
+use kbs_types::{Attestation, Tee};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(any(feature = "rvps-grpc", feature = "rvps-native"))]
+use std::{fs, str::FromStr};
+
+#[cfg(any(feature = "rvps-grpc", feature = "rvps-native"))]
+use crate::policy_engine::PolicyEngineType;
+
+#[cfg(all(feature = "rvps-native", feature = "corim"))]
+use crate::rvps::extractors::extractor_modules::corim::CorimExtractor;
+
+use crate::utils::{self, flatten_claims};
+
+/// Per-request options of [`AttestationService::evaluate_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct EvaluateOptions {
+    /// Token issued to the attester by a previous attestation. The new token
+    /// refers to it, and tells whether the TCB status is unchanged since.
+    pub previous_token: Option<String>,
+    /// Where to record the intermediate data of the verification, for
+    /// debugging, see [`verifier::diagnostics`].
+    pub diagnostics: Option<Diagnostics>,
+    /// Where to time the steps of the verifier, in builds with the
+    /// `profiling` feature, see [`verifier::profile`].
+    pub profile: Option<Profile>,
+    /// How the verifier compares the report data of the evidence with the
+    /// nonce, instead of as configured, see [`verifier::report_data`].
+    pub report_data_mode: Option<ReportDataMode>,
+    /// PEM CSR of the TEE public key, to issue a certificate for, see
+    /// [`crate::certificate`].
+    pub csr: Option<String>,
+    /// Values of the parameters the policy declares, configured for the
+    /// tenant and of the request, see [`ParameterValues`].
+    pub policy_parameters: ParameterValues,
+    /// Authenticated identity of the relay that submitted the evidence on
+    /// behalf of the attester, see [`crate::submitter`].
+    pub submitter: Option<Submitter>,
+    /// Metadata of the host signed by a registered host agent, see
+    /// [`crate::host`].
+    pub host_metadata: Option<HostMetadata>,
+    /// Version of the claims schema, instead of as configured, see
+    /// [`verifier::schema`].
+    pub claims_schema: Option<ClaimsSchema>,
+    /// Encoding of the measurement register claims, instead of as
+    /// configured, see [`verifier::encoding`].
+    pub measurement_encoding: Option<MeasurementEncoding>,
+    /// Format of the token, instead of as configured, see [`crate::token::ear`].
+    pub token_format: Option<TokenFormat>,
+    /// How much of the verification the token tells, instead of as
+    /// configured, see [`verifier::detail`].
+    pub detail_level: Option<DetailLevel>,
+    /// Tenant of the request, that selects the default policies, see
+    /// [`policy_engine::DefaultPolicy`].
+    pub tenant: Option<String>,
+    /// Where to report the steps of the evaluation as it reaches them, see
+    /// [`crate::progress`].
+    pub progress: Option<Progress>,
+    /// ID of the attestation in the audit trail, instead of a new one, see
+    /// [`crate::audit`].
+    pub attestation_id: Option<String>,
+    /// Init-data document of a bundle, see [`AttestationService::unbundle`],
+    /// or of the request, that the evidence must bind, see
+    /// [`crate::init_data`].
+    pub init_data: Option<BundledItem>,
+}
+
+/// Attestation of a batch, see [`AttestationService::verify_batch`].
+#[derive(Debug, Clone)]
+pub struct BatchItem {
+    pub tee: Tee,
+    pub nonce: String,
+    /// JSON attestation, as given to [`AttestationService::evaluate`].
+    pub attestation: String,
+    pub options: EvaluateOptions,
+}
+
+/// Outcome of [`AttestationService::evaluate_with_options`].
+#[derive(Debug, Clone)]
+pub struct Evaluation {
+    pub token: String,
+    /// Non-fatal verification warnings, see [`verifier::warnings`].
+    pub warnings: Vec<String>,
+    /// PEM certificate chain issued for the CSR of the request, if any.
+    pub certificate: Option<String>,
+    /// Secrets released by the post-verification hooks, by hook name, as
+    /// JWE encrypted to the TEE public key, see [`crate::hooks`].
+    pub secrets: BTreeMap<String, String>,
+    /// Entry of the token in the transparency log, see [`crate::transparency`].
+    pub receipt: Option<Receipt>,
+    /// The policies the claims were evaluated with, and their results.
+    pub policies: Vec<PolicyResult>,
+    /// The claims of the token, but those the token broker adds, such as
+    /// `iss` and `exp`, for callers that would otherwise parse the token.
+    pub claims: serde_json::Value,
+    /// ID of the attestation in the audit trail, if the AS has one, see
+    /// [`crate::audit`].
+    pub attestation_id: Option<String>,
+}
+
+/// What the checks of an evaluation appraised, to issue a token from, see
+/// [`AttestationService::appraise`].
+struct Appraised<'a> {
+    /// Admission of the evaluation, until the token is issued.
+    admitted: Admitted<'a>,
+    attestation: Attestation,
+    token_format: TokenFormat,
+    host: Option<serde_json::Value>,
+    warnings: Vec<String>,
+    verified_at: Instant,
+    replayed: Option<bool>,
+    flattened_claims: serde_json::Value,
+    deny_list_generation: u64,
+    claims_schema: ClaimsSchema,
+    claim_filters: claim_filter::ClaimFilters<'a>,
+    detail_level: DetailLevel,
+    unconfirmed: Vec<String>,
+    /// Policy ID, input and reference values of each policy, to cache.
+    appraised: Vec<(String, String, HashMap<String, Vec<String>>)>,
+    policy_evaluation: PolicyEvaluation,
+    policies: Vec<PolicyResult>,
+}
+
+pub struct AttestationService {
+    config: Config,
+    policy_engine: Box<dyn PolicyEngine + Send + Sync>,
+    rvps: Box<dyn RVPSAPI + Send + Sync>,
+    token_broker: Box<dyn AttestationTokenBroker + Send + Sync>,
+    co_signers: CoSigners,
+    claim_mapper: Option<Box<dyn ClaimMapper + Send + Sync>>,
+    certificate_issuer: Option<CertificateIssuer>,
+    hooks: Hooks,
+    evidence_decryptor: EvidenceDecryptor,
+    workers: WorkerPool,
+    admission: Admission,
+    rejections: Rejections,
+    shadow: ShadowStats,
+    transparency_log: Option<Box<dyn TransparencyLog + Send + Sync>>,
+    /// Removes the work dir of [`AttestationService::new_in_memory`] when
+    /// the service is dropped.
+    _temporary_work_dir: Option<tempfile::TempDir>,
+    seen_evidence: Option<SeenEvidence>,
+    failure_cache: Option<FailureCache>,
+    result_cache: Option<ResultCache>,
+    nonces: Option<Nonces>,
+    provisional: Option<Provisional>,
+    deny_list: DenyList,
+    reference_values_watch: Option<Watch>,
+    advisory_feed: Option<AdvisoryFeed>,
+    history: Option<History>,
+    audit: Option<Audit>,
+    tracer: Option<Tracer>,
+    spdm_devices: Vec<SpdmDevice>,
+    policy_signature_verifier: Option<PolicySignatureVerifier>,
+    host_agents: HostAgents,
+    maintenance: Mutex<MaintenanceStats>,
+    verifiers: VerifierRegistry,
+}
+
+impl AttestationService {
+    /// Create a new Attestation Service instance.
+    #[cfg(feature = "rvps-native")]
+    pub fn new(config: Config) -> Result<Self> {
+        let rvps_store = config.rvps_store_type.to_store()?;
+        #[allow(unused_mut)]
+        let mut rvps = rvps::Core::new(rvps_store);
+        #[cfg(feature = "corim")]
+        rvps.with_extractor(
+            "corim",
+            Box::new(CorimExtractor::new(verifier::anchors::anchors(
+                &config.trust_anchors.corim,
+            )?)),
+        );
+        Self::build(config, Box::new(rvps))
+    }
+
+    /// Create an Attestation Service for integration tests, which starts in
+    /// milliseconds and leaves nothing behind: the reference values are kept
+    /// in memory, the policies in a temporary work dir that is removed with
+    /// the service, and tokens are signed with an ephemeral key. Evidence of
+    /// the sample TEE, see [`self_test::sample_attestation`], complies with
+    /// the default policy.
+    #[cfg(feature = "rvps-native")]
+    pub fn new_in_memory() -> Result<Self> {
+        let work_dir = tempfile::tempdir().context("Create temporary work dir")?;
+        let config = Config {
+            work_dir: work_dir.path().to_path_buf(),
+            rvps_store_type: StoreType::InMemory,
+            ..Default::default()
+        };
+        let mut service = Self::new(config)?;
+        service._temporary_work_dir = Some(work_dir);
+        Ok(service)
+    }
+
+    /// Create a new Attestation Service, and connect to a remote rvps.
+    #[cfg(feature = "rvps-grpc")]
+    pub async fn new_with_rvps_grpc(rvps_addr: &str, config: Config) -> Result<Self> {
+        let mut rvps = rvps::Agent::new(rvps_addr).await?;
+        if let Some(cache) = &config.rvps_cache {
+            rvps = rvps.with_cache(cache.clone());
+        }
+        Self::build(config, Box::new(rvps))
+    }
+
+    /// Create an Attestation Service with the reference values of `rvps`.
+    #[cfg(any(feature = "rvps-grpc", feature = "rvps-native"))]
+    fn build(config: Config, rvps: Box<dyn RVPSAPI + Send + Sync>) -> Result<Self> {
+        if !config.work_dir.as_path().exists() {
+            fs::create_dir_all(&config.work_dir)
+                .map_err(|e| anyhow!("Create AS work dir failed: {:?}", e))?;
+        }
+
+        let policy_engine = PolicyEngineType::from_str(&config.policy_engine)
+            .map_err(|_| anyhow!("Policy Engine {} is not supported", &config.policy_engine))?
+            .to_policy_engine(config.work_dir.as_path())?;
+
+        let token_broker = config
+            .attestation_token_broker
+            .to_token_broker(config.attestation_token_config.clone())?;
+
+        if config.fips_mode() {
+            fips::check_config(&config)?;
+        }
+
+        let co_signers = CoSigners::new(&config.attestation_token_config.co_signers)?;
+        let claim_mapper = config
+            .attestation_token_config
+            .claim_mapper
+            .as_ref()
+            .map(ClaimMapperConfig::to_claim_mapper)
+            .transpose()
+            .context("Cannot load the token claim mapper")?;
+        let certificate_issuer = config
+            .certificate_issuer
+            .as_ref()
+            .map(CertificateIssuer::new)
+            .transpose()
+            .context("Cannot load the certificate issuer")?;
+        let hooks = Hooks::new(&config.post_verification_hooks)?;
+        let evidence_decryptor = EvidenceDecryptor::new(&config.evidence_decryption_keys)?;
+        let workers = WorkerPool::new(config.worker_threads);
+        let admission = Admission::new(config.admission.clone());
+        let seen_evidence = config.replay_protection.clone().map(SeenEvidence::new);
+        let failure_cache = config.failure_cache.clone().map(FailureCache::new);
+        let result_cache = config.result_cache.clone().map(ResultCache::new);
+        let nonces = config
+            .nonces
+            .clone()
+            .map(|nonces| Nonces::new(nonces, &config.work_dir))
+            .transpose()?;
+        let provisional = config
+            .tofu
+            .clone()
+            .map(|tofu| Provisional::new(tofu, &config.work_dir))
+            .transpose()?;
+        let deny_list = DenyList::new(&config.work_dir)?;
+        let reference_values_watch = config.reference_values_watch.clone().map(Watch::new);
+        let advisory_feed = config
+            .advisory_feed
+            .clone()
+            .map(AdvisoryFeed::new)
+            .transpose()?;
+        let transparency_log = config
+            .transparency_log
+            .as_ref()
+            .map(|log| log.to_transparency_log(&config.work_dir))
+            .transpose()
+            .context("Cannot open the transparency log")?;
+        let history = config
+            .history
+            .clone()
+            .map(|history| History::new(history, &config.work_dir))
+            .transpose()?;
+        let audit = config
+            .audit
+            .clone()
+            .map(Audit::new)
+            .transpose()
+            .context("Cannot open the audit trail")?;
+        let tracer = config.tracing.clone().map(Tracer::new).transpose()?;
+        let spdm_devices = spdm::devices(&config.all_spdm_devices(), config.crypto_backend)
+            .context("Cannot load the SPDM device verifiers")?;
+        let policy_signature_verifier = config
+            .policy_signing
+            .as_ref()
+            .map(PolicySignatureVerifier::new)
+            .transpose()
+            .context("Cannot load the policy admin key")?;
+        let host_agents =
+            HostAgents::new(&config.host_agents).context("Cannot load the host agent keys")?;
+        // Load the offline collateral bundle, if any, at startup.
+        #[cfg(any(feature = "tdx-verifier", feature = "sgx-verifier"))]
+        verifier::collateral::provider(&config.collateral)
+            .context("Cannot load the DCAP collateral")?;
+
+        Ok(Self {
+            config,
+            policy_engine,
+            rvps,
+            token_broker,
+            co_signers,
+            claim_mapper,
+            certificate_issuer,
+            hooks,
+            evidence_decryptor,
+            workers,
+            admission,
+            rejections: Rejections::default(),
+            shadow: ShadowStats::default(),
+            transparency_log,
+            _temporary_work_dir: None,
+            seen_evidence,
+            failure_cache,
+            result_cache,
+            nonces,
+            provisional,
+            deny_list,
+            reference_values_watch,
+            advisory_feed,
+            history,
+            audit,
+            tracer,
+            spdm_devices,
+            policy_signature_verifier,
+            host_agents,
+            maintenance: Mutex::default(),
+            verifiers: VerifierRegistry::default(),
+        })
+    }
+
+    /// Set Attestation Verification Policy. With `policy_signing` in the
+    /// config, the policy must be signed by the admin key, see
+    /// [`policy_engine::signing`].
+    pub async fn set_policy(&mut self, input: SetPolicyInput) -> Result<()> {
+        if let Some(verifier) = &self.policy_signature_verifier {
+            let policy = URL_SAFE_NO_PAD
+                .decode(&input.policy)
+                .context("Cannot Set Policy: malformed policy")?;
+            verifier
+                .verify(&policy, input.signature.as_deref())
+                .context("Cannot Set Policy")?;
+        }
+        self.policy_engine
+            .set_policy(input)
+            .await
+            .map_err(|e| anyhow!("Cannot Set Policy: {:?}", e))
+    }
+
+    /// Get the digest of each policy, by policy ID.
+    pub async fn list_policies(&self) -> Result<BTreeMap<String, String>> {
+        self.policy_engine
+            .list_policies()
+            .await
+            .context("Cannot List Policies")
+    }
+
+    /// Get the text of the policy `policy_id`.
+    pub async fn get_policy(&self, policy_id: &str) -> Result<String> {
+        self.policy_engine
+            .get_policy(policy_id)
+            .await
+            .context("Cannot Get Policy")
+    }
+
+    /// Delete the policy `policy_id`. Its revisions are kept, so that it
+    /// can be rolled back. With `policy_signing` in the config, `signature`
+    /// must be that of the [`signing::delete_statement`] by the admin key.
+    pub async fn delete_policy(&mut self, policy_id: &str, signature: Option<&str>) -> Result<()> {
+        if let Some(verifier) = &self.policy_signature_verifier {
+            verifier
+                .verify_operation(&signing::delete_statement(policy_id), signature)
+                .context("Cannot Delete Policy")?;
+        }
+        self.policy_engine
+            .delete_policy(policy_id)
+            .await
+            .context("Cannot Delete Policy")
+    }
+
+    /// Get the revisions of the policy `policy_id`, oldest first.
+    pub async fn policy_revisions(&self, policy_id: &str) -> Result<Vec<PolicyRevision>> {
+        self.policy_engine
+            .policy_revisions(policy_id)
+            .await
+            .context("Cannot Get Policy Revisions")
+    }
+
+    /// Set the policy `policy_id` back to its revision of `digest`. With
+    /// `policy_signing` in the config, `signature` must be that of the
+    /// [`signing::rollback_statement`] by the admin key.
+    pub async fn rollback_policy(
+        &mut self,
+        policy_id: &str,
+        digest: &str,
+        signature: Option<&str>,
+    ) -> Result<()> {
+        if let Some(verifier) = &self.policy_signature_verifier {
+            verifier
+                .verify_operation(&signing::rollback_statement(policy_id, digest), signature)
+                .context("Cannot Roll Back Policy")?;
+        }
+        self.policy_engine
+            .rollback_policy(policy_id, digest)
+            .await
+            .context("Cannot Roll Back Policy")
+    }
+
+    /// Run a candidate policy against a suite of claims fixtures, without
+    /// installing it, and return a report of the expected and actual decisions.
+    pub async fn test_policy(&self, input: TestPolicyInput) -> Result<PolicyTestReport> {
+        self.policy_engine
+            .test_policy(input)
+            .await
+            .map_err(|e| anyhow!("Cannot Test Policy: {:?}", e))
+    }
+
+    /// Store a new version of a data document that policies can consult at
+    /// `data.custom.<name>`, and return its version. With `policy_signing`
+    /// in the config, `signature` must be that of the
+    /// [`signing::set_policy_data_statement`] by the admin key.
+    pub async fn set_policy_data(
+        &mut self,
+        input: SetPolicyDataInput,
+        signature: Option<&str>,
+    ) -> Result<u64> {
+        if let Some(verifier) = &self.policy_signature_verifier {
+            verifier
+                .verify_operation(
+                    &signing::set_policy_data_statement(&input.name, &input.data),
+                    signature,
+                )
+                .context("Cannot Set Policy Data")?;
+        }
+        self.policy_engine
+            .set_policy_data(input)
+            .await
+            .context("Cannot Set Policy Data")
+    }
+
+    /// Get a version of a policy data document, the latest if `version` is
+    /// `None`.
+    pub async fn get_policy_data(&self, name: &str, version: Option<u64>) -> Result<PolicyData> {
+        self.policy_engine
+            .get_policy_data(name, version)
+            .await
+            .context("Cannot Get Policy Data")
+    }
+
+    /// Get the text of a policy by the digest in the `policy_digest` claim
+    /// of the tokens it admitted, even if it was replaced since.
+    pub async fn get_policy_by_digest(&self, digest: &str) -> Result<String> {
+        self.policy_engine
+            .get_policy_by_digest(digest)
+            .await
+            .context("Cannot Get Policy")
+    }
+
+    /// Stage a token signing key, given as an encrypted PKCS#8 PEM document,
+    /// for a planned issuer key migration. It is published with
+    /// [`AttestationService::signing_keys`] until it is promoted.
+    pub fn import_signing_key(&mut self, pkcs8_pem: &str, password: &str) -> Result<()> {
+        self.token_broker
+            .import_signing_key(pkcs8_pem, password)
+            .context("Cannot import signing key")
+    }
+
+    /// Get the public token signing keys in JWKS format: the active key,
+    /// the other configured keys until they expire, the staged key if any,
+    /// and the retired keys until the tokens they signed expire.
+    pub fn signing_keys(&self) -> Result<String> {
+        self.token_broker.pubkey_jwks()
+    }
+
+    /// Sign tokens with the staged signing key from now on. The key it
+    /// replaces is published, and its tokens accepted as previous tokens,
+    /// until they expire.
+    pub fn promote_signing_key(&mut self) -> Result<()> {
+        self.token_broker
+            .promote_signing_key()
+            .context("Cannot promote signing key")
+    }
+
+    /// Have tokens co-signed by `co_signer` too, after the configured
+    /// co-signers, e.g. to sign with a key held by a remote signing service,
+    /// see [`CoSigner`].
+    pub fn add_token_co_signer(&mut self, co_signer: Box<dyn CoSigner + Send + Sync>) {
+        self.co_signers.push(co_signer);
+    }
+
+    /// Reshape the claims of the tokens with `claim_mapper`, in place of the
+    /// configured one, see [`ClaimMapper`].
+    pub fn set_token_claim_mapper(&mut self, claim_mapper: Box<dyn ClaimMapper + Send + Sync>) {
+        self.claim_mapper = Some(claim_mapper);
+    }
+
+    /// Run `hook` for every allowed attestation too, after the configured
+    /// hooks, see [`PostVerificationHook`]. The attestation fails if a
+    /// `required` hook fails.
+    pub fn add_post_verification_hook(
+        &mut self,
+        name: &str,
+        required: bool,
+        hook: Box<dyn PostVerificationHook + Send + Sync>,
+    ) {
+        self.hooks.push(name, required, hook);
+    }
+
+    /// Verify the evidence of `tee` with the verifiers of `factory`, in
+    /// place of the compiled in verifier of `tee`, e.g. a verifier of
+    /// another crate. It is made from the verifier config, with its section
+    /// of `verifiers` in the AS config, see [`crate::verifier::registry`].
+    pub fn register_verifier(
+        &mut self,
+        tee: Tee,
+        factory: impl VerifierFactory + 'static,
+    ) -> Result<()> {
+        self.verifiers.register(tee, factory)
+    }
+
+    /// Export the policies, policy data and reference values as an archive
+    /// signed with the token signing key, see [`crate::backup`].
+    pub async fn export_state(&self) -> Result<String> {
+        let backup = Backup {
+            version: BACKUP_VERSION,
+            policy_engine: self.policy_engine.export_state().await?,
+            reference_values: self.rvps.export_reference_values().await?,
+            deny_list: Some(self.deny_list.list()?),
+            signing_keys: serde_json::from_str(&self.token_broker.pubkey_jwks()?)?,
+        };
+        self.token_broker
+            .issue(json!({ "typ": BACKUP_TYP, BACKUP_CLAIM: backup }))
+            .context("Cannot sign backup")
+    }
+
+    /// Restore an archive of [`AttestationService::export_state`], replacing
+    /// the policies, policy data versions and reference values with the
+    /// same names, and the deny-list. The archive must be signed by this
+    /// AS, or by a key of the `state_signers` of its config, and with
+    /// `policy_signing` in the config, each policy by the admin key.
+    pub async fn import_state(&mut self, archive: &str) -> Result<()> {
+        let claims = match self.token_broker.verify(archive) {
+            Ok(claims) => claims,
+            Err(e) => backup::signer_jwks(&self.config.state_signers)?
+                .iter()
+                .find_map(|jwks| self.token_broker.verify_with_jwks(archive, jwks).ok())
+                .ok_or(e)
+                .context("Invalid backup archive, not signed by this AS or a state signer")?,
+        };
+        let backup = Backup::from_claims(&claims)?;
+        if let Some(verifier) = &self.policy_signature_verifier {
+            let state = &backup.policy_engine;
+            for (policy_id, policy) in &state.policies {
+                let signature = state.signatures.get(&policy_digest(policy.as_bytes()));
+                verifier
+                    .verify(policy.as_bytes(), signature.map(String::as_str))
+                    .with_context(|| format!("Cannot restore policy {policy_id}"))?;
+            }
+        }
+
+        self.policy_engine
+            .import_state(backup.policy_engine)
+            .await
+            .context("Cannot restore policies")?;
+        if let Some(reference_values) = backup.reference_values {
+            self.rvps
+                .import_reference_values(reference_values)
+                .await
+                .context("Cannot restore reference values")?;
+        }
+        if let Some(deny_list) = backup.deny_list {
+            self.deny_list
+                .replace(deny_list)
+                .context("Cannot restore the deny-list")?;
+        }
+        Ok(())
+    }
+
+    /// Challenge an attester of `tee` to produce evidence with the strongest
+    /// freshness method that the verifier checks among those the attester
+    /// `offers`, all of them if empty, see [`freshness`].
+    pub async fn challenge(&self, tee: &Tee, offered: &[FreshnessMethod]) -> Result<Challenge> {
+        let verifier = self
+            .verifiers
+            .to_verifier(tee, &self.config.verifier_config(), None)?;
+        let method = freshness::negotiate(&verifier.freshness_methods(), offered)?;
+        let mut challenge = Challenge::new(method);
+        if let (Some(nonces), Some(nonce)) = (&self.nonces, &challenge.nonce) {
+            challenge.expires_at = Some(nonces.issue(nonce).await?);
+        }
+        Ok(challenge)
+    }
+
+    /// Whether the AS runs in FIPS mode, only using FIPS approved
+    /// algorithms.
+    pub fn fips_mode(&self) -> bool {
+        self.config.fips_mode()
+    }
+
+    /// Whether the AS refuses to start with insecure settings.
+    pub fn strict_security(&self) -> bool {
+        self.config.strict_security
+    }
+
+    /// The insecure settings the AS runs with, but those of the listeners
+    /// of its server, see [`posture`].
+    pub async fn security_posture(&self) -> Result<SecurityPosture> {
+        let mut posture = SecurityPosture::of_config(&self.config);
+        let default_policy = self.policy_engine.get_policy("default").await.ok();
+        if posture::permissive_default_policy(&self.config, default_policy.as_deref()) {
+            posture.add(Finding::PermissiveDefaultPolicy);
+        }
+        Ok(posture)
+    }
+
+    /// The status of the AS, but the digest of its config, which it does
+    /// not know, and the insecure settings of the listeners of its server,
+    /// see [`status`].
+    pub async fn service_status(&self) -> Result<ServiceStatus> {
+        let policies = self.policy_engine.list_policies().await?;
+        let reference_values = self
+            .rvps
+            .export_reference_values()
+            .await?
+            .map(|reference_values| status::reference_values_digest(&reference_values))
+            .transpose()?;
+        let verifiers = self
+            .capabilities()
+            .verifiers
+            .into_iter()
+            .filter(|verifier| verifier.enabled)
+            .map(|verifier| {
+                let version = match verifier.registered {
+                    true => status::REGISTERED_VERIFIER,
+                    false => env!("CARGO_PKG_VERSION"),
+                };
+                (verifier.tee, version.to_string())
+            })
+            .collect();
+        Ok(ServiceStatus {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            policies,
+            reference_values,
+            verifiers,
+            config: None,
+            fips_mode: self.fips_mode(),
+            insecure_settings: self
+                .security_posture()
+                .await?
+                .findings
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        })
+    }
+
+    /// Sign `status` with the token signing key, with the `nonce` of the
+    /// request, see [`status`].
+    pub fn issue_service_status(&self, status: &ServiceStatus, nonce: &str) -> Result<String> {
+        self.token_broker
+            .issue(json!({
+                "typ": SERVICE_STATUS_TYP,
+                SERVICE_STATUS_CLAIM: status,
+                "nonce": nonce,
+            }))
+            .context("Cannot sign the service status")
+    }
+
+    /// What the AS supports, see [`capabilities`].
+    pub fn capabilities(&self) -> Capabilities {
+        capabilities::capabilities(&self.config, &self.verifiers)
+    }
+
+    /// Canonical claims of the policy input, and the claims of each
+    /// verifier they are derived from, see [`canonical`].
+    pub fn canonical_claims() -> &'static [CanonicalClaim] {
+        canonical::CANONICAL_CLAIMS
+    }
+
+    /// Expiry of the verification collateral and trusted certificates
+    /// known so far, the soonest first, see [`verifier::expiry`].
+    pub fn collateral_expiry() -> Vec<Expiry> {
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        verifier::expiry::expiries(now)
+    }
+
+    /// Register the platforms of the fleet, whose DCAP collateral is kept
+    /// warm, in place of those registered before, and prefetch their
+    /// collateral, see [`verifier::collateral`].
+    #[cfg(any(feature = "tdx-verifier", feature = "sgx-verifier"))]
+    pub async fn register_platforms(
+        &self,
+        platforms: Vec<verifier::collateral::CollateralKey>,
+    ) -> Result<Vec<verifier::collateral::PlatformCollateral>> {
+        let provider = verifier::collateral::provider(&self.config.collateral)?;
+        provider.register(platforms)?;
+        Ok(provider.prefetch().await)
+    }
+
+    /// Saturation of the AS, against the limits of its admission config.
+    pub fn load(&self) -> Load {
+        self.admission.load(&self.workers)
+    }
+
+    /// The attestations rejected since the AS started, by TEE and stage.
+    pub fn rejections(&self) -> Vec<RejectionCount> {
+        self.rejections.counts()
+    }
+
+    /// The metrics of the AS in the Prometheus text format, see
+    /// [`crate::metrics`].
+    pub fn metrics(&self) -> String {
+        metrics::render(
+            self.rejections
+                .counts()
+                .into_iter()
+                .map(|count| (count.tee, count.stage.to_string(), count.count))
+                .collect(),
+        )
+    }
+
+    /// The evaluations of the shadow policies since the AS started, by
+    /// primary and shadow policy, see [`policy_engine::shadow`].
+    pub fn shadow_stats(&self) -> Vec<ShadowCount> {
+        self.shadow.counts()
+    }
+
+    /// Export the attestation history in `format`, with the selected
+    /// `columns`, or every column, see [`history`].
+    pub fn export_history(&self, format: ExportFormat, columns: &[String]) -> Result<Vec<u8>> {
+        let records = self
+            .history
+            .as_ref()
+            .ok_or_else(|| anyhow!("The AS records no attestation history"))?
+            .records()?;
+        history::export(&records, format, columns)
+    }
+
+    /// Prune the stores of the AS, see [`crate::maintenance`].
+    pub async fn maintain(&mut self) -> Result<MaintenanceReport> {
+        let mut report = MaintenanceReport::default();
+        if let Some(seen_evidence) = &self.seen_evidence {
+            report.replay_entries = seen_evidence.prune()?;
+        }
+        if let Some(nonces) = &self.nonces {
+            report.nonces = nonces
+                .prune()
+                .await
+                .context("Cannot prune the expired nonces")?;
+        }
+        report.reference_values = self
+            .rvps
+            .prune_expired()
+            .await
+            .context("Cannot prune the expired reference values")?;
+        if let Some(history) = &self.history {
+            (report.history_records, report.reclaimed_bytes) = history
+                .prune()
+                .context("Cannot prune the attestation history")?;
+        }
+        self.maintenance
+            .lock()
+            .map_err(|_| anyhow!("Maintenance stats are poisoned"))?
+            .record(&report);
+        Ok(report)
+    }
+
+    /// The maintenance runs since the AS started.
+    pub fn maintenance_stats(&self) -> Result<MaintenanceStats> {
+        Ok(self
+            .maintenance
+            .lock()
+            .map_err(|_| anyhow!("Maintenance stats are poisoned"))?
+            .clone())
+    }
+
+    /// The allowed attestations of the history that match `filter`, to
+    /// appraise again with [`AttestationService::reappraise`].
+    pub fn reappraisal_records(&self, filter: &ReappraisalFilter) -> Result<Vec<HistoryRecord>> {
+        let history = self
+            .history
+            .as_ref()
+            .ok_or_else(|| anyhow!("The AS records no attestation history"))?;
+        let mut records = history.records()?;
+        records.retain(|record| filter.matches(record));
+        Ok(records)
+    }
+
+    /// Verify the recorded evidence of the attestation `record` of the
+    /// history again, against the current collateral, reference values
+    /// and policies, and add to `report` whether it would now be denied,
+    /// see [`crate::reappraisal`]. Each record is appraised on its own, so
+    /// that a long reappraisal does not hold the AS.
+    pub async fn reappraise(
+        &self,
+        record: &HistoryRecord,
+        report: &mut ReappraisalReport,
+    ) -> Result<()> {
+        let history = self
+            .history
+            .as_ref()
+            .ok_or_else(|| anyhow!("The AS records no attestation history"))?;
+        let Some(evidence) = history.evidence(record)? else {
+            report.without_evidence += 1;
+            return Ok(());
+        };
+        let tee: Tee = serde_json::from_value(json!(record.tee))
+            .with_context(|| format!("Unknown TEE {} in the history", record.tee))?;
+        // The evidence is appraised against the current collateral,
+        // reference values and policies, not for its freshness.
+        let options = EvaluateOptions {
+            policy_parameters: evidence.policy_parameters.clone(),
+            tenant: record.tenant.clone(),
+            ..Default::default()
+        };
+        let verification = self
+            .explain_with(tee, &evidence.nonce, &evidence.attestation, options, false)
+            .await;
+        report.add(record, verification);
+        Ok(())
+    }
+
+    /// Proof of the inclusion of the token with receipt `index` in the
+    /// transparency log. The tree heads of the local log are signed by the
+    /// token broker, as statements that do not expire with the tokens.
+    pub async fn inclusion_proof(&self, index: u64) -> Result<InclusionProof> {
+        let mut proof = self
+            .transparency_log
+            .as_ref()
+            .ok_or_else(|| anyhow!("The AS has no transparency log"))?
+            .inclusion_proof(index)
+            .await?;
+        if proof.signed_tree_head.is_none() {
+            proof.signed_tree_head = Some(self.token_broker.issue_statement(json!({
+                "typ": TREE_HEAD_TYP,
+                "tree_size": proof.tree_size,
+                "root_hash": proof.root_hash,
+            }))?);
+        }
+        Ok(proof)
+    }
+
+    /// Whether the AS records the attestations in an audit trail, see
+    /// [`crate::audit`].
+    pub fn audited(&self) -> bool {
+        self.audit.is_some()
+    }
+
+    /// The audit record of the attestation `attestation_id`, if it is
+    /// still in the audit trail.
+    pub fn audit_record(&self, attestation_id: &str) -> Result<Option<AuditRecord>> {
+        self.audit
+            .as_ref()
+            .ok_or_else(|| anyhow!("The AS has no audit trail"))?
+            .record(attestation_id)
+    }
+
+    /// Check a composite evidence `bundle`, see [`as_types::bundle`], and
+    /// turn it into the evidence of a request. The AS must verify all the
+    /// kinds of devices of the bundle, rather than ignore their evidence.
+    pub fn unbundle(&self, bundle: EvidenceBundle) -> Result<Unbundled> {
+        let unbundled = bundle.unbundle().context("Invalid evidence bundle")?;
+        for kind in &unbundled.devices {
+            if !self
+                .spdm_devices
+                .iter()
+                .any(|device| device.evidence_field() == kind)
+            {
+                bail!("The AS does not verify the evidence of {kind} devices");
+            }
+        }
+        Ok(unbundled)
+    }
+
+    /// Evaluate Attestation Evidence.
+    /// Issue an attestation results token which contain TCB status and TEE public key.
+    /// If the policy denies the evidence, the error can be downcast to
+    /// [`policy_engine::PolicyDenied`] to get the policy violations. If the
+    /// policy does not apply to the evidence, it is a
+    /// [`policy_engine::PolicyMismatch`] error, and if the AS is overloaded,
+    /// an [`crate::admission::Overloaded`] error.
+    pub async fn evaluate(&self, tee: Tee, nonce: &str, attestation: &str) -> Result<String> {
+        let evaluation = self
+            .evaluate_with_options(tee, nonce, attestation, EvaluateOptions::default())
+            .await?;
+        Ok(evaluation.token)
+    }
+
+    /// Check that a batch of `len` attestations is within
+    /// `admission.max_batch_size`, before preparing it.
+    pub fn check_batch(&self, len: usize) -> Result<()> {
+        self.admission.check_batch(len)
+    }
+
+    /// Evaluate the attestations of a batch like
+    /// [`AttestationService::evaluate_with_options`], as many at a time as
+    /// there are worker threads, and return the outcome of each, in order.
+    /// Only a batch larger than `admission.max_batch_size` fails as a whole.
+    pub async fn verify_batch(&self, items: Vec<BatchItem>) -> Result<Vec<Result<Evaluation>>> {
+        self.check_batch(items.len())?;
+        Ok(futures::stream::iter(items)
+            .map(|item| async move {
+                self.evaluate_with_options(item.tee, &item.nonce, &item.attestation, item.options)
+                    .await
+            })
+            .buffered(self.workers.threads())
+            .collect()
+            .await)
+    }
+
+    /// Evaluate Attestation Evidence like [`AttestationService::evaluate`],
+    /// with per-request options, and return the verification warnings with
+    /// the token. The evaluation is recorded in the audit trail, if the AS
+    /// has one, see [`crate::audit`], and traced, if the AS sends traces,
+    /// see [`crate::tracing`].
+    pub async fn evaluate_with_options(
+        &self,
+        tee: Tee,
+        nonce: &str,
+        attestation: &str,
+        mut options: EvaluateOptions,
+    ) -> Result<Evaluation> {
+        let tee_name = serde_variant::to_variant_name(&tee)?;
+        let audit_trace = self.audit.as_ref().map(|audit| {
+            audit.start(
+                options.attestation_id.take(),
+                tee_name,
+                options.tenant.as_deref(),
+                attestation,
+            )
+        });
+        let started = SystemTime::now();
+        let (evaluation, spans) = tracing::collect(self.evaluate_traced(
+            tee,
+            nonce,
+            attestation,
+            options,
+            audit_trace.as_ref(),
+        ))
+        .await;
+        if let Some(tracer) = &self.tracer {
+            let error = evaluation.as_ref().err().map(|e| format!("{e:#}"));
+            tracer.export(tee_name, started, spans, error);
+        }
+        let (Some(audit), Some(audit_trace)) = (&self.audit, audit_trace) else {
+            return evaluation;
+        };
+        let attestation_id = audit.finish(audit_trace, &evaluation).await;
+        evaluation.map(|evaluation| Evaluation {
+            attestation_id: Some(attestation_id),
+            ..evaluation
+        })
+    }
+
+    /// [`AttestationService::evaluate_with_options`], recording the steps
+    /// of the evaluation in `audit_trace`, if audited.
+    async fn evaluate_traced(
+        &self,
+        tee: Tee,
+        nonce: &str,
+        attestation: &str,
+        options: EvaluateOptions,
+        audit_trace: Option<&AuditTrace>,
+    ) -> Result<Evaluation> {
+        let tee_name = serde_variant::to_variant_name(&tee)?;
+        let Appraised {
+            admitted: _admitted,
+            attestation,
+            token_format,
+            host,
+            warnings,
+            verified_at,
+            replayed,
+            flattened_claims,
+            deny_list_generation,
+            claims_schema,
+            claim_filters,
+            detail_level,
+            unconfirmed,
+            appraised,
+            policy_evaluation,
+            policies,
+        } = self
+            .appraise(
+                &tee,
+                nonce,
+                attestation,
+                &options,
+                audit_trace,
+                Explanation::none(),
+            )
+            .await?;
+        let reject = |stage: RejectionStage| {
+            move |e: anyhow::Error| {
+                if let Some(audit_trace) = audit_trace {
+                    audit_trace.rejected(stage);
+                }
+                self.rejections.reject(tee_name, stage, e)
+            }
+        };
+        let audit_step = |step: RejectionStage| {
+            if let Some(audit_trace) = audit_trace {
+                audit_trace.step(step);
+            }
+        };
+
+        let mut token_claims = json!({
+            "tee-pubkey": attestation.tee_pubkey.clone(),
+            "tcb-status": claim_filters.apply(&detail_level.token_claims(&flattened_claims)),
+            "evaluation-report": policy_evaluation.report,
+            "policy_digest": policy_evaluation.policy_digest,
+            "policies": policies,
+            "claims-schema": claims_schema,
+        });
+        if let Some(replayed) = replayed {
+            token_claims["replayed"] = replayed.into();
+        }
+        if let Some(submitter) = &options.submitter {
+            token_claims[submitter::SUBMITTER_FIELD] = json!(submitter);
+        }
+        if let Some(host) = host {
+            token_claims[host::HOST_FIELD] = host;
+        }
+        if !unconfirmed.is_empty() {
+            token_claims["unconfirmed"] = json!(unconfirmed);
+        }
+        if self.config.warnings_in_token && !warnings.is_empty() {
+            token_claims["warnings"] = json!(warnings);
+        }
+        let skipped_stages = self.config.verifier_pipelines.skipped(&tee);
+        if !skipped_stages.is_empty() {
+            token_claims["skipped-stages"] = json!(skipped_stages);
+        }
+        // Of the claims before the claim filters and the detail level, so
+        // that a change of a redacted claim breaks the continuity.
+        let continuity_digest = chain::continuity_digest(
+            tee_name,
+            &flattened_claims,
+            &self.config.token_chain_mutable_claims,
+        );
+        token_claims[chain::CONTINUITY_DIGEST_CLAIM] = continuity_digest.clone().into();
+        if let Some(previous_token) = &options.previous_token {
+            let previous = cosign::broker_signed(previous_token)
+                .and_then(|previous_token| self.token_broker.verify(&previous_token))
+                .context("Invalid previous token")
+                .map_err(reject(RejectionStage::Request))?;
+            chain::check_previous(
+                tee_name,
+                &previous,
+                &token_claims["tee-pubkey"],
+                chrono::Utc::now().timestamp(),
+            )
+            .context("Invalid previous token")
+            .map_err(reject(RejectionStage::Request))?;
+            // A previous token without the digest, e.g. mapped by a claim
+            // mapper that drops it, does not prove the continuity.
+            let continuity_ok =
+                ear::token_claim(tee_name, &previous, chain::CONTINUITY_DIGEST_CLAIM).as_str()
+                    == Some(continuity_digest.as_str());
+            if !continuity_ok {
+                let changed = chain::changed_claims(
+                    tee_name,
+                    ear::evidence_claims(tee_name, &previous),
+                    &token_claims["tcb-status"],
+                    &self.config.token_chain_mutable_claims,
+                );
+                info!("TCB status changed since the previous token: {changed:?}");
+            }
+            token_claims["previous_token"] = previous["jti"].clone();
+            token_claims["continuity_ok"] = continuity_ok.into();
+        }
+        if let Some(progress) = &options.progress {
+            progress.report(Step::Issuance);
+        }
+        audit_step(RejectionStage::Issuance);
+        let issuance_started = SystemTime::now();
+        let certificate = match &options.csr {
+            Some(csr) => Some(
+                self.certificate_issuer
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("The AS does not issue certificates"))
+                    .map_err(reject(RejectionStage::Request))?
+                    .issue(csr, &attestation.tee_pubkey, &token_claims["tcb-status"])
+                    .await
+                    .context("Certificate issuance failed")
+                    .map_err(reject(RejectionStage::Issuance))?,
+            ),
+            None => None,
+        };
+
+        let token_claims = match (token_format.is_ear(), &self.claim_mapper) {
+            (true, _) => ear::claims(tee_name, nonce, token_claims)
+                .context("EAR claims mapping failed")
+                .map_err(reject(RejectionStage::Issuance))?,
+            (false, Some(claim_mapper)) => claim_mapper
+                .map(tee_name, token_claims)
+                .context("Token claim mapping failed")
+                .map_err(reject(RejectionStage::Issuance))?,
+            (false, None) => token_claims,
+        };
+
+        let claims = token_claims.clone();
+        let attestation_results_token = match token_format {
+            TokenFormat::EarCose => URL_SAFE_NO_PAD.encode(
+                self.token_broker
+                    .issue_cose(token_claims)
+                    .map_err(reject(RejectionStage::Issuance))?,
+            ),
+            _ => self
+                .co_signers
+                .co_sign(
+                    self.token_broker
+                        .issue(token_claims)
+                        .map_err(reject(RejectionStage::Issuance))?,
+                )
+                .await
+                .map_err(reject(RejectionStage::Issuance))?,
+        };
+        metrics::count_token_issued(tee_name, &token_format.to_string());
+        tracing::record("issuance", issuance_started, Vec::new());
+        let receipt = match &self.transparency_log {
+            Some(log) => Some(
+                log.append(&attestation_results_token)
+                    .await
+                    .context("Transparency log append failed")
+                    .map_err(reject(RejectionStage::Issuance))?,
+            ),
+            None => None,
+        };
+        let secrets = self
+            .hooks
+            .run(
+                tee_name,
+                &attestation_results_token,
+                &attestation.tee_pubkey,
+            )
+            .await
+            .map_err(reject(RejectionStage::Issuance))?;
+        // COSE tokens cannot be presented to be refreshed.
+        if let Some(result_cache) = &self.result_cache {
+            if token_format != TokenFormat::EarCose {
+                let revision = result_cache::revision(appraised.iter().map(
+                    |(policy_id, _, reference_data)| {
+                        let policy_digest = policies
+                            .iter()
+                            .find(|policy| policy.policy_id == *policy_id)
+                            .map_or("", |policy| policy.policy_digest.as_str());
+                        (policy_id.as_str(), policy_digest, reference_data)
+                    },
+                ));
+                let cached = revision.and_then(|revision| {
+                    let appraisal = Appraisal {
+                        inputs: appraised
+                            .into_iter()
+                            .map(|(policy_id, input, _)| (policy_id, input))
+                            .collect(),
+                        revision,
+                        deny_list_generation,
+                        policies: policies.clone(),
+                        claims: claims.clone(),
+                    };
+                    self.cache_appraisal(
+                        result_cache,
+                        &attestation_results_token,
+                        appraisal,
+                        verified_at,
+                    )
+                });
+                if let Err(e) = cached {
+                    warn!("Cannot cache the attestation result: {e:#}");
+                }
+            }
+        }
+
+        Ok(Evaluation {
+            token: attestation_results_token,
+            warnings,
+            certificate,
+            secrets,
+            receipt,
+            policies,
+            claims,
+            attestation_id: None,
+        })
+    }
+
+    /// Run the checks of an evaluation, up to the policy decision, and
+    /// record them in `explanation`, if explaining: a token is issued from
+    /// what they appraised by [`AttestationService::evaluate_traced`], and
+    /// [`AttestationService::explain`] reports them.
+    async fn appraise<'a>(
+        &'a self,
+        tee: &Tee,
+        nonce: &str,
+        attestation: &str,
+        options: &EvaluateOptions,
+        audit_trace: Option<&AuditTrace>,
+        explanation: Explanation<'_>,
+    ) -> Result<Appraised<'a>> {
+        let tee_name = serde_variant::to_variant_name(tee)?;
+        // Explaining evidence does not reject it.
+        let reject = |stage: RejectionStage| {
+            move |e: anyhow::Error| {
+                if let Some(audit_trace) = audit_trace {
+                    audit_trace.rejected(stage);
+                }
+                match explanation.is_explaining() {
+                    true => e,
+                    false => self.rejections.reject(tee_name, stage, e),
+                }
+            }
+        };
+        let audit_step = |step: RejectionStage| {
+            if let Some(audit_trace) = audit_trace {
+                audit_trace.step(step);
+            }
+        };
+        let admitted = explanation
+            .check(
+                "Admission",
+                self.admission.admit(&self.workers).map_err(Into::into),
+                |_| "The AS has capacity for the verification".to_string(),
+            )
+            .map_err(reject(RejectionStage::Request))?;
+        if self.fips_mode() {
+            explanation
+                .check("FIPS approved algorithms", fips::check_tee(tee), |_| {
+                    "The TEE evidence is signed with approved algorithms".to_string()
+                })
+                .map_err(reject(RejectionStage::Request))?;
+        }
+        let posture = posture::check_tee(&self.config, tee);
+        let posture = match tee {
+            Tee::Sample => explanation.check("Sample verifier", posture, |_| {
+                "The sample verifier is turned on".to_string()
+            }),
+            _ => posture,
+        };
+        posture.map_err(reject(RejectionStage::Request))?;
+        let token_format = options
+            .token_format
+            .unwrap_or(self.config.attestation_token_config.format);
+        if token_format == TokenFormat::EarCose && !self.co_signers.is_empty() {
+            return Err(reject(RejectionStage::Request)(anyhow!(
+                "COSE tokens cannot be co-signed"
+            )));
+        }
+
+        let host = options
+            .host_metadata
+            .as_ref()
+            .map(|host| self.host_agents.verify(host, nonce))
+            .transpose()
+            .context("Invalid host metadata");
+        let host = match &options.host_metadata {
+            Some(_) => explanation.check("Host metadata", host, |_| {
+                "The host metadata is signed by a registered host agent".to_string()
+            }),
+            None => host,
+        }
+        .map_err(reject(RejectionStage::Request))?;
+
+        let attestation = explanation
+            .check(
+                "Evidence format",
+                serde_json::from_str::<Attestation>(attestation)
+                    .context("Failed to deserialize Attestation"),
+                |_| "The attestation is well formed".to_string(),
+            )
+            .map_err(reject(RejectionStage::Request))?;
+        let encrypted = attestation.tee_evidence.clone();
+        let attestation = explanation
+            .check(
+                "Evidence decryption",
+                self.evidence_decryptor
+                    .decrypt(attestation)
+                    .context("Failed to decrypt evidence"),
+                |attestation| match attestation.tee_evidence == encrypted {
+                    true => "The evidence is not encrypted".to_string(),
+                    false => "The evidence was decrypted".to_string(),
+                },
+            )
+            .map_err(reject(RejectionStage::Request))?;
+        // Explained evidence is verified again, rather than its cached
+        // failure reported.
+        let failure_cache = self
+            .failure_cache
+            .as_ref()
+            .filter(|_| !explanation.is_explaining());
+        if let Some(failure_cache) = failure_cache {
+            failure_cache
+                .check(tee_name, &attestation)
+                .map_err(|(stage, e)| reject(stage)(e))?;
+        }
+        let verifier = explanation
+            .check(
+                "Verifier",
+                self.verifiers.to_verifier(
+                    tee,
+                    &self.config.verifier_config(),
+                    options.report_data_mode,
+                ),
+                |_| format!("The AS verifies {tee_name} evidence"),
+            )
+            .map_err(reject(RejectionStage::Request))?;
+        // Replays are told apart by the signed part of the evidence, see
+        // [`crate::replay`].
+        let seen_evidence = self
+            .seen_evidence
+            .as_ref()
+            .filter(|_| explanation.checks_freshness());
+        let signed_evidence = match seen_evidence {
+            Some(_) => Some(
+                explanation
+                    .check(
+                        "Signed evidence",
+                        verifier.signed_evidence(&attestation),
+                        |signed| format!("The evidence signs {} bytes", signed.len()),
+                    )
+                    .map_err(reject(RejectionStage::Parse))?,
+            ),
+            None => None,
+        };
+        let freshness_methods = verifier.freshness_methods();
+        // Evidence bound to the time it was produced, rather than to a nonce,
+        // must be younger than its maximum age, see [`freshness`].
+        let evidence_time = freshness::evidence_time(&freshness_methods, nonce);
+        if let (Some(evidence_age), Some(evidence_time), true) = (
+            &self.config.evidence_age,
+            evidence_time,
+            explanation.checks_freshness(),
+        ) {
+            explanation
+                .check(
+                    "Evidence age",
+                    evidence_age.check(evidence_time, chrono::Utc::now()),
+                    |age| format!("The evidence was produced {age}s ago"),
+                )
+                .map_err(reject(RejectionStage::Freshness))?;
+        }
+        // The nonce of evidence of a verifier that checks nonces must have
+        // been issued by a challenge, see [`crate::nonces`], unless it is a
+        // time held to a maximum age.
+        let issued_nonces = self.nonces.as_ref().filter(|_| {
+            freshness_methods.contains(&FreshnessMethod::Nonce)
+                && (evidence_time.is_none() || self.config.evidence_age.is_none())
+                && explanation.checks_freshness()
+        });
+        if let Some(nonces) = issued_nonces {
+            explanation
+                .check("Nonce", nonces.check(nonce).await, |_| {
+                    "The nonce was issued by a challenge".to_string()
+                })
+                .map_err(reject(RejectionStage::Freshness))?;
+        }
+        let transforms = transform::transforms(
+            tee_name,
+            verifier.claim_transforms(),
+            &self.config.claim_transforms,
+        );
+
+        let detail_level = options.detail_level.unwrap_or(self.config.detail_level);
+        // Explained evidence is verified again, rather than its cached
+        // claims reported.
+        let result_cache = self
+            .result_cache
+            .as_ref()
+            .filter(|_| !explanation.is_explaining());
+        let evidence_digest = match result_cache {
+            Some(_) => Some(
+                result_cache::evidence_digest(
+                    tee_name,
+                    nonce,
+                    &format!("{:?} {detail_level}", options.report_data_mode),
+                    &attestation,
+                )
+                .map_err(reject(RejectionStage::Request))?,
+            ),
+            None => None,
+        };
+        let cached = result_cache
+            .zip(evidence_digest.as_deref())
+            .and_then(|(result_cache, digest)| result_cache.verified(digest));
+        if result_cache.is_some() {
+            metrics::count_result_cache_lookup(cached.is_some());
+        }
+        let (claims_from_tee_evidence, warnings, verified_at) = match cached {
+            Some((verified, verified_at)) => {
+                debug!(
+                    "Evidence of {tee_name} verified {}s ago, not verified again",
+                    verified_at.elapsed().as_secs()
+                );
+                if let Some(audit_trace) = audit_trace {
+                    audit_trace.cached();
+                }
+                (verified.claims, verified.warnings, verified_at)
+            }
+            None => {
+                // Verification is CPU bound, keep it off the async runtime.
+                let verifier_nonce = nonce.to_string();
+                let evidence = attestation.clone();
+                // The steps of the verifier are spans of the trace.
+                let profile = options
+                    .profile
+                    .clone()
+                    .or_else(|| self.tracer.as_ref().map(|_| Profile::new()));
+                let verify_started = SystemTime::now();
+                let (((verified, stage), warnings), verification_trace) = self
+                    .workers
+                    .run(trace::collect(crate::verifier::warnings::collect(
+                        diagnostics::collect(
+                            options.diagnostics.clone(),
+                            profile::collect(
+                                profile.clone(),
+                                tee_name,
+                                detail::collect(
+                                    detail_level,
+                                    pipeline::track(options.progress.clone(), async move {
+                                        verifier.evaluate(verifier_nonce, &evidence).await
+                                    }),
+                                ),
+                            ),
+                        ),
+                    )))
+                    .await?;
+                metrics::observe_verification(
+                    tee_name,
+                    verify_started.elapsed().unwrap_or_default(),
+                );
+                tracing::record_verification(
+                    verify_started,
+                    &verification_trace,
+                    &profile.map(|profile| profile.steps()).unwrap_or_default(),
+                );
+                if let Some(audit_trace) = audit_trace {
+                    audit_trace.verified(verification_trace);
+                }
+                explanation.record(|report| report.warnings = warnings.clone());
+                let verified = explanation.check(
+                    "TEE evidence",
+                    verified.context("Verifier evaluate failed"),
+                    |_| {
+                        "The signature, certificate chain and report data of the evidence are valid"
+                            .to_string()
+                    },
+                );
+                let claims = match verified {
+                    Ok(claims) => claims,
+                    Err(e) => {
+                        let stage = stage.map_or(RejectionStage::Verify, RejectionStage::from);
+                        if let Some(failure_cache) = failure_cache {
+                            if let Err(e) = failure_cache.record(tee_name, &attestation, stage, &e)
+                            {
+                                warn!("Cannot cache the verification failure: {e:#}");
+                            }
+                        }
+                        return Err(reject(stage)(e));
+                    }
+                };
+                let verified_at = match (result_cache, evidence_digest) {
+                    (Some(result_cache), Some(digest)) => result_cache.record_verified(
+                        digest,
+                        Verified {
+                            claims: claims.clone(),
+                            warnings: warnings.clone(),
+                        },
+                    ),
+                    _ => Instant::now(),
+                };
+                (claims, warnings, verified_at)
+            }
+        };
+        // Explaining evidence does not use up its nonce.
+        if let (Some(nonces), false) = (issued_nonces, explanation.is_explaining()) {
+            nonces
+                .consume(nonce)
+                .await
+                .map_err(reject(RejectionStage::Freshness))?;
+        }
+
+        let replayed = match (seen_evidence, &signed_evidence) {
+            (Some(seen_evidence), Some(signed_evidence)) => {
+                audit_step(RejectionStage::Replay);
+                // Explained evidence is not recorded, so that it is not a
+                // replay when it is submitted.
+                let replayed = match explanation.is_explaining() {
+                    true => seen_evidence.replayed(signed_evidence, &attestation.tee_pubkey),
+                    false => seen_evidence.check(signed_evidence, &attestation.tee_pubkey),
+                };
+                Some(
+                    explanation
+                        .check("Replay", replayed, |replayed| match replayed {
+                            true => "The evidence was verified before".to_string(),
+                            false => "The evidence was not verified before".to_string(),
+                        })
+                        .map_err(reject(RejectionStage::Replay))?,
+                )
+            }
+            _ => None,
+        };
+        let init_data = match &options.init_data {
+            Some(document) => {
+                audit_step(RejectionStage::InitData);
+                let init_data = InitData::parse(document).and_then(|init_data| {
+                    init_data.check(tee_name, &claims_from_tee_evidence)?;
+                    Ok(init_data)
+                });
+                let init_data = explanation
+                    .check("Init-data", init_data, |_| {
+                        "The evidence binds the init-data".to_string()
+                    })
+                    .map_err(reject(RejectionStage::InitData))?;
+                Some(init_data)
+            }
+            None => None,
+        };
+
+        let mut flattened_claims = explanation
+            .check(
+                "Claims",
+                flatten_claims(tee.clone(), &claims_from_tee_evidence),
+                |_| "The claims of the evidence were normalized".to_string(),
+            )
+            .map_err(reject(RejectionStage::ClaimsNormalize))?;
+        let deny_list_generation = self.deny_list.generation();
+        audit_step(RejectionStage::DenyList);
+        explanation
+            .check("Deny-list", self.deny_list.check(&flattened_claims), |_| {
+                "No claim is on the deny-list".to_string()
+            })
+            .map_err(reject(RejectionStage::DenyList))?;
+        let posture = posture::check_claims(&self.config, tee_name, &flattened_claims);
+        let posture = match self.config.deny_debug_tees {
+            true => explanation.check("Debuggable TEEs", posture, |_| {
+                "The TEE is not debuggable".to_string()
+            }),
+            false => posture,
+        };
+        posture.map_err(reject(RejectionStage::Policy))?;
+        let claims_schema = options.claims_schema.unwrap_or(self.config.claims_schema);
+        claims_schema.apply(tee_name, &mut flattened_claims, &transforms);
+        options
+            .measurement_encoding
+            .unwrap_or(self.config.measurement_encoding)
+            .apply(tee_name, &mut flattened_claims);
+        freshness::add_claim(&mut flattened_claims, &freshness_methods, nonce);
+        if let Some(evidence_time) = evidence_time {
+            timing::add_evidence_age(&mut flattened_claims, evidence_time, chrono::Utc::now());
+        }
+        let device_claims = explanation
+            .check(
+                "Device evidence",
+                spdm::appraise(&self.spdm_devices, nonce, &attestation, self.rvps.as_ref())
+                    .await
+                    .context("Device evidence verification failed"),
+                |claims| {
+                    let devices = claims
+                        .keys()
+                        .filter(|name| name.ends_with(".measurements_match"))
+                        .count();
+                    match devices {
+                        0 => "No device evidence is attached".to_string(),
+                        devices => format!("The SPDM evidence of {devices} devices is valid"),
+                    }
+                },
+            )
+            .map_err(reject(RejectionStage::Verify))?;
+        if let Some(claims) = flattened_claims.as_object_mut() {
+            claims.extend(device_claims);
+            if let Some(init_data) = &init_data {
+                claims.extend(init_data.claims());
+            }
+        }
+        let policy_ids = select_default_policies(
+            &self.config.default_policies,
+            tee,
+            options.tenant.as_deref(),
+        );
+        let claim_filters = claim_filter::select(
+            &self.config.claim_filters,
+            options.tenant.as_deref(),
+            &policy_ids
+                .iter()
+                .map(|policy_id| policy_id.clone().unwrap_or("default".to_string()))
+                .collect::<Vec<_>>(),
+        );
+        self.config
+            .claims_log
+            .log(tee_name, &flattened_claims, &claim_filters);
+        if let Some(progress) = &options.progress {
+            progress.report(Step::Policy);
+        }
+        audit_step(RejectionStage::ReferenceValues);
+        let mut evaluations = Vec::new();
+        let mut unconfirmed = Vec::new();
+        let mut appraised = Vec::new();
+        for policy_id in policy_ids {
+            let policy_name = policy_id.clone().unwrap_or("default".to_string());
+            let tcb = explanation
+                .check(
+                    "Policy input",
+                    self.policy_input(policy_id.as_deref(), &flattened_claims),
+                    |_| format!("The claims were selected for the policy {policy_name}"),
+                )
+                .map_err(reject(RejectionStage::ClaimsNormalize))?;
+            let mut reference_data_map = explanation
+                .check(
+                    "Reference values",
+                    self.get_reference_data(&tcb)
+                        .await
+                        .map_err(|e| anyhow!("Generate reference data failed{:?}", e)),
+                    |reference| {
+                        let count = reference.values().filter(|r| !r.is_empty()).count();
+                        format!("Reference values were found for {count} claims")
+                    },
+                )
+                .map_err(reject(RejectionStage::ReferenceValues))?;
+            if result_cache.is_some() {
+                appraised.push((policy_name.clone(), tcb.clone(), reference_data_map.clone()));
+            }
+            // Explaining evidence does not trust its values.
+            let provisional = self
+                .provisional
+                .as_ref()
+                .filter(|_| !explanation.is_explaining());
+            if let Some(provisional) = provisional {
+                let recorded = provisional
+                    .record(
+                        &format!("{tee:?}").to_lowercase(),
+                        &flattened_claims,
+                        &mut reference_data_map,
+                    )
+                    .map_err(reject(RejectionStage::ReferenceValues))?;
+                for claim in recorded {
+                    if !unconfirmed.contains(&claim) {
+                        unconfirmed.push(claim);
+                    }
+                }
+            }
+            explanation.compare(&tcb, &reference_data_map);
+            let tcb = match &options.submitter {
+                Some(submitter) => utils::add_to_input(
+                    &tcb,
+                    submitter::SUBMITTER_FIELD,
+                    serde_json::to_value(submitter)?,
+                )
+                .map_err(reject(RejectionStage::ClaimsNormalize))?,
+                None => tcb,
+            };
+            let tcb = match &host {
+                Some(host) => utils::add_to_input(&tcb, host::HOST_FIELD, host.clone())
+                    .map_err(reject(RejectionStage::ClaimsNormalize))?,
+                None => tcb,
+            };
+
+            let policy_id = policy_name;
+            let shadow_policy_id = self
+                .config
+                .shadow_policies
+                .get(&policy_id)
+                .filter(|_| !explanation.is_explaining());
+            let policy_started = SystemTime::now();
+            let primary = self.policy_engine.evaluate(
+                tee_name,
+                reference_data_map.clone(),
+                tcb.clone(),
+                Some(policy_id.clone()),
+                &options.policy_parameters,
+            );
+            let evaluation = match shadow_policy_id {
+                Some(shadow_policy_id) => {
+                    let shadow = self.policy_engine.evaluate(
+                        tee_name,
+                        reference_data_map,
+                        tcb,
+                        Some(shadow_policy_id.clone()),
+                        &options.policy_parameters,
+                    );
+                    let (evaluation, shadow) = futures::join!(primary, shadow);
+                    self.shadow.compare(
+                        tee_name,
+                        (&policy_id, &evaluation),
+                        (shadow_policy_id, &shadow),
+                    );
+                    evaluation
+                }
+                None => primary.await,
+            };
+            metrics::observe_policy_evaluation(
+                &policy_id,
+                policy_started.elapsed().unwrap_or_default(),
+            );
+            tracing::record(
+                "policy",
+                policy_started,
+                vec![("policy.id", policy_id.clone())],
+            );
+            if let Err(e) = &evaluation {
+                if let Some(denied) = e.downcast_ref::<policy_engine::PolicyDenied>() {
+                    explanation.record(|report| {
+                        report.violations.extend(denied.violations.iter().cloned())
+                    });
+                }
+            }
+            let evaluation = explanation.check("Policy", evaluation, |evaluation| {
+                format!(
+                    "The claims comply with the policy {policy_id} (SHA-256 {})",
+                    evaluation.policy_digest
+                )
+            });
+            evaluations.push((policy_id, evaluation));
+        }
+        audit_step(RejectionStage::Policy);
+        if let Some(audit_trace) = audit_trace {
+            audit_trace.policies(&evaluations);
+        }
+        // Explained evidence is not an attestation of the history.
+        let history = self
+            .history
+            .as_ref()
+            .filter(|_| !explanation.is_explaining());
+        if let Some(history) = history {
+            let decision = match evaluations.iter().find_map(|(_, e)| e.as_ref().err()) {
+                None => Some(PolicyDecision::Allow),
+                Some(e) if e.is::<policy_engine::PolicyDenied>() => Some(PolicyDecision::Deny),
+                Some(_) => None,
+            };
+            if let Some(decision) = decision {
+                let evidence = history.records_evidence().then(|| StoredEvidence {
+                    nonce: nonce.to_string(),
+                    attestation: json!(attestation).to_string(),
+                    policy_parameters: options.policy_parameters.clone(),
+                });
+                let tenant = options.tenant.as_deref();
+                if let Err(e) =
+                    history.record(tee_name, tenant, decision, &flattened_claims, evidence)
+                {
+                    warn!("Cannot record the attestation history: {e:#}");
+                }
+            }
+        }
+        let (policy_evaluation, policies) = policy_engine::combine_evaluations(evaluations)
+            .context("Policy Engine evaluation failed")
+            .map_err(reject(RejectionStage::Policy))?;
+        explanation.record(|report| report.decision = PolicyDecision::Allow);
+
+        Ok(Appraised {
+            admitted,
+            attestation,
+            token_format,
+            host,
+            warnings,
+            verified_at,
+            replayed,
+            flattened_claims,
+            deny_list_generation,
+            claims_schema,
+            claim_filters,
+            detail_level,
+            unconfirmed,
+            appraised,
+            policy_evaluation,
+            policies,
+        })
+    }
+
+    /// Refresh `token`, a token of the AS that has not expired, without
+    /// new evidence: issue a token with the same claims if the policies and
+    /// reference values it was appraised with are unchanged. Otherwise the
+    /// error is an [`AttestAgain`] error, and the client must attest with
+    /// new evidence, see [`crate::result_cache`].
+    pub async fn reattest(&self, token: &str) -> Result<Evaluation> {
+        let result_cache = self
+            .result_cache
+            .as_ref()
+            .ok_or_else(|| anyhow!("The AS does not refresh tokens"))?;
+        let claims = cosign::broker_signed(token)
+            .and_then(|token| self.token_broker.verify(&token))
+            .context("Invalid token")?;
+        let expired = claims["exp"]
+            .as_i64()
+            .map_or(true, |exp| exp <= chrono::Utc::now().timestamp());
+        if expired {
+            return Err(AttestAgain::new("the token has expired").into());
+        }
+        let jti = claims["jti"]
+            .as_str()
+            .ok_or_else(|| anyhow!("The token has no jti"))?;
+        let (appraisal, verified) = result_cache.appraisal(jti)?;
+
+        let policy_digests = self.policy_engine.list_policies().await?;
+        let mut reference_data = Vec::new();
+        for (_, input) in &appraisal.inputs {
+            reference_data.push(
+                self.get_reference_data(input)
+                    .await
+                    .context("Generate reference data failed")?,
+            );
+        }
+        let revision = result_cache::revision(appraisal.inputs.iter().zip(&reference_data).map(
+            |((policy_id, _), reference_data)| {
+                let policy_digest = policy_digests.get(policy_id).map_or("", String::as_str);
+                (policy_id.as_str(), policy_digest, reference_data)
+            },
+        ))?;
+        if revision != appraisal.revision {
+            return Err(AttestAgain::new(
+                "the policies or reference values changed since the token was issued",
+            )
+            .into());
+        }
+        if self.deny_list.generation() != appraisal.deny_list_generation {
+            return Err(
+                AttestAgain::new("the deny-list changed since the token was issued").into(),
+            );
+        }
+
+        let token = self
+            .co_signers
+            .co_sign(self.token_broker.issue(appraisal.claims.clone())?)
+            .await?;
+        let receipt = match &self.transparency_log {
+            Some(log) => Some(
+                log.append(&token)
+                    .await
+                    .context("Transparency log append failed")?,
+            ),
+            None => None,
+        };
+        if let Err(e) = self.cache_appraisal(result_cache, &token, appraisal.clone(), verified) {
+            warn!("Cannot cache the attestation result: {e:#}");
+        }
+        info!("Token {jti} refreshed");
+
+        Ok(Evaluation {
+            token,
+            warnings: Vec::new(),
+            certificate: None,
+            secrets: BTreeMap::new(),
+            receipt,
+            policies: appraisal.policies,
+            claims: appraisal.claims,
+            attestation_id: None,
+        })
+    }
+
+    /// Remember the appraisal of `token`, whose evidence was verified at
+    /// `verified`, so that the token can be refreshed.
+    fn cache_appraisal(
+        &self,
+        result_cache: &ResultCache,
+        token: &str,
+        appraisal: Appraisal,
+        verified: Instant,
+    ) -> Result<()> {
+        let claims =
+            cosign::broker_signed(token).and_then(|token| self.token_broker.verify(&token))?;
+        let jti = claims["jti"]
+            .as_str()
+            .ok_or_else(|| anyhow!("The token has no jti"))?;
+        result_cache.record_appraisal(jti.to_string(), appraisal, verified);
+        Ok(())
+    }
+
+    /// Verify the TD quotes of the `source` and `destination` of a TD
+    /// migration, both bound to `nonce`, the nonce of the migration
+    /// session, check them against each other, see [`crate::migration`],
+    /// and issue a token for the destination if the `migration_policy` of
+    /// the config allows the migration.
+    pub async fn evaluate_migration(
+        &self,
+        nonce: &str,
+        source: &str,
+        destination: &str,
+    ) -> Result<Evaluation> {
+        let _admitted = self.admission.admit(&self.workers)?;
+        let tee = Tee::Tdx;
+        let tee_name = serde_variant::to_variant_name(&tee)?;
+        let reject = |stage: RejectionStage| {
+            move |e: anyhow::Error| self.rejections.reject(tee_name, stage, e)
+        };
+        if self.fips_mode() {
+            fips::check_tee(&tee).map_err(reject(RejectionStage::Request))?;
+        }
+
+        let mut quotes = Vec::new();
+        let mut transforms = Vec::new();
+        let mut warnings = Vec::new();
+        for (side, attestation) in [
+            (migration::SOURCE_PREFIX, source),
+            (migration::DESTINATION_PREFIX, destination),
+        ] {
+            let attestation = serde_json::from_str::<Attestation>(attestation)
+                .with_context(|| format!("Failed to deserialize the {side} Attestation"))
+                .map_err(reject(RejectionStage::Request))?;
+            let attestation = self
+                .evidence_decryptor
+                .decrypt(attestation)
+                .with_context(|| format!("Failed to decrypt the {side} evidence"))
+                .map_err(reject(RejectionStage::Request))?;
+            let verifier = self
+                .verifiers
+                .to_verifier(&tee, &self.config.verifier_config(), None)
+                .map_err(reject(RejectionStage::Request))?;
+            transforms = transform::transforms(
+                tee_name,
+                verifier.claim_transforms(),
+                &self.config.claim_transforms,
+            );
+
+            let verifier_nonce = nonce.to_string();
+            let evidence = attestation.clone();
+            let ((verified, stage), quote_warnings) = self
+                .workers
+                .run(crate::verifier::warnings::collect(pipeline::track(
+                    None,
+                    async move { verifier.evaluate(verifier_nonce, &evidence).await },
+                )))
+                .await?;
+            let claims = verified
+                .with_context(|| format!("Verifier evaluate failed for the {side} quote"))
+                .map_err(reject(
+                    stage.map_or(RejectionStage::Verify, RejectionStage::from),
+                ))?;
+            let flattened_claims = flatten_claims(tee.clone(), &claims)
+                .map_err(reject(RejectionStage::ClaimsNormalize))?;
+            self.deny_list
+                .check(&flattened_claims)
+                .map_err(reject(RejectionStage::DenyList))?;
+            posture::check_claims(&self.config, tee_name, &flattened_claims)
+                .map_err(reject(RejectionStage::Policy))?;
+            warnings.extend(quote_warnings);
+            quotes.push((attestation, flattened_claims));
+        }
+        let Ok([(source, mut source_claims), (destination, mut destination_claims)]) =
+            <[_; 2]>::try_from(quotes)
+        else {
+            unreachable!("both quotes are verified");
+        };
+
+        // The checks are on the claims of the verifier, before the claims
+        // schema renames them.
+        let consistency = migration::check(&source_claims, &destination_claims);
+        if !consistency.is_consistent() {
+            info!("Inconsistent TD migration: {:?}", consistency.mismatches);
+        }
+        for claims in [&mut source_claims, &mut destination_claims] {
+            self.config
+                .claims_schema
+                .apply(tee_name, claims, &transforms);
+            self.config.measurement_encoding.apply(tee_name, claims);
+        }
+        let claims = migration::claims(&source_claims, &destination_claims, &consistency);
+
+        let policy_id = Some(self.config.migration_policy.clone());
+        let tcb = self
+            .policy_input(policy_id.as_deref(), &claims)
+            .map_err(reject(RejectionStage::ClaimsNormalize))?;
+        let reference_data_map = self
+            .get_reference_data(&tcb)
+            .await
+            .map_err(|e| anyhow!("Generate reference data failed{:?}", e))
+            .map_err(reject(RejectionStage::ReferenceValues))?;
+        let policy_evaluation = self
+            .policy_engine
+            .evaluate(
+                tee_name,
+                reference_data_map,
+                tcb,
+                policy_id,
+                &ParameterValues::default(),
+            )
+            .await
+            .context("Policy Engine evaluation failed")
+            .map_err(reject(RejectionStage::Policy))?;
+        let policy_digest = policy_evaluation.policy_digest;
+
+        let claim_filters = claim_filter::select(
+            &self.config.claim_filters,
+            None,
+            &[self.config.migration_policy.clone()],
+        );
+        let token_claims = json!({
+            "tee-pubkey": destination.tee_pubkey,
+            "source-tee-pubkey": source.tee_pubkey,
+            "tcb-status": claim_filters.apply(&claims),
+            "evaluation-report": policy_evaluation.report,
+            "policy_digest": policy_digest,
+            "claims-schema": self.config.claims_schema,
+        });
+        let claims = token_claims.clone();
+        let token = self
+            .co_signers
+            .co_sign(
+                self.token_broker
+                    .issue(token_claims)
+                    .map_err(reject(RejectionStage::Issuance))?,
+            )
+            .await
+            .map_err(reject(RejectionStage::Issuance))?;
+        let receipt = match &self.transparency_log {
+            Some(log) => Some(
+                log.append(&token)
+                    .await
+                    .context("Transparency log append failed")
+                    .map_err(reject(RejectionStage::Issuance))?,
+            ),
+            None => None,
+        };
+
+        Ok(Evaluation {
+            token,
+            warnings,
+            certificate: None,
+            secrets: BTreeMap::new(),
+            receipt,
+            policies: vec![PolicyResult {
+                policy_id: self.config.migration_policy.clone(),
+                policy_digest,
+                allow: true,
+            }],
+            claims,
+            attestation_id: None,
+        })
+    }
+
+    /// Verify Attestation Evidence like [`AttestationService::evaluate`],
+    /// without issuing a token, and report every check performed, the claims
+    /// compared with reference values and the policy decision. The checks
+    /// are those of the evaluation, which stops at the first failed one.
+    /// Explaining evidence neither uses up its nonce nor records it.
+    pub async fn explain(&self, tee: Tee, nonce: &str, attestation: &str) -> VerificationReport {
+        self.explain_with(tee, nonce, attestation, EvaluateOptions::default(), true)
+            .await
+    }
+
+    /// [`AttestationService::explain`], with per-request options, checking
+    /// the freshness of the evidence only if `freshness`.
+    async fn explain_with(
+        &self,
+        tee: Tee,
+        nonce: &str,
+        attestation: &str,
+        options: EvaluateOptions,
+        freshness: bool,
+    ) -> VerificationReport {
+        let report = Mutex::new(VerificationReport::new(format!("{tee:?}").to_lowercase()));
+        let _ = self
+            .appraise(
+                &tee,
+                nonce,
+                attestation,
+                &options,
+                None,
+                Explanation::new(&report, freshness),
+            )
+            .await;
+        report.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Run a canned verification of sample TEE evidence end to end, and the
+    /// probes of the platform quote infrastructure, see [`self_test`].
+    pub async fn self_test(&self) -> SelfTest {
+        let mut report = VerificationReport::new("sample".to_string());
+        let _ = self.self_test_checks(&mut report).await;
+        let platform = match &self.config.platform_probes {
+            Some(probes) => self_test::probe_platform(probes).await,
+            None => Vec::new(),
+        };
+        SelfTest {
+            service: report.checks,
+            platform,
+        }
+    }
+
+    async fn self_test_checks(&self, report: &mut VerificationReport) -> Result<()> {
+        let tee = Tee::Sample;
+        let tee_name = serde_variant::to_variant_name(&tee)?;
+        let nonce = uuid::Uuid::new_v4().to_string();
+        let attestation = self_test::sample_attestation(&nonce);
+        let verifier = self
+            .verifiers
+            .to_verifier(&tee, &self.config.verifier_config(), None)?;
+        let verified = self
+            .workers
+            .run(async move { verifier.evaluate(nonce, &attestation).await })
+            .await
+            .and_then(|verified| verified);
+        let claims = report.check("Verifier", verified, |_| {
+            "The sample evidence verifies".to_string()
+        })?;
+
+        let mut flattened_claims = flatten_claims(tee.clone(), &claims)?;
+        self.config
+            .claims_schema
+            .apply(tee_name, &mut flattened_claims, &[]);
+        let policy_id =
+            select_default_policies(&self.config.default_policies, &tee, None).swap_remove(0);
+        let tcb = self.policy_input(policy_id.as_deref(), &flattened_claims)?;
+        let reference_data_map = report.check(
+            "Reference values",
+            self.get_reference_data(&tcb).await,
+            |_| "The RVPS answers reference value queries".to_string(),
+        )?;
+
+        // A policy may well deny the sample TEE, the policy engine works
+        // as long as it comes to a decision.
+        let evaluation = match self
+            .policy_engine
+            .evaluate(
+                tee_name,
+                reference_data_map,
+                tcb,
+                policy_id,
+                &ParameterValues::default(),
+            )
+            .await
+        {
+            Err(e) if e.is::<policy_engine::PolicyDenied>() => Ok("denies"),
+            Err(e) if e.is::<policy_engine::PolicyMismatch>() => {
+                Ok("finds the policy does not apply to")
+            }
+            evaluation => evaluation.map(|_| "allows"),
+        };
+        report.check("Policy", evaluation, |decision| {
+            format!("The policy engine {decision} the sample evidence")
+        })?;
+
+        let token = self
+            .token_broker
+            .issue(json!({ "self-test": true, "tcb-status": flattened_claims }))
+            .and_then(|token| self.token_broker.verify(&token));
+        report.check("Token", token, |_| {
+            "The token broker issues tokens that verify".to_string()
+        })?;
+        Ok(())
+    }
+
+    /// The claims given as input to the policy, serialized, see
+    /// `policy_input_claims` of [`Config`]. Reference values are only
+    /// looked up for them.
+    fn policy_input(&self, policy_id: Option<&str>, claims: &serde_json::Value) -> Result<String> {
+        let input_claims = self
+            .config
+            .policy_input_claims
+            .get(policy_id.unwrap_or("default"));
+        Ok(match input_claims {
+            Some(patterns) => {
+                serde_json::to_string(&policy_engine::select_claims(claims, patterns))?
+            }
+            None => serde_json::to_string(claims)?,
+        })
+    }
+
+    async fn get_reference_data(&self, tcb_claims: &str) -> Result<HashMap<String, Vec<String>>> {
+        let mut data = HashMap::new();
+        let tcb_claims_map: HashMap<String, serde_json::Value> = serde_json::from_str(tcb_claims)?;
+        for (key, claim) in &tcb_claims_map {
+            let digest = self.rvps.get_digests(key).await?.unwrap_or_default();
+            data.insert(key.to_string(), digest.reference_data(claim)?);
+        }
+        Ok(data)
+    }
+
+    /// The reference values trusted on first use and not confirmed yet,
+    /// see [`crate::tofu`].
+    pub fn provisional_reference_values(&self) -> Result<Vec<ProvisionalValue>> {
+        match &self.provisional {
+            Some(provisional) => provisional.list(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Confirm the provisional values of `claims` into the reference values
+    /// of the integrated RVPS.
+    pub async fn confirm_reference_values(&mut self, claims: &[String]) -> Result<()> {
+        let provisional = self
+            .provisional
+            .as_ref()
+            .context("Trust on first use is not enabled")?;
+        let existing = self
+            .rvps
+            .export_reference_values()
+            .await?
+            .context("Reference values of a remote RVPS cannot be confirmed by the AS")?;
+        let confirmed = provisional.confirm(claims, &existing)?;
+        self.rvps
+            .import_reference_values(confirmed)
+            .await
+            .context("Cannot store the confirmed reference values")?;
+        provisional.discard(claims)
+    }
+
+    /// Discard the provisional values of `claims`.
+    pub fn discard_reference_values(&self, claims: &[String]) -> Result<()> {
+        self.provisional
+            .as_ref()
+            .context("Trust on first use is not enabled")?
+            .discard(claims)
+    }
+
+    /// The deny-listed claim values, see [`crate::deny_list`].
+    pub fn deny_list(&self) -> Result<Vec<DenyListEntry>> {
+        self.deny_list.list()
+    }
+
+    /// Deny the claim value of `entry` to evidence from now on. With
+    /// `policy_signing` in the config, `signature` must be that of the
+    /// [`signing::add_deny_list_statement`] by the admin key.
+    pub fn add_deny_list_entry(&self, entry: DenyListEntry, signature: Option<&str>) -> Result<()> {
+        if let Some(verifier) = &self.policy_signature_verifier {
+            verifier
+                .verify_operation(
+                    &signing::add_deny_list_statement(&entry.claim, &entry.value),
+                    signature,
+                )
+                .context("Cannot Add Deny-List Entry")?;
+        }
+        self.deny_list.add(entry)
+    }
+
+    /// Allow the value `value` of `claim` again. With `policy_signing` in
+    /// the config, `signature` must be that of the
+    /// [`signing::remove_deny_list_statement`] by the admin key.
+    pub fn remove_deny_list_entry(
+        &self,
+        claim: &str,
+        value: &str,
+        signature: Option<&str>,
+    ) -> Result<()> {
+        if let Some(verifier) = &self.policy_signature_verifier {
+            verifier
+                .verify_operation(
+                    &signing::remove_deny_list_statement(claim, value),
+                    signature,
+                )
+                .context("Cannot Remove Deny-List Entry")?;
+        }
+        self.deny_list.remove(claim, value)
+    }
+
+    /// Ingest the bundles of reference values added to or changed in the
+    /// watched directory since the last reload, see [`rvps::watch`], and
+    /// return how many were.
+    pub async fn reload_reference_values(&mut self) -> Result<usize> {
+        match &mut self.reference_values_watch {
+            Some(watch) => watch.reload(self.rvps.as_mut()).await,
+            None => Ok(0),
+        }
+    }
+
+    /// The period of the reloads of the watched bundles of reference
+    /// values, if any.
+    pub fn reference_values_watch_interval(&self) -> Option<Duration> {
+        self.reference_values_watch.as_ref().map(Watch::interval)
+    }
+
+    /// Apply the new advisories of the advisory feed to the
+    /// `advisory_baseline` policy data document, and return the version of
+    /// the document if one was added, see [`crate::advisories`].
+    pub async fn refresh_advisories(&mut self) -> Result<Option<u64>> {
+        let Some(feed) = &self.advisory_feed else {
+            return Ok(None);
+        };
+        // The policy engine only fails to get a document that does not exist.
+        let current = self
+            .policy_engine
+            .get_policy_data(ADVISORY_BASELINE, None)
+            .await
+            .ok()
+            .map(|current| current.data);
+        let Some(data) = feed.refresh(current).await? else {
+            return Ok(None);
+        };
+        // The feed is trusted by the config, its documents are not signed.
+        let version = self
+            .policy_engine
+            .set_policy_data(SetPolicyDataInput {
+                name: ADVISORY_BASELINE.to_string(),
+                data,
+            })
+            .await
+            .context("Cannot Set Policy Data")?;
+        Ok(Some(version))
+    }
+
+    /// The period of the polls of the advisory feed, if any.
+    pub fn advisory_feed_interval(&self) -> Option<Duration> {
+        self.advisory_feed.as_ref().map(AdvisoryFeed::interval)
+    }
+
+    /// Check a candidate `config` against the state of the AS, on top of
+    /// [`Config::check`]: the policies it evaluates by default, or as
+    /// shadows, are installed, which means they compiled, and its token
+    /// signing keys load.
+    pub async fn validate_config(&self, config: &Config) -> Result<()> {
+        config.check()?;
+        if config.fips_mode() {
+            fips::check_config(config)?;
+        }
+        self.check_installed_policies(config).await?;
+        config
+            .attestation_token_broker
+            .to_token_broker(config.attestation_token_config.clone())
+            .context("Cannot load the token signing keys")?;
+        Ok(())
+    }
+
+    /// Check a candidate `config` of untrusted origin against the state of
+    /// the AS, as [`AttestationService::validate_config`] but with
+    /// [`Config::check_static`]: neither the files it names nor its keys
+    /// are loaded.
+    pub async fn validate_config_statically(&self, config: &Config) -> Result<()> {
+        config.check_static()?;
+        if config.fips_mode() {
+            fips::check_config(config)?;
+        }
+        self.check_installed_policies(config).await
+    }
+
+    /// Check that the policies `config` evaluates by default, or as
+    /// shadows, are installed.
+    async fn check_installed_policies(&self, config: &Config) -> Result<()> {
+        let policies = self.list_policies().await?;
+        for policy_id in config
+            .default_policies
+            .iter()
+            .flat_map(|policy| policy.evaluated_policies())
+            .chain(config.shadow_policies.values().map(String::as_str))
+        {
+            if !policies.contains_key(policy_id) {
+                bail!("Policy {policy_id} is not installed");
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the [`RELOADABLE_SECTIONS`] of `config`, checked with
+    /// [`AttestationService::validate_config`], to the running AS. The other
+    /// sections are ignored until a restart: in particular the
+    /// `trust_anchors` and the `collateral` endpoints, which the verifiers
+    /// load when the AS starts. Return whether the watched directory of
+    /// reference values changed, so that it is scanned.
+    ///
+    /// [`RELOADABLE_SECTIONS`]: crate::config::RELOADABLE_SECTIONS
+    pub fn reload_config(&mut self, config: Config) -> bool {
+        let duration_min = config.attestation_token_config.duration_min;
+        if duration_min != self.config.attestation_token_config.duration_min {
+            self.token_broker.set_token_duration(duration_min);
+            self.config.attestation_token_config.duration_min = duration_min;
+        }
+        let watch_changed = config.reference_values_watch != self.config.reference_values_watch;
+        if watch_changed {
+            self.reference_values_watch = config.reference_values_watch.clone().map(Watch::new);
+            self.config.reference_values_watch = config.reference_values_watch;
+        }
+        self.config.default_policies = config.default_policies;
+        self.config.shadow_policies = config.shadow_policies;
+        self.config.migration_policy = config.migration_policy;
+        self.config.policy_input_claims = config.policy_input_claims;
+        self.config.claim_filters = config.claim_filters;
+        self.config.detail_level = config.detail_level;
+        self.config.evidence_age = config.evidence_age;
+        self.config.warnings_in_token = config.warnings_in_token;
+        watch_changed
+    }
+
+    /// Registry a new reference value
+    pub async fn registry_reference_value(&mut self, message: Message) -> Result<()> {
+        self.rvps.verify_and_extract(message).await
+    }
+}
+
+#[cfg(all(test, feature = "rvps-native"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_new_in_memory() {
+        let service = AttestationService::new_in_memory().unwrap();
+        let work_dir = service.config.work_dir.clone();
+        assert!(work_dir.is_dir());
+
+        let nonce = "integration-test";
+        let attestation = serde_json::to_string(&self_test::sample_attestation(nonce)).unwrap();
+        let token = service
+            .evaluate(Tee::Sample, nonce, &attestation)
+            .await
+            .unwrap();
+        assert!(!token.is_empty());
+
+        drop(service);
+        assert!(!work_dir.exists());
+    }
+}
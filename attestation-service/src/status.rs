@@ -0,0 +1,107 @@
+//! Status attestations of the AS itself.
+//!
+//! Relying parties trust the results of the AS as far as they trust its
+//! configuration: the policies it evaluates, the reference values it
+//! compares claims with, the verifiers it is built with and its settings.
+//! A service status is a token signed with the token signing key of the AS
+//! that states them, so that dependent systems and trust dashboards can
+//! check, continuously, that they have not drifted from what was approved:
+//!
+//! ```json
+//! {
+//!     "typ": "as-status+jwt",
+//!     "service-status": {
+//!         "version": "0.1.0",
+//!         "policies": { "default": "5d6c...", "tdx": "a1b2..." },
+//!         "reference_values": "sha256:9e0f...",
+//!         "verifiers": { "sample": "0.1.0", "tdx": "0.1.0" },
+//!         "config": "sha256:3f1c...",
+//!         "fips_mode": false,
+//!         "insecure_settings": ["sample_verifier"]
+//!     },
+//!     "nonce": "...",
+//!     ...
+//! }
+//! ```
+//!
+//! - `policies`: the digest of each policy, see
+//!   [`crate::policy_engine::policy_digest`];
+//! - `reference_values`: the SHA-256 of the reference values of the
+//!   integrated RVPS, which changes whenever one is registered, or expires;
+//!   `null` with a remote RVPS, which keeps them;
+//! - `verifiers`: the verifiers that verify evidence, with the version of
+//!   the AS they are built into, or `registered` for those registered at
+//!   runtime, see [`crate::verifier::registry`];
+//! - `config`: the SHA-256 of the config of the AS, if known, see
+//!   [`crate::config::ConfigLayers::digest`];
+//! - `insecure_settings`: the security posture, see [`crate::posture`].
+//!
+//! The `nonce` of the request is in the token, for the freshness of the
+//! status. The token has a `typ` claim of [`SERVICE_STATUS_TYP`], so that
+//! it is not taken for another token of the AS, such as an attestation
+//! token, which has none.
+
+use crate::rvps::ReferenceValue;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Claim of the status in the status token.
+pub(crate) const SERVICE_STATUS_CLAIM: &str = "service-status";
+
+/// `typ` claim of the status token.
+pub const SERVICE_STATUS_TYP: &str = "as-status+jwt";
+
+/// Version of the verifiers registered at runtime.
+pub const REGISTERED_VERIFIER: &str = "registered";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ServiceStatus {
+    /// Version of the AS.
+    pub version: String,
+    /// Digest of each policy, by policy ID.
+    pub policies: BTreeMap<String, String>,
+    /// Digest of the reference values of the integrated RVPS, `None` with a
+    /// remote RVPS.
+    pub reference_values: Option<String>,
+    /// Version of each verifier that verifies evidence, by TEE.
+    pub verifiers: BTreeMap<String, String>,
+    /// Digest of the config.
+    pub config: Option<String>,
+    pub fips_mode: bool,
+    pub insecure_settings: Vec<String>,
+}
+
+/// SHA-256 of a set of reference values, in any order.
+pub fn reference_values_digest(reference_values: &[ReferenceValue]) -> Result<String> {
+    let mut sorted: Vec<_> = reference_values.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+    let reference_values = serde_json::to_vec(&sorted)?;
+    Ok(format!(
+        "sha256:{}",
+        hex::encode(Sha256::digest(reference_values))
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference_value(name: &str) -> ReferenceValue {
+        ReferenceValue::new()
+            .unwrap()
+            .set_name(name)
+            .add_hash_value("sha256".to_string(), "00ff".to_string())
+    }
+
+    #[test]
+    fn test_reference_values_digest() {
+        let a = reference_value("mr_td");
+        let b = reference_value("mr_seam");
+        let digest = reference_values_digest(&[a.clone(), b.clone()]).unwrap();
+        assert!(digest.starts_with("sha256:"));
+        assert_eq!(digest, reference_values_digest(&[b, a.clone()]).unwrap());
+        assert_ne!(digest, reference_values_digest(&[a]).unwrap());
+    }
+}
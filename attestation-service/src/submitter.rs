@@ -0,0 +1,31 @@
+//! Delegated attestation: a trusted relay, such as a node agent, submits
+//! the evidence of a guest on its behalf. The relay authenticates to the
+//! front end of the AS, e.g. with a TLS client certificate, which passes
+//! its identity in [`crate::EvaluateOptions::submitter`].
+//!
+//! The identity is added to the policy input as `input.submitter`, so that
+//! policies can only accept some claims from some relays, and to the token
+//! as the `submitter` claim. Claims of the evidence are never named
+//! `submitter`, they are flattened into dotted names prefixed with their
+//! TEE.
+
+use serde::{Deserialize, Serialize};
+
+/// Name of the policy input field and of the token claim.
+pub const SUBMITTER_FIELD: &str = "submitter";
+
+/// Authenticated identity of the client that submitted the evidence.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Submitter {
+    /// Subject distinguished name of the client certificate, in RFC 4514
+    /// form, e.g. `CN=node-agent-7,O=Example`.
+    pub subject: String,
+
+    /// DNS names and URIs of the subject alternative names of the client
+    /// certificate.
+    #[serde(default)]
+    pub san: Vec<String>,
+
+    /// SHA-256 digest of the DER encoded client certificate, hex encoded.
+    pub fingerprint: String,
+}
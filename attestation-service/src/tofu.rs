@@ -0,0 +1,370 @@
+//! Trust on first use of reference values.
+//!
+//! Onboarding a new image in a lab means registering the reference values
+//! of its measurements before it can attest. With `tofu` in the AS config,
+//! the AS instead records the measurements of an attestation that match no
+//! reference value as provisional reference values, and evaluates the
+//! policy as if they were registered. The tokens list the claims checked
+//! against provisional values in their `unconfirmed` claim.
+//!
+//! An administrator reviews the provisional values, then confirms them into
+//! the reference values of the integrated RVPS, or discards them. They are
+//! kept in `tofu.json` in the work dir until then.
+//!
+//! Only the first value of a claim seen on a platform, told apart by the
+//! first of the `platform_claims` of the evidence, such as `snp.chip_id`,
+//! or by its TEE, is trusted: evidence of the same platform with another
+//! value is rejected until the first one is confirmed or discarded. Still,
+//! whatever runs first on a platform is trusted, so this mode must never be
+//! enabled in production.
+
+use crate::rvps::ReferenceValue;
+use crate::token::chain;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const TOFU_FILE: &str = "tofu.json";
+
+/// Algorithm recorded for the confirmed values. The AS only compares the
+/// values of the claims.
+const TOFU_ALG: &str = "tofu";
+
+const DEFAULT_EXPIRATION_DAYS: i64 = 365;
+
+fn default_expiration_days() -> i64 {
+    DEFAULT_EXPIRATION_DAYS
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TofuConfig {
+    /// Claims whose values are trusted on first use. A name ending with
+    /// `*` matches any claim with that prefix.
+    pub claims: Vec<String>,
+
+    /// Validity of the confirmed reference values, in days.
+    #[serde(default = "default_expiration_days")]
+    pub expiration_days: i64,
+
+    /// Claims that identify the platform of the evidence, such as
+    /// `snp.chip_id`, the first present of which keys the values trusted
+    /// on first use. Without one, the platform is the TEE of the evidence.
+    #[serde(default)]
+    pub platform_claims: Vec<String>,
+}
+
+/// A measurement recorded on first use, not confirmed yet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProvisionalValue {
+    pub claim: String,
+    pub value: String,
+    /// TEE of the attestation that recorded the value.
+    pub tee: String,
+    /// Platform the value was first seen on, see
+    /// [`TofuConfig::platform_claims`].
+    #[serde(default)]
+    pub platform: String,
+    pub first_seen: DateTime<Utc>,
+}
+
+/// The text of a claim value, as compared with reference values.
+fn claim_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// The provisional reference values, persisted in the work dir.
+pub(crate) struct Provisional {
+    config: TofuConfig,
+    path: PathBuf,
+    values: Mutex<Vec<ProvisionalValue>>,
+}
+
+impl Provisional {
+    pub fn new(config: TofuConfig, work_dir: &Path) -> Result<Self> {
+        let path = work_dir.join(TOFU_FILE);
+        let values = match path.exists() {
+            true => serde_json::from_slice(&fs::read(&path)?)
+                .with_context(|| format!("Malformed {}", path.display()))?,
+            false => Vec::new(),
+        };
+        Ok(Self {
+            config,
+            path,
+            values: Mutex::new(values),
+        })
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Vec<ProvisionalValue>>> {
+        self.values
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Provisional reference values are poisoned"))
+    }
+
+    /// Replace the file of the provisional values atomically, so that a
+    /// crash cannot leave it truncated.
+    fn save(&self, values: &[ProvisionalValue]) -> Result<()> {
+        let file = tempfile::NamedTempFile::new_in(self.path.parent().unwrap_or(Path::new(".")))?;
+        serde_json::to_writer_pretty(&file, values)?;
+        file.as_file().sync_all()?;
+        file.persist(&self.path)
+            .with_context(|| format!("Cannot write {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// The platform of the evidence of `tee` with `claims`, see
+    /// [`TofuConfig::platform_claims`].
+    fn platform(&self, tee: &str, claims: &Map<String, Value>) -> String {
+        self.config
+            .platform_claims
+            .iter()
+            .find_map(|claim| Some(format!("{claim}={}", claim_text(claims.get(claim)?))))
+            .unwrap_or_else(|| tee.to_string())
+    }
+
+    /// Record the values of the configured `claims` that match no
+    /// reference value, if they are the first seen on the platform of the
+    /// evidence, and add them to the `reference` values. Return the names
+    /// of the claims that match a provisional value only. Fails if a value
+    /// differs from the one first seen on the platform.
+    pub fn record(
+        &self,
+        tee: &str,
+        claims: &Value,
+        reference: &mut HashMap<String, Vec<String>>,
+    ) -> Result<Vec<String>> {
+        let Some(claims) = claims.as_object() else {
+            return Ok(Vec::new());
+        };
+        let platform = self.platform(tee, claims);
+        let mut values = self.lock()?;
+        let mut recorded = false;
+        let mut unconfirmed = Vec::new();
+
+        for (claim, value) in claims {
+            if !self
+                .config
+                .claims
+                .iter()
+                .any(|pattern| chain::matches(pattern, claim))
+            {
+                continue;
+            }
+            let text = claim_text(value);
+            let references = reference.entry(claim.clone()).or_default();
+            let equal = |r: &String| {
+                *r == text || (value.is_string() && r.strip_prefix("sha384:") == Some(&text))
+            };
+            if references.iter().any(equal) {
+                continue;
+            }
+
+            match values
+                .iter()
+                .find(|v| v.claim == *claim && v.platform == platform)
+            {
+                Some(first) if first.value != text => bail!(
+                    "{claim} = {text} differs from {} trusted on first use on {platform}",
+                    first.value
+                ),
+                Some(_) => (),
+                None => {
+                    info!("Trusting {claim} = {text} on first use on {platform}");
+                    values.push(ProvisionalValue {
+                        claim: claim.clone(),
+                        value: text.clone(),
+                        tee: tee.to_string(),
+                        platform: platform.clone(),
+                        first_seen: Utc::now(),
+                    });
+                    recorded = true;
+                }
+            }
+            references.push(text);
+            unconfirmed.push(claim.clone());
+        }
+
+        if recorded {
+            self.save(&values)?;
+        }
+        unconfirmed.sort();
+        Ok(unconfirmed)
+    }
+
+    pub fn list(&self) -> Result<Vec<ProvisionalValue>> {
+        Ok(self.lock()?.clone())
+    }
+
+    /// Merge the provisional values of `claims` into the `existing`
+    /// reference values. The caller stores the returned reference values,
+    /// then discards the provisional ones.
+    pub fn confirm(
+        &self,
+        claims: &[String],
+        existing: &[ReferenceValue],
+    ) -> Result<Vec<ReferenceValue>> {
+        let values = self.lock()?;
+        let expired = Utc::now() + Duration::days(self.config.expiration_days);
+        let mut confirmed = Vec::new();
+
+        for claim in claims {
+            let provisional: Vec<&ProvisionalValue> =
+                values.iter().filter(|v| v.claim == *claim).collect();
+            if provisional.is_empty() {
+                bail!("No provisional reference value for {claim}");
+            }
+            let mut rv = match existing.iter().find(|rv| rv.name() == claim) {
+                Some(rv) if *rv.expired() > expired => rv.clone(),
+                Some(rv) => rv.clone().set_expired(expired),
+                None => ReferenceValue::new()?.set_name(claim).set_expired(expired),
+            };
+            for v in provisional {
+                if !rv.hash_values().iter().any(|pair| *pair.value() == v.value) {
+                    rv = rv.add_hash_value(TOFU_ALG.to_string(), v.value.clone());
+                }
+            }
+            confirmed.push(rv);
+        }
+        Ok(confirmed)
+    }
+
+    /// Forget the provisional values of `claims`.
+    pub fn discard(&self, claims: &[String]) -> Result<()> {
+        let mut values = self.lock()?;
+        values.retain(|v| !claims.contains(&v.claim));
+        self.save(&values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn provisional(work_dir: &Path) -> Provisional {
+        Provisional::new(
+            TofuConfig {
+                claims: vec![
+                    "tdx.quote.body.mr_td".to_string(),
+                    "tdx.quote.body.rtmr_*".to_string(),
+                ],
+                expiration_days: DEFAULT_EXPIRATION_DAYS,
+                platform_claims: vec!["tdx.quote.body.mr_seam".to_string()],
+            },
+            work_dir,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let tofu = provisional(dir.path());
+        let claims = json!({
+            "tdx.quote.body.mr_td": "aa",
+            "tdx.quote.body.rtmr_0": "bb",
+            "tdx.quote.body.report_data": "cc",
+        });
+        let mut reference = HashMap::from([(
+            "tdx.quote.body.rtmr_0".to_string(),
+            vec!["sha384:bb".to_string()],
+        )]);
+
+        let unconfirmed = tofu.record("tdx", &claims, &mut reference).unwrap();
+        assert_eq!(unconfirmed, ["tdx.quote.body.mr_td"]);
+        assert_eq!(reference["tdx.quote.body.mr_td"], ["aa"]);
+        assert!(!reference.contains_key("tdx.quote.body.report_data"));
+
+        // Recorded once, and kept across restarts.
+        tofu.record("tdx", &claims, &mut HashMap::new()).unwrap();
+        let values = provisional(dir.path()).list().unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0].claim, "tdx.quote.body.mr_td");
+        assert_eq!(values[0].value, "aa");
+        assert_eq!(values[0].platform, "tdx");
+    }
+
+    #[test]
+    fn test_platforms() {
+        let dir = tempfile::tempdir().unwrap();
+        let tofu = provisional(dir.path());
+        let claims = |mr_seam: &str, mr_td: &str| {
+            json!({
+                "tdx.quote.body.mr_seam": mr_seam,
+                "tdx.quote.body.mr_td": mr_td,
+            })
+        };
+
+        tofu.record("tdx", &claims("01", "aa"), &mut HashMap::new())
+            .unwrap();
+        // Another value on the same platform.
+        let e = tofu
+            .record("tdx", &claims("01", "bb"), &mut HashMap::new())
+            .unwrap_err();
+        assert!(e.to_string().contains("differs from aa"));
+        // A registered value.
+        let mut reference =
+            HashMap::from([("tdx.quote.body.mr_td".to_string(), vec!["bb".to_string()])]);
+        assert!(tofu
+            .record("tdx", &claims("01", "bb"), &mut reference)
+            .unwrap()
+            .is_empty());
+        // The first value on another platform.
+        tofu.record("tdx", &claims("02", "bb"), &mut HashMap::new())
+            .unwrap();
+
+        let platforms: Vec<_> = tofu
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.platform, v.value))
+            .collect();
+        assert_eq!(
+            platforms,
+            [
+                ("tdx.quote.body.mr_seam=01".to_string(), "aa".to_string()),
+                ("tdx.quote.body.mr_seam=02".to_string(), "bb".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_confirm() {
+        let dir = tempfile::tempdir().unwrap();
+        let tofu = provisional(dir.path());
+        let claims = json!({
+            "tdx.quote.body.mr_td": "aa",
+            "tdx.quote.body.rtmr_0": "bb",
+        });
+        tofu.record("tdx", &claims, &mut HashMap::new()).unwrap();
+
+        let existing = vec![ReferenceValue::new()
+            .unwrap()
+            .set_name("tdx.quote.body.mr_td")
+            .add_hash_value("sha384".to_string(), "00".to_string())];
+        let confirmed = tofu
+            .confirm(&["tdx.quote.body.mr_td".to_string()], &existing)
+            .unwrap();
+        assert_eq!(confirmed.len(), 1);
+        let values: Vec<&String> = confirmed[0]
+            .hash_values()
+            .iter()
+            .map(|pair| pair.value())
+            .collect();
+        assert_eq!(values, ["00", "aa"]);
+        assert!(*confirmed[0].expired() > Utc::now());
+
+        tofu.discard(&["tdx.quote.body.mr_td".to_string()]).unwrap();
+        assert_eq!(tofu.list().unwrap().len(), 1);
+        assert!(tofu
+            .confirm(&["tdx.quote.body.mr_td".to_string()], &[])
+            .is_err());
+    }
+}
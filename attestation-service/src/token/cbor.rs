@@ -0,0 +1,352 @@
+//! Minimal CBOR codec, for the COSE tokens of [`super::ear`] and the
+//! front ends that take CBOR requests. Maps are encoded in the order of
+//! their entries.
+//!
+//! Decoding takes a single item of definite length, nested at most
+//! [`MAX_DEPTH`] deep, as constrained attesters send. An item converts to
+//! JSON, see [`Cbor::to_json`], for the CBOR requests to be served as the
+//! JSON ones.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{Map, Number, Value};
+
+/// Deepest nesting of arrays, maps and tags decoded.
+pub const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cbor {
+    Uint(u64),
+    /// A signed integer.
+    Int(i64),
+    Float(f64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Map(Vec<(Cbor, Cbor)>),
+    Tag(u64, Box<Cbor>),
+    Bool(bool),
+    Null,
+}
+
+impl From<&Value> for Cbor {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => Cbor::Null,
+            Value::Bool(b) => Cbor::Bool(*b),
+            Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+                (Some(n), _) => Cbor::Uint(n),
+                (None, Some(n)) => Cbor::Int(n),
+                _ => Cbor::Float(n.as_f64().unwrap_or_default()),
+            },
+            Value::String(s) => Cbor::Text(s.clone()),
+            Value::Array(a) => Cbor::Array(a.iter().map(Cbor::from).collect()),
+            Value::Object(o) => Cbor::Map(
+                o.iter()
+                    .map(|(k, v)| (Cbor::Text(k.clone()), Cbor::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// The initial byte of an item of `major` type, and its argument.
+fn head(out: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    match n {
+        0..=23 => out.push(major | n as u8),
+        24..=0xff => out.extend([major | 24, n as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend((n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend((n as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend(n.to_be_bytes());
+        }
+    }
+}
+
+/// A reader of the items of `data`.
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| anyhow!("Truncated CBOR item at byte {}", self.pos))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// The argument of an item with the additional information `info`.
+    fn argument(&mut self, info: u8) -> Result<u64> {
+        let len = match info {
+            0..=23 => return Ok(info.into()),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            31 => bail!("Indefinite length CBOR items are not supported"),
+            _ => bail!("Reserved CBOR additional information {info}"),
+        };
+        Ok(self
+            .take(len)?
+            .iter()
+            .fold(0, |n, b| n << 8 | u64::from(*b)))
+    }
+
+    /// The length of a string or container, that must fit in the input.
+    fn length(&mut self, info: u8) -> Result<usize> {
+        let len = self.argument(info)?;
+        usize::try_from(len)
+            .ok()
+            .filter(|len| *len <= self.data.len() - self.pos)
+            .ok_or_else(|| anyhow!("CBOR length {len} exceeds the input"))
+    }
+
+    fn item(&mut self, depth: usize) -> Result<Cbor> {
+        if depth > MAX_DEPTH {
+            bail!("CBOR nested deeper than {MAX_DEPTH}");
+        }
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        Ok(match major {
+            0 => Cbor::Uint(self.argument(info)?),
+            1 => {
+                let n = self.argument(info)?;
+                Cbor::Int(
+                    i64::try_from(n)
+                        .map(|n| -1 - n)
+                        .map_err(|_| anyhow!("CBOR negative integer -1-{n} out of range"))?,
+                )
+            }
+            2 => {
+                let len = self.length(info)?;
+                Cbor::Bytes(self.take(len)?.to_vec())
+            }
+            3 => {
+                let len = self.length(info)?;
+                let text = std::str::from_utf8(self.take(len)?).context("CBOR text not UTF-8")?;
+                Cbor::Text(text.to_string())
+            }
+            4 => {
+                let len = self.length(info)?;
+                let items = (0..len)
+                    .map(|_| self.item(depth + 1))
+                    .collect::<Result<_>>()?;
+                Cbor::Array(items)
+            }
+            5 => {
+                let len = self.length(info)?;
+                let entries = (0..len)
+                    .map(|_| Ok((self.item(depth + 1)?, self.item(depth + 1)?)))
+                    .collect::<Result<_>>()?;
+                Cbor::Map(entries)
+            }
+            6 => {
+                let tag = self.argument(info)?;
+                Cbor::Tag(tag, Box::new(self.item(depth + 1)?))
+            }
+            _ => match info {
+                20 => Cbor::Bool(false),
+                21 => Cbor::Bool(true),
+                // Undefined, as null.
+                22 | 23 => Cbor::Null,
+                25 => Cbor::Float(half(self.argument(info)? as u16)),
+                26 => Cbor::Float(f32::from_bits(self.argument(info)? as u32).into()),
+                27 => Cbor::Float(f64::from_bits(self.argument(info)?)),
+                _ => bail!("Unsupported CBOR simple value {info}"),
+            },
+        })
+    }
+}
+
+/// The value of a half-precision float.
+fn half(bits: u16) -> f64 {
+    let exponent = i32::from(bits >> 10 & 0x1f);
+    let mantissa = f64::from(bits & 0x3ff);
+    let magnitude = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(exponent - 25),
+    };
+    match bits >> 15 {
+        0 => magnitude,
+        _ => -magnitude,
+    }
+}
+
+impl Cbor {
+    /// Decode the single CBOR item of `data`.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut decoder = Decoder { data, pos: 0 };
+        let item = decoder.item(0)?;
+        if decoder.pos != data.len() {
+            bail!("{} bytes after the CBOR item", data.len() - decoder.pos);
+        }
+        Ok(item)
+    }
+
+    /// The JSON value of the item: byte strings are base64 encoded, as the
+    /// binary fields of JSON evidence are, tags are dropped, and integer
+    /// map keys are their decimal string. Other map keys, and floats that
+    /// are not finite, have no JSON value.
+    pub fn to_json(&self) -> Result<Value> {
+        Ok(match self {
+            Cbor::Uint(n) => (*n).into(),
+            Cbor::Int(n) => (*n).into(),
+            Cbor::Float(f) => Number::from_f64(*f)
+                .map(Value::Number)
+                .ok_or_else(|| anyhow!("CBOR float {f} has no JSON value"))?,
+            Cbor::Bytes(bytes) => STANDARD.encode(bytes).into(),
+            Cbor::Text(text) => text.clone().into(),
+            Cbor::Array(items) => items
+                .iter()
+                .map(Cbor::to_json)
+                .collect::<Result<Vec<_>>>()?
+                .into(),
+            Cbor::Map(entries) => {
+                let mut object = Map::new();
+                for (key, value) in entries {
+                    let key = match key {
+                        Cbor::Text(key) => key.clone(),
+                        Cbor::Uint(n) => n.to_string(),
+                        Cbor::Int(n) => n.to_string(),
+                        key => bail!("CBOR map key {key:?} has no JSON value"),
+                    };
+                    object.insert(key, value.to_json()?);
+                }
+                Value::Object(object)
+            }
+            Cbor::Tag(_, item) => item.to_json()?,
+            Cbor::Bool(b) => (*b).into(),
+            Cbor::Null => Value::Null,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            Cbor::Uint(n) => head(out, 0, *n),
+            Cbor::Int(n) if *n >= 0 => head(out, 0, *n as u64),
+            Cbor::Int(n) => head(out, 1, (-1 - *n) as u64),
+            Cbor::Float(f) => {
+                out.push(0xfb);
+                out.extend(f.to_be_bytes());
+            }
+            Cbor::Bytes(b) => {
+                head(out, 2, b.len() as u64);
+                out.extend(b);
+            }
+            Cbor::Text(s) => {
+                head(out, 3, s.len() as u64);
+                out.extend(s.as_bytes());
+            }
+            Cbor::Array(items) => {
+                head(out, 4, items.len() as u64);
+                for item in items {
+                    item.encode_into(out);
+                }
+            }
+            Cbor::Map(entries) => {
+                head(out, 5, entries.len() as u64);
+                for (key, value) in entries {
+                    key.encode_into(out);
+                    value.encode_into(out);
+                }
+            }
+            Cbor::Tag(tag, item) => {
+                head(out, 6, *tag);
+                item.encode_into(out);
+            }
+            Cbor::Bool(false) => out.push(0xf4),
+            Cbor::Bool(true) => out.push(0xf5),
+            Cbor::Null => out.push(0xf6),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Examples of RFC 8949, appendix A.
+    #[test]
+    fn test_encode() {
+        let hex = |cbor: Cbor| hex::encode(cbor.encode());
+        assert_eq!(hex(Cbor::Uint(0)), "00");
+        assert_eq!(hex(Cbor::Uint(24)), "1818");
+        assert_eq!(hex(Cbor::Uint(1000)), "1903e8");
+        assert_eq!(hex(Cbor::Uint(1000000)), "1a000f4240");
+        assert_eq!(hex(Cbor::Uint(1000000000000)), "1b000000e8d4a51000");
+        assert_eq!(hex(Cbor::Int(-1)), "20");
+        assert_eq!(hex(Cbor::Int(-1000)), "3903e7");
+        assert_eq!(hex(Cbor::Float(1.1)), "fb3ff199999999999a");
+        assert_eq!(hex(Cbor::Bytes(vec![1, 2, 3, 4])), "4401020304");
+        assert_eq!(hex(Cbor::Text("IETF".to_string())), "6449455446");
+        assert_eq!(
+            hex(Cbor::Tag(1, Box::new(Cbor::Uint(1363896240)))),
+            "c11a514b67b0"
+        );
+        assert_eq!(
+            hex(Cbor::from(&json!({"a": 1, "b": [2, 3]}))),
+            "a26161016162820203"
+        );
+        assert_eq!(hex(Cbor::from(&json!([false, true, null]))), "83f4f5f6");
+    }
+
+    #[test]
+    fn test_decode() {
+        let decode = |hex: &str| Cbor::decode(&hex::decode(hex).unwrap());
+        for value in [
+            json!({"a": 1, "b": [2, 3]}),
+            json!([false, true, null, -1000, 1000000000000u64, "IETF", 1.1]),
+        ] {
+            let cbor = Cbor::from(&value);
+            assert_eq!(Cbor::decode(&cbor.encode()).unwrap(), cbor);
+            assert_eq!(cbor.to_json().unwrap(), value);
+        }
+        // Half and single precision floats, of RFC 8949, appendix A.
+        assert_eq!(decode("f93c00").unwrap(), Cbor::Float(1.0));
+        assert_eq!(decode("f9c400").unwrap(), Cbor::Float(-4.0));
+        assert_eq!(decode("f90001").unwrap(), Cbor::Float(5.960464477539063e-8));
+        assert_eq!(decode("fa47c35000").unwrap(), Cbor::Float(100000.0));
+
+        // Byte strings are base64, integer keys strings, tags dropped.
+        let item = decode("a20144010203046161c11a514b67b0").unwrap();
+        assert_eq!(
+            item.to_json().unwrap(),
+            json!({"1": "AQIDBA==", "a": 1363896240})
+        );
+        assert!(decode("a1f6f6").unwrap().to_json().is_err());
+        assert!(decode("f97c00").unwrap().to_json().is_err());
+
+        // Truncated, trailing bytes, indefinite length, too long or deep.
+        assert!(decode("1903").is_err());
+        assert!(decode("0000").is_err());
+        assert!(decode("9fff").is_err());
+        assert!(decode("5bffffffffffffffff").is_err());
+        assert!(decode(&"81".repeat(MAX_DEPTH + 1)).is_err());
+        assert!(decode(&format!("{}00", "81".repeat(MAX_DEPTH))).is_ok());
+        assert!(decode("62c328").is_err());
+    }
+}
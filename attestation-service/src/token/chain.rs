@@ -0,0 +1,300 @@
+//! Chaining of attestation results tokens for re-attestation.
+//!
+//! A workload that re-attests can present its previous token, if it has
+//! not expired and was issued for the same TEE public key. The new token
+//! then refers to it by its `jti` in `previous_token`, and tells in
+//! `continuity_ok` whether the TCB status is unchanged since, apart from
+//! the claims that are allowed to change.
+//!
+//! The `tcb-status` of a token may be redacted or trimmed by the claim
+//! filters and the detail level, so every token carries the
+//! [`continuity_digest`] of the claims before they apply, and the
+//! continuity is that of the digests.
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+use super::ear;
+use crate::verifier::freshness::FRESHNESS_CLAIM;
+use crate::verifier::timing::{ATTESTATION_TIME_CLAIM, BOOT_TIME_CLAIM, EVIDENCE_AGE_CLAIM};
+
+/// Claims of every TEE that change on every attestation, as they tell how
+/// fresh the evidence is and when it was produced and verified. The boot
+/// time is derived from a clock of the evidence, and is off by a little
+/// every time.
+const VOLATILE_CLAIMS: &[&str] = &[
+    FRESHNESS_CLAIM,
+    EVIDENCE_AGE_CLAIM,
+    ATTESTATION_TIME_CLAIM,
+    BOOT_TIME_CLAIM,
+];
+
+/// Claims of `tee` that change on every attestation, as they are bound to
+/// the nonce or are clocks of the evidence.
+fn tee_volatile_claims(tee: &str) -> &'static [&'static str] {
+    match tee {
+        "tdx" => &["tdx.quote.body.report_data", "tdx.partitioning.l2.clock"],
+        "snp" => &["snp.report_data"],
+        "azsnpvtpm" => &[
+            "azsnpvtpm.quote.report_data",
+            "azsnpvtpm.runtime_data.user-data",
+            "azsnpvtpm.tpm.clock",
+        ],
+        "cca" => &[
+            "cca.cca-platform-token.cca-platform-challenge",
+            "cca.cca-realm-delegated-token.cca-realm-challenge",
+        ],
+        "se" => &["se.user_data"],
+        "sample" => &["sample.report_data"],
+        _ => &[],
+    }
+}
+
+/// Claim of the tokens with their [`continuity_digest`].
+pub(crate) const CONTINUITY_DIGEST_CLAIM: &str = "continuity_digest";
+
+/// Whether `name` matches `pattern`, which may end with `*` to match any
+/// claim name with that prefix.
+pub(crate) fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
+    }
+}
+
+/// Check that a re-attestation of `tee`, at `now` in seconds since the
+/// epoch, for `tee_pubkey`, can chain to the token of `previous` claims:
+/// the token is an attestation token, valid at `now`, and was issued for
+/// the same TEE key. The other tokens of the AS, such as service statuses,
+/// have a `typ` claim.
+pub(crate) fn check_previous(
+    tee: &str,
+    previous: &Value,
+    tee_pubkey: &Value,
+    now: i64,
+) -> Result<()> {
+    if !previous["typ"].is_null() {
+        bail!("The previous token is not an attestation token");
+    }
+    match previous["exp"].as_i64() {
+        Some(exp) if exp > now => {}
+        Some(_) => bail!("The previous token has expired"),
+        None => bail!("The previous token has no expiration time"),
+    }
+    if previous["nbf"].as_i64().is_some_and(|nbf| nbf > now) {
+        bail!("The previous token is not valid yet");
+    }
+    if ear::tee_pubkey(tee, previous) != tee_pubkey {
+        bail!("The previous token was issued for another TEE public key");
+    }
+    Ok(())
+}
+
+/// Whether the claim `name` of `tee` must stay the same for continuity,
+/// that is, it neither changes on every attestation nor is allowed to
+/// change by `mutable`.
+fn is_stable(tee: &str, name: &str, mutable: &[String]) -> bool {
+    !VOLATILE_CLAIMS.contains(&name)
+        && !tee_volatile_claims(tee).contains(&name)
+        && !mutable.iter().any(|pattern| matches(pattern, name))
+}
+
+/// Names of the claims that differ between two flattened TCB statuses of
+/// `tee`, leaving out the claims that are allowed to change.
+pub(crate) fn changed_claims(
+    tee: &str,
+    previous: &Value,
+    current: &Value,
+    mutable: &[String],
+) -> Vec<String> {
+    let empty = serde_json::Map::new();
+    let previous = previous.as_object().unwrap_or(&empty);
+    let current = current.as_object().unwrap_or(&empty);
+
+    let mut changed: Vec<String> = previous
+        .keys()
+        .chain(current.keys().filter(|name| !previous.contains_key(*name)))
+        .filter(|name| previous.get(*name) != current.get(*name))
+        .filter(|name| is_stable(tee, name, mutable))
+        .cloned()
+        .collect();
+    changed.sort();
+    changed
+}
+
+/// Hex SHA-256 digest of the flattened `claims` of `tee`, before any claim
+/// filter or detail level, leaving out the claims that are allowed to
+/// change, so that two attestations have the same digest if and only if
+/// [`changed_claims`] of their full claims is empty.
+pub(crate) fn continuity_digest(tee: &str, claims: &Value, mutable: &[String]) -> String {
+    let stable: BTreeMap<&String, &Value> = claims
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(name, _)| is_stable(tee, name, mutable))
+        .collect();
+    hex::encode(Sha256::digest(
+        serde_json::to_vec(&stable).unwrap_or_default(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::claim_filter::{self, ClaimFilter};
+    use serde_json::json;
+
+    #[test]
+    fn test_changed_claims() {
+        let previous = json!({
+            "tdx.quote.body.mr_td": "aa",
+            "tdx.quote.body.report_data": "01",
+            "tdx.quote.body.tcb_svn.0": 3,
+            "tdx.quote.body.tcb_svn.1": 1,
+            "tdx.ccel.kernel": "bb",
+        });
+
+        let mut current = previous.clone();
+        current["tdx.quote.body.report_data"] = json!("02");
+        assert!(changed_claims("tdx", &previous, &current, &[]).is_empty());
+
+        current["tdx.quote.body.tcb_svn.0"] = json!(4);
+        current["tdx.ccel.kernel"] = json!("cc");
+        current["tdx.ccel.kernel_parameters"] = json!("quiet");
+        assert_eq!(
+            changed_claims("tdx", &previous, &current, &[]),
+            [
+                "tdx.ccel.kernel",
+                "tdx.ccel.kernel_parameters",
+                "tdx.quote.body.tcb_svn.0"
+            ]
+        );
+
+        let mutable = [
+            "tdx.quote.body.tcb_svn.*".to_string(),
+            "tdx.ccel.*".to_string(),
+        ];
+        assert!(changed_claims("tdx", &previous, &current, &mutable).is_empty());
+
+        current["tdx.quote.body.mr_td"] = json!("dd");
+        assert_eq!(
+            changed_claims("tdx", &previous, &current, &mutable),
+            ["tdx.quote.body.mr_td"]
+        );
+    }
+
+    #[test]
+    fn test_continuity_digest() {
+        let previous = json!({
+            "tdx.quote.body.mr_td": "aa",
+            "tdx.quote.body.report_data": "01",
+            "tdx.quote.body.tcb_svn.0": 3,
+            "attestation_time": "2024-03-12T10:00:00+00:00",
+        });
+        let digest = continuity_digest("tdx", &previous, &[]);
+
+        let mut current = previous.clone();
+        current["tdx.quote.body.report_data"] = json!("02");
+        current["attestation_time"] = json!("2024-03-12T11:00:00+00:00");
+        assert_eq!(continuity_digest("tdx", &current, &[]), digest);
+
+        current["tdx.quote.body.tcb_svn.0"] = json!(4);
+        assert_ne!(continuity_digest("tdx", &current, &[]), digest);
+        let mutable = ["tdx.quote.body.tcb_svn.*".to_string()];
+        assert_eq!(
+            continuity_digest("tdx", &current, &mutable),
+            continuity_digest("tdx", &previous, &mutable)
+        );
+    }
+
+    #[test]
+    fn test_redacted_claim_changed() {
+        let filters: Vec<ClaimFilter> = serde_json::from_value(json!([
+            { "redact": ["tdx.quote.body.mr_config_id"], "omit": ["tdx.ccel.*"] }
+        ]))
+        .unwrap();
+        let filters = claim_filter::select(&filters, None, &[]);
+        let previous = json!({
+            "tdx.quote.body.mr_td": "aa",
+            "tdx.quote.body.mr_config_id": "00",
+            "tdx.ccel.kernel": "bb",
+        });
+        let mut current = previous.clone();
+        current["tdx.quote.body.mr_config_id"] = json!("01");
+
+        // The tokens cannot tell, their digests can.
+        assert!(changed_claims(
+            "tdx",
+            &filters.apply(&previous),
+            &filters.apply(&current),
+            &[]
+        )
+        .is_empty());
+        assert_ne!(
+            continuity_digest("tdx", &previous, &[]),
+            continuity_digest("tdx", &current, &[])
+        );
+
+        let mut current = previous.clone();
+        current["tdx.ccel.kernel"] = json!("cc");
+        assert_ne!(
+            continuity_digest("tdx", &previous, &[]),
+            continuity_digest("tdx", &current, &[])
+        );
+    }
+
+    #[test]
+    fn test_volatile_claims() {
+        let previous = json!({
+            "snp.measurement": "aa",
+            "snp.report_data": "01",
+            "freshness": "timestamp",
+            "evidence_age_seconds": 3,
+            "attestation_time": "2024-03-12T10:00:00+00:00",
+            "boot_time": "2024-03-12T09:00:00+00:00",
+        });
+        let current = json!({
+            "snp.measurement": "aa",
+            "snp.report_data": "02",
+            "freshness": "nonce",
+            "attestation_time": "2024-03-12T11:00:00+00:00",
+            "boot_time": "2024-03-12T09:00:01+00:00",
+        });
+        assert!(changed_claims("snp", &previous, &current, &[]).is_empty());
+        assert_eq!(
+            changed_claims("tdx", &previous, &current, &[]),
+            ["snp.report_data"]
+        );
+    }
+
+    #[test]
+    fn test_check_previous() {
+        let tee_pubkey = json!({ "kty": "RSA", "n": "AQAB", "e": "AQAB" });
+        let previous = json!({ "nbf": 100, "exp": 200, "tee-pubkey": tee_pubkey });
+        assert!(check_previous("snp", &previous, &tee_pubkey, 150).is_ok());
+
+        let e = check_previous("snp", &previous, &tee_pubkey, 200).unwrap_err();
+        assert!(e.to_string().contains("expired"));
+        let e = check_previous("snp", &previous, &tee_pubkey, 50).unwrap_err();
+        assert!(e.to_string().contains("not valid yet"));
+
+        let status =
+            json!({ "typ": "as-status+jwt", "nbf": 100, "exp": 200, "tee-pubkey": tee_pubkey });
+        let e = check_previous("snp", &status, &tee_pubkey, 150).unwrap_err();
+        assert!(e.to_string().contains("not an attestation token"));
+
+        let other = json!({ "kty": "RSA", "n": "AQAC", "e": "AQAB" });
+        let e = check_previous("snp", &previous, &other, 150).unwrap_err();
+        assert!(e.to_string().contains("another TEE public key"));
+
+        let ear = json!({
+            "nbf": 100,
+            "exp": 200,
+            "submods": { "snp": { "ear.veraison.key-attestation": { "akpub": tee_pubkey } } },
+        });
+        assert!(check_previous("snp", &ear, &tee_pubkey, 150).is_ok());
+        assert!(check_previous("snp", &ear, &other, 150).is_err());
+    }
+}
@@ -0,0 +1,304 @@
+//! Co-signing of attestation results tokens.
+//!
+//! Relying parties that require two-party control over attestation
+//! verdicts can require tokens to be signed by other keys than the token
+//! broker's, like an offline key of their organization. With co-signers,
+//! tokens are JWS in the
+//! [JSON general serialization](https://www.rfc-editor.org/rfc/rfc7515#section-7.2.1)
+//! instead of compact JWTs, with the signature of the token broker first,
+//! then one per co-signer in their configured order:
+//!
+//! ```json
+//! {
+//!     "payload": "<claims>",
+//!     "signatures": [
+//!         { "protected": "<broker header>", "signature": "<...>" },
+//!         { "protected": "<co-signer header>", "signature": "<...>" }
+//!     ]
+//! }
+//! ```
+//!
+//! The header of a co-signature has the `alg` and `kid` of the JWK of the
+//! co-signer.
+
+use anyhow::*;
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::sha2::Sha384;
+use rsa::signature::{RandomizedSigner, SignatureEncoding};
+use rsa::RsaPrivateKey;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// How long a signing command may take.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A key that co-signs the tokens, possibly held elsewhere than the AS.
+#[async_trait]
+pub trait CoSigner {
+    /// The public JWK of the key, with its `alg`.
+    fn jwk(&self) -> Value;
+
+    /// Sign the JWS signing input `<protected header>.<payload>`.
+    async fn sign(&self, signing_input: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Configuration of a co-signer, e.g.
+///
+/// ```json
+/// { "type": "file", "path": "/etc/as/cosign-key.pem" }
+/// { "type": "command", "command": ["/usr/bin/org-sign"], "jwk": "/etc/as/org-key.jwk" }
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CoSignerConfig {
+    /// A PEM encoded PKCS#8 (or PKCS#1) RSA private key file, signing with
+    /// RS384.
+    File { path: PathBuf },
+
+    /// A command that is given the signing input on its standard input,
+    /// and writes the base64url encoded signature on its standard output,
+    /// e.g. to sign with an offline key through a signing service. `jwk` is
+    /// the public JWK file of its key.
+    Command { command: Vec<String>, jwk: PathBuf },
+}
+
+impl CoSignerConfig {
+    fn to_co_signer(&self) -> Result<Box<dyn CoSigner + Send + Sync>> {
+        match self {
+            CoSignerConfig::File { path } => {
+                let pem = std::fs::read_to_string(path)
+                    .with_context(|| format!("read co-signing key {}", path.display()))?;
+                let key = RsaPrivateKey::from_pkcs8_pem(&pem)
+                    .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&pem))
+                    .map_err(|e| anyhow!("parse RSA private key: {e}"))?;
+                Ok(Box::new(KeyCoSigner(key)) as Box<dyn CoSigner + Send + Sync>)
+            }
+            CoSignerConfig::Command { command, jwk } => {
+                if command.is_empty() {
+                    bail!("empty co-signing command");
+                }
+                let jwk: Value = serde_json::from_slice(
+                    &std::fs::read(jwk)
+                        .with_context(|| format!("read co-signer JWK {}", jwk.display()))?,
+                )
+                .context("Malformed co-signer JWK")?;
+                if !jwk["alg"].is_string() {
+                    bail!("The co-signer JWK has no `alg`");
+                }
+                Ok(Box::new(CommandCoSigner {
+                    command: command.clone(),
+                    jwk,
+                }) as Box<dyn CoSigner + Send + Sync>)
+            }
+        }
+    }
+}
+
+struct KeyCoSigner(RsaPrivateKey);
+
+#[async_trait]
+impl CoSigner for KeyCoSigner {
+    fn jwk(&self) -> Value {
+        super::simple::jwk(&self.0.to_public_key())
+    }
+
+    async fn sign(&self, signing_input: &[u8]) -> Result<Vec<u8>> {
+        let signing_key = SigningKey::<Sha384>::new(self.0.clone());
+        let signature = signing_key.sign_with_rng(&mut rand::thread_rng(), signing_input);
+        Ok(signature.to_bytes().to_vec())
+    }
+}
+
+struct CommandCoSigner {
+    command: Vec<String>,
+    jwk: Value,
+}
+
+#[async_trait]
+impl CoSigner for CommandCoSigner {
+    fn jwk(&self) -> Value {
+        self.jwk.clone()
+    }
+
+    async fn sign(&self, signing_input: &[u8]) -> Result<Vec<u8>> {
+        let mut child = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("run co-signing command {}", self.command[0]))?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("No stdin of the co-signing command"))?;
+        stdin.write_all(signing_input).await?;
+        drop(stdin);
+
+        let output = tokio::time::timeout(COMMAND_TIMEOUT, child.wait_with_output())
+            .await
+            .map_err(|_| anyhow!("The co-signing command timed out"))??;
+        if !output.status.success() {
+            bail!("The co-signing command failed with {}", output.status);
+        }
+        URL_SAFE_NO_PAD
+            .decode(String::from_utf8(output.stdout)?.trim())
+            .context("Malformed signature of the co-signing command")
+    }
+}
+
+/// The co-signers of the tokens.
+#[derive(Default)]
+pub struct CoSigners(Vec<Box<dyn CoSigner + Send + Sync>>);
+
+impl CoSigners {
+    pub fn new(configs: &[CoSignerConfig]) -> Result<Self> {
+        configs
+            .iter()
+            .enumerate()
+            .map(|(index, config)| {
+                config
+                    .to_co_signer()
+                    .with_context(|| format!("load token co-signer {index}"))
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+
+    pub fn push(&mut self, co_signer: Box<dyn CoSigner + Send + Sync>) {
+        self.0.push(co_signer);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Add the co-signatures to the compact JWS `token`, which is returned
+    /// as is if there are no co-signers.
+    pub async fn co_sign(&self, token: String) -> Result<String> {
+        if self.0.is_empty() {
+            return Ok(token);
+        }
+        let mut parts = token.splitn(3, '.');
+        let (Some(protected), Some(payload), Some(signature)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            bail!("Malformed token");
+        };
+
+        let mut signatures = vec![json!({ "protected": protected, "signature": signature })];
+        for co_signer in &self.0 {
+            let jwk = co_signer.jwk();
+            let mut header = json!({ "alg": jwk["alg"] });
+            if jwk["kid"].is_string() {
+                header["kid"] = jwk["kid"].clone();
+            }
+            let protected = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?);
+            let signature = co_signer
+                .sign(format!("{protected}.{payload}").as_bytes())
+                .await
+                .with_context(|| format!("co-sign with key {}", jwk["kid"]))?;
+            signatures.push(json!({
+                "protected": protected,
+                "signature": URL_SAFE_NO_PAD.encode(signature),
+            }));
+        }
+
+        Ok(json!({ "payload": payload, "signatures": signatures }).to_string())
+    }
+}
+
+/// The compact JWS of the token broker signature of a co-signed `token`,
+/// which the broker can verify. Other tokens are returned as is.
+pub(crate) fn broker_signed(token: &str) -> Result<String> {
+    if !token.trim_start().starts_with('{') {
+        return Ok(token.to_string());
+    }
+    let jws: Value = serde_json::from_str(token).context("Malformed co-signed token")?;
+    let field = |value: &Value, name: &str| -> Result<String> {
+        value[name]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("Malformed co-signed token: no `{name}`"))
+    };
+    let broker = &jws["signatures"][0];
+    Ok(format!(
+        "{}.{}.{}",
+        field(broker, "protected")?,
+        field(&jws, "payload")?,
+        field(broker, "signature")?
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::{AttestationTokenBrokerType, AttestationTokenConfig};
+    use rsa::pkcs1v15::{Signature, VerifyingKey};
+    use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+    use rsa::signature::Verifier;
+
+    #[tokio::test]
+    async fn test_co_sign() {
+        let broker = AttestationTokenBrokerType::Simple
+            .to_token_broker(AttestationTokenConfig::default())
+            .unwrap();
+        let token = broker.issue(json!({ "tcb-status": {} })).unwrap();
+        assert_eq!(
+            CoSigners::default().co_sign(token.clone()).await.unwrap(),
+            token
+        );
+
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cosign-key.pem");
+        std::fs::write(&path, key.to_pkcs8_pem(LineEnding::LF).unwrap()).unwrap();
+        let co_signers = CoSigners::new(&[CoSignerConfig::File { path }]).unwrap();
+
+        let co_signed = co_signers.co_sign(token.clone()).await.unwrap();
+        let jws: Value = serde_json::from_str(&co_signed).unwrap();
+        assert_eq!(jws["signatures"].as_array().unwrap().len(), 2);
+
+        // The co-signature verifies with the co-signer key.
+        let co_signature = &jws["signatures"][1];
+        let header: Value = serde_json::from_slice(
+            &URL_SAFE_NO_PAD
+                .decode(co_signature["protected"].as_str().unwrap())
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            header["kid"],
+            super::super::simple::jwk(&key.to_public_key())["kid"]
+        );
+        let signing_input = format!(
+            "{}.{}",
+            co_signature["protected"].as_str().unwrap(),
+            jws["payload"].as_str().unwrap()
+        );
+        let signature = URL_SAFE_NO_PAD
+            .decode(co_signature["signature"].as_str().unwrap())
+            .unwrap();
+        VerifyingKey::<Sha384>::new(key.to_public_key())
+            .verify(
+                signing_input.as_bytes(),
+                &Signature::try_from(signature.as_slice()).unwrap(),
+            )
+            .unwrap();
+
+        // The broker still verifies its own signature.
+        assert_eq!(broker_signed(&co_signed).unwrap(), token);
+        assert!(broker.verify(&broker_signed(&co_signed).unwrap()).is_ok());
+        assert_eq!(broker_signed(&token).unwrap(), token);
+    }
+}
@@ -0,0 +1,258 @@
+//! EAR (EAT Attestation Results) tokens.
+//!
+//! Instead of its own claims, the AS can issue the results of attestations
+//! as [EAR](https://datatracker.ietf.org/doc/draft-fv-rats-ear/) tokens,
+//! with `"format": "ear_jwt"` or `"ear_cose"` in `attestation_token_config`,
+//! or per attestation request. The claims are those of the `ear` claim
+//! mapper, see [`super::mapper`], with the nonce of the request as
+//! `eat_nonce`. The submodule of the TEE has an AR4SI trustworthiness
+//! vector, derived from the claims of the AS:
+//!
+//! - `instance-identity`: affirming, the evidence verified and binds the TEE
+//!   key.
+//! - `configuration`: a warning if the TEE is debuggable, or if stages of
+//!   the verifier pipeline were skipped.
+//! - `executables`: affirming, the policy accepted the measurements, a
+//!   warning if some reference values are trusted on first use and not
+//!   confirmed yet.
+//! - `hardware`: from the `tcb_status` claim, a warning if the TCB is not up
+//!   to date or the verification raised warnings, contraindicated if it is
+//!   revoked, no claim if it is unknown.
+//!
+//! The `ear.status` of the submodule is the worst tier of the vector.
+//!
+//! EAR JWTs are signed and co-signed like the other tokens. COSE tokens are
+//! COSE_Sign1 structures of the claims encoded in CBOR, signed with RS384,
+//! and base64url encoded. They cannot be co-signed, nor be previous tokens.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::str::FromStr;
+
+use super::mapper::{ClaimMapper, EarMapper};
+use crate::verifier::tcb::TCB_STATUS_CLAIM;
+
+/// AR4SI trustworthiness claim values, of the general tiers.
+const NO_CLAIM: u8 = 0;
+const AFFIRMING: u8 = 2;
+const WARNING: u8 = 32;
+const CONTRAINDICATED: u8 = 96;
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenFormat {
+    /// JWT of the claims of the AS.
+    #[default]
+    Jwt,
+    /// EAR, as a JWT.
+    EarJwt,
+    /// EAR, as a COSE_Sign1 structure.
+    EarCose,
+}
+
+impl TokenFormat {
+    pub fn is_ear(self) -> bool {
+        self != TokenFormat::Jwt
+    }
+}
+
+impl fmt::Display for TokenFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Jwt => "jwt",
+            Self::EarJwt => "ear_jwt",
+            Self::EarCose => "ear_cose",
+        })
+    }
+}
+
+impl FromStr for TokenFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "jwt" => Ok(Self::Jwt),
+            "ear_jwt" => Ok(Self::EarJwt),
+            "ear_cose" => Ok(Self::EarCose),
+            _ => bail!("unknown token format {s}, expected jwt, ear_jwt or ear_cose"),
+        }
+    }
+}
+
+/// The tier of a trustworthiness claim value.
+fn tier(value: u8) -> u8 {
+    match value {
+        96.. => CONTRAINDICATED,
+        32.. => WARNING,
+        2.. => AFFIRMING,
+        _ => NO_CLAIM,
+    }
+}
+
+/// The `ear.status` and `ear.trustworthiness-vector` of the claims of the
+/// AS, of an attestation of `tee`.
+pub(super) fn appraisal(tee: &str, claims: &Map<String, Value>) -> (&'static str, Value) {
+    let evidence = claims.get("tcb-status").unwrap_or(&Value::Null);
+    // In the namespace of the TEE with the `v1` claims schema.
+    let tcb_status = evidence
+        .get(TCB_STATUS_CLAIM)
+        .or_else(|| evidence.get(format!("{tee}.{TCB_STATUS_CLAIM}")));
+    let hardware = match tcb_status.and_then(Value::as_str) {
+        Some("Revoked") => CONTRAINDICATED,
+        _ if claims.contains_key("warnings") => WARNING,
+        Some("UpToDate") => AFFIRMING,
+        Some("Unknown") | None => NO_CLAIM,
+        Some(_) => WARNING,
+    };
+    let configuration = match evidence["debug"] == true || claims.contains_key("skipped-stages") {
+        true => WARNING,
+        false => AFFIRMING,
+    };
+    let executables = match claims.contains_key("unconfirmed") {
+        true => WARNING,
+        false => AFFIRMING,
+    };
+    let vector = [
+        ("instance-identity", AFFIRMING),
+        ("configuration", configuration),
+        ("executables", executables),
+        ("hardware", hardware),
+    ];
+
+    let status = match vector.iter().map(|(_, value)| tier(*value)).max() {
+        Some(CONTRAINDICATED) => "contraindicated",
+        Some(WARNING) => "warning",
+        Some(AFFIRMING) => "affirming",
+        _ => "none",
+    };
+    let vector = vector
+        .into_iter()
+        .filter(|(_, value)| *value != NO_CLAIM)
+        .map(|(name, value)| (name.to_string(), value.into()))
+        .collect::<Map<_, _>>();
+    (status, Value::Object(vector))
+}
+
+/// The EAR claims of the AS `token_claims` of an attestation of `tee`,
+/// with `nonce`.
+pub fn claims(tee: &str, nonce: &str, token_claims: Value) -> Result<Value> {
+    let mut claims = EarMapper.map(tee, token_claims)?;
+    if !nonce.is_empty() {
+        claims["eat_nonce"] = nonce.into();
+    }
+    Ok(claims)
+}
+
+/// The claims of the TEE in the claims of an AS token, EAR or not.
+pub fn evidence_claims<'a>(tee: &str, claims: &'a Value) -> &'a Value {
+    match claims.get("submods") {
+        Some(submods) => &submods[tee]["ear.veraison.annotated-evidence"],
+        None => &claims["tcb-status"],
+    }
+}
+
+/// The claim `name` that the AS adds to the claims of the TEE, such as
+/// `previous_token`, in the claims of an AS token, EAR or not.
+pub fn token_claim<'a>(tee: &str, claims: &'a Value, name: &str) -> &'a Value {
+    match claims.get("submods") {
+        Some(submods) => &submods[tee]["ear.veraison.policy-claims"][name],
+        None => &claims[name],
+    }
+}
+
+/// The TEE public key in the claims of an AS token, EAR or not.
+pub fn tee_pubkey<'a>(tee: &str, claims: &'a Value) -> &'a Value {
+    match claims.get("submods") {
+        Some(submods) => &submods[tee]["ear.veraison.key-attestation"]["akpub"],
+        None => &claims["tee-pubkey"],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn appraise(tee: &str, claims: Value) -> (&'static str, Value) {
+        appraisal(tee, claims.as_object().unwrap())
+    }
+
+    #[test]
+    fn test_appraisal() {
+        let (status, vector) = appraise(
+            "tdx",
+            json!({ "tcb-status": { "tcb_status": "UpToDate", "debug": false } }),
+        );
+        assert_eq!(status, "affirming");
+        assert_eq!(
+            vector,
+            json!({
+                "instance-identity": AFFIRMING,
+                "configuration": AFFIRMING,
+                "executables": AFFIRMING,
+                "hardware": AFFIRMING,
+            })
+        );
+
+        let (status, vector) = appraise(
+            "tdx",
+            json!({
+                "tcb-status": { "tcb_status": "OutOfDate", "debug": true },
+                "unconfirmed": ["tdx.quote.body.mr_td"],
+            }),
+        );
+        assert_eq!(status, "warning");
+        assert_eq!(vector["configuration"], WARNING);
+        assert_eq!(vector["executables"], WARNING);
+        assert_eq!(vector["hardware"], WARNING);
+
+        // With the `v1` claims schema.
+        let (status, vector) = appraise(
+            "sgx",
+            json!({ "tcb-status": { "sgx.tcb_status": "Revoked" }, "warnings": ["expiring"] }),
+        );
+        assert_eq!(status, "contraindicated");
+        assert_eq!(vector["hardware"], CONTRAINDICATED);
+
+        // Without a TCB status, the hardware is not appraised.
+        let (status, vector) = appraise("sample", json!({ "tcb-status": {} }));
+        assert_eq!(status, "affirming");
+        assert!(vector.get("hardware").is_none());
+    }
+
+    #[test]
+    fn test_claims() {
+        let token_claims = json!({
+            "tee-pubkey": { "kty": "RSA" },
+            "tcb-status": { "tcb_status": "UpToDate" },
+            "policy_digest": "00ff",
+        });
+        let ear = claims("tdx", "abc", token_claims.clone()).unwrap();
+        assert_eq!(ear["eat_nonce"], "abc");
+        assert_eq!(ear["submods"]["tdx"]["ear.status"], "affirming");
+        assert_eq!(
+            evidence_claims("tdx", &ear),
+            &json!({ "tcb_status": "UpToDate" })
+        );
+        assert_eq!(
+            evidence_claims("tdx", &token_claims),
+            &token_claims["tcb-status"]
+        );
+
+        let ear = claims("tdx", "", token_claims).unwrap();
+        assert!(ear.get("eat_nonce").is_none());
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            "ear_cose".parse::<TokenFormat>().unwrap(),
+            TokenFormat::EarCose
+        );
+        assert!("cwt".parse::<TokenFormat>().is_err());
+        assert!(!TokenFormat::default().is_ear());
+        assert_eq!(TokenFormat::EarJwt.to_string(), "ear_jwt");
+    }
+}
@@ -0,0 +1,316 @@
+//! Mapping of the claims of attestation results tokens.
+//!
+//! Relying parties written for another attestation service expect its
+//! token claims. A claim mapper reshapes the claims of the AS (`tcb-status`,
+//! `evaluation-report`, `tee-pubkey`...) before the token is signed, the
+//! token broker adding its own claims (`iss`, `exp`, `jti`...) after. The
+//! mapper is set in the `claim_mapper` of `attestation_token_config`:
+//!
+//! ```json
+//! { "type": "ear" }
+//! { "type": "maa" }
+//! {
+//!     "type": "custom",
+//!     "claims": {
+//!         "measurement": "$['tcb-status']['snp.measurement']",
+//!         "pubkey": "$.tee-pubkey",
+//!         "policy": "$.policy_digest"
+//!     }
+//! }
+//! ```
+//!
+//! - `ear`: an [EAR](https://datatracker.ietf.org/doc/draft-fv-rats-ear/)
+//!   with one submodule named after the TEE, whose `ear.status` and
+//!   `ear.trustworthiness-vector` are derived from the claims as in
+//!   [`super::ear`]. The claims are its `ear.veraison.annotated-evidence`,
+//!   and the other claims of the AS its `ear.veraison.policy-claims`.
+//! - `maa`: the claims of Microsoft Azure Attestation tokens. Each claim is
+//!   named `x-ms-<attestation type>-<claim>`, e.g.
+//!   `x-ms-sevsnpvm-measurement` for `snp.measurement`, with the dots of its
+//!   name as dashes. The other claims of the AS are kept.
+//! - `custom`: only the claims of the `claims` rules, by claim name. A rule
+//!   is a path into the claims of the AS: `$` then `.name`, `['name']` for
+//!   names with dots, or `[index]`. Rules that select nothing are left out.
+//!
+//! Token chaining compares the `tcb-status` of the previous token, or the
+//! annotated evidence of an EAR, so it needs a `custom` mapping that keeps
+//! it.
+
+use anyhow::*;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+use super::ear;
+
+/// `eat_profile` of EARs.
+const EAR_PROFILE: &str = "tag:github.com,2023:veraison/ear";
+
+/// Reshape the claims of the tokens.
+pub trait ClaimMapper {
+    /// Map the `claims` of the token of evidence of `tee`.
+    fn map(&self, tee: &str, claims: Value) -> Result<Value>;
+}
+
+/// Configuration of the built-in claim mappers.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ClaimMapperConfig {
+    Ear,
+    Maa,
+    Custom { claims: BTreeMap<String, String> },
+}
+
+impl ClaimMapperConfig {
+    pub fn to_claim_mapper(&self) -> Result<Box<dyn ClaimMapper + Send + Sync>> {
+        match self {
+            ClaimMapperConfig::Ear => Ok(Box::new(EarMapper) as Box<dyn ClaimMapper + Send + Sync>),
+            ClaimMapperConfig::Maa => Ok(Box::new(MaaMapper) as Box<dyn ClaimMapper + Send + Sync>),
+            ClaimMapperConfig::Custom { claims } => {
+                let rules = claims
+                    .iter()
+                    .map(|(name, path)| {
+                        let path = parse_path(path)
+                            .with_context(|| format!("Invalid rule of claim {name}"))?;
+                        Ok((name.clone(), path))
+                    })
+                    .collect::<Result<_>>()?;
+                Ok(Box::new(CustomMapper { rules }) as Box<dyn ClaimMapper + Send + Sync>)
+            }
+        }
+    }
+}
+
+/// Take the claims of the AS the profiles map out of `claims`.
+fn take(claims: &mut Map<String, Value>, name: &str) -> Value {
+    claims.remove(name).unwrap_or(Value::Null)
+}
+
+pub(super) struct EarMapper;
+
+impl ClaimMapper for EarMapper {
+    fn map(&self, tee: &str, claims: Value) -> Result<Value> {
+        let Value::Object(mut claims) = claims else {
+            bail!("Token claims are not an object");
+        };
+        let (status, vector) = ear::appraisal(tee, &claims);
+        let tcb_status = take(&mut claims, "tcb-status");
+        let tee_pubkey = take(&mut claims, "tee-pubkey");
+        let policy_digest = take(&mut claims, "policy_digest");
+
+        let mut submod = json!({
+            "ear.status": status,
+            "ear.trustworthiness-vector": vector,
+            "ear.veraison.annotated-evidence": tcb_status,
+            "ear.veraison.policy-claims": claims,
+            "ear.veraison.key-attestation": { "akpub": tee_pubkey },
+        });
+        if let Some(digest) = policy_digest.as_str() {
+            submod["ear.appraisal-policy-id"] = format!("sha256:{digest}").into();
+        }
+        Ok(json!({
+            "eat_profile": EAR_PROFILE,
+            "iat": chrono::Utc::now().timestamp(),
+            "ear.verifier-id": {
+                "developer": "https://confidentialcontainers.org",
+                "build": concat!("attestation-service ", env!("CARGO_PKG_VERSION")),
+            },
+            "submods": { tee: submod },
+        }))
+    }
+}
+
+struct MaaMapper;
+
+impl MaaMapper {
+    /// The `x-ms-attestation-type` of the evidence of `tee`.
+    fn attestation_type(tee: &str) -> &str {
+        match tee {
+            "snp" | "azsnpvtpm" => "sevsnpvm",
+            "tdx" => "tdxvm",
+            tee => tee,
+        }
+    }
+}
+
+impl ClaimMapper for MaaMapper {
+    fn map(&self, tee: &str, claims: Value) -> Result<Value> {
+        let Value::Object(mut claims) = claims else {
+            bail!("Token claims are not an object");
+        };
+        let attestation_type = Self::attestation_type(tee);
+        let tcb_status = take(&mut claims, "tcb-status");
+        let tee_pubkey = take(&mut claims, "tee-pubkey");
+        let policy_digest = take(&mut claims, "policy_digest");
+
+        claims.insert("x-ms-ver".to_string(), "1.0".into());
+        claims.insert("x-ms-attestation-type".to_string(), attestation_type.into());
+        claims.insert("x-ms-policy-hash".to_string(), policy_digest);
+        claims.insert("x-ms-runtime".to_string(), json!({ "keys": [tee_pubkey] }));
+        for (name, value) in tcb_status.as_object().into_iter().flatten() {
+            let name = name.split_once('.').map_or(name.as_str(), |(_, name)| name);
+            claims.insert(
+                format!("x-ms-{attestation_type}-{}", name.replace('.', "-")),
+                value.clone(),
+            );
+        }
+        Ok(Value::Object(claims))
+    }
+}
+
+/// A step of a path into the claims.
+#[derive(Debug, PartialEq, Eq)]
+enum Step {
+    Name(String),
+    Index(usize),
+}
+
+/// Parse a path like `$.tee-pubkey` or `$['tcb-status']['snp.measurement']`.
+fn parse_path(path: &str) -> Result<Vec<Step>> {
+    let mut rest = path
+        .strip_prefix('$')
+        .ok_or_else(|| anyhow!("{path} does not start with `$`"))?;
+    let mut steps = Vec::new();
+    while !rest.is_empty() {
+        if let Some(name) = rest.strip_prefix('.') {
+            let end = name.find(['.', '[', ']']).unwrap_or(name.len());
+            if end == 0 {
+                bail!("Empty name in {path}");
+            }
+            steps.push(Step::Name(name[..end].to_string()));
+            rest = &name[end..];
+        } else if let Some(subscript) = rest.strip_prefix('[') {
+            let end = subscript
+                .find(']')
+                .ok_or_else(|| anyhow!("Unclosed `[` in {path}"))?;
+            let inner = &subscript[..end];
+            let quoted = inner
+                .strip_prefix('\'')
+                .and_then(|inner| inner.strip_suffix('\''))
+                .or_else(|| {
+                    inner
+                        .strip_prefix('"')
+                        .and_then(|inner| inner.strip_suffix('"'))
+                });
+            steps.push(match quoted {
+                Some(name) => Step::Name(name.to_string()),
+                None => Step::Index(
+                    inner
+                        .parse()
+                        .with_context(|| format!("Invalid subscript [{inner}] in {path}"))?,
+                ),
+            });
+            rest = &subscript[end + 1..];
+        } else {
+            bail!("Unexpected `{rest}` in {path}");
+        }
+    }
+    Ok(steps)
+}
+
+struct CustomMapper {
+    rules: Vec<(String, Vec<Step>)>,
+}
+
+impl ClaimMapper for CustomMapper {
+    fn map(&self, _tee: &str, claims: Value) -> Result<Value> {
+        let mut mapped = Map::new();
+        for (name, path) in &self.rules {
+            let value = path.iter().try_fold(&claims, |value, step| match step {
+                Step::Name(name) => value.get(name),
+                Step::Index(index) => value.get(index),
+            });
+            if let Some(value) = value {
+                mapped.insert(name.clone(), value.clone());
+            }
+        }
+        Ok(Value::Object(mapped))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims() -> Value {
+        json!({
+            "tee-pubkey": { "kty": "RSA", "alg": "RSA1_5", "n": "AQAB", "e": "AQAB" },
+            "tcb-status": {
+                "snp.measurement": "AAAA",
+                "snp.policy.smt_allowed": 1,
+            },
+            "evaluation-report": "{}",
+            "policy_digest": "00ff",
+            "claims-schema": "v2",
+        })
+    }
+
+    #[test]
+    fn test_ear() {
+        let mapper = ClaimMapperConfig::Ear.to_claim_mapper().unwrap();
+        let ear = mapper.map("snp", claims()).unwrap();
+        assert_eq!(ear["eat_profile"], EAR_PROFILE);
+        let submod = &ear["submods"]["snp"];
+        assert_eq!(submod["ear.status"], "affirming");
+        assert_eq!(submod["ear.trustworthiness-vector"]["instance-identity"], 2);
+        assert_eq!(submod["ear.appraisal-policy-id"], "sha256:00ff");
+        assert_eq!(
+            submod["ear.veraison.annotated-evidence"]["snp.measurement"],
+            "AAAA"
+        );
+        assert_eq!(submod["ear.veraison.policy-claims"]["claims-schema"], "v2");
+        assert_eq!(
+            submod["ear.veraison.key-attestation"]["akpub"]["kty"],
+            "RSA"
+        );
+
+        let mut claims = claims();
+        claims["warnings"] = json!([{ "code": "collateral-near-expiry" }]);
+        let ear = mapper.map("snp", claims).unwrap();
+        assert_eq!(ear["submods"]["snp"]["ear.status"], "warning");
+    }
+
+    #[test]
+    fn test_maa() {
+        let mapper = ClaimMapperConfig::Maa.to_claim_mapper().unwrap();
+        let maa = mapper.map("snp", claims()).unwrap();
+        assert_eq!(maa["x-ms-attestation-type"], "sevsnpvm");
+        assert_eq!(maa["x-ms-policy-hash"], "00ff");
+        assert_eq!(maa["x-ms-sevsnpvm-measurement"], "AAAA");
+        assert_eq!(maa["x-ms-sevsnpvm-policy-smt_allowed"], 1);
+        assert_eq!(maa["x-ms-runtime"]["keys"][0]["kty"], "RSA");
+        assert_eq!(maa["claims-schema"], "v2");
+        assert!(maa.get("tcb-status").is_none());
+    }
+
+    #[test]
+    fn test_custom() {
+        let config: ClaimMapperConfig = serde_json::from_value(json!({
+            "type": "custom",
+            "claims": {
+                "measurement": "$['tcb-status']['snp.measurement']",
+                "key-type": "$.tee-pubkey.kty",
+                "missing": "$.tcb-status[0]",
+            }
+        }))
+        .unwrap();
+        let mapped = config
+            .to_claim_mapper()
+            .unwrap()
+            .map("snp", claims())
+            .unwrap();
+        assert_eq!(mapped, json!({ "measurement": "AAAA", "key-type": "RSA" }));
+
+        assert_eq!(
+            parse_path("$.a[\"b.c\"][2]").unwrap(),
+            vec![
+                Step::Name("a".into()),
+                Step::Name("b.c".into()),
+                Step::Index(2)
+            ]
+        );
+        for path in ["a.b", "$..a", "$[x]", "$['a'", "$.a]"] {
+            assert!(parse_path(path).is_err(), "{path}");
+        }
+    }
+}
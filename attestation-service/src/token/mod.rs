@@ -6,8 +6,14 @@
 use anyhow::*;
 use serde::Deserialize;
 use serde_json::Value;
-use strum_macros::EnumString;
+use strum_macros::{EnumString, EnumVariantNames};
 
+pub mod cbor;
+pub(crate) mod chain;
+pub mod cosign;
+pub mod ear;
+pub mod mapper;
+pub mod signer;
 mod simple;
 
 const DEFAULT_TOKEN_TIMEOUT: i64 = 5;
@@ -17,17 +23,60 @@ pub trait AttestationTokenBroker {
     /// Return base64 encoded Json Web Token.
     fn issue(&self, custom_claims: Value) -> Result<String>;
 
+    /// Issue a signed statement of the AS with custom claims, as a JWT
+    /// without expiration time, for the statements that stay true after the
+    /// tokens expire, e.g. the tree heads of the transparency log. The
+    /// custom claims have a `typ` that tells the statement from a token.
+    fn issue_statement(&self, custom_claims: Value) -> Result<String>;
+
+    /// Issue a signed attestation token with custom claims, as a
+    /// [COSE_Sign1](https://www.rfc-editor.org/rfc/rfc9052#section-4.2)
+    /// structure of CBOR encoded claims.
+    fn issue_cose(&self, custom_claims: Value) -> Result<Vec<u8>>;
+
+    /// Verify the signature of a token issued by this broker and return its
+    /// claims. The expiration time is not checked, as the broker also signs
+    /// state archives, that are imported long after, and callers that need
+    /// a valid token check it, e.g. the chaining of re-attestations.
+    fn verify(&self, token: &str) -> Result<Value>;
+
+    /// Verify the signature of a token issued by another broker, with its
+    /// public keys in JWKS format, and return its claims.
+    fn verify_with_jwks(&self, token: &str, jwks: &str) -> Result<Value>;
+
     /// Get the public keys and X.509 formatted certificate chain of the attestation token broker.
-    /// Returns the certificate chain in [JWKS format](https://www.rfc-editor.org/rfc/rfc7517#appendix-B).
+    /// Returns the certificate chain in [JWKS format](https://www.rfc-editor.org/rfc/rfc7517#appendix-B),
+    /// the active key first, followed by the other published signing keys,
+    /// see [`signer`], then the staged key if any, then the retired keys
+    /// until the tokens they signed expire.
     fn pubkey_jwks(&self) -> Result<String>;
+
+    /// Stage a token signing key given as an encrypted PKCS#8 PEM document.
+    /// Tokens are signed with the active key until the staged key is
+    /// promoted, which lets relying parties fetch the staged key first.
+    fn import_signing_key(&mut self, pkcs8_pem: &str, password: &str) -> Result<()>;
+
+    /// Make the staged key the active token signing key, and retire the
+    /// keys it replaces.
+    fn promote_signing_key(&mut self) -> Result<()>;
+
+    /// Set the lifetime of the tokens issued from now on, in minutes.
+    fn set_token_duration(&mut self, duration_min: i64);
 }
 
-#[derive(Deserialize, Debug, Clone, EnumString)]
+#[derive(Deserialize, Debug, Clone, EnumString, EnumVariantNames)]
 pub enum AttestationTokenBrokerType {
     Simple,
 }
 
 impl AttestationTokenBrokerType {
+    /// Format of the tokens the broker issues.
+    pub fn token_format(&self) -> &'static str {
+        match self {
+            AttestationTokenBrokerType::Simple => "JWT",
+        }
+    }
+
     pub fn to_token_broker(
         &self,
         config: AttestationTokenConfig,
@@ -47,6 +96,23 @@ pub struct AttestationTokenConfig {
     pub duration_min: i64,
 
     pub issuer_name: Option<String>,
+
+    /// Keys that co-sign the tokens, see [`cosign`].
+    #[serde(default)]
+    pub co_signers: Vec<cosign::CoSignerConfig>,
+
+    /// Reshapes the claims of the tokens, see [`mapper`].
+    #[serde(default)]
+    pub claim_mapper: Option<mapper::ClaimMapperConfig>,
+
+    /// Format of the tokens, see [`ear`].
+    #[serde(default)]
+    pub format: ear::TokenFormat,
+
+    /// Keys that sign the tokens, see [`signer`]. A key generated when the
+    /// AS starts if none.
+    #[serde(default)]
+    pub signing_keys: Vec<signer::SigningKeyConfig>,
 }
 
 impl Default for AttestationTokenConfig {
@@ -54,6 +120,10 @@ impl Default for AttestationTokenConfig {
         Self {
             duration_min: DEFAULT_TOKEN_TIMEOUT,
             issuer_name: None,
+            co_signers: Vec::new(),
+            claim_mapper: None,
+            format: ear::TokenFormat::default(),
+            signing_keys: Vec::new(),
         }
     }
 }
@@ -0,0 +1,223 @@
+//! Token signing keys of Google Cloud KMS.
+//!
+//! The key version must be of a raw PKCS#1 RSA signing algorithm, e.g.
+//! `RSA_SIGN_RAW_PKCS1_3072`: Cloud KMS has no RSA PKCS#1 SHA-384
+//! algorithm, so the AS hashes the payload and gives KMS the `DigestInfo`
+//! of its SHA-384 digest to sign, which makes RS384 signatures. The KMS API
+//! is called with the OAuth2 access token of the `access_token_file`, read
+//! on every signature so that it can be refreshed by another process, or
+//! else with the token of the service account of the instance, from its
+//! metadata server.
+
+use anyhow::*;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::sha2::{Digest, Sha384};
+use rsa::RsaPublicKey;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+use super::TokenSigner;
+
+const DEFAULT_ENDPOINT: &str = "https://cloudkms.googleapis.com";
+
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+/// How long a KMS request may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// DER prefix of the `DigestInfo` of a SHA-384 digest, see
+/// [RFC 8017](https://www.rfc-editor.org/rfc/rfc8017#section-9.2).
+const SHA384_DIGEST_INFO_PREFIX: [u8; 19] = [
+    0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02, 0x05,
+    0x00, 0x04, 0x30,
+];
+
+/// The `DigestInfo` of the SHA-384 digest of `payload`, that PKCS#1 v1.5
+/// signs.
+fn digest_info(payload: &[u8]) -> Vec<u8> {
+    [&SHA384_DIGEST_INFO_PREFIX[..], &Sha384::digest(payload)].concat()
+}
+
+/// A key version of Cloud KMS, called through the REST API.
+pub struct KmsSigner {
+    /// `<endpoint>/v1/<key>`.
+    url: String,
+    access_token_file: Option<PathBuf>,
+    public_key: RsaPublicKey,
+    client: reqwest::Client,
+    /// Runs the requests, as signing is synchronous and may be called on a
+    /// runtime thread as well as out of any runtime.
+    runtime: BackgroundRuntime,
+}
+
+/// A runtime that can be dropped on the thread of another runtime, where
+/// blocking until its tasks are done is not allowed.
+struct BackgroundRuntime(Option<Runtime>);
+
+impl BackgroundRuntime {
+    /// Run `request`, and wait for it.
+    fn run(&self, request: impl Future<Output = Result<Value>> + Send + 'static) -> Result<Value> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.0
+            .as_ref()
+            .ok_or_else(|| anyhow!("The KMS signer is shut down"))?
+            .spawn(async move {
+                let _ = sender.send(request.await);
+            });
+        receiver
+            .recv()
+            .map_err(|_| anyhow!("The KMS request was aborted"))?
+    }
+}
+
+impl Drop for BackgroundRuntime {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+impl KmsSigner {
+    pub fn new(
+        key: &str,
+        endpoint: Option<&str>,
+        access_token_file: Option<PathBuf>,
+    ) -> Result<Self> {
+        let runtime = BackgroundRuntime(Some(
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .thread_name("kms-signer")
+                .enable_all()
+                .build()?,
+        ));
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+        let url = format!(
+            "{}/v1/{key}",
+            endpoint.unwrap_or(DEFAULT_ENDPOINT).trim_end_matches('/')
+        );
+
+        let response = runtime
+            .run(request(
+                &client,
+                format!("{url}/publicKey"),
+                access_token_file.clone(),
+                None,
+            ))
+            .with_context(|| format!("get the public key of KMS key {key}"))?;
+        let algorithm = response["algorithm"].as_str().unwrap_or_default();
+        if !algorithm.starts_with("RSA_SIGN_RAW_PKCS1_") {
+            bail!("KMS key {key} is a {algorithm} key, not a RSA_SIGN_RAW_PKCS1 one");
+        }
+        let public_key = response["pem"]
+            .as_str()
+            .ok_or_else(|| anyhow!("KMS key {key} has no PEM public key"))?;
+        let public_key = RsaPublicKey::from_public_key_pem(public_key)
+            .map_err(|e| anyhow!("parse public key of KMS key {key}: {e}"))?;
+
+        Ok(Self {
+            url,
+            access_token_file,
+            public_key,
+            client,
+            runtime,
+        })
+    }
+}
+
+/// A request of the KMS API to `url`, a POST of the JSON `body` if any, a
+/// GET otherwise.
+fn request(
+    client: &reqwest::Client,
+    url: String,
+    access_token_file: Option<PathBuf>,
+    body: Option<Value>,
+) -> impl Future<Output = Result<Value>> + Send + 'static {
+    let client = client.clone();
+    async move {
+        let access_token = access_token(&client, access_token_file).await?;
+        let request = match body {
+            Some(body) => client.post(&url).json(&body),
+            None => client.get(&url),
+        };
+        let response = request.bearer_auth(access_token).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            bail!("KMS answered {status}: {}", response.text().await?);
+        }
+        Ok(response.json().await?)
+    }
+}
+
+/// The OAuth2 access token of the KMS API.
+async fn access_token(client: &reqwest::Client, file: Option<PathBuf>) -> Result<String> {
+    if let Some(file) = file {
+        let token = std::fs::read_to_string(&file)
+            .with_context(|| format!("read KMS access token {}", file.display()))?;
+        return Ok(token.trim().to_string());
+    }
+    let response: Value = client
+        .get(METADATA_TOKEN_URL)
+        .header("Metadata-Flavor", "Google")
+        .send()
+        .await
+        .context("get the access token of the instance")?
+        .error_for_status()?
+        .json()
+        .await?;
+    response["access_token"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("The metadata server gave no access token"))
+}
+
+impl TokenSigner for KmsSigner {
+    fn public_key(&self) -> RsaPublicKey {
+        self.public_key.clone()
+    }
+
+    fn sign(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        let body = json!({ "data": STANDARD.encode(digest_info(payload)) });
+        let request = request(
+            &self.client,
+            format!("{}:asymmetricSign", self.url),
+            self.access_token_file.clone(),
+            Some(body),
+        );
+        let response = self.runtime.run(request).context("KMS signature failed")?;
+        let signature = response["signature"]
+            .as_str()
+            .ok_or_else(|| anyhow!("KMS answered no signature"))?;
+        Ok(STANDARD.decode(signature)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs1v15::{Signature, VerifyingKey};
+    use rsa::signature::Verifier;
+    use rsa::{Pkcs1v15Sign, RsaPrivateKey};
+
+    #[test]
+    fn test_digest_info() {
+        // What a raw PKCS#1 KMS key makes of the digest info is an RS384
+        // signature of the payload.
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let payload = b"header.claims";
+        let signature = key
+            .sign(Pkcs1v15Sign::new_unprefixed(), &digest_info(payload))
+            .unwrap();
+        VerifyingKey::<Sha384>::new(key.to_public_key())
+            .verify(payload, &Signature::try_from(&signature[..]).unwrap())
+            .unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+//! Token signing keys.
+//!
+//! Without `signing_keys` in `attestation_token_config`, tokens are signed
+//! with an RSA key generated when the AS starts. Otherwise they are signed
+//! with the configured keys, that can be held by an HSM or a cloud KMS so
+//! that they never reach the AS:
+//!
+//! ```json
+//! "signing_keys": [
+//!     { "type": "file", "path": "/etc/as/token-2024.pem", "not_after": "2025-01-15T00:00:00Z" },
+//!     {
+//!         "type": "pkcs11", "module": "/usr/lib/softhsm/libsofthsm2.so",
+//!         "token": "as", "pin": "1234", "label": "token-2025",
+//!         "not_before": "2025-01-01T00:00:00Z"
+//!     },
+//!     { "type": "kms", "key": "projects/p/locations/l/keyRings/as/cryptoKeys/token/cryptoKeyVersions/1" }
+//! ]
+//! ```
+//!
+//! Every key is an RSA key of at least 2048 bits, signing with RS384, and
+//! can have a validity period. Tokens are signed by the last key of the
+//! list that is valid. A key is published in the JWKS of the AS from the
+//! start, even before it is valid, so that relying parties can fetch it
+//! before it signs, until the tokens it signed have expired. Keys are so
+//! rotated with overlapping validity: a new key is added with a
+//! `not_before` in the future, and the old key gets the same `not_after`.
+
+use anyhow::*;
+use chrono::{DateTime, Duration, Utc};
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::sha2::Sha384;
+use rsa::signature::{RandomizedSigner, SignatureEncoding};
+use rsa::traits::PublicKeyParts;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+mod kms;
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
+
+/// Smallest RSA key that signs tokens.
+pub const MIN_RSA_KEY_BITS: usize = 2048;
+
+/// An RSA key that signs tokens with RS384.
+pub trait TokenSigner {
+    fn public_key(&self) -> RsaPublicKey;
+
+    /// The RSASSA-PKCS1-v1_5 SHA-384 signature of `payload`.
+    fn sign(&self, payload: &[u8]) -> Result<Vec<u8>>;
+}
+
+impl TokenSigner for RsaPrivateKey {
+    fn public_key(&self) -> RsaPublicKey {
+        self.to_public_key()
+    }
+
+    fn sign(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        let signing_key = SigningKey::<Sha384>::new(self.clone());
+        let signature = signing_key.sign_with_rng(&mut rand::thread_rng(), payload);
+        Ok(signature.to_bytes().to_vec())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SignerSource {
+    /// A PEM encoded PKCS#8 (or PKCS#1) RSA private key file.
+    File { path: PathBuf },
+
+    /// An RSA private key held by an HSM, used through its PKCS#11 module.
+    #[cfg(feature = "pkcs11")]
+    Pkcs11 {
+        module: PathBuf,
+        token: String,
+        pin: String,
+        label: String,
+    },
+
+    /// A key version of Google Cloud KMS, see [`kms`].
+    Kms {
+        /// Resource name of the key version.
+        key: String,
+        /// The KMS API, `https://cloudkms.googleapis.com` by default.
+        #[serde(default)]
+        endpoint: Option<String>,
+        /// File of the OAuth2 access token of the KMS API. The token of the
+        /// service account of the instance, from its metadata server, if
+        /// none.
+        #[serde(default)]
+        access_token_file: Option<PathBuf>,
+    },
+}
+
+/// Configuration of a token signing key.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SigningKeyConfig {
+    #[serde(flatten)]
+    pub source: SignerSource,
+    /// The key does not sign before.
+    #[serde(default)]
+    pub not_before: Option<DateTime<Utc>>,
+    /// The key does not sign after.
+    #[serde(default)]
+    pub not_after: Option<DateTime<Utc>>,
+}
+
+/// The RSA private key of a PEM file.
+fn read_key(path: &Path) -> Result<RsaPrivateKey> {
+    let pem = std::fs::read_to_string(path)
+        .with_context(|| format!("read token signing key {}", path.display()))?;
+    RsaPrivateKey::from_pkcs8_pem(&pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&pem))
+        .map_err(|e| anyhow!("parse RSA private key: {e}"))
+}
+
+impl SignerSource {
+    fn to_signer(&self) -> Result<Box<dyn TokenSigner + Send + Sync>> {
+        let signer = match self {
+            SignerSource::File { path } => {
+                Box::new(read_key(path)?) as Box<dyn TokenSigner + Send + Sync>
+            }
+            #[cfg(feature = "pkcs11")]
+            SignerSource::Pkcs11 {
+                module,
+                token,
+                pin,
+                label,
+            } => Box::new(pkcs11::Pkcs11Signer::new(module, token, pin, label)?),
+            SignerSource::Kms {
+                key,
+                endpoint,
+                access_token_file,
+            } => Box::new(kms::KmsSigner::new(
+                key,
+                endpoint.as_deref(),
+                access_token_file.clone(),
+            )?),
+        };
+        let bits = signer.public_key().size() * 8;
+        if bits < MIN_RSA_KEY_BITS {
+            bail!("RSA key of {bits} bits is too small, at least {MIN_RSA_KEY_BITS} bits are required");
+        }
+        Ok(signer)
+    }
+}
+
+/// A token signing key, with its validity period.
+pub(crate) struct ScheduledKey {
+    pub signer: Box<dyn TokenSigner + Send + Sync>,
+    not_before: Option<DateTime<Utc>>,
+    not_after: Option<DateTime<Utc>>,
+}
+
+impl ScheduledKey {
+    /// A key that is always valid.
+    pub fn unscheduled(signer: Box<dyn TokenSigner + Send + Sync>) -> Self {
+        Self {
+            signer,
+            not_before: None,
+            not_after: None,
+        }
+    }
+
+    /// Whether the key signs at `now`.
+    pub fn signs_at(&self, now: DateTime<Utc>) -> bool {
+        self.not_before.map_or(true, |not_before| not_before <= now)
+            && self.not_after.map_or(true, |not_after| now < not_after)
+    }
+
+    /// Whether the key is published at `now`: until the last token it
+    /// signed, valid for `token_duration`, has expired.
+    pub fn published_at(&self, now: DateTime<Utc>, token_duration: Duration) -> bool {
+        self.not_after
+            .map_or(true, |not_after| now < not_after + token_duration)
+    }
+}
+
+/// Load the keys of `configs`.
+pub(crate) fn load(configs: &[SigningKeyConfig]) -> Result<Vec<ScheduledKey>> {
+    configs
+        .iter()
+        .enumerate()
+        .map(|(i, config)| {
+            if let (Some(not_before), Some(not_after)) = (config.not_before, config.not_after) {
+                if not_before >= not_after {
+                    bail!("token signing key {i} is never valid");
+                }
+            }
+            let signer = config
+                .source
+                .to_signer()
+                .with_context(|| format!("load token signing key {i}"))?;
+            Ok(ScheduledKey {
+                signer,
+                not_before: config.not_before,
+                not_after: config.not_after,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key.pem");
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), MIN_RSA_KEY_BITS).unwrap();
+        std::fs::write(&path, key.to_pkcs8_pem(LineEnding::LF).unwrap()).unwrap();
+
+        let configs: Vec<SigningKeyConfig> = serde_json::from_value(serde_json::json!([
+            { "type": "file", "path": path, "not_after": "2030-01-01T00:00:00Z" },
+        ]))
+        .unwrap();
+        let keys = load(&configs).unwrap();
+        assert_eq!(keys[0].signer.public_key(), key.to_public_key());
+
+        let small = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        std::fs::write(&path, small.to_pkcs8_pem(LineEnding::LF).unwrap()).unwrap();
+        assert!(load(&configs).is_err());
+
+        let mut config = configs[0].clone();
+        config.not_before = config.not_after;
+        assert!(load(&[config]).is_err());
+    }
+
+    #[test]
+    fn test_schedule() {
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), MIN_RSA_KEY_BITS).unwrap();
+        let now = Utc::now();
+        let key = ScheduledKey {
+            signer: Box::new(key),
+            not_before: Some(now - Duration::days(1)),
+            not_after: Some(now + Duration::days(1)),
+        };
+        assert!(key.signs_at(now));
+        assert!(!key.signs_at(now - Duration::days(2)));
+        assert!(!key.signs_at(now + Duration::days(1)));
+        assert!(key.published_at(now - Duration::days(2), Duration::minutes(5)));
+        assert!(key.published_at(
+            now + Duration::days(1) + Duration::minutes(4),
+            Duration::minutes(5)
+        ));
+        assert!(!key.published_at(now + Duration::days(1), Duration::zero()));
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::*;
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, AttributeType, ObjectClass, ObjectHandle};
+use cryptoki::session::Session;
+use rsa::{BigUint, RsaPublicKey};
+use std::path::Path;
+use std::sync::Mutex;
+
+use super::TokenSigner;
+use crate::decryption::pkcs11::login;
+
+/// An RSA token signing key that never leaves the HSM holding it.
+pub struct Pkcs11Signer {
+    // Sessions are not `Sync`.
+    session: Mutex<Session>,
+    key: ObjectHandle,
+    public_key: RsaPublicKey,
+}
+
+impl Pkcs11Signer {
+    pub fn new(module: &Path, token: &str, pin: &str, label: &str) -> Result<Self> {
+        let session = login(module, token, pin)?;
+        let key = session
+            .find_objects(&[
+                Attribute::Class(ObjectClass::PRIVATE_KEY),
+                Attribute::Label(label.as_bytes().to_vec()),
+            ])?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("private key {label} not found in PKCS#11 token {token}"))?;
+
+        let (mut n, mut e) = (None, None);
+        for attribute in session.get_attributes(
+            key,
+            &[AttributeType::Modulus, AttributeType::PublicExponent],
+        )? {
+            match attribute {
+                Attribute::Modulus(modulus) => n = Some(BigUint::from_bytes_be(&modulus)),
+                Attribute::PublicExponent(exponent) => e = Some(BigUint::from_bytes_be(&exponent)),
+                _ => {}
+            }
+        }
+        let (Some(n), Some(e)) = (n, e) else {
+            bail!("private key {label} of PKCS#11 token {token} is not an RSA key");
+        };
+
+        Ok(Self {
+            session: Mutex::new(session),
+            key,
+            public_key: RsaPublicKey::new(n, e)?,
+        })
+    }
+}
+
+impl TokenSigner for Pkcs11Signer {
+    fn public_key(&self) -> RsaPublicKey {
+        self.public_key.clone()
+    }
+
+    fn sign(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        self.session
+            .lock()
+            .map_err(|_| anyhow!("PKCS#11 session lock poisoned"))?
+            .sign(&Mechanism::Sha384RsaPkcs, self.key, payload)
+            .map_err(|e| anyhow!("RS384 signature failed: {e}"))
+    }
+}
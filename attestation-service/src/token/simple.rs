@@ -5,62 +5,106 @@
 use anyhow::*;
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine;
-use rsa::pkcs1v15::SigningKey;
-use rsa::sha2::Sha384;
-use rsa::signature::{RandomizedSigner, SignatureEncoding};
+use rsa::pkcs1v15::{Signature, VerifyingKey};
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::sha2::{Digest, Sha256, Sha384};
+use rsa::signature::Verifier;
 use rsa::traits::PublicKeyParts;
-use rsa::RsaPrivateKey;
+use rsa::{RsaPrivateKey, RsaPublicKey};
 use serde_json::{json, Value};
 
+use crate::token::cbor::Cbor;
+use crate::token::signer::{self, ScheduledKey, TokenSigner};
 use crate::token::{AttestationTokenBroker, AttestationTokenConfig};
 
 const ISSUER_NAME: &str = "CoCo-Attestation-Service";
-const RSA_KEY_BITS: usize = 2048;
+const RSA_KEY_BITS: usize = signer::MIN_RSA_KEY_BITS;
 const SIMPLE_TOKEN_ALG: &str = "RS384";
 
+/// COSE labels and values, of the IANA registry.
+const COSE_SIGN1_TAG: u64 = 18;
+const COSE_HEADER_ALG: u64 = 1;
+const COSE_HEADER_KID: u64 = 4;
+const COSE_ALG_RS384: i64 = -258;
+
 pub struct SimpleAttestationTokenBroker {
-    private_key: RsaPrivateKey,
+    /// The keys that sign tokens in their validity period, see [`signer`].
+    keys: Vec<ScheduledKey>,
+    /// Imported key that signs tokens once promoted.
+    staged_key: Option<RsaPrivateKey>,
+    /// The keys that signed tokens before a promotion, until the tokens
+    /// they signed expire. They are published and their tokens verified
+    /// until then.
+    retired_keys: Vec<RetiredKey>,
     config: AttestationTokenConfig,
 }
 
+/// A key replaced by a promotion.
+struct RetiredKey {
+    public_key: RsaPublicKey,
+    /// When the last token the key signed expires.
+    until: chrono::DateTime<chrono::Utc>,
+}
+
 impl SimpleAttestationTokenBroker {
     pub fn new(config: AttestationTokenConfig) -> Result<Self> {
-        let mut rng = rand::thread_rng();
-        let private_key = RsaPrivateKey::new(&mut rng, RSA_KEY_BITS)?;
+        let mut keys = signer::load(&config.signing_keys)?;
+        if keys.is_empty() {
+            let mut rng = rand::thread_rng();
+            let private_key = RsaPrivateKey::new(&mut rng, RSA_KEY_BITS)?;
+            keys.push(ScheduledKey::unscheduled(Box::new(private_key)));
+        }
 
         Ok(Self {
-            private_key,
+            keys,
+            staged_key: None,
+            retired_keys: Vec::new(),
             config,
         })
     }
 }
 
-impl SimpleAttestationTokenBroker {
-    fn rs384_sign(&self, payload: &[u8]) -> Result<Vec<u8>> {
-        let mut rng = rand::thread_rng();
-        let signing_key = SigningKey::<Sha384>::new(self.private_key.clone());
-        let signature = signing_key.sign_with_rng(&mut rng, payload);
-        Ok(signature.to_bytes().to_vec())
-    }
+/// JWK of a token signing key, with its
+/// [thumbprint](https://www.rfc-editor.org/rfc/rfc7638) as key ID.
+pub(super) fn jwk(key: &RsaPublicKey) -> Value {
+    let n = URL_SAFE_NO_PAD.encode(key.n().to_bytes_be());
+    let e = URL_SAFE_NO_PAD.encode(key.e().to_bytes_be());
+    let thumbprint = Sha256::digest(format!(r#"{{"e":"{e}","kty":"RSA","n":"{n}"}}"#));
+
+    json!({
+        "kty": "RSA",
+        "alg": SIMPLE_TOKEN_ALG,
+        "kid": URL_SAFE_NO_PAD.encode(thumbprint),
+        "n": n,
+        "e": e,
+    })
 }
 
-impl AttestationTokenBroker for SimpleAttestationTokenBroker {
-    fn issue(&self, custom_claims: Value) -> Result<String> {
-        let header_value = json!({
-            "typ": "JWT",
-            "alg": SIMPLE_TOKEN_ALG,
-        });
-        let header_string = serde_json::to_string(&header_value)?;
-        let header_b64 = URL_SAFE_NO_PAD.encode(header_string.as_bytes());
+impl SimpleAttestationTokenBroker {
+    /// The key that signs tokens now: the last configured key that is
+    /// valid.
+    fn active_key(&self) -> Result<&dyn TokenSigner> {
+        let now = chrono::Utc::now();
+        self.keys
+            .iter()
+            .rev()
+            .find(|key| key.signs_at(now))
+            .map(|key| &*key.signer as &dyn TokenSigner)
+            .ok_or_else(|| anyhow!("No token signing key is valid now"))
+    }
 
+    /// The claims of a token signed with the key of `jwk`: the registered
+    /// claims, then `custom_claims`.
+    fn claims(&self, jwk: Value, custom_claims: Value) -> Result<Value> {
         let now = time::OffsetDateTime::now_utc();
         let exp = now + time::Duration::minutes(self.config.duration_min);
 
         let mut claims = json!({
             "iss": ISSUER_NAME,
-            "jwk": serde_json::from_str::<Value>(&self.pubkey_jwks()?)?["keys"][0].clone(),
+            "jwk": jwk,
             "nbf": now.unix_timestamp(),
             "exp": exp.unix_timestamp(),
+            "jti": uuid::Uuid::new_v4().to_string(),
         })
         .as_object()
         .ok_or_else(|| anyhow!("Internal Error: generate claims failed"))?
@@ -73,34 +117,422 @@ impl AttestationTokenBroker for SimpleAttestationTokenBroker {
                 .to_owned(),
         );
 
-        let claims_value = Value::Object(claims);
+        Ok(Value::Object(claims))
+    }
+
+    /// The public keys that verify the tokens of this broker.
+    fn verifying_keys(&self) -> Vec<RsaPublicKey> {
+        self.keys
+            .iter()
+            .map(|key| key.signer.public_key())
+            .chain(self.retired_public_keys(chrono::Utc::now()))
+            .collect()
+    }
+
+    /// The retired keys whose tokens are still valid `now`.
+    fn retired_public_keys(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> impl Iterator<Item = RsaPublicKey> + '_ {
+        self.retired_keys
+            .iter()
+            .filter(move |key| key.until > now)
+            .map(|key| key.public_key.clone())
+    }
+}
+
+fn rs384_verify(keys: Vec<RsaPublicKey>, payload: &[u8], signature: &[u8]) -> Result<()> {
+    let signature = Signature::try_from(signature)?;
+    keys.into_iter()
+        .map(VerifyingKey::<Sha384>::new)
+        .find_map(|key| key.verify(payload, &signature).ok())
+        .ok_or_else(|| anyhow!("Token signature verification failed"))
+}
+
+/// Verify the signature of `token` with one of `keys`, and return its
+/// claims.
+fn verify_token(token: &str, keys: Vec<RsaPublicKey>) -> Result<Value> {
+    let (signature_payload, signature_b64) = token
+        .rsplit_once('.')
+        .ok_or_else(|| anyhow!("Malformed token"))?;
+    let (header_b64, claims_b64) = signature_payload
+        .split_once('.')
+        .ok_or_else(|| anyhow!("Malformed token"))?;
+
+    let header: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header_b64)?)?;
+    if header["alg"] != SIMPLE_TOKEN_ALG {
+        bail!("Unsupported token algorithm {}", header["alg"]);
+    }
+    rs384_verify(
+        keys,
+        signature_payload.as_bytes(),
+        &URL_SAFE_NO_PAD.decode(signature_b64)?,
+    )?;
+
+    Ok(serde_json::from_slice(
+        &URL_SAFE_NO_PAD.decode(claims_b64)?,
+    )?)
+}
+
+/// The RSA keys of a JWKS.
+fn jwks_keys(jwks: &str) -> Result<Vec<RsaPublicKey>> {
+    let jwks: Value = serde_json::from_str(jwks).context("Malformed JWKS")?;
+    let keys = jwks["keys"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Malformed JWKS: no keys"))?;
+    keys.iter()
+        .filter(|key| key["kty"] == "RSA")
+        .map(|key| {
+            let component = |name: &str| -> Result<rsa::BigUint> {
+                let value = key[name]
+                    .as_str()
+                    .ok_or_else(|| anyhow!("Malformed JWK: no `{name}`"))?;
+                Ok(rsa::BigUint::from_bytes_be(&URL_SAFE_NO_PAD.decode(value)?))
+            };
+            Ok(RsaPublicKey::new(component("n")?, component("e")?)?)
+        })
+        .collect()
+}
+
+impl SimpleAttestationTokenBroker {
+    /// A JWT of `custom_claims`, with an expiration time unless it is a
+    /// statement.
+    fn issue_jwt(&self, custom_claims: Value, expires: bool) -> Result<String> {
+        let key = self.active_key()?;
+        let jwk = jwk(&key.public_key());
+        let header_value = json!({
+            "typ": "JWT",
+            "alg": SIMPLE_TOKEN_ALG,
+            "kid": jwk["kid"],
+        });
+        let header_string = serde_json::to_string(&header_value)?;
+        let header_b64 = URL_SAFE_NO_PAD.encode(header_string.as_bytes());
+
+        let mut claims_value = self.claims(jwk, custom_claims)?;
+        if !expires {
+            claims_value
+                .as_object_mut()
+                .ok_or_else(|| anyhow!("Internal Error: generate claims failed"))?
+                .remove("exp");
+        }
         let claims_string = serde_json::to_string(&claims_value)?;
         let claims_b64 = URL_SAFE_NO_PAD.encode(claims_string.as_bytes());
 
         let signature_payload = format!("{header_b64}.{claims_b64}");
-        let signature = self.rs384_sign(signature_payload.as_bytes())?;
+        let signature = key.sign(signature_payload.as_bytes())?;
         let signature_b64 = URL_SAFE_NO_PAD.encode(signature);
 
         let token = format!("{signature_payload}.{signature_b64}");
 
         Ok(token)
     }
+}
 
-    fn pubkey_jwks(&self) -> Result<String> {
-        let pubkey = self.private_key.to_public_key();
-        let n = pubkey.n().to_bytes_be();
-        let e = pubkey.e().to_bytes_be();
+impl AttestationTokenBroker for SimpleAttestationTokenBroker {
+    fn issue(&self, custom_claims: Value) -> Result<String> {
+        self.issue_jwt(custom_claims, true)
+    }
 
-        let jwk = json!({
-            "kty": "RSA",
-            "alg": SIMPLE_TOKEN_ALG,
-            "n": URL_SAFE_NO_PAD.encode(n),
-            "e": URL_SAFE_NO_PAD.encode(e),
-        });
+    fn issue_statement(&self, custom_claims: Value) -> Result<String> {
+        self.issue_jwt(custom_claims, false)
+    }
+
+    fn issue_cose(&self, custom_claims: Value) -> Result<Vec<u8>> {
+        let key = self.active_key()?;
+        let jwk = jwk(&key.public_key());
+        let kid = jwk["kid"].as_str().unwrap_or_default().as_bytes().to_vec();
+        let protected = Cbor::Map(vec![
+            (Cbor::Uint(COSE_HEADER_ALG), Cbor::Int(COSE_ALG_RS384)),
+            (Cbor::Uint(COSE_HEADER_KID), Cbor::Bytes(kid)),
+        ])
+        .encode();
+        let payload = Cbor::from(&self.claims(jwk, custom_claims)?).encode();
+
+        let sig_structure = Cbor::Array(vec![
+            Cbor::Text("Signature1".to_string()),
+            Cbor::Bytes(protected.clone()),
+            Cbor::Bytes(Vec::new()),
+            Cbor::Bytes(payload.clone()),
+        ]);
+        let signature = key.sign(&sig_structure.encode())?;
+
+        let sign1 = Cbor::Array(vec![
+            Cbor::Bytes(protected),
+            Cbor::Map(Vec::new()),
+            Cbor::Bytes(payload),
+            Cbor::Bytes(signature),
+        ]);
+        Ok(Cbor::Tag(COSE_SIGN1_TAG, Box::new(sign1)).encode())
+    }
+
+    fn verify(&self, token: &str) -> Result<Value> {
+        verify_token(token, self.verifying_keys())
+    }
+
+    fn verify_with_jwks(&self, token: &str, jwks: &str) -> Result<Value> {
+        verify_token(token, jwks_keys(jwks)?)
+    }
+
+    fn pubkey_jwks(&self) -> Result<String> {
+        let now = chrono::Utc::now();
+        let token_duration = chrono::Duration::minutes(self.config.duration_min);
+        let mut keys: Vec<RsaPublicKey> = self
+            .active_key()
+            .map(|key| key.public_key())
+            .into_iter()
+            .collect();
+        for key in &self.keys {
+            let public_key = key.signer.public_key();
+            if key.published_at(now, token_duration) && !keys.contains(&public_key) {
+                keys.push(public_key);
+            }
+        }
+        keys.extend(self.staged_key.iter().map(RsaPrivateKey::to_public_key));
+        for public_key in self.retired_public_keys(now) {
+            if !keys.contains(&public_key) {
+                keys.push(public_key);
+            }
+        }
+        let keys: Vec<Value> = keys.iter().map(jwk).collect();
         let jwks = json!({
-            "keys": vec![jwk],
+            "keys": keys,
         });
 
         Ok(serde_json::to_string(&jwks)?)
     }
+
+    fn import_signing_key(&mut self, pkcs8_pem: &str, password: &str) -> Result<()> {
+        let key = RsaPrivateKey::from_pkcs8_encrypted_pem(pkcs8_pem, password)
+            .map_err(|e| anyhow!("Failed to decrypt RSA private key: {e}"))?;
+        if key.size() * 8 < RSA_KEY_BITS {
+            bail!(
+                "RSA key of {} bits is too small, at least {RSA_KEY_BITS} bits are required",
+                key.size() * 8
+            );
+        }
+        key.validate()?;
+
+        self.staged_key = Some(key);
+        Ok(())
+    }
+
+    fn promote_signing_key(&mut self) -> Result<()> {
+        let staged_key = self
+            .staged_key
+            .take()
+            .ok_or_else(|| anyhow!("No signing key is staged"))?;
+        let staged_key = ScheduledKey::unscheduled(Box::new(staged_key));
+        let retired_keys = std::mem::replace(&mut self.keys, vec![staged_key]);
+        // Every generation whose tokens may still be valid is kept.
+        let now = chrono::Utc::now();
+        let until = now + chrono::Duration::minutes(self.config.duration_min);
+        self.retired_keys.retain(|key| key.until > now);
+        self.retired_keys
+            .extend(retired_keys.iter().map(|key| RetiredKey {
+                public_key: key.signer.public_key(),
+                until,
+            }));
+        Ok(())
+    }
+
+    fn set_token_duration(&mut self, duration_min: i64) {
+        self.config.duration_min = duration_min;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_and_verify() {
+        let broker = SimpleAttestationTokenBroker::new(AttestationTokenConfig::default()).unwrap();
+        let token = broker.issue(json!({ "tcb-status": { "svn": 1 } })).unwrap();
+
+        let claims = broker.verify(&token).unwrap();
+        assert_eq!(claims["tcb-status"]["svn"], 1);
+        assert!(claims["jti"].is_string());
+
+        let tampered = token.replacen('.', ".e30", 1);
+        assert!(broker.verify(&tampered).is_err());
+
+        let other = SimpleAttestationTokenBroker::new(AttestationTokenConfig::default()).unwrap();
+        assert!(other.verify(&token).is_err());
+
+        // With the JWKS of the issuer.
+        let jwks = broker.pubkey_jwks().unwrap();
+        let claims = other.verify_with_jwks(&token, &jwks).unwrap();
+        assert_eq!(claims["tcb-status"]["svn"], 1);
+        assert!(other
+            .verify_with_jwks(&token, &other.pubkey_jwks().unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_issue_statement() {
+        let broker = SimpleAttestationTokenBroker::new(AttestationTokenConfig::default()).unwrap();
+        let statement = broker
+            .issue_statement(json!({ "typ": "as-tree-head+jwt", "tree_size": 7 }))
+            .unwrap();
+
+        let claims = broker.verify(&statement).unwrap();
+        assert_eq!(claims["typ"], "as-tree-head+jwt");
+        assert_eq!(claims["tree_size"], 7);
+        assert!(claims["nbf"].is_i64());
+        assert!(claims.get("exp").is_none());
+    }
+
+    /// Split the byte string at the start of `bytes` off the rest.
+    fn byte_string(bytes: &[u8]) -> (&[u8], &[u8]) {
+        assert_eq!(bytes[0] >> 5, 2);
+        let (len, bytes) = match bytes[0] & 0x1f {
+            len @ 0..=23 => (len as usize, &bytes[1..]),
+            24 => (bytes[1] as usize, &bytes[2..]),
+            25 => (
+                u16::from_be_bytes([bytes[1], bytes[2]]) as usize,
+                &bytes[3..],
+            ),
+            _ => unreachable!(),
+        };
+        bytes.split_at(len)
+    }
+
+    #[test]
+    fn test_issue_cose() {
+        let broker = SimpleAttestationTokenBroker::new(AttestationTokenConfig::default()).unwrap();
+        let token = broker.issue_cose(json!({ "eat_profile": "ear" })).unwrap();
+
+        // Tag 18, of an array of 4 items.
+        assert_eq!(&token[..2], [0xd2, 0x84]);
+        let (protected, rest) = byte_string(&token[2..]);
+        let kid = jwk(&broker.active_key().unwrap().public_key())["kid"].clone();
+        let expected = Cbor::Map(vec![
+            (Cbor::Uint(COSE_HEADER_ALG), Cbor::Int(COSE_ALG_RS384)),
+            (
+                Cbor::Uint(COSE_HEADER_KID),
+                Cbor::Bytes(kid.as_str().unwrap().as_bytes().to_vec()),
+            ),
+        ]);
+        assert_eq!(protected, expected.encode());
+        // No unprotected headers.
+        assert_eq!(rest[0], 0xa0);
+        let (payload, rest) = byte_string(&rest[1..]);
+        let claim = Cbor::Text("eat_profile".to_string()).encode();
+        assert!(payload.windows(claim.len()).any(|window| window == claim));
+        let (signature, rest) = byte_string(rest);
+        assert!(rest.is_empty());
+
+        let sig_structure = Cbor::Array(vec![
+            Cbor::Text("Signature1".to_string()),
+            Cbor::Bytes(protected.to_vec()),
+            Cbor::Bytes(Vec::new()),
+            Cbor::Bytes(payload.to_vec()),
+        ]);
+        rs384_verify(broker.verifying_keys(), &sig_structure.encode(), signature).unwrap();
+        let other = SimpleAttestationTokenBroker::new(AttestationTokenConfig::default()).unwrap();
+        assert!(rs384_verify(other.verifying_keys(), &sig_structure.encode(), signature).is_err());
+    }
+
+    #[test]
+    fn test_scheduled_signing_keys() {
+        use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+
+        let dir = tempfile::tempdir().unwrap();
+        let now = chrono::Utc::now();
+        let mut config = AttestationTokenConfig::default();
+        let mut keys = Vec::new();
+        for (i, (not_before, not_after)) in [
+            (None, Some(now - chrono::Duration::hours(1))),
+            (None, None),
+            (Some(now + chrono::Duration::hours(1)), None),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let key = RsaPrivateKey::new(&mut rand::thread_rng(), RSA_KEY_BITS).unwrap();
+            let path = dir.path().join(format!("key-{i}.pem"));
+            std::fs::write(&path, key.to_pkcs8_pem(LineEnding::LF).unwrap()).unwrap();
+            config.signing_keys.push(signer::SigningKeyConfig {
+                source: signer::SignerSource::File { path },
+                not_before,
+                not_after,
+            });
+            keys.push(jwk(&key.to_public_key()));
+        }
+        let broker = SimpleAttestationTokenBroker::new(config).unwrap();
+
+        // The expired key is not published, the next one is, but the
+        // current one signs.
+        let jwks: Value = serde_json::from_str(&broker.pubkey_jwks().unwrap()).unwrap();
+        assert_eq!(jwks["keys"], json!([keys[1], keys[2]]));
+        let claims = broker.verify(&broker.issue(json!({})).unwrap()).unwrap();
+        assert_eq!(claims["jwk"], keys[1]);
+    }
+
+    fn encrypted_pkcs8_pem(key: &RsaPrivateKey, password: &str) -> String {
+        use rsa::pkcs8::{pkcs5, EncodePrivateKey, LineEnding, PrivateKeyInfo};
+
+        // Few iterations, to keep the test fast.
+        let params =
+            pkcs5::pbes2::Parameters::pbkdf2_sha256_aes256cbc(1000, &[0; 16], &[0; 16]).unwrap();
+        let document = key.to_pkcs8_der().unwrap();
+        PrivateKeyInfo::try_from(document.as_bytes())
+            .unwrap()
+            .encrypt_with_params(params, password)
+            .unwrap()
+            .to_pem("ENCRYPTED PRIVATE KEY", LineEnding::LF)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_signing_key_rotation() {
+        let mut broker =
+            SimpleAttestationTokenBroker::new(AttestationTokenConfig::default()).unwrap();
+        assert!(broker.promote_signing_key().is_err());
+        let old_token = broker.issue(json!({})).unwrap();
+
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), RSA_KEY_BITS).unwrap();
+        let pem = encrypted_pkcs8_pem(&key, "secret");
+        assert!(broker.import_signing_key(&pem, "wrong").is_err());
+        broker.import_signing_key(&pem, "secret").unwrap();
+
+        // The staged key is published, but does not sign tokens yet.
+        let jwks: Value = serde_json::from_str(&broker.pubkey_jwks().unwrap()).unwrap();
+        let new_jwk = jwk(&key.to_public_key());
+        assert_eq!(jwks["keys"].as_array().unwrap().len(), 2);
+        assert_eq!(jwks["keys"][1], new_jwk);
+        assert_ne!(
+            broker.verify(&broker.issue(json!({})).unwrap()).unwrap()["jwk"],
+            new_jwk
+        );
+
+        let old_jwk = jwk(&broker.active_key().unwrap().public_key());
+        broker.promote_signing_key().unwrap();
+        // The old key is published until the tokens it signed expire.
+        let jwks: Value = serde_json::from_str(&broker.pubkey_jwks().unwrap()).unwrap();
+        assert_eq!(jwks["keys"], json!([new_jwk, old_jwk]));
+
+        let claims = broker.verify(&broker.issue(json!({})).unwrap()).unwrap();
+        assert_eq!(claims["jwk"], new_jwk);
+        assert!(broker.verify(&old_token).is_ok());
+
+        // Another promotion keeps both generations.
+        let newer = RsaPrivateKey::new(&mut rand::thread_rng(), RSA_KEY_BITS).unwrap();
+        broker
+            .import_signing_key(&encrypted_pkcs8_pem(&newer, "secret"), "secret")
+            .unwrap();
+        broker.promote_signing_key().unwrap();
+        let jwks: Value = serde_json::from_str(&broker.pubkey_jwks().unwrap()).unwrap();
+        let newer_jwk = jwk(&newer.to_public_key());
+        assert_eq!(jwks["keys"], json!([newer_jwk, old_jwk, new_jwk]));
+        assert!(broker.verify(&old_token).is_ok());
+
+        // Once its tokens expired, the old key is neither published nor
+        // trusted.
+        broker.retired_keys[0].until = chrono::Utc::now() - chrono::Duration::seconds(1);
+        let jwks: Value = serde_json::from_str(&broker.pubkey_jwks().unwrap()).unwrap();
+        assert_eq!(jwks["keys"], json!([newer_jwk, new_jwk]));
+        assert!(broker.verify(&old_token).is_err());
+    }
 }
@@ -0,0 +1,325 @@
+//! OpenTelemetry traces of the attestations.
+//!
+//! With `tracing` in the AS config, the AS sends a trace of every
+//! attestation to an OpenTelemetry collector, as JSON to the OTLP/HTTP
+//! `<endpoint>/v1/traces`:
+//!
+//! ```json
+//! "tracing": { "endpoint": "http://collector:4318" }
+//! ```
+//!
+//! The `attestation` span of a trace covers the evaluation, with the TEE
+//! and the error, if any, as attributes. Its child spans tell where the time
+//! goes:
+//!
+//! * `verify`: the verifier, with a child span for each stage of its
+//!   pipeline, see [`crate::verifier::pipeline`], e.g. `parse` for the quote
+//!   parsing and `collateral_verify` for the signature verification, and in
+//!   builds with the `profiling` feature, a child span of its stage for each
+//!   step the verifier times, see [`crate::verifier::profile`];
+//! * `policy`: the evaluation of each policy, with its `policy.id`;
+//! * `issuance`: the issuance of the token.
+//!
+//! Traces are sent in the background: a collector that fails or lags does
+//! not hold attestations back, and its traces are lost.
+
+use crate::verifier::profile::TimedStep;
+use crate::verifier::trace::VerificationTrace;
+use anyhow::{bail, Context, Result};
+use rand::RngCore;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Timeout of the requests to the collector.
+const OTLP_TIMEOUT: Duration = Duration::from_secs(5);
+
+tokio::task_local! {
+    static SPANS: RefCell<Vec<Span>>;
+}
+
+fn default_service_name() -> String {
+    "attestation-service".to_string()
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct TracingConfig {
+    /// Base URL of the OTLP/HTTP receiver of the collector, e.g.
+    /// `http://collector:4318`.
+    pub endpoint: String,
+    /// `service.name` of the traces.
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+}
+
+impl TracingConfig {
+    pub fn check(&self) -> Result<()> {
+        if !self.endpoint.starts_with("https://") && !self.endpoint.starts_with("http://") {
+            bail!("OTLP endpoint {} is not an HTTP URL", self.endpoint);
+        }
+        Ok(())
+    }
+}
+
+/// A span of the trace of an attestation.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Span {
+    pub name: String,
+    /// Name of the parent span, the `attestation` span if `None`.
+    pub parent: Option<&'static str>,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(&'static str, String)>,
+}
+
+/// Run `attestation`, and return its output with the spans recorded.
+pub(crate) async fn collect<F: Future>(attestation: F) -> (F::Output, Vec<Span>) {
+    SPANS
+        .scope(RefCell::new(Vec::new()), async move {
+            let output = attestation.await;
+            (output, SPANS.with(RefCell::take))
+        })
+        .await
+}
+
+fn push(span: Span) {
+    let _ = SPANS.try_with(|spans| spans.borrow_mut().push(span));
+}
+
+/// Record the span `name` from `start` until now.
+pub(crate) fn record(name: &str, start: SystemTime, attributes: Vec<(&'static str, String)>) {
+    push(Span {
+        name: name.to_string(),
+        parent: None,
+        start,
+        end: SystemTime::now(),
+        attributes,
+    });
+}
+
+/// Record the `verify` span from `start` until now, and the spans of the
+/// stages of the verifier, from its `trace`, and of its `steps`.
+pub(crate) fn record_verification(
+    start: SystemTime,
+    trace: &VerificationTrace,
+    steps: &[TimedStep],
+) {
+    let end = SystemTime::now();
+    for (i, (stage, stage_start)) in trace.stages.iter().zip(&trace.started).enumerate() {
+        push(Span {
+            name: stage.to_string(),
+            parent: Some("verify"),
+            start: *stage_start,
+            end: trace.started.get(i + 1).copied().unwrap_or(end),
+            attributes: Vec::new(),
+        });
+    }
+    for step in steps {
+        push(Span {
+            name: step.name.to_string(),
+            parent: Some(step.parent.unwrap_or("verify")),
+            start: step.start,
+            end: step.end,
+            attributes: Vec::new(),
+        });
+    }
+    push(Span {
+        name: "verify".to_string(),
+        parent: None,
+        start,
+        end,
+        attributes: Vec::new(),
+    });
+}
+
+/// Sender of the traces to the collector.
+pub(crate) struct Tracer {
+    config: TracingConfig,
+    client: reqwest::Client,
+}
+
+impl Tracer {
+    pub fn new(config: TracingConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(OTLP_TIMEOUT)
+            .build()
+            .context("create HTTP client")?;
+        Ok(Self { config, client })
+    }
+
+    /// Send the trace of an attestation of `tee` from `start` until now,
+    /// with its `spans`, and the `error` that rejected it, if any.
+    pub fn export(&self, tee: &str, start: SystemTime, spans: Vec<Span>, error: Option<String>) {
+        let mut root = Span {
+            name: "attestation".to_string(),
+            parent: None,
+            start,
+            end: SystemTime::now(),
+            attributes: vec![("attestation.tee", tee.to_string())],
+        };
+        if let Some(error) = &error {
+            root.attributes.push(("attestation.error", error.clone()));
+        }
+        let traces = otlp_traces(&self.config.service_name, root, &spans, error.is_none());
+        let url = format!("{}/v1/traces", self.config.endpoint.trim_end_matches('/'));
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            let sent = async {
+                client
+                    .post(&url)
+                    .json(&traces)
+                    .send()
+                    .await?
+                    .error_for_status()
+            };
+            if let Err(e) = sent.await {
+                warn!("Cannot send the trace of the attestation to {url}: {e}");
+            }
+        });
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn random_id<const N: usize>() -> String {
+    let mut id = [0u8; N];
+    rand::thread_rng().fill_bytes(&mut id);
+    hex::encode(id)
+}
+
+/// The OTLP traces request of the `root` span and its `spans`.
+fn otlp_traces(service_name: &str, root: Span, spans: &[Span], ok: bool) -> Value {
+    let trace_id = random_id::<16>();
+    let root_id = random_id::<8>();
+    let ids: Vec<(&str, String)> = spans
+        .iter()
+        .map(|span| (span.name.as_str(), random_id::<8>()))
+        .collect();
+    let span = |span: &Span, span_id: &str, parent_id: Option<&str>| {
+        json!({
+            "traceId": trace_id,
+            "spanId": span_id,
+            "parentSpanId": parent_id.unwrap_or_default(),
+            "name": span.name,
+            // Internal.
+            "kind": 1,
+            "startTimeUnixNano": unix_nanos(span.start),
+            "endTimeUnixNano": unix_nanos(span.end),
+            "attributes": span
+                .attributes
+                .iter()
+                .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+                .collect::<Vec<_>>(),
+        })
+    };
+    let mut otlp_spans = vec![span(&root, &root_id, None)];
+    otlp_spans[0]["status"] = json!({ "code": if ok { 1 } else { 2 } });
+    for (child, (_, span_id)) in spans.iter().zip(&ids) {
+        let parent_id = child
+            .parent
+            .and_then(|parent| ids.iter().find(|(name, _)| *name == parent))
+            .map_or(root_id.as_str(), |(_, id)| id.as_str());
+        otlp_spans.push(span(child, span_id, Some(parent_id)));
+    }
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    { "key": "service.name", "value": { "stringValue": service_name } },
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "attestation_service" },
+                "spans": otlp_spans,
+            }],
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verifier::pipeline::Stage;
+
+    #[tokio::test]
+    async fn test_spans() {
+        let start = SystemTime::now();
+        let trace = VerificationTrace {
+            stages: vec![Stage::Parse, Stage::CollateralVerify],
+            started: vec![start, start + Duration::from_millis(5)],
+            ..Default::default()
+        };
+        let ((), spans) = collect(async {
+            let steps = [TimedStep {
+                name: "report_signature",
+                parent: Some("collateral_verify"),
+                start,
+                end: start + Duration::from_millis(1),
+            }];
+            record_verification(start, &trace, &steps);
+            record("policy", start, vec![("policy.id", "default".to_string())]);
+        })
+        .await;
+        let names: Vec<_> = spans.iter().map(|span| span.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "parse",
+                "collateral_verify",
+                "report_signature",
+                "verify",
+                "policy"
+            ]
+        );
+        assert_eq!(spans[0].end, start + Duration::from_millis(5));
+        assert_eq!(spans[1].end, spans[3].end);
+
+        let root = Span {
+            name: "attestation".to_string(),
+            parent: None,
+            start,
+            end: SystemTime::now(),
+            attributes: vec![("attestation.tee", "tdx".to_string())],
+        };
+        let traces = otlp_traces("as", root, &spans, false);
+        let otlp_spans = traces["resourceSpans"][0]["scopeSpans"][0]["spans"]
+            .as_array()
+            .unwrap();
+        assert_eq!(otlp_spans.len(), 6);
+        let (root, parse, collateral_verify, step, verify, policy) = (
+            &otlp_spans[0],
+            &otlp_spans[1],
+            &otlp_spans[2],
+            &otlp_spans[3],
+            &otlp_spans[4],
+            &otlp_spans[5],
+        );
+        assert_eq!(root["parentSpanId"], "");
+        assert_eq!(root["status"]["code"], 2);
+        assert_eq!(root["attributes"][0]["value"]["stringValue"], "tdx");
+        assert_eq!(parse["parentSpanId"], verify["spanId"]);
+        assert_eq!(step["parentSpanId"], collateral_verify["spanId"]);
+        assert_eq!(verify["parentSpanId"], root["spanId"]);
+        assert_eq!(policy["parentSpanId"], root["spanId"]);
+        assert_eq!(policy["traceId"], root["traceId"]);
+        assert_eq!(root["traceId"].as_str().unwrap().len(), 32);
+    }
+
+    #[test]
+    fn test_check() {
+        let config: TracingConfig =
+            serde_json::from_str(r#"{"endpoint": "collector:4318"}"#).unwrap();
+        assert_eq!(config.service_name, "attestation-service");
+        assert_eq!(
+            config.check().unwrap_err().to_string(),
+            "OTLP endpoint collector:4318 is not an HTTP URL"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+//! Merkle tree of the token digests in the work dir of the AS, hashed as
+//! in [RFC 6962](https://www.rfc-editor.org/rfc/rfc6962#section-2.1).
+
+use anyhow::*;
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use super::{InclusionProof, Receipt, TransparencyLog};
+
+const LOG_DIR: &str = "transparency";
+/// Leaf hashes of the tree, appended one after the other.
+const LEAVES_FILE: &str = "leaves";
+
+type Hash = [u8; 32];
+
+pub(super) fn leaf_hash(data: &[u8]) -> Hash {
+    Sha256::new()
+        .chain_update([0u8])
+        .chain_update(data)
+        .finalize()
+        .into()
+}
+
+pub(super) fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    Sha256::new()
+        .chain_update([1u8])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+/// The largest power of two smaller than `n`, which is at least 2.
+fn split(n: usize) -> usize {
+    1 << (usize::BITS - (n - 1).leading_zeros() - 1)
+}
+
+/// The Merkle Tree Hash of the `leaves`.
+fn root(leaves: &[Hash]) -> Hash {
+    match leaves.len() {
+        0 => Sha256::digest([]).into(),
+        1 => leaves[0],
+        n => {
+            let (left, right) = leaves.split_at(split(n));
+            node_hash(&root(left), &root(right))
+        }
+    }
+}
+
+/// The audit path of the leaf `index` among the `leaves`, bottom up.
+fn path(index: usize, leaves: &[Hash]) -> Vec<Hash> {
+    if leaves.len() <= 1 {
+        return Vec::new();
+    }
+    let k = split(leaves.len());
+    let (left, right) = leaves.split_at(k);
+    let (mut path, sibling) = match index < k {
+        true => (path(index, left), root(right)),
+        false => (path(index - k, right), root(left)),
+    };
+    path.push(sibling);
+    path
+}
+
+pub struct LocalLog {
+    path: PathBuf,
+    leaves: Mutex<Vec<Hash>>,
+}
+
+impl LocalLog {
+    pub fn new(work_dir: &Path) -> Result<Self> {
+        let dir = work_dir.join(LOG_DIR);
+        fs::create_dir_all(&dir).context("Create transparency log dir")?;
+        let path = dir.join(LEAVES_FILE);
+        let leaves = match fs::read(&path) {
+            Result::Ok(leaves) => {
+                if leaves.len() % 32 != 0 {
+                    bail!("Truncated transparency log {}", path.display());
+                }
+                leaves
+                    .chunks_exact(32)
+                    .map(|leaf| leaf.try_into().expect("32 bytes chunk"))
+                    .collect()
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).context("Read transparency log"),
+        };
+        Ok(Self {
+            path,
+            leaves: Mutex::new(leaves),
+        })
+    }
+
+    fn file(&self) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context("Open transparency log")
+    }
+}
+
+#[async_trait]
+impl TransparencyLog for LocalLog {
+    async fn append(&self, token: &str) -> Result<Receipt> {
+        let digest = Sha256::digest(token.as_bytes());
+        let leaf = leaf_hash(&digest);
+        let mut leaves = self.leaves.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut file = self.file()?;
+        file.write_all(&leaf)?;
+        file.sync_data()?;
+        leaves.push(leaf);
+        Ok(Receipt {
+            log: "local".to_string(),
+            index: leaves.len() as u64 - 1,
+            digest: format!("sha256:{}", hex::encode(digest)),
+            timestamp: time::OffsetDateTime::now_utc().unix_timestamp(),
+            entry: None,
+        })
+    }
+
+    async fn inclusion_proof(&self, index: u64) -> Result<InclusionProof> {
+        let leaves = self.leaves.lock().unwrap_or_else(PoisonError::into_inner);
+        if index >= leaves.len() as u64 {
+            bail!("No entry {index} in the transparency log");
+        }
+        Ok(InclusionProof {
+            index,
+            tree_size: leaves.len() as u64,
+            root_hash: hex::encode(root(&leaves)),
+            hashes: path(index as usize, &leaves)
+                .iter()
+                .map(hex::encode)
+                .collect(),
+            signed_tree_head: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_root() {
+        // Test vectors of the RFC 6962 reference implementation.
+        let leaves: Vec<_> = [&b""[..], &[0x00], &[0x10]]
+            .iter()
+            .map(|data| leaf_hash(data))
+            .collect();
+        assert_eq!(
+            hex::encode(root(&[])),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex::encode(root(&leaves[..1])),
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d"
+        );
+        assert_eq!(
+            hex::encode(root(&leaves[..2])),
+            "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"
+        );
+        assert_eq!(
+            hex::encode(root(&leaves[..3])),
+            "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inclusion_proof() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let log = LocalLog::new(work_dir.path()).unwrap();
+        let tokens: Vec<_> = (0..7).map(|i| format!("token-{i}")).collect();
+        for (i, token) in tokens.iter().enumerate() {
+            let receipt = log.append(token).await.unwrap();
+            assert_eq!(receipt.index, i as u64);
+        }
+        assert!(log.inclusion_proof(7).await.is_err());
+
+        // The tree is reloaded from the work dir.
+        let log = LocalLog::new(work_dir.path()).unwrap();
+        for (i, token) in tokens.iter().enumerate() {
+            let proof = log.inclusion_proof(i as u64).await.unwrap();
+            assert_eq!(proof.tree_size, 7);
+            proof.verify(token).unwrap();
+            assert!(proof.verify("forged").is_err());
+        }
+    }
+}
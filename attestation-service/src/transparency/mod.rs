@@ -0,0 +1,133 @@
+//! Transparency log of the issued tokens.
+//!
+//! With `transparency_log` in the AS config, every issued token is appended
+//! to an append-only log, and the attester gets a [`Receipt`] of the entry
+//! with the token. Relying parties can later fetch an [`InclusionProof`] of
+//! the entry, to prove that the token was issued before the signed tree
+//! head of the proof. The log is either
+//!
+//! * `local`: a Merkle tree of the SHA-256 digests of the tokens, in the
+//!   work dir of the AS, whose tree heads are signed with the token signing
+//!   key of the AS, as JWTs with `tree_size` and `root_hash` claims;
+//!   their `typ` claim is `as-tree-head+jwt`, that tells them from the
+//!   attestation tokens, and they have no expiration time, as a tree head
+//!   stays true after the tokens expire;
+//! * `rekor`: a [Rekor](https://docs.sigstore.dev/logging/overview/) log,
+//!   where the token is logged as a `hashedrekord` of its signature, and
+//!   whose checkpoints are signed by Rekor.
+//!
+//! ```json
+//! "transparency_log": { "type": "rekor", "url": "https://rekor.sigstore.dev" }
+//! ```
+
+use anyhow::*;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+mod local;
+mod rekor;
+
+/// The `typ` claim of the signed tree heads of the local log.
+pub const TREE_HEAD_TYP: &str = "as-tree-head+jwt";
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TransparencyLogConfig {
+    Local,
+    Rekor { url: String },
+}
+
+impl TransparencyLogConfig {
+    pub fn to_transparency_log(
+        &self,
+        work_dir: &Path,
+    ) -> Result<Box<dyn TransparencyLog + Send + Sync>> {
+        match self {
+            TransparencyLogConfig::Local => Ok(Box::new(local::LocalLog::new(work_dir)?)),
+            TransparencyLogConfig::Rekor { url } => Ok(Box::new(rekor::Rekor::new(url)?)),
+        }
+    }
+}
+
+/// The entry of a token in the transparency log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    /// `local` or `rekor`.
+    pub log: String,
+    /// Index of the entry in the log.
+    pub index: u64,
+    /// Digest of the logged data, like `sha256:<hex>`.
+    pub digest: String,
+    /// When the entry was appended, in Unix time.
+    pub timestamp: i64,
+    /// UUID of a Rekor entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry: Option<String>,
+}
+
+/// Proof that an entry is in the tree of a signed tree head, as in
+/// [RFC 9162](https://www.rfc-editor.org/rfc/rfc9162#section-2.1.3).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InclusionProof {
+    pub index: u64,
+    pub tree_size: u64,
+    /// Hex encoded root hash of the tree.
+    pub root_hash: String,
+    /// Hex encoded audit path of the entry, bottom up.
+    pub hashes: Vec<String>,
+    /// Signed tree head: a JWT of the AS for the local log, a checkpoint
+    /// for Rekor.
+    pub signed_tree_head: Option<String>,
+}
+
+impl InclusionProof {
+    /// Check that `token` is the entry of a local log proven by `self`.
+    /// The signed tree head has to be checked apart.
+    pub fn verify(&self, token: &str) -> Result<()> {
+        if self.index >= self.tree_size {
+            bail!(
+                "Entry {} is out of a tree of {}",
+                self.index,
+                self.tree_size
+            );
+        }
+        let mut index = self.index;
+        let mut last = self.tree_size - 1;
+        let mut hash = local::leaf_hash(&Sha256::digest(token.as_bytes()));
+        for sibling in &self.hashes {
+            let sibling: [u8; 32] = hex::decode(sibling)?
+                .try_into()
+                .map_err(|_| anyhow!("Illegal hash in the inclusion proof"))?;
+            if last == 0 {
+                bail!("The inclusion proof is too long");
+            }
+            if index & 1 == 1 || index == last {
+                hash = local::node_hash(&sibling, &hash);
+                while index & 1 == 0 && index != 0 {
+                    index >>= 1;
+                    last >>= 1;
+                }
+            } else {
+                hash = local::node_hash(&hash, &sibling);
+            }
+            index >>= 1;
+            last >>= 1;
+        }
+        if last != 0 || hex::encode(hash) != self.root_hash {
+            bail!("The token is not in the tree of the inclusion proof");
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+pub trait TransparencyLog {
+    /// Append the issued `token` to the log.
+    async fn append(&self, token: &str) -> Result<Receipt>;
+
+    /// Proof of the inclusion of entry `index` in the current tree. The
+    /// signed tree head is left to the AS for the local log.
+    async fn inclusion_proof(&self, index: u64) -> Result<InclusionProof>;
+}
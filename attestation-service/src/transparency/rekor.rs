@@ -0,0 +1,190 @@
+//! Client of a Rekor transparency log.
+//!
+//! A token is logged as a `hashedrekord` entry: the SHA-384 digest of the
+//! signing input of the token, its RS384 signature and the public key of
+//! the `jwk` claim of the token, so that Rekor checks the token signature
+//! itself.
+
+use anyhow::*;
+use async_trait::async_trait;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use rsa::pkcs8::{EncodePublicKey, LineEnding};
+use rsa::{BigUint, RsaPublicKey};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha384};
+use std::time::Duration;
+
+use super::{InclusionProof, Receipt, TransparencyLog};
+use crate::token::cosign::broker_signed;
+
+const REKOR_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Rekor {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl Rekor {
+    pub fn new(url: &str) -> Result<Self> {
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            bail!("Rekor URL {url} is not an HTTP URL");
+        }
+        let client = reqwest::Client::builder()
+            .timeout(REKOR_TIMEOUT)
+            .build()
+            .context("create HTTP client")?;
+        Ok(Self {
+            client,
+            url: url.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+/// The `hashedrekord` entry of a compact JWS `token`, and the digest it
+/// logs.
+fn hashed_rekord(token: &str) -> Result<(Value, String)> {
+    let (signing_input, signature) = token
+        .rsplit_once('.')
+        .ok_or_else(|| anyhow!("Malformed token"))?;
+    let (header, payload) = signing_input
+        .split_once('.')
+        .ok_or_else(|| anyhow!("Malformed token"))?;
+    let decode = |part: &str| -> Result<Value> {
+        Ok(serde_json::from_slice(&URL_SAFE_NO_PAD.decode(part)?)?)
+    };
+    if decode(header)?["alg"] != "RS384" {
+        bail!("Only RS384 tokens can be logged to Rekor");
+    }
+    let jwk = &decode(payload)?["jwk"];
+    let component = |name: &str| -> Result<BigUint> {
+        let value = jwk[name]
+            .as_str()
+            .ok_or_else(|| anyhow!("No `{name}` in the jwk claim of the token"))?;
+        Ok(BigUint::from_bytes_be(&URL_SAFE_NO_PAD.decode(value)?))
+    };
+    let public_key = RsaPublicKey::new(component("n")?, component("e")?)?
+        .to_public_key_pem(LineEnding::LF)
+        .map_err(|e| anyhow!("Encode token public key: {e}"))?;
+
+    let digest = hex::encode(Sha384::digest(signing_input.as_bytes()));
+    let entry = json!({
+        "apiVersion": "0.0.1",
+        "kind": "hashedrekord",
+        "spec": {
+            "signature": {
+                "content": STANDARD.encode(URL_SAFE_NO_PAD.decode(signature)?),
+                "publicKey": { "content": STANDARD.encode(public_key) },
+            },
+            "data": {
+                "hash": { "algorithm": "sha384", "value": digest },
+            },
+        },
+    });
+    Ok((entry, format!("sha384:{digest}")))
+}
+
+/// The only entry of a Rekor response, by UUID.
+fn log_entry(response: Value) -> Result<(String, Value)> {
+    response
+        .as_object()
+        .and_then(|entries| entries.iter().next())
+        .map(|(uuid, entry)| (uuid.clone(), entry.clone()))
+        .ok_or_else(|| anyhow!("No entry in the Rekor response"))
+}
+
+#[async_trait]
+impl TransparencyLog for Rekor {
+    async fn append(&self, token: &str) -> Result<Receipt> {
+        let (entry, digest) = hashed_rekord(&broker_signed(token)?)?;
+        let response: Value = self
+            .client
+            .post(format!("{}/api/v1/log/entries", self.url))
+            .json(&entry)
+            .send()
+            .await?
+            .error_for_status()
+            .context("Rekor refused the entry")?
+            .json()
+            .await?;
+        let (uuid, entry) = log_entry(response)?;
+        Ok(Receipt {
+            log: "rekor".to_string(),
+            index: entry["logIndex"]
+                .as_u64()
+                .ok_or_else(|| anyhow!("No logIndex in the Rekor entry"))?,
+            digest,
+            timestamp: entry["integratedTime"].as_i64().unwrap_or_default(),
+            entry: Some(uuid),
+        })
+    }
+
+    async fn inclusion_proof(&self, index: u64) -> Result<InclusionProof> {
+        let response: Value = self
+            .client
+            .get(format!("{}/api/v1/log/entries", self.url))
+            .query(&[("logIndex", index)])
+            .send()
+            .await?
+            .error_for_status()
+            .with_context(|| format!("No entry {index} in the Rekor log"))?
+            .json()
+            .await?;
+        let (_, entry) = log_entry(response)?;
+        let proof = &entry["verification"]["inclusionProof"];
+        let number = |name: &str| {
+            proof[name]
+                .as_u64()
+                .ok_or_else(|| anyhow!("No `{name}` in the Rekor inclusion proof"))
+        };
+        Ok(InclusionProof {
+            index: number("logIndex")?,
+            tree_size: number("treeSize")?,
+            root_hash: proof["rootHash"].as_str().unwrap_or_default().to_string(),
+            hashes: serde_json::from_value(proof["hashes"].clone())
+                .context("Malformed Rekor inclusion proof")?,
+            signed_tree_head: proof["checkpoint"].as_str().map(str::to_string),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::traits::PublicKeyParts;
+
+    #[test]
+    fn test_hashed_rekord() {
+        let key = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"RS384","typ":"JWT"}"#);
+        let payload = URL_SAFE_NO_PAD.encode(
+            json!({
+                "jwk": {
+                    "n": URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+                    "e": URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+                }
+            })
+            .to_string(),
+        );
+        let token = format!("{header}.{payload}.{}", URL_SAFE_NO_PAD.encode([1, 2, 3]));
+
+        let (entry, digest) = hashed_rekord(&token).unwrap();
+        let expected = hex::encode(Sha384::digest(format!("{header}.{payload}")));
+        assert_eq!(digest, format!("sha384:{expected}"));
+        assert_eq!(entry["spec"]["data"]["hash"]["value"], expected);
+        assert_eq!(entry["spec"]["signature"]["content"], "AQID");
+        let public_key = STANDARD
+            .decode(
+                entry["spec"]["signature"]["publicKey"]["content"]
+                    .as_str()
+                    .unwrap(),
+            )
+            .unwrap();
+        assert!(String::from_utf8(public_key)
+            .unwrap()
+            .starts_with("-----BEGIN PUBLIC KEY-----"));
+
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"ES256"}"#);
+        assert!(hashed_rekord(&format!("{header}.{payload}.AQID")).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! Bounded maps of entries that expire after a TTL, the in-memory caches
+//! of the AS: the failure cache, see [`crate::failure_cache`], the result
+//! cache, see [`crate::result_cache`], and the reference value cache of a
+//! remote RVPS.
+//!
+//! Expired entries are forgotten as new ones are inserted and, once the
+//! map holds `max_entries`, the oldest one makes room for a new one. The
+//! maps are not locked: each cache keeps its own behind a mutex.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+pub(crate) struct TtlCache<K, V> {
+    ttl: Duration,
+    max_entries: usize,
+    /// Entries, with the time they are counted from.
+    entries: HashMap<K, (Instant, V)>,
+}
+
+impl<K: Eq + Hash + Clone, V> TtlCache<K, V> {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// The entry of `key`, and the time it is counted from, if within the
+    /// TTL at `now`.
+    pub fn get<Q>(&self, key: &Q, now: Instant) -> Option<(Instant, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries
+            .get(key)
+            .filter(|(at, _)| now.duration_since(*at) < self.ttl)
+            .map(|(at, value)| (*at, value))
+    }
+
+    /// The entry of `key`, to update, and the time it is counted from, if
+    /// within the TTL at `now`.
+    pub fn get_mut<Q>(&mut self, key: &Q, now: Instant) -> Option<(Instant, &mut V)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let ttl = self.ttl;
+        self.entries
+            .get_mut(key)
+            .filter(|(at, _)| now.duration_since(*at) < ttl)
+            .map(|(at, value)| (*at, value))
+    }
+
+    /// The entry of `key`, and the time it is counted from, even if
+    /// expired, to tell an expired entry from an unknown one.
+    pub fn peek<Q>(&self, key: &Q) -> Option<(Instant, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries.get(key).map(|(at, value)| (*at, value))
+    }
+
+    /// Insert `value` of `key`, counted from `now`.
+    pub fn insert(&mut self, key: K, value: V, now: Instant) {
+        self.insert_since(key, value, now, now)
+    }
+
+    /// Insert `value` of `key`, counted from `since`, after forgetting the
+    /// entries expired at `now` and, if there are `max_entries`, the
+    /// oldest one.
+    pub fn insert_since(&mut self, key: K, value: V, since: Instant, now: Instant) {
+        let ttl = self.ttl;
+        self.entries
+            .retain(|_, (at, _)| now.duration_since(*at) < ttl);
+        if self.entries.len() >= self.max_entries && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (at, _))| *at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(key, (since, value));
+    }
+
+    /// Forget all the entries.
+    #[cfg_attr(not(feature = "rvps-grpc"), allow(dead_code))]
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ttl_cache() {
+        let mut cache = TtlCache::new(Duration::from_secs(30), 2);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(cache.get("first", start), None);
+        cache.insert("first".to_string(), 1, start);
+        cache.insert("second".to_string(), 2, at(1));
+        assert_eq!(cache.get("first", at(29)), Some((start, &1)));
+        *cache.get_mut("second", at(2)).unwrap().1 += 1;
+        assert_eq!(cache.get("second", at(2)), Some((at(1), &3)));
+
+        // The oldest entry makes room for a new one.
+        cache.insert("third".to_string(), 3, at(2));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("first", at(2)), None);
+        // Updating an entry makes no room.
+        cache.insert("third".to_string(), 4, at(3));
+        assert_eq!(cache.get("second", at(3)), Some((at(1), &3)));
+
+        // After the TTL, and forgotten on the next insertion.
+        assert_eq!(cache.get("second", at(31)), None);
+        assert_eq!(cache.peek("second"), Some((at(1), &3)));
+        assert!(cache.get_mut("second", at(31)).is_none());
+        cache.insert_since("fourth".to_string(), 5, start, at(32));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("fourth", at(29)), Some((start, &5)));
+        assert_eq!(cache.get("fourth", at(32)), None);
+
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+    }
+}
@@ -30,7 +30,7 @@ pub fn flatten_claims(
 ) -> Result<TeeEvidenceParsedClaim> {
     let mut map = Map::new();
     let tee_type = to_variant_name(&tee)?;
-    match claims {
+    match &**claims {
         Value::Object(obj) => {
             for (k, v) in obj {
                 flatten_helper(&mut map, v, format!("{tee_type}.{}", k.clone()));
@@ -39,7 +39,7 @@ pub fn flatten_claims(
         _ => bail!("input claims must be a map"),
     }
 
-    Ok(serde_json::Value::Object(map))
+    Ok(Value::Object(map).into())
 }
 
 /// Recursion algorithm helper of `flatten_claims`
@@ -72,6 +72,21 @@ fn flatten_helper(parent: &mut Map<String, Value>, child: &serde_json::Value, pr
     }
 }
 
+/// Add `value` as the `field` of the policy input `input`, the JSON object
+/// of the claims, which must not have it already.
+#[cfg(feature = "service")]
+pub(crate) fn add_to_input(input: &str, field: &str, value: Value) -> Result<String> {
+    let mut input: Value = serde_json::from_str(input)?;
+    let Some(claims) = input.as_object_mut() else {
+        bail!("The policy input is not a JSON object");
+    };
+    if claims.contains_key(field) {
+        bail!("The claims already have a `{field}` field");
+    }
+    claims.insert(field.to_string(), value);
+    Ok(serde_json::to_string(&input)?)
+}
+
 #[cfg(test)]
 mod tests {
     use assert_json_diff::assert_json_eq;
@@ -79,6 +94,31 @@ mod tests {
 
     use super::flatten_claims;
 
+    #[cfg(feature = "service")]
+    #[rstest::rstest]
+    #[case::submitter(
+        crate::submitter::SUBMITTER_FIELD,
+        json!({"subject": "CN=node-agent-7,O=Example", "san": ["node-7.example.com"]})
+    )]
+    #[case::host(crate::host::HOST_FIELD, json!({"agent": "node-agent", "cluster_id": "c1"}))]
+    fn test_add_to_input(#[case] field: &str, #[case] value: serde_json::Value) {
+        use super::add_to_input;
+
+        let input = json!({"tdx.quote.body.mr_td": "705e"}).to_string();
+        let added = add_to_input(&input, field, value.clone()).unwrap();
+        let added: serde_json::Value = serde_json::from_str(&added).unwrap();
+        assert_eq!(added["tdx.quote.body.mr_td"], "705e");
+        assert_eq!(added[field], value);
+
+        let forged = json!({ field: "forged" }).to_string();
+        let e = add_to_input(&forged, field, value.clone()).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            format!("The claims already have a `{field}` field")
+        );
+        assert!(add_to_input("[]", field, value).is_err());
+    }
+
     #[test]
     fn flatten() {
         let json = json!({
@@ -114,7 +154,7 @@ mod tests {
                 }
             }
         });
-        let flatten = flatten_claims(kbs_types::Tee::Tdx, &json).expect("flatten failed");
+        let flatten = flatten_claims(kbs_types::Tee::Tdx, &json.into()).expect("flatten failed");
         let expected = json!({
                 "tdx.ccel.kernel": "5b7aa6572f649714ff00b6a2b9170516a068fd1a0ba72aa8de27574131d454e6396d3bfa1727d9baf421618a942977fa",
                 "tdx.ccel.kernel_parameters.console": "hvc0",
@@ -0,0 +1,231 @@
+//! Trust anchors of the certificate chains of evidence.
+//!
+//! Vendors run several root certificates at once: AMD has one ARK per
+//! product line of SEV-SNP (Milan, Genoa...), and Intel rotates the root CA
+//! of SGX and TDX PCK certificates. `trust_anchors` in the AS config lists
+//! the PEM files of the anchors of each vendor:
+//!
+//! ```json
+//! "trust_anchors": {
+//!     "snp": ["/etc/as/genoa_ask_ark.pem"],
+//!     "intel": ["/etc/as/intel_sgx_root_ca.pem", "/etc/as/intel_sgx_root_ca_2.pem"],
+//!     "corim": ["/etc/as/acme_corim_root.pem"],
+//!     "ibm": ["/etc/as/digicert_root.pem"],
+//!     "microsoft": ["/etc/as/microsoft_supply_chain_root.pem"]
+//! }
+//! ```
+//!
+//! - `snp`: ASK and ARK chains, as the AMD KDS serves them at
+//!   `/vcek/v1/<product>/cert_chain`, in addition to the built-in Milan
+//!   one. The chain of a report is verified with the anchors of the product
+//!   line of its VCEK or VLEK, told by the common name of its issuer, e.g.
+//!   `SEV-Genoa`, in order until one validates it.
+//! - `intel`: Intel root CAs. The DCAP libraries verify the PCK chain of
+//!   TDX and SGX quotes up to the root CA they know of, and if there are
+//!   anchors, the chain must end at one of them.
+//! - `corim`: roots of the signers of CoRIM bundles of reference values,
+//!   see `crate::rvps::extractors::extractor_modules::corim`. CoRIMs are
+//!   only accepted with anchors.
+//! - `ibm`: roots of the chains of the IBM Z host key signing keys, which
+//!   sign the host key documents of IBM Secure Execution hosts, see
+//!   `super::se`.
+//! - `microsoft`: roots of the signers of the UVM endorsements of SEV-SNP
+//!   evidence of AKS, see `super::snp`. UVM endorsements are only accepted
+//!   with anchors.
+//!
+//! The anchor that validated the chain is in the `trust_anchor` claim of
+//! the TEE, as the SHA-256 of its root certificate, e.g.
+//! `sha256:6f9a...`, so that policies can pin it.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Claim of the anchor that validated the certificate chain of evidence.
+pub const TRUST_ANCHOR_CLAIM: &str = "trust_anchor";
+
+lazy_static! {
+    /// The anchors of each list of files, so that they are read once, not
+    /// by the verifiers made for every attestation.
+    static ref ANCHORS: Mutex<HashMap<Vec<PathBuf>, Arc<Vec<TrustAnchor>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// PEM files of the trust anchors of each vendor.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct TrustAnchorsConfig {
+    /// AMD ASK and ARK chains, of SEV-SNP product lines.
+    pub snp: Vec<PathBuf>,
+    /// Intel root CAs of the PCK certificates of TDX and SGX quotes.
+    pub intel: Vec<PathBuf>,
+    /// Roots of the signers of CoRIM bundles.
+    pub corim: Vec<PathBuf>,
+    /// Roots of the IBM Z host key signing keys.
+    pub ibm: Vec<PathBuf>,
+    /// Roots of the signers of UVM endorsements.
+    pub microsoft: Vec<PathBuf>,
+}
+
+impl TrustAnchorsConfig {
+    /// Check that all the anchors can be loaded, and that those of SNP are
+    /// ASK and ARK chains.
+    pub fn check(&self) -> Result<()> {
+        for (path, anchor) in self.snp.iter().zip(load(&self.snp).context("snp")?) {
+            if anchor.certs.len() != 2 {
+                bail!("snp: {} is not an ASK and ARK chain", path.display());
+            }
+        }
+        load(&self.intel).context("intel")?;
+        load(&self.corim).context("corim")?;
+        load(&self.ibm).context("ibm")?;
+        load(&self.microsoft).context("microsoft")?;
+        Ok(())
+    }
+}
+
+/// A trust anchor: the DER certificates of a PEM file, the root last.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrustAnchor {
+    pub certs: Vec<Vec<u8>>,
+}
+
+impl TrustAnchor {
+    pub fn from_pem(pem: &[u8]) -> Result<Self> {
+        let certs = pem_certificates(pem)?;
+        if certs.is_empty() {
+            bail!("No certificate");
+        }
+        Ok(Self { certs })
+    }
+
+    /// The DER root certificate.
+    pub fn root(&self) -> &[u8] {
+        self.certs.last().map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// The value of the [`TRUST_ANCHOR_CLAIM`] of the anchor.
+    pub fn claim(&self) -> String {
+        fingerprint(self.root())
+    }
+}
+
+/// The SHA-256 fingerprint of a DER certificate, as in the
+/// [`TRUST_ANCHOR_CLAIM`].
+pub fn fingerprint(der: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(der)))
+}
+
+/// The DER certificates of a PEM document, in order.
+pub fn pem_certificates(pem: &[u8]) -> Result<Vec<Vec<u8>>> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let mut pem = std::str::from_utf8(pem).context("Not PEM")?;
+    let mut certs = Vec::new();
+    while let Some(start) = pem.find(BEGIN) {
+        let body = &pem[start + BEGIN.len()..];
+        let end = body
+            .find(END)
+            .ok_or_else(|| anyhow!("Malformed PEM certificate"))?;
+        let base64: String = body[..end].chars().filter(|c| !c.is_whitespace()).collect();
+        certs.push(
+            base64::engine::general_purpose::STANDARD
+                .decode(base64)
+                .context("Malformed PEM certificate")?,
+        );
+        pem = &body[end + END.len()..];
+    }
+    Ok(certs)
+}
+
+fn load(paths: &[PathBuf]) -> Result<Vec<TrustAnchor>> {
+    paths
+        .iter()
+        .map(|path| {
+            let pem = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+            TrustAnchor::from_pem(&pem).with_context(|| format!("load {}", path.display()))
+        })
+        .collect()
+}
+
+/// The trust anchors of the PEM files of `paths`, read once.
+pub fn anchors(paths: &[PathBuf]) -> Result<Arc<Vec<TrustAnchor>>> {
+    let mut cache = ANCHORS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(anchors) = cache.get(paths) {
+        return Ok(anchors.clone());
+    }
+    let anchors = Arc::new(load(paths)?);
+    cache.insert(paths.to_vec(), anchors.clone());
+    Ok(anchors)
+}
+
+/// Check that the DER `root` of a certificate chain is one of `anchors`,
+/// if there are any, and return its [`TRUST_ANCHOR_CLAIM`].
+pub fn check_root(root: &[u8], anchors: &[TrustAnchor]) -> Result<String> {
+    if !anchors.is_empty() && !anchors.iter().any(|anchor| anchor.root() == root) {
+        bail!(
+            "The certificate chain ends at {}, which is not a configured trust anchor",
+            fingerprint(root)
+        );
+    }
+    Ok(fingerprint(root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pem(ders: &[&[u8]]) -> String {
+        ders.iter()
+            .map(|der| {
+                format!(
+                    "-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n",
+                    base64::engine::general_purpose::STANDARD.encode(der)
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_anchors() {
+        let anchor = TrustAnchor::from_pem(pem(&[b"ask", b"ark"]).as_bytes()).unwrap();
+        assert_eq!(anchor.certs, [b"ask".to_vec(), b"ark".to_vec()]);
+        assert_eq!(anchor.root(), b"ark");
+        assert_eq!(anchor.claim(), fingerprint(b"ark"));
+        assert!(anchor.claim().starts_with("sha256:"));
+        assert!(TrustAnchor::from_pem(b"no certificate").is_err());
+
+        assert_eq!(check_root(b"any", &[]).unwrap(), fingerprint(b"any"));
+        let trusted = [anchor];
+        assert_eq!(check_root(b"ark", &trusted).unwrap(), fingerprint(b"ark"));
+        assert!(check_root(b"ask", &trusted).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("anchor.pem");
+        fs::write(&path, pem(&[b"root"])).unwrap();
+        let config = TrustAnchorsConfig {
+            intel: vec![path.clone()],
+            ..Default::default()
+        };
+        config.check().unwrap();
+        assert_eq!(anchors(&config.intel).unwrap()[0].root(), b"root");
+        // Not an ASK and ARK chain.
+        let config = TrustAnchorsConfig {
+            snp: vec![path],
+            ..Default::default()
+        };
+        assert!(config.check().is_err());
+        let config = TrustAnchorsConfig {
+            snp: vec![dir.path().join("missing.pem")],
+            ..Default::default()
+        };
+        assert!(config.check().is_err());
+    }
+}
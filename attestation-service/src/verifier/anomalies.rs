@@ -0,0 +1,144 @@
+//! Structural anomalies of DCAP quotes.
+//!
+//! The DCAP libraries verify the signature of a quote, not every field of
+//! its structure: a quote with nonzero reserved fields, the vendor ID of
+//! another quoting enclave, or a signature data length that does not match
+//! the quote, is passed through as claims. So are integers written
+//! big-endian by a quote generator that got the byte order wrong, which
+//! then read as unexpected values. The TDX and SGX verifiers look for these
+//! anomalies after parsing a quote, and raise a warning for each, see
+//! [`super::warnings`]. With `strict_quote_parsing` in the AS config, they
+//! reject the quote instead, for high-assurance deployments.
+
+use anyhow::{bail, Result};
+
+use super::warnings;
+
+/// The QE vendor ID of the Intel quoting enclaves.
+pub const INTEL_QE_VENDOR_ID: [u8; 16] = [
+    0x93, 0x9a, 0x72, 0x33, 0xf7, 0x9c, 0x4c, 0xa9, 0x94, 0x0a, 0x0d, 0xb3, 0x95, 0x7f, 0x06, 0x07,
+];
+
+/// The anomalies found in a quote.
+#[derive(Debug)]
+pub struct Anomalies {
+    /// What the quote is, for the messages, e.g. `TD quote`.
+    what: &'static str,
+    found: Vec<String>,
+}
+
+impl Anomalies {
+    pub fn new(what: &'static str) -> Self {
+        Self {
+            what,
+            found: Vec::new(),
+        }
+    }
+
+    /// Check that the little-endian integer field `name`, of `value` read
+    /// from `size` bytes, is one of `expected`, and tell whether it is
+    /// big-endian if not.
+    pub fn integer(&mut self, name: &str, value: u64, size: usize, expected: &[u64]) {
+        if expected.contains(&value) {
+            return;
+        }
+        let swapped = value.swap_bytes() >> (64 - 8 * size);
+        match expected.contains(&swapped) {
+            true => self
+                .found
+                .push(format!("{name} is {value:#x}, a big-endian {swapped}")),
+            false => self.found.push(format!(
+                "{name} is {value:#x}, expected one of {expected:?}"
+            )),
+        }
+    }
+
+    /// Check that the reserved field `name` is zero.
+    pub fn reserved(&mut self, name: &str, bytes: &[u8]) {
+        if bytes.iter().any(|byte| *byte != 0) {
+            self.found
+                .push(format!("{name} is not zero: {}", hex::encode(bytes)));
+        }
+    }
+
+    /// Check that the quote was generated by an Intel quoting enclave.
+    pub fn vendor_id(&mut self, vendor_id: &[u8]) {
+        if vendor_id != INTEL_QE_VENDOR_ID {
+            self.found.push(format!(
+                "vendor_id {} is not the Intel QE vendor ID",
+                hex::encode(vendor_id)
+            ));
+        }
+    }
+
+    /// Check that the length `name` of a part of the quote matches its
+    /// `actual` length.
+    pub fn length(&mut self, name: &str, declared: usize, actual: usize) {
+        if declared != actual {
+            self.found
+                .push(format!("{name} is {declared}, but {actual} bytes follow"));
+        }
+    }
+
+    /// Reject the quote for its anomalies if `strict`, else raise a
+    /// warning for each.
+    pub fn check(self, strict: bool) -> Result<()> {
+        if self.found.is_empty() {
+            return Ok(());
+        }
+        if strict {
+            bail!(
+                "Malformed {}, rejected by strict_quote_parsing: {}",
+                self.what,
+                self.found.join("; ")
+            );
+        }
+        for anomaly in self.found {
+            warnings::raise(format!("Anomaly in the {}: {anomaly}", self.what));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anomalies() {
+        let mut anomalies = Anomalies::new("TD quote");
+        anomalies.integer("version", 4, 2, &[4, 5]);
+        anomalies.reserved("reserved", &[0; 4]);
+        anomalies.vendor_id(&INTEL_QE_VENDOR_ID);
+        anomalies.length("signature_data_len", 4300, 4300);
+        assert!(anomalies.found.is_empty());
+        anomalies.check(true).unwrap();
+
+        let mut anomalies = Anomalies::new("TD quote");
+        anomalies.integer("version", 0x0400, 2, &[4, 5]);
+        anomalies.integer("tee_type", 0x80, 4, &[0x81]);
+        anomalies.reserved("reserved", &[0, 1, 0, 0]);
+        anomalies.vendor_id(&[0; 16]);
+        anomalies.length("signature_data_len", 4300, 4299);
+        assert_eq!(
+            anomalies.found,
+            [
+                "version is 0x400, a big-endian 4",
+                "tee_type is 0x80, expected one of [129]",
+                "reserved is not zero: 00010000",
+                "vendor_id 00000000000000000000000000000000 is not the Intel QE vendor ID",
+                "signature_data_len is 4300, but 4299 bytes follow",
+            ]
+        );
+        let e = anomalies.check(true).unwrap_err().to_string();
+        assert!(e.starts_with("Malformed TD quote, rejected by strict_quote_parsing: version"));
+
+        let mut anomalies = Anomalies::new("SGX quote");
+        anomalies.integer("tee_type", 0x8100_0000, 4, &[0x81]);
+        assert_eq!(
+            anomalies.found,
+            ["tee_type is 0x81000000, a big-endian 129"]
+        );
+        anomalies.check(false).unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+//! Composite evidence of Azure confidential VMs.
+//!
+//! The paravisor of an Azure confidential VM wraps the hardware report of
+//! the VM, an SNP report or a TD report, in an HCL report, see
+//! [`crate::verifier::hcl`], whose runtime data holds the attestation key
+//! (AK) of the vTPM it provides to the guest. The guest is measured in the
+//! PCRs of the vTPM, see [`crate::verifier::vtpm`]. The composite evidence
+//! carries the HCL report, the vTPM quote and what verifies the hardware
+//! report: the VCEK of an SNP report, or the TD quote of a TD report.
+//!
+//! ```json
+//! {
+//!     "hcl_report": "<base64 HCL report>",
+//!     "vtpm_quote": { "message": "...", "signature": "...", "pcrs": { "7": "..." } },
+//!     "vcek": "<PEM VCEK>" | "td_quote": "<base64 TD quote>"
+//! }
+//! ```
+//!
+//! The hardware report is verified, its report data must be the hash of
+//! the runtime data, which certifies the AK, and the quote must be signed
+//! by the AK, quote the given PCR values and bind `SHA384(nonce||pubkey)`.
+//! The claims of both are merged, those of the hardware report under
+//! `quote` and those of the vTPM quote under `tpm`:
+//!
+//! ```json
+//! "hardware": "tdx",
+//! "quote": { "header": {...}, "body": { "mr_td": "...", ... } },
+//! "tpm": { "pcr_4": "...", "pcr_7": "...", "clock": 3600000 },
+//! "runtime_data": { "keys": [...], "vm-configuration": {...} }
+//! ```
+
+use super::{nonced_pub_key_hash, parse_tee_evidence, verify_snp_report, AzSnpVtpm};
+use crate::verifier::hcl::{self, ReportType};
+use crate::verifier::tcb::{self, Tcb};
+use crate::verifier::vtpm::{self, VerifiedVtpmQuote, VtpmQuote};
+use crate::verifier::{signed_parts, timing, Attestation, TeeEvidenceParsedClaim, VersionRange};
+use anyhow::{bail, Context, Result};
+use az_snp_vtpm::certs::Vcek;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sev::firmware::guest::AttestationReport;
+
+/// Composite evidence, told apart from the SNP evidence of the vTPM by its
+/// `hcl_report`.
+#[derive(Deserialize)]
+pub(crate) struct CvmEvidence {
+    /// HCL report, base64 encoded.
+    hcl_report: String,
+    vtpm_quote: VtpmQuote,
+    /// VCEK of the SNP report of the HCL report, PEM encoded.
+    #[serde(default)]
+    vcek: Option<String>,
+    /// TD quote of the TD report of the HCL report, base64 encoded.
+    #[serde(default)]
+    td_quote: Option<String>,
+}
+
+impl CvmEvidence {
+    /// The signed parts of the evidence: the HCL report and the message of
+    /// the vTPM quote.
+    pub(crate) fn signed(&self) -> Result<Vec<u8>> {
+        let hcl_report = decode_hcl_report(&self.hcl_report)?;
+        let message = base64::engine::general_purpose::STANDARD
+            .decode(&self.vtpm_quote.message)
+            .context("Malformed vTPM quote message")?;
+        Ok(signed_parts(&[&hcl_report, &message]))
+    }
+}
+
+/// A verified hardware report of an HCL report.
+struct HardwareReport {
+    /// `snp` or `tdx`.
+    hardware: &'static str,
+    claims: Value,
+    tcb: Tcb,
+    /// Runtime data of the HCL report, bound to the hardware report.
+    runtime_data: Value,
+}
+
+/// Verify composite evidence, bound to `nonce` and the TEE public key of
+/// `attestation`.
+pub(crate) async fn verify(
+    verifier: &AzSnpVtpm,
+    evidence: CvmEvidence,
+    nonce: &str,
+    attestation: &Attestation,
+) -> Result<TeeEvidenceParsedClaim> {
+    let report = match (&evidence.vcek, &evidence.td_quote) {
+        (Some(vcek), None) => verify_snp(&evidence.hcl_report, vcek, verifier.versions)?,
+        (None, Some(td_quote)) => verify_tdx(&evidence.hcl_report, td_quote, verifier).await?,
+        _ => bail!("Composite vTPM evidence has either the VCEK of an SNP report or a TD quote"),
+    };
+
+    let vtpm_quote = vtpm::verify(&evidence.vtpm_quote, &report.runtime_data)
+        .context("Failed to verify vTPM quote")?;
+    if vtpm_quote.nonce != nonced_pub_key_hash(attestation, nonce) {
+        bail!("The nonce of the vTPM quote is different from HASH(nonce||pubkey)");
+    }
+
+    let produced = timing::evidence_time(nonce).unwrap_or_else(Utc::now);
+    claims(report, &vtpm_quote, produced)
+}
+
+fn decode_hcl_report(hcl_report: &str) -> Result<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD
+        .decode(hcl_report)
+        .context("Malformed HCL report")
+}
+
+fn verify_snp(hcl_report: &str, vcek: &str, versions: VersionRange) -> Result<HardwareReport> {
+    let hcl_report = decode_hcl_report(hcl_report)?;
+    let hcl_report = hcl::parse(&hcl_report, ReportType::Snp)?;
+    let snp_report: AttestationReport =
+        bincode::deserialize(hcl_report.hw_report).context("Malformed SNP report in HCL report")?;
+    versions.check("SNP attestation report", snp_report.version)?;
+
+    verify_snp_report(&snp_report, &Vcek::from_pem(vcek)?)?;
+    hcl_report.check_report_data(&snp_report.report_data)?;
+
+    Ok(HardwareReport {
+        hardware: "snp",
+        claims: parse_tee_evidence(&snp_report).into_inner(),
+        tcb: tcb::from_snp(&snp_report.reported_tcb, &snp_report.current_tcb),
+        runtime_data: hcl::runtime_data(&hcl_report.runtime_data)?,
+    })
+}
+
+async fn verify_tdx(
+    hcl_report: &str,
+    td_quote: &str,
+    verifier: &AzSnpVtpm,
+) -> Result<HardwareReport> {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "tdx-verifier")] {
+            let (claims, tcb, runtime_data) =
+                crate::verifier::tdx::verify_cvm_quote(
+                    td_quote,
+                    hcl_report,
+                    verifier.td_versions,
+                    &verifier.collateral,
+                    verifier.strict_quote_parsing,
+                )
+                    .await
+                    .context("TD quote")?;
+            Ok(HardwareReport {
+                hardware: "tdx",
+                claims,
+                tcb,
+                runtime_data,
+            })
+        } else {
+            let _ = (hcl_report, td_quote, verifier);
+            bail!("feature `tdx-verifier` is not enabled, cannot verify the TD quote of the confidential VM");
+        }
+    }
+}
+
+/// Merge the claims of the hardware report and of the vTPM quote, and add
+/// the `boot_time` claim of the TPM clock, for a quote `produced` then.
+fn claims(
+    report: HardwareReport,
+    vtpm_quote: &VerifiedVtpmQuote,
+    produced: DateTime<Utc>,
+) -> Result<TeeEvidenceParsedClaim> {
+    let mut claims = json!({
+        "hardware": report.hardware,
+        "quote": report.claims,
+        "tpm": vtpm_quote.claims(),
+        "runtime_data": report.runtime_data,
+    });
+    report.tcb.add_claims(&mut claims)?;
+    let uptime = Duration::milliseconds(i64::try_from(vtpm_quote.clock).unwrap_or(i64::MAX));
+    timing::add_boot_time(&mut claims, uptime, produced);
+    Ok(TeeEvidenceParsedClaim::from(claims))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verifier::vtpm::tests::{runtime_data, vtpm_quote};
+
+    const HCL_REPORT: &[u8] = include_bytes!("../../../../test_data/az-hcl-data.bin");
+    const VCEK: &str = include_str!("../../../../test_data/az-vcek.pem");
+
+    #[test]
+    fn test_verify_snp() {
+        let hcl_report = base64::engine::general_purpose::STANDARD.encode(HCL_REPORT);
+        let report = verify_snp(&hcl_report, VCEK, VersionRange::default()).unwrap();
+        assert_eq!(report.hardware, "snp");
+        assert_eq!(report.claims["policy_debug_allowed"], "0");
+        assert_eq!(report.runtime_data["keys"][0]["kid"], "HCLAkPub");
+
+        // The runtime data is bound to the report.
+        let mut tampered = HCL_REPORT.to_vec();
+        tampered[hcl::HEADER_SIZE + hcl::HW_REPORT_SIZE + hcl::REQUEST_DATA_SIZE + 2] ^= 1;
+        let tampered = base64::engine::general_purpose::STANDARD.encode(tampered);
+        assert!(verify_snp(&tampered, VCEK, VersionRange::default()).is_err());
+    }
+
+    #[test]
+    fn test_claims() {
+        let (key, runtime_data) = runtime_data(&[]);
+        let runtime_data: Value = serde_json::from_str(&runtime_data).unwrap();
+        let vtpm_quote = vtpm::verify(&vtpm_quote(&key, &[7; 48]), &runtime_data).unwrap();
+        let report = HardwareReport {
+            hardware: "tdx",
+            claims: json!({ "body": { "mr_td": "705e" } }),
+            tcb: Tcb::default(),
+            runtime_data,
+        };
+
+        let produced = timing::evidence_time("2024-05-01T12:00:00Z").unwrap();
+        let claims = claims(report, &vtpm_quote, produced).unwrap();
+        assert_eq!(claims["hardware"], "tdx");
+        assert_eq!(claims["quote"]["body"]["mr_td"], "705e");
+        assert_eq!(claims["tpm"]["pcr_9"], hex::encode([9; 32]));
+        assert_eq!(claims["tpm"]["clock"], 3_600_000);
+        assert_eq!(
+            claims["runtime_data"]["vm-configuration"]["secure-boot"],
+            true
+        );
+        assert!(claims.get("tcb_status").is_some());
+        assert_eq!(claims["boot_time"], "2024-05-01T11:00:00+00:00");
+    }
+}
@@ -3,7 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use super::{Attestation, TeeEvidenceParsedClaim, Verifier};
+use super::{signed_parts, tcb, Attestation, TeeEvidenceParsedClaim, Verifier, VersionRange};
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use az_snp_vtpm::certs::{AmdChain, Vcek, X509};
@@ -12,12 +12,14 @@ use az_snp_vtpm::report::Validateable;
 use az_snp_vtpm::vtpm::{Quote, VerifyVTpmQuote};
 use base64::Engine;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use sev::firmware::guest::AttestationReport;
 use sev::firmware::host::TcbVersion;
 use sha2::{Digest, Sha384};
 use std::collections::BTreeMap;
 
+mod cvm;
+
 const HCL_VMPL_VALUE: u32 = 0;
 
 #[derive(Serialize, Deserialize)]
@@ -28,7 +30,17 @@ struct Evidence {
 }
 
 #[derive(Default)]
-pub struct AzSnpVtpm;
+pub struct AzSnpVtpm {
+    pub versions: VersionRange,
+    /// Accepted TD quote versions of composite evidence of TDX VMs.
+    pub td_versions: VersionRange,
+    /// DCAP collateral of the TD quotes.
+    #[cfg(feature = "tdx-verifier")]
+    pub collateral: std::sync::Arc<crate::verifier::collateral::Provider>,
+    /// Reject TD quotes with structural anomalies.
+    #[cfg(feature = "tdx-verifier")]
+    pub strict_quote_parsing: bool,
+}
 
 #[async_trait]
 impl Verifier for AzSnpVtpm {
@@ -37,11 +49,20 @@ impl Verifier for AzSnpVtpm {
         nonce: String,
         attestation: &Attestation,
     ) -> Result<TeeEvidenceParsedClaim> {
-        let evidence = serde_json::from_str::<Evidence>(&attestation.tee_evidence)
+        let evidence = serde_json::from_str::<Value>(&attestation.tee_evidence)
+            .context("Failed to deserialize vTPM SEV-SNP evidence")?;
+        if evidence.get("hcl_report").is_some() {
+            let evidence = serde_json::from_value::<cvm::CvmEvidence>(evidence)
+                .context("Failed to deserialize composite vTPM evidence")?;
+            return cvm::verify(self, evidence, &nonce, attestation).await;
+        }
+        let evidence = serde_json::from_value::<Evidence>(evidence)
             .context("Failed to deserialize vTPM SEV-SNP evidence")?;
 
         let hcl_data: HclData = evidence.report[..].try_into()?;
         let snp_report = hcl_data.report().snp_report();
+        self.versions
+            .check("SNP attestation report", snp_report.version)?;
         let vcek = Vcek::from_pem(&evidence.vcek)?;
 
         let hashed_quote = nonced_pub_key_hash(attestation, &nonce);
@@ -51,9 +72,26 @@ impl Verifier for AzSnpVtpm {
         let var_data = hcl_data.var_data();
         hcl_data.report().verify_report_data(var_data)?;
 
-        let claim = parse_tee_evidence(snp_report);
+        let mut claim = parse_tee_evidence(snp_report);
+        tcb::from_snp(&snp_report.reported_tcb, &snp_report.current_tcb).add_claims(&mut claim)?;
         Ok(claim)
     }
+
+    fn signed_evidence(&self, attestation: &Attestation) -> Result<Vec<u8>> {
+        let evidence = serde_json::from_str::<Value>(&attestation.tee_evidence)
+            .context("Failed to deserialize vTPM SEV-SNP evidence")?;
+        if evidence.get("hcl_report").is_some() {
+            let evidence = serde_json::from_value::<cvm::CvmEvidence>(evidence)
+                .context("Failed to deserialize composite vTPM evidence")?;
+            return evidence.signed();
+        }
+        let evidence = serde_json::from_value::<Evidence>(evidence)
+            .context("Failed to deserialize vTPM SEV-SNP evidence")?;
+        Ok(signed_parts(&[
+            &evidence.report,
+            &serde_json::to_vec(&evidence.quote)?,
+        ]))
+    }
 }
 
 fn verify_quote(quote: &Quote, hcl_data: &HclData, hashed_nonce: &[u8]) -> Result<()> {
@@ -104,33 +142,38 @@ fn parse_tee_evidence(report: &AttestationReport) -> TeeEvidenceParsedClaim {
     } = report.reported_tcb;
     let policy = report.policy;
 
-    let num_values = [
-        ("policy_abi_major", policy.abi_major()),
-        ("policy_abi_minor", policy.abi_minor()),
+    let flag_values = [
         ("policy_smt_allowed", policy.smt_allowed()),
         ("policy_migrate_ma", policy.migrate_ma_allowed()),
         ("policy_debug_allowed", policy.debug_allowed()),
         ("policy_single_socket", policy.single_socket_required()),
+        // platform info
+        ("platform_tsme_enabled", report.plat_info.tsme_enabled()),
+        ("platform_smt_enabled", report.plat_info.smt_enabled()),
+    ];
+
+    // Versions and SVNs are typed as numbers.
+    let num_values = [
+        ("policy_abi_major", policy.abi_major()),
+        ("policy_abi_minor", policy.abi_minor()),
         // versioning info
         ("reported_tcb_bootloader", bootloader as u64),
         ("reported_tcb_tee", tee as u64),
         ("reported_tcb_snp", snp as u64),
         ("reported_tcb_microcode", microcode as u64),
-        // platform info
-        ("platform_tsme_enabled", report.plat_info.tsme_enabled()),
-        ("platform_smt_enabled", report.plat_info.smt_enabled()),
     ];
 
-    let mut string_map: BTreeMap<_, _> = num_values
+    let mut claims_map: BTreeMap<_, Value> = flag_values
         .iter()
-        .map(|(k, v)| (*k, v.to_string()))
+        .map(|(k, v)| (*k, Value::from(v.to_string())))
         .collect();
-    string_map.insert(
+    claims_map.extend(num_values.iter().map(|(k, v)| (*k, Value::from(*v))));
+    claims_map.insert(
         "measurement",
-        base64::engine::general_purpose::STANDARD.encode(report.measurement),
+        Value::from(base64::engine::general_purpose::STANDARD.encode(report.measurement)),
     );
 
-    json!(string_map) as TeeEvidenceParsedClaim
+    TeeEvidenceParsedClaim::from(json!(claims_map))
 }
 
 fn nonced_pub_key_hash(attestation: &Attestation, nonce: &str) -> Vec<u8> {
@@ -144,6 +187,8 @@ fn nonced_pub_key_hash(attestation: &Attestation, nonce: &str) -> Vec<u8> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::verifier::conformance::{self, Conformance, DebugClaim, KnownClaims};
+    use kbs_types::Tee;
 
     #[test]
     fn test_verify_snp_report() {
@@ -199,17 +244,40 @@ mod tests {
           "measurement": "ofOTBBMke7OM/BcVeeo8EtX+SQHwx5L2P9ddmPHvgnwjUAZE4OaS5r6Rf5BQ09OM",
           "platform_smt_enabled": "0",
           "platform_tsme_enabled": "1",
-          "policy_abi_major": "0",
-          "policy_abi_minor": "31",
+          "policy_abi_major": 0,
+          "policy_abi_minor": 31,
           "policy_debug_allowed": "0",
           "policy_migrate_ma": "0",
           "policy_single_socket": "0",
           "policy_smt_allowed": "1",
-          "reported_tcb_bootloader": "3",
-          "reported_tcb_microcode": "115",
-          "reported_tcb_snp": "8",
-          "reported_tcb_tee": "0"
+          "reported_tcb_bootloader": 3,
+          "reported_tcb_microcode": 115,
+          "reported_tcb_snp": 8,
+          "reported_tcb_tee": 0
         });
-        assert!(claim == reference);
+        assert!(*claim == reference);
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        let report = include_bytes!("../../../../test_data/az-hcl-data.bin");
+        let hcl_data: HclData = report.as_slice().try_into().unwrap();
+        let claims = parse_tee_evidence(hcl_data.report().snp_report());
+
+        conformance::run(Conformance {
+            tee: Tee::AzSnpVtpm,
+            verifier: AzSnpVtpm::default(),
+            malformed: vec![json!({ "quote": {}, "report": [], "vcek": "" }).to_string()],
+            evidence: None,
+            claims: Some(KnownClaims {
+                claims,
+                debug: false,
+            }),
+            debug_claim: Some(DebugClaim {
+                name: "azsnpvtpm.policy_debug_allowed",
+                is_debug: |value| value.as_str()?.parse::<u8>().ok().map(|debug| debug != 0),
+            }),
+        })
+        .await;
     }
 }
@@ -0,0 +1,230 @@
+//! Canonical claims, common to TEE types.
+//!
+//! The same fact about a TEE is named and encoded differently by each
+//! verifier: the launch measurement is `mr_td` in hex for TDX and
+//! `measurement` in base64 for SNP, and whether the TEE is debuggable is a
+//! bit of the TD attributes for TDX and a flag of the guest policy for SNP.
+//! [`CANONICAL_CLAIMS`] relates each canonical claim to the claims of the
+//! verifiers it is derived from, and how, and the AS adds the canonical
+//! claims to the policy input from that table, so that a policy written
+//! against them holds across TEE types:
+//!
+//! ```rego
+//! allow {
+//!     input.debug == false
+//!     input.measurement == data.reference.measurement
+//! }
+//! ```
+//!
+//! The table is also served by the AS, as the documentation of portable
+//! claims, which cannot drift from what the AS does.
+
+use base64::Engine;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fmt;
+
+use super::tcb::{TCB_DATE_CLAIM, TCB_STATUS_CLAIM};
+use super::timing::BOOT_TIME_CLAIM;
+
+/// How a canonical claim is derived from the claim of a verifier.
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Conversion {
+    /// The claim as it is.
+    Copy,
+    /// A JSON boolean, or `"0"` and `"1"`.
+    Flag,
+    /// [`Conversion::Flag`], negated.
+    InvertedFlag,
+    /// The lowest bit of the first byte of a hex string.
+    LowBit,
+    /// A base64 string, re-encoded in hex.
+    Base64ToHex,
+}
+
+impl Conversion {
+    fn convert(self, value: &Value) -> Option<Value> {
+        let flag = |value: &Value| match value {
+            Value::Bool(flag) => Some(*flag),
+            Value::String(flag) => flag.parse::<u8>().ok().map(|flag| flag != 0),
+            _ => None,
+        };
+        match self {
+            Conversion::Copy => Some(value.clone()),
+            Conversion::Flag => flag(value).map(Value::Bool),
+            Conversion::InvertedFlag => flag(value).map(|flag| Value::Bool(!flag)),
+            Conversion::LowBit => {
+                let bytes = hex::decode(value.as_str()?).ok()?;
+                Some(Value::Bool(bytes.first()? & 1 == 1))
+            }
+            Conversion::Base64ToHex => {
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(value.as_str()?)
+                    .ok()?;
+                Some(hex::encode(bytes).into())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Conversion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(serde_variant::to_variant_name(self).unwrap_or_default())
+    }
+}
+
+/// A claim of a verifier that a canonical claim is derived from.
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub struct Source {
+    /// TEE name, as in the `tee` of attestation requests, e.g. `tdx`.
+    pub tee: &'static str,
+    /// Claim name, relative to the claims of the TEE.
+    pub claim: &'static str,
+    pub conversion: Conversion,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub struct CanonicalClaim {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub sources: &'static [Source],
+}
+
+const fn source(tee: &'static str, claim: &'static str, conversion: Conversion) -> Source {
+    Source {
+        tee,
+        claim,
+        conversion,
+    }
+}
+
+pub const CANONICAL_CLAIMS: &[CanonicalClaim] = &[
+    CanonicalClaim {
+        name: "measurement",
+        description: "Launch measurement of the TEE, hex encoded",
+        sources: &[
+            source("tdx", "quote.body.mr_td", Conversion::Copy),
+            source("sgx", "mr-enclave", Conversion::Copy),
+            source("snp", "measurement", Conversion::Base64ToHex),
+            source("azsnpvtpm", "measurement", Conversion::Base64ToHex),
+            source("csv", "measurement", Conversion::Base64ToHex),
+            source(
+                "cca",
+                "cca-realm-initial-measurement",
+                Conversion::Base64ToHex,
+            ),
+        ],
+    },
+    CanonicalClaim {
+        name: "debug",
+        description: "Whether the TEE is debuggable, a boolean",
+        sources: &[
+            source("tdx", "quote.body.td_attributes", Conversion::LowBit),
+            source("sgx", "debug", Conversion::Flag),
+            source("snp", "policy_debug_allowed", Conversion::Flag),
+            source("azsnpvtpm", "policy_debug_allowed", Conversion::Flag),
+            source("csv", "policy_nodbg", Conversion::InvertedFlag),
+            source("sample", "debug", Conversion::Flag),
+        ],
+    },
+    CanonicalClaim {
+        name: TCB_STATUS_CLAIM,
+        description:
+            "Vendor neutral TCB status, e.g. `UpToDate`, `Unknown` if the verifier has none",
+        sources: &[
+            source("tdx", TCB_STATUS_CLAIM, Conversion::Copy),
+            source("sgx", TCB_STATUS_CLAIM, Conversion::Copy),
+            source("snp", TCB_STATUS_CLAIM, Conversion::Copy),
+            source("azsnpvtpm", TCB_STATUS_CLAIM, Conversion::Copy),
+            source("cca", TCB_STATUS_CLAIM, Conversion::Copy),
+        ],
+    },
+    CanonicalClaim {
+        name: TCB_DATE_CLAIM,
+        description: "Release date of the TCB, RFC 3339, when the vendor gives one",
+        sources: &[
+            source("tdx", TCB_DATE_CLAIM, Conversion::Copy),
+            source("sgx", TCB_DATE_CLAIM, Conversion::Copy),
+            source("snp", TCB_DATE_CLAIM, Conversion::Copy),
+            source("azsnpvtpm", TCB_DATE_CLAIM, Conversion::Copy),
+            source("cca", TCB_DATE_CLAIM, Conversion::Copy),
+        ],
+    },
+    CanonicalClaim {
+        name: BOOT_TIME_CLAIM,
+        description: "When the guest booted, RFC 3339, when the evidence tells it",
+        sources: &[source("tdx", BOOT_TIME_CLAIM, Conversion::Copy)],
+    },
+];
+
+/// The value of the canonical claim `name` derived from the flattened
+/// `claims` of the verifier of `tee`, if any.
+pub fn get(name: &str, tee: &str, claims: &Value) -> Option<Value> {
+    let canonical = CANONICAL_CLAIMS.iter().find(|c| c.name == name)?;
+    derive(canonical, tee, claims.as_object()?)
+}
+
+fn derive(canonical: &CanonicalClaim, tee: &str, claims: &Map<String, Value>) -> Option<Value> {
+    canonical
+        .sources
+        .iter()
+        .filter(|source| source.tee == tee)
+        .find_map(|source| {
+            let value = claims.get(&format!("{tee}.{}", source.claim))?;
+            source.conversion.convert(value)
+        })
+}
+
+/// Add the canonical claims of `tee` to the flattened `claims`, from the
+/// claims of the verifier in [`CANONICAL_CLAIMS`]. The claims of the
+/// verifier stay, and those lifted by [`super::tcb::lift`] and
+/// [`super::timing::lift`] are left to them.
+pub fn apply(tee: &str, claims: &mut Value) {
+    let Some(claims) = claims.as_object_mut() else {
+        return;
+    };
+    for canonical in CANONICAL_CLAIMS {
+        if [TCB_STATUS_CLAIM, TCB_DATE_CLAIM, BOOT_TIME_CLAIM].contains(&canonical.name) {
+            continue;
+        }
+        if let Some(value) = derive(canonical, tee, claims) {
+            claims.insert(canonical.name.to_string(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_apply() {
+        let mut claims = json!({
+            "tdx.quote.body.mr_td": "705ee938",
+            "tdx.quote.body.td_attributes": "0100001000000000",
+        });
+        apply("tdx", &mut claims);
+        assert_eq!(claims["measurement"], "705ee938");
+        assert_eq!(claims["debug"], true);
+        assert_eq!(claims["tdx.quote.body.mr_td"], "705ee938");
+
+        let mut claims = json!({
+            "snp.measurement": "cF7pOA==",
+            "snp.policy_debug_allowed": "0",
+        });
+        apply("snp", &mut claims);
+        assert_eq!(claims["measurement"], "705ee938");
+        assert_eq!(claims["debug"], false);
+
+        let mut claims = json!({"csv.policy_nodbg": "1", "sgx.debug": true});
+        apply("csv", &mut claims);
+        assert_eq!(claims["debug"], false);
+        assert!(claims.get("measurement").is_none());
+
+        let table = serde_json::to_value(CANONICAL_CLAIMS).unwrap();
+        assert_eq!(table[1]["sources"][4]["conversion"], "inverted_flag");
+        assert_eq!(Conversion::Base64ToHex.to_string(), "base64_to_hex");
+    }
+}
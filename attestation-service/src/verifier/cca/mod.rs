@@ -4,24 +4,37 @@
 //
 
 use super::*;
+use crate::verifier::crypto::{CryptoBackend, EcdsaCurve};
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use base64::Engine;
 use core::result::Result::Ok;
 use ear::Ear;
 use jsonwebtoken::{self as jwt};
+use rsa::pkcs8::der::{pem, Decode};
+use rsa::pkcs8::SubjectPublicKeyInfoRef;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use sha2::{Digest, Sha384};
+use std::path::PathBuf;
 use std::str;
+use token::{CcaToken, PLATFORM_LIFECYCLE, PLATFORM_TOKEN_CLAIM, REALM_TOKEN_CLAIM};
 use veraison_apiclient::*;
 
+mod token;
+
 const VERAISON_ADDR: &str = "VERAISON_ADDR";
 const DEFAULT_VERAISON_ADDR: &str = "localhost:8080";
 const MEDIA_TYPE: &str = "application/eat-collection; profile=http://arm.com/CCA-SSD/1.0.0";
 
-#[derive(Debug, Default)]
-pub struct CCA {}
+/// The CCA verifier. Tokens are verified locally against `platform_keys`,
+/// or appraised by the Veraison verifier of `VERAISON_ADDR` if there is
+/// none.
+pub struct CCA {
+    /// CPAKs of the trusted platforms, as uncompressed SEC1 points.
+    pub platform_keys: Vec<Vec<u8>>,
+    pub crypto: Box<dyn CryptoBackend + Send + Sync>,
+}
 
 #[derive(Serialize, Deserialize)]
 struct CcaEvidence {
@@ -29,17 +42,25 @@ struct CcaEvidence {
     token: Vec<u8>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-struct RealmToken {
-    //cca_realm_personalization_value: String,
-    cca_realm_initial_measurement: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-struct Evidence {
-    cca_realm_delegated_token: RealmToken,
+/// Load the PEM public keys of the CPAKs at `paths`.
+pub fn load_platform_keys(paths: &[PathBuf]) -> Result<Vec<Vec<u8>>> {
+    paths
+        .iter()
+        .map(|path| {
+            let key_pem = std::fs::read(path)
+                .with_context(|| format!("Failed to read CPAK {}", path.display()))?;
+            let (_, der) = pem::decode_vec(&key_pem)
+                .map_err(|e| anyhow!("Malformed CPAK {}: {e}", path.display()))?;
+            let key = SubjectPublicKeyInfoRef::from_der(&der)
+                .map_err(|e| anyhow!("Malformed CPAK {}: {e}", path.display()))?
+                .subject_public_key
+                .raw_bytes()
+                .to_vec();
+            EcdsaCurve::of_public_key(&key)
+                .with_context(|| format!("Unsupported CPAK {}", path.display()))?;
+            Ok(key)
+        })
+        .collect()
 }
 
 fn my_evidence_builder(
@@ -63,24 +84,6 @@ impl Verifier for CCA {
         let evidence = serde_json::from_str::<CcaEvidence>(&attestation.tee_evidence)
             .context("Deserialize CCA Evidence failed.")?;
 
-        let host_url =
-            std::env::var(VERAISON_ADDR).unwrap_or_else(|_| DEFAULT_VERAISON_ADDR.to_string());
-
-        let discovery = Discovery::from_base_url(format!("http://{:}", host_url))?;
-
-        let verification_api = discovery.get_verification_api().await?;
-
-        let relative_endpoint = verification_api
-            .get_api_endpoint("newChallengeResponseSession")
-            .context("Failed to discover the verification endpoint details.")?;
-
-        let api_endpoint = format!("http://{:}{}", host_url, relative_endpoint);
-
-        // create a ChallengeResponse object
-        let cr = ChallengeResponseBuilder::new()
-            .with_new_session_url(api_endpoint)
-            .build()?;
-
         let mut hasher = Sha384::new();
         hasher.update(&nonce);
         hasher.update(&attestation.tee_pubkey.k_mod);
@@ -93,199 +96,197 @@ impl Verifier for CCA {
             hex::encode(&hash_of_nonce_pubkey)
         );
 
-        let token = evidence.token;
-        let n = Nonce::Value(hash_of_nonce_pubkey.clone());
-        let result = match cr.run(n, my_evidence_builder, token.clone()).await {
-            Err(e) => {
-                log::error!("Error: {}", e);
-                bail!("CCA Attestation failed with error: {:?}", e);
+        let claims = if self.platform_keys.is_empty() {
+            appraise_with_veraison(evidence.token.clone(), &hash_of_nonce_pubkey).await?;
+            // NOTE: For some corner case, the token cannot be parsed correctly but can be
+            // validated successfully by Veraison e.g. `Err` value: Todo("Remaining bytes (00)")'
+            // Instead of throwing an error, just print it out in this case.
+            match CcaToken::parse(&evidence.token) {
+                Ok(token) => token.claims(),
+                Err(e) => {
+                    log::info!("Error: {:?}", e);
+                    Value::Object(Map::new())
+                }
+            }
+        } else {
+            let token = CcaToken::parse(&evidence.token)?;
+            token
+                .verify(&self.platform_keys, self.crypto.as_ref())
+                .await?;
+            if token.realm_challenge()? != hash_of_nonce_pubkey {
+                bail!("HASH(nonce||pubkey) is different from the realm challenge");
             }
-            Ok(attestation_result) => attestation_result,
+            token.claims()
         };
 
-        // Get back the pub key to decrypt the ear which holds raw evidence and the session nonce
-        let public_key_pem = verification_api.ear_verification_key_as_pem()?;
-        let dk = jwt::DecodingKey::from_ec_pem(public_key_pem.as_bytes())
-            .context("get the decoding key from the pem public key")?;
-        let plain_ear = Ear::from_jwt(result.as_str(), jwt::Algorithm::ES256, &dk)
-            .context("decrypt the ear with the decoding key")?;
-
-        let ear_nonce = plain_ear.nonce.context("get nonce from ear")?;
-        let nonce_byte = base64::engine::general_purpose::URL_SAFE
-            .decode(ear_nonce.to_string())
-            .context("decode nonce byte from ear")?;
-
-        if hash_of_nonce_pubkey != nonce_byte {
-            bail!("HASH(nonce||pubkey) is different from that in ear's session nonce");
-        }
+        cca_generate_parsed_claim(claims).map_err(|e| anyhow!("error from CCA Verifier: {:?}", e))
+    }
 
-        // NOTE: The tcb returned is actually an empty `Evidence`, the code here is just a show case the parse of the CCA token
-        // to get the tcb is possible, but this is not actually fully implemented due to the below reasons:
-        // 1. CCA validation by the Verasion has some overlapping with the RVPS, the similar validation has been done by the Verasion already.
-        // 2. Each of key of the CCA token layout after the parse is an int from hex, it cannot be converted into a json easily without
-        // manually manipulation, which is dirty and complex, we can hold this for an while and see if the type of key can be redefined as String.
-        let tcb = parse_cca_token(token)?;
-        // Return Evidence parsed claim
-        cca_generate_parsed_claim(tcb).map_err(|e| anyhow!("error from CCA Verifier: {:?}", e))
+    fn signed_evidence(&self, attestation: &Attestation) -> Result<Vec<u8>> {
+        let evidence = serde_json::from_str::<CcaEvidence>(&attestation.tee_evidence)
+            .context("Deserialize CCA Evidence failed.")?;
+        Ok(evidence.token)
     }
 }
 
-/// The expected token layout looks like below,
-///
-/// In short:
-/// {
-/// "cca-platform-token" (44234): {
-///     ...
-///   },
-/// "cca-realm-delegated-token" (44241): {
-///     ...
-///   }
-/// }
-///
-/// and the details for each of them is listed here:
-///
-/// {
-///     265_1: "http://arm.com/CCA-SSD/1.0.0",
-///     10: h'07060504030201000f0e0d0c0b0a090817161514131211101f1e1d1c1b1a1918',
-///     2396_1: h'7f454c4602010100000000000000000003003e000100000050580000000000004000000000000000a0030200000000000000000040003800090040001c001b00',
-///     256_1: h'0107060504030201000f0e0d0c0b0a090817161514131211101f1e1d1c1b1a1918',
-///     2401_1: h'0107060504030201000f0e0d0c0b0a090817161514131211101f1e1d1c1b1a1918',
-///     2395_1: 12291_1,
-///     2402_1: "sha-256",
-///     2399_1: [
-///         {
-///             1: "BL",
-///             5: h'07060504030201000f0e0d0c0b0a090817161514131211101f1e1d1c1b1a1918',
-///             4: "3.4.2",
-///             2: h'07060504030201000f0e0d0c0b0a090817161514131211101f1e1d1c1b1a1918',
-///             6: "sha-256",
-///         },
-///         {
-///             1: "M1",
-///             5: h'07060504030201000f0e0d0c0b0a090817161514131211101f1e1d1c1b1a1918',
-///             4: "1.2",
-///             2: h'07060504030201000f0e0d0c0b0a090817161514131211101f1e1d1c1b1a1918',
-///         },
-///         {
-///             1: "M2",
-///             5: h'07060504030201000f0e0d0c0b0a090817161514131211101f1e1d1c1b1a1918',
-///             4: "1.2.3",
-///             2: h'07060504030201000f0e0d0c0b0a090817161514131211101f1e1d1c1b1a1918',
-///         },
-///         {
-///             1: "M3",
-///             5: h'07060504030201000f0e0d0c0b0a090817161514131211101f1e1d1c1b1a1918',
-///             4: "1",
-///             2: h'07060504030201000f0e0d0c0b0a090817161514131211101f1e1d1c1b1a1918',
-///         },
-///     ],
-///     2400_1: "whatever.com",
-/// }
-/// {
-///     10: h'00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000',
-///     44235_1: h'00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000',
-///     44237_1: h'0476f988091be585ed41801aecfab858548c63057e16b0e676120bbd0d2f9c29e056c5d41a0130eb9c21517899dc23146b28e1b062bd3ea4b315fd219f1cbb528cb6e74ca49be16773734f61a1ca61031b2bbf3d918f2f94ffc4228e50919544ae',
-///     44236_1: "sha-256",
-///     44240_1: "sha-256",
-///     44238_1: h'75a1fbc79a7d20a5ff843b914dfd8093d40cd07dd633401c8c42d697be224801',
-///     44239_1: [
-///         h'0000000000000000000000000000000000000000000000000000000000000000',
-///         h'0000000000000000000000000000000000000000000000000000000000000000',
-///         h'0000000000000000000000000000000000000000000000000000000000000000',
-///         h'0000000000000000000000000000000000000000000000000000000000000000',
-///     ],
-/// }
-fn parse_cca_token(token: Vec<u8>) -> Result<Evidence> {
-    let evidence = Evidence {
-        cca_realm_delegated_token: RealmToken {
-            cca_realm_initial_measurement: "".to_string(),
-        },
-    };
+/// Appraise `token` with the Veraison verifier of `VERAISON_ADDR`, which
+/// verifies it and its challenge, `hash_of_nonce_pubkey`.
+async fn appraise_with_veraison(token: Vec<u8>, hash_of_nonce_pubkey: &[u8]) -> Result<()> {
+    let host_url =
+        std::env::var(VERAISON_ADDR).unwrap_or_else(|_| DEFAULT_VERAISON_ADDR.to_string());
+
+    let discovery = Discovery::from_base_url(format!("http://{:}", host_url))?;
+
+    let verification_api = discovery.get_verification_api().await?;
+
+    let relative_endpoint = verification_api
+        .get_api_endpoint("newChallengeResponseSession")
+        .context("Failed to discover the verification endpoint details.")?;
+
+    let api_endpoint = format!("http://{:}{}", host_url, relative_endpoint);
 
-    // NOTE: For some corner case, the date cannot be parsed to token correctly but the token
-    // can be validated successfully by the CCA verifier e.g. `Err` value: Todo("Remaining bytes (00)")'
-    // Instead of throwing an error, just print it out in this case.
-    let mut di = match cbor_diag::parse_bytes(token) {
-        Ok(di) => di,
-        Err(err) => {
-            log::info!("Error: {:?}", err);
-            return Ok(evidence);
+    // create a ChallengeResponse object
+    let cr = ChallengeResponseBuilder::new()
+        .with_new_session_url(api_endpoint)
+        .build()?;
+
+    let n = Nonce::Value(hash_of_nonce_pubkey.to_vec());
+    let result = match cr.run(n, my_evidence_builder, token).await {
+        Err(e) => {
+            log::error!("Error: {}", e);
+            bail!("CCA Attestation failed with error: {:?}", e);
         }
+        Ok(attestation_result) => attestation_result,
     };
 
-    if let cbor_diag::DataItem::Tag {
-        tag: _,
-        bitwidth: _,
-        value,
-    } = di
-    {
-        di = *value;
-    }
+    // Get back the pub key to decrypt the ear which holds raw evidence and the session nonce
+    let public_key_pem = verification_api.ear_verification_key_as_pem()?;
+    let dk = jwt::DecodingKey::from_ec_pem(public_key_pem.as_bytes())
+        .context("get the decoding key from the pem public key")?;
+    let plain_ear = Ear::from_jwt(result.as_str(), jwt::Algorithm::ES256, &dk)
+        .context("decrypt the ear with the decoding key")?;
 
-    if let cbor_diag::DataItem::Map { data, .. } = di {
-        for item in data {
-            let cbor_diag::DataItem::ByteString(t) = item.1 else {
-                anyhow::bail!("DateItem is not a ByteString");
-            };
-
-            let val = cbor_diag::parse_bytes(t.data)?;
-
-            let cbor_diag::DataItem::Tag { value, .. } = val else {
-                anyhow::bail!("DateItem is not a Tag");
-            };
-
-            let cbor_diag::DataItem::Array { data, .. } = *value else {
-                anyhow::bail!("DateItem is not a Array");
-            };
-
-            if let cbor_diag::DataItem::ByteString(cose) = data
-                .get(2)
-                .ok_or_else(|| anyhow!("Cannot get raw bytes from token"))?
-            {
-                let v = &cose.data;
-                match cbor_diag::parse_bytes(v) {
-                    Ok(claims) => {
-                        info!("{}", claims.to_diag_pretty());
-                    }
-                    Err(e) => {
-                        error!("Error parsing claims: {}", e);
-                    }
-                }
-            };
-        }
+    let ear_nonce = plain_ear.nonce.context("get nonce from ear")?;
+    let nonce_byte = base64::engine::general_purpose::URL_SAFE
+        .decode(ear_nonce.to_string())
+        .context("decode nonce byte from ear")?;
+
+    if hash_of_nonce_pubkey != nonce_byte {
+        bail!("HASH(nonce||pubkey) is different from that in ear's session nonce");
     }
 
-    Ok(evidence)
+    Ok(())
 }
 
-fn cca_generate_parsed_claim(tcb: Evidence) -> Result<TeeEvidenceParsedClaim> {
+/// The claims of the tokens, flattened into `cca-platform-*` and
+/// `cca-realm-*` claims, e.g. `cca-realm-initial-measurement`, with the
+/// TCB status from the security lifecycle state of the platform.
+fn cca_generate_parsed_claim(tokens: Value) -> Result<TeeEvidenceParsedClaim> {
     let mut claim_map = Map::new();
-
-    claim_map.insert(
-        "cca-realm-initial-measurement".to_string(),
-        serde_json::Value::String(tcb.cca_realm_delegated_token.cca_realm_initial_measurement),
-    );
-
-    log::info!("\nParsed Evidence claims map: \n{:?}\n", &claim_map);
-
-    Ok(Value::Object(claim_map) as TeeEvidenceParsedClaim)
+    for token in [REALM_TOKEN_CLAIM, PLATFORM_TOKEN_CLAIM] {
+        if let Some(Value::Object(claims)) = tokens.get(token) {
+            claim_map.extend(claims.clone());
+        }
+    }
+    let lifecycle = claim_map
+        .get(PLATFORM_LIFECYCLE)
+        .and_then(Value::as_u64)
+        .and_then(|lifecycle| u16::try_from(lifecycle).ok());
+
+    let mut claims = Value::Object(claim_map);
+    if let Some(lifecycle) = lifecycle {
+        super::tcb::from_cca_lifecycle(lifecycle).add_claims(&mut claims)?;
+    }
+    Ok(TeeEvidenceParsedClaim::from(claims))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::verifier::conformance::{self, Conformance};
     use std::fs;
 
     #[test]
     fn test_cca_generate_parsed_claim() {
         let s = fs::read("../test_data/cca-claims.json").unwrap();
         let evidence = String::from_utf8_lossy(&s);
-        let tcb = serde_json::from_str::<Evidence>(&evidence).unwrap();
-        let parsed_claim = cca_generate_parsed_claim(tcb);
+        let tokens = serde_json::from_str::<Value>(&evidence).unwrap();
+        let parsed_claim = cca_generate_parsed_claim(tokens);
         assert!(parsed_claim.is_ok());
+        assert_eq!(
+            parsed_claim
+                .as_ref()
+                .unwrap()
+                .get_str("cca-realm-personalization-value"),
+            Some("QURBREFEQURBREFEQURBREFEQURBREFEQURBREFEQURBREFEQURBREFEQURBREFEQURBREFEQURBREFEQURBRA==")
+        );
+        // The test platform is secured.
+        assert_eq!(
+            parsed_claim.as_ref().unwrap().get_str("tcb_status"),
+            Some("UpToDate")
+        );
         let _ = fs::write(
             "test_data/cca_evidence_claim_output.txt",
             format!("{:?}", parsed_claim.unwrap()),
         );
     }
+
+    #[tokio::test]
+    async fn conformance() {
+        conformance::run(Conformance {
+            tee: Tee::Cca,
+            verifier: CCA {
+                platform_keys: Vec::new(),
+                crypto: CryptoBackendType::default().to_backend().unwrap(),
+            },
+            malformed: vec![serde_json::json!({ "token": "not base64!" }).to_string()],
+            evidence: None,
+            claims: None,
+            debug_claim: None,
+        })
+        .await;
+    }
+
+    #[cfg(feature = "crypto-openssl")]
+    #[tokio::test]
+    async fn test_local_verification() {
+        use super::token::tests::{cca_token, cpak, public_key};
+
+        let cpak = cpak();
+        let verifier = CCA {
+            platform_keys: vec![public_key(&cpak)],
+            crypto: CryptoBackendType::OpenSSL.to_backend().unwrap(),
+        };
+        let tee_pubkey = conformance::dummy_tee_pubkey();
+        let mut challenge = Sha384::new()
+            .chain_update("nonce")
+            .chain_update(&tee_pubkey.k_mod)
+            .chain_update(&tee_pubkey.k_exp)
+            .finalize()
+            .to_vec();
+        challenge.resize(64, 0);
+        let attestation = Attestation {
+            tee_pubkey,
+            tee_evidence: serde_json::to_string(&CcaEvidence {
+                token: cca_token(&challenge, &cpak),
+            })
+            .unwrap(),
+        };
+
+        let claims = verifier
+            .evaluate("nonce".to_string(), &attestation)
+            .await
+            .unwrap();
+        assert_eq!(
+            claims.get_str("cca-realm-initial-measurement"),
+            Some("Q0NDQ0NDQ0NDQ0NDQ0NDQ0NDQ0NDQ0NDQ0NDQ0NDQ0M=")
+        );
+        assert_eq!(claims.get_str("tcb_status"), Some("UpToDate"));
+
+        let err = verifier
+            .evaluate("other".to_string(), &attestation)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("realm challenge"));
+    }
 }
@@ -0,0 +1,591 @@
+//! CCA attestation tokens.
+//!
+//! A CCA token is a CBOR map of two COSE_Sign1 tokens: the platform token,
+//! signed by the CCA platform attestation key (CPAK) of the platform, and
+//! the realm token, signed by the realm attestation key (RAK). The
+//! challenge of the platform token is the hash of the RAK public key, which
+//! binds the realm token to the platform. Their claims are decoded to JSON
+//! under the names of the CCA security domain profile, byte strings in
+//! base64, the layout of `test_data/cca-claims.json`.
+
+use crate::verifier::crypto::{CryptoBackend, EcdsaCurve};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use cbor_diag::{ByteString, DataItem, IntegerWidth, Tag, TextString};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+/// Keys of the tokens in a CCA token.
+const CCA_PLATFORM_TOKEN: u64 = 44234;
+const CCA_REALM_DELEGATED_TOKEN: u64 = 44241;
+
+const COSE_SIGN1_TAG: u64 = 18;
+
+/// COSE algorithms of the tokens.
+const COSE_ES256: i64 = -7;
+const COSE_ES384: i64 = -35;
+
+/// COSE_Key labels of the RAK public key.
+const COSE_KEY_KTY: i64 = 1;
+const COSE_KEY_CRV: i64 = -1;
+const COSE_KEY_X: i64 = -2;
+const COSE_KEY_Y: i64 = -3;
+const COSE_KTY_EC2: i64 = 2;
+const COSE_CRV_P384: i64 = 2;
+
+pub(crate) const PLATFORM_TOKEN_CLAIM: &str = "cca-platform-token";
+pub(crate) const REALM_TOKEN_CLAIM: &str = "cca-realm-delegated-token";
+
+pub(crate) const PLATFORM_CHALLENGE: &str = "cca-platform-challenge";
+pub(crate) const PLATFORM_LIFECYCLE: &str = "cca-platform-lifecycle";
+pub(crate) const REALM_CHALLENGE: &str = "cca-realm-challenge";
+const REALM_PUBLIC_KEY: &str = "cca-realm-public-key";
+const REALM_PUBLIC_KEY_HASH_ALGO_ID: &str = "cca-realm-public-key-hash-algo-id";
+
+const PLATFORM_CLAIMS: &[(u64, &str)] = &[
+    (265, "cca-platform-profile"),
+    (10, PLATFORM_CHALLENGE),
+    (2396, "cca-platform-implementation-id"),
+    (256, "cca-platform-instance-id"),
+    (2401, "cca-platform-config"),
+    (2395, PLATFORM_LIFECYCLE),
+    (2399, "cca-platform-sw-components"),
+    (2400, "cca-platform-service-indicator"),
+    (2402, "cca-platform-hash-algo-id"),
+];
+
+const REALM_CLAIMS: &[(u64, &str)] = &[
+    (265, "cca-realm-profile"),
+    (10, REALM_CHALLENGE),
+    (44235, "cca-realm-personalization-value"),
+    (44236, "cca-realm-hash-algo-id"),
+    (44237, REALM_PUBLIC_KEY),
+    (44238, "cca-realm-initial-measurement"),
+    (44239, "cca-realm-extensible-measurements"),
+    (44240, REALM_PUBLIC_KEY_HASH_ALGO_ID),
+];
+
+/// Claims of the software components of a platform token.
+const SW_COMPONENT_CLAIMS: &[(u64, &str)] = &[
+    (1, "measurement-type"),
+    (2, "measurement-value"),
+    (4, "version"),
+    (5, "signer-id"),
+    (6, "hash-algo-id"),
+];
+
+fn byte_string(item: &DataItem) -> Option<&[u8]> {
+    match item {
+        DataItem::ByteString(ByteString { data, .. }) => Some(data),
+        _ => None,
+    }
+}
+
+fn integer(item: &DataItem) -> Option<i64> {
+    match item {
+        DataItem::Integer { value, .. } => i64::try_from(*value).ok(),
+        DataItem::Negative { value, .. } => i64::try_from(*value).ok().map(|value| -1 - value),
+        _ => None,
+    }
+}
+
+/// A COSE_Sign1 token.
+struct CoseSign1 {
+    protected: Vec<u8>,
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+    alg: i64,
+}
+
+impl CoseSign1 {
+    fn parse(name: &str, cose: &[u8]) -> Result<Self> {
+        let malformed = || anyhow!("Malformed CCA {name} token");
+        let item = cbor_diag::parse_bytes(cose).map_err(|_| malformed())?;
+        let item = match item {
+            DataItem::Tag {
+                tag: Tag(COSE_SIGN1_TAG),
+                value,
+                ..
+            } => *value,
+            item => item,
+        };
+        let DataItem::Array { data, .. } = item else {
+            bail!(malformed());
+        };
+        let [protected, _, payload, signature] = data.as_slice() else {
+            bail!(malformed());
+        };
+        let protected = byte_string(protected).ok_or_else(malformed)?.to_vec();
+        let payload = byte_string(payload).ok_or_else(malformed)?.to_vec();
+        let signature = byte_string(signature).ok_or_else(malformed)?.to_vec();
+
+        let DataItem::Map { data: headers, .. } =
+            cbor_diag::parse_bytes(&protected).map_err(|_| malformed())?
+        else {
+            bail!(malformed());
+        };
+        let alg = headers
+            .iter()
+            .find(|(label, _)| integer(label) == Some(1))
+            .and_then(|(_, alg)| integer(alg))
+            .ok_or_else(|| anyhow!("The CCA {name} token has no algorithm"))?;
+
+        Ok(Self {
+            protected,
+            payload,
+            signature,
+            alg,
+        })
+    }
+
+    /// The Sig_structure the signature is over.
+    fn to_be_signed(&self) -> Vec<u8> {
+        let byte_string = |data: &[u8]| {
+            DataItem::ByteString(ByteString {
+                data: data.to_vec(),
+                bitwidth: IntegerWidth::Unknown,
+            })
+        };
+        DataItem::Array {
+            data: vec![
+                DataItem::TextString(TextString {
+                    data: "Signature1".to_string(),
+                    bitwidth: IntegerWidth::Unknown,
+                }),
+                byte_string(&self.protected),
+                byte_string(&[]),
+                byte_string(&self.payload),
+            ],
+            bitwidth: Some(IntegerWidth::Unknown),
+        }
+        .to_bytes()
+    }
+
+    /// Verify the signature with `public_key`, an uncompressed SEC1 point.
+    async fn verify(
+        &self,
+        public_key: &[u8],
+        crypto: &(dyn CryptoBackend + Send + Sync),
+    ) -> Result<()> {
+        let curve = EcdsaCurve::of_public_key(public_key)?;
+        match (self.alg, curve) {
+            (COSE_ES256, EcdsaCurve::P256) | (COSE_ES384, EcdsaCurve::P384) => {}
+            (alg, curve) => bail!("Unsupported COSE algorithm {alg} for a {curve:?} key"),
+        }
+        if self.signature.len() != curve.scalar_size() * 2 {
+            bail!("Malformed signature");
+        }
+        let (r, s) = self.signature.split_at(curve.scalar_size());
+        crypto
+            .verify_ecdsa_with_key(public_key, &self.to_be_signed(), r, s)
+            .await
+    }
+
+    /// The claims of the payload, named after `names`.
+    fn claims(&self, name: &str, names: &[(u64, &str)]) -> Result<Map<String, Value>> {
+        let payload = cbor_diag::parse_bytes(&self.payload)
+            .map_err(|_| anyhow!("Malformed CCA {name} token claims"))?;
+        match to_json(&payload, names) {
+            Value::Object(claims) => Ok(claims),
+            _ => bail!("Malformed CCA {name} token claims"),
+        }
+    }
+}
+
+fn claim_name(key: &DataItem, names: &[(u64, &str)]) -> String {
+    match key {
+        DataItem::Integer { value, .. } => names
+            .iter()
+            .find(|(key, _)| key == value)
+            .map(|(_, name)| name.to_string())
+            .unwrap_or_else(|| value.to_string()),
+        DataItem::TextString(TextString { data, .. }) => data.clone(),
+        key => key.to_diag(),
+    }
+}
+
+/// The JSON of a claim, with the keys of maps named after `names`, and
+/// those of the maps in arrays, the software components, after
+/// [`SW_COMPONENT_CLAIMS`].
+fn to_json(item: &DataItem, names: &[(u64, &str)]) -> Value {
+    match item {
+        DataItem::Integer { value, .. } => (*value).into(),
+        DataItem::Negative { .. } => integer(item).map_or(Value::Null, Value::from),
+        DataItem::ByteString(ByteString { data, .. }) => base64::engine::general_purpose::STANDARD
+            .encode(data)
+            .into(),
+        DataItem::TextString(TextString { data, .. }) => data.clone().into(),
+        DataItem::Array { data, .. } => data
+            .iter()
+            .map(|item| to_json(item, SW_COMPONENT_CLAIMS))
+            .collect(),
+        DataItem::Map { data, .. } => Value::Object(
+            data.iter()
+                .map(|(key, value)| (claim_name(key, names), to_json(value, names)))
+                .collect(),
+        ),
+        DataItem::Tag { value, .. } => to_json(value, names),
+        _ => Value::Null,
+    }
+}
+
+/// A byte string claim.
+fn bytes_claim(claims: &Map<String, Value>, name: &str) -> Result<Vec<u8>> {
+    let value = claims
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("The CCA token has no {name}"))?;
+    base64::engine::general_purpose::STANDARD
+        .decode(value)
+        .with_context(|| format!("Malformed {name}"))
+}
+
+/// The RAK public key as an uncompressed SEC1 point, from the realm public
+/// key claim: the point itself, or a COSE_Key of it.
+fn realm_public_key(claim: &[u8]) -> Result<Vec<u8>> {
+    if EcdsaCurve::of_public_key(claim).is_ok() {
+        return Ok(claim.to_vec());
+    }
+    let malformed = || anyhow!("Malformed realm public key");
+    let DataItem::Map { data, .. } = cbor_diag::parse_bytes(claim).map_err(|_| malformed())? else {
+        bail!(malformed());
+    };
+    let label = |label: i64| {
+        data.iter()
+            .find(|(key, _)| integer(key) == Some(label))
+            .map(|(_, value)| value)
+    };
+    if label(COSE_KEY_KTY).and_then(integer) != Some(COSE_KTY_EC2)
+        || label(COSE_KEY_CRV).and_then(integer) != Some(COSE_CRV_P384)
+    {
+        bail!("The realm public key is not a P-384 key");
+    }
+    let x = label(COSE_KEY_X)
+        .and_then(byte_string)
+        .ok_or_else(malformed)?;
+    let y = label(COSE_KEY_Y)
+        .and_then(byte_string)
+        .ok_or_else(malformed)?;
+    Ok([&[0x04], x, y].concat())
+}
+
+/// The hash of `data` with the named algorithm of a CCA token.
+fn hash(algorithm: &str, data: &[u8]) -> Result<Vec<u8>> {
+    match algorithm {
+        "sha-256" => Ok(Sha256::digest(data).to_vec()),
+        "sha-384" => Ok(Sha384::digest(data).to_vec()),
+        "sha-512" => Ok(Sha512::digest(data).to_vec()),
+        algorithm => bail!("Unsupported hash algorithm {algorithm}"),
+    }
+}
+
+/// A parsed CCA token.
+pub(crate) struct CcaToken {
+    platform: CoseSign1,
+    realm: CoseSign1,
+    pub platform_claims: Map<String, Value>,
+    pub realm_claims: Map<String, Value>,
+}
+
+impl CcaToken {
+    pub fn parse(token: &[u8]) -> Result<Self> {
+        let malformed = || anyhow!("Malformed CCA token");
+        let item = cbor_diag::parse_bytes(token).map_err(|_| malformed())?;
+        let item = match item {
+            DataItem::Tag { value, .. } => *value,
+            item => item,
+        };
+        let DataItem::Map { data, .. } = item else {
+            bail!(malformed());
+        };
+        let token = |key: u64| {
+            data.iter()
+                .find(|(label, _)| integer(label) == i64::try_from(key).ok())
+                .and_then(|(_, token)| byte_string(token))
+        };
+
+        let platform = CoseSign1::parse(
+            "platform",
+            token(CCA_PLATFORM_TOKEN).ok_or_else(|| anyhow!("No CCA platform token"))?,
+        )?;
+        let realm = CoseSign1::parse(
+            "realm",
+            token(CCA_REALM_DELEGATED_TOKEN).ok_or_else(|| anyhow!("No CCA realm token"))?,
+        )?;
+        Ok(Self {
+            platform_claims: platform.claims("platform", PLATFORM_CLAIMS)?,
+            realm_claims: realm.claims("realm", REALM_CLAIMS)?,
+            platform,
+            realm,
+        })
+    }
+
+    /// The claims of both tokens.
+    pub fn claims(&self) -> Value {
+        let mut claims = Map::new();
+        claims.insert(
+            PLATFORM_TOKEN_CLAIM.to_string(),
+            self.platform_claims.clone().into(),
+        );
+        claims.insert(
+            REALM_TOKEN_CLAIM.to_string(),
+            self.realm_claims.clone().into(),
+        );
+        claims.into()
+    }
+
+    pub fn realm_challenge(&self) -> Result<Vec<u8>> {
+        bytes_claim(&self.realm_claims, REALM_CHALLENGE)
+    }
+
+    /// Verify the realm token with the RAK, that the platform token binds
+    /// the RAK, and the platform token with one of the `platform_keys`, the
+    /// CPAKs of the trusted platforms.
+    pub async fn verify(
+        &self,
+        platform_keys: &[Vec<u8>],
+        crypto: &(dyn CryptoBackend + Send + Sync),
+    ) -> Result<()> {
+        let rak = realm_public_key(&bytes_claim(&self.realm_claims, REALM_PUBLIC_KEY)?)?;
+        self.realm
+            .verify(&rak, crypto)
+            .await
+            .context("Invalid CCA realm token signature")?;
+
+        let rak_hash_algorithm = self
+            .realm_claims
+            .get(REALM_PUBLIC_KEY_HASH_ALGO_ID)
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("The CCA token has no {REALM_PUBLIC_KEY_HASH_ALGO_ID}"))?;
+        let rak_claim = bytes_claim(&self.realm_claims, REALM_PUBLIC_KEY)?;
+        if bytes_claim(&self.platform_claims, PLATFORM_CHALLENGE)?
+            != hash(rak_hash_algorithm, &rak_claim)?
+        {
+            bail!("The CCA platform token does not bind the realm public key");
+        }
+
+        for key in platform_keys {
+            if self.platform.verify(key, crypto).await.is_ok() {
+                return Ok(());
+            }
+        }
+        bail!("The CCA platform token is not signed by a trusted CPAK")
+    }
+}
+
+#[cfg(all(test, feature = "crypto-openssl"))]
+pub(crate) mod tests {
+    use super::*;
+    use crate::verifier::crypto::CryptoBackendType;
+    use openssl::bn::BigNumContext;
+    use openssl::ec::{EcGroup, EcKey, PointConversionForm};
+    use openssl::ecdsa::EcdsaSig;
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::Private;
+
+    fn bytes(data: &[u8]) -> DataItem {
+        DataItem::ByteString(ByteString {
+            data: data.to_vec(),
+            bitwidth: IntegerWidth::Unknown,
+        })
+    }
+
+    fn text(data: &str) -> DataItem {
+        DataItem::TextString(TextString {
+            data: data.to_string(),
+            bitwidth: IntegerWidth::Unknown,
+        })
+    }
+
+    fn uint(value: u64) -> DataItem {
+        DataItem::Integer {
+            value,
+            bitwidth: IntegerWidth::Unknown,
+        }
+    }
+
+    fn map(data: Vec<(DataItem, DataItem)>) -> DataItem {
+        DataItem::Map {
+            data,
+            bitwidth: Some(IntegerWidth::Unknown),
+        }
+    }
+
+    pub(crate) fn public_key(key: &EcKey<Private>) -> Vec<u8> {
+        key.public_key()
+            .to_bytes(
+                key.group(),
+                PointConversionForm::UNCOMPRESSED,
+                &mut BigNumContext::new().unwrap(),
+            )
+            .unwrap()
+    }
+
+    /// A COSE_Sign1 token of `payload` signed with `key`.
+    fn sign(payload: DataItem, key: &EcKey<Private>) -> Vec<u8> {
+        let (alg, digest, size) = match key.group().curve_name() {
+            Some(Nid::X9_62_PRIME256V1) => (COSE_ES256, MessageDigest::sha256(), 32),
+            _ => (COSE_ES384, MessageDigest::sha384(), 48),
+        };
+        let protected = map(vec![(
+            uint(1),
+            DataItem::Negative {
+                value: (-1 - alg) as u64,
+                bitwidth: IntegerWidth::Unknown,
+            },
+        )])
+        .to_bytes();
+        let mut cose = CoseSign1 {
+            protected,
+            payload: payload.to_bytes(),
+            signature: Vec::new(),
+            alg,
+        };
+        let digest = openssl::hash::hash(digest, &cose.to_be_signed()).unwrap();
+        let signature = EcdsaSig::sign(&digest, key).unwrap();
+        cose.signature = [
+            signature.r().to_vec_padded(size).unwrap(),
+            signature.s().to_vec_padded(size).unwrap(),
+        ]
+        .concat();
+
+        DataItem::Tag {
+            tag: Tag(COSE_SIGN1_TAG),
+            bitwidth: IntegerWidth::Unknown,
+            value: Box::new(DataItem::Array {
+                data: vec![
+                    bytes(&cose.protected),
+                    map(Vec::new()),
+                    bytes(&cose.payload),
+                    bytes(&cose.signature),
+                ],
+                bitwidth: Some(IntegerWidth::Unknown),
+            }),
+        }
+        .to_bytes()
+    }
+
+    /// A CCA token of a realm with `challenge`, signed with a fresh RAK and
+    /// `cpak`.
+    pub(crate) fn cca_token(challenge: &[u8], cpak: &EcKey<Private>) -> Vec<u8> {
+        let rak = EcKey::generate(&EcGroup::from_curve_name(Nid::SECP384R1).unwrap()).unwrap();
+        let rak_public_key = public_key(&rak);
+
+        let realm = map(vec![
+            (uint(10), bytes(challenge)),
+            (uint(44235), bytes(&[0x44; 64])),
+            (uint(44236), text("sha-256")),
+            (uint(44237), bytes(&rak_public_key)),
+            (uint(44238), bytes(&[0x43; 32])),
+            (
+                uint(44239),
+                DataItem::Array {
+                    data: vec![bytes(&[0; 32]); 4],
+                    bitwidth: Some(IntegerWidth::Unknown),
+                },
+            ),
+            (uint(44240), text("sha-256")),
+        ]);
+        let platform = map(vec![
+            (uint(265), text("http://arm.com/CCA-SSD/1.0.0")),
+            (uint(10), bytes(&Sha256::digest(&rak_public_key))),
+            (uint(2395), uint(0x3000)),
+            (
+                uint(2399),
+                DataItem::Array {
+                    data: vec![map(vec![
+                        (uint(1), text("BL")),
+                        (uint(2), bytes(&[0x03; 32])),
+                        (uint(4), text("3.4.2")),
+                    ])],
+                    bitwidth: Some(IntegerWidth::Unknown),
+                },
+            ),
+            (uint(2402), text("sha-256")),
+        ]);
+
+        DataItem::Tag {
+            tag: Tag(399),
+            bitwidth: IntegerWidth::Unknown,
+            value: Box::new(map(vec![
+                (uint(CCA_PLATFORM_TOKEN), bytes(&sign(platform, cpak))),
+                (uint(CCA_REALM_DELEGATED_TOKEN), bytes(&sign(realm, &rak))),
+            ])),
+        }
+        .to_bytes()
+    }
+
+    pub(crate) fn cpak() -> EcKey<Private> {
+        EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let crypto = CryptoBackendType::OpenSSL.to_backend().unwrap();
+        let cpak = cpak();
+        let token = CcaToken::parse(&cca_token(&[0x41; 64], &cpak)).unwrap();
+
+        assert_eq!(token.realm_challenge().unwrap(), [0x41; 64]);
+        let claims = token.claims();
+        assert_eq!(
+            claims[REALM_TOKEN_CLAIM]["cca-realm-initial-measurement"],
+            base64::engine::general_purpose::STANDARD.encode([0x43; 32])
+        );
+        assert_eq!(
+            claims[REALM_TOKEN_CLAIM]["cca-realm-extensible-measurements"]
+                .as_array()
+                .unwrap()
+                .len(),
+            4
+        );
+        assert_eq!(claims[PLATFORM_TOKEN_CLAIM][PLATFORM_LIFECYCLE], 0x3000);
+        assert_eq!(
+            claims[PLATFORM_TOKEN_CLAIM]["cca-platform-sw-components"][0]["version"],
+            "3.4.2"
+        );
+
+        token
+            .verify(&[public_key(&cpak)], crypto.as_ref())
+            .await
+            .unwrap();
+        let err = token
+            .verify(&[public_key(&self::cpak())], crypto.as_ref())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("trusted CPAK"));
+    }
+
+    #[test]
+    fn test_realm_public_key() {
+        let point = [[0x04].as_slice(), &[1; 48], &[2; 48]].concat();
+        assert_eq!(realm_public_key(&point).unwrap(), point);
+
+        let cose_key = map(vec![
+            (uint(1), uint(2)),
+            (
+                DataItem::Negative {
+                    value: 0,
+                    bitwidth: IntegerWidth::Unknown,
+                },
+                uint(2),
+            ),
+            (
+                DataItem::Negative {
+                    value: 1,
+                    bitwidth: IntegerWidth::Unknown,
+                },
+                bytes(&[1; 48]),
+            ),
+            (
+                DataItem::Negative {
+                    value: 2,
+                    bitwidth: IntegerWidth::Unknown,
+                },
+                bytes(&[2; 48]),
+            ),
+        ]);
+        assert_eq!(realm_public_key(&cose_key.to_bytes()).unwrap(), point);
+        assert!(realm_public_key(&[0x04; 10]).is_err());
+    }
+}
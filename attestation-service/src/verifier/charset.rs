@@ -0,0 +1,148 @@
+//! Charset of the strings of eventlogs.
+//!
+//! Strings of event data, such as the kernel command line measured by
+//! td-shim, are UTF-8 most of the time, but some firmware measures them as
+//! UTF-16LE, as UEFI strings are, and some have stray non-UTF-8 bytes. With
+//! `eventlog_strings` in the AS config set to:
+//!
+//! - `strict`, the default: UTF-8 and UTF-16LE strings are decoded, and
+//!   evidence with any other is rejected;
+//! - `lossy`: the invalid sequences of a string are replaced with U+FFFD,
+//!   instead of rejecting the evidence.
+//!
+//! UTF-16LE strings are told by their byte order mark, or by a zero high
+//! byte in every code unit, as ASCII strings have. The encoding a string
+//! was decoded with is in a claim next to it, e.g.
+//! `ccel.kernel_parameters_encoding` for the kernel command line: `utf-8`,
+//! `utf-16le`, `utf-8-lossy` or `utf-16le-lossy`, so that policies can
+//! reject those decoded lossily.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+/// Byte order mark of UTF-16LE strings.
+const UTF16LE_BOM: [u8; 2] = [0xff, 0xfe];
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventlogStrings {
+    /// Reject strings that are neither UTF-8 nor UTF-16LE.
+    #[default]
+    Strict,
+    /// Replace the invalid sequences of strings with U+FFFD.
+    Lossy,
+}
+
+/// The encoding a string of an eventlog was decoded with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf8Lossy,
+    Utf16LeLossy,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf8Lossy => "utf-8-lossy",
+            Encoding::Utf16LeLossy => "utf-16le-lossy",
+        }
+    }
+}
+
+/// Whether `data` looks like a UTF-16LE string: it has the byte order mark,
+/// or code units whose high bytes are all zero.
+fn is_utf16le(data: &[u8]) -> bool {
+    if data.starts_with(&UTF16LE_BOM) {
+        return data.len() % 2 == 0;
+    }
+    data.len() >= 2
+        && data.len() % 2 == 0
+        && data[0] != 0
+        && data.iter().skip(1).step_by(2).all(|b| *b == 0)
+}
+
+impl EventlogStrings {
+    /// Decode the string `data` of an eventlog, and return the encoding it
+    /// was decoded with.
+    pub fn decode(self, data: &[u8]) -> Result<(String, Encoding)> {
+        if is_utf16le(data) {
+            let data = data.strip_prefix(&UTF16LE_BOM).unwrap_or(data);
+            let units: Vec<u16> = data
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .collect();
+            return match (String::from_utf16(&units), self) {
+                (Ok(string), _) => Ok((string, Encoding::Utf16Le)),
+                (Err(_), EventlogStrings::Lossy) => {
+                    Ok((String::from_utf16_lossy(&units), Encoding::Utf16LeLossy))
+                }
+                (Err(e), EventlogStrings::Strict) => {
+                    bail!("Invalid UTF-16LE string: {e}, see `eventlog_strings`")
+                }
+            };
+        }
+
+        match (std::str::from_utf8(data), self) {
+            (Ok(string), _) => Ok((string.to_string(), Encoding::Utf8)),
+            (Err(_), EventlogStrings::Lossy) => Ok((
+                String::from_utf8_lossy(data).into_owned(),
+                Encoding::Utf8Lossy,
+            )),
+            (Err(e), EventlogStrings::Strict) => {
+                bail!("Invalid UTF-8 string: {e}, see `eventlog_strings`")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16le(string: &str) -> Vec<u8> {
+        string.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn test_decode() {
+        for mode in [EventlogStrings::Strict, EventlogStrings::Lossy] {
+            assert_eq!(
+                mode.decode(b"console=hvc0 rw\0").unwrap(),
+                ("console=hvc0 rw\0".to_string(), Encoding::Utf8)
+            );
+            assert_eq!(
+                mode.decode(&utf16le("console=hvc0 rw")).unwrap(),
+                ("console=hvc0 rw".to_string(), Encoding::Utf16Le)
+            );
+            let mut bom = UTF16LE_BOM.to_vec();
+            bom.extend(utf16le("rw"));
+            assert_eq!(
+                mode.decode(&bom).unwrap(),
+                ("rw".to_string(), Encoding::Utf16Le)
+            );
+            assert_eq!(mode.decode(b"").unwrap(), (String::new(), Encoding::Utf8));
+        }
+
+        let e = EventlogStrings::Strict
+            .decode(b"root=/dev/vda\xff")
+            .unwrap_err();
+        assert!(e.to_string().contains("see `eventlog_strings`"));
+        assert_eq!(
+            EventlogStrings::Lossy.decode(b"root=/dev/vda\xff").unwrap(),
+            ("root=/dev/vda\u{fffd}".to_string(), Encoding::Utf8Lossy)
+        );
+
+        // An unpaired surrogate.
+        let mut unpaired = UTF16LE_BOM.to_vec();
+        unpaired.extend([b'r', 0, 0x00, 0xd8]);
+        assert!(EventlogStrings::Strict.decode(&unpaired).is_err());
+        assert_eq!(
+            EventlogStrings::Lossy.decode(&unpaired).unwrap(),
+            ("r\u{fffd}".to_string(), Encoding::Utf16LeLossy)
+        );
+    }
+}
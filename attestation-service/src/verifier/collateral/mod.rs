@@ -0,0 +1,802 @@
+//! DCAP collateral of the verification of TDX and SGX quotes.
+//!
+//! Quotes are verified against the TCB info of the platform, the identity
+//! of the quoting enclave (QE) and the CRLs of the PCK certificates, all
+//! signed by Intel. By default, the quote provider library fetches them as
+//! its QCNL config says. With `collateral` in the AS config, the AS fetches
+//! them itself from the PCCS, or Intel PCS, of `pccs_url`, by the FMSPC and
+//! the CA of the PCK certificate of the quote, see [`pck`]:
+//!
+//! ```json
+//! "collateral": {
+//!     "pccs_url": "https://pccs.example:8081",
+//!     "cache_dir": "/var/lib/attestation-service/collateral"
+//! }
+//! ```
+//!
+//! Fetched collateral is cached in memory, and in `cache_dir` if set, and
+//! fetched again `refresh_before_secs` before the next update of its TCB
+//! info or QE identity. If that fails, the cached collateral is used with a
+//! warning until the quote verification library finds it out of date.
+//!
+//! For air-gapped deployments, `offline_bundle` is a JSON array of
+//! collateral, like the files of `cache_dir`, loaded at startup. Nothing is
+//! fetched then.
+//!
+//! The platforms of a known fleet can be registered, in `fleet` or with
+//! [`Provider::register`], so that their collateral is prefetched, see
+//! [`Provider::prefetch`], and kept warm instead of being fetched by the
+//! first attestation of each platform:
+//!
+//! ```json
+//! "collateral": {
+//!     "pccs_url": "https://pccs.example:8081",
+//!     "cache_dir": "/var/lib/attestation-service/collateral",
+//!     "fleet": [{ "tee": "tdx", "fmspc": "00806f050000", "ca": "platform" }],
+//!     "max_cached": 32
+//! }
+//! ```
+//!
+//! Registered platforms are persisted in `cache_dir`, and replace those of
+//! `fleet` across restarts. With `max_cached`, the cache holds the
+//! collateral of that many platforms at most: the collateral of registered
+//! platforms is never evicted, that of other platforms is evicted least
+//! recently used first, and no more platforms can be registered.
+
+use crate::metrics;
+use crate::verifier::{warnings, CollateralConfig};
+pub use crate::verifier::{CollateralKey, PckCa, QuoteTee};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::DateTime;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{c_char, CString};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+mod pccs;
+pub mod pck;
+
+lazy_static! {
+    /// The providers of each collateral config, so that collateral is
+    /// cached across the verifiers, made for every attestation.
+    static ref PROVIDERS: Mutex<HashMap<CollateralConfig, Arc<Provider>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// File of the registered platforms in the cache directory.
+const FLEET_FILE: &str = "fleet.json";
+
+/// The collateral of the quotes of a platform, as served by a PCCS: PEM
+/// certificate chains and CRLs, and the JSON TCB info and QE identity.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Collateral {
+    pub tee: QuoteTee,
+    pub fmspc: String,
+    pub ca: PckCa,
+    pub pck_crl_issuer_chain: String,
+    pub root_ca_crl: String,
+    pub pck_crl: String,
+    pub tcb_info_issuer_chain: String,
+    pub tcb_info: String,
+    pub qe_identity_issuer_chain: String,
+    pub qe_identity: String,
+}
+
+impl Collateral {
+    pub fn key(&self) -> CollateralKey {
+        CollateralKey {
+            tee: self.tee,
+            fmspc: self.fmspc.clone(),
+            ca: self.ca,
+        }
+    }
+
+    /// The next update of the TCB info or of the QE identity, the soonest,
+    /// as a Unix time.
+    pub fn next_update(&self) -> Result<i64> {
+        let next_update = |json: &str, field: &str| -> Result<i64> {
+            let value: Value = serde_json::from_str(json)
+                .with_context(|| format!("Malformed {field} of the collateral"))?;
+            let next_update = value[field]["nextUpdate"]
+                .as_str()
+                .ok_or_else(|| anyhow!("The {field} of the collateral has no nextUpdate"))?;
+            Ok(DateTime::parse_from_rfc3339(next_update)
+                .with_context(|| format!("Malformed nextUpdate of the {field}"))?
+                .timestamp())
+        };
+        Ok(next_update(&self.tcb_info, "tcbInfo")?
+            .min(next_update(&self.qe_identity, "enclaveIdentity")?))
+    }
+
+    /// The collateral in the layout of the quote verification library.
+    pub(crate) fn to_qve(&self) -> Result<QveCollateral> {
+        QveCollateral::new(self)
+    }
+}
+
+/// `sgx_ql_qve_collateral_t` of the quote verification library.
+#[repr(C)]
+struct RawCollateral {
+    /// `major_version` in the low 16 bits, `minor_version` in the high ones.
+    version: u32,
+    tee_type: u32,
+    pck_crl_issuer_chain: *const c_char,
+    pck_crl_issuer_chain_size: u32,
+    root_ca_crl: *const c_char,
+    root_ca_crl_size: u32,
+    pck_crl: *const c_char,
+    pck_crl_size: u32,
+    tcb_info_issuer_chain: *const c_char,
+    tcb_info_issuer_chain_size: u32,
+    tcb_info: *const c_char,
+    tcb_info_size: u32,
+    qe_identity_issuer_chain: *const c_char,
+    qe_identity_issuer_chain_size: u32,
+    qe_identity: *const c_char,
+    qe_identity_size: u32,
+}
+
+/// Collateral laid out for the quote verification library, with the
+/// strings it points to.
+pub(crate) struct QveCollateral {
+    raw: RawCollateral,
+    _strings: Vec<CString>,
+}
+
+// The pointers of the raw collateral point to the strings it owns, which
+// are never mutated.
+unsafe impl Send for QveCollateral {}
+unsafe impl Sync for QveCollateral {}
+
+impl QveCollateral {
+    fn new(collateral: &Collateral) -> Result<Self> {
+        let strings = [
+            &collateral.pck_crl_issuer_chain,
+            &collateral.root_ca_crl,
+            &collateral.pck_crl,
+            &collateral.tcb_info_issuer_chain,
+            &collateral.tcb_info,
+            &collateral.qe_identity_issuer_chain,
+            &collateral.qe_identity,
+        ]
+        .into_iter()
+        .map(|string| CString::new(string.as_str()).context("NUL in the collateral"))
+        .collect::<Result<Vec<_>>>()?;
+        // Sizes count the terminating NUL.
+        let field = |i: usize| -> (*const c_char, u32) {
+            (
+                strings[i].as_ptr(),
+                strings[i].as_bytes_with_nul().len() as u32,
+            )
+        };
+        let (major, minor, tee_type) = match collateral.tee {
+            QuoteTee::Sgx => (3, 1, 0),
+            QuoteTee::Tdx => (4, 0, 0x81),
+        };
+        let raw = RawCollateral {
+            version: major | (minor << 16),
+            tee_type,
+            pck_crl_issuer_chain: field(0).0,
+            pck_crl_issuer_chain_size: field(0).1,
+            root_ca_crl: field(1).0,
+            root_ca_crl_size: field(1).1,
+            pck_crl: field(2).0,
+            pck_crl_size: field(2).1,
+            tcb_info_issuer_chain: field(3).0,
+            tcb_info_issuer_chain_size: field(3).1,
+            tcb_info: field(4).0,
+            tcb_info_size: field(4).1,
+            qe_identity_issuer_chain: field(5).0,
+            qe_identity_issuer_chain_size: field(5).1,
+            qe_identity: field(6).0,
+            qe_identity_size: field(6).1,
+        };
+        Ok(Self {
+            raw,
+            _strings: strings,
+        })
+    }
+
+    /// The bytes of the `sgx_ql_qve_collateral_t` structure, valid as long
+    /// as `self`.
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: `raw` is a plain `repr(C)` structure of
+        // `size_of::<RawCollateral>()` bytes.
+        unsafe {
+            std::slice::from_raw_parts(
+                &self.raw as *const RawCollateral as *const u8,
+                std::mem::size_of::<RawCollateral>(),
+            )
+        }
+    }
+}
+
+/// Collateral in the cache, with when it last verified a quote, as a Unix
+/// time.
+#[derive(Debug)]
+struct Cached {
+    collateral: Collateral,
+    last_used: i64,
+}
+
+/// State of the collateral of a registered platform.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PlatformCollateral {
+    pub platform: CollateralKey,
+    /// The next update of its TCB info or QE identity, as a Unix time, if
+    /// its collateral is cached.
+    pub next_update: Option<i64>,
+    /// Why its collateral could not be fetched, if it could not.
+    pub error: Option<String>,
+}
+
+/// The collateral of a collateral config, fetched and cached, or loaded
+/// from an offline bundle.
+#[derive(Debug, Default)]
+pub struct Provider {
+    config: CollateralConfig,
+    client: Option<reqwest::Client>,
+    cache: Mutex<HashMap<CollateralKey, Cached>>,
+    /// The registered platforms, whose collateral is prefetched and never
+    /// evicted.
+    fleet: Mutex<HashSet<CollateralKey>>,
+}
+
+/// The collateral provider of `config`, shared by the verifiers made with
+/// the same config. The offline bundle is loaded the first time.
+pub fn provider(config: &CollateralConfig) -> Result<Arc<Provider>> {
+    let mut providers = PROVIDERS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(provider) = providers.get(config) {
+        return Ok(provider.clone());
+    }
+    let provider = Arc::new(Provider::new(config.clone())?);
+    providers.insert(config.clone(), provider.clone());
+    Ok(provider)
+}
+
+impl Provider {
+    pub fn new(config: CollateralConfig) -> Result<Self> {
+        let mut cache = HashMap::new();
+        let client = match (&config.offline_bundle, &config.pccs_url) {
+            (Some(_), Some(_)) => bail!("`offline_bundle` and `pccs_url` are exclusive"),
+            (Some(bundle), None) => {
+                for collateral in load_bundle(bundle)? {
+                    cache.insert(collateral.key(), Cached::new(collateral));
+                }
+                info!("Loaded the collateral of {} platforms", cache.len());
+                None
+            }
+            (None, Some(_)) => {
+                if let Some(cache_dir) = &config.cache_dir {
+                    fs::create_dir_all(cache_dir).with_context(|| {
+                        format!("Cannot create the collateral cache {}", cache_dir.display())
+                    })?;
+                    for collateral in load_cache(cache_dir)? {
+                        cache.insert(collateral.key(), Cached::new(collateral));
+                    }
+                }
+                Some(
+                    reqwest::Client::builder()
+                        .timeout(Duration::from_secs(config.timeout_secs))
+                        .build()?,
+                )
+            }
+            (None, None) => None,
+        };
+        let fleet = match config.cache_dir.as_deref().map(load_fleet).transpose()? {
+            Some(Some(fleet)) => fleet,
+            _ => config.fleet.clone(),
+        };
+        if !fleet.is_empty() && config.offline_bundle.is_none() && config.pccs_url.is_none() {
+            bail!("A fleet is registered, but no `pccs_url` to fetch its collateral from");
+        }
+        let fleet = check_fleet(fleet, config.max_cached)?;
+        if config.offline_bundle.is_none() {
+            evict(&mut cache, &fleet, &config);
+        }
+        Ok(Self {
+            config,
+            client,
+            cache: Mutex::new(cache),
+            fleet: Mutex::new(fleet),
+        })
+    }
+
+    /// Register `platforms` as the fleet, in place of the platforms
+    /// registered before, and persist them in `cache_dir`. Their collateral
+    /// is fetched by the next [`Self::prefetch`].
+    pub fn register(&self, platforms: Vec<CollateralKey>) -> Result<()> {
+        if self.config.offline_bundle.is_none() && self.config.pccs_url.is_none() {
+            bail!("The collateral is fetched by the quote provider library, not the AS");
+        }
+        let fleet = check_fleet(platforms, self.config.max_cached)?;
+        if let Some(cache_dir) = &self.config.cache_dir {
+            let mut platforms: Vec<_> = fleet.iter().cloned().collect();
+            platforms.sort_by_key(CollateralKey::to_string);
+            let path = cache_dir.join(FLEET_FILE);
+            let tmp = path.with_extension("json.tmp");
+            fs::write(&tmp, serde_json::to_vec(&platforms)?)
+                .and_then(|_| fs::rename(&tmp, &path))
+                .context("Cannot persist the registered platforms")?;
+        }
+        info!("Registered {} platforms", fleet.len());
+        *self.fleet.lock().unwrap_or_else(PoisonError::into_inner) = fleet;
+        Ok(())
+    }
+
+    /// Fetch the collateral of the registered platforms that is not cached,
+    /// or due for a refresh, one platform at a time, and return the state of
+    /// the collateral of each.
+    pub async fn prefetch(&self) -> Vec<PlatformCollateral> {
+        self.prefetch_at(chrono::Utc::now().timestamp()).await
+    }
+
+    async fn prefetch_at(&self, now: i64) -> Vec<PlatformCollateral> {
+        let mut fleet: Vec<_> = self
+            .fleet
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .cloned()
+            .collect();
+        fleet.sort_by_key(CollateralKey::to_string);
+        let mut states = Vec::with_capacity(fleet.len());
+        for platform in fleet {
+            let (next_update, error) = match self.collateral_of(&platform, now, false).await {
+                Ok(collateral) => (
+                    collateral.and_then(|collateral| collateral.next_update().ok()),
+                    None,
+                ),
+                Err(e) => {
+                    warn!("Cannot prefetch the collateral of {platform}: {e:#}");
+                    (None, Some(format!("{e:#}")))
+                }
+            };
+            states.push(PlatformCollateral {
+                platform,
+                next_update,
+                error,
+            });
+        }
+        states
+    }
+
+    /// The collateral to verify `quote` of `tee` with, `None` to leave it to
+    /// the quote provider library.
+    pub async fn collateral(&self, tee: QuoteTee, quote: &[u8]) -> Result<Option<Collateral>> {
+        self.collateral_at(tee, quote, chrono::Utc::now().timestamp())
+            .await
+    }
+
+    async fn collateral_at(
+        &self,
+        tee: QuoteTee,
+        quote: &[u8],
+        now: i64,
+    ) -> Result<Option<Collateral>> {
+        if self.config.offline_bundle.is_none() && self.config.pccs_url.is_none() {
+            return Ok(None);
+        }
+        let key = pck::collateral_key(tee, quote)?;
+        self.collateral_of(&key, now, true).await
+    }
+
+    /// The collateral of `key`, cached or fetched, as of `now`. It counts as
+    /// used to verify a quote if `used`.
+    async fn collateral_of(
+        &self,
+        key: &CollateralKey,
+        now: i64,
+        used: bool,
+    ) -> Result<Option<Collateral>> {
+        let cached = self
+            .cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_mut(key)
+            .map(|cached| {
+                if used {
+                    cached.last_used = now;
+                }
+                cached.collateral.clone()
+            });
+        if self.config.offline_bundle.is_some() {
+            return cached.map(Some).ok_or_else(|| {
+                anyhow!("The offline collateral bundle has no collateral of {key}")
+            });
+        }
+
+        if let Some(cached) = &cached {
+            let refresh_before = self.config.refresh_before_secs as i64;
+            let fresh = cached
+                .next_update()
+                .is_ok_and(|next_update| now < next_update - refresh_before);
+            if fresh {
+                return Ok(Some(cached.clone()));
+            }
+        }
+        let (Some(client), Some(pccs_url)) = (&self.client, &self.config.pccs_url) else {
+            return Ok(cached);
+        };
+        match pccs::fetch(
+            client,
+            pccs_url,
+            self.config.root_ca_crl_url.as_deref(),
+            key,
+        )
+        .await
+        {
+            Ok(collateral) => {
+                debug!("Fetched the collateral of {key}");
+                if let Some(cache_dir) = &self.config.cache_dir {
+                    if let Err(e) = store(cache_dir, &collateral) {
+                        warn!("Cannot cache the collateral of {key}: {e:#}");
+                    }
+                }
+                let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+                let last_used = match used {
+                    true => now,
+                    false => cache.get(key).map_or(0, |cached| cached.last_used),
+                };
+                cache.insert(
+                    key.clone(),
+                    Cached {
+                        collateral: collateral.clone(),
+                        last_used,
+                    },
+                );
+                let fleet = self.fleet.lock().unwrap_or_else(PoisonError::into_inner);
+                evict(&mut cache, &fleet, &self.config);
+                Ok(Some(collateral))
+            }
+            Err(e) => {
+                metrics::count_collateral_fetch_failure("pccs");
+                match cached {
+                    Some(cached) => {
+                        warnings::raise(format!(
+                            "Cannot refresh the collateral of {key}, verifying with the cached one: {e:#}"
+                        ));
+                        Ok(Some(cached))
+                    }
+                    None => Err(e.context(format!("Cannot fetch the collateral of {key}"))),
+                }
+            }
+        }
+    }
+}
+
+impl Cached {
+    fn new(collateral: Collateral) -> Self {
+        Self {
+            collateral,
+            last_used: 0,
+        }
+    }
+}
+
+/// Check the platforms of a fleet, and that the cache of `max_cached`
+/// platforms, if any, holds their collateral.
+fn check_fleet(
+    platforms: Vec<CollateralKey>,
+    max_cached: Option<usize>,
+) -> Result<HashSet<CollateralKey>> {
+    let fleet = platforms
+        .into_iter()
+        .map(CollateralKey::normalized)
+        .collect::<Result<HashSet<_>>>()?;
+    if let Some(max_cached) = max_cached {
+        if fleet.len() > max_cached {
+            bail!(
+                "{} platforms are registered, but the collateral of {max_cached} at most is cached",
+                fleet.len()
+            );
+        }
+    }
+    Ok(fleet)
+}
+
+/// Evict collateral of platforms out of `fleet` from `cache`, least
+/// recently used first, until it holds `max_cached` platforms at most, and
+/// from `cache_dir`.
+fn evict(
+    cache: &mut HashMap<CollateralKey, Cached>,
+    fleet: &HashSet<CollateralKey>,
+    config: &CollateralConfig,
+) {
+    let Some(max_cached) = config.max_cached else {
+        return;
+    };
+    while cache.len() > max_cached {
+        let Some(key) = cache
+            .iter()
+            .filter(|(key, _)| !fleet.contains(key))
+            .min_by_key(|(_, cached)| cached.last_used)
+            .map(|(key, _)| key.clone())
+        else {
+            return;
+        };
+        cache.remove(&key);
+        debug!("Evicted the collateral of {key}");
+        if let Some(cache_dir) = &config.cache_dir {
+            if let Err(e) = fs::remove_file(cache_path(cache_dir, &key)) {
+                warn!("Cannot remove the cached collateral of {key}: {e}");
+            }
+        }
+    }
+}
+
+/// The platforms registered in `cache_dir`, if any were.
+fn load_fleet(cache_dir: &Path) -> Result<Option<Vec<CollateralKey>>> {
+    let path = cache_dir.join(FLEET_FILE);
+    match fs::read(&path) {
+        Ok(fleet) => Ok(Some(serde_json::from_slice(&fleet).with_context(|| {
+            format!("Malformed registered platforms {}", path.display())
+        })?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Cannot read {}", path.display())),
+    }
+}
+
+/// Load an offline collateral bundle, a JSON array of collateral.
+pub fn load_bundle(path: &Path) -> Result<Vec<Collateral>> {
+    let bundle = fs::read(path)
+        .with_context(|| format!("Cannot read the collateral bundle {}", path.display()))?;
+    serde_json::from_slice(&bundle)
+        .with_context(|| format!("Malformed collateral bundle {}", path.display()))
+}
+
+fn cache_path(cache_dir: &Path, key: &CollateralKey) -> std::path::PathBuf {
+    cache_dir.join(format!("{key}.json"))
+}
+
+/// The collateral cached in `cache_dir`. Malformed files are skipped.
+fn load_cache(cache_dir: &Path) -> Result<Vec<Collateral>> {
+    let mut cached = Vec::new();
+    for entry in fs::read_dir(cache_dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+            && !path.ends_with(FLEET_FILE)
+        {
+            match fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|file| Ok(serde_json::from_slice::<Collateral>(&file)?))
+            {
+                Ok(collateral) => cached.push(collateral),
+                Err(e) => warn!("Skipping the cached collateral {}: {e:#}", path.display()),
+            }
+        }
+    }
+    Ok(cached)
+}
+
+fn store(cache_dir: &Path, collateral: &Collateral) -> Result<()> {
+    let path = cache_path(cache_dir, &collateral.key());
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec(collateral)?)?;
+    fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    pub(crate) fn collateral(fmspc: &str, next_update: &str) -> Collateral {
+        Collateral {
+            tee: QuoteTee::Tdx,
+            fmspc: fmspc.to_string(),
+            ca: PckCa::Platform,
+            pck_crl_issuer_chain: "chain".to_string(),
+            root_ca_crl: "crl".to_string(),
+            pck_crl: "crl".to_string(),
+            tcb_info_issuer_chain: "chain".to_string(),
+            tcb_info: json!({ "tcbInfo": { "nextUpdate": next_update } }).to_string(),
+            qe_identity_issuer_chain: "chain".to_string(),
+            qe_identity: json!({ "enclaveIdentity": { "nextUpdate": "2030-01-01T00:00:00Z" } })
+                .to_string(),
+        }
+    }
+
+    #[test]
+    fn test_collateral() {
+        let collateral = collateral("00806f050000", "2024-01-01T00:00:00Z");
+        assert_eq!(collateral.next_update().unwrap(), 1704067200);
+        assert_eq!(collateral.key().to_string(), "tdx-00806f050000-platform");
+
+        let qve = collateral.to_qve().unwrap();
+        assert_eq!(qve.raw.version, 4);
+        assert_eq!(qve.raw.tee_type, 0x81);
+        assert_eq!(
+            qve.raw.tcb_info_size as usize,
+            collateral.tcb_info.len() + 1
+        );
+        assert_eq!(qve.as_bytes().len(), std::mem::size_of::<RawCollateral>());
+    }
+
+    #[tokio::test]
+    async fn test_offline_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("bundle.json");
+        let fmspc = hex::encode(pck::tests::FMSPC);
+        fs::write(
+            &bundle,
+            serde_json::to_vec(&[collateral(&fmspc, "2024-01-01T00:00:00Z")]).unwrap(),
+        )
+        .unwrap();
+        let provider = Provider::new(CollateralConfig {
+            offline_bundle: Some(bundle.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let quote = pck::tests::tdx_quote(b"Intel SGX PCK Platform CA");
+        let collateral = provider.collateral(QuoteTee::Tdx, &quote).await.unwrap();
+        assert_eq!(collateral.unwrap().fmspc, fmspc);
+        let quote = pck::tests::tdx_quote(b"Intel SGX PCK Processor CA");
+        assert!(provider.collateral(QuoteTee::Tdx, &quote).await.is_err());
+
+        // Offline or online, not both.
+        assert!(Provider::new(CollateralConfig {
+            offline_bundle: Some(bundle),
+            pccs_url: Some("https://pccs.example".to_string()),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let fmspc = hex::encode(pck::tests::FMSPC);
+        store(dir.path(), &collateral(&fmspc, "2024-01-01T00:00:00Z")).unwrap();
+        let provider = Provider::new(CollateralConfig {
+            // Nothing listens there.
+            pccs_url: Some("http://127.0.0.1:9".to_string()),
+            cache_dir: Some(dir.path().to_path_buf()),
+            timeout_secs: 1,
+            ..Default::default()
+        })
+        .unwrap();
+        let quote = pck::tests::tdx_quote(b"Intel SGX PCK Platform CA");
+
+        // Fresh cached collateral is not fetched again.
+        let now = 1704067200 - 2 * 24 * 3600;
+        assert!(provider
+            .collateral_at(QuoteTee::Tdx, &quote, now)
+            .await
+            .unwrap()
+            .is_some());
+        // Collateral to refresh is used if it cannot be fetched.
+        let (collateral, warnings) = warnings::collect(async {
+            provider
+                .collateral_at(QuoteTee::Tdx, &quote, now + 24 * 3600)
+                .await
+        })
+        .await;
+        assert!(collateral.unwrap().is_some());
+        assert_eq!(warnings.len(), 1);
+
+        // Default config: left to the quote provider library.
+        let provider = Provider::default();
+        assert!(provider
+            .collateral(QuoteTee::Tdx, &quote)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    fn key(fmspc: &str) -> CollateralKey {
+        CollateralKey {
+            tee: QuoteTee::Tdx,
+            fmspc: fmspc.to_string(),
+            ca: PckCa::Platform,
+        }
+    }
+
+    #[test]
+    fn test_evict() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = CollateralConfig {
+            cache_dir: Some(dir.path().to_path_buf()),
+            max_cached: Some(2),
+            ..Default::default()
+        };
+        let mut cache = HashMap::new();
+        for (fmspc, last_used) in [
+            ("000000000001", 1),
+            ("000000000002", 2),
+            ("000000000003", 3),
+        ] {
+            let collateral = collateral(fmspc, "2030-01-01T00:00:00Z");
+            store(dir.path(), &collateral).unwrap();
+            cache.insert(
+                collateral.key(),
+                Cached {
+                    collateral,
+                    last_used,
+                },
+            );
+        }
+
+        // The least recently used platform out of the fleet goes first.
+        let fleet = HashSet::from([key("000000000001")]);
+        evict(&mut cache, &fleet, &config);
+        assert!(cache.contains_key(&key("000000000001")));
+        assert!(!cache.contains_key(&key("000000000002")));
+        assert!(cache.contains_key(&key("000000000003")));
+        assert_eq!(load_cache(dir.path()).unwrap().len(), 2);
+
+        // Registered platforms are never evicted.
+        let fleet = HashSet::from([key("000000000001"), key("000000000003")]);
+        cache.insert(
+            key("000000000004"),
+            Cached::new(collateral("000000000004", "2030-01-01T00:00:00Z")),
+        );
+        evict(&mut cache, &fleet, &config);
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains_key(&key("000000000004")));
+    }
+
+    #[tokio::test]
+    async fn test_fleet() {
+        let dir = tempfile::tempdir().unwrap();
+        store(
+            dir.path(),
+            &collateral("00806f050000", "2024-01-01T00:00:00Z"),
+        )
+        .unwrap();
+        let config = CollateralConfig {
+            // Nothing listens there.
+            pccs_url: Some("http://127.0.0.1:9".to_string()),
+            cache_dir: Some(dir.path().to_path_buf()),
+            timeout_secs: 1,
+            fleet: vec![key("00806F050000")],
+            max_cached: Some(2),
+            ..Default::default()
+        };
+        let provider = Provider::new(config.clone()).unwrap();
+
+        // Cached collateral is prefetched again only when due.
+        let now = 1704067200 - 2 * 24 * 3600;
+        let states = provider.prefetch_at(now).await;
+        assert_eq!(
+            states,
+            vec![PlatformCollateral {
+                platform: key("00806f050000"),
+                next_update: Some(1704067200),
+                error: None,
+            }]
+        );
+
+        // No more platforms than the budget, nor malformed FMSPCs.
+        let platforms = ["00806f050000", "00906ed50000", "00a06f000000"].map(key);
+        assert!(provider.register(platforms.to_vec()).is_err());
+        assert!(provider.register(vec![key("00806f05")]).is_err());
+
+        provider.register(platforms[1..].to_vec()).unwrap();
+        let states = provider.prefetch_at(now).await;
+        assert_eq!(states.len(), 2);
+        assert!(states.iter().all(|state| state.error.is_some()));
+
+        // Registered platforms replace the configured ones after a restart.
+        let provider = Provider::new(config.clone()).unwrap();
+        let states = provider.prefetch_at(now).await;
+        assert_eq!(states[0].platform, key("00906ed50000"));
+        assert_eq!(load_cache(dir.path()).unwrap().len(), 1);
+
+        // A fleet needs collateral fetched by the AS.
+        assert!(Provider::new(CollateralConfig {
+            fleet: vec![key("00806f050000")],
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! Client of the PCCS API, v4, also served by the Intel PCS.
+
+use super::{Collateral, CollateralKey, QuoteTee};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+
+const TCB_INFO_ISSUER_CHAIN: &str = "TCB-Info-Issuer-Chain";
+const QE_IDENTITY_ISSUER_CHAIN: &str = "SGX-Enclave-Identity-Issuer-Chain";
+const PCK_CRL_ISSUER_CHAIN: &str = "SGX-PCK-CRL-Issuer-Chain";
+
+/// Fetch the collateral of `key` from the PCCS at `base_url`, and the root
+/// CA CRL from `root_ca_crl_url` if set.
+pub(super) async fn fetch(
+    client: &reqwest::Client,
+    base_url: &str,
+    root_ca_crl_url: Option<&str>,
+    key: &CollateralKey,
+) -> Result<Collateral> {
+    let base_url = base_url.trim_end_matches('/');
+    let tee = key.tee;
+
+    let (tcb_info, tcb_info_issuer_chain) = get(
+        client,
+        &format!("{base_url}/{tee}/certification/v4/tcb?fmspc={}", key.fmspc),
+        Some(TCB_INFO_ISSUER_CHAIN),
+    )
+    .await?;
+    let (qe_identity, qe_identity_issuer_chain) = get(
+        client,
+        &format!("{base_url}/{tee}/certification/v4/qe/identity"),
+        Some(QE_IDENTITY_ISSUER_CHAIN),
+    )
+    .await?;
+    // PCK certificates and CRLs are the same for SGX and TDX.
+    let (pck_crl, pck_crl_issuer_chain) = get(
+        client,
+        &format!(
+            "{base_url}/{}/certification/v4/pckcrl?ca={}&encoding=pem",
+            QuoteTee::Sgx,
+            key.ca
+        ),
+        Some(PCK_CRL_ISSUER_CHAIN),
+    )
+    .await?;
+    let root_ca_crl_url = match root_ca_crl_url {
+        Some(url) => url.to_string(),
+        None => format!("{base_url}/{}/certification/v4/rootcacrl", QuoteTee::Sgx),
+    };
+    let (root_ca_crl, _) = get(client, &root_ca_crl_url, None).await?;
+
+    Ok(Collateral {
+        tee,
+        fmspc: key.fmspc.clone(),
+        ca: key.ca,
+        pck_crl_issuer_chain: pck_crl_issuer_chain.unwrap_or_default(),
+        root_ca_crl: crl_pem(&root_ca_crl)?,
+        pck_crl: crl_pem(&pck_crl)?,
+        tcb_info_issuer_chain: tcb_info_issuer_chain.unwrap_or_default(),
+        tcb_info: String::from_utf8(tcb_info).context("The TCB info is not UTF-8")?,
+        qe_identity_issuer_chain: qe_identity_issuer_chain.unwrap_or_default(),
+        qe_identity: String::from_utf8(qe_identity).context("The QE identity is not UTF-8")?,
+    })
+}
+
+/// GET `url`, and return the body with the issuer chain in `chain_header`.
+async fn get(
+    client: &reqwest::Client,
+    url: &str,
+    chain_header: Option<&str>,
+) -> Result<(Vec<u8>, Option<String>)> {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("GET {url}"))?;
+    if !response.status().is_success() {
+        bail!("GET {url}: {}", response.status());
+    }
+    let chain = match chain_header {
+        Some(header) => {
+            let chain = response
+                .headers()
+                .get(header)
+                .ok_or_else(|| anyhow!("GET {url}: no {header} header"))?
+                .to_str()
+                .with_context(|| format!("GET {url}: malformed {header} header"))?;
+            Some(percent_decode(chain)?)
+        }
+        None => None,
+    };
+    let body = response.bytes().await?.to_vec();
+    Ok((body, chain))
+}
+
+/// Decode the URL encoded issuer chain headers.
+fn percent_decode(encoded: &str) -> Result<String> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [
+                bytes.next().unwrap_or_default(),
+                bytes.next().unwrap_or_default(),
+            ];
+            let hex = std::str::from_utf8(&hex).context("Malformed URL encoding")?;
+            decoded.push(u8::from_str_radix(hex, 16).context("Malformed URL encoding")?);
+        } else {
+            decoded.push(byte);
+        }
+    }
+    String::from_utf8(decoded).context("The issuer chain is not UTF-8")
+}
+
+/// A CRL as PEM. Depending on their version and on the encoding asked for,
+/// PCCSs serve CRLs as PEM, hex encoded DER or DER.
+fn crl_pem(crl: &[u8]) -> Result<String> {
+    if let Ok(crl) = std::str::from_utf8(crl) {
+        let crl = crl.trim();
+        if crl.starts_with("-----BEGIN X509 CRL-----") {
+            return Ok(crl.to_string());
+        }
+        if let Ok(der) = hex::decode(crl) {
+            return Ok(der_to_pem(&der));
+        }
+    }
+    // DER CRLs are ASN.1 sequences.
+    if crl.first() != Some(&0x30) {
+        bail!("Malformed CRL");
+    }
+    Ok(der_to_pem(crl))
+}
+
+fn der_to_pem(der: &[u8]) -> String {
+    let base64 = base64::engine::general_purpose::STANDARD.encode(der);
+    let lines: Vec<_> = base64
+        .as_bytes()
+        .chunks(64)
+        .map(|line| std::str::from_utf8(line).expect("base64 is ASCII"))
+        .collect();
+    format!(
+        "-----BEGIN X509 CRL-----\n{}\n-----END X509 CRL-----",
+        lines.join("\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            percent_decode("-----BEGIN%20CERTIFICATE-----%0AMII%2B").unwrap(),
+            "-----BEGIN CERTIFICATE-----\nMII+"
+        );
+        assert!(percent_decode("%zz").is_err());
+
+        let pem = "-----BEGIN X509 CRL-----\nMAM=\n-----END X509 CRL-----";
+        assert_eq!(crl_pem(pem.as_bytes()).unwrap(), pem);
+        assert_eq!(crl_pem(b"300300").unwrap(), der_to_pem(&[0x30, 0x03, 0x00]));
+        assert_eq!(
+            crl_pem(&[0x30, 0x03, 0x00]).unwrap(),
+            der_to_pem(&[0x30, 0x03, 0x00])
+        );
+        assert!(crl_pem(b"not a CRL").is_err());
+    }
+}
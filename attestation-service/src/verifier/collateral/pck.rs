@@ -0,0 +1,381 @@
+//! The PCK certificate chain of SGX and TDX quotes, which says the
+//! collateral the quotes are verified with.
+
+use super::{CollateralKey, PckCa, QuoteTee};
+use crate::verifier::anchors::{self, TrustAnchor};
+use crate::verifier::trace;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+const QUOTE_HEADER_SIZE: usize = 48;
+const ENCLAVE_REPORT_SIZE: usize = 384;
+const TD_REPORT_10_SIZE: usize = 584;
+const ECDSA_SIGNATURE_SIZE: usize = 64;
+const ECDSA_KEY_SIZE: usize = 64;
+
+const TEE_TYPE_TDX: u32 = 0x81;
+
+/// Certification data of the PCK certificate chain, as PEM.
+const CERT_TYPE_PCK_CHAIN: u16 = 5;
+/// Certification data of the QE report, wrapping that of the PCK chain.
+const CERT_TYPE_QE_REPORT: u16 = 6;
+
+/// DER of the OID of the FMSPC in the SGX extensions, 1.2.840.113741.1.13.1.4.
+const FMSPC_OID: [u8; 12] = [
+    0x06, 0x0a, 0x2a, 0x86, 0x48, 0x86, 0xf8, 0x4d, 0x01, 0x0d, 0x01, 0x04,
+];
+
+/// DER of the OID of the SGX TCB components in the SGX extensions but its
+/// last arc, the component number, 1.2.840.113741.1.13.1.2.
+const TCB_COMPONENT_OID: [u8; 12] = [
+    0x06, 0x0b, 0x2a, 0x86, 0x48, 0x86, 0xf8, 0x4d, 0x01, 0x0d, 0x01, 0x02,
+];
+const SGX_TCB_COMPONENTS: u8 = 16;
+const PCESVN_COMPONENT: u8 = 17;
+const CPUSVN_COMPONENT: u8 = 18;
+
+const DER_INTEGER: u8 = 0x02;
+const DER_OCTET_STRING: u8 = 0x04;
+
+const PLATFORM_CA: &[u8] = b"Intel SGX PCK Platform CA";
+const PROCESSOR_CA: &[u8] = b"Intel SGX PCK Processor CA";
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], offset: usize) -> Self {
+        Self { bytes, offset }
+    }
+
+    fn take(&mut self, size: usize) -> Result<&'a [u8]> {
+        let end = self
+            .offset
+            .checked_add(size)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| anyhow!("Truncated quote"))?;
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+}
+
+/// The PEM PCK certificate chain of `quote`, leaf first.
+pub fn pck_chain(quote: &[u8]) -> Result<&[u8]> {
+    let mut reader = Reader::new(quote, 0);
+    let header = reader.take(QUOTE_HEADER_SIZE)?;
+    let version = u16::from_le_bytes([header[0], header[1]]);
+    let tee_type = u32::from_le_bytes(header[4..8].try_into()?);
+    match version {
+        3 => reader.take(ENCLAVE_REPORT_SIZE)?,
+        4 if tee_type == TEE_TYPE_TDX => reader.take(TD_REPORT_10_SIZE)?,
+        4 => reader.take(ENCLAVE_REPORT_SIZE)?,
+        5 => {
+            let _body_type = reader.u16()?;
+            let size = reader.u32()?;
+            reader.take(size as usize)?
+        }
+        version => bail!("Unsupported quote version {version}"),
+    };
+
+    let signature_data_size = reader.u32()?;
+    let mut reader = Reader::new(reader.take(signature_data_size as usize)?, 0);
+    reader.take(ECDSA_SIGNATURE_SIZE + ECDSA_KEY_SIZE)?;
+    if version == 3 {
+        // The QE report certification data, without its type and size.
+        return qe_report_pck_chain(&mut reader);
+    }
+    certification_data(&mut reader)
+}
+
+fn certification_data<'a>(reader: &mut Reader<'a>) -> Result<&'a [u8]> {
+    let cert_type = reader.u16()?;
+    let size = reader.u32()?;
+    let mut data = Reader::new(reader.take(size as usize)?, 0);
+    match cert_type {
+        CERT_TYPE_PCK_CHAIN => Ok(data.bytes),
+        CERT_TYPE_QE_REPORT => qe_report_pck_chain(&mut data),
+        cert_type => bail!("Unsupported certification data type {cert_type}"),
+    }
+}
+
+fn qe_report_pck_chain<'a>(reader: &mut Reader<'a>) -> Result<&'a [u8]> {
+    reader.take(ENCLAVE_REPORT_SIZE + ECDSA_SIGNATURE_SIZE)?;
+    let auth_data_size = reader.u16()?;
+    reader.take(auth_data_size as usize)?;
+    certification_data(reader)
+}
+
+/// The DER certificates of a PEM chain, leaf first.
+fn chain_ders(chain: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let certs = anchors::pem_certificates(chain).context("Malformed PCK chain")?;
+    if certs.is_empty() {
+        bail!("No certificate in the PCK chain");
+    }
+    Ok(certs)
+}
+
+/// Check that the PCK chain of `quote` ends at one of the Intel `trusted`
+/// if there are any, and return the claim of its root, see
+/// [`anchors::check_root`]. The chain is recorded in the trace of the
+/// verification, see [`trace`].
+pub fn trust_anchor(quote: &[u8], trusted: &[TrustAnchor]) -> Result<String> {
+    let chain = chain_ders(pck_chain(quote)?)?;
+    let root = chain.last().map(Vec::as_slice).unwrap_or_default();
+    let claim = anchors::check_root(root, trusted).context("PCK chain")?;
+    trace::record_chain("PCK", chain);
+    Ok(claim)
+}
+
+/// The PEM certificates of the PCK chain of `quote`, leaf first.
+pub fn pem_chain(quote: &[u8]) -> Result<Vec<String>> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let chain = std::str::from_utf8(pck_chain(quote)?).context("Malformed PCK chain")?;
+    Ok(chain
+        .split_inclusive(END)
+        .filter_map(|cert| cert.find(BEGIN).map(|begin| &cert[begin..]))
+        .filter(|cert| cert.ends_with(END))
+        .map(str::to_string)
+        .collect())
+}
+
+/// The TCB of a platform, in the SGX extensions of its PCK certificate.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PckTcb {
+    /// SVNs of the 16 SGX TCB components.
+    pub sgx_tcb_components: Vec<u32>,
+    pub pce_svn: u32,
+    /// Hex CPU SVN.
+    pub cpu_svn: String,
+}
+
+/// The DER value of the SGX TCB component `component` of the PCK
+/// certificate `leaf`, of type `tag`.
+fn tcb_component(leaf: &[u8], component: u8, tag: u8) -> Result<&[u8]> {
+    let oid = [&TCB_COMPONENT_OID[..], &[component]].concat();
+    let value = find(leaf, &oid)
+        .map(|oid_start| oid_start + oid.len())
+        .ok_or_else(|| anyhow!("No TCB component {component} in the PCK certificate"))?;
+    // The values are short, their length is a single byte.
+    match leaf.get(value..value + 2) {
+        Some([value_tag, length]) if *value_tag == tag && *length < 0x80 => leaf
+            .get(value + 2..value + 2 + *length as usize)
+            .ok_or_else(|| anyhow!("Truncated PCK certificate")),
+        _ => bail!("Malformed TCB component {component} in the PCK certificate"),
+    }
+}
+
+fn pck_tcb(leaf: &[u8]) -> Result<PckTcb> {
+    let integer = |component| -> Result<u32> {
+        let value = tcb_component(leaf, component, DER_INTEGER)?;
+        if value.len() > 4 {
+            bail!("TCB component {component} is out of range");
+        }
+        Ok(value
+            .iter()
+            .fold(0, |integer, byte| integer << 8 | u32::from(*byte)))
+    };
+    Ok(PckTcb {
+        sgx_tcb_components: (1..=SGX_TCB_COMPONENTS)
+            .map(integer)
+            .collect::<Result<_>>()?,
+        pce_svn: integer(PCESVN_COMPONENT)?,
+        cpu_svn: hex::encode(tcb_component(leaf, CPUSVN_COMPONENT, DER_OCTET_STRING)?),
+    })
+}
+
+/// The TCB of the platform of `quote`, in the SGX extensions of its PCK
+/// certificate.
+pub fn tcb(quote: &[u8]) -> Result<PckTcb> {
+    pck_tcb(&chain_ders(pck_chain(quote)?)?[0])
+}
+
+/// The forensic detail of `quote`: its PCK chain and the TCB components of
+/// its platform, see [`crate::verifier::detail`].
+pub fn forensic_claims(quote: &[u8]) -> Result<Value> {
+    Ok(json!({
+        "pck_chain": pem_chain(quote)?,
+        "tcb_components": tcb(quote)?,
+    }))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// What collateral verifies `quote` of `tee`: the FMSPC in the SGX
+/// extensions of its PCK certificate, and the CA that issued it.
+pub fn collateral_key(tee: QuoteTee, quote: &[u8]) -> Result<CollateralKey> {
+    let leaf = chain_ders(pck_chain(quote)?)?.swap_remove(0);
+
+    // The FMSPC is an OCTET STRING of 6 bytes after its OID.
+    let fmspc = find(&leaf, &FMSPC_OID)
+        .map(|oid| oid + FMSPC_OID.len())
+        .and_then(|value| leaf.get(value..value + 8))
+        .filter(|value| value[..2] == [0x04, 0x06])
+        .ok_or_else(|| anyhow!("No FMSPC in the PCK certificate"))?;
+    let ca = if find(&leaf, PLATFORM_CA).is_some() {
+        PckCa::Platform
+    } else if find(&leaf, PROCESSOR_CA).is_some() {
+        PckCa::Processor
+    } else {
+        bail!("Unknown issuer of the PCK certificate");
+    };
+
+    Ok(CollateralKey {
+        tee,
+        fmspc: hex::encode(&fmspc[2..]),
+        ca,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use base64::Engine;
+
+    pub(crate) const FMSPC: [u8; 6] = [0x00, 0x80, 0x6f, 0x05, 0x00, 0x00];
+
+    /// A PEM chain of a fake PCK certificate, of the SGX extensions and the
+    /// issuer only.
+    fn pck_chain_pem(issuer: &[u8]) -> Vec<u8> {
+        let mut der = vec![0x30, 0x00];
+        der.extend(issuer);
+        der.extend(FMSPC_OID);
+        der.extend([0x04, 0x06]);
+        der.extend(FMSPC);
+        let base64 = base64::engine::general_purpose::STANDARD.encode(der);
+        format!("-----BEGIN CERTIFICATE-----\n{base64}\n-----END CERTIFICATE-----\n").into_bytes()
+    }
+
+    fn certification_data(cert_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = cert_type.to_le_bytes().to_vec();
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    /// A v4 TD quote, of its PCK chain only, issued by `issuer`.
+    pub(crate) fn tdx_quote(issuer: &[u8]) -> Vec<u8> {
+        let mut qe_report = vec![0; ENCLAVE_REPORT_SIZE + ECDSA_SIGNATURE_SIZE];
+        qe_report.extend(0u16.to_le_bytes());
+        qe_report.extend(certification_data(
+            CERT_TYPE_PCK_CHAIN,
+            &pck_chain_pem(issuer),
+        ));
+        let mut signature_data = vec![0; ECDSA_SIGNATURE_SIZE + ECDSA_KEY_SIZE];
+        signature_data.extend(certification_data(CERT_TYPE_QE_REPORT, &qe_report));
+
+        let mut quote = vec![0; QUOTE_HEADER_SIZE + TD_REPORT_10_SIZE];
+        quote[0] = 4;
+        quote[4] = TEE_TYPE_TDX as u8;
+        quote.extend((signature_data.len() as u32).to_le_bytes());
+        quote.extend(signature_data);
+        quote
+    }
+
+    #[test]
+    fn test_collateral_key() {
+        let quote = tdx_quote(PLATFORM_CA);
+        assert_eq!(pck_chain(&quote).unwrap(), pck_chain_pem(PLATFORM_CA));
+        let key = collateral_key(QuoteTee::Tdx, &quote).unwrap();
+        assert_eq!(key.fmspc, "00806f050000");
+        assert_eq!(key.ca, PckCa::Platform);
+
+        let key = collateral_key(QuoteTee::Tdx, &tdx_quote(PROCESSOR_CA)).unwrap();
+        assert_eq!(key.ca, PckCa::Processor);
+
+        // A v3 SGX quote, with the QE report certification data unwrapped.
+        let mut signature_data = vec![0; ECDSA_SIGNATURE_SIZE + ECDSA_KEY_SIZE];
+        signature_data.extend(vec![0; ENCLAVE_REPORT_SIZE + ECDSA_SIGNATURE_SIZE]);
+        signature_data.extend(0u16.to_le_bytes());
+        signature_data.extend(certification_data(
+            CERT_TYPE_PCK_CHAIN,
+            &pck_chain_pem(PLATFORM_CA),
+        ));
+        let mut quote = vec![0; QUOTE_HEADER_SIZE + ENCLAVE_REPORT_SIZE];
+        quote[0] = 3;
+        quote.extend((signature_data.len() as u32).to_le_bytes());
+        quote.extend(signature_data);
+        let key = collateral_key(QuoteTee::Sgx, &quote).unwrap();
+        assert_eq!(key.to_string(), "sgx-00806f050000-platform");
+
+        assert!(collateral_key(QuoteTee::Sgx, &quote[..quote.len() - 10]).is_err());
+        assert!(collateral_key(QuoteTee::Sgx, b"not a quote").is_err());
+    }
+
+    #[test]
+    fn test_pck_tcb() {
+        let mut leaf = vec![0x30, 0x00];
+        for component in 1..=SGX_TCB_COMPONENTS {
+            leaf.extend(TCB_COMPONENT_OID);
+            leaf.extend([component, DER_INTEGER, 0x01, component]);
+        }
+        // An SVN of 128 and more has a leading 0 byte.
+        leaf.extend(TCB_COMPONENT_OID);
+        leaf.extend([PCESVN_COMPONENT, DER_INTEGER, 0x02, 0x00, 0x80]);
+        leaf.extend(TCB_COMPONENT_OID);
+        leaf.extend([CPUSVN_COMPONENT, DER_OCTET_STRING, 0x10]);
+        leaf.extend([0xaa; 16]);
+        let tcb = pck_tcb(&leaf).unwrap();
+        assert_eq!(tcb.sgx_tcb_components, (1..=16).collect::<Vec<u32>>());
+        assert_eq!(tcb.pce_svn, 128);
+        assert_eq!(tcb.cpu_svn, "aa".repeat(16));
+        assert!(pck_tcb(&leaf[..leaf.len() - 1]).is_err());
+
+        let quote = std::fs::read("../test_data/tdx_quote_4.dat").unwrap();
+        let claims = forensic_claims(&quote).unwrap();
+        let chain = claims["pck_chain"].as_array().unwrap();
+        assert_eq!(chain.len(), 3);
+        assert!(chain.iter().all(|cert| cert
+            .as_str()
+            .unwrap()
+            .starts_with("-----BEGIN CERTIFICATE-----")));
+        assert_eq!(
+            claims["tcb_components"]["sgx_tcb_components"]
+                .as_array()
+                .unwrap()
+                .len(),
+            16
+        );
+    }
+
+    #[test]
+    fn test_trust_anchor() {
+        let quote = std::fs::read("../test_data/tdx_quote_4.dat").unwrap();
+        let chain = chain_ders(pck_chain(&quote).unwrap()).unwrap();
+        assert_eq!(chain.len(), 3);
+        let root = chain.last().unwrap();
+        assert_eq!(
+            trust_anchor(&quote, &[]).unwrap(),
+            anchors::fingerprint(root)
+        );
+
+        let intermediate = TrustAnchor {
+            certs: vec![chain[1].clone()],
+        };
+        assert!(trust_anchor(&quote, &[intermediate.clone()]).is_err());
+        let root = TrustAnchor {
+            certs: vec![root.clone()],
+        };
+        assert_eq!(
+            trust_anchor(&quote, &[intermediate, root.clone()]).unwrap(),
+            root.claim()
+        );
+    }
+}
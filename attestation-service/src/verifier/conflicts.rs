@@ -0,0 +1,147 @@
+//! Conflicting claims.
+//!
+//! Some claims can be measured by more than one entity, such as the
+//! `kernel` of TDX evidence, measured by td-shim or by TDVF. Evidence where
+//! they measured different values shows a firmware misconfiguration, which
+//! should not end as one value silently overwriting the other. The claim is
+//! left out instead, so that policies that check it fail closed, each
+//! value is kept under the key of the entity that measured it, e.g.
+//! `kernel_td_shim` and `kernel_tdvf`, a warning is raised, and the conflict
+//! is recorded in the `claim_conflicts` diagnostics, see
+//! [`super::diagnostics`]:
+//!
+//! ```json
+//! "claim_conflicts": [
+//!     {
+//!         "claim": "ccel.kernel",
+//!         "values": {
+//!             "ccel.kernel_td_shim": "5b7aa6572f649714ff00b6a2b9170516a068fd1a0ba72aa8de27574131d454e6396d3bfa1727d9baf421618a942977fa",
+//!             "ccel.kernel_tdvf": "0d9a5b1e..."
+//!         }
+//!     }
+//! ]
+//! ```
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use super::{diagnostics, warnings};
+
+/// Name of the diagnostics of the conflicts.
+pub const CLAIM_CONFLICTS_DIAGNOSTIC: &str = "claim_conflicts";
+
+/// Values that different entities measured for the same claim.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ClaimConflict {
+    /// Path of the claim, e.g. `ccel.kernel`.
+    pub claim: String,
+    /// The value of each entity, by the path of the claim it is kept as.
+    pub values: BTreeMap<String, Value>,
+}
+
+/// Insert `claim` in `claims`, at `path`, with the value measured by each
+/// of the `sources`, as `(entity, value)` pairs. If the entities measured
+/// different values, insert `<claim>_<entity>` for each value instead, and
+/// return the conflict, which is recorded.
+pub(crate) fn insert(
+    claims: &mut Map<String, Value>,
+    path: &str,
+    claim: &str,
+    sources: Vec<(&str, Option<Value>)>,
+) -> Option<ClaimConflict> {
+    let measured: Vec<(&str, Value)> = sources
+        .into_iter()
+        .filter_map(|(entity, value)| Some((entity, value?)))
+        .collect();
+    let (_, first) = measured.first()?;
+    if measured.iter().all(|(_, value)| value == first) {
+        claims.insert(claim.to_string(), first.clone());
+        return None;
+    }
+
+    let mut values = BTreeMap::new();
+    for (entity, value) in measured {
+        let key = format!("{claim}_{entity}");
+        values.insert(format!("{path}.{key}"), value.clone());
+        claims.insert(key, value);
+    }
+    let conflict = ClaimConflict {
+        claim: format!("{path}.{claim}"),
+        values,
+    };
+    warnings::raise(format!(
+        "Conflicting {} claims: {}",
+        conflict.claim,
+        conflict
+            .values
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+    diagnostics::push(CLAIM_CONFLICTS_DIAGNOSTIC, &conflict);
+    Some(conflict)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_insert() {
+        let mut claims = Map::new();
+        let diagnostics = diagnostics::Diagnostics::new();
+        let (conflicts, warnings) =
+            warnings::collect(diagnostics::collect(Some(diagnostics.clone()), async {
+                [
+                    insert(
+                        &mut claims,
+                        "ccel",
+                        "kernel",
+                        vec![("td_shim", Some(json!("aa"))), ("tdvf", Some(json!("aa")))],
+                    ),
+                    insert(
+                        &mut claims,
+                        "ccel",
+                        "td_hob",
+                        vec![("td_shim", None), ("tdvf", Some(json!("bb")))],
+                    ),
+                    insert(&mut claims, "ccel", "cfv", vec![("tdvf", None)]),
+                    insert(
+                        &mut claims,
+                        "ccel",
+                        "initrd",
+                        vec![("td_shim", Some(json!("cc"))), ("tdvf", Some(json!("dd")))],
+                    ),
+                ]
+            }))
+            .await;
+
+        assert_eq!(conflicts[..3], [None, None, None]);
+        assert_eq!(
+            Value::Object(claims),
+            json!({
+                "kernel": "aa",
+                "td_hob": "bb",
+                "initrd_td_shim": "cc",
+                "initrd_tdvf": "dd",
+            })
+        );
+        assert_eq!(
+            warnings,
+            ["Conflicting ccel.initrd claims: ccel.initrd_td_shim, ccel.initrd_tdvf"]
+        );
+        assert_eq!(
+            diagnostics.to_json(),
+            json!({
+                "claim_conflicts": [{
+                    "claim": "ccel.initrd",
+                    "values": { "ccel.initrd_td_shim": "cc", "ccel.initrd_tdvf": "dd" },
+                }]
+            })
+        );
+        assert_eq!(conflicts[3].as_ref().unwrap().claim, "ccel.initrd");
+    }
+}
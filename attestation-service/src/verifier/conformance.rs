@@ -0,0 +1,203 @@
+//! Conformance test suite that every verifier must pass.
+//!
+//! A verifier joins the suite by describing the evidence and claims it can
+//! offer from `test_data` in a [`Conformance`], and calling [`run`] from a
+//! test of its own module:
+//!
+//! ```ignore
+//! #[tokio::test]
+//! async fn conformance() {
+//!     conformance::run(Conformance { .. }).await;
+//! }
+//! ```
+//!
+//! The suite checks that
+//! - malformed evidence is rejected, without panicking;
+//! - claims are a JSON object that flattens into well formed claims;
+//! - evidence is bound to the nonce and TEE public key by its report data;
+//! - claims tell whether the TEE is debuggable.
+//!
+//! The report data check needs evidence that the verifier accepts, which
+//! is not available offline for most hardware TEEs as their verification
+//! needs collateral from the vendor. Those only run the other checks.
+
+use super::*;
+use crate::utils::flatten_claims;
+use kbs_types::TeePubKey;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Malformed `tee-evidence` that every verifier must reject.
+const MALFORMED_EVIDENCE: &[&str] = &[
+    "",
+    "null",
+    "[]",
+    "{}",
+    "not a json",
+    r#"{"quote": "not base64!"}"#,
+    r#"{"quote": ""}"#,
+];
+
+/// Evidence that the verifier accepts.
+pub(crate) struct Evidence {
+    pub nonce: String,
+    pub attestation: Attestation,
+    /// Whether the TEE that produced the evidence is debuggable.
+    pub debug: bool,
+}
+
+/// Claims parsed from a known TEE report.
+pub(crate) struct KnownClaims {
+    pub claims: TeeEvidenceParsedClaim,
+    /// Whether the TEE that produced the report is debuggable.
+    pub debug: bool,
+}
+
+/// The flattened claim which tells whether the TEE is debuggable.
+pub(crate) struct DebugClaim {
+    pub name: &'static str,
+    pub is_debug: fn(&Value) -> Option<bool>,
+}
+
+pub(crate) struct Conformance<V> {
+    pub tee: Tee,
+    pub verifier: V,
+    /// TEE specific malformed `tee-evidence`, e.g. a truncated quote. They
+    /// are checked on top of [`MALFORMED_EVIDENCE`].
+    pub malformed: Vec<String>,
+    /// `None` if no evidence can be verified offline.
+    pub evidence: Option<Evidence>,
+    pub claims: Option<KnownClaims>,
+    pub debug_claim: Option<DebugClaim>,
+}
+
+/// A TEE public key for evidence that is not bound to any.
+pub(crate) fn dummy_tee_pubkey() -> TeePubKey {
+    TeePubKey {
+        kty: "RSA".to_string(),
+        alg: "RSA1_5".to_string(),
+        k_mod: "conformance".to_string(),
+        k_exp: "AQAB".to_string(),
+    }
+}
+
+pub(crate) async fn run<V: Verifier>(suite: Conformance<V>) {
+    rejects_malformed_evidence(&suite).await;
+
+    if let Some(evidence) = &suite.evidence {
+        binds_report_data(&suite, evidence).await;
+
+        let claims = suite
+            .verifier
+            .evaluate(evidence.nonce.clone(), &evidence.attestation)
+            .await
+            .unwrap_or_else(|e| panic!("{:?}: valid evidence is rejected: {e:?}", suite.tee));
+        check_claims(&suite, &claims, evidence.debug);
+    }
+
+    if let Some(known) = &suite.claims {
+        check_claims(&suite, &known.claims, known.debug);
+    }
+}
+
+async fn rejects_malformed_evidence<V: Verifier>(suite: &Conformance<V>) {
+    let malformed = MALFORMED_EVIDENCE
+        .iter()
+        .map(|evidence| evidence.to_string())
+        .chain(suite.malformed.iter().cloned());
+
+    for tee_evidence in malformed {
+        let attestation = Attestation {
+            tee_pubkey: dummy_tee_pubkey(),
+            tee_evidence,
+        };
+        let res = suite
+            .verifier
+            .evaluate("nonce".to_string(), &attestation)
+            .await;
+        assert!(
+            res.is_err(),
+            "{:?}: malformed evidence is accepted: {}",
+            suite.tee,
+            attestation.tee_evidence
+        );
+    }
+}
+
+async fn binds_report_data<V: Verifier>(suite: &Conformance<V>, evidence: &Evidence) {
+    let res = suite
+        .verifier
+        .evaluate(format!("{}-other", evidence.nonce), &evidence.attestation)
+        .await;
+    assert!(
+        res.is_err(),
+        "{:?}: evidence is accepted with another nonce",
+        suite.tee
+    );
+
+    let attestation = Attestation {
+        tee_pubkey: TeePubKey {
+            k_mod: format!("{}-other", evidence.attestation.tee_pubkey.k_mod),
+            ..evidence.attestation.tee_pubkey.clone()
+        },
+        tee_evidence: evidence.attestation.tee_evidence.clone(),
+    };
+    let res = suite
+        .verifier
+        .evaluate(evidence.nonce.clone(), &attestation)
+        .await;
+    assert!(
+        res.is_err(),
+        "{:?}: evidence is accepted with another TEE public key",
+        suite.tee
+    );
+}
+
+fn check_claims<V>(suite: &Conformance<V>, claims: &TeeEvidenceParsedClaim, debug: bool) {
+    let tee = &suite.tee;
+    let object = claims
+        .as_object()
+        .unwrap_or_else(|| panic!("{tee:?}: claims are not a JSON object"));
+    assert!(!object.is_empty(), "{tee:?}: claims are empty");
+
+    let flattened = flatten_claims(tee.clone(), claims)
+        .unwrap_or_else(|e| panic!("{tee:?}: claims cannot be flattened: {e:?}"));
+    for (name, value) in flattened.as_object().unwrap() {
+        assert!(
+            !name.contains("..") && !name.ends_with('.') && !name.contains(char::is_whitespace),
+            "{tee:?}: malformed claim name `{name}`"
+        );
+        assert!(
+            !value.is_array() && !value.is_object(),
+            "{tee:?}: claim `{name}` is not a scalar"
+        );
+    }
+
+    // The AS looks reference values up by flattened claim name.
+    serde_json::from_str::<HashMap<String, Value>>(&flattened.to_string())
+        .unwrap_or_else(|e| panic!("{tee:?}: claims are not a map of claim names: {e:?}"));
+
+    let debug_claim = suite
+        .debug_claim
+        .as_ref()
+        .unwrap_or_else(|| panic!("{tee:?}: no claim tells whether the TEE is debuggable"));
+    let value = flattened
+        .get(debug_claim.name)
+        .unwrap_or_else(|| panic!("{tee:?}: claim `{}` is missing", debug_claim.name));
+    assert_eq!(
+        (debug_claim.is_debug)(value),
+        Some(debug),
+        "{tee:?}: debug state is not detected from `{}` = {value}",
+        debug_claim.name
+    );
+
+    // Portable policies rely on the canonical claims of the table.
+    let mut flattened = flattened;
+    canonical::apply(serde_variant::to_variant_name(tee).unwrap(), &mut flattened);
+    assert_eq!(
+        flattened.get("debug").and_then(Value::as_bool),
+        Some(debug),
+        "{tee:?}: canonical `debug` claim does not match `{}`",
+        debug_claim.name
+    );
+}
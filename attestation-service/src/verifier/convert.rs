@@ -0,0 +1,608 @@
+//! Conversions of captured evidence, for offline analysis.
+//!
+//! Incident responders get evidence as raw binaries, e.g. a TD quote read
+//! from configfs-tsm, as hex dumps in logs, or as the claims of a token.
+//! The functions of this module convert between them for TD quotes,
+//! version 4 and 5, and SNP attestation reports, from the layout of their
+//! fields, without verifying them:
+//!
+//! - [`hexdump`] and [`from_hexdump`]: raw bytes to an `xxd` hex dump and
+//!   back, plain hex being accepted too;
+//! - [`claims`]: raw evidence to claims named like those of the verifier,
+//!   e.g. `quote.body.mr_td` or `reported_tcb_snp`, with every field but
+//!   the reserved ones;
+//! - [`from_claims`]: claims, nested or flattened like those of tokens, to
+//!   the signed part of the evidence, its signature not being in claims;
+//! - [`annotate`] and [`annotate_claims`]: raw evidence or claims to a hex
+//!   dump of their fields by name, the bytes the claims do not tell being
+//!   `??`.
+//!
+//! `as-tool convert` runs them from the command line.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use serde_json::{Map, Value};
+use std::fmt::Write;
+
+/// Kind of captured evidence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display)]
+pub enum EvidenceKind {
+    /// A TD quote, version 4 or 5.
+    #[strum(serialize = "tdx")]
+    TdxQuote,
+    /// An SNP attestation report.
+    #[strum(serialize = "snp")]
+    SnpReport,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    /// A little-endian integer, a JSON number.
+    LeUint,
+    /// An array of bytes, a JSON array of numbers.
+    Bytes,
+    Hex,
+    Base64,
+    /// Reserved, not a claim.
+    Reserved,
+}
+
+use Encoding::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Field {
+    name: &'static str,
+    offset: usize,
+    size: usize,
+    encoding: Encoding,
+}
+
+const TDX_HEADER: &[(&str, usize, Encoding)] = &[
+    ("quote.header.version", 2, LeUint),
+    ("quote.header.att_key_type", 2, LeUint),
+    ("quote.header.tee_type", 4, LeUint),
+    ("quote.header.reserved", 4, Hex),
+    ("quote.header.vendor_id", 16, Hex),
+    ("quote.header.user_data", 20, Hex),
+];
+
+/// The type and size of the body of a version 5 quote.
+const TDX_BODY_DESCRIPTOR: &[(&str, usize, Encoding)] = &[
+    ("quote.body_type", 2, LeUint),
+    ("quote.body_size", 4, LeUint),
+];
+
+const TDX_BODY: &[(&str, usize, Encoding)] = &[
+    ("quote.body.tcb_svn", 16, Bytes),
+    ("quote.body.mr_seam", 48, Hex),
+    ("quote.body.mrsigner_seam", 48, Hex),
+    ("quote.body.seam_attributes", 8, Hex),
+    ("quote.body.td_attributes", 8, Hex),
+    ("quote.body.xfam", 8, Hex),
+    ("quote.body.mr_td", 48, Hex),
+    ("quote.body.mr_config_id", 48, Hex),
+    ("quote.body.mr_owner", 48, Hex),
+    ("quote.body.mr_owner_config", 48, Hex),
+    ("quote.body.rtmr_0", 48, Hex),
+    ("quote.body.rtmr_1", 48, Hex),
+    ("quote.body.rtmr_2", 48, Hex),
+    ("quote.body.rtmr_3", 48, Hex),
+    ("quote.body.report_data", 64, Hex),
+];
+
+/// The fields a TD report 1.5 body adds to a 1.0 one.
+const TDX_BODY_1_5: &[(&str, usize, Encoding)] = &[
+    ("quote.body.tee_tcb_svn_2", 16, Bytes),
+    ("quote.body.mr_servicetd", 48, Hex),
+];
+
+/// Type of the body of a version 5 quote of a TDX 1.5 module.
+const TDX_BODY_TYPE_1_5: u64 = 3;
+
+/// The SNP attestation report, version 2, of the SEV-SNP firmware ABI
+/// specification. The SVNs of each TCB version are named like the claims
+/// of the verifier.
+const SNP_REPORT: &[(&str, usize, Encoding)] = &[
+    ("version", 4, LeUint),
+    ("guest_svn", 4, LeUint),
+    ("policy", 8, Hex),
+    ("family_id", 16, Hex),
+    ("image_id", 16, Hex),
+    ("vmpl", 4, LeUint),
+    ("sig_algo", 4, LeUint),
+    ("current_tcb.bootloader", 1, LeUint),
+    ("current_tcb.tee", 1, LeUint),
+    ("current_tcb.reserved", 4, Reserved),
+    ("current_tcb.snp", 1, LeUint),
+    ("current_tcb.microcode", 1, LeUint),
+    ("plat_info", 8, Hex),
+    ("key_info", 4, Hex),
+    ("reserved_0", 4, Reserved),
+    ("report_data", 64, Hex),
+    // Base64, like the claim of the verifier.
+    ("measurement", 48, Base64),
+    ("host_data", 32, Hex),
+    ("id_key_digest", 48, Hex),
+    ("author_key_digest", 48, Hex),
+    ("report_id", 32, Hex),
+    ("report_id_ma", 32, Hex),
+    ("reported_tcb_bootloader", 1, LeUint),
+    ("reported_tcb_tee", 1, LeUint),
+    ("reported_tcb_reserved", 4, Reserved),
+    ("reported_tcb_snp", 1, LeUint),
+    ("reported_tcb_microcode", 1, LeUint),
+    ("reserved_1", 24, Reserved),
+    ("chip_id", 64, Hex),
+    ("committed_tcb.bootloader", 1, LeUint),
+    ("committed_tcb.tee", 1, LeUint),
+    ("committed_tcb.reserved", 4, Reserved),
+    ("committed_tcb.snp", 1, LeUint),
+    ("committed_tcb.microcode", 1, LeUint),
+    ("current_build", 1, LeUint),
+    ("current_minor", 1, LeUint),
+    ("current_major", 1, LeUint),
+    ("reserved_2", 1, Reserved),
+    ("committed_build", 1, LeUint),
+    ("committed_minor", 1, LeUint),
+    ("committed_major", 1, LeUint),
+    ("reserved_3", 1, Reserved),
+    ("launch_tcb.bootloader", 1, LeUint),
+    ("launch_tcb.tee", 1, LeUint),
+    ("launch_tcb.reserved", 4, Reserved),
+    ("launch_tcb.snp", 1, LeUint),
+    ("launch_tcb.microcode", 1, LeUint),
+    ("reserved_4", 168, Reserved),
+];
+
+/// Size of the part of an SNP report its signature signs.
+const SNP_SIGNED_SIZE: usize = 0x2a0;
+
+const SNP_SIGNATURE: &[(&str, usize, Encoding)] = &[
+    ("signature.r", 72, Hex),
+    ("signature.s", 72, Hex),
+    ("signature.reserved", 368, Reserved),
+];
+
+/// Lay out `fields` after those of `layout`.
+fn lay_out(layout: &mut Vec<Field>, fields: &[(&'static str, usize, Encoding)]) {
+    let mut offset = end(layout);
+    for &(name, size, encoding) in fields {
+        layout.push(Field {
+            name,
+            offset,
+            size,
+            encoding,
+        });
+        offset += size;
+    }
+}
+
+fn end(layout: &[Field]) -> usize {
+    layout.last().map_or(0, |field| field.offset + field.size)
+}
+
+/// The signed part of a TD quote of `version`, with a TD report 1.5 body
+/// if `body_1_5`.
+fn tdx_payload(version: u64, body_1_5: bool) -> Vec<Field> {
+    let mut layout = Vec::new();
+    lay_out(&mut layout, TDX_HEADER);
+    if version == 5 {
+        lay_out(&mut layout, TDX_BODY_DESCRIPTOR);
+    }
+    lay_out(&mut layout, TDX_BODY);
+    if version == 5 && body_1_5 {
+        lay_out(&mut layout, TDX_BODY_1_5);
+    }
+    layout
+}
+
+fn snp_signed() -> Vec<Field> {
+    let mut layout = Vec::new();
+    lay_out(&mut layout, SNP_REPORT);
+    layout
+}
+
+fn le_uint(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, byte| (value << 8) | u64::from(*byte))
+}
+
+/// The `size` bytes of `raw` at `offset`.
+fn bytes<'a>(what: &str, raw: &'a [u8], offset: usize, size: usize) -> Result<&'a [u8]> {
+    raw.get(offset..offset + size).ok_or_else(|| {
+        anyhow!(
+            "{what} is too short: {} bytes, expected at least {}",
+            raw.len(),
+            offset + size
+        )
+    })
+}
+
+/// The layout of the `raw` evidence, that it fills.
+fn raw_layout(kind: EvidenceKind, raw: &[u8]) -> Result<Vec<Field>> {
+    match kind {
+        EvidenceKind::TdxQuote => {
+            let version = le_uint(bytes("TD quote", raw, 0, 2)?);
+            if version != 4 && version != 5 {
+                bail!("TD quote of version {version}, expected 4 or 5");
+            }
+            let body_1_5 =
+                version == 5 && le_uint(bytes("TD quote", raw, 48, 2)?) == TDX_BODY_TYPE_1_5;
+            let mut layout = tdx_payload(version, body_1_5);
+            let payload_size = end(&layout);
+            bytes("TD quote", raw, 0, payload_size)?;
+            if raw.len() > payload_size {
+                lay_out(&mut layout, &[("quote.signature_data_len", 4, LeUint)]);
+                bytes("TD quote", raw, 0, end(&layout))?;
+                let signature_size = raw.len() - end(&layout);
+                if signature_size > 0 {
+                    lay_out(
+                        &mut layout,
+                        &[("quote.signature_data", signature_size, Hex)],
+                    );
+                }
+            }
+            Ok(layout)
+        }
+        EvidenceKind::SnpReport => {
+            let mut layout = snp_signed();
+            match raw.len() {
+                SNP_SIGNED_SIZE => (),
+                n if n == SNP_SIGNED_SIZE + 512 => {
+                    lay_out(&mut layout, SNP_SIGNATURE);
+                }
+                n => bail!(
+                    "SNP report is {n} bytes, expected {} or {SNP_SIGNED_SIZE} without its signature",
+                    SNP_SIGNED_SIZE + 512
+                ),
+            }
+            Ok(layout)
+        }
+    }
+}
+
+/// The claims of the `raw` evidence of `kind`.
+pub fn claims(kind: EvidenceKind, raw: &[u8]) -> Result<Value> {
+    let mut claims = Map::new();
+    for field in raw_layout(kind, raw)? {
+        let bytes = &raw[field.offset..field.offset + field.size];
+        let value = match field.encoding {
+            LeUint => Value::from(le_uint(bytes)),
+            Bytes => Value::from(bytes.to_vec()),
+            Hex => Value::from(hex::encode(bytes)),
+            Base64 => Value::from(base64::engine::general_purpose::STANDARD.encode(bytes)),
+            Reserved => continue,
+        };
+        insert(&mut claims, field.name, value);
+    }
+    Ok(Value::Object(claims))
+}
+
+/// Insert `value` at the dotted `path` of `claims`.
+fn insert(claims: &mut Map<String, Value>, path: &str, value: Value) {
+    match path.split_once('.') {
+        Some((key, rest)) => {
+            let nested = claims
+                .entry(key)
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(nested) = nested {
+                insert(nested, rest, value);
+            }
+        }
+        None => {
+            claims.insert(path.to_string(), value);
+        }
+    }
+}
+
+/// Flatten the nested objects of `claims` into dotted names.
+fn flatten(prefix: &str, claims: &Value, flat: &mut Map<String, Value>) {
+    match claims {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let name = match prefix {
+                    "" => key.clone(),
+                    prefix => format!("{prefix}.{key}"),
+                };
+                flatten(&name, value, flat);
+            }
+        }
+        value => {
+            flat.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+/// The bytes of the claim `field`, of `value`.
+fn claim_bytes(field: &Field, value: &Value) -> Result<Vec<u8>> {
+    let bytes = match (field.encoding, value) {
+        (LeUint, Value::Number(number)) => {
+            let number = number.as_u64().ok_or_else(|| anyhow!("not an integer"))?;
+            if field.size < 8 && number >> (8 * field.size) != 0 {
+                bail!("{number} does not fit in {} bytes", field.size);
+            }
+            number.to_le_bytes()[..field.size.min(8)].to_vec()
+        }
+        (Bytes, Value::Array(values)) => values
+            .iter()
+            .map(|value| {
+                value
+                    .as_u64()
+                    .and_then(|value| u8::try_from(value).ok())
+                    .ok_or_else(|| anyhow!("{value} is not a byte"))
+            })
+            .collect::<Result<_>>()?,
+        (Hex, Value::String(value)) => hex::decode(value)?,
+        (Base64, Value::String(value)) => {
+            base64::engine::general_purpose::STANDARD.decode(value)?
+        }
+        (_, value) => bail!("unexpected {value}"),
+    };
+    if bytes.len() != field.size {
+        bail!("{} bytes, expected {}", bytes.len(), field.size);
+    }
+    Ok(bytes)
+}
+
+/// The bytes of the signed part of the evidence of `kind` that the
+/// `claims` tell, `None` for those they do not.
+fn claimed_bytes(kind: EvidenceKind, claims: &Value) -> Result<(Vec<Field>, Vec<Option<u8>>)> {
+    let mut flat = Map::new();
+    flatten("", claims, &mut flat);
+    // Flattened claims of tokens are named after the TEE.
+    let prefix = format!("{kind}.");
+    let claim = |name: &str| {
+        flat.get(name)
+            .or_else(|| flat.get(&format!("{prefix}{name}")))
+    };
+    let layout = match kind {
+        EvidenceKind::TdxQuote => {
+            let version = claim("quote.header.version")
+                .and_then(Value::as_u64)
+                .unwrap_or(4);
+            tdx_payload(version, claim("quote.body.tee_tcb_svn_2").is_some())
+        }
+        EvidenceKind::SnpReport => snp_signed(),
+    };
+    let mut bytes = vec![None; end(&layout)];
+    for field in &layout {
+        let value = match (field.encoding, claim(field.name)) {
+            (Reserved, _) => Some(vec![0; field.size]),
+            (_, Some(value)) => {
+                Some(claim_bytes(field, value).with_context(|| format!("Claim {}", field.name))?)
+            }
+            (_, None) => None,
+        };
+        if let Some(value) = value {
+            for (byte, value) in bytes[field.offset..].iter_mut().zip(value) {
+                *byte = Some(value);
+            }
+        }
+    }
+    Ok((layout, bytes))
+}
+
+/// The signed part of the evidence of `kind` of the `claims`, which must
+/// have all its fields.
+pub fn from_claims(kind: EvidenceKind, claims: &Value) -> Result<Vec<u8>> {
+    let (layout, bytes) = claimed_bytes(kind, claims)?;
+    let missing: Vec<_> = layout
+        .iter()
+        .filter(|field| bytes[field.offset].is_none())
+        .map(|field| field.name)
+        .collect();
+    if !missing.is_empty() {
+        bail!("Claims without {}", missing.join(", "));
+    }
+    Ok(bytes.into_iter().flatten().collect())
+}
+
+/// A hex dump of `bytes` in the format of `xxd`.
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let hex = chunk
+            .chunks(2)
+            .map(hex::encode)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let ascii: String = chunk
+            .iter()
+            .map(|byte| match byte {
+                0x20..=0x7e => *byte as char,
+                _ => '.',
+            })
+            .collect();
+        let _ = writeln!(dump, "{:08x}: {hex:<39}  {ascii}", line * 16);
+    }
+    dump
+}
+
+/// The bytes of a hex dump in the format of `xxd`, or of plain hex,
+/// whitespace aside.
+pub fn from_hexdump(dump: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for (number, line) in dump.lines().enumerate() {
+        let hex = match line.split_once(':') {
+            // Offset, hex, then the ASCII column.
+            Some((_, rest)) => {
+                let rest = rest.strip_prefix(' ').unwrap_or(rest);
+                rest.split_once("  ").map_or(rest, |(hex, _)| hex)
+            }
+            None => line,
+        };
+        let hex: String = hex.split_whitespace().collect();
+        bytes.extend(hex::decode(hex).with_context(|| format!("Line {}", number + 1))?);
+    }
+    Ok(bytes)
+}
+
+fn title(kind: EvidenceKind, layout: &[Field], bytes: &[Option<u8>]) -> String {
+    let uint = |offset: usize, size: usize| {
+        bytes[offset..offset + size]
+            .iter()
+            .copied()
+            .collect::<Option<Vec<_>>>()
+            .map(|bytes| le_uint(&bytes))
+    };
+    // The version is the first field of both.
+    let version = layout
+        .first()
+        .and_then(|field| uint(field.offset, field.size))
+        .map_or("?".to_string(), |version| version.to_string());
+    match kind {
+        EvidenceKind::TdxQuote => format!("TD quote, version {version}"),
+        EvidenceKind::SnpReport => format!("SNP attestation report, version {version}"),
+    }
+}
+
+/// The hex dump of the fields of `layout` of `bytes`, by name.
+fn annotated(kind: EvidenceKind, layout: &[Field], bytes: &[Option<u8>]) -> String {
+    let mut dump = format!("# {}\n", title(kind, layout, bytes));
+    for field in layout {
+        let field_bytes = &bytes[field.offset..field.offset + field.size];
+        for (line, chunk) in field_bytes.chunks(16).enumerate() {
+            let hex = chunk
+                .iter()
+                .map(|byte| byte.map_or("??".to_string(), |byte| format!("{byte:02x}")))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let name = match line {
+                0 => field.name,
+                _ => "",
+            };
+            let _ = writeln!(dump, "{:08x}  {hex:<47}  {name}", field.offset + line * 16);
+        }
+    }
+    dump
+}
+
+/// The hex dump of the fields of the `raw` evidence of `kind`, by name.
+pub fn annotate(kind: EvidenceKind, raw: &[u8]) -> Result<String> {
+    let layout = raw_layout(kind, raw)?;
+    let bytes: Vec<_> = raw.iter().copied().map(Some).collect();
+    Ok(annotated(kind, &layout, &bytes))
+}
+
+/// The hex dump of the fields of the signed part of the evidence of `kind`
+/// of the `claims`, by name.
+pub fn annotate_claims(kind: EvidenceKind, claims: &Value) -> Result<String> {
+    let (layout, bytes) = claimed_bytes(kind, claims)?;
+    Ok(annotated(kind, &layout, &bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A version 4 TD quote, with 8 bytes of signature data.
+    fn td_quote() -> Vec<u8> {
+        let mut quote = vec![0u8; 632];
+        quote[..8].copy_from_slice(&[4, 0, 2, 0, 0x81, 0, 0, 0]);
+        quote[48] = 3;
+        // MRTD.
+        quote[48 + 136..48 + 184].fill(0x70);
+        quote.extend(8u32.to_le_bytes());
+        quote.extend([0xee; 8]);
+        quote
+    }
+
+    #[test]
+    fn test_tdx() {
+        let quote = td_quote();
+        let parsed = claims(EvidenceKind::TdxQuote, &quote).unwrap();
+        assert_eq!(parsed["quote"]["header"]["version"], 4);
+        assert_eq!(parsed["quote"]["header"]["tee_type"], 0x81);
+        assert_eq!(parsed["quote"]["body"]["tcb_svn"][0], 3);
+        assert_eq!(parsed["quote"]["body"]["mr_td"], "70".repeat(48));
+        assert_eq!(parsed["quote"]["signature_data_len"], 8);
+        assert_eq!(parsed["quote"]["signature_data"], "ee".repeat(8));
+
+        // The signed part, from the claims, nested or flattened like those
+        // of tokens.
+        assert_eq!(
+            from_claims(EvidenceKind::TdxQuote, &parsed).unwrap(),
+            quote[..632]
+        );
+        let mut flat = Map::new();
+        flatten("tdx", &parsed, &mut flat);
+        assert_eq!(
+            from_claims(EvidenceKind::TdxQuote, &Value::Object(flat)).unwrap(),
+            quote[..632]
+        );
+
+        let annotated = annotate(EvidenceKind::TdxQuote, &quote).unwrap();
+        let lines: Vec<_> = annotated.lines().collect();
+        assert_eq!(lines[0], "# TD quote, version 4");
+        assert_eq!(
+            lines[1],
+            format!("00000000  04 00{}  quote.header.version", " ".repeat(42))
+        );
+        assert!(annotated.contains(&format!(
+            "000000b8  {}  quote.body.mr_td",
+            ["70"; 16].join(" ")
+        )));
+        assert!(lines.last().unwrap().ends_with("quote.signature_data"));
+
+        assert!(from_claims(EvidenceKind::TdxQuote, &json!({"quote": {}})).is_err());
+        assert_eq!(
+            format!(
+                "{:#}",
+                from_claims(
+                    EvidenceKind::TdxQuote,
+                    &json!({"quote": {"header": {"version": 65536}}})
+                )
+                .unwrap_err()
+            ),
+            "Claim quote.header.version: 65536 does not fit in 2 bytes"
+        );
+        assert!(claims(EvidenceKind::TdxQuote, &quote[..100]).is_err());
+    }
+
+    #[test]
+    fn test_annotate_claims() {
+        let annotated = annotate_claims(
+            EvidenceKind::SnpReport,
+            &json!({"snp.version": 2, "snp.measurement": base64::engine::general_purpose::STANDARD.encode([0xaa; 48])}),
+        )
+        .unwrap();
+        let lines: Vec<_> = annotated.lines().collect();
+        assert_eq!(lines[0], "# SNP attestation report, version 2");
+        assert!(lines[1].starts_with("00000000  02 00 00 00"));
+        assert!(lines[2].starts_with("00000004  ?? ?? ?? ??"));
+        assert!(annotated.contains(&format!("00000090  {}  measurement", ["aa"; 16].join(" "))));
+        // Reserved fields are zeros.
+        assert!(annotated.contains("0000003a  00 00 00 00"));
+
+        let report = vec![0u8; SNP_SIGNED_SIZE + 512];
+        let parsed = claims(EvidenceKind::SnpReport, &report).unwrap();
+        assert_eq!(parsed["current_tcb"]["snp"], 0);
+        assert_eq!(parsed["signature"]["r"], "00".repeat(72));
+        assert!(parsed.get("reserved_0").is_none());
+        assert_eq!(
+            from_claims(EvidenceKind::SnpReport, &parsed).unwrap(),
+            report[..SNP_SIGNED_SIZE]
+        );
+        assert!(claims(EvidenceKind::SnpReport, &report[1..]).is_err());
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let bytes = b"\x04\x00\x02\x00TDX quote\xff".to_vec();
+        let dump = hexdump(&bytes);
+        assert_eq!(
+            dump,
+            "00000000: 0400 0200 5444 5820 7175 6f74 65ff       ....TDX quote.\n"
+        );
+        assert_eq!(from_hexdump(&dump).unwrap(), bytes);
+        assert_eq!(
+            from_hexdump("04000200\n5444 5820 7175 6f74 65ff\n").unwrap(),
+            bytes
+        );
+        assert_eq!(from_hexdump(&hexdump(&td_quote())).unwrap(), td_quote());
+        assert!(from_hexdump("00000000: 0g00").is_err());
+    }
+}
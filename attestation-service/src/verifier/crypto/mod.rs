@@ -0,0 +1,272 @@
+//! Crypto backends of the verifiers.
+//!
+//! Verifiers check the certificate chains and signatures of evidence
+//! through a [`CryptoBackend`] rather than a given crypto library, so that
+//! deployments can pick the library that suits them:
+//! - `OpenSSL` (feature `crypto-openssl`): the system OpenSSL, with the
+//!   providers and engines it is configured with, e.g. a FIPS provider or a
+//!   hardware accelerator;
+//! - `Ring` (feature `crypto-ring`): [ring](https://github.com/briansmith/ring).
+//!
+//! The backend is selected with `crypto_backend` in the AS config. The
+//! methods are async so that a backend can offload the work to a device or
+//! a remote service. TDX and SGX quotes are verified by the Intel QVL, and
+//! do not go through the backend.
+
+use anyhow::*;
+use async_trait::async_trait;
+use serde::Deserialize;
+use strum_macros::EnumString;
+
+#[cfg(feature = "crypto-openssl")]
+mod openssl_backend;
+#[cfg(feature = "crypto-ring")]
+mod ring_backend;
+
+#[async_trait]
+pub trait CryptoBackend {
+    /// Verify the signature of the DER certificate `cert` with the public
+    /// key of the DER certificate `issuer`.
+    async fn verify_certificate(&self, cert: &[u8], issuer: &[u8]) -> Result<()>;
+
+    /// Verify an ECDSA P-384 signature of the SHA-384 of `data` with the
+    /// public key of the DER certificate `cert`. The signature is given by
+    /// its big-endian `r` and `s`.
+    async fn verify_ecdsa_p384_sha384(
+        &self,
+        cert: &[u8],
+        data: &[u8],
+        r: &[u8],
+        s: &[u8],
+    ) -> Result<()>;
+
+    /// Verify an ECDSA signature of `data` with a bare public key, an
+    /// uncompressed SEC1 point of P-256 or P-384, whose curve tells the
+    /// digest: SHA-256 for P-256 and SHA-384 for P-384, see [`EcdsaCurve`].
+    /// The signature is given by its big-endian `r` and `s`.
+    async fn verify_ecdsa_with_key(
+        &self,
+        public_key: &[u8],
+        data: &[u8],
+        r: &[u8],
+        s: &[u8],
+    ) -> Result<()>;
+
+    /// Verify an RSASSA-PSS signature of the SHA-384 of `data`, with MGF1
+    /// over SHA-384 and a salt as long as the digest, with the public key of
+    /// the DER certificate `cert`.
+    async fn verify_rsa_pss_sha384(&self, cert: &[u8], data: &[u8], signature: &[u8])
+        -> Result<()>;
+}
+
+/// Curve of a bare ECDSA public key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcdsaCurve {
+    P256,
+    P384,
+}
+
+impl EcdsaCurve {
+    /// The curve of an uncompressed SEC1 point.
+    pub fn of_public_key(public_key: &[u8]) -> Result<Self> {
+        match (public_key.first(), public_key.len()) {
+            (Some(0x04), 65) => Ok(Self::P256),
+            (Some(0x04), 97) => Ok(Self::P384),
+            _ => bail!("Unsupported ECDSA public key, not an uncompressed P-256 or P-384 point"),
+        }
+    }
+
+    /// Size of the scalars of the signatures, and of the coordinates of
+    /// the points.
+    pub fn scalar_size(&self) -> usize {
+        match self {
+            Self::P256 => 32,
+            Self::P384 => 48,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+pub enum CryptoBackendType {
+    OpenSSL,
+    Ring,
+}
+
+impl Default for CryptoBackendType {
+    // OpenSSL if it is built in, as it can be configured with FIPS and
+    // hardware providers.
+    fn default() -> Self {
+        if cfg!(feature = "crypto-ring") && !cfg!(feature = "crypto-openssl") {
+            CryptoBackendType::Ring
+        } else {
+            CryptoBackendType::OpenSSL
+        }
+    }
+}
+
+impl CryptoBackendType {
+    pub fn to_backend(&self) -> Result<Box<dyn CryptoBackend + Send + Sync>> {
+        match self {
+            CryptoBackendType::OpenSSL => {
+                cfg_if::cfg_if! {
+                    if #[cfg(feature = "crypto-openssl")] {
+                        Ok(Box::new(openssl_backend::OpenSSL) as Box<dyn CryptoBackend + Send + Sync>)
+                    } else {
+                        bail!("feature `crypto-openssl` is not enabled!")
+                    }
+                }
+            }
+            CryptoBackendType::Ring => {
+                cfg_if::cfg_if! {
+                    if #[cfg(feature = "crypto-ring")] {
+                        Ok(Box::new(ring_backend::Ring) as Box<dyn CryptoBackend + Send + Sync>)
+                    } else {
+                        bail!("feature `crypto-ring` is not enabled!")
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "crypto-openssl"))]
+mod tests {
+    use super::*;
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNumContext,
+        ec::{EcGroup, EcKey, PointConversionForm},
+        ecdsa::EcdsaSig,
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::PKey,
+        rsa::{Padding, Rsa},
+        sign::{RsaPssSaltlen, Signer},
+        x509::{X509NameBuilder, X509},
+    };
+    use sha2::{Digest, Sha384};
+
+    /// The backends that are built in.
+    fn backends() -> Vec<Box<dyn CryptoBackend + Send + Sync>> {
+        [CryptoBackendType::OpenSSL, CryptoBackendType::Ring]
+            .iter()
+            .filter_map(|backend| backend.to_backend().ok())
+            .collect()
+    }
+
+    fn self_signed_cert(key: &PKey<openssl::pkey::Private>) -> Vec<u8> {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "test").unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(key, MessageDigest::sha384()).unwrap();
+        builder.build().to_der().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_backends() {
+        let group = EcGroup::from_curve_name(Nid::SECP384R1).unwrap();
+        let ec_key = EcKey::generate(&group).unwrap();
+        let key = PKey::from_ec_key(ec_key.clone()).unwrap();
+        let cert = self_signed_cert(&key);
+        let other_cert =
+            self_signed_cert(&PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap());
+
+        let data = b"attestation report";
+        let signature = EcdsaSig::sign(&Sha384::digest(data), &ec_key).unwrap();
+        let (r, s) = (signature.r().to_vec(), signature.s().to_vec());
+
+        for backend in backends() {
+            backend.verify_certificate(&cert, &cert).await.unwrap();
+            assert!(backend
+                .verify_certificate(&cert, &other_cert)
+                .await
+                .is_err());
+
+            backend
+                .verify_ecdsa_p384_sha384(&cert, data, &r, &s)
+                .await
+                .unwrap();
+            assert!(backend
+                .verify_ecdsa_p384_sha384(&cert, b"forged report", &r, &s)
+                .await
+                .is_err());
+            assert!(backend
+                .verify_ecdsa_p384_sha384(&other_cert, data, &r, &s)
+                .await
+                .is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ecdsa_with_key() {
+        let data = b"platform token";
+        for (nid, digest) in [
+            (Nid::X9_62_PRIME256V1, MessageDigest::sha256()),
+            (Nid::SECP384R1, MessageDigest::sha384()),
+        ] {
+            let group = EcGroup::from_curve_name(nid).unwrap();
+            let key = EcKey::generate(&group).unwrap();
+            let mut ctx = BigNumContext::new().unwrap();
+            let public_key = key
+                .public_key()
+                .to_bytes(&group, PointConversionForm::UNCOMPRESSED, &mut ctx)
+                .unwrap();
+            let signature =
+                EcdsaSig::sign(&openssl::hash::hash(digest, data).unwrap(), &key).unwrap();
+            let (r, s) = (signature.r().to_vec(), signature.s().to_vec());
+
+            for backend in backends() {
+                backend
+                    .verify_ecdsa_with_key(&public_key, data, &r, &s)
+                    .await
+                    .unwrap();
+                assert!(backend
+                    .verify_ecdsa_with_key(&public_key, b"forged token", &r, &s)
+                    .await
+                    .is_err());
+                assert!(backend
+                    .verify_ecdsa_with_key(&public_key[1..], data, &r, &s)
+                    .await
+                    .is_err());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rsa_pss() {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let cert = self_signed_cert(&key);
+
+        let data = b"uvm endorsements";
+        let mut signer = Signer::new(MessageDigest::sha384(), &key).unwrap();
+        signer.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+        signer.set_rsa_mgf1_md(MessageDigest::sha384()).unwrap();
+        signer
+            .set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)
+            .unwrap();
+        let signature = signer.sign_oneshot_to_vec(data).unwrap();
+
+        for backend in backends() {
+            backend
+                .verify_rsa_pss_sha384(&cert, data, &signature)
+                .await
+                .unwrap();
+            assert!(backend
+                .verify_rsa_pss_sha384(&cert, b"forged endorsements", &signature)
+                .await
+                .is_err());
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use super::{CryptoBackend, EcdsaCurve};
+use anyhow::*;
+use async_trait::async_trait;
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    ec::{EcGroup, EcKey, EcPoint},
+    ecdsa::EcdsaSig,
+    hash::{hash, MessageDigest},
+    nid::Nid,
+    rsa::Padding,
+    sign::{RsaPssSaltlen, Verifier},
+    x509::X509,
+};
+use sha2::{Digest, Sha384};
+
+/// Crypto backend of the system OpenSSL.
+pub struct OpenSSL;
+
+#[async_trait]
+impl CryptoBackend for OpenSSL {
+    async fn verify_certificate(&self, cert: &[u8], issuer: &[u8]) -> Result<()> {
+        let cert = X509::from_der(cert).context("Failed to load certificate")?;
+        let issuer = X509::from_der(issuer).context("Failed to load issuer certificate")?;
+
+        let issuer_key = issuer.public_key()?;
+        if !cert.verify(&issuer_key)? {
+            bail!("Invalid certificate signature");
+        }
+        Ok(())
+    }
+
+    async fn verify_ecdsa_p384_sha384(
+        &self,
+        cert: &[u8],
+        data: &[u8],
+        r: &[u8],
+        s: &[u8],
+    ) -> Result<()> {
+        let cert = X509::from_der(cert).context("Failed to load certificate")?;
+        let key = EcKey::try_from(cert.public_key()?)?;
+        let signature =
+            EcdsaSig::from_private_components(BigNum::from_slice(r)?, BigNum::from_slice(s)?)?;
+
+        if !signature.verify(&Sha384::digest(data), &key)? {
+            bail!("Invalid ECDSA signature");
+        }
+        Ok(())
+    }
+
+    async fn verify_ecdsa_with_key(
+        &self,
+        public_key: &[u8],
+        data: &[u8],
+        r: &[u8],
+        s: &[u8],
+    ) -> Result<()> {
+        let (nid, digest) = match EcdsaCurve::of_public_key(public_key)? {
+            EcdsaCurve::P256 => (Nid::X9_62_PRIME256V1, MessageDigest::sha256()),
+            EcdsaCurve::P384 => (Nid::SECP384R1, MessageDigest::sha384()),
+        };
+        let group = EcGroup::from_curve_name(nid)?;
+        let mut ctx = BigNumContext::new()?;
+        let point = EcPoint::from_bytes(&group, public_key, &mut ctx)
+            .context("Invalid ECDSA public key")?;
+        let key = EcKey::from_public_key(&group, &point)?;
+        let signature =
+            EcdsaSig::from_private_components(BigNum::from_slice(r)?, BigNum::from_slice(s)?)?;
+
+        if !signature.verify(&hash(digest, data)?, &key)? {
+            bail!("Invalid ECDSA signature");
+        }
+        Ok(())
+    }
+
+    async fn verify_rsa_pss_sha384(
+        &self,
+        cert: &[u8],
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        let cert = X509::from_der(cert).context("Failed to load certificate")?;
+        let key = cert.public_key()?;
+
+        let mut verifier = Verifier::new(MessageDigest::sha384(), &key)?;
+        verifier.set_rsa_padding(Padding::PKCS1_PSS)?;
+        verifier.set_rsa_mgf1_md(MessageDigest::sha384())?;
+        verifier.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+        if !verifier.verify_oneshot(signature, data)? {
+            bail!("Invalid RSASSA-PSS signature");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use super::{CryptoBackend, EcdsaCurve};
+use anyhow::*;
+use async_trait::async_trait;
+use ring::signature::{self, UnparsedPublicKey, VerificationAlgorithm};
+use x509_parser::oid_registry::*;
+use x509_parser::prelude::*;
+
+/// Size of the scalars of P-384 signatures.
+const P384_SCALAR_SIZE: usize = 48;
+
+/// Crypto backend of ring, which parses certificates with x509-parser.
+pub struct Ring;
+
+fn parse_certificate(cert: &[u8]) -> Result<X509Certificate<'_>> {
+    let (_, cert) = X509Certificate::from_der(cert).context("Failed to load certificate")?;
+    Ok(cert)
+}
+
+/// Algorithm of a certificate signature. RSASSA-PSS signatures must be
+/// over SHA-384, as those of the AMD certificates.
+fn signature_algorithm(oid: &Oid) -> Result<&'static dyn VerificationAlgorithm> {
+    match oid {
+        oid if *oid == OID_PKCS1_RSASSAPSS => Ok(&signature::RSA_PSS_2048_8192_SHA384),
+        oid if *oid == OID_PKCS1_SHA256WITHRSA => Ok(&signature::RSA_PKCS1_2048_8192_SHA256),
+        oid if *oid == OID_PKCS1_SHA384WITHRSA => Ok(&signature::RSA_PKCS1_2048_8192_SHA384),
+        oid if *oid == OID_SIG_ECDSA_WITH_SHA256 => Ok(&signature::ECDSA_P256_SHA256_ASN1),
+        oid if *oid == OID_SIG_ECDSA_WITH_SHA384 => Ok(&signature::ECDSA_P384_SHA384_ASN1),
+        oid => bail!("Unsupported certificate signature algorithm {oid}"),
+    }
+}
+
+/// The scalars `r` and `s` of an ECDSA signature zero padded to
+/// `scalar_size`, one after the other, as ring takes them.
+fn fixed_signature(r: &[u8], s: &[u8], scalar_size: usize) -> Result<Vec<u8>> {
+    let mut signature = vec![0u8; scalar_size * 2];
+    for (scalar, padded) in [r, s].iter().zip(signature.chunks_mut(scalar_size)) {
+        let scalar = &scalar[scalar.iter().take_while(|byte| **byte == 0).count()..];
+        if scalar.len() > scalar_size {
+            bail!("Malformed ECDSA signature");
+        }
+        padded[scalar_size - scalar.len()..].copy_from_slice(scalar);
+    }
+    Ok(signature)
+}
+
+#[async_trait]
+impl CryptoBackend for Ring {
+    async fn verify_certificate(&self, cert: &[u8], issuer: &[u8]) -> Result<()> {
+        let cert = parse_certificate(cert)?;
+        let issuer = parse_certificate(issuer)?;
+
+        let algorithm = signature_algorithm(&cert.signature_algorithm.algorithm)?;
+        UnparsedPublicKey::new(algorithm, &issuer.public_key().subject_public_key.data)
+            .verify(cert.tbs_certificate.as_ref(), &cert.signature_value.data)
+            .map_err(|_| anyhow!("Invalid certificate signature"))
+    }
+
+    async fn verify_ecdsa_p384_sha384(
+        &self,
+        cert: &[u8],
+        data: &[u8],
+        r: &[u8],
+        s: &[u8],
+    ) -> Result<()> {
+        let cert = parse_certificate(cert)?;
+        let signature = fixed_signature(r, s, P384_SCALAR_SIZE)?;
+
+        UnparsedPublicKey::new(
+            &signature::ECDSA_P384_SHA384_FIXED,
+            &cert.public_key().subject_public_key.data,
+        )
+        .verify(data, &signature)
+        .map_err(|_| anyhow!("Invalid ECDSA signature"))
+    }
+
+    async fn verify_ecdsa_with_key(
+        &self,
+        public_key: &[u8],
+        data: &[u8],
+        r: &[u8],
+        s: &[u8],
+    ) -> Result<()> {
+        let curve = EcdsaCurve::of_public_key(public_key)?;
+        let algorithm = match curve {
+            EcdsaCurve::P256 => &signature::ECDSA_P256_SHA256_FIXED,
+            EcdsaCurve::P384 => &signature::ECDSA_P384_SHA384_FIXED,
+        };
+        let signature = fixed_signature(r, s, curve.scalar_size())?;
+
+        UnparsedPublicKey::new(algorithm, public_key)
+            .verify(data, &signature)
+            .map_err(|_| anyhow!("Invalid ECDSA signature"))
+    }
+
+    async fn verify_rsa_pss_sha384(
+        &self,
+        cert: &[u8],
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        let cert = parse_certificate(cert)?;
+
+        UnparsedPublicKey::new(
+            &signature::RSA_PSS_2048_8192_SHA384,
+            &cert.public_key().subject_public_key.data,
+        )
+        .verify(data, signature)
+        .map_err(|_| anyhow!("Invalid RSASSA-PSS signature"))
+    }
+}
@@ -57,6 +57,12 @@ impl Verifier for CsvVerifier {
 
         parse_tee_evidence(&report_raw)
     }
+
+    fn signed_evidence(&self, attestation: &Attestation) -> Result<Vec<u8>> {
+        let tee_evidence = serde_json::from_str::<CsvEvidence>(&attestation.tee_evidence)
+            .context("Deserialize Quote failed.")?;
+        Ok(bincode::serialize(&tee_evidence.attestation_report)?)
+    }
 }
 
 fn calculate_expected_report_data(nonce: &String, tee_pubkey: &TeePubKey) -> [u8; 64] {
@@ -151,10 +157,10 @@ fn parse_tee_evidence(report: &AttestationReport) -> Result<TeeEvidenceParsedCla
         "policy_csv": format!("{}", body.policy.csv()),
         "policy_csv3": format!("{}", body.policy.csv3()),
         "policy_asid_reuse": format!("{}", body.policy.asid_reuse()),
-        "policy_hsk_version": format!("{}", body.policy.hsk_version()),
-        "policy_cek_version": format!("{}", body.policy.cek_version()),
-        "policy_api_major": format!("{}", body.policy.api_major()),
-        "policy_api_minor": format!("{}", body.policy.api_minor()),
+        "policy_hsk_version": body.policy.hsk_version(),
+        "policy_cek_version": body.policy.cek_version(),
+        "policy_api_major": body.policy.api_major(),
+        "policy_api_minor": body.policy.api_minor(),
 
         // launch info inject with pdh and session data
         "user_pubkey_digest": format!("{}", base64::engine::general_purpose::STANDARD.encode(body.user_pubkey_digest)),
@@ -165,5 +171,30 @@ fn parse_tee_evidence(report: &AttestationReport) -> Result<TeeEvidenceParsedCla
         "measurement": format!("{}", base64::engine::general_purpose::STANDARD.encode(body.measure)),
     });
 
-    Ok(claims_map as TeeEvidenceParsedClaim)
+    Ok(TeeEvidenceParsedClaim::from(claims_map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verifier::conformance::{self, Conformance, DebugClaim};
+
+    #[tokio::test]
+    async fn conformance() {
+        conformance::run(Conformance {
+            tee: Tee::Csv,
+            verifier: CsvVerifier::default(),
+            malformed: vec![
+                json!({ "attestation_report": {}, "cert_chain": {} }).to_string(),
+                json!({ "cert_chain": {} }).to_string(),
+            ],
+            evidence: None,
+            claims: None,
+            debug_claim: Some(DebugClaim {
+                name: "csv.policy_nodbg",
+                is_debug: |value| value.as_str()?.parse::<u8>().ok().map(|nodbg| nodbg == 0),
+            }),
+        })
+        .await;
+    }
 }
@@ -0,0 +1,151 @@
+//! Detail levels of verification results.
+//!
+//! Attestation requests select how much of the verification their token
+//! tells, trading its size for diagnosability, and `detail_level` in the AS
+//! config is the level of the requests that do not:
+//!
+//! - `verdict`: the token only has the vendor neutral TCB status of the
+//!   evidence, see [`super::tcb`], and the results of the policies. The
+//!   claims of the evidence are still appraised by the policies;
+//! - `standard`, the default: the token has the claims of the evidence;
+//! - `forensic`: the SGX and TDX verifiers also claim the PEM certificates
+//!   of the PCK chain of the quote, leaf first, and the TCB components of
+//!   the platform, as the PCK certificate gives them and, for TDX, of the
+//!   TDX module in the quote:
+//!
+//! ```json
+//! "forensic": {
+//!     "pck_chain": ["-----BEGIN CERTIFICATE-----\n...", "...", "..."],
+//!     "tcb_components": {
+//!         "sgx_tcb_components": [4, 4, 2, 2, 3, 1, 0, 5, 0, 0, 0, 0, 0, 0, 0, 0],
+//!         "pce_svn": 13,
+//!         "cpu_svn": "04040202030100050000000000000000",
+//!         "tee_tcb_svn": [5, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+//!     }
+//! }
+//! ```
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+
+use super::tcb::{TCB_DATE_CLAIM, TCB_STATUS_CLAIM};
+
+/// Claim of the forensic detail of a verifier.
+#[cfg_attr(
+    not(any(feature = "tdx-verifier", feature = "sgx-verifier")),
+    allow(dead_code)
+)]
+pub(crate) const FORENSIC_CLAIM: &str = "forensic";
+
+tokio::task_local! {
+    static DETAIL_LEVEL: DetailLevel;
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DetailLevel {
+    /// The TCB status only.
+    Verdict,
+    /// The claims of the evidence.
+    #[default]
+    Standard,
+    /// The claims, with the certificate chain and TCB components of the
+    /// quote.
+    Forensic,
+}
+
+impl DetailLevel {
+    /// The claims of the token of the flattened `claims`.
+    pub fn token_claims(self, claims: &Value) -> Value {
+        match (self, claims) {
+            (Self::Verdict, Value::Object(claims)) => Value::Object(
+                claims
+                    .iter()
+                    .filter(|(name, _)| [TCB_STATUS_CLAIM, TCB_DATE_CLAIM].contains(&name.as_str()))
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect(),
+            ),
+            _ => claims.clone(),
+        }
+    }
+}
+
+/// Run `verification` at `level`.
+pub(crate) async fn collect<F: Future>(level: DetailLevel, verification: F) -> F::Output {
+    DETAIL_LEVEL.scope(level, verification).await
+}
+
+/// Whether the verifiers add their forensic detail to their claims.
+#[cfg_attr(
+    not(any(feature = "tdx-verifier", feature = "sgx-verifier")),
+    allow(dead_code)
+)]
+pub(crate) fn forensic() -> bool {
+    DETAIL_LEVEL
+        .try_with(|level| *level == DetailLevel::Forensic)
+        .unwrap_or(false)
+}
+
+impl fmt::Display for DetailLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Verdict => "verdict",
+            Self::Standard => "standard",
+            Self::Forensic => "forensic",
+        })
+    }
+}
+
+impl FromStr for DetailLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "verdict" => Ok(Self::Verdict),
+            "standard" => Ok(Self::Standard),
+            "forensic" => Ok(Self::Forensic),
+            _ => bail!("unknown detail level {s}, expected verdict, standard or forensic"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_token_claims() {
+        let claims = json!({
+            "tdx.quote.body.mr_td": "00",
+            "tcb_status": "UpToDate",
+            "tcb_date": "2024-03-13T00:00:00+00:00",
+        });
+        assert_eq!(
+            DetailLevel::Verdict.token_claims(&claims),
+            json!({ "tcb_status": "UpToDate", "tcb_date": "2024-03-13T00:00:00+00:00" })
+        );
+        assert_eq!(DetailLevel::Standard.token_claims(&claims), claims);
+        assert_eq!(DetailLevel::Forensic.token_claims(&claims), claims);
+
+        for level in [
+            DetailLevel::Verdict,
+            DetailLevel::Standard,
+            DetailLevel::Forensic,
+        ] {
+            assert_eq!(level.to_string().parse::<DetailLevel>().unwrap(), level);
+        }
+        assert!("full".parse::<DetailLevel>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_collect() {
+        assert!(collect(DetailLevel::Forensic, async { forensic() }).await);
+        assert!(!collect(DetailLevel::Standard, async { forensic() }).await);
+        assert!(!forensic());
+    }
+}
@@ -0,0 +1,151 @@
+//! Per-request verification diagnostics.
+//!
+//! To debug a verification without turning on verbose logging for every
+//! request, a caller passes [`Diagnostics`] in the options of an
+//! evaluation, and verifiers record their intermediate data in it: the
+//! subjects of the certificate chains they verified, the RTMR values
+//! replayed from the eventlog next to those of the quote, the versions of
+//! the collateral they used. The caller reads them once the evaluation is
+//! done, whether it succeeded or not.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+tokio::task_local! {
+    static DIAGNOSTICS: Diagnostics;
+}
+
+/// Diagnostics of an evaluation, by name, e.g. `tdx.rtmr`.
+#[derive(Clone, Debug, Default)]
+pub struct Diagnostics(Arc<Mutex<Map<String, Value>>>);
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The diagnostics recorded so far, as a JSON object.
+    pub fn to_json(&self) -> Value {
+        Value::Object(self.0.lock().map(|map| map.clone()).unwrap_or_default())
+    }
+}
+
+/// Run `verification`, recording its diagnostics in `diagnostics`, if any.
+pub(crate) async fn collect<F: Future>(
+    diagnostics: Option<Diagnostics>,
+    verification: F,
+) -> F::Output {
+    match diagnostics {
+        Some(diagnostics) => DIAGNOSTICS.scope(diagnostics, verification).await,
+        None => verification.await,
+    }
+}
+
+/// Whether diagnostics are recorded, to skip computing them otherwise.
+pub(crate) fn enabled() -> bool {
+    DIAGNOSTICS.try_with(|_| ()).is_ok()
+}
+
+/// Record the diagnostic `name` of the evidence being verified, if
+/// diagnostics are recorded.
+pub(crate) fn record(name: &str, value: impl Serialize) {
+    let _ = DIAGNOSTICS.try_with(|diagnostics| {
+        if let (Ok(mut map), Ok(value)) = (diagnostics.0.lock(), serde_json::to_value(value)) {
+            map.insert(name.to_string(), value);
+        }
+    });
+}
+
+/// Append `value` to the list diagnostic `name` of the evidence being
+/// verified, if diagnostics are recorded.
+#[cfg_attr(not(feature = "tdx-verifier"), allow(dead_code))]
+pub(crate) fn push(name: &str, value: impl Serialize) {
+    let _ = DIAGNOSTICS.try_with(|diagnostics| {
+        if let (Ok(mut map), Ok(value)) = (diagnostics.0.lock(), serde_json::to_value(value)) {
+            match map.get_mut(name) {
+                Some(Value::Array(values)) => values.push(value),
+                _ => {
+                    map.insert(name.to_string(), Value::Array(vec![value]));
+                }
+            }
+        }
+    });
+}
+
+/// Record the DCAP collateral that verified a quote, with the quote
+/// verification result, as `{name}.collateral`.
+#[cfg(any(feature = "tdx-verifier", feature = "sgx-verifier"))]
+pub(crate) fn record_dcap_collateral(
+    name: &str,
+    supplemental: Option<&sgx_dcap_quoteverify_rs::sgx_ql_qv_supplemental_t>,
+    collateral_expiration_status: u32,
+    result: sgx_dcap_quoteverify_rs::sgx_ql_qv_result_t,
+) {
+    if !enabled() {
+        return;
+    }
+    let mut collateral = serde_json::json!({
+        "result": format!("{result:?}"),
+        "expired": collateral_expiration_status != 0,
+    });
+    if let (Some(supplemental), Some(collateral)) = (supplemental, collateral.as_object_mut()) {
+        for (key, value) in [
+            (
+                "tcb_eval_ref_num",
+                Value::from(supplemental.tcb_eval_ref_num),
+            ),
+            ("pck_crl_num", Value::from(supplemental.pck_crl_num)),
+            ("root_ca_crl_num", Value::from(supplemental.root_ca_crl_num)),
+            (
+                "tcb_level_date_tag",
+                Value::from(supplemental.tcb_level_date_tag),
+            ),
+            (
+                "earliest_issue_date",
+                Value::from(supplemental.earliest_issue_date),
+            ),
+            (
+                "latest_issue_date",
+                Value::from(supplemental.latest_issue_date),
+            ),
+            (
+                "earliest_expiration_date",
+                Value::from(supplemental.earliest_expiration_date),
+            ),
+        ] {
+            collateral.insert(key.to_string(), value);
+        }
+    }
+    record(&format!("{name}.collateral"), collateral);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_collect() {
+        let diagnostics = Diagnostics::new();
+        let output = collect(Some(diagnostics.clone()), async {
+            assert!(enabled());
+            record("snp.cert_chain", ["CN=VCEK", "CN=SEV-Milan"]);
+            42
+        })
+        .await;
+        assert_eq!(output, 42);
+        assert_eq!(
+            diagnostics.to_json(),
+            serde_json::json!({"snp.cert_chain": ["CN=VCEK", "CN=SEV-Milan"]})
+        );
+
+        // Out of a collection, diagnostics are dropped.
+        collect(None, async {
+            assert!(!enabled());
+            record("dropped", true);
+        })
+        .await;
+        assert_eq!(diagnostics.to_json().as_object().unwrap().len(), 1);
+    }
+}
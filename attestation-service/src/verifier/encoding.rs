@@ -0,0 +1,233 @@
+//! Encoding of the measurement register claims.
+//!
+//! Verifiers emit measurement registers as the vendor tools print them:
+//! hex for the TDX `mr_td` and the SGX `mr-enclave`, base64 for the SNP
+//! `measurement`. Reference values stored in another encoding would have to
+//! be converted in the policy. `measurement_encoding` in the AS config
+//! re-encodes the claims of [`MEASUREMENT_CLAIMS`], and the canonical
+//! `measurement` claim, in one encoding for all TEEs: `hex`, `hex_upper` or
+//! `base64`. `native` leaves them as the verifiers emit them, the default.
+//! Attestation requests can ask for another encoding.
+
+use anyhow::{bail, Result};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MeasurementEncoding {
+    /// As the verifier emits them.
+    #[default]
+    Native,
+    /// Lowercase hex.
+    Hex,
+    /// Uppercase hex.
+    HexUpper,
+    /// Standard base64, padded.
+    Base64,
+}
+
+/// A measurement register claim of a verifier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeasurementClaim {
+    /// TEE name, as in the `tee` of attestation requests, e.g. `tdx`.
+    pub tee: &'static str,
+    /// Claim name, relative to the claims of the TEE. A `*` at the end of
+    /// a segment of the name stands for the rest of the segment, e.g. the
+    /// index of an array.
+    pub claim: &'static str,
+    /// How the verifier encodes the claim, [`MeasurementEncoding::Hex`] or
+    /// [`MeasurementEncoding::Base64`].
+    pub encoding: MeasurementEncoding,
+}
+
+const fn hex_claim(tee: &'static str, claim: &'static str) -> MeasurementClaim {
+    MeasurementClaim {
+        tee,
+        claim,
+        encoding: MeasurementEncoding::Hex,
+    }
+}
+
+const fn base64_claim(tee: &'static str, claim: &'static str) -> MeasurementClaim {
+    MeasurementClaim {
+        tee,
+        claim,
+        encoding: MeasurementEncoding::Base64,
+    }
+}
+
+pub const MEASUREMENT_CLAIMS: &[MeasurementClaim] = &[
+    hex_claim("tdx", "quote.body.mr_seam"),
+    hex_claim("tdx", "quote.body.mrsigner_seam"),
+    hex_claim("tdx", "quote.body.mr_td"),
+    hex_claim("tdx", "quote.body.mr_config_id"),
+    hex_claim("tdx", "quote.body.mr_owner"),
+    hex_claim("tdx", "quote.body.mr_owner_config"),
+    hex_claim("tdx", "enclave.mr-enclave"),
+    hex_claim("tdx", "enclave.mr-signer"),
+    hex_claim("sgx", "mr-enclave"),
+    hex_claim("sgx", "mr-signer"),
+    base64_claim("snp", "measurement"),
+    base64_claim("azsnpvtpm", "measurement"),
+    hex_claim("azsnpvtpm", "quote.body.mr_td"),
+    hex_claim("azsnpvtpm", "tpm.pcr_*"),
+    base64_claim("csv", "measurement"),
+    base64_claim("cca", "cca-realm-initial-measurement"),
+    base64_claim("cca", "cca-realm-extensible-measurements.*"),
+    base64_claim("cca", "cca-platform-sw-components.*.measurement-value"),
+];
+
+/// The canonical `measurement` claim, see [`super::canonical`], hex
+/// encoded.
+const CANONICAL_MEASUREMENT: &str = "measurement";
+
+impl MeasurementEncoding {
+    fn decode(self, value: &str) -> Result<Vec<u8>> {
+        Ok(match self {
+            Self::Base64 => base64::engine::general_purpose::STANDARD.decode(value)?,
+            _ => hex::decode(value)?,
+        })
+    }
+
+    fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            Self::Native | Self::Hex => hex::encode(bytes),
+            Self::HexUpper => hex::encode_upper(bytes),
+            Self::Base64 => base64::engine::general_purpose::STANDARD.encode(bytes),
+        }
+    }
+
+    /// Re-encode the measurement register claims of `tee` in the flattened
+    /// `claims`. Claims that cannot be decoded are left as they are, and
+    /// logged.
+    pub fn apply(self, tee: &str, claims: &mut Value) {
+        if self == Self::Native {
+            return;
+        }
+        let Some(claims) = claims.as_object_mut() else {
+            return;
+        };
+        let prefix = format!("{tee}.");
+        for (name, value) in claims.iter_mut() {
+            let native = match name.strip_prefix(&prefix) {
+                Some(claim) => match MEASUREMENT_CLAIMS
+                    .iter()
+                    .find(|measurement| measurement.tee == tee && matches(measurement.claim, claim))
+                {
+                    Some(measurement) => measurement.encoding,
+                    None => continue,
+                },
+                None if name == CANONICAL_MEASUREMENT => Self::Hex,
+                None => continue,
+            };
+            let Some(raw) = value.as_str() else {
+                continue;
+            };
+            match native.decode(raw) {
+                Ok(bytes) => *value = self.encode(&bytes).into(),
+                Err(e) => warn!("Cannot re-encode claim {name} ({self}): {e}"),
+            }
+        }
+    }
+}
+
+/// Whether the claim `name` matches `pattern`, segment by segment.
+fn matches(pattern: &str, name: &str) -> bool {
+    let mut segments = name.split('.');
+    pattern.split('.').all(|expected| match segments.next() {
+        Some(segment) => match expected.strip_suffix('*') {
+            Some(prefix) => segment.starts_with(prefix),
+            None => expected == segment,
+        },
+        None => false,
+    }) && segments.next().is_none()
+}
+
+impl fmt::Display for MeasurementEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Native => "native",
+            Self::Hex => "hex",
+            Self::HexUpper => "hex_upper",
+            Self::Base64 => "base64",
+        })
+    }
+}
+
+impl FromStr for MeasurementEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "native" => Ok(Self::Native),
+            "hex" => Ok(Self::Hex),
+            "hex_upper" => Ok(Self::HexUpper),
+            "base64" => Ok(Self::Base64),
+            _ => {
+                bail!("unknown measurement encoding {s}, expected native, hex, hex_upper or base64")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_apply() {
+        let claims = json!({
+            "snp.measurement": "cF7pOA==",
+            "snp.policy_debug_allowed": "0",
+            "measurement": "705ee938",
+        });
+
+        let mut hex = claims.clone();
+        MeasurementEncoding::HexUpper.apply("snp", &mut hex);
+        assert_eq!(hex["snp.measurement"], "705EE938");
+        assert_eq!(hex["measurement"], "705EE938");
+        assert_eq!(hex["snp.policy_debug_allowed"], "0");
+
+        let mut native = claims.clone();
+        MeasurementEncoding::Native.apply("snp", &mut native);
+        assert_eq!(native, claims);
+
+        let mut claims = json!({
+            "cca.cca-platform-sw-components.1.measurement-value": "cF7pOA==",
+            "cca.cca-platform-sw-components.1.signer-id": "cF7pOA==",
+            "cca.cca-realm-extensible-measurements.0": "not base64",
+        });
+        MeasurementEncoding::Hex.apply("cca", &mut claims);
+        assert_eq!(
+            claims["cca.cca-platform-sw-components.1.measurement-value"],
+            "705ee938"
+        );
+        assert_eq!(
+            claims["cca.cca-platform-sw-components.1.signer-id"],
+            "cF7pOA=="
+        );
+        assert_eq!(
+            claims["cca.cca-realm-extensible-measurements.0"],
+            "not base64"
+        );
+
+        let mut claims = json!({ "tdx.quote.body.mr_td": "705ee938" });
+        MeasurementEncoding::Base64.apply("tdx", &mut claims);
+        assert_eq!(claims["tdx.quote.body.mr_td"], "cF7pOA==");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "hex_upper".parse::<MeasurementEncoding>().unwrap(),
+            MeasurementEncoding::HexUpper
+        );
+        assert!("HEX".parse::<MeasurementEncoding>().is_err());
+        assert_eq!(MeasurementEncoding::Base64.to_string(), "base64");
+    }
+}
@@ -0,0 +1,109 @@
+//! Expiry of the verification collateral and of the trusted certificates.
+//!
+//! Evidence stops verifying when the collateral or the certificates it is
+//! verified against expire, all evidence of a TEE at once. The expiry of
+//! the trusted certificates built in the AS, like the AMD ARK and ASK, is
+//! known from the start, and that of the collateral of the quote provider
+//! whenever a quote is verified with it. The AS keeps the latest of each,
+//! so that they can be monitored, and alerted on ahead of expiry.
+
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, PoisonError};
+
+const SECS_PER_DAY: i64 = 24 * 3600;
+
+lazy_static! {
+    /// Expiry of each collateral and trusted certificate, as a Unix time.
+    static ref EXPIRIES: Mutex<BTreeMap<String, i64>> = Mutex::new(trusted_certificates());
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expiry {
+    /// E.g. `snp.ark` or `tdx.collateral`.
+    pub name: String,
+    /// Unix time.
+    pub not_after: i64,
+    /// Whole days left, negative once expired.
+    pub days_until_expiry: i64,
+}
+
+fn trusted_certificates() -> BTreeMap<String, i64> {
+    #[allow(unused_mut)]
+    let mut expiries = BTreeMap::new();
+    #[cfg(feature = "snp-verifier")]
+    match super::snp::trusted_cert_expiries() {
+        Ok(certs) => expiries.extend(certs),
+        Err(e) => warn!("Cannot read the expiry of the SNP certificates: {e:#}"),
+    }
+    expiries
+}
+
+/// Record that `name` expires at `not_after`, a Unix time. It replaces
+/// what was recorded before, as collateral is refreshed.
+#[cfg_attr(
+    not(any(feature = "tdx-verifier", feature = "sgx-verifier")),
+    allow(dead_code)
+)]
+pub(crate) fn record(name: &str, not_after: i64) {
+    EXPIRIES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(name.to_string(), not_after);
+}
+
+/// The expiry of the collateral and trusted certificates known so far, as
+/// of `now`, a Unix time. The soonest first.
+pub fn expiries(now: i64) -> Vec<Expiry> {
+    let mut expiries: Vec<_> = EXPIRIES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .map(|(name, not_after)| Expiry {
+            name: name.clone(),
+            not_after: *not_after,
+            days_until_expiry: (not_after - now).div_euclid(SECS_PER_DAY),
+        })
+        .collect();
+    expiries.sort_by_key(|expiry| expiry.not_after);
+    expiries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expiries() {
+        record("test.later", 1000 + 30 * SECS_PER_DAY);
+        record("test.sooner", 1000 + 3 * SECS_PER_DAY + 10);
+        record("test.expired", 1000 - 10);
+        let days: Vec<_> = expiries(1000)
+            .into_iter()
+            .filter(|expiry| expiry.name.starts_with("test."))
+            .map(|expiry| (expiry.name, expiry.days_until_expiry))
+            .collect();
+        assert_eq!(
+            days,
+            [
+                ("test.expired".to_string(), -1),
+                ("test.sooner".to_string(), 3),
+                ("test.later".to_string(), 30)
+            ]
+        );
+
+        // Refreshed collateral replaces the previous expiry.
+        record("test.sooner", 1000 + 90 * SECS_PER_DAY);
+        assert!(expiries(1000)
+            .iter()
+            .any(|expiry| expiry.name == "test.sooner" && expiry.days_until_expiry == 90));
+    }
+
+    #[cfg(feature = "snp-verifier")]
+    #[test]
+    fn test_trusted_certificates() {
+        let certs = trusted_certificates();
+        assert!(certs.contains_key("snp.ark"));
+        assert!(certs.contains_key("snp.ask"));
+    }
+}
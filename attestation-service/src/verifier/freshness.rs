@@ -0,0 +1,172 @@
+//! Freshness of evidence.
+//!
+//! The AS tells that evidence was produced for the attestation at hand, and
+//! is not replayed, by one of these methods, strongest first:
+//!
+//! - `nonce`: the report data binds a nonce of the challenge and the TEE
+//!   public key, see [`super::report_data`];
+//! - `timestamp`: the evidence is bound to the time it was produced. The
+//!   attester, which needs no challenge, takes an RFC 3339 time, e.g.
+//!   `2024-05-01T12:00:00Z`, as the nonce of its evidence, that the report
+//!   data of an SNP report or a TD quote, or the qualifying data of a vTPM
+//!   quote, binds as it would a random nonce. The time is so signed by the
+//!   TEE, and with `evidence_age` in the AS config, the AS rejects evidence
+//!   older than a maximum age, and claims its `evidence_age_seconds`, see
+//!   [`super::timing`]. Verifiers that check nonces check timestamps;
+//! - `none`: the evidence is not bound to the attestation, e.g. when the
+//!   `freshness` stage is left out of the pipeline of the verifier. Only
+//!   `replay_protection` in the AS config can flag evidence submitted again.
+//!
+//! Verifiers declare the methods they check with
+//! [`super::Verifier::freshness_methods`]. A challenge negotiates the
+//! strongest of them that the attester supports, and the method of the
+//! verified evidence is recorded in the `freshness` claim, so that policies
+//! can reject the weaker ones.
+
+use super::timing;
+use anyhow::{bail, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Name of the claim of the freshness method.
+pub const FRESHNESS_CLAIM: &str = "freshness";
+
+/// Size of the nonces of challenges.
+const NONCE_SIZE: usize = 32;
+
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, EnumString, Display,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum FreshnessMethod {
+    Nonce,
+    Timestamp,
+    None,
+}
+
+/// The strongest of the `supported` methods that the attester `offers`, or
+/// of all the `supported` ones if the attester offers none.
+pub fn negotiate(
+    supported: &[FreshnessMethod],
+    offered: &[FreshnessMethod],
+) -> Result<FreshnessMethod> {
+    let negotiated = supported
+        .iter()
+        .filter(|method| offered.is_empty() || offered.contains(method))
+        .min();
+    match negotiated {
+        Some(method) => Ok(*method),
+        None => bail!(
+            "No common freshness method, the verifier supports {}",
+            supported
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// The method of evidence bound to `nonce` by a verifier that checks
+/// `methods`: `timestamp` if the nonce is a time and the verifier checks
+/// timestamps, else the strongest of `methods`.
+pub(crate) fn method(methods: &[FreshnessMethod], nonce: &str) -> Option<FreshnessMethod> {
+    match timing::evidence_time(nonce) {
+        Some(_) if methods.contains(&FreshnessMethod::Timestamp) => {
+            Some(FreshnessMethod::Timestamp)
+        }
+        _ => methods.first().copied(),
+    }
+}
+
+/// The time evidence bound to `nonce` was produced at, if it is bound to
+/// a timestamp, see [`method`].
+pub(crate) fn evidence_time(methods: &[FreshnessMethod], nonce: &str) -> Option<DateTime<Utc>> {
+    match method(methods, nonce) {
+        Some(FreshnessMethod::Timestamp) => timing::evidence_time(nonce),
+        _ => None,
+    }
+}
+
+/// Add the `freshness` claim of evidence bound to `nonce`, verified by a
+/// verifier that checks `methods`.
+pub(crate) fn add_claim(claims: &mut Value, methods: &[FreshnessMethod], nonce: &str) {
+    if let (Some(claims), Some(method)) = (claims.as_object_mut(), method(methods, nonce)) {
+        claims.insert(FRESHNESS_CLAIM.to_string(), method.to_string().into());
+    }
+}
+
+/// What the attester is asked to produce its evidence with.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Challenge {
+    pub freshness: FreshnessMethod,
+    /// Base64 encoded random nonce to bind in the report data of the
+    /// evidence with the `nonce` method.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// Unix time the nonce expires at, if the AS keeps the nonces it
+    /// issues, see `crate::nonces`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+}
+
+impl Challenge {
+    pub fn new(freshness: FreshnessMethod) -> Self {
+        let nonce = (freshness == FreshnessMethod::Nonce).then(|| {
+            let mut nonce = [0u8; NONCE_SIZE];
+            rand::thread_rng().fill_bytes(&mut nonce);
+            STANDARD.encode(nonce)
+        });
+        Self {
+            freshness,
+            nonce,
+            expires_at: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use FreshnessMethod::{Nonce, Timestamp};
+
+    const NONE: FreshnessMethod = FreshnessMethod::None;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(&[NONE, Nonce], &[]).unwrap(), Nonce);
+        assert_eq!(
+            negotiate(&[Nonce, Timestamp], &[NONE, Timestamp]).unwrap(),
+            Timestamp
+        );
+        assert_eq!(negotiate(&[NONE], &[NONE, Nonce]).unwrap(), NONE);
+        assert!(negotiate(&[Nonce], &[Timestamp]).is_err());
+        assert!(negotiate(&[], &[]).is_err());
+    }
+
+    #[test]
+    fn test_method() {
+        let nonce = "mHjlA2YGfXqW0vLsbVAPy2kIs3bD8JRt2BWX1MyTpqU=";
+        let time = "2024-05-01T12:00:00Z";
+        assert_eq!(method(&[Nonce, Timestamp], nonce), Some(Nonce));
+        assert_eq!(method(&[Nonce, Timestamp], time), Some(Timestamp));
+        assert_eq!(method(&[NONE], time), Some(NONE));
+        assert_eq!(method(&[], nonce), None);
+        assert!(evidence_time(&[Nonce, Timestamp], time).is_some());
+        assert!(evidence_time(&[Nonce], time).is_none());
+    }
+
+    #[test]
+    fn test_challenge() {
+        let challenge = Challenge::new(Nonce);
+        let nonce = STANDARD.decode(challenge.nonce.unwrap()).unwrap();
+        assert_eq!(nonce.len(), NONCE_SIZE);
+        assert!(Challenge::new(NONE).nonce.is_none());
+        assert_eq!("timestamp".parse::<FreshnessMethod>().unwrap(), Timestamp);
+    }
+}
@@ -0,0 +1,128 @@
+//! HCL reports of the paravisor of Azure confidential VMs.
+//!
+//! The paravisor (HCL) of a confidential VM wraps the hardware report of
+//! the VM, an SNP report or a TD report, in an HCL report with runtime
+//! data: a JSON document of the keys of the vTPM, the VM configuration and
+//! the user data of the attester. The report data of the hardware report
+//! is a hash of the runtime data, which binds it to the report, and the
+//! user data binds the nonce and the TEE public key.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::fmt;
+
+const HCL_SIGNATURE: &[u8] = b"HCLA";
+
+/// Size of the attestation header before the hardware report.
+pub(crate) const HEADER_SIZE: usize = 0x20;
+
+/// Size of the hardware report area, that of an SNP report. TD reports are
+/// smaller, and zero padded.
+pub(crate) const HW_REPORT_SIZE: usize = 0x4a0;
+
+/// Size of the IGVM request data header before the runtime data.
+pub(crate) const REQUEST_DATA_SIZE: usize = 0x14;
+
+/// Hardware report of an HCL report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ReportType {
+    Snp,
+    Tdx,
+}
+
+impl ReportType {
+    fn id(&self) -> u32 {
+        match self {
+            Self::Snp => 2,
+            Self::Tdx => 4,
+        }
+    }
+}
+
+impl fmt::Display for ReportType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Snp => "an SNP report",
+            Self::Tdx => "a TD report",
+        })
+    }
+}
+
+pub(crate) struct HclReport<'a> {
+    /// The hardware report, zero padded.
+    pub hw_report: &'a [u8],
+    /// The runtime data, bound to the hardware report.
+    pub runtime_data: Vec<u8>,
+    hash_type: u32,
+}
+
+/// Split an HCL report into its hardware report of type `report_type`
+/// and its runtime data.
+pub(crate) fn parse(hcl_report: &[u8], report_type: ReportType) -> Result<HclReport<'_>> {
+    let u32_at = |offset: usize| -> Result<u32> {
+        let bytes = hcl_report
+            .get(offset..offset + 4)
+            .ok_or_else(|| anyhow!("HCL report is too short"))?;
+        Ok(u32::from_le_bytes(bytes.try_into()?))
+    };
+
+    if !hcl_report.starts_with(HCL_SIGNATURE) {
+        bail!("Not an HCL report");
+    }
+    let request_data = HEADER_SIZE + HW_REPORT_SIZE;
+    let found = u32_at(request_data + 8)?;
+    if found != report_type.id() {
+        bail!("HCL report of type {found} is not {report_type}");
+    }
+    let hash_type = u32_at(request_data + 12)?;
+    let runtime_data_size = u32_at(request_data + 16)? as usize;
+    let runtime_data = hcl_report
+        .get(request_data + REQUEST_DATA_SIZE..)
+        .and_then(|data| data.get(..runtime_data_size))
+        .ok_or_else(|| anyhow!("HCL report is too short"))?
+        .to_vec();
+
+    Ok(HclReport {
+        hw_report: &hcl_report[HEADER_SIZE..request_data],
+        runtime_data,
+        hash_type,
+    })
+}
+
+impl HclReport<'_> {
+    /// Check that `report_data`, of the hardware report, is the hash of the
+    /// runtime data.
+    pub fn check_report_data(&self, report_data: &[u8]) -> Result<()> {
+        let digest = match self.hash_type {
+            1 => Sha256::digest(&self.runtime_data).to_vec(),
+            2 => Sha384::digest(&self.runtime_data).to_vec(),
+            3 => Sha512::digest(&self.runtime_data).to_vec(),
+            hash_type => bail!("Unsupported HCL report data hash type {hash_type}"),
+        };
+        if !report_data.starts_with(&digest) {
+            bail!("The report data is not the hash of the HCL runtime data");
+        }
+        Ok(())
+    }
+}
+
+/// The runtime data of an HCL report, as JSON.
+pub(crate) fn runtime_data(runtime_data: &[u8]) -> Result<Value> {
+    serde_json::from_slice(runtime_data).context("Malformed HCL runtime data")
+}
+
+/// Check that the user data of the runtime data binds the nonce, as
+/// `binds_nonce` tells.
+pub(crate) fn check_user_data(
+    runtime_data: &Value,
+    binds_nonce: impl FnOnce(&[u8]) -> bool,
+) -> Result<()> {
+    let user_data = runtime_data["user-data"]
+        .as_str()
+        .ok_or_else(|| anyhow!("The HCL runtime data has no user data"))?;
+    if !hex::decode(user_data).is_ok_and(|user_data| binds_nonce(&user_data)) {
+        bail!("User Data Mismatch");
+    }
+    Ok(())
+}
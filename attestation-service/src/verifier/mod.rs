@@ -1,9 +1,67 @@
 use anyhow::*;
 use as_types::TeeEvidenceParsedClaim;
 use async_trait::async_trait;
+use crypto::CryptoBackendType;
+use freshness::FreshnessMethod;
 use kbs_types::{Attestation, Tee};
+use pipeline::VerifierPipelines;
+use report_data::{ReportDataMode, ReportDataModes};
+use schema::ClaimsSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use transform::ClaimTransform;
 
+pub mod anchors;
+#[cfg(any(feature = "tdx-verifier", feature = "sgx-verifier"))]
+pub mod anomalies;
+pub mod canonical;
+pub mod charset;
+#[cfg(any(feature = "tdx-verifier", feature = "sgx-verifier"))]
+pub mod collateral;
+#[cfg(feature = "tdx-verifier")]
+pub mod conflicts;
+pub mod convert;
+pub mod crypto;
+pub mod detail;
+pub mod diagnostics;
+pub mod encoding;
+pub mod expiry;
+pub mod freshness;
+#[cfg(any(
+    feature = "snp-verifier",
+    feature = "tdx-verifier",
+    feature = "az-snp-vtpm-verifier"
+))]
+pub(crate) mod hcl;
+#[cfg(feature = "service")]
+pub mod nvidia_gpu;
+pub mod pipeline;
+pub mod platform;
+pub mod profile;
+pub mod registry;
+pub mod report_data;
 pub mod sample;
+pub mod schema;
+#[cfg(feature = "service")]
+pub mod snp_launch;
+#[cfg(feature = "service")]
+pub mod spdm;
+pub mod tcb;
+#[cfg(feature = "service")]
+pub mod tdx_launch;
+pub mod timing;
+pub mod trace;
+pub mod transform;
+pub mod typed;
+#[cfg(any(feature = "tdx-verifier", feature = "az-snp-vtpm-verifier"))]
+pub(crate) mod vtpm;
+pub mod warnings;
+
+#[cfg(test)]
+pub(crate) mod conformance;
 
 #[cfg(feature = "az-snp-vtpm-verifier")]
 pub mod az_snp_vtpm;
@@ -23,13 +81,379 @@ pub mod csv;
 #[cfg(feature = "cca-verifier")]
 pub mod cca;
 
-pub(crate) fn to_verifier(tee: &Tee) -> Result<Box<dyn Verifier + Send + Sync>> {
+#[cfg(feature = "se-verifier")]
+pub mod se;
+
+/// Accepted versions of an evidence format, bounds included.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct VersionRange {
+    pub min: Option<u32>,
+    pub max: Option<u32>,
+    /// Versions below are accepted with a warning.
+    #[serde(default)]
+    pub deprecated_below: Option<u32>,
+}
+
+impl VersionRange {
+    /// Take the bounds that are not set from `default`.
+    pub fn or(self, default: VersionRange) -> Self {
+        Self {
+            min: self.min.or(default.min),
+            max: self.max.or(default.max),
+            deprecated_below: self.deprecated_below.or(default.deprecated_below),
+        }
+    }
+
+    pub fn check(&self, format: &'static str, version: u32) -> Result<(), UnsupportedVersion> {
+        if self.min.is_some_and(|min| version < min) || self.max.is_some_and(|max| version > max) {
+            return Err(UnsupportedVersion {
+                format,
+                version,
+                accepted: *self,
+            });
+        }
+        if self
+            .deprecated_below
+            .is_some_and(|deprecated| version < deprecated)
+        {
+            warnings::raise(format!("Deprecated {format} version {version}"));
+        }
+        std::result::Result::Ok(())
+    }
+}
+
+impl fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) if min == max => write!(f, "{min}"),
+            (Some(min), Some(max)) => write!(f, "{min} to {max}"),
+            (Some(min), None) => write!(f, "{min} or later"),
+            (None, Some(max)) => write!(f, "up to {max}"),
+            (None, None) => write!(f, "any"),
+        }
+    }
+}
+
+/// Evidence format versions accepted by the verifiers, to enforce the
+/// deprecation of old formats.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct EvidenceVersions {
+    /// TD quote version.
+    pub tdx: VersionRange,
+    /// SGX quote version, of SGX evidence and of enclaves nested in a TD.
+    pub sgx: VersionRange,
+    /// SEV-SNP attestation report version. Only version 2 is accepted by
+    /// default.
+    pub snp: VersionRange,
+    /// SEV-SNP attestation report version of Azure vTPM evidence.
+    #[serde(rename = "azsnpvtpm")]
+    pub az_snp_vtpm: VersionRange,
+}
+
+/// Local verification of the NVIDIA confidential GPU evidence attached to
+/// TEE evidence, see [`nvidia_gpu`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct NvidiaGpuConfig {
+    /// PEM certificate of the NVIDIA device identity root CA, that the
+    /// certificate chains of the GPUs end with.
+    pub root_certificate: PathBuf,
+}
+
+/// Local verification of CCA tokens, see [`cca`].
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct CcaConfig {
+    /// PEM public keys of the CCA platform attestation keys (CPAK) of the
+    /// trusted platforms. Tokens are appraised by the Veraison verifier of
+    /// `VERAISON_ADDR` if there is none.
+    pub platform_keys: Vec<PathBuf>,
+}
+
+/// Header of a trusted IBM Secure Execution guest image, its digests and
+/// tag as hex.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct SeImage {
+    /// Page list digest.
+    pub pld: String,
+    /// Address list digest.
+    pub ald: String,
+    /// Tweak list digest.
+    pub tld: String,
+    pub tag: String,
+}
+
+/// Local verification of IBM Secure Execution evidence, see [`se`].
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct SeConfig {
+    /// PEM chains of the IBM Z host key signing keys, the signing key
+    /// first and the root CA last.
+    pub signing_keys: Vec<PathBuf>,
+    /// PEM host key documents of the trusted hosts. Any host whose HKD is
+    /// signed by a signing key is trusted if none.
+    pub host_key_documents: Vec<PathBuf>,
+    /// PEM RSA private key that the measurement keys of the attestation
+    /// requests are encrypted to.
+    pub measurement_key: Option<PathBuf>,
+    /// Headers of the trusted guest images.
+    pub images: Vec<SeImage>,
+}
+
+/// TEE of a quote, and of its collateral.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuoteTee {
+    Sgx,
+    Tdx,
+}
+
+impl fmt::Display for QuoteTee {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sgx => "sgx",
+            Self::Tdx => "tdx",
+        })
+    }
+}
+
+/// CA of a PCK certificate, which issues the PCK CRL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PckCa {
+    Processor,
+    Platform,
+}
+
+impl fmt::Display for PckCa {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Processor => "processor",
+            Self::Platform => "platform",
+        })
+    }
+}
+
+/// What collateral verifies a quote.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CollateralKey {
+    pub tee: QuoteTee,
+    /// Family-Model-Stepping-Platform-CustomSKU of the platform, hex
+    /// encoded.
+    pub fmspc: String,
+    pub ca: PckCa,
+}
+
+impl CollateralKey {
+    /// The key with its FMSPC checked, and lower case.
+    #[cfg_attr(
+        not(any(feature = "tdx-verifier", feature = "sgx-verifier")),
+        allow(dead_code)
+    )]
+    fn normalized(mut self) -> Result<Self> {
+        if self.fmspc.len() != 12 || hex::decode(&self.fmspc).is_err() {
+            bail!("Invalid FMSPC {}, expected 6 hex encoded bytes", self.fmspc);
+        }
+        self.fmspc.make_ascii_lowercase();
+        Ok(self)
+    }
+}
+
+impl fmt::Display for CollateralKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}-{}", self.tee, self.fmspc, self.ca)
+    }
+}
+/// DCAP collateral of the verification of TDX and SGX quotes, see
+/// [`collateral`]. By default, the quote provider library fetches it as its
+/// QCNL config says.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct CollateralConfig {
+    /// PCCS, or Intel PCS, to fetch the collateral from, e.g.
+    /// `https://pccs.example:8081`.
+    pub pccs_url: Option<String>,
+    /// URL of the CRL of the Intel SGX root CA, which Intel PCS does not
+    /// serve. `<pccs_url>/sgx/certification/v4/rootcacrl` by default.
+    pub root_ca_crl_url: Option<String>,
+    /// Directory to cache the fetched collateral in, across restarts.
+    /// Collateral is only cached in memory if none.
+    pub cache_dir: Option<PathBuf>,
+    /// Fetch the collateral again this long before its next update, in
+    /// seconds.
+    pub refresh_before_secs: u64,
+    /// Timeout of the requests to the PCCS, in seconds.
+    pub timeout_secs: u64,
+    /// Collateral bundle loaded at startup, for offline verification: no
+    /// collateral is fetched, quotes of platforms the bundle has no
+    /// collateral of are rejected. Exclusive with `pccs_url`.
+    pub offline_bundle: Option<PathBuf>,
+    /// Platforms whose collateral is prefetched and kept warm, unless
+    /// others were registered since, see [`collateral::Provider::register`].
+    pub fleet: Vec<CollateralKey>,
+    /// Most platforms whose collateral is cached, in memory and in
+    /// `cache_dir`. Unbounded if none.
+    pub max_cached: Option<usize>,
+    /// Period of the prefetch of the collateral of the fleet, in seconds.
+    pub prefetch_interval_secs: u64,
+}
+
+impl Default for CollateralConfig {
+    fn default() -> Self {
+        Self {
+            pccs_url: None,
+            root_ca_crl_url: None,
+            cache_dir: None,
+            refresh_before_secs: 24 * 3600,
+            timeout_secs: 30,
+            offline_bundle: None,
+            fleet: Vec::new(),
+            max_cached: None,
+            prefetch_interval_secs: 3600,
+        }
+    }
+}
+
+/// Guest policy that the SEV-SNP verifier requires of attestation reports,
+/// on top of their signature. Nothing is required by default.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct SnpGuestPolicy {
+    /// Reject guests whose policy allows debugging.
+    pub deny_debug: bool,
+    /// Reject guests whose policy allows association with a migration
+    /// agent.
+    pub deny_migration_agent: bool,
+    /// Reject guests whose policy allows SMT.
+    pub deny_smt: bool,
+    /// Reject guests whose policy does not require a single socket.
+    pub require_single_socket: bool,
+    /// Reject guests whose policy requires a firmware ABI older than this,
+    /// as `[major, minor]`.
+    pub min_abi: Option<(u64, u64)>,
+}
+
+/// The evidence format version is not accepted.
+#[derive(Debug)]
+pub struct UnsupportedVersion {
+    /// Evidence format, e.g. `TD quote`.
+    pub format: &'static str,
+    pub version: u32,
+    pub accepted: VersionRange,
+}
+
+impl fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Unsupported {} version {}, accepted versions: {}",
+            self.format, self.version, self.accepted
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedVersion {}
+
+/// The config of the verifiers, the part of the AS config that the
+/// verifiers take. Fields missing from the config take their default value.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct VerifierConfig {
+    /// Accepted evidence format versions of each verifier.
+    pub evidence_versions: EvidenceVersions,
+    /// How each verifier compares the report data of evidence with the
+    /// nonce and TEE public key, see [`report_data`].
+    pub report_data: ReportDataModes,
+    /// Stages of the verifiers, see [`pipeline`].
+    pub verifier_pipelines: VerifierPipelines,
+    /// Reject evidence that comes without the eventlog of the boot
+    /// measurements.
+    pub require_eventlog: bool,
+    /// Reject DCAP quotes with structural anomalies, instead of a warning,
+    /// see [`anomalies`].
+    pub strict_quote_parsing: bool,
+    /// How strings of eventlogs are decoded, see [`charset`].
+    pub eventlog_strings: charset::EventlogStrings,
+    /// Claim the kernel command line of TDX eventlogs in canonical form.
+    pub canonical_kernel_cmdline: bool,
+    /// Claim the kernel flags of TDX eventlogs as booleans, with the listed
+    /// flags `false` if absent.
+    pub kernel_flags: Option<Vec<String>>,
+    /// Accept TDX evidence with a TD report in place of the quote.
+    pub tdx_td_reports: bool,
+    /// Trust anchors of the certificate chains of evidence of each vendor,
+    /// see [`anchors`].
+    pub trust_anchors: anchors::TrustAnchorsConfig,
+    /// Crypto backend that verifies the certificate chains and signatures
+    /// of evidence.
+    pub crypto_backend: CryptoBackendType,
+    /// Transforms of hex claims into decoded integer claims, by flattened
+    /// claim name, see [`transform`].
+    pub claim_transforms: HashMap<String, ClaimTransform>,
+    /// Version of the claims schema, see [`schema`].
+    pub claims_schema: ClaimsSchema,
+    /// Guest policy required of SEV-SNP attestation reports.
+    pub snp_guest_policy: SnpGuestPolicy,
+    /// Local verification of CCA tokens.
+    pub cca: CcaConfig,
+    /// Verification of IBM Secure Execution evidence.
+    pub se: SeConfig,
+    /// DCAP collateral of TDX and SGX quotes.
+    pub collateral: CollateralConfig,
+    /// Config sections of the registered verifiers, by TEE name, see
+    /// [`registry`].
+    pub verifiers: HashMap<String, serde_json::Value>,
+}
+
+impl VerifierConfig {
+    /// The config section of the verifier of `tee`, its default if there
+    /// is none.
+    pub fn section<T: DeserializeOwned + Default>(&self, tee: &Tee) -> Result<T> {
+        let name = serde_variant::to_variant_name(tee)?;
+        match self.verifiers.get(name) {
+            Some(section) => serde_json::from_value(section.clone())
+                .with_context(|| format!("Invalid config of the {name} verifier")),
+            None => Ok(T::default()),
+        }
+    }
+}
+
+/// Verify the `attestation` evidence of `tee`, bound to `nonce`, and return
+/// its flattened claims, in the claims schema of [`VerifierConfig`]. Reference values and policies are
+/// left to the caller: this is all there is without the `service` feature.
+pub async fn verify(
+    tee: Tee,
+    nonce: &str,
+    attestation: &Attestation,
+    config: &VerifierConfig,
+) -> Result<TeeEvidenceParsedClaim> {
+    registry::VerifierRegistry::default()
+        .verify(tee, nonce, attestation, config)
+        .await
+}
+
+/// The verifier of `tee`, comparing the report data of the evidence as
+/// `report_data` says, or as configured for the verifier if `None`.
+pub(crate) fn to_verifier(
+    tee: &Tee,
+    config: &VerifierConfig,
+    report_data: Option<ReportDataMode>,
+) -> Result<Box<dyn Verifier + Send + Sync>> {
+    let versions = &config.evidence_versions;
+    let report_data = report_data.unwrap_or_else(|| config.report_data.of(tee));
     match tee {
         Tee::Sev => todo!(),
         Tee::AzSnpVtpm => {
             cfg_if::cfg_if! {
                 if #[cfg(feature = "az-snp-vtpm-verifier")] {
-                    Ok(Box::<az_snp_vtpm::AzSnpVtpm>::default() as Box<dyn Verifier + Send + Sync>)
+                    Ok(Box::new(az_snp_vtpm::AzSnpVtpm {
+                        versions: versions.az_snp_vtpm,
+                        td_versions: versions.tdx,
+                        #[cfg(feature = "tdx-verifier")]
+                        collateral: collateral::provider(&config.collateral)?,
+                        #[cfg(feature = "tdx-verifier")]
+                        strict_quote_parsing: config.strict_quote_parsing,
+                    }) as Box<dyn Verifier + Send + Sync>)
                 } else {
                     todo!()
                 }
@@ -38,7 +462,20 @@ pub(crate) fn to_verifier(tee: &Tee) -> Result<Box<dyn Verifier + Send + Sync>>
         Tee::Tdx => {
             cfg_if::cfg_if! {
                 if #[cfg(feature = "tdx-verifier")] {
-                    Ok(Box::<tdx::Tdx>::default() as Box<dyn Verifier + Send + Sync>)
+                    Ok(Box::new(tdx::Tdx {
+                        versions: versions.tdx,
+                        enclave_versions: versions.sgx,
+                        pipeline: config.verifier_pipelines.tdx()?,
+                        require_eventlog: config.require_eventlog,
+                        report_data,
+                        collateral: collateral::provider(&config.collateral)?,
+                        strict_quote_parsing: config.strict_quote_parsing,
+                        eventlog_strings: config.eventlog_strings,
+                        canonical_kernel_cmdline: config.canonical_kernel_cmdline,
+                        kernel_flags: config.kernel_flags.clone(),
+                        trust_anchors: anchors::anchors(&config.trust_anchors.intel)?,
+                        td_reports: config.tdx_td_reports,
+                    }) as Box<dyn Verifier + Send + Sync>)
                 } else {
                     todo!()
                 }
@@ -47,7 +484,15 @@ pub(crate) fn to_verifier(tee: &Tee) -> Result<Box<dyn Verifier + Send + Sync>>
         Tee::Snp => {
             cfg_if::cfg_if! {
                 if #[cfg(feature = "snp-verifier")] {
-                    Ok(Box::<snp::Snp>::default() as Box<dyn Verifier + Send + Sync>)
+                    Ok(Box::new(snp::Snp {
+                        versions: versions.snp,
+                        crypto: config.crypto_backend.to_backend()?,
+                        pipeline: config.verifier_pipelines.snp()?,
+                        report_data,
+                        guest_policy: config.snp_guest_policy.clone(),
+                        trust_anchors: anchors::anchors(&config.trust_anchors.snp)?,
+                        uvm_anchors: anchors::anchors(&config.trust_anchors.microsoft)?,
+                    }) as Box<dyn Verifier + Send + Sync>)
                 } else {
                     bail!("SNP Verifier not enabled.")
                 }
@@ -57,7 +502,13 @@ pub(crate) fn to_verifier(tee: &Tee) -> Result<Box<dyn Verifier + Send + Sync>>
         Tee::Sgx => {
             cfg_if::cfg_if! {
                 if #[cfg(feature = "sgx-verifier")] {
-                    Ok(Box::<sgx::SgxVerifier>::default() as Box<dyn Verifier + Send + Sync>)
+                    Ok(Box::new(sgx::SgxVerifier {
+                        versions: versions.sgx,
+                        report_data,
+                        collateral: collateral::provider(&config.collateral)?,
+                        strict_quote_parsing: config.strict_quote_parsing,
+                        trust_anchors: anchors::anchors(&config.trust_anchors.intel)?,
+                    }) as Box<dyn Verifier + Send + Sync>)
                 } else {
                     anyhow::bail!("feature `sgx-verifier` is not enabled!");
                 }
@@ -77,7 +528,10 @@ pub(crate) fn to_verifier(tee: &Tee) -> Result<Box<dyn Verifier + Send + Sync>>
         Tee::Cca => {
             cfg_if::cfg_if! {
                 if #[cfg(feature = "cca-verifier")] {
-                    Ok(Box::<cca::CCA>::default() as Box<dyn Verifier + Send + Sync>)
+                    Ok(Box::new(cca::CCA {
+                        platform_keys: cca::load_platform_keys(&config.cca.platform_keys)?,
+                        crypto: config.crypto_backend.to_backend()?,
+                    }) as Box<dyn Verifier + Send + Sync>)
                 } else {
                     anyhow::bail!("feature `cca-verifier` is not enabled!");
                 }
@@ -86,6 +540,17 @@ pub(crate) fn to_verifier(tee: &Tee) -> Result<Box<dyn Verifier + Send + Sync>>
     }
 }
 
+/// The concatenation of the signed `parts` of evidence, each prefixed with
+/// its length, see [`Verifier::signed_evidence`].
+pub(crate) fn signed_parts(parts: &[&[u8]]) -> Vec<u8> {
+    let mut signed = Vec::new();
+    for part in parts {
+        signed.extend((part.len() as u64).to_be_bytes());
+        signed.extend(*part);
+    }
+    signed
+}
+
 #[async_trait]
 pub trait Verifier {
     /// Verify the hardware signature and report data in TEE quote.
@@ -96,4 +561,75 @@ pub trait Verifier {
         nonce: String,
         attestation: &Attestation,
     ) -> Result<TeeEvidenceParsedClaim>;
+
+    /// The bytes of the evidence that its signature covers, such as the
+    /// quote or report, that tell evidence apart whatever its encoding, see
+    /// [`crate::replay`]. The submitted evidence if it has no such part.
+    fn signed_evidence(&self, attestation: &Attestation) -> Result<Vec<u8>> {
+        Ok(attestation.tee_evidence.as_bytes().to_vec())
+    }
+
+    /// The freshness methods of the evidence the verifier checks, strongest
+    /// first, see [`freshness`].
+    fn freshness_methods(&self) -> Vec<FreshnessMethod> {
+        vec![FreshnessMethod::Nonce, FreshnessMethod::Timestamp]
+    }
+
+    /// Claims given as hex that are integers, to be emitted decoded too,
+    /// see [`transform`]. Names are relative to the claims of the verifier.
+    fn claim_transforms(&self) -> &'static [(&'static str, ClaimTransform)] {
+        &[]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_range() {
+        let range = VersionRange {
+            min: Some(2),
+            ..Default::default()
+        };
+        assert!(range.check("report", 1).is_err());
+        assert!(range.check("report", 2).is_ok());
+        assert!(range.check("report", 5).is_ok());
+        assert_eq!(range.to_string(), "2 or later");
+
+        let range = range.or(VersionRange {
+            min: Some(1),
+            max: Some(3),
+            deprecated_below: Some(3),
+        });
+        assert_eq!(
+            range,
+            VersionRange {
+                min: Some(2),
+                max: Some(3),
+                deprecated_below: Some(3),
+            }
+        );
+        let err = range.check("report", 4).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unsupported report version 4, accepted versions: 2 to 3"
+        );
+
+        assert!(VersionRange::default().check("report", 0).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_deprecated_version() {
+        let range = VersionRange {
+            deprecated_below: Some(3),
+            ..Default::default()
+        };
+        let (_, warnings) = warnings::collect(async {
+            range.check("report", 2).unwrap();
+            range.check("report", 3).unwrap();
+        })
+        .await;
+        assert_eq!(warnings, ["Deprecated report version 2"]);
+    }
 }
@@ -0,0 +1,93 @@
+//! Local verification of NVIDIA confidential GPU evidence, as SPDM device
+//! evidence of the `nvidia_gpu` profile, see [`super::spdm`].
+//!
+//! Confidential VMs with NVIDIA Hopper GPUs attach the evidence of their
+//! GPUs to their TEE evidence, in its `nvidia_gpus` field. The AS verifies
+//! it without NVIDIA's Remote Attestation Service, so that clusters without
+//! access to NVIDIA's cloud can attest GPUs: the certificate chains of the
+//! GPUs end with the NVIDIA device identity root CA, `root_certificate` of
+//! `nvidia_gpu` in the AS config, and the reference values of the
+//! measurements, `nvidia_gpu.measurements.<index>`, are registered in the
+//! RVPS from the driver and VBIOS RIMs by the `nvidia-rim` extractor.
+//!
+//! Besides the claims of SPDM devices, the claims of each GPU, under
+//! `nvidia_gpu.<n>`, are the `driver_version` and `vbios_version` of the
+//! opaque data of its response. Evidence with GPU evidence is rejected if
+//! the AS does not verify it.
+
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value};
+
+use super::spdm::{DeviceProfile, ProfileType, SpdmDeviceConfig};
+use super::NvidiaGpuConfig;
+
+/// Prefix of the names of the reference values of the measurements, in
+/// the RVPS.
+pub const MEASUREMENT_REFERENCE_PREFIX: &str = "nvidia_gpu.measurements.";
+
+/// Prefix of the claims of the GPUs.
+const CLAIM_PREFIX: &str = "nvidia_gpu";
+
+/// Field of the TEE evidence holding the evidence of the GPUs.
+pub const EVIDENCE_FIELD: &str = "nvidia_gpus";
+
+/// `DataType` of the opaque data fields of the NVIDIA driver version and
+/// VBIOS version.
+const DRIVER_VERSION_FIELD: u16 = 3;
+const VBIOS_VERSION_FIELD: u16 = 6;
+
+pub struct NvidiaGpuProfile;
+
+impl DeviceProfile for NvidiaGpuProfile {
+    fn name(&self) -> &str {
+        CLAIM_PREFIX
+    }
+
+    fn evidence_field(&self) -> &str {
+        EVIDENCE_FIELD
+    }
+
+    fn opaque_claims(&self, fields: &BTreeMap<u16, &[u8]>) -> Map<String, Value> {
+        let mut claims = Map::new();
+        if let Some(version) = fields.get(&DRIVER_VERSION_FIELD) {
+            let version = String::from_utf8_lossy(version);
+            claims.insert(
+                "driver_version".to_string(),
+                version.trim_end_matches('\0').into(),
+            );
+        }
+        if let Some(version) = fields.get(&VBIOS_VERSION_FIELD) {
+            claims.insert("vbios_version".to_string(), hex::encode(version).into());
+        }
+        claims
+    }
+}
+
+impl From<&NvidiaGpuConfig> for SpdmDeviceConfig {
+    fn from(config: &NvidiaGpuConfig) -> Self {
+        Self {
+            profile: ProfileType::NvidiaGpu,
+            name: None,
+            root_certificate: config.root_certificate.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opaque_claims() {
+        let fields = BTreeMap::from([
+            (DRIVER_VERSION_FIELD, &b"535.86\0\0"[..]),
+            (VBIOS_VERSION_FIELD, &[0x96, 0x00, 0x72][..]),
+            (1, &b"ignored"[..]),
+        ]);
+        let claims = NvidiaGpuProfile.opaque_claims(&fields);
+        assert_eq!(claims.len(), 2);
+        assert_eq!(claims["driver_version"], "535.86");
+        assert_eq!(claims["vbios_version"], "960072");
+    }
+}
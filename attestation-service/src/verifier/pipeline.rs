@@ -0,0 +1,350 @@
+//! Verifier pipelines.
+//!
+//! The TDX and SNP verifiers run evidence through stages:
+//! 1. `parse`: decode the evidence and check its format version;
+//! 2. `collateral_verify`: verify the signature and certificate chain of
+//!    the evidence, e.g. with the Intel QVL and its collateral;
+//! 3. `eventlog_replay`: replay the event log up to the measurement
+//!    registers of the evidence (TDX only);
+//! 4. `freshness`: check that the report data binds the nonce and the TEE
+//!    public key;
+//! 5. `claims_normalize`: generate the claims given to the policy.
+//!
+//! `verifier_pipelines` in the AS config declares the stages of a TEE, in
+//! the order they run, e.g.
+//!
+//! ```json
+//! "verifier_pipelines": {
+//!     "tdx": ["parse", "freshness", "eventlog_replay", "claims_normalize"]
+//! }
+//! ```
+//!
+//! `parse` and `claims_normalize` are required, first and last. The other
+//! stages can be reordered, e.g. to fail fast on stale evidence.
+//! `collateral_verify` and `freshness`, without which any evidence would
+//! verify, can only be left out with `"allow_insecure": true`, e.g. to skip
+//! collateral checks in a lab without PCCS access. The stages left out are
+//! listed in the `skipped-stages` claim of the tokens, as such evidence
+//! must not be trusted in production.
+//!
+//! Verifiers [`enter`] each stage as they run it, so that the AS can tell
+//! which stage rejected evidence, report its progress, trace the stages it
+//! ran, see [`super::trace`], and profile them, see [`super::profile`].
+
+use super::freshness::FreshnessMethod;
+use crate::progress::{Progress, Step};
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::future::Future;
+
+tokio::task_local! {
+    static CURRENT_STAGE: Cell<Option<Stage>>;
+    static PROGRESS: Option<Progress>;
+}
+
+/// Run `verification`, reporting the stages it enters to `progress`, and
+/// return its output with the last stage it entered, `None` for verifiers
+/// without stages.
+pub(crate) async fn track<F: Future>(
+    progress: Option<Progress>,
+    verification: F,
+) -> (F::Output, Option<Stage>) {
+    PROGRESS
+        .scope(
+            progress,
+            CURRENT_STAGE.scope(Cell::new(None), async move {
+                let output = verification.await;
+                (output, CURRENT_STAGE.with(Cell::get))
+            }),
+        )
+        .await
+}
+
+/// Enter `stage` of the evidence being verified.
+#[cfg_attr(
+    not(any(feature = "tdx-verifier", feature = "snp-verifier")),
+    allow(dead_code)
+)]
+pub(crate) fn enter(stage: Stage) {
+    let _ = CURRENT_STAGE.try_with(|current| current.set(Some(stage)));
+    super::trace::enter(stage);
+    super::profile::enter(stage.into());
+    let _ = PROGRESS.try_with(|progress| {
+        if let Some(progress) = progress {
+            progress.report(Step::Verifier(stage));
+        }
+    });
+}
+
+#[derive(
+    Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, EnumString, Display, IntoStaticStr,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Stage {
+    Parse,
+    CollateralVerify,
+    EventlogReplay,
+    Freshness,
+    ClaimsNormalize,
+}
+
+/// Stages of the TDX verifier, in their default order.
+pub const TDX_STAGES: &[Stage] = &[
+    Stage::Parse,
+    Stage::CollateralVerify,
+    Stage::EventlogReplay,
+    Stage::Freshness,
+    Stage::ClaimsNormalize,
+];
+
+/// Stages of the SNP verifier, in their default order.
+pub const SNP_STAGES: &[Stage] = &[
+    Stage::Parse,
+    Stage::CollateralVerify,
+    Stage::Freshness,
+    Stage::ClaimsNormalize,
+];
+
+/// The stages that can only be left out with `allow_insecure`.
+pub const SECURITY_STAGES: &[Stage] = &[Stage::CollateralVerify, Stage::Freshness];
+
+/// The optional stages of a verifier, in the order they run between
+/// `parse` and `claims_normalize`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "Vec<Stage>")]
+pub struct Pipeline {
+    checks: Vec<Stage>,
+}
+
+impl TryFrom<Vec<Stage>> for Pipeline {
+    type Error = anyhow::Error;
+
+    fn try_from(stages: Vec<Stage>) -> Result<Self> {
+        let [Stage::Parse, checks @ .., Stage::ClaimsNormalize] = stages.as_slice() else {
+            bail!("A verifier pipeline must start with `parse` and end with `claims_normalize`");
+        };
+        for (i, stage) in checks.iter().enumerate() {
+            match stage {
+                Stage::Parse => {
+                    bail!("Stage `parse` must only be the first of the verifier pipeline")
+                }
+                Stage::ClaimsNormalize => {
+                    bail!("Stage `claims_normalize` must only be the last of the verifier pipeline")
+                }
+                _ if checks[..i].contains(stage) => {
+                    bail!("Stage `{stage}` is repeated in the verifier pipeline")
+                }
+                _ => {}
+            }
+        }
+        Ok(Self {
+            checks: checks.to_vec(),
+        })
+    }
+}
+
+impl Pipeline {
+    /// The pipeline of all the `stages` of a verifier.
+    pub fn new(stages: &[Stage]) -> Self {
+        Self::try_from(stages.to_vec()).expect("Invalid verifier stages")
+    }
+
+    /// Check that the stages are among the `stages` of the `tee` verifier.
+    pub fn check_supported(&self, tee: &str, stages: &[Stage]) -> Result<()> {
+        if let Some(stage) = self.checks.iter().find(|stage| !stages.contains(stage)) {
+            bail!("The {tee} verifier has no `{stage}` stage");
+        }
+        Ok(())
+    }
+
+    /// Check that the pipeline of the `tee` verifier has the
+    /// [`SECURITY_STAGES`], unless `allow_insecure`.
+    pub fn check_secure(&self, tee: &str, allow_insecure: bool) -> Result<()> {
+        match SECURITY_STAGES
+            .iter()
+            .find(|stage| !self.checks.contains(stage))
+        {
+            Some(stage) if !allow_insecure => {
+                bail!("The {tee} pipeline leaves out `{stage}`, which needs `allow_insecure`")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The freshness methods of the evidence the pipeline checks: the nonce
+    /// or timestamp with the `freshness` stage, none without.
+    pub fn freshness_methods(&self) -> Vec<FreshnessMethod> {
+        match self.checks.contains(&Stage::Freshness) {
+            true => vec![FreshnessMethod::Nonce, FreshnessMethod::Timestamp],
+            false => vec![FreshnessMethod::None],
+        }
+    }
+
+    /// The stages between `parse` and `claims_normalize`, in order.
+    pub fn checks(&self) -> &[Stage] {
+        &self.checks
+    }
+
+    /// The `stages` of a verifier that are left out.
+    pub fn skipped(&self, stages: &[Stage]) -> Vec<Stage> {
+        stages
+            .iter()
+            .filter(|stage| {
+                !matches!(stage, Stage::Parse | Stage::ClaimsNormalize)
+                    && !self.checks.contains(stage)
+            })
+            .copied()
+            .collect()
+    }
+}
+
+/// Verifier pipelines of the AS config, all stages of the verifier if not
+/// set.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VerifierPipelines {
+    pub tdx: Option<Pipeline>,
+    pub snp: Option<Pipeline>,
+    /// Allow pipelines without some of the [`SECURITY_STAGES`], whose
+    /// evidence must not be trusted in production.
+    pub allow_insecure: bool,
+}
+
+impl VerifierPipelines {
+    pub fn tdx(&self) -> Result<Pipeline> {
+        let pipeline = self
+            .tdx
+            .clone()
+            .unwrap_or_else(|| Pipeline::new(TDX_STAGES));
+        pipeline.check_supported("TDX", TDX_STAGES)?;
+        pipeline.check_secure("TDX", self.allow_insecure)?;
+        Ok(pipeline)
+    }
+
+    pub fn snp(&self) -> Result<Pipeline> {
+        let pipeline = self
+            .snp
+            .clone()
+            .unwrap_or_else(|| Pipeline::new(SNP_STAGES));
+        pipeline.check_supported("SNP", SNP_STAGES)?;
+        pipeline.check_secure("SNP", self.allow_insecure)?;
+        Ok(pipeline)
+    }
+
+    /// The stages of the `tee` verifier that are left out.
+    pub fn skipped(&self, tee: &kbs_types::Tee) -> Vec<Stage> {
+        match tee {
+            kbs_types::Tee::Tdx => self.tdx.as_ref().map(|p| p.skipped(TDX_STAGES)),
+            kbs_types::Tee::Snp => self.snp.as_ref().map(|p| p.skipped(SNP_STAGES)),
+            _ => None,
+        }
+        .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline() {
+        let pipeline: Pipeline = serde_json::from_str(
+            r#"["parse", "freshness", "eventlog_replay", "claims_normalize"]"#,
+        )
+        .unwrap();
+        assert_eq!(pipeline.checks(), [Stage::Freshness, Stage::EventlogReplay]);
+        assert_eq!(pipeline.skipped(TDX_STAGES), [Stage::CollateralVerify]);
+        pipeline.check_supported("TDX", TDX_STAGES).unwrap();
+        assert!(pipeline.check_supported("SNP", SNP_STAGES).is_err());
+        assert_eq!(
+            pipeline.freshness_methods(),
+            [FreshnessMethod::Nonce, FreshnessMethod::Timestamp]
+        );
+        assert_eq!(
+            Pipeline::new(&[Stage::Parse, Stage::ClaimsNormalize]).freshness_methods(),
+            [FreshnessMethod::None]
+        );
+
+        for invalid in [
+            r#"["freshness", "claims_normalize"]"#,
+            r#"["parse", "freshness"]"#,
+            r#"["parse", "attest", "claims_normalize"]"#,
+        ] {
+            assert!(
+                serde_json::from_str::<Pipeline>(invalid).is_err(),
+                "{invalid}"
+            );
+        }
+        for (invalid, error) in [
+            (
+                r#"["parse", "freshness", "freshness", "claims_normalize"]"#,
+                "Stage `freshness` is repeated in the verifier pipeline",
+            ),
+            (
+                r#"["parse", "parse", "claims_normalize"]"#,
+                "Stage `parse` must only be the first of the verifier pipeline",
+            ),
+            (
+                r#"["parse", "claims_normalize", "claims_normalize"]"#,
+                "Stage `claims_normalize` must only be the last of the verifier pipeline",
+            ),
+        ] {
+            let e = serde_json::from_str::<Pipeline>(invalid).unwrap_err();
+            assert!(e.to_string().starts_with(error), "{e}");
+        }
+
+        pipeline.check_secure("TDX", true).unwrap();
+        let e = pipeline.check_secure("TDX", false).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "The TDX pipeline leaves out `collateral_verify`, which needs `allow_insecure`"
+        );
+        Pipeline::new(TDX_STAGES)
+            .check_secure("TDX", false)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_track() {
+        let (progress, mut steps) = Progress::channel();
+        let (output, stage) = track(Some(progress), async {
+            enter(Stage::Parse);
+            enter(Stage::Freshness);
+            42
+        })
+        .await;
+        assert_eq!(output, 42);
+        assert_eq!(stage, Some(Stage::Freshness));
+        assert_eq!(steps.try_recv().unwrap(), Step::Verifier(Stage::Parse));
+        assert_eq!(steps.try_recv().unwrap(), Step::Verifier(Stage::Freshness));
+
+        let (_, stage) = track(None, async {}).await;
+        assert_eq!(stage, None);
+    }
+
+    #[test]
+    fn test_verifier_pipelines() {
+        let pipelines: VerifierPipelines =
+            serde_json::from_str(r#"{"snp": ["parse", "freshness", "claims_normalize"]}"#).unwrap();
+        assert!(pipelines.snp().is_err());
+        let pipelines = VerifierPipelines {
+            allow_insecure: true,
+            ..pipelines
+        };
+        pipelines.snp().unwrap();
+        assert_eq!(pipelines.tdx().unwrap(), Pipeline::new(TDX_STAGES));
+        assert!(pipelines.skipped(&kbs_types::Tee::Tdx).is_empty());
+        assert_eq!(
+            pipelines.skipped(&kbs_types::Tee::Snp),
+            [Stage::CollateralVerify]
+        );
+
+        assert!(serde_json::from_str::<VerifierPipelines>(
+            r#"{"sgx": ["parse", "claims_normalize"]}"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! The hardware generation of the attested platform.
+//!
+//! Policies and analytics that segment platforms by hardware generation
+//! would otherwise decode the FMSPC of Intel PCK certificates or the
+//! product line of AMD certificates by hand. From the `v2` claims schema,
+//! see [`super::schema`], the `platform.product` claim names it, from the
+//! claims of the verifiers:
+//!
+//! - TDX and SGX: `Intel TDX SPR`, `Intel SGX ICX`..., from the CPU model
+//!   in the `fmspc` claim, the FMSPC of the PCK certificate;
+//! - SNP: `AMD SNP Genoa`..., from the `product_line` claim, the product
+//!   line of the certificate chain of the VCEK or VLEK;
+//! - CCA: `ARM CCA v1`, from the major version of the platform profile;
+//! - CSV: `Hygon CSV`.
+//!
+//! Without the claims it is derived from, e.g. with the `collateral_verify`
+//! stage left out, or for an unknown CPU model, the claim only names the
+//! vendor and TEE, e.g. `Intel TDX`. There is none for the sample TEE.
+
+use serde_json::{Map, Value};
+
+/// Name of the claim of the hardware generation.
+pub const PLATFORM_PRODUCT_CLAIM: &str = "platform.product";
+
+/// Claim of the FMSPC of the PCK certificate of TDX and SGX quotes.
+pub const FMSPC_CLAIM: &str = "fmspc";
+
+/// Claim of the product line of AMD certificate chains.
+pub const PRODUCT_LINE_CLAIM: &str = "product_line";
+
+/// Intel CPU models of family 6, by their short names.
+const INTEL_MODELS: &[(u8, &str)] = &[
+    (0x55, "CLX"),
+    (0x6a, "ICX"),
+    (0x6c, "ICX-D"),
+    (0x8e, "KBL"),
+    (0x8f, "SPR"),
+    (0x9e, "CFL"),
+    (0xa5, "CML"),
+    (0xad, "GNR"),
+    (0xae, "GNR-D"),
+    (0xaf, "SRF"),
+    (0xcf, "EMR"),
+];
+
+/// The short name of the CPU model of a hex FMSPC, whose nibbles are the
+/// extended model, the extended family, the family, the model...
+fn intel_model(fmspc: &str) -> Option<&'static str> {
+    let nibble = |i: usize| {
+        fmspc
+            .get(i..i + 1)
+            .and_then(|n| u8::from_str_radix(n, 16).ok())
+    };
+    if nibble(4)? != 6 {
+        return None;
+    }
+    let model = (nibble(2)? << 4) | nibble(5)?;
+    INTEL_MODELS
+        .iter()
+        .find(|(m, _)| *m == model)
+        .map(|(_, name)| *name)
+}
+
+/// The major version of a CCA platform profile, e.g. `1` of
+/// `http://arm.com/CCA-SSD/1.0.0` or `tag:arm.com,2023:cca_platform#1.0.0`.
+fn cca_profile_version(profile: &str) -> Option<&str> {
+    let version = profile.rsplit(['/', '#']).next()?;
+    let major = version.split('.').next()?;
+    (!major.is_empty() && major.chars().all(|c| c.is_ascii_digit())).then_some(major)
+}
+
+fn product(tee: &str, claims: &Map<String, Value>) -> Option<String> {
+    let claim = |name: &str| claims.get(&format!("{tee}.{name}")).and_then(Value::as_str);
+    let (vendor, generation) = match tee {
+        "tdx" => ("Intel TDX", claim(FMSPC_CLAIM).and_then(intel_model)),
+        "sgx" => ("Intel SGX", claim(FMSPC_CLAIM).and_then(intel_model)),
+        "snp" => ("AMD SNP", claim(PRODUCT_LINE_CLAIM)),
+        "azsnpvtpm" => match claim("hardware") {
+            Some("tdx") => ("Intel TDX", None),
+            _ => ("AMD SNP", None),
+        },
+        "cca" => (
+            "ARM CCA",
+            claim("cca-platform-profile").and_then(cca_profile_version),
+        ),
+        "csv" => ("Hygon CSV", None),
+        _ => return None,
+    };
+    Some(match (tee, generation) {
+        ("cca", Some(version)) => format!("{vendor} v{version}"),
+        (_, Some(generation)) => format!("{vendor} {generation}"),
+        (_, None) => vendor.to_string(),
+    })
+}
+
+/// Add the `platform.product` claim of `tee` to the flattened `claims`.
+pub fn apply(tee: &str, claims: &mut Value) {
+    let Some(claims) = claims.as_object_mut() else {
+        return;
+    };
+    if let Some(product) = product(tee, claims) {
+        claims.insert(PLATFORM_PRODUCT_CLAIM.to_string(), product.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn product_of(tee: &str, mut claims: Value) -> Option<Value> {
+        apply(tee, &mut claims);
+        claims.get(PLATFORM_PRODUCT_CLAIM).cloned()
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(
+            product_of("tdx", json!({ "tdx.fmspc": "00806f050000" })).unwrap(),
+            "Intel TDX SPR"
+        );
+        assert_eq!(
+            product_of("sgx", json!({ "sgx.fmspc": "00606A000000" })).unwrap(),
+            "Intel SGX ICX"
+        );
+        assert_eq!(
+            product_of("sgx", json!({ "sgx.fmspc": "00906ED50000" })).unwrap(),
+            "Intel SGX CFL"
+        );
+        // Unknown model, or no FMSPC.
+        assert_eq!(
+            product_of("tdx", json!({ "tdx.fmspc": "00f06f000000" })).unwrap(),
+            "Intel TDX"
+        );
+        assert_eq!(product_of("tdx", json!({})).unwrap(), "Intel TDX");
+
+        assert_eq!(
+            product_of("snp", json!({ "snp.product_line": "Genoa" })).unwrap(),
+            "AMD SNP Genoa"
+        );
+        assert_eq!(
+            product_of("azsnpvtpm", json!({ "azsnpvtpm.hardware": "tdx" })).unwrap(),
+            "Intel TDX"
+        );
+        assert_eq!(
+            product_of(
+                "cca",
+                json!({ "cca.cca-platform-profile": "http://arm.com/CCA-SSD/1.0.0" })
+            )
+            .unwrap(),
+            "ARM CCA v1"
+        );
+        assert_eq!(
+            product_of(
+                "cca",
+                json!({ "cca.cca-platform-profile": "tag:arm.com,2023:cca_platform#1.0.0" })
+            )
+            .unwrap(),
+            "ARM CCA v1"
+        );
+        assert_eq!(product_of("csv", json!({})).unwrap(), "Hygon CSV");
+        assert!(product_of("sample", json!({})).is_none());
+    }
+}
@@ -0,0 +1,266 @@
+//! Per-request profiles of the verifiers.
+//!
+//! Verifiers time the parsing and crypto steps of their pipeline stages,
+//! e.g. the decoding of the report or the verification of each certificate
+//! of its chain, see [`step`]. In builds with the `profiling` feature, a
+//! caller passes a [`Profile`] in the options of an evaluation, and reads
+//! the time of each step as folded stacks once the evaluation is done:
+//!
+//! ```text
+//! snp;collateral_verify;cert_chain;ask_signature 812
+//! ```
+//!
+//! One line per stack, with the time spent in its last frame, out of its
+//! child steps, in microseconds. Folded stacks are the input of flamegraph
+//! tools such as `inferno-flamegraph` or `flamegraph.pl`, and the profiles
+//! of many requests, concatenated, add up to the flamegraph of a benchmark,
+//! e.g. to compare the verifiers of two TEEs step by step. The
+//! OpenTelemetry traces of the AS also have a span for each step, see
+//! [`crate::tracing`]. Without the feature, steps are not timed.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Whether verifiers time their steps.
+const ENABLED: bool = cfg!(feature = "profiling");
+
+tokio::task_local! {
+    static PROFILE: Profile;
+}
+
+/// A step timed by a verifier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedStep {
+    pub name: &'static str,
+    /// The step or stage the step is part of, `None` out of any.
+    pub parent: Option<&'static str>,
+    pub start: SystemTime,
+    pub end: SystemTime,
+}
+
+#[derive(Debug)]
+struct Frame {
+    name: &'static str,
+    stage: bool,
+    start: Instant,
+    started: SystemTime,
+    /// Time spent in the child frames.
+    children: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Frames {
+    /// The open frames, the TEE first.
+    open: Vec<Frame>,
+    /// Time spent in the last frame of each stack, out of its children.
+    stacks: BTreeMap<String, Duration>,
+    steps: Vec<TimedStep>,
+}
+
+impl Frames {
+    fn close(&mut self) {
+        let Some(frame) = self.open.pop() else {
+            return;
+        };
+        let elapsed = frame.start.elapsed();
+        let stack = self
+            .open
+            .iter()
+            .map(|open| open.name)
+            .chain([frame.name])
+            .collect::<Vec<_>>()
+            .join(";");
+        *self.stacks.entry(stack).or_default() += elapsed.saturating_sub(frame.children);
+        if let Some(parent) = self.open.last_mut() {
+            parent.children += elapsed;
+        }
+        if !frame.stage {
+            self.steps.push(TimedStep {
+                name: frame.name,
+                // The first frame is that of the TEE.
+                parent: match self.open.len() {
+                    0 | 1 => None,
+                    n => Some(self.open[n - 1].name),
+                },
+                start: frame.started,
+                end: SystemTime::now(),
+            });
+        }
+    }
+}
+
+/// Profile of an evaluation, see the module documentation.
+#[derive(Clone, Debug, Default)]
+pub struct Profile(Arc<Mutex<Frames>>);
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn frames(&self) -> MutexGuard<'_, Frames> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Open the frame `name`, and return the number of frames open out of
+    /// it.
+    fn open(&self, name: &'static str, stage: bool) -> usize {
+        let mut frames = self.frames();
+        frames.open.push(Frame {
+            name,
+            stage,
+            start: Instant::now(),
+            started: SystemTime::now(),
+            children: Duration::ZERO,
+        });
+        frames.open.len() - 1
+    }
+
+    /// Close the frames open out of the first `depth` ones.
+    fn close(&self, depth: usize) {
+        let mut frames = self.frames();
+        while frames.open.len() > depth {
+            frames.close();
+        }
+    }
+
+    /// The profile as folded stacks, in microseconds.
+    pub fn folded(&self) -> String {
+        self.frames()
+            .stacks
+            .iter()
+            .map(|(stack, time)| format!("{stack} {}\n", time.as_micros()))
+            .collect()
+    }
+
+    /// The steps timed so far, in the order they ended.
+    pub fn steps(&self) -> Vec<TimedStep> {
+        self.frames().steps.clone()
+    }
+}
+
+/// Run the verification of evidence of `tee`, timing its steps in
+/// `profile`, if any.
+#[cfg_attr(not(feature = "service"), allow(dead_code))]
+pub(crate) async fn collect<F: Future>(
+    profile: Option<Profile>,
+    tee: &'static str,
+    verification: F,
+) -> F::Output {
+    match profile.filter(|_| ENABLED) {
+        Some(profile) => {
+            profile.open(tee, true);
+            let output = PROFILE.scope(profile.clone(), verification).await;
+            profile.close(0);
+            output
+        }
+        None => verification.await,
+    }
+}
+
+/// Record that the verifier entered `stage`, ending the previous one.
+#[cfg_attr(
+    not(any(feature = "tdx-verifier", feature = "snp-verifier")),
+    allow(dead_code)
+)]
+pub(crate) fn enter(stage: &'static str) {
+    let _ = PROFILE.try_with(|profile| {
+        profile.close(1);
+        profile.open(stage, true);
+    });
+}
+
+/// A step of a verifier, timed until dropped.
+#[must_use]
+pub(crate) struct Step(Option<(Profile, usize)>);
+
+impl Drop for Step {
+    fn drop(&mut self) {
+        if let Some((profile, depth)) = &self.0 {
+            profile.close(*depth);
+        }
+    }
+}
+
+/// Time the step `name` of the current stage, or of the current step,
+/// until the returned [`Step`] is dropped.
+#[cfg_attr(
+    not(any(feature = "tdx-verifier", feature = "snp-verifier")),
+    allow(dead_code)
+)]
+pub(crate) fn step(name: &'static str) -> Step {
+    Step(
+        PROFILE
+            .try_with(|profile| (profile.clone(), profile.open(name, false)))
+            .ok(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        let profile = Profile::new();
+        profile.open("snp", true);
+        profile.open("parse", true);
+        let step = profile.open("decode_evidence", false);
+        profile.close(step);
+        profile.close(1);
+        profile.open("collateral_verify", true);
+        let chain = profile.open("cert_chain", false);
+        let ask = profile.open("ask_signature", false);
+        std::thread::sleep(Duration::from_millis(2));
+        profile.close(ask);
+        // An unclosed step is closed with its parent.
+        profile.open("vcek_signature", false);
+        profile.close(chain);
+        profile.close(0);
+
+        let folded = profile.folded();
+        let stacks: Vec<_> = folded
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().0)
+            .collect();
+        assert_eq!(
+            stacks,
+            [
+                "snp",
+                "snp;collateral_verify",
+                "snp;collateral_verify;cert_chain",
+                "snp;collateral_verify;cert_chain;ask_signature",
+                "snp;collateral_verify;cert_chain;vcek_signature",
+                "snp;parse",
+                "snp;parse;decode_evidence",
+            ]
+        );
+        let ask_time: u64 = folded
+            .lines()
+            .nth(3)
+            .unwrap()
+            .rsplit_once(' ')
+            .unwrap()
+            .1
+            .parse()
+            .unwrap();
+        assert!(ask_time >= 2000);
+
+        let steps: Vec<_> = profile
+            .steps()
+            .into_iter()
+            .map(|step| (step.name, step.parent))
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                ("decode_evidence", Some("parse")),
+                ("ask_signature", Some("cert_chain")),
+                ("vcek_signature", Some("cert_chain")),
+                ("cert_chain", Some("collateral_verify")),
+            ]
+        );
+    }
+}
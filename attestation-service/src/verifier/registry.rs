@@ -0,0 +1,190 @@
+//! Verifiers registered at runtime.
+//!
+//! The verifiers of the AS are compiled in, one per TEE type, see
+//! [`super::to_verifier`]. Verifiers of other crates, e.g. for a TEE type
+//! that the AS has no verifier of, or a fork of one of its verifiers, are
+//! registered in a [`VerifierRegistry`] at startup, and take over the TEE
+//! type they are registered for. They are made from the verifier config,
+//! whose `verifiers` holds a config section for each of them, by TEE name,
+//! see [`VerifierConfig::section`]:
+//!
+//! ```json
+//! "verifiers": {
+//!     "sev": { "kds_url": "https://kdsintf.amd.com" }
+//! }
+//! ```
+
+use super::{transform, ReportDataMode, TeeEvidenceParsedClaim, Verifier, VerifierConfig};
+use crate::utils::flatten_claims;
+use anyhow::{Context, Result};
+use kbs_types::{Attestation, Tee};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Makes the verifier of a TEE type, for every attestation.
+pub trait VerifierFactory: Send + Sync {
+    /// The verifier, comparing the report data of evidence with the nonce
+    /// and TEE public key as `report_data` says.
+    fn verifier(
+        &self,
+        config: &VerifierConfig,
+        report_data: ReportDataMode,
+    ) -> Result<Box<dyn Verifier + Send + Sync>>;
+}
+
+impl<F> VerifierFactory for F
+where
+    F: Fn(&VerifierConfig, ReportDataMode) -> Result<Box<dyn Verifier + Send + Sync>> + Send + Sync,
+{
+    fn verifier(
+        &self,
+        config: &VerifierConfig,
+        report_data: ReportDataMode,
+    ) -> Result<Box<dyn Verifier + Send + Sync>> {
+        self(config, report_data)
+    }
+}
+
+/// The verifiers registered for TEE types, in place of the compiled in
+/// ones.
+#[derive(Clone, Default)]
+pub struct VerifierRegistry {
+    factories: BTreeMap<String, (Tee, Arc<dyn VerifierFactory>)>,
+}
+
+impl VerifierRegistry {
+    /// Verify the evidence of `tee` with the verifiers of `factory`, in
+    /// place of those registered or compiled in before.
+    pub fn register(&mut self, tee: Tee, factory: impl VerifierFactory + 'static) -> Result<()> {
+        let name = serde_variant::to_variant_name(&tee)?.to_string();
+        log::info!("Registered a verifier of {name}");
+        self.factories.insert(name, (tee, Arc::new(factory)));
+        Ok(())
+    }
+
+    /// The TEE types that have a registered verifier.
+    pub fn registered(&self) -> Vec<Tee> {
+        self.factories
+            .values()
+            .map(|(tee, _)| tee.clone())
+            .collect()
+    }
+
+    pub fn is_registered(&self, tee: &Tee) -> bool {
+        serde_variant::to_variant_name(tee).is_ok_and(|name| self.factories.contains_key(name))
+    }
+
+    /// The verifier of `tee`, the registered one if any, else the compiled
+    /// in one, see [`super::to_verifier`].
+    pub fn to_verifier(
+        &self,
+        tee: &Tee,
+        config: &VerifierConfig,
+        report_data: Option<ReportDataMode>,
+    ) -> Result<Box<dyn Verifier + Send + Sync>> {
+        let name = serde_variant::to_variant_name(tee)?;
+        match self.factories.get(name) {
+            Some((_, factory)) => factory
+                .verifier(
+                    config,
+                    report_data.unwrap_or_else(|| config.report_data.of(tee)),
+                )
+                .with_context(|| format!("Cannot make the registered verifier of {name}")),
+            None => super::to_verifier(tee, config, report_data),
+        }
+    }
+
+    /// Verify the `attestation` evidence of `tee`, bound to `nonce`, with
+    /// the registered verifiers, see [`super::verify`].
+    pub async fn verify(
+        &self,
+        tee: Tee,
+        nonce: &str,
+        attestation: &Attestation,
+        config: &VerifierConfig,
+    ) -> Result<TeeEvidenceParsedClaim> {
+        let verifier = self.to_verifier(&tee, config, None)?;
+        let tee_name = serde_variant::to_variant_name(&tee)?;
+        let transforms = transform::transforms(
+            tee_name,
+            verifier.claim_transforms(),
+            &config.claim_transforms,
+        );
+        let claims = verifier.evaluate(nonce.to_string(), attestation).await?;
+        let mut flattened_claims = flatten_claims(tee, &claims)?;
+        config
+            .claims_schema
+            .apply(tee_name, &mut flattened_claims, &transforms);
+        Ok(flattened_claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use kbs_types::TeePubKey;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Deserialize, Default)]
+    struct FixedConfig {
+        measurement: String,
+    }
+
+    /// A verifier of any evidence, with the measurement of its config.
+    struct Fixed(String);
+
+    #[async_trait]
+    impl Verifier for Fixed {
+        async fn evaluate(
+            &self,
+            _nonce: String,
+            _attestation: &Attestation,
+        ) -> Result<TeeEvidenceParsedClaim> {
+            Ok(json!({ "measurement": self.0 }).into())
+        }
+    }
+
+    fn fixed(
+        config: &VerifierConfig,
+        _report_data: ReportDataMode,
+    ) -> Result<Box<dyn Verifier + Send + Sync>> {
+        let config: FixedConfig = config.section(&Tee::Sev)?;
+        Ok(Box::new(Fixed(config.measurement)))
+    }
+
+    #[tokio::test]
+    async fn test_registry() {
+        let mut registry = VerifierRegistry::default();
+        assert!(!registry.is_registered(&Tee::Sev));
+        registry.register(Tee::Sev, fixed).unwrap();
+        assert!(registry.is_registered(&Tee::Sev));
+        assert!(matches!(registry.registered()[..], [Tee::Sev]));
+
+        let config: VerifierConfig = serde_json::from_value(json!({
+            "verifiers": { "sev": { "measurement": "705e" } },
+        }))
+        .unwrap();
+        let attestation = Attestation {
+            tee_pubkey: TeePubKey {
+                kty: "RSA".to_string(),
+                alg: "RSA1_5".to_string(),
+                k_mod: "n".to_string(),
+                k_exp: "AQAB".to_string(),
+            },
+            tee_evidence: "{}".to_string(),
+        };
+        let claims = registry
+            .verify(Tee::Sev, "nonce", &attestation, &config)
+            .await
+            .unwrap();
+        assert_eq!(claims["sev.measurement"], "705e");
+
+        // Other TEE types keep their compiled in verifier.
+        assert!(registry
+            .verify(Tee::Sample, "nonce", &attestation, &config)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! Comparison of the report data of evidence with the nonce and the TEE
+//! public key it binds.
+//!
+//! Attesters put `HASH(nonce || pubkey)` in the report data of their
+//! evidence, but not all the same way: some pad the digest with something
+//! else than zeros, some truncate it, some use another hash algorithm. The
+//! comparison is chosen per verifier in the AS config, or per request, and
+//! recorded in the `report_data_mode` claim so that policies can reject the
+//! weaker ones:
+//!
+//! - `exact`: `SHA384(nonce || pubkey)` zero padded to the size of the report
+//!   data, the default;
+//! - `prefix`, `prefix:<n>`: the report data starts with the SHA384 digest,
+//!   or with its first `n` bytes (at least 32), whatever follows;
+//! - `hash:<algorithm>`: the report data starts with the `sha256`, `sha384`
+//!   or `sha512` digest, whatever follows.
+
+use anyhow::{anyhow, bail, Result};
+use kbs_types::{Attestation, Tee};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::fmt;
+use std::str::FromStr;
+
+/// Size of the SHA384 digest.
+const SHA384_SIZE: usize = 48;
+
+/// Shortest digest prefix accepted, for the binding to keep 256 bits of
+/// security.
+const MIN_PREFIX: usize = 32;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    #[default]
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    fn digest(&self, parts: &[&[u8]]) -> Vec<u8> {
+        fn digest<D: Digest>(parts: &[&[u8]]) -> Vec<u8> {
+            let mut hasher = D::new();
+            for part in parts {
+                hasher.update(part);
+            }
+            hasher.finalize().to_vec()
+        }
+        match self {
+            Self::Sha256 => digest::<Sha256>(parts),
+            Self::Sha384 => digest::<Sha384>(parts),
+            Self::Sha512 => digest::<Sha512>(parts),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sha256 => "sha256",
+            Self::Sha384 => "sha384",
+            Self::Sha512 => "sha512",
+        })
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sha256" => Ok(Self::Sha256),
+            "sha384" => Ok(Self::Sha384),
+            "sha512" => Ok(Self::Sha512),
+            _ => bail!("unknown hash algorithm {s}, expected sha256, sha384 or sha512"),
+        }
+    }
+}
+
+/// How the report data of evidence is compared with `HASH(nonce || pubkey)`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum ReportDataMode {
+    /// Equal to the SHA384 digest, zero padded.
+    #[default]
+    Exact,
+    /// Starts with the first bytes of the SHA384 digest, all of them if
+    /// `None`.
+    Prefix(Option<usize>),
+    /// Starts with the digest of the given algorithm.
+    Hash(HashAlgorithm),
+}
+
+impl ReportDataMode {
+    /// Whether `report_data` binds `nonce` and the TEE public key of
+    /// `attestation`.
+    pub fn matches(&self, nonce: &str, attestation: &Attestation, report_data: &[u8]) -> bool {
+        let algorithm = match self {
+            Self::Hash(algorithm) => *algorithm,
+            _ => HashAlgorithm::Sha384,
+        };
+        let digest = algorithm.digest(&[
+            nonce.as_bytes(),
+            attestation.tee_pubkey.k_mod.as_bytes(),
+            attestation.tee_pubkey.k_exp.as_bytes(),
+        ]);
+        log::info!("HASH(nonce||pubkey):\n\t{}\n", hex::encode(&digest));
+
+        match self {
+            Self::Exact => {
+                report_data.len() >= digest.len()
+                    && report_data.starts_with(&digest)
+                    && report_data[digest.len()..].iter().all(|byte| *byte == 0)
+            }
+            Self::Prefix(length) => {
+                report_data.starts_with(&digest[..length.unwrap_or(digest.len())])
+            }
+            Self::Hash(_) => report_data.starts_with(&digest),
+        }
+    }
+
+    /// Add the `report_data_mode` claim to the claims of a verifier.
+    pub fn add_claim(&self, claims: &mut Value) {
+        if let Some(claims) = claims.as_object_mut() {
+            claims.insert("report_data_mode".to_string(), self.to_string().into());
+        }
+    }
+}
+
+impl fmt::Display for ReportDataMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Exact => write!(f, "exact"),
+            Self::Prefix(None) => write!(f, "prefix"),
+            Self::Prefix(Some(length)) => write!(f, "prefix:{length}"),
+            Self::Hash(algorithm) => write!(f, "hash:{algorithm}"),
+        }
+    }
+}
+
+impl FromStr for ReportDataMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            None if s == "exact" => Ok(Self::Exact),
+            None if s == "prefix" => Ok(Self::Prefix(None)),
+            Some(("prefix", length)) => {
+                let length = length
+                    .parse()
+                    .map_err(|_| anyhow!("invalid prefix length {length}"))?;
+                if !(MIN_PREFIX..=SHA384_SIZE).contains(&length) {
+                    bail!("prefix length must be {MIN_PREFIX} to {SHA384_SIZE} bytes");
+                }
+                Ok(Self::Prefix(Some(length)))
+            }
+            Some(("hash", algorithm)) => Ok(Self::Hash(algorithm.parse()?)),
+            _ => bail!(
+                "unknown report data mode {s}, expected exact, prefix[:<n>] or hash:<algorithm>"
+            ),
+        }
+    }
+}
+
+impl TryFrom<String> for ReportDataMode {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+/// Report data comparison of each verifier.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ReportDataModes {
+    pub tdx: ReportDataMode,
+    pub sgx: ReportDataMode,
+    pub snp: ReportDataMode,
+}
+
+impl ReportDataModes {
+    /// The comparison of the verifier of `tee`.
+    pub fn of(&self, tee: &Tee) -> ReportDataMode {
+        match tee {
+            Tee::Tdx => self.tdx,
+            Tee::Sgx => self.sgx,
+            Tee::Snp => self.snp,
+            _ => ReportDataMode::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kbs_types::TeePubKey;
+
+    #[test]
+    fn test_matches() {
+        let attestation = Attestation {
+            tee_pubkey: TeePubKey {
+                kty: "RSA".to_string(),
+                alg: "RSA1_5".to_string(),
+                k_mod: "mod".to_string(),
+                k_exp: "AQAB".to_string(),
+            },
+            tee_evidence: String::new(),
+        };
+        let digest = HashAlgorithm::Sha384.digest(&[b"nonce", b"mod", b"AQAB"]);
+        let mut zero_padded = digest.clone();
+        zero_padded.resize(64, 0);
+        let mut one_padded = digest.clone();
+        one_padded.resize(64, 0xff);
+        let mut truncated = digest[..32].to_vec();
+        truncated.resize(64, 0);
+
+        let exact = ReportDataMode::Exact;
+        assert!(exact.matches("nonce", &attestation, &zero_padded));
+        assert!(!exact.matches("nonce", &attestation, &one_padded));
+        assert!(!exact.matches("other", &attestation, &zero_padded));
+
+        let prefix: ReportDataMode = "prefix".parse().unwrap();
+        assert!(prefix.matches("nonce", &attestation, &one_padded));
+        assert!(!prefix.matches("nonce", &attestation, &truncated));
+        let prefix: ReportDataMode = "prefix:32".parse().unwrap();
+        assert!(prefix.matches("nonce", &attestation, &truncated));
+
+        let sha512 = HashAlgorithm::Sha512.digest(&[b"nonce", b"mod", b"AQAB"]);
+        let hash: ReportDataMode = "hash:sha512".parse().unwrap();
+        assert!(hash.matches("nonce", &attestation, &sha512));
+        assert!(!hash.matches("nonce", &attestation, &zero_padded));
+    }
+
+    #[test]
+    fn test_parse() {
+        for mode in ["exact", "prefix", "prefix:40", "hash:sha256"] {
+            assert_eq!(mode.parse::<ReportDataMode>().unwrap().to_string(), mode);
+        }
+        for mode in ["prefix:16", "prefix:64", "hash:md5", "loose"] {
+            assert!(mode.parse::<ReportDataMode>().is_err());
+        }
+
+        let modes: ReportDataModes = serde_json::from_str(r#"{"tdx": "prefix"}"#).unwrap();
+        assert_eq!(modes.of(&Tee::Tdx), ReportDataMode::Prefix(None));
+        assert_eq!(modes.of(&Tee::Snp), ReportDataMode::Exact);
+
+        let mut claims = serde_json::json!({});
+        ReportDataMode::Exact.add_claim(&mut claims);
+        assert_eq!(claims["report_data_mode"], "exact");
+    }
+}
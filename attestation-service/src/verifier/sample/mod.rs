@@ -11,6 +11,8 @@ use sha2::{Digest, Sha384};
 struct SampleTeeEvidence {
     svn: String,
     report_data: String,
+    #[serde(default)]
+    debug: bool,
 }
 
 #[derive(Debug, Default)]
@@ -61,8 +63,55 @@ async fn verify_tee_evidence(
 // Example: CPU SVN, RTMR, etc.
 fn parse_tee_evidence(quote: &SampleTeeEvidence) -> Result<TeeEvidenceParsedClaim> {
     let claims_map = json!({
-        "svn": quote.svn
+        "svn": quote.svn,
+        "debug": quote.debug,
     });
 
-    Ok(claims_map as TeeEvidenceParsedClaim)
+    Ok(TeeEvidenceParsedClaim::from(claims_map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verifier::conformance::{self, Conformance, DebugClaim, Evidence};
+    use serde_json::Value;
+
+    fn sample_evidence(nonce: &str, debug: bool) -> Attestation {
+        let tee_pubkey = conformance::dummy_tee_pubkey();
+        let mut hasher = Sha384::new();
+        hasher.update(nonce);
+        hasher.update(&tee_pubkey.k_mod);
+        hasher.update(&tee_pubkey.k_exp);
+        let report_data = base64::engine::general_purpose::STANDARD.encode(hasher.finalize());
+
+        let tee_evidence = json!({
+            "svn": "1",
+            "report_data": report_data,
+            "debug": debug,
+        });
+        Attestation {
+            tee_pubkey,
+            tee_evidence: tee_evidence.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        conformance::run(Conformance {
+            tee: Tee::Sample,
+            verifier: Sample::default(),
+            malformed: vec![json!({"svn": "1", "report_data": "AAAA"}).to_string()],
+            evidence: Some(Evidence {
+                nonce: "nonce".to_string(),
+                attestation: sample_evidence("nonce", true),
+                debug: true,
+            }),
+            claims: None,
+            debug_claim: Some(DebugClaim {
+                name: "sample.debug",
+                is_debug: Value::as_bool,
+            }),
+        })
+        .await;
+    }
 }
@@ -0,0 +1,122 @@
+//! Versions of the claims schema, the shape of the claims policies see.
+//!
+//! - `v1`: the claims as the verifiers produce them, flattened, with
+//!   integers and flags as the hex strings of the evidence. Policies
+//!   written before the normalized claims keep working against it.
+//! - `v2`: the `v1` claims, plus the decoded claims of [`super::transform`],
+//!   the canonical claims of [`super::canonical`], the hardware generation
+//!   of [`super::platform`], and the TCB status lifted out of the claims of
+//!   the TEE by [`super::tcb::lift`], with the time of boot and of
+//!   attestation of [`super::timing`]. The default.
+//! - `v3`: the `v2` claims, with the bitmaps and SVNs of the evidence typed
+//!   by [`super::typed`]: flags and named SVNs in place of hex strings.
+//!
+//! The AS config sets the version with `claims_schema`, and attestation
+//! requests can ask for another one, so that deployments move to a new
+//! version policy by policy. The version is recorded in the
+//! `claims-schema` claim of the token.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+use super::transform::{self, ClaimTransform};
+use super::{canonical, platform, tcb, timing, typed};
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClaimsSchema {
+    V1,
+    #[default]
+    V2,
+    V3,
+}
+
+impl ClaimsSchema {
+    /// Bring the flattened `claims` of `tee` to this version, with the
+    /// claim `transforms` of the verifier.
+    pub fn apply(self, tee: &str, claims: &mut Value, transforms: &[(String, ClaimTransform)]) {
+        if self == ClaimsSchema::V1 {
+            return;
+        }
+        transform::apply(claims, transforms);
+        canonical::apply(tee, claims);
+        platform::apply(tee, claims);
+        tcb::lift(tee, claims);
+        timing::lift(tee, claims, chrono::Utc::now());
+        if self == ClaimsSchema::V3 {
+            typed::apply(tee, claims);
+        }
+    }
+}
+
+impl fmt::Display for ClaimsSchema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::V1 => "v1",
+            Self::V2 => "v2",
+            Self::V3 => "v3",
+        })
+    }
+}
+
+impl FromStr for ClaimsSchema {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "v1" => Ok(Self::V1),
+            "v2" => Ok(Self::V2),
+            "v3" => Ok(Self::V3),
+            _ => bail!("unknown claims schema {s}, expected v1, v2 or v3"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn claims() -> Value {
+        json!({
+            "tdx.quote.body.xfam": "e700000000000000",
+            "tdx.quote.body.td_attributes": "0100000000000000",
+            "tdx.tcb_status": "UpToDate",
+        })
+    }
+
+    #[test]
+    fn test_apply() {
+        let transforms = vec![("tdx.quote.body.xfam".to_string(), ClaimTransform::LeUint)];
+
+        let mut v1 = claims();
+        ClaimsSchema::V1.apply("tdx", &mut v1, &transforms);
+        assert_eq!(v1, claims());
+
+        let mut v2 = claims();
+        ClaimsSchema::V2.apply("tdx", &mut v2, &transforms);
+        assert_eq!(v2["tdx.quote.body.xfam_int"], 0xe7);
+        assert_eq!(v2["debug"], true);
+        assert_eq!(v2["tcb_status"], "UpToDate");
+        assert!(v2["attestation_time"].is_string());
+
+        let mut v3 = claims();
+        ClaimsSchema::V3.apply("tdx", &mut v3, &transforms);
+        assert_eq!(v3["tdx.quote.body.xfam_int"], 0xe7);
+        assert_eq!(v3["tdx.quote.body.xfam.avx512"], true);
+        assert_eq!(v3["tdx.quote.body.td_attributes.debug"], true);
+        assert!(v3.get("tdx.quote.body.xfam").is_none());
+        assert_eq!(v3["debug"], true);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("v1".parse::<ClaimsSchema>().unwrap(), ClaimsSchema::V1);
+        assert_eq!(ClaimsSchema::default().to_string(), "v2");
+        assert_eq!("v3".parse::<ClaimsSchema>().unwrap(), ClaimsSchema::V3);
+        assert!("v4".parse::<ClaimsSchema>().is_err());
+    }
+}
@@ -0,0 +1,577 @@
+//! IBM Secure Execution (SE) verifier, for s390x guests.
+//!
+//! An SE guest is attested with an attestation request that only the
+//! hosts it was encrypted for can read: the requester encrypts a random
+//! measurement key to the host key documents (HKD) of the hosts, and the
+//! Ultravisor of the host returns a measurement, the HMAC-SHA512 with that
+//! key of
+//!
+//! ```text
+//! PLD || ALD || TLD || tag || config UID || user data || additional data
+//! ```
+//!
+//! where the page list, address list and tweak list digests and the tag
+//! come from the SE header of the guest image. The evidence is the
+//! attestation response, with the HKD the request was encrypted for, and
+//! the measurement key of the request encrypted by the requester to the RSA
+//! `measurement_key` of the verifier, with OAEP over SHA-256:
+//!
+//! ```json
+//! {
+//!     "measurement": "<base64>",
+//!     "config_uid": "<hex>",
+//!     "user_data": "<base64>",
+//!     "additional_data": "<base64>",
+//!     "image_tag": "<hex>",
+//!     "host_key_document": "<PEM>",
+//!     "encrypted_measurement_key": "<base64>"
+//! }
+//! ```
+//!
+//! The verifier checks that the HKD is signed by an IBM Z host key signing
+//! key of `se.signing_keys` in the AS config, whose chains end at an `ibm`
+//! trust anchor, if any, see [`super::anchors`], and that it is one of
+//! `se.host_key_documents`, if any. It then recomputes the measurement with
+//! the SE header of the image of the `image_tag` among `se.images`, and
+//! compares the user data with the nonce and TEE public key as
+//! [`ReportDataMode`] says.
+//!
+//! The public part of `measurement_key` must only be known to the
+//! requester: whoever holds it can make up measurement keys.
+//!
+//! kbs-types has no TEE type of SE yet, so the verifier is not compiled in
+//! for a TEE type. Programs built on the AS register [`factory`] for the TEE
+//! type their attesters send SE evidence as, see [`super::registry`].
+
+use super::anchors::{self, TrustAnchor, TRUST_ANCHOR_CLAIM};
+use super::crypto::CryptoBackend;
+use super::*;
+use anyhow::{anyhow, bail, Context, Result};
+use as_types::ClaimsBuilder;
+use async_trait::async_trait;
+use base64::Engine;
+use core::result::Result::Ok;
+use openssl::encrypt::Decrypter;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Padding;
+use openssl::sign::Signer;
+use openssl::x509::X509;
+use std::sync::Arc;
+
+/// Size of the PLD, ALD and TLD, SHA-512 digests.
+const DIGEST_SIZE: usize = 64;
+
+/// Size of the tag of an SE header.
+const TAG_SIZE: usize = 16;
+
+/// Size of the configuration UID of a guest.
+const CONFIG_UID_SIZE: usize = 16;
+
+/// Size of the measurement key, and of the measurement, an HMAC-SHA512.
+const MEASUREMENT_SIZE: usize = 64;
+
+/// Largest user data of an attestation response.
+const MAX_USER_DATA: usize = 256;
+
+/// Organization of the IBM Z host key signing keys.
+const SIGNING_KEY_ORGANIZATION: &str = "International Business Machines Corporation";
+
+/// Organizational unit of the IBM Z host key signing keys.
+const SIGNING_KEY_UNIT: &str = "IBM Z Host Key Signing Service";
+
+#[derive(Deserialize)]
+struct SeEvidence {
+    /// Base64 measurement of the attestation response.
+    measurement: String,
+    /// Hex configuration UID of the guest.
+    config_uid: String,
+    /// Base64 user data of the attestation response, which binds the nonce
+    /// and the TEE public key.
+    user_data: String,
+    /// Base64 additional data of the attestation response, if any.
+    #[serde(default)]
+    additional_data: String,
+    /// Hex tag of the SE header of the guest image.
+    image_tag: String,
+    /// PEM HKD the attestation request was encrypted for.
+    host_key_document: String,
+    /// Base64 measurement key of the attestation request, encrypted to
+    /// the measurement key of the verifier.
+    encrypted_measurement_key: String,
+}
+
+/// The SE header of a trusted guest image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Image {
+    pld: Vec<u8>,
+    ald: Vec<u8>,
+    tld: Vec<u8>,
+    tag: Vec<u8>,
+}
+
+impl Image {
+    fn new(image: &SeImage) -> Result<Self> {
+        let decode = |name: &str, value: &str, size: usize| -> Result<Vec<u8>> {
+            let bytes = hex::decode(value).with_context(|| format!("{name} is not hex"))?;
+            if bytes.len() != size {
+                bail!("{name} is {} bytes, expected {size}", bytes.len());
+            }
+            Ok(bytes)
+        };
+        let tag = decode("tag", &image.tag, TAG_SIZE)?;
+        let decode = |name: &str, value: &str| {
+            decode(name, value, DIGEST_SIZE).with_context(|| format!("Image {}", hex::encode(&tag)))
+        };
+        Ok(Self {
+            pld: decode("pld", &image.pld)?,
+            ald: decode("ald", &image.ald)?,
+            tld: decode("tld", &image.tld)?,
+            tag,
+        })
+    }
+}
+
+/// The SE verifier.
+pub struct Se {
+    /// DER chains of the host key signing keys, the signing key first.
+    signing_keys: Vec<Vec<Vec<u8>>>,
+    trust_anchors: Arc<Vec<TrustAnchor>>,
+    /// DER HKDs of the trusted hosts, any host if empty.
+    host_key_documents: Vec<Vec<u8>>,
+    measurement_key: PKey<Private>,
+    images: Vec<Image>,
+    report_data: ReportDataMode,
+    crypto: Box<dyn CryptoBackend + Send + Sync>,
+}
+
+fn read(path: &std::path::Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("read {}", path.display()))
+}
+
+/// The SE verifier of `config`, comparing the user data of attestation
+/// responses as `report_data` says. Register it for the TEE type of SE
+/// evidence with [`registry::VerifierRegistry::register`].
+pub fn factory(
+    config: &VerifierConfig,
+    report_data: ReportDataMode,
+) -> Result<Box<dyn Verifier + Send + Sync>> {
+    let se = &config.se;
+    let measurement_key = se
+        .measurement_key
+        .as_ref()
+        .ok_or_else(|| anyhow!("No se.measurement_key in the config"))?;
+    let measurement_key = PKey::private_key_from_pem(&read(measurement_key)?)
+        .with_context(|| format!("Malformed measurement key {}", measurement_key.display()))?;
+    let signing_keys = se
+        .signing_keys
+        .iter()
+        .map(|path| {
+            let chain = anchors::pem_certificates(&read(path)?)
+                .with_context(|| format!("load {}", path.display()))?;
+            if chain.is_empty() {
+                bail!("No certificate in {}", path.display());
+            }
+            Ok(chain)
+        })
+        .collect::<Result<_>>()?;
+    let host_key_documents = se
+        .host_key_documents
+        .iter()
+        .map(|path| single_certificate(&read(path)?))
+        .collect::<Result<_>>()?;
+    let images = se.images.iter().map(Image::new).collect::<Result<_>>()?;
+    Ok(Box::new(Se {
+        signing_keys,
+        trust_anchors: anchors::anchors(&config.trust_anchors.ibm)?,
+        host_key_documents,
+        measurement_key,
+        images,
+        report_data,
+        crypto: config.crypto_backend.to_backend()?,
+    }))
+}
+
+/// The DER certificate of a PEM document of one certificate.
+fn single_certificate(pem: &[u8]) -> Result<Vec<u8>> {
+    match anchors::pem_certificates(pem)?.as_slice() {
+        [certificate] => Ok(certificate.clone()),
+        certificates => bail!("Expected one certificate, got {}", certificates.len()),
+    }
+}
+
+fn base64(name: &str, value: &str) -> Result<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD
+        .decode(value)
+        .with_context(|| format!("{name} is not base64"))
+}
+
+/// The value of the entry `nid` of the subject of `certificate`, if any.
+fn subject_entry(certificate: &X509, nid: Nid) -> Option<String> {
+    certificate
+        .subject_name()
+        .entries_by_nid(nid)
+        .next()
+        .and_then(|entry| String::from_utf8(entry.data().as_slice().to_vec()).ok())
+}
+
+impl Se {
+    /// Verify that `hkd` is a trusted HKD signed by a host key signing key,
+    /// and return the [`TRUST_ANCHOR_CLAIM`] of the chain of the
+    /// signing key.
+    async fn verify_host_key_document(&self, hkd: &[u8]) -> Result<String> {
+        if !self.host_key_documents.is_empty()
+            && !self.host_key_documents.iter().any(|trusted| trusted == hkd)
+        {
+            bail!(
+                "Host key document {} is not a trusted one",
+                anchors::fingerprint(hkd)
+            );
+        }
+        let certificate = X509::from_der(hkd).context("Malformed host key document")?;
+        if certificate.not_after() < openssl::asn1::Asn1Time::days_from_now(0)? {
+            bail!("Host key document expired on {}", certificate.not_after());
+        }
+        let mut errors = Vec::new();
+        for chain in &self.signing_keys {
+            match self.verify_signing_key_chain(hkd, chain).await {
+                Ok(anchor) => return Ok(anchor),
+                Err(e) => errors.push(format!("{e:#}")),
+            }
+        }
+        bail!(
+            "Host key document is not signed by a host key signing key: {}",
+            errors.join("; ")
+        )
+    }
+
+    /// Verify that the signing key of `chain` signed `hkd`, and that
+    /// `chain` ends at a trust anchor.
+    async fn verify_signing_key_chain(&self, hkd: &[u8], chain: &[Vec<u8>]) -> Result<String> {
+        let signing_key = X509::from_der(&chain[0]).context("Malformed host key signing key")?;
+        if subject_entry(&signing_key, Nid::ORGANIZATIONNAME).as_deref()
+            != Some(SIGNING_KEY_ORGANIZATION)
+            || subject_entry(&signing_key, Nid::ORGANIZATIONALUNITNAME).as_deref()
+                != Some(SIGNING_KEY_UNIT)
+        {
+            bail!("Not an IBM Z host key signing key");
+        }
+        self.crypto.verify_certificate(hkd, &chain[0]).await?;
+        for link in chain.windows(2) {
+            self.crypto.verify_certificate(&link[0], &link[1]).await?;
+        }
+        anchors::check_root(&chain[chain.len() - 1], &self.trust_anchors)
+    }
+
+    /// The measurement key of the attestation request, decrypted.
+    fn measurement_key(&self, encrypted: &[u8]) -> Result<Vec<u8>> {
+        let mut decrypter = Decrypter::new(&self.measurement_key)?;
+        decrypter.set_rsa_padding(Padding::PKCS1_OAEP)?;
+        decrypter.set_rsa_oaep_md(MessageDigest::sha256())?;
+        decrypter.set_rsa_mgf1_md(MessageDigest::sha256())?;
+        let mut key = vec![0; decrypter.decrypt_len(encrypted)?];
+        let size = decrypter
+            .decrypt(encrypted, &mut key)
+            .context("Cannot decrypt the measurement key")?;
+        key.truncate(size);
+        if key.len() != MEASUREMENT_SIZE {
+            bail!(
+                "Measurement key is {} bytes, expected {MEASUREMENT_SIZE}",
+                key.len()
+            );
+        }
+        Ok(key)
+    }
+}
+
+/// The measurement of the attestation response of a guest of `image`.
+fn measurement(
+    key: &[u8],
+    image: &Image,
+    config_uid: &[u8],
+    user_data: &[u8],
+    additional_data: &[u8],
+) -> Result<Vec<u8>> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha512(), &key)?;
+    for item in [
+        &image.pld,
+        &image.ald,
+        &image.tld,
+        &image.tag,
+        config_uid,
+        user_data,
+        additional_data,
+    ] {
+        signer.update(item)?;
+    }
+    Ok(signer.sign_to_vec()?)
+}
+
+#[async_trait]
+impl Verifier for Se {
+    async fn evaluate(
+        &self,
+        nonce: String,
+        attestation: &Attestation,
+    ) -> Result<TeeEvidenceParsedClaim> {
+        let evidence = serde_json::from_str::<SeEvidence>(&attestation.tee_evidence)
+            .context("Deserialize SE evidence failed.")?;
+        let measurement_value = base64("measurement", &evidence.measurement)?;
+        let config_uid = hex::decode(&evidence.config_uid).context("config_uid is not hex")?;
+        let user_data = base64("user_data", &evidence.user_data)?;
+        let additional_data = base64("additional_data", &evidence.additional_data)?;
+        let image_tag = hex::decode(&evidence.image_tag).context("image_tag is not hex")?;
+        let encrypted_key = base64(
+            "encrypted_measurement_key",
+            &evidence.encrypted_measurement_key,
+        )?;
+        if measurement_value.len() != MEASUREMENT_SIZE {
+            bail!("Measurement is not an HMAC-SHA512");
+        }
+        if config_uid.len() != CONFIG_UID_SIZE {
+            bail!("config_uid is not {CONFIG_UID_SIZE} bytes");
+        }
+        if user_data.len() > MAX_USER_DATA {
+            bail!("user_data is longer than {MAX_USER_DATA} bytes");
+        }
+        let hkd = single_certificate(evidence.host_key_document.as_bytes())
+            .context("Malformed host key document")?;
+
+        let trust_anchor = self.verify_host_key_document(&hkd).await?;
+        let image = self
+            .images
+            .iter()
+            .find(|image| image.tag == image_tag)
+            .ok_or_else(|| anyhow!("Unknown SE image tag {}", evidence.image_tag))?;
+        let key = self.measurement_key(&encrypted_key)?;
+        let expected = measurement(&key, image, &config_uid, &user_data, &additional_data)?;
+        if !openssl::memcmp::eq(&expected, &measurement_value) {
+            bail!("SE measurement mismatch");
+        }
+        if !self.report_data.matches(&nonce, attestation, &user_data) {
+            bail!("User data does not bind HASH(nonce||pubkey)");
+        }
+
+        let mut claims = ClaimsBuilder::new()
+            .hex("image_tag", &image.tag)
+            .hex("image_pld", &image.pld)
+            .hex("config_uid", config_uid)
+            .hex("user_data", &user_data)
+            .hex("additional_data", additional_data)
+            .claim("host_key_document", anchors::fingerprint(&hkd))
+            .claim(TRUST_ANCHOR_CLAIM, trust_anchor)
+            .build();
+        self.report_data.add_claim(&mut claims);
+        Ok(claims)
+    }
+
+    fn signed_evidence(&self, attestation: &Attestation) -> Result<Vec<u8>> {
+        let evidence = serde_json::from_str::<SeEvidence>(&attestation.tee_evidence)
+            .context("Deserialize SE evidence failed")?;
+        // The measurement is the HMAC of the attestation response.
+        base64("measurement", &evidence.measurement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verifier::conformance;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::encrypt::Encrypter;
+    use openssl::rsa::Rsa;
+    use openssl::x509::{X509Builder, X509NameBuilder};
+    use serde_json::json;
+    use sha2::{Digest, Sha384};
+
+    fn certificate(
+        organization: &str,
+        unit: &str,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+    ) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::ORGANIZATIONNAME, organization)
+            .unwrap();
+        name.append_entry_by_nid(Nid::ORGANIZATIONALUNITNAME, unit)
+            .unwrap();
+        let name = name.build();
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder
+            .set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(30).unwrap())
+            .unwrap();
+        let (issuer_name, signing_key) = match issuer {
+            Some((issuer, issuer_key)) => (issuer.subject_name(), issuer_key),
+            None => (name.as_ref(), key),
+        };
+        builder.set_issuer_name(issuer_name).unwrap();
+        builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    fn key() -> PKey<Private> {
+        PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap()
+    }
+
+    fn pem(certificate: &X509) -> String {
+        String::from_utf8(certificate.to_pem().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_se() {
+        let root_key = key();
+        let root = certificate("DigiCert Inc", "www.digicert.com", &root_key, None);
+        let signing_key_key = key();
+        let signing_key = certificate(
+            SIGNING_KEY_ORGANIZATION,
+            SIGNING_KEY_UNIT,
+            &signing_key_key,
+            Some((&root, &root_key)),
+        );
+        let hkd = certificate(
+            SIGNING_KEY_ORGANIZATION,
+            "IBM Z Host Key",
+            &key(),
+            Some((&signing_key, &signing_key_key)),
+        );
+        let image = Image {
+            pld: vec![1; DIGEST_SIZE],
+            ald: vec![2; DIGEST_SIZE],
+            tld: vec![3; DIGEST_SIZE],
+            tag: vec![4; TAG_SIZE],
+        };
+        let verifier_key = key();
+        let se = Se {
+            signing_keys: vec![vec![signing_key.to_der().unwrap(), root.to_der().unwrap()]],
+            trust_anchors: Arc::new(vec![TrustAnchor {
+                certs: vec![root.to_der().unwrap()],
+            }]),
+            host_key_documents: Vec::new(),
+            measurement_key: verifier_key.clone(),
+            images: vec![image.clone()],
+            report_data: ReportDataMode::default(),
+            crypto: CryptoBackendType::OpenSSL.to_backend().unwrap(),
+        };
+
+        let tee_pubkey = conformance::dummy_tee_pubkey();
+        let mut user_data = Sha384::new()
+            .chain_update("nonce")
+            .chain_update(&tee_pubkey.k_mod)
+            .chain_update(&tee_pubkey.k_exp)
+            .finalize()
+            .to_vec();
+        user_data.resize(64, 0);
+        let measurement_key = [5; MEASUREMENT_SIZE];
+        let mut encrypter = Encrypter::new(&verifier_key).unwrap();
+        encrypter.set_rsa_padding(Padding::PKCS1_OAEP).unwrap();
+        encrypter.set_rsa_oaep_md(MessageDigest::sha256()).unwrap();
+        encrypter.set_rsa_mgf1_md(MessageDigest::sha256()).unwrap();
+        let mut encrypted_key = vec![0; encrypter.encrypt_len(&measurement_key).unwrap()];
+        let size = encrypter
+            .encrypt(&measurement_key, &mut encrypted_key)
+            .unwrap();
+        encrypted_key.truncate(size);
+        let config_uid = [6; CONFIG_UID_SIZE];
+        let value = measurement(&measurement_key, &image, &config_uid, &user_data, b"").unwrap();
+        let base64 = |bytes: &[u8]| base64::engine::general_purpose::STANDARD.encode(bytes);
+        let mut evidence = json!({
+            "measurement": base64(&value),
+            "config_uid": hex::encode(config_uid),
+            "user_data": base64(&user_data),
+            "image_tag": hex::encode(&image.tag),
+            "host_key_document": pem(&hkd),
+            "encrypted_measurement_key": base64(&encrypted_key),
+        });
+        let attestation = |evidence: &serde_json::Value| Attestation {
+            tee_pubkey: tee_pubkey.clone(),
+            tee_evidence: evidence.to_string(),
+        };
+
+        let claims = se
+            .evaluate("nonce".to_string(), &attestation(&evidence))
+            .await
+            .unwrap();
+        assert_eq!(claims["image_tag"], hex::encode(&image.tag));
+        assert_eq!(claims["config_uid"], hex::encode(config_uid));
+        assert_eq!(
+            claims["host_key_document"],
+            anchors::fingerprint(&hkd.to_der().unwrap())
+        );
+        assert_eq!(
+            claims["trust_anchor"],
+            anchors::fingerprint(&root.to_der().unwrap())
+        );
+
+        let e = se
+            .evaluate("other".to_string(), &attestation(&evidence))
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("User data"));
+
+        evidence["additional_data"] = base64(b"tampered").into();
+        let e = se
+            .evaluate("nonce".to_string(), &attestation(&evidence))
+            .await
+            .unwrap_err();
+        assert_eq!(e.to_string(), "SE measurement mismatch");
+
+        evidence["image_tag"] = hex::encode([7; TAG_SIZE]).into();
+        let e = se
+            .evaluate("nonce".to_string(), &attestation(&evidence))
+            .await
+            .unwrap_err();
+        assert!(e.to_string().starts_with("Unknown SE image tag"));
+
+        // An HKD that the signing key did not sign.
+        let rogue_key = key();
+        let rogue = certificate(SIGNING_KEY_ORGANIZATION, SIGNING_KEY_UNIT, &rogue_key, None);
+        evidence["host_key_document"] = pem(&certificate(
+            SIGNING_KEY_ORGANIZATION,
+            "IBM Z Host Key",
+            &key(),
+            Some((&rogue, &rogue_key)),
+        ))
+        .into();
+        let e = se
+            .evaluate("nonce".to_string(), &attestation(&evidence))
+            .await
+            .unwrap_err();
+        assert!(e
+            .to_string()
+            .starts_with("Host key document is not signed by a host key signing key"));
+    }
+
+    #[test]
+    fn test_image() {
+        let image = SeImage {
+            pld: "01".repeat(DIGEST_SIZE),
+            ald: "02".repeat(DIGEST_SIZE),
+            tld: "03".repeat(DIGEST_SIZE),
+            tag: "04".repeat(TAG_SIZE),
+        };
+        assert_eq!(Image::new(&image).unwrap().tag, [4; TAG_SIZE]);
+        let image = SeImage {
+            pld: "01".repeat(DIGEST_SIZE - 1),
+            ..image
+        };
+        assert_eq!(
+            format!("{:#}", Image::new(&image).unwrap_err()),
+            format!(
+                "Image {}: pld is 63 bytes, expected 64",
+                "04".repeat(TAG_SIZE)
+            )
+        );
+    }
+}
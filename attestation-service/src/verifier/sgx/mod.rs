@@ -5,6 +5,7 @@
 
 use std::{
     mem,
+    sync::Arc,
     time::{Duration, SystemTime},
 };
 
@@ -18,19 +19,26 @@ use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use sgx_dcap_quoteverify_rs::{
     sgx_ql_qv_result_t, sgx_ql_qv_supplemental_t, tee_get_supplemental_data_version_and_size,
-    tee_qv_get_collateral, tee_supp_data_descriptor_t, tee_verify_quote,
+    tee_supp_data_descriptor_t, tee_verify_quote,
 };
-use sha2::{Digest, Sha384};
 
 use self::types::sgx_quote3_t;
 
-use super::Verifier;
+use super::anchors::{TrustAnchor, TRUST_ANCHOR_CLAIM};
+use super::anomalies::Anomalies;
+use super::collateral::{pck, Provider, QuoteTee, QveCollateral};
+use super::report_data::ReportDataMode;
+use super::tcb::{self, Tcb};
+use super::{detail, diagnostics, expiry, platform, warnings, Verifier, VersionRange};
 
 #[allow(non_camel_case_types)]
 mod types;
 
 pub const QUOTE_SIZE: usize = 436;
 
+/// The enclave is in debug mode.
+const SGX_FLAGS_DEBUG: u64 = 0x0000_0000_0000_0002;
+
 #[derive(Debug, Serialize, Deserialize)]
 struct SgxEvidence {
     // Base64 encoded SGX quote.
@@ -38,7 +46,19 @@ struct SgxEvidence {
 }
 
 #[derive(Debug, Default)]
-pub struct SgxVerifier {}
+pub struct SgxVerifier {
+    pub versions: VersionRange,
+    /// Comparison of the report data with the nonce and TEE public key.
+    pub report_data: ReportDataMode,
+    /// DCAP collateral of the quotes.
+    pub collateral: Arc<Provider>,
+    /// Reject quotes with structural anomalies, instead of a warning, see
+    /// [`super::anomalies`].
+    pub strict_quote_parsing: bool,
+    /// Intel root CAs that the PCK certificate chain must end at, if any,
+    /// see [`super::anchors`].
+    pub trust_anchors: Arc<Vec<TrustAnchor>>,
+}
 
 #[async_trait]
 impl Verifier for SgxVerifier {
@@ -50,45 +70,122 @@ impl Verifier for SgxVerifier {
         let tee_evidence = serde_json::from_str::<SgxEvidence>(&attestation.tee_evidence)
             .context("Deserialize Quote failed.")?;
 
-        let mut hasher = Sha384::new();
-        hasher.update(&nonce);
-        hasher.update(&attestation.tee_pubkey.k_mod);
-        hasher.update(&attestation.tee_pubkey.k_exp);
-        let mut hash_of_nonce_pubkey = hasher.finalize().to_vec();
-        hash_of_nonce_pubkey.extend([0; 16]);
-
         debug!("TEE-Evidence<Sgx Occlum>: {:?}", &tee_evidence);
 
-        verify_evidence(hash_of_nonce_pubkey, tee_evidence).await
+        verify_evidence(self, &nonce, attestation, tee_evidence).await
+    }
+
+    fn signed_evidence(&self, attestation: &Attestation) -> Result<Vec<u8>> {
+        let tee_evidence = serde_json::from_str::<SgxEvidence>(&attestation.tee_evidence)
+            .context("Deserialize Quote failed.")?;
+        Ok(base64::engine::general_purpose::STANDARD.decode(tee_evidence.quote)?)
     }
 }
 
 pub fn parse_sgx_quote(quote: &[u8]) -> Result<sgx_quote3_t> {
+    if quote.len() < QUOTE_SIZE {
+        bail!(
+            "SGX quote is too short: {} bytes, expected at least {QUOTE_SIZE}",
+            quote.len()
+        );
+    }
     let quote_body = &quote[..QUOTE_SIZE];
     quote_body
         .pread::<sgx_quote3_t>(0)
         .map_err(|e| anyhow!("Parse SGX quote failed: {:?}", e))
 }
 
+/// Look for structural anomalies in the parsed `quote`, of `quote_bin`,
+/// see [`super::anomalies`].
+fn check_anomalies(quote_bin: &[u8], quote: &sgx_quote3_t, strict: bool) -> Result<()> {
+    let (header, body) = (&quote.header, &quote.report_body);
+    let mut anomalies = Anomalies::new("SGX quote");
+    anomalies.integer("version", header.version.into(), 2, &[3, 4]);
+    anomalies.integer("att_key_type", header.att_key_type.into(), 2, &[2, 3]);
+    anomalies.reserved("reserved1", &body.reserved1);
+    anomalies.reserved("reserved2", &body.reserved2);
+    anomalies.reserved("reserved3", &body.reserved3);
+    anomalies.reserved("reserved4", &body.reserved4);
+    anomalies.vendor_id(&header.vendor_id);
+    anomalies.length(
+        "signature_data_len",
+        quote.signature_data_len as usize,
+        quote_bin.len() - QUOTE_SIZE,
+    );
+    anomalies.check(strict)
+}
+
 async fn verify_evidence(
-    hash_of_nonce_pubkey: Vec<u8>,
+    verifier: &SgxVerifier,
+    nonce: &str,
+    attestation: &Attestation,
     evidence: SgxEvidence,
 ) -> Result<TeeEvidenceParsedClaim> {
     let quote_bin = base64::engine::general_purpose::STANDARD.decode(evidence.quote.clone())?;
 
-    ecdsa_quote_verification(&quote_bin)
-        .await
-        .context("Evidence's identity verification error.")?;
-
-    let quote = parse_sgx_quote(&quote_bin)?;
-    if quote.report_body.report_data.d.to_vec() != hash_of_nonce_pubkey {
+    let (quote, tcb, trust_anchor) = verify_quote(
+        &quote_bin,
+        verifier.versions,
+        &verifier.collateral,
+        verifier.strict_quote_parsing,
+        &verifier.trust_anchors,
+    )
+    .await?;
+    if !verifier
+        .report_data
+        .matches(nonce, attestation, &quote.report_body.report_data.d)
+    {
         bail!("HASH(nonce||pubkey) is different from that in SGX Quote");
     }
 
-    generate_parsed_claims(quote)
+    let mut claims = generate_parsed_claims(quote)?;
+    tcb.add_claims(&mut claims)?;
+    claims[TRUST_ANCHOR_CLAIM] = trust_anchor.into();
+    if let Ok(key) = pck::collateral_key(QuoteTee::Sgx, &quote_bin) {
+        claims[platform::FMSPC_CLAIM] = key.fmspc.into();
+    }
+    if detail::forensic() {
+        match pck::forensic_claims(&quote_bin) {
+            Ok(forensic) => claims[detail::FORENSIC_CLAIM] = forensic,
+            Err(e) => warnings::raise(format!("No forensic detail of the SGX quote: {e:#}")),
+        }
+    }
+    verifier.report_data.add_claim(&mut claims);
+    Ok(claims)
+}
+
+/// Parse an SGX quote of an accepted version and verify its signature, and
+/// return it with the TCB of the platform and the trust anchor of its PCK
+/// certificate chain. The caller checks what the report data of the quote
+/// is bound to.
+pub(crate) async fn verify_quote(
+    quote_bin: &[u8],
+    versions: VersionRange,
+    collateral: &Provider,
+    strict_quote_parsing: bool,
+    trusted: &[TrustAnchor],
+) -> Result<(sgx_quote3_t, Tcb, String)> {
+    let quote = parse_sgx_quote(quote_bin)?;
+    versions.check("SGX quote", quote.header.version.into())?;
+    check_anomalies(quote_bin, &quote, strict_quote_parsing)?;
+
+    let tcb = ecdsa_quote_verification(quote_bin, collateral)
+        .await
+        .context("Evidence's identity verification error.")?;
+    let trust_anchor = pck::trust_anchor(quote_bin, trusted)?;
+
+    Ok((quote, tcb, trust_anchor))
 }
 
-async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
+async fn ecdsa_quote_verification(quote: &[u8], collateral: &Provider) -> Result<Tcb> {
+    // The collateral fetched by the AS, if it fetches it, else the quote
+    // provider library fetches it.
+    let collateral = collateral
+        .collateral(QuoteTee::Sgx, quote)
+        .await?
+        .map(|collateral| collateral.to_qve())
+        .transpose()?;
+
     let mut supp_data: sgx_ql_qv_supplemental_t = Default::default();
     let mut supp_data_desc = tee_supp_data_descriptor_t {
         major_version: 0,
@@ -117,19 +214,7 @@ async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
         ),
     }
 
-    // get collateral
-    let _collateral = match tee_qv_get_collateral(quote) {
-        std::result::Result::Ok(c) => {
-            debug!("tee_qv_get_collateral successfully returned.");
-            Some(c)
-        }
-        Err(e) => {
-            warn!("tee_qv_get_collateral failed: {:#04x}", e as u32);
-            None
-        }
-    };
-
-    let p_collateral: Option<&[u8]> = None;
+    let p_collateral = collateral.as_ref().map(QveCollateral::as_bytes);
 
     // set current time. This is only for sample purposes, in production mode a trusted time should be used.
     //
@@ -149,6 +234,22 @@ async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
             .map_err(|e| anyhow!("tee_verify_quote failed: {:#04x}", e as u32))?;
 
     debug!("tee_verify_quote successfully returned.");
+    diagnostics::record_dcap_collateral(
+        "sgx",
+        (supp_data_desc.data_size != 0).then_some(&supp_data),
+        collateral_expiration_status,
+        quote_verification_result,
+    );
+    let mut tcb_date_tag = 0;
+    if supp_data_desc.data_size != 0 {
+        warnings::check_collateral_expiry(
+            "SGX quote",
+            supp_data.earliest_expiration_date,
+            current_time,
+        );
+        expiry::record("sgx.collateral", supp_data.earliest_expiration_date);
+        tcb_date_tag = supp_data.tcb_level_date_tag;
+    }
 
     // check verification result
     match quote_verification_result {
@@ -158,7 +259,7 @@ async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
             if collateral_expiration_status == 0 {
                 debug!("Verification completed successfully.");
             } else {
-                warn!("Verification completed, but collateral is out of date based on 'expiration_check_date' you provided.");
+                warnings::raise("The SGX quote collateral is out of date");
             }
         }
         sgx_ql_qv_result_t::SGX_QL_QV_RESULT_CONFIG_NEEDED
@@ -166,10 +267,10 @@ async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
         | sgx_ql_qv_result_t::SGX_QL_QV_RESULT_OUT_OF_DATE_CONFIG_NEEDED
         | sgx_ql_qv_result_t::SGX_QL_QV_RESULT_SW_HARDENING_NEEDED
         | sgx_ql_qv_result_t::SGX_QL_QV_RESULT_CONFIG_AND_SW_HARDENING_NEEDED => {
-            warn!(
-                "Verification completed with Non-terminal result: {:x}",
+            warnings::raise(format!(
+                "The SGX quote verification completed with non-terminal result: {:x}",
                 quote_verification_result as u32
-            );
+            ));
         }
         _ => {
             bail!(
@@ -179,10 +280,10 @@ async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
         }
     }
 
-    Ok(())
+    Ok(tcb::from_dcap(quote_verification_result, tcb_date_tag))
 }
 
-fn generate_parsed_claims(quote: sgx_quote3_t) -> Result<TeeEvidenceParsedClaim> {
+pub(crate) fn generate_parsed_claims(quote: sgx_quote3_t) -> Result<TeeEvidenceParsedClaim> {
     // TODO: Add more claims
     // related issue: https://github.com/confidential-containers/enclave-cc/issues/121
     let mut claim_map = Map::new();
@@ -195,8 +296,24 @@ fn generate_parsed_claims(quote: sgx_quote3_t) -> Result<TeeEvidenceParsedClaim>
         "mr-enclave".to_string(),
         Value::String(hex::encode(quote.report_body.mr_enclave.m)),
     );
+    claim_map.insert(
+        "isv-prod-id".to_string(),
+        Value::from(quote.report_body.isv_prod_id),
+    );
+    claim_map.insert(
+        "isv-svn".to_string(),
+        Value::from(quote.report_body.isv_svn),
+    );
+    claim_map.insert(
+        "config-svn".to_string(),
+        Value::from(quote.report_body.config_svn),
+    );
+    claim_map.insert(
+        "debug".to_string(),
+        Value::Bool(quote.report_body.attributes.flags & SGX_FLAGS_DEBUG != 0),
+    );
 
-    Ok(Value::Object(claim_map) as TeeEvidenceParsedClaim)
+    Ok(TeeEvidenceParsedClaim::from(Value::Object(claim_map)))
 }
 
 #[cfg(test)]
@@ -204,6 +321,9 @@ mod tests {
     use rstest::rstest;
 
     use super::*;
+    use crate::verifier::conformance::{self, Conformance, DebugClaim, KnownClaims};
+    use kbs_types::Tee;
+    use serde_json::json;
     use std::fs;
 
     #[rstest]
@@ -217,13 +337,54 @@ mod tests {
         let _ = fs::write("../test_data/parse_sgx_quote_output.txt", parsed_quote);
     }
 
+    #[test]
+    fn test_check_anomalies() {
+        let quote_bin = fs::read("../test_data/occlum_quote.dat").unwrap();
+        let quote = parse_sgx_quote(&quote_bin).unwrap();
+        check_anomalies(&quote_bin, &quote, true).unwrap();
+
+        // The version, big-endian.
+        let mut tampered = quote_bin.clone();
+        tampered[..2].copy_from_slice(&[0, 3]);
+        let quote = parse_sgx_quote(&tampered).unwrap();
+        let e = check_anomalies(&tampered, &quote, true).unwrap_err();
+        assert!(e.to_string().contains("version is 0x300, a big-endian 3"));
+        check_anomalies(&tampered, &quote, false).unwrap();
+    }
+
     #[ignore]
     #[rstest]
     #[tokio::test]
     #[case("../test_data/occlum_quote.dat")]
     async fn test_verify_sgx_quote(#[case] quote_dir: &str) {
         let quote_bin = fs::read(quote_dir).unwrap();
-        let res = ecdsa_quote_verification(quote_bin.as_slice()).await;
+        let res = ecdsa_quote_verification(quote_bin.as_slice(), &Provider::default()).await;
         assert!(res.is_ok());
     }
+
+    #[tokio::test]
+    async fn conformance() {
+        let quote_bin = fs::read("../test_data/occlum_quote.dat").unwrap();
+        let claims = generate_parsed_claims(parse_sgx_quote(&quote_bin).unwrap()).unwrap();
+
+        let b64 = |data: &[u8]| base64::engine::general_purpose::STANDARD.encode(data);
+        conformance::run(Conformance {
+            tee: Tee::Sgx,
+            verifier: SgxVerifier::default(),
+            malformed: vec![
+                json!({ "quote": b64(&quote_bin[..100]) }).to_string(),
+                json!({ "quote": b64(&quote_bin) }).to_string(),
+            ],
+            evidence: None,
+            claims: Some(KnownClaims {
+                claims,
+                debug: true,
+            }),
+            debug_claim: Some(DebugClaim {
+                name: "sgx.debug",
+                is_debug: Value::as_bool,
+            }),
+        })
+        .await;
+    }
 }
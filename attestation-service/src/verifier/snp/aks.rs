@@ -0,0 +1,475 @@
+//! SEV-SNP evidence of confidential containers on AKS.
+//!
+//! On AKS, the paravisor (HCL) wraps the SNP report in an envelope with
+//! runtime data, see [`crate::verifier::hcl`].
+//!
+//! The evidence can carry the UVM endorsements, a COSE_Sign1 document
+//! signed by Microsoft with the launch measurement and SVN of the utility
+//! VM image. They are checked against the measurement of the report, and
+//! exposed as `uvm_endorsements` claims next to the `runtime_data` ones.
+//! Their certificate chain must end at one of the `microsoft` trust anchors
+//! of the AS config, see [`crate::verifier::anchors`], and evidence with
+//! UVM endorsements is rejected without one. The `signer` claim is the
+//! SHA-256 of the root certificate of the endorsements.
+
+use super::SnpEvidence;
+use crate::verifier::anchors::{self, TrustAnchor};
+use crate::verifier::crypto::CryptoBackend;
+use crate::verifier::hcl::{self, ReportType};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use cbor_diag::{ByteString, DataItem, IntegerWidth, Tag, TextString};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sev::firmware::guest::AttestationReport;
+use sev::firmware::host::CertTableEntry;
+use sha2::{Digest, Sha256};
+
+/// COSE algorithms of UVM endorsements.
+const COSE_ES384: i64 = -35;
+const COSE_PS384: i64 = -38;
+
+/// COSE header of the certificate chain, leaf first.
+const COSE_X5CHAIN: u64 = 33;
+
+const COSE_SIGN1_TAG: u64 = 18;
+
+/// AKS evidence, told apart from plain SNP evidence by its `hcl_report`.
+#[derive(Deserialize)]
+pub(crate) struct AksEvidence {
+    /// HCL report, base64 encoded.
+    hcl_report: String,
+    cert_chain: Vec<CertTableEntry>,
+    /// COSE_Sign1 UVM endorsements, base64 encoded.
+    #[serde(default)]
+    uvm_endorsements: Option<String>,
+}
+
+/// The HCL envelope and UVM endorsements of AKS evidence.
+pub(crate) struct Envelope {
+    runtime_data: Vec<u8>,
+    uvm_endorsements: Option<UvmEndorsements>,
+}
+
+/// Parse AKS evidence into the SNP evidence it wraps and its envelope, and
+/// check that the runtime data and UVM endorsements are bound to the report.
+pub(crate) fn parse(evidence: AksEvidence) -> Result<(SnpEvidence, Envelope)> {
+    let hcl_report = base64::engine::general_purpose::STANDARD
+        .decode(evidence.hcl_report)
+        .context("Malformed HCL report")?;
+    let (attestation_report, raw_report, runtime_data) = parse_hcl_report(&hcl_report)?;
+
+    let uvm_endorsements = evidence
+        .uvm_endorsements
+        .map(|uvm_endorsements| -> Result<_> {
+            let cose = base64::engine::general_purpose::STANDARD
+                .decode(uvm_endorsements)
+                .context("Malformed UVM endorsements")?;
+            let uvm_endorsements = UvmEndorsements::parse(&cose)?;
+            uvm_endorsements.check_measurement(&attestation_report)?;
+            Ok(uvm_endorsements)
+        })
+        .transpose()?;
+
+    Ok((
+        SnpEvidence {
+            attestation_report,
+            cert_chain: evidence.cert_chain,
+            raw_report,
+        },
+        Envelope {
+            runtime_data,
+            uvm_endorsements,
+        },
+    ))
+}
+
+/// Split an HCL report into its SNP report, parsed and raw, and runtime
+/// data, and check that the report data is the hash of the runtime data.
+fn parse_hcl_report(hcl_report: &[u8]) -> Result<(AttestationReport, Vec<u8>, Vec<u8>)> {
+    let hcl_report = hcl::parse(hcl_report, ReportType::Snp)?;
+    let attestation_report: AttestationReport =
+        bincode::deserialize(hcl_report.hw_report).context("Malformed SNP report in HCL report")?;
+    hcl_report.check_report_data(&attestation_report.report_data)?;
+
+    Ok((
+        attestation_report,
+        hcl_report.hw_report.to_vec(),
+        hcl_report.runtime_data,
+    ))
+}
+
+impl Envelope {
+    /// Check that the user data of the runtime data binds the nonce, as
+    /// `binds_nonce` tells.
+    pub fn check_user_data(&self, binds_nonce: impl FnOnce(&[u8]) -> bool) -> Result<()> {
+        hcl::check_user_data(&hcl::runtime_data(&self.runtime_data)?, binds_nonce)
+    }
+
+    /// Verify the signature of the UVM endorsements, if any, up to one of
+    /// the `trusted` roots.
+    pub async fn verify(
+        &self,
+        crypto: &(dyn CryptoBackend + Send + Sync),
+        trusted: &[TrustAnchor],
+    ) -> Result<()> {
+        match &self.uvm_endorsements {
+            Some(uvm_endorsements) => uvm_endorsements.verify(crypto, trusted).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Add the runtime data and UVM endorsements claims to the SNP claims.
+    pub fn add_claims(&self, claims: &mut Value) -> Result<()> {
+        claims["runtime_data"] = hcl::runtime_data(&self.runtime_data)?;
+        if let Some(uvm_endorsements) = &self.uvm_endorsements {
+            claims["uvm_endorsements"] = uvm_endorsements.claims();
+        }
+        Ok(())
+    }
+}
+
+/// UVM endorsements, a COSE_Sign1 document.
+struct UvmEndorsements {
+    protected: Vec<u8>,
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+    alg: i64,
+    /// DER certificates, leaf first.
+    x5chain: Vec<Vec<u8>>,
+    issuer: Option<String>,
+    feed: Option<String>,
+    launch_measurement: String,
+    svn: String,
+}
+
+fn byte_string(item: &DataItem) -> Option<&[u8]> {
+    match item {
+        DataItem::ByteString(ByteString { data, .. }) => Some(data),
+        _ => None,
+    }
+}
+
+fn text_string(item: &DataItem) -> Option<&str> {
+    match item {
+        DataItem::TextString(TextString { data, .. }) => Some(data),
+        _ => None,
+    }
+}
+
+impl UvmEndorsements {
+    fn parse(cose: &[u8]) -> Result<Self> {
+        let malformed = || anyhow!("Malformed UVM endorsements");
+        let item = cbor_diag::parse_bytes(cose).map_err(|_| malformed())?;
+        let item = match item {
+            DataItem::Tag {
+                tag: Tag(COSE_SIGN1_TAG),
+                value,
+                ..
+            } => *value,
+            item => item,
+        };
+        let DataItem::Array { data, .. } = item else {
+            bail!(malformed());
+        };
+        let [protected, _, payload, signature] = data.as_slice() else {
+            bail!(malformed());
+        };
+        let protected = byte_string(protected).ok_or_else(malformed)?.to_vec();
+        let payload = byte_string(payload).ok_or_else(malformed)?.to_vec();
+        let signature = byte_string(signature).ok_or_else(malformed)?.to_vec();
+
+        let DataItem::Map { data: headers, .. } =
+            cbor_diag::parse_bytes(&protected).map_err(|_| malformed())?
+        else {
+            bail!(malformed());
+        };
+        let mut alg = None;
+        let mut x5chain = Vec::new();
+        let mut issuer = None;
+        let mut feed = None;
+        for (label, value) in &headers {
+            match (label, value) {
+                (DataItem::Integer { value: 1, .. }, DataItem::Negative { value, .. }) => {
+                    alg = Some(-1 - *value as i64);
+                }
+                (
+                    DataItem::Integer {
+                        value: COSE_X5CHAIN,
+                        ..
+                    },
+                    DataItem::Array { data, .. },
+                ) => {
+                    x5chain = data
+                        .iter()
+                        .map(|cert| byte_string(cert).map(<[u8]>::to_vec))
+                        .collect::<Option<_>>()
+                        .ok_or_else(malformed)?;
+                }
+                (
+                    DataItem::Integer {
+                        value: COSE_X5CHAIN,
+                        ..
+                    },
+                    cert,
+                ) => {
+                    x5chain = vec![byte_string(cert).ok_or_else(malformed)?.to_vec()];
+                }
+                (label, value) => match text_string(label) {
+                    Some("iss") => issuer = text_string(value).map(str::to_string),
+                    Some("feed") => feed = text_string(value).map(str::to_string),
+                    _ => {}
+                },
+            }
+        }
+        if x5chain.is_empty() {
+            bail!("The UVM endorsements have no certificate chain");
+        }
+
+        let endorsement: Value =
+            serde_json::from_slice(&payload).context("Malformed UVM endorsements payload")?;
+        let field = |name: &str| {
+            endorsement[name]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("The UVM endorsements have no {name}"))
+        };
+
+        Ok(Self {
+            alg: alg.ok_or_else(|| anyhow!("The UVM endorsements have no algorithm"))?,
+            launch_measurement: field("x-ms-sevsnpvm-launchmeasurement")?,
+            svn: field("x-ms-sevsnpvm-guestsvn")?,
+            protected,
+            payload,
+            signature,
+            x5chain,
+            issuer,
+            feed,
+        })
+    }
+
+    /// Check that the UVM endorsements are for the launch measurement of
+    /// the report.
+    fn check_measurement(&self, report: &AttestationReport) -> Result<()> {
+        if hex::decode(&self.launch_measurement).ok().as_deref() != Some(&report.measurement[..]) {
+            bail!("The UVM endorsements are for another launch measurement");
+        }
+        Ok(())
+    }
+
+    /// Verify the signature of the endorsements with the leaf certificate,
+    /// and the certificate chain up to its self-signed root, which must be
+    /// one of the `trusted` roots.
+    async fn verify(
+        &self,
+        crypto: &(dyn CryptoBackend + Send + Sync),
+        trusted: &[TrustAnchor],
+    ) -> Result<()> {
+        // An empty list of anchors would accept any root.
+        if trusted.is_empty() {
+            bail!("UVM endorsements are only accepted with `microsoft` trust anchors");
+        }
+        let root = &self.x5chain[self.x5chain.len() - 1];
+        anchors::check_root(root, trusted).context("Untrusted UVM endorsements signer")?;
+
+        let to_be_signed = DataItem::Array {
+            data: vec![
+                DataItem::TextString(TextString {
+                    data: "Signature1".to_string(),
+                    bitwidth: IntegerWidth::Unknown,
+                }),
+                DataItem::ByteString(ByteString {
+                    data: self.protected.clone(),
+                    bitwidth: IntegerWidth::Unknown,
+                }),
+                DataItem::ByteString(ByteString {
+                    data: Vec::new(),
+                    bitwidth: IntegerWidth::Unknown,
+                }),
+                DataItem::ByteString(ByteString {
+                    data: self.payload.clone(),
+                    bitwidth: IntegerWidth::Unknown,
+                }),
+            ],
+            bitwidth: Some(IntegerWidth::Unknown),
+        }
+        .to_bytes();
+
+        let leaf = &self.x5chain[0];
+        match self.alg {
+            COSE_ES384 => {
+                if self.signature.len() != 96 {
+                    bail!("Malformed UVM endorsements signature");
+                }
+                let (r, s) = self.signature.split_at(48);
+                crypto
+                    .verify_ecdsa_p384_sha384(leaf, &to_be_signed, r, s)
+                    .await
+            }
+            COSE_PS384 => {
+                crypto
+                    .verify_rsa_pss_sha384(leaf, &to_be_signed, &self.signature)
+                    .await
+            }
+            alg => bail!("Unsupported UVM endorsements algorithm {alg}"),
+        }
+        .context("Invalid UVM endorsements signature")?;
+
+        for (cert, issuer) in self.x5chain.iter().zip(self.x5chain.iter().skip(1)) {
+            crypto
+                .verify_certificate(cert, issuer)
+                .await
+                .context("Invalid UVM endorsements certificate chain")?;
+        }
+        crypto
+            .verify_certificate(root, root)
+            .await
+            .context("The UVM endorsements root is not self-signed")
+    }
+
+    fn claims(&self) -> Value {
+        let root = &self.x5chain[self.x5chain.len() - 1];
+        json!({
+            "issuer": self.issuer,
+            "feed": self.feed,
+            "launch_measurement": self.launch_measurement,
+            "svn": self.svn,
+            "signer": hex::encode(Sha256::digest(root)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verifier::crypto::CryptoBackendType;
+
+    fn hcl_report() -> Vec<u8> {
+        include_bytes!("../../../../test_data/az-hcl-data.bin").to_vec()
+    }
+
+    fn byte_item(data: &[u8]) -> DataItem {
+        DataItem::ByteString(ByteString {
+            data: data.to_vec(),
+            bitwidth: IntegerWidth::Unknown,
+        })
+    }
+
+    fn text_item(data: &str) -> DataItem {
+        DataItem::TextString(TextString {
+            data: data.to_string(),
+            bitwidth: IntegerWidth::Unknown,
+        })
+    }
+
+    #[test]
+    fn test_parse_hcl_report() {
+        let (report, raw_report, runtime_data) = parse_hcl_report(&hcl_report()).unwrap();
+        assert_eq!(report.vmpl, 0);
+        assert_eq!(raw_report[..4], report.version.to_le_bytes());
+        let envelope = Envelope {
+            runtime_data,
+            uvm_endorsements: None,
+        };
+
+        let mut claims = json!({});
+        envelope.add_claims(&mut claims).unwrap();
+        assert_eq!(
+            claims["runtime_data"]["vm-configuration"]["secure-boot"],
+            true
+        );
+        assert_eq!(claims["runtime_data"]["keys"][0]["kid"], "HCLAkPub");
+        // No user data binds a nonce.
+        assert!(envelope
+            .check_user_data(|user_data| user_data == [0; 64])
+            .is_err());
+
+        // Tampered runtime data.
+        let mut tampered = hcl_report();
+        tampered[hcl::HEADER_SIZE + hcl::HW_REPORT_SIZE + hcl::REQUEST_DATA_SIZE + 2] ^= 1;
+        assert!(parse_hcl_report(&tampered).is_err());
+        assert!(parse_hcl_report(&hcl_report()[..0x400]).is_err());
+        assert!(parse_hcl_report(b"HCLB").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parse_uvm_endorsements() {
+        let (report, ..) = parse_hcl_report(&hcl_report()).unwrap();
+        let protected = DataItem::Map {
+            data: vec![
+                (
+                    DataItem::Integer {
+                        value: 1,
+                        bitwidth: IntegerWidth::Unknown,
+                    },
+                    DataItem::Negative {
+                        value: 34,
+                        bitwidth: IntegerWidth::Unknown,
+                    },
+                ),
+                (
+                    DataItem::Integer {
+                        value: COSE_X5CHAIN,
+                        bitwidth: IntegerWidth::Unknown,
+                    },
+                    byte_item(b"cert"),
+                ),
+                (text_item("feed"), text_item("ContainerPlat-AMD-UVM")),
+            ],
+            bitwidth: Some(IntegerWidth::Unknown),
+        }
+        .to_bytes();
+        let payload = json!({
+            "x-ms-sevsnpvm-guestsvn": "101",
+            "x-ms-sevsnpvm-launchmeasurement": hex::encode(report.measurement),
+        })
+        .to_string();
+        let cose = DataItem::Tag {
+            tag: Tag(COSE_SIGN1_TAG),
+            bitwidth: IntegerWidth::Unknown,
+            value: Box::new(DataItem::Array {
+                data: vec![
+                    byte_item(&protected),
+                    DataItem::Map {
+                        data: vec![],
+                        bitwidth: Some(IntegerWidth::Unknown),
+                    },
+                    byte_item(payload.as_bytes()),
+                    byte_item(&[0; 96]),
+                ],
+                bitwidth: Some(IntegerWidth::Unknown),
+            }),
+        }
+        .to_bytes();
+
+        let uvm_endorsements = UvmEndorsements::parse(&cose).unwrap();
+        assert_eq!(uvm_endorsements.alg, COSE_ES384);
+        assert_eq!(uvm_endorsements.x5chain, [b"cert".to_vec()]);
+        uvm_endorsements.check_measurement(&report).unwrap();
+        let claims = uvm_endorsements.claims();
+        assert_eq!(claims["feed"], "ContainerPlat-AMD-UVM");
+        assert_eq!(claims["svn"], "101");
+        assert_eq!(claims["signer"], hex::encode(Sha256::digest(b"cert")));
+
+        let mut other = report;
+        other.measurement[0] ^= 1;
+        assert!(uvm_endorsements.check_measurement(&other).is_err());
+        assert!(UvmEndorsements::parse(&protected).is_err());
+
+        // Only signed by a trust anchor.
+        let crypto = CryptoBackendType::default().to_backend().unwrap();
+        let e = uvm_endorsements
+            .verify(crypto.as_ref(), &[])
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("trust anchors"));
+        let untrusted = TrustAnchor {
+            certs: vec![b"other".to_vec()],
+        };
+        let e = uvm_endorsements
+            .verify(crypto.as_ref(), &[untrusted])
+            .await
+            .unwrap_err();
+        assert_eq!(e.to_string(), "Untrusted UVM endorsements signer");
+    }
+}
@@ -2,26 +2,31 @@ use anyhow::{anyhow, Context, Result};
 use base64::Engine;
 extern crate serde;
 use self::serde::{Deserialize, Serialize};
+use super::anchors::{self, TrustAnchor, TRUST_ANCHOR_CLAIM};
+use super::crypto::CryptoBackend;
+use super::pipeline::{self, Pipeline, Stage};
 use super::*;
 use asn1_rs::{oid, Integer, OctetString, Oid};
 use async_trait::async_trait;
-use kbs_types::TeePubKey;
-use openssl::{
-    ec::EcKey,
-    ecdsa,
-    pkey::{PKey, Public},
-    x509,
-};
 use serde_json::json;
-use sev::firmware::guest::AttestationReport;
+use sev::firmware::guest::{AttestationReport, GuestPolicy};
+use sev::firmware::host::TcbVersion;
 use sev::firmware::host::{CertTableEntry, CertType};
-use sha2::{Digest, Sha384};
+use std::sync::Arc;
+use x509_parser::pem::Pem;
 use x509_parser::prelude::*;
 
+mod aks;
+
 #[derive(Serialize, Deserialize)]
 struct SnpEvidence {
     attestation_report: AttestationReport,
     cert_chain: Vec<CertTableEntry>,
+    /// The report as signed: the bytes of the HCL report of AKS evidence,
+    /// or else the report serialized, that the signature check holds to
+    /// these exact bytes.
+    #[serde(skip)]
+    raw_report: Vec<u8>,
 }
 
 const HW_ID_OID: Oid<'static> = oid!(1.3.6 .1 .4 .1 .3704 .1 .4);
@@ -30,8 +35,135 @@ const SNP_SPL_OID: Oid<'static> = oid!(1.3.6 .1 .4 .1 .3704 .1 .3 .3);
 const TEE_SPL_OID: Oid<'static> = oid!(1.3.6 .1 .4 .1 .3704 .1 .3 .2);
 const LOADER_SPL_OID: Oid<'static> = oid!(1.3.6 .1 .4 .1 .3704 .1 .3 .1);
 
-#[derive(Debug, Default)]
-pub struct Snp {}
+/// Attestation report versions the verifier can parse.
+pub(crate) const REPORT_VERSIONS: VersionRange = VersionRange {
+    min: Some(2),
+    max: Some(2),
+    deprecated_below: None,
+};
+
+/// Offset of the signature in an attestation report, which signs the
+/// bytes before it.
+const SIGNATURE_OFFSET: usize = 0x2a0;
+
+/// Size of each of the `r` and `s` fields of the report signature, that
+/// hold the little-endian P-384 scalars zero extended.
+const SIGNATURE_FIELD_SIZE: usize = 72;
+
+/// Size of P-384 scalars.
+const P384_SCALAR_SIZE: usize = 48;
+
+/// Offset of the key info of an attestation report, whose bits 2 to 4
+/// tell the key that signs the report.
+const KEY_INFO_OFFSET: usize = 0x48;
+
+/// GUID of the VLEK in the certificate table of the extended report.
+const VLEK_GUID: &str = "a8074bc2-a25a-483e-aae6-39c045a0b8a1";
+
+/// Common name of the ASVK of a product line, which signs its VLEKs, less
+/// the product line, e.g. `SEV-VLEK-Milan`.
+const ASVK_COMMON_NAME_PREFIX: &str = "SEV-VLEK-";
+
+/// The key that signs an attestation report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SigningKey {
+    /// Versioned Chip Endorsement Key, unique to the chip.
+    Vcek,
+    /// Versioned Loaded Endorsement Key, loaded by the cloud provider.
+    Vlek,
+}
+
+impl SigningKey {
+    /// The signing key of a report, as signed.
+    fn of_report(report: &[u8]) -> Result<Self> {
+        let key_info = report
+            .get(KEY_INFO_OFFSET..KEY_INFO_OFFSET + 4)
+            .ok_or_else(|| anyhow!("Attestation report is too short"))?;
+        let key_info = u32::from_le_bytes(key_info.try_into()?);
+        match (key_info >> 2) & 0b111 {
+            0 => Ok(Self::Vcek),
+            1 => Ok(Self::Vlek),
+            7 => bail!("The attestation report is not signed"),
+            key => bail!("Unknown attestation report signing key {key}"),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Vcek => "vcek",
+            Self::Vlek => "vlek",
+        }
+    }
+}
+
+/// The guest policy bits of a report that [`SnpGuestPolicy`] checks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct GuestPolicyBits {
+    abi: (u64, u64),
+    smt: bool,
+    migrate_ma: bool,
+    debug: bool,
+    single_socket: bool,
+}
+
+impl From<&GuestPolicy> for GuestPolicyBits {
+    fn from(policy: &GuestPolicy) -> Self {
+        Self {
+            abi: (policy.abi_major(), policy.abi_minor()),
+            smt: policy.smt_allowed() != 0,
+            migrate_ma: policy.migrate_ma_allowed() != 0,
+            debug: policy.debug_allowed() != 0,
+            single_socket: policy.single_socket_required() != 0,
+        }
+    }
+}
+
+impl GuestPolicyBits {
+    /// Fail with every requirement of `required` the policy breaks.
+    fn check(&self, required: &SnpGuestPolicy) -> Result<()> {
+        let mut violations = Vec::new();
+        if required.deny_debug && self.debug {
+            violations.push("allows debugging".to_string());
+        }
+        if required.deny_migration_agent && self.migrate_ma {
+            violations.push("allows a migration agent".to_string());
+        }
+        if required.deny_smt && self.smt {
+            violations.push("allows SMT".to_string());
+        }
+        if required.require_single_socket && !self.single_socket {
+            violations.push("does not require a single socket".to_string());
+        }
+        if let Some(min_abi) = required.min_abi {
+            if self.abi < min_abi {
+                violations.push(format!(
+                    "requires ABI {}.{}, below {}.{}",
+                    self.abi.0, self.abi.1, min_abi.0, min_abi.1
+                ));
+            }
+        }
+        if !violations.is_empty() {
+            bail!("The SNP guest policy {}", violations.join(", "));
+        }
+        Ok(())
+    }
+}
+
+pub struct Snp {
+    pub versions: VersionRange,
+    pub crypto: Box<dyn CryptoBackend + Send + Sync>,
+    pub pipeline: Pipeline,
+    /// Comparison of the report data with the nonce and TEE public key.
+    pub report_data: ReportDataMode,
+    /// Guest policy required of the reports.
+    pub guest_policy: SnpGuestPolicy,
+    /// ASK and ARK chains of product lines, besides the built-in Milan one,
+    /// see [`anchors`].
+    pub trust_anchors: Arc<Vec<TrustAnchor>>,
+    /// Roots of the signers of the UVM endorsements of AKS evidence, see
+    /// [`aks`].
+    pub uvm_anchors: Arc<Vec<TrustAnchor>>,
+}
 
 #[async_trait]
 impl Verifier for Snp {
@@ -40,26 +172,118 @@ impl Verifier for Snp {
         nonce: String,
         attestation: &Attestation,
     ) -> Result<TeeEvidenceParsedClaim> {
-        let tee_evidence = serde_json::from_str::<SnpEvidence>(&attestation.tee_evidence)
+        pipeline::enter(Stage::Parse);
+        let step = profile::step("decode_evidence");
+        let evidence = serde_json::from_str::<serde_json::Value>(&attestation.tee_evidence)
             .context("Deserialize Quote failed.")?;
-
-        verify_report_signature(&tee_evidence)?;
-
-        let report = tee_evidence.attestation_report;
-        if report.version != 2 {
-            return Err(anyhow!("Unexpected report version"));
+        let (tee_evidence, envelope) = match evidence.get("hcl_report") {
+            Some(_) => {
+                let evidence = serde_json::from_value::<aks::AksEvidence>(evidence)
+                    .context("Deserialize AKS evidence failed.")?;
+                let (tee_evidence, envelope) = aks::parse(evidence)?;
+                (tee_evidence, Some(envelope))
+            }
+            None => {
+                let mut evidence = serde_json::from_value::<SnpEvidence>(evidence)
+                    .context("Deserialize Quote failed.")?;
+                evidence.raw_report = bincode::serialize(&evidence.attestation_report)?;
+                (evidence, None)
+            }
+        };
+        drop(step);
+
+        self.versions.or(REPORT_VERSIONS).check(
+            "SNP attestation report",
+            tee_evidence.attestation_report.version,
+        )?;
+
+        if tee_evidence.attestation_report.vmpl != 0 {
+            return Err(anyhow!("VMPL Check Failed"));
         }
 
-        if report.vmpl != 0 {
-            return Err(anyhow!("VMPL Check Failed"));
+        GuestPolicyBits::from(&tee_evidence.attestation_report.policy).check(&self.guest_policy)?;
+        let signing_key = SigningKey::of_report(&tee_evidence.raw_report)?;
+
+        let mut anchor = None;
+        for stage in self.pipeline.checks() {
+            pipeline::enter(*stage);
+            match stage {
+                Stage::CollateralVerify => {
+                    anchor = Some(
+                        verify_report_signature(
+                            &tee_evidence,
+                            signing_key,
+                            &self.trust_anchors,
+                            self.crypto.as_ref(),
+                        )
+                        .await?,
+                    );
+                    if let Some(envelope) = &envelope {
+                        let _step = profile::step("hcl_envelope");
+                        envelope
+                            .verify(self.crypto.as_ref(), &self.uvm_anchors)
+                            .await?;
+                    }
+                }
+                Stage::Freshness => {
+                    let binds_nonce = |report_data: &[u8]| {
+                        self.report_data.matches(&nonce, attestation, report_data)
+                    };
+                    match &envelope {
+                        // The report data binds the runtime data, whose
+                        // user data binds the nonce.
+                        Some(envelope) => envelope.check_user_data(binds_nonce)?,
+                        None => {
+                            if !binds_nonce(&tee_evidence.attestation_report.report_data) {
+                                return Err(anyhow!("Report Data Mismatch"));
+                            }
+                        }
+                    }
+                }
+                stage => bail!("The SNP verifier has no `{stage}` stage"),
+            }
         }
 
-        let expected_report_data = calculate_expected_report_data(&nonce, &attestation.tee_pubkey);
-        if report.report_data != expected_report_data {
-            return Err(anyhow!("Report Data Mismatch"));
+        pipeline::enter(Stage::ClaimsNormalize);
+        let report = &tee_evidence.attestation_report;
+        let mut claims = parse_tee_evidence(report, signing_key);
+        if let (Some(anchor), Some(claims)) = (anchor, claims.as_object_mut()) {
+            claims.insert(
+                platform::PRODUCT_LINE_CLAIM.to_string(),
+                anchor.product.into(),
+            );
+            claims.insert(TRUST_ANCHOR_CLAIM.to_string(), anchor.claim.into());
         }
+        // The TCB versions are only known to be genuine if the report
+        // signature is checked.
+        if self.pipeline.checks().contains(&Stage::CollateralVerify) {
+            tcb::from_snp(&report.reported_tcb, &report.current_tcb).add_claims(&mut claims)?;
+        }
+        if let Some(envelope) = &envelope {
+            envelope.add_claims(&mut claims)?;
+        }
+        self.report_data.add_claim(&mut claims);
+        Ok(claims)
+    }
 
-        Ok(parse_tee_evidence(&report))
+    fn freshness_methods(&self) -> Vec<FreshnessMethod> {
+        self.pipeline.freshness_methods()
+    }
+
+    fn signed_evidence(&self, attestation: &Attestation) -> Result<Vec<u8>> {
+        let evidence = serde_json::from_str::<serde_json::Value>(&attestation.tee_evidence)
+            .context("Deserialize Quote failed.")?;
+        match evidence
+            .get("hcl_report")
+            .and_then(serde_json::Value::as_str)
+        {
+            Some(hcl_report) => Ok(base64::engine::general_purpose::STANDARD.decode(hcl_report)?),
+            None => {
+                let evidence = serde_json::from_value::<SnpEvidence>(evidence)
+                    .context("Deserialize Quote failed.")?;
+                Ok(bincode::serialize(&evidence.attestation_report)?)
+            }
+        }
     }
 }
 
@@ -97,18 +321,29 @@ fn get_oid_int(vcek: &x509_parser::certificate::TbsCertificate, oid: Oid) -> Res
     val_int.as_u8().context("Unexpected data size")
 }
 
-fn verify_report_signature(evidence: &SnpEvidence) -> Result<()> {
+/// Verify the signature of the report of `evidence`, and return the anchor
+/// of the certificate chain of its signing key.
+async fn verify_report_signature(
+    evidence: &SnpEvidence,
+    signing_key: SigningKey,
+    trusted: &[TrustAnchor],
+    crypto: &(dyn CryptoBackend + Send + Sync),
+) -> Result<AmdAnchor> {
     // check cert chain
-    let vcek = verify_cert_chain(&evidence.cert_chain)?;
-
-    // OpenSSL bindings do not expose custom extensions
-    // Parse the vcek using x509_parser
-    let vcek_der = &vcek.to_der()?;
-    let parsed_vcek = X509Certificate::from_der(vcek_der)?.1.tbs_certificate;
+    let step = profile::step("cert_chain");
+    let (vcek, anchor) = match signing_key {
+        SigningKey::Vcek => verify_cert_chain(&evidence.cert_chain, trusted, crypto).await?,
+        SigningKey::Vlek => verify_vlek_cert_chain(&evidence.cert_chain, trusted, crypto).await?,
+    };
+    drop(step);
+    let step = profile::step("vcek_extensions");
+    let parsed_vcek = X509Certificate::from_der(&vcek)?.1.tbs_certificate;
 
     // verify vcek fields
-    // chip id
-    if get_oid_octets::<64>(&parsed_vcek, HW_ID_OID)? != evidence.attestation_report.chip_id {
+    // chip id, which VLEKs do not bind
+    if signing_key == SigningKey::Vcek
+        && get_oid_octets::<64>(&parsed_vcek, HW_ID_OID)? != evidence.attestation_report.chip_id
+    {
         return Err(anyhow!("Chip ID mismatch"));
     }
 
@@ -134,18 +369,42 @@ fn verify_report_signature(evidence: &SnpEvidence) -> Result<()> {
         return Err(anyhow!("Boot loader verion mismatch"));
     }
 
-    // verify report signature
-    let sig = ecdsa::EcdsaSig::try_from(&evidence.attestation_report.signature)?;
-    let data = &bincode::serialize(&evidence.attestation_report)?[..=0x29f];
+    drop(step);
 
-    sig.verify(data, EcKey::try_from(vcek.public_key()?)?.as_ref())
+    // verify report signature
+    let _step = profile::step("report_signature");
+    let report = &evidence.raw_report;
+    let (r, s) = report_signature(report)?;
+    crypto
+        .verify_ecdsa_p384_sha384(&vcek, &report[..SIGNATURE_OFFSET], &r, &s)
+        .await
         .context("Signature validation failed.")?;
 
-    Ok(())
+    Ok(anchor)
+}
+
+/// The big-endian `r` and `s` of the signature of a serialized report.
+fn report_signature(report: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let scalar = |offset: usize| -> Result<Vec<u8>> {
+        let mut scalar = report
+            .get(offset..offset + P384_SCALAR_SIZE)
+            .ok_or_else(|| anyhow!("Attestation report is too short"))?
+            .to_vec();
+        scalar.reverse();
+        Ok(scalar)
+    };
+
+    Ok((
+        scalar(SIGNATURE_OFFSET)?,
+        scalar(SIGNATURE_OFFSET + SIGNATURE_FIELD_SIZE)?,
+    ))
 }
 
-fn load_milan_cert_chain() -> Result<(x509::X509, x509::X509)> {
-    let certs = x509::X509::stack_from_pem(include_bytes!("milan_ask_ark.pem"))?;
+/// The DER Milan ASK and ARK.
+fn load_milan_cert_chain() -> Result<(Vec<u8>, Vec<u8>)> {
+    let certs = Pem::iter_from_buffer(include_bytes!("milan_ask_ark.pem"))
+        .map(|pem| Ok(pem?.contents))
+        .collect::<Result<Vec<_>>>()?;
     if certs.len() != 2 {
         bail!("Malformed Milan ASK/ARK");
     }
@@ -154,101 +413,289 @@ fn load_milan_cert_chain() -> Result<(x509::X509, x509::X509)> {
     Ok((certs[0].clone(), certs[1].clone()))
 }
 
-fn verify_cert_chain(cert_chain: &[CertTableEntry]) -> Result<x509::X509> {
+/// The expiry of the Milan ASK and ARK, as Unix times.
+pub(crate) fn trusted_cert_expiries() -> Result<Vec<(String, i64)>> {
     let (ask, ark) = load_milan_cert_chain()?;
+    [("snp.ask", ask), ("snp.ark", ark)]
+        .into_iter()
+        .map(|(name, der)| {
+            let (_, cert) = X509Certificate::from_der(&der)
+                .with_context(|| format!("Malformed {name} certificate"))?;
+            Ok((name.to_string(), cert.validity().not_after.timestamp()))
+        })
+        .collect()
+}
 
-    let raw_vcek = cert_chain
-        .iter()
-        .find(|c| c.cert_type == CertType::VCEK)
-        .ok_or_else(|| anyhow!("VCEK not found."))?;
-    let vcek = x509::X509::from_der(raw_vcek.data()).context("Failed to load VCEK")?;
+/// An ASK and ARK that a certificate chain was verified with.
+#[derive(Debug)]
+struct AmdAnchor {
+    /// The product line, e.g. `Milan`.
+    product: String,
+    ask: Vec<u8>,
+    ark: Vec<u8>,
+    /// The value of the [`TRUST_ANCHOR_CLAIM`].
+    claim: String,
+}
 
-    // ARK -> ARK
-    ark.verify(&(ark.public_key().unwrap() as PKey<Public>))
-        .context("Invalid ARK Signature")?;
+/// The product line of an AMD certificate name: the end of its common name,
+/// e.g. `Genoa` of `ARK-Genoa`, `SEV-Genoa` or `SEV-VLEK-Genoa`.
+fn product_line(name: &X509Name) -> Result<String> {
+    let cn = name
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+        .ok_or_else(|| anyhow!("No common name in {name}"))?;
+    cn.rsplit_once('-')
+        .map(|(_, product)| product.to_string())
+        .ok_or_else(|| anyhow!("No product line in the common name {cn}"))
+}
 
-    // ARK -> ASK
-    ask.verify(&(ark.public_key()? as PKey<Public>))
-        .context("Invalid ASK Signature")?;
+/// The built-in Milan anchor and the configured `trusted` ones of the
+/// product line `product`, in order.
+fn amd_anchors(product: &str, trusted: &[TrustAnchor]) -> Result<Vec<AmdAnchor>> {
+    let (ask, ark) = load_milan_cert_chain()?;
+    let mut candidates = vec![(ask, ark)];
+    for anchor in trusted {
+        let [ask, ark] = anchor.certs.as_slice() else {
+            bail!("Malformed SNP trust anchor {}", anchor.claim());
+        };
+        candidates.push((ask.clone(), ark.clone()));
+    }
 
-    // ASK -> VCEK
-    vcek.verify(&(ask.public_key()? as PKey<Public>))
-        .context("Invalid VCEK Signature")?;
+    let mut matching = Vec::new();
+    for (ask, ark) in candidates {
+        let (_, parsed_ark) = X509Certificate::from_der(&ark).context("Malformed ARK")?;
+        let ark_product = product_line(parsed_ark.subject())?;
+        if ark_product == product {
+            matching.push(AmdAnchor {
+                product: ark_product,
+                claim: anchors::fingerprint(&ark),
+                ask,
+                ark,
+            });
+        }
+    }
+    if matching.is_empty() {
+        bail!("No trust anchor of the {product} product line");
+    }
+    Ok(matching)
+}
 
-    Ok(vcek)
+/// Verify `key`, the VCEK, or the VLEK signed by `asvk`, up to the ARK of
+/// each of `anchors` in turn, and return the first that validates it.
+async fn first_valid_anchor(
+    anchors: Vec<AmdAnchor>,
+    asvk: Option<&[u8]>,
+    key: &[u8],
+    crypto: &(dyn CryptoBackend + Send + Sync),
+) -> Result<AmdAnchor> {
+    let (ask_name, key_name, ask_step, key_step) = match asvk {
+        Some(_) => ("ASVK", "VLEK", "asvk_signature", "vlek_signature"),
+        None => ("ASK", "VCEK", "ask_signature", "vcek_signature"),
+    };
+    let mut error = anyhow!("No trust anchor");
+    for anchor in anchors {
+        let ask = asvk.unwrap_or(&anchor.ask);
+        let verified = async {
+            // ARK -> ARK
+            let step = profile::step("ark_signature");
+            crypto
+                .verify_certificate(&anchor.ark, &anchor.ark)
+                .await
+                .context("Invalid ARK Signature")?;
+            drop(step);
+
+            // ARK -> ASK
+            let step = profile::step(ask_step);
+            crypto
+                .verify_certificate(ask, &anchor.ark)
+                .await
+                .with_context(|| format!("Invalid {ask_name} Signature"))?;
+            drop(step);
+
+            // ASK -> VCEK
+            let _step = profile::step(key_step);
+            crypto
+                .verify_certificate(key, ask)
+                .await
+                .with_context(|| format!("Invalid {key_name} Signature"))
+        };
+        match verified.await {
+            Ok(()) => {
+                let chain = vec![key.to_vec(), ask.to_vec(), anchor.ark.clone()];
+                trace::record_chain(key_name, chain);
+                return Ok(anchor);
+            }
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
 }
 
-fn calculate_expected_report_data(nonce: &String, tee_pubkey: &TeePubKey) -> [u8; 64] {
-    let mut hasher = Sha384::new();
+/// Verify the VCEK up to an ARK of its product line, and return it in DER,
+/// with the anchor that validated it.
+async fn verify_cert_chain(
+    cert_chain: &[CertTableEntry],
+    trusted: &[TrustAnchor],
+    crypto: &(dyn CryptoBackend + Send + Sync),
+) -> Result<(Vec<u8>, AmdAnchor)> {
+    let vcek = cert_chain
+        .iter()
+        .find(|c| c.cert_type == CertType::VCEK)
+        .ok_or_else(|| anyhow!("VCEK not found."))?
+        .data()
+        .to_vec();
+    let (_, parsed_vcek) = X509Certificate::from_der(&vcek).context("Malformed VCEK")?;
+    let anchors = amd_anchors(&product_line(parsed_vcek.issuer())?, trusted)?;
+    if diagnostics::enabled() {
+        let subject = |cert: &[u8]| {
+            X509Certificate::from_der(cert)
+                .map(|(_, cert)| cert.subject().to_string())
+                .unwrap_or_else(|e| format!("unparsable certificate: {e}"))
+        };
+        let mut chain = vec![subject(&vcek)];
+        for anchor in &anchors {
+            chain.extend([subject(&anchor.ask), subject(&anchor.ark)]);
+        }
+        diagnostics::record("snp.cert_chain", chain);
+    }
 
-    hasher.update(nonce.as_bytes());
-    hasher.update(&tee_pubkey.k_mod);
-    hasher.update(&tee_pubkey.k_exp);
+    let anchor = first_valid_anchor(anchors, None, &vcek, crypto).await?;
+    Ok((vcek, anchor))
+}
 
-    let partial_hash = hasher.finalize();
+/// Verify the VLEK up to an ARK of its product line through the ASVK of the
+/// certificate table, and return it in DER, with the anchor that validated
+/// it.
+async fn verify_vlek_cert_chain(
+    cert_chain: &[CertTableEntry],
+    trusted: &[TrustAnchor],
+    crypto: &(dyn CryptoBackend + Send + Sync),
+) -> Result<(Vec<u8>, AmdAnchor)> {
+    let vlek = cert_chain
+        .iter()
+        .find(|c| matches!(&c.cert_type, CertType::OTHER(guid) if guid.to_string() == VLEK_GUID))
+        .ok_or_else(|| anyhow!("VLEK not found."))?
+        .data()
+        .to_vec();
+    let asvk = cert_chain
+        .iter()
+        .find(|c| c.cert_type == CertType::ASK)
+        .ok_or_else(|| anyhow!("ASVK not found."))?
+        .data()
+        .to_vec();
+    let (_, parsed_asvk) = X509Certificate::from_der(&asvk).context("Malformed ASVK")?;
+    let is_asvk = parsed_asvk.subject().iter_common_name().any(|cn| {
+        cn.as_str()
+            .is_ok_and(|cn| cn.starts_with(ASVK_COMMON_NAME_PREFIX))
+    });
+    if !is_asvk {
+        bail!("The ASK of the certificate table is not an {ASVK_COMMON_NAME_PREFIX}* ASVK");
+    }
+    let anchors = amd_anchors(&product_line(parsed_asvk.subject())?, trusted)?;
 
-    let mut hash = [0u8; 64];
-    hash[..48].copy_from_slice(&partial_hash);
+    let anchor = first_valid_anchor(anchors, Some(&asvk), &vlek, crypto).await?;
+    Ok((vlek, anchor))
+}
 
-    hash
+/// A TCB version as claims.
+fn tcb_claims(tcb: &TcbVersion) -> serde_json::Value {
+    json!({
+        "bootloader": tcb.bootloader,
+        "tee": tcb.tee,
+        "snp": tcb.snp,
+        "microcode": tcb.microcode,
+    })
 }
 
-fn parse_tee_evidence(report: &AttestationReport) -> TeeEvidenceParsedClaim {
+fn parse_tee_evidence(
+    report: &AttestationReport,
+    signing_key: SigningKey,
+) -> TeeEvidenceParsedClaim {
     let claims_map = json!({
         // policy fields
-        "policy_abi_major": format!("{}",report.policy.abi_major()),
-        "policy_abi_minor": format!("{}", report.policy.abi_minor()),
+        "policy_abi_major": report.policy.abi_major(),
+        "policy_abi_minor": report.policy.abi_minor(),
         "policy_smt_allowed": format!("{}", report.policy.smt_allowed()),
         "policy_migrate_ma": format!("{}", report.policy.migrate_ma_allowed()),
         "policy_debug_allowed": format!("{}", report.policy.debug_allowed()),
         "policy_single_socket": format!("{}", report.policy.single_socket_required()),
 
         // versioning info
-        "reported_tcb_bootloader": format!("{}", report.reported_tcb.bootloader),
-        "reported_tcb_tee": format!("{}", report.reported_tcb.tee),
-        "reported_tcb_snp": format!("{}", report.reported_tcb.snp),
-        "reported_tcb_microcode": format!("{}", report.reported_tcb.microcode),
+        "reported_tcb_bootloader": report.reported_tcb.bootloader,
+        "reported_tcb_tee": report.reported_tcb.tee,
+        "reported_tcb_snp": report.reported_tcb.snp,
+        "reported_tcb_microcode": report.reported_tcb.microcode,
 
         // platform info
         "platform_tsme_enabled": format!("{}", report.plat_info.tsme_enabled()),
         "platform_smt_enabled": format!("{}", report.plat_info.smt_enabled()),
 
+        "platform_version": format!("{}.{}.{}", report.current_major, report.current_minor, report.current_build),
+        "committed_version": format!("{}.{}.{}", report.committed_major, report.committed_minor, report.committed_build),
+        "current_tcb": tcb_claims(&report.current_tcb),
+        "committed_tcb": tcb_claims(&report.committed_tcb),
+        "launch_tcb": tcb_claims(&report.launch_tcb),
+
         // measurement
         "measurement": format!("{}", base64::engine::general_purpose::STANDARD.encode(report.measurement)),
+
+        // report fields, in hex unlike the measurement
+        "version": report.version,
+        "guest_svn": report.guest_svn,
+        "vmpl": report.vmpl,
+        "family_id": hex::encode(report.family_id),
+        "image_id": hex::encode(report.image_id),
+        "report_data": hex::encode(report.report_data),
+        "host_data": hex::encode(report.host_data),
+        "id_key_digest": hex::encode(report.id_key_digest),
+        "author_key_digest": hex::encode(report.author_key_digest),
+        "report_id": hex::encode(report.report_id),
+        "report_id_ma": hex::encode(report.report_id_ma),
+        "signing_key": signing_key.name(),
     });
 
-    claims_map as TeeEvidenceParsedClaim
+    TeeEvidenceParsedClaim::from(claims_map)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use openssl::nid::Nid;
+    use crate::verifier::conformance::{self, Conformance, DebugClaim};
+    use crate::verifier::crypto::CryptoBackendType;
     use sev::firmware::host::CertTableEntry;
 
-    #[test]
-    fn check_milan_certificates() {
+    fn crypto() -> Box<dyn CryptoBackend + Send + Sync> {
+        CryptoBackendType::default().to_backend().unwrap()
+    }
+
+    #[tokio::test]
+    async fn check_milan_certificates() {
         let (ask, ark) = load_milan_cert_chain().unwrap();
         assert_eq!(get_common_name(&ark).unwrap(), "ARK-Milan");
         assert_eq!(get_common_name(&ask).unwrap(), "SEV-Milan");
 
-        assert!(ark
-            .verify(&(ark.public_key().unwrap() as PKey<Public>))
+        let crypto = crypto();
+        crypto
+            .verify_certificate(&ark, &ark)
+            .await
             .context("Invalid ARK Signature")
-            .unwrap());
+            .unwrap();
 
-        assert!(ask
-            .verify(&(ark.public_key().unwrap() as PKey<Public>))
+        crypto
+            .verify_certificate(&ask, &ark)
+            .await
             .context("Invalid ASK Signature")
-            .unwrap());
+            .unwrap();
     }
 
-    fn get_common_name(cert: &x509::X509) -> Result<String> {
-        let mut entries = cert.subject_name().entries_by_nid(Nid::COMMONNAME);
+    fn get_common_name(cert: &[u8]) -> Result<String> {
+        let (_, cert) = X509Certificate::from_der(cert)?;
+        let mut entries = cert.subject().iter_common_name();
 
         if let Some(e) = entries.next() {
             assert_eq!(entries.count(), 0);
-            return Ok(e.data().as_utf8()?.to_string());
+            return Ok(e.as_str()?.to_string());
         }
         Err(anyhow!("No CN found"))
     }
@@ -298,21 +745,133 @@ mod tests {
         }
     }
 
-    #[test]
-    fn check_vcek_signature_verification() {
+    #[tokio::test]
+    async fn check_vcek_signature_verification() {
         let vcek = include_bytes!("test-vcek.der").to_vec();
         let cert_table = vec![CertTableEntry::new(CertType::VCEK, vcek)];
-        verify_cert_chain(&cert_table).unwrap();
+        let (_, anchor) = verify_cert_chain(&cert_table, &[], crypto().as_ref())
+            .await
+            .unwrap();
+        assert_eq!(anchor.product, "Milan");
+        assert_eq!(anchor.claim, anchors::fingerprint(&anchor.ark));
     }
 
     #[test]
-    fn check_vcek_signature_failure() {
+    fn check_anchor_selection() {
+        let (ask, ark) = load_milan_cert_chain().unwrap();
+        let milan = TrustAnchor {
+            certs: vec![ask, ark],
+        };
+        // The built-in Milan anchor first, then the configured ones.
+        let anchors = amd_anchors("Milan", &[milan.clone()]).unwrap();
+        assert_eq!(anchors.len(), 2);
+        assert!(anchors.iter().all(|anchor| anchor.claim == milan.claim()));
+
+        let e = amd_anchors("Genoa", &[milan]).unwrap_err();
+        assert_eq!(e.to_string(), "No trust anchor of the Genoa product line");
+        let ark_only = TrustAnchor {
+            certs: vec![load_milan_cert_chain().unwrap().1],
+        };
+        assert!(amd_anchors("Milan", &[ark_only]).is_err());
+    }
+
+    #[tokio::test]
+    async fn check_vcek_signature_failure() {
         let mut vcek = include_bytes!("test-vcek.der").to_vec();
 
         // corrupt some byte
         vcek[7] += 1;
 
         let cert_table = vec![CertTableEntry::new(CertType::VCEK, vcek)];
-        assert!(verify_cert_chain(&cert_table).is_err());
+        assert!(verify_cert_chain(&cert_table, &[], crypto().as_ref())
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn check_report_signature_layout() {
+        let mut report = vec![0u8; SIGNATURE_OFFSET + SIGNATURE_FIELD_SIZE * 2];
+        report[SIGNATURE_OFFSET] = 1;
+        report[SIGNATURE_OFFSET + P384_SCALAR_SIZE - 1] = 2;
+        report[SIGNATURE_OFFSET + SIGNATURE_FIELD_SIZE] = 3;
+
+        let (r, s) = report_signature(&report).unwrap();
+        assert_eq!(r.len(), P384_SCALAR_SIZE);
+        assert_eq!((r[0], r[P384_SCALAR_SIZE - 1]), (2, 1));
+        assert_eq!(s[P384_SCALAR_SIZE - 1], 3);
+        assert!(report_signature(&report[..SIGNATURE_OFFSET]).is_err());
+    }
+
+    #[test]
+    fn check_signing_key() {
+        let mut report = vec![0u8; SIGNATURE_OFFSET];
+        assert_eq!(SigningKey::of_report(&report).unwrap(), SigningKey::Vcek);
+        report[KEY_INFO_OFFSET] = 1 << 2;
+        assert_eq!(SigningKey::of_report(&report).unwrap(), SigningKey::Vlek);
+        report[KEY_INFO_OFFSET] = 7 << 2;
+        assert!(SigningKey::of_report(&report).is_err());
+        assert!(SigningKey::of_report(&report[..KEY_INFO_OFFSET]).is_err());
+    }
+
+    #[test]
+    fn check_guest_policy() {
+        let policy = GuestPolicyBits {
+            abi: (1, 51),
+            smt: true,
+            migrate_ma: false,
+            debug: true,
+            single_socket: false,
+        };
+        policy.check(&SnpGuestPolicy::default()).unwrap();
+        policy
+            .check(&SnpGuestPolicy {
+                deny_migration_agent: true,
+                min_abi: Some((1, 51)),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let err = policy
+            .check(&SnpGuestPolicy {
+                deny_debug: true,
+                deny_smt: true,
+                require_single_socket: true,
+                min_abi: Some((1, 52)),
+                ..Default::default()
+            })
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "The SNP guest policy allows debugging, allows SMT, \
+             does not require a single socket, requires ABI 1.51, below 1.52"
+        );
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        conformance::run(Conformance {
+            tee: Tee::Snp,
+            verifier: Snp {
+                versions: VersionRange::default(),
+                crypto: crypto(),
+                pipeline: Pipeline::new(pipeline::SNP_STAGES),
+                report_data: ReportDataMode::default(),
+                guest_policy: SnpGuestPolicy::default(),
+                trust_anchors: Arc::default(),
+                uvm_anchors: Arc::default(),
+            },
+            malformed: vec![
+                json!({ "attestation_report": {}, "cert_chain": [] }).to_string(),
+                json!({ "cert_chain": [] }).to_string(),
+            ],
+            evidence: None,
+            claims: None,
+            debug_claim: Some(DebugClaim {
+                name: "snp.policy_debug_allowed",
+                is_debug: |value| value.as_str()?.parse::<u8>().ok().map(|debug| debug != 0),
+            }),
+        })
+        .await;
     }
 }
@@ -0,0 +1,324 @@
+//! Precomputation of the launch measurement of an SNP guest, to register it
+//! as the reference value of the `snp.measurement` claim when the guest
+//! image is built. The measurement of QEMU/KVM guests booted with OVMF is
+//! replayed as in [sev-snp-measure](https://github.com/virtee/sev-snp-measure),
+//! page by page:
+//!
+//! * the OVMF binary, mapped right below 4 GiB,
+//! * the SEV metadata sections of OVMF: zero pages, the secrets and CPUID
+//!   pages, and the page of the kernel, initrd and command line hashes when
+//!   the kernel is booted directly,
+//! * a VMSA page for each vCPU.
+
+use anyhow::*;
+use base64::Engine;
+use chrono::{Months, Utc};
+use sha2::{Digest, Sha256, Sha384};
+
+use crate::rvps::ReferenceValue;
+
+pub(crate) mod ovmf;
+mod vmsa;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Size of the PAGE_INFO structure that is hashed into the digest.
+const PAGE_INFO_SIZE: u16 = 0x70;
+
+/// GPA of the VMSA pages.
+const VMSA_GPA: u64 = 0xffff_ffff_f000;
+
+/// The SNP guest features of the VMSA, only `SNPActive` by default.
+pub const DEFAULT_GUEST_FEATURES: u64 = 0x1;
+
+/// Name of the reference value of the launch measurement.
+pub const MEASUREMENT_CLAIM: &str = "snp.measurement";
+
+/// The reference value expires in the default time (months).
+const DEFAULT_EXPIRED_TIME: u32 = 12;
+
+const SEV_HASH_TABLE_HEADER_GUID: &str = "9438d606-4f22-4cc9-b479-a793d411fd21";
+const SEV_KERNEL_ENTRY_GUID: &str = "4de79437-abd2-427f-b835-d5b172d2045b";
+const SEV_INITRD_ENTRY_GUID: &str = "44baf731-3a2f-4bd7-9af1-41e29169781d";
+const SEV_CMDLINE_ENTRY_GUID: &str = "97d02dd8-bd20-4c94-aa78-e7714d36ab2a";
+
+/// CPUID signatures of the vCPU types of QEMU, by family, model and
+/// stepping.
+const VCPU_TYPES: &[(&str, (u32, u32, u32))] = &[
+    ("EPYC", (23, 1, 2)),
+    ("EPYC-v1", (23, 1, 2)),
+    ("EPYC-v2", (23, 1, 2)),
+    ("EPYC-IBPB", (23, 1, 2)),
+    ("EPYC-v3", (23, 1, 2)),
+    ("EPYC-v4", (23, 1, 2)),
+    ("EPYC-Rome", (23, 49, 0)),
+    ("EPYC-Rome-v1", (23, 49, 0)),
+    ("EPYC-Rome-v2", (23, 49, 0)),
+    ("EPYC-Rome-v3", (23, 49, 0)),
+    ("EPYC-Milan", (25, 1, 1)),
+    ("EPYC-Milan-v1", (25, 1, 1)),
+    ("EPYC-Milan-v2", (25, 1, 1)),
+    ("EPYC-Genoa", (25, 17, 0)),
+    ("EPYC-Genoa-v1", (25, 17, 0)),
+];
+
+/// The guest to measure.
+#[derive(Debug, Clone)]
+pub struct GuestConfig {
+    pub ovmf: Vec<u8>,
+    /// The kernel, initrd and command line of a direct boot, whose hashes
+    /// are measured.
+    pub kernel: Option<Vec<u8>>,
+    pub initrd: Option<Vec<u8>>,
+    pub append: Option<String>,
+    pub vcpus: u32,
+    /// CPUID signature of the vCPUs, see [`vcpu_sig`].
+    pub vcpu_sig: u32,
+    pub guest_features: u64,
+}
+
+/// The CPUID signature (EAX of leaf 1) of a QEMU vCPU type, e.g.
+/// `EPYC-Milan`.
+pub fn vcpu_sig(vcpu_type: &str) -> Result<u32> {
+    let (_, (family, model, stepping)) = VCPU_TYPES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(vcpu_type))
+        .ok_or_else(|| anyhow!("Unknown vCPU type {vcpu_type}"))?;
+    let (family_low, family_high) = match family {
+        family if *family > 0xf => (0xf, family - 0xf),
+        family => (*family, 0),
+    };
+    Ok((family_high << 20)
+        | ((model >> 4) << 16)
+        | (family_low << 8)
+        | ((model & 0xf) << 4)
+        | (stepping & 0xf))
+}
+
+fn sha384(data: &[u8]) -> [u8; 48] {
+    let mut digest = [0; 48];
+    digest.copy_from_slice(&Sha384::digest(data));
+    digest
+}
+
+/// The launch digest, extended with every page added to the guest as the
+/// SNP_LAUNCH_UPDATE command of the firmware does.
+struct LaunchDigest([u8; 48]);
+
+#[derive(Clone, Copy)]
+enum PageType {
+    Normal = 1,
+    Vmsa = 2,
+    Zero = 3,
+    Secrets = 5,
+    Cpuid = 6,
+}
+
+impl LaunchDigest {
+    fn update(&mut self, page_type: PageType, gpa: u64, contents: &[u8; 48]) {
+        let mut page_info = Vec::with_capacity(PAGE_INFO_SIZE as usize);
+        page_info.extend_from_slice(&self.0);
+        page_info.extend_from_slice(contents);
+        page_info.extend_from_slice(&PAGE_INFO_SIZE.to_le_bytes());
+        page_info.push(page_type as u8);
+        // Not an IMI page, no VMPL permissions.
+        page_info.extend_from_slice(&[0; 5]);
+        page_info.extend_from_slice(&gpa.to_le_bytes());
+        self.0 = sha384(&page_info);
+    }
+
+    fn update_normal_pages(&mut self, gpa: u64, data: &[u8]) {
+        for (i, page) in data.chunks(PAGE_SIZE).enumerate() {
+            let gpa = gpa + (i * PAGE_SIZE) as u64;
+            self.update(PageType::Normal, gpa, &sha384(page));
+        }
+    }
+
+    fn update_zero_pages(&mut self, gpa: u64, size: u32) {
+        for offset in (0..size as u64).step_by(PAGE_SIZE) {
+            self.update(PageType::Zero, gpa + offset, &[0; 48]);
+        }
+    }
+}
+
+/// The page of the table of the kernel, initrd and command line hashes,
+/// which OVMF checks before booting them. The table is at `offset` in the
+/// page.
+fn kernel_hashes_page(guest: &GuestConfig, kernel: &[u8], offset: usize) -> Result<Vec<u8>> {
+    let cmdline = match &guest.append {
+        Some(append) => [append.as_bytes(), b"\0"].concat(),
+        None => b"\0".to_vec(),
+    };
+    let entries = [
+        (SEV_CMDLINE_ENTRY_GUID, Sha256::digest(cmdline)),
+        (
+            SEV_INITRD_ENTRY_GUID,
+            Sha256::digest(guest.initrd.as_deref().unwrap_or_default()),
+        ),
+        (SEV_KERNEL_ENTRY_GUID, Sha256::digest(kernel)),
+    ];
+
+    // Each entry is its GUID, its length and its hash, after the GUID and
+    // the length of the table.
+    let entry_size = 16 + 2 + 32;
+    let table_size = 16 + 2 + entries.len() * entry_size;
+    let mut table = uuid_bytes(SEV_HASH_TABLE_HEADER_GUID);
+    table.extend_from_slice(&(table_size as u16).to_le_bytes());
+    for (guid, hash) in entries {
+        table.extend_from_slice(&uuid_bytes(guid));
+        table.extend_from_slice(&(entry_size as u16).to_le_bytes());
+        table.extend_from_slice(&hash);
+    }
+    // Padded to 16 bytes.
+    table.resize((table_size + 15) & !15, 0);
+
+    let mut page = vec![0; PAGE_SIZE];
+    page.get_mut(offset..offset + table.len())
+        .ok_or_else(|| anyhow!("The kernel hashes table crosses a page"))?
+        .copy_from_slice(&table);
+    Ok(page)
+}
+
+fn uuid_bytes(guid: &str) -> Vec<u8> {
+    uuid::Uuid::parse_str(guid)
+        .expect("valid GUID")
+        .to_bytes_le()
+        .to_vec()
+}
+
+/// The launch measurement of `guest`, as in the `measurement` of its SNP
+/// attestation reports.
+pub fn launch_measurement(guest: &GuestConfig) -> Result<[u8; 48]> {
+    if guest.vcpus == 0 {
+        bail!("The guest has no vCPUs");
+    }
+    if guest.kernel.is_none() && (guest.initrd.is_some() || guest.append.is_some()) {
+        bail!("An initrd or command line is only measured with a kernel");
+    }
+    let ovmf = ovmf::Ovmf::parse(&guest.ovmf)?;
+    let mut digest = LaunchDigest([0; 48]);
+    digest.update_normal_pages(ovmf.gpa(), ovmf.data());
+
+    let mut hashes_measured = false;
+    for section in ovmf.sections()? {
+        let gpa = section.gpa as u64;
+        match section.section_type {
+            ovmf::SNP_SEC_MEMORY | ovmf::SVSM_CAA => digest.update_zero_pages(gpa, section.size),
+            ovmf::SNP_SECRETS => digest.update(PageType::Secrets, gpa, &[0; 48]),
+            ovmf::CPUID => digest.update(PageType::Cpuid, gpa, &[0; 48]),
+            ovmf::SNP_KERNEL_HASHES => match &guest.kernel {
+                Some(kernel) => {
+                    if section.size as usize != PAGE_SIZE {
+                        bail!("The kernel hashes section of OVMF is not a page");
+                    }
+                    let offset = ovmf.sev_hashes_table_gpa()? as usize & (PAGE_SIZE - 1);
+                    digest.update_normal_pages(gpa, &kernel_hashes_page(guest, kernel, offset)?);
+                    hashes_measured = true;
+                }
+                None => digest.update_zero_pages(gpa, section.size),
+            },
+            other => bail!("Unknown type {other:#x} of an OVMF SEV metadata section"),
+        }
+    }
+    if guest.kernel.is_some() && !hashes_measured {
+        bail!("The OVMF binary does not measure the kernel of a direct boot");
+    }
+
+    let ap_eip = match ovmf.sev_es_reset_eip()? {
+        Some(eip) => eip,
+        None if guest.vcpus == 1 => 0,
+        None => bail!("The OVMF binary has no reset vector for the APs"),
+    };
+    for page in vmsa::pages(guest.vcpus, ap_eip, guest.vcpu_sig, guest.guest_features) {
+        digest.update(PageType::Vmsa, VMSA_GPA, &sha384(&page));
+    }
+    Ok(digest.0)
+}
+
+/// The value of the `snp.measurement` claim of a launch measurement.
+pub fn measurement_claim(measurement: &[u8; 48]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(measurement)
+}
+
+/// A reference value of the `snp.measurement` claim for `measurement`.
+pub fn reference_value(measurement: &[u8; 48]) -> Result<ReferenceValue> {
+    let expired = Utc::now()
+        .checked_add_months(Months::new(DEFAULT_EXPIRED_TIME))
+        .ok_or_else(|| anyhow!("Expired time calculated overflowed"))?;
+    Ok(ReferenceValue::new()?
+        .set_name(MEASUREMENT_CLAIM)
+        .set_expired(expired)
+        .set_metadata("provenance", "snp-launch-measurement")
+        .add_hash_value("sha384".to_string(), measurement_claim(measurement)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ovmf::Section;
+
+    fn guest(kernel: Option<&[u8]>) -> GuestConfig {
+        let sections = [
+            (0x80_0000, 0x9000, ovmf::SNP_SEC_MEMORY),
+            (0x80_9000, 0x1000, ovmf::SNP_SECRETS),
+            (0x80_a000, 0x1000, ovmf::CPUID),
+            (0x80_b000, 0x1000, ovmf::SNP_KERNEL_HASHES),
+        ]
+        .map(|(gpa, size, section_type)| Section {
+            gpa,
+            size,
+            section_type,
+        });
+        let entries = ovmf::build_entries(0x80_bc00, 0xffff_f000);
+        GuestConfig {
+            ovmf: ovmf::build(0x4000, &sections, &entries),
+            kernel: kernel.map(<[u8]>::to_vec),
+            initrd: kernel.map(|_| b"initrd".to_vec()),
+            append: kernel.map(|_| "console=ttyS0".to_string()),
+            vcpus: 2,
+            vcpu_sig: vcpu_sig("EPYC-Milan").unwrap(),
+            guest_features: DEFAULT_GUEST_FEATURES,
+        }
+    }
+
+    #[test]
+    fn test_vcpu_sig() {
+        assert_eq!(vcpu_sig("EPYC-v4").unwrap(), 0x800f12);
+        assert_eq!(vcpu_sig("EPYC-Rome").unwrap(), 0x830f10);
+        assert_eq!(vcpu_sig("epyc-milan").unwrap(), 0xa00f11);
+        assert_eq!(vcpu_sig("EPYC-Genoa").unwrap(), 0xa10f10);
+        assert!(vcpu_sig("Opteron_G5").is_err());
+    }
+
+    #[test]
+    fn test_launch_measurement() {
+        // Computed independently from the same guest.
+        assert_eq!(
+            hex::encode(launch_measurement(&guest(None)).unwrap()),
+            "2c0eea23921b701d174fac3e9883a51d3ee0a793517a7de6329cd02217870acded38de43dea70f4fe8b3746b170dc2e2"
+        );
+        assert_eq!(
+            hex::encode(launch_measurement(&guest(Some(b"kernel"))).unwrap()),
+            "29148b3c303dfbddd99dfe071210a66426ad4712f69d255ea5f7de15184473c0c481b5ad9fb649a7f81a616a02136c76"
+        );
+
+        let mut single = guest(None);
+        single.vcpus = 1;
+        assert_ne!(
+            launch_measurement(&single).unwrap(),
+            launch_measurement(&guest(None)).unwrap()
+        );
+        single.vcpus = 0;
+        assert!(launch_measurement(&single).is_err());
+        let mut no_kernel = guest(None);
+        no_kernel.append = Some("console=ttyS0".to_string());
+        assert!(launch_measurement(&no_kernel).is_err());
+    }
+
+    #[test]
+    fn test_reference_value() {
+        let rv = reference_value(&[0xff; 48]).unwrap();
+        assert_eq!(rv.name(), MEASUREMENT_CLAIM);
+        assert_eq!(rv.hash_values()[0].value(), &"/".repeat(64));
+    }
+}
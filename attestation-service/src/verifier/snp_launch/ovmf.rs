@@ -0,0 +1,248 @@
+//! The parts of an OVMF binary that the launch measurement depends on: the
+//! GUIDed table at the end of the binary, which locates the SEV metadata
+//! sections, the kernel hashes table and the reset vector of the APs. The
+//! table also locates the TDX metadata of TDVF, see
+//! [`crate::verifier::tdx_launch`].
+
+use anyhow::*;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// OVMF is mapped right below 4 GiB.
+const FOUR_GB: u64 = 0x1_0000_0000;
+
+const FOOTER_TABLE_GUID: &str = "96b582de-1fb2-45f7-baea-a366c55a082d";
+const SEV_HASH_TABLE_RV_GUID: &str = "7255371f-3a3b-4b04-927b-1da6efa8d454";
+const SEV_ES_RESET_BLOCK_GUID: &str = "00f771de-1a7e-4fcb-890e-68c77e2fb44e";
+const SEV_METADATA_GUID: &str = "dc886566-984a-4798-a75e-5585a7bf67cc";
+
+/// Size of the header of a table entry: its size, then its GUID.
+const ENTRY_HEADER_SIZE: usize = 2 + 16;
+
+/// The table ends 32 bytes before the end of the binary.
+const FOOTER_OFFSET_FROM_END: usize = 32;
+
+/// Signature, size, version and number of sections.
+const METADATA_HEADER_SIZE: usize = 16;
+const METADATA_SIGNATURE: &[u8] = b"ASEV";
+/// GPA, size and type.
+const SECTION_DESC_SIZE: usize = 12;
+
+/// Types of the SEV metadata sections.
+pub const SNP_SEC_MEMORY: u32 = 1;
+pub const SNP_SECRETS: u32 = 2;
+pub const CPUID: u32 = 3;
+pub const SVSM_CAA: u32 = 4;
+pub const SNP_KERNEL_HASHES: u32 = 0x10;
+
+/// A SEV metadata section, that the VMM adds to the guest memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub gpa: u32,
+    pub size: u32,
+    pub section_type: u32,
+}
+
+pub struct Ovmf<'a> {
+    data: &'a [u8],
+    table: HashMap<Uuid, &'a [u8]>,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    let bytes = data
+        .get(offset..offset + 2)
+        .ok_or_else(|| anyhow!("OVMF binary truncated at {offset:#x}"))?;
+    Ok(u16::from_le_bytes(bytes.try_into()?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .ok_or_else(|| anyhow!("OVMF binary truncated at {offset:#x}"))?;
+    Ok(u32::from_le_bytes(bytes.try_into()?))
+}
+
+/// The GUID of the 16 bytes at `offset`, in the mixed endian encoding of
+/// UEFI.
+fn read_guid(data: &[u8], offset: usize) -> Result<Uuid> {
+    let bytes = data
+        .get(offset..offset + 16)
+        .ok_or_else(|| anyhow!("OVMF binary truncated at {offset:#x}"))?;
+    Ok(Uuid::from_bytes_le(bytes.try_into()?))
+}
+
+fn guid(guid: &str) -> Uuid {
+    Uuid::parse_str(guid).expect("valid GUID")
+}
+
+impl<'a> Ovmf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let footer = data
+            .len()
+            .checked_sub(FOOTER_OFFSET_FROM_END + ENTRY_HEADER_SIZE)
+            .ok_or_else(|| anyhow!("OVMF binary too small"))?;
+        if read_guid(data, footer + 2)? != guid(FOOTER_TABLE_GUID) {
+            bail!("No GUIDed table at the end of the OVMF binary");
+        }
+        let table_size = (read_u16(data, footer)? as usize)
+            .checked_sub(ENTRY_HEADER_SIZE)
+            .ok_or_else(|| anyhow!("Illegal size of the OVMF table"))?;
+        let mut entries = footer
+            .checked_sub(table_size)
+            .and_then(|start| data.get(start..footer))
+            .ok_or_else(|| anyhow!("Illegal size of the OVMF table"))?;
+
+        // Entries are read from the end of the table.
+        let mut table = HashMap::new();
+        while entries.len() >= ENTRY_HEADER_SIZE {
+            let header = entries.len() - ENTRY_HEADER_SIZE;
+            let size = read_u16(entries, header)? as usize;
+            if size < ENTRY_HEADER_SIZE || size > entries.len() {
+                bail!("Illegal size of an OVMF table entry");
+            }
+            table.insert(
+                read_guid(entries, header + 2)?,
+                &entries[entries.len() - size..header],
+            );
+            entries = &entries[..entries.len() - size];
+        }
+        Ok(Self { data, table })
+    }
+
+    /// Where the binary is mapped in the guest.
+    pub fn gpa(&self) -> u64 {
+        FOUR_GB - self.data.len() as u64
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The data of the table entry of GUID `name`.
+    pub fn entry(&self, name: &str) -> Option<&'a [u8]> {
+        self.table.get(&guid(name)).copied()
+    }
+
+    /// GPA of the table of the kernel, initrd and command line hashes.
+    pub fn sev_hashes_table_gpa(&self) -> Result<u32> {
+        let entry = self
+            .entry(SEV_HASH_TABLE_RV_GUID)
+            .ok_or_else(|| anyhow!("The OVMF binary has no kernel hashes table"))?;
+        read_u32(entry, 0)
+    }
+
+    /// Reset vector of the APs, `None` if OVMF does not support SEV-ES.
+    pub fn sev_es_reset_eip(&self) -> Result<Option<u32>> {
+        self.entry(SEV_ES_RESET_BLOCK_GUID)
+            .map(|entry| read_u32(entry, 0))
+            .transpose()
+    }
+
+    /// The SEV metadata sections, in order.
+    pub fn sections(&self) -> Result<Vec<Section>> {
+        let entry = self
+            .entry(SEV_METADATA_GUID)
+            .ok_or_else(|| anyhow!("The OVMF binary has no SEV metadata"))?;
+        let offset_from_end = read_u32(entry, 0)? as usize;
+        let start = self
+            .data
+            .len()
+            .checked_sub(offset_from_end)
+            .ok_or_else(|| anyhow!("Illegal offset of the SEV metadata"))?;
+        if self.data.get(start..start + 4) != Some(METADATA_SIGNATURE) {
+            bail!("Illegal signature of the SEV metadata");
+        }
+        let size = read_u32(self.data, start + 4)? as usize;
+        let count = read_u32(self.data, start + 12)? as usize;
+        if METADATA_HEADER_SIZE + count * SECTION_DESC_SIZE > size {
+            bail!("The SEV metadata is too small for its {count} sections");
+        }
+        (0..count)
+            .map(|i| {
+                let desc = start + METADATA_HEADER_SIZE + i * SECTION_DESC_SIZE;
+                Ok(Section {
+                    gpa: read_u32(self.data, desc)?,
+                    size: read_u32(self.data, desc + 4)?,
+                    section_type: read_u32(self.data, desc + 8)?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A minimal OVMF binary, with a table of the given entries and SEV
+/// metadata of the given sections.
+#[cfg(test)]
+pub(super) fn build(size: usize, sections: &[Section], entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut data = vec![0xaa; size];
+
+    // The metadata at the start of the binary.
+    let mut metadata = METADATA_SIGNATURE.to_vec();
+    let metadata_size = METADATA_HEADER_SIZE + sections.len() * SECTION_DESC_SIZE;
+    metadata.extend_from_slice(&(metadata_size as u32).to_le_bytes());
+    metadata.extend_from_slice(&1u32.to_le_bytes());
+    metadata.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+    for section in sections {
+        metadata.extend_from_slice(&section.gpa.to_le_bytes());
+        metadata.extend_from_slice(&section.size.to_le_bytes());
+        metadata.extend_from_slice(&section.section_type.to_le_bytes());
+    }
+    data[..metadata.len()].copy_from_slice(&metadata);
+
+    let mut table = Vec::new();
+    let metadata_entry = (SEV_METADATA_GUID, (size as u32).to_le_bytes().to_vec());
+    for (name, entry) in entries.iter().chain([&metadata_entry]) {
+        table.extend_from_slice(entry);
+        table.extend_from_slice(&((entry.len() + ENTRY_HEADER_SIZE) as u16).to_le_bytes());
+        table.extend_from_slice(&guid(name).to_bytes_le());
+    }
+    table.extend_from_slice(&((table.len() + ENTRY_HEADER_SIZE) as u16).to_le_bytes());
+    table.extend_from_slice(&guid(FOOTER_TABLE_GUID).to_bytes_le());
+    let end = size - FOOTER_OFFSET_FROM_END;
+    data[end - table.len()..end].copy_from_slice(&table);
+    data
+}
+
+#[cfg(test)]
+pub(super) fn build_entries(hashes_table_gpa: u32, reset_eip: u32) -> Vec<(&'static str, Vec<u8>)> {
+    let mut hashes_table = hashes_table_gpa.to_le_bytes().to_vec();
+    hashes_table.extend_from_slice(&0x400u32.to_le_bytes());
+    vec![
+        (SEV_HASH_TABLE_RV_GUID, hashes_table),
+        (SEV_ES_RESET_BLOCK_GUID, reset_eip.to_le_bytes().to_vec()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let sections = vec![
+            Section {
+                gpa: 0x80_0000,
+                size: 0x9000,
+                section_type: SNP_SEC_MEMORY,
+            },
+            Section {
+                gpa: 0x80_d000,
+                size: 0x1000,
+                section_type: SNP_SECRETS,
+            },
+        ];
+        let data = build(0x20_0000, &sections, &build_entries(0x80_ac00, 0xffff_f000));
+        let ovmf = Ovmf::parse(&data).unwrap();
+        assert_eq!(ovmf.gpa(), 0xffe0_0000);
+        assert_eq!(ovmf.sections().unwrap(), sections);
+        assert_eq!(ovmf.sev_hashes_table_gpa().unwrap(), 0x80_ac00);
+        assert_eq!(ovmf.sev_es_reset_eip().unwrap(), Some(0xffff_f000));
+
+        let data = build(0x20_0000, &sections, &[]);
+        let ovmf = Ovmf::parse(&data).unwrap();
+        assert!(ovmf.sev_hashes_table_gpa().is_err());
+        assert_eq!(ovmf.sev_es_reset_eip().unwrap(), None);
+
+        assert!(Ovmf::parse(&[0; 0x1000]).is_err());
+    }
+}
@@ -0,0 +1,84 @@
+//! The initial VMSA of the vCPUs of a QEMU/KVM guest, as measured by the
+//! firmware: the `sev_es_save_area` of the Linux kernel, in the state KVM
+//! resets the vCPUs to.
+
+use super::PAGE_SIZE;
+
+/// Reset vector of the BSP.
+const BSP_EIP: u32 = 0xffff_fff0;
+
+// Offsets of the fields of the save area.
+const ES: usize = 0x000;
+const CS: usize = 0x010;
+const SS: usize = 0x020;
+const DS: usize = 0x030;
+const FS: usize = 0x040;
+const GS: usize = 0x050;
+const GDTR: usize = 0x060;
+const LDTR: usize = 0x070;
+const IDTR: usize = 0x080;
+const TR: usize = 0x090;
+const EFER: usize = 0x0d0;
+const CR4: usize = 0x148;
+const CR0: usize = 0x158;
+const DR7: usize = 0x160;
+const DR6: usize = 0x168;
+const RFLAGS: usize = 0x170;
+const RIP: usize = 0x178;
+const G_PAT: usize = 0x268;
+const RDX: usize = 0x310;
+const SEV_FEATURES: usize = 0x3b0;
+const XCR0: usize = 0x3e8;
+const MXCSR: usize = 0x408;
+const X87_FCW: usize = 0x410;
+
+fn put(page: &mut [u8], offset: usize, value: &[u8]) {
+    page[offset..offset + value.len()].copy_from_slice(value);
+}
+
+/// A `vmcb_seg`: selector, attributes, limit and base.
+fn put_segment(page: &mut [u8], offset: usize, selector: u16, attrib: u16, base: u64) {
+    put(page, offset, &selector.to_le_bytes());
+    put(page, offset + 2, &attrib.to_le_bytes());
+    put(page, offset + 4, &0xffffu32.to_le_bytes());
+    put(page, offset + 8, &base.to_le_bytes());
+}
+
+/// The VMSA page of a vCPU that starts at `eip`.
+fn save_area(eip: u32, vcpu_sig: u32, sev_features: u64) -> Vec<u8> {
+    let mut page = vec![0; PAGE_SIZE];
+    for segment in [ES, SS, DS, FS, GS] {
+        put_segment(&mut page, segment, 0, 0x93, 0);
+    }
+    put_segment(&mut page, CS, 0xf000, 0x9b, (eip & 0xffff_0000) as u64);
+    put_segment(&mut page, GDTR, 0, 0, 0);
+    put_segment(&mut page, LDTR, 0, 0x82, 0);
+    put_segment(&mut page, IDTR, 0, 0, 0);
+    put_segment(&mut page, TR, 0, 0x8b, 0);
+
+    // KVM enables EFER.SVME and CR4.MCE.
+    put(&mut page, EFER, &0x1000u64.to_le_bytes());
+    put(&mut page, CR4, &0x40u64.to_le_bytes());
+    put(&mut page, CR0, &0x10u64.to_le_bytes());
+    put(&mut page, DR7, &0x400u64.to_le_bytes());
+    put(&mut page, DR6, &0xffff_0ff0u64.to_le_bytes());
+    put(&mut page, RFLAGS, &0x2u64.to_le_bytes());
+    put(&mut page, RIP, &((eip & 0xffff) as u64).to_le_bytes());
+    put(&mut page, G_PAT, &0x0007_0406_0007_0406u64.to_le_bytes());
+    put(&mut page, RDX, &(vcpu_sig as u64).to_le_bytes());
+    put(&mut page, SEV_FEATURES, &sev_features.to_le_bytes());
+    put(&mut page, XCR0, &0x1u64.to_le_bytes());
+    put(&mut page, MXCSR, &0x1f80u32.to_le_bytes());
+    put(&mut page, X87_FCW, &0x37fu16.to_le_bytes());
+    page
+}
+
+/// The VMSA pages of `vcpus` vCPUs: the BSP, then the APs, which start at
+/// `ap_eip`.
+pub fn pages(vcpus: u32, ap_eip: u32, vcpu_sig: u32, sev_features: u64) -> Vec<Vec<u8>> {
+    let bsp = save_area(BSP_EIP, vcpu_sig, sev_features);
+    let ap = save_area(ap_eip, vcpu_sig, sev_features);
+    std::iter::once(bsp)
+        .chain(std::iter::repeat_n(ap, vcpus.saturating_sub(1) as usize))
+        .collect()
+}
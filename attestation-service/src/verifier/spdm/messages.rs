@@ -0,0 +1,268 @@
+//! SPDM 1.1 `GET_MEASUREMENTS` request and `MEASUREMENTS` response, as
+//! defined in the DMTF [SPDM specification](https://www.dmtf.org/dsp/DSP0274).
+//!
+//! Lengths declared in the messages are not trusted: messages that are
+//! truncated or declare more than they hold fail to parse. Later SPDM
+//! versions sign a transcript hash rather than the messages, and are not
+//! supported.
+
+use anyhow::*;
+use std::collections::BTreeMap;
+
+/// `RequestResponseCode` of `GET_MEASUREMENTS`.
+const GET_MEASUREMENTS: u8 = 0xe0;
+
+/// `RequestResponseCode` of `MEASUREMENTS`.
+const MEASUREMENTS: u8 = 0x60;
+
+/// Supported `SPDMVersion`s, 1.0 and 1.1.
+const VERSIONS: [u8; 2] = [0x10, 0x11];
+
+/// Bit of `Param1` of `GET_MEASUREMENTS` requesting a signed response.
+const SIGNATURE_REQUESTED: u8 = 0x01;
+
+/// Size of the nonces of the request and the response.
+pub const NONCE_SIZE: usize = 32;
+
+/// Size of an ECDSA P-384 signature, `r` then `s`.
+pub const SIGNATURE_SIZE: usize = 96;
+
+/// Bit of the `MeasurementSpecification` of a block in the DMTF format.
+const DMTF_SPECIFICATION: u8 = 0x01;
+
+/// Reads the fields of a message one after the other.
+struct Reader<'a> {
+    data: &'a [u8],
+    message: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], message: &'static str) -> Self {
+        Self { data, message }
+    }
+
+    fn bytes(&mut self, len: usize, field: &str) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            bail!(
+                "{} {field} of {len} bytes exceeds the {} bytes left",
+                self.message,
+                self.data.len()
+            );
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self, field: &str) -> Result<u8> {
+        Ok(self.bytes(1, field)?[0])
+    }
+
+    fn u16(&mut self, field: &str) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2, field)?.try_into()?))
+    }
+
+    fn u24(&mut self, field: &str) -> Result<usize> {
+        let bytes = self.bytes(3, field)?;
+        Ok(usize::from(bytes[0]) | usize::from(bytes[1]) << 8 | usize::from(bytes[2]) << 16)
+    }
+}
+
+/// A signed `GET_MEASUREMENTS` request.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MeasurementsRequest {
+    pub version: u8,
+    pub nonce: [u8; NONCE_SIZE],
+}
+
+impl MeasurementsRequest {
+    /// Parse the request at the start of `data`, and return it with its
+    /// size.
+    pub fn parse(data: &[u8]) -> Result<(Self, usize)> {
+        let mut reader = Reader::new(data, "GET_MEASUREMENTS");
+        let version = reader.u8("SPDMVersion")?;
+        if !VERSIONS.contains(&version) {
+            bail!("Unsupported SPDM version {version:#x}");
+        }
+        if reader.u8("RequestResponseCode")? != GET_MEASUREMENTS {
+            bail!("Not a GET_MEASUREMENTS request");
+        }
+        if reader.u8("Param1")? & SIGNATURE_REQUESTED == 0 {
+            bail!("GET_MEASUREMENTS does not request a signature");
+        }
+        reader.u8("Param2")?;
+        let nonce = reader.bytes(NONCE_SIZE, "Nonce")?.try_into()?;
+        reader.u8("SlotIDParam")?;
+        Ok((Self { version, nonce }, data.len() - reader.data.len()))
+    }
+}
+
+/// A signed `MEASUREMENTS` response.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MeasurementsResponse<'a> {
+    /// Measurement values in the DMTF format, by index.
+    pub measurements: BTreeMap<u8, &'a [u8]>,
+    pub nonce: [u8; NONCE_SIZE],
+    pub opaque_data: &'a [u8],
+    /// The part of the response covered by the signature.
+    pub signed: &'a [u8],
+    pub signature: &'a [u8],
+}
+
+impl<'a> MeasurementsResponse<'a> {
+    /// Parse the response `data`, of a request of SPDM `version`.
+    pub fn parse(data: &'a [u8], version: u8) -> Result<Self> {
+        let mut reader = Reader::new(data, "MEASUREMENTS");
+        if reader.u8("SPDMVersion")? != version {
+            bail!("MEASUREMENTS version does not match the request");
+        }
+        if reader.u8("RequestResponseCode")? != MEASUREMENTS {
+            bail!("Not a MEASUREMENTS response");
+        }
+        reader.u8("Param1")?;
+        reader.u8("Param2")?;
+        let blocks = reader.u8("NumberOfBlocks")?;
+        let record_length = reader.u24("MeasurementRecordLength")?;
+        let mut record = Reader::new(
+            reader.bytes(record_length, "MeasurementRecord")?,
+            "Measurement block",
+        );
+        let nonce = reader.bytes(NONCE_SIZE, "Nonce")?.try_into()?;
+        let opaque_length = reader.u16("OpaqueLength")?;
+        let opaque_data = reader.bytes(opaque_length.into(), "OpaqueData")?;
+        let signed = &data[..data.len() - reader.data.len()];
+        let signature = reader.bytes(SIGNATURE_SIZE, "Signature")?;
+        if !reader.data.is_empty() {
+            bail!(
+                "{} bytes after the MEASUREMENTS signature",
+                reader.data.len()
+            );
+        }
+
+        let mut measurements = BTreeMap::new();
+        for _ in 0..blocks {
+            let index = record.u8("Index")?;
+            let specification = record.u8("MeasurementSpecification")?;
+            let size = record.u16("MeasurementSize")?;
+            let mut block = Reader::new(record.bytes(size.into(), "Measurement")?, "Measurement");
+            if specification & DMTF_SPECIFICATION == 0 {
+                bail!("Measurement {index} is not in the DMTF format");
+            }
+            block.u8("DMTFSpecMeasurementValueType")?;
+            let value_size = block.u16("DMTFSpecMeasurementValueSize")?;
+            let value = block.bytes(value_size.into(), "DMTFSpecMeasurementValue")?;
+            if measurements.insert(index, value).is_some() {
+                bail!("Measurement {index} given twice");
+            }
+        }
+        if !record.data.is_empty() {
+            bail!("MeasurementRecord holds more than {blocks} blocks");
+        }
+
+        Ok(Self {
+            measurements,
+            nonce,
+            opaque_data,
+            signed,
+            signature,
+        })
+    }
+}
+
+/// The fields of opaque data made of `DataType`, `DataSize` then the data,
+/// by type.
+pub fn opaque_fields(data: &[u8]) -> Result<BTreeMap<u16, &[u8]>> {
+    let mut reader = Reader::new(data, "Opaque data");
+    let mut fields = BTreeMap::new();
+    while !reader.data.is_empty() {
+        let data_type = reader.u16("DataType")?;
+        let size = reader.u16("DataSize")?;
+        fields.insert(data_type, reader.bytes(size.into(), "Data")?);
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A signed `GET_MEASUREMENTS` request of all the measurements.
+    pub fn request(nonce: &[u8; NONCE_SIZE]) -> Vec<u8> {
+        let mut request = vec![0x11, GET_MEASUREMENTS, SIGNATURE_REQUESTED, 0xff];
+        request.extend_from_slice(nonce);
+        request.push(0);
+        request
+    }
+
+    /// A `MEASUREMENTS` response of `measurements` without its signature.
+    pub fn response(measurements: &[(u8, &[u8])], opaque_data: &[u8]) -> Vec<u8> {
+        let mut record = Vec::new();
+        for (index, value) in measurements {
+            record.extend_from_slice(&[*index, DMTF_SPECIFICATION]);
+            record.extend_from_slice(&(value.len() as u16 + 3).to_le_bytes());
+            record.push(0x01);
+            record.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            record.extend_from_slice(value);
+        }
+        let mut response = vec![0x11, MEASUREMENTS, 0, 0, measurements.len() as u8];
+        response.extend_from_slice(&(record.len() as u32).to_le_bytes()[..3]);
+        response.extend_from_slice(&record);
+        response.extend_from_slice(&[0x5a; NONCE_SIZE]);
+        response.extend_from_slice(&(opaque_data.len() as u16).to_le_bytes());
+        response.extend_from_slice(opaque_data);
+        response
+    }
+
+    #[test]
+    fn test_parse_measurements() {
+        let data = request(&[7; NONCE_SIZE]);
+        let (request, size) = MeasurementsRequest::parse(&data).unwrap();
+        assert_eq!(size, data.len());
+        assert_eq!(request.nonce, [7; NONCE_SIZE]);
+
+        let opaque_data = [3, 0, 4, 0, b'5', b'3', b'5', 0];
+        let mut data = response(&[(1, &[0xaa; 48]), (2, &[0xbb; 48])], &opaque_data);
+        let signed = data.len();
+        data.extend_from_slice(&[0; SIGNATURE_SIZE]);
+        let response = MeasurementsResponse::parse(&data, request.version).unwrap();
+        assert_eq!(response.measurements[&1], &[0xaa; 48]);
+        assert_eq!(response.measurements[&2], &[0xbb; 48]);
+        assert_eq!(response.nonce, [0x5a; NONCE_SIZE]);
+        assert_eq!(response.signed, &data[..signed]);
+        assert_eq!(response.signature.len(), SIGNATURE_SIZE);
+        assert_eq!(opaque_fields(response.opaque_data).unwrap()[&3], b"535\0");
+    }
+
+    #[test]
+    fn test_reject_malformed_measurements() {
+        let data = request(&[7; NONCE_SIZE]);
+        for len in 0..data.len() {
+            assert!(MeasurementsRequest::parse(&data[..len]).is_err());
+        }
+        let mut later = data.clone();
+        later[0] = 0x12;
+        assert!(MeasurementsRequest::parse(&later).is_err());
+
+        let mut data = response(&[(1, &[0xaa; 48])], &[]);
+        data.extend_from_slice(&[0; SIGNATURE_SIZE]);
+        for len in 0..data.len() {
+            assert!(MeasurementsResponse::parse(&data[..len], 0x11).is_err());
+        }
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert!(MeasurementsResponse::parse(&trailing, 0x11).is_err());
+        assert!(MeasurementsResponse::parse(&data, 0x12).is_err());
+
+        // Record length beyond the response
+        let mut overlong = data.clone();
+        overlong[5..8].copy_from_slice(&[0xff, 0xff, 0xff]);
+        assert!(MeasurementsResponse::parse(&overlong, 0x11).is_err());
+
+        // Block size beyond the record
+        let mut overlong = data;
+        overlong[10..12].copy_from_slice(&0xffffu16.to_le_bytes());
+        assert!(MeasurementsResponse::parse(&overlong, 0x11).is_err());
+
+        assert!(opaque_fields(&[3, 0, 4, 0, b'5']).is_err());
+    }
+}
@@ -0,0 +1,618 @@
+//! Verification of the SPDM evidence of devices attached to TEE evidence,
+//! so that GPUs, SmartNICs or storage devices that speak SPDM are attested
+//! along with the CPU TEE, in composite evidence:
+//!
+//! ```json
+//! {
+//!     "quote": "...",
+//!     "nic": [
+//!         {
+//!             "attestation_report": "<base64>",
+//!             "certificate_chain": "-----BEGIN CERTIFICATE-----..."
+//!         }
+//!     ]
+//! }
+//! ```
+//!
+//! Each kind of device has a [`DeviceProfile`], which names the field of
+//! the TEE evidence with the evidence of the devices, and the claims of the
+//! devices. The AS verifies the devices of the profiles of `spdm_devices`
+//! in its config. The attestation report of a device is the SPDM
+//! `GET_MEASUREMENTS` request of the host followed by the `MEASUREMENTS`
+//! response of the device, see [`messages`]:
+//!
+//! - the certificate chain of the device, leaf first, ends with the root CA
+//!   of the vendor, `root_certificate` of the profile config;
+//! - the response is signed with ECDSA P-384 and SHA-384 by the key of the
+//!   leaf certificate;
+//! - the nonce of the request is `SHA256(nonce || pubkey)`, binding the
+//!   evidence to the nonce and TEE public key like the report data of the
+//!   CPU evidence;
+//! - each measurement is compared with the reference value of its index,
+//!   `<profile>.measurements.<index>`. Measurements without reference
+//!   value are not compared.
+//!
+//! The claims of each device are added to the claims of the TEE evidence,
+//! under `<profile>.<n>`: `measurements.<index>` (hex),
+//! `measurements_match`, false when a measurement does not match its
+//! reference value or when no reference value was found, the indices of the
+//! `mismatched_measurements`, and the claims the profile takes from the
+//! opaque data of the response. Policies should require
+//! `measurements_match` of every device.
+//!
+//! Built in profiles:
+//! - `generic`: devices named in the config, whose evidence is in the field
+//!   of their name, without claims of the opaque data;
+//! - `nvidia_gpu`: NVIDIA confidential GPUs, see [`super::nvidia_gpu`].
+
+pub mod messages;
+
+use std::collections::BTreeMap;
+
+use anyhow::*;
+use kbs_types::Attestation;
+use pkcs8::der::pem;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::path::PathBuf;
+
+use super::crypto::{CryptoBackend, CryptoBackendType};
+use super::nvidia_gpu::{self, NvidiaGpuProfile};
+use super::report_data::{HashAlgorithm, ReportDataMode};
+use crate::rvps::RVPSAPI;
+use messages::{MeasurementsRequest, MeasurementsResponse, SIGNATURE_SIZE};
+
+/// A kind of SPDM device.
+pub trait DeviceProfile {
+    /// Name of the devices, which prefixes their claims and the names of
+    /// their reference values, e.g. `nvidia_gpu`.
+    fn name(&self) -> &str;
+
+    /// Field of the TEE evidence with the evidence of the devices.
+    fn evidence_field(&self) -> &str;
+
+    /// Claims of the opaque data of the `MEASUREMENTS` response of a device,
+    /// given as fields by `DataType`, see [`messages::opaque_fields`].
+    fn opaque_claims(&self, _fields: &BTreeMap<u16, &[u8]>) -> Map<String, Value> {
+        Map::new()
+    }
+}
+
+/// Devices that have no profile of their own.
+pub struct GenericProfile {
+    name: String,
+}
+
+impl DeviceProfile for GenericProfile {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn evidence_field(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileType {
+    Generic,
+    NvidiaGpu,
+}
+
+/// Local verification of the SPDM evidence of the devices of a profile.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct SpdmDeviceConfig {
+    pub profile: ProfileType,
+    /// Name of the devices of the `generic` profile, letters, digits and
+    /// `_`.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// PEM certificate of the root CA that the certificate chains of the
+    /// devices end with.
+    pub root_certificate: PathBuf,
+}
+
+impl SpdmDeviceConfig {
+    fn to_profile(&self) -> Result<Box<dyn DeviceProfile + Send + Sync>> {
+        match (self.profile, &self.name) {
+            (ProfileType::Generic, Some(name)) => {
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    bail!("Invalid SPDM device name `{name}`");
+                }
+                Ok(Box::new(GenericProfile { name: name.clone() }))
+            }
+            (ProfileType::Generic, None) => {
+                bail!("SPDM devices of the generic profile need a name")
+            }
+            (ProfileType::NvidiaGpu, None) => Ok(Box::new(NvidiaGpuProfile)),
+            (_, Some(_)) => bail!("Only SPDM devices of the generic profile are named"),
+        }
+    }
+}
+
+/// Evidence of a device.
+#[derive(Deserialize, Debug)]
+pub struct DeviceEvidence {
+    /// Base64 encoded SPDM request and response.
+    attestation_report: String,
+    /// PEM certificates of the device, leaf first.
+    certificate_chain: String,
+}
+
+/// Verifier of the devices of a profile.
+pub struct SpdmDevice {
+    profile: Box<dyn DeviceProfile + Send + Sync>,
+    /// DER certificate of the root CA of the devices.
+    root: Vec<u8>,
+    crypto: Box<dyn CryptoBackend + Send + Sync>,
+}
+
+/// Claims of a verified device.
+#[derive(Debug, Default, PartialEq)]
+pub struct DeviceClaims {
+    /// Hex encoded measurements, by index.
+    pub measurements: Vec<(u8, String)>,
+    /// Claims of the profile.
+    pub profile: Map<String, Value>,
+}
+
+impl SpdmDevice {
+    pub fn new(config: &SpdmDeviceConfig, crypto_backend: CryptoBackendType) -> Result<Self> {
+        let profile = config.to_profile()?;
+        let root = std::fs::read_to_string(&config.root_certificate).with_context(|| {
+            format!(
+                "Cannot read the {} root certificate {}",
+                profile.name(),
+                config.root_certificate.display()
+            )
+        })?;
+        let root = certificates(&root)?
+            .pop()
+            .ok_or_else(|| anyhow!("No {} root certificate", profile.name()))?;
+        Ok(Self {
+            profile,
+            root,
+            crypto: crypto_backend.to_backend()?,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.profile.name()
+    }
+
+    /// Field of the TEE evidence with the evidence of the devices.
+    pub fn evidence_field(&self) -> &str {
+        self.profile.evidence_field()
+    }
+
+    /// Verify the evidence of a device, bound to `nonce` and the TEE public
+    /// key of `attestation`.
+    pub async fn verify(
+        &self,
+        nonce: &str,
+        attestation: &Attestation,
+        evidence: &DeviceEvidence,
+    ) -> Result<DeviceClaims> {
+        let report = base64::Engine::decode(
+            &base64::engine::general_purpose::STANDARD,
+            &evidence.attestation_report,
+        )
+        .context("Invalid SPDM attestation report")?;
+        let (request, request_size) = MeasurementsRequest::parse(&report)?;
+        let response = MeasurementsResponse::parse(&report[request_size..], request.version)?;
+
+        let chain = certificates(&evidence.certificate_chain)?;
+        let leaf = self.verify_chain(&chain).await?;
+        let (r, s) = response.signature.split_at(SIGNATURE_SIZE / 2);
+        self.crypto
+            .verify_ecdsa_p384_sha384(leaf, &report[..request_size + response.signed.len()], r, s)
+            .await
+            .context("Invalid SPDM attestation report signature")?;
+
+        if !ReportDataMode::Hash(HashAlgorithm::Sha256).matches(nonce, attestation, &request.nonce)
+        {
+            bail!("SPDM attestation report does not bind the nonce and TEE public key");
+        }
+
+        let fields = messages::opaque_fields(response.opaque_data)?;
+        Ok(DeviceClaims {
+            measurements: response
+                .measurements
+                .iter()
+                .map(|(index, value)| (*index, hex::encode(value)))
+                .collect(),
+            profile: self.profile.opaque_claims(&fields),
+        })
+    }
+
+    /// Verify that `chain` ends with the root CA, and return its leaf.
+    async fn verify_chain<'a>(&self, chain: &'a [Vec<u8>]) -> Result<&'a [u8]> {
+        let leaf = chain
+            .first()
+            .ok_or_else(|| anyhow!("Empty device certificate chain"))?;
+        for pair in chain.windows(2) {
+            self.crypto
+                .verify_certificate(&pair[0], &pair[1])
+                .await
+                .context("Invalid device certificate chain")?;
+        }
+        let last = chain.last().unwrap_or(leaf);
+        if *last != self.root {
+            self.crypto
+                .verify_certificate(last, &self.root)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Device certificate chain does not end with the {} root CA",
+                        self.name()
+                    )
+                })?;
+        }
+        Ok(leaf)
+    }
+
+    /// Verify the `evidence` of the devices, and compare their measurements
+    /// with the reference values of the RVPS. Returns the flattened claims
+    /// of the devices.
+    async fn appraise(
+        &self,
+        nonce: &str,
+        attestation: &Attestation,
+        evidence: Value,
+        rvps: &(dyn RVPSAPI + Send + Sync),
+    ) -> Result<Map<String, Value>> {
+        let evidence: Vec<DeviceEvidence> =
+            serde_json::from_value(evidence).context("Invalid device evidence")?;
+        let mut claims = Map::new();
+        for (n, evidence) in evidence.iter().enumerate() {
+            let device_claims = self
+                .verify(nonce, attestation, evidence)
+                .await
+                .with_context(|| format!("Device {n}"))?;
+            let prefix = format!("{}.{n}", self.name());
+
+            let mut compared = 0;
+            let mut mismatched = Vec::new();
+            for (index, value) in &device_claims.measurements {
+                let reference = rvps
+                    .get_digests(&format!("{}.measurements.{index}", self.name()))
+                    .await?;
+                if let Some(reference) = reference {
+                    compared += 1;
+                    if !reference
+                        .hash_values
+                        .iter()
+                        .any(|digest| digest.eq_ignore_ascii_case(value))
+                    {
+                        mismatched.push(*index);
+                    }
+                }
+                claims.insert(
+                    format!("{prefix}.measurements.{index}"),
+                    value.clone().into(),
+                );
+            }
+            claims.insert(
+                format!("{prefix}.measurements_match"),
+                (compared > 0 && mismatched.is_empty()).into(),
+            );
+            claims.insert(
+                format!("{prefix}.mismatched_measurements"),
+                mismatched.into(),
+            );
+            for (name, value) in device_claims.profile {
+                claims.insert(format!("{prefix}.{name}"), value);
+            }
+        }
+        Ok(claims)
+    }
+}
+
+/// Verifiers of the devices of the `configs`, which must have different
+/// names.
+pub fn devices(
+    configs: &[SpdmDeviceConfig],
+    crypto_backend: CryptoBackendType,
+) -> Result<Vec<SpdmDevice>> {
+    let mut devices: Vec<SpdmDevice> = Vec::new();
+    for config in configs {
+        let device = SpdmDevice::new(config, crypto_backend)?;
+        if devices.iter().any(|other| other.name() == device.name()) {
+            bail!("Two SPDM device profiles named {}", device.name());
+        }
+        devices.push(device);
+    }
+    Ok(devices)
+}
+
+/// Verify the evidence of the devices attached to the TEE evidence of
+/// `attestation`, if any. Returns the flattened claims of the devices.
+pub(crate) async fn appraise(
+    devices: &[SpdmDevice],
+    nonce: &str,
+    attestation: &Attestation,
+    rvps: &(dyn RVPSAPI + Send + Sync),
+) -> Result<Map<String, Value>> {
+    let mut claims = Map::new();
+    let std::result::Result::Ok(mut evidence) =
+        serde_json::from_str::<Value>(&attestation.tee_evidence)
+    else {
+        return Ok(claims);
+    };
+    // GPU evidence is never ignored, since a policy that does not expect
+    // the GPUs would let a workload use them unattested.
+    if evidence.get(nvidia_gpu::EVIDENCE_FIELD).is_some()
+        && !devices
+            .iter()
+            .any(|device| device.profile.evidence_field() == nvidia_gpu::EVIDENCE_FIELD)
+    {
+        bail!("The AS does not verify NVIDIA GPU evidence");
+    }
+    for device in devices {
+        if let Some(evidence) = evidence.get_mut(device.profile.evidence_field()) {
+            claims.extend(
+                device
+                    .appraise(nonce, attestation, evidence.take(), rvps)
+                    .await
+                    .with_context(|| format!("Invalid {} evidence", device.name()))?,
+            );
+        }
+    }
+    Ok(claims)
+}
+
+/// The DER certificates of a PEM certificate chain.
+fn certificates(chain: &str) -> Result<Vec<Vec<u8>>> {
+    const END: &str = "-----END CERTIFICATE-----";
+    chain
+        .split_inclusive(END)
+        .filter(|block| block.contains(END))
+        .map(|block| {
+            let (label, der) = pem::decode_vec(block.trim().as_bytes())
+                .map_err(|e| anyhow!("Invalid PEM certificate: {e}"))?;
+            ensure!(label == "CERTIFICATE", "Unexpected PEM {label}");
+            Ok(der)
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "crypto-openssl"))]
+mod tests {
+    use super::messages::tests::{request, response};
+    use super::*;
+    use crate::rvps::{store::in_memory::InMemory, Core, ReferenceValue, Store};
+    use kbs_types::TeePubKey;
+    use openssl::{
+        asn1::Asn1Time,
+        ec::{EcGroup, EcKey},
+        ecdsa::EcdsaSig,
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::{PKey, Private},
+        x509::{X509NameBuilder, X509},
+    };
+    use serde_json::json;
+    use sha2::{Digest, Sha256, Sha384};
+
+    fn key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::SECP384R1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn certificate(
+        name: &str,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+    ) -> X509 {
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_nid(Nid::COMMONNAME, name).unwrap();
+        let subject = subject.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&subject).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        match issuer {
+            Some((issuer, issuer_key)) => {
+                builder.set_issuer_name(issuer.subject_name()).unwrap();
+                builder.sign(issuer_key, MessageDigest::sha384()).unwrap();
+            }
+            None => {
+                builder.set_issuer_name(&subject).unwrap();
+                builder.sign(key, MessageDigest::sha384()).unwrap();
+            }
+        }
+        builder.build()
+    }
+
+    fn attestation(tee_evidence: Value) -> Attestation {
+        Attestation {
+            tee_pubkey: TeePubKey {
+                kty: "RSA".to_string(),
+                alg: "RSA1_5".to_string(),
+                k_mod: "AQAB".to_string(),
+                k_exp: "AQAB".to_string(),
+            },
+            tee_evidence: tee_evidence.to_string(),
+        }
+    }
+
+    struct Device {
+        verifier: SpdmDevice,
+        leaf: X509,
+        leaf_key: PKey<Private>,
+    }
+
+    fn device(profile: Box<dyn DeviceProfile + Send + Sync>) -> Device {
+        let root_key = key();
+        let root = certificate("Device Identity CA", &root_key, None);
+        let leaf_key = key();
+        let leaf = certificate("Device", &leaf_key, Some((&root, &root_key)));
+        Device {
+            verifier: SpdmDevice {
+                profile,
+                root: root.to_der().unwrap(),
+                crypto: CryptoBackendType::OpenSSL.to_backend().unwrap(),
+            },
+            leaf,
+            leaf_key,
+        }
+    }
+
+    fn nic() -> Device {
+        device(Box::new(GenericProfile {
+            name: "nic".to_string(),
+        }))
+    }
+
+    fn evidence(device: &Device, nonce: &str, measurements: &[(u8, &[u8])]) -> DeviceEvidence {
+        let binding: [u8; 32] = Sha256::digest(format!("{nonce}AQABAQAB")).into();
+        let mut report = request(&binding);
+        report.extend_from_slice(&response(measurements, &[3, 0, 4, 0, b'5', b'3', b'5', 0]));
+        let signature =
+            EcdsaSig::sign(&Sha384::digest(&report), &device.leaf_key.ec_key().unwrap()).unwrap();
+        report.extend_from_slice(&signature.r().to_vec_padded(48).unwrap());
+        report.extend_from_slice(&signature.s().to_vec_padded(48).unwrap());
+        DeviceEvidence {
+            attestation_report: base64::Engine::encode(
+                &base64::engine::general_purpose::STANDARD,
+                report,
+            ),
+            certificate_chain: String::from_utf8(device.leaf.to_pem().unwrap()).unwrap(),
+        }
+    }
+
+    fn evidence_json(device: &Device, measurements: &[(u8, &[u8])]) -> Value {
+        let evidence = evidence(device, "nonce", measurements);
+        json!([{
+            "attestation_report": evidence.attestation_report,
+            "certificate_chain": evidence.certificate_chain,
+        }])
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let nic = nic();
+        let evidence = evidence(&nic, "nonce", &[(1, &[0xaa; 48])]);
+        let claims = nic
+            .verifier
+            .verify("nonce", &attestation(json!({})), &evidence)
+            .await
+            .unwrap();
+        assert_eq!(claims.measurements, vec![(1, hex::encode([0xaa; 48]))]);
+        assert!(claims.profile.is_empty());
+
+        // Another nonce
+        assert!(nic
+            .verifier
+            .verify("other nonce", &attestation(json!({})), &evidence)
+            .await
+            .is_err());
+
+        // Another device vendor
+        let other = self::nic();
+        assert!(other
+            .verifier
+            .verify("nonce", &attestation(json!({})), &evidence)
+            .await
+            .is_err());
+
+        // Tampered measurements
+        let mut report = base64::Engine::decode(
+            &base64::engine::general_purpose::STANDARD,
+            &evidence.attestation_report,
+        )
+        .unwrap();
+        report[60] ^= 1;
+        let tampered = DeviceEvidence {
+            attestation_report: base64::Engine::encode(
+                &base64::engine::general_purpose::STANDARD,
+                report,
+            ),
+            certificate_chain: evidence.certificate_chain.clone(),
+        };
+        assert!(nic
+            .verifier
+            .verify("nonce", &attestation(json!({})), &tampered)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_appraise() {
+        let mut store = InMemory::default();
+        for name in ["nic.measurements.1", "nvidia_gpu.measurements.1"] {
+            let reference = ReferenceValue::new()
+                .unwrap()
+                .set_name(name)
+                .set_expired(chrono::Utc::now() + chrono::Duration::days(1))
+                .add_hash_value("sha384".to_string(), hex::encode([0xaa; 48]));
+            store.set(reference.name().to_string(), reference).unwrap();
+        }
+        let rvps = Core::new(Box::new(store));
+
+        let nic = nic();
+        let gpu = device(Box::new(NvidiaGpuProfile));
+        let evidence = json!({
+            "quote": "",
+            "nic": evidence_json(&nic, &[(1, &[0xaa; 48]), (2, &[0xcc; 48])]),
+            "nvidia_gpus": evidence_json(&gpu, &[(1, &[0xbb; 48])]),
+        });
+        let devices = [nic.verifier, gpu.verifier];
+        let claims = appraise(&devices, "nonce", &attestation(evidence.clone()), &rvps)
+            .await
+            .unwrap();
+        assert_eq!(claims["nic.0.measurements_match"], true);
+        assert_eq!(claims["nic.0.measurements.2"], hex::encode([0xcc; 48]));
+        assert_eq!(claims["nvidia_gpu.0.measurements_match"], false);
+        assert_eq!(claims["nvidia_gpu.0.mismatched_measurements"], json!([1]));
+        assert_eq!(claims["nvidia_gpu.0.driver_version"], "535");
+
+        // Devices without evidence
+        let claims = appraise(&devices, "nonce", &attestation(json!({})), &rvps)
+            .await
+            .unwrap();
+        assert!(claims.is_empty());
+
+        // GPU evidence that the AS does not verify
+        assert!(
+            appraise(&devices[..1], "nonce", &attestation(evidence), &rvps)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_config() {
+        let config = |json: Value| {
+            serde_json::from_value::<SpdmDeviceConfig>(json)
+                .unwrap()
+                .to_profile()
+        };
+        let profile = config(json!({
+            "profile": "generic",
+            "name": "nvme",
+            "root_certificate": "/ca.pem"
+        }))
+        .unwrap();
+        assert_eq!(profile.evidence_field(), "nvme");
+        let profile =
+            config(json!({ "profile": "nvidia_gpu", "root_certificate": "/ca.pem" })).unwrap();
+        assert_eq!(profile.evidence_field(), "nvidia_gpus");
+
+        assert!(config(json!({ "profile": "generic", "root_certificate": "/ca.pem" })).is_err());
+        assert!(config(json!({
+            "profile": "generic",
+            "name": "nic.0",
+            "root_certificate": "/ca.pem"
+        }))
+        .is_err());
+    }
+}
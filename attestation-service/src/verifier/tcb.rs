@@ -0,0 +1,220 @@
+//! Vendor neutral TCB status.
+//!
+//! Each vendor tells whether a platform runs its latest TCB its own way:
+//! Intel with the TCB level status of DCAP quote verification, AMD with TCB
+//! versions, Arm with the security lifecycle state of CCA platforms. The
+//! verifiers map them onto [`TcbStatus`], in a `tcb_status` claim, with the
+//! release date of the TCB in a `tcb_date` claim when the vendor gives one.
+//! The AS lifts both out of the claims of the TEE, so that one policy rule
+//! holds across TEE types:
+//!
+//! ```rego
+//! allow { input.tcb_status == "UpToDate" }
+//! ```
+
+use anyhow::Result;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const TCB_STATUS_CLAIM: &str = "tcb_status";
+pub const TCB_DATE_CLAIM: &str = "tcb_date";
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TcbStatus {
+    /// The platform runs the latest TCB of the vendor.
+    UpToDate,
+    /// The TCB is up to date, software mitigations are needed in the TEE.
+    SwHardeningNeeded,
+    /// The TCB is up to date, the platform configuration is not.
+    ConfigurationNeeded,
+    /// The platform runs an outdated TCB.
+    OutOfDate,
+    /// The platform must not be trusted any more.
+    Revoked,
+    /// The verifier cannot tell, or did not check.
+    #[default]
+    Unknown,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Tcb {
+    pub status: TcbStatus,
+    pub date: Option<DateTime<Utc>>,
+}
+
+impl Tcb {
+    pub fn new(status: TcbStatus) -> Self {
+        Self { status, date: None }
+    }
+
+    /// Add the `tcb_status` and `tcb_date` claims to the `claims` of a
+    /// verifier.
+    pub fn add_claims(&self, claims: &mut Value) -> Result<()> {
+        if let Some(claims) = claims.as_object_mut() {
+            claims.insert(
+                TCB_STATUS_CLAIM.to_string(),
+                serde_json::to_value(self.status)?,
+            );
+            if let Some(date) = self.date {
+                claims.insert(TCB_DATE_CLAIM.to_string(), date.to_rfc3339().into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Move the `tcb_status` and `tcb_date` claims of `tee` out of its
+/// namespace in the flattened `claims`, `Unknown` if the verifier has no
+/// status.
+pub fn lift(tee: &str, claims: &mut Value) {
+    let Some(claims) = claims.as_object_mut() else {
+        return;
+    };
+    for name in [TCB_STATUS_CLAIM, TCB_DATE_CLAIM] {
+        if let Some(value) = claims.remove(&format!("{tee}.{name}")) {
+            claims.insert(name.to_string(), value);
+        }
+    }
+    if !claims.contains_key(TCB_STATUS_CLAIM) {
+        claims.insert(
+            TCB_STATUS_CLAIM.to_string(),
+            serde_json::to_value(TcbStatus::Unknown).unwrap_or_default(),
+        );
+    }
+}
+
+/// The TCB of a DCAP quote verification, the date being the TCB level
+/// date tag of the supplemental data, if any.
+#[cfg(any(feature = "tdx-verifier", feature = "sgx-verifier"))]
+pub fn from_dcap(result: sgx_dcap_quoteverify_rs::sgx_ql_qv_result_t, date_tag: i64) -> Tcb {
+    use sgx_dcap_quoteverify_rs::sgx_ql_qv_result_t::*;
+
+    let status = match result {
+        SGX_QL_QV_RESULT_OK => TcbStatus::UpToDate,
+        SGX_QL_QV_RESULT_SW_HARDENING_NEEDED => TcbStatus::SwHardeningNeeded,
+        SGX_QL_QV_RESULT_CONFIG_NEEDED | SGX_QL_QV_RESULT_CONFIG_AND_SW_HARDENING_NEEDED => {
+            TcbStatus::ConfigurationNeeded
+        }
+        SGX_QL_QV_RESULT_OUT_OF_DATE | SGX_QL_QV_RESULT_OUT_OF_DATE_CONFIG_NEEDED => {
+            TcbStatus::OutOfDate
+        }
+        SGX_QL_QV_RESULT_REVOKED => TcbStatus::Revoked,
+        _ => TcbStatus::Unknown,
+    };
+    Tcb {
+        status,
+        date: (date_tag > 0)
+            .then(|| Utc.timestamp_opt(date_tag, 0).single())
+            .flatten(),
+    }
+}
+
+/// The TCB of an SNP report, from its reported and current TCB versions.
+/// The platform is out of date if it reports, and is attested by the VCEK
+/// of, a TCB older than it runs.
+#[cfg(any(feature = "snp-verifier", feature = "az-snp-vtpm-verifier"))]
+pub fn from_snp(
+    reported: &sev::firmware::host::TcbVersion,
+    current: &sev::firmware::host::TcbVersion,
+) -> Tcb {
+    let components =
+        |tcb: &sev::firmware::host::TcbVersion| [tcb.bootloader, tcb.tee, tcb.snp, tcb.microcode];
+    from_snp_components(components(reported), components(current))
+}
+
+/// [`from_snp`] with TCB versions as `[bootloader, tee, snp, microcode]`.
+#[cfg(any(feature = "snp-verifier", feature = "az-snp-vtpm-verifier"))]
+fn from_snp_components(reported: [u8; 4], current: [u8; 4]) -> Tcb {
+    let status = if reported == current {
+        TcbStatus::UpToDate
+    } else if reported.iter().zip(current).any(|(r, c)| *r < c) {
+        TcbStatus::OutOfDate
+    } else {
+        TcbStatus::Unknown
+    };
+    Tcb::new(status)
+}
+
+/// The TCB of a CCA platform, from its PSA security lifecycle state. Only
+/// secured platforms are up to date.
+#[cfg(feature = "cca-verifier")]
+pub fn from_cca_lifecycle(lifecycle: u16) -> Tcb {
+    let status = match lifecycle >> 8 {
+        // Secured.
+        0x30 => TcbStatus::UpToDate,
+        // Assembly and test, PSA RoT provisioning, and debug states.
+        0x10 | 0x20 | 0x40 | 0x50 => TcbStatus::ConfigurationNeeded,
+        // Decommissioned.
+        0x60 => TcbStatus::Revoked,
+        _ => TcbStatus::Unknown,
+    };
+    Tcb::new(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_lift() {
+        let mut verifier_claims = json!({"quote": {}});
+        Tcb {
+            status: TcbStatus::OutOfDate,
+            date: Utc.timestamp_opt(1_700_000_000, 0).single(),
+        }
+        .add_claims(&mut verifier_claims)
+        .unwrap();
+        assert_eq!(verifier_claims["tcb_status"], "OutOfDate");
+
+        let mut claims = json!({
+            "tdx.tcb_status": "OutOfDate",
+            "tdx.tcb_date": verifier_claims["tcb_date"],
+            "tdx.enclave.tcb_status": "UpToDate",
+        });
+        lift("tdx", &mut claims);
+        assert_eq!(
+            claims,
+            json!({
+                "tcb_status": "OutOfDate",
+                "tcb_date": "2023-11-14T22:13:20+00:00",
+                "tdx.enclave.tcb_status": "UpToDate",
+            })
+        );
+
+        let mut claims = json!({"sample.svn": "1"});
+        lift("sample", &mut claims);
+        assert_eq!(claims["tcb_status"], "Unknown");
+    }
+
+    #[cfg(any(feature = "snp-verifier", feature = "az-snp-vtpm-verifier"))]
+    #[test]
+    fn test_from_snp() {
+        assert_eq!(
+            from_snp_components([3, 0, 8, 115], [3, 0, 8, 115]).status,
+            TcbStatus::UpToDate
+        );
+        assert_eq!(
+            from_snp_components([2, 0, 8, 115], [3, 0, 8, 115]).status,
+            TcbStatus::OutOfDate
+        );
+        assert_eq!(
+            from_snp_components([4, 0, 8, 115], [3, 0, 8, 115]).status,
+            TcbStatus::Unknown
+        );
+    }
+
+    #[cfg(feature = "cca-verifier")]
+    #[test]
+    fn test_from_cca_lifecycle() {
+        assert_eq!(from_cca_lifecycle(0x3000).status, TcbStatus::UpToDate);
+        assert_eq!(from_cca_lifecycle(0x30ff).status, TcbStatus::UpToDate);
+        assert_eq!(
+            from_cca_lifecycle(0x5000).status,
+            TcbStatus::ConfigurationNeeded
+        );
+        assert_eq!(from_cca_lifecycle(0x6000).status, TcbStatus::Revoked);
+        assert_eq!(from_cca_lifecycle(0).status, TcbStatus::Unknown);
+    }
+}
@@ -4,22 +4,64 @@
 //
 
 //! This module helps parse all fields inside a TDX Quote and CCEL and
-//! serialize them into a JSON. The format will look lile
+//! serialize them into a JSON. Fields that are semantically integers
+//! (versions, types and SVNs) are decoded from little-endian into JSON
+//! numbers, the `tcb_svn` being an array of one SVN per component. Other
+//! fields are hex encoded. CCEL digests are taken from the SHA-384 bank, or
+//! from the SHA-256 bank if the log has no SHA-384 digests, as told by
+//! `digest_algorithm`. SHA-256 digests are prefixed with `sha256:`.
+//! `eventlog_present` tells whether the evidence came with a CCEL, and so
+//! whether the boot measurements were checked. `td_hob` is the digest of the
+//! TD HOB measured by td-shim or TDVF, and `cfv` the digest of the
+//! Configuration Firmware Volume measured by TDVF, which holds the secure
+//! boot keys and other firmware configuration. `kernel_parameters` are
+//! parsed as the kernel does, see [`super::cmdline`]. If td-shim and TDVF both
+//! measured a kernel or a TD HOB, with different digests, the claim is
+//! replaced with one per firmware, e.g. `kernel_td_shim` and `kernel_tdvf`,
+//! see [`crate::verifier::conflicts`]. Version 5 quotes of TDX 1.5 modules
+//! also have `tee_tcb_svn_2`, the TCB SVN of the module the TD was migrated
+//! from, and `mr_servicetd`, the measurement of its service TD, in their
+//! body. `events` are all the events
+//! extended into the RTMRs, with their decoded event data, see
+//! [`super::eventlog::replay`]. The format will look lile
 //! ```json
 //! {
+//!  "eventlog_present": true,
 //!  "ccel": {
+//!    "digest_algorithm": "sha384",
+//!    "td_hob": "c6e6d33de4104b8196acfb57a9866ef6a85d413e86c1be96486e857b464591f4e2d252414346e9b98960246d2219a0eb",
 //!    "kernel": "5b7aa6572f649714ff00b6a2b9170516a068fd1a0ba72aa8de27574131d454e6396d3bfa1727d9baf421618a942977fa",
 //!    "kernel_parameters": {
 //!      "console": "hvc0",
 //!      "root": "/dev/vda1",
 //!      "rw": null
-//!    }
+//!    },
+//!    "kernel_parameters_encoding": "utf-8",
+//!    "container_images": [
+//!      {
+//!        "name": "registry.example.com/app:v1",
+//!        "digest": "sha256:0c8ba5b1e0b4a0b5c1bc7d0c0a7d0c9ff2e4f7b5d9a3e8c1a2b3c4d5e6f7a8b9"
+//!      }
+//!    ],
+//!    "events": [
+//!      {
+//!        "rtmr": 1,
+//!        "mr_index": 2,
+//!        "type": "EV_EFI_PLATFORM_FIRMWARE_BLOB2",
+//!        "digest": "5b7aa6572f649714ff00b6a2b9170516a068fd1a0ba72aa8de27574131d454e6396d3bfa1727d9baf421618a942977fa",
+//!        "data": {
+//!          "description": "td_payload",
+//!          "base": 9441280,
+//!          "length": 268435456
+//!        }
+//!      }
+//!    ]
 //!  },
 //!  "quote": {
 //!    "header":{
-//!        "version": "0400",
-//!        "att_key_type": "0200",
-//!        "tee_type": "81000000",
+//!        "version": 4,
+//!        "att_key_type": 2,
+//!        "tee_type": 129,
 //!        "reserved": "00000000",
 //!        "vendor_id": "939a7233f79c4ca9940a0db3957f0607",
 //!        "user_data": "d099bfec0a477aa85a605dceabf2b10800000000"
@@ -34,7 +76,7 @@
 //!        "seam_attributes": "0000000000000000",
 //!        "td_attributes": "0100001000000000",
 //!        "mr_seam": "2fd279c16164a93dd5bf373d834328d46008c2b693af9ebb865b08b2ced320c9a89b4869a9fab60fbe9d0c5a5363c656",
-//!        "tcb_svn": "03000500000000000000000000000000",
+//!        "tcb_svn": [3, 0, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
 //!        "xfam": "e742060000000000"
 //!    }
 //!  }
@@ -42,107 +84,156 @@
 //! ```
 
 use anyhow::*;
-use as_types::TeeEvidenceParsedClaim;
+use as_types::{ClaimsBuilder, TeeEvidenceParsedClaim};
 use byteorder::{LittleEndian, ReadBytesExt};
+use core::mem::size_of;
 use serde_json::{Map, Value};
 
 use super::{
-    eventlog::{CcEventLog, MeasuredEntity},
-    quote::Quote,
+    cmdline,
+    eventlog::{split_field, CcEventLog, MeasuredEntity},
+    quote::{Quote, ReportBody, ReportBody1_5},
+    td_report::TdReport,
 };
-
-macro_rules! parse_claim {
-    ($map_name: ident, $key_name: literal, $field: ident) => {
-        $map_name.insert($key_name.to_string(), serde_json::Value::Object($field))
-    };
-    ($map_name: ident, $key_name: literal, $field: expr) => {
-        $map_name.insert(
-            $key_name.to_string(),
-            serde_json::Value::String(hex::encode($field)),
-        )
-    };
-}
+use crate::verifier::charset::{Encoding, EventlogStrings};
+use crate::verifier::conflicts;
 
 pub fn generate_parsed_claim(
     quote: Quote,
     cc_eventlog: Option<CcEventLog>,
+    strings: EventlogStrings,
 ) -> Result<TeeEvidenceParsedClaim> {
-    let mut quote_map = Map::new();
-    let mut quote_body = Map::new();
-    let mut quote_header = Map::new();
     // Claims from TD Quote Header.
-    parse_claim!(quote_header, "version", quote.header.version);
-    parse_claim!(quote_header, "att_key_type", quote.header.att_key_type);
-    parse_claim!(quote_header, "tee_type", quote.header.tee_type);
-    parse_claim!(quote_header, "reserved", quote.header.reserved);
-    parse_claim!(quote_header, "vendor_id", quote.header.vendor_id);
-    parse_claim!(quote_header, "user_data", quote.header.user_data);
+    let header = &quote.header;
+    let quote_header = ClaimsBuilder::new()
+        .le_uint("version", &header.version)
+        .le_uint("att_key_type", &header.att_key_type)
+        .le_uint("tee_type", &header.tee_type)
+        .hex("reserved", header.reserved)
+        .hex("vendor_id", header.vendor_id)
+        .hex("user_data", header.user_data);
+    parsed_claims(
+        Some(quote_header),
+        &quote.report_body,
+        quote.report_body_1_5.as_ref(),
+        cc_eventlog,
+        strings,
+    )
+}
+
+/// Claims of a TD report, those of a quote of the report without header,
+/// see [`super::td_report`].
+pub fn generate_td_report_claims(
+    report: &TdReport,
+    cc_eventlog: Option<CcEventLog>,
+    strings: EventlogStrings,
+) -> Result<TeeEvidenceParsedClaim> {
+    parsed_claims(
+        None,
+        &report.body,
+        report.body_1_5.as_ref(),
+        cc_eventlog,
+        strings,
+    )
+}
+
+fn parsed_claims(
+    quote_header: Option<ClaimsBuilder>,
+    body: &ReportBody,
+    body_1_5: Option<&ReportBody1_5>,
+    cc_eventlog: Option<CcEventLog>,
+    strings: EventlogStrings,
+) -> Result<TeeEvidenceParsedClaim> {
     // Claims from TD Quote Body. We ignore RTMRs because when verifying the integrity of
     // the eventlog (CCEL), they have already been consumed.
-    parse_claim!(quote_body, "tcb_svn", quote.report_body.tcb_svn);
-    parse_claim!(quote_body, "mr_seam", quote.report_body.mr_seam);
-    parse_claim!(quote_body, "mrsigner_seam", quote.report_body.mrsigner_seam);
-    parse_claim!(
-        quote_body,
-        "seam_attributes",
-        quote.report_body.seam_attributes
-    );
-    parse_claim!(quote_body, "td_attributes", quote.report_body.td_attributes);
-    parse_claim!(quote_body, "xfam", quote.report_body.xfam);
-    parse_claim!(quote_body, "mr_td", quote.report_body.mr_td);
-    parse_claim!(quote_body, "mr_config_id", quote.report_body.mr_config_id);
-    parse_claim!(quote_body, "mr_owner", quote.report_body.mr_owner);
-    parse_claim!(
-        quote_body,
-        "mr_owner_config",
-        quote.report_body.mr_owner_config
-    );
-    parse_claim!(quote_body, "report_data", quote.report_body.report_data);
-
-    parse_claim!(quote_map, "header", quote_header);
-    parse_claim!(quote_map, "body", quote_body);
+    let quote_body = ClaimsBuilder::new()
+        .claim("tcb_svn", body.tcb_svn.to_vec())
+        .hex("mr_seam", body.mr_seam)
+        .hex("mrsigner_seam", body.mrsigner_seam)
+        .hex("seam_attributes", body.seam_attributes)
+        .hex("td_attributes", body.td_attributes)
+        .hex("xfam", body.xfam)
+        .hex("mr_td", body.mr_td)
+        .hex("mr_config_id", body.mr_config_id)
+        .hex("mr_owner", body.mr_owner)
+        .hex("mr_owner_config", body.mr_owner_config)
+        .hex("report_data", body.report_data);
+    let quote_body = match body_1_5 {
+        Some(body_1_5) => quote_body
+            .claim("tee_tcb_svn_2", body_1_5.tee_tcb_svn_2.to_vec())
+            .hex("mr_servicetd", body_1_5.mr_servicetd),
+        None => quote_body,
+    };
 
     // Claims from CC EventLog.
     let mut ccel_map = Map::new();
+    let eventlog_present = cc_eventlog.is_some();
     if let Some(ccel) = cc_eventlog {
-        parse_ccel(ccel, &mut ccel_map)?;
+        parse_ccel(ccel, &mut ccel_map, strings)?;
     } else {
         warn!("parse CC EventLog: CCEL is null");
     }
 
-    let mut claims = Map::new();
-    parse_claim!(claims, "quote", quote_map);
-    parse_claim!(claims, "ccel", ccel_map);
-    log::info!("\nParsed Evidence claims map: \n{:?}\n", &claims);
-
-    Ok(Value::Object(claims) as TeeEvidenceParsedClaim)
+    let quote = match quote_header {
+        Some(quote_header) => ClaimsBuilder::new().nest("header", quote_header),
+        None => ClaimsBuilder::new(),
+    };
+    Ok(ClaimsBuilder::new()
+        .nest("quote", quote.nest("body", quote_body))
+        .claim("ccel", ccel_map)
+        .claim("eventlog_present", eventlog_present)
+        .build())
 }
 
-fn parse_ccel(ccel: CcEventLog, ccel_map: &mut Map<String, Value>) -> Result<()> {
-    // Digest of kernel using td-shim
-    match ccel.query_digest(MeasuredEntity::TdShimKernel) {
-        Some(kernel_digest) => {
+fn parse_ccel(
+    ccel: CcEventLog,
+    ccel_map: &mut Map<String, Value>,
+    strings: EventlogStrings,
+) -> Result<()> {
+    // Measurement bank the digests below are taken from
+    match ccel.digest_algorithm() {
+        std::result::Result::Ok(algorithm) => {
             ccel_map.insert(
-                "kernel".to_string(),
-                serde_json::Value::String(kernel_digest),
+                "digest_algorithm".to_string(),
+                serde_json::Value::String(algorithm.to_string()),
             );
         }
         _ => {
-            warn!("No td-shim kernel hash in CCEL");
+            warn!("No supported digests in CCEL");
         }
     }
 
-    // Digest of kernel using TDVF
-    match ccel.query_digest(MeasuredEntity::TdvfKernel) {
-        Some(kernel_digest) => {
-            ccel_map.insert(
-                "kernel".to_string(),
-                serde_json::Value::String(kernel_digest),
-            );
-        }
-        _ => {
-            warn!("No tdvf kernel hash in CCEL");
-        }
+    // Digest of the TD HOB, using td-shim or TDVF
+    let td_hob = conflicts::insert(
+        ccel_map,
+        "ccel",
+        "td_hob",
+        vec![
+            ("td_shim", digest_claim(&ccel, MeasuredEntity::TdShim)),
+            ("tdvf", digest_claim(&ccel, MeasuredEntity::TdvfHob)),
+        ],
+    );
+    if td_hob.is_none() && !ccel_map.contains_key("td_hob") {
+        warn!("No TD HOB hash in CCEL");
+    }
+
+    // Digest of the Configuration Firmware Volume, only measured by TDVF
+    if let Some(cfv_digest) = ccel.query_digest(MeasuredEntity::TdvfCfv) {
+        ccel_map.insert("cfv".to_string(), serde_json::Value::String(cfv_digest));
+    }
+
+    // Digest of kernel using td-shim or TDVF
+    let kernel = conflicts::insert(
+        ccel_map,
+        "ccel",
+        "kernel",
+        vec![
+            ("td_shim", digest_claim(&ccel, MeasuredEntity::TdShimKernel)),
+            ("tdvf", digest_claim(&ccel, MeasuredEntity::TdvfKernel)),
+        ],
+    );
+    if kernel.is_none() && !ccel_map.contains_key("kernel") {
+        warn!("No kernel hash in CCEL");
     }
 
     // Map of Kernel Parameters
@@ -151,20 +242,49 @@ fn parse_ccel(ccel: CcEventLog, ccel_map: &mut Map<String, Value>) -> Result<()>
             let td_shim_platform_config_info =
                 TdShimPlatformConfigInfo::try_from(&config_info[..])?;
 
-            let parameters = parse_kernel_parameters(td_shim_platform_config_info.data)?;
+            let (parameters, encoding) =
+                parse_kernel_parameters(td_shim_platform_config_info.data, strings)?;
             ccel_map.insert(
                 "kernel_parameters".to_string(),
                 serde_json::Value::Object(parameters),
             );
+            ccel_map.insert(
+                "kernel_parameters_encoding".to_string(),
+                serde_json::Value::String(encoding.as_str().to_string()),
+            );
         }
         _ => {
             warn!("No kernel parameters in CCEL");
         }
     }
 
+    // Every event extended into the RTMRs
+    match ccel.replay() {
+        std::result::Result::Ok(replay) => {
+            ccel_map.insert("events".to_string(), serde_json::to_value(replay.events)?);
+        }
+        Err(e) => {
+            warn!("Cannot replay CC EventLog: {e:#}");
+        }
+    }
+
+    // Container images launched by the agent
+    let container_images = ccel.container_images()?;
+    if !container_images.is_empty() {
+        ccel_map.insert(
+            "container_images".to_string(),
+            serde_json::to_value(container_images)?,
+        );
+    }
+
     Ok(())
 }
 
+/// The digest of `entity` in `ccel`, as a claim.
+fn digest_claim(ccel: &CcEventLog, entity: MeasuredEntity) -> Option<Value> {
+    ccel.query_digest(entity).map(Value::String)
+}
+
 /// Kernel Commandline Event inside Eventlog
 pub struct TdShimPlatformConfigInfo<'a> {
     pub descriptor: [u8; 16],
@@ -175,19 +295,11 @@ pub struct TdShimPlatformConfigInfo<'a> {
 impl<'a> TryFrom<&'a [u8]> for TdShimPlatformConfigInfo<'a> {
     type Error = anyhow::Error;
 
-    fn try_from(data: &'a [u8]) -> std::result::Result<Self, Self::Error> {
-        if data.len() < core::mem::size_of::<[u8; 16]>() + core::mem::size_of::<u32>() {
-            bail!("give data slice is too short");
-        }
-
-        let descriptor = data[0..core::mem::size_of::<[u8; 16]>()].try_into()?;
-        let info_length = (&data[core::mem::size_of::<[u8; 16]>()
-            ..core::mem::size_of::<[u8; 16]>() + core::mem::size_of::<u32>()])
-            .read_u32::<LittleEndian>()?;
-        let data = &data[core::mem::size_of::<[u8; 16]>() + core::mem::size_of::<u32>()
-            ..core::mem::size_of::<[u8; 16]>()
-                + core::mem::size_of::<u32>()
-                + info_length as usize];
+    fn try_from(mut data: &'a [u8]) -> std::result::Result<Self, Self::Error> {
+        let descriptor = split_field(&mut data, size_of::<[u8; 16]>(), "Descriptor")?.try_into()?;
+        let info_length =
+            split_field(&mut data, size_of::<u32>(), "Info length")?.read_u32::<LittleEndian>()?;
+        let data = split_field(&mut data, usize::try_from(info_length)?, "Info")?;
         Ok(Self {
             descriptor,
             info_length,
@@ -196,31 +308,21 @@ impl<'a> TryFrom<&'a [u8]> for TdShimPlatformConfigInfo<'a> {
     }
 }
 
-fn parse_kernel_parameters(kernel_parameters: &[u8]) -> Result<Map<String, Value>> {
-    let parameters_str = String::from_utf8(kernel_parameters.to_vec())?;
-    debug!("kernel parameters: {parameters_str}");
-
-    let parameters = parameters_str
-        .split(&[' ', '\n', '\r', '\0'])
-        .collect::<Vec<&str>>()
-        .iter()
-        .filter_map(|item| {
-            if item.is_empty() {
-                return None;
-            }
-            let it: Vec<&str> = item.split('=').collect();
-            match it.len() {
-                1 => Some((it[0].to_owned(), Value::Null)),
-                2 => Some((it[0].to_owned(), Value::String(it[1].to_owned()))),
-                _ => {
-                    warn!("Illegal parameter: {item}");
-                    None
-                }
-            }
-        })
-        .collect();
+fn parse_kernel_parameters(
+    kernel_parameters: &[u8],
+    strings: EventlogStrings,
+) -> Result<(Map<String, Value>, Encoding)> {
+    let (parameters_str, encoding) = strings
+        .decode(kernel_parameters)
+        .context("kernel parameters")?;
+    debug!(
+        "kernel parameters ({}): {parameters_str}",
+        encoding.as_str()
+    );
 
-    Ok(parameters)
+    let parameters = cmdline::claims(cmdline::tokenize(&parameters_str));
+
+    Ok((parameters, encoding))
 }
 
 #[cfg(test)]
@@ -228,9 +330,101 @@ mod tests {
     use assert_json_diff::assert_json_eq;
     use serde_json::json;
 
-    use crate::verifier::tdx::{eventlog::CcEventLog, quote::parse_tdx_quote};
+    use crate::verifier::charset::{Encoding, EventlogStrings};
+    use crate::verifier::tdx::{
+        eventlog::CcEventLog,
+        quote::{parse_tdx_quote, ReportBody1_5},
+    };
+
+    use super::{
+        generate_parsed_claim, parse_ccel, parse_kernel_parameters, TdShimPlatformConfigInfo,
+    };
+
+    /// A TD_SHIM_PLATFORM_CONFIG_INFO of `info`, declaring `info_length`.
+    fn config_info(info_length: u32, info: &[u8]) -> Vec<u8> {
+        let mut data = b"td_payload_info\0".to_vec();
+        data.extend_from_slice(&info_length.to_le_bytes());
+        data.extend_from_slice(info);
+        data
+    }
+
+    #[test]
+    fn parse_td_shim_platform_config_info() {
+        let data = config_info(7, b"rw ro=1 trailing");
+        let info = TdShimPlatformConfigInfo::try_from(&data[..]).unwrap();
+        assert_eq!(&info.descriptor, b"td_payload_info\0");
+        assert_eq!(info.info_length, 7);
+        assert_eq!(info.data, b"rw ro=1");
+
+        let data = config_info(0, b"");
+        let info = TdShimPlatformConfigInfo::try_from(&data[..]).unwrap();
+        assert!(info.data.is_empty());
+    }
+
+    #[test]
+    fn parse_kernel_parameters_charsets() {
+        let (parameters, encoding) =
+            parse_kernel_parameters(b"console=hvc0 rw\0", EventlogStrings::Strict).unwrap();
+        assert_eq!(encoding, Encoding::Utf8);
+        assert_eq!(
+            serde_json::Value::Object(parameters),
+            json!({ "console": "hvc0", "rw": null })
+        );
+
+        let utf16le: Vec<u8> = "console=hvc0 rw"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let (parameters, encoding) =
+            parse_kernel_parameters(&utf16le, EventlogStrings::Strict).unwrap();
+        assert_eq!(encoding, Encoding::Utf16Le);
+        assert_eq!(
+            serde_json::Value::Object(parameters),
+            json!({ "console": "hvc0", "rw": null })
+        );
+
+        // A stray byte fails the parse, unless lossy.
+        assert!(parse_kernel_parameters(b"root=/dev/vda1 \xff", EventlogStrings::Strict).is_err());
+        let (parameters, encoding) =
+            parse_kernel_parameters(b"root=/dev/vda1 \xff", EventlogStrings::Lossy).unwrap();
+        assert_eq!(encoding, Encoding::Utf8Lossy);
+        assert_eq!(parameters["root"], "/dev/vda1");
+    }
+
+    #[test]
+    fn reject_malformed_td_shim_platform_config_info() {
+        // Truncated descriptor or info length
+        let data = config_info(7, b"rw ro=1");
+        for len in 0..20 {
+            assert!(TdShimPlatformConfigInfo::try_from(&data[..len]).is_err());
+        }
+
+        // Info length beyond the event data
+        for info_length in [8, 4096, u32::MAX] {
+            let data = config_info(info_length, b"rw ro=1");
+            assert!(TdShimPlatformConfigInfo::try_from(&data[..]).is_err());
+        }
+    }
 
-    use super::generate_parsed_claim;
+    #[test]
+    fn parse_td_report_1_5_claims() {
+        let quote_bin = std::fs::read("../test_data/tdx_quote_4.dat").expect("read quote failed");
+        let mut quote = parse_tdx_quote(&quote_bin).expect("parse quote");
+        let claims = generate_parsed_claim(quote, None, EventlogStrings::default()).unwrap();
+        assert!(claims["quote"]["body"].get("mr_servicetd").is_none());
+
+        quote = parse_tdx_quote(&quote_bin).expect("parse quote");
+        quote.report_body_1_5 = Some(ReportBody1_5 {
+            tee_tcb_svn_2: [4, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            mr_servicetd: [0; 48],
+        });
+        let claims = generate_parsed_claim(quote, None, EventlogStrings::default()).unwrap();
+        assert_eq!(
+            claims["quote"]["body"]["tee_tcb_svn_2"],
+            json!([4, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+        );
+        assert_eq!(claims["quote"]["body"]["mr_servicetd"], "00".repeat(48));
+    }
 
     #[test]
     fn parse_tdx_claims() {
@@ -238,21 +432,32 @@ mod tests {
         let ccel_bin = std::fs::read("../test_data/CCEL_data").expect("read ccel failed");
         let quote = parse_tdx_quote(&quote_bin).expect("parse quote");
         let ccel = CcEventLog::try_from(ccel_bin).expect("parse ccel");
-        let claims = generate_parsed_claim(quote, Some(ccel)).expect("parse claim failed");
+        let mut claims = generate_parsed_claim(quote, Some(ccel), EventlogStrings::default())
+            .expect("parse claim failed");
+        let events = claims["ccel"]
+            .as_object_mut()
+            .and_then(|ccel| ccel.remove("events"))
+            .expect("events claim");
+        assert_eq!(events.as_array().map(Vec::len), Some(5));
+        assert_eq!(events[3]["data"]["description"], "td_payload");
         let expected = json!({
+            "eventlog_present": true,
             "ccel": {
+                "digest_algorithm": "sha384",
+                "td_hob": "c6e6d33de4104b8196acfb57a9866ef6a85d413e86c1be96486e857b464591f4e2d252414346e9b98960246d2219a0eb",
                 "kernel": "5b7aa6572f649714ff00b6a2b9170516a068fd1a0ba72aa8de27574131d454e6396d3bfa1727d9baf421618a942977fa",
                 "kernel_parameters": {
                     "console": "hvc0",
                     "root": "/dev/vda1",
                     "rw": null
-                }
+                },
+                "kernel_parameters_encoding": "utf-8"
             },
             "quote": {
                 "header":{
-                    "version": "0400",
-                    "att_key_type": "0200",
-                    "tee_type": "81000000",
+                    "version": 4,
+                    "att_key_type": 2,
+                    "tee_type": 129,
                     "reserved": "00000000",
                     "vendor_id": "939a7233f79c4ca9940a0db3957f0607",
                     "user_data": "d099bfec0a477aa85a605dceabf2b10800000000"
@@ -267,7 +472,7 @@ mod tests {
                     "seam_attributes": "0000000000000000",
                     "td_attributes": "0100001000000000",
                     "mr_seam": "2fd279c16164a93dd5bf373d834328d46008c2b693af9ebb865b08b2ced320c9a89b4869a9fab60fbe9d0c5a5363c656",
-                    "tcb_svn": "03000500000000000000000000000000",
+                    "tcb_svn": [3, 0, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
                     "xfam": "e742060000000000"
                 }
             }
@@ -275,4 +480,54 @@ mod tests {
 
         assert_json_eq!(expected, claims);
     }
+    #[test]
+    fn parse_conflicting_kernels() {
+        use eventlog_rs::{ElDigest, Eventlog, EventlogEntry};
+        use sha2::{Digest, Sha384};
+
+        let event = |event_desc: &[u8], kernel: &[u8]| EventlogEntry {
+            target_measurement_registry: 2,
+            event_type: "EV_EFI_PLATFORM_FIRMWARE_BLOB2".to_string(),
+            digests: vec![ElDigest {
+                alg: "TPM_ALG_SHA384".to_string(),
+                digest: Sha384::digest(kernel).to_vec(),
+            }],
+            event_desc: event_desc.to_vec(),
+        };
+        let ccel = |kernels: &[&[u8]]| CcEventLog {
+            cc_events: Eventlog {
+                log: vec![
+                    event(b"\x0btd_payload\0", kernels[0]),
+                    event(b"k\0e\0r\0n\0e\0l\0", kernels[1]),
+                ],
+            },
+        };
+
+        let mut claims = serde_json::Map::new();
+        parse_ccel(
+            ccel(&[b"vmlinuz", b"vmlinuz"]),
+            &mut claims,
+            EventlogStrings::default(),
+        )
+        .unwrap();
+        assert_eq!(claims["kernel"], hex::encode(Sha384::digest(b"vmlinuz")));
+
+        // Each measured its own kernel.
+        let mut claims = serde_json::Map::new();
+        parse_ccel(
+            ccel(&[b"vmlinuz", b"bzImage"]),
+            &mut claims,
+            EventlogStrings::default(),
+        )
+        .unwrap();
+        assert!(!claims.contains_key("kernel"));
+        assert_eq!(
+            claims["kernel_td_shim"],
+            hex::encode(Sha384::digest(b"vmlinuz"))
+        );
+        assert_eq!(
+            claims["kernel_tdvf"],
+            hex::encode(Sha384::digest(b"bzImage"))
+        );
+    }
 }
@@ -0,0 +1,232 @@
+//! Kernel command line parsing.
+//!
+//! The command line is split into parameters at whitespace and NULs out of
+//! quotes, as the kernel does. Single or double quotes keep the whitespace
+//! they enclose, and are dropped. A parameter is split into its name and
+//! value at its first `=`, so that the value keeps the `=` that follow,
+//! e.g. `module.opt=a=b`. The claims of the parameters map their names to
+//! their values, or to `null` for flags, and to the array of their values,
+//! in order, for parameters given more than once:
+//!
+//! ```text
+//! console=ttyS0 console=hvc0 dyndbg="file drm* +p" module.opt=a=b rw
+//! ```
+//!
+//! ```json
+//! {
+//!   "console": ["ttyS0", "hvc0"],
+//!   "dyndbg": "file drm* +p",
+//!   "module.opt": "a=b",
+//!   "rw": null
+//! }
+//! ```
+//!
+//! With `canonical_kernel_cmdline` in the AS config, the command line is
+//! also claimed in a canonical form, see [`canonical`], that does not
+//! depend on the order of the parameters.
+//!
+//! With `kernel_flags` in the AS config, the flags are `true` rather than
+//! `null`, and are also claimed in a `kernel_flags` object, see [`flags`],
+//! with `false` for the flags of the config that are not on the command
+//! line:
+//!
+//! ```json
+//! {
+//!   "kernel_parameters": { "console": "hvc0", "rw": true },
+//!   "kernel_flags": { "nomodeset": false, "quiet": false, "rw": true }
+//! }
+//! ```
+
+use serde_json::{Map, Value};
+
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || c == '\0'
+}
+
+/// The parameters of `cmdline`, in order, with their value if any.
+pub fn tokenize(cmdline: &str) -> Vec<(String, Option<String>)> {
+    let mut parameters = Vec::new();
+    let mut chars = cmdline.chars().peekable();
+    loop {
+        while chars.next_if(|c| is_separator(*c)).is_some() {}
+        if chars.peek().is_none() {
+            return parameters;
+        }
+        let mut name = String::new();
+        let mut value: Option<String> = None;
+        let mut quote = None;
+        for c in chars.by_ref() {
+            match (quote, c) {
+                (_, '=') if value.is_none() => {
+                    value = Some(String::new());
+                    continue;
+                }
+                (Some(open), c) if c == open => quote = None,
+                (None, '"' | '\'') => quote = Some(c),
+                (None, c) if is_separator(c) => break,
+                (_, c) => value.as_mut().unwrap_or(&mut name).push(c),
+            }
+        }
+        if name.is_empty() {
+            warn!(
+                "Kernel parameter without name: ={}",
+                value.unwrap_or_default()
+            );
+            continue;
+        }
+        parameters.push((name, value));
+    }
+}
+
+/// The claims of `parameters`: their values by name, the array of their
+/// values for parameters given more than once.
+pub fn claims(parameters: Vec<(String, Option<String>)>) -> Map<String, Value> {
+    let mut claims = Map::new();
+    for (name, value) in parameters {
+        let value = value.map_or(Value::Null, Value::String);
+        match claims.get_mut(&name) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(first) => *first = Value::Array(vec![first.take(), value]),
+            None => {
+                claims.insert(name, value);
+            }
+        }
+    }
+    claims
+}
+
+/// `value` quoted if it would not be a single parameter value otherwise.
+fn quoted(value: &str) -> String {
+    if !value.is_empty() && !value.contains(|c| is_separator(c) || c == '"' || c == '\'') {
+        return value.to_string();
+    }
+    match value.contains('"') {
+        true => format!("'{value}'"),
+        false => format!("\"{value}\""),
+    }
+}
+
+/// The canonical command line of the parameter `claims`: the parameters
+/// sorted by name, those given more than once in their order, separated by
+/// a space, with their values quoted only if they have to be. Command lines
+/// with the same parameters have the same canonical form.
+pub fn canonical(claims: &Map<String, Value>) -> String {
+    let parameter = |name: &str, value: &Value| match value.as_str() {
+        Some(value) => format!("{name}={}", quoted(value)),
+        None => name.to_string(),
+    };
+    claims
+        .iter()
+        .flat_map(|(name, value)| match value {
+            Value::Array(values) => values
+                .iter()
+                .map(|value| parameter(name, value))
+                .collect::<Vec<_>>(),
+            value => vec![parameter(name, value)],
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Set the flags of the parameter `claims` to `true`, and return the
+/// `kernel_flags` claims: `true` for each flag of the command line, `false`
+/// for each of the `known` flags that is not one. A parameter given with a
+/// value is not a flag.
+pub fn flags(claims: &mut Map<String, Value>, known: &[String]) -> Map<String, Value> {
+    let mut flags = Map::new();
+    for (name, value) in claims.iter_mut() {
+        let values = match value {
+            Value::Array(values) => values.iter_mut().collect(),
+            value => vec![value],
+        };
+        for value in values.into_iter().filter(|value| value.is_null()) {
+            *value = Value::Bool(true);
+            flags.insert(name.clone(), Value::Bool(true));
+        }
+    }
+    for name in known {
+        flags.entry(name.clone()).or_insert(Value::Bool(false));
+    }
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(cmdline: &str) -> Value {
+        Value::Object(claims(tokenize(cmdline)))
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            parse("console=hvc0  root=/dev/vda1 rw\0\0"),
+            json!({ "console": "hvc0", "root": "/dev/vda1", "rw": null })
+        );
+        // Values keep their `=`, and quoted whitespace.
+        assert_eq!(
+            parse(r#"module.opt=a=b dyndbg="file drm* +p" 'init=/bin/sh -c x' empty="""#),
+            json!({
+                "module.opt": "a=b",
+                "dyndbg": "file drm* +p",
+                "init": "/bin/sh -c x",
+                "empty": ""
+            })
+        );
+        assert_eq!(
+            parse(r#"quote='say "hi"' unterminated="a b"#),
+            json!({ "quote": "say \"hi\"", "unterminated": "a b" })
+        );
+        // Repeated parameters are arrays, in order.
+        assert_eq!(
+            parse("console=ttyS0 quiet console=hvc0 quiet =orphan"),
+            json!({ "console": ["ttyS0", "hvc0"], "quiet": [null, null] })
+        );
+        assert!(tokenize(" \n\t").is_empty());
+    }
+
+    #[test]
+    fn test_canonical() {
+        let cmdline =
+            r#"rw console=ttyS0 dyndbg="file drm* +p" console=hvc0 m.o=a=b e="" q='x "y"'"#;
+        let canonical = canonical(&claims(tokenize(cmdline)));
+        assert_eq!(
+            canonical,
+            r#"console=ttyS0 console=hvc0 dyndbg="file drm* +p" e="" m.o=a=b q='x "y"' rw"#
+        );
+        // The canonical form is a fixed point, and does not depend on the
+        // order of the parameters.
+        assert_eq!(parse(&canonical), parse(cmdline));
+        assert_eq!(
+            super::canonical(&claims(tokenize("b=2 a=1"))),
+            super::canonical(&claims(tokenize("a=1  b='2'")))
+        );
+    }
+
+    #[test]
+    fn test_flags() {
+        let mut parameters = claims(tokenize("rw console=hvc0 quiet quiet nomodeset=0"));
+        let known = ["rw", "nomodeset", "single"].map(String::from);
+        let flags = flags(&mut parameters, &known);
+        assert_eq!(
+            Value::Object(parameters.clone()),
+            json!({
+                "rw": true,
+                "console": "hvc0",
+                "quiet": [true, true],
+                "nomodeset": "0"
+            })
+        );
+        assert_eq!(
+            Value::Object(flags),
+            json!({ "rw": true, "quiet": true, "nomodeset": false, "single": false })
+        );
+        // The canonical form keeps the flags bare.
+        assert_eq!(
+            canonical(&parameters),
+            "console=hvc0 nomodeset=0 quiet quiet rw"
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Cross-checks of the values that both the TD quote and the CC EventLog
+//! tell. The quote only binds the eventlog through the RTMRs, and the CCEL
+//! claims are taken from the event data, so these checks are defense in
+//! depth against forged eventlogs:
+//!
+//! * `rtmr`: every RTMR of the quote is the replay of the eventlog, even
+//!   when the verifier pipeline skips the `eventlog_replay` stage.
+//! * `boot_register`: the kernel and its parameters are measured into
+//!   RTMR[1], where the firmware measures them.
+//! * `kernel`: all the kernel events have the same digest, so that the
+//!   `kernel` claim is the only kernel that was measured.
+//! * `kernel_parameters`: the kernel parameters of the event data are
+//!   those the event digest measured.
+//!
+//! The checks that apply to the evidence, and the mismatches they found,
+//! are the `consistency` claims, e.g.
+//!
+//! ```json
+//! "consistency": {
+//!     "checks": ["rtmr", "boot_register", "kernel", "kernel_parameters"],
+//!     "mismatches": [
+//!         {
+//!             "check": "kernel_parameters",
+//!             "detail": "The kernel parameters of the eventlog do not match their digest"
+//!         }
+//!     ]
+//! }
+//! ```
+
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+use super::{
+    claims::TdShimPlatformConfigInfo,
+    eventlog::{CcEventLog, MeasuredEntity, Rtmr},
+};
+
+/// Index of RTMR[1] in the CCEL, where index 0 stands for MRTD.
+const BOOT_RTMR_INDEX: u32 = 2;
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct Consistency {
+    pub checks: Vec<&'static str>,
+    pub mismatches: Vec<Mismatch>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub check: &'static str,
+    pub detail: String,
+}
+
+impl Consistency {
+    fn mismatch(&mut self, check: &'static str, detail: String) {
+        self.mismatches.push(Mismatch { check, detail });
+    }
+}
+
+/// Cross-check the RTMRs of the quote with the eventlog.
+pub fn check(rtmr_from_quote: Rtmr, ccel: &CcEventLog) -> Consistency {
+    let mut consistency = Consistency::default();
+
+    consistency.checks.push("rtmr");
+    match ccel.replay() {
+        Ok(replay) => {
+            for mismatch in replay.mismatches(rtmr_from_quote) {
+                consistency.mismatch(
+                    "rtmr",
+                    format!(
+                        "RTMR[{}] of the quote is {}, the eventlog replays to {}",
+                        mismatch.index, mismatch.quote, mismatch.eventlog
+                    ),
+                );
+            }
+        }
+        Err(e) => consistency.mismatch("rtmr", format!("The eventlog cannot be replayed: {e}")),
+    }
+
+    // The other checks need digests of the eventlog.
+    let Ok(algorithm) = ccel.digest_algorithm() else {
+        return consistency;
+    };
+    let kernels: Vec<_> = ccel
+        .query_events(MeasuredEntity::TdShimKernel)
+        .chain(ccel.query_events(MeasuredEntity::TdvfKernel))
+        .collect();
+    let parameters: Vec<_> = ccel
+        .query_events(MeasuredEntity::TdShimKernelParams)
+        .collect();
+
+    if !kernels.is_empty() || !parameters.is_empty() {
+        consistency.checks.push("boot_register");
+        for event_entry in kernels.iter().chain(&parameters) {
+            if event_entry.target_measurement_registry != BOOT_RTMR_INDEX {
+                consistency.mismatch(
+                    "boot_register",
+                    format!(
+                        "{} event measured into register {} of the eventlog instead of RTMR[1]",
+                        event_entry.event_type, event_entry.target_measurement_registry
+                    ),
+                );
+            }
+        }
+    }
+
+    if !kernels.is_empty() {
+        consistency.checks.push("kernel");
+        let digests: BTreeSet<_> = kernels
+            .iter()
+            .filter_map(|event_entry| algorithm.select(&event_entry.digests))
+            .map(|digest| algorithm.claim(digest))
+            .collect();
+        if digests.len() > 1 {
+            consistency.mismatch(
+                "kernel",
+                format!(
+                    "The eventlog measures {} kernels: {}",
+                    digests.len(),
+                    digests.into_iter().collect::<Vec<_>>().join(", ")
+                ),
+            );
+        }
+    }
+
+    if !parameters.is_empty() {
+        consistency.checks.push("kernel_parameters");
+        for event_entry in &parameters {
+            let measured = TdShimPlatformConfigInfo::try_from(&event_entry.event_desc[..])
+                .ok()
+                .zip(algorithm.select(&event_entry.digests))
+                .is_some_and(|(config_info, digest)| algorithm.hash(config_info.data) == digest);
+            if !measured {
+                consistency.mismatch(
+                    "kernel_parameters",
+                    "The kernel parameters of the eventlog do not match their digest".to_string(),
+                );
+            }
+        }
+    }
+
+    consistency
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eventlog_rs::{ElDigest, Eventlog, EventlogEntry};
+    use sha2::{Digest, Sha384};
+
+    fn event(index: u32, event_desc: Vec<u8>, digest: Vec<u8>) -> EventlogEntry {
+        EventlogEntry {
+            target_measurement_registry: index,
+            event_type: "EV_EVENT_TAG".to_string(),
+            digests: vec![ElDigest {
+                alg: "TPM_ALG_SHA384".to_string(),
+                digest,
+            }],
+            event_desc,
+        }
+    }
+
+    fn kernel_parameters(parameters: &[u8]) -> Vec<u8> {
+        let mut event_desc = MeasuredEntity::TdShimKernelParams.to_string().into_bytes();
+        event_desc.extend_from_slice(&(parameters.len() as u32).to_le_bytes());
+        event_desc.extend_from_slice(parameters);
+        event_desc
+    }
+
+    #[test]
+    fn test_check() {
+        let kernel = MeasuredEntity::TdvfKernel.to_string().into_bytes();
+        let parameters = b"console=hvc0";
+        let mut ccel = CcEventLog {
+            cc_events: Eventlog {
+                log: vec![
+                    event(2, kernel.clone(), Sha384::digest(b"kernel").to_vec()),
+                    event(
+                        2,
+                        kernel_parameters(parameters),
+                        Sha384::digest(parameters).to_vec(),
+                    ),
+                ],
+            },
+        };
+        let rtmr = ccel.rebuild_rtmr().unwrap();
+
+        let consistency = check(rtmr, &ccel);
+        assert_eq!(
+            consistency.checks,
+            vec!["rtmr", "boot_register", "kernel", "kernel_parameters"]
+        );
+        assert!(consistency.mismatches.is_empty());
+
+        // The parameters were changed after they were measured, a second
+        // kernel is measured into RTMR[2], and the quote is of another log.
+        ccel.cc_events.log[1] = event(
+            2,
+            kernel_parameters(b"console=hvc0 init=/bin/sh"),
+            Sha384::digest(parameters).to_vec(),
+        );
+        ccel.cc_events
+            .log
+            .push(event(3, kernel, Sha384::digest(b"other").to_vec()));
+        let consistency = check(rtmr, &ccel);
+        let mut checks: Vec<_> = consistency
+            .mismatches
+            .iter()
+            .map(|mismatch| mismatch.check)
+            .collect();
+        checks.sort();
+        assert_eq!(
+            checks,
+            vec!["boot_register", "kernel", "kernel_parameters", "rtmr"]
+        );
+    }
+}
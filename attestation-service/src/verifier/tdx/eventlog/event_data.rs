@@ -0,0 +1,369 @@
+//! Decoding of the event data of the CC EventLog, after the event type, as
+//! defined in the TCG PC Client Platform Firmware Profile Specification,
+//! section 'Event Descriptions', and in the td-shim spec for the
+//! `EV_EVENT_TAG` events of td-shim.
+//!
+//! Decoded event data are JSON objects of the fields of the event data.
+//! Text fields are strings, without their NUL terminators. Binary fields
+//! are hex encoded when they are at most [`MAX_INLINE_BYTES`] long, longer
+//! ones, like the HOB of td-shim or the certificates of the `db` variable,
+//! are only told by their length in a `<field>_length` field: the digest of
+//! the event is what measures them. Event data that cannot be decoded after
+//! its type is decoded as text, or as bytes.
+
+use anyhow::{bail, Result};
+use byteorder::{LittleEndian, ReadBytesExt};
+use core::mem::size_of;
+use serde_json::{Map, Value};
+
+use super::{split_field, ParsedUefiPlatformFirmwareBlob2, CONTAINER_IMAGE_EVENT_TAG};
+
+/// Binary fields longer than this are only told by their length.
+pub const MAX_INLINE_BYTES: usize = 64;
+
+/// Size of an `EFI_GUID`.
+const GUID_SIZE: usize = 16;
+
+/// Size of the descriptor of a `TD_SHIM_PLATFORM_CONFIG_INFO`.
+const TD_SHIM_DESCRIPTOR_SIZE: usize = 16;
+
+/// Decode the event data `data` of an event of type `event_type`.
+pub fn decode(event_type: &str, data: &[u8]) -> Value {
+    let decoded = match event_type {
+        _ if data.starts_with(CONTAINER_IMAGE_EVENT_TAG) => container_image(data),
+        "EV_EFI_PLATFORM_FIRMWARE_BLOB2" => firmware_blob2(data),
+        "EV_EFI_PLATFORM_FIRMWARE_BLOB" => firmware_blob(data),
+        "EV_EFI_HANDOFF_TABLES2" => handoff_tables2(data),
+        "EV_EFI_HANDOFF_TABLES" => handoff_tables(data),
+        "EV_EFI_VARIABLE_DRIVER_CONFIG"
+        | "EV_EFI_VARIABLE_BOOT"
+        | "EV_EFI_VARIABLE_BOOT2"
+        | "EV_EFI_VARIABLE_AUTHORITY" => variable(data),
+        "EV_EFI_BOOT_SERVICES_APPLICATION"
+        | "EV_EFI_BOOT_SERVICES_DRIVER"
+        | "EV_EFI_RUNTIME_SERVICES_DRIVER" => image_load(data),
+        "EV_EVENT_TAG" => td_shim_config_info(data),
+        "EV_SEPARATOR" => separator(data),
+        _ => Ok(raw(data)),
+    };
+    decoded.unwrap_or_else(|e| {
+        debug!("Cannot decode {event_type} event data: {e:#}");
+        raw(data)
+    })
+}
+
+/// Event data of an unknown or malformed event: its text, if it is text,
+/// else its bytes.
+fn raw(data: &[u8]) -> Value {
+    let mut map = Map::new();
+    match text(data) {
+        Some(text) => {
+            map.insert("text".to_string(), Value::String(text));
+        }
+        None => bytes(&mut map, "data", data),
+    }
+    Value::Object(map)
+}
+
+/// `data` as a string, if it is printable UTF-8 once its NUL terminators
+/// are trimmed.
+fn text(data: &[u8]) -> Option<String> {
+    let text = std::str::from_utf8(data).ok()?.trim_end_matches('\0');
+    if text.chars().any(|c| c.is_control() && !c.is_whitespace()) {
+        return None;
+    }
+    Some(text.to_string())
+}
+
+/// Insert the binary field `name`, hex encoded, or its length only.
+fn bytes(map: &mut Map<String, Value>, name: &str, data: &[u8]) {
+    if data.len() <= MAX_INLINE_BYTES {
+        map.insert(name.to_string(), Value::String(hex::encode(data)));
+    } else {
+        map.insert(format!("{name}_length"), Value::from(data.len()));
+    }
+}
+
+/// Insert the field `name`, as text or as bytes.
+fn text_or_bytes(map: &mut Map<String, Value>, name: &str, data: &[u8]) {
+    match text(data) {
+        Some(text) => {
+            map.insert(name.to_string(), Value::String(text));
+        }
+        None => bytes(map, name, data),
+    }
+}
+
+/// An `EFI_GUID`, in its registry format, e.g.
+/// `8be4df61-93ca-11d2-aa0d-00e098032b8c`.
+fn guid(data: &mut &[u8]) -> Result<String> {
+    let guid = split_field(data, GUID_SIZE, "GUID")?;
+    Ok(format!(
+        "{:08x}-{:04x}-{:04x}-{}-{}",
+        (&guid[0..4]).read_u32::<LittleEndian>()?,
+        (&guid[4..6]).read_u16::<LittleEndian>()?,
+        (&guid[6..8]).read_u16::<LittleEndian>()?,
+        hex::encode(&guid[8..10]),
+        hex::encode(&guid[10..16]),
+    ))
+}
+
+fn read_u64(data: &mut &[u8], field: &str) -> Result<u64> {
+    Ok(split_field(data, size_of::<u64>(), field)?.read_u64::<LittleEndian>()?)
+}
+
+/// `split_field` of a length declared in the event data as a `u64`.
+fn split_u64_field<'a>(data: &mut &'a [u8], len: u64, field: &str) -> Result<&'a [u8]> {
+    let len = usize::try_from(len)?;
+    split_field(data, len, field)
+}
+
+fn container_image(data: &[u8]) -> Result<Value> {
+    let image: Value = serde_json::from_slice(&data[CONTAINER_IMAGE_EVENT_TAG.len()..])?;
+    let mut map = Map::new();
+    map.insert("container_image".to_string(), image);
+    Ok(Value::Object(map))
+}
+
+/// `UEFI_PLATFORM_FIRMWARE_BLOB2`
+fn firmware_blob2(data: &[u8]) -> Result<Value> {
+    let blob = ParsedUefiPlatformFirmwareBlob2::try_from(data.to_vec())?;
+    let mut map = Map::new();
+    text_or_bytes(&mut map, "description", &blob.desc);
+    map.insert("base".to_string(), Value::from(blob.blob_base));
+    map.insert("length".to_string(), Value::from(blob.blob_length));
+    Ok(Value::Object(map))
+}
+
+/// `UEFI_PLATFORM_FIRMWARE_BLOB`
+fn firmware_blob(mut data: &[u8]) -> Result<Value> {
+    let mut map = Map::new();
+    map.insert(
+        "base".to_string(),
+        Value::from(read_u64(&mut data, "Blob base")?),
+    );
+    map.insert(
+        "length".to_string(),
+        Value::from(read_u64(&mut data, "Blob length")?),
+    );
+    Ok(Value::Object(map))
+}
+
+/// The `EFI_CONFIGURATION_TABLE`s of `UEFI_HANDOFF_TABLE_POINTERS`, by
+/// their vendor GUID.
+fn configuration_tables(mut data: &[u8]) -> Result<Value> {
+    let count = read_u64(&mut data, "Number of tables")?;
+    let mut tables = Vec::new();
+    for _ in 0..count {
+        tables.push(Value::String(guid(&mut data)?));
+        read_u64(&mut data, "Vendor table")?;
+    }
+    Ok(Value::Array(tables))
+}
+
+/// `UEFI_HANDOFF_TABLE_POINTERS2`
+fn handoff_tables2(mut data: &[u8]) -> Result<Value> {
+    let desc_len = split_field(&mut data, size_of::<u8>(), "Description length")?[0];
+    let desc = split_field(&mut data, desc_len as usize, "Description")?;
+    let mut map = Map::new();
+    text_or_bytes(&mut map, "description", desc);
+    map.insert("tables".to_string(), configuration_tables(data)?);
+    Ok(Value::Object(map))
+}
+
+/// `UEFI_HANDOFF_TABLE_POINTERS`
+fn handoff_tables(data: &[u8]) -> Result<Value> {
+    let mut map = Map::new();
+    map.insert("tables".to_string(), configuration_tables(data)?);
+    Ok(Value::Object(map))
+}
+
+/// `UEFI_VARIABLE_DATA`
+fn variable(mut data: &[u8]) -> Result<Value> {
+    let vendor = guid(&mut data)?;
+    let name_length = read_u64(&mut data, "Unicode name length")?;
+    let data_length = read_u64(&mut data, "Variable data length")?;
+    let name = split_u64_field(
+        &mut data,
+        name_length.saturating_mul(size_of::<u16>() as u64),
+        "Unicode name",
+    )?;
+    let name: Vec<u16> = name
+        .chunks_exact(size_of::<u16>())
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    let value = split_u64_field(&mut data, data_length, "Variable data")?;
+
+    let mut map = Map::new();
+    map.insert("guid".to_string(), Value::String(vendor));
+    map.insert(
+        "name".to_string(),
+        Value::String(
+            String::from_utf16(&name)?
+                .trim_end_matches('\0')
+                .to_string(),
+        ),
+    );
+    bytes(&mut map, "data", value);
+    Ok(Value::Object(map))
+}
+
+/// `UEFI_IMAGE_LOAD_EVENT`
+fn image_load(mut data: &[u8]) -> Result<Value> {
+    let mut map = Map::new();
+    map.insert(
+        "image_location".to_string(),
+        Value::from(read_u64(&mut data, "Image location")?),
+    );
+    map.insert(
+        "image_length".to_string(),
+        Value::from(read_u64(&mut data, "Image length")?),
+    );
+    map.insert(
+        "link_time_address".to_string(),
+        Value::from(read_u64(&mut data, "Image link time address")?),
+    );
+    let device_path_length = read_u64(&mut data, "Device path length")?;
+    let device_path = split_u64_field(&mut data, device_path_length, "Device path")?;
+    bytes(&mut map, "device_path", device_path);
+    Ok(Value::Object(map))
+}
+
+/// `TD_SHIM_PLATFORM_CONFIG_INFO`, e.g. the TD HOB or the kernel parameters
+/// measured by td-shim.
+fn td_shim_config_info(mut data: &[u8]) -> Result<Value> {
+    let descriptor = split_field(&mut data, TD_SHIM_DESCRIPTOR_SIZE, "Descriptor")?;
+    let Some(descriptor) = text(descriptor) else {
+        bail!("Not a td-shim platform config info");
+    };
+    let info_length =
+        split_field(&mut data, size_of::<u32>(), "Info length")?.read_u32::<LittleEndian>()?;
+    let info = split_field(&mut data, usize::try_from(info_length)?, "Info")?;
+
+    let mut map = Map::new();
+    map.insert("descriptor".to_string(), Value::String(descriptor));
+    text_or_bytes(&mut map, "info", info);
+    Ok(Value::Object(map))
+}
+
+fn separator(data: &[u8]) -> Result<Value> {
+    let mut map = Map::new();
+    bytes(&mut map, "value", data);
+    Ok(Value::Object(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_decode_firmware_blob2() {
+        let mut data = vec![11];
+        data.extend_from_slice(b"td_payload\0");
+        data.extend_from_slice(&0x9010_0000u64.to_le_bytes());
+        data.extend_from_slice(&0x1000u64.to_le_bytes());
+        assert_eq!(
+            decode("EV_EFI_PLATFORM_FIRMWARE_BLOB2", &data),
+            json!({"description": "td_payload", "base": 0x9010_0000u64, "length": 0x1000})
+        );
+    }
+
+    #[test]
+    fn test_decode_variable() {
+        // SecureBoot of EFI_GLOBAL_VARIABLE
+        let mut data = vec![
+            0x61, 0xdf, 0xe4, 0x8b, 0xca, 0x93, 0xd2, 0x11, 0xaa, 0x0d, 0x00, 0xe0, 0x98, 0x03,
+            0x2b, 0x8c,
+        ];
+        data.extend_from_slice(&10u64.to_le_bytes());
+        data.extend_from_slice(&1u64.to_le_bytes());
+        for c in "SecureBoot".encode_utf16() {
+            data.extend_from_slice(&c.to_le_bytes());
+        }
+        data.push(1);
+        assert_eq!(
+            decode("EV_EFI_VARIABLE_DRIVER_CONFIG", &data),
+            json!({
+                "guid": "8be4df61-93ca-11d2-aa0d-00e098032b8c",
+                "name": "SecureBoot",
+                "data": "01"
+            })
+        );
+
+        // Variable data beyond the event data
+        data.truncate(data.len() - 1);
+        assert_eq!(
+            decode("EV_EFI_VARIABLE_DRIVER_CONFIG", &data),
+            json!({ "data": hex::encode(&data) })
+        );
+    }
+
+    #[test]
+    fn test_decode_image_load() {
+        let mut data = Vec::new();
+        for field in [0x7e00_0000u64, 0x20_0000, 0, 4] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(&[0x7f, 0xff, 0x04, 0x00]);
+        assert_eq!(
+            decode("EV_EFI_BOOT_SERVICES_APPLICATION", &data),
+            json!({
+                "image_location": 0x7e00_0000u64,
+                "image_length": 0x20_0000,
+                "link_time_address": 0,
+                "device_path": "7fff0400"
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_td_shim_config_info() {
+        let mut data = b"td_payload_info\0".to_vec();
+        data.extend_from_slice(&7u32.to_le_bytes());
+        data.extend_from_slice(b"rw ro=1");
+        assert_eq!(
+            decode("EV_EVENT_TAG", &data),
+            json!({"descriptor": "td_payload_info", "info": "rw ro=1"})
+        );
+
+        let mut data = b"td_hob\0\0\0\0\0\0\0\0\0\0".to_vec();
+        data.extend_from_slice(&128u32.to_le_bytes());
+        data.extend_from_slice(&[0xff; 128]);
+        assert_eq!(
+            decode("EV_EVENT_TAG", &data),
+            json!({"descriptor": "td_hob", "info_length": 128})
+        );
+    }
+
+    #[test]
+    fn test_decode_raw() {
+        assert_eq!(
+            decode("EV_IPL", b"grub_cmd linux\0"),
+            json!({"text": "grub_cmd linux"})
+        );
+        assert_eq!(
+            decode("EV_SEPARATOR", &[0; 4]),
+            json!({"value": "00000000"})
+        );
+        // Truncated event data of a known type
+        assert_eq!(
+            decode("EV_EFI_PLATFORM_FIRMWARE_BLOB", &[0xff; 12]),
+            json!({"data": "ff".repeat(12)})
+        );
+        assert_eq!(
+            decode("EV_EVENT_TAG", &[0xff; 200]),
+            json!({"data_length": 200})
+        );
+    }
+
+    #[test]
+    fn test_decode_container_image() {
+        let mut data = CONTAINER_IMAGE_EVENT_TAG.to_vec();
+        data.extend_from_slice(br#"{"name":"app","digest":"sha256:0123"}"#);
+        assert_eq!(
+            decode("EV_EVENT_TAG", &data),
+            json!({"container_image": {"name": "app", "digest": "sha256:0123"}})
+        );
+    }
+}
@@ -0,0 +1,554 @@
+use super::quote::ReportBody;
+use anyhow::*;
+use byteorder::{LittleEndian, ReadBytesExt};
+use core::mem::size_of;
+use eventlog_rs::{ElDigest, Eventlog, EventlogEntry};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384};
+use std::convert::TryFrom;
+use std::string::ToString;
+
+mod event_data;
+pub mod replay;
+
+pub use replay::{MeasuredEvent, Replay};
+
+#[derive(Debug, Clone, EnumString, Display)]
+pub enum MeasuredEntity {
+    #[strum(serialize = "td_hob\0")]
+    TdShim,
+    #[strum(serialize = "td_payload\0")]
+    TdShimKernel,
+    #[strum(serialize = "td_payload_info\0")]
+    TdShimKernelParams,
+    #[strum(serialize = "k\0e\0r\0n\0e\0l\0")]
+    TdvfKernel,
+    #[strum(serialize = "TdxTable\0")]
+    TdvfHob,
+    #[strum(serialize = "Fv(")]
+    TdvfCfv,
+}
+
+/// Size of the description of the firmware volume events of TDVF,
+/// `Fv(<FV name GUID>)` and its NUL terminator.
+const TDVF_FV_DESCRIPTION_SIZE: u8 = 41;
+
+/// Event data of the events the agent logs when it measures a container
+/// image into RTMR3 starts with this tag, followed by the [`ContainerImage`]
+/// in JSON. The event digest is the hash of the whole event data.
+pub const CONTAINER_IMAGE_EVENT_TAG: &[u8] = b"container_image\0";
+
+/// Index of RTMR3 in the CCEL, where index 0 stands for MRTD.
+const RTMR3_INDEX: u32 = 4;
+
+/// Size of an RTMR, which is always a SHA-384 register.
+const RTMR_SIZE: usize = 48;
+
+/// Hash algorithm of a measurement bank of the CCEL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum DigestAlgorithm {
+    Sha384,
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    /// Supported banks, in order of preference.
+    const PREFERRED: [Self; 2] = [Self::Sha384, Self::Sha256];
+
+    /// The digest of this algorithm among the digests of an event. The
+    /// eventlog names algorithms after their TPM_ALG_ID, e.g. `TPM_ALG_SHA384`.
+    pub(super) fn select<'a>(&self, digests: &'a [ElDigest]) -> Option<&'a [u8]> {
+        digests
+            .iter()
+            .find(|digest| {
+                digest
+                    .alg
+                    .trim_start_matches("TPM_ALG_")
+                    .eq_ignore_ascii_case(&self.to_string())
+            })
+            .map(|digest| digest.digest.as_slice())
+    }
+
+    pub(super) fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha384 => Sha384::digest(data).to_vec(),
+            Self::Sha256 => Sha256::digest(data).to_vec(),
+        }
+    }
+
+    /// Hex encoded digest claim. SHA-384 digests are bare, other digests are
+    /// prefixed with their algorithm, like `sha256:<hex>`, so that they never
+    /// match reference values of another algorithm.
+    pub fn claim(&self, digest: &[u8]) -> String {
+        match self {
+            Self::Sha384 => hex::encode(digest),
+            _ => format!("{self}:{}", hex::encode(digest)),
+        }
+    }
+}
+
+/// A container image the agent measured before launching it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContainerImage {
+    pub name: String,
+    pub digest: String,
+}
+
+impl ContainerImage {
+    /// Parse the image from the data of an event, or return `None` if it is
+    /// not a container image event. `digest` is the digest of the event that
+    /// was extended into the RTMR, it must match the event data.
+    fn from_event(
+        event_desc: &[u8],
+        algorithm: DigestAlgorithm,
+        digest: &[u8],
+    ) -> Result<Option<Self>> {
+        let Some(data) = event_desc.strip_prefix(CONTAINER_IMAGE_EVENT_TAG) else {
+            return Ok(None);
+        };
+        if algorithm.hash(event_desc) != digest {
+            bail!("Container image event data does not match its digest");
+        }
+        let image = serde_json::from_slice(data).context("Illegal container image event")?;
+        Ok(Some(image))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Rtmr {
+    pub rtmr0: [u8; 48],
+    pub rtmr1: [u8; 48],
+    pub rtmr2: [u8; 48],
+    pub rtmr3: [u8; 48],
+}
+
+impl From<[[u8; RTMR_SIZE]; 4]> for Rtmr {
+    fn from([rtmr0, rtmr1, rtmr2, rtmr3]: [[u8; RTMR_SIZE]; 4]) -> Self {
+        Self {
+            rtmr0,
+            rtmr1,
+            rtmr2,
+            rtmr3,
+        }
+    }
+}
+
+impl From<&ReportBody> for Rtmr {
+    fn from(report_body: &ReportBody) -> Self {
+        Self {
+            rtmr0: report_body.rtmr_0,
+            rtmr1: report_body.rtmr_1,
+            rtmr2: report_body.rtmr_2,
+            rtmr3: report_body.rtmr_3,
+        }
+    }
+}
+
+impl Rtmr {
+    /// The registers, RTMR[0] to RTMR[3], in hex.
+    pub fn to_hex(self) -> [String; 4] {
+        [self.rtmr0, self.rtmr1, self.rtmr2, self.rtmr3].map(hex::encode)
+    }
+}
+
+#[derive(Clone)]
+pub struct CcEventLog {
+    pub cc_events: Eventlog,
+}
+
+impl TryFrom<Vec<u8>> for CcEventLog {
+    type Error = anyhow::Error;
+    fn try_from(data: Vec<u8>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            cc_events: Eventlog::try_from(data)?,
+        })
+    }
+}
+
+impl CcEventLog {
+    /// Check the RTMRs of the TD quote against the replay of the eventlog,
+    /// register by register.
+    pub fn integrity_check(&self, rtmr_from_quote: Rtmr) -> Result<()> {
+        self.replay()?.check(rtmr_from_quote)
+    }
+
+    /// Events that are extended into a measurement register. `EV_NO_ACTION`
+    /// events, like the spec ID event, are informative only.
+    fn measured_events(&self) -> impl Iterator<Item = &EventlogEntry> {
+        self.cc_events
+            .log
+            .iter()
+            .filter(|event_entry| event_entry.event_type != "EV_NO_ACTION")
+    }
+
+    /// The measurement bank the digests of the eventlog are taken from: the
+    /// SHA-384 bank if the log has one, else the SHA-256 bank.
+    pub fn digest_algorithm(&self) -> Result<DigestAlgorithm> {
+        DigestAlgorithm::PREFERRED
+            .into_iter()
+            .find(|algorithm| {
+                self.measured_events()
+                    .any(|event_entry| algorithm.select(&event_entry.digests).is_some())
+            })
+            .ok_or_else(|| anyhow!("No SHA-384 or SHA-256 digests in CC EventLog"))
+    }
+
+    /// Replay the events into the RTMRs. RTMRs are SHA-384 registers, the
+    /// digests of a SHA-256 bank are extended into them zero padded.
+    pub fn rebuild_rtmr(&self) -> Result<Rtmr> {
+        Ok(self.replay()?.rtmr)
+    }
+
+    /// Replay the events into the RTMRs, decoding the data of every event.
+    pub fn replay(&self) -> Result<Replay> {
+        replay::replay(self)
+    }
+
+    pub fn query_digest(&self, entity: MeasuredEntity) -> Option<String> {
+        let event_desc_prefix = Self::generate_query_key_prefix(entity)?;
+        let algorithm = self.digest_algorithm().ok()?;
+
+        for event_entry in self.measured_events() {
+            if event_entry.event_desc.starts_with(&event_desc_prefix) {
+                let digest = algorithm.select(&event_entry.digests)?;
+                return Some(algorithm.claim(digest));
+            }
+        }
+        None
+    }
+
+    /// Every measured event of `entity`, in log order.
+    pub fn query_events(&self, entity: MeasuredEntity) -> impl Iterator<Item = &EventlogEntry> {
+        let event_desc_prefix = Self::generate_query_key_prefix(entity).unwrap_or_default();
+        self.measured_events()
+            .filter(move |event_entry| event_entry.event_desc.starts_with(&event_desc_prefix))
+    }
+
+    /// The container images measured into RTMR3, in launch order.
+    pub fn container_images(&self) -> Result<Vec<ContainerImage>> {
+        let mut images = Vec::new();
+        if self.cc_events.log.is_empty() {
+            return Ok(images);
+        }
+
+        let algorithm = self.digest_algorithm()?;
+        for event_entry in self.measured_events() {
+            if event_entry.target_measurement_registry != RTMR3_INDEX {
+                continue;
+            }
+            let digest = match algorithm.select(&event_entry.digests) {
+                Some(digest) => digest,
+                None => continue,
+            };
+            if let Some(image) =
+                ContainerImage::from_event(&event_entry.event_desc, algorithm, digest)?
+            {
+                images.push(image);
+            }
+        }
+        Ok(images)
+    }
+
+    #[allow(dead_code)]
+    pub fn query_event_data(&self, entity: MeasuredEntity) -> Option<Vec<u8>> {
+        let event_desc_prefix = Self::generate_query_key_prefix(entity)?;
+
+        for event_entry in self.cc_events.log.clone() {
+            if event_entry.event_desc.len() < event_desc_prefix.len() {
+                continue;
+            }
+            if &event_entry.event_desc[..event_desc_prefix.len()] == event_desc_prefix.as_slice() {
+                return Some(event_entry.event_desc);
+            }
+        }
+        None
+    }
+
+    #[allow(unused_assignments)]
+    fn generate_query_key_prefix(entity: MeasuredEntity) -> Option<Vec<u8>> {
+        let mut event_desc_prefix = Vec::new();
+        match entity {
+            MeasuredEntity::TdShimKernel | MeasuredEntity::TdvfHob => {
+                // Event data is in UEFI_PLATFORM_FIRMWARE_BLOB2 format
+                // Defined in TCG PC Client Platform Firmware Profile Specification section
+                // 'UEFI_PLATFORM_FIRMWARE_BLOB Structure Definition', or in
+                // UEFI_HANDOFF_TABLE_POINTERS2 format for the TD HOB, which
+                // also starts with the size of its description
+                let entity_name = entity.to_string();
+                event_desc_prefix = vec![entity_name.as_bytes().len() as u8];
+                event_desc_prefix.extend_from_slice(entity_name.as_bytes());
+            }
+            MeasuredEntity::TdvfKernel => {
+                event_desc_prefix = entity.to_string().as_bytes().to_vec();
+            }
+            MeasuredEntity::TdvfCfv => {
+                // Event data is in UEFI_PLATFORM_FIRMWARE_BLOB2 format, whose
+                // description tells the name GUID of the CFV
+                event_desc_prefix = vec![TDVF_FV_DESCRIPTION_SIZE];
+                event_desc_prefix.extend_from_slice(entity.to_string().as_bytes());
+            }
+            MeasuredEntity::TdShim | MeasuredEntity::TdShimKernelParams => {
+                // Event data is in TD_SHIM_PLATFORM_CONFIG_INFO format
+                // Defined in td-shim spec 'Table 3.5-4 TD_SHIM_PLATFORM_CONFIG_INFO'
+                // link: https://github.com/confidential-containers/td-shim/blob/main/doc/tdshim_spec.md
+                event_desc_prefix = entity.to_string().as_bytes().to_vec();
+            }
+        }
+        Some(event_desc_prefix)
+    }
+}
+
+/// Split the `len` bytes of `field` off the front of the event data `data`.
+/// Lengths declared in the event data are not trusted: they fail to parse
+/// instead of panicking when they exceed the data left.
+pub(super) fn split_field<'a>(data: &mut &'a [u8], len: usize, field: &str) -> Result<&'a [u8]> {
+    if data.len() < len {
+        bail!(
+            "{field} of {len} bytes exceeds the {} bytes left in the event data",
+            data.len()
+        );
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Ok(head)
+}
+
+/// Defined in TCG PC Client Platform Firmware Profile Specification section
+/// 'UEFI_PLATFORM_FIRMWARE_BLOB Structure Definition'
+pub struct ParsedUefiPlatformFirmwareBlob2 {
+    pub desc_len: u8,
+    pub desc: Vec<u8>,
+    pub blob_base: u64,
+    pub blob_length: u64,
+}
+
+impl TryFrom<Vec<u8>> for ParsedUefiPlatformFirmwareBlob2 {
+    type Error = anyhow::Error;
+
+    fn try_from(data: Vec<u8>) -> Result<Self, Self::Error> {
+        let mut data = &data[..];
+        let desc_len = split_field(&mut data, size_of::<u8>(), "Description length")?[0];
+        let desc = split_field(&mut data, desc_len as usize, "Description")?.to_vec();
+        let blob_base =
+            split_field(&mut data, size_of::<u64>(), "Blob base")?.read_u64::<LittleEndian>()?;
+        let blob_length =
+            split_field(&mut data, size_of::<u64>(), "Blob length")?.read_u64::<LittleEndian>()?;
+
+        Ok(Self {
+            desc_len,
+            desc,
+            blob_base,
+            blob_length,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_parse_eventlog() {
+        let ccel_bin = fs::read("../test_data/CCEL_data").unwrap();
+        let ccel = CcEventLog::try_from(ccel_bin).unwrap();
+
+        let _ = fs::write(
+            "test_data/parse_eventlog_output.txt",
+            format!("{}", &ccel.cc_events),
+        );
+    }
+
+    #[test]
+    fn test_rebuild_rtmr() {
+        let ccel_bin = fs::read("../test_data/CCEL_data").unwrap();
+        let ccel = CcEventLog::try_from(ccel_bin).unwrap();
+
+        let rtmr_result = ccel.rebuild_rtmr();
+        assert!(rtmr_result.is_ok());
+        let rtmr = rtmr_result.unwrap();
+
+        let output = format!(
+            "RTMR[0]\n\t{}\nRTMR[1]\n\t{}\nRTMR[2]\n\t{}\nRTMR[3]\n\t{}",
+            hex::encode(rtmr.rtmr0),
+            hex::encode(rtmr.rtmr1),
+            hex::encode(rtmr.rtmr2),
+            hex::encode(rtmr.rtmr3)
+        );
+
+        let _ = fs::write("../test_data/rebuild_rtmr_output.txt", output);
+    }
+
+    #[test]
+    fn test_query_digest() {
+        let ccel_bin = fs::read("../test_data/CCEL_data").unwrap();
+        let ccel = CcEventLog::try_from(ccel_bin).unwrap();
+
+        let kernel_hash = ccel.query_digest(MeasuredEntity::TdShimKernel);
+        let kernel_params_hash = ccel.query_digest(MeasuredEntity::TdShimKernelParams);
+
+        assert!(kernel_hash.is_some());
+        assert!(kernel_params_hash.is_some());
+
+        assert_eq!(
+            kernel_hash.unwrap(),
+            "5b7aa6572f649714ff00b6a2b9170516a068fd1a0ba72aa8de27574131d454e6396d3bfa1727d9baf421618a942977fa".to_string()
+        );
+        assert_eq!(
+            kernel_params_hash.unwrap(),
+            "64ed1e5a47e8632f80faf428465bd987af3e8e4ceb10a5a9f387b6302e30f4993bded2331f0691c4a38ad34e4cbbc627".to_string()
+        );
+    }
+
+    #[test]
+    fn test_query_tdvf_digests() {
+        let event = |event_desc: &[u8], digest: &[u8]| EventlogEntry {
+            target_measurement_registry: 1,
+            event_type: "EV_EFI_PLATFORM_FIRMWARE_BLOB2".to_string(),
+            digests: vec![ElDigest {
+                alg: "TPM_ALG_SHA384".to_string(),
+                digest: Sha384::digest(digest).to_vec(),
+            }],
+            event_desc: event_desc.to_vec(),
+        };
+        let mut cfv = vec![TDVF_FV_DESCRIPTION_SIZE];
+        cfv.extend_from_slice(b"Fv(7CB8BDC9-F8EB-4F34-AAEA-3EE4AF6516A1)\0");
+        let mut hob = vec![9];
+        hob.extend_from_slice(b"TdxTable\0");
+        let ccel = CcEventLog {
+            cc_events: Eventlog {
+                log: vec![event(&hob, b"hob"), event(&cfv, b"cfv")],
+            },
+        };
+
+        assert_eq!(
+            ccel.query_digest(MeasuredEntity::TdvfHob),
+            Some(hex::encode(Sha384::digest(b"hob")))
+        );
+        assert_eq!(
+            ccel.query_digest(MeasuredEntity::TdvfCfv),
+            Some(hex::encode(Sha384::digest(b"cfv")))
+        );
+        assert_eq!(ccel.query_digest(MeasuredEntity::TdShim), None);
+    }
+
+    #[test]
+    fn test_container_image_event() {
+        let mut event_desc = CONTAINER_IMAGE_EVENT_TAG.to_vec();
+        event_desc
+            .extend_from_slice(br#"{"name":"registry.example.com/app:v1","digest":"sha256:0123"}"#);
+        let digest = Sha384::digest(&event_desc).to_vec();
+
+        let image = ContainerImage::from_event(&event_desc, DigestAlgorithm::Sha384, &digest)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            image,
+            ContainerImage {
+                name: "registry.example.com/app:v1".to_string(),
+                digest: "sha256:0123".to_string(),
+            }
+        );
+
+        // The event data was changed after it was measured.
+        assert!(
+            ContainerImage::from_event(&event_desc, DigestAlgorithm::Sha384, &[0; 48]).is_err()
+        );
+        assert!(ContainerImage::from_event(&event_desc, DigestAlgorithm::Sha256, &digest).is_err());
+
+        assert!(
+            ContainerImage::from_event(b"td_payload\0", DigestAlgorithm::Sha384, &digest)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    fn event(index: u32, digests: &[(&str, Vec<u8>)], event_desc: &[u8]) -> EventlogEntry {
+        EventlogEntry {
+            target_measurement_registry: index,
+            event_type: "EV_EVENT_TAG".to_string(),
+            digests: digests
+                .iter()
+                .map(|(alg, digest)| ElDigest {
+                    alg: alg.to_string(),
+                    digest: digest.clone(),
+                })
+                .collect(),
+            event_desc: event_desc.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_parse_firmware_blob2() {
+        let mut data = vec![3];
+        data.extend_from_slice(b"Fv\0");
+        data.extend_from_slice(&0xff00_0000u64.to_le_bytes());
+        data.extend_from_slice(&0x1000u64.to_le_bytes());
+        let blob = ParsedUefiPlatformFirmwareBlob2::try_from(data.clone()).unwrap();
+        assert_eq!(blob.desc_len, 3);
+        assert_eq!(blob.desc, b"Fv\0");
+        assert_eq!(blob.blob_base, 0xff00_0000);
+        assert_eq!(blob.blob_length, 0x1000);
+
+        // Truncated anywhere
+        for len in 0..data.len() {
+            assert!(ParsedUefiPlatformFirmwareBlob2::try_from(data[..len].to_vec()).is_err());
+        }
+
+        // Description longer than the event data
+        data[0] = u8::MAX;
+        assert!(ParsedUefiPlatformFirmwareBlob2::try_from(data).is_err());
+    }
+
+    #[test]
+    fn test_sha256_bank() {
+        let kernel = MeasuredEntity::TdvfKernel.to_string();
+        let sha256 = Sha256::digest(b"kernel").to_vec();
+        let ccel = CcEventLog {
+            cc_events: Eventlog {
+                log: vec![event(
+                    3,
+                    &[("TPM_ALG_SHA256", sha256.clone())],
+                    kernel.as_bytes(),
+                )],
+            },
+        };
+
+        assert_eq!(ccel.digest_algorithm().unwrap(), DigestAlgorithm::Sha256);
+        assert_eq!(
+            ccel.query_digest(MeasuredEntity::TdvfKernel).unwrap(),
+            format!("sha256:{}", hex::encode(&sha256))
+        );
+
+        let mut extended = [0u8; 96];
+        extended[48..80].copy_from_slice(&sha256);
+        let rtmr = ccel.rebuild_rtmr().unwrap();
+        assert_eq!(rtmr.rtmr2[..], Sha384::digest(extended)[..]);
+        assert_eq!(rtmr.rtmr0, [0; 48]);
+    }
+
+    #[test]
+    fn test_sha384_bank_is_preferred() {
+        let kernel = MeasuredEntity::TdvfKernel.to_string();
+        let sha384 = Sha384::digest(b"kernel").to_vec();
+        let ccel = CcEventLog {
+            cc_events: Eventlog {
+                log: vec![event(
+                    3,
+                    &[
+                        ("TPM_ALG_SHA256", Sha256::digest(b"kernel").to_vec()),
+                        ("TPM_ALG_SHA384", sha384.clone()),
+                    ],
+                    kernel.as_bytes(),
+                )],
+            },
+        };
+
+        assert_eq!(ccel.digest_algorithm().unwrap(), DigestAlgorithm::Sha384);
+        assert_eq!(
+            ccel.query_digest(MeasuredEntity::TdvfKernel).unwrap(),
+            hex::encode(&sha384)
+        );
+    }
+}
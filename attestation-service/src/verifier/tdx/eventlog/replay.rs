@@ -0,0 +1,239 @@
+//! Replay of the CC EventLog into the RTMRs.
+//!
+//! Every event that is extended into a measurement register is walked in
+//! log order: its digest of the measurement bank of the log is extended
+//! into the RTMR it targets, and its event data is decoded after its event
+//! type, see [`super::event_data`]. The RTMRs so rebuilt are compared with
+//! those of the TD quote register by register, and the walked events are
+//! the `ccel.events` claims, e.g.
+//!
+//! ```json
+//! "events": [
+//!     {
+//!         "rtmr": 1,
+//!         "mr_index": 2,
+//!         "type": "EV_EFI_PLATFORM_FIRMWARE_BLOB2",
+//!         "digest": "5b7aa6572f649714ff00b6a2b9170516a068fd1a0ba72aa8de27574131d454e6396d3bfa1727d9baf421618a942977fa",
+//!         "data": {
+//!             "description": "td_payload",
+//!             "base": 9441280,
+//!             "length": 268435456
+//!         }
+//!     }
+//! ]
+//! ```
+//!
+//! Only events of RTMR[0] to RTMR[3] are replayed: events of MRTD, which is
+//! measured by the TDX module rather than extended, and of unknown registers
+//! are skipped.
+
+use anyhow::{anyhow, bail, Result};
+use eventlog_rs::EventlogEntry;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha384};
+
+use super::{event_data, CcEventLog, DigestAlgorithm, Rtmr, RTMR_SIZE};
+use crate::verifier::warnings;
+
+/// An event of the CC EventLog extended into an RTMR.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MeasuredEvent {
+    /// The RTMR the event is extended into, 0 to 3.
+    pub rtmr: u32,
+    /// Index of the register in the CCEL, where index 0 stands for MRTD
+    /// and index 1 to 4 for RTMR[0] to RTMR[3]. It is the PCR index of the
+    /// event in TPM eventlogs.
+    pub mr_index: u32,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    /// Digest of the event, as a digest claim of the bank of the log.
+    pub digest: String,
+    /// The event data, decoded after the event type.
+    pub data: Value,
+}
+
+/// The RTMRs and the events of a replayed CC EventLog.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub rtmr: Rtmr,
+    /// The events extended into the RTMRs, in log order.
+    pub events: Vec<MeasuredEvent>,
+}
+
+/// A register whose value in the TD quote is not the replay of the
+/// eventlog.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtmrMismatch {
+    pub index: usize,
+    pub quote: String,
+    pub eventlog: String,
+}
+
+impl Replay {
+    /// Compare the replayed registers with `rtmr_from_quote`, RTMR by RTMR.
+    pub fn mismatches(&self, rtmr_from_quote: Rtmr) -> Vec<RtmrMismatch> {
+        rtmr_from_quote
+            .to_hex()
+            .into_iter()
+            .zip(self.rtmr.to_hex())
+            .enumerate()
+            .filter(|(_, (quote, eventlog))| quote != eventlog)
+            .map(|(index, (quote, eventlog))| RtmrMismatch {
+                index,
+                quote,
+                eventlog,
+            })
+            .collect()
+    }
+
+    /// Fail unless every RTMR of `rtmr_from_quote` is the replay of the
+    /// eventlog.
+    pub fn check(&self, rtmr_from_quote: Rtmr) -> Result<()> {
+        let mismatches = self.mismatches(rtmr_from_quote);
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+        let details = mismatches
+            .iter()
+            .map(|m| {
+                format!(
+                    "RTMR[{}] of the TD quote is {}, the eventlog replays to {}",
+                    m.index, m.quote, m.eventlog
+                )
+            })
+            .collect::<Vec<_>>();
+        bail!(
+            "RTMR values from TD quote is not equal with the values from EventLog: {}",
+            details.join("; ")
+        )
+    }
+}
+
+/// Extend `digest` into `rtmr`. Digests shorter than an RTMR, of the
+/// SHA-256 bank, are zero padded.
+fn extend(rtmr: &mut [u8; RTMR_SIZE], digest: &[u8]) {
+    let mut extended = [0u8; RTMR_SIZE];
+    extended[..digest.len()].copy_from_slice(digest);
+    let mut hasher = Sha384::new();
+    hasher.update(&rtmr[..]);
+    hasher.update(extended);
+    rtmr.copy_from_slice(&hasher.finalize());
+}
+
+fn measured_event(
+    event_entry: &EventlogEntry,
+    algorithm: DigestAlgorithm,
+    digest: &[u8],
+) -> MeasuredEvent {
+    MeasuredEvent {
+        rtmr: event_entry.target_measurement_registry - 1,
+        mr_index: event_entry.target_measurement_registry,
+        event_type: event_entry.event_type.clone(),
+        digest: algorithm.claim(digest),
+        data: event_data::decode(&event_entry.event_type, &event_entry.event_desc),
+    }
+}
+
+/// Replay the measured events of `ccel`.
+pub fn replay(ccel: &CcEventLog) -> Result<Replay> {
+    let mut rtmrs = [[0u8; RTMR_SIZE]; 4];
+    let mut events = Vec::new();
+    if ccel.cc_events.log.is_empty() {
+        return Ok(Replay {
+            rtmr: Rtmr::from(rtmrs),
+            events,
+        });
+    }
+
+    let algorithm = ccel.digest_algorithm()?;
+    for event_entry in ccel.measured_events() {
+        // Index 0 stands for MRTD, which events do not extend.
+        let rtmr = match event_entry.target_measurement_registry {
+            index @ 1..=4 => &mut rtmrs[index as usize - 1],
+            0 => continue,
+            index => {
+                warnings::raise(format!(
+                    "Skipped CC EventLog event {} of unknown register {index}",
+                    event_entry.event_type
+                ));
+                continue;
+            }
+        };
+        let digest = algorithm
+            .select(&event_entry.digests)
+            .ok_or_else(|| anyhow!("CC EventLog event without {algorithm} digest"))?;
+
+        extend(rtmr, digest);
+        events.push(measured_event(event_entry, algorithm, digest));
+    }
+
+    Ok(Replay {
+        rtmr: Rtmr::from(rtmrs),
+        events,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::MeasuredEntity;
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn test_replay_ccel() {
+        let ccel = CcEventLog::try_from(fs::read("../test_data/CCEL_data").unwrap()).unwrap();
+        let replay = replay(&ccel).unwrap();
+
+        let types: Vec<_> = replay
+            .events
+            .iter()
+            .map(|event| (event.rtmr, event.event_type.as_str()))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                (0, "EV_EVENT_TAG"),
+                (0, "EV_SEPARATOR"),
+                (1, "EV_SEPARATOR"),
+                (1, "EV_EFI_PLATFORM_FIRMWARE_BLOB2"),
+                (1, "EV_EVENT_TAG"),
+            ]
+        );
+        assert_eq!(replay.events[0].data["descriptor"], "td_hob");
+        assert_eq!(
+            replay.events[3].digest,
+            ccel.query_digest(MeasuredEntity::TdShimKernel).unwrap()
+        );
+        assert_eq!(replay.events[3].data["description"], json!("td_payload"));
+        assert_eq!(
+            replay.events[4].data["info"],
+            "root=/dev/vda1 console=hvc0 rw"
+        );
+
+        // The registers without events keep their initial value.
+        assert_eq!(replay.rtmr.rtmr2, [0; RTMR_SIZE]);
+        assert_eq!(replay.rtmr.rtmr3, [0; RTMR_SIZE]);
+        assert!(replay.mismatches(replay.rtmr).is_empty());
+        assert!(replay.check(replay.rtmr).is_ok());
+    }
+
+    #[test]
+    fn test_per_rtmr_mismatch() {
+        let ccel = CcEventLog::try_from(fs::read("../test_data/CCEL_data").unwrap()).unwrap();
+        let replay = replay(&ccel).unwrap();
+
+        let mut quote = replay.rtmr;
+        quote.rtmr1 = [0; RTMR_SIZE];
+        let mismatches = replay.mismatches(quote);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].index, 1);
+        assert_eq!(mismatches[0].quote, hex::encode([0; RTMR_SIZE]));
+        assert_eq!(mismatches[0].eventlog, hex::encode(replay.rtmr.rtmr1));
+
+        let err = replay.check(quote).unwrap_err();
+        assert!(err.to_string().contains("RTMR[1] of the TD quote"));
+        assert!(!err.to_string().contains("RTMR[0]"));
+    }
+}
@@ -1,19 +1,29 @@
 use anyhow::{anyhow, Context, Result};
 extern crate serde;
 extern crate strum;
-use crate::verifier::tdx::claims::generate_parsed_claim;
+use crate::verifier::tdx::claims::{generate_parsed_claim, generate_td_report_claims};
 
 use self::serde::{Deserialize, Serialize};
+use super::anchors::{TrustAnchor, TRUST_ANCHOR_CLAIM};
+use super::charset::EventlogStrings;
+use super::pipeline::{self, Pipeline, Stage, TDX_STAGES};
+use super::tcb::Tcb;
 use super::*;
 use async_trait::async_trait;
 use base64::Engine;
 use eventlog::{CcEventLog, Rtmr};
-use quote::{ecdsa_quote_verification, parse_tdx_quote};
+use quote::{check_anomalies, ecdsa_quote_verification, parse_tdx_quote, Quote, ReportBody};
 use sha2::{Digest, Sha384};
+use std::sync::Arc;
+use td_report::{parse_td_report, TdReport};
 
 mod claims;
+mod cmdline;
+mod consistency;
 mod eventlog;
+mod partitioning;
 mod quote;
+mod td_report;
 
 #[derive(Serialize, Deserialize, Debug)]
 struct TdxEvidence {
@@ -21,11 +31,74 @@ struct TdxEvidence {
     // refer to https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html#cc-event-log-acpi-table.
     cc_eventlog: Option<String>,
     // Base64 encoded TD quote.
-    quote: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quote: Option<String>,
+    // Base64 encoded TD report, in place of the quote, see [`td_report`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    td_report: Option<String>,
+    // Base64 encoded SGX quote of an enclave running inside the TD. Its
+    // report data must be `SHA384(MRTD || TD report data)`, zero padded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sgx_quote: Option<String>,
+    // Base64 encoded HCL report of the paravisor of a TD partitioning
+    // guest, wrapping the TD report of the quote.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hcl_report: Option<String>,
+    // vTPM quote of the PCRs of the L2 guest of a TD partitioning guest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    vtpm_quote: Option<vtpm::VtpmQuote>,
 }
 
-#[derive(Debug, Default)]
-pub struct Tdx {}
+#[derive(Debug)]
+pub struct Tdx {
+    pub versions: VersionRange,
+    /// Accepted SGX quote versions of enclaves nested in the TD.
+    pub enclave_versions: VersionRange,
+    pub pipeline: Pipeline,
+    /// Reject evidence without CC eventlog, instead of a warning and an
+    /// `eventlog_present` claim set to false.
+    pub require_eventlog: bool,
+    /// Comparison of the report data with the nonce and TEE public key.
+    pub report_data: ReportDataMode,
+    /// DCAP collateral of the quotes.
+    pub collateral: Arc<collateral::Provider>,
+    /// Reject quotes with structural anomalies, instead of a warning, see
+    /// [`super::anomalies`].
+    pub strict_quote_parsing: bool,
+    /// How the strings of the CC eventlog are decoded, see
+    /// [`super::charset`].
+    pub eventlog_strings: EventlogStrings,
+    /// Claim the kernel command line in canonical form, see [`cmdline`].
+    pub canonical_kernel_cmdline: bool,
+    /// Claim the kernel flags as booleans, with these flags `false` if
+    /// absent, see [`cmdline::flags`].
+    pub kernel_flags: Option<Vec<String>>,
+    /// Intel root CAs that the PCK certificate chain must end at, if any,
+    /// see [`super::anchors`].
+    pub trust_anchors: Arc<Vec<TrustAnchor>>,
+    /// Accept evidence with a TD report in place of the quote, see
+    /// [`td_report`].
+    pub td_reports: bool,
+}
+
+impl Default for Tdx {
+    fn default() -> Self {
+        Self {
+            versions: VersionRange::default(),
+            enclave_versions: VersionRange::default(),
+            pipeline: Pipeline::new(TDX_STAGES),
+            require_eventlog: false,
+            report_data: ReportDataMode::default(),
+            collateral: Arc::default(),
+            strict_quote_parsing: false,
+            eventlog_strings: EventlogStrings::default(),
+            canonical_kernel_cmdline: false,
+            kernel_flags: None,
+            trust_anchors: Arc::default(),
+            td_reports: false,
+        }
+    }
+}
 
 #[async_trait]
 impl Verifier for Tdx {
@@ -37,77 +110,366 @@ impl Verifier for Tdx {
         let tdx_evidence = serde_json::from_str::<TdxEvidence>(&attestation.tee_evidence)
             .context("Deserialize TDX Evidence failed.")?;
 
-        let mut hasher = Sha384::new();
-        hasher.update(&nonce);
-        hasher.update(&attestation.tee_pubkey.k_mod);
-        hasher.update(&attestation.tee_pubkey.k_exp);
-        let mut hash_of_nonce_pubkey = hasher.finalize().to_vec();
-        hash_of_nonce_pubkey.extend([0; 16]);
+        verify_evidence(self, &nonce, attestation, &tdx_evidence)
+            .await
+            .context("TDX Verifier")
+    }
 
-        log::info!(
-            "HASH(nonce||pubkey):\n\t{}\n",
-            hex::encode(&hash_of_nonce_pubkey)
-        );
+    fn claim_transforms(&self) -> &'static [(&'static str, ClaimTransform)] {
+        TDX_CLAIM_TRANSFORMS
+    }
 
-        verify_evidence(hash_of_nonce_pubkey, &tdx_evidence)
-            .await
-            .map_err(|e| anyhow!("TDX Verifier: {:?}", e))
+    fn freshness_methods(&self) -> Vec<FreshnessMethod> {
+        self.pipeline.freshness_methods()
     }
-}
 
-#[allow(unused_assignments)]
-async fn verify_evidence(
-    hash_of_nonce_pubkey: Vec<u8>,
-    evidence: &TdxEvidence,
-) -> Result<TeeEvidenceParsedClaim> {
-    // Verify TD quote ECDSA signature.
-    let quote_bin = base64::engine::general_purpose::STANDARD.decode(evidence.quote.clone())?;
-    ecdsa_quote_verification(quote_bin.as_slice()).await?;
+    fn signed_evidence(&self, attestation: &Attestation) -> Result<Vec<u8>> {
+        let tdx_evidence = serde_json::from_str::<TdxEvidence>(&attestation.tee_evidence)
+            .context("Deserialize TDX Evidence failed.")?;
+        let signed = tdx_evidence
+            .quote
+            .or(tdx_evidence.td_report)
+            .ok_or_else(|| anyhow!("TDX evidence has neither a quote nor a TD report"))?;
+        Ok(base64::engine::general_purpose::STANDARD.decode(signed)?)
+    }
+}
 
-    // Parse quote and Compare report data
-    let quote = parse_tdx_quote(&quote_bin)?;
+/// The attribute bitmaps of the TD quote, little-endian.
+const TDX_CLAIM_TRANSFORMS: &[(&str, ClaimTransform)] = &[
+    ("quote.body.seam_attributes", ClaimTransform::LeUint),
+    ("quote.body.td_attributes", ClaimTransform::LeUint),
+    ("quote.body.xfam", ClaimTransform::LeUint),
+];
 
-    log::info!("{}\n", &quote);
+/// The TD quote of the evidence, or its TD report.
+enum Report {
+    Quote(Vec<u8>, Quote),
+    TdReport(TdReport),
+}
 
-    if hash_of_nonce_pubkey != quote.report_body.report_data.to_vec() {
-        return Err(anyhow!(
-            "HASH(nonce||pubkey) is different from that in TDX Quote"
-        ));
+impl Report {
+    fn body(&self) -> &ReportBody {
+        match self {
+            Self::Quote(_, quote) => &quote.report_body,
+            Self::TdReport(td_report) => &td_report.body,
+        }
     }
+}
+
+async fn verify_evidence(
+    verifier: &Tdx,
+    nonce: &str,
+    attestation: &Attestation,
+    evidence: &TdxEvidence,
+) -> Result<TeeEvidenceParsedClaim> {
+    // Parse
+    pipeline::enter(Stage::Parse);
+    let report = match (&evidence.quote, &evidence.td_report) {
+        (Some(quote), None) => {
+            let step = profile::step("decode_quote");
+            let quote_bin = base64::engine::general_purpose::STANDARD.decode(quote)?;
+            let quote = parse_tdx_quote(&quote_bin)?;
+            drop(step);
+            verifier
+                .versions
+                .check("TD quote", u16::from_le_bytes(quote.header.version).into())?;
+            let step = profile::step("quote_anomalies");
+            check_anomalies(&quote_bin, &quote, verifier.strict_quote_parsing)?;
+            drop(step);
+            log::info!("{}\n", &quote);
+            Report::Quote(quote_bin, quote)
+        }
+        (None, Some(td_report)) => {
+            if !verifier.td_reports {
+                bail!("TD reports are not accepted in place of quotes, see `tdx_td_reports`");
+            }
+            if evidence.sgx_quote.is_some() {
+                bail!("An SGX quote is only accepted with a TD quote");
+            }
+            let _step = profile::step("decode_td_report");
+            let report_bin = base64::engine::general_purpose::STANDARD.decode(td_report)?;
+            let td_report = parse_td_report(&report_bin, verifier.strict_quote_parsing)?;
+            log::info!("TD report:\n{}\n", td_report.body);
+            Report::TdReport(td_report)
+        }
+        (Some(_), Some(_)) => bail!("TDX evidence has both a quote and a TD report"),
+        (None, None) => bail!("TDX evidence has neither a quote nor a TD report"),
+    };
 
-    // Verify Integrity of CC Eventlog
-    let mut ccel_data = Vec::default();
-    let mut ccel_option = Option::default();
-    match &evidence.cc_eventlog {
+    let ccel = match &evidence.cc_eventlog {
         Some(el) => {
-            ccel_data = base64::engine::general_purpose::STANDARD.decode(el)?;
+            let _step = profile::step("decode_eventlog");
+            let ccel_data = base64::engine::general_purpose::STANDARD.decode(el)?;
             let ccel = CcEventLog::try_from(ccel_data)
                 .map_err(|e| anyhow!("Parse CC Eventlog failed: {:?}", e))?;
-            ccel_option = Some(ccel.clone());
-
             log::debug!("Get CC Eventlog. \n{}\n", &ccel.cc_events);
-
-            let rtmr_from_quote = Rtmr {
-                rtmr0: quote.report_body.rtmr_0,
-                rtmr1: quote.report_body.rtmr_1,
-                rtmr2: quote.report_body.rtmr_2,
-                rtmr3: quote.report_body.rtmr_3,
-            };
-
-            ccel.integrity_check(rtmr_from_quote)?;
+            Some(ccel)
+        }
+        None if verifier.require_eventlog => {
+            bail!("There is no CC EventLog in the evidence, and one is required")
         }
         None => {
-            warn!("There is no CC EventLog in Evidence!!!");
+            warnings::raise("There is no CC EventLog in the evidence");
+            None
+        }
+    };
+
+    let partitioning = match (&evidence.hcl_report, &evidence.vtpm_quote, &report) {
+        (Some(hcl_report), vtpm_quote, Report::Quote(_, quote)) => Some(
+            partitioning::parse(hcl_report, vtpm_quote.as_ref(), quote)
+                .context("TD partitioning")?,
+        ),
+        (Some(_), _, Report::TdReport(_)) => {
+            bail!("An HCL report is only accepted with a TD quote")
+        }
+        (None, Some(_), _) => bail!("A vTPM quote is only accepted with an HCL report"),
+        (None, None, _) => None,
+    };
+
+    let mut enclave_claims = None;
+    let mut tcb = Tcb::default();
+    let mut trust_anchor = None;
+    for stage in verifier.pipeline.checks() {
+        pipeline::enter(*stage);
+        match stage {
+            Stage::CollateralVerify => {
+                let (quote_bin, quote) = match &report {
+                    Report::Quote(quote_bin, quote) => (quote_bin, quote),
+                    // The MAC of a TD report only verifies on its platform.
+                    Report::TdReport(_) => {
+                        warnings::raise("The TD report is not signed, it is taken on trust");
+                        continue;
+                    }
+                };
+                // Verify TD quote ECDSA signature.
+                let step = profile::step("quote_verification");
+                tcb = ecdsa_quote_verification(quote_bin.as_slice(), &verifier.collateral).await?;
+                drop(step);
+                let step = profile::step("pck_trust_anchor");
+                trust_anchor = Some(collateral::pck::trust_anchor(
+                    quote_bin,
+                    &verifier.trust_anchors,
+                )?);
+                drop(step);
+
+                if let Some(sgx_quote) = &evidence.sgx_quote {
+                    let _step = profile::step("enclave_verification");
+                    enclave_claims = Some(verify_enclave(quote, sgx_quote, verifier).await?);
+                }
+            }
+            Stage::EventlogReplay => {
+                // Verify Integrity of CC Eventlog
+                if let Some(ccel) = &ccel {
+                    let _step = profile::step("rtmr_replay");
+                    let rtmr_from_quote = Rtmr::from(report.body());
+                    if diagnostics::enabled() {
+                        diagnostics::record(
+                            "tdx.rtmr",
+                            serde_json::json!({
+                                "quote": rtmr_from_quote.to_hex(),
+                                "eventlog": ccel.rebuild_rtmr()?.to_hex(),
+                            }),
+                        );
+                    }
+                    ccel.integrity_check(rtmr_from_quote)?;
+                }
+            }
+            Stage::Freshness => {
+                let binds_nonce = |report_data: &[u8]| {
+                    verifier
+                        .report_data
+                        .matches(nonce, attestation, report_data)
+                };
+                match &partitioning {
+                    // The report data binds the runtime data of the
+                    // paravisor, whose user data binds the nonce.
+                    Some(partitioning) => partitioning.check_nonce(binds_nonce)?,
+                    // Compare report data
+                    None => {
+                        if !binds_nonce(&report.body().report_data) {
+                            return Err(anyhow!(
+                                "HASH(nonce||pubkey) is different from that in TDX Quote"
+                            ));
+                        }
+                    }
+                }
+            }
+            stage => bail!("The TDX verifier has no `{stage}` stage"),
         }
     }
 
+    pipeline::enter(Stage::ClaimsNormalize);
+    let consistency = ccel
+        .as_ref()
+        .map(|ccel| consistency::check(Rtmr::from(report.body()), ccel));
+
+    let tee_tcb_svn = report.body().tcb_svn;
     // Return Evidence parsed claim
-    generate_parsed_claim(quote, ccel_option)
+    let step = profile::step("generate_claims");
+    let (mut claims, quote_bin) = match report {
+        Report::Quote(quote_bin, quote) => {
+            let mut claims = generate_parsed_claim(quote, ccel, verifier.eventlog_strings)?;
+            claims["evidence_type"] = "quote".into();
+            claims["reduced_assurance"] = false.into();
+            (claims, Some(quote_bin))
+        }
+        Report::TdReport(td_report) => {
+            let mut claims =
+                generate_td_report_claims(&td_report, ccel, verifier.eventlog_strings)?;
+            claims["evidence_type"] = "tdreport".into();
+            claims["reduced_assurance"] = true.into();
+            claims["td_report"] = td_report.claims();
+            (claims, None)
+        }
+    };
+    drop(step);
+    if let Some(known) = &verifier.kernel_flags {
+        if let Some(ccel) = claims
+            .get_mut("ccel")
+            .and_then(serde_json::Value::as_object_mut)
+        {
+            if let Some(serde_json::Value::Object(parameters)) = ccel.get_mut("kernel_parameters") {
+                let kernel_flags = cmdline::flags(parameters, known);
+                ccel.insert("kernel_flags".to_string(), kernel_flags.into());
+            }
+        }
+    }
+    if verifier.canonical_kernel_cmdline {
+        if let Some(ccel) = claims
+            .get_mut("ccel")
+            .and_then(serde_json::Value::as_object_mut)
+        {
+            if let Some(serde_json::Value::Object(parameters)) = ccel.get("kernel_parameters") {
+                let kernel_cmdline = cmdline::canonical(parameters);
+                ccel.insert("kernel_cmdline".to_string(), kernel_cmdline.into());
+            }
+        }
+    }
+    tcb.add_claims(&mut claims)?;
+    if let Some(trust_anchor) = trust_anchor {
+        claims[TRUST_ANCHOR_CLAIM] = trust_anchor.into();
+    }
+    if let Some(quote_bin) = &quote_bin {
+        if let Ok(key) = collateral::pck::collateral_key(collateral::QuoteTee::Tdx, quote_bin) {
+            claims[platform::FMSPC_CLAIM] = key.fmspc.into();
+        }
+        if detail::forensic() {
+            match collateral::pck::forensic_claims(quote_bin) {
+                Ok(mut forensic) => {
+                    forensic["tcb_components"]["tee_tcb_svn"] = serde_json::json!(tee_tcb_svn);
+                    claims[detail::FORENSIC_CLAIM] = forensic;
+                }
+                Err(e) => warnings::raise(format!("No forensic detail of the TD quote: {e:#}")),
+            }
+        }
+    }
+    verifier.report_data.add_claim(&mut claims);
+    if let Some(partitioning) = &partitioning {
+        // The quote was produced at the time it binds, if it binds one.
+        let produced = verifier
+            .pipeline
+            .checks()
+            .contains(&Stage::Freshness)
+            .then(|| timing::evidence_time(nonce))
+            .flatten()
+            .unwrap_or_else(chrono::Utc::now);
+        partitioning.add_claims(&mut claims, produced);
+    }
+    if let (Some(enclave_claims), Some(claims)) = (enclave_claims, claims.as_object_mut()) {
+        claims.insert("enclave".to_string(), enclave_claims.into_inner());
+    }
+    if let (Some(consistency), Some(claims)) = (consistency, claims.as_object_mut()) {
+        claims.insert(
+            "consistency".to_string(),
+            serde_json::to_value(consistency)?,
+        );
+    }
+
+    Ok(claims)
+}
+
+/// Verify the TD quote of an Azure TDX confidential VM, whose paravisor
+/// wraps the quoted TD report in `hcl_report`, see [`crate::verifier::hcl`].
+/// Return the `header` and `body` claims of the quote, its TCB, and the
+/// runtime data of the HCL report, which certifies the vTPM attestation key.
+#[cfg(feature = "az-snp-vtpm-verifier")]
+pub(crate) async fn verify_cvm_quote(
+    quote: &str,
+    hcl_report: &str,
+    versions: VersionRange,
+    collateral: &collateral::Provider,
+    strict_quote_parsing: bool,
+) -> Result<(serde_json::Value, Tcb, serde_json::Value)> {
+    let quote_bin = base64::engine::general_purpose::STANDARD.decode(quote)?;
+    let quote = parse_tdx_quote(&quote_bin)?;
+    versions.check("TD quote", u16::from_le_bytes(quote.header.version).into())?;
+    check_anomalies(&quote_bin, &quote, strict_quote_parsing)?;
+    let partitioning = partitioning::parse(hcl_report, None, &quote)?;
+    let tcb = ecdsa_quote_verification(&quote_bin, collateral).await?;
+
+    let mut claims = generate_parsed_claim(quote, None, EventlogStrings::default())?;
+    Ok((
+        claims["quote"].take(),
+        tcb,
+        partitioning.into_runtime_data(),
+    ))
+}
+
+/// Report data that binds an SGX enclave to the TD it runs in, and so to
+/// the nonce and TEE public key of the TD quote.
+#[cfg(feature = "sgx-verifier")]
+fn enclave_report_data(td_quote: &Quote) -> Vec<u8> {
+    let mut hasher = Sha384::new();
+    hasher.update(td_quote.report_body.mr_td);
+    hasher.update(td_quote.report_body.report_data);
+    let mut report_data = hasher.finalize().to_vec();
+    report_data.extend([0; 16]);
+    report_data
+}
+
+#[cfg(feature = "sgx-verifier")]
+fn check_enclave_binding(td_quote: &Quote, report_data: &[u8]) -> Result<()> {
+    if enclave_report_data(td_quote) != report_data {
+        bail!("SGX enclave is not bound to the TD: HASH(MRTD||report_data) is different from that in SGX Quote");
+    }
+    Ok(())
+}
+
+async fn verify_enclave(
+    td_quote: &Quote,
+    sgx_quote: &str,
+    verifier: &Tdx,
+) -> Result<TeeEvidenceParsedClaim> {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "sgx-verifier")] {
+            let quote_bin = base64::engine::general_purpose::STANDARD.decode(sgx_quote)?;
+            let (quote, tcb, trust_anchor) = sgx::verify_quote(
+                &quote_bin,
+                verifier.enclave_versions,
+                &verifier.collateral,
+                verifier.strict_quote_parsing,
+                &verifier.trust_anchors,
+            )
+                .await
+                .context("SGX enclave in the TD")?;
+            check_enclave_binding(td_quote, &quote.report_body.report_data.d)?;
+
+            let mut claims = sgx::generate_parsed_claims(quote)?;
+            tcb.add_claims(&mut claims)?;
+            claims[TRUST_ANCHOR_CLAIM] = trust_anchor.into();
+            Ok(claims)
+        } else {
+            let _ = (td_quote, sgx_quote, verifier);
+            bail!("feature `sgx-verifier` is not enabled, cannot verify the SGX enclave in the TD");
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::verifier::conformance::{self, Conformance, DebugClaim, KnownClaims};
+    use serde_json::json;
     use std::fs;
 
     #[test]
@@ -117,7 +479,7 @@ mod tests {
         let quote_bin = fs::read("../test_data/tdx_quote_4.dat").unwrap();
         let quote = parse_tdx_quote(&quote_bin).unwrap();
 
-        let parsed_claim = generate_parsed_claim(quote, Some(ccel));
+        let parsed_claim = generate_parsed_claim(quote, Some(ccel), EventlogStrings::default());
         assert!(parsed_claim.is_ok());
 
         let _ = fs::write(
@@ -125,4 +487,151 @@ mod tests {
             format!("{:?}", parsed_claim.unwrap()),
         );
     }
+
+    #[cfg(feature = "sgx-verifier")]
+    #[test]
+    fn test_enclave_binding() {
+        let td_quote = parse_tdx_quote(&fs::read("../test_data/tdx_quote_4.dat").unwrap()).unwrap();
+        let mut sgx_quote_bin = fs::read("../test_data/occlum_quote.dat").unwrap();
+
+        let sgx_quote = sgx::parse_sgx_quote(&sgx_quote_bin).unwrap();
+        assert!(check_enclave_binding(&td_quote, &sgx_quote.report_body.report_data.d).is_err());
+
+        // The report data is at offset 368 of an SGX quote.
+        sgx_quote_bin[368..432].copy_from_slice(&enclave_report_data(&td_quote));
+        let sgx_quote = sgx::parse_sgx_quote(&sgx_quote_bin).unwrap();
+        assert!(check_enclave_binding(&td_quote, &sgx_quote.report_body.report_data.d).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_version() {
+        let quote_bin = fs::read("../test_data/tdx_quote_4.dat").unwrap();
+        let attestation = Attestation {
+            tee_pubkey: conformance::dummy_tee_pubkey(),
+            tee_evidence: json!({
+                "quote": base64::engine::general_purpose::STANDARD.encode(quote_bin)
+            })
+            .to_string(),
+        };
+        let verifier = Tdx {
+            versions: VersionRange {
+                min: Some(5),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let err = verifier
+            .evaluate("nonce".to_string(), &attestation)
+            .await
+            .unwrap_err();
+        let unsupported = err.downcast_ref::<UnsupportedVersion>().unwrap();
+        assert_eq!(unsupported.version, 4);
+    }
+
+    #[tokio::test]
+    async fn test_require_eventlog() {
+        let quote_bin = fs::read("../test_data/tdx_quote_4.dat").unwrap();
+        let attestation = Attestation {
+            tee_pubkey: conformance::dummy_tee_pubkey(),
+            tee_evidence: json!({
+                "quote": base64::engine::general_purpose::STANDARD.encode(quote_bin)
+            })
+            .to_string(),
+        };
+        let verifier = Tdx {
+            require_eventlog: true,
+            ..Default::default()
+        };
+
+        let err = verifier
+            .evaluate("nonce".to_string(), &attestation)
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("one is required"));
+    }
+
+    #[tokio::test]
+    async fn test_td_report() {
+        let nonce = "nonce";
+        let tee_pubkey = conformance::dummy_tee_pubkey();
+        let mut report_data = [0; 64];
+        report_data[..48].copy_from_slice(&Sha384::digest(
+            [
+                nonce.as_bytes(),
+                tee_pubkey.k_mod.as_bytes(),
+                tee_pubkey.k_exp.as_bytes(),
+            ]
+            .concat(),
+        ));
+        let report = td_report::tests::td_report([0x70; 48], report_data);
+        let attestation = Attestation {
+            tee_pubkey,
+            tee_evidence: json!({
+                "td_report": base64::engine::general_purpose::STANDARD.encode(report)
+            })
+            .to_string(),
+        };
+
+        let err = Tdx::default()
+            .evaluate(nonce.to_string(), &attestation)
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("`tdx_td_reports`"));
+
+        let verifier = Tdx {
+            td_reports: true,
+            ..Default::default()
+        };
+        let claims = verifier
+            .evaluate(nonce.to_string(), &attestation)
+            .await
+            .unwrap();
+        assert_eq!(claims["evidence_type"], "tdreport");
+        assert_eq!(claims["reduced_assurance"], true);
+        assert_eq!(claims["quote"]["body"]["mr_td"], "70".repeat(48));
+        assert!(claims["quote"].get("header").is_none());
+        assert_eq!(claims["td_report"]["report_type"], 0x81);
+
+        let err = verifier
+            .evaluate("another nonce".to_string(), &attestation)
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("HASH(nonce||pubkey)"));
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        let ccel_bin = fs::read("../test_data/CCEL_data").unwrap();
+        let ccel = CcEventLog::try_from(ccel_bin).unwrap();
+        let quote_bin = fs::read("../test_data/tdx_quote_4.dat").unwrap();
+        let quote = parse_tdx_quote(&quote_bin).unwrap();
+        let claims = generate_parsed_claim(quote, Some(ccel), EventlogStrings::default()).unwrap();
+
+        let b64 = |data: &[u8]| base64::engine::general_purpose::STANDARD.encode(data);
+        conformance::run(Conformance {
+            tee: Tee::Tdx,
+            verifier: Tdx::default(),
+            malformed: vec![
+                json!({ "quote": b64(&quote_bin[..100]) }).to_string(),
+                json!({ "quote": b64(&quote_bin), "cc_eventlog": "not base64!" }).to_string(),
+                json!({ "quote": b64(&quote_bin), "cc_eventlog": b64(&[0; 16]) }).to_string(),
+            ],
+            evidence: None,
+            // The TD of the test quote has the DEBUG attribute.
+            claims: Some(KnownClaims {
+                claims,
+                debug: true,
+            }),
+            // Bit 0 of TD attributes is DEBUG.
+            debug_claim: Some(DebugClaim {
+                name: "tdx.quote.body.td_attributes",
+                is_debug: |value| {
+                    let attributes = hex::decode(value.as_str()?).ok()?;
+                    Some(attributes.first()? & 1 == 1)
+                },
+            }),
+        })
+        .await;
+    }
 }
@@ -0,0 +1,204 @@
+//! Evidence of TD partitioning guests.
+//!
+//! With TD partitioning, the TD runs a paravisor as L1 VMM and the guest OS
+//! as an L2 VM. The TD quote measures the L1: MRTD and the RTMRs are those
+//! of the paravisor, which the guest cannot change. The paravisor wraps the
+//! TD report in an HCL report, see [`crate::verifier::hcl`], whose runtime
+//! data holds the keys of the vTPM it provides to the L2 and the user data
+//! binding the nonce. The L2 is measured in the PCRs of the vTPM, which the
+//! evidence can carry in a quote signed by the vTPM attestation key (AK) of
+//! the runtime data.
+//!
+//! The evidence has the `hcl_report` and optional `vtpm_quote` next to the
+//! TD quote, and its claims tell the L1 measurements apart from the L2 ones:
+//!
+//! ```json
+//! "partitioning": {
+//!     "l1": { "mr_td": "...", "rtmr_0": "...", "rtmr_1": "...", "rtmr_2": "...", "rtmr_3": "..." },
+//!     "l2": { "pcr_4": "...", "pcr_7": "...", "pcr_11": "...", "clock": 3600000 },
+//!     "runtime_data": { "keys": [...], "vm-configuration": {...}, "user-data": "..." }
+//! }
+//! ```
+//!
+//! `clock` is the TPM clock of the vTPM quote, the milliseconds the vTPM,
+//! which the paravisor starts with the guest, has been running. The guest
+//! so booted `clock` before it was attested, which is the `boot_time` claim.
+
+use super::quote::Quote;
+use crate::verifier::hcl::{self, ReportType};
+use crate::verifier::timing;
+use crate::verifier::vtpm::{self, VerifiedVtpmQuote, VtpmQuote};
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{json, Map, Value};
+
+/// Offset of the report data in a TD report, in its REPORTMACSTRUCT.
+const TD_REPORT_DATA_OFFSET: usize = 0x80;
+
+/// Offset of MRTD in a TD report, in its TDINFO_STRUCT.
+const TD_REPORT_MR_TD_OFFSET: usize = 0x210;
+
+/// The HCL envelope and vTPM quote of a TD partitioning guest.
+pub(crate) struct Partitioning {
+    /// Measurements of the paravisor, from the TD quote.
+    l1: Map<String, Value>,
+    runtime_data: Value,
+    vtpm: Option<VerifiedVtpmQuote>,
+}
+
+/// Parse the HCL report and vTPM quote of a TD partitioning guest, and
+/// check that they are bound to the TD quote.
+pub(crate) fn parse(
+    hcl_report: &str,
+    vtpm_quote: Option<&VtpmQuote>,
+    quote: &Quote,
+) -> Result<Partitioning> {
+    let hcl_report = base64::engine::general_purpose::STANDARD
+        .decode(hcl_report)
+        .context("Malformed HCL report")?;
+    let hcl_report = hcl::parse(&hcl_report, ReportType::Tdx)?;
+
+    // The TD report of the HCL report is the one quoted.
+    let field = |offset: usize, size: usize| hcl_report.hw_report.get(offset..offset + size);
+    if field(TD_REPORT_DATA_OFFSET, 64) != Some(&quote.report_body.report_data[..])
+        || field(TD_REPORT_MR_TD_OFFSET, 48) != Some(&quote.report_body.mr_td[..])
+    {
+        bail!("The TD report of the HCL report is not the one of the TD quote");
+    }
+    hcl_report.check_report_data(&quote.report_body.report_data)?;
+    let runtime_data = hcl::runtime_data(&hcl_report.runtime_data)?;
+
+    let vtpm = vtpm_quote
+        .map(|vtpm_quote| vtpm::verify(vtpm_quote, &runtime_data))
+        .transpose()
+        .context("vTPM quote")?;
+
+    let body = &quote.report_body;
+    let mut l1 = Map::new();
+    for (name, register) in [
+        ("mr_td", &body.mr_td),
+        ("rtmr_0", &body.rtmr_0),
+        ("rtmr_1", &body.rtmr_1),
+        ("rtmr_2", &body.rtmr_2),
+        ("rtmr_3", &body.rtmr_3),
+    ] {
+        l1.insert(name.to_string(), hex::encode(register).into());
+    }
+
+    Ok(Partitioning {
+        l1,
+        runtime_data,
+        vtpm,
+    })
+}
+
+impl Partitioning {
+    /// Check that the user data of the runtime data, and the nonce of the
+    /// vTPM quote if any, bind the nonce, as `binds_nonce` tells.
+    pub fn check_nonce(&self, binds_nonce: impl Fn(&[u8]) -> bool) -> Result<()> {
+        hcl::check_user_data(&self.runtime_data, &binds_nonce)?;
+        if let Some(vtpm) = &self.vtpm {
+            if !binds_nonce(&vtpm.nonce) {
+                bail!("The nonce of the vTPM quote is different from HASH(nonce||pubkey)");
+            }
+        }
+        Ok(())
+    }
+
+    /// The runtime data of the HCL report.
+    #[cfg(feature = "az-snp-vtpm-verifier")]
+    pub fn into_runtime_data(self) -> Value {
+        self.runtime_data
+    }
+
+    /// Add the `partitioning` claims, and the `boot_time` claim if there is
+    /// a vTPM quote, `produced` then, to the TDX claims.
+    pub fn add_claims(&self, claims: &mut Value, produced: DateTime<Utc>) {
+        claims["partitioning"] = json!({
+            "l1": self.l1,
+            "runtime_data": self.runtime_data,
+        });
+        if let Some(vtpm) = &self.vtpm {
+            claims["partitioning"]["l2"] = vtpm.claims().into();
+            let uptime = Duration::milliseconds(i64::try_from(vtpm.clock).unwrap_or(i64::MAX));
+            timing::add_boot_time(claims, uptime, produced);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::quote::parse_tdx_quote;
+    use super::*;
+    use crate::verifier::vtpm::tests::{runtime_data, vtpm_quote};
+    use sha2::{Digest, Sha256};
+
+    /// HCL report of a TD report with `report_data` and `mr_td`.
+    fn hcl_report(runtime_data: &[u8], report_data: &[u8], mr_td: &[u8]) -> Vec<u8> {
+        let mut report = b"HCLA".to_vec();
+        report.resize(
+            hcl::HEADER_SIZE + hcl::HW_REPORT_SIZE + hcl::REQUEST_DATA_SIZE,
+            0,
+        );
+        let td_report = hcl::HEADER_SIZE;
+        report[td_report + TD_REPORT_DATA_OFFSET..][..64].copy_from_slice(report_data);
+        report[td_report + TD_REPORT_MR_TD_OFFSET..][..48].copy_from_slice(mr_td);
+        let request_data = hcl::HEADER_SIZE + hcl::HW_REPORT_SIZE;
+        for (offset, value) in [(8, 4u32), (12, 1), (16, runtime_data.len() as u32)] {
+            report[request_data + offset..][..4].copy_from_slice(&value.to_le_bytes());
+        }
+        report.extend(runtime_data);
+        report
+    }
+
+    #[test]
+    fn test_parse() {
+        let (key, runtime_data) = runtime_data(&[7; 64]);
+
+        let mut quote =
+            parse_tdx_quote(&std::fs::read("../test_data/tdx_quote_4.dat").unwrap()).unwrap();
+        quote.report_body.report_data = [0; 64];
+        quote.report_body.report_data[..32].copy_from_slice(&Sha256::digest(&runtime_data));
+        let b64 = |data: &[u8]| base64::engine::general_purpose::STANDARD.encode(data);
+        let hcl = b64(&hcl_report(
+            runtime_data.as_bytes(),
+            &quote.report_body.report_data,
+            &quote.report_body.mr_td,
+        ));
+
+        let mut vtpm_quote = vtpm_quote(&key, &[7; 64]);
+
+        let partitioning = parse(&hcl, Some(&vtpm_quote), &quote).unwrap();
+        assert!(partitioning.check_nonce(|nonce| nonce == [7; 64]).is_ok());
+        assert!(partitioning.check_nonce(|nonce| nonce == [8; 64]).is_err());
+        let mut claims = json!({});
+        partitioning.add_claims(&mut claims, Utc::now());
+        assert_eq!(
+            claims["partitioning"]["l1"]["mr_td"],
+            hex::encode(quote.report_body.mr_td)
+        );
+        assert_eq!(claims["partitioning"]["l2"]["pcr_9"], hex::encode([9; 32]));
+        assert_eq!(claims["partitioning"]["l2"]["clock"], 3_600_000);
+        let boot_time =
+            chrono::DateTime::parse_from_rfc3339(claims["boot_time"].as_str().unwrap()).unwrap();
+        assert!(Utc::now() - Duration::hours(1) - boot_time < Duration::minutes(1));
+        assert_eq!(
+            claims["partitioning"]["runtime_data"]["vm-configuration"]["secure-boot"],
+            true
+        );
+
+        // A PCR value that is not the quoted one.
+        vtpm_quote.pcrs.insert(9, hex::encode([1; 32]));
+        assert!(parse(&hcl, Some(&vtpm_quote), &quote).is_err());
+
+        // A signature by another key.
+        vtpm_quote.pcrs.insert(9, hex::encode([9; 32]));
+        vtpm_quote.signature = b64(&[0; 128]);
+        assert!(parse(&hcl, Some(&vtpm_quote), &quote).is_err());
+
+        // An HCL report of another TD.
+        quote.report_body.mr_td = [0; 48];
+        assert!(parse(&hcl, None, &quote).is_err());
+    }
+}
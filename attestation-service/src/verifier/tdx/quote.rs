@@ -1,8 +1,12 @@
+use crate::verifier::anomalies::Anomalies;
+use crate::verifier::collateral::{Provider, QuoteTee, QveCollateral};
+use crate::verifier::tcb::{self, Tcb};
+use crate::verifier::{diagnostics, expiry, warnings};
 use anyhow::{anyhow, bail, Result};
 use core::fmt;
 use qvl::{
     sgx_ql_qv_result_t, sgx_ql_qv_supplemental_t, tee_get_supplemental_data_version_and_size,
-    tee_qv_get_collateral, tee_supp_data_descriptor_t, tee_verify_quote,
+    tee_supp_data_descriptor_t, tee_verify_quote,
 };
 use scroll::Pread;
 use std::convert::TryInto;
@@ -11,7 +15,21 @@ use std::time::{Duration, SystemTime};
 
 use sgx_dcap_quoteverify_rs as qvl;
 
-pub const QUOTE_PAYLOAD_SIZE: usize = 632;
+/// Size of the payload of a version 4 quote: its header and TD report
+/// body.
+pub const QUOTE_PAYLOAD_SIZE: usize = QUOTE_HEADER_SIZE + REPORT_BODY_SIZE;
+
+const QUOTE_HEADER_SIZE: usize = 48;
+const REPORT_BODY_SIZE: usize = 584;
+/// Size of the fields that a TD report 1.5 body adds to a 1.0 one.
+const REPORT_BODY_1_5_SIZE: usize = 64;
+/// Size of the type and size of the body of a version 5 quote.
+const BODY_DESCRIPTOR_SIZE: usize = 6;
+
+/// Type of the body of a version 5 quote of a TDX 1.0 module.
+const BODY_TYPE_TD_REPORT_1_0: u16 = 2;
+/// Type of the body of a version 5 quote of a TDX 1.5 module.
+const BODY_TYPE_TD_REPORT_1_5: u16 = 3;
 
 /// The quote header. It is designed to compatible with earlier versions of the quote.
 #[repr(C)]
@@ -126,32 +144,182 @@ impl fmt::Display for ReportBody {
     }
 }
 
-/// TD Quote Payload(Version 4)
-/// First 632 bytes of TD Quote
-/// Excluding the signature data attached at the end of the Quote.
-///
-/// Refer to: https://github.com/intel/SGXDataCenterAttestationPrimitives/blob/master/QuoteGeneration/quote_wrapper/common/inc/sgx_quote_4.h#L141
+/// Fields that a TD report 1.5 body adds after those of a 1.0 body.
 #[repr(C)]
 #[derive(Debug, Pread)]
+pub struct ReportBody1_5 {
+    ///< 584: TEE_TCB_SVN of the TDX module that the TD was migrated from
+    pub tee_tcb_svn_2: [u8; 16],
+    ///< 600: Measurement of the service TD bound to the TD, 0'ed if none
+    pub mr_servicetd: [u8; 48],
+}
+
+impl fmt::Display for ReportBody1_5 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "TD Report 1.5:
+            \n\tTEE TCB SVN 2:\n\t{:X?}
+            \n\tMRSERVICETD:\n\t{:X?}",
+            hex::encode(self.tee_tcb_svn_2),
+            hex::encode(self.mr_servicetd)
+        )
+    }
+}
+
+/// TD Quote Payload (Version 4 or 5)
+/// The header and TD report body of a TD Quote,
+/// excluding the signature data attached at the end of the Quote.
+///
+/// A version 4 quote is its header, then a TD report 1.0 body. A version 5
+/// quote is its header, then the type and size of its body, then the body:
+/// a TD report 1.0 body, or a 1.5 body with `report_body_1_5` as well.
+///
+/// Refer to: https://github.com/intel/SGXDataCenterAttestationPrimitives/blob/master/QuoteGeneration/quote_wrapper/common/inc/sgx_quote_4.h#L141
+/// and https://github.com/intel/SGXDataCenterAttestationPrimitives/blob/master/QuoteGeneration/quote_wrapper/common/inc/sgx_quote_5.h
+#[derive(Debug)]
 pub struct Quote {
     pub header: QuoteHeader,
     pub report_body: ReportBody,
+    /// The fields of a TD report 1.5 body, if the quote has one.
+    pub report_body_1_5: Option<ReportBody1_5>,
+}
+
+impl Quote {
+    pub fn version(&self) -> u16 {
+        u16::from_le_bytes(self.header.version)
+    }
+
+    /// Size of the payload, which the signature data follows.
+    pub fn payload_size(&self) -> usize {
+        match self.version() {
+            5 => {
+                let body_1_5 = self
+                    .report_body_1_5
+                    .as_ref()
+                    .map_or(0, |_| REPORT_BODY_1_5_SIZE);
+                QUOTE_HEADER_SIZE + BODY_DESCRIPTOR_SIZE + REPORT_BODY_SIZE + body_1_5
+            }
+            _ => QUOTE_PAYLOAD_SIZE,
+        }
+    }
 }
 
 impl fmt::Display for Quote {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "TD Quote:\n{}\n{}\n", self.header, self.report_body)
+        write!(f, "TD Quote:\n{}\n{}\n", self.header, self.report_body)?;
+        if let Some(body_1_5) = &self.report_body_1_5 {
+            writeln!(f, "{body_1_5}")?;
+        }
+        Ok(())
     }
 }
 
+/// The `size` bytes of `quote_bin` at `offset`.
+fn field(quote_bin: &[u8], offset: usize, size: usize) -> Result<&[u8]> {
+    quote_bin.get(offset..offset + size).ok_or_else(|| {
+        anyhow!(
+            "TD quote is too short: {} bytes, expected at least {}",
+            quote_bin.len(),
+            offset + size
+        )
+    })
+}
+
+/// Parse the payload of a version 4 or 5 TD quote. Quotes of other
+/// versions are parsed as version 4 ones, to be rejected by the version
+/// check of the verifier.
 pub fn parse_tdx_quote(quote_bin: &[u8]) -> Result<Quote> {
-    let quote_body = &quote_bin[..QUOTE_PAYLOAD_SIZE];
-    quote_body
-        .pread::<Quote>(0)
-        .map_err(|e| anyhow!("Parse TD quote failed: {:?}", e))
+    let parse_error = |e| anyhow!("Parse TD quote failed: {:?}", e);
+    let header = field(quote_bin, 0, QUOTE_HEADER_SIZE)?
+        .pread::<QuoteHeader>(0)
+        .map_err(parse_error)?;
+    let (body_offset, body_1_5) = match u16::from_le_bytes(header.version) {
+        5 => {
+            let descriptor = field(quote_bin, QUOTE_HEADER_SIZE, BODY_DESCRIPTOR_SIZE)?;
+            let body_type = u16::from_le_bytes([descriptor[0], descriptor[1]]);
+            let body_size = u32::from_le_bytes(descriptor[2..].try_into()?) as usize;
+            let body_1_5 = match body_type {
+                BODY_TYPE_TD_REPORT_1_0 => false,
+                BODY_TYPE_TD_REPORT_1_5 => true,
+                _ => bail!("TD quote body of type {body_type} is not a TD report"),
+            };
+            let expected = REPORT_BODY_SIZE + if body_1_5 { REPORT_BODY_1_5_SIZE } else { 0 };
+            if body_size != expected {
+                bail!(
+                    "TD quote body of type {body_type} is {body_size} bytes, expected {expected}"
+                );
+            }
+            (QUOTE_HEADER_SIZE + BODY_DESCRIPTOR_SIZE, body_1_5)
+        }
+        _ => (QUOTE_HEADER_SIZE, false),
+    };
+    let report_body = field(quote_bin, body_offset, REPORT_BODY_SIZE)?
+        .pread::<ReportBody>(0)
+        .map_err(parse_error)?;
+    let report_body_1_5 = match body_1_5 {
+        true => Some(
+            field(
+                quote_bin,
+                body_offset + REPORT_BODY_SIZE,
+                REPORT_BODY_1_5_SIZE,
+            )?
+            .pread::<ReportBody1_5>(0)
+            .map_err(parse_error)?,
+        ),
+        false => None,
+    };
+    Ok(Quote {
+        header,
+        report_body,
+        report_body_1_5,
+    })
+}
+
+/// Look for structural anomalies in the parsed `quote`, of `quote_bin`,
+/// see [`crate::verifier::anomalies`].
+pub fn check_anomalies(quote_bin: &[u8], quote: &Quote, strict: bool) -> Result<()> {
+    let header = &quote.header;
+    let version = quote.version();
+    let mut anomalies = Anomalies::new("TD quote");
+    anomalies.integer("version", version.into(), 2, &[4, 5]);
+    anomalies.integer(
+        "att_key_type",
+        u16::from_le_bytes(header.att_key_type).into(),
+        2,
+        &[2, 3],
+    );
+    anomalies.integer(
+        "tee_type",
+        u32::from_le_bytes(header.tee_type).into(),
+        4,
+        &[0x81],
+    );
+    anomalies.reserved("reserved", &header.reserved);
+    anomalies.reserved("seam_attributes", &quote.report_body.seam_attributes);
+    anomalies.vendor_id(&header.vendor_id);
+    // The signature data follows the payload of a version 4 or 5 quote. A
+    // quote without it fails the signature verification.
+    let payload_size = quote.payload_size();
+    if let (4 | 5, Some(len)) = (version, quote_bin.get(payload_size..payload_size + 4)) {
+        anomalies.length(
+            "signature_data_len",
+            u32::from_le_bytes(len.try_into()?) as usize,
+            quote_bin.len() - payload_size - 4,
+        );
+    }
+    anomalies.check(strict)
 }
 
-pub async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
+pub async fn ecdsa_quote_verification(quote: &[u8], collateral: &Provider) -> Result<Tcb> {
+    // The collateral fetched by the AS, if it fetches it, else the quote
+    // provider library fetches it.
+    let collateral = collateral
+        .collateral(QuoteTee::Tdx, quote)
+        .await?
+        .map(|collateral| collateral.to_qve())
+        .transpose()?;
+
     let mut supp_data: sgx_ql_qv_supplemental_t = Default::default();
     let mut supp_data_desc = tee_supp_data_descriptor_t {
         major_version: 0,
@@ -180,19 +348,7 @@ pub async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
         ),
     }
 
-    // get collateral
-    let _collateral = match tee_qv_get_collateral(quote) {
-        Ok(c) => {
-            debug!("tee_qv_get_collateral successfully returned.");
-            Some(c)
-        }
-        Err(e) => {
-            warn!("tee_qv_get_collateral failed: {:#04x}", e as u32);
-            None
-        }
-    };
-
-    let p_collateral: Option<&[u8]> = None;
+    let p_collateral = collateral.as_ref().map(QveCollateral::as_bytes);
 
     // set current time. This is only for sample purposes, in production mode a trusted time should be used.
     //
@@ -212,6 +368,22 @@ pub async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
             .map_err(|e| anyhow!("tee_verify_quote failed: {:#04x}", e as u32))?;
 
     debug!("tee_verify_quote successfully returned.");
+    diagnostics::record_dcap_collateral(
+        "tdx",
+        (supp_data_desc.data_size != 0).then_some(&supp_data),
+        collateral_expiration_status,
+        quote_verification_result,
+    );
+    let mut tcb_date_tag = 0;
+    if supp_data_desc.data_size != 0 {
+        warnings::check_collateral_expiry(
+            "TD quote",
+            supp_data.earliest_expiration_date,
+            current_time,
+        );
+        expiry::record("tdx.collateral", supp_data.earliest_expiration_date);
+        tcb_date_tag = supp_data.tcb_level_date_tag;
+    }
 
     // check verification result
     match quote_verification_result {
@@ -221,7 +393,7 @@ pub async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
             if collateral_expiration_status == 0 {
                 debug!("Verification completed successfully.");
             } else {
-                warn!("Verification completed, but collateral is out of date based on 'expiration_check_date' you provided.");
+                warnings::raise("The TD quote collateral is out of date");
             }
         }
         sgx_ql_qv_result_t::SGX_QL_QV_RESULT_CONFIG_NEEDED
@@ -229,10 +401,10 @@ pub async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
         | sgx_ql_qv_result_t::SGX_QL_QV_RESULT_OUT_OF_DATE_CONFIG_NEEDED
         | sgx_ql_qv_result_t::SGX_QL_QV_RESULT_SW_HARDENING_NEEDED
         | sgx_ql_qv_result_t::SGX_QL_QV_RESULT_CONFIG_AND_SW_HARDENING_NEEDED => {
-            warn!(
-                "Verification completed with Non-terminal result: {:x}",
+            warnings::raise(format!(
+                "The TD quote verification completed with non-terminal result: {:x}",
                 quote_verification_result as u32
-            );
+            ));
         }
         _ => {
             bail!(
@@ -242,7 +414,7 @@ pub async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
         }
     }
 
-    Ok(())
+    Ok(tcb::from_dcap(quote_verification_result, tcb_date_tag))
 }
 
 #[cfg(test)]
@@ -261,11 +433,86 @@ mod tests {
         let _ = fs::write("test_data/parse_tdx_quote_output.txt", parsed_quote);
     }
 
+    #[test]
+    fn test_check_anomalies() {
+        let quote_bin = fs::read("../test_data/tdx_quote_4.dat").unwrap();
+        let quote = parse_tdx_quote(&quote_bin).unwrap();
+        check_anomalies(&quote_bin, &quote, true).unwrap();
+        check_anomalies(&quote_bin[..quote_bin.len() - 1], &quote, true).unwrap_err();
+
+        let mut tampered = quote_bin.clone();
+        tampered[8] = 1;
+        let quote = parse_tdx_quote(&tampered).unwrap();
+        let e = check_anomalies(&tampered, &quote, true).unwrap_err();
+        assert!(e.to_string().contains("reserved is not zero"));
+        check_anomalies(&tampered, &quote, false).unwrap();
+    }
+
+    /// `tdx_quote_4.dat` made a version 5 quote, with a TD report 1.5 body
+    /// if `body_1_5`.
+    fn quote_5(body_1_5: bool) -> Vec<u8> {
+        let quote_4 = fs::read("../test_data/tdx_quote_4.dat").unwrap();
+        let (body_type, body_size) = match body_1_5 {
+            true => (
+                BODY_TYPE_TD_REPORT_1_5,
+                REPORT_BODY_SIZE + REPORT_BODY_1_5_SIZE,
+            ),
+            false => (BODY_TYPE_TD_REPORT_1_0, REPORT_BODY_SIZE),
+        };
+        let mut quote = quote_4[..QUOTE_HEADER_SIZE].to_vec();
+        quote[..2].copy_from_slice(&5u16.to_le_bytes());
+        quote.extend(body_type.to_le_bytes());
+        quote.extend((body_size as u32).to_le_bytes());
+        quote.extend(&quote_4[QUOTE_HEADER_SIZE..QUOTE_PAYLOAD_SIZE]);
+        if body_1_5 {
+            quote.extend([1; 16]);
+            quote.extend([0xab; 48]);
+        }
+        quote.extend(&quote_4[QUOTE_PAYLOAD_SIZE..]);
+        quote
+    }
+
+    #[test]
+    fn test_parse_tdx_quote_5() {
+        let quote_4 = parse_tdx_quote(&fs::read("../test_data/tdx_quote_4.dat").unwrap()).unwrap();
+        assert!(quote_4.report_body_1_5.is_none());
+        assert_eq!(quote_4.payload_size(), QUOTE_PAYLOAD_SIZE);
+
+        let quote_bin = quote_5(false);
+        let quote = parse_tdx_quote(&quote_bin).unwrap();
+        assert_eq!(quote.version(), 5);
+        assert_eq!(quote.report_body.mr_td, quote_4.report_body.mr_td);
+        assert!(quote.report_body_1_5.is_none());
+        check_anomalies(&quote_bin, &quote, true).unwrap();
+
+        let quote_bin = quote_5(true);
+        let quote = parse_tdx_quote(&quote_bin).unwrap();
+        assert_eq!(
+            quote.report_body.report_data,
+            quote_4.report_body.report_data
+        );
+        let body_1_5 = quote.report_body_1_5.as_ref().unwrap();
+        assert_eq!(body_1_5.tee_tcb_svn_2, [1; 16]);
+        assert_eq!(body_1_5.mr_servicetd, [0xab; 48]);
+        assert_eq!(quote.payload_size(), 48 + 6 + 584 + 64);
+        check_anomalies(&quote_bin, &quote, true).unwrap();
+        check_anomalies(&quote_bin[..quote_bin.len() - 1], &quote, true).unwrap_err();
+
+        // A body whose size is not that of its type, or of another type.
+        let mut tampered = quote_bin.clone();
+        tampered[50] = 0;
+        assert!(parse_tdx_quote(&tampered).is_err());
+        let mut tampered = quote_bin.clone();
+        tampered[48] = 1;
+        assert!(parse_tdx_quote(&tampered).is_err());
+        assert!(parse_tdx_quote(&quote_bin[..600]).is_err());
+    }
+
     #[ignore]
     #[tokio::test]
     async fn test_verify_tdx_quote() {
         let quote_bin = fs::read("../test_data/quote.dat").unwrap();
-        let res = ecdsa_quote_verification(quote_bin.as_slice()).await;
+        let res = ecdsa_quote_verification(quote_bin.as_slice(), &Provider::default()).await;
         assert!(res.is_ok(), "error");
     }
 }
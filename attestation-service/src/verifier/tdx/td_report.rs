@@ -0,0 +1,235 @@
+//! TD reports, for CI and nested or emulated TDX environments.
+//!
+//! A TD gets its TDREPORT_STRUCT from the TDX module with TDG.MR.REPORT,
+//! then has the quoting enclave of the host sign it into a TD quote.
+//! Environments without a quoting enclave, such as CI runners and nested
+//! virtualization, only have the TD report. Its MAC is computed with a key
+//! that never leaves the CPU, so that only the TDX module and the quoting
+//! enclave of the same platform can verify it: the AS checks the integrity
+//! of the structure instead, the hashes that bind its parts and the sanity
+//! of its fields, and takes the rest on trust.
+//!
+//! With `tdx_td_reports` in the AS config, the TDX verifier accepts
+//! evidence with a base64 `td_report` in place of the `quote`. Its claims
+//! are those of a quote without header, with `evidence_type` set to
+//! `tdreport` and `reduced_assurance` to true, for policies to tell them
+//! from those of signed quotes, and the fields of its REPORTMACSTRUCT:
+//!
+//! ```json
+//! "td_report": {
+//!     "report_type": 129,
+//!     "report_subtype": 0,
+//!     "report_version": 0,
+//!     "cpu_svn": "...",
+//!     "mac": "..."
+//! }
+//! ```
+//!
+//! Accepting TD reports is an insecure setting, see [`crate::posture`].
+
+use super::quote::{ReportBody, ReportBody1_5};
+use crate::verifier::anomalies::Anomalies;
+use anyhow::{bail, Result};
+use as_types::ClaimsBuilder;
+use serde_json::Value;
+use sha2::{Digest, Sha384};
+
+/// Size of a TDREPORT_STRUCT.
+pub const TD_REPORT_SIZE: usize = 1024;
+
+/// Offset and size of the TEE_TCB_INFO of a TD report.
+const TEE_TCB_INFO_OFFSET: usize = 256;
+const TEE_TCB_INFO_SIZE: usize = 239;
+/// Offset and size of the TDINFO_STRUCT of a TD report.
+const TD_INFO_OFFSET: usize = 512;
+const TD_INFO_SIZE: usize = 512;
+
+/// REPORTTYPE.TYPE of the TD reports of TDX.
+const REPORT_TYPE_TDX: u8 = 0x81;
+/// REPORTTYPE.VERSION of the TD reports of TDX 1.5 modules, whose TDINFO
+/// has SERVTD_HASH.
+const REPORT_VERSION_1_5: u8 = 1;
+
+/// The `N` bytes of `report` at `offset`.
+fn array<const N: usize>(report: &[u8], offset: usize) -> [u8; N] {
+    let mut array = [0; N];
+    array.copy_from_slice(&report[offset..offset + N]);
+    array
+}
+
+/// A TD report, see the module documentation.
+#[derive(Debug)]
+pub struct TdReport {
+    pub report_type: u8,
+    pub report_subtype: u8,
+    pub report_version: u8,
+    pub cpu_svn: [u8; 16],
+    pub mac: [u8; 32],
+    /// The fields of the report, as in the body of a TD quote.
+    pub body: ReportBody,
+    /// The fields of a TDX 1.5 report, if the report is one.
+    pub body_1_5: Option<ReportBody1_5>,
+}
+
+impl TdReport {
+    /// The `td_report` claims of the report.
+    pub fn claims(&self) -> Value {
+        ClaimsBuilder::new()
+            .claim("report_type", self.report_type)
+            .claim("report_subtype", self.report_subtype)
+            .claim("report_version", self.report_version)
+            .hex("cpu_svn", self.cpu_svn)
+            .hex("mac", self.mac)
+            .build()
+            .into_inner()
+    }
+}
+
+/// Parse the TD report `report`, and check its integrity. Its reserved
+/// fields are checked as those of quotes, see [`Anomalies`].
+pub fn parse_td_report(report: &[u8], strict: bool) -> Result<TdReport> {
+    if report.len() != TD_REPORT_SIZE {
+        bail!(
+            "TD report is {} bytes, expected {TD_REPORT_SIZE}",
+            report.len()
+        );
+    }
+    let tee_tcb_info = &report[TEE_TCB_INFO_OFFSET..TEE_TCB_INFO_OFFSET + TEE_TCB_INFO_SIZE];
+    let td_info = &report[TD_INFO_OFFSET..TD_INFO_OFFSET + TD_INFO_SIZE];
+
+    let (report_type, report_subtype, report_version) = (report[0], report[1], report[2]);
+    if report_type != REPORT_TYPE_TDX {
+        bail!("Report of type {report_type:#x} is not a TD report");
+    }
+    if Sha384::digest(tee_tcb_info).as_slice() != &report[32..80] {
+        bail!("TEE_TCB_INFO_HASH of the TD report does not match its TEE_TCB_INFO");
+    }
+    if Sha384::digest(td_info).as_slice() != &report[80..128] {
+        bail!("TEE_INFO_HASH of the TD report does not match its TDINFO");
+    }
+    let mac: [u8; 32] = array(report, 224);
+    if mac.iter().all(|byte| *byte == 0) {
+        bail!("TD report has no MAC");
+    }
+
+    let mut anomalies = Anomalies::new("TD report");
+    anomalies.integer("report_subtype", report_subtype.into(), 1, &[0]);
+    anomalies.integer("report_version", report_version.into(), 1, &[0, 1]);
+    anomalies.reserved("report_type.reserved", &report[3..4]);
+    anomalies.reserved("reserved_1", &report[4..16]);
+    anomalies.reserved("reserved_2", &report[192..224]);
+    anomalies.reserved("tee_tcb_info.reserved", &tee_tcb_info[144..]);
+    anomalies.reserved("reserved", &report[495..512]);
+    anomalies.reserved("td_info.reserved", &td_info[448..]);
+    if report_version != REPORT_VERSION_1_5 {
+        anomalies.reserved("tee_tcb_info.tee_tcb_svn_2", &tee_tcb_info[128..144]);
+        anomalies.reserved("td_info.servtd_hash", &td_info[400..448]);
+    }
+    anomalies.check(strict)?;
+
+    let body = ReportBody {
+        tcb_svn: array(tee_tcb_info, 8),
+        mr_seam: array(tee_tcb_info, 24),
+        mrsigner_seam: array(tee_tcb_info, 72),
+        seam_attributes: array(tee_tcb_info, 120),
+        td_attributes: array(td_info, 0),
+        xfam: array(td_info, 8),
+        mr_td: array(td_info, 16),
+        mr_config_id: array(td_info, 64),
+        mr_owner: array(td_info, 112),
+        mr_owner_config: array(td_info, 160),
+        rtmr_0: array(td_info, 208),
+        rtmr_1: array(td_info, 256),
+        rtmr_2: array(td_info, 304),
+        rtmr_3: array(td_info, 352),
+        report_data: array(report, 128),
+    };
+    let body_1_5 = (report_version == REPORT_VERSION_1_5).then(|| ReportBody1_5 {
+        tee_tcb_svn_2: array(tee_tcb_info, 128),
+        mr_servicetd: array(td_info, 400),
+    });
+    Ok(TdReport {
+        report_type,
+        report_subtype,
+        report_version,
+        cpu_svn: array(report, 16),
+        mac,
+        body,
+        body_1_5,
+    })
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// A TD report with `mr_td` and `report_data`, and the hashes of its
+    /// parts.
+    pub fn td_report(mr_td: [u8; 48], report_data: [u8; 64]) -> Vec<u8> {
+        let mut report = vec![0; TD_REPORT_SIZE];
+        report[0] = REPORT_TYPE_TDX;
+        report[128..192].copy_from_slice(&report_data);
+        report[224..256].fill(0x5a);
+        // TEE_TCB_SVN and MRSEAM.
+        report[264] = 3;
+        report[280..328].fill(0x2f);
+        report[528..576].copy_from_slice(&mr_td);
+        rehash(&mut report);
+        report
+    }
+
+    /// Update the hashes of the parts of `report`.
+    fn rehash(report: &mut [u8]) {
+        let tee_tcb_info_hash =
+            Sha384::digest(&report[TEE_TCB_INFO_OFFSET..TEE_TCB_INFO_OFFSET + TEE_TCB_INFO_SIZE]);
+        report[32..80].copy_from_slice(&tee_tcb_info_hash);
+        let td_info_hash = Sha384::digest(&report[TD_INFO_OFFSET..TD_INFO_OFFSET + TD_INFO_SIZE]);
+        report[80..128].copy_from_slice(&td_info_hash);
+    }
+
+    #[test]
+    fn test_parse_td_report() {
+        let mut report = td_report([0x70; 48], [0x7c; 64]);
+        let parsed = parse_td_report(&report, true).unwrap();
+        assert_eq!(parsed.body.mr_td, [0x70; 48]);
+        assert_eq!(parsed.body.report_data, [0x7c; 64]);
+        assert_eq!(parsed.body.tcb_svn[0], 3);
+        assert_eq!(parsed.body.mr_seam, [0x2f; 48]);
+        assert!(parsed.body_1_5.is_none());
+        assert_eq!(parsed.claims()["report_type"], 0x81);
+
+        // A TDX 1.5 report, with a service TD.
+        report[2] = REPORT_VERSION_1_5;
+        report[912..960].fill(0x11);
+        rehash(&mut report);
+        let parsed = parse_td_report(&report, true).unwrap();
+        assert_eq!(parsed.body_1_5.unwrap().mr_servicetd, [0x11; 48]);
+
+        // MRTD changed after the report was made.
+        let mut tampered = report.clone();
+        tampered[528] ^= 1;
+        let e = parse_td_report(&tampered, true).unwrap_err();
+        assert!(e.to_string().contains("TEE_INFO_HASH"));
+
+        let mut tampered = report.clone();
+        tampered[264] = 4;
+        let e = parse_td_report(&tampered, true).unwrap_err();
+        assert!(e.to_string().contains("TEE_TCB_INFO_HASH"));
+
+        let mut tampered = report.clone();
+        tampered[224..256].fill(0);
+        assert!(parse_td_report(&tampered, true).is_err());
+
+        let mut tampered = report.clone();
+        tampered[0] = 0;
+        assert!(parse_td_report(&tampered, true).is_err());
+
+        assert!(parse_td_report(&report[..512], true).is_err());
+
+        let mut tampered = report;
+        tampered[500] = 1;
+        let e = parse_td_report(&tampered, true).unwrap_err();
+        assert!(e.to_string().contains("reserved is not zero"));
+        parse_td_report(&tampered, false).unwrap();
+    }
+}
@@ -0,0 +1,336 @@
+//! Precomputation of the MRTD of a TD from its firmware image, to register
+//! it as the reference value of the `tdx.quote.body.mr_td` claim when the
+//! image is built.
+//!
+//! The firmware, TDVF or td-shim, describes in its TDX metadata the
+//! sections the VMM adds to the TD before it starts. MRTD is the SHA-384
+//! digest of the TDH.MEM.PAGE.ADD of every page of these sections, each
+//! followed by the TDH.MR.EXTEND of the 256 byte chunks of the page when the
+//! section is measured, in the order KVM adds them. Sections added with
+//! TDH.MEM.PAGE.AUG, after the TD starts, are not measured.
+//!
+//! RTMR0 depends on the TD HOB and the ACPI tables, so on the memory and
+//! devices of the TD. Only the digest of the Configuration Firmware Volume
+//! that TDVF measures into it, the `tdx.ccel.cfv` claim, is computed.
+
+use anyhow::*;
+use chrono::{Months, Utc};
+use sha2::{Digest, Sha384};
+
+use super::snp_launch::ovmf::Ovmf;
+use crate::rvps::ReferenceValue;
+
+const PAGE_SIZE: usize = 4096;
+const MR_EXTEND_CHUNK_SIZE: usize = 256;
+
+/// Size of the buffer hashed for each operation, before the data of
+/// TDH.MR.EXTEND.
+const OPERATION_SIZE: usize = 128;
+
+const TDX_METADATA_OFFSET_GUID: &str = "e47a6535-984a-4798-865e-4685a7bf8ec2";
+
+/// Without an OVMF table, the offset of the metadata from the start of the
+/// image is 32 bytes before its end.
+const METADATA_POINTER_OFFSET_FROM_END: usize = 0x20;
+
+/// Signature, length, version and number of sections.
+const METADATA_HEADER_SIZE: usize = 16;
+const METADATA_SIGNATURE: &[u8] = b"TDVF";
+const SECTION_SIZE: usize = 32;
+
+/// Types of the TDX metadata sections.
+const SECTION_CFV: u32 = 1;
+const SECTION_PAYLOAD: u32 = 5;
+
+/// Attributes of the TDX metadata sections.
+const ATTRIBUTE_MR_EXTEND: u32 = 0x1;
+const ATTRIBUTE_PAGE_AUG: u32 = 0x2;
+
+/// Names of the reference values.
+pub const MR_TD_CLAIM: &str = "tdx.quote.body.mr_td";
+pub const CFV_CLAIM: &str = "tdx.ccel.cfv";
+
+/// The reference values expire in the default time (months).
+const DEFAULT_EXPIRED_TIME: u32 = 12;
+
+/// The TD to measure.
+#[derive(Debug, Clone)]
+pub struct TdConfig {
+    /// The TDVF or td-shim image.
+    pub firmware: Vec<u8>,
+    /// The payload loaded by the VMM in the payload section of td-shim.
+    pub payload: Option<Vec<u8>>,
+}
+
+/// The measurements of a TD known before it starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TdMeasurements {
+    pub mr_td: [u8; 48],
+    /// Digest of the Configuration Firmware Volume of TDVF.
+    pub cfv: Option<[u8; 48]>,
+}
+
+/// A section of the TDX metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Section {
+    data_offset: u32,
+    raw_data_size: u32,
+    memory_address: u64,
+    memory_data_size: u64,
+    section_type: u32,
+    attributes: u32,
+}
+
+fn read<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N]> {
+    Ok(data
+        .get(offset..offset + N)
+        .ok_or_else(|| anyhow!("Firmware image truncated at {offset:#x}"))?
+        .try_into()?)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    Ok(u32::from_le_bytes(read(data, offset)?))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    Ok(u64::from_le_bytes(read(data, offset)?))
+}
+
+/// Offset of the TDX metadata in `firmware`, given by the OVMF table of
+/// TDVF, or by the metadata pointer of td-shim.
+fn metadata_offset(firmware: &[u8]) -> Result<usize> {
+    if let Some(entry) = Ovmf::parse(firmware)
+        .ok()
+        .and_then(|ovmf| ovmf.entry(TDX_METADATA_OFFSET_GUID))
+    {
+        return firmware
+            .len()
+            .checked_sub(read_u32(entry, 0)? as usize)
+            .ok_or_else(|| anyhow!("Illegal offset of the TDX metadata"));
+    }
+    let pointer = firmware
+        .len()
+        .checked_sub(METADATA_POINTER_OFFSET_FROM_END)
+        .ok_or_else(|| anyhow!("Firmware image too small"))?;
+    Ok(read_u32(firmware, pointer)? as usize)
+}
+
+fn sections(firmware: &[u8]) -> Result<Vec<Section>> {
+    let start = metadata_offset(firmware)?;
+    if firmware.get(start..start + 4) != Some(METADATA_SIGNATURE) {
+        bail!("No TDX metadata in the firmware image");
+    }
+    let length = read_u32(firmware, start + 4)? as usize;
+    let count = read_u32(firmware, start + 12)? as usize;
+    if METADATA_HEADER_SIZE + count * SECTION_SIZE > length {
+        bail!("The TDX metadata is too small for its {count} sections");
+    }
+    (0..count)
+        .map(|i| {
+            let desc = start + METADATA_HEADER_SIZE + i * SECTION_SIZE;
+            Ok(Section {
+                data_offset: read_u32(firmware, desc)?,
+                raw_data_size: read_u32(firmware, desc + 4)?,
+                memory_address: read_u64(firmware, desc + 8)?,
+                memory_data_size: read_u64(firmware, desc + 16)?,
+                section_type: read_u32(firmware, desc + 24)?,
+                attributes: read_u32(firmware, desc + 28)?,
+            })
+        })
+        .collect()
+}
+
+/// The operation of the TDX module hashed into MRTD, `operation` padded
+/// to 16 bytes, then the GPA.
+fn operation(operation: &[u8], gpa: u64) -> [u8; OPERATION_SIZE] {
+    let mut buffer = [0; OPERATION_SIZE];
+    buffer[..operation.len()].copy_from_slice(operation);
+    buffer[16..24].copy_from_slice(&gpa.to_le_bytes());
+    buffer
+}
+
+/// The data the VMM copies in `section`.
+fn section_data<'a>(td: &'a TdConfig, section: &Section) -> Result<&'a [u8]> {
+    if section.section_type == SECTION_PAYLOAD && section.raw_data_size == 0 {
+        return Ok(td.payload.as_deref().unwrap_or_default());
+    }
+    let start = section.data_offset as usize;
+    td.firmware
+        .get(start..start + section.raw_data_size as usize)
+        .ok_or_else(|| anyhow!("A TDX metadata section is out of the firmware image"))
+}
+
+/// The measurements of `td`, as in its TD quotes and its CCEL.
+pub fn measurements(td: &TdConfig) -> Result<TdMeasurements> {
+    let mut mr_td = Sha384::new();
+    let mut cfv = None;
+    for section in sections(&td.firmware)? {
+        let data = section_data(td, &section)?;
+        if section.section_type == SECTION_CFV {
+            let mut digest = [0; 48];
+            digest.copy_from_slice(&Sha384::digest(data));
+            cfv = Some(digest);
+        }
+        if section.attributes & ATTRIBUTE_PAGE_AUG != 0 {
+            continue;
+        }
+        if !section.memory_address.is_multiple_of(PAGE_SIZE as u64)
+            || !section.memory_data_size.is_multiple_of(PAGE_SIZE as u64)
+        {
+            bail!(
+                "TDX metadata section at {:#x} is not page aligned",
+                section.memory_address
+            );
+        }
+        if data.len() as u64 > section.memory_data_size {
+            bail!(
+                "The data of the TDX metadata section at {:#x} exceeds its memory",
+                section.memory_address
+            );
+        }
+
+        let mut memory = data.to_vec();
+        memory.resize(section.memory_data_size as usize, 0);
+        for (i, page) in memory.chunks(PAGE_SIZE).enumerate() {
+            let gpa = section.memory_address + (i * PAGE_SIZE) as u64;
+            mr_td.update(operation(b"MEM.PAGE.ADD", gpa));
+            if section.attributes & ATTRIBUTE_MR_EXTEND == 0 {
+                continue;
+            }
+            for (j, chunk) in page.chunks(MR_EXTEND_CHUNK_SIZE).enumerate() {
+                let gpa = gpa + (j * MR_EXTEND_CHUNK_SIZE) as u64;
+                mr_td.update(operation(b"MR.EXTEND", gpa));
+                mr_td.update(chunk);
+            }
+        }
+    }
+
+    let mut digest = [0; 48];
+    digest.copy_from_slice(&mr_td.finalize());
+    Ok(TdMeasurements { mr_td: digest, cfv })
+}
+
+/// Reference values of the `tdx.quote.body.mr_td` claim, and of the
+/// `tdx.ccel.cfv` claim for TDVF, for `measurements`.
+pub fn reference_values(measurements: &TdMeasurements) -> Result<Vec<ReferenceValue>> {
+    let expired = Utc::now()
+        .checked_add_months(Months::new(DEFAULT_EXPIRED_TIME))
+        .ok_or_else(|| anyhow!("Expired time calculated overflowed"))?;
+    let claims = [
+        (MR_TD_CLAIM, Some(measurements.mr_td)),
+        (CFV_CLAIM, measurements.cfv),
+    ];
+    claims
+        .into_iter()
+        .filter_map(|(name, digest)| digest.map(|digest| (name, digest)))
+        .map(|(name, digest)| {
+            Ok(ReferenceValue::new()?
+                .set_name(name)
+                .set_expired(expired)
+                .set_metadata("provenance", "tdx-launch-measurement")
+                .add_hash_value("sha384".to_string(), hex::encode(digest)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A td-shim like image of `size` bytes, with a metadata pointer and the
+    /// given sections, whose data is 0x5a.
+    fn firmware(size: usize, sections: &[Section]) -> Vec<u8> {
+        let mut firmware = vec![0x5a; size];
+        let mut metadata = METADATA_SIGNATURE.to_vec();
+        let length = METADATA_HEADER_SIZE + sections.len() * SECTION_SIZE;
+        for value in [length, 1, sections.len()] {
+            metadata.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        for section in sections {
+            metadata.extend_from_slice(&section.data_offset.to_le_bytes());
+            metadata.extend_from_slice(&section.raw_data_size.to_le_bytes());
+            metadata.extend_from_slice(&section.memory_address.to_le_bytes());
+            metadata.extend_from_slice(&section.memory_data_size.to_le_bytes());
+            metadata.extend_from_slice(&section.section_type.to_le_bytes());
+            metadata.extend_from_slice(&section.attributes.to_le_bytes());
+        }
+        let start = size - 0x1000;
+        firmware[start..start + metadata.len()].copy_from_slice(&metadata);
+        let pointer = size - METADATA_POINTER_OFFSET_FROM_END;
+        firmware[pointer..pointer + 4].copy_from_slice(&(start as u32).to_le_bytes());
+        firmware
+    }
+
+    fn section(
+        data_offset: u32,
+        raw_data_size: u32,
+        memory_address: u64,
+        memory_data_size: u64,
+        section_type: u32,
+        attributes: u32,
+    ) -> Section {
+        Section {
+            data_offset,
+            raw_data_size,
+            memory_address,
+            memory_data_size,
+            section_type,
+            attributes,
+        }
+    }
+
+    #[test]
+    fn test_measurements() {
+        let sections = [
+            // BFV, CFV, TD HOB, temporary and permanent memory
+            section(0x2000, 0x2000, 0xffff_e000, 0x2000, 0, ATTRIBUTE_MR_EXTEND),
+            section(0, 0x1000, 0xffff_c000, 0x1000, SECTION_CFV, 0),
+            section(0, 0, 0x80_9000, 0x1000, 2, 0),
+            section(0, 0, 0x80_0000, 0x2000, 3, 0),
+            section(0, 0, 0x100_0000, 0x1000, 4, ATTRIBUTE_PAGE_AUG),
+            section(
+                0,
+                0,
+                0x110_0000,
+                0x1000,
+                SECTION_PAYLOAD,
+                ATTRIBUTE_MR_EXTEND,
+            ),
+        ];
+        let td = TdConfig {
+            firmware: firmware(0x4000, &sections),
+            payload: Some(b"payload".to_vec()),
+        };
+        let measured = measurements(&td).unwrap();
+        // Computed independently from the same TD.
+        assert_eq!(
+            hex::encode(measured.mr_td),
+            "9d5ec98c18324e8210d30074f0787d8c76d5de8c184a3fbd6d4e09a9b42c87bbee603dba70c71168ae76293ddb69ef3b"
+        );
+        assert_eq!(
+            measured.cfv,
+            Some(Sha384::digest(&td.firmware[..0x1000]).into())
+        );
+
+        let rvs = reference_values(&measured).unwrap();
+        assert_eq!(rvs.len(), 2);
+        assert_eq!(rvs[0].name(), MR_TD_CLAIM);
+        assert_eq!(
+            rvs[0].hash_values()[0].value(),
+            &hex::encode(measured.mr_td)
+        );
+        assert_eq!(rvs[1].name(), CFV_CLAIM);
+
+        let unaligned = [section(0, 0x100, 0x80_0100, 0x1000, 3, 0)];
+        let td = TdConfig {
+            firmware: firmware(0x4000, &unaligned),
+            payload: None,
+        };
+        assert!(measurements(&td).is_err());
+        let td = TdConfig {
+            firmware: vec![0; 0x4000],
+            payload: None,
+        };
+        assert!(measurements(&td).is_err());
+    }
+}
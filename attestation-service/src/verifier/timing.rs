@@ -0,0 +1,181 @@
+//! Time of boot, time of attestation and evidence age claims.
+//!
+//! - `attestation_time`: when the AS verified the evidence.
+//! - `boot_time`: when the guest booted, for the TEEs whose evidence tells
+//!   how long the guest has been running, e.g. the clock of the vTPM quote
+//!   of TD partitioning guests, before the time the evidence was produced,
+//!   if it is bound to one, else before its verification. Absent otherwise.
+//! - `evidence_age_seconds`: how long before its verification the evidence
+//!   was produced, for evidence bound to the time it was produced rather
+//!   than to a nonce, see [`super::freshness`]. Absent otherwise.
+//!
+//! Both are RFC 3339 times, like the `tcb_date` of [`super::tcb`], which
+//! tells the release date of the TCB the platform booted with. The AS lifts
+//! the `boot_time` claim out of the claims of the TEE, so that one policy
+//! rule holds across TEE types, and the helper functions of the
+//! `data.attestation.timing` policy package compare them with dates:
+//!
+//! ```rego
+//! import data.attestation.timing
+//!
+//! allow { timing.booted_since("2024-03-12") }
+//! ```
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+
+pub const ATTESTATION_TIME_CLAIM: &str = "attestation_time";
+pub const BOOT_TIME_CLAIM: &str = "boot_time";
+pub const EVIDENCE_AGE_CLAIM: &str = "evidence_age_seconds";
+
+const DEFAULT_CLOCK_SKEW_SECS: u64 = 60;
+
+fn default_clock_skew_secs() -> u64 {
+    DEFAULT_CLOCK_SKEW_SECS
+}
+
+/// Maximum age of evidence bound to the time it was produced.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EvidenceAgeConfig {
+    /// Evidence produced longer ago than this is rejected, in seconds.
+    pub max_age_secs: u64,
+    /// How far the clock of the attester may be off that of the AS, either
+    /// way, in seconds.
+    #[serde(default = "default_clock_skew_secs")]
+    pub clock_skew_secs: u64,
+}
+
+impl EvidenceAgeConfig {
+    /// Check the age of evidence `produced` at that time, verified `now`,
+    /// and return it in seconds, 0 for evidence from the future within
+    /// the clock skew allowance.
+    pub fn check(&self, produced: DateTime<Utc>, now: DateTime<Utc>) -> Result<u64> {
+        let age = (now - produced).num_seconds();
+        let skew = i64::try_from(self.clock_skew_secs).unwrap_or(i64::MAX);
+        if age < -skew {
+            bail!(
+                "Evidence was produced at {}, {}s in the future, beyond the clock skew of {}s",
+                produced.to_rfc3339(),
+                -age,
+                self.clock_skew_secs
+            );
+        }
+        let age = u64::try_from(age).unwrap_or(0);
+        if age > self.max_age_secs.saturating_add(self.clock_skew_secs) {
+            bail!(
+                "Evidence is {age}s old, older than the maximum age of {}s",
+                self.max_age_secs
+            );
+        }
+        Ok(age)
+    }
+}
+
+/// The time evidence bound to `nonce` was produced at, if the nonce is an
+/// RFC 3339 time rather than a random nonce, see [`super::freshness`].
+pub fn evidence_time(nonce: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(nonce)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// Add the `evidence_age_seconds` claim, of evidence produced at
+/// `produced` and verified `now`, to the flattened `claims`.
+pub fn add_evidence_age(claims: &mut Value, produced: DateTime<Utc>, now: DateTime<Utc>) {
+    if let Some(claims) = claims.as_object_mut() {
+        let age = (now - produced).num_seconds().max(0);
+        claims.insert(EVIDENCE_AGE_CLAIM.to_string(), age.into());
+    }
+}
+
+/// Add the `boot_time` claim to the `claims` of a verifier, for a guest
+/// that has been running for `uptime` at `now`.
+pub fn add_boot_time(claims: &mut Value, uptime: Duration, now: DateTime<Utc>) {
+    if let Some(claims) = claims.as_object_mut() {
+        claims.insert(
+            BOOT_TIME_CLAIM.to_string(),
+            (now - uptime).to_rfc3339().into(),
+        );
+    }
+}
+
+/// Move the `boot_time` claim of `tee` out of its namespace in the
+/// flattened `claims`, and add the `attestation_time` claim, `now`.
+pub fn lift(tee: &str, claims: &mut Value, now: DateTime<Utc>) {
+    let Some(claims) = claims.as_object_mut() else {
+        return;
+    };
+    if let Some(value) = claims.remove(&format!("{tee}.{BOOT_TIME_CLAIM}")) {
+        claims.insert(BOOT_TIME_CLAIM.to_string(), value);
+    }
+    claims.insert(ATTESTATION_TIME_CLAIM.to_string(), now.to_rfc3339().into());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    #[test]
+    fn test_lift() {
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let mut verifier_claims = json!({"quote": {}});
+        add_boot_time(&mut verifier_claims, Duration::hours(1), now);
+        assert_eq!(verifier_claims["boot_time"], "2023-11-14T21:13:20+00:00");
+
+        let mut claims = json!({
+            "tdx.boot_time": verifier_claims["boot_time"],
+            "tdx.quote.body.mr_td": "705e",
+        });
+        lift("tdx", &mut claims, now);
+        assert_eq!(
+            claims,
+            json!({
+                "boot_time": "2023-11-14T21:13:20+00:00",
+                "attestation_time": "2023-11-14T22:13:20+00:00",
+                "tdx.quote.body.mr_td": "705e",
+            })
+        );
+
+        let mut claims = json!({"sample.svn": "1"});
+        lift("sample", &mut claims, now);
+        assert!(claims.get("boot_time").is_none());
+        assert_eq!(claims["attestation_time"], "2023-11-14T22:13:20+00:00");
+    }
+
+    #[test]
+    fn test_evidence_age() {
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        assert!(evidence_time("mHjlA2YGfXqW0vLsbVAPy2kIs3bD8JRt2BWX1MyTpqU=").is_none());
+        let produced = evidence_time("2023-11-14T23:13:20+01:00").unwrap();
+        assert_eq!(produced, now);
+
+        let config = EvidenceAgeConfig {
+            max_age_secs: 300,
+            clock_skew_secs: 60,
+        };
+        assert_eq!(
+            config.check(now - Duration::seconds(300), now).unwrap(),
+            300
+        );
+        assert_eq!(
+            config.check(now - Duration::seconds(360), now).unwrap(),
+            360
+        );
+        let e = config.check(now - Duration::seconds(361), now).unwrap_err();
+        assert!(e.to_string().contains("361s old"));
+        assert_eq!(config.check(now + Duration::seconds(60), now).unwrap(), 0);
+        let e = config.check(now + Duration::seconds(61), now).unwrap_err();
+        assert!(e.to_string().contains("61s in the future"));
+
+        let mut claims = json!({});
+        add_evidence_age(&mut claims, now - Duration::seconds(42), now);
+        assert_eq!(claims[EVIDENCE_AGE_CLAIM], 42);
+
+        let config: EvidenceAgeConfig = serde_json::from_str(r#"{"max_age_secs": 30}"#).unwrap();
+        assert_eq!(config.clock_skew_secs, DEFAULT_CLOCK_SKEW_SECS);
+    }
+}
@@ -0,0 +1,104 @@
+//! Trace of the verification of evidence.
+//!
+//! The audit trail of the AS tells, for every attestation, which stages of
+//! its pipeline the verifier ran, see [`super::pipeline`], and which
+//! certificate chains the evidence was verified with. Verifiers record them
+//! in the trace of the verification as they run, and the AS collects the
+//! trace of each verification. The OpenTelemetry traces of the AS also time
+//! the stages from it.
+
+use super::pipeline::Stage;
+use std::cell::RefCell;
+use std::future::Future;
+use std::time::SystemTime;
+
+tokio::task_local! {
+    static TRACE: RefCell<VerificationTrace>;
+}
+
+/// A certificate chain that evidence was verified with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateChain {
+    /// The key the chain certifies, e.g. `PCK` or `VCEK`.
+    pub name: String,
+    /// The DER certificates of the chain, leaf first.
+    pub certificates: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerificationTrace {
+    /// The stages the verifier entered, in order.
+    pub stages: Vec<Stage>,
+    /// When the verifier entered each of the `stages`.
+    pub started: Vec<SystemTime>,
+    pub chains: Vec<CertificateChain>,
+}
+
+/// Run `verification`, and return its output with its trace.
+pub(crate) async fn collect<F: Future>(verification: F) -> (F::Output, VerificationTrace) {
+    TRACE
+        .scope(RefCell::new(VerificationTrace::default()), async move {
+            let output = verification.await;
+            (output, TRACE.with(RefCell::take))
+        })
+        .await
+}
+
+/// Record that the verifier entered `stage`.
+pub(crate) fn enter(stage: Stage) {
+    let _ = TRACE.try_with(|trace| {
+        let mut trace = trace.borrow_mut();
+        trace.stages.push(stage);
+        trace.started.push(SystemTime::now());
+    });
+}
+
+/// Record the chain of DER `certificates`, leaf first, that verified the
+/// `name` key of the evidence.
+#[cfg_attr(
+    not(any(
+        feature = "tdx-verifier",
+        feature = "sgx-verifier",
+        feature = "snp-verifier"
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn record_chain(name: &str, certificates: Vec<Vec<u8>>) {
+    let _ = TRACE.try_with(|trace| {
+        trace.borrow_mut().chains.push(CertificateChain {
+            name: name.to_string(),
+            certificates,
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_collect() {
+        let (output, trace) = collect(async {
+            enter(Stage::Parse);
+            record_chain("PCK", vec![b"leaf".to_vec(), b"root".to_vec()]);
+            enter(Stage::ClaimsNormalize);
+            42
+        })
+        .await;
+        assert_eq!(output, 42);
+        assert_eq!(trace.stages, [Stage::Parse, Stage::ClaimsNormalize]);
+        assert!(trace.started[0] <= trace.started[1]);
+        assert_eq!(
+            trace.chains,
+            [CertificateChain {
+                name: "PCK".to_string(),
+                certificates: vec![b"leaf".to_vec(), b"root".to_vec()],
+            }]
+        );
+
+        // Out of a collection, nothing is recorded.
+        enter(Stage::Parse);
+        let (_, trace) = collect(async {}).await;
+        assert_eq!(trace, VerificationTrace::default());
+    }
+}
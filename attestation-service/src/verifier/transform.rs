@@ -0,0 +1,145 @@
+//! Decoded claims next to raw ones.
+//!
+//! Some fields of the evidence are integers that verifiers render as hex,
+//! like the TDX `xfam` ("e742060000000000", little-endian). Policies would
+//! have to swap bytes to compare them. Such claims are emitted decoded too,
+//! as a JSON number named after the claim with an `_int` suffix, e.g.
+//! `"tdx.quote.body.xfam_int": 411367`, the raw claim staying as it is.
+//!
+//! Verifiers tell which of their claims to decode with
+//! [`super::Verifier::claim_transforms`], and `claim_transforms` of the AS
+//! config adds or overrides transforms, by flattened claim name:
+//!
+//! ```json
+//! "claim_transforms": {
+//!     "tdx.quote.body.td_attributes": "le_uint",
+//!     "tdx.quote.body.xfam": "none"
+//! }
+//! ```
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Suffix of the names of the decoded claims.
+pub const DECODED_SUFFIX: &str = "_int";
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimTransform {
+    /// Hex encoded little-endian unsigned integer, up to 8 bytes.
+    LeUint,
+    /// Hex encoded big-endian unsigned integer, up to 8 bytes.
+    BeUint,
+    /// No decoded claim, to turn off a transform of a verifier.
+    None,
+}
+
+impl ClaimTransform {
+    fn decode(self, claim: &str) -> Result<Option<Value>> {
+        let mut bytes = match self {
+            ClaimTransform::None => return Ok(None),
+            _ => hex::decode(claim)?,
+        };
+        if bytes.len() > 8 {
+            bail!("{} bytes do not fit in an integer", bytes.len());
+        }
+        if self == ClaimTransform::LeUint {
+            bytes.reverse();
+        }
+        let int = bytes.iter().fold(0u64, |int, byte| int << 8 | *byte as u64);
+        Ok(Some(int.into()))
+    }
+}
+
+/// The transforms of the claims of a verifier, by flattened claim name:
+/// `verifier` are those of the verifier, with names relative to the claims
+/// of `tee`, overridden by the `config` ones.
+pub fn transforms(
+    tee: &str,
+    verifier: &[(&str, ClaimTransform)],
+    config: &HashMap<String, ClaimTransform>,
+) -> Vec<(String, ClaimTransform)> {
+    let mut transforms: HashMap<String, ClaimTransform> = verifier
+        .iter()
+        .map(|(name, transform)| (format!("{tee}.{name}"), *transform))
+        .collect();
+    let prefix = format!("{tee}.");
+    transforms.extend(
+        config
+            .iter()
+            .filter(|(name, _)| name.starts_with(&prefix))
+            .map(|(name, transform)| (name.clone(), *transform)),
+    );
+    let mut transforms: Vec<_> = transforms.into_iter().collect();
+    transforms.sort_by(|a, b| a.0.cmp(&b.0));
+    transforms
+}
+
+/// Add the decoded claims to the flattened `claims`. Claims that are
+/// missing or cannot be decoded are left out, and logged.
+pub fn apply(claims: &mut Value, transforms: &[(String, ClaimTransform)]) {
+    let Some(claims) = claims.as_object_mut() else {
+        return;
+    };
+    for (name, transform) in transforms {
+        let Some(raw) = claims.get(name).and_then(Value::as_str) else {
+            continue;
+        };
+        match transform.decode(raw) {
+            Ok(Some(decoded)) => {
+                claims.insert(format!("{name}{DECODED_SUFFIX}"), decoded);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Cannot decode claim {name} ({transform:?}): {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_apply() {
+        let mut claims = json!({
+            "tdx.quote.header.version": 4,
+            "tdx.quote.body.xfam": "e742060000000000",
+            "tdx.quote.body.td_attributes": "0100001000000000",
+            "tdx.quote.body.mr_td": "705ee9381b8633a9fbe532b52345e8433343d2868959f57889d84ca3",
+            "tdx.quote.body.report_data": "0400",
+        });
+        let transforms = transforms(
+            "tdx",
+            &[
+                ("quote.body.xfam", ClaimTransform::LeUint),
+                ("quote.body.td_attributes", ClaimTransform::LeUint),
+                ("quote.body.mr_td", ClaimTransform::LeUint),
+                ("quote.header.version", ClaimTransform::LeUint),
+            ],
+            &HashMap::from([
+                (
+                    "tdx.quote.body.td_attributes".to_string(),
+                    ClaimTransform::None,
+                ),
+                (
+                    "tdx.quote.body.report_data".to_string(),
+                    ClaimTransform::BeUint,
+                ),
+                ("snp.chip_id".to_string(), ClaimTransform::LeUint),
+            ]),
+        );
+        assert_eq!(transforms.len(), 5);
+
+        apply(&mut claims, &transforms);
+        assert_eq!(claims["tdx.quote.body.xfam_int"], 0x0642e7);
+        assert_eq!(claims["tdx.quote.body.xfam"], "e742060000000000");
+        assert_eq!(claims["tdx.quote.body.report_data_int"], 0x0400);
+        // Turned off, too long, and not hex.
+        assert!(claims.get("tdx.quote.body.td_attributes_int").is_none());
+        assert!(claims.get("tdx.quote.body.mr_td_int").is_none());
+        assert!(claims.get("tdx.quote.header.version_int").is_none());
+    }
+}
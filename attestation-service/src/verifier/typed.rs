@@ -0,0 +1,149 @@
+//! Typed claims, in place of the hex strings of bitmaps and SVNs.
+//!
+//! The TD attributes and XFAM of TDX quotes are bitmaps, that verifiers
+//! emit as the hex of the evidence ("0000001000000000", little-endian), and
+//! the TCB SVN is an array of SVNs whose components are only known by their
+//! index. From the `v3` claims schema, see [`super::schema`], they are
+//! decoded into sub-objects of named flags and SVNs:
+//!
+//! ```json
+//! "tdx.quote.body.td_attributes.debug": false,
+//! "tdx.quote.body.td_attributes.sept_ve_disable": true,
+//! "tdx.quote.body.xfam.avx512": true,
+//! "tdx.quote.body.tcb_svn.tdx_module_major": 1
+//! ```
+//!
+//! The hex claims they are decoded from are left out. Bits of the bitmaps
+//! without a name here are only in the `_int` claims of
+//! [`super::transform`].
+
+use serde_json::{Map, Value};
+
+/// Named bits of a little-endian bitmap. A name of several bits is set if
+/// any of them is.
+type Bits = &'static [(&'static str, &'static [u32])];
+
+const TD_ATTRIBUTES: Bits = &[
+    ("debug", &[0]),
+    ("sept_ve_disable", &[28]),
+    ("migratable", &[29]),
+    ("pks", &[30]),
+    ("kl", &[31]),
+    ("perfmon", &[63]),
+];
+
+/// The XSAVE features of XCR0 and IA32_XSS.
+const XFAM: Bits = &[
+    ("x87", &[0]),
+    ("sse", &[1]),
+    ("avx", &[2]),
+    ("mpx", &[3, 4]),
+    ("avx512", &[5, 6, 7]),
+    ("pt", &[8]),
+    ("pk", &[9]),
+    ("enqcmd", &[10]),
+    ("cet", &[11, 12]),
+    ("uli", &[14]),
+    ("lbr", &[15]),
+    ("amx", &[17, 18]),
+];
+
+/// Named components of the TEE TCB SVN.
+const TCB_SVN: &[(&str, usize)] = &[
+    ("tdx_module_minor", 0),
+    ("tdx_module_major", 1),
+    ("late_microcode_update", 2),
+];
+
+/// Where the TD quote body is in the claims of each TEE.
+const TD_QUOTE_BODIES: &[(&str, &str)] = &[("tdx", "quote.body"), ("azsnpvtpm", "quote.body")];
+
+fn flags(hex_claim: &str, bits: Bits) -> Option<Map<String, Value>> {
+    let bytes = hex::decode(hex_claim).ok()?;
+    let bit = |bit: u32| {
+        bytes
+            .get(bit as usize / 8)
+            .is_some_and(|byte| byte >> (bit % 8) & 1 == 1)
+    };
+    Some(
+        bits.iter()
+            .map(|(name, bits)| (name.to_string(), bits.iter().any(|b| bit(*b)).into()))
+            .collect(),
+    )
+}
+
+/// Replace the bitmap claims of the TD quote of `tee` in the flattened
+/// `claims` with flags, and name the TCB SVN components. Claims that are
+/// not hex are left as they are.
+pub fn apply(tee: &str, claims: &mut Value) {
+    let Some(claims) = claims.as_object_mut() else {
+        return;
+    };
+    for (_, body) in TD_QUOTE_BODIES
+        .iter()
+        .filter(|(body_tee, _)| *body_tee == tee)
+    {
+        let prefix = format!("{tee}.{body}");
+        for (name, bits) in [("td_attributes", TD_ATTRIBUTES), ("xfam", XFAM)] {
+            let claim = format!("{prefix}.{name}");
+            let Some(flags) = claims
+                .get(&claim)
+                .and_then(Value::as_str)
+                .and_then(|hex_claim| flags(hex_claim, bits))
+            else {
+                continue;
+            };
+            claims.remove(&claim);
+            for (flag, value) in flags {
+                claims.insert(format!("{claim}.{flag}"), value);
+            }
+        }
+        for (name, index) in TCB_SVN {
+            if let Some(svn) = claims.get(&format!("{prefix}.tcb_svn.{index}")).cloned() {
+                claims.insert(format!("{prefix}.tcb_svn.{name}"), svn);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_apply() {
+        let mut claims = json!({
+            "tdx.quote.body.td_attributes": "0000001000000000",
+            "tdx.quote.body.xfam": "e702060000000000",
+            "tdx.quote.body.tcb_svn.0": 3,
+            "tdx.quote.body.tcb_svn.1": 1,
+            "tdx.quote.body.mr_td": "705ee938",
+        });
+        apply("tdx", &mut claims);
+
+        assert!(claims.get("tdx.quote.body.td_attributes").is_none());
+        assert_eq!(claims["tdx.quote.body.td_attributes.debug"], false);
+        assert_eq!(claims["tdx.quote.body.td_attributes.sept_ve_disable"], true);
+        assert_eq!(claims["tdx.quote.body.xfam.x87"], true);
+        assert_eq!(claims["tdx.quote.body.xfam.avx512"], true);
+        assert_eq!(claims["tdx.quote.body.xfam.amx"], true);
+        assert_eq!(claims["tdx.quote.body.xfam.cet"], false);
+        assert_eq!(claims["tdx.quote.body.tcb_svn.tdx_module_minor"], 3);
+        assert_eq!(claims["tdx.quote.body.tcb_svn.tdx_module_major"], 1);
+        assert!(claims
+            .get("tdx.quote.body.tcb_svn.late_microcode_update")
+            .is_none());
+        assert_eq!(claims["tdx.quote.body.mr_td"], "705ee938");
+
+        // Not hex, or of another TEE.
+        let mut claims = json!({
+            "tdx.quote.body.xfam": "not hex",
+            "snp.quote.body.xfam": "e702060000000000",
+        });
+        let expected = claims.clone();
+        apply("tdx", &mut claims);
+        apply("snp", &mut claims);
+        assert_eq!(claims, expected);
+    }
+}
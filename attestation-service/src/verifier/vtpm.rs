@@ -0,0 +1,288 @@
+//! Quotes of the vTPM of Azure confidential VMs.
+//!
+//! The paravisor of a confidential VM provides the guest with a vTPM, whose
+//! attestation key (AK) is in the runtime data of the HCL report, see
+//! [`crate::verifier::hcl`]. As the hardware report binds the runtime data,
+//! it certifies the AK, and a quote of the PCRs signed by the AK carries
+//! the measurements of the guest.
+//!
+//! The evidence has the quote with the values of the quoted PCRs:
+//!
+//! ```json
+//! "vtpm_quote": {
+//!     "message": "<base64 TPMS_ATTEST>",
+//!     "signature": "<base64 RSASSA-PKCS1-v1_5 SHA-256 signature>",
+//!     "pcrs": { "4": "<hex>", "7": "<hex>", "11": "<hex>" }
+//! }
+//! ```
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Key ID of the vTPM attestation key in the runtime data.
+const AK_KID: &str = "HCLAkPub";
+
+const TPM_GENERATED_VALUE: u32 = 0xff54_4347;
+const TPM_ST_ATTEST_QUOTE: u16 = 0x8018;
+const TPM_ALG_SHA256: u16 = 0x000b;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct VtpmQuote {
+    /// TPMS_ATTEST structure of the quote, base64 encoded.
+    pub message: String,
+    /// RSASSA-PKCS1-v1_5 SHA-256 signature of the message by the AK,
+    /// base64 encoded.
+    pub signature: String,
+    /// Hex values of the quoted SHA-256 PCRs, by index.
+    pub pcrs: BTreeMap<u8, String>,
+}
+
+/// A vTPM quote whose signature and PCR values were verified.
+pub(crate) struct VerifiedVtpmQuote {
+    pub pcrs: BTreeMap<u8, String>,
+    /// The qualifying data of the quote, that binds the nonce.
+    pub nonce: Vec<u8>,
+    /// The TPM clock, in milliseconds.
+    pub clock: u64,
+}
+
+impl VerifiedVtpmQuote {
+    /// The `pcr_N` claims of the quoted PCRs, and the `clock` claim.
+    pub fn claims(&self) -> Map<String, Value> {
+        let mut claims: Map<String, Value> = self
+            .pcrs
+            .iter()
+            .map(|(index, value)| (format!("pcr_{index}"), value.to_lowercase().into()))
+            .collect();
+        claims.insert("clock".to_string(), self.clock.into());
+        claims
+    }
+}
+
+/// The RSA vTPM attestation key of the runtime data.
+fn attestation_key(runtime_data: &Value) -> Result<RsaPublicKey> {
+    let key = runtime_data["keys"]
+        .as_array()
+        .and_then(|keys| keys.iter().find(|key| key["kid"] == AK_KID))
+        .ok_or_else(|| anyhow!("The HCL runtime data has no {AK_KID} key"))?;
+    let component = |name: &str| -> Result<BigUint> {
+        let encoded = key[name]
+            .as_str()
+            .ok_or_else(|| anyhow!("The {AK_KID} key has no {name}"))?;
+        let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(encoded.trim_end_matches('='))
+            .with_context(|| format!("Malformed {name} of the {AK_KID} key"))?;
+        Ok(BigUint::from_bytes_be(&bytes))
+    };
+    RsaPublicKey::new(component("n")?, component("e")?).context("Invalid attestation key")
+}
+
+/// Verify the signature of a vTPM quote by the AK of the runtime data, and
+/// that it quotes the given PCR values.
+pub(crate) fn verify(vtpm_quote: &VtpmQuote, runtime_data: &Value) -> Result<VerifiedVtpmQuote> {
+    let decode = |data: &str, what: &str| {
+        base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .with_context(|| format!("Malformed {what}"))
+    };
+    let message = decode(&vtpm_quote.message, "message")?;
+    let signature = decode(&vtpm_quote.signature, "signature")?;
+    attestation_key(runtime_data)?
+        .verify(
+            Pkcs1v15Sign::new::<Sha256>(),
+            &Sha256::digest(&message),
+            &signature,
+        )
+        .map_err(|e| anyhow!("Invalid signature: {e}"))?;
+
+    let attest = parse_attest(&message)?;
+    let mut hasher = Sha256::new();
+    for index in &attest.pcrs {
+        let value = vtpm_quote
+            .pcrs
+            .get(index)
+            .ok_or_else(|| anyhow!("No value of the quoted PCR {index}"))?;
+        hasher.update(hex::decode(value).with_context(|| format!("Malformed PCR {index}"))?);
+    }
+    if let Some(index) = vtpm_quote
+        .pcrs
+        .keys()
+        .find(|index| !attest.pcrs.contains(index))
+    {
+        bail!("PCR {index} is not quoted");
+    }
+    if hasher.finalize().as_slice() != attest.pcr_digest {
+        bail!("The PCR values do not match the digest of the quote");
+    }
+
+    Ok(VerifiedVtpmQuote {
+        pcrs: vtpm_quote.pcrs.clone(),
+        nonce: attest.extra_data,
+        clock: attest.clock,
+    })
+}
+
+/// The fields of a TPMS_ATTEST quote that the verifier checks.
+struct Attest {
+    extra_data: Vec<u8>,
+    /// `clock` of the TPMS_CLOCK_INFO, in milliseconds.
+    clock: u64,
+    /// Indexes of the quoted SHA-256 PCRs, in the order they are digested.
+    pcrs: Vec<u8>,
+    pcr_digest: Vec<u8>,
+}
+
+fn parse_attest(message: &[u8]) -> Result<Attest> {
+    let mut reader = Reader(message);
+    if reader.u32()? != TPM_GENERATED_VALUE || reader.u16()? != TPM_ST_ATTEST_QUOTE {
+        bail!("Not a TPM quote");
+    }
+    // qualifiedSigner
+    reader.sized()?;
+    let extra_data = reader.sized()?.to_vec();
+    let clock = reader.u64()?;
+    // The rest of clockInfo, and firmwareVersion
+    reader.take(9 + 8)?;
+
+    let mut pcrs = Vec::new();
+    for _ in 0..reader.u32()? {
+        let hash = reader.u16()?;
+        let size = reader.take(1)?[0] as usize;
+        let select = reader.take(size)?;
+        if hash != TPM_ALG_SHA256 {
+            bail!("Only SHA-256 PCRs are supported, not algorithm {hash:#06x}");
+        }
+        for index in 0..size * 8 {
+            if select[index / 8] & (1 << (index % 8)) != 0 {
+                pcrs.push(index as u8);
+            }
+        }
+    }
+    let pcr_digest = reader.sized()?.to_vec();
+
+    Ok(Attest {
+        extra_data,
+        clock,
+        pcrs,
+        pcr_digest,
+    })
+}
+
+/// Big-endian reader of TPM structures.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8]> {
+        if self.0.len() < size {
+            bail!("TPM quote is too short");
+        }
+        let (data, rest) = self.0.split_at(size);
+        self.0 = rest;
+        Ok(data)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into()?))
+    }
+
+    /// A TPM2B structure, its data preceded by its size.
+    fn sized(&mut self) -> Result<&'a [u8]> {
+        let size = self.u16()? as usize;
+        self.take(size)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use rsa::traits::PublicKeyParts;
+    use rsa::RsaPrivateKey;
+    use serde_json::json;
+
+    /// An attestation key, and runtime data with it and `user_data`.
+    pub(crate) fn runtime_data(user_data: &[u8]) -> (RsaPrivateKey, String) {
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let b64url =
+            |n: &BigUint| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(n.to_bytes_be());
+        let runtime_data = json!({
+            "keys": [{ "kid": AK_KID, "kty": "RSA", "n": b64url(key.n()), "e": b64url(key.e()) }],
+            "vm-configuration": { "secure-boot": true },
+            "user-data": hex::encode(user_data),
+        })
+        .to_string();
+        (key, runtime_data)
+    }
+
+    /// TPMS_ATTEST quote of the SHA-256 PCRs 0 and 9, an hour after boot.
+    fn attest(extra_data: &[u8], pcr_digest: &[u8]) -> Vec<u8> {
+        let mut attest = TPM_GENERATED_VALUE.to_be_bytes().to_vec();
+        attest.extend(TPM_ST_ATTEST_QUOTE.to_be_bytes());
+        attest.extend([0, 0]);
+        attest.extend((extra_data.len() as u16).to_be_bytes());
+        attest.extend(extra_data);
+        attest.extend(3_600_000u64.to_be_bytes());
+        attest.extend([0; 9 + 8]);
+        attest.extend(1u32.to_be_bytes());
+        attest.extend(TPM_ALG_SHA256.to_be_bytes());
+        attest.extend([3, 0b0000_0001, 0b0000_0010, 0]);
+        attest.extend((pcr_digest.len() as u16).to_be_bytes());
+        attest.extend(pcr_digest);
+        attest
+    }
+
+    /// A quote of the PCRs 0 and 9, of values `[0; 32]` and `[9; 32]`,
+    /// signed by `key`.
+    pub(crate) fn vtpm_quote(key: &RsaPrivateKey, extra_data: &[u8]) -> VtpmQuote {
+        let b64 = |data: &[u8]| base64::engine::general_purpose::STANDARD.encode(data);
+        let pcrs = [[0u8; 32], [9; 32]];
+        let message = attest(extra_data, &Sha256::digest(pcrs.concat()));
+        let signature = key
+            .sign(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(&message))
+            .unwrap();
+        VtpmQuote {
+            message: b64(&message),
+            signature: b64(&signature),
+            pcrs: BTreeMap::from([(0, hex::encode(pcrs[0])), (9, hex::encode(pcrs[1]))]),
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let (key, runtime_data) = runtime_data(&[7; 64]);
+        let runtime_data: Value = serde_json::from_str(&runtime_data).unwrap();
+        let mut quote = vtpm_quote(&key, &[7; 64]);
+
+        let verified = verify(&quote, &runtime_data).unwrap();
+        assert_eq!(verified.nonce, [7; 64]);
+        let claims = verified.claims();
+        assert_eq!(claims["pcr_9"], hex::encode([9; 32]));
+        assert_eq!(claims["clock"], 3_600_000);
+
+        // A PCR that is not quoted.
+        quote.pcrs.insert(1, hex::encode([1; 32]));
+        assert!(verify(&quote, &runtime_data).is_err());
+        quote.pcrs.remove(&1);
+
+        // A PCR value that is not the quoted one.
+        quote.pcrs.insert(9, hex::encode([1; 32]));
+        assert!(verify(&quote, &runtime_data).is_err());
+
+        // A quote signed by another AK.
+        let (_, other_runtime_data) = self::runtime_data(&[7; 64]);
+        let other_runtime_data: Value = serde_json::from_str(&other_runtime_data).unwrap();
+        assert!(verify(&vtpm_quote(&key, &[7; 64]), &other_runtime_data).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+//! Non-fatal verification warnings.
+//!
+//! Verifiers raise warnings for evidence that verifies but shows signs of
+//! degradation, such as collateral out of date or near expiry, a deprecated
+//! evidence format version, or eventlog events skipped. The AS collects the
+//! warnings of each verification, and returns them with the token, so that
+//! they are not only found in the server logs.
+
+use std::cell::RefCell;
+use std::future::Future;
+
+/// Collateral expiring within this period raises a warning, in seconds.
+const COLLATERAL_EXPIRY_WARNING: i64 = 7 * 24 * 3600;
+
+tokio::task_local! {
+    static WARNINGS: RefCell<Vec<String>>;
+}
+
+/// Run `verification`, and return its output with the warnings it raised.
+pub(crate) async fn collect<F: Future>(verification: F) -> (F::Output, Vec<String>) {
+    WARNINGS
+        .scope(RefCell::new(Vec::new()), async move {
+            let output = verification.await;
+            (output, WARNINGS.with(RefCell::take))
+        })
+        .await
+}
+
+/// Raise a warning of the evidence being verified. It is logged too.
+pub(crate) fn raise(warning: impl Into<String>) {
+    let warning = warning.into();
+    warn!("{warning}");
+    let _ = WARNINGS.try_with(|warnings| warnings.borrow_mut().push(warning));
+}
+
+/// Raise a warning if the verification collateral of `what` expires
+/// within a week of `now`. Both are Unix times.
+#[cfg_attr(
+    not(any(feature = "tdx-verifier", feature = "sgx-verifier")),
+    allow(dead_code)
+)]
+pub(crate) fn check_collateral_expiry(what: &str, earliest_expiration: i64, now: i64) {
+    let remaining = earliest_expiration - now;
+    if (0..COLLATERAL_EXPIRY_WARNING).contains(&remaining) {
+        raise(format!(
+            "The {what} collateral expires in {} hours",
+            remaining / 3600
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_collect() {
+        let (output, warnings) = collect(async {
+            raise("first");
+            check_collateral_expiry("TD quote", 1000 + 3600 * 5, 1000);
+            check_collateral_expiry("TD quote", 1000 + COLLATERAL_EXPIRY_WARNING, 1000);
+            42
+        })
+        .await;
+        assert_eq!(output, 42);
+        assert_eq!(
+            warnings,
+            ["first", "The TD quote collateral expires in 5 hours"]
+        );
+
+        // Out of a collection, warnings are only logged.
+        raise("logged");
+        let (_, warnings) = collect(async {}).await;
+        assert!(warnings.is_empty());
+    }
+}
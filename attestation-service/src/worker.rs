@@ -0,0 +1,190 @@
+//! Pool of worker threads for CPU bound work.
+//!
+//! Verifying evidence means checking signatures and hashing, and often
+//! calling blocking vendor libraries, which would stall the async runtime
+//! and every lightweight request it serves under load. Such work runs on
+//! `threads` dedicated threads instead, each driving the jobs it takes
+//! from a queue on a runtime of its own, so that it neither takes the
+//! blocking threads of the main runtime nor blocks one of its workers.
+
+use anyhow::{anyhow, Context, Result};
+use futures::FutureExt;
+use log::error;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::runtime;
+use tokio::sync::oneshot;
+
+/// A job of the pool, sending its output back itself.
+type Job = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+pub(crate) struct WorkerPool {
+    threads: usize,
+    jobs: Mutex<Sender<Job>>,
+    queued: Arc<AtomicUsize>,
+    running: Arc<AtomicUsize>,
+}
+
+/// Counts a job in a gauge of the pool until dropped, even if the job
+/// panics.
+struct Gauge<'a>(&'a AtomicUsize);
+
+impl<'a> Gauge<'a> {
+    fn new(gauge: &'a AtomicUsize) -> Self {
+        gauge.fetch_add(1, Ordering::SeqCst);
+        Self(gauge)
+    }
+}
+
+impl Drop for Gauge<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Run the jobs of `jobs` until the pool is dropped.
+fn work(jobs: Arc<Mutex<Receiver<Job>>>) {
+    let runtime = match runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            error!("Cannot start the runtime of a worker thread: {e}");
+            return;
+        }
+    };
+    loop {
+        let job = jobs.lock().unwrap_or_else(PoisonError::into_inner).recv();
+        match job {
+            Ok(job) => runtime.block_on(job),
+            Err(_) => return,
+        }
+    }
+}
+
+impl WorkerPool {
+    /// Create a pool running up to `threads` jobs in parallel, or as many
+    /// as there are CPUs if `None`.
+    pub fn new(threads: Option<usize>) -> Self {
+        let threads = threads
+            .or_else(|| std::thread::available_parallelism().ok().map(Into::into))
+            .unwrap_or(1)
+            .max(1);
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads {
+            let receiver = receiver.clone();
+            if let Err(e) = std::thread::Builder::new()
+                .name(format!("as-worker-{i}"))
+                .spawn(move || work(receiver))
+            {
+                error!("Cannot start a worker thread: {e}");
+            }
+        }
+        Self {
+            threads,
+            jobs: Mutex::new(sender),
+            queued: Arc::default(),
+            running: Arc::default(),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Jobs waiting for a free worker.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Jobs running on a worker.
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Run `job` to completion on a worker thread. It waits for a free
+    /// worker if they are all busy, and is skipped if cancelled before.
+    pub async fn run<F>(&self, job: F) -> Result<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let queued = self.queued.clone();
+        let running = self.running.clone();
+        queued.fetch_add(1, Ordering::SeqCst);
+        let job: Job = Box::pin(async move {
+            queued.fetch_sub(1, Ordering::SeqCst);
+            if sender.is_closed() {
+                return;
+            }
+            let output = {
+                let _running = Gauge::new(&running);
+                AssertUnwindSafe(job).catch_unwind().await
+            };
+            if let Ok(output) = output {
+                let _ = sender.send(output);
+            }
+        });
+        if self
+            .jobs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .send(job)
+            .is_err()
+        {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return Err(anyhow!("Worker pool is closed"));
+        }
+        receiver.await.context("Worker thread panicked")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_parallelism_is_bounded() {
+        let pool = Arc::new(WorkerPool::new(Some(2)));
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+
+        let jobs = (0..8).map(|_| {
+            let pool = pool.clone();
+            let running = running.clone();
+            let max_running = max_running.clone();
+            async move {
+                pool.run(async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+                .await
+            }
+        });
+        for res in futures::future::join_all(jobs).await {
+            res.unwrap();
+        }
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_panic_is_an_error() {
+        let pool = WorkerPool::new(None);
+        assert_eq!(pool.run(async { 1 }).await.unwrap(), 1);
+        let thread = pool
+            .run(async { std::thread::current().name().map(str::to_string) })
+            .await
+            .unwrap();
+        assert!(thread.unwrap().starts_with("as-worker-"));
+        assert!(pool.run(async { panic!("job failed") }).await.is_err());
+    }
+}
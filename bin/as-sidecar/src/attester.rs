@@ -0,0 +1,71 @@
+//! Attesters, which produce the evidence of the TEE the sidecar runs in.
+
+use anyhow::*;
+use base64::Engine;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+pub enum Attester {
+    /// Evidence of the sample TEE, which anyone can forge, to try the flow
+    /// out against an AS with the sample verifier.
+    Sample,
+    /// A command, e.g. the attester of the TEE, given the base64 report
+    /// data on its stdin, that prints the `tee-evidence` on its stdout.
+    Command(Vec<String>),
+}
+
+impl Attester {
+    /// The evidence of the TEE, binding `report_data`.
+    pub async fn evidence(&self, report_data: &[u8]) -> Result<String> {
+        let report_data = base64::engine::general_purpose::STANDARD.encode(report_data);
+        match self {
+            Self::Sample => Ok(serde_json::json!({
+                "svn": "1",
+                "report_data": report_data,
+            })
+            .to_string()),
+            Self::Command(command) => run(command, &report_data).await,
+        }
+    }
+}
+
+async fn run(command: &[String], report_data: &str) -> Result<String> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("The attester command is empty"))?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Cannot run the attester {program}"))?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("No stdin of the attester"))?;
+    stdin.write_all(report_data.as_bytes()).await?;
+    drop(stdin);
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!("The attester {program} failed with {}", output.status);
+    }
+    let evidence = String::from_utf8(output.stdout).context("The evidence is not UTF-8")?;
+    Ok(evidence.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_command() {
+        let attester = Attester::Command(vec!["cat".to_string()]);
+        assert_eq!(attester.evidence(b"nonce").await.unwrap(), "bm9uY2U=");
+
+        let attester = Attester::Command(vec!["false".to_string()]);
+        assert!(attester.evidence(b"nonce").await.is_err());
+    }
+}
@@ -0,0 +1,266 @@
+//! Reference sidecar of the Attestation Service.
+//!
+//! Runs next to a workload in a TEE, attests it to the AS, and writes the
+//! token, and the secrets released with it, to files that the workload
+//! reads: it gets a nonce from the AS, gets evidence binding it from the
+//! attester, submits the evidence, and validates the token before writing
+//! it. The token is refreshed before it expires, until the sidecar is
+//! stopped, or once with `--once`.
+
+use anyhow::*;
+use clap::{App, Arg};
+use log::{error, info, warn};
+use shadow_rs::shadow;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub mod as_api {
+    tonic::include_proto!("attestation");
+}
+
+use crate::as_api::{
+    attestation_service_client::AttestationServiceClient, AttestationRequest, ChallengeRequest,
+    GetSigningKeysRequest, Tee as GrpcTee,
+};
+use crate::attester::Attester;
+use crate::tee_key::TeeKey;
+use crate::token::Token;
+
+mod attester;
+mod tee_key;
+mod token;
+
+shadow!(build);
+
+/// Default address of the AS
+const DEFAULT_ADDR: &str = "http://127.0.0.1:3000";
+
+/// Seconds to wait before attesting again after a failed attestation
+const RETRY_SECS: u64 = 10;
+
+/// Where the sidecar writes what the workload reads.
+struct Outputs {
+    token_path: PathBuf,
+    secrets_dir: Option<PathBuf>,
+}
+
+fn to_grpc_tee(tee: &str) -> Result<GrpcTee> {
+    Ok(match tee {
+        "sev" => GrpcTee::Sev,
+        "sgx" => GrpcTee::Sgx,
+        "snp" => GrpcTee::Snp,
+        "tdx" => GrpcTee::Tdx,
+        "csv" => GrpcTee::Csv,
+        "sample" => GrpcTee::Sample,
+        tee => bail!("Unknown TEE {tee}"),
+    })
+}
+
+/// Attest once, and write the token and the secrets released with it.
+async fn attest(
+    addr: &str,
+    tee: GrpcTee,
+    attester: &Attester,
+    tee_key: &TeeKey,
+    previous_token: Option<&str>,
+    outputs: &Outputs,
+) -> Result<(String, Token)> {
+    let mut client = AttestationServiceClient::connect(addr.to_string())
+        .await
+        .context("Cannot connect to the AS")?;
+
+    let challenge = client
+        .challenge(ChallengeRequest {
+            tee: tee as i32,
+            freshness_methods: vec!["nonce".to_string()],
+        })
+        .await
+        .context("Challenge")?
+        .into_inner();
+
+    let tee_evidence = attester
+        .evidence(&tee_key.report_data(&challenge.nonce))
+        .await
+        .context("Cannot get the evidence")?;
+    let evidence = serde_json::to_string(&kbs_types::Attestation {
+        tee_pubkey: tee_key.public_key(),
+        tee_evidence,
+    })?;
+
+    let response = client
+        .attestation_evaluate(AttestationRequest {
+            tee: tee as i32,
+            nonce: challenge.nonce,
+            evidence,
+            previous_token: previous_token.unwrap_or_default().to_string(),
+            ..Default::default()
+        })
+        .await
+        .context("Attestation")?
+        .into_inner();
+    for warning in &response.warnings {
+        warn!("Attestation warning: {warning}");
+    }
+
+    let jwks = client
+        .get_signing_keys(GetSigningKeysRequest {})
+        .await
+        .context("Cannot get the signing keys of the AS")?
+        .into_inner()
+        .jwks;
+    let token = token::validate(&response.attestation_token, &jwks, &tee_key.public_key())
+        .context("Invalid token")?;
+
+    write_private(&outputs.token_path, response.attestation_token.as_bytes())?;
+    if let Some(secrets_dir) = &outputs.secrets_dir {
+        for (name, jwe) in &response.secrets {
+            if name.is_empty() || name.starts_with('.') || name.contains('/') {
+                bail!("The secret name {name} is not a file name");
+            }
+            let secret = tee_key
+                .open(jwe)
+                .with_context(|| format!("Cannot decrypt the secret {name}"))?;
+            write_private(&secrets_dir.join(name), &secret)?;
+        }
+    }
+    info!(
+        "Attested, token written to {}, {} secrets released",
+        outputs.token_path.display(),
+        response.secrets.len()
+    );
+
+    Ok((response.attestation_token, token))
+}
+
+/// Replace the file at `path` with `contents`, readable by its owner only,
+/// so that readers never see a partial file.
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .with_context(|| format!("Cannot write {}", tmp.display()))?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path).with_context(|| format!("Cannot write {}", path.display()))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let version = format!(
+        "\nv{}\ncommit: {}\nbuildtime: {}",
+        build::PKG_VERSION,
+        build::COMMIT_HASH,
+        build::BUILD_TIME
+    );
+
+    let matches = App::new("as-sidecar")
+        .version(version.as_str())
+        .long_version(version.as_str())
+        .author("Confidential-Containers Team")
+        .about("Attest the TEE to the AS, and write the token and the released secrets to files")
+        .arg(
+            Arg::with_name("addr")
+                .long("addr")
+                .value_name("addr")
+                .help("The address of the AS")
+                .takes_value(true)
+                .default_value(DEFAULT_ADDR)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("tee")
+                .long("tee")
+                .value_name("tee")
+                .help("The TEE to attest: sev, sgx, snp, tdx, csv or sample")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("token-path")
+                .long("token-path")
+                .value_name("token-path")
+                .help("The path to write the token to")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("secrets-dir")
+                .long("secrets-dir")
+                .value_name("secrets-dir")
+                .help("The directory to write the secrets released with the token to, by name")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("once")
+                .long("once")
+                .help("Attest once and exit, with an error if the attestation failed")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("attester")
+                .value_name("attester")
+                .help("The attester command, given the base64 report data on its stdin, that prints the evidence. Built in for the sample TEE")
+                .multiple_values(true)
+                .last(true)
+                .required(false),
+        )
+        .get_matches();
+
+    let addr = matches.value_of("addr").expect("no AS addr input");
+    let tee_name = matches.value_of("tee").expect("no TEE input");
+    let tee = to_grpc_tee(tee_name)?;
+    let attester = match matches.values_of("attester") {
+        Some(command) => Attester::Command(command.map(str::to_string).collect()),
+        None if tee_name == "sample" => Attester::Sample,
+        None => bail!("An attester command is needed for the {tee_name} TEE"),
+    };
+    let outputs = Outputs {
+        token_path: PathBuf::from(matches.value_of("token-path").expect("no token path input")),
+        secrets_dir: matches.value_of("secrets-dir").map(PathBuf::from),
+    };
+
+    let tee_key = TeeKey::generate()?;
+    if matches.is_present("once") {
+        attest(addr, tee, &attester, &tee_key, None, &outputs).await?;
+        return Ok(());
+    }
+
+    let mut previous_token: Option<(String, Token)> = None;
+    loop {
+        previous_token = previous_token.filter(|(_, token)| !token.expired());
+        let wait = match attest(
+            addr,
+            tee,
+            &attester,
+            &tee_key,
+            previous_token.as_ref().map(|(raw, _)| raw.as_str()),
+            &outputs,
+        )
+        .await
+        {
+            Result::Ok((raw, token)) => {
+                let wait = token.refresh_after().max(RETRY_SECS);
+                previous_token = Some((raw, token));
+                wait
+            }
+            Err(e) => {
+                error!("Attestation failed: {e:#}");
+                RETRY_SECS
+            }
+        };
+        tokio::time::sleep(Duration::from_secs(wait)).await;
+    }
+}
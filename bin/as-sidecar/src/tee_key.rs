@@ -0,0 +1,140 @@
+//! The TEE key pair of the sidecar. Its public key is bound to the evidence
+//! and the token, and the secrets released with the token are encrypted to
+//! it.
+
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use anyhow::*;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use kbs_types::TeePubKey;
+use rsa::traits::PublicKeyParts;
+use rsa::{Oaep, RsaPrivateKey};
+use serde_json::Value;
+use sha2::{Digest, Sha256, Sha384};
+
+const KEY_BITS: usize = 2048;
+
+/// Key encryption algorithm of the secrets.
+const KEY_ALG: &str = "RSA-OAEP-256";
+
+pub struct TeeKey {
+    private_key: RsaPrivateKey,
+}
+
+impl TeeKey {
+    /// Generate a new key pair, which never leaves the memory of the
+    /// sidecar.
+    pub fn generate() -> Result<Self> {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), KEY_BITS)
+            .context("Cannot generate the TEE key")?;
+        Ok(Self { private_key })
+    }
+
+    pub fn public_key(&self) -> TeePubKey {
+        TeePubKey {
+            kty: "RSA".to_string(),
+            alg: KEY_ALG.to_string(),
+            k_mod: URL_SAFE_NO_PAD.encode(self.private_key.n().to_bytes_be()),
+            k_exp: URL_SAFE_NO_PAD.encode(self.private_key.e().to_bytes_be()),
+        }
+    }
+
+    /// `SHA384(nonce || pubkey)`, what the evidence must bind in its report
+    /// data.
+    pub fn report_data(&self, nonce: &str) -> Vec<u8> {
+        let public_key = self.public_key();
+        let mut hasher = Sha384::new();
+        hasher.update(nonce);
+        hasher.update(&public_key.k_mod);
+        hasher.update(&public_key.k_exp);
+        hasher.finalize().to_vec()
+    }
+
+    /// Decrypt a secret released with the token, a compact JWE encrypted
+    /// to the public key.
+    pub fn open(&self, jwe: &str) -> Result<Vec<u8>> {
+        let parts: Vec<_> = jwe.split('.').collect();
+        let [header, encrypted_key, iv, ciphertext, tag] = parts[..] else {
+            bail!("Malformed JWE");
+        };
+        let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).context("Malformed JWE");
+        let protected: Value = serde_json::from_slice(&decode(header)?)?;
+        if protected["alg"] != KEY_ALG || protected["enc"] != "A256GCM" {
+            bail!(
+                "Unsupported JWE algorithms {} and {}",
+                protected["alg"],
+                protected["enc"]
+            );
+        }
+
+        let cek = self
+            .private_key
+            .decrypt(Oaep::new::<Sha256>(), &decode(encrypted_key)?)
+            .map_err(|e| anyhow!("Key decryption failed: {e}"))?;
+        let mut ciphertext = decode(ciphertext)?;
+        ciphertext.extend(decode(tag)?);
+        Aes256Gcm::new_from_slice(&cek)
+            .map_err(|e| anyhow!("{e}"))?
+            .decrypt(
+                Nonce::from_slice(&decode(iv)?),
+                Payload {
+                    msg: &ciphertext,
+                    aad: header.as_bytes(),
+                },
+            )
+            .map_err(|e| anyhow!("Secret decryption failed: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+    use serde_json::json;
+
+    /// Encrypt `secret` to `key` like the post-verification hooks of the AS.
+    fn seal(secret: &[u8], key: &TeeKey) -> String {
+        let mut rng = rand::thread_rng();
+        let mut cek = [0u8; 32];
+        rng.fill_bytes(&mut cek);
+        let iv = [7u8; 12];
+        let encrypted_key = key
+            .private_key
+            .to_public_key()
+            .encrypt(&mut rng, Oaep::new::<Sha256>(), &cek)
+            .unwrap();
+        let header = URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&json!({ "alg": KEY_ALG, "enc": "A256GCM" })).unwrap());
+        let mut ciphertext = Aes256Gcm::new_from_slice(&cek)
+            .unwrap()
+            .encrypt(
+                Nonce::from_slice(&iv),
+                Payload {
+                    msg: secret,
+                    aad: header.as_bytes(),
+                },
+            )
+            .unwrap();
+        let tag = ciphertext.split_off(ciphertext.len() - 16);
+        [
+            header,
+            URL_SAFE_NO_PAD.encode(encrypted_key),
+            URL_SAFE_NO_PAD.encode(iv),
+            URL_SAFE_NO_PAD.encode(ciphertext),
+            URL_SAFE_NO_PAD.encode(tag),
+        ]
+        .join(".")
+    }
+
+    #[test]
+    fn test_open() {
+        let key = TeeKey::generate().unwrap();
+        let jwe = seal(b"disk key", &key);
+        assert_eq!(key.open(&jwe).unwrap(), b"disk key");
+
+        let other = TeeKey::generate().unwrap();
+        assert!(other.open(&jwe).is_err());
+        assert!(key.open("not.a.jwe").is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Validation of the attestation token, before it is handed to the
+//! workload.
+
+use anyhow::*;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use kbs_types::TeePubKey;
+use rsa::pkcs1v15::{Signature, VerifyingKey};
+use rsa::signature::Verifier;
+use rsa::{BigUint, RsaPublicKey};
+use serde_json::Value;
+use sha2::Sha384;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Algorithm of the tokens of the AS.
+const TOKEN_ALG: &str = "RS384";
+
+/// A token signed by the AS and bound to the TEE key.
+#[derive(Debug)]
+pub struct Token {
+    pub claims: Value,
+    /// Not before, in seconds since the epoch.
+    pub nbf: u64,
+    /// Expiry, in seconds since the epoch.
+    pub exp: u64,
+}
+
+impl Token {
+    /// Seconds after which the token is refreshed: four fifths of its
+    /// lifetime, so that it is replaced before it expires.
+    pub fn refresh_after(&self) -> u64 {
+        self.exp.saturating_sub(self.nbf) * 4 / 5
+    }
+
+    /// Whether the token has expired, and so the AS no longer chains a
+    /// re-attestation to it.
+    pub fn expired(&self) -> bool {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(true, |now| now.as_secs() >= self.exp)
+    }
+}
+
+/// Check that `token` is signed by a key of `jwks`, the signing keys of the
+/// AS, is valid now, and is bound to `tee_pubkey`.
+pub fn validate(token: &str, jwks: &str, tee_pubkey: &TeePubKey) -> Result<Token> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    validate_at(token, jwks, tee_pubkey, now)
+}
+
+fn validate_at(token: &str, jwks: &str, tee_pubkey: &TeePubKey, now: u64) -> Result<Token> {
+    let (payload, signature) = token
+        .rsplit_once('.')
+        .ok_or_else(|| anyhow!("Malformed token"))?;
+    let (header, claims) = payload
+        .split_once('.')
+        .ok_or_else(|| anyhow!("Malformed token"))?;
+
+    let header: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header)?)?;
+    if header["alg"] != TOKEN_ALG {
+        bail!("Unsupported token algorithm {}", header["alg"]);
+    }
+    let signature = Signature::try_from(URL_SAFE_NO_PAD.decode(signature)?.as_slice())?;
+    jwks_keys(jwks)?
+        .into_iter()
+        .map(VerifyingKey::<Sha384>::new)
+        .find_map(|key| key.verify(payload.as_bytes(), &signature).ok())
+        .ok_or_else(|| anyhow!("The token is not signed by the AS"))?;
+
+    let claims: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims)?)?;
+    let time = |name: &str| {
+        claims[name]
+            .as_u64()
+            .ok_or_else(|| anyhow!("The token has no `{name}`"))
+    };
+    let (nbf, exp) = (time("nbf")?, time("exp")?);
+    if now < nbf || now >= exp {
+        bail!("The token is not valid now");
+    }
+    if claims["tee-pubkey"] != serde_json::to_value(tee_pubkey)? {
+        bail!("The token is bound to another TEE key");
+    }
+
+    Ok(Token { claims, nbf, exp })
+}
+
+/// The RSA keys of a JWKS.
+fn jwks_keys(jwks: &str) -> Result<Vec<RsaPublicKey>> {
+    let jwks: Value = serde_json::from_str(jwks).context("Malformed JWKS")?;
+    let keys = jwks["keys"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Malformed JWKS: no keys"))?;
+    keys.iter()
+        .filter(|key| key["kty"] == "RSA")
+        .map(|key| {
+            let component = |name: &str| -> Result<BigUint> {
+                let value = key[name]
+                    .as_str()
+                    .ok_or_else(|| anyhow!("Malformed JWK: no `{name}`"))?;
+                Ok(BigUint::from_bytes_be(&URL_SAFE_NO_PAD.decode(value)?))
+            };
+            Ok(RsaPublicKey::new(component("n")?, component("e")?)?)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tee_key::TeeKey;
+    use rsa::pkcs1v15::SigningKey;
+    use rsa::signature::{RandomizedSigner, SignatureEncoding};
+    use rsa::traits::PublicKeyParts;
+    use rsa::RsaPrivateKey;
+    use serde_json::json;
+
+    fn sign(key: &RsaPrivateKey, claims: Value) -> String {
+        let encode = |value: Value| URL_SAFE_NO_PAD.encode(value.to_string());
+        let payload = format!(
+            "{}.{}",
+            encode(json!({ "typ": "JWT", "alg": TOKEN_ALG })),
+            encode(claims)
+        );
+        let signature = SigningKey::<Sha384>::new(key.clone())
+            .sign_with_rng(&mut rand::thread_rng(), payload.as_bytes());
+        format!("{payload}.{}", URL_SAFE_NO_PAD.encode(signature.to_bytes()))
+    }
+
+    #[test]
+    fn test_validate() {
+        let as_key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let jwks = json!({ "keys": [{
+            "kty": "RSA",
+            "n": URL_SAFE_NO_PAD.encode(as_key.n().to_bytes_be()),
+            "e": URL_SAFE_NO_PAD.encode(as_key.e().to_bytes_be()),
+        }] })
+        .to_string();
+        let tee_pubkey = TeeKey::generate().unwrap().public_key();
+        let token = sign(
+            &as_key,
+            json!({ "nbf": 1000, "exp": 1600, "tee-pubkey": tee_pubkey }),
+        );
+
+        let validated = validate_at(&token, &jwks, &tee_pubkey, 1200).unwrap();
+        assert_eq!(validated.exp, 1600);
+        assert_eq!(validated.refresh_after(), 480);
+
+        // Expired.
+        assert!(validate_at(&token, &jwks, &tee_pubkey, 1600).is_err());
+        // Bound to another key.
+        let other_pubkey = TeeKey::generate().unwrap().public_key();
+        assert!(validate_at(&token, &jwks, &other_pubkey, 1200).is_err());
+        // Not signed by the AS.
+        let other_key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let forged = sign(
+            &other_key,
+            json!({ "nbf": 1000, "exp": 1600, "tee-pubkey": tee_pubkey }),
+        );
+        assert!(validate_at(&forged, &jwks, &tee_pubkey, 1200).is_err());
+    }
+}
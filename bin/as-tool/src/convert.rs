@@ -0,0 +1,66 @@
+//! Conversion of captured evidence between raw binaries, hex dumps and claims
+
+use anyhow::*;
+use attestation_service::verifier::convert::{self, EvidenceKind};
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// Read `input`, or stdin if `-`.
+fn read(input: &str) -> Result<Vec<u8>> {
+    match input {
+        "-" => {
+            let mut data = Vec::new();
+            std::io::stdin().read_to_end(&mut data)?;
+            Ok(data)
+        }
+        _ => std::fs::read(input).with_context(|| format!("read {input}")),
+    }
+}
+
+fn text(data: Vec<u8>) -> Result<String> {
+    String::from_utf8(data).context("input is not UTF-8")
+}
+
+/// Convert the evidence of `tee` in `input` from the format `from`, `raw`,
+/// `hex` or `claims`, to the format `to`, `raw`, `hex`, `claims` or
+/// `annotated`, and write it to `output`, or to stdout.
+pub fn convert(tee: &str, from: &str, to: &str, input: &str, output: Option<&str>) -> Result<()> {
+    let kind = EvidenceKind::from_str(tee).map_err(|_| anyhow!("Unknown TEE {tee}"))?;
+    let data = read(input)?;
+    let converted = match (from, to) {
+        ("claims", "annotated") => {
+            let claims = serde_json::from_slice(&data).context("parse claims")?;
+            convert::annotate_claims(kind, &claims)?.into_bytes()
+        }
+        ("claims", _) => {
+            let claims = serde_json::from_slice(&data).context("parse claims")?;
+            let raw = convert::from_claims(kind, &claims)?;
+            from_raw(kind, raw, to)?
+        }
+        ("hex", _) => from_raw(kind, convert::from_hexdump(&text(data)?)?, to)?,
+        ("raw", _) => from_raw(kind, data, to)?,
+        _ => bail!("Unknown input format {from}"),
+    };
+    match output {
+        Some(output) => {
+            std::fs::write(output, converted).with_context(|| format!("write {output}"))?
+        }
+        None => std::io::stdout().write_all(&converted)?,
+    }
+    Ok(())
+}
+
+/// Convert the `raw` evidence of `kind` to the format `to`.
+fn from_raw(kind: EvidenceKind, raw: Vec<u8>, to: &str) -> Result<Vec<u8>> {
+    Ok(match to {
+        "raw" => raw,
+        "hex" => convert::hexdump(&raw).into_bytes(),
+        "claims" => {
+            let mut claims = serde_json::to_vec_pretty(&convert::claims(kind, &raw)?)?;
+            claims.push(b'\n');
+            claims
+        }
+        "annotated" => convert::annotate(kind, &raw)?.into_bytes(),
+        _ => bail!("Unknown output format {to}"),
+    })
+}
@@ -0,0 +1,26 @@
+//! Export of the attestation history of the AS
+
+use anyhow::*;
+use attestation_service::history::{self, ExportFormat};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Export the attestation history of the AS of `work_dir` in `format`, with
+/// the selected `columns`, to `output`, or to stdout. Return the number of
+/// exported attestations.
+pub fn export_history(
+    work_dir: &Path,
+    format: &str,
+    columns: &[String],
+    output: Option<&str>,
+) -> Result<usize> {
+    let format = ExportFormat::from_str(format)?;
+    let records = history::read_history(work_dir).context("read attestation history")?;
+    let data = history::export(&records, format, columns)?;
+    match output {
+        Some(output) => std::fs::write(output, data).with_context(|| format!("write {output}"))?,
+        None => std::io::stdout().write_all(&data)?,
+    }
+    Ok(records.len())
+}
@@ -0,0 +1,127 @@
+//! Precomputation of the launch measurements of SNP guests and TDs
+
+use anyhow::*;
+use attestation_service::rvps::ReferenceValue;
+use attestation_service::verifier::snp_launch::{self, GuestConfig};
+use attestation_service::verifier::tdx_launch::{self, TdConfig};
+use serde::Serialize;
+
+use crate::migrate;
+
+/// The launch measurement of a guest, as in its attestation reports.
+#[derive(Serialize, Debug)]
+pub struct LaunchMeasurement {
+    /// The value of the `snp.measurement` claim.
+    pub measurement: String,
+    pub measurement_hex: String,
+    /// The store the measurement was registered to as a reference value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registered: Option<String>,
+}
+
+/// The guest images and vCPUs to measure, as given on the command line.
+pub struct GuestImages<'a> {
+    pub ovmf: &'a str,
+    pub kernel: Option<&'a str>,
+    pub initrd: Option<&'a str>,
+    pub append: Option<&'a str>,
+    pub vcpus: u32,
+    pub vcpu_sig: u32,
+    pub guest_features: u64,
+}
+
+/// The MRTD of a TD, and the digest of its Configuration Firmware Volume.
+#[derive(Serialize, Debug)]
+pub struct TdMeasurements {
+    /// The value of the `tdx.quote.body.mr_td` claim.
+    pub mr_td: String,
+    /// The value of the `tdx.ccel.cfv` claim, for TDVF.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cfv: Option<String>,
+    /// The store the measurements were registered to as reference values.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registered: Option<String>,
+}
+
+fn read(path: &str) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("read {path}"))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Register `rvs` in the store `register`, given as `<type>:<path>`.
+fn register_reference_values(register: &str, rvs: Vec<ReferenceValue>) -> Result<()> {
+    let (store_type, path) = migrate::parse_store(register)?;
+    let mut store = store_type
+        .open(&path)
+        .context("open reference value store")?;
+    for rv in rvs {
+        store
+            .set(rv.name().to_string(), rv)
+            .context("register reference value")?;
+    }
+    Ok(())
+}
+
+/// Compute the launch measurement of `images`, and register it as the
+/// reference value of the `snp.measurement` claim in the store `register`,
+/// given as `<type>:<path>`.
+pub fn snp_launch_digest(images: GuestImages, register: Option<&str>) -> Result<LaunchMeasurement> {
+    let guest = GuestConfig {
+        ovmf: read(images.ovmf)?,
+        kernel: images.kernel.map(read).transpose()?,
+        initrd: images.initrd.map(read).transpose()?,
+        append: images.append.map(str::to_string),
+        vcpus: images.vcpus,
+        vcpu_sig: images.vcpu_sig,
+        guest_features: images.guest_features,
+    };
+    let measurement = snp_launch::launch_measurement(&guest)?;
+
+    if let Some(register) = register {
+        register_reference_values(register, vec![snp_launch::reference_value(&measurement)?])?;
+    }
+
+    Ok(LaunchMeasurement {
+        measurement: snp_launch::measurement_claim(&measurement),
+        measurement_hex: hex(&measurement),
+        registered: register.map(str::to_string),
+    })
+}
+
+/// Compute the MRTD of the TD of the `firmware` image, with the td-shim
+/// `payload`, and register it as the reference value of the
+/// `tdx.quote.body.mr_td` claim in the store `register`, given as
+/// `<type>:<path>`, with the digest of the CFV of TDVF.
+pub fn tdx_launch_digest(
+    firmware: &str,
+    payload: Option<&str>,
+    register: Option<&str>,
+) -> Result<TdMeasurements> {
+    let td = TdConfig {
+        firmware: read(firmware)?,
+        payload: payload.map(read).transpose()?,
+    };
+    let measurements = tdx_launch::measurements(&td)?;
+
+    if let Some(register) = register {
+        register_reference_values(register, tdx_launch::reference_values(&measurements)?)?;
+    }
+
+    Ok(TdMeasurements {
+        mr_td: hex(&measurements.mr_td),
+        cfv: measurements.cfv.as_ref().map(|cfv| hex(cfv)),
+        registered: register.map(str::to_string),
+    })
+}
+
+/// Parse a number given in decimal or in hex with a `0x` prefix.
+pub fn parse_number(value: &str) -> Result<u64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .with_context(|| format!("{value} is not a number"))
+}
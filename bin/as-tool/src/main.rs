@@ -0,0 +1,484 @@
+//! Offline tooling for the Attestation Service
+
+use anyhow::*;
+use attestation_service::verifier::snp_launch;
+use clap::{App, Arg, Command};
+use log::info;
+use shadow_rs::shadow;
+use std::path::Path;
+
+shadow!(build);
+
+mod convert;
+mod export;
+mod launch;
+mod migrate;
+mod policy;
+mod simulate;
+
+/// Default policy engine used by the offline tools
+const DEFAULT_POLICY_ENGINE: &str = "opa";
+
+/// Default vCPU type of the measured SNP guests
+const DEFAULT_VCPU_TYPE: &str = "EPYC-v4";
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let version = format!(
+        "\nv{}\ncommit: {}\nbuildtime: {}",
+        build::PKG_VERSION,
+        build::COMMIT_HASH,
+        build::BUILD_TIME
+    );
+
+    let matches = App::new("as-tool")
+        .version(version.as_str())
+        .long_version(version.as_str())
+        .author("Confidential-Containers Team")
+        .subcommand(
+            Command::new("test-policy")
+                .about("Run a policy against a suite of claims fixtures")
+                .arg(
+                    Arg::with_name("policy")
+                        .long("policy")
+                        .value_name("policy")
+                        .help("The path to the policy file")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("fixtures")
+                        .long("fixtures")
+                        .value_name("fixtures")
+                        .help("The path to the JSON file holding the list of claims fixtures")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("parameters")
+                        .long("parameters")
+                        .value_name("parameters")
+                        .help("The path to the JSON file declaring the parameters of the policy")
+                        .takes_value(true)
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("policy-engine")
+                        .long("policy-engine")
+                        .value_name("policy-engine")
+                        .help("The policy engine to evaluate the policy with")
+                        .takes_value(true)
+                        .default_value(DEFAULT_POLICY_ENGINE)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("migrate")
+                .about("Move the policies and reference values of the AS between storage backends")
+                .arg(
+                    Arg::with_name("from-store")
+                        .long("from-store")
+                        .value_name("type:path")
+                        .help("The reference value store to migrate from, e.g. LocalFs:/var/lib/rvps")
+                        .takes_value(true)
+                        .requires("to-store"),
+                )
+                .arg(
+                    Arg::with_name("to-store")
+                        .long("to-store")
+                        .value_name("type:path")
+                        .help("The reference value store to migrate to, e.g. LocalJson:/var/lib/rvps.json")
+                        .takes_value(true)
+                        .requires("from-store"),
+                )
+                .arg(
+                    Arg::with_name("from-work-dir")
+                        .long("from-work-dir")
+                        .value_name("from-work-dir")
+                        .help("The AS work dir to migrate the policies from")
+                        .takes_value(true)
+                        .requires("to-work-dir"),
+                )
+                .arg(
+                    Arg::with_name("to-work-dir")
+                        .long("to-work-dir")
+                        .value_name("to-work-dir")
+                        .help("The AS work dir to migrate the policies to")
+                        .takes_value(true)
+                        .requires("from-work-dir"),
+                )
+                .arg(
+                    Arg::with_name("policy-engine")
+                        .long("policy-engine")
+                        .value_name("policy-engine")
+                        .help("The policy engine of the work dirs")
+                        .takes_value(true)
+                        .default_value(DEFAULT_POLICY_ENGINE)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("simulate")
+                .about("Replay past attestations against a proposed policy and reference values, and report the decisions that change")
+                .arg(
+                    Arg::with_name("claims")
+                        .long("claims")
+                        .value_name("claims")
+                        .help("The path to the JSON lines file of past attestations, each with its tee and its claims or token")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("policy")
+                        .long("policy")
+                        .value_name("policy")
+                        .help("The path to the proposed policy file")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("reference-values")
+                        .long("reference-values")
+                        .value_name("type:path")
+                        .help("The proposed reference value store, e.g. LocalJson:/tmp/rvps.json")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("current-policy")
+                        .long("current-policy")
+                        .value_name("current-policy")
+                        .help("The path to the current policy file, for past attestations without a recorded decision")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("current-reference-values")
+                        .long("current-reference-values")
+                        .value_name("type:path")
+                        .help("The current reference value store, e.g. LocalFs:/var/lib/rvps")
+                        .takes_value(true)
+                        .requires("current-policy"),
+                )
+                .arg(
+                    Arg::with_name("policy-engine")
+                        .long("policy-engine")
+                        .value_name("policy-engine")
+                        .help("The policy engine to evaluate the policies with")
+                        .takes_value(true)
+                        .default_value(DEFAULT_POLICY_ENGINE)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("snp-launch-digest")
+                .about("Compute the expected launch measurement of an SNP guest, and optionally register it as a reference value")
+                .arg(
+                    Arg::with_name("ovmf")
+                        .long("ovmf")
+                        .value_name("ovmf")
+                        .help("The path to the OVMF binary of the guest")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("kernel")
+                        .long("kernel")
+                        .value_name("kernel")
+                        .help("The path to the kernel of a direct boot")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("initrd")
+                        .long("initrd")
+                        .value_name("initrd")
+                        .help("The path to the initrd of a direct boot")
+                        .takes_value(true)
+                        .requires("kernel"),
+                )
+                .arg(
+                    Arg::with_name("append")
+                        .long("append")
+                        .value_name("append")
+                        .help("The kernel command line of a direct boot")
+                        .takes_value(true)
+                        .requires("kernel"),
+                )
+                .arg(
+                    Arg::with_name("vcpus")
+                        .long("vcpus")
+                        .value_name("vcpus")
+                        .help("The number of vCPUs of the guest")
+                        .takes_value(true)
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::with_name("vcpu-type")
+                        .long("vcpu-type")
+                        .value_name("vcpu-type")
+                        .help("The QEMU vCPU type of the guest, e.g. EPYC-Milan")
+                        .takes_value(true)
+                        .default_value(DEFAULT_VCPU_TYPE)
+                        .conflicts_with("vcpu-sig"),
+                )
+                .arg(
+                    Arg::with_name("vcpu-sig")
+                        .long("vcpu-sig")
+                        .value_name("vcpu-sig")
+                        .help("The CPUID signature of the vCPUs, instead of their type")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("guest-features")
+                        .long("guest-features")
+                        .value_name("guest-features")
+                        .help("The SEV features of the guest VMSA")
+                        .takes_value(true)
+                        .default_value("0x1"),
+                )
+                .arg(
+                    Arg::with_name("register")
+                        .long("register")
+                        .value_name("type:path")
+                        .help("The reference value store to register the measurement to, e.g. LocalFs:/var/lib/rvps")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            Command::new("tdx-launch-digest")
+                .about("Compute the expected MRTD of a TD from its firmware image, and optionally register it as a reference value")
+                .arg(
+                    Arg::with_name("firmware")
+                        .long("firmware")
+                        .value_name("firmware")
+                        .help("The path to the TDVF or td-shim image of the TD")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("payload")
+                        .long("payload")
+                        .value_name("payload")
+                        .help("The path to the payload the VMM loads for td-shim")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("register")
+                        .long("register")
+                        .value_name("type:path")
+                        .help("The reference value store to register the measurements to, e.g. LocalFs:/var/lib/rvps")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            Command::new("export-history")
+                .about("Export the attestation history of the AS in columnar form, for analytics")
+                .arg(
+                    Arg::with_name("work-dir")
+                        .long("work-dir")
+                        .value_name("work-dir")
+                        .help("The work dir of the AS")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("format")
+                        .help("The export format, csv or parquet")
+                        .takes_value(true)
+                        .default_value("csv"),
+                )
+                .arg(
+                    Arg::with_name("columns")
+                        .long("columns")
+                        .value_name("columns")
+                        .help("Comma separated columns to export: id, timestamp, tee, decision or claim names, a name ending with * matching any claim with that prefix. Every column by default")
+                        .takes_value(true)
+                        .use_value_delimiter(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .value_name("output")
+                        .help("The path to the file to export to, instead of stdout")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            Command::new("convert")
+                .about("Convert captured evidence between raw binaries, hex dumps and claims, or annotate its fields")
+                .arg(
+                    Arg::with_name("tee")
+                        .long("tee")
+                        .value_name("tee")
+                        .help("The kind of evidence, tdx for a TD quote or snp for an SNP attestation report")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .value_name("from")
+                        .help("The input format: raw, hex for an xxd hex dump or plain hex, or claims as JSON")
+                        .takes_value(true)
+                        .default_value("raw"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .value_name("to")
+                        .help("The output format: raw, hex, claims, or annotated for a hex dump of the fields by name")
+                        .takes_value(true)
+                        .default_value("annotated"),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .value_name("input")
+                        .help("The path to the evidence to convert, - for stdin")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .value_name("output")
+                        .help("The path to the file to write to, instead of stdout")
+                        .takes_value(true),
+                ),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        Some(("test-policy", sub_cmd)) => {
+            let policy = sub_cmd.value_of("policy").expect("no policy input");
+            let fixtures = sub_cmd.value_of("fixtures").expect("no fixtures input");
+            let engine = sub_cmd
+                .value_of("policy-engine")
+                .expect("no policy engine input");
+            let parameters = sub_cmd.value_of("parameters");
+            let report = policy::test_policy(engine, policy, fixtures, parameters).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !report.success() {
+                bail!(
+                    "{} of {} fixtures failed",
+                    report.failed,
+                    report.results.len()
+                );
+            }
+            info!("All {} fixtures passed.", report.passed);
+            Ok(())
+        }
+        Some(("migrate", sub_cmd)) => {
+            let mut report = migrate::MigrationReport::default();
+            let stores = (sub_cmd.value_of("from-store"), sub_cmd.value_of("to-store"));
+            let work_dirs = (
+                sub_cmd.value_of("from-work-dir"),
+                sub_cmd.value_of("to-work-dir"),
+            );
+            if stores.0.is_none() && work_dirs.0.is_none() {
+                bail!("Nothing to migrate, give stores or work dirs");
+            }
+            if let (Some(from), Some(to)) = stores {
+                report.reference_values = migrate::migrate_reference_values(from, to)?;
+            }
+            if let (Some(from), Some(to)) = work_dirs {
+                let engine = sub_cmd
+                    .value_of("policy-engine")
+                    .expect("no policy engine input");
+                (report.policies, report.policy_data_versions) =
+                    migrate::migrate_policies(engine, Path::new(from), Path::new(to)).await?;
+            }
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            info!("Migration verified.");
+            Ok(())
+        }
+        Some(("simulate", sub_cmd)) => {
+            let claims = sub_cmd.value_of("claims").expect("no claims input");
+            let engine = sub_cmd
+                .value_of("policy-engine")
+                .expect("no policy engine input");
+            let proposed = simulate::Rollout {
+                policy: sub_cmd.value_of("policy").expect("no policy input"),
+                reference_values: sub_cmd.value_of("reference-values"),
+            };
+            let current = sub_cmd
+                .value_of("current-policy")
+                .map(|policy| simulate::Rollout {
+                    policy,
+                    reference_values: sub_cmd.value_of("current-reference-values"),
+                });
+            let report = simulate::simulate(engine, claims, current, proposed).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            info!(
+                "{} of {} attestations would flip from allow to deny, {} from deny to allow, {} could not be simulated.",
+                report.allow_to_deny, report.attestations, report.deny_to_allow, report.errors
+            );
+            Ok(())
+        }
+        Some(("snp-launch-digest", sub_cmd)) => {
+            let vcpu_sig = match sub_cmd.value_of("vcpu-sig") {
+                Some(sig) => u32::try_from(launch::parse_number(sig)?)?,
+                None => snp_launch::vcpu_sig(
+                    sub_cmd.value_of("vcpu-type").expect("no vcpu type input"),
+                )?,
+            };
+            let images = launch::GuestImages {
+                ovmf: sub_cmd.value_of("ovmf").expect("no ovmf input"),
+                kernel: sub_cmd.value_of("kernel"),
+                initrd: sub_cmd.value_of("initrd"),
+                append: sub_cmd.value_of("append"),
+                vcpus: sub_cmd
+                    .value_of("vcpus")
+                    .expect("no vcpus input")
+                    .parse()
+                    .context("parse vcpus")?,
+                vcpu_sig,
+                guest_features: launch::parse_number(
+                    sub_cmd
+                        .value_of("guest-features")
+                        .expect("no guest features input"),
+                )?,
+            };
+            let measurement = launch::snp_launch_digest(images, sub_cmd.value_of("register"))?;
+            println!("{}", serde_json::to_string_pretty(&measurement)?);
+            if let Some(store) = &measurement.registered {
+                info!("Launch measurement registered to {store}.");
+            }
+            Ok(())
+        }
+        Some(("tdx-launch-digest", sub_cmd)) => {
+            let measurements = launch::tdx_launch_digest(
+                sub_cmd.value_of("firmware").expect("no firmware input"),
+                sub_cmd.value_of("payload"),
+                sub_cmd.value_of("register"),
+            )?;
+            println!("{}", serde_json::to_string_pretty(&measurements)?);
+            if let Some(store) = &measurements.registered {
+                info!("Launch measurements registered to {store}.");
+            }
+            Ok(())
+        }
+        Some(("export-history", sub_cmd)) => {
+            let work_dir = sub_cmd.value_of("work-dir").expect("no work dir input");
+            let format = sub_cmd.value_of("format").expect("no format input");
+            let columns: Vec<String> = sub_cmd
+                .values_of("columns")
+                .map(|columns| columns.map(str::to_string).collect())
+                .unwrap_or_default();
+            let output = sub_cmd.value_of("output");
+            let exported = export::export_history(Path::new(work_dir), format, &columns, output)?;
+            info!("{exported} attestations exported.");
+            Ok(())
+        }
+        Some(("convert", sub_cmd)) => convert::convert(
+            sub_cmd.value_of("tee").expect("no tee input"),
+            sub_cmd.value_of("from").expect("no from input"),
+            sub_cmd.value_of("to").expect("no to input"),
+            sub_cmd.value_of("input").expect("no input"),
+            sub_cmd.value_of("output"),
+        ),
+        _ => bail!("error occurs for subcommand"),
+    }
+}
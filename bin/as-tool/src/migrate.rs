@@ -0,0 +1,97 @@
+//! Migration of the state of the AS between storage backends
+
+use anyhow::*;
+use attestation_service::policy_engine::PolicyEngineType;
+use attestation_service::rvps::store::{Store, StoreType};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// What was migrated, once read back from the destination.
+#[derive(Serialize, Debug, Default)]
+pub struct MigrationReport {
+    pub reference_values: usize,
+    pub policies: usize,
+    pub policy_data_versions: usize,
+}
+
+/// Parse a store given as `<type>:<path>`, e.g. `LocalFs:/var/lib/rvps`.
+pub fn parse_store(store: &str) -> Result<(StoreType, PathBuf)> {
+    let (store_type, path) = store
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Store {store} is not given as <type>:<path>"))?;
+    let store_type = StoreType::try_from(store_type)
+        .map_err(|_| anyhow!("Store type {store_type} is not supported"))?;
+    Ok((store_type, PathBuf::from(path)))
+}
+
+/// Copy the reference values of the store `from` into the store `to`, and
+/// check that they read back the same from `to`.
+pub fn migrate_reference_values(from: &str, to: &str) -> Result<usize> {
+    let (from_type, from_path) = parse_store(from)?;
+    let (to_type, to_path) = parse_store(to)?;
+    if from_path == to_path {
+        bail!("The source and destination stores are the same");
+    }
+    let source = from_type.open(&from_path).context("open source store")?;
+    let mut destination = to_type.open(&to_path).context("open destination store")?;
+    copy_reference_values(source.as_ref(), destination.as_mut())
+}
+
+fn copy_reference_values(
+    source: &(dyn Store + Send + Sync),
+    destination: &mut (dyn Store + Send + Sync),
+) -> Result<usize> {
+    let rvs = source.list().context("list source reference values")?;
+    for rv in &rvs {
+        destination
+            .set(rv.name().to_string(), rv.clone())
+            .with_context(|| format!("write reference value {}", rv.name()))?;
+    }
+    for rv in &rvs {
+        if destination.get(rv.name())?.as_ref() != Some(rv) {
+            bail!(
+                "Reference value {} differs in the destination store",
+                rv.name()
+            );
+        }
+    }
+    Ok(rvs.len())
+}
+
+/// Copy the policies and every version of the policy data documents of the
+/// policy engine work dir `from` into the work dir `to`, and check that
+/// they read back the same from `to`.
+pub async fn migrate_policies(engine: &str, from: &Path, to: &Path) -> Result<(usize, usize)> {
+    if from == to {
+        bail!("The source and destination work dirs are the same");
+    }
+    let engine_type = PolicyEngineType::from_str(engine)
+        .map_err(|_| anyhow!("Policy Engine {engine} is not supported"))?;
+    let source = engine_type
+        .to_policy_engine(from)
+        .context("open source policy engine")?;
+    let mut destination = engine_type
+        .to_policy_engine(to)
+        .context("open destination policy engine")?;
+
+    let state = source.export_state().await?;
+    destination.import_state(state.clone()).await?;
+
+    let migrated = destination.export_state().await?;
+    for (id, policy) in &state.policies {
+        if migrated.policies.get(id) != Some(policy) {
+            bail!("Policy {id} differs in the destination work dir");
+        }
+    }
+    for data in &state.policy_data {
+        if !migrated.policy_data.contains(data) {
+            bail!(
+                "Version {} of policy data {} differs in the destination work dir",
+                data.version,
+                data.name
+            );
+        }
+    }
+    Ok((state.policies.len(), state.policy_data.len()))
+}
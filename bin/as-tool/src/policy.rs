@@ -0,0 +1,49 @@
+//! Policy related offline tools
+
+use anyhow::*;
+use as_types::{PolicyParameter, PolicyTestFixture, PolicyTestReport, TestPolicyInput};
+use attestation_service::policy_engine::PolicyEngineType;
+use base64::Engine;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Run the policy stored at `policy_path` against the fixtures stored at
+/// `fixtures_path`, using a throw-away policy engine instance. The policy
+/// declares the parameters stored at `parameters_path`, if any.
+pub async fn test_policy(
+    engine: &str,
+    policy_path: &str,
+    fixtures_path: &str,
+    parameters_path: Option<&str>,
+) -> Result<PolicyTestReport> {
+    let policy = std::fs::read(policy_path).context("read policy")?;
+    let fixtures = std::fs::read(fixtures_path).context("read fixtures")?;
+    let fixtures: Vec<PolicyTestFixture> =
+        serde_json::from_slice(&fixtures).context("deserialize fixtures")?;
+    let parameters: Vec<PolicyParameter> = match parameters_path {
+        Some(path) => {
+            let parameters = std::fs::read(path).context("read parameters")?;
+            serde_json::from_slice(&parameters).context("deserialize parameters")?
+        }
+        None => Vec::new(),
+    };
+
+    let policy_type = Path::new(policy_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .ok_or_else(|| anyhow!("Cannot get policy type from {policy_path}"))?;
+
+    let work_dir = tempfile::tempdir().context("create policy engine work dir")?;
+    let policy_engine = PolicyEngineType::from_str(engine)
+        .map_err(|_| anyhow!("Policy Engine {engine} is not supported"))?
+        .to_policy_engine(work_dir.path())?;
+
+    let input = TestPolicyInput {
+        r#type: policy_type.to_string(),
+        policy: base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(policy),
+        fixtures,
+        parameters,
+    };
+
+    policy_engine.test_policy(input).await
+}
@@ -0,0 +1,274 @@
+//! Simulation of a policy and reference value rollout over past attestations
+
+use anyhow::*;
+use as_types::{PolicyDecision, PolicyViolation, SetPolicyInput};
+use attestation_service::policy_engine::{
+    ParameterValues, PolicyDenied, PolicyEngine, PolicyEngineType, PolicyMismatch,
+};
+use attestation_service::rvps::{Core, RVPSAPI};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::migrate::parse_store;
+
+const CURRENT_POLICY_ID: &str = "current";
+const PROPOSED_POLICY_ID: &str = "proposed";
+
+/// A past attestation, one JSON object per line of the claims file. The
+/// claims are the `tcb-status` of its token, given either as they are or
+/// with the token itself. Attestations with a token were allowed, unless
+/// `decision` says otherwise.
+#[derive(Deserialize, Debug)]
+struct Record {
+    #[serde(default)]
+    id: Option<String>,
+    tee: String,
+    #[serde(default)]
+    claims: Option<Value>,
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    decision: Option<PolicyDecision>,
+}
+
+impl Record {
+    fn claims(&self) -> Result<Value> {
+        if let Some(claims) = &self.claims {
+            return Ok(claims.clone());
+        }
+        let token = self
+            .token
+            .as_ref()
+            .ok_or_else(|| anyhow!("neither claims nor token"))?;
+        let payload = token
+            .split('.')
+            .nth(1)
+            .ok_or_else(|| anyhow!("malformed token"))?;
+        let payload: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload)?)
+            .context("malformed token claims")?;
+        match payload.get("tcb-status") {
+            Some(claims) => Ok(claims.clone()),
+            None => bail!("token without tcb-status"),
+        }
+    }
+
+    fn recorded_decision(&self) -> Option<PolicyDecision> {
+        self.decision
+            .or_else(|| self.token.as_ref().map(|_| PolicyDecision::Allow))
+    }
+}
+
+/// A past attestation whose decision changes, or that could not be
+/// simulated.
+#[derive(Serialize, Debug)]
+pub struct Change {
+    /// The `id` of the attestation, or its line in the claims file.
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<PolicyDecision>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proposed: Option<PolicyDecision>,
+    /// Why the proposed policy denies the attestation.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<PolicyViolation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct SimulationReport {
+    pub attestations: usize,
+    pub unchanged: usize,
+    pub allow_to_deny: usize,
+    pub deny_to_allow: usize,
+    pub errors: usize,
+    pub changes: Vec<Change>,
+}
+
+/// A policy and the reference values it is evaluated with.
+pub struct Rollout<'a> {
+    pub policy: &'a str,
+    /// A reference value store given as `<type>:<path>`, none if `None`.
+    pub reference_values: Option<&'a str>,
+}
+
+/// The reference values of an evaluation.
+struct ReferenceValues(Option<Core>);
+
+impl ReferenceValues {
+    fn open(store: Option<&str>) -> Result<Self> {
+        let Some(store) = store else {
+            return Ok(Self(None));
+        };
+        let (store_type, path) = parse_store(store)?;
+        let store = store_type
+            .open(&path)
+            .with_context(|| format!("open reference value store {store}"))?;
+        Ok(Self(Some(Core::new(store))))
+    }
+
+    /// The reference data of the claims, as the AS computes it.
+    async fn reference_data(&self, claims: &Value) -> Result<HashMap<String, Vec<String>>> {
+        let mut data = HashMap::new();
+        let (Some(rvps), Some(claims)) = (&self.0, claims.as_object()) else {
+            return Ok(data);
+        };
+        for (name, claim) in claims {
+            let digest = rvps.get_digests(name).await?.unwrap_or_default();
+            data.insert(name.clone(), digest.reference_data(claim)?);
+        }
+        Ok(data)
+    }
+}
+
+async fn set_policy(
+    engine: &mut (dyn PolicyEngine + Send + Sync),
+    policy_id: &str,
+    policy_path: &str,
+) -> Result<()> {
+    let policy = std::fs::read(policy_path).with_context(|| format!("read {policy_path}"))?;
+    let policy_type = Path::new(policy_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .ok_or_else(|| anyhow!("Cannot get policy type from {policy_path}"))?;
+    engine
+        .set_policy(SetPolicyInput {
+            r#type: policy_type.to_string(),
+            policy_id: policy_id.to_string(),
+            policy: URL_SAFE_NO_PAD.encode(policy),
+            selector: None,
+            parameters: Vec::new(),
+            signature: None,
+        })
+        .await
+        .with_context(|| format!("set policy {policy_path}"))
+}
+
+/// The decision of the policy `policy_id`, with the violations of a denial.
+/// Evidence the policy does not apply to is denied, as by the AS.
+async fn decide(
+    engine: &(dyn PolicyEngine + Send + Sync),
+    policy_id: &str,
+    reference_values: &ReferenceValues,
+    tee: &str,
+    claims: &Value,
+) -> Result<(PolicyDecision, Vec<PolicyViolation>)> {
+    let reference_data = reference_values.reference_data(claims).await?;
+    let evaluation = engine
+        .evaluate(
+            tee,
+            reference_data,
+            claims.to_string(),
+            Some(policy_id.to_string()),
+            &ParameterValues::default(),
+        )
+        .await;
+    match evaluation {
+        Result::Ok(_) => Ok((PolicyDecision::Allow, Vec::new())),
+        Err(e) => match e.downcast::<PolicyDenied>() {
+            Result::Ok(denied) => Ok((PolicyDecision::Deny, denied.violations)),
+            Err(e) if e.is::<PolicyMismatch>() => Ok((PolicyDecision::Deny, Vec::new())),
+            Err(e) => Err(e),
+        },
+    }
+}
+
+/// Replay the past attestations of `claims_path` against the `proposed`
+/// policy and reference values, and report those whose decision changes
+/// from the recorded one, or from that of the `current` policy and
+/// reference values when there is none.
+pub async fn simulate(
+    engine: &str,
+    claims_path: &str,
+    current: Option<Rollout<'_>>,
+    proposed: Rollout<'_>,
+) -> Result<SimulationReport> {
+    let records = std::fs::read_to_string(claims_path).context("read claims")?;
+
+    let work_dir = tempfile::tempdir().context("create policy engine work dir")?;
+    let mut policy_engine = PolicyEngineType::from_str(engine)
+        .map_err(|_| anyhow!("Policy Engine {engine} is not supported"))?
+        .to_policy_engine(work_dir.path())?;
+    set_policy(policy_engine.as_mut(), PROPOSED_POLICY_ID, proposed.policy).await?;
+    let proposed_rvs = ReferenceValues::open(proposed.reference_values)?;
+    let current_rvs = match &current {
+        Some(current) => {
+            set_policy(policy_engine.as_mut(), CURRENT_POLICY_ID, current.policy).await?;
+            Some(ReferenceValues::open(current.reference_values)?)
+        }
+        None => None,
+    };
+
+    let mut report = SimulationReport::default();
+    for (index, line) in records.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        report.attestations += 1;
+        let mut change = Change {
+            id: format!("line {}", index + 1),
+            current: None,
+            proposed: None,
+            violations: Vec::new(),
+            error: None,
+        };
+
+        let simulation = async {
+            let record: Record = serde_json::from_str(line).context("malformed attestation")?;
+            if let Some(id) = &record.id {
+                change.id = id.clone();
+            }
+            let claims = record.claims()?;
+            change.current = match (record.recorded_decision(), &current_rvs) {
+                (Some(decision), _) => Some(decision),
+                (None, Some(current_rvs)) => Some(
+                    decide(
+                        policy_engine.as_ref(),
+                        CURRENT_POLICY_ID,
+                        current_rvs,
+                        &record.tee,
+                        &claims,
+                    )
+                    .await
+                    .context("current policy")?
+                    .0,
+                ),
+                (None, None) => bail!("no recorded decision, and no current policy"),
+            };
+            let (decision, violations) = decide(
+                policy_engine.as_ref(),
+                PROPOSED_POLICY_ID,
+                &proposed_rvs,
+                &record.tee,
+                &claims,
+            )
+            .await
+            .context("proposed policy")?;
+            change.proposed = Some(decision);
+            change.violations = violations;
+            Ok(())
+        };
+
+        match simulation.await {
+            Err(e) => {
+                change.error = Some(format!("{e:#}"));
+                report.errors += 1;
+            }
+            Result::Ok(()) if change.current == change.proposed => {
+                report.unchanged += 1;
+                continue;
+            }
+            Result::Ok(()) if change.proposed == Some(PolicyDecision::Deny) => {
+                report.allow_to_deny += 1
+            }
+            Result::Ok(()) => report.deny_to_allow += 1,
+        }
+        report.changes.push(change);
+    }
+    Ok(report)
+}
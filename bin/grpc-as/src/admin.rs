@@ -0,0 +1,142 @@
+//! Authorization of the admin APIs.
+//!
+//! The admin APIs, such as `ImportSigningKey`, `ImportState` or
+//! `ValidateConfig`, are only served to administrators: the clients of a
+//! listener with `"admin": true`, e.g. a Unix domain socket that only
+//! administrators can reach, and the clients authenticated with one of the
+//! client certificates of `admins` in the config file:
+//!
+//! ```json
+//! "admins": {
+//!     "subjects": ["CN=as-admin, O=Example"],
+//!     "spki_sha256": ["<hex SHA-256 of the SubjectPublicKeyInfo>"]
+//! }
+//! ```
+//!
+//! A certificate issued by the `client_ca` of a listener does not make an
+//! administrator by itself, as the same CA may also authenticate tenants,
+//! see [`crate::usage`], and relays.
+
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tonic::{Request, Status};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::listener::ListenerAccess;
+
+/// Client certificates allowed to call some APIs.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct CertificateAllowList {
+    /// Subject DNs of the certificates, in the form of those of the
+    /// `certificate_tenants` of the usage accounting.
+    #[serde(default)]
+    pub subjects: Vec<String>,
+
+    /// Hex SHA-256 digests of the DER SubjectPublicKeyInfo of the
+    /// certificates, which stay the same when a certificate is renewed
+    /// with the same key.
+    #[serde(default)]
+    pub spki_sha256: Vec<String>,
+}
+
+impl CertificateAllowList {
+    pub fn check(&self) -> Result<()> {
+        for digest in &self.spki_sha256 {
+            if hex::decode(digest).map_or(true, |digest| digest.len() != 32) {
+                bail!("{digest} is not a hex SHA-256 digest");
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the client certificate `der` is one of the list.
+    pub fn contains(&self, der: &[u8]) -> Result<bool> {
+        let (_, cert) =
+            X509Certificate::from_der(der).map_err(|e| anyhow!("parse client certificate: {e}"))?;
+        let spki = hex::encode(Sha256::digest(cert.public_key().raw));
+        Ok(self.subjects.contains(&cert.subject().to_string())
+            || self
+                .spki_sha256
+                .iter()
+                .any(|digest| digest.eq_ignore_ascii_case(&spki)))
+    }
+
+    /// Refuse `request`, as `action` requires, unless it is received on an
+    /// admin listener or its client certificate is one of the list.
+    pub fn authorize<T>(&self, request: &Request<T>, action: &str) -> Result<(), Status> {
+        let admin_listener = request
+            .extensions()
+            .get::<ListenerAccess>()
+            .is_some_and(|access| access.admin);
+        if admin_listener {
+            return Ok(());
+        }
+        let Some(cert) = request
+            .peer_certs()
+            .and_then(|certs| certs.first().cloned())
+        else {
+            return Err(Status::unauthenticated(format!(
+                "{action} requires a client certificate"
+            )));
+        };
+        match self.contains(cert.get_ref()) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Status::permission_denied(format!(
+                "{action} is not allowed to this client certificate"
+            ))),
+            Err(e) => Err(Status::unauthenticated(format!("{e:#}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERT: &[u8] =
+        include_bytes!("../../../attestation-service/src/verifier/snp/test-vcek.der");
+    const SPKI_SHA256: &str = "b235e3954ba583361b63393cadf32c064a8b3320464dec7f25a1519029cf78e0";
+
+    #[test]
+    fn test_contains() {
+        let (_, cert) = X509Certificate::from_der(CERT).unwrap();
+        let by_subject = CertificateAllowList {
+            subjects: vec![cert.subject().to_string()],
+            spki_sha256: Vec::new(),
+        };
+        assert!(by_subject.contains(CERT).unwrap());
+        let by_spki = CertificateAllowList {
+            subjects: vec!["CN=as-admin".to_string()],
+            spki_sha256: vec![SPKI_SHA256.to_uppercase()],
+        };
+        assert!(by_spki.check().is_ok());
+        assert!(by_spki.contains(CERT).unwrap());
+        assert!(!CertificateAllowList::default().contains(CERT).unwrap());
+        assert!(CertificateAllowList::default()
+            .contains(b"not DER")
+            .is_err());
+
+        let invalid = CertificateAllowList {
+            subjects: Vec::new(),
+            spki_sha256: vec!["b235".to_string()],
+        };
+        assert!(invalid.check().is_err());
+    }
+
+    #[test]
+    fn test_authorize() {
+        let admins = CertificateAllowList::default();
+        let e = admins
+            .authorize(&Request::new(()), "Importing the state")
+            .unwrap_err();
+        assert_eq!(e.code(), tonic::Code::Unauthenticated);
+
+        let mut request = Request::new(());
+        request.extensions_mut().insert(ListenerAccess {
+            diagnostics: false,
+            admin: true,
+        });
+        assert!(admins.authorize(&request, "Importing the state").is_ok());
+    }
+}
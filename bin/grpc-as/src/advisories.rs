@@ -0,0 +1,36 @@
+//! Polls of the security advisory feed of the `advisory_feed` section of
+//! the AS config, which tighten the `advisory_baseline` policy data
+//! document, see `attestation_service::advisories`. It polls the feed at
+//! startup, then every `poll_secs`. Warm standbys do not poll the feed,
+//! they replicate the baseline of their primary.
+
+use anyhow::Result;
+use log::warn;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::server::AttestationServer;
+use crate::supervisor::Shutdown;
+
+/// Apply the advisories of the feed of the AS of `server`, until
+/// `shutdown`.
+pub async fn watch(server: Arc<RwLock<AttestationServer>>, mut shutdown: Shutdown) -> Result<()> {
+    let Some(period) = server
+        .read()
+        .await
+        .attestation_service
+        .advisory_feed_interval()
+    else {
+        return Ok(());
+    };
+    let mut interval = tokio::time::interval(period);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = shutdown.signalled() => return Ok(()),
+        }
+        if let Err(e) = server.write().await.refresh_advisories().await {
+            warn!("Poll of the advisory feed failed: {e:#}");
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Structured claims of the gRPC responses.
+//!
+//! The claims of a token are in the token, but clients in other languages
+//! than Rust would decode the token, and then parse its JSON claims, only
+//! to read them. `AttestationResponse` also has them as a `Claims` message,
+//! a `google.protobuf.Struct` with the version of its layout, and the
+//! diagnostics as a `google.protobuf.Struct`, so that absent claims are
+//! told by field presence.
+
+use crate::as_api::Claims;
+use prost_types::{value::Kind, ListValue, Struct};
+use serde_json::Value;
+
+/// Version of the layout of the `Claims` message.
+pub const CLAIMS_VERSION: u32 = 1;
+
+/// Claim of the token with the version of its claims schema.
+const CLAIMS_SCHEMA_CLAIM: &str = "claims-schema";
+
+fn to_proto_value(value: &Value) -> prost_types::Value {
+    let kind = match value {
+        Value::Null => Kind::NullValue(0),
+        Value::Bool(b) => Kind::BoolValue(*b),
+        Value::Number(n) => Kind::NumberValue(n.as_f64().unwrap_or_default()),
+        Value::String(s) => Kind::StringValue(s.clone()),
+        Value::Array(values) => Kind::ListValue(ListValue {
+            values: values.iter().map(to_proto_value).collect(),
+        }),
+        Value::Object(_) => Kind::StructValue(to_struct(value).unwrap_or_default()),
+    };
+    prost_types::Value { kind: Some(kind) }
+}
+
+/// The `google.protobuf.Struct` of a JSON object, `None` for other values.
+pub fn to_struct(value: &Value) -> Option<Struct> {
+    Some(Struct {
+        fields: value
+            .as_object()?
+            .iter()
+            .map(|(name, value)| (name.clone(), to_proto_value(value)))
+            .collect(),
+    })
+}
+
+/// The `Claims` message of the claims of a token.
+pub fn claims(claims: &Value) -> Option<Claims> {
+    Some(Claims {
+        version: CLAIMS_VERSION,
+        claims_schema: claims[CLAIMS_SCHEMA_CLAIM]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        claims: Some(to_struct(claims)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_claims() {
+        let token_claims = json!({
+            "claims-schema": "v2",
+            "tcb-status": { "tdx.quote.header.version": 4, "tdx.ccel.kernel_parameters.rw": null },
+            "policies": [{ "policy_id": "default", "allow": true }],
+        });
+        let claims = claims(&token_claims).unwrap();
+        assert_eq!(claims.version, CLAIMS_VERSION);
+        assert_eq!(claims.claims_schema, "v2");
+
+        let fields = claims.claims.unwrap().fields;
+        let Some(Kind::StructValue(tcb_status)) = &fields["tcb-status"].kind else {
+            panic!("tcb-status is not a struct");
+        };
+        assert_eq!(
+            tcb_status.fields["tdx.quote.header.version"].kind,
+            Some(Kind::NumberValue(4.0))
+        );
+        assert_eq!(
+            tcb_status.fields["tdx.ccel.kernel_parameters.rw"].kind,
+            Some(Kind::NullValue(0))
+        );
+        let Some(Kind::ListValue(policies)) = &fields["policies"].kind else {
+            panic!("policies is not a list");
+        };
+        assert_eq!(policies.values.len(), 1);
+
+        assert!(to_struct(&json!("not an object")).is_none());
+        assert!(super::claims(&json!([])).is_none());
+    }
+}
@@ -0,0 +1,995 @@
+//! CoAP front end, for constrained attesters.
+//!
+//! Embedded and edge attesters, such as IoT TEEs, may have no HTTP/2 stack
+//! to speak gRPC. With `coap` in the AS config file, the server also
+//! serves the RATS challenge-response over CoAP (RFC 7252) on UDP:
+//!
+//! ```json
+//! "coap": { "address": "0.0.0.0:5683" }
+//! ```
+//!
+//! - `POST /challenge`, with `{"tee": "tdx", "freshness_methods": [...]}`,
+//!   answers `{"freshness": "nonce", "nonce": "..."}`, like the
+//!   `Challenge` gRPC API;
+//! - `POST /attest`, with `{"tee": "tdx", "nonce": "...", "evidence": "..."}`,
+//!   the evidence the JSON attestation or the attestation itself, answers
+//!   `{"token": "...", "warnings": [...]}`, like the `AttestationEvaluate`
+//!   gRPC API, with an optional `token_format`.
+//!
+//! Payloads are JSON, content format 50, or CBOR, content format 60, and
+//! the `Accept` option asks for JSON, CBOR or a COSE token, content format
+//! 18, see [`crate::content`]. Evidence larger than a datagram
+//! is sent with block-wise transfer (RFC 7959 `Block1`). Only this subset
+//! of CoAP is spoken: no observation, no proxying, no DTLS, so the
+//! listener should be reached over a trusted network, the evidence and
+//! tokens being integrity protected by themselves.
+//!
+//! At most `concurrency` requests are served at the same time, the others
+//! answered 5.03 Service Unavailable, and block-wise transfers in progress
+//! are capped, in all and per peer address. Retransmissions of a
+//! confirmable request are answered the response already sent, or not at
+//! all while it is served, instead of serving the request again.
+
+use anyhow::{bail, Context, Result};
+use attestation_service::verifier::freshness::FreshnessMethod;
+use attestation_service::{EvaluateOptions, Tee, TokenFormat};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::{RwLock, Semaphore};
+
+use crate::content::ContentFormat;
+use crate::server::AttestationServer;
+use crate::supervisor::Shutdown;
+
+const VERSION: u8 = 1;
+const PAYLOAD_MARKER: u8 = 0xff;
+
+/// Largest datagram received.
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+/// Largest request accepted, reassembled from its blocks.
+const MAX_PAYLOAD: usize = 1024 * 1024;
+
+/// Time after which an incomplete block-wise transfer is dropped.
+const BLOCK_TRANSFER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time the message ID of a confirmable request is remembered, the
+/// `EXCHANGE_LIFETIME` of RFC 7252.
+const EXCHANGE_LIFETIME: Duration = Duration::from_secs(247);
+
+/// Largest number of confirmable requests remembered.
+const MAX_EXCHANGES: usize = 1024;
+
+/// Seconds after which a peer answered 5.03 may try again.
+const RETRY_AFTER: u32 = 5;
+
+const OPTION_URI_PATH: u16 = 11;
+const OPTION_CONTENT_FORMAT: u16 = 12;
+const OPTION_MAX_AGE: u16 = 14;
+const OPTION_ACCEPT: u16 = 17;
+const OPTION_BLOCK1: u16 = 27;
+
+/// Request and response codes, `class << 5 | detail`.
+mod code {
+    pub const POST: u8 = 0x02;
+    pub const CHANGED: u8 = 0x44;
+    pub const CONTINUE: u8 = 0x5f;
+    pub const BAD_REQUEST: u8 = 0x80;
+    pub const FORBIDDEN: u8 = 0x83;
+    pub const NOT_FOUND: u8 = 0x84;
+    pub const METHOD_NOT_ALLOWED: u8 = 0x85;
+    pub const NOT_ACCEPTABLE: u8 = 0x86;
+    pub const REQUEST_ENTITY_INCOMPLETE: u8 = 0x88;
+    pub const REQUEST_ENTITY_TOO_LARGE: u8 = 0x8d;
+    pub const UNSUPPORTED_CONTENT_FORMAT: u8 = 0x8f;
+    pub const SERVICE_UNAVAILABLE: u8 = 0xa3;
+}
+
+fn default_concurrency() -> usize {
+    16
+}
+
+fn default_max_transfers() -> usize {
+    64
+}
+
+fn default_max_transfers_per_peer() -> usize {
+    4
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct CoapConfig {
+    /// `<ip>:<port>` of the UDP socket, e.g. `0.0.0.0:5683`.
+    pub address: String,
+
+    /// Requests served at the same time.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+
+    /// Block-wise transfers in progress, of all peers.
+    #[serde(default = "default_max_transfers")]
+    pub max_transfers: usize,
+
+    /// Block-wise transfers in progress of a peer address.
+    #[serde(default = "default_max_transfers_per_peer")]
+    pub max_transfers_per_peer: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MessageType {
+    Confirmable,
+    NonConfirmable,
+    Acknowledgement,
+    Reset,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Message {
+    kind: MessageType,
+    code: u8,
+    id: u16,
+    token: Vec<u8>,
+    /// Options, sorted by number.
+    options: Vec<(u16, Vec<u8>)>,
+    payload: Vec<u8>,
+}
+
+/// An option delta or length nibble and its extended bytes.
+fn read_extended(nibble: u8, data: &[u8], pos: &mut usize) -> Result<u16> {
+    let mut byte = || {
+        let b = *data.get(*pos).context("Truncated CoAP option")?;
+        *pos += 1;
+        Ok::<u16, anyhow::Error>(b as u16)
+    };
+    match nibble {
+        0..=12 => Ok(nibble as u16),
+        13 => Ok(byte()? + 13),
+        14 => Ok((byte()? << 8 | byte()?) + 269),
+        _ => bail!("Reserved CoAP option nibble"),
+    }
+}
+
+fn write_extended(value: u16) -> (u8, Vec<u8>) {
+    match value {
+        0..=12 => (value as u8, Vec::new()),
+        13..=268 => (13, vec![(value - 13) as u8]),
+        _ => (14, (value - 269).to_be_bytes().to_vec()),
+    }
+}
+
+fn uint(value: &[u8]) -> u32 {
+    value.iter().fold(0, |n, b| n << 8 | *b as u32)
+}
+
+fn encode_uint(value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    bytes[skip..].to_vec()
+}
+
+impl Message {
+    fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 4 {
+            bail!("CoAP message too short");
+        }
+        if data[0] >> 6 != VERSION {
+            bail!("Unsupported CoAP version {}", data[0] >> 6);
+        }
+        let kind = match (data[0] >> 4) & 0x3 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        };
+        let token_len = (data[0] & 0xf) as usize;
+        if token_len > 8 {
+            bail!("Invalid CoAP token length {token_len}");
+        }
+        let mut pos = 4 + token_len;
+        let token = data.get(4..pos).context("Truncated CoAP token")?.to_vec();
+
+        let mut options = Vec::new();
+        let mut number = 0u16;
+        let mut payload = Vec::new();
+        while pos < data.len() {
+            let header = data[pos];
+            pos += 1;
+            if header == PAYLOAD_MARKER {
+                if pos == data.len() {
+                    bail!("Empty CoAP payload after the payload marker");
+                }
+                payload = data[pos..].to_vec();
+                break;
+            }
+            let delta = read_extended(header >> 4, data, &mut pos)?;
+            let len = read_extended(header & 0xf, data, &mut pos)? as usize;
+            number = number.checked_add(delta).context("CoAP option overflow")?;
+            let value = data
+                .get(pos..pos + len)
+                .context("Truncated CoAP option value")?;
+            options.push((number, value.to_vec()));
+            pos += len;
+        }
+
+        Ok(Self {
+            kind,
+            code: data[1],
+            id: u16::from_be_bytes([data[2], data[3]]),
+            token,
+            options,
+            payload,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let kind = match self.kind {
+            MessageType::Confirmable => 0,
+            MessageType::NonConfirmable => 1,
+            MessageType::Acknowledgement => 2,
+            MessageType::Reset => 3,
+        };
+        let mut data = vec![VERSION << 6 | kind << 4 | self.token.len() as u8, self.code];
+        data.extend_from_slice(&self.id.to_be_bytes());
+        data.extend_from_slice(&self.token);
+
+        let mut options = self.options.clone();
+        options.sort_by_key(|(number, _)| *number);
+        let mut previous = 0;
+        for (number, value) in options {
+            let (delta, delta_ext) = write_extended(number - previous);
+            let (len, len_ext) = write_extended(value.len() as u16);
+            data.push(delta << 4 | len);
+            data.extend(delta_ext);
+            data.extend(len_ext);
+            data.extend(value);
+            previous = number;
+        }
+        if !self.payload.is_empty() {
+            data.push(PAYLOAD_MARKER);
+            data.extend_from_slice(&self.payload);
+        }
+        data
+    }
+
+    fn option(&self, number: u16) -> Option<&[u8]> {
+        self.options
+            .iter()
+            .find(|(n, _)| *n == number)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    fn path(&self) -> String {
+        self.options
+            .iter()
+            .filter(|(n, _)| *n == OPTION_URI_PATH)
+            .map(|(_, segment)| String::from_utf8_lossy(segment))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// The response to this request, piggybacked on the acknowledgement of
+    /// a confirmable request.
+    fn response(&self, id: u16, code: u8, payload: Vec<u8>) -> Message {
+        self.response_as(id, code, ContentFormat::Json, payload)
+    }
+
+    /// The response to this request, with a payload of `format`.
+    fn response_as(&self, id: u16, code: u8, format: ContentFormat, payload: Vec<u8>) -> Message {
+        let (kind, id) = match self.kind {
+            MessageType::Confirmable => (MessageType::Acknowledgement, self.id),
+            _ => (MessageType::NonConfirmable, id),
+        };
+        let mut options = Vec::new();
+        if !payload.is_empty() {
+            options.push((OPTION_CONTENT_FORMAT, encode_uint(format.coap())));
+        }
+        Message {
+            kind,
+            code,
+            id,
+            token: self.token.clone(),
+            options,
+            payload,
+        }
+    }
+}
+
+/// A `Block1` option: number of the block, whether more follow, size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Block {
+    num: u32,
+    more: bool,
+    size: usize,
+}
+
+impl Block {
+    fn parse(value: &[u8]) -> Result<Self> {
+        let value = uint(value);
+        let szx = value & 0x7;
+        if szx == 7 {
+            bail!("Reserved CoAP block size");
+        }
+        Ok(Self {
+            num: value >> 4,
+            more: value & 0x8 != 0,
+            size: 1 << (szx + 4),
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let szx = self.size.trailing_zeros() - 4;
+        encode_uint(self.num << 4 | u32::from(self.more) << 3 | szx)
+    }
+}
+
+/// Block-wise transfers of requests in progress, by peer and path.
+struct Transfers {
+    max: usize,
+    max_per_peer: usize,
+    transfers: HashMap<(SocketAddr, String), (Instant, Vec<u8>)>,
+}
+
+/// What to do with a block of a request.
+#[derive(Debug, PartialEq, Eq)]
+enum Reassembly {
+    /// Ask for the next block.
+    Continue(Block),
+    /// The request is complete.
+    Complete(Vec<u8>),
+    /// Reject the request with this code.
+    Reject(u8),
+}
+
+impl Transfers {
+    fn new(config: &CoapConfig) -> Self {
+        Self {
+            max: config.max_transfers,
+            max_per_peer: config.max_transfers_per_peer,
+            transfers: HashMap::new(),
+        }
+    }
+
+    /// Transfers in progress of the peer address `ip`.
+    fn of_peer(&self, ip: IpAddr) -> usize {
+        self.transfers
+            .keys()
+            .filter(|(peer, _)| peer.ip() == ip)
+            .count()
+    }
+
+    fn add(&mut self, peer: SocketAddr, request: &Message, now: Instant) -> Reassembly {
+        self.transfers
+            .retain(|_, (started, _)| now.duration_since(*started) < BLOCK_TRANSFER_TIMEOUT);
+        let Some(block) = request.option(OPTION_BLOCK1) else {
+            return Reassembly::Complete(request.payload.clone());
+        };
+        let Ok(block) = Block::parse(block) else {
+            return Reassembly::Reject(code::BAD_REQUEST);
+        };
+        let key = (peer, request.path());
+        if block.num == 0
+            && block.more
+            && !self.transfers.contains_key(&key)
+            && (self.transfers.len() >= self.max || self.of_peer(peer.ip()) >= self.max_per_peer)
+        {
+            return Reassembly::Reject(code::SERVICE_UNAVAILABLE);
+        }
+        let mut payload = match block.num {
+            0 => Vec::new(),
+            _ => match self.transfers.remove(&key) {
+                Some((_, payload)) => payload,
+                None => return Reassembly::Reject(code::REQUEST_ENTITY_INCOMPLETE),
+            },
+        };
+        if payload.len() != block.num as usize * block.size {
+            return Reassembly::Reject(code::REQUEST_ENTITY_INCOMPLETE);
+        }
+        payload.extend_from_slice(&request.payload);
+        if payload.len() > MAX_PAYLOAD {
+            return Reassembly::Reject(code::REQUEST_ENTITY_TOO_LARGE);
+        }
+        match block.more {
+            true => {
+                self.transfers.insert(key, (now, payload));
+                Reassembly::Continue(block)
+            }
+            false => Reassembly::Complete(payload),
+        }
+    }
+}
+
+/// Confirmable requests of the last `EXCHANGE_LIFETIME`, by peer and
+/// message ID, with their response once sent.
+#[derive(Default)]
+struct Exchanges {
+    responses: HashMap<(SocketAddr, u16), Option<Vec<u8>>>,
+    order: VecDeque<(Instant, (SocketAddr, u16))>,
+}
+
+/// Whether a confirmable request was already received.
+#[derive(Debug, PartialEq, Eq)]
+enum Exchange {
+    /// First received, to serve.
+    New,
+    /// A retransmission of a request being served.
+    Pending,
+    /// A retransmission of a request answered this response.
+    Answered(Vec<u8>),
+}
+
+impl Exchanges {
+    fn start(&mut self, peer: SocketAddr, id: u16, now: Instant) -> Exchange {
+        while let Some((received, key)) = self.order.front() {
+            if now.duration_since(*received) < EXCHANGE_LIFETIME && self.order.len() < MAX_EXCHANGES
+            {
+                break;
+            }
+            self.responses.remove(key);
+            self.order.pop_front();
+        }
+        match self.responses.get(&(peer, id)) {
+            Some(Some(response)) => Exchange::Answered(response.clone()),
+            Some(None) => Exchange::Pending,
+            None => {
+                self.responses.insert((peer, id), None);
+                self.order.push_back((now, (peer, id)));
+                Exchange::New
+            }
+        }
+    }
+
+    fn answer(&mut self, peer: SocketAddr, id: u16, response: &[u8]) {
+        if let Some(answer) = self.responses.get_mut(&(peer, id)) {
+            *answer = Some(response.to_vec());
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ChallengeRequest {
+    tee: Tee,
+    #[serde(default)]
+    freshness_methods: Vec<FreshnessMethod>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AttestRequest {
+    tee: Tee,
+    nonce: String,
+    /// The JSON attestation, or the attestation itself.
+    evidence: serde_json::Value,
+    /// Format of the token, instead of as configured, or as the response
+    /// format tells, see [`crate::content`].
+    #[serde(default)]
+    token_format: Option<TokenFormat>,
+}
+
+#[derive(Debug, Serialize)]
+struct AttestResponse {
+    token: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// The formats of a request and of its response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Formats {
+    request: ContentFormat,
+    response: ContentFormat,
+}
+
+/// Serve the request of `path` with `payload`, and return the response
+/// code, format and payload.
+async fn handle(
+    server: &RwLock<AttestationServer>,
+    path: &str,
+    payload: &[u8],
+    formats: Formats,
+) -> (u8, ContentFormat, Vec<u8>) {
+    // Errors of COSE responses are CBOR.
+    let error_format = match formats.response {
+        ContentFormat::Cose => ContentFormat::Cbor,
+        format => format,
+    };
+    let error = |code, error: String| {
+        let payload = error_format
+            .encode(&ErrorResponse { error })
+            .unwrap_or_default();
+        (code, error_format, payload)
+    };
+    let ok = |response: Result<Vec<u8>>| match response {
+        Ok(payload) => (code::CHANGED, formats.response, payload),
+        Err(e) => error(code::BAD_REQUEST, format!("{e:#}")),
+    };
+    match path {
+        "challenge" => {
+            if formats.response == ContentFormat::Cose {
+                return error(
+                    code::NOT_ACCEPTABLE,
+                    "A challenge is not a token".to_string(),
+                );
+            }
+            let request: ChallengeRequest = match formats.request.decode(payload) {
+                Ok(request) => request,
+                Err(e) => return error(code::BAD_REQUEST, format!("Malformed challenge: {e:#}")),
+            };
+            let challenge = server
+                .read()
+                .await
+                .attestation_service
+                .challenge(&request.tee, &request.freshness_methods)
+                .await;
+            match challenge {
+                Ok(challenge) => ok(formats.response.encode(&challenge)),
+                Err(e) => error(code::BAD_REQUEST, format!("Challenge failed: {e:#}")),
+            }
+        }
+        "attest" => {
+            let request: AttestRequest = match formats.request.decode(payload) {
+                Ok(request) => request,
+                Err(e) => {
+                    return error(
+                        code::BAD_REQUEST,
+                        format!("Malformed attestation request: {e:#}"),
+                    )
+                }
+            };
+            let token_format = match formats.response.token_format(request.token_format) {
+                Ok(token_format) => token_format,
+                Err(e) => return error(code::NOT_ACCEPTABLE, e.to_string()),
+            };
+            let evidence = match request.evidence {
+                serde_json::Value::String(evidence) => evidence,
+                evidence => evidence.to_string(),
+            };
+            let evaluation = server
+                .read()
+                .await
+                .attestation_service
+                .evaluate_with_options(
+                    request.tee,
+                    &request.nonce,
+                    &evidence,
+                    EvaluateOptions {
+                        token_format,
+                        ..Default::default()
+                    },
+                )
+                .await;
+            match evaluation {
+                Ok(evaluation) => ok(formats.response.encode_attestation(
+                    &AttestResponse {
+                        warnings: evaluation.warnings,
+                        token: evaluation.token.clone(),
+                    },
+                    &evaluation.token,
+                    token_format,
+                )),
+                Err(e) => error(code::FORBIDDEN, format!("Attestation: {e:#}")),
+            }
+        }
+        _ => error(code::NOT_FOUND, format!("No resource /{path}")),
+    }
+}
+
+/// Bind the UDP socket of `config`.
+pub async fn bind(config: &CoapConfig) -> Result<UdpSocket> {
+    let socket = UdpSocket::bind(&config.address)
+        .await
+        .with_context(|| format!("Cannot bind the CoAP socket {}", config.address))?;
+    info!("CoAP listen socket: {}", config.address);
+    Ok(socket)
+}
+
+/// Send `response` to `request` of `peer`, remembered to answer the
+/// retransmissions of a confirmable request.
+async fn send(
+    socket: &UdpSocket,
+    exchanges: &Mutex<Exchanges>,
+    peer: SocketAddr,
+    request: &Message,
+    response: &Message,
+) {
+    let response = response.encode();
+    if request.kind == MessageType::Confirmable {
+        exchanges
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .answer(peer, request.id, &response);
+    }
+    if let Err(e) = socket.send_to(&response, peer).await {
+        warn!("CoAP response to {peer} not sent: {e}");
+    }
+}
+
+/// Serve the CoAP requests received on `socket`, until `shutdown`.
+pub async fn serve(
+    config: CoapConfig,
+    socket: Arc<UdpSocket>,
+    server: Arc<RwLock<AttestationServer>>,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    let mut transfers = Transfers::new(&config);
+    let exchanges = Arc::new(Mutex::new(Exchanges::default()));
+    let permits = Arc::new(Semaphore::new(config.concurrency.max(1)));
+    // Random, not to collide with the message IDs of a previous run the
+    // peers may still remember.
+    let mut next_id: u16 = rand::random();
+    let mut buf = vec![0; MAX_DATAGRAM];
+    loop {
+        let received = tokio::select! {
+            received = socket.recv_from(&mut buf) => received,
+            _ = shutdown.signalled() => return Ok(()),
+        };
+        let (len, peer) = match received {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("CoAP receive failed: {e}");
+                continue;
+            }
+        };
+        let request = match Message::parse(&buf[..len]) {
+            Ok(request) => request,
+            Err(e) => {
+                debug!("Malformed CoAP message of {peer}: {e:#}");
+                continue;
+            }
+        };
+        // Acknowledgements and resets of the non-confirmable responses
+        // need nothing, and an empty message is a ping.
+        if matches!(
+            request.kind,
+            MessageType::Acknowledgement | MessageType::Reset
+        ) {
+            continue;
+        }
+        if request.kind == MessageType::Confirmable {
+            let exchange = exchanges
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .start(peer, request.id, Instant::now());
+            match exchange {
+                Exchange::New => {}
+                Exchange::Pending => continue,
+                Exchange::Answered(response) => {
+                    if let Err(e) = socket.send_to(&response, peer).await {
+                        warn!("CoAP response to {peer} not sent: {e}");
+                    }
+                    continue;
+                }
+            }
+        }
+        next_id = next_id.wrapping_add(1);
+        let response = match respond(&mut transfers, peer, &request, next_id) {
+            Action::Respond(response) => response,
+            Action::Serve(payload, formats) => {
+                let Ok(permit) = permits.clone().try_acquire_owned() else {
+                    debug!("CoAP /{} of {peer}: busy", request.path());
+                    let mut response =
+                        request.response(next_id, code::SERVICE_UNAVAILABLE, Vec::new());
+                    response
+                        .options
+                        .push((OPTION_MAX_AGE, encode_uint(RETRY_AFTER)));
+                    send(&socket, &exchanges, peer, &request, &response).await;
+                    continue;
+                };
+                let socket = socket.clone();
+                let server = server.clone();
+                let exchanges = exchanges.clone();
+                tokio::spawn(async move {
+                    let path = request.path();
+                    let (code, format, body) = handle(&server, &path, &payload, formats).await;
+                    drop(permit);
+                    debug!("CoAP /{path} of {peer}: {code:#x}");
+                    let mut response = request.response_as(next_id, code, format, body);
+                    if let Some(block) = request.option(OPTION_BLOCK1) {
+                        response.options.push((OPTION_BLOCK1, block.to_vec()));
+                    }
+                    send(&socket, &exchanges, peer, &request, &response).await;
+                });
+                continue;
+            }
+        };
+        send(&socket, &exchanges, peer, &request, &response).await;
+    }
+}
+
+/// What to do with a request.
+enum Action {
+    /// Answer right away.
+    Respond(Message),
+    /// Serve the request, whose payload is complete.
+    Serve(Vec<u8>, Formats),
+}
+
+/// What to do with `request` of `peer`, answered with message ID `id` if
+/// not confirmable.
+fn respond(transfers: &mut Transfers, peer: SocketAddr, request: &Message, id: u16) -> Action {
+    if request.code == 0 {
+        return Action::Respond(Message {
+            kind: MessageType::Reset,
+            code: 0,
+            id: request.id,
+            token: Vec::new(),
+            options: Vec::new(),
+            payload: Vec::new(),
+        });
+    }
+    if request.code != code::POST {
+        return Action::Respond(request.response(id, code::METHOD_NOT_ALLOWED, Vec::new()));
+    }
+    let format = |option| match request.option(option).map(uint) {
+        None => Some(ContentFormat::Json),
+        Some(number) => ContentFormat::from_coap(number),
+    };
+    let formats = match (format(OPTION_CONTENT_FORMAT), format(OPTION_ACCEPT)) {
+        (Some(ContentFormat::Cose) | None, _) => {
+            return Action::Respond(request.response(
+                id,
+                code::UNSUPPORTED_CONTENT_FORMAT,
+                Vec::new(),
+            ))
+        }
+        (_, None) => {
+            return Action::Respond(request.response(id, code::NOT_ACCEPTABLE, Vec::new()))
+        }
+        (Some(request), Some(response)) => Formats { request, response },
+    };
+    match transfers.add(peer, request, Instant::now()) {
+        Reassembly::Complete(payload) => Action::Serve(payload, formats),
+        Reassembly::Continue(block) => {
+            let mut response = request.response(id, code::CONTINUE, Vec::new());
+            response.options.push((OPTION_BLOCK1, block.encode()));
+            Action::Respond(response)
+        }
+        Reassembly::Reject(code) => Action::Respond(request.response(id, code, Vec::new())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> CoapConfig {
+        serde_json::from_str(r#"{"address": "127.0.0.1:5683"}"#).unwrap()
+    }
+
+    fn post(path: &str, options: Vec<(u16, Vec<u8>)>, payload: &[u8]) -> Message {
+        let mut all: Vec<(u16, Vec<u8>)> = path
+            .split('/')
+            .map(|segment| (OPTION_URI_PATH, segment.as_bytes().to_vec()))
+            .collect();
+        all.extend(options);
+        all.sort_by_key(|(number, _)| *number);
+        Message {
+            kind: MessageType::Confirmable,
+            code: code::POST,
+            id: 0x1234,
+            token: vec![0xca, 0xfe],
+            options: all,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_encode_parse() {
+        let long_path = "a".repeat(300);
+        let message = post(
+            &format!("attest/{long_path}"),
+            vec![(
+                OPTION_CONTENT_FORMAT,
+                encode_uint(ContentFormat::Json.coap()),
+            )],
+            br#"{"tee": "sample"}"#,
+        );
+        let data = message.encode();
+        assert_eq!(data[0], 0x42);
+        let parsed = Message::parse(&data).unwrap();
+        assert_eq!(parsed, message);
+        assert_eq!(parsed.path(), format!("attest/{long_path}"));
+        assert_eq!(
+            parsed.option(OPTION_CONTENT_FORMAT).map(uint),
+            Some(ContentFormat::Json.coap())
+        );
+
+        assert!(Message::parse(&[0x40, 0x01]).is_err());
+        assert!(Message::parse(&[0x80, 0x01, 0, 0]).is_err());
+        assert!(Message::parse(&[0x40, 0x02, 0, 0, 0xff]).is_err());
+    }
+
+    #[test]
+    fn test_response() {
+        let request = post("challenge", Vec::new(), b"{}");
+        let response = request.response(7, code::CHANGED, b"{}".to_vec());
+        assert_eq!(response.kind, MessageType::Acknowledgement);
+        assert_eq!(response.id, request.id);
+        assert_eq!(response.token, request.token);
+
+        let request = Message {
+            kind: MessageType::NonConfirmable,
+            ..request
+        };
+        let response = request.response(7, code::CHANGED, Vec::new());
+        assert_eq!(response.kind, MessageType::NonConfirmable);
+        assert_eq!(response.id, 7);
+    }
+
+    #[test]
+    fn test_formats() {
+        let peer: SocketAddr = "192.0.2.1:5683".parse().unwrap();
+        let mut transfers = Transfers::new(&config());
+        let mut serve = |options| {
+            let request = post("attest", options, b"\xa0");
+            respond(&mut transfers, peer, &request, 7)
+        };
+        let cbor = encode_uint(ContentFormat::Cbor.coap());
+        let cose = encode_uint(ContentFormat::Cose.coap());
+        let Action::Serve(_, formats) = serve(vec![
+            (OPTION_CONTENT_FORMAT, cbor.clone()),
+            (OPTION_ACCEPT, cose.clone()),
+        ]) else {
+            panic!("request not served");
+        };
+        assert_eq!(
+            formats,
+            Formats {
+                request: ContentFormat::Cbor,
+                response: ContentFormat::Cose
+            }
+        );
+        let Action::Serve(_, formats) = serve(Vec::new()) else {
+            panic!("request not served");
+        };
+        assert_eq!(formats.response, ContentFormat::Json);
+
+        let Action::Respond(response) = serve(vec![(OPTION_CONTENT_FORMAT, cose)]) else {
+            panic!("COSE request served");
+        };
+        assert_eq!(response.code, code::UNSUPPORTED_CONTENT_FORMAT);
+        let Action::Respond(response) = serve(vec![(OPTION_ACCEPT, encode_uint(40))]) else {
+            panic!("link format response served");
+        };
+        assert_eq!(response.code, code::NOT_ACCEPTABLE);
+    }
+
+    #[test]
+    fn test_block1() {
+        let peer: SocketAddr = "192.0.2.1:5683".parse().unwrap();
+        let now = Instant::now();
+        let mut transfers = Transfers::new(&config());
+        let block = |num, more| {
+            Block {
+                num,
+                more,
+                size: 16,
+            }
+            .encode()
+        };
+
+        let first = post("attest", vec![(OPTION_BLOCK1, block(0, true))], &[1; 16]);
+        assert_eq!(
+            transfers.add(peer, &first, now),
+            Reassembly::Continue(Block {
+                num: 0,
+                more: true,
+                size: 16
+            })
+        );
+        // A block out of order.
+        let skipped = post("attest", vec![(OPTION_BLOCK1, block(2, false))], &[3; 4]);
+        assert_eq!(
+            transfers.add(peer, &skipped, now),
+            Reassembly::Reject(code::REQUEST_ENTITY_INCOMPLETE)
+        );
+
+        transfers.add(peer, &first, now);
+        let last = post("attest", vec![(OPTION_BLOCK1, block(1, false))], &[2; 4]);
+        let Reassembly::Complete(payload) = transfers.add(peer, &last, now) else {
+            panic!("transfer not complete");
+        };
+        assert_eq!(payload.len(), 20);
+        assert_eq!(payload[16..], [2; 4]);
+
+        // A transfer that timed out.
+        transfers.add(peer, &first, now);
+        assert_eq!(
+            transfers.add(peer, &last, now + BLOCK_TRANSFER_TIMEOUT),
+            Reassembly::Reject(code::REQUEST_ENTITY_INCOMPLETE)
+        );
+    }
+
+    #[test]
+    fn test_transfer_limits() {
+        let now = Instant::now();
+        let mut transfers = Transfers::new(&CoapConfig {
+            max_transfers: 3,
+            max_transfers_per_peer: 2,
+            ..config()
+        });
+        let first = |path| {
+            let block = Block {
+                num: 0,
+                more: true,
+                size: 16,
+            };
+            post(path, vec![(OPTION_BLOCK1, block.encode())], &[1; 16])
+        };
+        let peer: SocketAddr = "192.0.2.1:5683".parse().unwrap();
+        let other_port: SocketAddr = "192.0.2.1:5684".parse().unwrap();
+        let other_peer: SocketAddr = "192.0.2.2:5683".parse().unwrap();
+
+        assert!(matches!(
+            transfers.add(peer, &first("attest"), now),
+            Reassembly::Continue(_)
+        ));
+        assert!(matches!(
+            transfers.add(other_port, &first("attest"), now),
+            Reassembly::Continue(_)
+        ));
+        // The peer address has its transfers, whatever its port.
+        assert_eq!(
+            transfers.add(peer, &first("challenge"), now),
+            Reassembly::Reject(code::SERVICE_UNAVAILABLE)
+        );
+        // A transfer started again is not a new one.
+        assert!(matches!(
+            transfers.add(peer, &first("attest"), now),
+            Reassembly::Continue(_)
+        ));
+        assert!(matches!(
+            transfers.add(other_peer, &first("attest"), now),
+            Reassembly::Continue(_)
+        ));
+        assert_eq!(
+            transfers.add(other_peer, &first("challenge"), now),
+            Reassembly::Reject(code::SERVICE_UNAVAILABLE)
+        );
+        // Single block requests need no transfer.
+        assert!(matches!(
+            transfers.add(other_peer, &post("challenge", Vec::new(), b"{}"), now),
+            Reassembly::Complete(_)
+        ));
+    }
+
+    #[test]
+    fn test_exchanges() {
+        let peer: SocketAddr = "192.0.2.1:5683".parse().unwrap();
+        let now = Instant::now();
+        let mut exchanges = Exchanges::default();
+
+        assert_eq!(exchanges.start(peer, 1, now), Exchange::New);
+        assert_eq!(exchanges.start(peer, 1, now), Exchange::Pending);
+        exchanges.answer(peer, 1, b"response");
+        assert_eq!(
+            exchanges.start(peer, 1, now),
+            Exchange::Answered(b"response".to_vec())
+        );
+        assert_eq!(
+            exchanges.start("192.0.2.2:5683".parse().unwrap(), 1, now),
+            Exchange::New
+        );
+
+        // Forgotten after their lifetime, or when too many.
+        assert_eq!(
+            exchanges.start(peer, 1, now + EXCHANGE_LIFETIME),
+            Exchange::New
+        );
+        for id in 2..=MAX_EXCHANGES as u16 + 1 {
+            exchanges.start(peer, id, now + EXCHANGE_LIFETIME);
+        }
+        assert_eq!(
+            exchanges.start(peer, 1, now + EXCHANGE_LIFETIME),
+            Exchange::New
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Content negotiation of the REST and CoAP front ends.
+//!
+//! Constrained attesters and relying parties may prefer CBOR to JSON. The
+//! front ends take challenge and attestation requests in JSON or CBOR, as
+//! told by their content type, and answer in the first format the client
+//! accepts, JSON if it does not tell:
+//!
+//! | Format | HTTP media type                            | CoAP content format |
+//! |--------|--------------------------------------------|---------------------|
+//! | JSON   | `application/json`                         | 50                  |
+//! | CBOR   | `application/cbor`                         | 60                  |
+//! | COSE   | `application/cose; cose-type="cose-sign1"` | 18                  |
+//!
+//! A CBOR request is converted to JSON, see [`Cbor::to_json`], and served
+//! as the JSON one, by the same verification pipeline: its byte strings
+//! are base64 encoded, and its evidence may be the attestation itself, as
+//! a map with byte strings for its binary fields.
+//!
+//! A CBOR response is the JSON response encoded in CBOR, but for an EAR
+//! COSE token, which is the COSE_Sign1 byte string rather than its
+//! base64url encoding. A COSE response is the COSE_Sign1 token alone, and
+//! only answers attestations. Attestations answered in CBOR or COSE issue
+//! `ear_cose` tokens, unless the request asks for another `token_format`,
+//! which a COSE response cannot have.
+
+use anyhow::{anyhow, bail, Context, Result};
+use attestation_service::{Cbor, TokenFormat};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Format of the body of a request or response.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ContentFormat {
+    #[default]
+    Json,
+    Cbor,
+    /// A COSE_Sign1 token, only for responses.
+    Cose,
+}
+
+impl ContentFormat {
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Cbor => "application/cbor",
+            Self::Cose => "application/cose; cose-type=\"cose-sign1\"",
+        }
+    }
+
+    /// The CoAP content format number.
+    pub fn coap(self) -> u32 {
+        match self {
+            Self::Json => 50,
+            Self::Cbor => 60,
+            Self::Cose => 18,
+        }
+    }
+
+    pub fn from_coap(number: u32) -> Option<Self> {
+        [Self::Json, Self::Cbor, Self::Cose]
+            .into_iter()
+            .find(|format| format.coap() == number)
+    }
+
+    /// The format of the media type of an `Accept` or `Content-Type`
+    /// header, with its parameters. `*/*` and `application/*` are JSON.
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        let mut parameters = media_type.split(';').map(str::trim);
+        let format = match parameters.next()?.to_ascii_lowercase().as_str() {
+            "application/json" | "application/*" | "*/*" => Self::Json,
+            "application/cbor" => Self::Cbor,
+            "application/cose" => Self::Cose,
+            _ => return None,
+        };
+        // COSE messages other than COSE_Sign1 are not issued.
+        let cose_type = parameters
+            .filter_map(|parameter| parameter.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("cose-type"))
+            .map(|(_, value)| value.trim().trim_matches('"'));
+        match (format, cose_type) {
+            (Self::Cose, Some(cose_type)) if cose_type != "cose-sign1" => None,
+            (format, _) => Some(format),
+        }
+    }
+
+    /// The format of a request body of `content_type`, JSON if unset.
+    pub fn of_request(content_type: Option<&str>) -> Result<Self> {
+        let Some(content_type) = content_type else {
+            return Ok(Self::Json);
+        };
+        match Self::from_media_type(content_type) {
+            Some(format @ (Self::Json | Self::Cbor)) => Ok(format),
+            _ => bail!("Unsupported content type {content_type}"),
+        }
+    }
+
+    /// The format of a response to a client that accepts the comma
+    /// separated media types `accept`: the first supported one, JSON if
+    /// unset.
+    pub fn of_accept(accept: Option<&str>) -> Result<Self> {
+        let Some(accept) = accept else {
+            return Ok(Self::Json);
+        };
+        accept
+            .split(',')
+            .find_map(Self::from_media_type)
+            .ok_or_else(|| anyhow!("None of the accepted media types {accept} is supported"))
+    }
+
+    /// Decode a request `body` of this format.
+    pub fn decode<T: DeserializeOwned>(self, body: &[u8]) -> Result<T> {
+        match self {
+            Self::Json => Ok(serde_json::from_slice(body)?),
+            _ => {
+                let value = Cbor::decode(body)?.to_json()?;
+                Ok(serde_json::from_value(value)?)
+            }
+        }
+    }
+
+    /// Encode the response `value`, in CBOR for COSE responses, such as
+    /// their errors, that are not tokens.
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            Self::Json => Ok(serde_json::to_vec(value)?),
+            _ => Ok(Cbor::from(&serde_json::to_value(value)?).encode()),
+        }
+    }
+
+    /// The token format of an attestation answered in this format, for
+    /// the `requested` one, see the module documentation.
+    pub fn token_format(self, requested: Option<TokenFormat>) -> Result<Option<TokenFormat>> {
+        match (self, requested) {
+            (Self::Json, requested) => Ok(requested),
+            (_, None | Some(TokenFormat::EarCose)) => Ok(Some(TokenFormat::EarCose)),
+            (Self::Cbor, requested) => Ok(requested),
+            (Self::Cose, Some(format)) => bail!("A {format} token is not a COSE_Sign1"),
+        }
+    }
+
+    /// Encode the attestation `response`, whose `token` is of
+    /// `token_format`.
+    pub fn encode_attestation<T: Serialize>(
+        self,
+        response: &T,
+        token: &str,
+        token_format: Option<TokenFormat>,
+    ) -> Result<Vec<u8>> {
+        let cose = || {
+            URL_SAFE_NO_PAD
+                .decode(token)
+                .context("Malformed COSE token")
+        };
+        match (self, token_format) {
+            (Self::Cbor, Some(TokenFormat::EarCose)) => {
+                let Cbor::Map(mut entries) = Cbor::from(&serde_json::to_value(response)?) else {
+                    bail!("The attestation response is not a map");
+                };
+                for (key, value) in &mut entries {
+                    if *key == Cbor::Text("token".to_string()) {
+                        *value = Cbor::Bytes(cose()?);
+                    }
+                }
+                Ok(Cbor::Map(entries).encode())
+            }
+            (Self::Cose, Some(TokenFormat::EarCose)) => cose(),
+            (Self::Cose, _) => bail!("The token is not a COSE_Sign1"),
+            (format, _) => format.encode(response),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_negotiation() {
+        assert_eq!(
+            ContentFormat::of_request(None).unwrap(),
+            ContentFormat::Json
+        );
+        assert_eq!(
+            ContentFormat::of_request(Some("application/cbor")).unwrap(),
+            ContentFormat::Cbor
+        );
+        assert_eq!(
+            ContentFormat::of_request(Some("application/json; charset=utf-8")).unwrap(),
+            ContentFormat::Json
+        );
+        assert!(ContentFormat::of_request(Some("application/cose")).is_err());
+        assert!(ContentFormat::of_request(Some("text/plain")).is_err());
+
+        assert_eq!(ContentFormat::of_accept(None).unwrap(), ContentFormat::Json);
+        assert_eq!(
+            ContentFormat::of_accept(Some("text/html, application/cbor, */*")).unwrap(),
+            ContentFormat::Cbor
+        );
+        assert_eq!(
+            ContentFormat::of_accept(Some(r#"application/cose; cose-type="cose-sign1""#)).unwrap(),
+            ContentFormat::Cose
+        );
+        assert!(ContentFormat::of_accept(Some("application/cose; cose-type=cose-mac0")).is_err());
+        assert!(ContentFormat::of_accept(Some("text/html")).is_err());
+
+        assert_eq!(ContentFormat::from_coap(60), Some(ContentFormat::Cbor));
+        assert_eq!(ContentFormat::from_coap(0), None);
+    }
+
+    #[test]
+    fn test_token_format() {
+        let (jwt, cose) = (Some(TokenFormat::Jwt), Some(TokenFormat::EarCose));
+        assert_eq!(ContentFormat::Json.token_format(None).unwrap(), None);
+        assert_eq!(ContentFormat::Cbor.token_format(None).unwrap(), cose);
+        assert_eq!(ContentFormat::Cbor.token_format(jwt).unwrap(), jwt);
+        assert_eq!(ContentFormat::Cose.token_format(cose).unwrap(), cose);
+        assert!(ContentFormat::Cose.token_format(jwt).is_err());
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        // Evidence as a CBOR map, its quote a byte string.
+        let request = Cbor::Map(vec![
+            (Cbor::Text("tee".to_string()), Cbor::Text("tdx".to_string())),
+            (
+                Cbor::Text("evidence".to_string()),
+                Cbor::Map(vec![(
+                    Cbor::Text("quote".to_string()),
+                    Cbor::Bytes(vec![1, 2, 3]),
+                )]),
+            ),
+        ]);
+        let decoded: Value = ContentFormat::Cbor.decode(&request.encode()).unwrap();
+        assert_eq!(
+            decoded,
+            json!({"tee": "tdx", "evidence": {"quote": "AQID"}})
+        );
+        assert!(ContentFormat::Cbor.decode::<Value>(b"{}").is_err());
+
+        let response = json!({"token": "0oRDoQEm", "warnings": ["stale"]});
+        let cose = Some(TokenFormat::EarCose);
+        let encoded = ContentFormat::Cbor
+            .encode_attestation(&response, "0oRDoQEm", cose)
+            .unwrap();
+        let Cbor::Map(entries) = Cbor::decode(&encoded).unwrap() else {
+            panic!("not a map");
+        };
+        assert_eq!(
+            entries[0],
+            (
+                Cbor::Text("token".to_string()),
+                Cbor::Bytes(vec![0xd2, 0x84, 0x43, 0xa1, 0x01, 0x26])
+            )
+        );
+        assert_eq!(
+            ContentFormat::Cose
+                .encode_attestation(&response, "0oRDoQEm", cose)
+                .unwrap(),
+            [0xd2, 0x84, 0x43, 0xa1, 0x01, 0x26]
+        );
+        assert_eq!(
+            ContentFormat::Json
+                .encode_attestation(&response, "0oRDoQEm", cose)
+                .unwrap(),
+            serde_json::to_vec(&response).unwrap()
+        );
+        assert!(ContentFormat::Cose
+            .encode_attestation(&response, "eyJhbGciOi", None)
+            .is_err());
+    }
+}
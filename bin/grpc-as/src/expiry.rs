@@ -0,0 +1,191 @@
+//! Webhook alerts ahead of the expiry of the verification collateral and
+//! trusted certificates of the AS.
+//!
+//! The watchdog checks the expiries known to the AS periodically, and
+//! POSTs an alert to the webhook when one comes within a threshold of days,
+//! once per threshold, and once more when it has expired. Renewed
+//! collateral starts over. Alerts the webhook failed to take are sent again
+//! at the next check.
+
+use anyhow::{Context, Result};
+use attestation_service::{verifier::expiry::Expiry, AttestationService as Service};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::supervisor::Shutdown;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn default_days_before() -> Vec<i64> {
+    vec![30, 7, 1]
+}
+
+fn default_interval_secs() -> u64 {
+    3600
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct ExpiryAlertConfig {
+    /// URL the alerts are POSTed to, as JSON.
+    pub webhook: String,
+
+    /// Days before expiry to alert at.
+    #[serde(default = "default_days_before")]
+    pub days_before: Vec<i64>,
+
+    /// Period of the checks, in seconds.
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl ExpiryAlertConfig {
+    pub fn check(&self) -> Result<()> {
+        reqwest::Url::parse(&self.webhook)
+            .with_context(|| format!("Invalid expiry alert webhook {}", self.webhook))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct Alert {
+    name: String,
+    not_after: i64,
+    days_until_expiry: i64,
+    /// The threshold crossed, `None` once expired.
+    threshold_days: Option<i64>,
+    message: String,
+}
+
+/// The alerts sent, to send each only once.
+struct Alerts {
+    days_before: Vec<i64>,
+    /// The expiry and threshold last alerted on, by name. A `None`
+    /// threshold for an expired one.
+    sent: HashMap<String, (i64, Option<i64>)>,
+}
+
+impl Alerts {
+    fn new(days_before: &[i64]) -> Self {
+        Self {
+            days_before: days_before.to_vec(),
+            sent: HashMap::new(),
+        }
+    }
+
+    /// The alert to send for `expiry`, if any.
+    fn due(&self, expiry: &Expiry) -> Option<Alert> {
+        let (threshold, message) = match expiry.days_until_expiry {
+            days if days < 0 => (None, format!("{} has expired", expiry.name)),
+            days => {
+                let threshold = self
+                    .days_before
+                    .iter()
+                    .filter(|threshold| days <= **threshold)
+                    .min()?;
+                (
+                    Some(*threshold),
+                    format!("{} expires in {days} days", expiry.name),
+                )
+            }
+        };
+        let already_sent = match self.sent.get(&expiry.name) {
+            Some((not_after, sent)) if *not_after == expiry.not_after => match (sent, threshold) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(sent), Some(threshold)) => *sent <= threshold,
+            },
+            _ => false,
+        };
+        (!already_sent).then(|| Alert {
+            name: expiry.name.clone(),
+            not_after: expiry.not_after,
+            days_until_expiry: expiry.days_until_expiry,
+            threshold_days: threshold,
+            message,
+        })
+    }
+
+    fn sent(&mut self, alert: &Alert) {
+        self.sent
+            .insert(alert.name.clone(), (alert.not_after, alert.threshold_days));
+    }
+}
+
+/// Check the expiries and send the alerts due, until `shutdown`.
+pub async fn watch(config: ExpiryAlertConfig, mut shutdown: Shutdown) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()
+        .context("Cannot create the webhook client")?;
+    info!("Alerting on collateral expiry to {}", config.webhook);
+
+    let mut alerts = Alerts::new(&config.days_before);
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = shutdown.signalled() => return Ok(()),
+        }
+        for expiry in Service::collateral_expiry() {
+            let Some(alert) = alerts.due(&expiry) else {
+                continue;
+            };
+            warn!("{}", alert.message);
+            match post(&client, &config.webhook, &alert).await {
+                Ok(()) => alerts.sent(&alert),
+                Err(e) => warn!("Expiry alert of {} not sent: {e:#}", alert.name),
+            }
+        }
+    }
+}
+
+async fn post(client: &reqwest::Client, webhook: &str, alert: &Alert) -> Result<()> {
+    client
+        .post(webhook)
+        .json(alert)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expiry(days_until_expiry: i64, not_after: i64) -> Expiry {
+        Expiry {
+            name: "snp.ask".to_string(),
+            not_after,
+            days_until_expiry,
+        }
+    }
+
+    #[test]
+    fn test_due() {
+        let mut alerts = Alerts::new(&default_days_before());
+        assert_eq!(alerts.due(&expiry(45, 1)), None);
+
+        let alert = alerts.due(&expiry(20, 1)).unwrap();
+        assert_eq!(alert.threshold_days, Some(30));
+        assert_eq!(alert.message, "snp.ask expires in 20 days");
+        alerts.sent(&alert);
+        assert_eq!(alerts.due(&expiry(19, 1)), None);
+
+        let alert = alerts.due(&expiry(5, 1)).unwrap();
+        assert_eq!(alert.threshold_days, Some(7));
+        alerts.sent(&alert);
+
+        let alert = alerts.due(&expiry(-1, 1)).unwrap();
+        assert_eq!(alert.threshold_days, None);
+        assert_eq!(alert.message, "snp.ask has expired");
+        alerts.sent(&alert);
+        assert_eq!(alerts.due(&expiry(-2, 1)), None);
+
+        // Renewed, and near expiry again.
+        let alert = alerts.due(&expiry(25, 2)).unwrap();
+        assert_eq!(alert.threshold_days, Some(30));
+    }
+}
@@ -0,0 +1,629 @@
+//! Listener configuration of the gRPC Attestation Service.
+//!
+//! The server can accept requests on several listeners at the same time,
+//! e.g. an IPv4 and an IPv6 TCP socket plus a local Unix domain socket,
+//! each of them with its own TLS settings. Listeners are configured in the
+//! `listeners` section of the AS config file:
+//!
+//! ```json
+//! {
+//!     "listeners": [
+//!         { "address": "0.0.0.0:3000" },
+//!         {
+//!             "address": "[::]:3000",
+//!             "tls": {
+//!                 "cert": "/etc/attestation-service/server.crt",
+//!                 "key": "/etc/attestation-service/server.key",
+//!                 "client_ca": "/etc/attestation-service/ca.crt"
+//!             }
+//!         },
+//!         { "address": "unix:/run/attestation-service/as.sock" }
+//!     ]
+//! }
+//! ```
+//!
+//! In FIPS mode, TLS listeners only negotiate AES-GCM cipher suites with
+//! ECDHE over P-256 or P-384.
+//!
+//! Listeners accept gzip compressed requests, and compress responses for
+//! clients that accept it, which `"compression": []` turns off. This is
+//! standard gRPC compression, so any gRPC client can send large evidence,
+//! e.g. with full IMA logs, compressed.
+//!
+//! On mutual TLS listeners, the identity of the client certificate of an
+//! attestation request is passed to the AS as the submitter of the
+//! evidence, see `attestation_service::submitter`, so that relays can
+//! attest guests on their behalf.
+
+use crate::admin::CertificateAllowList;
+use crate::coap::CoapConfig;
+use crate::expiry::ExpiryAlertConfig;
+use crate::maintenance::MaintenanceConfig;
+use crate::metrics::MetricsConfig;
+use crate::queue::QueueWorkerConfig;
+use crate::reload::ConfigReloadConfig;
+use crate::replication::ReplicationConfig;
+use crate::rest::RestConfig;
+use crate::usage::UsageConfig;
+use anyhow::{anyhow, bail, Context, Result};
+use attestation_service::Submitter;
+use futures::{Stream, StreamExt};
+use log::warn;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use socket2::{Domain, Socket, Type};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, UnixListener};
+use tokio_rustls::rustls::{
+    self, cipher_suite, kx_group, server::AllowAnyAuthenticatedClient, version, RootCertStore,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tonic::codec::CompressionEncoding;
+use tonic::service::Interceptor;
+use tonic::transport::server::Connected;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+use tonic::{Request, Status};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+const UNIX_SCHEME: &str = "unix:";
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// TLS handshakes run concurrently on a listener in FIPS mode.
+const TLS_HANDSHAKES: usize = 64;
+
+/// Time a client has to complete its TLS handshake, so that slow clients
+/// do not hold the handshakes of the others.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// FIPS approved TLS cipher suites.
+const FIPS_CIPHER_SUITES: &[rustls::SupportedCipherSuite] = &[
+    cipher_suite::TLS13_AES_256_GCM_SHA384,
+    cipher_suite::TLS13_AES_128_GCM_SHA256,
+    cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+    cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+    cipher_suite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+    cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+];
+
+/// FIPS approved key exchange groups.
+const FIPS_KX_GROUPS: &[&rustls::SupportedKxGroup] = &[&kx_group::SECP384R1, &kx_group::SECP256R1];
+
+/// gRPC message compression. zstd is not supported by tonic 0.8.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+}
+
+impl Compression {
+    pub fn encoding(self) -> Result<CompressionEncoding> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(CompressionEncoding::Gzip),
+            #[cfg(not(feature = "gzip"))]
+            Compression::Gzip => bail!("grpc-as is built without gzip compression"),
+        }
+    }
+}
+
+/// Compression of the listeners, if not configured.
+fn default_compression() -> Vec<Compression> {
+    match cfg!(feature = "gzip") {
+        true => vec![Compression::Gzip],
+        false => Vec::new(),
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// PEM encoded certificate chain of the server.
+    pub cert: PathBuf,
+
+    /// PEM encoded private key of the server certificate.
+    pub key: PathBuf,
+
+    /// PEM encoded CA certificate(s). If set, clients must authenticate
+    /// with a certificate issued by one of them (mutual TLS).
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    pub fn server_tls_config(&self) -> Result<ServerTlsConfig> {
+        let cert = std::fs::read(&self.cert)
+            .with_context(|| format!("read TLS certificate {}", self.cert.display()))?;
+        let key = std::fs::read(&self.key)
+            .with_context(|| format!("read TLS private key {}", self.key.display()))?;
+
+        let mut config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+        if let Some(path) = &self.client_ca {
+            let ca = std::fs::read(path)
+                .with_context(|| format!("read TLS client CA {}", path.display()))?;
+            config = config.client_ca_root(Certificate::from_pem(ca));
+        }
+
+        Ok(config)
+    }
+
+    /// A TLS acceptor of gRPC that only negotiates FIPS approved
+    /// algorithms.
+    pub fn fips_acceptor(&self) -> Result<TlsAcceptor> {
+        self.acceptor(b"h2")
+    }
+
+    /// A TLS acceptor of HTTP/1.1, with the algorithms of
+    /// [`Self::fips_acceptor`].
+    pub fn http1_acceptor(&self) -> Result<TlsAcceptor> {
+        self.acceptor(b"http/1.1")
+    }
+
+    fn acceptor(&self, alpn: &[u8]) -> Result<TlsAcceptor> {
+        let certs = read_pem(&self.cert, rustls_pemfile::certs)
+            .with_context(|| format!("read TLS certificate {}", self.cert.display()))?;
+        let key = read_pem(&self.key, rustls_pemfile::read_all)
+            .with_context(|| format!("read TLS private key {}", self.key.display()))?
+            .into_iter()
+            .find_map(|item| match item {
+                rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::ECKey(key) => Some(key),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("no private key in {}", self.key.display()))?;
+
+        let builder = rustls::ServerConfig::builder()
+            .with_cipher_suites(FIPS_CIPHER_SUITES)
+            .with_kx_groups(FIPS_KX_GROUPS)
+            .with_protocol_versions(&[&version::TLS13, &version::TLS12])?;
+        let builder = match &self.client_ca {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for ca in read_pem(path, rustls_pemfile::certs)
+                    .with_context(|| format!("read TLS client CA {}", path.display()))?
+                {
+                    roots.add(&rustls::Certificate(ca))?;
+                }
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut config = builder.with_single_cert(
+            certs.into_iter().map(rustls::Certificate).collect(),
+            rustls::PrivateKey(key),
+        )?;
+        config.alpn_protocols = vec![alpn.to_vec()];
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// The submitter identified by the client certificate `der`, the leaf of
+/// the chain the client authenticated with.
+pub fn submitter(der: &[u8]) -> Result<Submitter> {
+    let (_, cert) =
+        X509Certificate::from_der(der).map_err(|e| anyhow!("parse client certificate: {e}"))?;
+    let san = match cert
+        .subject_alternative_name()
+        .map_err(|e| anyhow!("parse client certificate SAN: {e}"))?
+    {
+        Some(san) => san
+            .value
+            .general_names
+            .iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(name) | GeneralName::URI(name) => Some(name.to_string()),
+                _ => None,
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    Ok(Submitter {
+        subject: cert.subject().to_string(),
+        san,
+        fingerprint: hex::encode(Sha256::digest(der)),
+    })
+}
+
+fn read_pem<T>(
+    path: &Path,
+    parse: fn(&mut dyn std::io::BufRead) -> std::io::Result<Vec<T>>,
+) -> Result<Vec<T>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    Ok(parse(&mut reader)?)
+}
+
+/// Accept TLS connections on `incoming`. Connections that fail the
+/// handshake, or do not complete it in time, are dropped.
+pub fn tls_incoming<S, IO>(
+    incoming: S,
+    acceptor: TlsAcceptor,
+) -> impl Stream<Item = std::io::Result<TlsStream<IO>>>
+where
+    S: Stream<Item = std::io::Result<IO>>,
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    incoming
+        .map(move |conn| {
+            let acceptor = acceptor.clone();
+            async move {
+                tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(conn?))
+                    .await
+                    .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))?
+            }
+        })
+        .buffer_unordered(TLS_HANDSHAKES)
+        .filter_map(|conn| async move {
+            match conn {
+                Ok(conn) => Some(Ok(conn)),
+                Err(e) => {
+                    warn!("TLS handshake failed: {e}");
+                    None
+                }
+            }
+        })
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct ListenerConfig {
+    /// Either `<ip>:<port>` (`[<ipv6>]:<port>` for IPv6) for a TCP socket,
+    /// or `unix:<path>` for a Unix domain socket.
+    pub address: String,
+
+    /// Serve plain text if not set.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+
+    /// Accepted request compression, also used for the responses of the
+    /// clients that accept it.
+    #[serde(default = "default_compression")]
+    pub compression: Vec<Compression>,
+
+    /// Let attestation requests ask for verification diagnostics. They
+    /// disclose details of the verification, so they should only be
+    /// enabled on listeners that administrators alone can reach.
+    #[serde(default)]
+    pub diagnostics: bool,
+
+    /// Serve the admin APIs to every client of the listener, see
+    /// [`crate::admin`]. Only for listeners that administrators alone can
+    /// reach.
+    #[serde(default)]
+    pub admin: bool,
+}
+
+/// What the listener a request is received on allows. It intercepts the
+/// requests of the listener, to add itself to their extensions.
+#[derive(Clone, Copy, Debug)]
+pub struct ListenerAccess {
+    pub diagnostics: bool,
+    pub admin: bool,
+}
+
+impl Interceptor for ListenerAccess {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        request.extensions_mut().insert(*self);
+        Ok(request)
+    }
+}
+
+impl ListenerConfig {
+    /// A plain text gRPC listener on `address`.
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            tls: None,
+            compression: default_compression(),
+            diagnostics: false,
+            admin: false,
+        }
+    }
+
+    /// Whether the listener serves TCP without TLS. Unix domain sockets are
+    /// only reachable from the host.
+    pub fn is_plaintext(&self) -> bool {
+        self.tls.is_none() && matches!(self.address.parse(), Ok(ListenAddress::Tcp(_)))
+    }
+}
+
+/// The part of the AS config file read by the gRPC server itself. The rest
+/// of the file is the `attestation_service::config::Config`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ServerConfig {
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+
+    /// Client certificates of the administrators, see [`crate::admin`].
+    #[serde(default)]
+    pub admins: CertificateAllowList,
+
+    /// Client certificates of the warm standbys allowed to subscribe to
+    /// the state, see [`crate::replication`].
+    #[serde(default)]
+    pub standbys: CertificateAllowList,
+
+    /// Per-tenant usage accounting and quotas.
+    #[serde(default)]
+    pub usage: UsageConfig,
+
+    /// Webhook alerts ahead of the expiry of the verification collateral
+    /// and trusted certificates.
+    #[serde(default)]
+    pub expiry_alerts: Option<ExpiryAlertConfig>,
+
+    /// Also consume attestation requests from a message queue, see
+    /// [`crate::queue`].
+    #[serde(default)]
+    pub queue_worker: Option<QueueWorkerConfig>,
+
+    /// Run as a warm standby of a primary server, see
+    /// [`crate::replication`].
+    #[serde(default)]
+    pub replication: Option<ReplicationConfig>,
+
+    /// Periodic pruning of the stores of the AS, see
+    /// [`crate::maintenance`].
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
+
+    /// Also serve constrained attesters over CoAP, see [`crate::coap`].
+    #[serde(default)]
+    pub coap: Option<CoapConfig>,
+
+    /// Also serve clients without a gRPC stack over HTTP/JSON, see
+    /// [`crate::rest`].
+    #[serde(default)]
+    pub rest: Option<RestConfig>,
+
+    /// Also serve the Prometheus metrics of the AS, see [`crate::metrics`].
+    #[serde(default)]
+    pub metrics: Option<MetricsConfig>,
+
+    /// Parameters of the policies, by tenant. They take precedence over
+    /// those of the requests.
+    #[serde(default)]
+    pub tenant_policy_parameters: HashMap<String, Map<String, Value>>,
+
+    /// Log filter, like `info` or `attestation_service=debug`. It takes
+    /// precedence over `RUST_LOG`.
+    #[serde(default)]
+    pub log_level: Option<String>,
+
+    /// Reload the config file while serving, see [`crate::reload`].
+    #[serde(default)]
+    pub config_reload: Option<ConfigReloadConfig>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(address: &str) -> Result<Self> {
+        match address.strip_prefix(UNIX_SCHEME) {
+            Some(path) => {
+                // Accept both `unix:/path` and `unix:///path`.
+                let path = path.strip_prefix("//").unwrap_or(path);
+                if path.is_empty() {
+                    bail!("empty Unix domain socket path in listen address {address}");
+                }
+                Ok(Self::Unix(PathBuf::from(path)))
+            }
+            None => address
+                .parse()
+                .map(Self::Tcp)
+                .map_err(|e| anyhow!("invalid listen address {address}: {e}")),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            Self::Unix(path) => write!(f, "{UNIX_SCHEME}{}", path.display()),
+        }
+    }
+}
+
+impl ListenAddress {
+    pub fn bind(&self) -> Result<BoundSocket> {
+        match self {
+            Self::Tcp(addr) => Ok(BoundSocket::Tcp(bind_tcp(*addr)?)),
+            Self::Unix(path) => Ok(BoundSocket::Unix(bind_unix(path)?)),
+        }
+    }
+}
+
+/// A listening socket, bound by the server or passed by systemd.
+#[derive(Debug)]
+pub enum BoundSocket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// Bind a TCP listener. IPv6 sockets are bound as IPv6 only, so that an
+/// IPv4 and an IPv6 listener can share the same port.
+pub fn bind_tcp(addr: SocketAddr) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket
+        .bind(&addr.into())
+        .with_context(|| format!("bind {addr}"))?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// Bind a Unix domain socket listener, replacing a stale socket left by a
+/// previous run. Anything else at `path` is left alone, and fails.
+pub fn bind_unix(path: &Path) -> Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)
+            .with_context(|| format!("remove stale socket {}", path.display()))?,
+        Ok(_) => bail!("{} exists and is not a socket", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("stat {}", path.display())),
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    UnixListener::bind(path).with_context(|| format!("bind {}", path.display()))
+}
+
+/// A Unix domain socket connection that can be served by tonic.
+#[derive(Debug)]
+pub struct UnixStream(pub tokio::net::UnixStream);
+
+impl Connected for UnixStream {
+    type ConnectInfo = ();
+
+    fn connect_info(&self) -> Self::ConnectInfo {}
+}
+
+impl AsyncRead for UnixStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UnixStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bind_unix() {
+        let path = std::env::temp_dir().join(format!("grpc-as-bind-{}.sock", std::process::id()));
+        let listener = bind_unix(&path).unwrap();
+        drop(listener);
+        // The socket left by a previous run is replaced.
+        bind_unix(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        std::fs::write(&path, "not a socket").unwrap();
+        let e = bind_unix(&path).unwrap_err();
+        assert!(e.to_string().ends_with("exists and is not a socket"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn parse_listen_address() {
+        assert_eq!(
+            "127.0.0.1:3000".parse::<ListenAddress>().unwrap(),
+            ListenAddress::Tcp("127.0.0.1:3000".parse().unwrap())
+        );
+        assert_eq!(
+            "[::]:3000".parse::<ListenAddress>().unwrap(),
+            ListenAddress::Tcp("[::]:3000".parse().unwrap())
+        );
+        assert_eq!(
+            "unix:/run/as.sock".parse::<ListenAddress>().unwrap(),
+            ListenAddress::Unix(PathBuf::from("/run/as.sock"))
+        );
+        assert_eq!(
+            "unix:///run/as.sock".parse::<ListenAddress>().unwrap(),
+            ListenAddress::Unix(PathBuf::from("/run/as.sock"))
+        );
+        assert_eq!(
+            "unix:///run/as.sock"
+                .parse::<ListenAddress>()
+                .unwrap()
+                .to_string(),
+            "unix:/run/as.sock"
+        );
+        assert!("unix:".parse::<ListenAddress>().is_err());
+        assert!("localhost".parse::<ListenAddress>().is_err());
+    }
+
+    #[test]
+    fn parse_listeners() {
+        let config: ServerConfig = serde_json::from_str(
+            r#"{
+                "work_dir": "/var/lib/attestation-service/",
+                "listeners": [
+                    { "address": "0.0.0.0:3000" },
+                    {
+                        "address": "[::]:3000",
+                        "tls": { "cert": "server.crt", "key": "server.key" },
+                        "compression": [],
+                        "diagnostics": true
+                    },
+                    { "address": "unix:/run/as-admin.sock", "admin": true }
+                ],
+                "admins": { "subjects": ["CN=as-admin"] }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.listeners.len(), 3);
+        assert_eq!(config.listeners[0], ListenerConfig::new("0.0.0.0:3000"));
+        assert_eq!(
+            config.listeners[1].tls,
+            Some(TlsConfig {
+                cert: PathBuf::from("server.crt"),
+                key: PathBuf::from("server.key"),
+                client_ca: None,
+            })
+        );
+        assert!(config.listeners[1].compression.is_empty());
+        assert!(config.listeners[1].diagnostics);
+        assert!(!config.listeners[1].admin);
+        assert!(config.listeners[2].admin);
+        assert_eq!(config.admins.subjects, ["CN=as-admin"]);
+        assert!(config.listeners[0].is_plaintext());
+        assert!(!config.listeners[1].is_plaintext());
+        assert!(!ListenerConfig::new("unix:/run/as.sock").is_plaintext());
+        assert!(serde_json::from_str::<ListenerConfig>(
+            r#"{ "address": "0.0.0.0:3000", "compression": ["zstd"] }"#
+        )
+        .is_err());
+    }
+}
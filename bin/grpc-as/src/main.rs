@@ -1,6 +1,8 @@
 use anyhow::Result;
+use attestation_service::config::Config;
 use clap::{App, Arg};
 use shadow_rs::shadow;
+use std::path::PathBuf;
 
 pub mod as_api {
     tonic::include_proto!("attestation");
@@ -12,12 +14,29 @@ pub mod rvps_api {
 
 shadow!(build);
 
+mod admin;
+mod advisories;
+mod claims;
+mod coap;
+mod content;
+mod expiry;
+mod listener;
+mod maintenance;
+mod metrics;
+mod prefetch;
+mod queue;
+mod reference_values;
+mod reload;
+mod replication;
+mod rest;
 mod server;
+mod streaming;
+mod supervisor;
+mod systemd;
+mod usage;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-
     let version = format!(
         "\nv{}\ncommit: {}\nbuildtime: {}",
         build::PKG_VERSION,
@@ -33,7 +52,7 @@ async fn main() -> Result<()> {
             Arg::with_name("socket")
                 .long("socket")
                 .value_name("SOCKET")
-                .help("Socket that the server will listen on to accept requests.")
+                .help("Socket that the server will listen on to accept requests. Overrides the listeners of the config file.")
                 .takes_value(true),
         )
         .arg(
@@ -48,15 +67,73 @@ async fn main() -> Result<()> {
             Arg::with_name("config")
                 .long("config")
                 .value_name("config")
-                .help("File path of AS config (JSON), left blank to use default config")
+                .help("File path of AS config (JSON, or TOML if it ends with .toml), left blank to use default config")
                 .required(false)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("set")
+                .long("set")
+                .value_name("KEY=VALUE")
+                .help("Override a value of the config, like `listeners.0.address=0.0.0.0:50004`. Takes precedence over the config file and the AS_CONFIG_* env vars")
+                .takes_value(true)
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .value_name("LEVEL")
+                .help("Log filter, like `info` or `attestation_service=debug`. Same as `--set log_level=LEVEL`")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("check-config")
+                .long("check-config")
+                .help("Validate the config, load the keys and probe the RVPS and the endpoints the AS fetches from, then exit without serving")
+                .takes_value(false),
+        )
         .get_matches();
 
+    let config_source = reload::ConfigSource {
+        path: matches.value_of("config").map(PathBuf::from),
+        overrides: matches
+            .values_of("set")
+            .into_iter()
+            .flatten()
+            .map(String::from)
+            .collect(),
+        log_level: matches.value_of("log-level").map(String::from),
+    };
+    let layers = config_source.layers()?;
+    let config: Config = layers.load()?;
+    let server_config: listener::ServerConfig = layers.load()?;
+
+    match &server_config.log_level {
+        Some(level) => env_logger::Builder::new().parse_filters(level).init(),
+        None => env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+            .init(),
+    }
+
     let rvps_addr = matches.value_of("rvps-addr");
-    let config_path = matches.value_of("config");
-    let server = server::start(matches.value_of("socket"), rvps_addr, config_path);
+    if matches.is_present("check-config") {
+        server::check_config(
+            matches.value_of("socket"),
+            rvps_addr,
+            &config,
+            &server_config,
+        )
+        .await?;
+        println!("Configuration is valid");
+        return Ok(());
+    }
+    let server = server::start(
+        matches.value_of("socket"),
+        rvps_addr,
+        config,
+        config_source,
+        layers,
+        server_config,
+    );
     tokio::try_join!(server)?;
 
     Ok(())
@@ -0,0 +1,73 @@
+//! Periodic maintenance of the stores of the AS, see
+//! `attestation_service::maintenance`, so that a long-running server does
+//! not grow without bound. It runs every hour by default, as configured in
+//! the `maintenance` section of the AS config file, and not at all with an
+//! `interval_secs` of 0:
+//!
+//! ```json
+//! "maintenance": { "interval_secs": 3600 }
+//! ```
+//!
+//! The totals of the runs are served by the `GetMaintenanceStats` API. A
+//! run in progress completes before the server shuts down.
+
+use anyhow::Result;
+use log::{info, warn};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use crate::server::AttestationServer;
+use crate::supervisor::Shutdown;
+
+fn default_interval_secs() -> u64 {
+    3600
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct MaintenanceConfig {
+    /// Period of the maintenance runs, in seconds, 0 to turn them off.
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_interval_secs(),
+        }
+    }
+}
+
+/// Run the maintenance of the AS of `server`, until `shutdown`.
+pub async fn run(
+    config: MaintenanceConfig,
+    server: Arc<RwLock<AttestationServer>>,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    if config.interval_secs == 0 {
+        return Ok(());
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+    // The first tick completes right away, the stores are fresh at startup.
+    interval.tick().await;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = shutdown.signalled() => return Ok(()),
+        }
+        match server.write().await.attestation_service.maintain().await {
+            Ok(report) => info!(
+                "Maintenance: {} replay digests, {} expired nonces, {} expired reference \
+                 values, {} history records pruned, {} bytes reclaimed",
+                report.replay_entries,
+                report.nonces,
+                report.reference_values,
+                report.history_records,
+                report.reclaimed_bytes
+            ),
+            Err(e) => warn!("Maintenance failed: {e:#}"),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Prometheus metrics endpoint.
+//!
+//! With `metrics` in the AS config file, the server also serves the
+//! metrics of the AS, see `attestation_service::metrics`, in the
+//! Prometheus text format at `GET /metrics` on its own HTTP listener:
+//!
+//! ```json
+//! "metrics": { "address": "0.0.0.0:9090" }
+//! ```
+//!
+//! The listener has no TLS and no authentication, and is meant for the
+//! scraper on the internal network. It is reported as plaintext by the
+//! security posture.
+
+use anyhow::{anyhow, Result};
+use axum::http::header::CONTENT_TYPE;
+use axum::routing::get;
+use axum::Router;
+use log::{debug, info};
+use serde::Deserialize;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use crate::server::AttestationServer;
+use crate::supervisor::Shutdown;
+
+/// Time after which a scrape that did not send its request is dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const TEXT_FORMAT: &str = "text/plain; version=0.0.4";
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct MetricsConfig {
+    /// `<ip>:<port>` of the HTTP listener, e.g. `0.0.0.0:9090`.
+    pub address: String,
+}
+
+impl MetricsConfig {
+    pub fn address(&self) -> Result<SocketAddr> {
+        self.address
+            .parse()
+            .map_err(|e| anyhow!("Invalid metrics address {}: {e}", self.address))
+    }
+}
+
+/// Serve the metrics of `server` until the listener fails, or `shutdown`.
+pub async fn serve(
+    config: MetricsConfig,
+    server: Arc<RwLock<AttestationServer>>,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    let address = config.address()?;
+    let app = router(move || {
+        let server = server.clone();
+        async move { server.read().await.attestation_service.metrics() }
+    });
+    info!("Metrics listen to {address}");
+    axum::Server::try_bind(&address)
+        .map_err(|e| anyhow!("Cannot bind the metrics address {address}: {e}"))?
+        .http1_header_read_timeout(REQUEST_TIMEOUT)
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(async move { shutdown.signalled().await })
+        .await
+        .map_err(|e| anyhow!("Metrics server: {e}"))?;
+    debug!("Metrics server stopped");
+    Ok(())
+}
+
+/// `GET /metrics`, answered with the metrics that `render` renders. Other
+/// methods get a 405 and other paths a 404.
+fn router<F, R>(render: F) -> Router
+where
+    F: Fn() -> R + Clone + Send + Sync + 'static,
+    R: Future<Output = String> + Send,
+{
+    Router::new().route(
+        "/metrics",
+        get(move || {
+            let render = render.clone();
+            async move { ([(CONTENT_TYPE, TEXT_FORMAT)], render().await) }
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    #[tokio::test]
+    async fn test_router() {
+        let app = router(|| async { "attestation_tokens_issued_total 1\n".to_string() });
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        let client = reqwest::Client::new();
+        let response = client.get(format!("{url}/metrics")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], TEXT_FORMAT);
+        assert_eq!(
+            response.text().await.unwrap(),
+            "attestation_tokens_issued_total 1\n"
+        );
+        let response = client
+            .get(format!("{url}/metrics?name[]=x"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = client.post(format!("{url}/metrics")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let response = client.get(format!("{url}/")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_address() {
+        let config = MetricsConfig {
+            address: "0.0.0.0".to_string(),
+        };
+        assert!(config
+            .address()
+            .unwrap_err()
+            .to_string()
+            .starts_with("Invalid metrics address 0.0.0.0"));
+    }
+}
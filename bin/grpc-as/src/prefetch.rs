@@ -0,0 +1,47 @@
+//! Prefetch of the DCAP collateral of the registered platforms, see
+//! `attestation_service::verifier::collateral`, so that the first
+//! attestation of a platform of the fleet, after a restart too, does not
+//! wait for its collateral to be fetched. It runs at startup, then every
+//! `prefetch_interval_secs` of the `collateral` section of the AS config,
+//! and not at all with 0. Platforms are registered with the
+//! `RegisterPlatforms` API.
+
+use anyhow::Result;
+use attestation_service::verifier::collateral::Provider;
+use log::{info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::supervisor::Shutdown;
+
+/// Prefetch the collateral of the platforms registered with `provider`
+/// every `interval_secs`, until `shutdown`.
+pub async fn run(
+    interval_secs: u64,
+    provider: Arc<Provider>,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    if interval_secs == 0 {
+        return Ok(());
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = shutdown.signalled() => return Ok(()),
+        }
+        let platforms = provider.prefetch().await;
+        let failed = platforms
+            .iter()
+            .filter(|platform| platform.error.is_some())
+            .count();
+        match failed {
+            0 if platforms.is_empty() => (),
+            0 => info!("Collateral of {} platforms prefetched", platforms.len()),
+            _ => warn!(
+                "Collateral of {failed} of {} platforms could not be prefetched",
+                platforms.len()
+            ),
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! Attestation requests consumed from a message queue.
+//!
+//! For batch pipelines that verify large volumes of stored evidence, like
+//! nightly fleet audits, the server can also pull attestation requests
+//! from a NATS subject, as a member of a queue group so that several
+//! servers share the load, and publish the results to the reply subject
+//! of each request, or to a configured reply subject.
+//!
+//! Requests and results are JSON documents:
+//!
+//! ```json
+//! {"id": "node-42", "tee": "tdx", "nonce": "...", "evidence": "..."}
+//! {"id": "node-42", "token": "...", "warnings": []}
+//! {"id": "node-42", "error": "..."}
+//! ```
+//!
+//! Only the core NATS protocol over plain TCP is spoken, enough to
+//! subscribe and publish.
+
+use anyhow::{anyhow, bail, Context, Result};
+use attestation_service::{EvaluateOptions, Tee};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{Mutex, RwLock, Semaphore};
+
+use crate::server::AttestationServer;
+use crate::supervisor::Shutdown;
+
+const NATS_SCHEME: &str = "nats://";
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Largest request accepted, evidence with its eventlog included.
+const MAX_PAYLOAD: usize = 16 * 1024 * 1024;
+
+fn default_queue_group() -> String {
+    "attestation-service".to_string()
+}
+
+fn default_concurrency() -> usize {
+    16
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct QueueWorkerConfig {
+    /// NATS server, e.g. `nats://127.0.0.1:4222`.
+    pub url: String,
+
+    /// Subject the attestation requests are published to.
+    pub subject: String,
+
+    /// Queue group of the servers sharing the requests.
+    #[serde(default = "default_queue_group")]
+    pub queue_group: String,
+
+    /// Subject the results are published to when a request has no reply
+    /// subject of its own. Such requests are dropped if not set.
+    #[serde(default)]
+    pub reply_subject: Option<String>,
+
+    /// Requests evaluated at the same time.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+}
+
+impl QueueWorkerConfig {
+    /// The `host:port` of the NATS server.
+    pub fn address(&self) -> Result<&str> {
+        self.url
+            .strip_prefix(NATS_SCHEME)
+            .filter(|address| !address.is_empty())
+            .ok_or_else(|| anyhow!("Queue URL {} is not nats://<host>:<port>", self.url))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct QueueRequest {
+    /// Given back in the result, to match it with the request.
+    #[serde(default)]
+    id: Option<String>,
+    tee: Tee,
+    nonce: String,
+    evidence: String,
+    #[serde(default)]
+    previous_token: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+struct QueueResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// A message of the NATS server.
+#[derive(Debug, PartialEq, Eq)]
+enum ServerOp {
+    Msg {
+        subject: String,
+        reply_to: Option<String>,
+        payload: Vec<u8>,
+    },
+    Ping,
+    Err(String),
+    /// `INFO`, `+OK` and `PONG`, nothing to do.
+    Other,
+}
+
+/// The subject, reply subject and payload size of a `MSG` line.
+fn parse_msg(args: &str) -> Result<(String, Option<String>, usize)> {
+    let args: Vec<_> = args.split_whitespace().collect();
+    let (subject, reply_to, size) = match args[..] {
+        [subject, _sid, size] => (subject, None, size),
+        [subject, _sid, reply_to, size] => (subject, Some(reply_to.to_string()), size),
+        _ => bail!("Malformed NATS message {}", args.join(" ")),
+    };
+    let size = size
+        .parse()
+        .with_context(|| format!("Malformed NATS message size {size}"))?;
+    if size > MAX_PAYLOAD {
+        bail!("NATS message of {size} bytes is too large");
+    }
+    Ok((subject.to_string(), reply_to, size))
+}
+
+async fn read_op(reader: &mut BufReader<OwnedReadHalf>) -> Result<ServerOp> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        bail!("Connection closed by the NATS server");
+    }
+    let line = line.trim_end();
+    let (op, args) = line.split_once(' ').unwrap_or((line, ""));
+    match op.to_ascii_uppercase().as_str() {
+        "MSG" => {
+            let (subject, reply_to, size) = parse_msg(args)?;
+            // The payload is followed by CRLF.
+            let mut payload = vec![0; size + 2];
+            reader.read_exact(&mut payload).await?;
+            payload.truncate(size);
+            Ok(ServerOp::Msg {
+                subject,
+                reply_to,
+                payload,
+            })
+        }
+        "PING" => Ok(ServerOp::Ping),
+        "-ERR" => Ok(ServerOp::Err(args.trim_matches('\'').to_string())),
+        "INFO" | "+OK" | "PONG" => Ok(ServerOp::Other),
+        _ => bail!("Unexpected NATS message {line}"),
+    }
+}
+
+async fn publish(writer: &Mutex<OwnedWriteHalf>, subject: &str, payload: &[u8]) -> Result<()> {
+    let mut message = format!("PUB {subject} {}\r\n", payload.len()).into_bytes();
+    message.extend_from_slice(payload);
+    message.extend_from_slice(b"\r\n");
+    writer.lock().await.write_all(&message).await?;
+    Ok(())
+}
+
+async fn evaluate(server: &RwLock<AttestationServer>, payload: &[u8]) -> QueueResult {
+    let request: QueueRequest = match serde_json::from_slice(payload) {
+        Ok(request) => request,
+        Err(e) => {
+            return QueueResult {
+                error: Some(format!("Malformed attestation request: {e}")),
+                ..Default::default()
+            }
+        }
+    };
+    let evaluation = server
+        .read()
+        .await
+        .attestation_service
+        .evaluate_with_options(
+            request.tee,
+            &request.nonce,
+            &request.evidence,
+            EvaluateOptions {
+                previous_token: request.previous_token,
+                ..Default::default()
+            },
+        )
+        .await;
+    match evaluation {
+        Ok(evaluation) => QueueResult {
+            id: request.id,
+            token: Some(evaluation.token),
+            warnings: evaluation.warnings,
+            error: None,
+        },
+        Err(e) => QueueResult {
+            id: request.id,
+            error: Some(format!("Attestation: {e:#}")),
+            ..Default::default()
+        },
+    }
+}
+
+/// Consume the attestation requests of the queue, reconnecting to the
+/// NATS server whenever the connection is lost, until `shutdown`.
+pub async fn consume(
+    config: QueueWorkerConfig,
+    server: Arc<RwLock<AttestationServer>>,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    loop {
+        tokio::select! {
+            consumed = consume_connection(&config, server.clone()) => {
+                if let Err(e) = consumed {
+                    warn!("Queue worker: {e:#}, reconnecting");
+                }
+            }
+            _ = shutdown.signalled() => return Ok(()),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(RECONNECT_DELAY) => (),
+            _ = shutdown.signalled() => return Ok(()),
+        }
+    }
+}
+
+async fn consume_connection(
+    config: &QueueWorkerConfig,
+    server: Arc<RwLock<AttestationServer>>,
+) -> Result<()> {
+    let address = config.address()?;
+    let stream = TcpStream::connect(address)
+        .await
+        .with_context(|| format!("Cannot connect to {}", config.url))?;
+    let (reader, writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let writer = Arc::new(Mutex::new(writer));
+
+    let connect = serde_json::json!({
+        "verbose": false,
+        "pedantic": false,
+        "name": "attestation-service",
+        "lang": "rust",
+        "version": env!("CARGO_PKG_VERSION"),
+    });
+    let subscribe = format!(
+        "CONNECT {connect}\r\nSUB {} {} 1\r\n",
+        config.subject, config.queue_group
+    );
+    writer.lock().await.write_all(subscribe.as_bytes()).await?;
+    info!(
+        "Consuming attestation requests of {} on {}",
+        config.subject, config.url
+    );
+
+    let permits = Arc::new(Semaphore::new(config.concurrency.max(1)));
+    loop {
+        let (subject, reply_to, payload) = match read_op(&mut reader).await? {
+            ServerOp::Msg {
+                subject,
+                reply_to,
+                payload,
+            } => (subject, reply_to, payload),
+            ServerOp::Ping => {
+                writer.lock().await.write_all(b"PONG\r\n").await?;
+                continue;
+            }
+            ServerOp::Err(e) => bail!("NATS server error: {e}"),
+            ServerOp::Other => continue,
+        };
+        let Some(reply_to) = reply_to.or_else(|| config.reply_subject.clone()) else {
+            warn!("Attestation request of {subject} dropped, it has no reply subject");
+            continue;
+        };
+
+        // Stop reading requests while `concurrency` are evaluated.
+        let permit = permits.clone().acquire_owned().await?;
+        let server = server.clone();
+        let writer = writer.clone();
+        tokio::spawn(async move {
+            let result = evaluate(&server, &payload).await;
+            debug!("Attestation result to {reply_to}: {result:?}");
+            let result = serde_json::to_vec(&result).unwrap_or_default();
+            if let Err(e) = publish(&writer, &reply_to, &result).await {
+                warn!("Attestation result to {reply_to} not published: {e:#}");
+            }
+            drop(permit);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_msg() {
+        assert_eq!(
+            parse_msg("as.requests 1 12").unwrap(),
+            ("as.requests".to_string(), None, 12)
+        );
+        assert_eq!(
+            parse_msg("as.requests 1 _INBOX.abc 12").unwrap(),
+            (
+                "as.requests".to_string(),
+                Some("_INBOX.abc".to_string()),
+                12
+            )
+        );
+        assert!(parse_msg("as.requests 12").is_err());
+        assert!(parse_msg("as.requests 1 many").is_err());
+        assert!(parse_msg(&format!("as.requests 1 {}", MAX_PAYLOAD + 1)).is_err());
+    }
+
+    #[test]
+    fn test_address() {
+        let config: QueueWorkerConfig =
+            serde_json::from_str(r#"{"url": "nats://127.0.0.1:4222", "subject": "as.requests"}"#)
+                .unwrap();
+        assert_eq!(config.address().unwrap(), "127.0.0.1:4222");
+        assert_eq!(config.queue_group, "attestation-service");
+        assert_eq!(config.concurrency, 16);
+
+        let config = QueueWorkerConfig {
+            url: "kafka://127.0.0.1:9092".to_string(),
+            ..config
+        };
+        assert!(config.address().is_err());
+    }
+}
@@ -0,0 +1,41 @@
+//! Hot-reload of the bundles of reference values dropped in the watched
+//! directory of the `reference_values_watch` section of the AS config, see
+//! `attestation_service::rvps::watch`. It scans the directory at startup,
+//! then every `poll_secs`. Warm standbys do not ingest the bundles, they
+//! replicate the reference values of their primary.
+//!
+//! A reload of the config that changes the section applies at once, see
+//! [`crate::reload`], but one that adds it to a config without it applies
+//! on restart.
+
+use anyhow::Result;
+use log::warn;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::server::AttestationServer;
+use crate::supervisor::Shutdown;
+
+/// Reload the watched bundles of reference values of the AS of `server`,
+/// until `shutdown`.
+pub async fn watch(server: Arc<RwLock<AttestationServer>>, mut shutdown: Shutdown) -> Result<()> {
+    loop {
+        // The period is read at each scan, as a reload of the config may
+        // change it.
+        let Some(period) = server
+            .read()
+            .await
+            .attestation_service
+            .reference_values_watch_interval()
+        else {
+            return Ok(());
+        };
+        if let Err(e) = server.write().await.reload_reference_values().await {
+            warn!("Reload of the reference values failed: {e:#}");
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(period) => (),
+            _ = shutdown.signalled() => return Ok(()),
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! Hot-reload of the config file, and validation of candidate configs.
+//!
+//! With `config_reload` in the config file, the server layers its config
+//! again every `poll_secs`, 10 by default, from the config file, the env
+//! vars and the overrides of the command line, see
+//! `attestation_service::config::ConfigLayers`:
+//!
+//! ```json
+//! "config_reload": { "poll_secs": 10 }
+//! ```
+//!
+//! A changed config is checked first, as by `--check-config`, with the
+//! endpoints it fetches from probed, and against the state of the AS: the
+//! policies it evaluates must be installed and its token signing keys must
+//! load. A config that fails is logged once and the running config is
+//! kept. Otherwise the sections of `RELOADABLE_SECTIONS` of the AS config
+//! and `tenant_policy_parameters` apply right away, and the changes of the
+//! other sections are logged as taking effect on restart, such as the
+//! `trust_anchors` and `collateral` endpoints the verifiers load on start.
+//!
+//! The `ValidateConfig` API checks a candidate config file, and tells
+//! which of its sections would be reloaded and which need a restart,
+//! without applying it. As the candidate comes from a client, it is only
+//! checked statically, see [`check_candidate_statically`]: the files it
+//! names are not read, nor its endpoints probed, lest the API reaches
+//! files and hosts on behalf of the client.
+
+use anyhow::{bail, Context, Result};
+use attestation_service::config::{Config, ConfigChanges, ConfigLayers, RELOADABLE_SECTIONS};
+use log::warn;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use crate::listener::ServerConfig;
+use crate::server::{check_config, check_server_config, AttestationServer};
+use crate::supervisor::Shutdown;
+
+/// Sections of the server config applied without a restart.
+const SERVER_RELOADABLE_SECTIONS: &[&str] = &["tenant_policy_parameters"];
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn default_poll_secs() -> u64 {
+    10
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct ConfigReloadConfig {
+    /// Period of the reloads of the config file, in seconds.
+    #[serde(default = "default_poll_secs")]
+    pub poll_secs: u64,
+}
+
+impl ConfigReloadConfig {
+    pub fn check(&self) -> Result<()> {
+        if self.poll_secs == 0 {
+            bail!("config_reload.poll_secs must be positive");
+        }
+        Ok(())
+    }
+}
+
+/// Where the config of the server is layered from.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigSource {
+    pub path: Option<PathBuf>,
+    /// `<key>=<value>` overrides of the command line.
+    pub overrides: Vec<String>,
+    /// Log filter of the command line.
+    pub log_level: Option<String>,
+}
+
+impl ConfigSource {
+    /// The config, layered from the config file.
+    pub fn layers(&self) -> Result<ConfigLayers> {
+        self.layer(ConfigLayers::new(self.path.as_deref())?)
+    }
+
+    /// The config, layered from the candidate config file `document`
+    /// instead of the config file.
+    pub fn candidate(&self, document: &str) -> Result<ConfigLayers> {
+        self.layer(ConfigLayers::from_document(document)?)
+    }
+
+    fn layer(&self, layers: ConfigLayers) -> Result<ConfigLayers> {
+        // Defaults < config file < env vars < command line.
+        let mut layers = layers.with_env(std::env::vars())?;
+        for assignment in &self.overrides {
+            layers = layers.with_override(assignment)?;
+        }
+        if let Some(level) = &self.log_level {
+            layers.set("log_level", level.as_str().into())?;
+        }
+        Ok(layers)
+    }
+}
+
+/// A checked candidate config, and how it differs from the running one.
+pub struct ConfigUpdate {
+    pub layers: ConfigLayers,
+    pub config: Config,
+    pub server_config: ServerConfig,
+    pub changes: ConfigChanges,
+}
+
+/// The sections of the AS and server configs applied without a restart.
+pub fn reloadable_sections() -> Vec<&'static str> {
+    RELOADABLE_SECTIONS
+        .iter()
+        .chain(SERVER_RELOADABLE_SECTIONS)
+        .copied()
+        .collect()
+}
+
+/// Check the candidate config `layers` as `--check-config` does, and
+/// probe the endpoints it fetches from.
+pub async fn check_candidate(layers: &ConfigLayers) -> Result<(Config, ServerConfig)> {
+    let config: Config = layers.load()?;
+    let server_config: ServerConfig = layers.load()?;
+    check_config(None, None, &config, &server_config).await?;
+    Ok((config, server_config))
+}
+
+/// Check the candidate config `layers` without reading the files it names
+/// nor connecting to its endpoints, and return the AS config.
+pub fn check_candidate_statically(layers: &ConfigLayers) -> Result<Config> {
+    let config: Config = layers.load()?;
+    let server_config: ServerConfig = layers.load()?;
+    config.check_static()?;
+    check_server_config(None, &server_config)?;
+    Ok(config)
+}
+
+/// The HTTP endpoints that the AS of `config` fetches from.
+fn endpoints(config: &Config) -> Vec<&str> {
+    let collateral = [
+        config.collateral.pccs_url.as_deref(),
+        config.collateral.root_ca_crl_url.as_deref(),
+    ];
+    let advisory_feed = config
+        .advisory_feed
+        .as_ref()
+        .map(|feed| feed.source.as_str())
+        .filter(|source| source.starts_with("https://") || source.starts_with("http://"));
+    collateral
+        .into_iter()
+        .chain([advisory_feed])
+        .flatten()
+        .collect()
+}
+
+/// Check that the HTTP endpoints of `config` answer, whatever their
+/// status.
+pub async fn probe_endpoints(config: &Config) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        .build()
+        .context("create HTTP client")?;
+    for endpoint in endpoints(config) {
+        client
+            .get(endpoint)
+            .send()
+            .await
+            .with_context(|| format!("Endpoint {endpoint} is not reachable"))?;
+    }
+    Ok(())
+}
+
+/// Reload the config of `server` every `period`, until `shutdown`.
+pub async fn watch(
+    server: Arc<RwLock<AttestationServer>>,
+    period: Duration,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    let Some(source) = server.read().await.config_source.clone() else {
+        return Ok(());
+    };
+    let mut interval = tokio::time::interval(period);
+    // The last error, logged once rather than at every poll.
+    let mut last_error = None;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = shutdown.signalled() => return Ok(()),
+        }
+        let result = match source.layers() {
+            Ok(layers) => {
+                let running = server.read().await.config_digest();
+                if running == Some(layers.digest()) {
+                    last_error = None;
+                    continue;
+                }
+                reload(&server, layers).await
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => last_error = None,
+            Err(e) => {
+                let error = format!("{e:#}");
+                if last_error.as_ref() != Some(&error) {
+                    warn!("Config not reloaded, the running config is kept: {error}");
+                    last_error = Some(error);
+                }
+            }
+        }
+    }
+}
+
+async fn reload(server: &RwLock<AttestationServer>, layers: ConfigLayers) -> Result<()> {
+    // The endpoints are probed without holding the server.
+    let (config, server_config) = check_candidate(&layers).await?;
+    let update = server
+        .read()
+        .await
+        .config_update(layers, config, server_config)
+        .await?;
+    server.write().await.apply_config_update(update).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source() {
+        let source = ConfigSource {
+            path: None,
+            overrides: vec!["attestation_token_config.duration_min=10".to_string()],
+            log_level: Some("debug".to_string()),
+        };
+        let current = source.layers().unwrap();
+        let candidate = source
+            .candidate(
+                r#"
+migration_policy = "td-migration"
+
+[attestation_token_config]
+duration_min = 30
+
+[tenant_policy_parameters.acme]
+min_svn = 3
+"#,
+            )
+            .unwrap();
+        // The overrides of the command line still apply.
+        let config: Config = candidate.load().unwrap();
+        assert_eq!(config.attestation_token_config.duration_min, 10);
+        assert_eq!(config.migration_policy, "td-migration");
+        let server_config: ServerConfig = candidate.load().unwrap();
+        assert_eq!(server_config.log_level.as_deref(), Some("debug"));
+
+        let changes = current.changes(&candidate, &reloadable_sections());
+        assert_eq!(
+            changes.reloaded,
+            ["migration_policy", "tenant_policy_parameters"]
+        );
+        assert!(changes.restart_needed.is_empty());
+        let candidate = source
+            .candidate(r#"{"listeners": [{"address": "0.0.0.0:3000"}]}"#)
+            .unwrap();
+        let changes = current.changes(&candidate, &reloadable_sections());
+        assert_eq!(changes.restart_needed, ["listeners"]);
+    }
+
+    #[test]
+    fn test_check_candidate_statically() {
+        let source = ConfigSource::default();
+        // The files are not read, nor the endpoints probed.
+        let candidate = source
+            .candidate(
+                r#"{
+                    "state_signers": ["/nonexistent/primary-keys.json"],
+                    "collateral": {"pccs_url": "https://192.0.2.1:8081"}
+                }"#,
+            )
+            .unwrap();
+        check_candidate_statically(&candidate).unwrap();
+        let candidate = source
+            .candidate(r#"{"listeners": [{"address": "not an address"}]}"#)
+            .unwrap();
+        assert!(check_candidate_statically(&candidate).is_err());
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let mut config = Config::default();
+        assert!(endpoints(&config).is_empty());
+        config.collateral.pccs_url = Some("https://pccs.example:8081".to_string());
+        config.advisory_feed = serde_json::from_value(serde_json::json!({
+            "source": "/etc/attestation-service/advisories.json"
+        }))
+        .unwrap();
+        assert_eq!(endpoints(&config), ["https://pccs.example:8081"]);
+        config.advisory_feed = serde_json::from_value(serde_json::json!({
+            "source": "https://security.example.com/advisories.json"
+        }))
+        .unwrap();
+        assert_eq!(endpoints(&config).len(), 2);
+    }
+}
@@ -0,0 +1,204 @@
+//! Warm standby replication of the policies and reference values.
+//!
+//! A secondary server configured with `replication` subscribes to the
+//! state of a primary with `SubscribeState`. The primary streams its state,
+//! as exported by `ExportState`, when the subscription starts and after
+//! every change of its policies, policy data or reference values, and the
+//! standby imports each snapshot. The standby keeps verifying evidence with
+//! the replicated state, and refuses changes of its own, so it can take
+//! over with a warm state as soon as it is restarted without `replication`,
+//! without a database shared with the primary:
+//!
+//! ```json
+//! "replication": {
+//!     "primary": "https://as-primary.example.com:50004",
+//!     "tls": {
+//!         "cert": "/etc/attestation-service/standby.crt",
+//!         "key": "/etc/attestation-service/standby.key",
+//!         "ca": "/etc/attestation-service/primary-ca.crt"
+//!     }
+//! }
+//! ```
+//!
+//! The standby connects to the primary over TLS, and authenticates with the
+//! client certificate of `tls`, as the primary only streams its state to
+//! the certificates of its `standbys`, see [`crate::admin`]:
+//!
+//! ```json
+//! "standbys": { "subjects": ["CN=as-standby, O=Example"] }
+//! ```
+//!
+//! Snapshots are signed by the primary, and only imported if its public
+//! keys, as returned by its `GetSigningKeys`, are in the `state_signers` of
+//! the AS config of the standby, see `attestation_service::backup`. Token
+//! signing keys are not replicated: relying parties must trust the keys of
+//! both servers, or the same key must be imported into both.
+
+use anyhow::{bail, Context, Result};
+use attestation_service::config::Config;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+
+use crate::as_api::attestation_service_client::AttestationServiceClient;
+use crate::as_api::SubscribeStateRequest;
+use crate::server::AttestationServer;
+use crate::supervisor::Shutdown;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct ReplicationConfig {
+    /// gRPC address of the primary, e.g. `https://as-primary:50004`.
+    pub primary: String,
+
+    /// TLS settings of the connection to the primary.
+    pub tls: ReplicationTlsConfig,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct ReplicationTlsConfig {
+    /// PEM encoded client certificate chain of the standby, one of the
+    /// `standbys` of the primary.
+    pub cert: PathBuf,
+
+    /// PEM encoded private key of the client certificate.
+    pub key: PathBuf,
+
+    /// PEM encoded CA certificate(s) of the certificate of the primary.
+    pub ca: PathBuf,
+}
+
+impl ReplicationTlsConfig {
+    fn client_tls_config(&self) -> Result<ClientTlsConfig> {
+        let read = |path: &PathBuf| {
+            std::fs::read(path).with_context(|| format!("read {}", path.display()))
+        };
+        Ok(ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(read(&self.ca)?))
+            .identity(Identity::from_pem(read(&self.cert)?, read(&self.key)?)))
+    }
+}
+
+impl ReplicationConfig {
+    /// Check the replication of the AS of `config`, which must trust the
+    /// keys of the primary.
+    pub fn check(&self, config: &Config) -> Result<()> {
+        self.endpoint()?;
+        if config.state_signers.is_empty() {
+            bail!("The keys of the primary must be in the state_signers of the AS config");
+        }
+        Ok(())
+    }
+
+    /// The endpoint of the primary, over TLS with the client certificate of
+    /// the standby.
+    fn endpoint(&self) -> Result<Endpoint> {
+        if !self.primary.starts_with("https://") {
+            bail!("The primary address {} must be https://", self.primary);
+        }
+        Endpoint::from_shared(self.primary.clone())
+            .with_context(|| format!("Invalid primary address {}", self.primary))?
+            .tls_config(self.tls.client_tls_config()?)
+            .context("Invalid replication TLS config")
+    }
+}
+
+/// Replicate the state of the primary into `server`, reconnecting to the
+/// primary whenever the subscription is lost, until `shutdown`.
+pub async fn follow(
+    config: ReplicationConfig,
+    server: Arc<RwLock<AttestationServer>>,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    loop {
+        tokio::select! {
+            followed = follow_subscription(&config, &server) => {
+                if let Err(e) = followed {
+                    warn!("Replication of {}: {e:#}, reconnecting", config.primary);
+                }
+            }
+            _ = shutdown.signalled() => return Ok(()),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(RECONNECT_DELAY) => (),
+            _ = shutdown.signalled() => return Ok(()),
+        }
+    }
+}
+
+async fn follow_subscription(
+    config: &ReplicationConfig,
+    server: &Arc<RwLock<AttestationServer>>,
+) -> Result<()> {
+    let channel = config
+        .endpoint()?
+        .connect()
+        .await
+        .with_context(|| format!("Cannot connect to {}", config.primary))?;
+    let mut client = AttestationServiceClient::new(channel);
+    let mut snapshots = client
+        .subscribe_state(SubscribeStateRequest {})
+        .await
+        .context("Cannot subscribe to the state")?
+        .into_inner();
+    info!("Replicating the state of {}", config.primary);
+    while let Some(snapshot) = snapshots.message().await? {
+        let mut server = server.write().await;
+        server
+            .attestation_service
+            .import_state(&snapshot.archive)
+            .await
+            .with_context(|| format!("Cannot import revision {}", snapshot.revision))?;
+        server.replicated_revision = snapshot.revision;
+        debug!("Replicated revision {}", snapshot.revision);
+    }
+    bail!("The primary ended the subscription")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(primary: &str, cert: &str) -> ReplicationConfig {
+        ReplicationConfig {
+            primary: primary.to_string(),
+            tls: ReplicationTlsConfig {
+                cert: PathBuf::from(cert),
+                key: PathBuf::from("../../test_data/standby.key"),
+                ca: PathBuf::from("../../test_data/standby.crt"),
+            },
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        const CERT: &str = "../../test_data/standby.crt";
+        let mut as_config = Config::default();
+        // Without the keys of the primary.
+        assert!(config("https://127.0.0.1:50004", CERT)
+            .check(&as_config)
+            .is_err());
+        as_config.state_signers = vec!["/etc/as/primary-keys.json".into()];
+        config("https://127.0.0.1:50004", CERT)
+            .check(&as_config)
+            .unwrap();
+
+        // Without TLS, or without a client certificate.
+        assert!(config("http://127.0.0.1:50004", CERT)
+            .check(&as_config)
+            .is_err());
+        assert!(config("not a uri", CERT).check(&as_config).is_err());
+        assert!(config("https://127.0.0.1:50004", "missing.crt")
+            .check(&as_config)
+            .is_err());
+        assert!(serde_json::from_str::<ReplicationConfig>(
+            r#"{"primary": "https://127.0.0.1:50004"}"#
+        )
+        .is_err());
+    }
+}
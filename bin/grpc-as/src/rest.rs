@@ -0,0 +1,547 @@
+//! HTTP/JSON front end, for clients without a gRPC stack.
+//!
+//! Built with the `rest` feature, and with `rest` in the AS config file,
+//! the server also serves a subset of its API as JSON over HTTP/1.1:
+//!
+//! ```json
+//! "rest": { "address": "0.0.0.0:8080" }
+//! ```
+//!
+//! - `GET /challenge?tee=tdx&freshness_methods=nonce,timestamp` answers
+//!   `{"freshness": "nonce", "nonce": "..."}`, like the `Challenge` gRPC
+//!   API;
+//! - `POST /attest`, with `{"tee": "tdx", "nonce": "...", "evidence": "..."}`,
+//!   the evidence base64 encoded or the attestation itself, answers
+//!   `{"token": "...", "warnings": [...]}`, like the `AttestationEvaluate`
+//!   gRPC API, with the same tenant header, quotas and policy parameters,
+//!   and an optional `detail_level`, `token_format` and `init_data`
+//!   document;
+//! - `POST /reattest`, with `{"token": "..."}`, answers `{"token": "..."}`,
+//!   a refreshed token, like the `Reattest` gRPC API;
+//! - `GET /policies` answers the digests of the policies by ID, like the
+//!   `ListPolicies` gRPC API;
+//! - `GET /certs` answers the public token signing keys in JWKS format,
+//!   like the `GetSigningKeys` gRPC API, for relying parties to verify
+//!   tokens;
+//! - `GET /health` answers `{"status": "ok", "tasks": [...]}` once the AS
+//!   serves, with the health of the background tasks, see
+//!   [`crate::supervisor`], and a `degraded` status while one of them
+//!   waits to be restarted.
+//!
+//! `/challenge` and `/attest` also take and answer CBOR, and `/attest` a
+//! COSE token, as negotiated with the `Content-Type` and `Accept` headers,
+//! see [`crate::content`].
+//!
+//! Errors are `{"error": "..."}`, with the HTTP status of the gRPC status
+//! of the same error. With `tls` in the `rest` config, the same as that of
+//! the gRPC listeners, the listener serves HTTPS, with the FIPS approved
+//! algorithms only, see [`TlsConfig::http1_acceptor`]:
+//!
+//! ```json
+//! "rest": {
+//!     "address": "0.0.0.0:8443",
+//!     "tls": { "cert": "/etc/as/rest.crt", "key": "/etc/as/rest.key" }
+//! }
+//! ```
+//!
+//! Without it, the listener should be reached through a TLS terminating
+//! proxy, and is reported as plaintext by the security posture.
+
+#![cfg_attr(not(feature = "rest"), allow(dead_code))]
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use tonic::Code;
+
+use crate::content::ContentFormat;
+use crate::listener::TlsConfig;
+use crate::supervisor::{Shutdown, TaskHealth};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct RestConfig {
+    /// `<ip>:<port>` of the HTTP listener, e.g. `0.0.0.0:8080`.
+    pub address: String,
+
+    /// Serve plain text if not set. Clients are authenticated by their
+    /// API key, so `client_ca` should not be set.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+}
+
+impl RestConfig {
+    /// The address of the listener, that only builds with the `rest`
+    /// feature serve.
+    pub fn address(&self) -> Result<SocketAddr> {
+        if !cfg!(feature = "rest") {
+            bail!("This grpc-as is built without the rest feature");
+        }
+        self.address
+            .parse()
+            .map_err(|e| anyhow!("Invalid REST address {}: {e}", self.address))
+    }
+
+    /// Whether the listener serves HTTP without TLS.
+    pub fn is_plaintext(&self) -> bool {
+        self.tls.is_none()
+    }
+}
+
+/// Largest request body accepted.
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct ChallengeQuery {
+    tee: attestation_service::Tee,
+    /// Comma separated freshness methods the attester supports.
+    #[serde(default)]
+    freshness_methods: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AttestRequest {
+    tee: attestation_service::Tee,
+    nonce: String,
+    /// Base64 encoded JSON attestation, or the attestation itself.
+    evidence: serde_json::Value,
+    /// Parameters of the policy, if it declares any.
+    #[serde(default)]
+    policy_parameters: serde_json::Map<String, serde_json::Value>,
+    /// Detail of the verification results in the token, the configured
+    /// level if unset.
+    #[serde(default)]
+    detail_level: Option<attestation_service::verifier::detail::DetailLevel>,
+    /// Init-data document, TOML or JSON, that the evidence binds, if any.
+    #[serde(default)]
+    init_data: String,
+    /// Format of the token, instead of as configured, or as the response
+    /// format tells, see [`crate::content`].
+    #[serde(default)]
+    token_format: Option<attestation_service::TokenFormat>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReattestRequest {
+    token: String,
+}
+
+#[derive(Debug, Serialize)]
+struct AttestResponse {
+    token: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    certificate: String,
+}
+
+#[derive(Debug, Serialize)]
+struct HealthResponse {
+    status: &'static str,
+    tasks: Vec<TaskHealth>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// The freshness methods of a comma separated list.
+fn freshness_methods(
+    methods: &str,
+) -> Result<Vec<attestation_service::verifier::freshness::FreshnessMethod>> {
+    methods
+        .split(',')
+        .filter(|method| !method.is_empty())
+        .map(|method| {
+            method
+                .parse()
+                .map_err(|_| anyhow!("Invalid freshness method {method}"))
+        })
+        .collect()
+}
+
+/// The JSON attestation of the `evidence` of a request, base64 encoded or
+/// the attestation itself.
+fn attestation(evidence: serde_json::Value) -> Result<String> {
+    match evidence {
+        serde_json::Value::String(evidence) => decode_evidence(&evidence),
+        serde_json::Value::Object(_) => Ok(evidence.to_string()),
+        _ => bail!("Malformed evidence: neither base64 nor an attestation"),
+    }
+}
+
+/// The JSON attestation of the base64 `evidence`.
+fn decode_evidence(evidence: &str) -> Result<String> {
+    use base64::Engine;
+
+    let evidence = base64::engine::general_purpose::STANDARD
+        .decode(evidence)
+        .map_err(|e| anyhow!("Malformed evidence: {e}"))?;
+    String::from_utf8(evidence).map_err(|_| anyhow!("The evidence is not UTF-8"))
+}
+
+/// The HTTP status of a gRPC status `code`.
+fn http_status(code: Code) -> u16 {
+    match code {
+        Code::Ok => 200,
+        Code::InvalidArgument | Code::OutOfRange => 400,
+        Code::Unauthenticated => 401,
+        Code::PermissionDenied => 403,
+        Code::NotFound => 404,
+        Code::AlreadyExists | Code::Aborted => 409,
+        Code::FailedPrecondition => 412,
+        Code::ResourceExhausted => 429,
+        Code::Unimplemented => 501,
+        Code::Unavailable => 503,
+        Code::DeadlineExceeded => 504,
+        _ => 500,
+    }
+}
+
+#[cfg(feature = "rest")]
+pub use serve::serve;
+
+#[cfg(feature = "rest")]
+mod serve {
+    use super::*;
+    use axum::body::Bytes;
+    use axum::extract::{DefaultBodyLimit, Query, State};
+    use axum::http::header::{ACCEPT, CONTENT_TYPE};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use hyper::server::accept;
+    use log::{debug, info};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio::sync::RwLock;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::metadata::MetadataMap;
+    use tonic::Status;
+
+    use crate::as_api::AttestationRequest;
+    use crate::listener::tls_incoming;
+    use crate::server::{to_grpc_tee, AttestationServer};
+
+    type Server = Arc<RwLock<AttestationServer>>;
+
+    fn error(status: u16, error: String) -> Response {
+        let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        (status, Json(ErrorResponse { error })).into_response()
+    }
+
+    fn status_error(status: Status) -> Response {
+        error(http_status(status.code()), status.message().to_string())
+    }
+
+    /// An error answered in `format`, CBOR for COSE.
+    fn error_as(format: ContentFormat, status: u16, error: String) -> Response {
+        let format = match format {
+            ContentFormat::Json => return self::error(status, error),
+            _ => ContentFormat::Cbor,
+        };
+        let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        match format.encode(&ErrorResponse { error }) {
+            Ok(body) => (status, [(CONTENT_TYPE, format.media_type())], body).into_response(),
+            Err(e) => self::error(500, format!("{e:#}")),
+        }
+    }
+
+    /// A response `body` of `format`.
+    fn body(format: ContentFormat, body: Result<Vec<u8>>) -> Response {
+        match body {
+            Ok(body) => ([(CONTENT_TYPE, format.media_type())], body).into_response(),
+            Err(e) => error_as(format, 500, format!("{e:#}")),
+        }
+    }
+
+    /// The format of the request body and of the response, as told by the
+    /// `headers`, or the error response.
+    #[allow(clippy::result_large_err)]
+    fn formats(headers: &HeaderMap) -> Result<(ContentFormat, ContentFormat), Response> {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+        let response =
+            ContentFormat::of_accept(header(ACCEPT)).map_err(|e| error(406, e.to_string()))?;
+        let request = ContentFormat::of_request(header(CONTENT_TYPE))
+            .map_err(|e| error_as(response, 415, e.to_string()))?;
+        Ok((request, response))
+    }
+
+    async fn challenge(
+        State(server): State<Server>,
+        headers: HeaderMap,
+        Query(query): Query<ChallengeQuery>,
+    ) -> Response {
+        let format = match formats(&headers) {
+            Ok((_, ContentFormat::Cose)) => {
+                return error(406, "A challenge is not a token".to_string())
+            }
+            Ok((_, format)) => format,
+            Err(response) => return response,
+        };
+        let offered = match freshness_methods(&query.freshness_methods) {
+            Ok(offered) => offered,
+            Err(e) => return error_as(format, 400, e.to_string()),
+        };
+        let challenge = server
+            .read()
+            .await
+            .attestation_service
+            .challenge(&query.tee, &offered)
+            .await;
+        match challenge {
+            Ok(challenge) => body(format, format.encode(&challenge)),
+            Err(e) => error_as(format, 412, format!("Challenge Failed: {e:#}")),
+        }
+    }
+
+    async fn attest(State(server): State<Server>, headers: HeaderMap, request: Bytes) -> Response {
+        let (request_format, format) = match formats(&headers) {
+            Ok(formats) => formats,
+            Err(response) => return response,
+        };
+        let request: AttestRequest = match request_format.decode(&request) {
+            Ok(request) => request,
+            Err(e) => return error_as(format, 400, format!("Malformed request: {e:#}")),
+        };
+        let token_format = match format.token_format(request.token_format) {
+            Ok(token_format) => token_format,
+            Err(e) => return error_as(format, 406, e.to_string()),
+        };
+        let evidence = match attestation(request.evidence) {
+            Ok(evidence) => evidence,
+            Err(e) => return error_as(format, 400, e.to_string()),
+        };
+        let policy_parameters = match request.policy_parameters.is_empty() {
+            true => String::new(),
+            false => serde_json::Value::Object(request.policy_parameters).to_string(),
+        };
+        let Some(tee) = to_grpc_tee(&request.tee) else {
+            return error_as(
+                format,
+                400,
+                format!("TEE {:?} is not supported", request.tee),
+            );
+        };
+        let server = server.read().await;
+        let caller = match server.anonymous_caller(&MetadataMap::from_headers(headers)) {
+            Ok(caller) => caller,
+            Err(status) => {
+                return error_as(
+                    format,
+                    http_status(status.code()),
+                    status.message().to_string(),
+                )
+            }
+        };
+        let response = server
+            .attestation(
+                caller,
+                AttestationRequest {
+                    tee: tee as i32,
+                    nonce: request.nonce,
+                    evidence,
+                    policy_parameters,
+                    detail_level: request
+                        .detail_level
+                        .map(|level| level.to_string())
+                        .unwrap_or_default(),
+                    init_data: request.init_data,
+                    token_format: token_format
+                        .map(|format| format.to_string())
+                        .unwrap_or_default(),
+                    ..Default::default()
+                },
+                None,
+            )
+            .await;
+        match response {
+            Ok(response) => body(
+                format,
+                format.encode_attestation(
+                    &AttestResponse {
+                        token: response.attestation_token.clone(),
+                        warnings: response.warnings,
+                        certificate: response.certificate,
+                    },
+                    &response.attestation_token,
+                    token_format,
+                ),
+            ),
+            Err(status) => error_as(
+                format,
+                http_status(status.code()),
+                status.message().to_string(),
+            ),
+        }
+    }
+
+    async fn reattest(
+        State(server): State<Server>,
+        headers: HeaderMap,
+        Json(request): Json<ReattestRequest>,
+    ) -> Response {
+        let server = server.read().await;
+        let response = match server.anonymous_caller(&MetadataMap::from_headers(headers)) {
+            Ok(caller) => server.reattest(caller, &request.token).await,
+            Err(status) => Err(status),
+        };
+        match response {
+            Ok(response) => Json(AttestResponse {
+                token: response.attestation_token,
+                warnings: response.warnings,
+                certificate: response.certificate,
+            })
+            .into_response(),
+            Err(status) => status_error(status),
+        }
+    }
+
+    async fn policies(State(server): State<Server>) -> Response {
+        let policies = server
+            .read()
+            .await
+            .attestation_service
+            .list_policies()
+            .await;
+        match policies {
+            Ok(policies) => Json(policies).into_response(),
+            Err(e) => error(500, format!("List Policies Failed: {e:#}")),
+        }
+    }
+
+    async fn certs(State(server): State<Server>) -> Response {
+        let jwks = server
+            .read()
+            .await
+            .attestation_service
+            .signing_keys()
+            .and_then(|jwks| Ok(serde_json::from_str::<serde_json::Value>(&jwks)?));
+        match jwks {
+            Ok(jwks) => Json(jwks).into_response(),
+            Err(e) => error(500, format!("Get Signing Keys Failed: {e:#}")),
+        }
+    }
+
+    async fn health(State(server): State<Server>) -> Json<HealthResponse> {
+        let health = server.read().await.tasks.clone();
+        Json(HealthResponse {
+            status: match health.is_healthy() {
+                true => "ok",
+                false => "degraded",
+            },
+            tasks: health.tasks(),
+        })
+    }
+
+    /// Serve the HTTP requests of `config`, until `shutdown`.
+    pub async fn serve(config: RestConfig, server: Server, mut shutdown: Shutdown) -> Result<()> {
+        let address = config.address()?;
+        let app = Router::new()
+            .route("/challenge", get(challenge))
+            .route("/attest", post(attest))
+            .route("/reattest", post(reattest))
+            .route("/policies", get(policies))
+            .route("/certs", get(certs))
+            .route("/health", get(health))
+            .layer(DefaultBodyLimit::max(MAX_BODY))
+            .with_state(server);
+        let shutdown = async move { shutdown.signalled().await };
+        match &config.tls {
+            Some(tls) => {
+                let acceptor = tls.http1_acceptor()?;
+                let listener = TcpListener::bind(address)
+                    .await
+                    .map_err(|e| anyhow!("Cannot bind the REST address {address}: {e}"))?;
+                info!("REST listen address: {address} (TLS)");
+                let incoming = tls_incoming(TcpListenerStream::new(listener), acceptor);
+                axum::Server::builder(accept::from_stream(incoming))
+                    .serve(app.into_make_service())
+                    .with_graceful_shutdown(shutdown)
+                    .await
+            }
+            None => {
+                info!("REST listen address: {address} (plain text)");
+                axum::Server::try_bind(&address)
+                    .map_err(|e| anyhow!("Cannot bind the REST address {address}: {e}"))?
+                    .serve(app.into_make_service())
+                    .with_graceful_shutdown(shutdown)
+                    .await
+            }
+        }
+        .map_err(|e| anyhow!("REST server: {e}"))?;
+        debug!("REST server stopped");
+        Ok(())
+    }
+}
+
+/// Serving the HTTP requests needs the `rest` feature.
+#[cfg(not(feature = "rest"))]
+pub async fn serve(
+    config: RestConfig,
+    _server: std::sync::Arc<tokio::sync::RwLock<crate::server::AttestationServer>>,
+    _shutdown: Shutdown,
+) -> Result<()> {
+    config.address().map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use attestation_service::verifier::freshness::FreshnessMethod;
+
+    #[test]
+    fn test_freshness_methods() {
+        assert_eq!(
+            freshness_methods("nonce,timestamp").unwrap(),
+            [FreshnessMethod::Nonce, FreshnessMethod::Timestamp]
+        );
+        assert!(freshness_methods("").unwrap().is_empty());
+        assert!(freshness_methods("nonce,sometime").is_err());
+    }
+
+    #[test]
+    fn test_decode_evidence() {
+        assert_eq!(
+            decode_evidence("eyJ0ZWUtcHVia2V5Ijoge319").unwrap(),
+            "{\"tee-pubkey\": {}}"
+        );
+        assert!(decode_evidence("not base64!").is_err());
+        assert!(decode_evidence("/w==").is_err());
+    }
+
+    #[test]
+    fn test_attestation() {
+        assert_eq!(
+            attestation(serde_json::json!("eyJ0ZWUtcHVia2V5Ijoge319")).unwrap(),
+            "{\"tee-pubkey\": {}}"
+        );
+        assert_eq!(
+            attestation(serde_json::json!({"quote": "AQID"})).unwrap(),
+            r#"{"quote":"AQID"}"#
+        );
+        assert!(attestation(serde_json::json!([1, 2])).is_err());
+    }
+
+    #[test]
+    fn test_address() {
+        let config: RestConfig = serde_json::from_str(r#"{"address": "0.0.0.0:8080"}"#).unwrap();
+        assert_eq!(config.address().is_ok(), cfg!(feature = "rest"));
+        assert!(config.is_plaintext());
+        let config: RestConfig = serde_json::from_str(
+            r#"{"address": "0.0.0.0:8443", "tls": {"cert": "rest.crt", "key": "rest.key"}}"#,
+        )
+        .unwrap();
+        assert!(!config.is_plaintext());
+        let config: RestConfig = serde_json::from_str(r#"{"address": "localhost"}"#).unwrap();
+        assert!(config.address().is_err());
+    }
+
+    #[test]
+    fn test_http_status() {
+        assert_eq!(http_status(Code::PermissionDenied), 403);
+        assert_eq!(http_status(Code::ResourceExhausted), 429);
+        assert_eq!(http_status(Code::Internal), 500);
+    }
+}
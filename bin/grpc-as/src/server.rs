@@ -1,20 +1,106 @@
-use anyhow::{anyhow, Result};
-use attestation_service::{config::Config, AttestationService as Service, Tee};
-use log::{debug, info};
-use std::path::Path;
+use anyhow::{anyhow, Context, Result};
+use as_types::bundle::{BundledItem, EvidenceBundle};
+use attestation_service::{
+    admission::Overloaded,
+    audit,
+    config::{Config, ConfigChanges, ConfigLayers},
+    deny_list::{Denied, DenyListEntry},
+    explain::{Check, ReportFormat},
+    failure_cache::CachedFailure,
+    history::{ExportFormat, HistoryRecord},
+    init_data,
+    policy_engine::{self, ParameterValues, PolicyDenied, PolicyMismatch},
+    posture::{Finding, SecurityPosture},
+    progress::Progress,
+    reappraisal::{ReappraisalFilter, ReappraisalReport},
+    replay::Replayed,
+    result_cache::AttestAgain,
+    rvps::Agent,
+    verifier::{
+        collateral, detail::DetailLevel, diagnostics::Diagnostics, encoding::MeasurementEncoding,
+        freshness::FreshnessMethod, profile::Profile, report_data::ReportDataMode,
+        schema::ClaimsSchema, CollateralKey, UnsupportedVersion,
+    },
+    AttestationService as Service, BatchItem, EvaluateOptions, Evaluation, HostMetadata, Submitter,
+    Tee, TokenFormat,
+};
+use chrono::{DateTime, Utc};
+use futures::future::try_join_all;
+use futures::{Stream, StreamExt, TryStreamExt};
+use log::{debug, info, warn};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::str::FromStr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex, OwnedMutexGuard, RwLock};
+use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream, WatchStream};
+use tonic::metadata::MetadataValue;
+use tonic::service::interceptor::InterceptedService;
 use tonic::transport::Server;
-use tonic::{Request, Response, Status};
+use tonic::{Code, Request, Response, Status, Streaming};
 
 use crate::as_api::attestation_service_server::{AttestationService, AttestationServiceServer};
 use crate::as_api::{
-    AttestationRequest, AttestationResponse, SetPolicyRequest, SetPolicyResponse, Tee as GrpcTee,
+    AddDenyListEntryRequest, AddDenyListEntryResponse, AttestationBatchRequest,
+    AttestationBatchResponse, AttestationBatchResult, AttestationChunk, AttestationProgress,
+    AttestationRequest, AttestationResponse, BundledItem as GrpcBundledItem, CanonicalClaim,
+    CanonicalClaimSource, ChallengeRequest, ChallengeResponse, CollateralExpiry,
+    ConfirmReferenceValuesRequest, ConfirmReferenceValuesResponse, DeletePolicyRequest,
+    DeletePolicyResponse, DenyListEntry as GrpcDenyListEntry, DiscardReferenceValuesRequest,
+    DiscardReferenceValuesResponse, EvidenceBundle as GrpcEvidenceBundle, EvidenceFormat,
+    ExplainAttestationRequest, ExplainAttestationResponse, ExportHistoryRequest,
+    ExportHistoryResponse, ExportStateRequest, ExportStateResponse, FleetPlatform,
+    GetAuditRecordRequest, GetAuditRecordResponse, GetCanonicalClaimsRequest,
+    GetCanonicalClaimsResponse, GetCapabilitiesRequest, GetCapabilitiesResponse,
+    GetCollateralExpiryRequest, GetCollateralExpiryResponse, GetInclusionProofRequest,
+    GetInclusionProofResponse, GetLoadRequest, GetLoadResponse, GetMaintenanceStatsRequest,
+    GetMaintenanceStatsResponse, GetPolicyByDigestRequest, GetPolicyByDigestResponse,
+    GetPolicyDataRequest, GetPolicyDataResponse, GetPolicyRequest, GetPolicyResponse,
+    GetPolicyRevisionsRequest, GetPolicyRevisionsResponse, GetReappraisalReportRequest,
+    GetReappraisalReportResponse, GetRejectionsRequest, GetRejectionsResponse,
+    GetServiceInfoRequest, GetServiceInfoResponse, GetServiceStatusRequest,
+    GetServiceStatusResponse, GetShadowStatsRequest, GetShadowStatsResponse, GetSigningKeysRequest,
+    GetSigningKeysResponse, GetTaskHealthRequest, GetTaskHealthResponse, GetUsageRequest,
+    GetUsageResponse, ImportSigningKeyRequest, ImportSigningKeyResponse, ImportStateRequest,
+    ImportStateResponse, ListDenyListRequest, ListDenyListResponse, ListPoliciesRequest,
+    ListPoliciesResponse, ListProvisionalReferenceValuesRequest,
+    ListProvisionalReferenceValuesResponse, MigrationRequest, PlatformCollateral, PolicyResult,
+    PolicyRevision, PromoteSigningKeyRequest, PromoteSigningKeyResponse, ReappraiseEvidenceRequest,
+    ReappraiseEvidenceResponse, ReattestRequest, RegisterPlatformsRequest,
+    RegisterPlatformsResponse, RejectionCount, RemoveDenyListEntryRequest,
+    RemoveDenyListEntryResponse, RollbackPolicyRequest, RollbackPolicyResponse, SelfTestCheck,
+    SelfTestRequest, SelfTestResponse, SetPolicyDataRequest, SetPolicyDataResponse,
+    SetPolicyRequest, SetPolicyResponse, ShadowCount, StateSnapshot, SubscribeStateRequest,
+    TaskHealth, Tee as GrpcTee, TenantUsage, TestPolicyRequest, TestPolicyResponse,
+    ValidateConfigRequest, ValidateConfigResponse, VerifierCapabilities,
 };
 
+use crate::admin::CertificateAllowList;
+use crate::advisories;
+use crate::claims;
+use crate::coap;
+use crate::expiry;
+use crate::listener::{
+    submitter, tls_incoming, BoundSocket, ListenAddress, ListenerAccess, ListenerConfig,
+    ServerConfig, UnixStream,
+};
+use crate::maintenance;
+use crate::metrics;
+use crate::prefetch;
+use crate::queue;
+use crate::reference_values;
+use crate::reload::{self, ConfigSource, ConfigUpdate};
+use crate::replication;
+use crate::rest;
 use crate::rvps_api::reference_value_provider_service_server::{
     ReferenceValueProviderService, ReferenceValueProviderServiceServer,
 };
+use crate::streaming;
+use crate::supervisor::{self, Health, Supervisor};
+use crate::systemd;
+use crate::usage::{Usage, UsageConfig, DEFAULT_TENANT};
 
 use crate::rvps_api::{
     ReferenceValueQueryRequest, ReferenceValueQueryResponse, ReferenceValueRegisterRequest,
@@ -23,6 +109,36 @@ use crate::rvps_api::{
 
 const DEFAULT_SOCK: &str = "127.0.0.1:3000";
 
+/// The collateral key of a platform of the `RegisterPlatforms` API.
+fn to_collateral_key(platform: FleetPlatform) -> Result<CollateralKey, Status> {
+    fn field<T: serde::de::DeserializeOwned>(name: &str, value: String) -> Result<T, Status> {
+        serde_json::from_value(Value::String(value.clone()))
+            .map_err(|_| Status::invalid_argument(format!("Invalid platform {name} {value}")))
+    }
+    Ok(CollateralKey {
+        tee: field("tee", platform.tee)?,
+        fmspc: platform.fmspc,
+        ca: field("ca", platform.ca)?,
+    })
+}
+
+fn from_grpc_bundle(bundle: GrpcEvidenceBundle) -> EvidenceBundle {
+    let item = |item: GrpcBundledItem| BundledItem {
+        media_type: item.media_type,
+        name: item.name,
+        data: item.data,
+    };
+    EvidenceBundle {
+        version: bundle.version,
+        evidence: bundle.evidence.into_iter().map(item).collect(),
+        runtime_data: bundle
+            .runtime_data
+            .map(item)
+            .unwrap_or_else(|| BundledItem::new("", Vec::new())),
+        init_data: bundle.init_data.map(item),
+    }
+}
+
 fn to_kbs_tee(tee: GrpcTee) -> Tee {
     match tee {
         GrpcTee::Sev => Tee::Sev,
@@ -34,18 +150,97 @@ fn to_kbs_tee(tee: GrpcTee) -> Tee {
     }
 }
 
+#[cfg(feature = "rest")]
+pub(crate) fn to_grpc_tee(tee: &Tee) -> Option<GrpcTee> {
+    match tee {
+        Tee::Sev => Some(GrpcTee::Sev),
+        Tee::Sgx => Some(GrpcTee::Sgx),
+        Tee::Snp => Some(GrpcTee::Snp),
+        Tee::Tdx => Some(GrpcTee::Tdx),
+        Tee::Csv => Some(GrpcTee::Csv),
+        Tee::Sample => Some(GrpcTee::Sample),
+        _ => None,
+    }
+}
+
 pub struct AttestationServer {
-    attestation_service: Service,
+    pub attestation_service: Service,
+    usage: Usage,
+    tenant_policy_parameters: HashMap<String, Map<String, Value>>,
+    /// Revision of the replicated state, see [`crate::replication`].
+    state_revision: watch::Sender<u64>,
+    /// The primary of a warm standby, which refuses changes of the
+    /// replicated state.
+    primary: Option<String>,
+    /// Revision of the state of the primary last replicated.
+    pub replicated_revision: u64,
+    /// Whether some listener serves without TLS.
+    pub plaintext_listeners: bool,
+    /// Client certificates of the administrators.
+    pub admins: CertificateAllowList,
+    /// Client certificates of the warm standbys.
+    pub standbys: CertificateAllowList,
+    /// Where the config is layered from, to reload it, see
+    /// [`crate::reload`].
+    pub config_source: Option<ConfigSource>,
+    /// The layered config running, whose digest is in the service status,
+    /// see `attestation_service::status`.
+    pub config_layers: Option<ConfigLayers>,
+    /// Health of the background tasks, see [`crate::supervisor`].
+    pub tasks: Health,
+    /// The report of the last reappraisal of the history, locked while a
+    /// reappraisal runs in the background, see [`reappraise`].
+    reappraisal: Arc<Mutex<Option<ReappraisalReport>>>,
 }
 
-impl AttestationServer {
-    pub async fn new(rvps_addr: Option<&str>, config_path: Option<&str>) -> Result<Self> {
-        let config = match config_path {
-            Some(path) => Config::try_from(Path::new(path))
-                .map_err(|e| anyhow!("Read AS config file failed: {:?}", e))?,
-            None => Config::default(),
-        };
+/// The client of an attestation request, as told by its transport.
+#[derive(Clone)]
+pub(crate) struct Caller {
+    tenant: String,
+    /// Whether the listener of the request returns diagnostics.
+    diagnostics_allowed: bool,
+    /// Identity of the client certificate, see
+    /// `attestation_service::submitter`.
+    submitter: Option<Submitter>,
+}
 
+/// Refuse the admin `request` of a client that is not an administrator,
+/// see [`crate::admin`], as `action` requires.
+async fn require_admin<T>(
+    server: &RwLock<AttestationServer>,
+    request: &Request<T>,
+    action: &str,
+) -> Result<(), Status> {
+    server.read().await.admins.authorize(request, action)
+}
+
+impl Caller {
+    fn new<T>(usage: &Usage, request: &Request<T>) -> Result<Self, Status> {
+        let submitter = request
+            .peer_certs()
+            .and_then(|certs| certs.first().map(|cert| submitter(cert.get_ref())))
+            .transpose()
+            .map_err(|e| Status::unauthenticated(format!("{e:#}")))?;
+        let tenant = usage.tenant(request.metadata(), submitter.as_ref())?;
+        Ok(Self {
+            tenant,
+            diagnostics_allowed: request
+                .extensions()
+                .get::<ListenerAccess>()
+                .is_some_and(|access| access.diagnostics),
+            submitter,
+        })
+    }
+}
+
+impl AttestationServer {
+    pub async fn new(
+        rvps_addr: Option<&str>,
+        config: Config,
+        usage: UsageConfig,
+        tenant_policy_parameters: HashMap<String, Map<String, Value>>,
+        primary: Option<String>,
+    ) -> Result<Self> {
         let service = match rvps_addr {
             Some(addr) => {
                 info!("Connect to remote RVPS [{addr}] (gRPC Mode)");
@@ -59,6 +254,395 @@ impl AttestationServer {
 
         Ok(Self {
             attestation_service: service,
+            usage: Usage::new(usage),
+            tenant_policy_parameters,
+            state_revision: watch::channel(0).0,
+            primary,
+            replicated_revision: 0,
+            plaintext_listeners: false,
+            admins: CertificateAllowList::default(),
+            standbys: CertificateAllowList::default(),
+            config_source: None,
+            config_layers: None,
+            tasks: Health::default(),
+            reappraisal: Arc::default(),
+        })
+    }
+
+    /// The insecure settings the server runs with, see
+    /// `attestation_service::posture`.
+    pub async fn security_posture(&self) -> Result<SecurityPosture> {
+        let mut posture = self.attestation_service.security_posture().await?;
+        if self.plaintext_listeners {
+            posture.add(Finding::PlaintextListener);
+        }
+        Ok(posture)
+    }
+
+    /// The status refusing changes of the replicated state on a warm
+    /// standby.
+    fn read_only(&self) -> Option<Status> {
+        self.primary
+            .as_ref()
+            .map(|primary| Status::failed_precondition(format!("Read-only standby of {primary}")))
+    }
+
+    /// Notify the standbys of a change of the replicated state.
+    fn state_changed(&self) {
+        self.state_revision.send_modify(|revision| *revision += 1);
+    }
+
+    /// Ingest the bundles of reference values of the watched directory,
+    /// but on a warm standby.
+    pub async fn reload_reference_values(&mut self) -> Result<()> {
+        if self.primary.is_some() {
+            return Ok(());
+        }
+        if self.attestation_service.reload_reference_values().await? > 0 {
+            self.state_changed();
+        }
+        Ok(())
+    }
+
+    /// Digest of the layered config running, if known.
+    pub fn config_digest(&self) -> Option<String> {
+        self.config_layers.as_ref().map(ConfigLayers::digest)
+    }
+
+    /// The update of the running config to the candidate `layers`, checked
+    /// with [`reload::check_candidate`] into `config` and `server_config`,
+    /// once checked against the state of the AS.
+    pub async fn config_update(
+        &self,
+        layers: ConfigLayers,
+        config: Config,
+        server_config: ServerConfig,
+    ) -> Result<ConfigUpdate> {
+        self.attestation_service.validate_config(&config).await?;
+        let changes = self.config_changes(&layers);
+        Ok(ConfigUpdate {
+            layers,
+            config,
+            server_config,
+            changes,
+        })
+    }
+
+    /// The sections of the candidate config `layers` that differ from the
+    /// running one.
+    pub fn config_changes(&self, layers: &ConfigLayers) -> ConfigChanges {
+        match &self.config_layers {
+            Some(running) => running.changes(layers, &reload::reloadable_sections()),
+            None => Default::default(),
+        }
+    }
+
+    /// Apply the reloadable sections of the config `update`, and log the
+    /// others as taking effect on restart.
+    pub async fn apply_config_update(&mut self, update: ConfigUpdate) -> Result<()> {
+        let watch_changed = self.attestation_service.reload_config(update.config);
+        self.tenant_policy_parameters = update.server_config.tenant_policy_parameters;
+        self.config_layers = Some(update.layers);
+        if !update.changes.reloaded.is_empty() {
+            info!("Config reloaded: {}", update.changes.reloaded.join(", "));
+        }
+        if !update.changes.restart_needed.is_empty() {
+            warn!(
+                "Config changes take effect on restart: {}",
+                update.changes.restart_needed.join(", ")
+            );
+        }
+        if watch_changed {
+            self.reload_reference_values().await?;
+        }
+        Ok(())
+    }
+
+    /// Apply the new advisories of the advisory feed to the advisory
+    /// baseline, but on a warm standby.
+    pub async fn refresh_advisories(&mut self) -> Result<()> {
+        if self.primary.is_some() {
+            return Ok(());
+        }
+        if let Some(version) = self.attestation_service.refresh_advisories().await? {
+            info!("Advisory baseline updated to version {version}");
+            self.state_changed();
+        }
+        Ok(())
+    }
+
+    /// The policy parameters of a request of `tenant`: those configured for
+    /// the tenant, and those of the request, which only supply the
+    /// parameters that policies declare `from_request`.
+    fn policy_parameters(&self, tenant: &str, requested: &str) -> Result<ParameterValues> {
+        let requested = match requested {
+            "" => Map::new(),
+            requested => serde_json::from_str(requested)
+                .map_err(|e| anyhow!("Malformed policy parameters: {e}"))?,
+        };
+        Ok(ParameterValues {
+            configured: self
+                .tenant_policy_parameters
+                .get(tenant)
+                .cloned()
+                .unwrap_or_default(),
+            requested,
+        })
+    }
+
+    /// The caller of a request of a front end without client certificates,
+    /// such as [`crate::rest`], with the tenant of the API key of its
+    /// `metadata`, if any.
+    #[cfg(feature = "rest")]
+    pub(crate) fn anonymous_caller(
+        &self,
+        metadata: &tonic::metadata::MetadataMap,
+    ) -> Result<Caller, Status> {
+        Ok(Caller {
+            tenant: self.usage.tenant(metadata, None)?,
+            diagnostics_allowed: false,
+            submitter: None,
+        })
+    }
+
+    /// The attestation of the `request` of `caller`, with its options,
+    /// reporting the steps of its evaluation to `progress`.
+    fn attestation_item(
+        &self,
+        caller: Caller,
+        request: AttestationRequest,
+        progress: Option<Progress>,
+    ) -> Result<BatchItem, Status> {
+        self.usage.record(&caller.tenant)?;
+        if request.diagnostics && !caller.diagnostics_allowed {
+            return Err(Status::permission_denied(
+                "Diagnostics are not enabled on this listener",
+            ));
+        }
+        let diagnostics = request.diagnostics.then(Diagnostics::new);
+        if request.profile && !caller.diagnostics_allowed {
+            return Err(Status::permission_denied(
+                "Profiles are not enabled on this listener",
+            ));
+        }
+        if request.profile && !cfg!(feature = "profiling") {
+            return Err(Status::unimplemented(
+                "This grpc-as is built without the profiling feature",
+            ));
+        }
+        let profile = request.profile.then(Profile::new);
+        let report_data_mode = match request.report_data_mode.as_str() {
+            "" => None,
+            mode => Some(
+                mode.parse::<ReportDataMode>()
+                    .map_err(|e| Status::invalid_argument(e.to_string()))?,
+            ),
+        };
+        let claims_schema = match request.claims_schema.as_str() {
+            "" => None,
+            schema => Some(
+                schema
+                    .parse::<ClaimsSchema>()
+                    .map_err(|e| Status::invalid_argument(e.to_string()))?,
+            ),
+        };
+        let measurement_encoding = match request.measurement_encoding.as_str() {
+            "" => None,
+            encoding => Some(
+                encoding
+                    .parse::<MeasurementEncoding>()
+                    .map_err(|e| Status::invalid_argument(e.to_string()))?,
+            ),
+        };
+        let token_format = match request.token_format.as_str() {
+            "" => None,
+            format => Some(
+                format
+                    .parse::<TokenFormat>()
+                    .map_err(|e| Status::invalid_argument(e.to_string()))?,
+            ),
+        };
+        let detail_level = match request.detail_level.as_str() {
+            "" => None,
+            level => Some(
+                level
+                    .parse::<DetailLevel>()
+                    .map_err(|e| Status::invalid_argument(e.to_string()))?,
+            ),
+        };
+        let policy_parameters = self
+            .policy_parameters(&caller.tenant, &request.policy_parameters)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        let (tee, attestation, init_data) = match request.bundle {
+            Some(bundle) => {
+                let unbundled = self
+                    .attestation_service
+                    .unbundle(from_grpc_bundle(bundle))
+                    .map_err(|e| Status::invalid_argument(format!("{e:#}")))?;
+                let attestation = serde_json::to_string(&unbundled.attestation)
+                    .map_err(|e| Status::internal(e.to_string()))?;
+                (unbundled.tee, attestation, unbundled.init_data)
+            }
+            None => (
+                to_kbs_tee(
+                    GrpcTee::from_i32(request.tee)
+                        .ok_or_else(|| Status::aborted(format!("Invalid TEE {}", request.tee)))?,
+                ),
+                request.evidence,
+                None,
+            ),
+        };
+        let init_data = match (init_data, request.init_data) {
+            (Some(_), document) if !document.is_empty() => {
+                return Err(Status::invalid_argument(
+                    "The init-data is both in the bundle and in the request",
+                ))
+            }
+            (None, document) if !document.is_empty() => Some(init_data::item(document)),
+            (init_data, _) => init_data,
+        };
+
+        debug!("Evidence: {}", &attestation);
+
+        Ok(BatchItem {
+            tee,
+            nonce: request.nonce,
+            attestation,
+            options: EvaluateOptions {
+                previous_token: (!request.previous_token.is_empty())
+                    .then_some(request.previous_token),
+                diagnostics,
+                profile,
+                report_data_mode,
+                csr: (!request.csr.is_empty()).then_some(request.csr),
+                policy_parameters,
+                submitter: caller.submitter,
+                host_metadata: request.host_metadata.map(|host| HostMetadata {
+                    agent: host.agent,
+                    metadata: host.metadata,
+                    signature: host.signature,
+                }),
+                claims_schema,
+                measurement_encoding,
+                token_format,
+                detail_level,
+                tenant: Some(caller.tenant),
+                progress,
+                attestation_id: self
+                    .attestation_service
+                    .audited()
+                    .then(audit::new_attestation_id),
+                init_data,
+            },
+        })
+    }
+
+    /// Evaluate the attestation `request` of `caller`, reporting the steps
+    /// of the evaluation to `progress`, see [`crate::streaming`].
+    pub(crate) async fn attestation(
+        &self,
+        caller: Caller,
+        request: AttestationRequest,
+        progress: Option<Progress>,
+    ) -> Result<AttestationResponse, Status> {
+        let item = self.attestation_item(caller, request, progress)?;
+        let diagnostics = item.options.diagnostics.clone();
+        let profile = item.options.profile.clone();
+        let attestation_id = item.options.attestation_id.clone();
+        let evaluation = self
+            .attestation_service
+            .evaluate_with_options(item.tee, &item.nonce, &item.attestation, item.options)
+            .await
+            .map_err(|e| {
+                let status = with_diagnostics(attestation_status(e), diagnostics.as_ref());
+                with_attestation_id(status, attestation_id.as_deref())
+            })?;
+        attestation_response(evaluation, diagnostics, profile)
+    }
+
+    /// Evaluate the attestation `requests` of `caller` as a batch, see
+    /// [`attestation_service::AttestationService::verify_batch`].
+    pub(crate) async fn attestation_batch(
+        &self,
+        caller: Caller,
+        requests: Vec<AttestationRequest>,
+    ) -> Result<AttestationBatchResponse, Status> {
+        self.attestation_service
+            .check_batch(requests.len())
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let mut results = vec![AttestationBatchResult::default(); requests.len()];
+        let mut positions = Vec::new();
+        let mut items = Vec::new();
+        for (position, request) in requests.into_iter().enumerate() {
+            match self.attestation_item(caller.clone(), request, None) {
+                Ok(item) => {
+                    positions.push(position);
+                    items.push(item);
+                }
+                Err(status) => results[position] = batch_result(Err(status)),
+            }
+        }
+        let options: Vec<_> = items
+            .iter()
+            .map(|item| {
+                (
+                    item.options.diagnostics.clone(),
+                    item.options.profile.clone(),
+                    item.options.attestation_id.clone(),
+                )
+            })
+            .collect();
+        let evaluations = self
+            .attestation_service
+            .verify_batch(items)
+            .await
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        for ((position, evaluation), (diagnostics, profile, attestation_id)) in
+            positions.into_iter().zip(evaluations).zip(options)
+        {
+            results[position] = batch_result(
+                evaluation
+                    .map_err(|e| {
+                        let status = with_diagnostics(attestation_status(e), diagnostics.as_ref());
+                        with_attestation_id(status, attestation_id.as_deref())
+                    })
+                    .and_then(|evaluation| attestation_response(evaluation, diagnostics, profile)),
+            );
+        }
+        Ok(AttestationBatchResponse { results })
+    }
+
+    /// Refresh the `token` of `caller` without new evidence, see
+    /// [`attestation_service::result_cache`].
+    pub(crate) async fn reattest(
+        &self,
+        caller: Caller,
+        token: &str,
+    ) -> Result<AttestationResponse, Status> {
+        self.usage.record(&caller.tenant)?;
+        let evaluation = self
+            .attestation_service
+            .reattest(token)
+            .await
+            .map_err(|e| match e.is::<AttestAgain>() {
+                true => Status::failed_precondition(format!("Reattest: {e:#}")),
+                false => Status::aborted(format!("Reattest: {e:#}")),
+            })?;
+
+        debug!("Refreshed Attestation Token: {}", &evaluation.token);
+
+        Ok(AttestationResponse {
+            attestation_token: evaluation.token,
+            receipt: evaluation
+                .receipt
+                .map(|receipt| serde_json::to_string(&receipt))
+                .transpose()
+                .map_err(|e| Status::internal(format!("Serialize receipt: {e}")))?
+                .unwrap_or_default(),
+            policies: policy_results(evaluation.policies),
+            claims: claims::claims(&evaluation.claims),
+            ..Default::default()
         })
     }
 }
@@ -76,43 +660,1107 @@ impl AttestationService for Arc<RwLock<AttestationServer>> {
         let set_policy_input: as_types::SetPolicyInput = serde_json::from_str(&request.input)
             .map_err(|_| Status::aborted("Bad SetPolicyInput"))?;
 
-        self.write()
-            .await
+        let mut server = self.write().await;
+        if let Some(status) = server.read_only() {
+            return Err(status);
+        }
+        server
             .attestation_service
             .set_policy(set_policy_input)
             .await
             .map_err(|e| Status::aborted(format!("Set Attestation Policy Failed: {e}")))?;
+        server.state_changed();
 
         Ok(Response::new(SetPolicyResponse {}))
     }
 
+    async fn test_attestation_policy(
+        &self,
+        request: Request<TestPolicyRequest>,
+    ) -> Result<Response<TestPolicyResponse>, Status> {
+        let request: TestPolicyRequest = request.into_inner();
+
+        debug!("TestPolicyInput: {}", &request.input);
+
+        let test_policy_input: as_types::TestPolicyInput = serde_json::from_str(&request.input)
+            .map_err(|_| Status::aborted("Bad TestPolicyInput"))?;
+
+        let report = self
+            .read()
+            .await
+            .attestation_service
+            .test_policy(test_policy_input)
+            .await
+            .map_err(|e| Status::aborted(format!("Test Attestation Policy Failed: {e}")))?;
+
+        let report = serde_json::to_string(&report)
+            .map_err(|e| Status::aborted(format!("Serialize policy test report: {e}")))?;
+
+        Ok(Response::new(TestPolicyResponse { report }))
+    }
+
+    async fn set_policy_data(
+        &self,
+        request: Request<SetPolicyDataRequest>,
+    ) -> Result<Response<SetPolicyDataResponse>, Status> {
+        let request: SetPolicyDataRequest = request.into_inner();
+
+        let set_policy_data_input: as_types::SetPolicyDataInput =
+            serde_json::from_str(&request.input)
+                .map_err(|_| Status::aborted("Bad SetPolicyDataInput"))?;
+
+        let mut server = self.write().await;
+        if let Some(status) = server.read_only() {
+            return Err(status);
+        }
+        let version = server
+            .attestation_service
+            .set_policy_data(
+                set_policy_data_input,
+                Some(request.signature.as_str()).filter(|signature| !signature.is_empty()),
+            )
+            .await
+            .map_err(|e| Status::aborted(format!("Set Policy Data Failed: {e:#}")))?;
+        server.state_changed();
+
+        Ok(Response::new(SetPolicyDataResponse { version }))
+    }
+
+    async fn get_policy_data(
+        &self,
+        request: Request<GetPolicyDataRequest>,
+    ) -> Result<Response<GetPolicyDataResponse>, Status> {
+        let request: GetPolicyDataRequest = request.into_inner();
+
+        let data = self
+            .read()
+            .await
+            .attestation_service
+            .get_policy_data(
+                &request.name,
+                (request.version != 0).then_some(request.version),
+            )
+            .await
+            .map_err(|e| Status::not_found(format!("Get Policy Data Failed: {e:#}")))?;
+
+        let data = serde_json::to_string(&data)
+            .map_err(|e| Status::aborted(format!("Serialize policy data: {e}")))?;
+
+        Ok(Response::new(GetPolicyDataResponse { data }))
+    }
+
+    async fn get_policy_by_digest(
+        &self,
+        request: Request<GetPolicyByDigestRequest>,
+    ) -> Result<Response<GetPolicyByDigestResponse>, Status> {
+        let request: GetPolicyByDigestRequest = request.into_inner();
+
+        let policy = self
+            .read()
+            .await
+            .attestation_service
+            .get_policy_by_digest(&request.digest)
+            .await
+            .map_err(|e| Status::not_found(format!("Get Policy Failed: {e:#}")))?;
+
+        Ok(Response::new(GetPolicyByDigestResponse { policy }))
+    }
+
+    async fn list_policies(
+        &self,
+        _request: Request<ListPoliciesRequest>,
+    ) -> Result<Response<ListPoliciesResponse>, Status> {
+        let policies = self
+            .read()
+            .await
+            .attestation_service
+            .list_policies()
+            .await
+            .map_err(|e| Status::internal(format!("List Policies Failed: {e:#}")))?;
+
+        Ok(Response::new(ListPoliciesResponse {
+            policies: policies.into_iter().collect(),
+        }))
+    }
+
+    async fn get_policy(
+        &self,
+        request: Request<GetPolicyRequest>,
+    ) -> Result<Response<GetPolicyResponse>, Status> {
+        let request: GetPolicyRequest = request.into_inner();
+
+        let policy = self
+            .read()
+            .await
+            .attestation_service
+            .get_policy(&request.policy_id)
+            .await
+            .map_err(|e| Status::not_found(format!("Get Policy Failed: {e:#}")))?;
+
+        Ok(Response::new(GetPolicyResponse { policy }))
+    }
+
+    async fn delete_policy(
+        &self,
+        request: Request<DeletePolicyRequest>,
+    ) -> Result<Response<DeletePolicyResponse>, Status> {
+        let request: DeletePolicyRequest = request.into_inner();
+
+        let mut server = self.write().await;
+        if let Some(status) = server.read_only() {
+            return Err(status);
+        }
+        server
+            .attestation_service
+            .delete_policy(
+                &request.policy_id,
+                Some(request.signature.as_str()).filter(|signature| !signature.is_empty()),
+            )
+            .await
+            .map_err(|e| Status::aborted(format!("Delete Policy Failed: {e:#}")))?;
+        server.state_changed();
+
+        Ok(Response::new(DeletePolicyResponse {}))
+    }
+
+    async fn get_policy_revisions(
+        &self,
+        request: Request<GetPolicyRevisionsRequest>,
+    ) -> Result<Response<GetPolicyRevisionsResponse>, Status> {
+        let request: GetPolicyRevisionsRequest = request.into_inner();
+
+        let revisions = self
+            .read()
+            .await
+            .attestation_service
+            .policy_revisions(&request.policy_id)
+            .await
+            .map_err(|e| Status::internal(format!("Get Policy Revisions Failed: {e:#}")))?;
+
+        Ok(Response::new(GetPolicyRevisionsResponse {
+            revisions: revisions
+                .into_iter()
+                .map(|revision| PolicyRevision {
+                    digest: revision.digest,
+                    set_at: revision.set_at,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn rollback_policy(
+        &self,
+        request: Request<RollbackPolicyRequest>,
+    ) -> Result<Response<RollbackPolicyResponse>, Status> {
+        let request: RollbackPolicyRequest = request.into_inner();
+
+        let mut server = self.write().await;
+        if let Some(status) = server.read_only() {
+            return Err(status);
+        }
+        server
+            .attestation_service
+            .rollback_policy(
+                &request.policy_id,
+                &request.digest,
+                Some(request.signature.as_str()).filter(|signature| !signature.is_empty()),
+            )
+            .await
+            .map_err(|e| Status::aborted(format!("Roll Back Policy Failed: {e:#}")))?;
+        server.state_changed();
+
+        Ok(Response::new(RollbackPolicyResponse {}))
+    }
+
+    async fn import_signing_key(
+        &self,
+        request: Request<ImportSigningKeyRequest>,
+    ) -> Result<Response<ImportSigningKeyResponse>, Status> {
+        require_admin(self, &request, "Importing a signing key").await?;
+        let request: ImportSigningKeyRequest = request.into_inner();
+
+        let mut server = self.write().await;
+        if let Some(status) = server.read_only() {
+            return Err(status);
+        }
+        server
+            .attestation_service
+            .import_signing_key(&request.pkcs8_pem, &request.password)
+            .map_err(|e| Status::aborted(format!("Import Signing Key Failed: {e:#}")))?;
+
+        info!("Token signing key staged");
+        Ok(Response::new(ImportSigningKeyResponse {}))
+    }
+
+    async fn get_signing_keys(
+        &self,
+        _request: Request<GetSigningKeysRequest>,
+    ) -> Result<Response<GetSigningKeysResponse>, Status> {
+        let jwks = self
+            .read()
+            .await
+            .attestation_service
+            .signing_keys()
+            .map_err(|e| Status::aborted(format!("Get Signing Keys Failed: {e:#}")))?;
+
+        Ok(Response::new(GetSigningKeysResponse { jwks }))
+    }
+
+    async fn promote_signing_key(
+        &self,
+        request: Request<PromoteSigningKeyRequest>,
+    ) -> Result<Response<PromoteSigningKeyResponse>, Status> {
+        require_admin(self, &request, "Promoting a signing key").await?;
+
+        let mut server = self.write().await;
+        if let Some(status) = server.read_only() {
+            return Err(status);
+        }
+        server
+            .attestation_service
+            .promote_signing_key()
+            .map_err(|e| Status::aborted(format!("Promote Signing Key Failed: {e:#}")))?;
+
+        info!("Staged token signing key promoted");
+        Ok(Response::new(PromoteSigningKeyResponse {}))
+    }
+
+    async fn export_state(
+        &self,
+        request: Request<ExportStateRequest>,
+    ) -> Result<Response<ExportStateResponse>, Status> {
+        // The archive holds the policies, policy data and reference values.
+        require_admin(self, &request, "Exporting the state").await?;
+        let archive = self
+            .read()
+            .await
+            .attestation_service
+            .export_state()
+            .await
+            .map_err(|e| Status::aborted(format!("Export State Failed: {e:#}")))?;
+
+        info!("Service state exported");
+        Ok(Response::new(ExportStateResponse { archive }))
+    }
+
+    async fn import_state(
+        &self,
+        request: Request<ImportStateRequest>,
+    ) -> Result<Response<ImportStateResponse>, Status> {
+        // The archive replaces the policies and reference values.
+        require_admin(self, &request, "Importing the state").await?;
+        let request: ImportStateRequest = request.into_inner();
+
+        let mut server = self.write().await;
+        if let Some(status) = server.read_only() {
+            return Err(status);
+        }
+        server
+            .attestation_service
+            .import_state(&request.archive)
+            .await
+            .map_err(|e| Status::aborted(format!("Import State Failed: {e:#}")))?;
+        server.state_changed();
+
+        info!("Service state imported");
+        Ok(Response::new(ImportStateResponse {}))
+    }
+
+    type SubscribeStateStream =
+        Pin<Box<dyn Stream<Item = Result<StateSnapshot, Status>> + Send + 'static>>;
+
+    async fn subscribe_state(
+        &self,
+        request: Request<SubscribeStateRequest>,
+    ) -> Result<Response<Self::SubscribeStateStream>, Status> {
+        // The snapshots hold the state, as those of `ExportState`.
+        let revisions = {
+            let server = self.read().await;
+            server
+                .standbys
+                .authorize(&request, "Subscribing to the state")?;
+            server.state_revision.subscribe()
+        };
+        let server = self.clone();
+
+        info!("State subscriber connected");
+        // The current state first, then the state after every change.
+        // Changes made while a snapshot is exported are coalesced.
+        let snapshots = WatchStream::new(revisions).then(move |revision| {
+            let server = server.clone();
+            async move {
+                let archive = server
+                    .read()
+                    .await
+                    .attestation_service
+                    .export_state()
+                    .await
+                    .map_err(|e| Status::aborted(format!("Export State Failed: {e:#}")))?;
+                Ok(StateSnapshot { revision, archive })
+            }
+        });
+        Ok(Response::new(Box::pin(snapshots)))
+    }
+
+    async fn list_provisional_reference_values(
+        &self,
+        request: Request<ListProvisionalReferenceValuesRequest>,
+    ) -> Result<Response<ListProvisionalReferenceValuesResponse>, Status> {
+        require_admin(self, &request, "Listing provisional reference values").await?;
+        let values = self
+            .read()
+            .await
+            .attestation_service
+            .provisional_reference_values()
+            .map_err(|e| {
+                Status::aborted(format!("List Provisional Reference Values Failed: {e:#}"))
+            })?;
+        let values = serde_json::to_string(&values)
+            .map_err(|e| Status::internal(format!("Serialize values failed: {e}")))?;
+
+        Ok(Response::new(ListProvisionalReferenceValuesResponse {
+            values,
+        }))
+    }
+
+    async fn confirm_reference_values(
+        &self,
+        request: Request<ConfirmReferenceValuesRequest>,
+    ) -> Result<Response<ConfirmReferenceValuesResponse>, Status> {
+        require_admin(self, &request, "Confirming reference values").await?;
+        let request: ConfirmReferenceValuesRequest = request.into_inner();
+
+        let mut server = self.write().await;
+        if let Some(status) = server.read_only() {
+            return Err(status);
+        }
+        server
+            .attestation_service
+            .confirm_reference_values(&request.claims)
+            .await
+            .map_err(|e| Status::aborted(format!("Confirm Reference Values Failed: {e:#}")))?;
+        server.state_changed();
+
+        info!("Reference values confirmed: {:?}", request.claims);
+        Ok(Response::new(ConfirmReferenceValuesResponse {}))
+    }
+
+    async fn discard_reference_values(
+        &self,
+        request: Request<DiscardReferenceValuesRequest>,
+    ) -> Result<Response<DiscardReferenceValuesResponse>, Status> {
+        require_admin(self, &request, "Discarding reference values").await?;
+        let request: DiscardReferenceValuesRequest = request.into_inner();
+
+        let mut server = self.write().await;
+        if let Some(status) = server.read_only() {
+            return Err(status);
+        }
+        server
+            .attestation_service
+            .discard_reference_values(&request.claims)
+            .map_err(|e| Status::aborted(format!("Discard Reference Values Failed: {e:#}")))?;
+        server.state_changed();
+
+        info!(
+            "Provisional reference values discarded: {:?}",
+            request.claims
+        );
+        Ok(Response::new(DiscardReferenceValuesResponse {}))
+    }
+
+    async fn add_deny_list_entry(
+        &self,
+        request: Request<AddDenyListEntryRequest>,
+    ) -> Result<Response<AddDenyListEntryResponse>, Status> {
+        require_admin(self, &request, "Adding a deny-list entry").await?;
+        let request: AddDenyListEntryRequest = request.into_inner();
+        let entry = request
+            .entry
+            .ok_or_else(|| Status::invalid_argument("No deny-list entry"))?;
+
+        let server = self.read().await;
+        if let Some(status) = server.read_only() {
+            return Err(status);
+        }
+        server
+            .attestation_service
+            .add_deny_list_entry(
+                DenyListEntry {
+                    claim: entry.claim,
+                    value: entry.value,
+                    reason: entry.reason,
+                    advisory: (!entry.advisory.is_empty()).then_some(entry.advisory),
+                    added: Utc::now(),
+                },
+                Some(request.signature.as_str()).filter(|signature| !signature.is_empty()),
+            )
+            .map_err(|e| Status::aborted(format!("Add Deny-List Entry Failed: {e:#}")))?;
+        server.state_changed();
+
+        Ok(Response::new(AddDenyListEntryResponse {}))
+    }
+
+    async fn remove_deny_list_entry(
+        &self,
+        request: Request<RemoveDenyListEntryRequest>,
+    ) -> Result<Response<RemoveDenyListEntryResponse>, Status> {
+        require_admin(self, &request, "Removing a deny-list entry").await?;
+        let request: RemoveDenyListEntryRequest = request.into_inner();
+
+        let server = self.read().await;
+        if let Some(status) = server.read_only() {
+            return Err(status);
+        }
+        server
+            .attestation_service
+            .remove_deny_list_entry(
+                &request.claim,
+                &request.value,
+                Some(request.signature.as_str()).filter(|signature| !signature.is_empty()),
+            )
+            .map_err(|e| Status::aborted(format!("Remove Deny-List Entry Failed: {e:#}")))?;
+        server.state_changed();
+
+        Ok(Response::new(RemoveDenyListEntryResponse {}))
+    }
+
+    async fn list_deny_list(
+        &self,
+        _request: Request<ListDenyListRequest>,
+    ) -> Result<Response<ListDenyListResponse>, Status> {
+        let entries = self
+            .read()
+            .await
+            .attestation_service
+            .deny_list()
+            .map_err(|e| Status::aborted(format!("List Deny-List Failed: {e:#}")))?;
+
+        Ok(Response::new(ListDenyListResponse {
+            entries: entries.into_iter().map(deny_list_entry).collect(),
+        }))
+    }
+
+    async fn get_service_info(
+        &self,
+        _request: Request<GetServiceInfoRequest>,
+    ) -> Result<Response<GetServiceInfoResponse>, Status> {
+        let server = self.read().await;
+        let posture = server
+            .security_posture()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(GetServiceInfoResponse {
+            version: crate::build::PKG_VERSION.to_string(),
+            fips_mode: server.attestation_service.fips_mode(),
+            primary: server.primary.clone().unwrap_or_default(),
+            replicated_revision: server.replicated_revision,
+            insecure_settings: posture.findings.iter().map(ToString::to_string).collect(),
+        }))
+    }
+
+    async fn get_service_status(
+        &self,
+        request: Request<GetServiceStatusRequest>,
+    ) -> Result<Response<GetServiceStatusResponse>, Status> {
+        // The status discloses the configuration of the AS, so it is only
+        // served to administrators.
+        require_admin(self, &request, "The service status").await?;
+        let request: GetServiceStatusRequest = request.into_inner();
+
+        let server = self.read().await;
+        let posture = server
+            .security_posture()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        let mut status = server
+            .attestation_service
+            .service_status()
+            .await
+            .map_err(|e| Status::internal(format!("Get Service Status Failed: {e:#}")))?;
+        status.config = server.config_digest();
+        status.insecure_settings = posture.findings.iter().map(ToString::to_string).collect();
+        let token = server
+            .attestation_service
+            .issue_service_status(&status, &request.nonce)
+            .map_err(|e| Status::internal(format!("Get Service Status Failed: {e:#}")))?;
+
+        Ok(Response::new(GetServiceStatusResponse { token }))
+    }
+
+    async fn validate_config(
+        &self,
+        request: Request<ValidateConfigRequest>,
+    ) -> Result<Response<ValidateConfigResponse>, Status> {
+        // The checks disclose the configuration of the AS, as the status.
+        require_admin(self, &request, "Validating a config").await?;
+        let request: ValidateConfigRequest = request.into_inner();
+
+        let server = self.read().await;
+        let source = server.config_source.clone().unwrap_or_default();
+        let invalid = |e: anyhow::Error| Status::invalid_argument(format!("Invalid config: {e:#}"));
+        let layers = source.candidate(&request.config).map_err(invalid)?;
+        // The candidate comes from a client: the files it names are not
+        // read and its endpoints are not probed.
+        let config = reload::check_candidate_statically(&layers).map_err(invalid)?;
+        server
+            .attestation_service
+            .validate_config_statically(&config)
+            .await
+            .map_err(invalid)?;
+        let changes = server.config_changes(&layers);
+
+        Ok(Response::new(ValidateConfigResponse {
+            reloaded_sections: changes.reloaded,
+            restart_sections: changes.restart_needed,
+        }))
+    }
+
+    async fn get_usage(
+        &self,
+        request: Request<GetUsageRequest>,
+    ) -> Result<Response<GetUsageResponse>, Status> {
+        let request: GetUsageRequest = request.into_inner();
+        let tenant = (!request.tenant.is_empty()).then_some(request.tenant.as_str());
+
+        let usage = self
+            .read()
+            .await
+            .usage
+            .get(tenant)
+            .into_iter()
+            .map(|(tenant, usage)| TenantUsage {
+                tenant,
+                attestations: usage.attestations,
+                throttled: usage.throttled,
+                period_attestations: usage.period_attestations,
+                quota: usage.quota.unwrap_or_default(),
+            })
+            .collect();
+
+        Ok(Response::new(GetUsageResponse { usage }))
+    }
+
+    async fn get_load(
+        &self,
+        _request: Request<GetLoadRequest>,
+    ) -> Result<Response<GetLoadResponse>, Status> {
+        let load = self.read().await.attestation_service.load();
+        Ok(Response::new(GetLoadResponse {
+            in_flight: load.in_flight as u64,
+            running: load.running as u64,
+            queued: load.queued as u64,
+            workers: load.workers as u64,
+            shed: load.shed,
+            max_in_flight: load.max_in_flight.unwrap_or_default() as u64,
+            max_queue_depth: load.max_queue_depth.unwrap_or_default() as u64,
+        }))
+    }
+
+    async fn get_maintenance_stats(
+        &self,
+        _request: Request<GetMaintenanceStatsRequest>,
+    ) -> Result<Response<GetMaintenanceStatsResponse>, Status> {
+        let stats = self
+            .read()
+            .await
+            .attestation_service
+            .maintenance_stats()
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(GetMaintenanceStatsResponse {
+            runs: stats.runs,
+            last_run: stats
+                .last_run
+                .map(|time| time.to_rfc3339())
+                .unwrap_or_default(),
+            replay_entries: stats.total.replay_entries as u64,
+            reference_values: stats.total.reference_values as u64,
+            history_records: stats.total.history_records as u64,
+            reclaimed_bytes: stats.total.reclaimed_bytes,
+            nonces: stats.total.nonces as u64,
+        }))
+    }
+
+    async fn get_task_health(
+        &self,
+        _request: Request<GetTaskHealthRequest>,
+    ) -> Result<Response<GetTaskHealthResponse>, Status> {
+        let tasks = self
+            .read()
+            .await
+            .tasks
+            .tasks()
+            .into_iter()
+            .map(|task| TaskHealth {
+                name: task.name.to_string(),
+                state: task.state.as_str().to_string(),
+                restarts: task.restarts,
+                last_error: task.last_error.unwrap_or_default(),
+            })
+            .collect();
+        Ok(Response::new(GetTaskHealthResponse { tasks }))
+    }
+
+    async fn get_rejections(
+        &self,
+        _request: Request<GetRejectionsRequest>,
+    ) -> Result<Response<GetRejectionsResponse>, Status> {
+        let rejections = self
+            .read()
+            .await
+            .attestation_service
+            .rejections()
+            .into_iter()
+            .map(|rejection| RejectionCount {
+                tee: rejection.tee,
+                stage: rejection.stage.to_string(),
+                count: rejection.count,
+            })
+            .collect();
+        Ok(Response::new(GetRejectionsResponse { rejections }))
+    }
+
+    async fn get_shadow_stats(
+        &self,
+        _request: Request<GetShadowStatsRequest>,
+    ) -> Result<Response<GetShadowStatsResponse>, Status> {
+        let shadow_stats = self
+            .read()
+            .await
+            .attestation_service
+            .shadow_stats()
+            .into_iter()
+            .map(|count| ShadowCount {
+                policy_id: count.policy_id,
+                shadow_policy_id: count.shadow_policy_id,
+                evaluations: count.evaluations,
+                discrepancies: count.discrepancies,
+                errors: count.errors,
+            })
+            .collect();
+        Ok(Response::new(GetShadowStatsResponse { shadow_stats }))
+    }
+
+    async fn get_inclusion_proof(
+        &self,
+        request: Request<GetInclusionProofRequest>,
+    ) -> Result<Response<GetInclusionProofResponse>, Status> {
+        let proof = self
+            .read()
+            .await
+            .attestation_service
+            .inclusion_proof(request.into_inner().index)
+            .await
+            .map_err(|e| Status::not_found(format!("Get Inclusion Proof Failed: {e:#}")))?;
+        let proof = serde_json::to_string(&proof)
+            .map_err(|e| Status::internal(format!("Serialize inclusion proof: {e}")))?;
+        Ok(Response::new(GetInclusionProofResponse { proof }))
+    }
+
+    async fn get_audit_record(
+        &self,
+        request: Request<GetAuditRecordRequest>,
+    ) -> Result<Response<GetAuditRecordResponse>, Status> {
+        let server = self.read().await;
+        // Administrators get any record, other clients those of their
+        // tenant, which they must authenticate: the anonymous clients of
+        // the default tenant cannot tell each other apart.
+        let tenant = match server.admins.authorize(&request, "Getting an audit record") {
+            Ok(()) => None,
+            Err(status) => {
+                let caller = Caller::new(&server.usage, &request)?;
+                if caller.tenant == DEFAULT_TENANT {
+                    return Err(status);
+                }
+                Some(caller.tenant)
+            }
+        };
+        let attestation_id = request.into_inner().attestation_id;
+        let record = server
+            .attestation_service
+            .audit_record(&attestation_id)
+            .map_err(|e| Status::aborted(format!("Get Audit Record Failed: {e:#}")))?
+            // The records of other tenants are not disclosed to exist.
+            .filter(|record| tenant.is_none() || record.tenant == tenant)
+            .ok_or_else(|| {
+                Status::not_found(format!("No audit record of attestation {attestation_id}"))
+            })?;
+        let record = serde_json::to_string(&record)
+            .map_err(|e| Status::internal(format!("Serialize audit record: {e}")))?;
+        Ok(Response::new(GetAuditRecordResponse { record }))
+    }
+
+    async fn export_history(
+        &self,
+        request: Request<ExportHistoryRequest>,
+    ) -> Result<Response<ExportHistoryResponse>, Status> {
+        // The history has the claims and decisions of every attestation.
+        require_admin(self, &request, "Exporting the history").await?;
+        let request: ExportHistoryRequest = request.into_inner();
+        let format = ExportFormat::from_str(&request.format)
+            .map_err(|e| Status::invalid_argument(format!("{e}")))?;
+        let data = self
+            .read()
+            .await
+            .attestation_service
+            .export_history(format, &request.columns)
+            .map_err(|e| Status::aborted(format!("Export History Failed: {e:#}")))?;
+
+        info!("Attestation history exported");
+        Ok(Response::new(ExportHistoryResponse { data }))
+    }
+
+    async fn reappraise_evidence(
+        &self,
+        request: Request<ReappraiseEvidenceRequest>,
+    ) -> Result<Response<ReappraiseEvidenceResponse>, Status> {
+        // The report tells the measurements of the attestations.
+        require_admin(self, &request, "Reappraising the evidence").await?;
+        let request: ReappraiseEvidenceRequest = request.into_inner();
+        let since = match request.since.as_str() {
+            "" => None,
+            since => Some(
+                DateTime::parse_from_rfc3339(since)
+                    .map_err(|e| Status::invalid_argument(format!("Invalid since {since}: {e}")))?
+                    .with_timezone(&Utc),
+            ),
+        };
+        let filter = ReappraisalFilter {
+            tee: (!request.tee.is_empty()).then_some(request.tee),
+            since,
+        };
+        let (records, last) = {
+            let server = self.read().await;
+            let last = server
+                .reappraisal
+                .clone()
+                .try_lock_owned()
+                .map_err(|_| Status::failed_precondition("A reappraisal is running"))?;
+            let records = server
+                .attestation_service
+                .reappraisal_records(&filter)
+                .map_err(|e| Status::aborted(format!("Reappraise Evidence Failed: {e:#}")))?;
+            (records, last)
+        };
+
+        let attestations = records.len() as u64;
+        info!("Reappraisal of {attestations} attestations started");
+        tokio::spawn(reappraise(self.clone(), records, last));
+        Ok(Response::new(ReappraiseEvidenceResponse { attestations }))
+    }
+
+    async fn get_reappraisal_report(
+        &self,
+        request: Request<GetReappraisalReportRequest>,
+    ) -> Result<Response<GetReappraisalReportResponse>, Status> {
+        require_admin(self, &request, "Getting the reappraisal report").await?;
+        let reappraisal = self.read().await.reappraisal.clone();
+        let Ok(last) = reappraisal.try_lock() else {
+            return Ok(Response::new(GetReappraisalReportResponse {
+                running: true,
+                report: String::new(),
+            }));
+        };
+        let report = match &*last {
+            Some(report) => serde_json::to_string(report)
+                .map_err(|e| Status::internal(format!("Serialize reappraisal report: {e}")))?,
+            None => String::new(),
+        };
+        Ok(Response::new(GetReappraisalReportResponse {
+            running: false,
+            report,
+        }))
+    }
+
+    async fn get_collateral_expiry(
+        &self,
+        _request: Request<GetCollateralExpiryRequest>,
+    ) -> Result<Response<GetCollateralExpiryResponse>, Status> {
+        let expiries = Service::collateral_expiry()
+            .into_iter()
+            .map(|expiry| CollateralExpiry {
+                name: expiry.name,
+                not_after: expiry.not_after,
+                days_until_expiry: expiry.days_until_expiry,
+            })
+            .collect();
+        Ok(Response::new(GetCollateralExpiryResponse { expiries }))
+    }
+
+    async fn register_platforms(
+        &self,
+        request: Request<RegisterPlatformsRequest>,
+    ) -> Result<Response<RegisterPlatformsResponse>, Status> {
+        // The collateral of the platforms is fetched and refreshed from then on.
+        require_admin(self, &request, "Registering platforms").await?;
+        let platforms = request
+            .into_inner()
+            .platforms
+            .into_iter()
+            .map(to_collateral_key)
+            .collect::<Result<Vec<_>, _>>()?;
+        let platforms = self
+            .read()
+            .await
+            .attestation_service
+            .register_platforms(platforms)
+            .await
+            .map_err(|e| Status::failed_precondition(format!("Register Platforms Failed: {e:#}")))?
+            .into_iter()
+            .map(|state| PlatformCollateral {
+                platform: Some(FleetPlatform {
+                    tee: state.platform.tee.to_string(),
+                    fmspc: state.platform.fmspc,
+                    ca: state.platform.ca.to_string(),
+                }),
+                next_update: state.next_update.unwrap_or_default(),
+                error: state.error.unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+
+        info!("{} platforms registered", platforms.len());
+        Ok(Response::new(RegisterPlatformsResponse { platforms }))
+    }
+
+    async fn get_capabilities(
+        &self,
+        _request: Request<GetCapabilitiesRequest>,
+    ) -> Result<Response<GetCapabilitiesResponse>, Status> {
+        let capabilities = self.read().await.attestation_service.capabilities();
+        let verifiers = capabilities
+            .verifiers
+            .into_iter()
+            .map(|verifier| VerifierCapabilities {
+                tee: verifier.tee,
+                registered: verifier.registered,
+                enabled: verifier.enabled,
+                freshness_methods: verifier
+                    .freshness_methods
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect(),
+                evidence_formats: verifier
+                    .evidence_versions
+                    .into_iter()
+                    .map(|evidence| EvidenceFormat {
+                        format: evidence.format.to_string(),
+                        min_version: evidence.accepted.min.unwrap_or_default(),
+                        max_version: evidence.accepted.max.unwrap_or_default(),
+                        accepted: evidence.accepted.to_string(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        Ok(Response::new(GetCapabilitiesResponse {
+            verifiers,
+            policy_engines: capabilities.policy_engines,
+            policy_engine: capabilities.policy_engine,
+            token_brokers: capabilities.token_brokers,
+            token_broker: capabilities.token_broker,
+            token_format: capabilities.token_format,
+        }))
+    }
+
+    async fn challenge(
+        &self,
+        request: Request<ChallengeRequest>,
+    ) -> Result<Response<ChallengeResponse>, Status> {
+        let request: ChallengeRequest = request.into_inner();
+        let tee = GrpcTee::from_i32(request.tee)
+            .ok_or_else(|| Status::invalid_argument(format!("Invalid TEE {}", request.tee)))?;
+        let offered = request
+            .freshness_methods
+            .iter()
+            .map(|method| {
+                FreshnessMethod::from_str(method)
+                    .with_context(|| format!("Invalid freshness method {method}"))
+            })
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| Status::invalid_argument(format!("{e:#}")))?;
+
+        let challenge = self
+            .read()
+            .await
+            .attestation_service
+            .challenge(&to_kbs_tee(tee), &offered)
+            .await
+            .map_err(|e| Status::failed_precondition(format!("Challenge Failed: {e:#}")))?;
+
+        debug!("Challenge of {tee:?}: {}", challenge.freshness);
+        Ok(Response::new(ChallengeResponse {
+            freshness_method: challenge.freshness.to_string(),
+            nonce: challenge.nonce.unwrap_or_default(),
+            expires_at: challenge.expires_at.unwrap_or_default(),
+        }))
+    }
+
+    async fn get_canonical_claims(
+        &self,
+        _request: Request<GetCanonicalClaimsRequest>,
+    ) -> Result<Response<GetCanonicalClaimsResponse>, Status> {
+        let claims = Service::canonical_claims()
+            .iter()
+            .map(|claim| CanonicalClaim {
+                name: claim.name.to_string(),
+                description: claim.description.to_string(),
+                sources: claim
+                    .sources
+                    .iter()
+                    .map(|source| CanonicalClaimSource {
+                        tee: source.tee.to_string(),
+                        claim: source.claim.to_string(),
+                        conversion: source.conversion.to_string(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        Ok(Response::new(GetCanonicalClaimsResponse { claims }))
+    }
+
+    async fn self_test(
+        &self,
+        _request: Request<SelfTestRequest>,
+    ) -> Result<Response<SelfTestResponse>, Status> {
+        let self_test = self.read().await.attestation_service.self_test().await;
+        let checks = |checks: Vec<Check>| {
+            checks
+                .into_iter()
+                .map(|check| SelfTestCheck {
+                    name: check.name,
+                    passed: check.passed,
+                    details: check.details,
+                })
+                .collect()
+        };
+        Ok(Response::new(SelfTestResponse {
+            service: checks(self_test.service),
+            platform: checks(self_test.platform),
+        }))
+    }
+
     async fn attestation_evaluate(
         &self,
         request: Request<AttestationRequest>,
     ) -> Result<Response<AttestationResponse>, Status> {
-        let request: AttestationRequest = request.into_inner();
+        let server = self.read().await;
+        let caller = Caller::new(&server.usage, &request)?;
+        let response = server
+            .attestation(caller, request.into_inner(), None)
+            .await?;
+        Ok(Response::new(response))
+    }
 
-        debug!("Evidence: {}", &request.evidence);
+    async fn attestation_evaluate_batch(
+        &self,
+        request: Request<AttestationBatchRequest>,
+    ) -> Result<Response<AttestationBatchResponse>, Status> {
+        let server = self.read().await;
+        let caller = Caller::new(&server.usage, &request)?;
+        let response = server
+            .attestation_batch(caller, request.into_inner().requests)
+            .await?;
+        Ok(Response::new(response))
+    }
 
-        let attestation_token = self
-            .read()
-            .await
+    type AttestationEvaluateStreamStream =
+        Pin<Box<dyn Stream<Item = Result<AttestationProgress, Status>> + Send + 'static>>;
+
+    async fn attestation_evaluate_stream(
+        &self,
+        request: Request<Streaming<AttestationChunk>>,
+    ) -> Result<Response<Self::AttestationEvaluateStreamStream>, Status> {
+        let caller = Caller::new(&self.read().await.usage, &request)?;
+        let request = streaming::assemble(request.into_inner()).await?;
+        let (progress, steps) = Progress::channel();
+        let server = self.clone();
+        let evaluation = async move {
+            server
+                .read()
+                .await
+                .attestation(caller, request, Some(progress))
+                .await
+        };
+        Ok(Response::new(Box::pin(streaming::progress(
+            steps, evaluation,
+        ))))
+    }
+
+    async fn evaluate_migration(
+        &self,
+        request: Request<MigrationRequest>,
+    ) -> Result<Response<AttestationResponse>, Status> {
+        let server = self.read().await;
+        let caller = Caller::new(&server.usage, &request)?;
+        server.usage.record(&caller.tenant)?;
+        let request: MigrationRequest = request.into_inner();
+
+        debug!("Source evidence: {}", &request.source_evidence);
+        debug!("Destination evidence: {}", &request.destination_evidence);
+
+        let evaluation = server
             .attestation_service
-            .evaluate(
-                to_kbs_tee(
-                    GrpcTee::from_i32(request.tee)
-                        .ok_or_else(|| Status::aborted(format!("Invalid TEE {}", request.tee)))?,
-                ),
+            .evaluate_migration(
                 &request.nonce,
-                &request.evidence,
+                &request.source_evidence,
+                &request.destination_evidence,
             )
             .await
-            .map_err(|e| Status::aborted(format!("Attestation: {e}")))?;
+            .map_err(attestation_status)?;
 
-        debug!("Attestation Token: {}", &attestation_token);
+        debug!("Migration Token: {}", &evaluation.token);
 
-        let res = AttestationResponse { attestation_token };
-        Ok(Response::new(res))
+        Ok(Response::new(AttestationResponse {
+            attestation_token: evaluation.token,
+            warnings: evaluation.warnings,
+            receipt: evaluation
+                .receipt
+                .map(|receipt| serde_json::to_string(&receipt))
+                .transpose()
+                .map_err(|e| Status::internal(format!("Serialize receipt: {e}")))?
+                .unwrap_or_default(),
+            policies: policy_results(evaluation.policies),
+            claims: claims::claims(&evaluation.claims),
+            ..Default::default()
+        }))
+    }
+
+    async fn reattest(
+        &self,
+        request: Request<ReattestRequest>,
+    ) -> Result<Response<AttestationResponse>, Status> {
+        let server = self.read().await;
+        let caller = Caller::new(&server.usage, &request)?;
+        let response = server.reattest(caller, &request.get_ref().token).await?;
+        Ok(Response::new(response))
+    }
+
+    async fn explain_attestation(
+        &self,
+        request: Request<ExplainAttestationRequest>,
+    ) -> Result<Response<ExplainAttestationResponse>, Status> {
+        let server = self.read().await;
+        let caller = Caller::new(&server.usage, &request)?;
+        server.usage.record(&caller.tenant)?;
+        let request: ExplainAttestationRequest = request.into_inner();
+
+        let format = match request.format.as_str() {
+            "" => ReportFormat::Markdown,
+            format => ReportFormat::from_str(format)
+                .map_err(|_| Status::invalid_argument(format!("Invalid report format {format}")))?,
+        };
+        let tee = GrpcTee::from_i32(request.tee)
+            .ok_or_else(|| Status::aborted(format!("Invalid TEE {}", request.tee)))?;
+
+        let report = server
+            .attestation_service
+            .explain(to_kbs_tee(tee), &request.nonce, &request.evidence)
+            .await;
+
+        debug!("Verification decision: {:?}", report.decision);
+
+        Ok(Response::new(ExplainAttestationResponse {
+            report: report.render(format),
+        }))
     }
 }
 
@@ -138,34 +1786,543 @@ impl ReferenceValueProviderService for Arc<RwLock<AttestationServer>> {
 
         let message = serde_json::from_str(&request.message)
             .map_err(|e| Status::aborted(format!("Parse message: {e}")))?;
-        self.write()
-            .await
+        let mut server = self.write().await;
+        if let Some(status) = server.read_only() {
+            return Err(status);
+        }
+        server
             .attestation_service
             .registry_reference_value(message)
             .await
             .map_err(|e| Status::aborted(format!("Register reference value: {e}")))?;
+        server.state_changed();
 
         let res = ReferenceValueRegisterResponse {};
         Ok(Response::new(res))
     }
 }
 
+fn policy_results(results: Vec<policy_engine::PolicyResult>) -> Vec<PolicyResult> {
+    results
+        .into_iter()
+        .map(|result| PolicyResult {
+            policy_id: result.policy_id,
+            policy_digest: result.policy_digest,
+            allow: result.allow,
+        })
+        .collect()
+}
+
+/// The status of a failed attestation.
+/// The response of an attestation `evaluation`, with its `diagnostics` and
+/// `profile`.
+fn attestation_response(
+    evaluation: Evaluation,
+    diagnostics: Option<Diagnostics>,
+    profile: Option<Profile>,
+) -> Result<AttestationResponse, Status> {
+    debug!("Attestation Token: {}", &evaluation.token);
+
+    let diagnostics = diagnostics.map(|diagnostics| diagnostics.to_json());
+    Ok(AttestationResponse {
+        attestation_token: evaluation.token,
+        warnings: evaluation.warnings,
+        diagnostics_struct: diagnostics.as_ref().and_then(claims::to_struct),
+        diagnostics: diagnostics
+            .map(|diagnostics| diagnostics.to_string())
+            .unwrap_or_default(),
+        certificate: evaluation.certificate.unwrap_or_default(),
+        secrets: evaluation.secrets.into_iter().collect(),
+        receipt: evaluation
+            .receipt
+            .map(|receipt| serde_json::to_string(&receipt))
+            .transpose()
+            .map_err(|e| Status::internal(format!("Serialize receipt: {e}")))?
+            .unwrap_or_default(),
+        policies: policy_results(evaluation.policies),
+        claims: claims::claims(&evaluation.claims),
+        attestation_id: evaluation.attestation_id.unwrap_or_default(),
+        profile: profile.map(|profile| profile.folded()).unwrap_or_default(),
+    })
+}
+
+/// The error `status` of an attestation, with its `diagnostics` in the
+/// `diagnostics-bin` metadata if requested.
+fn with_diagnostics(mut status: Status, diagnostics: Option<&Diagnostics>) -> Status {
+    if let Some(diagnostics) = diagnostics {
+        status.metadata_mut().insert_bin(
+            "diagnostics-bin",
+            MetadataValue::from_bytes(diagnostics.to_json().to_string().as_bytes()),
+        );
+    }
+    status
+}
+
+/// The error `status` of an attestation, with its `attestation_id` in the
+/// `attestation-id` metadata if it is audited.
+fn with_attestation_id(mut status: Status, attestation_id: Option<&str>) -> Status {
+    if let Some(attestation_id) = attestation_id.and_then(|id| MetadataValue::try_from(id).ok()) {
+        status
+            .metadata_mut()
+            .insert("attestation-id", attestation_id);
+    }
+    status
+}
+
+/// The outcome of an attestation request of a batch.
+fn batch_result(response: Result<AttestationResponse, Status>) -> AttestationBatchResult {
+    match response {
+        Ok(response) => AttestationBatchResult {
+            response: Some(response),
+            ..Default::default()
+        },
+        Err(status) => AttestationBatchResult {
+            response: None,
+            code: status.code() as i32,
+            error: status.message().to_string(),
+            attestation_id: status
+                .metadata()
+                .get("attestation-id")
+                .and_then(|id| id.to_str().ok())
+                .unwrap_or_default()
+                .to_string(),
+        },
+    }
+}
+
+fn deny_list_entry(entry: DenyListEntry) -> GrpcDenyListEntry {
+    GrpcDenyListEntry {
+        claim: entry.claim,
+        value: entry.value,
+        reason: entry.reason,
+        advisory: entry.advisory.unwrap_or_default(),
+        added: entry.added.to_rfc3339(),
+    }
+}
+
+fn attestation_status(e: anyhow::Error) -> Status {
+    // A deny-listed value is told apart from a policy denial by the JSON
+    // object of its entry in the status details.
+    if let Some(denied) = e.downcast_ref::<Denied>() {
+        return Status::with_details(
+            Code::PermissionDenied,
+            format!("Attestation: {e:#}"),
+            serde_json::to_vec(&denied.entry).unwrap_or_default().into(),
+        );
+    }
+    // Return the violations as JSON in the status details, so that
+    // attesters can tell why they were denied.
+    if let Some(denied) = e.downcast_ref::<PolicyDenied>() {
+        return Status::with_details(
+            Code::PermissionDenied,
+            format!("Attestation: {e:#}"),
+            serde_json::to_vec(&denied.violations)
+                .unwrap_or_default()
+                .into(),
+        );
+    }
+    if e.is::<PolicyMismatch>() {
+        return Status::failed_precondition(format!("Attestation: {e:#}"));
+    }
+    if e.is::<Replayed>() {
+        return Status::permission_denied(format!("Attestation: {e:#}"));
+    }
+    if e.is::<UnsupportedVersion>() {
+        return Status::invalid_argument(format!("Attestation: {e:#}"));
+    }
+    // Attesters that retry evidence that failed verification are rate
+    // limited until the failure expires.
+    if e.is::<Overloaded>() || e.is::<CachedFailure>() {
+        return Status::resource_exhausted(format!("Attestation: {e:#}"));
+    }
+    Status::aborted(format!("Attestation: {e:#}"))
+}
+
 pub async fn start(
     socket: Option<&str>,
     rvps_addr: Option<&str>,
-    config_path: Option<&str>,
+    config: Config,
+    config_source: ConfigSource,
+    config_layers: ConfigLayers,
+    server_config: ServerConfig,
 ) -> Result<()> {
-    let socket = socket.unwrap_or(DEFAULT_SOCK).parse()?;
-    info!("Listen socket: {}", &socket);
-
-    let attestation_server = Arc::new(RwLock::new(
-        AttestationServer::new(rvps_addr, config_path).await?,
-    ));
-
-    Server::builder()
-        .add_service(AttestationServiceServer::new(attestation_server.clone()))
-        .add_service(ReferenceValueProviderServiceServer::new(attestation_server))
-        .serve(socket)
-        .await?;
+    // An explicit `--socket` takes precedence over the listeners of the
+    // config file.
+    let listeners = match socket {
+        Some(socket) => vec![ListenerConfig::new(socket)],
+        None => server_config.listeners,
+    };
+    let listeners = match listeners.is_empty() {
+        true => vec![ListenerConfig::new(DEFAULT_SOCK)],
+        false => listeners,
+    };
+
+    let plaintext_listeners = server_config.coap.is_some()
+        || server_config
+            .rest
+            .as_ref()
+            .is_some_and(rest::RestConfig::is_plaintext)
+        || server_config.metrics.is_some()
+        || listeners.iter().any(ListenerConfig::is_plaintext);
+    let collateral_config = config.collateral.clone();
+    if let Some(replication) = &server_config.replication {
+        replication.check(&config)?;
+    }
+    let mut attestation_server = AttestationServer::new(
+        rvps_addr,
+        config,
+        server_config.usage,
+        server_config.tenant_policy_parameters,
+        server_config
+            .replication
+            .as_ref()
+            .map(|replication| replication.primary.clone()),
+    )
+    .await?;
+    let fips_mode = attestation_server.attestation_service.fips_mode();
+    if fips_mode {
+        info!("FIPS mode");
+    }
+    attestation_server.plaintext_listeners = plaintext_listeners;
+    server_config.admins.check().context("admins")?;
+    attestation_server.admins = server_config.admins;
+    server_config.standbys.check().context("standbys")?;
+    attestation_server.standbys = server_config.standbys;
+    let config_reload = config_source
+        .path
+        .is_some()
+        .then_some(server_config.config_reload)
+        .flatten();
+    attestation_server.config_source = Some(config_source);
+    attestation_server.config_layers = Some(config_layers);
+    let posture = attestation_server.security_posture().await?;
+    posture.log();
+    posture.enforce(attestation_server.attestation_service.strict_security())?;
+    let mut supervisor = Supervisor::new();
+    attestation_server.tasks = supervisor.health();
+    let attestation_server = Arc::new(RwLock::new(attestation_server));
+
+    // The tasks are stopped in the reverse order: the front ends before
+    // the tasks that keep the stores of the AS.
+    let server = attestation_server.clone();
+    let maintenance = server_config.maintenance;
+    supervisor.spawn("maintenance", move |shutdown| {
+        maintenance::run(maintenance.clone(), server.clone(), shutdown)
+    });
+    if let Some(config_reload) = config_reload {
+        config_reload.check()?;
+        let period = Duration::from_secs(config_reload.poll_secs);
+        let server = attestation_server.clone();
+        supervisor.spawn("config_reload", move |shutdown| {
+            reload::watch(server.clone(), period, shutdown)
+        });
+    }
+    let server = attestation_server.clone();
+    supervisor.spawn("reference_values", move |shutdown| {
+        reference_values::watch(server.clone(), shutdown)
+    });
+    let server = attestation_server.clone();
+    supervisor.spawn("advisories", move |shutdown| {
+        advisories::watch(server.clone(), shutdown)
+    });
+    let provider = collateral::provider(&collateral_config)?;
+    let prefetch_interval_secs = collateral_config.prefetch_interval_secs;
+    supervisor.spawn("prefetch", move |shutdown| {
+        prefetch::run(prefetch_interval_secs, provider.clone(), shutdown)
+    });
+    if let Some(expiry_alerts) = server_config.expiry_alerts {
+        expiry_alerts.check()?;
+        supervisor.spawn("expiry_alerts", move |shutdown| {
+            expiry::watch(expiry_alerts.clone(), shutdown)
+        });
+    }
+    if let Some(replication) = server_config.replication {
+        info!("Warm standby of {}", replication.primary);
+        let server = attestation_server.clone();
+        supervisor.spawn("replication", move |shutdown| {
+            replication::follow(replication.clone(), server.clone(), shutdown)
+        });
+    }
+    if let Some(queue_worker) = server_config.queue_worker {
+        queue_worker.address()?;
+        let server = attestation_server.clone();
+        supervisor.spawn("queue_worker", move |shutdown| {
+            queue::consume(queue_worker.clone(), server.clone(), shutdown)
+        });
+    }
+    if let Some(coap) = server_config.coap {
+        let socket = Arc::new(coap::bind(&coap).await?);
+        let server = attestation_server.clone();
+        supervisor.spawn("coap", move |shutdown| {
+            coap::serve(coap.clone(), socket.clone(), server.clone(), shutdown)
+        });
+    }
+    if let Some(rest) = server_config.rest {
+        rest.address()?;
+        let server = attestation_server.clone();
+        supervisor.spawn("rest", move |shutdown| {
+            rest::serve(rest.clone(), server.clone(), shutdown)
+        });
+    }
+    if let Some(metrics) = server_config.metrics {
+        metrics.address()?;
+        let server = attestation_server.clone();
+        supervisor.spawn("metrics", move |shutdown| {
+            metrics::serve(metrics.clone(), server.clone(), shutdown)
+        });
+    }
+
+    // Serve the sockets passed by systemd if socket activated, with the
+    // settings of the listener of the same address.
+    let activated = systemd::listen_fds()?;
+    let sockets = match activated.is_empty() {
+        true => listeners
+            .into_iter()
+            .map(|listener| {
+                let socket = listener.address.parse::<ListenAddress>()?.bind()?;
+                Ok((listener, socket))
+            })
+            .collect::<Result<Vec<_>>>()?,
+        false => activated
+            .into_iter()
+            .map(|(address, socket)| {
+                let listener = listeners
+                    .iter()
+                    .find(|listener| listener.address.parse().ok().as_ref() == Some(&address))
+                    .cloned()
+                    .unwrap_or_else(|| ListenerConfig::new(&address.to_string()));
+                (listener, socket)
+            })
+            .collect(),
+    };
+    systemd::notify("READY=1")?;
+
+    let mut servers = Vec::new();
+    for (listener, socket) in sockets {
+        servers.push(serve(
+            listener,
+            socket,
+            attestation_server.clone(),
+            fips_mode,
+        ));
+    }
+    let served = tokio::select! {
+        served = try_join_all(servers) => served.map(drop),
+        stopped = supervisor::stop_signal() => {
+            info!("Shutting down");
+            stopped
+        }
+    };
+    systemd::notify("STOPPING=1")?;
+    supervisor.shutdown().await;
+
+    served
+}
+
+/// Validate the config for `--check-config`, without starting the server:
+/// the AS config, the listener addresses and TLS keys, and that the remote
+/// RVPS, if any, is reachable.
+pub async fn check_config(
+    socket: Option<&str>,
+    rvps_addr: Option<&str>,
+    config: &Config,
+    server_config: &ServerConfig,
+) -> Result<()> {
+    config.check()?;
+    let listeners = check_server_config(socket, server_config)?;
+    for listener in &listeners {
+        // The FIPS acceptor parses the certificates and keys, which tonic
+        // only does when serving.
+        if let Some(tls) = &listener.tls {
+            tls.fips_acceptor()
+                .with_context(|| format!("Invalid TLS config of {}", listener.address))?;
+        }
+    }
+
+    // The policies are only known to the AS, so the posture of the config
+    // alone is checked.
+    let mut posture = SecurityPosture::of_config(config);
+    if server_config.coap.is_some()
+        || server_config
+            .rest
+            .as_ref()
+            .is_some_and(rest::RestConfig::is_plaintext)
+        || server_config.metrics.is_some()
+        || listeners.iter().any(ListenerConfig::is_plaintext)
+    {
+        posture.add(Finding::PlaintextListener);
+    }
+    posture.log();
+    posture.enforce(config.strict_security)?;
+
+    if let Some(rest) = &server_config.rest {
+        if let Some(tls) = &rest.tls {
+            tls.http1_acceptor()
+                .with_context(|| format!("Invalid TLS config of {}", rest.address))?;
+        }
+    }
+
+    if let Some(replication) = &server_config.replication {
+        replication.check(config)?;
+    }
+
+    reload::probe_endpoints(config).await?;
+
+    if let Some(addr) = rvps_addr {
+        Agent::new(addr)
+            .await
+            .with_context(|| format!("Cannot connect to RVPS {addr}"))?;
+    }
+
+    Ok(())
+}
+
+/// The checks of `server_config` that neither read the files it names nor
+/// connect to its endpoints, and the listeners it serves, on `socket` if
+/// given.
+pub fn check_server_config(
+    socket: Option<&str>,
+    server_config: &ServerConfig,
+) -> Result<Vec<ListenerConfig>> {
+    server_config.admins.check().context("admins")?;
+    server_config.standbys.check().context("standbys")?;
+
+    let listeners = match socket {
+        Some(socket) => vec![ListenerConfig::new(socket)],
+        None => server_config.listeners.clone(),
+    };
+    let listeners = match listeners.is_empty() {
+        true => vec![ListenerConfig::new(DEFAULT_SOCK)],
+        false => listeners,
+    };
+    for listener in &listeners {
+        listener
+            .address
+            .parse::<ListenAddress>()
+            .with_context(|| format!("Invalid listener address {}", listener.address))?;
+        for compression in &listener.compression {
+            compression
+                .encoding()
+                .with_context(|| format!("Invalid compression of {}", listener.address))?;
+        }
+    }
+
+    if let Some(expiry_alerts) = &server_config.expiry_alerts {
+        expiry_alerts.check()?;
+    }
+
+    if let Some(queue_worker) = &server_config.queue_worker {
+        queue_worker.address()?;
+    }
+
+    if let Some(rest) = &server_config.rest {
+        rest.address()?;
+    }
+
+    if let Some(metrics) = &server_config.metrics {
+        metrics.address()?;
+    }
+
+    if let Some(config_reload) = &server_config.config_reload {
+        config_reload.check()?;
+    }
+
+    Ok(listeners)
+}
+
+/// Appraise the evidence of the attestations `records` of the history
+/// again, in the background, and keep the report in `last`. The server is
+/// only held for one record at a time, as for an attestation.
+async fn reappraise(
+    server: Arc<RwLock<AttestationServer>>,
+    records: Vec<HistoryRecord>,
+    mut last: OwnedMutexGuard<Option<ReappraisalReport>>,
+) {
+    let mut report = ReappraisalReport::default();
+    for record in &records {
+        let result = server
+            .read()
+            .await
+            .attestation_service
+            .reappraise(record, &mut report)
+            .await;
+        if let Err(e) = result {
+            warn!("Attestation {} not appraised again: {e:#}", record.id);
+            report.errors += 1;
+        }
+    }
+    info!(
+        "{} attestations appraised again, {} would now be denied",
+        report.reappraised,
+        report.now_denied.len()
+    );
+    *last = Some(report);
+}
+
+async fn serve(
+    listener: ListenerConfig,
+    socket: BoundSocket,
+    server: Arc<RwLock<AttestationServer>>,
+    fips_mode: bool,
+) -> Result<()> {
+    let mut builder = Server::builder();
+    // The TLS config of tonic cannot restrict the cipher suites, so TLS is
+    // terminated before tonic in FIPS mode.
+    let mut fips_acceptor = None;
+    match &listener.tls {
+        Some(tls) if fips_mode => fips_acceptor = Some(tls.fips_acceptor()?),
+        Some(tls) => builder = builder.tls_config(tls.server_tls_config()?)?,
+        None => (),
+    }
+
+    info!(
+        "Listen socket: {} ({})",
+        listener.address,
+        if listener.tls.is_some() {
+            "TLS"
+        } else {
+            "plain text"
+        }
+    );
+
+    let mut attestation = AttestationServiceServer::new(server.clone());
+    let mut rvps = ReferenceValueProviderServiceServer::new(server);
+    for compression in &listener.compression {
+        let encoding = compression.encoding()?;
+        attestation = attestation
+            .accept_compressed(encoding)
+            .send_compressed(encoding);
+        rvps = rvps.accept_compressed(encoding).send_compressed(encoding);
+    }
+    let access = ListenerAccess {
+        diagnostics: listener.diagnostics,
+        admin: listener.admin,
+    };
+    let attestation = InterceptedService::new(attestation, access);
+    let router = builder.add_service(attestation).add_service(rvps);
+
+    match (socket, fips_acceptor) {
+        (BoundSocket::Tcp(socket), None) => {
+            let incoming = TcpListenerStream::new(socket);
+            router.serve_with_incoming(incoming).await?;
+        }
+        (BoundSocket::Tcp(socket), Some(acceptor)) => {
+            let incoming = TcpListenerStream::new(socket);
+            router
+                .serve_with_incoming(tls_incoming(incoming, acceptor))
+                .await?;
+        }
+        (BoundSocket::Unix(socket), None) => {
+            let incoming = UnixListenerStream::new(socket).map_ok(UnixStream);
+            router.serve_with_incoming(incoming).await?;
+        }
+        (BoundSocket::Unix(socket), Some(acceptor)) => {
+            let incoming = UnixListenerStream::new(socket).map_ok(UnixStream);
+            router
+                .serve_with_incoming(tls_incoming(incoming, acceptor))
+                .await?;
+        }
+    }
+
     Ok(())
 }
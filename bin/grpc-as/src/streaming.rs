@@ -0,0 +1,177 @@
+//! Streaming attestation, for large evidence.
+//!
+//! Evidence with a long event log or IMA log can exceed the largest gRPC
+//! message, 4 MiB by default. `AttestationEvaluateStream` takes the
+//! request in `AttestationChunk`s instead: the first has the request, with
+//! the start of its evidence, if any, and the next ones the rest of the
+//! evidence. Once the client has sent the last chunk, the server streams
+//! `AttestationProgress` back: `received`, the stages of the verifier
+//! pipeline as it enters them, `policy` and `issuance`, then `done` with
+//! the response, or the error status of the attestation.
+
+use attestation_service::progress::Step;
+use futures::{Stream, StreamExt};
+use std::future::Future;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::Status;
+
+use crate::as_api::{
+    AttestationChunk, AttestationProgress, AttestationRequest, AttestationResponse,
+};
+
+/// Largest evidence accepted, reassembled from its chunks.
+const MAX_STREAMED_EVIDENCE: usize = 64 * 1024 * 1024;
+
+/// Reassemble the request streamed in `chunks`.
+pub async fn assemble(
+    mut chunks: impl Stream<Item = Result<AttestationChunk, Status>> + Unpin,
+) -> Result<AttestationRequest, Status> {
+    let first = chunks
+        .next()
+        .await
+        .ok_or_else(|| Status::invalid_argument("No attestation request"))??;
+    let mut request = first
+        .request
+        .ok_or_else(|| Status::invalid_argument("The first chunk has no attestation request"))?;
+    let mut evidence = std::mem::take(&mut request.evidence).into_bytes();
+    evidence.extend(first.evidence);
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        if chunk.request.is_some() {
+            return Err(Status::invalid_argument(
+                "Only the first chunk has the attestation request",
+            ));
+        }
+        if evidence.len() + chunk.evidence.len() > MAX_STREAMED_EVIDENCE {
+            return Err(Status::resource_exhausted(format!(
+                "The evidence is larger than {MAX_STREAMED_EVIDENCE} bytes"
+            )));
+        }
+        evidence.extend(chunk.evidence);
+    }
+    request.evidence = String::from_utf8(evidence)
+        .map_err(|_| Status::invalid_argument("The evidence is not UTF-8"))?;
+    Ok(request)
+}
+
+fn step(step: &str) -> AttestationProgress {
+    AttestationProgress {
+        step: step.to_string(),
+        response: None,
+    }
+}
+
+/// Stream the `steps` of `evaluation` as it runs, then its response.
+pub fn progress<F>(
+    mut steps: UnboundedReceiver<Step>,
+    evaluation: F,
+) -> impl Stream<Item = Result<AttestationProgress, Status>>
+where
+    F: Future<Output = Result<AttestationResponse, Status>> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let _ = sender.send(Ok(step("received")));
+        tokio::pin!(evaluation);
+        let result = loop {
+            tokio::select! {
+                biased;
+                Some(reached) = steps.recv() => {
+                    let _ = sender.send(Ok(step(&reached.to_string())));
+                }
+                result = &mut evaluation => break result,
+            }
+        };
+        while let Ok(reached) = steps.try_recv() {
+            let _ = sender.send(Ok(step(&reached.to_string())));
+        }
+        let _ = sender.send(result.map(|response| AttestationProgress {
+            step: "done".to_string(),
+            response: Some(response),
+        }));
+    });
+    UnboundedReceiverStream::new(receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use attestation_service::progress::Progress;
+    use attestation_service::verifier::pipeline::Stage;
+    use futures::stream;
+
+    fn chunk(
+        request: Option<AttestationRequest>,
+        evidence: &[u8],
+    ) -> Result<AttestationChunk, Status> {
+        Ok(AttestationChunk {
+            request,
+            evidence: evidence.to_vec(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_assemble() {
+        let request = AttestationRequest {
+            nonce: "n0".to_string(),
+            evidence: "{\"tee_".to_string(),
+            ..Default::default()
+        };
+        // A chunk boundary in the middle of a UTF-8 character.
+        let chunks = stream::iter(vec![
+            chunk(Some(request.clone()), b"pubkey\": \"\xc3"),
+            chunk(None, b"\xa9\"}"),
+        ]);
+        let assembled = assemble(chunks).await.unwrap();
+        assert_eq!(assembled.nonce, "n0");
+        assert_eq!(assembled.evidence, "{\"tee_pubkey\": \"\u{e9}\"}");
+
+        let e = assemble(stream::iter(vec![chunk(None, b"{}")]))
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), tonic::Code::InvalidArgument);
+        let e = assemble(stream::iter(vec![
+            chunk(Some(request.clone()), b""),
+            chunk(Some(request.clone()), b""),
+        ]))
+        .await
+        .unwrap_err();
+        assert_eq!(e.code(), tonic::Code::InvalidArgument);
+        let e = assemble(stream::iter(vec![
+            chunk(Some(request), b""),
+            chunk(None, &vec![b' '; MAX_STREAMED_EVIDENCE + 1]),
+        ]))
+        .await
+        .unwrap_err();
+        assert_eq!(e.code(), tonic::Code::ResourceExhausted);
+        assert!(assemble(stream::iter(vec![])).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_progress() {
+        let (reporter, steps) = Progress::channel();
+        let evaluation = async move {
+            reporter.report(Step::Verifier(Stage::Parse));
+            reporter.report(Step::Policy);
+            Ok(AttestationResponse {
+                attestation_token: "token".to_string(),
+                ..Default::default()
+            })
+        };
+        let messages: Vec<_> = progress(steps, evaluation).collect().await;
+        let steps: Vec<_> = messages
+            .iter()
+            .map(|message| message.as_ref().unwrap().step.as_str())
+            .collect();
+        assert_eq!(steps, ["received", "parse", "policy", "done"]);
+        let response = messages[3].as_ref().unwrap().response.as_ref().unwrap();
+        assert_eq!(response.attestation_token, "token");
+
+        let (_, steps) = Progress::channel();
+        let failed = async { Err::<AttestationResponse, _>(Status::permission_denied("denied")) };
+        let messages: Vec<_> = progress(steps, failed).collect().await;
+        assert_eq!(messages.len(), 2);
+        assert!(messages[1].is_err());
+    }
+}
@@ -0,0 +1,363 @@
+//! Supervision of the background tasks of the server: the maintenance
+//! runs, the reload of the config and of the reference values, the polls
+//! of the advisory feed, the collateral prefetch and expiry alerts, the
+//! replication of a standby, and the front ends other than gRPC.
+//!
+//! The [`Supervisor`] starts each task from a factory, so that it can start
+//! it again: a task that fails or panics is restarted after a backoff that
+//! doubles from 1 second to 5 minutes, and starts over from 1 second once
+//! the task has run for 5 minutes. A task that returns without error, e.g.
+//! the maintenance with an `interval_secs` of 0, is done.
+//!
+//! On SIGTERM or SIGINT, the server stops serving gRPC, then stops the
+//! tasks one at a time, in the reverse order they were started: the front
+//! ends first, then the tasks that keep the stores of the AS. Each task is
+//! given a [`Shutdown`] signal that it waits for between units of work, so
+//! that a maintenance run or a reload in progress completes, and a task
+//! still running 10 seconds after its signal is aborted.
+//!
+//! The state of each task, with its restarts and last error, is served by
+//! the `GetTaskHealth` API and by `GET /health` of the REST front end.
+
+use anyhow::Result;
+use futures::FutureExt;
+use log::{info, warn};
+use serde::Serialize;
+use std::any::Any;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// First delay before a failed task is restarted.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest delay before a failed task is restarted, and how long a task
+/// runs before its backoff starts over.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// How long a task has to stop once signalled, before it is aborted.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Signal of the shutdown of the server, given to each task.
+#[derive(Clone, Debug)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// Wait for the shutdown. Cancellation safe, for `tokio::select!`.
+    pub async fn signalled(&mut self) {
+        // The supervisor is gone if the channel is closed, a shutdown too.
+        let _ = self.0.wait_for(|stopping| *stopping).await;
+    }
+
+    fn is_signalled(&self) -> bool {
+        *self.0.borrow()
+    }
+}
+
+/// State of a supervised task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    /// The task failed, and waits for its backoff to restart.
+    Restarting,
+    /// The task returned without error.
+    Done,
+    /// The task was stopped by the shutdown.
+    Stopped,
+}
+
+impl TaskState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Running => "running",
+            Self::Restarting => "restarting",
+            Self::Done => "done",
+            Self::Stopped => "stopped",
+        }
+    }
+}
+
+/// Health of a supervised task.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TaskHealth {
+    pub name: &'static str,
+    pub state: TaskState,
+    /// Restarts since the server started.
+    pub restarts: u64,
+    /// Error of the last failure, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// Health of the supervised tasks, in the order they were started.
+#[derive(Clone, Debug, Default)]
+pub struct Health(Arc<Mutex<Vec<TaskHealth>>>);
+
+impl Health {
+    pub fn tasks(&self) -> Vec<TaskHealth> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Whether no task waits to be restarted.
+    pub fn is_healthy(&self) -> bool {
+        self.tasks()
+            .iter()
+            .all(|task| task.state != TaskState::Restarting)
+    }
+
+    fn add(&self, name: &'static str) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(TaskHealth {
+                name,
+                state: TaskState::Running,
+                restarts: 0,
+                last_error: None,
+            });
+    }
+
+    fn update(&self, name: &'static str, update: impl FnOnce(&mut TaskHealth)) {
+        let mut tasks = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(task) = tasks.iter_mut().find(|task| task.name == name) {
+            update(task);
+        }
+    }
+}
+
+struct Task {
+    name: &'static str,
+    stop: watch::Sender<bool>,
+    handle: JoinHandle<()>,
+}
+
+/// The supervisor of the background tasks, see the module documentation.
+pub struct Supervisor {
+    tasks: Vec<Task>,
+    health: Health,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    stop_timeout: Duration,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self {
+            tasks: Vec::new(),
+            health: Health::default(),
+            min_backoff: MIN_BACKOFF,
+            max_backoff: MAX_BACKOFF,
+            stop_timeout: STOP_TIMEOUT,
+        }
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The health of the tasks, kept up to date as they run.
+    pub fn health(&self) -> Health {
+        self.health.clone()
+    }
+
+    /// Start the task `name`, with a [`Shutdown`] signal, and restart it
+    /// from `task` whenever it fails, until the shutdown.
+    pub fn spawn<F, T>(&mut self, name: &'static str, task: F)
+    where
+        F: FnMut(Shutdown) -> T + Send + 'static,
+        T: Future<Output = Result<()>> + Send + 'static,
+    {
+        let (stop, stopping) = watch::channel(false);
+        self.health.add(name);
+        let handle = tokio::spawn(supervise(
+            name,
+            task,
+            Shutdown(stopping),
+            self.health.clone(),
+            (self.min_backoff, self.max_backoff),
+        ));
+        self.tasks.push(Task { name, stop, handle });
+    }
+
+    /// Stop the tasks, the last started first, each within the stop
+    /// timeout.
+    pub async fn shutdown(self) {
+        for mut task in self.tasks.into_iter().rev() {
+            let _ = task.stop.send(true);
+            if tokio::time::timeout(self.stop_timeout, &mut task.handle)
+                .await
+                .is_err()
+            {
+                warn!(
+                    "Task {} did not stop within {}s, aborted",
+                    task.name,
+                    self.stop_timeout.as_secs()
+                );
+                task.handle.abort();
+            }
+            self.health.update(task.name, |health| {
+                if health.state != TaskState::Done {
+                    health.state = TaskState::Stopped;
+                }
+            });
+        }
+        info!("Background tasks stopped");
+    }
+}
+
+/// Run the task `name` until it is done or `shutdown`, restarting it after
+/// a backoff when it fails.
+async fn supervise<F, T>(
+    name: &'static str,
+    mut task: F,
+    mut shutdown: Shutdown,
+    health: Health,
+    (min_backoff, max_backoff): (Duration, Duration),
+) where
+    F: FnMut(Shutdown) -> T,
+    T: Future<Output = Result<()>>,
+{
+    let mut backoff = min_backoff;
+    loop {
+        let started = Instant::now();
+        health.update(name, |health| health.state = TaskState::Running);
+        let error = match AssertUnwindSafe(task(shutdown.clone()))
+            .catch_unwind()
+            .await
+        {
+            Ok(Ok(())) => {
+                if !shutdown.is_signalled() {
+                    health.update(name, |health| health.state = TaskState::Done);
+                }
+                return;
+            }
+            Ok(Err(e)) => format!("{e:#}"),
+            Err(panic) => format!("panicked: {}", panic_message(panic.as_ref())),
+        };
+        if shutdown.is_signalled() {
+            return;
+        }
+        if started.elapsed() >= max_backoff {
+            backoff = min_backoff;
+        }
+        warn!(
+            "Task {name} failed: {error}, restarting in {}s",
+            backoff.as_secs()
+        );
+        health.update(name, |health| {
+            health.state = TaskState::Restarting;
+            health.restarts += 1;
+            health.last_error = Some(error);
+        });
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => (),
+            _ = shutdown.signalled() => return,
+        }
+        backoff = (backoff * 2).min(max_backoff);
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message,
+        (_, Some(message)) => message,
+        _ => "unknown panic",
+    }
+}
+
+/// Wait for SIGTERM or SIGINT.
+pub async fn stop_signal() -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = terminate.recv() => (),
+        interrupted = tokio::signal::ctrl_c() => interrupted?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn supervisor() -> Supervisor {
+        Supervisor {
+            min_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(60),
+            stop_timeout: Duration::from_millis(100),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restart() {
+        let mut supervisor = supervisor();
+        let runs = Arc::new(AtomicU32::new(0));
+        let task_runs = runs.clone();
+        supervisor.spawn("flaky", move |_| {
+            let runs = task_runs.clone();
+            async move {
+                match runs.fetch_add(1, Ordering::SeqCst) {
+                    0 => bail!("connection refused"),
+                    1 => panic!("corrupted state"),
+                    _ => Ok(()),
+                }
+            }
+        });
+        let health = supervisor.health();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        let tasks = health.tasks();
+        assert_eq!(tasks[0].state, TaskState::Done);
+        assert_eq!(tasks[0].restarts, 2);
+        assert_eq!(
+            tasks[0].last_error.as_deref(),
+            Some("panicked: corrupted state")
+        );
+        assert!(health.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let mut supervisor = Supervisor {
+            min_backoff: Duration::from_secs(60),
+            ..supervisor()
+        };
+        let stopped = Arc::new(Mutex::new(Vec::new()));
+        for name in ["maintenance", "rest"] {
+            let stopped = stopped.clone();
+            supervisor.spawn(name, move |mut shutdown| {
+                let stopped = stopped.clone();
+                async move {
+                    shutdown.signalled().await;
+                    stopped.lock().unwrap().push(name);
+                    Ok(())
+                }
+            });
+        }
+        // A task that does not wait for the signal is aborted.
+        supervisor.spawn("stuck", |_| std::future::pending());
+        supervisor.spawn("failing", |_| async { bail!("unreachable") });
+        let health = supervisor.health();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!health.is_healthy());
+
+        supervisor.shutdown().await;
+        assert_eq!(*stopped.lock().unwrap(), ["rest", "maintenance"]);
+        assert!(health
+            .tasks()
+            .iter()
+            .all(|task| task.state == TaskState::Stopped));
+    }
+}
@@ -0,0 +1,136 @@
+//! systemd socket activation and readiness notification.
+//!
+//! When started by a systemd socket unit, the server serves the sockets
+//! that systemd passes it (`LISTEN_FDS`) instead of binding its own, so
+//! that the unit can drop the privileges to bind them and restrict who
+//! connects with the socket unit settings. The listeners of the config
+//! file still give the TLS and compression settings of the sockets with
+//! the same address.
+//!
+//! When started by a `Type=notify` service unit, the server tells systemd
+//! that it is ready (`NOTIFY_SOCKET`) once its sockets are listening.
+
+use crate::listener::{BoundSocket, ListenAddress};
+use anyhow::{anyhow, bail, Context, Result};
+use socket2::{Socket, Type};
+use std::env;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::Path;
+use tokio::net::{TcpListener, UnixListener};
+
+/// First file descriptor passed by systemd.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// The sockets passed by systemd, none if the server is not socket
+/// activated. The environment variables of socket activation are removed,
+/// so that they are not inherited by child processes.
+pub fn listen_fds() -> Result<Vec<(ListenAddress, BoundSocket)>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+
+    // The sockets are passed to another process if the PID does not match.
+    let (Some(pid), Some(fds)) = (pid, fds) else {
+        return Ok(Vec::new());
+    };
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+        return Ok(Vec::new());
+    }
+    let fds: RawFd = fds
+        .parse()
+        .map_err(|e| anyhow!("invalid LISTEN_FDS {fds}: {e}"))?;
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + fds)
+        .map(|fd| inherit(fd).with_context(|| format!("socket activated file descriptor {fd}")))
+        .collect()
+}
+
+/// A listening stream socket, from its file descriptor passed by systemd.
+fn inherit(fd: RawFd) -> Result<(ListenAddress, BoundSocket)> {
+    // SAFETY: systemd passes the file descriptors to this process, and
+    // `listen_fds` removes LISTEN_FDS so each is only taken once.
+    let socket = unsafe { Socket::from_raw_fd(fd) };
+    socket.set_cloexec(true)?;
+    if socket.r#type()? != Type::STREAM || !socket.is_listener()? {
+        bail!("not a listening stream socket, only ListenStream= is supported");
+    }
+    socket.set_nonblocking(true)?;
+
+    if let Some(addr) = socket.local_addr()?.as_socket() {
+        let listener = TcpListener::from_std(socket.into())?;
+        return Ok((ListenAddress::Tcp(addr), BoundSocket::Tcp(listener)));
+    }
+    let listener: std::os::unix::net::UnixListener = socket.into();
+    let path = listener
+        .local_addr()?
+        .as_pathname()
+        .ok_or_else(|| anyhow!("Unix domain socket without a path"))?
+        .to_path_buf();
+    Ok((
+        ListenAddress::Unix(path),
+        BoundSocket::Unix(UnixListener::from_std(listener)?),
+    ))
+}
+
+/// Notify systemd of the `state` of the server, e.g. `READY=1`, if it is
+/// started by a `Type=notify` unit.
+pub fn notify(state: &str) -> Result<()> {
+    match env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => notify_socket(Path::new(&socket), state)
+            .with_context(|| format!("notify systemd of {state}")),
+        None => Ok(()),
+    }
+}
+
+fn notify_socket(socket: &Path, state: &str) -> Result<()> {
+    let datagram = UnixDatagram::unbound()?;
+    // `@` starts the name of a socket in the abstract namespace.
+    let addr = match socket.as_os_str().as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(socket)?,
+    };
+    datagram.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::io::IntoRawFd;
+
+    #[tokio::test]
+    async fn inherit_sockets() {
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let (address, socket) = inherit(tcp.into_raw_fd()).unwrap();
+        assert_eq!(address, ListenAddress::Tcp(addr));
+        assert!(matches!(socket, BoundSocket::Tcp(_)));
+
+        let path = env::temp_dir().join(format!("grpc-as-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let unix = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let (address, socket) = inherit(unix.into_raw_fd()).unwrap();
+        assert_eq!(address, ListenAddress::Unix(path.clone()));
+        assert!(matches!(socket, BoundSocket::Unix(_)));
+
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(inherit(udp.into_raw_fd()).is_err());
+
+        let notify = path.with_extension("notify");
+        let _ = std::fs::remove_file(&notify);
+        let systemd = UnixDatagram::bind(&notify).unwrap();
+        notify_socket(&notify, "READY=1").unwrap();
+        let mut buf = [0; 16];
+        let len = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+
+        for path in [path, notify] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
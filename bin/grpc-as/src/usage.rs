@@ -0,0 +1,342 @@
+//! Per-tenant usage accounting and quotas of attestation requests.
+//!
+//! Tenants are told apart by how their clients authenticate: by the
+//! subject or a subject alternative name of their client certificate, or
+//! by an API key in a request metadata header, `x-api-key` by default.
+//! Other requests are accounted to the `default` tenant, and requests with
+//! an unknown API key are refused, so that the tenants are only those of
+//! the config. The counters are kept in memory from the start of the
+//! server, so a billing job should collect them with `GetUsage`
+//! periodically.
+
+use attestation_service::submitter::Submitter;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tonic::metadata::MetadataMap;
+use tonic::Status;
+
+pub const DEFAULT_TENANT: &str = "default";
+
+const DEFAULT_API_KEY_HEADER: &str = "x-api-key";
+
+const QUOTA_PERIOD: Duration = Duration::from_secs(3600);
+
+fn default_api_key_header() -> String {
+    DEFAULT_API_KEY_HEADER.to_string()
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct UsageConfig {
+    /// Tenants of the clients with a client certificate, by the subject
+    /// distinguished name, in RFC 4514 form, or a DNS name or URI of the
+    /// subject alternative names of the certificate.
+    #[serde(default)]
+    pub certificate_tenants: HashMap<String, String>,
+
+    /// Tenants of the clients with an API key, by the hex SHA-256 digest of
+    /// the key, so that the config does not hold the keys.
+    #[serde(default)]
+    pub api_keys: HashMap<String, String>,
+
+    /// Request metadata header of the API key.
+    #[serde(default = "default_api_key_header")]
+    pub api_key_header: String,
+
+    /// Attestations per hour allowed to each tenant, by tenant name.
+    #[serde(default)]
+    pub quotas: HashMap<String, u64>,
+
+    /// Attestations per hour allowed to the tenants without a quota of
+    /// their own. Unlimited if not set.
+    #[serde(default)]
+    pub default_quota: Option<u64>,
+}
+
+impl Default for UsageConfig {
+    fn default() -> Self {
+        Self {
+            certificate_tenants: HashMap::new(),
+            api_keys: HashMap::new(),
+            api_key_header: default_api_key_header(),
+            quotas: HashMap::new(),
+            default_quota: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TenantUsage {
+    /// Attestations served since the server started.
+    pub attestations: u64,
+    /// Attestations refused since the server started, as over quota.
+    pub throttled: u64,
+    /// Attestations served in the current quota period.
+    pub period_attestations: u64,
+    pub quota: Option<u64>,
+}
+
+/// An attestation refused by the usage accounting.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Refused {
+    /// The API key of the request is not one of the config.
+    UnknownApiKey,
+    /// The tenant is not one of the config.
+    UnknownTenant(String),
+    /// The tenant has used up its quota of attestations of the hour.
+    QuotaExceeded { tenant: String, quota: u64 },
+}
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownApiKey => write!(f, "Unknown API key"),
+            Self::UnknownTenant(tenant) => write!(f, "Unknown tenant {tenant}"),
+            Self::QuotaExceeded { tenant, quota } => write!(
+                f,
+                "Tenant {tenant} is over its quota of {quota} attestations per hour"
+            ),
+        }
+    }
+}
+
+impl From<Refused> for Status {
+    fn from(refused: Refused) -> Self {
+        match refused {
+            Refused::UnknownApiKey => Status::unauthenticated(refused.to_string()),
+            Refused::UnknownTenant(_) => Status::permission_denied(refused.to_string()),
+            Refused::QuotaExceeded { .. } => Status::resource_exhausted(refused.to_string()),
+        }
+    }
+}
+
+struct Counters {
+    attestations: u64,
+    throttled: u64,
+    period_start: Instant,
+    period_attestations: u64,
+}
+
+pub struct Usage {
+    config: UsageConfig,
+    tenants: Mutex<HashMap<String, Counters>>,
+}
+
+impl Usage {
+    pub fn new(config: UsageConfig) -> Self {
+        Self {
+            config,
+            tenants: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The tenant of a request with `metadata`, of a client authenticated
+    /// as `submitter` with a client certificate, if any.
+    pub fn tenant(
+        &self,
+        metadata: &MetadataMap,
+        submitter: Option<&Submitter>,
+    ) -> Result<String, Refused> {
+        let by_certificate = submitter.and_then(|submitter| {
+            std::iter::once(&submitter.subject)
+                .chain(&submitter.san)
+                .find_map(|name| self.config.certificate_tenants.get(name))
+        });
+        if let Some(tenant) = by_certificate {
+            return Ok(tenant.clone());
+        }
+        match metadata.get(self.config.api_key_header.as_str()) {
+            Some(api_key) => {
+                let digest = hex::encode(Sha256::digest(api_key.as_bytes()));
+                self.config
+                    .api_keys
+                    .get(&digest)
+                    .cloned()
+                    .ok_or(Refused::UnknownApiKey)
+            }
+            None => Ok(DEFAULT_TENANT.to_string()),
+        }
+    }
+
+    /// Whether `tenant` is one of the config.
+    fn known(&self, tenant: &str) -> bool {
+        tenant == DEFAULT_TENANT
+            || self.config.quotas.contains_key(tenant)
+            || self
+                .config
+                .certificate_tenants
+                .values()
+                .chain(self.config.api_keys.values())
+                .any(|known| known == tenant)
+    }
+
+    fn quota(&self, tenant: &str) -> Option<u64> {
+        self.config
+            .quotas
+            .get(tenant)
+            .copied()
+            .or(self.config.default_quota)
+    }
+
+    /// Account an attestation of `tenant`, or refuse it if the tenant is
+    /// unknown or over quota.
+    pub fn record(&self, tenant: &str) -> Result<(), Refused> {
+        self.record_at(tenant, Instant::now())
+    }
+
+    fn record_at(&self, tenant: &str, now: Instant) -> Result<(), Refused> {
+        if !self.known(tenant) {
+            return Err(Refused::UnknownTenant(tenant.to_string()));
+        }
+        let quota = self.quota(tenant);
+        let mut tenants = self.tenants.lock().unwrap_or_else(PoisonError::into_inner);
+        let counters = tenants
+            .entry(tenant.to_string())
+            .or_insert_with(|| Counters {
+                attestations: 0,
+                throttled: 0,
+                period_start: now,
+                period_attestations: 0,
+            });
+
+        if now.duration_since(counters.period_start) >= QUOTA_PERIOD {
+            counters.period_start = now;
+            counters.period_attestations = 0;
+        }
+        if let Some(quota) = quota.filter(|quota| counters.period_attestations >= *quota) {
+            counters.throttled += 1;
+            return Err(Refused::QuotaExceeded {
+                tenant: tenant.to_string(),
+                quota,
+            });
+        }
+        counters.attestations += 1;
+        counters.period_attestations += 1;
+        Ok(())
+    }
+
+    /// The usage of `tenant`, or of all tenants if `None`.
+    pub fn get(&self, tenant: Option<&str>) -> BTreeMap<String, TenantUsage> {
+        self.get_at(tenant, Instant::now())
+    }
+
+    fn get_at(&self, tenant: Option<&str>, now: Instant) -> BTreeMap<String, TenantUsage> {
+        let tenants = self.tenants.lock().unwrap_or_else(PoisonError::into_inner);
+        tenants
+            .iter()
+            .filter(|(name, _)| match tenant {
+                Some(tenant) => tenant == name.as_str(),
+                None => true,
+            })
+            .map(|(name, counters)| {
+                let period_attestations =
+                    match now.duration_since(counters.period_start) < QUOTA_PERIOD {
+                        true => counters.period_attestations,
+                        false => 0,
+                    };
+                (
+                    name.clone(),
+                    TenantUsage {
+                        attestations: counters.attestations,
+                        throttled: counters.throttled,
+                        period_attestations,
+                        quota: self.quota(name),
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage() -> Usage {
+        Usage::new(UsageConfig {
+            certificate_tenants: HashMap::from([(
+                "spiffe://example.org/team-a".to_string(),
+                "team-a".to_string(),
+            )]),
+            api_keys: HashMap::from([(
+                hex::encode(Sha256::digest("secret-b")),
+                "team-b".to_string(),
+            )]),
+            quotas: HashMap::from([("team-a".to_string(), 2)]),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_tenant() {
+        let usage = usage();
+        let mut metadata = MetadataMap::new();
+        assert_eq!(usage.tenant(&metadata, None).unwrap(), DEFAULT_TENANT);
+        // The tenant is not taken from what the client says.
+        metadata.insert("x-tenant-id", "team-a".parse().unwrap());
+        assert_eq!(usage.tenant(&metadata, None).unwrap(), DEFAULT_TENANT);
+
+        metadata.insert("x-api-key", "secret-b".parse().unwrap());
+        assert_eq!(usage.tenant(&metadata, None).unwrap(), "team-b");
+        let submitter = Submitter {
+            subject: "CN=agent".to_string(),
+            san: vec!["spiffe://example.org/team-a".to_string()],
+            fingerprint: "aa".to_string(),
+        };
+        assert_eq!(usage.tenant(&metadata, Some(&submitter)).unwrap(), "team-a");
+
+        metadata.insert("x-api-key", "guessed".parse().unwrap());
+        assert_eq!(usage.tenant(&metadata, None), Err(Refused::UnknownApiKey));
+    }
+
+    #[test]
+    fn test_quota() {
+        let usage = usage();
+        let start = Instant::now();
+
+        usage.record_at("team-a", start).unwrap();
+        usage.record_at("team-a", start).unwrap();
+        assert_eq!(
+            usage.record_at("team-a", start),
+            Err(Refused::QuotaExceeded {
+                tenant: "team-a".to_string(),
+                quota: 2
+            })
+        );
+        for _ in 0..5 {
+            usage.record_at("team-b", start).unwrap();
+        }
+
+        assert_eq!(
+            usage.get_at(Some("team-a"), start),
+            BTreeMap::from([(
+                "team-a".to_string(),
+                TenantUsage {
+                    attestations: 2,
+                    throttled: 1,
+                    period_attestations: 2,
+                    quota: Some(2),
+                }
+            )])
+        );
+
+        // A new quota period.
+        let later = start + QUOTA_PERIOD;
+        usage.record_at("team-a", later).unwrap();
+        let all = usage.get_at(None, later);
+        assert_eq!(all["team-a"].attestations, 3);
+        assert_eq!(all["team-a"].period_attestations, 1);
+        assert_eq!(all["team-b"].period_attestations, 0);
+        assert_eq!(all["team-b"].quota, None);
+
+        // No counters are made for tenants that are not configured.
+        assert_eq!(
+            usage.record_at("team-c", start),
+            Err(Refused::UnknownTenant("team-c".to_string()))
+        );
+        assert!(!usage.get_at(None, start).contains_key("team-c"));
+    }
+}